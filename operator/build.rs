@@ -1,12 +1,44 @@
 use std::fs;
-use kube::CustomResourceExt;
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::{CustomResourceConversion, CustomResourceDefinition, ServiceReference, WebhookClientConfig, WebhookConversion};
+use kube::{core::crd::merge_crds, CustomResourceExt};
 use vpn_types::*;
 
+/// Returns the Mask CRD, which stores objects as v1. Converting them to
+/// v2 requires the conversion webhook, so v2 is only served by default
+/// if `webhook` is true. The webhook is expected where the chart installs
+/// it, as `vpn-webhook` in the `vpn` namespace; edit the service reference
+/// if installed differently.
+fn mask_crd(webhook: bool) -> CustomResourceDefinition {
+    let mut crd = merge_crds(vec![Mask::crd(), v2::Mask::crd()], "v1").unwrap();
+    if !webhook {
+        for version in crd.spec.versions.iter_mut().filter(|v| v.name == "v2") {
+            version.served = false;
+        }
+        return crd;
+    }
+    crd.spec.conversion = Some(CustomResourceConversion {
+        strategy: "Webhook".to_owned(),
+        webhook: Some(WebhookConversion {
+            conversion_review_versions: vec!["v1".to_owned()],
+            client_config: Some(WebhookClientConfig {
+                service: Some(ServiceReference { name: "vpn-webhook".to_owned(), namespace: "vpn".to_owned(), path: Some("/convert".to_owned()), port: Some(443) }),
+                ..Default::default()
+            }),
+        }),
+    });
+    crd
+}
+
 fn main() {
     let _ = fs::create_dir("../crds");
-    fs::write("../crds/vpn.beebs.dev_mask_crd.yaml", serde_yaml::to_string(&Mask::crd()).unwrap()).unwrap();
+    let _ = fs::create_dir("../crds/webhook");
+    fs::write("../crds/vpn.beebs.dev_clustermaskprovider_crd.yaml", serde_yaml::to_string(&ClusterMaskProvider::crd()).unwrap()).unwrap();
+    fs::write("../crds/vpn.beebs.dev_mask_crd.yaml", serde_yaml::to_string(&mask_crd(false)).unwrap()).unwrap();
+    fs::write("../crds/webhook/vpn.beebs.dev_mask_crd.yaml", serde_yaml::to_string(&mask_crd(true)).unwrap()).unwrap();
     fs::write("../crds/vpn.beebs.dev_maskconsumer_crd.yaml", serde_yaml::to_string(&MaskConsumer::crd()).unwrap()).unwrap();
     fs::write("../crds/vpn.beebs.dev_maskprovider_crd.yaml", serde_yaml::to_string(&MaskProvider::crd()).unwrap()).unwrap();
+    fs::write("../crds/vpn.beebs.dev_maskquota_crd.yaml", serde_yaml::to_string(&MaskQuota::crd()).unwrap()).unwrap();
     fs::write("../crds/vpn.beebs.dev_maskreservation_crd.yaml", serde_yaml::to_string(&MaskReservation::crd()).unwrap()).unwrap();
+    fs::write("../crds/vpn.beebs.dev_vpnaccount_crd.yaml", serde_yaml::to_string(&VpnAccount::crd()).unwrap()).unwrap();
+    fs::write("../crds/vpn.beebs.dev_vpnfleet_crd.yaml", serde_yaml::to_string(&VpnFleet::crd()).unwrap()).unwrap();
 }
-
@@ -0,0 +1,468 @@
+use json_patch::{PatchOperation, ReplaceOperation};
+use k8s_openapi::api::core::v1::{Namespace, Secret};
+use kube::{
+    api::{ListParams, ObjectMeta, Patch, PatchParams, PostParams, Resource},
+    Api, Client,
+};
+use std::collections::BTreeMap;
+use vpn_types::*;
+
+use crate::util::{
+    list::list_all_paginated, messages, patch::*, Error, ErrorContext, CLUSTER_PROVIDER_LABEL,
+    MANAGER_NAME,
+};
+
+/// A change to bring one of the [`ClusterMaskProvider`]'s children
+/// up to date, decided by [`plan`].
+#[derive(Debug, PartialEq)]
+pub enum ChildChange {
+    /// Copy the credentials into the namespace, or update the copy.
+    CopySecret { namespace: String },
+
+    /// Create the child [`MaskProvider`] in the namespace.
+    Create { namespace: String },
+
+    /// Revert the child [`MaskProvider`]'s spec to the desired one.
+    Update { namespace: String },
+
+    /// Delete the child [`MaskProvider`] and its credentials, as the
+    /// namespace is no longer targeted.
+    Delete { namespace: String },
+}
+
+/// What the [`ClusterMaskProvider`]'s children should be brought to,
+/// along with their current state.
+#[derive(Debug, PartialEq)]
+pub struct Plan {
+    /// The next change to make, if any children are out of date.
+    pub change: Option<ChildChange>,
+
+    /// State of the child in each targeted namespace, sorted by namespace.
+    pub children: Vec<ChildProviderStatus>,
+}
+
+/// Returns the name of the credentials Secret copied next to each child.
+pub fn get_child_secret_name(name: &str) -> String {
+    format!("{}-credentials", name)
+}
+
+/// Returns the number of slots each child has. The only child of a
+/// shared ClusterMaskProvider has all of them.
+pub fn slots_per_namespace(instance: &ClusterMaskProvider) -> usize {
+    match instance.spec.shared {
+        Some(true) => instance.spec.provider.max_slots,
+        _ => instance
+            .spec
+            .slots_per_namespace
+            .unwrap_or(instance.spec.provider.max_slots),
+    }
+}
+
+/// Returns true if the ClusterMaskProvider should have a child in the
+/// namespace, either because it's listed in `targetNamespaces` or it
+/// has every label of the `namespaceSelector`. Namespaces that are
+/// being deleted are never targeted.
+pub fn is_target(spec: &ClusterMaskProviderSpec, namespace: &Namespace) -> bool {
+    if namespace.metadata.deletion_timestamp.is_some() {
+        return false;
+    }
+    let name = namespace.metadata.name.as_deref().unwrap_or_default();
+    if spec
+        .target_namespaces
+        .as_ref()
+        .is_some_and(|t| t.iter().any(|n| n == name))
+    {
+        return true;
+    }
+    let labels = namespace.metadata.labels.clone().unwrap_or_default();
+    spec.namespace_selector.as_ref().is_some_and(|selector| {
+        !selector.is_empty() && selector.iter().all(|(k, v)| labels.get(k) == Some(v))
+    })
+}
+
+/// Returns the namespace of the only child of a shared ClusterMaskProvider:
+/// the operator's, or that of the credentials if it isn't known. Returns
+/// None if the ClusterMaskProvider has a child in each targeted namespace.
+pub fn shared_namespace(
+    instance: &ClusterMaskProvider,
+    operator_namespace: Option<&str>,
+) -> Option<String> {
+    (instance.spec.shared == Some(true)).then(|| {
+        operator_namespace
+            .unwrap_or(&instance.spec.secret_namespace)
+            .to_owned()
+    })
+}
+
+/// Returns true if the resource was created for the ClusterMaskProvider,
+/// as opposed to one with the same name that it mustn't touch.
+pub fn is_child(meta: &ObjectMeta, instance: &ClusterMaskProvider) -> bool {
+    let uid = instance.metadata.uid.as_deref();
+    meta.owner_references
+        .as_ref()
+        .is_some_and(|ors| ors.iter().any(|or| Some(or.uid.as_str()) == uid))
+}
+
+/// Returns the metadata of a resource created for the ClusterMaskProvider
+/// in the namespace. The owner reference lets the garbage collector
+/// delete the children along with the ClusterMaskProvider.
+fn child_meta(instance: &ClusterMaskProvider, name: String, namespace: &str) -> ObjectMeta {
+    let mut labels = BTreeMap::new();
+    labels.insert("app".to_owned(), MANAGER_NAME.to_owned());
+    labels.insert(
+        CLUSTER_PROVIDER_LABEL.to_owned(),
+        instance.metadata.name.clone().unwrap(),
+    );
+    ObjectMeta {
+        name: Some(name),
+        namespace: Some(namespace.to_owned()),
+        labels: Some(labels),
+        owner_references: Some(vec![instance.controller_owner_ref(&()).unwrap()]),
+        ..Default::default()
+    }
+}
+
+/// Returns the spec every child should have: the ClusterMaskProvider's,
+/// using the copied credentials and its slots per namespace.
+pub fn child_spec(instance: &ClusterMaskProvider) -> MaskProviderSpec {
+    MaskProviderSpec {
+        secret: get_child_secret_name(instance.metadata.name.as_deref().unwrap()),
+        max_slots: slots_per_namespace(instance),
+        ..instance.spec.provider.clone()
+    }
+}
+
+/// Returns the child MaskProvider in the namespace.
+pub fn child_provider(instance: &ClusterMaskProvider, namespace: &str) -> MaskProvider {
+    MaskProvider {
+        metadata: child_meta(instance, instance.metadata.name.clone().unwrap(), namespace),
+        spec: child_spec(instance),
+        status: None,
+    }
+}
+
+/// Returns the copy of the credentials Secret in the namespace.
+pub fn child_secret(instance: &ClusterMaskProvider, source: &Secret, namespace: &str) -> Secret {
+    let name = get_child_secret_name(instance.metadata.name.as_deref().unwrap());
+    Secret {
+        metadata: child_meta(instance, name, namespace),
+        data: source.data.clone(),
+        type_: source.type_.clone(),
+        ..Default::default()
+    }
+}
+
+/// Returns true if the copy has the same credentials as the source.
+fn is_secret_current(copy: &Secret, source: &Secret) -> bool {
+    copy.data == source.data && copy.type_ == source.type_
+}
+
+/// Returns true if the MaskProvider's phase means it needs attention.
+fn is_error_phase(phase: MaskProviderPhase) -> bool {
+    matches!(
+        phase,
+        MaskProviderPhase::ErrSecretNotFound
+            | MaskProviderPhase::ErrVerifyFailed
+            | MaskProviderPhase::ErrSecretSuffixCollision
+            | MaskProviderPhase::ErrAccountNotFound
+            | MaskProviderPhase::ErrConfig
+    )
+}
+
+/// Decides the next change to the ClusterMaskProvider's children, given
+/// the credentials, the targeted namespaces, and every MaskProvider and
+/// Secret in the cluster named like a child. Resources with a child's
+/// name that don't belong to the ClusterMaskProvider are left alone and
+/// reported in the child's status. Credentials are copied before the
+/// child is created, so it never misses its Secret.
+pub fn plan(
+    instance: &ClusterMaskProvider,
+    source: &Secret,
+    targets: &[String],
+    providers: &[MaskProvider],
+    secrets: &[Secret],
+) -> Plan {
+    let desired = child_spec(instance);
+    let in_namespace =
+        |meta: &ObjectMeta, namespace: &str| meta.namespace.as_deref() == Some(namespace);
+    let mut change = None;
+    let mut children = Vec::new();
+    let mut targets = targets.to_vec();
+    targets.sort();
+    targets.dedup();
+    for namespace in &targets {
+        let provider = providers
+            .iter()
+            .find(|p| in_namespace(&p.metadata, namespace));
+        let secret = secrets
+            .iter()
+            .find(|s| in_namespace(&s.metadata, namespace));
+        let conflict = match (provider, secret) {
+            (Some(p), _) if !is_child(&p.metadata, instance) => Some(messages::child_conflict(
+                "MaskProvider",
+                namespace,
+                p.metadata.name.as_deref().unwrap_or_default(),
+            )),
+            (_, Some(s)) if !is_child(&s.metadata, instance) => Some(messages::child_conflict(
+                "Secret",
+                namespace,
+                s.metadata.name.as_deref().unwrap_or_default(),
+            )),
+            _ => None,
+        };
+        if conflict.is_none() && change.is_none() {
+            let namespace = namespace.clone();
+            change = match (provider, secret) {
+                (_, None) => Some(ChildChange::CopySecret { namespace }),
+                (_, Some(s)) if !is_secret_current(s, source) => {
+                    Some(ChildChange::CopySecret { namespace })
+                }
+                (None, _) => Some(ChildChange::Create { namespace }),
+                (Some(p), _) if p.spec != desired => Some(ChildChange::Update { namespace }),
+                _ => None,
+            };
+        }
+        let status = provider
+            .filter(|_| conflict.is_none())
+            .and_then(|p| p.status.as_ref());
+        children.push(ChildProviderStatus {
+            namespace: namespace.clone(),
+            phase: status.and_then(|s| s.phase),
+            active_slots: status.and_then(|s| s.active_slots),
+            message: conflict,
+        });
+    }
+    // Remove the children of namespaces that are no longer targeted.
+    if change.is_none() {
+        change = providers
+            .iter()
+            .map(|p| &p.metadata)
+            .chain(secrets.iter().map(|s| &s.metadata))
+            .filter(|meta| is_child(meta, instance))
+            .filter_map(|meta| meta.namespace.clone())
+            .find(|namespace| !targets.contains(namespace))
+            .map(|namespace| ChildChange::Delete { namespace });
+    }
+    Plan { change, children }
+}
+
+/// Returns the ClusterMaskProvider's phase and message given the state
+/// of its children, which are all expected to be up to date.
+pub fn aggregate(
+    children: &[ChildProviderStatus],
+    slots_per_namespace: usize,
+) -> (ClusterMaskProviderPhase, String) {
+    let degraded: Vec<String> = children
+        .iter()
+        .filter(|c| c.message.is_some() || c.phase.is_some_and(is_error_phase))
+        .map(|c| c.namespace.clone())
+        .collect();
+    if !degraded.is_empty() {
+        return (
+            ClusterMaskProviderPhase::Degraded,
+            messages::children_degraded(&degraded),
+        );
+    }
+    let pending = children
+        .iter()
+        .filter(|c| {
+            !matches!(
+                c.phase,
+                Some(MaskProviderPhase::Ready) | Some(MaskProviderPhase::Active)
+            )
+        })
+        .count();
+    if pending > 0 {
+        return (
+            ClusterMaskProviderPhase::Propagating,
+            messages::children_propagating(pending, children.len()),
+        );
+    }
+    let active_slots = active_slots(children);
+    let phase = if active_slots == 0 {
+        ClusterMaskProviderPhase::Ready
+    } else {
+        ClusterMaskProviderPhase::Active
+    };
+    let message = messages::children_ready(
+        children.len(),
+        active_slots,
+        children.len() * slots_per_namespace,
+    );
+    (phase, message)
+}
+
+/// Returns the number of slots in use with all of the children together.
+pub fn active_slots(children: &[ChildProviderStatus]) -> usize {
+    children.iter().filter_map(|c| c.active_slots).sum()
+}
+
+/// Returns the ClusterMaskProvider's credentials Secret, if it exists.
+pub async fn get_source_secret(
+    client: Client,
+    instance: &ClusterMaskProvider,
+) -> Result<Option<Secret>, Error> {
+    let api: Api<Secret> = Api::namespaced(client, &instance.spec.secret_namespace);
+    let name = &instance.spec.provider.secret;
+    match api.get(name).await {
+        Ok(secret) => Ok(Some(secret)),
+        Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(None),
+        Err(e) => Err(e).context_kind_name("Secret", name),
+    }
+}
+
+/// Returns the names of the namespaces the ClusterMaskProvider targets.
+pub async fn list_targets(
+    client: Client,
+    instance: &ClusterMaskProvider,
+) -> Result<Vec<String>, Error> {
+    let api: Api<Namespace> = Api::all(client);
+    Ok(list_all_paginated(&api, &ListParams::default())
+        .await?
+        .into_iter()
+        .filter(|ns| is_target(&instance.spec, ns))
+        .filter_map(|ns| ns.metadata.name)
+        .collect())
+}
+
+/// Lists the resources in every namespace named like the children of
+/// the ClusterMaskProvider, whether they belong to it or not.
+pub async fn list_named<K>(client: Client, name: &str) -> Result<Vec<K>, Error>
+where
+    K: Resource<DynamicType = ()> + Clone + serde::de::DeserializeOwned + std::fmt::Debug,
+{
+    let api: Api<K> = Api::all(client);
+    let lp = ListParams::default().fields(&format!("metadata.name={}", name));
+    list_all_paginated(&api, &lp).await
+}
+
+/// Updates the ClusterMaskProvider's phase to Pending, which indicates
+/// the resource made its initial appearance to the operator.
+pub async fn pending(client: Client, instance: &ClusterMaskProvider) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.set_phase(ClusterMaskProviderPhase::Pending, messages::PENDING);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Updates the ClusterMaskProvider's phase to ErrSecretNotFound.
+pub async fn secret_not_found(client: Client, instance: &ClusterMaskProvider) -> Result<(), Error> {
+    let message = messages::cluster_secret_not_found(
+        &instance.spec.secret_namespace,
+        &instance.spec.provider.secret,
+    );
+    patch_status(client, instance, |status| {
+        status.set_phase(ClusterMaskProviderPhase::ErrSecretNotFound, message);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Copies the credentials into the namespace, replacing a stale copy.
+pub async fn copy_secret(
+    client: Client,
+    instance: &ClusterMaskProvider,
+    namespace: &str,
+) -> Result<(), Error> {
+    let source = match get_source_secret(client.clone(), instance).await? {
+        Some(source) => source,
+        // The next reconciliation reports the missing Secret.
+        None => return Ok(()),
+    };
+    let mut secret = child_secret(instance, &source, namespace);
+    let name = secret.metadata.name.clone().unwrap();
+    let api: Api<Secret> = Api::namespaced(client, namespace);
+    match api.get(&name).await {
+        Ok(existing) => {
+            secret.metadata.resource_version = existing.metadata.resource_version;
+            api.replace(&name, &PostParams::default(), &secret)
+                .await
+                .context_kind_name("Secret", &name)?;
+        }
+        Err(kube::Error::Api(ae)) if ae.code == 404 => {
+            api.create(&PostParams::default(), &secret)
+                .await
+                .context_kind_name("Secret", &name)?;
+        }
+        Err(e) => return Err(e).context_kind_name("Secret", &name),
+    }
+    Ok(())
+}
+
+/// Creates the child MaskProvider in the namespace.
+pub async fn create_child(
+    client: Client,
+    instance: &ClusterMaskProvider,
+    namespace: &str,
+) -> Result<(), Error> {
+    let child = child_provider(instance, namespace);
+    let api: Api<MaskProvider> = Api::namespaced(client, namespace);
+    api.create(&PostParams::default(), &child)
+        .await
+        .context_kind_name("MaskProvider", child.metadata.name.as_deref().unwrap())?;
+    Ok(())
+}
+
+/// Reverts the child MaskProvider's spec to the desired one, undoing
+/// any edits made to the child and propagating changes to the
+/// ClusterMaskProvider. The whole spec is replaced, so fields unset in
+/// the desired spec are removed from the child rather than left behind
+/// as a merge patch would.
+pub async fn update_child(
+    client: Client,
+    instance: &ClusterMaskProvider,
+    namespace: &str,
+) -> Result<(), Error> {
+    let name = instance.metadata.name.as_deref().unwrap();
+    let api: Api<MaskProvider> = Api::namespaced(client, namespace);
+    let patch = json_patch::Patch(vec![PatchOperation::Replace(ReplaceOperation {
+        path: "/spec".to_owned(),
+        value: serde_json::to_value(child_spec(instance))?,
+    })]);
+    api.patch(name, &PatchParams::default(), &Patch::Json::<()>(patch))
+        .await
+        .context_kind_name("MaskProvider", name)?;
+    Ok(())
+}
+
+/// Deletes the child MaskProvider and its credentials from the namespace.
+/// The child's own deletion logic still waits for its slots to be released.
+pub async fn delete_child(
+    client: Client,
+    instance: &ClusterMaskProvider,
+    namespace: &str,
+) -> Result<(), Error> {
+    let name = instance.metadata.name.as_deref().unwrap();
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), namespace);
+    match api.delete(name, &Default::default()).await {
+        Ok(_) => {}
+        Err(kube::Error::Api(ae)) if ae.code == 404 => {}
+        Err(e) => return Err(e).context_kind_name("MaskProvider", name),
+    }
+    let secret_name = get_child_secret_name(name);
+    let api: Api<Secret> = Api::namespaced(client, namespace);
+    match api.delete(&secret_name, &Default::default()).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(()),
+        Err(e) => Err(e).context_kind_name("Secret", &secret_name),
+    }
+}
+
+/// Records the state of the children in the ClusterMaskProvider's status.
+pub async fn update_status(
+    client: Client,
+    instance: &ClusterMaskProvider,
+    children: Vec<ChildProviderStatus>,
+) -> Result<(), Error> {
+    let slots = slots_per_namespace(instance);
+    let (phase, message) = aggregate(&children, slots);
+    patch_status(client, instance, |status| {
+        status.set_phase(phase, message);
+        status.max_slots = Some(children.len() * slots);
+        status.active_slots = Some(active_slots(&children));
+        status.children = Some(children);
+    })
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,34 @@
+pub(crate) mod actions;
+pub(crate) mod reconcile;
+
+pub use reconcile::run;
+
+use crate::rbac::Rule;
+
+/// API access the ClusterMaskProvider controller needs. See [`crate::rbac`].
+pub const RBAC: &[Rule] = &[
+    Rule::new(
+        "vpn.beebs.dev",
+        &["clustermaskproviders"],
+        &["get", "list", "watch", "patch"],
+    ),
+    Rule::new(
+        "vpn.beebs.dev",
+        &["clustermaskproviders/status"],
+        &["patch"],
+    ),
+    // A MaskProvider is created in each selected namespace.
+    Rule::new(
+        "vpn.beebs.dev",
+        &["maskproviders"],
+        &["get", "list", "watch", "create", "patch", "delete"],
+    ),
+    // The credentials are copied along with it, and kept up to date.
+    Rule::new(
+        "",
+        &["secrets"],
+        &["get", "list", "watch", "create", "update", "delete"],
+    ),
+    // The selected namespaces are listed on every reconciliation.
+    Rule::new("", &["namespaces"], &["list"]),
+];
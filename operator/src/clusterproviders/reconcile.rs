@@ -0,0 +1,382 @@
+use futures::stream::StreamExt;
+use k8s_openapi::api::core::v1::Secret;
+use kube::{
+    api::ListParams,
+    client::Client,
+    runtime::{controller::Action, reflector::ObjectRef, Controller},
+    Api, ResourceExt,
+};
+use std::sync::Arc;
+use tokio::{sync::Semaphore, time::Duration};
+use vpn_types::*;
+
+use super::actions::{self, get_child_secret_name, ChildChange};
+use crate::util::{
+    explain, needs_refresh,
+    patch::{ensure_status_initialized, record_action_error},
+    report_reconcile_error, ControllerOptions, Error, CLUSTER_PROVIDER_LABEL, PROBE_INTERVAL,
+};
+
+#[cfg(feature = "metrics")]
+use crate::util::metrics::ControllerMetrics;
+
+/// Entrypoint for the `ClusterMaskProvider` controller. If `concurrency` is set, at most
+/// that many reconciliations will be performed at the same time. Active statuses are
+/// only rewritten once they are older than `status_freshness`. The children of shared
+/// ClusterMaskProviders are kept in `operator_namespace`, if given.
+pub async fn run(
+    client: Client,
+    concurrency: Option<usize>,
+    status_freshness: Duration,
+    operator_namespace: Option<String>,
+    options: ControllerOptions,
+) -> Result<(), Error> {
+    println!("Starting ClusterMaskProvider controller...");
+
+    // Preparation of resources used by the `kube_runtime::Controller`
+    let crd_api: Api<ClusterMaskProvider> = Api::all(client.clone());
+    let context: Arc<ContextData> = Arc::new(ContextData::new(
+        client.clone(),
+        concurrency,
+        status_freshness,
+        operator_namespace,
+        options,
+    ));
+
+    // The children are watched by their label rather than with `owns`, as
+    // the owner of a namespaced child is looked up in the child's namespace.
+    // Edits to a child are reverted as soon as they're made, while new and
+    // relabeled namespaces are picked up by the next requeue.
+    Controller::new(crd_api, ListParams::default())
+        .watches(
+            Api::<MaskProvider>::all(client.clone()),
+            ListParams::default().labels(CLUSTER_PROVIDER_LABEL),
+            |provider| child_owner(&provider),
+        )
+        .watches(
+            Api::<Secret>::all(client),
+            ListParams::default().labels(CLUSTER_PROVIDER_LABEL),
+            |secret| child_owner(&secret),
+        )
+        .run(reconcile, on_error, context)
+        .for_each(|_reconciliation_result| async move {})
+        .await;
+    Ok(())
+}
+
+/// Maps a child MaskProvider or credentials Secret to the
+/// `ClusterMaskProvider` named by its label.
+fn child_owner<K: ResourceExt>(child: &K) -> Option<ObjectRef<ClusterMaskProvider>> {
+    let name = child.labels().get(CLUSTER_PROVIDER_LABEL)?;
+    Some(ObjectRef::new(name))
+}
+
+/// Context injected with each `reconcile` and `on_error` method invocation.
+struct ContextData {
+    /// Kubernetes client to make Kubernetes API requests with. Required for K8S resource management.
+    client: Client,
+
+    /// Limits the number of concurrent reconciliations, if configured.
+    semaphore: Option<Semaphore>,
+
+    /// How long an unchanged status goes without being rewritten.
+    status_freshness: Duration,
+
+    /// Namespace of the operator, where the children of shared
+    /// ClusterMaskProviders are kept.
+    operator_namespace: Option<String>,
+
+    /// Settings from the command line, e.g. whether actions are explained.
+    options: ControllerOptions,
+
+    #[cfg(feature = "metrics")]
+    metrics: ControllerMetrics,
+}
+
+impl ContextData {
+    /// Constructs a new instance of ContextData.
+    ///
+    /// # Arguments:
+    /// - `client`: A Kubernetes client to make Kubernetes REST API requests with. Resources
+    ///   will be created and deleted with this client.
+    /// - `concurrency`: Optional maximum number of concurrent reconciliations.
+    /// - `status_freshness`: How long an unchanged status goes without being rewritten.
+    /// - `operator_namespace`: Namespace of the operator, if known.
+    /// - `options`: Settings from the command line.
+    pub fn new(
+        client: Client,
+        concurrency: Option<usize>,
+        status_freshness: Duration,
+        operator_namespace: Option<String>,
+        options: ControllerOptions,
+    ) -> Self {
+        let semaphore = concurrency.map(Semaphore::new);
+        ContextData {
+            client,
+            semaphore,
+            options,
+            status_freshness,
+            operator_namespace,
+            #[cfg(feature = "metrics")]
+            metrics: ControllerMetrics::new("clusterproviders"),
+        }
+    }
+}
+
+/// Action to be taken upon a [`ClusterMaskProvider`] resource during reconciliation
+#[derive(Debug, PartialEq)]
+pub(crate) enum ClusterProviderAction {
+    /// Set the [`ClusterMaskProviderStatus::phase`] to [`Pending`](ClusterMaskProviderPhase::Pending).
+    Pending,
+
+    /// Set the [`ClusterMaskProviderStatus::phase`] to
+    /// [`ErrSecretNotFound`](ClusterMaskProviderPhase::ErrSecretNotFound).
+    SecretNotFound,
+
+    /// Copy the credentials into the namespace, or update the copy.
+    CopySecret { namespace: String },
+
+    /// Create the child [`MaskProvider`] in the namespace.
+    CreateChild { namespace: String },
+
+    /// Revert the child [`MaskProvider`]'s spec to the desired one.
+    UpdateChild { namespace: String },
+
+    /// Delete the child [`MaskProvider`] and its credentials from
+    /// a namespace that is no longer targeted.
+    DeleteChild { namespace: String },
+
+    /// Record the state of the children in the status.
+    Status { children: Vec<ChildProviderStatus> },
+
+    /// The [`ClusterMaskProvider`] resource is in desired state and requires no actions to be taken.
+    NoOp,
+}
+
+impl ClusterProviderAction {
+    fn to_str(&self) -> &str {
+        match self {
+            ClusterProviderAction::Pending => "Pending",
+            ClusterProviderAction::SecretNotFound => "SecretNotFound",
+            ClusterProviderAction::CopySecret { .. } => "CopySecret",
+            ClusterProviderAction::CreateChild { .. } => "CreateChild",
+            ClusterProviderAction::UpdateChild { .. } => "UpdateChild",
+            ClusterProviderAction::DeleteChild { .. } => "DeleteChild",
+            ClusterProviderAction::Status { .. } => "Status",
+            ClusterProviderAction::NoOp => "NoOp",
+        }
+    }
+}
+
+impl From<ChildChange> for ClusterProviderAction {
+    fn from(change: ChildChange) -> Self {
+        match change {
+            ChildChange::CopySecret { namespace } => {
+                ClusterProviderAction::CopySecret { namespace }
+            }
+            ChildChange::Create { namespace } => ClusterProviderAction::CreateChild { namespace },
+            ChildChange::Update { namespace } => ClusterProviderAction::UpdateChild { namespace },
+            ChildChange::Delete { namespace } => ClusterProviderAction::DeleteChild { namespace },
+        }
+    }
+}
+
+/// Reconciliation function for the [`ClusterMaskProvider`] resource.
+async fn reconcile(
+    instance: Arc<ClusterMaskProvider>,
+    context: Arc<ContextData>,
+) -> Result<Action, Error> {
+    // Wait for a permit if the number of concurrent reconciliations is limited.
+    let _permit = match context.semaphore {
+        Some(ref semaphore) => Some(semaphore.acquire().await.unwrap()),
+        None => None,
+    };
+
+    // The `Client` is shared -> a clone from the reference is obtained
+    let client: Client = context.client.clone();
+
+    // The resource is cluster-scoped, so it's only identified by its name.
+    let name = instance.name_any();
+
+    // Increment total number of reconciles for the ClusterMaskProvider resource.
+    #[cfg(feature = "metrics")]
+    context.metrics.reconcile(&name, "");
+
+    // Benchmark the read phase of reconciliation.
+    #[cfg(feature = "metrics")]
+    let start = std::time::Instant::now();
+
+    // Read phase of reconciliation determines goal during the write phase.
+    let action = determine_action(
+        client.clone(),
+        &instance,
+        context.status_freshness,
+        context.operator_namespace.as_deref(),
+    )
+    .await?;
+
+    if action != ClusterProviderAction::NoOp {
+        println!("{} ACTION: {:?}", name, action);
+    }
+
+    // Report the read phase performance and count the action.
+    #[cfg(feature = "metrics")]
+    context.metrics.read(&name, "", action.to_str(), start);
+
+    // Benchmark the write phase of reconciliation.
+    #[cfg(feature = "metrics")]
+    let timer = match action {
+        // Don't measure performance for NoOp actions.
+        ClusterProviderAction::NoOp => None,
+        // Start a performance timer for the write phase.
+        _ => Some(context.metrics.write(&name, "", action.to_str())),
+    };
+
+    // Perform the action. The action's name is attached to any error
+    // so the error handler can report which action failed.
+    let action_name = action.to_str().to_owned();
+    let result = explain::scope(
+        context.options.explain,
+        "clusterproviders",
+        &action_name,
+        apply_action(client, &instance, action),
+    )
+    .await;
+
+    // Report the write phase performance with the action's outcome.
+    #[cfg(feature = "metrics")]
+    if let Some(timer) = timer {
+        timer.observe(&result);
+    }
+
+    result.map_err(|e| Error::action(&action_name, e))
+}
+
+/// Performs the action as decided by the `determine_action` function.
+/// This is the write phase of reconciliation.
+async fn apply_action(
+    client: Client,
+    instance: &ClusterMaskProvider,
+    action: ClusterProviderAction,
+) -> Result<Action, Error> {
+    Ok(match action {
+        ClusterProviderAction::Pending => {
+            actions::pending(client, instance).await?;
+            Action::requeue(Duration::ZERO)
+        }
+        ClusterProviderAction::SecretNotFound => {
+            actions::secret_not_found(client, instance).await?;
+            Action::requeue(PROBE_INTERVAL)
+        }
+        ClusterProviderAction::CopySecret { namespace } => {
+            actions::copy_secret(client, instance, &namespace).await?;
+            Action::requeue(Duration::ZERO)
+        }
+        ClusterProviderAction::CreateChild { namespace } => {
+            actions::create_child(client, instance, &namespace).await?;
+            Action::requeue(Duration::ZERO)
+        }
+        ClusterProviderAction::UpdateChild { namespace } => {
+            actions::update_child(client, instance, &namespace).await?;
+            Action::requeue(Duration::ZERO)
+        }
+        ClusterProviderAction::DeleteChild { namespace } => {
+            actions::delete_child(client, instance, &namespace).await?;
+            Action::requeue(Duration::ZERO)
+        }
+        ClusterProviderAction::Status { children } => {
+            actions::update_status(client, instance, children).await?;
+            Action::requeue(PROBE_INTERVAL)
+        }
+        // The children are garbage collected along with a deleted
+        // ClusterMaskProvider, and otherwise rechecked periodically
+        // to pick up new and relabeled namespaces.
+        ClusterProviderAction::NoOp => Action::requeue(PROBE_INTERVAL),
+    })
+}
+
+/// Resources arrives into reconciliation queue in a certain state. This function looks at
+/// the state of given `ClusterMaskProvider` resource and decides which actions needs to be performed.
+/// The finite set of possible actions is represented by the `ClusterProviderAction` enum.
+///
+/// # Arguments
+/// - `instance`: A reference to `ClusterMaskProvider` being reconciled to decide next action upon.
+/// - `status_freshness`: How long an unchanged status goes without being rewritten.
+/// - `operator_namespace`: Namespace of the operator, where the child of a shared
+///   `ClusterMaskProvider` is kept.
+pub(crate) async fn determine_action(
+    client: Client,
+    instance: &ClusterMaskProvider,
+    status_freshness: Duration,
+    operator_namespace: Option<&str>,
+) -> Result<ClusterProviderAction, Error> {
+    if instance.metadata.deletion_timestamp.is_some() {
+        return Ok(ClusterProviderAction::NoOp);
+    }
+
+    // The rest of the controller code assumes the presence of the
+    // status object and its bookkeeping fields. If any of these are
+    // missing, the first thing that should be done is initializing them.
+    let status = match ensure_status_initialized(instance) {
+        Ok(status) => status,
+        Err(_) => return Ok(ClusterProviderAction::Pending),
+    };
+
+    // Without credentials, the children can't be created or kept up to date.
+    let source = match actions::get_source_secret(client.clone(), instance).await? {
+        Some(source) => source,
+        None if status.phase == Some(ClusterMaskProviderPhase::ErrSecretNotFound)
+            && !needs_refresh(status, status_freshness) =>
+        {
+            return Ok(ClusterProviderAction::NoOp)
+        }
+        None => return Ok(ClusterProviderAction::SecretNotFound),
+    };
+
+    // Bring the children up to date one at a time.
+    let name = instance.name_any();
+    // A shared ClusterMaskProvider only has a child in the operator's namespace.
+    let targets = match actions::shared_namespace(instance, operator_namespace) {
+        Some(namespace) => vec![namespace],
+        None => actions::list_targets(client.clone(), instance).await?,
+    };
+    let providers = actions::list_named(client.clone(), &name).await?;
+    let secrets = actions::list_named(client, &get_child_secret_name(&name)).await?;
+    let plan = actions::plan(instance, &source, &targets, &providers, &secrets);
+    if let Some(change) = plan.change {
+        return Ok(change.into());
+    }
+
+    // Only rewrite the status when the children changed or it's stale.
+    let (phase, message) =
+        actions::aggregate(&plan.children, actions::slots_per_namespace(instance));
+    if status.phase != Some(phase)
+        || status.message.as_deref() != Some(message.as_str())
+        || status.children.as_ref() != Some(&plan.children)
+        || needs_refresh(status, status_freshness)
+    {
+        return Ok(ClusterProviderAction::Status {
+            children: plan.children,
+        });
+    }
+    Ok(ClusterProviderAction::NoOp)
+}
+
+/// Actions to be taken when a reconciliation fails - for whatever reason.
+/// Prints out the error to `stderr` and requeues the resource for another reconciliation after
+/// five seconds.
+///
+/// # Arguments
+/// - `instance`: The erroneous resource.
+/// - `error`: A reference to the `kube::Error` that occurred during reconciliation.
+/// - `context`: Context Data "injected" automatically by kube-rs. Its client is used
+///   to record the failed action in the resource's status object.
+fn on_error(
+    instance: Arc<ClusterMaskProvider>,
+    error: &Error,
+    context: Arc<ContextData>,
+) -> Action {
+    let action = report_reconcile_error(&instance, error);
+    record_action_error(context.client.clone(), instance, "clusterproviders", error);
+    action
+}
@@ -0,0 +1,182 @@
+use kube::{api::ListParams, Api, Client};
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+use vpn_types::*;
+
+use crate::util::{
+    is_verification_reservation, list::list_all_paginated, Error, VERIFICATION_LABEL,
+};
+
+/// Returns the name of the VpnAccount the MaskProvider belongs to, if any.
+pub fn account_ref(provider: &MaskProvider) -> Option<&str> {
+    provider.spec.account_ref.as_deref()
+}
+
+/// Counts the slots reserved with all of the MaskProviders that belong
+/// to the account. Reservations made for verification don't count, as
+/// they aren't real consumers and are released once verification ends.
+pub fn count_connections(
+    account: &str,
+    providers: &[MaskProvider],
+    reservations: &[MaskReservation],
+) -> usize {
+    let uids: Vec<&str> = providers
+        .iter()
+        .filter(|p| account_ref(p) == Some(account))
+        .filter_map(|p| p.metadata.uid.as_deref())
+        .collect();
+    reservations
+        .iter()
+        .filter(|r| {
+            r.metadata
+                .owner_references
+                .iter()
+                .flatten()
+                .any(|or| uids.contains(&or.uid.as_str()))
+        })
+        .filter(|r| !is_verification_reservation(r))
+        .count()
+}
+
+/// Returns the number of slots currently reserved with the account. This
+/// is never cached, as a stale count would allow the ceiling to be exceeded.
+pub async fn get_connections(client: Client, account: &str) -> Result<usize, Error> {
+    let api: Api<MaskProvider> = Api::all(client.clone());
+    let providers: Vec<MaskProvider> = list_all_paginated(&api, &Default::default())
+        .await?
+        .into_iter()
+        .filter(|p| account_ref(p) == Some(account))
+        .collect();
+    // Only list reservations in namespaces with one of the account's MaskProviders.
+    let namespaces: BTreeSet<&str> = providers
+        .iter()
+        .filter_map(|p| p.metadata.namespace.as_deref())
+        .collect();
+    // Leave out reservations made for verification on the server.
+    let lp = ListParams::default().labels(&format!("!{}", VERIFICATION_LABEL));
+    let mut reservations = Vec::new();
+    for namespace in namespaces {
+        let api: Api<MaskReservation> = Api::namespaced(client.clone(), namespace);
+        reservations.extend(list_all_paginated(&api, &lp).await?);
+    }
+    Ok(count_connections(account, &providers, &reservations))
+}
+
+/// Returns the VpnAccount with the given name, or None if it doesn't exist.
+pub async fn get_account(client: Client, name: &str) -> Result<Option<VpnAccount>, Error> {
+    let api: Api<VpnAccount> = Api::all(client);
+    match api.get(name).await {
+        Ok(account) => Ok(Some(account)),
+        Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns the account's utilization for the MaskProvider's status.
+pub async fn get_utilization(
+    client: Client,
+    account: &VpnAccount,
+) -> Result<AccountUtilization, Error> {
+    let name = account.metadata.name.clone().unwrap();
+    Ok(AccountUtilization {
+        connections: get_connections(client, &name).await?,
+        max_connections: account.spec.max_connections,
+        name,
+    })
+}
+
+/// The result of asking to reserve a slot with a MaskProvider.
+pub enum Admission {
+    /// The MaskProvider doesn't belong to an account.
+    Unrestricted,
+
+    /// The account has room for another connection. The guard must be held
+    /// until the reservation is created, so concurrent reconciliations can't
+    /// both take the last connection.
+    Admitted(OwnedMutexGuard<()>),
+
+    /// The account has no connections available, or doesn't exist.
+    Full(String),
+}
+
+/// An account's connection limit and when it was fetched.
+/// The limit is None if the VpnAccount doesn't exist.
+type CachedLimit = (Instant, Option<usize>);
+
+/// Enforces the connection ceilings of VpnAccounts shared by several
+/// MaskProviders. The accounts' limits are cached for `ttl`, as they
+/// rarely change, while the connections are always counted afresh.
+/// Admission to each account is serialized within the controller.
+pub struct Accounts {
+    ttl: Duration,
+    limits: Mutex<HashMap<String, CachedLimit>>,
+    locks: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+impl Accounts {
+    pub fn new(ttl: Duration) -> Self {
+        Accounts {
+            ttl,
+            limits: Mutex::new(HashMap::new()),
+            locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks whether a slot can be reserved with the MaskProvider
+    /// without exceeding the ceiling of the account it belongs to.
+    pub async fn admit(&self, client: Client, provider: &MaskProvider) -> Result<Admission, Error> {
+        let account = match account_ref(provider) {
+            Some(account) => account,
+            None => return Ok(Admission::Unrestricted),
+        };
+        let guard = self.lock(account).await;
+        let admitted = match self.max_connections(client.clone(), account).await? {
+            // The ceiling can't be enforced without the VpnAccount.
+            None => false,
+            Some(max) => get_connections(client, account).await? < max,
+        };
+        Ok(if admitted {
+            Admission::Admitted(guard)
+        } else {
+            Admission::Full(account.to_owned())
+        })
+    }
+
+    /// Acquires the lock that serializes admission to the account.
+    async fn lock(&self, account: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            // Locks no one holds or waits on are dropped, so accounts
+            // that are no longer used don't keep theirs.
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(account.to_owned()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+
+    /// Returns the number of accounts with a lock.
+    #[cfg(test)]
+    pub fn locked_accounts(&self) -> usize {
+        self.locks.lock().unwrap().len()
+    }
+
+    /// Returns the account's connection limit, using the cache if possible.
+    async fn max_connections(&self, client: Client, account: &str) -> Result<Option<usize>, Error> {
+        if let Some((fetched, max)) = self.limits.lock().unwrap().get(account) {
+            if fetched.elapsed() < self.ttl {
+                return Ok(*max);
+            }
+        }
+        let max = get_account(client, account)
+            .await?
+            .map(|a| a.spec.max_connections);
+        let mut limits = self.limits.lock().unwrap();
+        limits.retain(|_, (fetched, _)| fetched.elapsed() < self.ttl);
+        limits.insert(account.to_owned(), (Instant::now(), max));
+        Ok(max)
+    }
+}
@@ -1,20 +1,117 @@
-use crate::util::{messages, patch::*, Error};
-use k8s_openapi::api::core::v1::Secret;
+use crate::providers::transforms::effective_secret_name;
+use crate::util::{clock::Clock, events, messages, patch::*, schedule, Error, ErrorContext};
+use chrono::{DateTime, Utc};
+use json_patch::{AddOperation, PatchOperation, ReplaceOperation, TestOperation};
+use k8s_openapi::api::core::v1::{Secret, Service};
 use kube::{
-    api::{ObjectMeta, Resource},
-    Api, Client,
+    api::{DeleteParams, ObjectMeta, Patch, PatchParams, Preconditions, Resource},
+    Api, Client, ResourceExt,
 };
+use serde_json::Value;
 use std::collections::BTreeMap;
 use vpn_types::*;
 
-use crate::util::{PROVIDER_UID_LABEL, VERIFICATION_LABEL};
+use super::{
+    account::{Accounts, Admission},
+    anti_affinity,
+    default_providers::ProviderTags,
+    gluetun, projection,
+    quota::{QuotaAdmission, Quotas},
+    required_labels, rollout, secret_template,
+    selector::ProviderSelector,
+    slots::{bounded_name, reservation_name, reservation_slot, MAX_LABEL_LEN, MAX_NAME_LEN},
+    succession,
+    topology::{self, TopologyTier},
+    OptInLabel,
+};
+use crate::util::{
+    consumer_purpose,
+    list::{list_all_paginated, list_provider_reservations},
+    verified_provider_uid, CANARY_LABEL, CONSUMER_NAMESPACE_LABEL, CONSUMER_UID_LABEL,
+    CREDENTIALS_HASH_ANNOTATION, GLUETUN_VERSION_ANNOTATION, MASK_NAME_LABEL, PROVIDER_UID_LABEL,
+    SOURCE_HASH_ANNOTATION, VERIFICATION_LABEL,
+};
 
 /// Updates the `MaskConsumer`'s phase to Pending, which indicates
 /// the resource made its initial appearance to the operator.
 pub async fn pending(client: Client, instance: &MaskConsumer) -> Result<(), Error> {
     patch_status(client, instance, |status| {
-        status.message = Some(messages::PENDING.to_owned());
-        status.phase = Some(MaskConsumerPhase::Pending);
+        status.set_phase(MaskConsumerPhase::Pending, messages::PENDING);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Updates the `MaskConsumer`'s phase to ErrNamespaceNotOptedIn, with
+/// a message explaining how to opt the namespace in.
+pub async fn namespace_not_opted_in(
+    client: Client,
+    namespace: &str,
+    instance: &MaskConsumer,
+    label: &OptInLabel,
+) -> Result<(), Error> {
+    let message = messages::err_namespace_not_opted_in(namespace, &label.to_string());
+    patch_status(client, instance, |status| {
+        status.set_phase(MaskConsumerPhase::ErrNamespaceNotOptedIn, message);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Updates the `MaskConsumer`'s phase to ErrSecretConflict, with a message
+/// naming the `Secret` that's in the way of the credentials copy. The
+/// assigned slot is kept so the copy can be made as soon as it's removed.
+pub async fn secret_conflict(
+    client: Client,
+    instance: &MaskConsumer,
+    message: String,
+) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.set_phase(MaskConsumerPhase::ErrSecretConflict, message);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Updates the `MaskConsumer`'s phase to ErrInvalidSpec, with a message
+/// naming the setting that can't be applied to the credentials. The
+/// assigned slot is kept so the copy can be made once they allow it.
+pub async fn invalid_spec(
+    client: Client,
+    instance: &MaskConsumer,
+    message: String,
+) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.set_phase(MaskConsumerPhase::ErrInvalidSpec, message);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Updates the `MaskConsumer`'s phase to ErrProviderLost, with a message
+/// naming the `MaskProvider` that was deleted or replaced. A Warning event
+/// is only published upon entering the phase.
+pub async fn provider_lost(
+    client: Client,
+    instance: &MaskConsumer,
+    message: String,
+) -> Result<(), Error> {
+    let phase = instance.status.as_ref().and_then(|s| s.phase);
+    patch_status(client.clone(), instance, |status| {
+        status.set_phase(MaskConsumerPhase::ErrProviderLost, message.clone());
+    })
+    .await?;
+    if phase != Some(MaskConsumerPhase::ErrProviderLost) {
+        events::warn(client, instance, "ProviderLost", "Unassign", message).await;
+    }
+    Ok(())
+}
+
+/// Updates the `MaskConsumer`'s phase to Waiting with a message
+/// explaining that assignments are frozen by the operator.
+pub async fn assignments_frozen(client: Client, instance: &MaskConsumer) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.clear_provider(messages::ASSIGNMENTS_FROZEN);
     })
     .await?;
     Ok(())
@@ -23,8 +120,35 @@ pub async fn pending(client: Client, instance: &MaskConsumer) -> Result<(), Erro
 /// Updates the `MaskConsumer`'s phase to Active.
 pub async fn active(client: Client, instance: &MaskConsumer) -> Result<(), Error> {
     patch_status(client, instance, |status| {
-        status.phase = Some(MaskConsumerPhase::Active);
-        status.message = Some(messages::ACTIVE.to_owned());
+        status.set_phase(MaskConsumerPhase::Active, messages::ACTIVE);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Shows in the `MaskConsumer`'s status that its assigned `MaskProvider`
+/// no longer permits its namespace, or clears it if `message` is None.
+pub async fn policy_violation(
+    client: Client,
+    instance: &MaskConsumer,
+    message: Option<String>,
+) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.policy_violation = message;
+    })
+    .await?;
+    Ok(())
+}
+
+/// Records the ServiceAccounts whose Pods used the `MaskConsumer`'s
+/// credentials in its status, removing the list once it's empty.
+pub async fn record_consumers(
+    client: Client,
+    instance: &MaskConsumer,
+    records: Vec<ConsumerRecord>,
+) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.consumers = Some(records).filter(|records| !records.is_empty());
     })
     .await?;
     Ok(())
@@ -33,8 +157,21 @@ pub async fn active(client: Client, instance: &MaskConsumer) -> Result<(), Error
 /// Updates the `MaskConsumer`'s phase to Terminating.
 pub async fn terminating(client: Client, instance: &MaskConsumer) -> Result<(), Error> {
     patch_status(client, instance, |status| {
-        status.phase = Some(MaskConsumerPhase::Terminating);
-        status.message = Some(messages::TERMINATING.to_owned());
+        status.set_phase(MaskConsumerPhase::Terminating, messages::TERMINATING);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Keeps the `MaskConsumer` in the Terminating phase with a message
+/// naming the Pods that still use its credentials.
+pub async fn withdrawal_blocked(
+    client: Client,
+    instance: &MaskConsumer,
+    message: String,
+) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.set_phase(MaskConsumerPhase::Terminating, message);
     })
     .await?;
     Ok(())
@@ -53,17 +190,10 @@ pub async fn assign_verify_provider(
     // Get the MaskProvider resource we are verifying. It must be in the same
     // namespace as the MaskConsumer and have the given uid.
     let provider_api: Api<MaskProvider> = Api::namespaced(client.clone(), namespace);
-    let provider = provider_api
-        .list(&Default::default())
+    let provider = list_all_paginated(&provider_api, &Default::default())
         .await?
         .into_iter()
-        .filter(|p| {
-            p.metadata
-                .uid
-                .as_deref()
-                .map_or(false, |uid| uid == provider_uid)
-        })
-        .next()
+        .find(|p| p.metadata.uid.as_deref() == Some(provider_uid))
         .ok_or_else(|| {
             Error::UserInputError(format!(
                 "MaskProvider with uid {} not found in namespace {}",
@@ -71,55 +201,104 @@ pub async fn assign_verify_provider(
             ))
         })?;
     // Only assign the MaskProvider that the MaskConsumer is meant to verify.
-    if try_reserve_slot(client.clone(), name, namespace, instance, &provider).await? {
+    let placement = Placement {
+        tags: &ProviderTags::Any,
+        region: None,
+    };
+    if try_reserve_slot(
+        client.clone(),
+        name,
+        namespace,
+        instance,
+        &provider,
+        &placement,
+        &QuotaAdmission::default(),
+    )
+    .await?
+        == SlotOutcome::Reserved
+    {
         // MaskProvider had an open slot and it was reserved.
         return Ok(true);
     }
     // See if we can prune any dangling slot reservations.
     if prune_provider(client.clone(), &provider).await? {
         // Slots were pruned so we should be able to reserve one now.
-        if try_reserve_slot(client.clone(), name, namespace, instance, &provider).await? {
+        if try_reserve_slot(
+            client.clone(),
+            name,
+            namespace,
+            instance,
+            &provider,
+            &placement,
+            &QuotaAdmission::default(),
+        )
+        .await?
+            == SlotOutcome::Reserved
+        {
             return Ok(true);
         }
     }
     // Still unable to find a slot after pruning.
     patch_status(client, instance, |status| {
-        status.phase = Some(MaskConsumerPhase::Waiting);
-        status.message = Some(messages::WAITING.to_owned());
+        status.clear_provider(messages::WAITING);
     })
     .await?;
     Ok(false)
 }
 
+/// How the MaskProviders tried for a MaskConsumer are chosen, which is
+/// named in its status message once a slot is reserved.
+pub struct Placement<'a> {
+    /// Tags the MaskProviders are filtered with.
+    pub tags: &'a ProviderTags,
+
+    /// Region of the MaskConsumer's namespace whose MaskProviders are
+    /// tried first, if the MaskConsumer is topology aware.
+    pub region: Option<&'a str>,
+}
+
 /// Assigns a new MaskProvider to the MaskConsumer. Prunes and retries if necessary.
-/// Returns true if a MaskProvider was assigned, false otherwise.
+/// MaskProviders whose VpnAccount has no connections available are skipped, as
+/// are those assigned to other members of the MaskConsumer's anti-affinity group
+/// and those a MaskQuota in its namespace doesn't permit another slot with.
+/// The selector decides the order in which the MaskProviders are tried, out of
+/// those with any of the placement's tags, after those in the placement's
+/// region if it has one. Returns true if a MaskProvider was assigned, false
+/// otherwise.
 pub async fn assign_provider(
     client: Client,
-    name: &str,
-    namespace: &str,
     instance: &MaskConsumer,
+    placement: &Placement<'_>,
+    accounts: &Accounts,
+    quotas: &Quotas,
+    clock: &Clock,
+    selector: &dyn ProviderSelector,
 ) -> Result<bool, Error> {
+    let name = instance.metadata.name.as_deref().unwrap();
+    let namespace = instance.metadata.namespace.as_deref().unwrap();
+    let tags = placement.tags;
+
     // This will be set to the MaskProvider's uid if the MaskConsumer is meant
     // for verification of the credentials. In this case, a slot will be assigned
     // regardless of the MaskProvider's phase. The only problem that may occur is
     // that all slots are already in use.
-    if let Some(provider_uid) = instance
-        .metadata
-        .labels
-        .as_ref()
-        .map_or(None, |l| l.get(VERIFICATION_LABEL).map(|v| v.as_str()))
-    {
+    if let Some(provider_uid) = verified_provider_uid(instance) {
         return assign_verify_provider(client, name, namespace, instance, provider_uid).await;
     }
 
     // See if there are any providers available.
     let providers =
-        list_active_providers(client.clone(), instance.spec.providers.as_ref(), namespace).await?;
+        list_active_providers(client.clone(), tags.filter(), namespace, clock.now()).await?;
+    let providers = retain_canary_target(providers, instance);
     if providers.is_empty() {
-        // No valid MaskProviders at all. Reflect the error in the status.
+        // No valid MaskProviders at all. Reflect the error in the status,
+        // naming the namespace's default providers if they were used.
+        let message = match tags {
+            ProviderTags::Namespace(tags) => messages::err_no_default_providers(namespace, tags),
+            _ => messages::ERR_NO_PROVIDERS.to_owned(),
+        };
         patch_status(client, instance, |status| {
-            status.phase = Some(MaskConsumerPhase::ErrNoProviders);
-            status.message = Some(messages::ERR_NO_PROVIDERS.to_owned());
+            status.set_phase(MaskConsumerPhase::ErrNoProviders, message);
         })
         .await?;
 
@@ -127,41 +306,132 @@ pub async fn assign_provider(
         return Ok(false);
     }
 
+    // Only assign MaskProviders whose required labels the Mask has.
+    let (providers, missing_labels) = required_labels::exclude(providers, instance);
+    if providers.is_empty() {
+        // Name the missing labels so they can be added to the Mask.
+        patch_status(client, instance, |status| {
+            status.set_phase(
+                MaskConsumerPhase::ErrMissingLabels,
+                messages::err_missing_labels(&missing_labels),
+            );
+        })
+        .await?;
+        return Ok(false);
+    }
+
+    // Never share a MaskProvider with another member of the anti-affinity group.
+    let members = anti_affinity::list_members(client.clone(), instance).await?;
+    let providers = anti_affinity::exclude(providers, &members);
+    if providers.is_empty() {
+        // Every suitable MaskProvider is taken by the group, so wait
+        // for one of the members to release it.
+        let message = messages::anti_affinity_waiting(
+            anti_affinity::group(instance).unwrap(),
+            &anti_affinity::assigned_members(&members),
+        );
+        patch_status(client, instance, |status| {
+            status.clear_provider(message);
+        })
+        .await?;
+        return Ok(false);
+    }
+
+    // Never reserve more slots than the namespace's MaskQuotas allow. The
+    // admission is held until the slot is reserved.
+    let admission = quotas.admit(client.clone(), instance).await?;
+    let (providers, exceeded) = admission.exclude(providers);
+    if providers.is_empty() {
+        quota_exceeded(client, instance, exceeded.join(" ")).await?;
+        return Ok(false);
+    }
+
+    // A MaskConsumer that succeeds another, e.g. because its Mask was
+    // renamed, takes over the predecessor's slot rather than waiting for
+    // one, as long as the predecessor's MaskProvider is still suitable.
+    if take_over_predecessor(client.clone(), name, instance, placement, &providers).await? {
+        return Ok(true);
+    }
+
     // For the first attempt, filter out the MaskProviders that have reached
     // their capacity. This way we can try not slamming the kube api server
     // with a bunch of requests that are likely to fail in the first place.
     // The status object may be stale, so if we fail the first attempt we
     // won't do this the second time.
-    let providers = providers
+    let providers: Vec<MaskProvider> = providers
         .into_iter()
         .filter(|p| {
-            p.status.as_ref().map_or(true, |s| {
-                s.active_slots.map_or(true, |a| a < p.spec.max_slots)
-            })
+            p.status
+                .as_ref()
+                .is_none_or(|s| s.active_slots.is_none_or(|a| a < p.spec.max_slots))
         })
         .collect();
+    let first_count = providers.len();
+    let providers = order(selector, providers, instance, placement);
 
     // Try to assign a provider for the first time.
-    if assign_provider_base(client.clone(), name, namespace, instance, &providers).await? {
-        return Ok(true);
+    let mut full_accounts = Vec::new();
+    match assign_provider_base(
+        client.clone(),
+        instance,
+        placement,
+        &providers,
+        accounts,
+        &admission,
+        &mut full_accounts,
+    )
+    .await?
+    {
+        SlotOutcome::Reserved => return Ok(true),
+        SlotOutcome::OverQuota(message) => {
+            quota_exceeded(client, instance, message).await?;
+            return Ok(false);
+        }
+        SlotOutcome::Full => {}
     }
 
     // Remove dangling reservations and try again.
     let pruned = prune(client.clone()).await?;
     let new_providers =
-        list_active_providers(client.clone(), instance.spec.providers.as_ref(), namespace).await?;
-    if pruned || providers.len() != new_providers.len() {
+        list_active_providers(client.clone(), tags.filter(), namespace, clock.now()).await?;
+    let new_providers = retain_canary_target(new_providers, instance);
+    let (new_providers, _) = required_labels::exclude(new_providers, instance);
+    let new_providers = anti_affinity::exclude(new_providers, &members);
+    let (new_providers, _) = admission.exclude(new_providers);
+    if pruned || first_count != new_providers.len() {
+        let new_providers = order(selector, new_providers, instance, placement);
         // Try a second time if we pruned or if we excluded any MaskProviders
         // during the first attempt due to possibly stale status objects.
-        if assign_provider_base(client.clone(), name, namespace, instance, &new_providers).await? {
-            return Ok(true);
+        match assign_provider_base(
+            client.clone(),
+            instance,
+            placement,
+            &new_providers,
+            accounts,
+            &admission,
+            &mut full_accounts,
+        )
+        .await?
+        {
+            SlotOutcome::Reserved => return Ok(true),
+            SlotOutcome::OverQuota(message) => {
+                quota_exceeded(client, instance, message).await?;
+                return Ok(false);
+            }
+            SlotOutcome::Full => {}
         }
     }
 
-    // Unable to find an empty slot with any MaskProvider.
+    // Unable to find an empty slot with any MaskProvider. Name the
+    // accounts that were full, as their MaskProviders may have open
+    // slots that the MaskConsumer is still not allowed to reserve.
+    let message = if full_accounts.is_empty() {
+        messages::WAITING.to_owned()
+    } else {
+        messages::account_full(&full_accounts)
+    };
     patch_status(client, instance, |status| {
-        status.phase = Some(MaskConsumerPhase::Waiting);
-        status.message = Some(messages::WAITING.to_owned());
+        status.clear_provider(message);
     })
     .await?;
 
@@ -169,87 +439,719 @@ pub async fn assign_provider(
     Ok(false)
 }
 
-// Attempts to reserve a slot with the MaskProvider. Returns true
-// if a slot was reserved, false otherwise.
+/// Updates the MaskConsumer's phase to ErrQuotaExceeded with the message
+/// naming the exhausted MaskQuotas. A Warning event is published when the
+/// MaskConsumer enters the phase, but not on every retry.
+async fn quota_exceeded(
+    client: Client,
+    instance: &MaskConsumer,
+    message: String,
+) -> Result<(), Error> {
+    let phase = instance.status.as_ref().and_then(|s| s.phase);
+    patch_status(client.clone(), instance, |status| {
+        status.set_phase(MaskConsumerPhase::ErrQuotaExceeded, message.clone());
+    })
+    .await?;
+    if phase != Some(MaskConsumerPhase::ErrQuotaExceeded) {
+        events::warn(client, instance, "QuotaExceeded", "Assign", message).await;
+    }
+    Ok(())
+}
+
+/// Takes over the reservation of the MaskConsumer's predecessor, if it
+/// names one that is going away and was assigned one of the `providers`.
+/// Returns true if the MaskConsumer was assigned the reservation.
+async fn take_over_predecessor(
+    client: Client,
+    name: &str,
+    instance: &MaskConsumer,
+    placement: &Placement<'_>,
+    providers: &[MaskProvider],
+) -> Result<bool, Error> {
+    let predecessor = match succession::get_predecessor(client.clone(), instance).await? {
+        Some(predecessor) => predecessor,
+        None => return Ok(false),
+    };
+    let assigned = match predecessor
+        .status
+        .as_ref()
+        .and_then(|s| s.provider.as_ref())
+    {
+        Some(assigned) => assigned,
+        None => return Ok(false),
+    };
+    let provider = match providers
+        .iter()
+        .find(|p| p.metadata.uid.as_deref() == Some(&assigned.uid))
+    {
+        Some(provider) => provider,
+        None => return Ok(false),
+    };
+    let provider_name = provider.metadata.name.as_deref().unwrap();
+    let reservations = list_provider_reservations(client.clone(), provider).await?;
+    let (reservation, slot) = match succession::predecessor_reservation(&reservations, &predecessor)
+        .filter(|mr| has_marks(mr, &reservation_marks(instance)))
+        .and_then(|mr| reservation_slot(mr, provider_name).map(|slot| (mr, slot)))
+        .filter(|(_, slot)| *slot < provider.spec.max_slots)
+    {
+        Some(found) => found,
+        None => return Ok(false),
+    };
+    let owner_uid = instance.metadata.uid.as_deref().unwrap();
+    let reservation =
+        match take_over_reservation(client.clone(), reservation, name, owner_uid).await? {
+            Some(reservation) => reservation,
+            // The predecessor's reservation was released in the meantime.
+            None => return Ok(false),
+        };
+    assign_reservation(
+        client.clone(),
+        name,
+        instance,
+        provider,
+        placement,
+        &reservation,
+        slot,
+    )
+    .await?;
+    let note = messages::succession(
+        &predecessor.name_any(),
+        slot,
+        &assigned.namespace,
+        &assigned.name,
+    );
+    events::publish(client, instance, "Succession", "Assign", note).await;
+    Ok(true)
+}
+
+/// Orders the candidates with the selector, then moves those in the
+/// placement's region to the front, if it has one.
+fn order(
+    selector: &dyn ProviderSelector,
+    candidates: Vec<MaskProvider>,
+    instance: &MaskConsumer,
+    placement: &Placement<'_>,
+) -> Vec<MaskProvider> {
+    let candidates = selector.select(candidates, instance);
+    match placement.region {
+        Some(region) => topology::prefer_region(candidates, region),
+        None => candidates,
+    }
+}
+
+/// The outcome of trying to reserve a slot with a MaskProvider.
+#[derive(Debug, PartialEq)]
+enum SlotOutcome {
+    /// A slot was reserved and assigned to the MaskConsumer.
+    Reserved,
+
+    /// None of the MaskProvider's slots could be reserved.
+    Full,
+
+    /// A slot was reserved, but given up again because reservations made
+    /// at the same time took the rest of a MaskQuota. Contains the message
+    /// naming the MaskQuota.
+    OverQuota(String),
+}
+
+// Attempts to reserve a slot with the MaskProvider, confirming it with
+// the quota `admission`. The status message names the `placement` the
+// MaskProvider was picked by.
 async fn try_reserve_slot(
     client: Client,
     name: &str,
     namespace: &str,
     instance: &MaskConsumer,
     provider: &MaskProvider,
-) -> Result<bool, Error> {
+    placement: &Placement<'_>,
+    admission: &QuotaAdmission,
+) -> Result<SlotOutcome, Error> {
     let owner_uid = instance.metadata.uid.as_deref().unwrap();
-    let provider_name = provider.metadata.name.as_deref().unwrap();
-    let provider_namespace = provider.metadata.namespace.as_deref().unwrap();
-    let slots = list_inactive_slots(client.clone(), provider).await?;
+    // Propagate the verification and canary labels so the MaskProvider
+    // can tell their reservations apart from those of real consumers.
+    let marks = reservation_marks(instance);
+    // A MaskConsumer that was deleted and recreated under the same name,
+    // e.g. by GitOps, takes over the reservation its predecessor left
+    // behind rather than waiting for it to be garbage collected.
+    let reservations = list_provider_reservations(client.clone(), provider).await?;
+    if let Some((orphan, slot)) = orphaned_reservation(&reservations, instance, provider) {
+        if let Some(reservation) =
+            take_over_reservation(client.clone(), orphan, name, owner_uid).await?
+        {
+            assign_reservation(
+                client,
+                name,
+                instance,
+                provider,
+                placement,
+                &reservation,
+                slot,
+            )
+            .await?;
+            return Ok(SlotOutcome::Reserved);
+        }
+    }
+    let mut slots = inactive_slots(provider, &reservations);
+    // Attempt the slot the Mask used last before falling back to the normal scan.
+    if let Some(preferred) = preferred_slot(instance, provider) {
+        // The slot may still be held by this Mask's previous MaskConsumer.
+        if !slots.contains(&preferred) && prune_slot(client.clone(), provider, preferred).await? {
+            slots.push(preferred);
+        }
+        if let Some(i) = slots.iter().position(|slot| *slot == preferred) {
+            slots.remove(i);
+            slots.insert(0, preferred);
+        }
+    }
     for slot in slots {
         // Try and take the slot.
-        let reservation =
-            match create_reservation(client.clone(), name, namespace, provider, slot, owner_uid)
-                .await
-            {
-                // Slot was reserved successfully.
-                Ok(reservation) => reservation,
-                // Slot is already reserved.
-                Err(kube::Error::Api(e)) if e.code == 409 => continue,
-                // Unknown failure reserving slot.
-                Err(e) => return Err(e.into()),
-            };
-        let msg = format!(
-            "reserved slot {} for MaskProvider {}/{}",
-            slot, provider_namespace, provider_name,
-        );
-        // Patch the MaskConsumer resource to assign the MaskProvider.
-        let provider_uid = provider.metadata.uid.clone().unwrap();
-        patch_status(client, instance, move |status| {
-            let secret = format!("{}-{}", name, &provider_uid);
-            status.provider = Some(AssignedProvider {
-                name: provider_name.to_owned(),
-                namespace: provider_namespace.to_owned(),
-                uid: provider_uid,
-                reservation: reservation.metadata.uid.clone().unwrap(),
-                slot,
-                secret,
-            });
-            status.message = Some(msg);
-        })
+        let reservation = match create_reservation(
+            client.clone(),
+            name,
+            namespace,
+            provider,
+            slot,
+            owner_uid,
+            &marks,
+        )
+        .await
+        {
+            // Slot was reserved successfully.
+            Ok(reservation) => reservation,
+            // Slot is already reserved.
+            Err(kube::Error::Api(e)) if e.code == 409 => continue,
+            // Unknown failure reserving slot.
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(message) = admission
+            .confirm(client.clone(), provider, &reservation)
+            .await?
+        {
+            // Another reservation took the rest of the quota first.
+            release_reservation(client, &reservation).await?;
+            return Ok(SlotOutcome::OverQuota(message));
+        }
+        assign_reservation(
+            client,
+            name,
+            instance,
+            provider,
+            placement,
+            &reservation,
+            slot,
+        )
         .await?;
-        // Next reconciliation will create the credentials Secret,
-        // after which the MaskConsumer's phase will become Active.
-        return Ok(true);
+        return Ok(SlotOutcome::Reserved);
     }
     // Failed to reserve a slot with the MaskProvider.
-    Ok(false)
+    Ok(SlotOutcome::Full)
 }
 
-/// Assigns a new MaskProvider to the Mask. Returns true
-/// if a MaskProvider was assigned, false otherwise.
-async fn assign_provider_base(
+/// Deletes the MaskReservation that was just made, freeing its slot.
+async fn release_reservation(client: Client, reservation: &MaskReservation) -> Result<(), Error> {
+    let name = reservation.name_any();
+    let api: Api<MaskReservation> =
+        Api::namespaced(client, reservation.metadata.namespace.as_deref().unwrap());
+    match api.delete(&name, &Default::default()).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(()),
+        Err(e) => Err(e).context_kind_name("MaskReservation", &name),
+    }
+}
+
+/// Returns the name of the ClusterMaskProvider that controls the
+/// MaskProvider, if it's one of its children.
+pub fn parent_cluster_provider(provider: &MaskProvider) -> Option<String> {
+    provider
+        .metadata
+        .owner_references
+        .iter()
+        .flatten()
+        .find(|or| or.kind == "ClusterMaskProvider" && or.controller == Some(true))
+        .map(|or| or.name.clone())
+}
+
+/// Patches the MaskConsumer's status to assign it the MaskProvider
+/// through the reservation of the slot.
+async fn assign_reservation(
     client: Client,
     name: &str,
+    instance: &MaskConsumer,
+    provider: &MaskProvider,
+    placement: &Placement<'_>,
+    reservation: &MaskReservation,
+    slot: usize,
+) -> Result<(), Error> {
+    let provider_name = provider.metadata.name.as_deref().unwrap();
+    let provider_namespace = provider.metadata.namespace.as_deref().unwrap();
+    // Record which tier the MaskProvider came from for topology-aware consumers.
+    let topology = placement.region.map(|region| {
+        let same_region = topology::tier(provider, region) == TopologyTier::SameRegion;
+        messages::topology_tier(region, same_region)
+    });
+    let msg = messages::reserved_slot(
+        slot,
+        provider_namespace,
+        provider_name,
+        placement.tags.source().as_deref(),
+        topology.as_deref(),
+    );
+    // Resolve the settings now so later changes to the provider's
+    // defaults don't retroactively alter this assignment.
+    let effective_settings = instance
+        .spec
+        .settings
+        .with_defaults(provider.spec.mask_defaults.as_ref());
+    // Patch the MaskConsumer resource to assign the MaskProvider.
+    let provider_uid = provider.metadata.uid.clone().unwrap();
+    let secret = copy_secret_name(name, instance, provider, slot);
+    let provider = AssignedProvider {
+        name: provider_name.to_owned(),
+        namespace: provider_namespace.to_owned(),
+        uid: provider_uid,
+        reservation: reservation.metadata.uid.clone().unwrap(),
+        slot,
+        secret,
+        gluetun_version: provider.spec.gluetun_version.clone(),
+        copy_encryption: provider.spec.copy_encryption.clone(),
+        secret_hash: None,
+        cluster_provider: parent_cluster_provider(provider),
+    };
+    patch_status(client, instance, move |status| {
+        status.set_assigned(provider, effective_settings, msg);
+    })
+    .await?;
+    // Next reconciliation will create the credentials Secret,
+    // after which the MaskConsumer's phase will become Active.
+    Ok(())
+}
+
+/// Returns the reservation and slot left behind with the MaskProvider by
+/// a previous MaskConsumer with the same name and namespace. Names are
+/// unique, so the previous MaskConsumer no longer exists if the UID
+/// differs. Reservations with other verification or canary labels were
+/// made for a different purpose and are left alone.
+pub fn orphaned_reservation<'a>(
+    reservations: &'a [MaskReservation],
+    instance: &MaskConsumer,
+    provider: &MaskProvider,
+) -> Option<(&'a MaskReservation, usize)> {
+    let provider_name = provider.metadata.name.as_deref().unwrap();
+    let marks = reservation_marks(instance);
+    reservations
+        .iter()
+        .filter(|mr| {
+            Some(&mr.spec.name) == instance.metadata.name.as_ref()
+                && Some(&mr.spec.namespace) == instance.metadata.namespace.as_ref()
+                && Some(&mr.spec.uid) != instance.metadata.uid.as_ref()
+                && mr.metadata.deletion_timestamp.is_none()
+        })
+        .filter(|mr| has_marks(mr, &marks))
+        .find_map(|mr| {
+            reservation_slot(mr, provider_name)
+                .filter(|slot| *slot < provider.spec.max_slots)
+                .map(|slot| (mr, slot))
+        })
+}
+
+/// Points the reservation at the MaskConsumer with `owner_name` and
+/// `owner_uid`. The patch only applies if the reservation still belongs
+/// to the previous MaskConsumer, so returns None if it was deleted or
+/// taken over first.
+pub async fn take_over_reservation(
+    client: Client,
+    reservation: &MaskReservation,
+    owner_name: &str,
+    owner_uid: &str,
+) -> Result<Option<MaskReservation>, Error> {
+    let name = reservation.name_any();
+    let mr_api: Api<MaskReservation> =
+        Api::namespaced(client, reservation.metadata.namespace.as_deref().unwrap());
+    let patch = json_patch::Patch(vec![
+        PatchOperation::Test(TestOperation {
+            path: "/spec/uid".to_owned(),
+            value: Value::from(reservation.spec.uid.clone()),
+        }),
+        PatchOperation::Replace(ReplaceOperation {
+            path: "/spec/name".to_owned(),
+            value: Value::from(owner_name),
+        }),
+        PatchOperation::Replace(ReplaceOperation {
+            path: "/spec/uid".to_owned(),
+            value: Value::from(owner_uid),
+        }),
+    ]);
+    match mr_api
+        .patch(&name, &PatchParams::default(), &Patch::Json::<()>(patch))
+        .await
+    {
+        Ok(reservation) => Ok(Some(reservation)),
+        // The reservation is gone, or its UID no longer matches.
+        Err(kube::Error::Api(e)) if e.code == 404 || e.code == 422 => Ok(None),
+        Err(e) => Err(e).context_kind_name("MaskReservation", &name),
+    }
+}
+
+/// Returns true if the reservation has the same verification and canary
+/// labels as `marks`, i.e. it was made for the same purpose.
+fn has_marks(reservation: &MaskReservation, marks: &BTreeMap<String, String>) -> bool {
+    let labels = reservation.labels();
+    [VERIFICATION_LABEL, CANARY_LABEL]
+        .iter()
+        .all(|key| labels.get(*key) == marks.get(*key))
+}
+
+/// Returns the MaskConsumer's verification and canary labels, which
+/// are copied to its reservations. The verification label is only
+/// copied if the MaskConsumer's purpose is verification.
+pub fn reservation_marks(instance: &MaskConsumer) -> BTreeMap<String, String> {
+    let verification = consumer_purpose(instance) == MaskConsumerPurpose::Verification;
+    instance
+        .labels()
+        .iter()
+        .filter(|(k, _)| (verification && *k == VERIFICATION_LABEL) || *k == CANARY_LABEL)
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+/// Returns only the MaskProvider a canary MaskConsumer tests, so the
+/// canary is never assigned another one. Other MaskConsumers may be
+/// assigned any of the MaskProviders.
+pub fn retain_canary_target(
+    providers: Vec<MaskProvider>,
+    instance: &MaskConsumer,
+) -> Vec<MaskProvider> {
+    match instance.labels().get(CANARY_LABEL) {
+        Some(uid) => providers
+            .into_iter()
+            .filter(|p| p.metadata.uid.as_ref() == Some(uid))
+            .collect(),
+        None => providers,
+    }
+}
+
+/// Returns the name of the credentials Secret copied for the MaskConsumer
+/// with the given name on the slot, rendered from the MaskProvider's copy
+/// Secret name template if it has a valid one. Verification copies are
+/// internal to the operator, so they always get the default name.
+pub fn copy_secret_name(
+    name: &str,
+    instance: &MaskConsumer,
+    provider: &MaskProvider,
+    slot: usize,
+) -> String {
+    match provider.spec.copy_secret_name_template.as_deref() {
+        Some(template)
+            if consumer_purpose(instance) != MaskConsumerPurpose::Verification
+                && secret_template::validate(provider).is_ok() =>
+        {
+            secret_template::render(
+                template,
+                name,
+                provider.metadata.name.as_deref().unwrap(),
+                slot,
+                instance.metadata.uid.as_deref().unwrap_or_default(),
+            )
+        }
+        _ => secret_name(name, provider),
+    }
+}
+
+/// Returns the name of the credentials Secret for the MaskConsumer with
+/// the given name. The MaskProvider's stable secret suffix is used if it
+/// has one, so the name survives recreating the MaskProvider. Otherwise,
+/// the MaskProvider's UID is used. Names that would be too long are
+/// shortened with a hash, so the name is only ever read back from
+/// [`AssignedProvider::secret`].
+pub fn secret_name(name: &str, provider: &MaskProvider) -> String {
+    let suffix = provider
+        .spec
+        .stable_secret_suffix
+        .as_deref()
+        .or(provider.metadata.uid.as_deref())
+        .unwrap();
+    bounded_name(name, &format!("-{}", suffix), MAX_NAME_LEN)
+}
+
+/// Returns the labels of the MaskConsumer's credentials Secret, which
+/// let it be selected by the MaskProvider it was copied from and by the
+/// Mask and MaskConsumer it was copied for.
+pub fn secret_labels(
+    instance: &MaskConsumer,
+    provider: &AssignedProvider,
+) -> BTreeMap<String, String> {
+    let mut labels = BTreeMap::new();
+    labels.insert(PROVIDER_UID_LABEL.to_owned(), provider.uid.clone());
+    // The MaskConsumer has the same name as its Mask.
+    labels.insert(
+        MASK_NAME_LABEL.to_owned(),
+        bounded_name(&instance.name_any(), "", MAX_LABEL_LEN),
+    );
+    if let Some(uid) = instance.metadata.uid.as_ref() {
+        labels.insert(CONSUMER_UID_LABEL.to_owned(), uid.clone());
+    }
+    labels
+}
+
+/// Returns the labels the MaskConsumer's credentials Secret should have
+/// but doesn't. Secrets copied before all of the labels were added are
+/// migrated by patching them in.
+pub fn missing_secret_labels(secret: &Secret, instance: &MaskConsumer) -> BTreeMap<String, String> {
+    let provider = match instance.status.as_ref().and_then(|s| s.provider.as_ref()) {
+        Some(provider) => provider,
+        None => return BTreeMap::new(),
+    };
+    let existing = secret.metadata.labels.as_ref();
+    secret_labels(instance, provider)
+        .into_iter()
+        .filter(|(k, v)| existing.and_then(|l| l.get(k)) != Some(v))
+        .collect()
+}
+
+/// Adds the missing labels to the MaskConsumer's credentials Secret.
+pub async fn label_secret(
+    client: Client,
     namespace: &str,
+    name: &str,
+    labels: BTreeMap<String, String>,
+) -> Result<(), Error> {
+    let api: Api<Secret> = Api::namespaced(client, namespace);
+    let patch = serde_json::json!({
+        "metadata": {
+            "labels": labels
+        }
+    });
+    api.patch(name, &Default::default(), &Patch::Merge(&patch))
+        .await
+        .context_kind_name("Secret", name)?;
+    Ok(())
+}
+
+/// Returns the annotations that engage the external encryption controller
+/// for the copy of the credentials, if the MaskProvider configured one.
+pub fn secret_annotations(provider: &AssignedProvider) -> BTreeMap<String, String> {
+    provider
+        .copy_encryption
+        .iter()
+        .map(|e| (e.annotation_key.clone(), e.value().to_owned()))
+        .collect()
+}
+
+/// Prefix of the annotations the operator sets on the copies itself.
+const OPERATOR_ANNOTATION_PREFIX: &str = "vpn.beebs.dev/";
+
+/// Maximum length of the name part of an annotation key.
+const MAX_ANNOTATION_NAME_LEN: usize = 63;
+
+/// Returns an error describing why the MaskProvider's copy encryption
+/// annotation can't be set on the copies, if it has one. The key must be
+/// a valid annotation key, i.e. an optional DNS subdomain prefix and a
+/// name, and may not use the operator's own prefix, as it would collide
+/// with the annotations the operator relies on.
+pub fn validate_copy_encryption(provider: &MaskProvider) -> Result<(), String> {
+    let key = match provider.spec.copy_encryption.as_ref() {
+        Some(encryption) => encryption.annotation_key.as_str(),
+        None => return Ok(()),
+    };
+    if key.starts_with(OPERATOR_ANNOTATION_PREFIX) {
+        return Err(format!(
+            "copyEncryption.annotationKey '{}' may not use the {} prefix, which is reserved for the operator",
+            key, OPERATOR_ANNOTATION_PREFIX
+        ));
+    }
+    let (prefix, name) = match key.split_once('/') {
+        Some((prefix, name)) => (Some(prefix), name),
+        None => (None, key),
+    };
+    let alphanumeric = |c: char| c.is_ascii_alphanumeric();
+    let valid_name = !name.is_empty()
+        && name.len() <= MAX_ANNOTATION_NAME_LEN
+        && name.starts_with(alphanumeric)
+        && name.ends_with(alphanumeric)
+        && name
+            .chars()
+            .all(|c| alphanumeric(c) || c == '-' || c == '_' || c == '.');
+    if !valid_name || !prefix.is_none_or(secret_template::is_dns_subdomain) {
+        return Err(format!(
+            "copyEncryption.annotationKey '{}' is not a valid annotation key",
+            key
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the encryption annotations the MaskConsumer's credentials
+/// Secret should have but doesn't, e.g. because they were removed.
+pub fn missing_secret_annotations(
+    secret: &Secret,
     instance: &MaskConsumer,
+) -> BTreeMap<String, String> {
+    let provider = match instance.status.as_ref().and_then(|s| s.provider.as_ref()) {
+        Some(provider) => provider,
+        None => return BTreeMap::new(),
+    };
+    let existing = secret.metadata.annotations.as_ref();
+    secret_annotations(provider)
+        .into_iter()
+        .filter(|(k, v)| existing.and_then(|a| a.get(k)) != Some(v))
+        .collect()
+}
+
+/// Adds the missing annotations to the MaskConsumer's credentials Secret.
+pub async fn annotate_secret(
+    client: Client,
+    namespace: &str,
+    name: &str,
+    annotations: BTreeMap<String, String>,
+) -> Result<(), Error> {
+    let api: Api<Secret> = Api::namespaced(client, namespace);
+    let patch = serde_json::json!({
+        "metadata": {
+            "annotations": annotations
+        }
+    });
+    api.patch(name, &Default::default(), &Patch::Merge(&patch))
+        .await
+        .context_kind_name("Secret", name)?;
+    Ok(())
+}
+
+/// Returns true if the credentials Secret was created for the MaskConsumer
+/// and its assigned MaskProvider. With a stable secret suffix, a Secret with
+/// the expected name may be a copy left behind by a previous MaskConsumer or
+/// made for a different MaskProvider, so the name alone isn't enough.
+pub fn is_consumer_secret(secret: &Secret, instance: &MaskConsumer) -> bool {
+    let provider = match instance.status.as_ref().and_then(|s| s.provider.as_ref()) {
+        Some(provider) => provider,
+        None => return false,
+    };
+    let labeled = secret
+        .metadata
+        .labels
+        .as_ref()
+        .and_then(|l| l.get(PROVIDER_UID_LABEL))
+        .is_some_and(|uid| *uid == provider.uid);
+    let owned = secret
+        .metadata
+        .owner_references
+        .iter()
+        .flatten()
+        .any(|or| Some(&or.uid) == instance.metadata.uid.as_ref());
+    labeled && owned
+}
+
+/// Returns the reason the existing Secret with the name of the MaskConsumer's
+/// credentials copy can't be adopted, or None if it's a copy the operator
+/// made and can be taken over. A copy is labeled with the UID of the
+/// MaskProvider it was made for, which must be the assigned MaskProvider,
+/// except with a stable secret suffix, where a copy made for a previous
+/// MaskProvider with the same suffix is expected. A copy made for another
+/// Mask is in use by it. Copies are only ever owned by MaskConsumers, so
+/// any other owner means the Secret is in use.
+pub fn secret_conflict_reason(secret: &Secret, instance: &MaskConsumer) -> Option<String> {
+    let provider = instance.status.as_ref()?.provider.as_ref()?;
+    let label = secret
+        .metadata
+        .labels
+        .as_ref()
+        .and_then(|l| l.get(PROVIDER_UID_LABEL));
+    let stable_suffix = !provider.secret.ends_with(&provider.uid);
+    match label {
+        None => return Some("was not created by vpn-operator".to_owned()),
+        Some(uid) if *uid != provider.uid && !stable_suffix => {
+            return Some(format!("holds credentials for MaskProvider {}", uid))
+        }
+        Some(_) => {}
+    }
+    // Names rendered from a template may be shared by the copies of
+    // different Masks, which must not take over each other's.
+    let mask = bounded_name(&instance.name_any(), "", MAX_LABEL_LEN);
+    if let Some(other) = secret
+        .metadata
+        .labels
+        .as_ref()
+        .and_then(|l| l.get(MASK_NAME_LABEL))
+        .filter(|other| **other != mask)
+    {
+        return Some(format!("holds credentials for Mask {}", other));
+    }
+    secret
+        .metadata
+        .owner_references
+        .iter()
+        .flatten()
+        .find(|or| or.kind != "MaskConsumer")
+        .map(|or| format!("is owned by {} {}", or.kind, or.name))
+}
+
+/// Returns the slot the MaskConsumer should attempt first
+/// with the MaskProvider, as specified by its slot affinity.
+fn preferred_slot(instance: &MaskConsumer, provider: &MaskProvider) -> Option<usize> {
+    instance
+        .spec
+        .slot_affinity
+        .as_ref()
+        .filter(|affinity| provider.metadata.uid.as_deref() == Some(&affinity.provider_uid))
+        .map(|affinity| affinity.slot)
+        .filter(|slot| *slot < provider.spec.max_slots)
+}
+
+/// Assigns a new MaskProvider to the Mask. Returns true if a MaskProvider
+/// was assigned, false otherwise. The names of any VpnAccounts that kept
+/// a MaskProvider from being assigned are added to `full_accounts`.
+async fn assign_provider_base(
+    client: Client,
+    instance: &MaskConsumer,
+    placement: &Placement<'_>,
     providers: &Vec<MaskProvider>,
-) -> Result<bool, Error> {
+    accounts: &Accounts,
+    admission: &QuotaAdmission,
+    full_accounts: &mut Vec<String>,
+) -> Result<SlotOutcome, Error> {
+    let name = instance.metadata.name.as_deref().unwrap();
+    let namespace = instance.metadata.namespace.as_deref().unwrap();
     for provider in providers {
-        if try_reserve_slot(client.clone(), name, namespace, instance, provider).await? {
-            return Ok(true);
+        // Hold the account's admission until the slot is reserved.
+        let _guard = match accounts.admit(client.clone(), provider).await? {
+            Admission::Unrestricted => None,
+            Admission::Admitted(guard) => Some(guard),
+            Admission::Full(account) => {
+                if !full_accounts.contains(&account) {
+                    full_accounts.push(account);
+                }
+                continue;
+            }
+        };
+        match try_reserve_slot(
+            client.clone(),
+            name,
+            namespace,
+            instance,
+            provider,
+            placement,
+            admission,
+        )
+        .await?
+        {
+            SlotOutcome::Full => continue,
+            outcome => return Ok(outcome),
         }
     }
-    Ok(false)
+    Ok(SlotOutcome::Full)
 }
 
 /// Lists all MaskProvider resources, cluster-wide, that are in the Active phase.
 /// An optional filter can specified, in which case only MaskProviders with a
-/// matching tags will be returned.
-async fn list_active_providers(
+/// matching tags will be returned. MaskProviders outside of their availability
+/// hours at `now` are excluded.
+pub async fn list_active_providers(
     client: Client,
     filter_tags: Option<&Vec<String>>,
     mask_namespace: &str,
+    now: DateTime<Utc>,
 ) -> Result<Vec<MaskProvider>, Error> {
     let api: Api<MaskProvider> = Api::all(client);
-    let mut providers: Vec<MaskProvider> = api
-        .list(&Default::default())
+    let mut providers: Vec<MaskProvider> = list_all_paginated(&api, &Default::default())
         .await?
         .into_iter()
         .filter(|p| p.metadata.deletion_timestamp.is_none())
@@ -260,29 +1162,27 @@ async fn list_active_providers(
             p.spec
                 .namespaces
                 .as_ref()
-                .map_or(true, |ns| ns.iter().any(|n| n == mask_namespace))
+                .is_none_or(|ns| ns.iter().any(|n| n == mask_namespace))
         })
         .filter(|p| {
             // Ignore MaskProviders that aren't in the Ready or Active phases.
             p.status
                 .as_ref()
-                .map_or(None, |s| s.phase)
-                .map_or(false, |p| {
-                    p == MaskProviderPhase::Ready || p == MaskProviderPhase::Active
-                })
+                .and_then(|s| s.phase)
+                .is_some_and(|p| p == MaskProviderPhase::Ready || p == MaskProviderPhase::Active)
         })
+        // Ignore MaskProviders whose availability hours are over.
+        .filter(|p| schedule::is_available(p, now))
         .collect();
-    if let Some(ref filter_tags) = filter_tags {
+    if let Some(filter_tags) = filter_tags {
         // The Mask is asking for one or more specific MaskProviders.
         // Only return MaskProviders with matching tags.
-        providers = providers
-            .into_iter()
-            .filter(|p| {
-                p.spec.tags.as_ref().map_or(false, |t| {
-                    t.iter().any(|v| filter_tags.iter().any(|l| l == v))
-                })
-            })
-            .collect();
+        providers.retain(|p| {
+            p.spec
+                .tags
+                .as_ref()
+                .is_some_and(|t| t.iter().any(|v| filter_tags.iter().any(|l| l == v)))
+        });
     }
     Ok(providers)
 }
@@ -290,29 +1190,37 @@ async fn list_active_providers(
 /// Prunes dangling slots for a given `MaskProvider`.
 async fn prune_provider(client: Client, provider: &MaskProvider) -> Result<bool, Error> {
     let mut pruned = false;
-    let name = provider.metadata.name.as_deref().unwrap();
-    let namespace = provider.metadata.namespace.as_deref().unwrap();
-    let mr_api: Api<MaskReservation> = Api::namespaced(client.clone(), namespace);
     for slot in 0..provider.spec.max_slots {
-        let reservation_name = format!("{}-{}", name, slot);
-        if !check_prune(client.clone(), namespace, provider, slot, &reservation_name).await? {
-            continue;
-        }
-        mr_api
-            .delete(&reservation_name, &Default::default())
-            .await?;
-        pruned = true;
+        pruned |= prune_slot(client.clone(), provider, slot).await?;
     }
     Ok(pruned)
 }
 
+/// Deletes the slot's MaskReservation if it is dangling.
+/// Returns true if the MaskReservation was deleted.
+async fn prune_slot(client: Client, provider: &MaskProvider, slot: usize) -> Result<bool, Error> {
+    let name = provider.metadata.name.as_deref().unwrap();
+    let namespace = provider.metadata.namespace.as_deref().unwrap();
+    let reservation_name = reservation_name(name, slot);
+    if !check_prune(client.clone(), namespace, provider, slot, &reservation_name).await? {
+        return Ok(false);
+    }
+    let mr_api: Api<MaskReservation> = Api::namespaced(client, namespace);
+    match mr_api.delete(&reservation_name, &Default::default()).await {
+        Ok(_) => Ok(true),
+        // The MaskReservation was deleted in the meantime, so the slot is free.
+        Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(true),
+        Err(e) => Err(e).context_kind_name("MaskReservation", &reservation_name),
+    }
+}
+
 /// Deletes dangling reservations that no longer have associated MaskConsumers.
 /// These shouldn't occur under normal operation as the finalizers should prevent
 /// the MaskReservation resources from being deleted before their MaskConsumers.
 async fn prune(client: Client) -> Result<bool, Error> {
     let mut pruned = false;
     let provider_api: Api<MaskProvider> = Api::all(client.clone());
-    let providers = provider_api.list(&Default::default()).await?;
+    let providers = list_all_paginated(&provider_api, &Default::default()).await?;
     for provider in &providers {
         if prune_provider(client.clone(), provider).await? {
             pruned = true;
@@ -326,8 +1234,12 @@ async fn prune(client: Client) -> Result<bool, Error> {
 /// garbage collect the slots for a `MaskProvider`.
 pub async fn delete(client: Client, name: &str, namespace: &str) -> Result<(), Error> {
     let mr_api: Api<MaskConsumer> = Api::namespaced(client, namespace);
-    mr_api.delete(name, &Default::default()).await?;
-    Ok(())
+    match mr_api.delete(name, &Default::default()).await {
+        Ok(_) => Ok(()),
+        // The MaskConsumer was deleted in the meantime, e.g. with its namespace.
+        Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(()),
+        Err(e) => Err(e).context_kind_name("MaskConsumer", name),
+    }
 }
 
 /// Returns true if the slot needs to be garbage collected. Under normal operation
@@ -343,14 +1255,14 @@ async fn check_prune(
     let provider_uid = provider.metadata.uid.as_deref().unwrap();
     // Start by getting the slot's MaskReservation.
     let mr_api: Api<MaskReservation> = Api::namespaced(client.clone(), namespace);
-    let reservation = match mr_api.get(&reservation_name).await {
+    let reservation = match mr_api.get(reservation_name).await {
         // Don't garbage collect slots unless they belong to the MaskProvider.
         Ok(reservation)
             if reservation
                 .metadata
                 .owner_references
                 .as_ref()
-                .map_or(false, |o| o.iter().any(|r| r.uid == provider_uid)) =>
+                .is_some_and(|o| o.iter().any(|r| r.uid == provider_uid)) =>
         {
             // This MaskReservation belongs to the MaskProvider.
             reservation
@@ -375,7 +1287,7 @@ async fn check_prune(
         // Associated MaskConsumer no longer exists. Garbage collect it.
         Err(kube::Error::Api(e)) if e.code == 404 => Ok(true),
         // Error getting MaskConsumer resource.
-        Err(e) => return Err(e.into()),
+        Err(e) => Err(e.into()),
     }
 }
 
@@ -392,7 +1304,7 @@ fn consumer_uses_reservation(
         .unwrap()
         .provider
         .as_ref()
-        .map_or(false, |assigned| {
+        .is_some_and(|assigned| {
             provider.metadata.name.as_deref() == Some(&assigned.name)
                 && provider.metadata.namespace.as_deref() == Some(&assigned.namespace)
                 && assigned.slot == slot
@@ -407,14 +1319,14 @@ pub async fn create_reservation(
     provider: &MaskProvider,
     slot: usize,
     owner_uid: &str,
+    marks: &BTreeMap<String, String>,
 ) -> Result<MaskReservation, kube::Error> {
     let mr_api: Api<MaskReservation> = Api::namespaced(client, namespace);
     let mr = MaskReservation {
         metadata: ObjectMeta {
-            name: Some(format!(
-                "{}-{}",
+            name: Some(reservation_name(
                 provider.metadata.name.as_deref().unwrap(),
-                slot
+                slot,
             )),
             namespace: provider.metadata.namespace.clone(),
             // Set the MaskProvider as the owner reference so the
@@ -425,72 +1337,127 @@ pub async fn create_reservation(
             // MaskProvider resource. This ensure they are all
             // no matter how quickly it is recreated.
             owner_references: Some(vec![provider.controller_owner_ref(&()).unwrap()]),
+            labels: Some({
+                let mut labels = BTreeMap::new();
+                // Let the API server filter the MaskProvider's reservations.
+                labels.insert(
+                    PROVIDER_UID_LABEL.to_owned(),
+                    provider.metadata.uid.clone().unwrap(),
+                );
+                // And those counting towards the namespace's MaskQuotas.
+                labels.insert(CONSUMER_NAMESPACE_LABEL.to_owned(), namespace.to_owned());
+                // Mark reservations made for verifying or testing the MaskProvider.
+                labels.extend(marks.clone());
+                labels
+            }),
             ..Default::default()
         },
         spec: MaskReservationSpec {
             name: name.to_owned(),
             namespace: namespace.to_owned(),
             uid: owner_uid.to_owned(),
+            slot: Some(slot),
         },
         ..Default::default()
     };
-    Ok(mr_api.create(&Default::default(), &mr).await?)
+    mr_api.create(&Default::default(), &mr).await
 }
 
-/// Returns a list of inactive slot numbers for the `MaskProvider`.
-pub async fn list_inactive_slots(
-    client: Client,
-    provider: &MaskProvider,
-) -> Result<Vec<usize>, Error> {
-    let active_slots = list_active_slots(client, provider).await?;
-    Ok((0..provider.spec.max_slots)
+/// Returns the slot numbers of the `MaskProvider` that none of its
+/// reservations hold.
+pub fn inactive_slots(provider: &MaskProvider, reservations: &[MaskReservation]) -> Vec<usize> {
+    let provider_name = provider.metadata.name.as_deref().unwrap();
+    let active_slots: Vec<usize> = reservations
+        .iter()
+        // Extract the slot numbers and ignore any that are malformed.
+        .filter_map(|mr| reservation_slot(mr, provider_name))
+        .collect();
+    (0..provider.spec.max_slots)
         .filter(|slot| !active_slots.contains(slot))
-        .collect())
+        .collect()
 }
 
-/// Returns a list of active slot numbers for the `MaskProvider`.
-pub async fn list_active_slots(
+/// Returns the assigned MaskProvider's secret resource, which contains
+/// the environment variables for connecting to a VPN server. The
+/// MaskProvider is found by name, so one that replaced the assigned
+/// MaskProvider under the same name is refused with
+/// [`Error::ProviderDeparted`], as is one that is going away.
+pub async fn get_provider_secret(
     client: Client,
-    provider: &MaskProvider,
-) -> Result<Vec<usize>, Error> {
-    let provider_uid = provider.metadata.uid.as_deref().unwrap();
-    let mr_api: Api<MaskReservation> = Api::namespaced(
-        client.clone(),
-        provider.metadata.namespace.as_deref().unwrap(),
-    );
-    Ok(mr_api
-        .list(&Default::default())
-        .await?
-        .into_iter()
-        .map(|cm| cm.metadata)
-        .filter(|meta| {
-            // Filter out MaskReservations that don't belong to the MaskProvider.
-            meta.owner_references
-                .as_ref()
-                .map_or(false, |orefs| orefs.iter().any(|o| o.uid == provider_uid))
-        })
-        .filter_map(|meta| {
-            // Extract the slot numbers and ignore any that are malformed.
-            meta.name
-                .as_ref()
-                .unwrap()
-                .split('-')
-                .last()
-                .map(|slot| slot.parse::<usize>().ok())
-                .flatten()
-        })
-        .collect())
+    provider: &AssignedProvider,
+) -> Result<Secret, Error> {
+    match find_provider(client.clone(), provider).await? {
+        Some(found) if !is_departing(Some(&found), provider) => {
+            read_provider_secret(client, &found).await
+        }
+        _ => Err(Error::ProviderDeparted(format!(
+            "{}/{}",
+            provider.namespace, provider.name
+        ))),
+    }
 }
 
-/// Returns the MaskProvider's secret resource, which contains the
-/// environment variables for connecting to a VPN server.
-async fn get_provider_secret(client: Client, name: &str, namespace: &str) -> Result<Secret, Error> {
-    // Get the MaskProvider resource.
-    let provider_api: Api<MaskProvider> = Api::namespaced(client.clone(), namespace);
-    let provider = provider_api.get(name).await?;
-    // Get the referenced Secret.
-    let secret_api: Api<Secret> = Api::namespaced(client, namespace);
-    Ok(secret_api.get(&provider.spec.secret).await?)
+/// Returns the referenced Secret of the MaskProvider, or its transformed
+/// credentials.
+async fn read_provider_secret(client: Client, provider: &MaskProvider) -> Result<Secret, Error> {
+    let secret_name = effective_secret_name(provider);
+    let secret_api: Api<Secret> =
+        Api::namespaced(client, provider.metadata.namespace.as_deref().unwrap());
+    secret_api
+        .get(&secret_name)
+        .await
+        .context_kind_name("Secret", &secret_name)
+}
+
+/// Returns true if the assigned MaskProvider is gone, was replaced by
+/// another of the same name, or is being deleted. Its credentials must
+/// not be copied then, or the copy could outlive it.
+pub fn is_departing(found: Option<&MaskProvider>, provider: &AssignedProvider) -> bool {
+    match found {
+        Some(found) => {
+            found.metadata.uid.as_deref() != Some(provider.uid.as_str())
+                || found.metadata.deletion_timestamp.is_some()
+        }
+        None => true,
+    }
+}
+
+/// Gets the assigned MaskProvider, if it still exists.
+async fn find_provider(
+    client: Client,
+    provider: &AssignedProvider,
+) -> Result<Option<MaskProvider>, Error> {
+    let api: Api<MaskProvider> = Api::namespaced(client, &provider.namespace);
+    api.get_opt(&provider.name)
+        .await
+        .context_kind_name("MaskProvider", &provider.name)
+}
+
+/// Returns true if the assigned MaskProvider is going away.
+pub async fn provider_departing(
+    client: Client,
+    provider: &AssignedProvider,
+) -> Result<bool, Error> {
+    let found = find_provider(client, provider).await?;
+    Ok(is_departing(found.as_ref(), provider))
+}
+
+/// Returns true if another MaskProvider of the same name replaced the
+/// assigned MaskProvider.
+pub async fn provider_replaced(client: Client, provider: &AssignedProvider) -> Result<bool, Error> {
+    let found = find_provider(client, provider).await?;
+    Ok(found.is_some_and(|found| found.metadata.uid.as_deref() != Some(provider.uid.as_str())))
+}
+
+/// Returns the MaskProvider assigned to the MaskConsumer, or
+/// [`IncompleteStatus`](Error::IncompleteStatus) if its status doesn't
+/// record one, e.g. because it was reset since the action was decided.
+pub fn assigned_provider(instance: &MaskConsumer) -> Result<&AssignedProvider, Error> {
+    instance
+        .status
+        .as_ref()
+        .and_then(|s| s.provider.as_ref())
+        .ok_or_else(|| incomplete_status(instance, "the assigned MaskProvider"))
 }
 
 /// Creates the secret for the Mask to use. It is a copy of the MaskProvider's secret.
@@ -499,28 +1466,263 @@ pub async fn create_secret(
     namespace: &str,
     instance: &MaskConsumer,
 ) -> Result<(), Error> {
-    let provider = instance.status.as_ref().unwrap().provider.as_ref().unwrap();
-    let provider_secret =
-        get_provider_secret(client.clone(), &provider.name, &provider.namespace).await?;
+    let provider = assigned_provider(instance)?;
+    // The MaskProvider may have started going away since the action was
+    // decided. Its unassignment may already have deleted the copy, so
+    // don't bring it back. The MaskConsumer is deleted next reconcile.
+    let provider_secret = match get_provider_secret(client.clone(), provider).await {
+        Ok(secret) => secret,
+        Err(Error::ProviderDeparted(_)) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let secret = match consumer_secret(namespace, instance, provider_secret) {
+        Ok(secret) => secret,
+        // Show why the credentials can't be copied in the status.
+        Err(Error::InvalidSpec(message)) => return invalid_spec(client, instance, message).await,
+        Err(e) => return Err(e),
+    };
+    let api: Api<Secret> = Api::namespaced(client.clone(), namespace);
+    match api.create(&Default::default(), &secret).await {
+        Ok(_) => Ok(()),
+        // A Secret with the name already exists. Take it over if it's a
+        // copy the operator left behind, otherwise leave it alone.
+        Err(kube::Error::Api(ae)) if ae.code == 409 => {
+            let name = secret.name_any();
+            let existing = api.get(&name).await.context_kind_name("Secret", &name)?;
+            match secret_conflict_reason(&existing, instance) {
+                None => adopt_secret(api, existing, secret).await,
+                // The predecessor's copy is adopted once it's going away.
+                Some(_)
+                    if succession::is_predecessor_copy(&existing, instance)
+                        && succession::predecessor_released(client.clone(), instance).await? =>
+                {
+                    adopt_secret(api, existing, secret).await
+                }
+                Some(reason) => {
+                    let message = messages::err_secret_conflict(namespace, &name, &reason);
+                    secret_conflict(client, instance, message).await
+                }
+            }
+        }
+        Err(e) => Err(e).context_kind_name("Secret", &secret.name_any()),
+    }
+}
+
+/// Copies the MaskProvider's current credentials over the MaskConsumer's
+/// stale copy `existing`, in place like when the copy is adopted.
+pub async fn sync_secret(
+    client: Client,
+    namespace: &str,
+    instance: &MaskConsumer,
+    existing: Secret,
+) -> Result<(), Error> {
+    let provider = assigned_provider(instance)?;
+    // Copying a MaskProvider that replaced the assigned one would mix up
+    // their credentials, so leave the copy for the MaskConsumer's deletion.
+    let provider_secret = match get_provider_secret(client.clone(), provider).await {
+        Ok(secret) => secret,
+        Err(Error::ProviderDeparted(_)) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let secret = match consumer_secret(namespace, instance, provider_secret) {
+        Ok(secret) => secret,
+        // The stale copy is left alone until the credentials allow it.
+        Err(Error::InvalidSpec(message)) => return invalid_spec(client, instance, message).await,
+        Err(e) => return Err(e),
+    };
+    let api: Api<Secret> = Api::namespaced(client, namespace);
+    adopt_secret(api, existing, secret).await
+}
+
+/// Creates the Service requested with the MaskConsumer's `spec.service`.
+/// A Service with the name that appeared since the action was decided is
+/// left alone, and the next reconcile decides what to do with it.
+pub async fn create_service(
+    client: Client,
+    namespace: &str,
+    service: Service,
+) -> Result<(), Error> {
+    let api: Api<Service> = Api::namespaced(client, namespace);
+    match api.create(&Default::default(), &service).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(ae)) if ae.code == 409 => Ok(()),
+        Err(e) => Err(e).context_kind_name("Service", &service.name_any()),
+    }
+}
+
+/// Overwrites the type, selector and ports of the Service the MaskConsumer
+/// owns with the desired ones, leaving the fields filled in by the API
+/// server, such as the cluster IP, alone.
+pub async fn patch_service(client: Client, namespace: &str, service: Service) -> Result<(), Error> {
+    let name = service.name_any();
+    let spec = service.spec.unwrap_or_default();
+    // Adding a member that exists replaces it.
+    let add = |path: &str, value: Value| {
+        PatchOperation::Add(AddOperation {
+            path: path.to_owned(),
+            value,
+        })
+    };
+    let patch = json_patch::Patch(vec![
+        add("/spec/type", serde_json::to_value(spec.type_).unwrap()),
+        add(
+            "/spec/selector",
+            serde_json::to_value(spec.selector).unwrap(),
+        ),
+        add("/spec/ports", serde_json::to_value(spec.ports).unwrap()),
+    ]);
+    let api: Api<Service> = Api::namespaced(client, namespace);
+    api.patch(&name, &PatchParams::default(), &Patch::Json::<()>(patch))
+        .await
+        .context_kind_name("Service", &name)?;
+    Ok(())
+}
+
+/// Keeps the MaskConsumer Active, but shows in its status that a Service
+/// with its name that it doesn't own is in the way of the one requested.
+pub async fn service_conflict(
+    client: Client,
+    instance: &MaskConsumer,
+    message: String,
+) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.set_phase(MaskConsumerPhase::Active, message);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Records the name of the Service the MaskConsumer owns in its status.
+pub async fn record_service(
+    client: Client,
+    instance: &MaskConsumer,
+    name: String,
+) -> Result<(), Error> {
+    patch_status(client, instance, move |status| {
+        status.service = Some(name);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Records the checksum of the credentials Secret in the MaskConsumer's
+/// status, after rolling out the opted-in workloads that use it if
+/// `rollout` is set with `--annotate-consuming-pods`.
+pub async fn record_secret_hash(
+    client: Client,
+    namespace: &str,
+    instance: &MaskConsumer,
+    hash: String,
+    rollout: bool,
+) -> Result<(), Error> {
+    if rollout {
+        let secret = &assigned_provider(instance)?.secret;
+        rollout::annotate_workloads(client.clone(), namespace, secret, &hash).await?;
+    }
+    patch_status(client, instance, move |status| {
+        if let Some(provider) = status.provider.as_mut() {
+            provider.secret_hash = Some(hash);
+        }
+    })
+    .await?;
+    Ok(())
+}
+
+/// Replaces the existing Secret with the MaskConsumer's copy in place,
+/// so consumers never observe the Secret missing. This updates both its
+/// owner and its data if they're stale. Immutable Secrets with different
+/// data can't be replaced, so they are deleted and the next reconciliation
+/// creates the copy.
+async fn adopt_secret(api: Api<Secret>, existing: Secret, mut secret: Secret) -> Result<(), Error> {
+    let name = secret.metadata.name.clone().unwrap();
+    if existing.immutable == Some(true) && existing.data != secret.data {
+        api.delete(
+            &name,
+            &DeleteParams {
+                preconditions: Some(Preconditions {
+                    uid: existing.metadata.uid,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )
+        .await
+        .context_kind_name("Secret", &name)?;
+        return Ok(());
+    }
+    secret.metadata.resource_version = existing.metadata.resource_version;
+    api.replace(&name, &Default::default(), &secret)
+        .await
+        .context_kind_name("Secret", &name)?;
+    Ok(())
+}
+
+/// Returns the credentials Secret for the MaskConsumer, built from the
+/// MaskProvider's secret according to the settings that were resolved
+/// when the MaskProvider was assigned. Fails if the file projection
+/// is invalid for the credentials.
+pub fn consumer_secret(
+    namespace: &str,
+    instance: &MaskConsumer,
+    provider_secret: Secret,
+) -> Result<Secret, Error> {
+    let provider = assigned_provider(instance)?;
+    // Consumers assigned before effective settings were recorded
+    // only use their explicit settings.
+    let settings = instance
+        .status
+        .as_ref()
+        .and_then(|s| s.effective_settings.clone())
+        .unwrap_or_else(|| instance.spec.settings.clone());
+    // Remember which credentials the copy is made from.
+    let source_hash = rollout::credentials_hash(provider_secret.data.as_ref());
+    // Inherit the data from the MaskProvider's secret,
+    // restricted to the allowed keys if specified.
+    let data = match settings.secret_keys {
+        Some(ref keys) => provider_secret
+            .data
+            .map(|data| data.into_iter().filter(|(k, _)| keys.contains(k)).collect()),
+        None => provider_secret.data,
+    };
+    // Lay out the credentials in the requested format, then add the
+    // projected files alongside them.
+    let data = data
+        .map(|data| {
+            let formatted =
+                gluetun::format_data(data.clone(), settings.secret_format.unwrap_or_default());
+            match settings.file_projection {
+                Some(ref projection) => projection::project(formatted, &data, projection),
+                None => Ok(formatted),
+            }
+        })
+        .transpose()?;
     let oref = instance.controller_owner_ref(&()).unwrap();
-    let secret = Secret {
+    Ok(Secret {
         metadata: ObjectMeta {
             name: Some(provider.secret.clone()),
             namespace: Some(namespace.to_owned()),
             // Delete the Secret when the Mask is deleted.
             owner_references: Some(vec![oref]),
-            labels: Some({
-                let mut labels = BTreeMap::new();
-                labels.insert(PROVIDER_UID_LABEL.to_owned(), provider.uid.clone());
-                labels
+            labels: Some(secret_labels(instance, provider)),
+            annotations: Some({
+                // Set first so the operator's own annotations take precedence.
+                let mut annotations = secret_annotations(provider);
+                // Let consumers tell when the credentials have changed.
+                annotations.insert(
+                    CREDENTIALS_HASH_ANNOTATION.to_owned(),
+                    rollout::credentials_hash(data.as_ref()),
+                );
+                annotations.insert(SOURCE_HASH_ANNOTATION.to_owned(), source_hash);
+                // Tell consumers which gluetun version the credentials are for.
+                if let Some(version) = provider.gluetun_version.as_ref() {
+                    annotations.insert(GLUETUN_VERSION_ANNOTATION.to_owned(), version.clone());
+                }
+                annotations
             }),
             ..Default::default()
         },
-        // Inherit all of the data from the MaskProvider's secret.
-        data: provider_secret.data,
+        data,
+        immutable: settings.immutable_secret.filter(|immutable| *immutable),
         ..Default::default()
-    };
-    let api: Api<Secret> = Api::namespaced(client, namespace);
-    api.create(&Default::default(), &secret).await?;
-    Ok(())
+    })
 }
@@ -0,0 +1,92 @@
+use chrono::{DateTime, Utc};
+use kube::{Api, Client};
+use vpn_types::*;
+
+use crate::util::{list::list_all_paginated, Error};
+
+/// Returns the name of the MaskConsumer's anti-affinity group, if any.
+pub fn group(instance: &MaskConsumer) -> Option<&str> {
+    instance
+        .spec
+        .anti_affinity
+        .as_ref()
+        .map(|a| a.group.as_str())
+}
+
+/// Returns the uid of the MaskProvider assigned to the MaskConsumer.
+fn assigned_uid(instance: &MaskConsumer) -> Option<&str> {
+    instance
+        .status
+        .as_ref()
+        .and_then(|s| s.provider.as_ref())
+        .map(|p| p.uid.as_str())
+}
+
+/// Lists the other MaskConsumers in the MaskConsumer's namespace that are
+/// in its anti-affinity group. Returns nothing if it isn't in a group.
+pub async fn list_members(
+    client: Client,
+    instance: &MaskConsumer,
+) -> Result<Vec<MaskConsumer>, Error> {
+    let group = match group(instance) {
+        Some(group) => group,
+        None => return Ok(Vec::new()),
+    };
+    let api: Api<MaskConsumer> =
+        Api::namespaced(client, instance.metadata.namespace.as_deref().unwrap());
+    Ok(list_all_paginated(&api, &Default::default())
+        .await?
+        .into_iter()
+        .filter(|mc| self::group(mc) == Some(group) && mc.metadata.uid != instance.metadata.uid)
+        .collect())
+}
+
+/// Removes the MaskProviders assigned to any of the members, including
+/// members that are being deleted, as they still hold the credentials.
+pub fn exclude(providers: Vec<MaskProvider>, members: &[MaskConsumer]) -> Vec<MaskProvider> {
+    providers
+        .into_iter()
+        .filter(|p| {
+            !members
+                .iter()
+                .any(|m| assigned_uid(m).is_some() && assigned_uid(m) == p.metadata.uid.as_deref())
+        })
+        .collect()
+}
+
+/// Returns the names of the members that are assigned a MaskProvider.
+pub fn assigned_members(members: &[MaskConsumer]) -> Vec<String> {
+    let mut names: Vec<String> = members
+        .iter()
+        .filter(|m| assigned_uid(m).is_some())
+        .filter_map(|m| m.metadata.name.clone())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Orders MaskConsumers by when they were created, then by name.
+fn seniority(instance: &MaskConsumer) -> (Option<DateTime<Utc>>, Option<&str>) {
+    (
+        instance.metadata.creation_timestamp.as_ref().map(|t| t.0),
+        instance.metadata.name.as_deref(),
+    )
+}
+
+/// Returns the member that is assigned the same MaskProvider as the
+/// MaskConsumer and was created before it, in which case the member
+/// keeps the MaskProvider and the MaskConsumer has to be reassigned.
+/// Members that are being deleted are about to release the MaskProvider
+/// and are ignored.
+pub fn conflicting_member<'a>(
+    instance: &MaskConsumer,
+    members: &'a [MaskConsumer],
+) -> Option<&'a MaskConsumer> {
+    let uid = assigned_uid(instance)?;
+    members
+        .iter()
+        .filter(|m| m.metadata.deletion_timestamp.is_none())
+        .filter(|m| assigned_uid(m) == Some(uid))
+        .filter(|m| seniority(m) < seniority(instance))
+        .min_by(|a, b| seniority(a).cmp(&seniority(b)))
+}
@@ -0,0 +1,166 @@
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::{Pod, PodSpec};
+use kube::{Api, Client};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use vpn_types::*;
+
+use super::withdrawal::{is_finished, spec_references_secret};
+use crate::util::{clock, list::list_all_paginated, Error};
+
+/// Maximum number of records in [`MaskConsumerStatus::consumers`].
+/// The oldest records are dropped first.
+pub const MAX_RECORDS: usize = 10;
+
+/// How long a record is kept after its Pods were last seen using the
+/// credentials, long enough to answer who used them last month.
+pub const RECORD_RETENTION: Duration = Duration::from_secs(35 * 24 * 60 * 60);
+
+/// Returns the amount of time since the timestamp. Timestamps that can't
+/// be parsed are infinitely old, while those in the future, which can
+/// happen with clock skew, are brand new.
+fn since(timestamp: &str, now: DateTime<Utc>) -> Duration {
+    clock::parse_timestamp(timestamp)
+        .map_or(Duration::MAX, |t| (now - t).to_std().unwrap_or_default())
+}
+
+/// Merges the ServiceAccounts whose Pods currently use the credentials of
+/// `provider`, given as `namespace/name`, into the records. A record's
+/// `lastSeen` is only moved forward once it's older than `resolution`, so
+/// Pods that keep running don't cause a status update every reconcile.
+/// Records that haven't been seen for `retention` are pruned, and only
+/// the [`MAX_RECORDS`] most recently added records are kept.
+pub fn merge_records(
+    records: &[ConsumerRecord],
+    service_accounts: &BTreeSet<String>,
+    provider: &str,
+    now: DateTime<Utc>,
+    resolution: Duration,
+    retention: Duration,
+) -> Vec<ConsumerRecord> {
+    let timestamp = clock::format_timestamp(now);
+    let seen = |r: &ConsumerRecord| {
+        r.provider == provider && service_accounts.contains(&r.service_account)
+    };
+    let mut merged: Vec<ConsumerRecord> = records
+        .iter()
+        .filter(|r| seen(r) || since(&r.last_seen, now) <= retention)
+        .map(
+            |r| match seen(r) && since(&r.last_seen, now) >= resolution {
+                true => ConsumerRecord {
+                    last_seen: timestamp.clone(),
+                    ..r.clone()
+                },
+                false => r.clone(),
+            },
+        )
+        .collect();
+    for service_account in service_accounts {
+        if !merged
+            .iter()
+            .any(|r| r.provider == provider && &r.service_account == service_account)
+        {
+            merged.push(ConsumerRecord {
+                service_account: service_account.clone(),
+                provider: provider.to_owned(),
+                first_seen: timestamp.clone(),
+                last_seen: timestamp.clone(),
+            });
+        }
+    }
+    let excess = merged.len().saturating_sub(MAX_RECORDS);
+    merged.drain(..excess);
+    merged
+}
+
+/// Returns the name of the ServiceAccount the Pods with the spec run as.
+pub fn service_account(spec: &PodSpec) -> String {
+    spec.service_account_name
+        .clone()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "default".to_owned())
+}
+
+/// The labels and specs of a namespace's Pods that haven't exited, and
+/// when they were listed.
+type CachedPods = (Instant, Vec<(BTreeMap<String, String>, PodSpec)>);
+
+/// Finds the ServiceAccounts whose Pods use credentials. The Pods of
+/// each namespace are listed at most once every `ttl`, so auditing many
+/// MaskConsumers in the same namespace doesn't hammer the API server.
+pub struct PodUsage {
+    ttl: Duration,
+    cache: Mutex<HashMap<String, CachedPods>>,
+}
+
+impl PodUsage {
+    pub fn new(ttl: Duration) -> Self {
+        PodUsage {
+            ttl,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the ServiceAccounts of the Pods in the namespace that
+    /// haven't exited and reference the Secret.
+    pub async fn service_accounts(
+        &self,
+        client: Client,
+        namespace: &str,
+        secret: &str,
+    ) -> Result<BTreeSet<String>, Error> {
+        let pods = self.pods(client, namespace).await?;
+        Ok(pods
+            .iter()
+            .filter(|(_, spec)| spec_references_secret(spec, secret))
+            .map(|(_, spec)| service_account(spec))
+            .collect())
+    }
+
+    /// Returns true if any of the Pods in the namespace that haven't
+    /// exited references the Secret and carries all of the labels.
+    pub async fn any_labeled(
+        &self,
+        client: Client,
+        namespace: &str,
+        secret: &str,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<bool, Error> {
+        let pods = self.pods(client, namespace).await?;
+        Ok(pods.iter().any(|(pod_labels, spec)| {
+            spec_references_secret(spec, secret)
+                && labels.iter().all(|(k, v)| pod_labels.get(k) == Some(v))
+        }))
+    }
+
+    /// Returns the labels and specs of the namespace's Pods, using the
+    /// cache if possible.
+    async fn pods(
+        &self,
+        client: Client,
+        namespace: &str,
+    ) -> Result<Vec<(BTreeMap<String, String>, PodSpec)>, Error> {
+        if let Some((listed, pods)) = self.cache.lock().unwrap().get(namespace) {
+            if listed.elapsed() < self.ttl {
+                return Ok(pods.clone());
+            }
+        }
+        let api: Api<Pod> = Api::namespaced(client, namespace);
+        let pods: Vec<(BTreeMap<String, String>, PodSpec)> =
+            list_all_paginated(&api, &Default::default())
+                .await?
+                .into_iter()
+                .filter(|pod| !is_finished(pod))
+                .filter_map(|pod| Some((pod.metadata.labels.unwrap_or_default(), pod.spec?)))
+                .collect();
+        // Expired listings are dropped so namespaces that are no longer
+        // audited don't keep their Pods cached.
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (listed, _)| listed.elapsed() < self.ttl);
+        cache.insert(namespace.to_owned(), (Instant::now(), pods.clone()));
+        Ok(pods)
+    }
+}
@@ -0,0 +1,99 @@
+use kube::Client;
+use std::sync::Arc;
+use vpn_types::*;
+
+use super::namespaces::NamespaceCache;
+use crate::util::{Error, DEFAULT_PROVIDERS_ANNOTATION};
+
+/// The tags used to filter the MaskProviders a MaskConsumer may be
+/// assigned, and where they came from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProviderTags {
+    /// The tags in the MaskConsumer's `spec.providers`.
+    Spec(Vec<String>),
+
+    /// The tags in the namespace's default providers annotation,
+    /// used because the MaskConsumer doesn't specify any.
+    Namespace(Vec<String>),
+
+    /// Neither specifies any tags, so any MaskProvider may be assigned.
+    Any,
+}
+
+impl ProviderTags {
+    /// Returns the tags to use for the MaskConsumer. Its `spec.providers`
+    /// always wins over the namespace's default providers.
+    pub fn resolve(instance: &MaskConsumer, namespace_default: Option<Vec<String>>) -> Self {
+        match (instance.spec.providers.as_ref(), namespace_default) {
+            (Some(tags), _) => ProviderTags::Spec(tags.clone()),
+            (None, Some(tags)) => ProviderTags::Namespace(tags),
+            (None, None) => ProviderTags::Any,
+        }
+    }
+
+    /// Returns the tags to filter the MaskProviders with, if any.
+    pub fn filter(&self) -> Option<&Vec<String>> {
+        match self {
+            ProviderTags::Spec(tags) | ProviderTags::Namespace(tags) => Some(tags),
+            ProviderTags::Any => None,
+        }
+    }
+
+    /// Describes where the tags came from for the status message,
+    /// or None if no tags are used.
+    pub fn source(&self) -> Option<String> {
+        match self {
+            ProviderTags::Spec(tags) => Some(format!("spec.providers ({})", tags.join(", "))),
+            ProviderTags::Namespace(tags) => Some(format!(
+                "the namespace's {} annotation ({})",
+                DEFAULT_PROVIDERS_ANNOTATION,
+                tags.join(", "),
+            )),
+            ProviderTags::Any => None,
+        }
+    }
+}
+
+/// Parses the value of the default providers annotation, a comma-separated
+/// list of tags. Returns None if it doesn't contain any tags.
+pub fn parse_default_providers(value: &str) -> Option<Vec<String>> {
+    let tags: Vec<String> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_owned)
+        .collect();
+    if tags.is_empty() {
+        None
+    } else {
+        Some(tags)
+    }
+}
+
+/// Looks up the default providers of namespaces from their annotations,
+/// which come from the shared cache like the labels checked by
+/// [`NamespaceOptIn`](super::optin::NamespaceOptIn), so assigning many
+/// MaskConsumers in the same namespace doesn't hammer the API server.
+pub struct NamespaceDefaults {
+    namespaces: Arc<NamespaceCache>,
+}
+
+impl NamespaceDefaults {
+    pub fn new(namespaces: Arc<NamespaceCache>) -> Self {
+        NamespaceDefaults { namespaces }
+    }
+
+    /// Returns the default providers of the namespace, if it has any,
+    /// using the cache if possible.
+    pub async fn providers(
+        &self,
+        client: Client,
+        namespace: &str,
+    ) -> Result<Option<Vec<String>>, Error> {
+        let meta = self.namespaces.get(client, namespace).await?;
+        Ok(meta
+            .annotations
+            .get(DEFAULT_PROVIDERS_ANNOTATION)
+            .and_then(|value| parse_default_providers(value)))
+    }
+}
@@ -0,0 +1,40 @@
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+use vpn_types::*;
+
+use crate::util::{clock, HEARTBEAT_ANNOTATION};
+
+/// How long an external MaskConsumer may go without renewing its
+/// heartbeat, unless another is configured with `--external-heartbeat-timeout`.
+pub const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Returns true if the MaskConsumer was created by a client outside of
+/// the cluster, so no Pods are expected to use its credentials.
+pub fn is_external(instance: &MaskConsumer) -> bool {
+    instance.spec.external == Some(true)
+}
+
+/// Returns when the client last renewed the MaskConsumer's heartbeat, or
+/// when the MaskConsumer was created if it never did. A heartbeat that
+/// can't be parsed counts as never renewed.
+pub fn last_heartbeat(instance: &MaskConsumer) -> Option<DateTime<Utc>> {
+    instance
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|a| a.get(HEARTBEAT_ANNOTATION))
+        .and_then(|value| clock::parse_timestamp(value).ok())
+        .or_else(|| instance.metadata.creation_timestamp.as_ref().map(|t| t.0))
+}
+
+/// Returns true if the MaskConsumer is external and its client hasn't
+/// renewed the heartbeat within `timeout` as of `now`. Heartbeats from
+/// the future are current.
+pub fn heartbeat_expired(instance: &MaskConsumer, timeout: Duration, now: DateTime<Utc>) -> bool {
+    is_external(instance)
+        && last_heartbeat(instance).is_none_or(|at| {
+            now.signed_duration_since(at)
+                .to_std()
+                .is_ok_and(|age| age > timeout)
+        })
+}
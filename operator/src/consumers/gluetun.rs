@@ -0,0 +1,137 @@
+use k8s_openapi::ByteString;
+use std::collections::BTreeMap;
+use vpn_types::SecretFormat;
+
+/// Key of the rendered gluetun config file within the copied Secret.
+pub const CONFIG_KEY: &str = "config.toml";
+
+/// Prefixes of gluetun's environment variables, and the section of the
+/// config file that variables with each prefix are rendered to. The
+/// prefix is stripped and the rest of the name is lowercased to form
+/// the key, e.g. `OPENVPN_USER` becomes `user` under `[openvpn]`.
+pub const SECTIONS: [(&str, &str); 10] = [
+    ("VPN_", "vpn"),
+    ("OPENVPN_", "openvpn"),
+    ("WIREGUARD_", "wireguard"),
+    ("SERVER_", "server_selection"),
+    ("FIREWALL_", "firewall"),
+    ("DOT_", "dns"),
+    ("HTTPPROXY_", "http_proxy"),
+    ("SHADOWSOCKS_", "shadowsocks"),
+    ("HEALTH_", "health"),
+    ("UPDATER_", "updater"),
+];
+
+/// Section for variables without a well-known prefix. They keep their
+/// original names, so no credentials are lost in the rendering.
+pub const EXTRA_SECTION: &str = "extra";
+
+/// Returns the section and key that the variable is rendered to. Only
+/// names in upper snake case are mapped to a section, so the original
+/// name can always be recovered from the section and key.
+fn locate(name: &str) -> (&'static str, String) {
+    SECTIONS
+        .iter()
+        .find_map(|(prefix, section)| {
+            let rest = name.strip_prefix(prefix)?;
+            let upper_snake = !rest.is_empty()
+                && rest
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+            upper_snake.then(|| (*section, rest.to_ascii_lowercase()))
+        })
+        .unwrap_or((EXTRA_SECTION, name.to_owned()))
+}
+
+/// Renders the environment variables into a gluetun config file. The
+/// output only depends on the variables and not on their order, so
+/// reconciling the same credentials always yields the same file.
+pub fn render(env: &BTreeMap<String, String>) -> String {
+    let mut sections: BTreeMap<&str, BTreeMap<String, &str>> = BTreeMap::new();
+    for (name, value) in env {
+        let (section, key) = locate(name);
+        sections.entry(section).or_default().insert(key, value);
+    }
+    let order = SECTIONS
+        .iter()
+        .map(|(_, section)| *section)
+        .chain([EXTRA_SECTION]);
+    let mut out =
+        String::from("# Rendered by vpn-operator from the assigned MaskProvider's credentials.\n");
+    for section in order {
+        let entries = match sections.get(section) {
+            Some(entries) => entries,
+            None => continue,
+        };
+        out.push_str(&format!("\n[{}]\n", section));
+        for (key, value) in entries {
+            out.push_str(&format!("{} = {}\n", toml_key(key), toml_string(value)));
+        }
+    }
+    out
+}
+
+/// Returns the key as is if it's a valid bare key, or quoted otherwise.
+fn toml_key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare {
+        key.to_owned()
+    } else {
+        toml_string(key)
+    }
+}
+
+/// Returns the value as a TOML basic string.
+fn toml_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Lays out the credentials in the given format. Values that aren't valid
+/// UTF-8 can't be rendered into the config file, so they're always kept
+/// as their own keys. A `config.toml` key in the credentials themselves
+/// is replaced by the rendered file.
+pub fn format_data(
+    data: BTreeMap<String, ByteString>,
+    format: SecretFormat,
+) -> BTreeMap<String, ByteString> {
+    if format == SecretFormat::Env {
+        return data;
+    }
+    let mut env = BTreeMap::new();
+    let mut binary = BTreeMap::new();
+    for (key, value) in &data {
+        match std::str::from_utf8(&value.0) {
+            Ok(text) if key != CONFIG_KEY => {
+                env.insert(key.clone(), text.to_owned());
+            }
+            Ok(_) => {}
+            Err(_) => {
+                binary.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    let config = ByteString(render(&env).into_bytes());
+    let mut formatted = match format {
+        SecretFormat::Both => data,
+        _ => binary,
+    };
+    formatted.insert(CONFIG_KEY.to_owned(), config);
+    formatted
+}
@@ -1,4 +1,73 @@
-mod actions;
-mod reconcile;
+pub(crate) mod account;
+pub(crate) mod actions;
+pub(crate) mod anti_affinity;
+pub(crate) mod audit;
+pub(crate) mod default_providers;
+pub(crate) mod external;
+pub(crate) mod gluetun;
+pub(crate) mod namespaces;
+pub(crate) mod optin;
+pub(crate) mod policy;
+pub(crate) mod projection;
+pub(crate) mod quota;
+pub(crate) mod reconcile;
+pub(crate) mod required_labels;
+pub mod rollout;
+pub(crate) mod secret_template;
+pub(crate) mod selector;
+pub(crate) mod service;
+pub(crate) mod slots;
+pub(crate) mod succession;
+pub(crate) mod topology;
+pub(crate) mod withdrawal;
 
-pub use reconcile::run;
+pub use optin::OptInLabel;
+pub use reconcile::{run, ConsumerSettings};
+
+use crate::rbac::Rule;
+
+/// API access the MaskConsumer controller needs. See [`crate::rbac`].
+pub const RBAC: &[Rule] = &[
+    Rule::new(
+        "vpn.beebs.dev",
+        &["maskconsumers"],
+        &["get", "list", "watch", "patch"],
+    ),
+    Rule::new("vpn.beebs.dev", &["maskconsumers/status"], &["patch"]),
+    // Successions check that the predecessor's Mask is going away.
+    Rule::new("vpn.beebs.dev", &["masks"], &["get"]),
+    Rule::new(
+        "vpn.beebs.dev",
+        &["maskproviders"],
+        &["get", "list", "watch"],
+    ),
+    Rule::new(
+        "vpn.beebs.dev",
+        &["maskreservations"],
+        &["get", "list", "watch", "create", "patch", "delete"],
+    ),
+    Rule::new(
+        "vpn.beebs.dev",
+        &["maskquotas", "vpnaccounts"],
+        &["get", "list", "watch"],
+    ),
+    // The usage of each MaskQuota is shown in its status.
+    Rule::new("vpn.beebs.dev", &["maskquotas/status"], &["patch"]),
+    // The credentials are copied to the MaskConsumer's namespace.
+    Rule::new(
+        "",
+        &["secrets"],
+        &[
+            "get", "list", "watch", "create", "update", "patch", "delete",
+        ],
+    ),
+    // Opt-in labels, default providers and topology are read from the
+    // MaskConsumer's namespace.
+    Rule::new("", &["namespaces"], &["get", "list"]),
+    // Credentials are withdrawn from, and audited in, the Pods using them.
+    Rule::new("", &["pods"], &["list"]),
+    // Services requested by Masks expose the Pods using the credentials.
+    Rule::new("", &["services"], &["get", "create", "patch"]),
+    // Opted-in workloads are rolled out when their credentials change.
+    Rule::new("apps", &["deployments", "statefulsets"], &["list", "patch"]),
+];
@@ -0,0 +1,66 @@
+use k8s_openapi::api::core::v1::Namespace;
+use kube::{Api, Client};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::util::Error;
+
+/// The labels and annotations of a namespace, which is all the MaskConsumer
+/// controller reads from it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NamespaceMeta {
+    pub labels: BTreeMap<String, String>,
+    pub annotations: BTreeMap<String, String>,
+}
+
+/// A namespace's labels and annotations and when they were fetched.
+type CachedMeta = (Instant, Arc<NamespaceMeta>);
+
+/// Caches the labels and annotations of namespaces for `ttl`. The opt-in
+/// label, the default providers and the region of a namespace are all
+/// read from the same cached copy, so reconciling many MaskConsumers in
+/// the same namespace fetches it once per `ttl` rather than once per
+/// lookup. Expired namespaces are dropped whenever one is fetched, so
+/// deleted namespaces don't stay cached.
+pub struct NamespaceCache {
+    ttl: Duration,
+    cache: Mutex<HashMap<String, CachedMeta>>,
+}
+
+impl NamespaceCache {
+    pub fn new(ttl: Duration) -> Self {
+        NamespaceCache {
+            ttl,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the labels and annotations of the namespace, using the
+    /// cache if possible.
+    pub async fn get(&self, client: Client, namespace: &str) -> Result<Arc<NamespaceMeta>, Error> {
+        if let Some((fetched, meta)) = self.cache.lock().unwrap().get(namespace) {
+            if fetched.elapsed() < self.ttl {
+                return Ok(meta.clone());
+            }
+        }
+        let api: Api<Namespace> = Api::all(client);
+        let metadata = api.get(namespace).await?.metadata;
+        let meta = Arc::new(NamespaceMeta {
+            labels: metadata.labels.unwrap_or_default(),
+            annotations: metadata.annotations.unwrap_or_default(),
+        });
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (fetched, _)| fetched.elapsed() < self.ttl);
+        cache.insert(namespace.to_owned(), (Instant::now(), meta.clone()));
+        Ok(meta)
+    }
+
+    /// Returns the number of namespaces cached.
+    #[cfg(test)]
+    pub fn cached(&self) -> usize {
+        self.cache.lock().unwrap().len()
+    }
+}
@@ -0,0 +1,63 @@
+use kube::Client;
+use std::{fmt, str::FromStr, sync::Arc};
+
+use super::namespaces::NamespaceCache;
+use crate::util::Error;
+
+/// A label that a namespace must have in order to receive copies
+/// of VPN credentials, parsed from `key=value`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OptInLabel {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for OptInLabel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok(OptInLabel {
+                key: key.to_owned(),
+                value: value.to_owned(),
+            }),
+            _ => Err(format!(
+                "expected a label in the form key=value, got '{}'",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OptInLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+/// Checks whether namespaces are opted in to receiving copies of
+/// VPN credentials. Namespace labels come from the shared cache so
+/// that reconciling many MaskConsumers in the same namespace doesn't
+/// hammer the API server, while still noticing a label that was
+/// added later within a bounded amount of time.
+pub struct NamespaceOptIn {
+    label: OptInLabel,
+    namespaces: Arc<NamespaceCache>,
+}
+
+impl NamespaceOptIn {
+    pub fn new(label: OptInLabel, namespaces: Arc<NamespaceCache>) -> Self {
+        NamespaceOptIn { label, namespaces }
+    }
+
+    /// Returns the label the namespaces must have.
+    pub fn label(&self) -> &OptInLabel {
+        &self.label
+    }
+
+    /// Returns true if the namespace has the opt-in label.
+    pub async fn is_opted_in(&self, client: Client, namespace: &str) -> Result<bool, Error> {
+        let meta = self.namespaces.get(client, namespace).await?;
+        Ok(meta.labels.get(&self.label.key) == Some(&self.label.value))
+    }
+}
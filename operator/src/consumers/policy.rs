@@ -0,0 +1,177 @@
+use k8s_openapi::api::core::v1::Secret;
+use kube::{Api, Client};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use vpn_types::*;
+
+use crate::util::{consumer_purpose, messages, Error, SOURCE_HASH_ANNOTATION};
+
+/// The parts of a MaskProvider that decide which namespaces may keep
+/// using it after it was assigned, and whether their copies of its
+/// credentials are current.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProviderPolicy {
+    /// The MaskProvider's `spec.namespaces` allowlist, if any.
+    pub namespaces: Option<Vec<String>>,
+
+    /// What happens to MaskConsumers in namespaces that aren't permitted.
+    pub on_violation: PolicyViolationAction,
+
+    /// The checksum of the MaskProvider's credentials from its
+    /// `status.secretHash`, if it was recorded yet.
+    pub secret_hash: Option<String>,
+}
+
+impl ProviderPolicy {
+    /// Returns the policy of the MaskProvider.
+    pub fn of(provider: &MaskProvider) -> Self {
+        ProviderPolicy {
+            namespaces: provider.spec.namespaces.clone(),
+            on_violation: provider.spec.on_policy_violation.unwrap_or_default(),
+            secret_hash: provider.status.as_ref().and_then(|s| s.secret_hash.clone()),
+        }
+    }
+
+    /// Returns true if the copy of the credentials was made from other
+    /// credentials than the MaskProvider's current ones, going by their
+    /// checksums. Nothing is known to be stale until the MaskProvider
+    /// recorded its checksum.
+    pub fn is_stale(&self, secret: &Secret) -> bool {
+        let copied = secret
+            .metadata
+            .annotations
+            .as_ref()
+            .and_then(|a| a.get(SOURCE_HASH_ANNOTATION));
+        self.secret_hash
+            .as_ref()
+            .is_some_and(|hash| copied != Some(hash))
+    }
+
+    /// Returns true if MaskConsumers in the namespace may use the
+    /// MaskProvider. A MaskProvider without an allowlist permits
+    /// every namespace.
+    pub fn permits(&self, namespace: &str) -> bool {
+        self.namespaces
+            .as_ref()
+            .is_none_or(|namespaces| namespaces.iter().any(|n| n == namespace))
+    }
+}
+
+/// Outcome of re-checking an assigned MaskConsumer against the
+/// namespace allowlist of its MaskProvider.
+#[derive(Debug, PartialEq)]
+pub enum PolicyCheck {
+    /// The status already reflects the outcome.
+    Unchanged,
+
+    /// The namespace is permitted again, or violations are ignored, so the
+    /// violation shown in the status is cleared.
+    Cleared,
+
+    /// The violation is shown in the status and a Warning event.
+    /// Contains the message explaining it.
+    Warn(String),
+
+    /// The MaskConsumer's slot and credentials are released.
+    /// Contains the message explaining why.
+    Evict(String),
+}
+
+/// Re-checks that the assigned MaskProvider's policy still permits the
+/// MaskConsumer's namespace. `policy` is None if the MaskProvider is gone,
+/// which is handled when its reservations are deleted. MaskConsumers
+/// verifying the MaskProvider are never held to its allowlist.
+pub fn check(
+    instance: &MaskConsumer,
+    namespace: &str,
+    provider: &AssignedProvider,
+    policy: Option<&ProviderPolicy>,
+) -> PolicyCheck {
+    let shown = instance
+        .status
+        .as_ref()
+        .and_then(|status| status.policy_violation.as_deref());
+    let on_violation = match policy {
+        Some(policy)
+            if consumer_purpose(instance) == MaskConsumerPurpose::Workload
+                && !policy.permits(namespace) =>
+        {
+            policy.on_violation
+        }
+        _ => PolicyViolationAction::Ignore,
+    };
+    match on_violation {
+        PolicyViolationAction::Ignore if shown.is_some() => PolicyCheck::Cleared,
+        PolicyViolationAction::Ignore => PolicyCheck::Unchanged,
+        PolicyViolationAction::Warn => {
+            let message =
+                messages::policy_violation(namespace, &provider.namespace, &provider.name);
+            if shown == Some(message.as_str()) {
+                PolicyCheck::Unchanged
+            } else {
+                PolicyCheck::Warn(message)
+            }
+        }
+        PolicyViolationAction::Evict => PolicyCheck::Evict(messages::policy_eviction(
+            namespace,
+            &provider.namespace,
+            &provider.name,
+        )),
+    }
+}
+
+/// A MaskProvider's policy and when it was fetched. The policy is
+/// None if the MaskProvider doesn't exist.
+type CachedPolicy = (Instant, Option<ProviderPolicy>);
+
+/// Looks up the policies of assigned MaskProviders. Policies are cached
+/// for `ttl`, keyed by the MaskProvider's UID, so that re-checking many
+/// MaskConsumers assigned the same MaskProvider doesn't hammer the API
+/// server, while still noticing a change to its allowlist or credentials
+/// within a bounded amount of time.
+pub struct ProviderPolicies {
+    ttl: Duration,
+    cache: Mutex<HashMap<String, CachedPolicy>>,
+}
+
+impl ProviderPolicies {
+    pub fn new(ttl: Duration) -> Self {
+        ProviderPolicies {
+            ttl,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the policy of the assigned MaskProvider, using the cache
+    /// if possible, or None if it no longer exists.
+    pub async fn get(
+        &self,
+        client: Client,
+        provider: &AssignedProvider,
+    ) -> Result<Option<ProviderPolicy>, Error> {
+        if let Some((fetched, policy)) = self.cache.lock().unwrap().get(&provider.uid) {
+            if fetched.elapsed() < self.ttl {
+                return Ok(policy.clone());
+            }
+        }
+        let api: Api<MaskProvider> = Api::namespaced(client, &provider.namespace);
+        let policy = match api.get(&provider.name).await {
+            // A MaskProvider with the same name may have replaced it.
+            Ok(p) if p.metadata.uid.as_deref() == Some(provider.uid.as_str()) => {
+                Some(ProviderPolicy::of(&p))
+            }
+            Ok(_) => None,
+            Err(kube::Error::Api(ae)) if ae.code == 404 => None,
+            Err(e) => return Err(e.into()),
+        };
+        // Expired policies are dropped so deleted MaskProviders don't
+        // stay cached.
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (fetched, _)| fetched.elapsed() < self.ttl);
+        cache.insert(provider.uid.clone(), (Instant::now(), policy.clone()));
+        Ok(policy)
+    }
+}
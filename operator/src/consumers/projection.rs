@@ -0,0 +1,59 @@
+use k8s_openapi::ByteString;
+use std::collections::BTreeMap;
+
+use crate::util::Error;
+
+/// Maximum length of a key in a Secret's data.
+const MAX_KEY_LENGTH: usize = 253;
+
+/// Returns true if the name can be used as a key in a Secret's data,
+/// which is also the name of the file the key is mounted as.
+pub fn is_valid_key(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_KEY_LENGTH
+        && name != "."
+        && name != ".."
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// Adds the projected files to the copied credentials `data`. The
+/// source keys are looked up in `credentials`, which are the copied
+/// credentials before they were laid out, so files can be projected
+/// from keys that were rendered into the gluetun config file. Fails if
+/// a file name isn't a valid Secret key or would replace a key of the
+/// copy, or if its source key isn't among the copied credentials.
+pub fn project(
+    mut data: BTreeMap<String, ByteString>,
+    credentials: &BTreeMap<String, ByteString>,
+    projection: &BTreeMap<String, String>,
+) -> Result<BTreeMap<String, ByteString>, Error> {
+    for (file, source) in projection {
+        if !is_valid_key(file) {
+            return Err(invalid(format!(
+                "'{}' is not a valid file name, expected alphanumeric characters, '-', '_' or '.'",
+                file
+            )));
+        }
+        if data.contains_key(file) {
+            return Err(invalid(format!(
+                "file '{}' would replace the key of the same name",
+                file
+            )));
+        }
+        let value = credentials.get(source).ok_or_else(|| {
+            invalid(format!(
+                "source key '{}' of file '{}' is not among the copied credentials",
+                source, file
+            ))
+        })?;
+        data.insert(file.clone(), value.clone());
+    }
+    Ok(data)
+}
+
+/// Returns the error for an invalid file projection.
+fn invalid(message: String) -> Error {
+    Error::InvalidSpec(format!("spec.fileProjection: {}", message))
+}
@@ -0,0 +1,489 @@
+use futures::StreamExt;
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    runtime::{
+        reflector::{self, store::Writer, Store},
+        watcher,
+    },
+    Api, Client, Resource, ResourceExt,
+};
+use serde::de::DeserializeOwned;
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+use vpn_types::*;
+
+use crate::util::{
+    clock, consumer_purpose, is_canary_reservation, is_verification_reservation,
+    list::list_all_paginated, messages, Error, ErrorContext, CANARY_LABEL,
+    CONSUMER_NAMESPACE_LABEL, PROBE_INTERVAL, VERIFICATION_LABEL,
+};
+
+/// Slots reserved by the MaskConsumers in a namespace.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Usage {
+    /// Slots reserved with any MaskProvider.
+    pub slots: usize,
+
+    /// Slots reserved with the MaskProviders that have each tag.
+    pub tags: BTreeMap<String, usize>,
+}
+
+/// Counts the slots reserved by the MaskConsumers in the namespace.
+/// Reservations made for verification or canaries don't count, as they
+/// aren't made for the namespace's workloads.
+pub fn count_usage<P: Borrow<MaskProvider>, R: Borrow<MaskReservation>>(
+    namespace: &str,
+    providers: &[P],
+    reservations: &[R],
+) -> Usage {
+    let mut usage = Usage::default();
+    for reservation in counted(namespace, reservations) {
+        usage.slots += 1;
+        for tag in provider_tags(providers, reservation) {
+            *usage.tags.entry(tag.clone()).or_default() += 1;
+        }
+    }
+    usage
+}
+
+/// Returns the namespace's reservations that count towards its quotas.
+fn counted<'a, R: Borrow<MaskReservation>>(
+    namespace: &'a str,
+    reservations: &'a [R],
+) -> impl Iterator<Item = &'a MaskReservation> {
+    reservations.iter().map(Borrow::borrow).filter(move |r| {
+        r.spec.namespace == namespace
+            && !is_verification_reservation(r)
+            && !is_canary_reservation(r)
+    })
+}
+
+/// Returns the tags of the MaskProvider owning the reservation, which
+/// are none if it was deleted.
+fn provider_tags<'a, P: Borrow<MaskProvider>>(
+    providers: &'a [P],
+    reservation: &MaskReservation,
+) -> &'a [String] {
+    providers
+        .iter()
+        .map(Borrow::borrow)
+        .find(|p| {
+            reservation
+                .metadata
+                .owner_references
+                .iter()
+                .flatten()
+                .any(|or| p.metadata.uid.as_ref() == Some(&or.uid))
+        })
+        .and_then(|p| p.spec.tags.as_deref())
+        .unwrap_or_default()
+}
+
+/// The MaskProviders and MaskReservations that quotas are counted from,
+/// cached by watching them cluster-wide. Until both watches have listed
+/// everything, they're listed with every call instead, as counting from
+/// a partial cache would allow a quota to be exceeded.
+#[derive(Clone)]
+pub struct UsageCache {
+    providers: Store<MaskProvider>,
+    reservations: Store<MaskReservation>,
+    providers_ready: Arc<AtomicBool>,
+    reservations_ready: Arc<AtomicBool>,
+}
+
+impl UsageCache {
+    /// Returns a cache that's kept up to date by watches spawned on the
+    /// runtime for as long as the process runs.
+    pub fn watch(client: Client) -> Self {
+        let (providers, providers_writer) = reflector::store();
+        let (reservations, reservations_writer) = reflector::store();
+        let cache = UsageCache {
+            providers,
+            reservations,
+            providers_ready: Arc::new(AtomicBool::new(false)),
+            reservations_ready: Arc::new(AtomicBool::new(false)),
+        };
+        tokio::spawn(reflect(
+            Api::all(client.clone()),
+            ListParams::default(),
+            providers_writer,
+            cache.providers_ready.clone(),
+        ));
+        // Leave out reservations made for verification and canaries on the server.
+        tokio::spawn(reflect(
+            Api::all(client),
+            ListParams::default().labels(&format!("!{},!{}", VERIFICATION_LABEL, CANARY_LABEL)),
+            reservations_writer,
+            cache.reservations_ready.clone(),
+        ));
+        cache
+    }
+
+    /// Returns a cache that's never filled, so everything is always listed.
+    #[cfg(test)]
+    pub fn unwatched() -> Self {
+        UsageCache {
+            providers: reflector::store().0,
+            reservations: reflector::store().0,
+            providers_ready: Arc::new(AtomicBool::new(false)),
+            reservations_ready: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Returns the MaskProviders and the MaskReservations that may count
+    /// towards quotas, cluster-wide.
+    async fn snapshot(
+        &self,
+        client: Client,
+    ) -> Result<(Vec<Arc<MaskProvider>>, Vec<Arc<MaskReservation>>), Error> {
+        if self.providers_ready.load(Ordering::Acquire)
+            && self.reservations_ready.load(Ordering::Acquire)
+        {
+            return Ok((self.providers.state(), self.reservations.state()));
+        }
+        let api: Api<MaskProvider> = Api::all(client.clone());
+        let providers = list_all_paginated(&api, &Default::default()).await?;
+        let lp =
+            ListParams::default().labels(&format!("!{},!{}", VERIFICATION_LABEL, CANARY_LABEL));
+        let api: Api<MaskReservation> = Api::all(client);
+        let reservations = list_all_paginated(&api, &lp).await?;
+        Ok((
+            providers.into_iter().map(Arc::new).collect(),
+            reservations.into_iter().map(Arc::new).collect(),
+        ))
+    }
+}
+
+/// Keeps the store behind `writer` up to date with the resources, setting
+/// `ready` once they were all listed. Errors are logged and retried.
+async fn reflect<K>(api: Api<K>, lp: ListParams, writer: Writer<K>, ready: Arc<AtomicBool>)
+where
+    K: Resource + Clone + DeserializeOwned + Debug + Send + Sync + 'static,
+    K::DynamicType: Default + Eq + std::hash::Hash + Clone,
+{
+    let mut events = reflector::reflector(writer, watcher(api, lp)).boxed();
+    while let Some(event) = events.next().await {
+        match event {
+            Ok(watcher::Event::Restarted(_)) => ready.store(true, Ordering::Release),
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!(
+                    "Failed to watch {} for the usage of MaskQuotas: {}",
+                    K::plural(&Default::default()),
+                    e
+                );
+                tokio::time::sleep(PROBE_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Returns the message explaining why the quotas don't permit another
+/// slot to be reserved with the MaskProvider, or None if they do.
+pub fn exceeded(quotas: &[MaskQuota], usage: &Usage, provider: &MaskProvider) -> Option<String> {
+    for quota in quotas {
+        let name = quota.name_any();
+        if usage.slots >= quota.spec.max_slots {
+            return Some(messages::err_quota_exceeded(&name, quota.spec.max_slots));
+        }
+        let tagged = |tag: &String| provider.spec.tags.iter().flatten().any(|t| t == tag);
+        for limit in quota.spec.tags.iter().flatten() {
+            let used = usage.tags.get(&limit.tag).copied().unwrap_or_default();
+            if tagged(&limit.tag) && used >= limit.max_slots {
+                return Some(messages::err_tag_quota_exceeded(
+                    &name,
+                    &limit.tag,
+                    limit.max_slots,
+                ));
+            }
+        }
+    }
+    None
+}
+
+/// The MaskQuotas that apply to a MaskConsumer's assignment, and how
+/// much of them its namespace uses.
+#[derive(Default)]
+pub struct QuotaAdmission {
+    namespace: String,
+    quotas: Vec<MaskQuota>,
+    usage: Usage,
+
+    /// The MaskProviders the usage was counted from.
+    providers: Vec<Arc<MaskProvider>>,
+
+    /// The namespace's reservations the usage was counted from.
+    reservations: Vec<Arc<MaskReservation>>,
+
+    /// Held until the slot is reserved, so concurrent reconciliations
+    /// can't both take the last slot of a quota.
+    _guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl QuotaAdmission {
+    /// Splits the MaskProviders into those the quotas permit reserving a
+    /// slot with, and the messages explaining why the others aren't.
+    pub fn exclude(&self, providers: Vec<MaskProvider>) -> (Vec<MaskProvider>, Vec<String>) {
+        let mut reasons = Vec::new();
+        let permitted = providers
+            .into_iter()
+            .filter(|p| match exceeded(&self.quotas, &self.usage, p) {
+                Some(reason) => {
+                    if !reasons.contains(&reason) {
+                        reasons.push(reason);
+                    }
+                    false
+                }
+                None => true,
+            })
+            .collect();
+        (permitted, reasons)
+    }
+
+    /// Returns the message explaining why the reservation that was just
+    /// made with the MaskProvider has to be given up again, or None if it
+    /// can be kept. The lock held by the admission only keeps this replica
+    /// from taking the last slot of a quota twice, so the reservations are
+    /// listed afresh to find those made at the same time by other replicas,
+    /// or missing from the cache. The oldest of them keep the slots.
+    pub async fn confirm(
+        &self,
+        client: Client,
+        provider: &MaskProvider,
+        reservation: &MaskReservation,
+    ) -> Result<Option<String>, Error> {
+        if self.quotas.is_empty() {
+            return Ok(None);
+        }
+        let lp = ListParams::default().labels(&format!(
+            "{}={},!{},!{}",
+            CONSUMER_NAMESPACE_LABEL, self.namespace, VERIFICATION_LABEL, CANARY_LABEL
+        ));
+        let api: Api<MaskReservation> = Api::all(client);
+        let mut competing = list_all_paginated(&api, &lp).await?;
+        // Reservations made before the label was set are only in the cache.
+        competing.extend(
+            self.reservations
+                .iter()
+                .filter(|r| !r.labels().contains_key(CONSUMER_NAMESPACE_LABEL))
+                .map(|r| (**r).clone()),
+        );
+        competing.sort_by_key(|r| {
+            (
+                r.metadata.creation_timestamp.clone(),
+                r.namespace(),
+                r.name_any(),
+            )
+        });
+        let tags = |r: &MaskReservation| match r.metadata.uid == reservation.metadata.uid {
+            true => provider.spec.tags.as_deref().unwrap_or_default(),
+            false => provider_tags(&self.providers, r),
+        };
+        // Returns how many reservations matching the filter precede this one.
+        let rank = |filter: &dyn Fn(&MaskReservation) -> bool| {
+            competing
+                .iter()
+                .filter(|r| filter(r))
+                .take_while(|r| r.metadata.uid != reservation.metadata.uid)
+                .count()
+        };
+        for quota in &self.quotas {
+            let name = quota.name_any();
+            if rank(&|_| true) >= quota.spec.max_slots {
+                return Ok(Some(messages::err_quota_exceeded(
+                    &name,
+                    quota.spec.max_slots,
+                )));
+            }
+            let tagged = |tag: &String| provider.spec.tags.iter().flatten().any(|t| t == tag);
+            for limit in quota.spec.tags.iter().flatten() {
+                if tagged(&limit.tag) && rank(&|r| tags(r).contains(&limit.tag)) >= limit.max_slots
+                {
+                    return Ok(Some(messages::err_tag_quota_exceeded(
+                        &name,
+                        &limit.tag,
+                        limit.max_slots,
+                    )));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A namespace's MaskQuotas and when they were fetched.
+type CachedQuotas = (Instant, Vec<MaskQuota>);
+
+/// Enforces the MaskQuotas of the namespaces. The quotas are cached for
+/// `ttl`, as they rarely change, and the usage is counted from `usage`.
+/// Admission within each namespace is serialized within the controller,
+/// and each reservation is confirmed against the others made at the same
+/// time, e.g. by other replicas, with [`QuotaAdmission::confirm`].
+pub struct Quotas {
+    ttl: Duration,
+    usage: UsageCache,
+    quotas: Mutex<HashMap<String, CachedQuotas>>,
+    locks: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+impl Quotas {
+    pub fn new(ttl: Duration, usage: UsageCache) -> Self {
+        Quotas {
+            ttl,
+            usage,
+            quotas: Mutex::new(HashMap::new()),
+            locks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the cache the usage is counted from.
+    pub fn usage(&self) -> &UsageCache {
+        &self.usage
+    }
+
+    /// Returns the MaskQuotas that apply to the MaskConsumer's assignment,
+    /// along with its namespace's usage. Only workloads are subject to
+    /// quotas, so verification and canary MaskConsumers are unrestricted.
+    pub async fn admit(
+        &self,
+        client: Client,
+        instance: &MaskConsumer,
+    ) -> Result<QuotaAdmission, Error> {
+        if consumer_purpose(instance) != MaskConsumerPurpose::Workload {
+            return Ok(QuotaAdmission::default());
+        }
+        let namespace = instance.namespace().unwrap();
+        let quotas = self.quotas(client.clone(), &namespace).await?;
+        if quotas.is_empty() {
+            return Ok(QuotaAdmission::default());
+        }
+        let guard = self.lock(&namespace).await;
+        let (providers, reservations) = self.usage.snapshot(client).await?;
+        let reservations: Vec<_> = reservations
+            .into_iter()
+            .filter(|r| r.spec.namespace == namespace)
+            .collect();
+        Ok(QuotaAdmission {
+            usage: count_usage(&namespace, &providers, &reservations),
+            namespace,
+            quotas,
+            providers,
+            reservations,
+            _guard: Some(guard),
+        })
+    }
+
+    /// Acquires the lock that serializes admission within the namespace.
+    /// The locks nobody holds or waits for are dropped along the way.
+    async fn lock(&self, namespace: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(namespace.to_owned()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+
+    /// Returns the namespace's MaskQuotas ordered by name, using the cache
+    /// if possible. None are enforced if the CRD isn't installed.
+    async fn quotas(&self, client: Client, namespace: &str) -> Result<Vec<MaskQuota>, Error> {
+        if let Some((fetched, quotas)) = self.quotas.lock().unwrap().get(namespace) {
+            if fetched.elapsed() < self.ttl {
+                return Ok(quotas.clone());
+            }
+        }
+        let api: Api<MaskQuota> = Api::namespaced(client, namespace);
+        let mut quotas = match list_all_paginated(&api, &Default::default()).await {
+            Ok(quotas) => quotas,
+            Err(e) if e.status_code() == Some(404) => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        quotas.sort_by_key(|q| q.name_any());
+        let mut cache = self.quotas.lock().unwrap();
+        // Drop the namespaces whose quotas expired, e.g. because they're gone.
+        cache.retain(|_, (fetched, _)| fetched.elapsed() < self.ttl);
+        cache.insert(namespace.to_owned(), (Instant::now(), quotas.clone()));
+        Ok(quotas)
+    }
+}
+
+/// Returns the MaskQuota's status showing the namespace's usage.
+/// The last updated timestamp is left for the caller to set.
+pub fn quota_status(quota: &MaskQuota, usage: &Usage) -> MaskQuotaStatus {
+    let tags: Vec<MaskQuotaTagUsage> = quota
+        .spec
+        .tags
+        .iter()
+        .flatten()
+        .map(|limit| MaskQuotaTagUsage {
+            tag: limit.tag.clone(),
+            used_slots: usage.tags.get(&limit.tag).copied().unwrap_or_default(),
+            max_slots: limit.max_slots,
+        })
+        .collect();
+    MaskQuotaStatus {
+        used_slots: Some(usage.slots),
+        tags: Some(tags).filter(|tags| !tags.is_empty()),
+        last_updated: None,
+    }
+}
+
+/// Writes the current usage to the status of every MaskQuota whose
+/// status doesn't already show it.
+pub async fn update_usage(client: Client, cache: &UsageCache) -> Result<(), Error> {
+    let api: Api<MaskQuota> = Api::all(client.clone());
+    let quotas = match list_all_paginated(&api, &Default::default()).await {
+        Ok(quotas) => quotas,
+        // The CRD isn't installed, so there's nothing to update.
+        Err(e) if e.status_code() == Some(404) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if quotas.is_empty() {
+        return Ok(());
+    }
+    let (providers, reservations) = cache.snapshot(client.clone()).await?;
+    for quota in quotas {
+        let namespace = quota.namespace().unwrap();
+        let usage = count_usage(&namespace, &providers, &reservations);
+        let status = quota_status(&quota, &usage);
+        let current = quota
+            .status
+            .as_ref()
+            .is_some_and(|s| s.used_slots == status.used_slots && s.tags == status.tags);
+        if current {
+            continue;
+        }
+        let status = MaskQuotaStatus {
+            last_updated: Some(clock::now_k8s()),
+            ..status
+        };
+        let name = quota.name_any();
+        let api: Api<MaskQuota> = Api::namespaced(client.clone(), &namespace);
+        let patch = serde_json::json!({ "status": status });
+        api.patch_status(&name, &PatchParams::default(), &Patch::Merge(&patch))
+            .await
+            .context_kind_name("MaskQuota", &name)?;
+    }
+    Ok(())
+}
+
+/// Keeps the usage shown in the MaskQuotas' statuses up to date,
+/// refreshing it from `cache` every `interval`. Failures are logged and
+/// retried at the next interval.
+pub async fn run_usage_updater(client: Client, cache: UsageCache, interval: Duration) {
+    loop {
+        if let Err(e) = update_usage(client.clone(), &cache).await {
+            eprintln!("Failed to update the usage of MaskQuotas: {}", e);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -1,30 +1,81 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use futures::stream::StreamExt;
-use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::api::core::v1::{ObjectReference, Secret, Service};
 use kube::{
-    api::ListParams, client::Client, runtime::controller::Action, runtime::Controller, Api,
-    ResourceExt,
+    api::ListParams,
+    client::Client,
+    runtime::{controller::Action, events::Reporter, Controller},
+    Api, ResourceExt,
 };
-use std::sync::Arc;
-use tokio::time::Duration;
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::{sync::Semaphore, time::Duration};
 use vpn_types::*;
 
-use super::actions;
+use super::{
+    account::Accounts,
+    actions, anti_affinity,
+    audit::{self, PodUsage},
+    default_providers::{NamespaceDefaults, ProviderTags},
+    external,
+    namespaces::NamespaceCache,
+    optin::{NamespaceOptIn, OptInLabel},
+    policy::{self, PolicyCheck, ProviderPolicies},
+    quota::{self, Quotas, UsageCache},
+    rollout,
+    selector::ProviderSelector,
+    service,
+    slots::reservation_name,
+    succession,
+    topology::{self, NamespaceTopology},
+    withdrawal,
+};
 use crate::util::{
+    clock::Clock,
+    config::OperatorConfig,
+    events, explain,
     finalizer::{self, FINALIZER_NAME},
-    Error, PROBE_INTERVAL,
+    messages, needs_refresh,
+    patch::{ensure_status_initialized, record_action_error, status_phase},
+    report_reconcile_error, ControllerOptions, Error, ErrorContext, CANARY_LABEL, PROBE_INTERVAL,
 };
 
 #[cfg(feature = "metrics")]
 use crate::util::metrics::ControllerMetrics;
 
-/// Entrypoint for the `MaskConsumer` controller.
-pub async fn run(client: Client) -> Result<(), Error> {
+/// Entrypoint for the `MaskConsumer` controller. If `concurrency` is set, at most
+/// that many reconciliations will be performed at the same time. If `opt_in_label`
+/// is set, credentials are only copied into namespaces with that label. No new
+/// slots are reserved while `config` reports that assignments are frozen.
+/// The rest of the controller's `settings` come from the command line.
+pub async fn run(
+    client: Client,
+    concurrency: Option<usize>,
+    opt_in_label: Option<OptInLabel>,
+    config: Arc<OperatorConfig>,
+    selector: Arc<dyn ProviderSelector>,
+    settings: ConsumerSettings,
+    options: ControllerOptions,
+) -> Result<(), Error> {
     println!("Starting MaskConsumer controller...");
 
     // Preparation of resources used by the `kube_runtime::Controller`
     let crd_api: Api<MaskConsumer> = Api::all(client.clone());
-    let context: Arc<ContextData> = Arc::new(ContextData::new(client.clone()));
+    let context: Arc<ContextData> = Arc::new(ContextData::new(
+        client.clone(),
+        concurrency,
+        opt_in_label,
+        config,
+        selector,
+        settings,
+        options,
+    ));
+
+    // Show the namespaces' usage of their MaskQuotas.
+    tokio::spawn(quota::run_usage_updater(
+        client.clone(),
+        context.quotas.usage().clone(),
+        PROBE_INTERVAL,
+    ));
 
     // The controller comes from the `kube_runtime` crate and manages the reconciliation process.
     // It requires the following information:
@@ -49,11 +100,97 @@ pub async fn run(client: Client) -> Result<(), Error> {
     Ok(())
 }
 
+/// Settings of the MaskConsumer controller from the command line.
+#[derive(Clone, Debug)]
+pub struct ConsumerSettings {
+    /// How long a MaskConsumer with the `WaitForPods` deletion policy
+    /// waits for Pods using its credentials before deleting them anyway.
+    pub withdrawal_grace_period: Duration,
+
+    /// How long an unchanged status goes without being rewritten.
+    pub status_freshness: Duration,
+
+    /// Whether the opted-in workloads using a MaskConsumer's credentials
+    /// are rolled out when they change, with `--annotate-consuming-pods`.
+    pub rollout: bool,
+
+    /// Settings of the topology-aware strategy.
+    pub topology: topology::Settings,
+
+    /// How long external MaskConsumers may go without renewing their
+    /// heartbeat before they're deleted.
+    pub heartbeat_timeout: Duration,
+}
+
+impl Default for ConsumerSettings {
+    fn default() -> Self {
+        ConsumerSettings {
+            withdrawal_grace_period: Duration::from_secs(300),
+            status_freshness: Duration::from_secs(600),
+            rollout: false,
+            topology: Default::default(),
+            heartbeat_timeout: external::DEFAULT_HEARTBEAT_TIMEOUT,
+        }
+    }
+}
+
 /// Context injected with each `reconcile` and `on_error` method invocation.
-struct ContextData {
+pub(crate) struct ContextData {
     /// Kubernetes client to make Kubernetes API requests with. Required for K8S resource management.
     client: Client,
 
+    /// Limits the number of concurrent reconciliations, if configured.
+    semaphore: Option<Semaphore>,
+
+    /// Restricts credentials to opted-in namespaces, if configured.
+    namespace_opt_in: Option<NamespaceOptIn>,
+
+    /// Looks up the default providers of namespaces.
+    namespace_defaults: NamespaceDefaults,
+
+    /// Looks up the regions of namespaces for topology-aware assignment.
+    namespace_topology: NamespaceTopology,
+
+    /// Runtime configuration, used to check if assignments are frozen.
+    config: Arc<OperatorConfig>,
+
+    /// Enforces the connection ceilings of shared VpnAccounts.
+    accounts: Accounts,
+
+    /// Enforces the MaskQuotas of the MaskConsumers' namespaces.
+    quotas: Quotas,
+
+    /// Looks up the namespace allowlists of assigned MaskProviders.
+    policies: ProviderPolicies,
+
+    /// Finds the ServiceAccounts whose Pods use credentials.
+    pod_usage: PodUsage,
+
+    /// Source of the current time for checking availability hours.
+    clock: Clock,
+
+    /// Decides which MaskProviders are tried first during assignment.
+    selector: Arc<dyn ProviderSelector>,
+
+    /// How long a MaskConsumer with the `WaitForPods` deletion policy
+    /// waits for Pods using its credentials before deleting them anyway.
+    withdrawal_grace_period: Duration,
+
+    /// How long an unchanged status goes without being rewritten.
+    status_freshness: Duration,
+
+    /// Whether the opted-in workloads using the credentials are rolled out.
+    rollout: bool,
+
+    /// How long external MaskConsumers may go without renewing their heartbeat.
+    heartbeat_timeout: Duration,
+
+    /// Reports the resources' phase transitions as events.
+    reporter: Reporter,
+
+    /// Settings from the command line, e.g. whether actions are explained.
+    options: ControllerOptions,
+
     #[cfg(feature = "metrics")]
     metrics: ControllerMetrics,
 }
@@ -63,39 +200,260 @@ impl ContextData {
     ///
     /// # Arguments:
     /// - `client`: A Kubernetes client to make Kubernetes REST API requests with. Resources
-    /// will be created and deleted with this client.
-    pub fn new(client: Client) -> Self {
+    ///   will be created and deleted with this client.
+    /// - `concurrency`: Optional maximum number of concurrent reconciliations.
+    /// - `opt_in_label`: Optional label namespaces must have to receive credentials.
+    /// - `config`: Runtime configuration of the operator.
+    /// - `selector`: Orders the MaskProviders a MaskConsumer may be assigned.
+    /// - `settings`: Settings of the controller from the command line.
+    /// - `options`: Settings from the command line shared with the other controllers.
+    pub fn new(
+        client: Client,
+        concurrency: Option<usize>,
+        opt_in_label: Option<OptInLabel>,
+        config: Arc<OperatorConfig>,
+        selector: Arc<dyn ProviderSelector>,
+        settings: ConsumerSettings,
+        options: ControllerOptions,
+    ) -> Self {
+        let ConsumerSettings {
+            withdrawal_grace_period,
+            status_freshness,
+            rollout,
+            topology,
+            heartbeat_timeout,
+        } = settings;
+        let semaphore = concurrency.map(Semaphore::new);
+        // Namespace labels and annotations are re-checked every probe
+        // interval, and the opt-in, defaults and region share one copy.
+        let namespaces = Arc::new(NamespaceCache::new(PROBE_INTERVAL));
+        let namespace_opt_in =
+            opt_in_label.map(|label| NamespaceOptIn::new(label, namespaces.clone()));
+        let namespace_defaults = NamespaceDefaults::new(namespaces.clone());
+        let namespace_topology = NamespaceTopology::new(topology, namespaces);
+        // VpnAccount limits are re-fetched every probe interval.
+        let accounts = Accounts::new(PROBE_INTERVAL);
+        // MaskQuotas are re-fetched every probe interval, and their usage
+        // is counted from the watched MaskProviders and MaskReservations.
+        let quotas = Quotas::new(PROBE_INTERVAL, UsageCache::watch(client.clone()));
+        // MaskProvider allowlists are re-fetched every probe interval.
+        let policies = ProviderPolicies::new(PROBE_INTERVAL);
+        // Pods using credentials are re-listed every probe interval.
+        let pod_usage = PodUsage::new(PROBE_INTERVAL);
         #[cfg(feature = "metrics")]
         {
-            return ContextData {
+            ContextData {
                 client,
+                semaphore,
+                options,
+                reporter: events::reporter(),
+                namespace_opt_in,
+                namespace_defaults,
+                namespace_topology,
+                config,
+                accounts,
+                quotas,
+                policies,
+                pod_usage,
+                clock: Clock::System,
+                selector,
+                withdrawal_grace_period,
+                status_freshness,
+                rollout,
+                heartbeat_timeout,
                 metrics: ControllerMetrics::new("consumers"),
-            };
+            }
         }
         #[cfg(not(feature = "metrics"))]
         {
-            return ContextData { client };
+            return ContextData {
+                client,
+                semaphore,
+                options,
+                reporter: events::reporter(),
+                namespace_opt_in,
+                namespace_defaults,
+                namespace_topology,
+                config,
+                accounts,
+                quotas,
+                policies,
+                pod_usage,
+                clock: Clock::System,
+                selector,
+                withdrawal_grace_period,
+                status_freshness,
+                rollout,
+                heartbeat_timeout,
+            };
+        }
+    }
+
+    /// Constructs a ContextData with the operator's default settings whose
+    /// metrics aren't registered globally, so tests can construct many.
+    #[cfg(test)]
+    pub(crate) fn for_tests(
+        client: Client,
+        opt_in_label: Option<OptInLabel>,
+        config: Arc<OperatorConfig>,
+    ) -> Self {
+        let settings = ConsumerSettings::default();
+        let namespaces = Arc::new(NamespaceCache::new(PROBE_INTERVAL));
+        ContextData {
+            client,
+            semaphore: None,
+            options: ControllerOptions::default(),
+            reporter: events::reporter(),
+            namespace_opt_in: opt_in_label
+                .map(|label| NamespaceOptIn::new(label, namespaces.clone())),
+            namespace_defaults: NamespaceDefaults::new(namespaces.clone()),
+            namespace_topology: NamespaceTopology::new(settings.topology, namespaces),
+            config,
+            accounts: Accounts::new(PROBE_INTERVAL),
+            quotas: Quotas::new(PROBE_INTERVAL, UsageCache::unwatched()),
+            policies: ProviderPolicies::new(PROBE_INTERVAL),
+            pod_usage: PodUsage::new(PROBE_INTERVAL),
+            clock: Clock::System,
+            selector: Arc::new(super::selector::ListOrder),
+            withdrawal_grace_period: settings.withdrawal_grace_period,
+            status_freshness: settings.status_freshness,
+            rollout: settings.rollout,
+            heartbeat_timeout: settings.heartbeat_timeout,
+            #[cfg(feature = "metrics")]
+            metrics: ControllerMetrics::with_registry("consumers", &prometheus::Registry::new()),
         }
     }
 }
 
 /// Action to be taken upon an `MaskConsumer` resource during reconciliation
 #[derive(Debug, PartialEq)]
-enum ConsumerAction {
+pub(crate) enum ConsumerAction {
     /// Set the [`MaskConsumer`]'s phase to [`Pending`](MaskConsumerPhase::Pending)
     /// and add the finalizer to ensure proper garbage collection.
     Pending,
 
     /// Delete all subresources and remove finalizer only when all subresources are deleted.
     /// If `delete_resource` is true, the [`MaskConsumer`] resource will be deleted as well.
-    Delete { delete_resource: bool },
+    /// Any Pods still using the credentials are warned first.
+    Delete {
+        delete_resource: bool,
+        pods: Vec<ObjectReference>,
+    },
+
+    /// Keep the [`MaskConsumer`] in the [`Terminating`](MaskConsumerPhase::Terminating)
+    /// phase because its [`DeletionPolicy`] waits for the Pods still using its credentials
+    /// to exit. The Pods are only warned the first time they block the deletion.
+    WaitForPods {
+        pods: Vec<ObjectReference>,
+        warn: bool,
+    },
+
+    /// Unassign the [`MaskConsumer`]'s [`MaskProvider`] because it is also
+    /// assigned to an older member of its anti-affinity group. The
+    /// [`MaskConsumer`] is deleted like with [`ConsumerAction::Delete`], and
+    /// its [`Mask`] recreates it to be assigned another [`MaskProvider`].
+    /// Contains the message explaining the conflict.
+    AntiAffinityConflict {
+        message: String,
+        pods: Vec<ObjectReference>,
+    },
+
+    /// Delete the external [`MaskConsumer`], releasing its slot, because its
+    /// client stopped renewing the heartbeat. Contains the event's note.
+    HeartbeatExpired(String),
 
     /// Attempt to assign the [`MaskConsumer`] a [`MaskProvider`].
     Assign,
 
+    /// Set the [`MaskConsumer`]'s phase to [`Waiting`](MaskConsumerPhase::Waiting)
+    /// without reserving a slot because assignments are frozen.
+    AssignmentsFrozen,
+
     /// Create the credentials [`Secret`](k8s_openapi::api::core::v1::Secret) for the [`MaskConsumer`].
     CreateSecret,
 
+    /// Record the checksum of the credentials [`Secret`] in
+    /// [`AssignedProvider::secret_hash`], after annotating the Pod templates
+    /// of the opted-in workloads using it so they're rolled out.
+    RecordSecretHash(String),
+
+    /// Copy the [`MaskProvider`]'s current credentials over the contained
+    /// credentials [`Secret`], whose checksum annotation shows it was copied
+    /// from other credentials.
+    SyncSecret(Box<Secret>),
+
+    /// Add the labels missing from the credentials [`Secret`], which was
+    /// copied before they were set on every copy. Contains the labels.
+    LabelSecret(BTreeMap<String, String>),
+
+    /// Add the encryption annotations missing from the credentials
+    /// [`Secret`]. Contains the annotations.
+    AnnotateSecret(BTreeMap<String, String>),
+
+    /// Show in the [`MaskConsumer`]'s status that its assigned [`MaskProvider`]
+    /// no longer permits its namespace, and publish a Warning event. Contains
+    /// the message explaining the violation.
+    PolicyViolation(String),
+
+    /// Clear the policy violation from the [`MaskConsumer`]'s status because
+    /// its namespace is permitted again or violations are ignored.
+    PolicyCleared,
+
+    /// Unassign the [`MaskConsumer`]'s [`MaskProvider`] because it no longer
+    /// permits its namespace. The [`MaskConsumer`] is deleted like with
+    /// [`ConsumerAction::AntiAffinityConflict`], and its [`Mask`] recreates
+    /// it to wait for a [`MaskProvider`] that permits the namespace.
+    PolicyEviction {
+        message: String,
+        pods: Vec<ObjectReference>,
+    },
+
+    /// Create the Service requested with [`MaskConsumerSpec::service`],
+    /// now that a Pod using the credentials carries its selector labels.
+    CreateService(Box<Service>),
+
+    /// Update the type, selector and ports of the contained Service, which
+    /// the [`MaskConsumer`] owns, because they drifted from its
+    /// [`MaskConsumerSpec::service`].
+    PatchService(Box<Service>),
+
+    /// Show in the Active [`MaskConsumer`]'s status that a Service with its
+    /// name that it doesn't own is in the way. Contains the message.
+    ServiceConflict(String),
+
+    /// Record the name of the [`MaskConsumer`]'s Service in
+    /// [`MaskConsumerStatus::service`]. Contains the name.
+    RecordService(String),
+
+    /// Update [`MaskConsumerStatus::consumers`] with the ServiceAccounts
+    /// whose Pods were seen using the credentials. Contains the new records.
+    RecordConsumers(Vec<ConsumerRecord>),
+
+    /// Set the [`MaskConsumer`]'s phase to
+    /// [`ErrNamespaceNotOptedIn`](MaskConsumerPhase::ErrNamespaceNotOptedIn)
+    /// because its namespace is missing the opt-in label.
+    NamespaceNotOptedIn(OptInLabel),
+
+    /// Set the [`MaskConsumer`]'s phase to
+    /// [`ErrSecretConflict`](MaskConsumerPhase::ErrSecretConflict) because
+    /// a [`Secret`] with the name of its credentials copy can't be adopted.
+    /// Contains the message naming the [`Secret`].
+    SecretConflict(String),
+
+    /// Set the [`MaskConsumer`]'s phase to
+    /// [`ErrInvalidSpec`](MaskConsumerPhase::ErrInvalidSpec) because its
+    /// settings can't be applied to the credentials. Contains the message
+    /// naming the setting.
+    InvalidSpec(String),
+
+    /// Set the [`MaskConsumer`]'s phase to
+    /// [`ErrProviderLost`](MaskConsumerPhase::ErrProviderLost) because its
+    /// assigned [`MaskProvider`] was deleted or replaced, so its [`Mask`]
+    /// shows the assignment was revoked before the [`MaskConsumer`] is
+    /// deleted like with [`ConsumerAction::Delete`]. Contains the message
+    /// naming the [`MaskProvider`].
+    ProviderLost(String),
+
     /// Signals that the [`MaskConsumer`] is fully reconciled.
     Active,
 
@@ -108,8 +466,28 @@ impl ConsumerAction {
         match self {
             ConsumerAction::Pending => "Pending",
             ConsumerAction::Delete { .. } => "Delete",
+            ConsumerAction::WaitForPods { .. } => "WaitForPods",
+            ConsumerAction::AntiAffinityConflict { .. } => "AntiAffinityConflict",
+            ConsumerAction::HeartbeatExpired(_) => "HeartbeatExpired",
             ConsumerAction::Assign => "Assign",
+            ConsumerAction::AssignmentsFrozen => "AssignmentsFrozen",
             ConsumerAction::CreateSecret => "CreateSecret",
+            ConsumerAction::RecordSecretHash(_) => "RecordSecretHash",
+            ConsumerAction::SyncSecret(_) => "SyncSecret",
+            ConsumerAction::LabelSecret(_) => "LabelSecret",
+            ConsumerAction::AnnotateSecret(_) => "AnnotateSecret",
+            ConsumerAction::PolicyViolation(_) => "PolicyViolation",
+            ConsumerAction::PolicyCleared => "PolicyCleared",
+            ConsumerAction::PolicyEviction { .. } => "PolicyEviction",
+            ConsumerAction::CreateService(_) => "CreateService",
+            ConsumerAction::PatchService(_) => "PatchService",
+            ConsumerAction::ServiceConflict(_) => "ServiceConflict",
+            ConsumerAction::RecordService(_) => "RecordService",
+            ConsumerAction::RecordConsumers(_) => "RecordConsumers",
+            ConsumerAction::NamespaceNotOptedIn(_) => "NamespaceNotOptedIn",
+            ConsumerAction::SecretConflict(_) => "SecretConflict",
+            ConsumerAction::InvalidSpec(_) => "InvalidSpec",
+            ConsumerAction::ProviderLost(_) => "ProviderLost",
             ConsumerAction::Active => "Active",
             ConsumerAction::NoOp => "NoOp",
         }
@@ -123,9 +501,10 @@ fn needs_finalizer(instance: &MaskConsumer) -> bool {
 
 /// Returns true if the `MaskConsumer` resource requires a status
 /// update to set the phase to Pending. This should be the first action
-/// for any managed resource.
+/// for any managed resource, and is repeated for any status that is
+/// missing fields the controller relies on.
 fn needs_pending(instance: &MaskConsumer) -> bool {
-    needs_finalizer(instance) || instance.status.as_ref().map_or(true, |s| s.phase.is_none())
+    needs_finalizer(instance) || ensure_status_initialized(instance).is_err()
 }
 
 /// Reconciliation function for the `MaskConsumer` resource.
@@ -133,6 +512,12 @@ async fn reconcile(
     instance: Arc<MaskConsumer>,
     context: Arc<ContextData>,
 ) -> Result<Action, Error> {
+    // Wait for a permit if the number of concurrent reconciliations is limited.
+    let _permit = match context.semaphore {
+        Some(ref semaphore) => Some(semaphore.acquire().await.unwrap()),
+        None => None,
+    };
+
     // The `Client` is shared -> a clone from the reference is obtained
     let client: Client = context.client.clone();
 
@@ -157,38 +542,24 @@ async fn reconcile(
 
     // Increment total number of reconciles for the MaskConsumer resource.
     #[cfg(feature = "metrics")]
-    context
-        .metrics
-        .reconcile_counter
-        .with_label_values(&[&name, &namespace])
-        .inc();
+    context.metrics.reconcile(&name, &namespace);
 
     // Benchmark the read phase of reconciliation.
     #[cfg(feature = "metrics")]
     let start = std::time::Instant::now();
 
     // Read phase of reconciliation determines goal during the write phase.
-    let action = determine_action(client.clone(), &name, &namespace, &instance).await?;
+    let action = determine_action(client.clone(), &namespace, &instance, &context).await?;
 
     if action != ConsumerAction::NoOp {
         println!("{}/{} ACTION: {:?}", namespace, name, action);
     }
 
-    // Report the read phase performance.
-    #[cfg(feature = "metrics")]
-    context
-        .metrics
-        .read_histogram
-        .with_label_values(&[&name, &namespace, action.to_str()])
-        .observe(start.elapsed().as_secs_f64());
-
-    // Increment the counter for the action.
+    // Report the read phase performance and count the action.
     #[cfg(feature = "metrics")]
     context
         .metrics
-        .action_counter
-        .with_label_values(&[&name, &namespace, action.to_str()])
-        .inc();
+        .read(&name, &namespace, action.to_str(), start);
 
     // Benchmark the write phase of reconciliation.
     #[cfg(feature = "metrics")]
@@ -196,21 +567,48 @@ async fn reconcile(
         // Don't measure performance for NoOp actions.
         ConsumerAction::NoOp => None,
         // Start a performance timer for the write phase.
-        _ => Some(
-            context
-                .metrics
-                .write_histogram
-                .with_label_values(&[&name, &namespace, action.to_str()])
-                .start_timer(),
-        ),
+        _ => Some(context.metrics.write(&name, &namespace, action.to_str())),
     };
 
-    // Performs action as decided by the `determine_action` function.
-    // This is the write phase of reconciliation.
-    let result = match action {
+    // Perform the action. The action's name is attached to any error
+    // so the error handler can report which action failed, and to the
+    // events published for the phase transitions it makes.
+    let action_name = action.to_str().to_owned();
+    let result = events::scope(
+        context.reporter.clone(),
+        &action_name,
+        explain::scope(
+            context.options.explain,
+            "consumers",
+            &action_name,
+            apply_action(client, &name, &namespace, &instance, action, &context),
+        ),
+    )
+    .await;
+
+    // Report the write phase performance with the action's outcome.
+    #[cfg(feature = "metrics")]
+    if let Some(timer) = timer {
+        timer.observe(&result);
+    }
+
+    result.map_err(|e| Error::action(&action_name, e))
+}
+
+/// Performs the action as decided by the `determine_action` function.
+/// This is the write phase of reconciliation.
+pub(crate) async fn apply_action(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    instance: &MaskConsumer,
+    action: ConsumerAction,
+    context: &ContextData,
+) -> Result<Action, Error> {
+    Ok(match action {
         ConsumerAction::Pending => {
             // Add a finalizer so the resource can be properly garbage collected.
-            let instance = finalizer::add(client.clone(), &name, &namespace).await?;
+            let instance = finalizer::add(client.clone(), name, namespace).await?;
 
             // Update the phase to Pending.
             actions::pending(client, &instance).await?;
@@ -218,26 +616,75 @@ async fn reconcile(
             // Requeue immediately.
             Action::requeue(Duration::ZERO)
         }
-        ConsumerAction::Delete { delete_resource } => {
-            // Show that the reservation is being terminated.
-            actions::terminating(client.clone(), &instance).await?;
-
-            // Remove the finalizer from the MaskConsumer resource.
-            finalizer::delete::<MaskConsumer>(client.clone(), &name, &namespace).await?;
+        ConsumerAction::Delete {
+            delete_resource,
+            pods,
+        } => delete_consumer(client, name, namespace, instance, delete_resource, pods).await?,
+        ConsumerAction::AntiAffinityConflict { message, pods } => {
+            // Explain why the MaskProvider is being unassigned.
+            events::warn(
+                client.clone(),
+                instance,
+                "AntiAffinityConflict",
+                "Reassign",
+                message,
+            )
+            .await;
 
-            if delete_resource {
-                // Delete the `MaskConsumer` resource itself. This will be
-                // triggered whenever the MaskReservation that reserves a slot
-                // with the provider could not be found.
-                actions::delete(client, &name, &namespace).await?;
-            }
+            // The Mask recreates the MaskConsumer, which is then
+            // assigned a MaskProvider that the group isn't using.
+            delete_consumer(client, name, namespace, instance, true, pods).await?
+        }
+        ConsumerAction::HeartbeatExpired(note) => {
+            // Tell the external client why its slot was released.
+            events::warn(client.clone(), instance, "HeartbeatExpired", "Delete", note).await;
 
-            // Child resources will be deleted by kubernetes.
+            // The deletion withdraws the credentials like any other.
+            actions::delete(client, name, namespace).await?;
             Action::await_change()
         }
         ConsumerAction::Assign => {
             // Assign a new provider to the MaskConsumer.
-            if !actions::assign_provider(client, &name, &namespace, &instance).await? {
+            // MaskConsumers without their own providers fall back to the
+            // namespace's default providers. The annotation is read at
+            // assignment time, so changing it doesn't affect assigned ones.
+            let namespace_default = match instance.spec.providers {
+                Some(_) => None,
+                // Canaries test one specific MaskProvider regardless of the defaults.
+                None if instance.labels().contains_key(CANARY_LABEL) => None,
+                None => {
+                    context
+                        .namespace_defaults
+                        .providers(client.clone(), namespace)
+                        .await?
+                }
+            };
+            let tags = ProviderTags::resolve(instance, namespace_default);
+            // Topology-aware MaskConsumers try the MaskProviders in the
+            // region of their namespace first, if it is labeled with one.
+            let region = if context.namespace_topology.is_topology_aware(instance) {
+                context
+                    .namespace_topology
+                    .region(client.clone(), namespace)
+                    .await?
+            } else {
+                None
+            };
+            let placement = actions::Placement {
+                tags: &tags,
+                region: region.as_deref(),
+            };
+            if !actions::assign_provider(
+                client,
+                instance,
+                &placement,
+                &context.accounts,
+                &context.quotas,
+                &context.clock,
+                context.selector.as_ref(),
+            )
+            .await?
+            {
                 // Failed to assign a provider. Wait a bit and retry.
                 return Ok(Action::requeue(PROBE_INTERVAL));
             }
@@ -245,60 +692,412 @@ async fn reconcile(
             // Requeue immediately to set the phase to "Active".
             Action::requeue(Duration::ZERO)
         }
+        ConsumerAction::AssignmentsFrozen => {
+            // Show that the MaskConsumer is waiting on the freeze to be lifted.
+            actions::assignments_frozen(client, instance).await?;
+
+            // Check back after a delay so unfreezing resumes assignment.
+            Action::requeue(PROBE_INTERVAL)
+        }
         ConsumerAction::CreateSecret => {
             // Create the credentials env secret in the MaskConsumer's namespace.
-            actions::create_secret(client, &namespace, &instance).await?;
+            actions::create_secret(client, namespace, instance).await?;
 
             // Requeue immediately to set the phase to Active.
             Action::requeue(Duration::ZERO)
         }
+        ConsumerAction::RecordSecretHash(hash) => {
+            // Roll out the opted-in workloads and remember the credentials.
+            actions::record_secret_hash(client, namespace, instance, hash, context.rollout).await?;
+
+            // Requeue immediately to set the phase to Active.
+            Action::requeue(Duration::ZERO)
+        }
+        ConsumerAction::SyncSecret(existing) => {
+            // Replace the stale copy with the current credentials.
+            actions::sync_secret(client, namespace, instance, *existing).await?;
+
+            // Requeue immediately to record the new checksum.
+            Action::requeue(Duration::ZERO)
+        }
+        ConsumerAction::LabelSecret(labels) => {
+            // Migrate the credentials Secret to the current set of labels.
+            let secret = &actions::assigned_provider(instance)?.secret;
+            actions::label_secret(client, namespace, secret, labels).await?;
+
+            // Requeue immediately to set the phase to Active.
+            Action::requeue(Duration::ZERO)
+        }
+        ConsumerAction::AnnotateSecret(annotations) => {
+            // Restore the annotations the external encryption relies on.
+            let secret = &actions::assigned_provider(instance)?.secret;
+            actions::annotate_secret(client, namespace, secret, annotations).await?;
+
+            // Requeue immediately to set the phase to Active.
+            Action::requeue(Duration::ZERO)
+        }
+        ConsumerAction::WaitForPods { pods, warn } => {
+            let secret = &actions::assigned_provider(instance)?.secret;
+            if warn {
+                withdrawal::warn(client.clone(), instance, secret, &pods).await;
+            }
+
+            // Name the Pods that hold up the deletion in the status object.
+            let message = messages::withdrawal_blocked(secret, &withdrawal::pod_names(&pods));
+            actions::withdrawal_blocked(client, instance, message).await?;
+
+            // Check back after a delay, as Pods exiting don't trigger a reconcile.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        ConsumerAction::PolicyViolation(message) => {
+            // Explain why the namespace is no longer permitted.
+            events::warn(
+                client.clone(),
+                instance,
+                "PolicyViolation",
+                "Warn",
+                message.clone(),
+            )
+            .await;
+
+            // Show the violation in the status object, keeping the slot.
+            actions::policy_violation(client, instance, Some(message)).await?;
+
+            // Requeue immediately to keep the Active status up-to-date.
+            Action::requeue(Duration::ZERO)
+        }
+        ConsumerAction::PolicyCleared => {
+            // The namespace is permitted again.
+            actions::policy_violation(client, instance, None).await?;
+
+            // Requeue immediately to keep the Active status up-to-date.
+            Action::requeue(Duration::ZERO)
+        }
+        ConsumerAction::CreateService(service) => {
+            // Expose the Pods using the credentials in the namespace.
+            actions::create_service(client, namespace, *service).await?;
+
+            // Requeue immediately to record the Service's name.
+            Action::requeue(Duration::ZERO)
+        }
+        ConsumerAction::PatchService(service) => {
+            // Bring the Service in line with the MaskConsumer's spec.
+            actions::patch_service(client, namespace, *service).await?;
+
+            // Requeue immediately to record the Service's name.
+            Action::requeue(Duration::ZERO)
+        }
+        ConsumerAction::ServiceConflict(message) => {
+            // Name the Service that's in the way in the status object.
+            actions::service_conflict(client, instance, message).await?;
+
+            // Requeue immediately to record which ServiceAccounts use the credentials.
+            Action::requeue(Duration::ZERO)
+        }
+        ConsumerAction::RecordService(name) => {
+            // Show which Service belongs to the MaskConsumer.
+            actions::record_service(client, instance, name).await?;
+
+            // Requeue immediately to keep the Active status up-to-date.
+            Action::requeue(Duration::ZERO)
+        }
+        ConsumerAction::RecordConsumers(records) => {
+            // Keep an audit trail of the workloads using the credentials.
+            actions::record_consumers(client, instance, records).await?;
+
+            // Requeue immediately to keep the Active status up-to-date.
+            Action::requeue(Duration::ZERO)
+        }
+        ConsumerAction::PolicyEviction { message, pods } => {
+            // Explain why the MaskProvider is being unassigned.
+            events::warn(
+                client.clone(),
+                instance,
+                "PolicyViolation",
+                "Evict",
+                message,
+            )
+            .await;
+
+            // The Mask recreates the MaskConsumer, which then waits for
+            // a MaskProvider that permits the namespace.
+            delete_consumer(client, name, namespace, instance, true, pods).await?
+        }
+        ConsumerAction::SecretConflict(message) => {
+            // Name the conflicting Secret in the status object.
+            actions::secret_conflict(client, instance, message).await?;
+
+            // Check back after a delay so removing the Secret unblocks it.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        ConsumerAction::InvalidSpec(message) => {
+            // Name the setting that can't be applied in the status object.
+            actions::invalid_spec(client, instance, message).await?;
+
+            // Check back after a delay so changing the credentials unblocks it.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        ConsumerAction::ProviderLost(message) => {
+            // Show that the assignment was revoked before releasing the slot.
+            actions::provider_lost(client, instance, message).await?;
+
+            // Requeue immediately to delete the MaskConsumer.
+            Action::requeue(Duration::ZERO)
+        }
+        ConsumerAction::NamespaceNotOptedIn(label) => {
+            // Reflect the error in the status object with remediation guidance.
+            actions::namespace_not_opted_in(client, namespace, instance, &label).await?;
+
+            // Re-check after a delay so labeling the namespace unblocks it.
+            Action::requeue(PROBE_INTERVAL)
+        }
         ConsumerAction::Active => {
             // Update the phase to Active, meaning the reservation is in use.
-            actions::active(client, &instance).await?;
+            actions::active(client, instance).await?;
 
             // Resource is fully reconciled.
             Action::requeue(PROBE_INTERVAL)
         }
         // The resource is already in desired state, do nothing and re-check after 10 seconds
         ConsumerAction::NoOp => Action::requeue(PROBE_INTERVAL),
-    };
+    })
+}
 
-    #[cfg(feature = "metrics")]
-    if let Some(timer) = timer {
-        timer.observe_duration();
+/// Deletes the MaskConsumer's subresources and removes its finalizer. If
+/// `delete_resource` is true, the MaskConsumer resource is deleted as well.
+/// Any Pods still using the credentials are warned first.
+async fn delete_consumer(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    instance: &MaskConsumer,
+    delete_resource: bool,
+    pods: Vec<ObjectReference>,
+) -> Result<Action, Error> {
+    // Tell the Pods still using the credentials that they're going away.
+    if !pods.is_empty() {
+        let secret = &actions::assigned_provider(instance)?.secret;
+        withdrawal::warn(client.clone(), instance, secret, &pods).await;
     }
 
-    Ok(result)
+    // Show that the reservation is being terminated.
+    actions::terminating(client.clone(), instance).await?;
+
+    // Remove the finalizer from the MaskConsumer resource.
+    finalizer::delete::<MaskConsumer>(client.clone(), name, namespace).await?;
+
+    if delete_resource {
+        // Delete the `MaskConsumer` resource itself. This will be
+        // triggered whenever the MaskReservation that reserves a slot
+        // with the provider could not be found.
+        actions::delete(client, name, namespace).await?;
+    }
+
+    // Child resources will be deleted by kubernetes.
+    Ok(Action::await_change())
+}
+
+/// Determines the action for a MaskConsumer that is being deleted. Pods
+/// that still use its credentials are warned, and with the `WaitForPods`
+/// deletion policy the finalizer is held until they exit or the grace
+/// period expires.
+async fn determine_delete_action(
+    client: Client,
+    instance: &MaskConsumer,
+    grace_period: Duration,
+    now: DateTime<Utc>,
+) -> Result<ConsumerAction, Error> {
+    // Deletion skips the Pending action, so a partially written status
+    // is treated like any other phase rather than failing.
+    let (phase, age) = get_consumer_phase(instance)
+        .map_or((None, Duration::MAX), |(phase, age)| (Some(phase), age));
+    // A MaskProvider whose deletion was forced deletes its MaskConsumers,
+    // so show that the assignment was revoked before withdrawing it.
+    if let Some(provider) = get_assigned_provider(instance).filter(|_| {
+        !matches!(
+            phase,
+            Some(MaskConsumerPhase::Terminating | MaskConsumerPhase::ErrProviderLost)
+        )
+    }) {
+        if actions::provider_departing(client.clone(), provider).await? {
+            return Ok(ConsumerAction::ProviderLost(messages::err_provider_lost(
+                &provider.namespace,
+                &provider.name,
+            )));
+        }
+    }
+    let pods = match get_assigned_provider(instance) {
+        Some(provider) => {
+            withdrawal::list_referencing_pods(client, instance, &provider.secret).await?
+        }
+        // Nothing was copied, so nothing can be using it.
+        None => Vec::new(),
+    };
+    // Pods are warned once, when they're first found blocking the deletion.
+    let warn = phase != Some(MaskConsumerPhase::Terminating);
+    if pods.is_empty()
+        || instance.spec.deletion_policy.unwrap_or_default() == DeletionPolicy::Immediate
+        || withdrawal::grace_period_expired(instance, grace_period, now)
+    {
+        return Ok(ConsumerAction::Delete {
+            delete_resource: false,
+            pods: if warn { pods } else { Vec::new() },
+        });
+    }
+    let secret = &actions::assigned_provider(instance)?.secret;
+    let message = messages::withdrawal_blocked(secret, &withdrawal::pod_names(&pods));
+    let current = instance.status.as_ref().and_then(|s| s.message.as_deref());
+    if !warn && current == Some(message.as_str()) && age <= PROBE_INTERVAL {
+        // Already showing the blocking Pods.
+        return Ok(ConsumerAction::NoOp);
+    }
+    Ok(ConsumerAction::WaitForPods { pods, warn })
 }
 
 /// Returns the phase of the MaskConsumer.
 pub fn get_consumer_phase(instance: &MaskConsumer) -> Result<(MaskConsumerPhase, Duration), Error> {
-    let status = instance
-        .status
-        .as_ref()
-        .ok_or_else(|| Error::UserInputError("No status".to_string()))?;
-    let phase = status
-        .phase
-        .ok_or_else(|| Error::UserInputError("No phase".to_string()))?;
-    let last_updated: chrono::DateTime<Utc> = status
-        .last_updated
-        .as_ref()
-        .ok_or_else(|| Error::UserInputError("No lastUpdated".to_string()))?
-        .parse()?;
-    let age: chrono::Duration = Utc::now() - last_updated;
-    Ok((phase, age.to_std()?))
+    status_phase(instance)
 }
 
 /// Determines if any provider-related actions are needed for the MaskConsumer.
+/// Returns the NamespaceNotOptedIn action if namespace opt-in is
+/// required and the namespace is missing the label.
+async fn check_opt_in(
+    client: Client,
+    namespace: &str,
+    namespace_opt_in: Option<&NamespaceOptIn>,
+) -> Result<Option<ConsumerAction>, Error> {
+    match namespace_opt_in {
+        Some(opt_in) if !opt_in.is_opted_in(client, namespace).await? => Ok(Some(
+            ConsumerAction::NamespaceNotOptedIn(opt_in.label().clone()),
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Returns the action to take while assignments are frozen. Consumers
+/// that already show the freeze are only refreshed when their status
+/// becomes stale, so the freeze doesn't cause a stream of status updates.
+fn check_frozen(
+    instance: &MaskConsumer,
+    status_freshness: Duration,
+) -> Result<ConsumerAction, Error> {
+    let (phase, _) = get_consumer_phase(instance)?;
+    let status = ensure_status_initialized(instance)?;
+    if phase != MaskConsumerPhase::Waiting
+        || status.message.as_deref() != Some(messages::ASSIGNMENTS_FROZEN)
+        || needs_refresh(status, status_freshness)
+    {
+        Ok(ConsumerAction::AssignmentsFrozen)
+    } else {
+        Ok(ConsumerAction::NoOp)
+    }
+}
+
+/// Returns the action to take while a Secret is in the way of the
+/// credentials copy. The status is only refreshed when the message
+/// changes or becomes stale, so the conflict doesn't cause a stream of
+/// status updates or requests to create the Secret.
+fn check_secret_conflict(
+    instance: &MaskConsumer,
+    message: String,
+    status_freshness: Duration,
+) -> Result<ConsumerAction, Error> {
+    let (phase, _) = get_consumer_phase(instance)?;
+    let status = ensure_status_initialized(instance)?;
+    if phase != MaskConsumerPhase::ErrSecretConflict
+        || status.message.as_deref() != Some(message.as_str())
+        || needs_refresh(status, status_freshness)
+    {
+        Ok(ConsumerAction::SecretConflict(message))
+    } else {
+        Ok(ConsumerAction::NoOp)
+    }
+}
+
+/// Returns `action`, which copies the credentials, unless the MaskConsumer
+/// is in the ErrInvalidSpec phase and its settings still can't be applied
+/// to them. The credentials are only read here while in that phase, so
+/// the copy is made as soon as they change to allow it. The status is
+/// only refreshed when the message changes or becomes stale.
+async fn check_invalid_spec(
+    client: Client,
+    namespace: &str,
+    instance: &MaskConsumer,
+    action: ConsumerAction,
+    status_freshness: Duration,
+) -> Result<ConsumerAction, Error> {
+    let (phase, _) = get_consumer_phase(instance)?;
+    if phase != MaskConsumerPhase::ErrInvalidSpec {
+        return Ok(action);
+    }
+    let provider = actions::assigned_provider(instance)?;
+    let provider_secret = match actions::get_provider_secret(client, provider).await {
+        Ok(secret) => secret,
+        // The action leaves the copy alone if the MaskProvider departed.
+        Err(Error::ProviderDeparted(_)) => return Ok(action),
+        Err(e) => return Err(e),
+    };
+    let message = match actions::consumer_secret(namespace, instance, provider_secret) {
+        Ok(_) => return Ok(action),
+        Err(Error::InvalidSpec(message)) => message,
+        Err(e) => return Err(e),
+    };
+    let status = ensure_status_initialized(instance)?;
+    if status.message.as_deref() != Some(message.as_str())
+        || needs_refresh(status, status_freshness)
+    {
+        Ok(ConsumerAction::InvalidSpec(message))
+    } else {
+        Ok(ConsumerAction::NoOp)
+    }
+}
+
+/// Returns the action to take once the assigned MaskProvider was deleted
+/// or replaced. The loss is shown in the status before the MaskConsumer is
+/// deleted, so its Mask can tell a revoked assignment from one that was
+/// never made. Pods using the credentials are warned upon deletion.
+async fn check_provider_lost(
+    client: Client,
+    instance: &MaskConsumer,
+    provider: &AssignedProvider,
+) -> Result<ConsumerAction, Error> {
+    let (phase, _) = get_consumer_phase(instance)?;
+    if phase != MaskConsumerPhase::ErrProviderLost {
+        return Ok(ConsumerAction::ProviderLost(messages::err_provider_lost(
+            &provider.namespace,
+            &provider.name,
+        )));
+    }
+    let pods = withdrawal::list_referencing_pods(client, instance, &provider.secret).await?;
+    Ok(ConsumerAction::Delete {
+        delete_resource: true,
+        pods,
+    })
+}
+
 async fn determine_provider_action(
     client: Client,
     namespace: &str,
     instance: &MaskConsumer,
+    namespace_opt_in: Option<&NamespaceOptIn>,
+    assignments_frozen: bool,
+    status_freshness: Duration,
+    policies: &ProviderPolicies,
 ) -> Result<Option<ConsumerAction>, Error> {
     // See if the MaskConsumer should be assigned a MaskProvider.
     let provider = match get_assigned_provider(instance) {
-        // We need to assign a MaskProvider to this MaskConsumer.
-        None => return Ok(Some(ConsumerAction::Assign)),
+        // Don't reserve a slot for a namespace that can't receive the credentials.
+        None => {
+            if let Some(action) = check_opt_in(client, namespace, namespace_opt_in).await? {
+                return Ok(Some(action));
+            }
+            // Don't reserve new slots while assignments are frozen.
+            if assignments_frozen {
+                return Ok(Some(check_frozen(instance, status_freshness)?));
+            }
+            return Ok(Some(ConsumerAction::Assign));
+        }
         // MaskProvider has already been assigned.
         Some(p) => p,
     };
@@ -307,23 +1106,152 @@ async fn determine_provider_action(
     // If it does not exist, we should delete this MaskConsumer immediately.
     if get_reservation(client.clone(), provider).await?.is_none() {
         // MaskReservation has been deleted, so we should delete this MaskConsumer.
+        // The slot is already gone, so Pods using the credentials are only warned.
+        // The MaskProvider may have been deleted without releasing its slots.
+        if actions::provider_departing(client.clone(), provider).await? {
+            return Ok(Some(check_provider_lost(client, instance, provider).await?));
+        }
+        let pods = withdrawal::list_referencing_pods(client, instance, &provider.secret).await?;
         return Ok(Some(ConsumerAction::Delete {
             delete_resource: true,
+            pods,
         }));
     }
 
+    // An older member of the anti-affinity group may have been assigned the
+    // same MaskProvider concurrently, or the MaskConsumer may have joined the
+    // group after it was assigned. Either way, the older member keeps it.
+    let members = anti_affinity::list_members(client.clone(), instance).await?;
+    if let Some(member) = anti_affinity::conflicting_member(instance, &members) {
+        let message = messages::anti_affinity_conflict(
+            anti_affinity::group(instance).unwrap(),
+            &provider.namespace,
+            &provider.name,
+            member.metadata.name.as_deref().unwrap_or_default(),
+        );
+        let pods = withdrawal::list_referencing_pods(client, instance, &provider.secret).await?;
+        return Ok(Some(ConsumerAction::AntiAffinityConflict { message, pods }));
+    }
+
     // Ensure the Secret containing the env credentials exists.
     // The Secret should exist in the same namespace as the MaskConsumer.
-    if get_secret(client, namespace, &provider.secret)
-        .await?
-        .is_none()
+    let secret = get_secret(client.clone(), namespace, &provider.secret).await?;
+    if !secret
+        .as_ref()
+        .is_some_and(|secret| actions::is_consumer_secret(secret, instance))
+    {
+        // Something other than the operator's copy is in the way, so
+        // don't touch it and wait for it to be removed.
+        if let Some(reason) = secret
+            .as_ref()
+            .and_then(|secret| actions::secret_conflict_reason(secret, instance))
+        {
+            // The copy left behind by the predecessor is adopted instead,
+            // once the predecessor is going away.
+            let adoptable = secret
+                .as_ref()
+                .is_some_and(|secret| succession::is_predecessor_copy(secret, instance))
+                && succession::predecessor_released(client.clone(), instance).await?;
+            if !adoptable {
+                let message = messages::err_secret_conflict(namespace, &provider.secret, &reason);
+                return Ok(Some(check_secret_conflict(
+                    instance,
+                    message,
+                    status_freshness,
+                )?));
+            }
+        }
+        // Never copy the credentials of a MaskProvider that is going away.
+        // Its unassignment may have deleted the copy already, and copying
+        // them again would leave it behind, so release the slot instead.
+        if actions::provider_departing(client.clone(), provider).await? {
+            return Ok(Some(check_provider_lost(client, instance, provider).await?));
+        }
+        // The credentials secret doesn't exist or is a copy left behind by
+        // another MaskConsumer or made for another MaskProvider, so we should
+        // create it as long as the namespace is allowed to receive credentials.
+        if let Some(action) = check_opt_in(client.clone(), namespace, namespace_opt_in).await? {
+            return Ok(Some(action));
+        }
+        return Ok(Some(
+            check_invalid_spec(
+                client,
+                namespace,
+                instance,
+                ConsumerAction::CreateSecret,
+                status_freshness,
+            )
+            .await?,
+        ));
+    }
+
+    // Record the checksum of newly written credentials. Secrets written
+    // before checksums were annotated are left alone.
+    if let Some(hash) = secret
+        .as_ref()
+        .and_then(rollout::secret_hash)
+        .filter(|hash| provider.secret_hash.as_deref() != Some(*hash))
     {
-        // The credentials secret doesn't exist, so we should create it.
-        return Ok(Some(ConsumerAction::CreateSecret));
+        return Ok(Some(ConsumerAction::RecordSecretHash(hash.to_owned())));
     }
 
-    // No provider-related actions necessary.
-    Ok(None)
+    // Label Secrets copied before every copy was labeled with its Mask.
+    let missing = secret
+        .as_ref()
+        .map(|secret| actions::missing_secret_labels(secret, instance))
+        .unwrap_or_default();
+    if !missing.is_empty() {
+        return Ok(Some(ConsumerAction::LabelSecret(missing)));
+    }
+
+    // Without its encryption annotation, the copy may be stored in the clear.
+    let missing = secret
+        .as_ref()
+        .map(|secret| actions::missing_secret_annotations(secret, instance))
+        .unwrap_or_default();
+    if !missing.is_empty() {
+        return Ok(Some(ConsumerAction::AnnotateSecret(missing)));
+    }
+
+    // The MaskProvider's policy is cached, so this doesn't cost a request
+    // every reconcile. Its credentials are only read if the copy is stale.
+    let policy = policies.get(client.clone(), provider).await?;
+    // Without a policy, the MaskProvider may have been replaced by another
+    // of the same name while the copy stayed behind. The copy and the slot
+    // belong to the assigned MaskProvider, so release the slot rather than
+    // keep a copy the new MaskProvider doesn't account for.
+    if policy.is_none() && actions::provider_replaced(client.clone(), provider).await? {
+        return Ok(Some(check_provider_lost(client, instance, provider).await?));
+    }
+    if let Some(secret) =
+        secret.filter(|secret| policy.as_ref().is_some_and(|p| p.is_stale(secret)))
+    {
+        return Ok(Some(
+            check_invalid_spec(
+                client,
+                namespace,
+                instance,
+                ConsumerAction::SyncSecret(Box::new(secret)),
+                status_freshness,
+            )
+            .await?,
+        ));
+    }
+
+    // The MaskProvider's allowlist may have changed since the assignment.
+    Ok(
+        match policy::check(instance, namespace, provider, policy.as_ref()) {
+            // No provider-related actions necessary.
+            PolicyCheck::Unchanged => None,
+            PolicyCheck::Cleared => Some(ConsumerAction::PolicyCleared),
+            PolicyCheck::Warn(message) => Some(ConsumerAction::PolicyViolation(message)),
+            PolicyCheck::Evict(message) => {
+                let pods =
+                    withdrawal::list_referencing_pods(client, instance, &provider.secret).await?;
+                Some(ConsumerAction::PolicyEviction { message, pods })
+            }
+        },
+    )
 }
 
 /// Resources arrives into reconciliation queue in a certain state. This function looks at
@@ -332,41 +1260,164 @@ async fn determine_provider_action(
 ///
 /// # Arguments
 /// - `instance`: A reference to `MaskConsumer` being reconciled to decide next action upon.
-async fn determine_action(
+/// - `context`: The controller's settings, such as the namespace opt-in label
+///   and whether assignments are frozen.
+pub(crate) async fn determine_action(
     client: Client,
-    _name: &str,
     namespace: &str,
     instance: &MaskConsumer,
+    context: &ContextData,
 ) -> Result<ConsumerAction, Error> {
     if instance.metadata.deletion_timestamp.is_some() {
-        return Ok(ConsumerAction::Delete {
-            delete_resource: false,
-        });
+        return determine_delete_action(
+            client,
+            instance,
+            context.withdrawal_grace_period,
+            context.clock.now(),
+        )
+        .await;
+    }
+
+    // Release the slot of an external client that stopped renewing its heartbeat.
+    let timeout = context.heartbeat_timeout;
+    if external::heartbeat_expired(instance, timeout, context.clock.now()) {
+        return Ok(ConsumerAction::HeartbeatExpired(
+            messages::heartbeat_expired(&timeout),
+        ));
     }
 
     // The rest of the controller code assumes the presence of the
-    // status object and its phase field. If neither of these exist,
-    // the first thing that should be done is initializing them.
+    // status object and its bookkeeping fields. If any of these are
+    // missing, the first thing that should be done is initializing them.
     if needs_pending(instance) {
         return Ok(ConsumerAction::Pending);
     }
 
     // Check if there are any provider-related actions to take.
-    if let Some(action) = determine_provider_action(client, namespace, instance).await? {
+    if let Some(action) = determine_provider_action(
+        client.clone(),
+        namespace,
+        instance,
+        context.namespace_opt_in.as_ref(),
+        context.config.assignments_frozen(),
+        context.status_freshness,
+        &context.policies,
+    )
+    .await?
+    {
+        return Ok(action);
+    }
+
+    // Expose the Pods using the credentials once one of them shows up.
+    if let Some(action) =
+        determine_service_action(client.clone(), namespace, instance, context).await?
+    {
+        return Ok(action);
+    }
+
+    // Record which ServiceAccounts' Pods use the credentials.
+    if let Some(action) = determine_audit_action(client, namespace, instance, context).await? {
         return Ok(action);
     }
 
     // Keep the Active status up-to-date.
-    determine_status_action(instance)
+    determine_status_action(instance, context.status_freshness)
+}
+
+/// Returns the action creating the Service requested with the
+/// MaskConsumer's `spec.service` once a Pod using the credentials carries
+/// its selector labels, updating it when it drifts from the spec, or
+/// recording its name once it exists. A Service with the name that isn't
+/// owned by the MaskConsumer is left alone, and named in the status.
+async fn determine_service_action(
+    client: Client,
+    namespace: &str,
+    instance: &MaskConsumer,
+    context: &ContextData,
+) -> Result<Option<ConsumerAction>, Error> {
+    let name = instance.name_any();
+    let (spec, provider) = match (
+        instance.spec.service.as_ref(),
+        get_assigned_provider(instance),
+    ) {
+        // No Pods use the credentials of external MaskConsumers.
+        (Some(spec), Some(provider))
+            if !external::is_external(instance) && service::validate(&name, spec).is_ok() =>
+        {
+            (spec, provider)
+        }
+        _ => return Ok(None),
+    };
+    let api: Api<Service> = Api::namespaced(client.clone(), namespace);
+    let existing = api
+        .get_opt(&name)
+        .await
+        .context_kind_name("Service", &name)?;
+    let recorded = instance.status.as_ref().and_then(|s| s.service.as_deref());
+    let desired = service::desired_service(instance, spec);
+    Ok(match existing {
+        Some(existing) if service::is_owned(&existing, instance) => {
+            if service::has_drifted(&existing, &desired) {
+                Some(ConsumerAction::PatchService(Box::new(desired)))
+            } else {
+                (recorded != Some(name.as_str())).then_some(ConsumerAction::RecordService(name))
+            }
+        }
+        Some(_) => {
+            // Keep the message shown, refreshing it in place of the Active status.
+            let message = messages::service_conflict(&name);
+            let status = ensure_status_initialized(instance)?;
+            (status.message.as_ref() != Some(&message)
+                || needs_refresh(status, context.status_freshness))
+            .then_some(ConsumerAction::ServiceConflict(message))
+        }
+        None => context
+            .pod_usage
+            .any_labeled(client, namespace, &provider.secret, &spec.selector_labels)
+            .await?
+            .then(|| ConsumerAction::CreateService(Box::new(desired))),
+    })
+}
+
+/// Returns the action updating the records of the ServiceAccounts whose
+/// Pods use the credentials of the assigned MaskProvider, if they changed.
+async fn determine_audit_action(
+    client: Client,
+    namespace: &str,
+    instance: &MaskConsumer,
+    context: &ContextData,
+) -> Result<Option<ConsumerAction>, Error> {
+    let provider = match get_assigned_provider(instance) {
+        // No Pods use the credentials of external MaskConsumers.
+        Some(provider) if !external::is_external(instance) => provider,
+        _ => return Ok(None),
+    };
+    let service_accounts = context
+        .pod_usage
+        .service_accounts(client, namespace, &provider.secret)
+        .await?;
+    let current = instance
+        .status
+        .as_ref()
+        .and_then(|s| s.consumers.as_deref())
+        .unwrap_or_default();
+    let records = audit::merge_records(
+        current,
+        &service_accounts,
+        &format!("{}/{}", provider.namespace, provider.name),
+        context.clock.now(),
+        context.status_freshness,
+        audit::RECORD_RETENTION,
+    );
+    Ok((records != current).then_some(ConsumerAction::RecordConsumers(records)))
 }
 
 /// Gets the Secret that contains the credentials for the Mask.
-/// Even if the Secret exists, this may still return None if
-/// the Secret's provider label doesn't match the expected uid.
+/// The Secret may belong to another MaskConsumer or MaskProvider
+/// if a stable secret suffix is used, so callers should check its
+/// ownership with [`actions::is_consumer_secret`].
 async fn get_secret(client: Client, namespace: &str, name: &str) -> Result<Option<Secret>, Error> {
     let api: Api<Secret> = Api::namespaced(client, namespace);
-    // Because the Secret's name includese the uid, we don't
-    // have the to check the resource labels for a match.
     match api.get(name).await {
         Ok(secret) => Ok(Some(secret)),
         Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(None),
@@ -376,10 +1427,7 @@ async fn get_secret(client: Client, namespace: &str, name: &str) -> Result<Optio
 
 /// Returns the MaskConsumer's assigned provider from its status object.
 fn get_assigned_provider(instance: &MaskConsumer) -> Option<&AssignedProvider> {
-    instance
-        .status
-        .as_ref()
-        .map_or(None, |s| s.provider.as_ref())
+    instance.status.as_ref().and_then(|s| s.provider.as_ref())
 }
 
 /// Returns the [`MaskReservation`] resource referenced by the [`AssignedProvider`].
@@ -387,7 +1435,7 @@ async fn get_reservation(
     client: Client,
     provider: &AssignedProvider,
 ) -> Result<Option<MaskReservation>, Error> {
-    let reservation_name = format!("{}-{}", provider.name, provider.slot);
+    let reservation_name = reservation_name(&provider.name, provider.slot);
     let mr_api: Api<MaskReservation> = Api::namespaced(client, &provider.namespace);
     match mr_api.get(&reservation_name).await {
         // Ensure the MaskReservation's UID matches that in the AssignedProvider.
@@ -396,7 +1444,7 @@ async fn get_reservation(
                 .metadata
                 .uid
                 .as_deref()
-                .map_or(false, |uid| uid == provider.reservation) =>
+                .is_some_and(|uid| uid == provider.reservation) =>
         {
             // Referenced MaskReservation still exists.
             Ok(Some(mr))
@@ -408,11 +1456,18 @@ async fn get_reservation(
     }
 }
 
-/// Determines the action given that the only thing left to do
-/// is periodically keeping the Active phase up-to-date.
-fn determine_status_action(instance: &MaskConsumer) -> Result<ConsumerAction, Error> {
-    let (phase, age) = get_consumer_phase(instance)?;
-    if phase != MaskConsumerPhase::Active || age > PROBE_INTERVAL {
+/// Determines the action given that the only thing left to do is keeping
+/// the Active phase up-to-date. The MaskReservation and Secret are checked
+/// on every reconcile, so the status is only rewritten when the phase
+/// changes or it's older than `status_freshness`.
+fn determine_status_action(
+    instance: &MaskConsumer,
+    status_freshness: Duration,
+) -> Result<ConsumerAction, Error> {
+    let (phase, _) = get_consumer_phase(instance)?;
+    if phase != MaskConsumerPhase::Active
+        || needs_refresh(ensure_status_initialized(instance)?, status_freshness)
+    {
         Ok(ConsumerAction::Active)
     } else {
         Ok(ConsumerAction::NoOp)
@@ -426,8 +1481,10 @@ fn determine_status_action(instance: &MaskConsumer) -> Result<ConsumerAction, Er
 /// # Arguments
 /// - `instance`: The erroneous resource.
 /// - `error`: A reference to the `kube::Error` that occurred during reconciliation.
-/// - `_context`: Unused argument. Context Data "injected" automatically by kube-rs.
-fn on_error(instance: Arc<MaskConsumer>, error: &Error, _context: Arc<ContextData>) -> Action {
-    eprintln!("Reconciliation error:\n{:?}.\n{:?}", error, instance);
-    Action::requeue(Duration::from_secs(5))
+/// - `context`: Context Data "injected" automatically by kube-rs. Its client is used
+///   to record the failed action in the resource's status object.
+fn on_error(instance: Arc<MaskConsumer>, error: &Error, context: Arc<ContextData>) -> Action {
+    let action = report_reconcile_error(&instance, error);
+    record_action_error(context.client.clone(), instance, "consumers", error);
+    action
 }
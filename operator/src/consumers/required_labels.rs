@@ -0,0 +1,45 @@
+use kube::ResourceExt;
+use vpn_types::*;
+
+use crate::util::CANARY_LABEL;
+
+/// Returns the labels the MaskProvider requires that the MaskConsumer
+/// doesn't have or has an empty value for. The MaskConsumer's labels
+/// are kept in sync with its Mask's. Canaries test the MaskProvider
+/// regardless of its required labels.
+pub fn missing(provider: &MaskProvider, instance: &MaskConsumer) -> Vec<String> {
+    let labels = instance.labels();
+    if labels.contains_key(CANARY_LABEL) {
+        return Vec::new();
+    }
+    provider
+        .spec
+        .required_mask_labels
+        .iter()
+        .flatten()
+        .filter(|key| labels.get(*key).is_none_or(|value| value.is_empty()))
+        .cloned()
+        .collect()
+}
+
+/// Removes the MaskProviders requiring labels that the MaskConsumer is
+/// missing. Returns the remaining MaskProviders along with the sorted
+/// labels that the removed ones required.
+pub fn exclude(
+    providers: Vec<MaskProvider>,
+    instance: &MaskConsumer,
+) -> (Vec<MaskProvider>, Vec<String>) {
+    let mut missing_keys = Vec::new();
+    let providers = providers
+        .into_iter()
+        .filter(|p| {
+            let missing = missing(p, instance);
+            let eligible = missing.is_empty();
+            missing_keys.extend(missing);
+            eligible
+        })
+        .collect();
+    missing_keys.sort();
+    missing_keys.dedup();
+    (providers, missing_keys)
+}
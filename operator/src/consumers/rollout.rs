@@ -0,0 +1,136 @@
+use k8s_openapi::{
+    api::{
+        apps::v1::{Deployment, StatefulSet},
+        core::v1::{PodTemplateSpec, Secret},
+    },
+    ByteString,
+};
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    Api, Client, Resource, ResourceExt,
+};
+use openssl::sha::Sha256;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, fmt::Debug};
+
+use super::withdrawal::spec_references_secret;
+use crate::util::{
+    list::list_all_paginated, Error, ErrorContext, CREDENTIALS_HASH_ANNOTATION, ROLLOUT_LABEL,
+};
+
+/// Returns the hex-encoded SHA-256 checksum of the Secret data. Keys are
+/// hashed in sorted order, and each key and value is prefixed with its
+/// length, so the same data always has the same checksum and no two
+/// different sets of data share one.
+pub fn credentials_hash(data: Option<&BTreeMap<String, ByteString>>) -> String {
+    let mut hasher = Sha256::new();
+    for (key, value) in data.into_iter().flatten() {
+        hasher.update(&(key.len() as u64).to_be_bytes());
+        hasher.update(key.as_bytes());
+        hasher.update(&(value.0.len() as u64).to_be_bytes());
+        hasher.update(&value.0);
+    }
+    hasher
+        .finish()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Returns the checksum recorded in the credentials Secret's annotations.
+pub fn secret_hash(secret: &Secret) -> Option<&str> {
+    secret
+        .metadata
+        .annotations
+        .as_ref()?
+        .get(CREDENTIALS_HASH_ANNOTATION)
+        .map(|hash| hash.as_str())
+}
+
+/// Returns true if the workload with the labels opted in to being rolled
+/// out, its Pod template references the Secret and the template isn't
+/// already annotated with the checksum.
+pub fn needs_rollout(
+    labels: &BTreeMap<String, String>,
+    template: &PodTemplateSpec,
+    secret: &str,
+    hash: &str,
+) -> bool {
+    let opted_in = labels.get(ROLLOUT_LABEL).map(|v| v.as_str()) == Some("true");
+    let references = template
+        .spec
+        .as_ref()
+        .is_some_and(|spec| spec_references_secret(spec, secret));
+    let annotated = template
+        .metadata
+        .as_ref()
+        .and_then(|m| m.annotations.as_ref())
+        .and_then(|a| a.get(CREDENTIALS_HASH_ANNOTATION))
+        .map(|v| v.as_str())
+        == Some(hash);
+    opted_in && references && !annotated
+}
+
+/// Returns the patch that annotates a workload's Pod template with the
+/// checksum, which makes its controller roll out new Pods.
+pub fn template_patch(hash: &str) -> Value {
+    json!({
+        "spec": {
+            "template": {
+                "metadata": {
+                    "annotations": {
+                        CREDENTIALS_HASH_ANNOTATION: hash
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Annotates the Pod templates of the opted-in Deployments and StatefulSets
+/// in the namespace that use the credentials Secret with its checksum.
+/// Workloads without the [`ROLLOUT_LABEL`] label are never modified.
+pub async fn annotate_workloads(
+    client: Client,
+    namespace: &str,
+    secret: &str,
+    hash: &str,
+) -> Result<(), Error> {
+    let deployments: Api<Deployment> = Api::namespaced(client.clone(), namespace);
+    annotate(&deployments, secret, hash, |d| {
+        d.spec.as_ref().map(|s| &s.template)
+    })
+    .await?;
+    let stateful_sets: Api<StatefulSet> = Api::namespaced(client, namespace);
+    annotate(&stateful_sets, secret, hash, |s| {
+        s.spec.as_ref().map(|s| &s.template)
+    })
+    .await
+}
+
+/// Annotates the Pod templates of the opted-in workloads listed with the
+/// API that need to be rolled out.
+async fn annotate<K>(
+    api: &Api<K>,
+    secret: &str,
+    hash: &str,
+    template: fn(&K) -> Option<&PodTemplateSpec>,
+) -> Result<(), Error>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug,
+{
+    // Only opted-in workloads are listed, so others are never touched.
+    let lp = ListParams::default().labels(&format!("{}=true", ROLLOUT_LABEL));
+    let patch = template_patch(hash);
+    for workload in list_all_paginated(api, &lp).await? {
+        if !template(&workload).is_some_and(|t| needs_rollout(workload.labels(), t, secret, hash)) {
+            continue;
+        }
+        let name = workload.name_any();
+        api.patch(&name, &PatchParams::default(), &Patch::Merge(&patch))
+            .await
+            .context_kind_name(&K::kind(&()), &name)?;
+    }
+    Ok(())
+}
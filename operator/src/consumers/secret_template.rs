@@ -0,0 +1,92 @@
+use vpn_types::*;
+
+use super::slots::{bounded_name, MAX_NAME_LEN};
+
+/// Placeholder for the Mask's name, which is also the MaskConsumer's.
+pub const MASK: &str = "${MASK}";
+
+/// Placeholder for the MaskProvider's name.
+pub const PROVIDER: &str = "${PROVIDER}";
+
+/// Placeholder for the slot reserved with the MaskProvider.
+pub const SLOT: &str = "${SLOT}";
+
+/// Placeholder for the first eight characters of the MaskConsumer's UID.
+pub const UID_SHORT: &str = "${UID_SHORT}";
+
+/// Number of characters of the MaskConsumer's UID used for [`UID_SHORT`].
+const UID_SHORT_LEN: usize = 8;
+
+/// Returns the name rendered from the copy Secret name template. Names
+/// that would be too long are shortened with a hash, as default names are.
+pub fn render(template: &str, mask: &str, provider: &str, slot: usize, uid: &str) -> String {
+    let uid_short: String = uid.chars().take(UID_SHORT_LEN).collect();
+    let name = template
+        .replace(MASK, mask)
+        .replace(PROVIDER, provider)
+        .replace(SLOT, &slot.to_string())
+        .replace(UID_SHORT, &uid_short);
+    bounded_name(&name, "", MAX_NAME_LEN)
+}
+
+/// Returns an error describing why the MaskProvider's copy Secret name
+/// template can't be used, if it has one. The template may only contain
+/// the known placeholders, must render valid names, and must tell the
+/// copies of different Masks in a namespace apart unless there's only
+/// one slot to assign.
+pub fn validate(provider: &MaskProvider) -> Result<(), String> {
+    let template = match provider.spec.copy_secret_name_template.as_deref() {
+        Some(template) => template,
+        None => return Ok(()),
+    };
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end + 1)
+            .ok_or_else(|| {
+                format!(
+                    "copySecretNameTemplate '{}' has an unclosed placeholder",
+                    template
+                )
+            })?;
+        let placeholder = &rest[start..end];
+        if ![MASK, PROVIDER, SLOT, UID_SHORT].contains(&placeholder) {
+            return Err(format!(
+                "copySecretNameTemplate '{}' has unknown placeholder {}",
+                template, placeholder
+            ));
+        }
+        rest = &rest[end..];
+    }
+    if provider.spec.max_slots > 1 && !template.contains(MASK) && !template.contains(UID_SHORT) {
+        return Err(format!(
+            "copySecretNameTemplate '{}' must contain {} or {}, as the copies of several Masks in a namespace would have the same name",
+            template, MASK, UID_SHORT
+        ));
+    }
+    // The names substituted for the placeholders are valid names, which
+    // begin and end with an alphanumeric character like the samples do,
+    // so any name rendered is valid if the sample is.
+    let sample = render(template, "mask", "provider", 0, "0123abcd");
+    if !is_dns_subdomain(&sample) {
+        return Err(format!(
+            "copySecretNameTemplate '{}' doesn't render a valid Secret name, e.g. '{}'",
+            template, sample
+        ));
+    }
+    Ok(())
+}
+
+/// Returns true if the name is a valid DNS-1123 subdomain, which
+/// is required of the names of most resources, including Secrets.
+pub fn is_dns_subdomain(name: &str) -> bool {
+    let alphanumeric = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.split('.').all(|label| {
+            label.starts_with(alphanumeric)
+                && label.ends_with(alphanumeric)
+                && label.chars().all(|c| alphanumeric(c) || c == '-')
+        })
+}
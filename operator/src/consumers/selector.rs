@@ -0,0 +1,153 @@
+use std::{collections::BTreeMap, sync::Arc};
+use vpn_types::*;
+
+use super::slots::fnv1a;
+use crate::util::Error;
+
+/// Name of the selector that is used unless another is configured.
+pub const DEFAULT_SELECTOR: &str = "default";
+
+/// Decides which [`MaskProvider`]s a [`MaskConsumer`] should be assigned
+/// first. The consumers controller tries to reserve a slot with each of
+/// the returned [`MaskProvider`]s in order, stopping at the first one
+/// with a free slot. Candidates omitted from the result are never tried.
+///
+/// The candidates have already been filtered down to the [`MaskProvider`]s
+/// the [`MaskConsumer`] may be assigned, so a selector only has to order
+/// them. Selectors are called for every assignment, so they should be
+/// fast and must not block.
+///
+/// Custom selectors are compiled into the operator and registered with
+/// a [`SelectorRegistry`] in `register_selectors` in `main.rs`, after
+/// which they can be chosen with `--provider-selector`. The example is
+/// the operator's own `test/same_namespace.rs`, which the tests register
+/// and run, as doctests aren't run for the operator binary:
+///
+/// ```rust
+#[doc = include_str!("../test/same_namespace.rs")]
+///
+/// // In `register_selectors` in `main.rs`:
+/// registry.register("same-namespace", SameNamespace);
+/// ```
+pub trait ProviderSelector: Send + Sync {
+    /// Returns the candidates in the order they should be tried.
+    fn select(&self, candidates: Vec<MaskProvider>, consumer: &MaskConsumer) -> Vec<MaskProvider>;
+}
+
+/// Tries the candidates in the order they were listed by the API server.
+/// This is how [`MaskProvider`]s were always chosen before selectors
+/// were configurable.
+pub struct ListOrder;
+
+impl ProviderSelector for ListOrder {
+    fn select(&self, candidates: Vec<MaskProvider>, _consumer: &MaskConsumer) -> Vec<MaskProvider> {
+        candidates
+    }
+}
+
+/// Tries the candidates with the smallest fraction of their slots in
+/// use first, spreading [`MaskConsumer`]s evenly across [`MaskProvider`]s
+/// of different sizes. Ties keep the order they were listed in.
+pub struct LeastLoaded;
+
+impl LeastLoaded {
+    /// Returns the fraction of the [`MaskProvider`]'s slots in use,
+    /// according to its status.
+    fn load(provider: &MaskProvider) -> f64 {
+        let active = provider
+            .status
+            .as_ref()
+            .and_then(|s| s.active_slots)
+            .unwrap_or(0);
+        active as f64 / provider.spec.max_slots.max(1) as f64
+    }
+}
+
+impl ProviderSelector for LeastLoaded {
+    fn select(
+        &self,
+        mut candidates: Vec<MaskProvider>,
+        _consumer: &MaskConsumer,
+    ) -> Vec<MaskProvider> {
+        candidates.sort_by(|a, b| Self::load(a).total_cmp(&Self::load(b)));
+        candidates
+    }
+}
+
+/// Orders the candidates at random in proportion to their
+/// [`MaskProviderSpec::weight`], so a [`MaskProvider`] with twice the
+/// weight is tried first twice as often. The order is derived from the
+/// UIDs of the [`MaskConsumer`] and [`MaskProvider`]s with weighted
+/// rendezvous hashing, so it is the same for every retry of the same
+/// [`MaskConsumer`], even across upgrades of the operator, as the hash
+/// is [`fnv1a`]. Candidates with a weight of zero are never tried.
+pub struct Weighted;
+
+impl Weighted {
+    /// Returns the candidate's score for the [`MaskConsumer`]. Lower
+    /// scores are tried first.
+    fn score(provider: &MaskProvider, consumer: &MaskConsumer) -> f64 {
+        let hash = fnv1a(&format!(
+            "{}/{}",
+            consumer.metadata.uid.as_deref().unwrap_or_default(),
+            provider.metadata.uid.as_deref().unwrap_or_default(),
+        ));
+        // Map the hash uniformly onto (0, 1].
+        let unit = (hash as f64 + 1.0) / (1u64 << 32) as f64;
+        -unit.ln() / provider.spec.weight.unwrap_or(1) as f64
+    }
+}
+
+impl ProviderSelector for Weighted {
+    fn select(&self, candidates: Vec<MaskProvider>, consumer: &MaskConsumer) -> Vec<MaskProvider> {
+        let mut scored: Vec<(f64, MaskProvider)> = candidates
+            .into_iter()
+            .filter(|p| p.spec.weight != Some(0))
+            .map(|p| (Self::score(&p, consumer), p))
+            .collect();
+        scored.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        scored.into_iter().map(|(_, p)| p).collect()
+    }
+}
+
+/// Named [`ProviderSelector`]s that can be chosen with `--provider-selector`.
+#[derive(Clone)]
+pub struct SelectorRegistry {
+    selectors: BTreeMap<String, Arc<dyn ProviderSelector>>,
+}
+
+impl SelectorRegistry {
+    /// Returns a registry with the built-in selectors: `default`,
+    /// `least-loaded` and `weighted`.
+    pub fn builtin() -> Self {
+        let mut registry = SelectorRegistry {
+            selectors: BTreeMap::new(),
+        };
+        registry.register(DEFAULT_SELECTOR, ListOrder);
+        registry.register("least-loaded", LeastLoaded);
+        registry.register("weighted", Weighted);
+        registry
+    }
+
+    /// Registers the selector under the name, replacing any selector
+    /// that was registered under the same name.
+    pub fn register(&mut self, name: &str, selector: impl ProviderSelector + 'static) {
+        self.selectors.insert(name.to_owned(), Arc::new(selector));
+    }
+
+    /// Returns the selector registered under the name.
+    pub fn get(&self, name: &str) -> Result<Arc<dyn ProviderSelector>, Error> {
+        self.selectors.get(name).cloned().ok_or_else(|| {
+            Error::UserInputError(format!(
+                "unknown provider selector '{}', expected one of: {}",
+                name,
+                self.names().join(", ")
+            ))
+        })
+    }
+
+    /// Returns the names of the registered selectors.
+    pub fn names(&self) -> Vec<&str> {
+        self.selectors.keys().map(String::as_str).collect()
+    }
+}
@@ -0,0 +1,134 @@
+use k8s_openapi::{
+    api::core::v1::{Service, ServicePort, ServiceSpec},
+    apimachinery::pkg::util::intstr::IntOrString,
+};
+use kube::{api::ObjectMeta, Resource, ResourceExt};
+use std::collections::BTreeMap;
+use vpn_types::*;
+
+use super::slots::{bounded_name, MAX_LABEL_LEN};
+use crate::util::{messages, CONSUMER_UID_LABEL, MASK_NAME_LABEL};
+
+/// Returns true if the port is a valid TCP port number.
+fn is_port(port: i32) -> bool {
+    (1..=65535).contains(&port)
+}
+
+/// Returns true if the name is valid for a Service, i.e. a DNS label
+/// that starts with a letter.
+fn is_service_name(name: &str) -> bool {
+    name.len() <= MAX_LABEL_LEN
+        && name.starts_with(|c: char| c.is_ascii_lowercase())
+        && !name.ends_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Checks the Service requested by the Mask named `name`, returning the
+/// message shown in its status if it can't be created.
+pub fn validate(name: &str, service: &MaskServiceSpec) -> Result<(), String> {
+    if !is_service_name(name) {
+        return Err(messages::invalid_service(&format!(
+            "'{}' isn't a valid Service name",
+            name
+        )));
+    }
+    if !is_port(service.port) {
+        return Err(messages::invalid_service(&format!(
+            "port {} is out of range",
+            service.port
+        )));
+    }
+    if let Some(target_port) = service.target_port.filter(|port| !is_port(*port)) {
+        return Err(messages::invalid_service(&format!(
+            "targetPort {} is out of range",
+            target_port
+        )));
+    }
+    if service.selector_labels.is_empty() {
+        return Err(messages::invalid_service(
+            "selectorLabels must not be empty",
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the Service requested with the MaskConsumer's `spec.service`.
+/// It has the name of the MaskConsumer, which is that of its Mask, and is
+/// owned by the MaskConsumer so it's garbage collected along with it.
+pub fn desired_service(instance: &MaskConsumer, service: &MaskServiceSpec) -> Service {
+    let mut labels = BTreeMap::new();
+    labels.insert(
+        MASK_NAME_LABEL.to_owned(),
+        bounded_name(&instance.name_any(), "", MAX_LABEL_LEN),
+    );
+    if let Some(uid) = instance.metadata.uid.as_ref() {
+        labels.insert(CONSUMER_UID_LABEL.to_owned(), uid.clone());
+    }
+    Service {
+        metadata: ObjectMeta {
+            name: Some(instance.name_any()),
+            namespace: instance.metadata.namespace.clone(),
+            owner_references: Some(vec![instance.controller_owner_ref(&()).unwrap()]),
+            labels: Some(labels),
+            ..Default::default()
+        },
+        spec: Some(ServiceSpec {
+            type_: Some("ClusterIP".to_owned()),
+            selector: Some(service.selector_labels.clone()),
+            ports: Some(vec![ServicePort {
+                port: service.port,
+                target_port: Some(IntOrString::Int(
+                    service.target_port.unwrap_or(service.port),
+                )),
+                protocol: Some("TCP".to_owned()),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Returns the parts of the Service's spec the MaskConsumer controls, with
+/// the defaults the API server fills in applied so they compare equal.
+fn managed_spec(service: &Service) -> ServiceSpec {
+    let spec = service.spec.clone().unwrap_or_default();
+    let ports = spec
+        .ports
+        .unwrap_or_default()
+        .into_iter()
+        .map(|p| ServicePort {
+            port: p.port,
+            target_port: Some(p.target_port.unwrap_or(IntOrString::Int(p.port))),
+            protocol: Some(p.protocol.unwrap_or_else(|| "TCP".to_owned())),
+            ..Default::default()
+        })
+        .collect();
+    ServiceSpec {
+        type_: Some(spec.type_.unwrap_or_else(|| "ClusterIP".to_owned())),
+        selector: Some(spec.selector.unwrap_or_default()),
+        ports: Some(ports),
+        ..Default::default()
+    }
+}
+
+/// Returns true if the type, selector or ports of the existing Service
+/// differ from the desired one's, e.g. because the Mask's `spec.service`
+/// was changed after the Service was created.
+pub fn has_drifted(existing: &Service, desired: &Service) -> bool {
+    managed_spec(existing) != managed_spec(desired)
+}
+
+/// Returns true if the Service is owned by the MaskConsumer, as opposed to
+/// one with the same name that was created by something else.
+pub fn is_owned(service: &Service, instance: &MaskConsumer) -> bool {
+    let uid = instance.metadata.uid.as_deref();
+    service
+        .metadata
+        .owner_references
+        .iter()
+        .flatten()
+        .any(|or| Some(or.uid.as_str()) == uid)
+}
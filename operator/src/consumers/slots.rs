@@ -0,0 +1,57 @@
+use vpn_types::MaskReservation;
+
+/// Maximum length of a resource name (a DNS subdomain).
+pub const MAX_NAME_LEN: usize = 253;
+
+/// Maximum length of a label value.
+pub const MAX_LABEL_LEN: usize = 63;
+
+/// Returns the name of the MaskReservation for the MaskProvider's slot,
+/// which is `<provider>-<slot>`. If that would be too long, the provider
+/// name is truncated and a hash of the full name is added to keep names
+/// unique among MaskProviders that share a long prefix.
+pub fn reservation_name(provider_name: &str, slot: usize) -> String {
+    let name = format!("{}-{}", provider_name, slot);
+    if name.len() <= MAX_NAME_LEN {
+        return name;
+    }
+    let suffix = format!("-{:08x}-{}", fnv1a(provider_name), slot);
+    // Names are ASCII, and the prefix must not end with a separator.
+    let prefix = provider_name[..MAX_NAME_LEN - suffix.len()].trim_end_matches(['-', '.']);
+    format!("{}{}", prefix, suffix)
+}
+
+/// Returns `<name><suffix>` if it is at most `max_len` long. Otherwise,
+/// the name is truncated and a hash of the full result is added before
+/// the suffix, which is kept intact so it can still be recognized.
+pub fn bounded_name(name: &str, suffix: &str, max_len: usize) -> String {
+    let full = format!("{}{}", name, suffix);
+    if full.len() <= max_len {
+        return full;
+    }
+    let tail = format!("-{:08x}{}", fnv1a(&full), suffix);
+    // Names are ASCII, and the prefix must not end with a separator.
+    let end = max_len.saturating_sub(tail.len()).min(name.len());
+    let prefix = name[..end].trim_end_matches(['-', '.']);
+    format!("{}{}", prefix, tail)
+}
+
+/// Returns the slot reserved by the MaskReservation, which is stored in
+/// its spec. Older reservations only have the slot in their name, so it
+/// is parsed from there, as long as the name is exactly what
+/// [`reservation_name`] gives for the MaskProvider and that slot.
+pub fn reservation_slot(reservation: &MaskReservation, provider_name: &str) -> Option<usize> {
+    reservation.spec.slot.or_else(|| {
+        let name = reservation.metadata.name.as_deref()?;
+        let slot = name.rsplit('-').next()?.parse().ok()?;
+        (reservation_name(provider_name, slot) == name).then_some(slot)
+    })
+}
+
+/// 32-bit FNV-1a hash, used because it is stable across Rust versions
+/// and reservation and Secret names must never change once they are created.
+pub fn fnv1a(s: &str) -> u32 {
+    s.bytes().fold(0x811c9dc5, |hash, b| {
+        (hash ^ b as u32).wrapping_mul(0x01000193)
+    })
+}
@@ -0,0 +1,124 @@
+use k8s_openapi::{api::core::v1::Secret, apimachinery::pkg::apis::meta::v1::OwnerReference};
+use kube::{Api, Client};
+use vpn_types::*;
+
+use super::{
+    external::is_external,
+    slots::{bounded_name, MAX_LABEL_LEN},
+};
+use crate::util::{Error, ErrorContext, MASK_NAME_LABEL, PROVIDER_UID_LABEL};
+
+/// Returns the name of the MaskConsumer that the MaskConsumer succeeds,
+/// if it names one other than itself.
+pub fn predecessor_name(instance: &MaskConsumer) -> Option<&str> {
+    instance
+        .spec
+        .succession_of
+        .as_deref()
+        .filter(|name| Some(*name) != instance.metadata.name.as_deref())
+}
+
+/// Returns the owner reference of the MaskConsumer to its Mask, if a
+/// Mask created it.
+fn mask_owner(consumer: &MaskConsumer) -> Option<&OwnerReference> {
+    consumer
+        .metadata
+        .owner_references
+        .iter()
+        .flatten()
+        .find(|or| or.kind == "Mask")
+}
+
+/// Returns true if the predecessor can be succeeded: it was created by a
+/// Mask, and either it's being deleted or that Mask is gone or being
+/// deleted, so it's no longer using its slot. `mask` is the Mask with the
+/// name of the predecessor's owner, if it exists. A live predecessor is
+/// never taken over, whatever names it as its predecessor, and neither
+/// is a MaskConsumer created by an external client.
+pub fn is_released(predecessor: &MaskConsumer, mask: Option<&Mask>) -> bool {
+    let owner = match mask_owner(predecessor) {
+        Some(owner) if !is_external(predecessor) => owner,
+        _ => return false,
+    };
+    predecessor.metadata.deletion_timestamp.is_some()
+        || mask.is_none_or(|mask| {
+            mask.metadata.uid.as_deref() != Some(&owner.uid)
+                || mask.metadata.deletion_timestamp.is_some()
+        })
+}
+
+/// Returns true if the MaskConsumer names a predecessor in the same
+/// namespace that can be succeeded.
+pub async fn predecessor_released(client: Client, instance: &MaskConsumer) -> Result<bool, Error> {
+    Ok(get_predecessor(client, instance).await?.is_some())
+}
+
+/// Returns the MaskConsumer's predecessor, if it names one that still
+/// exists in the same namespace and can be succeeded. A predecessor
+/// that is already gone left its reservation to be pruned, which frees
+/// the slot just the same.
+pub async fn get_predecessor(
+    client: Client,
+    instance: &MaskConsumer,
+) -> Result<Option<MaskConsumer>, Error> {
+    let name = match predecessor_name(instance) {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    let namespace = instance.metadata.namespace.as_deref().unwrap();
+    let predecessor = match Api::<MaskConsumer>::namespaced(client.clone(), namespace)
+        .get_opt(name)
+        .await
+        .context_kind_name("MaskConsumer", name)?
+    {
+        Some(predecessor) => predecessor,
+        None => return Ok(None),
+    };
+    let mask = match mask_owner(&predecessor) {
+        Some(owner) => Api::<Mask>::namespaced(client, namespace)
+            .get_opt(&owner.name)
+            .await
+            .context_kind_name("Mask", &owner.name)?,
+        None => None,
+    };
+    Ok(is_released(&predecessor, mask.as_ref()).then_some(predecessor))
+}
+
+/// Returns the reservation that the predecessor was assigned, out of
+/// the MaskProvider's reservations, if it still holds it.
+pub fn predecessor_reservation<'a>(
+    reservations: &'a [MaskReservation],
+    predecessor: &MaskConsumer,
+) -> Option<&'a MaskReservation> {
+    let assigned = predecessor.status.as_ref()?.provider.as_ref()?;
+    reservations.iter().find(|mr| {
+        mr.metadata.uid.as_deref() == Some(&assigned.reservation)
+            && Some(&mr.spec.name) == predecessor.metadata.name.as_ref()
+            && Some(&mr.spec.namespace) == predecessor.metadata.namespace.as_ref()
+            && Some(&mr.spec.uid) == predecessor.metadata.uid.as_ref()
+            && mr.metadata.deletion_timestamp.is_none()
+    })
+}
+
+/// Returns true if the Secret is the copy of the credentials of the
+/// assigned MaskProvider that was made for the MaskConsumer's predecessor,
+/// which happens to have the same name as the MaskConsumer's own copy,
+/// e.g. if the copy Secret name template doesn't include the Mask's name.
+pub fn is_predecessor_copy(secret: &Secret, instance: &MaskConsumer) -> bool {
+    let (name, provider) = match (
+        predecessor_name(instance),
+        instance.status.as_ref().and_then(|s| s.provider.as_ref()),
+    ) {
+        (Some(name), Some(provider)) => (name, provider),
+        _ => return false,
+    };
+    let labels = secret.metadata.labels.clone().unwrap_or_default();
+    labels.get(MASK_NAME_LABEL) == Some(&bounded_name(name, "", MAX_LABEL_LEN))
+        && labels.get(PROVIDER_UID_LABEL) == Some(&provider.uid)
+        && secret
+            .metadata
+            .owner_references
+            .iter()
+            .flatten()
+            .all(|or| or.kind == "MaskConsumer" && or.name == name)
+}
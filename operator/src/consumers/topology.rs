@@ -0,0 +1,117 @@
+use kube::Client;
+use std::sync::Arc;
+use vpn_types::*;
+
+use super::namespaces::NamespaceCache;
+use crate::util::Error;
+
+/// Namespace label holding the region of the namespace, unless another
+/// is configured with `--topology-label`.
+pub const DEFAULT_TOPOLOGY_LABEL: &str = "topology.vpn.beebs.dev/region";
+
+/// Key of the [`MaskProvider`] tags that name its region, as in `region=eu`.
+pub const REGION_TAG_KEY: &str = "region";
+
+/// Settings of the topology-aware strategy, from `--topology-label`
+/// and `--topology-aware`.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Namespace label holding the region of the namespace.
+    pub label: String,
+
+    /// Whether MaskConsumers that don't choose a strategy are topology aware.
+    pub by_default: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            label: DEFAULT_TOPOLOGY_LABEL.to_owned(),
+            by_default: false,
+        }
+    }
+}
+
+/// Returns the regions the MaskProvider is tagged with. Tags of the
+/// form `region=<value>` are recognized, ignoring whitespace around the
+/// key and value. Other tags, including bare region names, are not.
+pub fn provider_regions(provider: &MaskProvider) -> impl Iterator<Item = &str> {
+    provider
+        .spec
+        .tags
+        .iter()
+        .flatten()
+        .filter_map(|tag| tag.split_once('='))
+        .filter(|(key, _)| key.trim() == REGION_TAG_KEY)
+        .map(|(_, value)| value.trim())
+        .filter(|value| !value.is_empty())
+}
+
+/// Which group of candidates a MaskProvider was assigned from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TopologyTier {
+    /// The MaskProvider is tagged with the namespace's region.
+    SameRegion,
+
+    /// The MaskProvider was a fallback outside the namespace's region.
+    OtherRegion,
+}
+
+/// Returns the tier of the MaskProvider for a namespace in the region.
+pub fn tier(provider: &MaskProvider, region: &str) -> TopologyTier {
+    if provider_regions(provider).any(|r| r == region) {
+        TopologyTier::SameRegion
+    } else {
+        TopologyTier::OtherRegion
+    }
+}
+
+/// Orders the candidates so that those in the region are tried first,
+/// followed by all others. Each tier keeps the order it had before.
+pub fn prefer_region(candidates: Vec<MaskProvider>, region: &str) -> Vec<MaskProvider> {
+    let (mut same, other): (Vec<_>, Vec<_>) = candidates
+        .into_iter()
+        .partition(|p| tier(p, region) == TopologyTier::SameRegion);
+    same.extend(other);
+    same
+}
+
+/// Looks up the regions of namespaces from their labels, which come from
+/// the shared cache like those checked by
+/// [`NamespaceOptIn`](super::optin::NamespaceOptIn), so assigning many
+/// MaskConsumers in the same namespace doesn't hammer the API server.
+pub struct NamespaceTopology {
+    settings: Settings,
+    namespaces: Arc<NamespaceCache>,
+}
+
+impl NamespaceTopology {
+    pub fn new(settings: Settings, namespaces: Arc<NamespaceCache>) -> Self {
+        NamespaceTopology {
+            settings,
+            namespaces,
+        }
+    }
+
+    /// Returns true if the MaskProviders of the MaskConsumer are ordered
+    /// by the region of its namespace. MaskConsumers without
+    /// [`MaskConsumerSpec::strategy`] are if the strategy is the default.
+    pub fn is_topology_aware(&self, instance: &MaskConsumer) -> bool {
+        match instance.spec.strategy {
+            Some(AssignmentStrategy::TopologyAware) => true,
+            Some(AssignmentStrategy::Selector) => false,
+            None => self.settings.by_default,
+        }
+    }
+
+    /// Returns the region of the namespace, if it is labeled with one,
+    /// using the cache if possible.
+    pub async fn region(&self, client: Client, namespace: &str) -> Result<Option<String>, Error> {
+        let meta = self.namespaces.get(client, namespace).await?;
+        Ok(meta
+            .labels
+            .get(&self.settings.label)
+            .filter(|value| !value.is_empty())
+            .cloned())
+    }
+}
@@ -0,0 +1,158 @@
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::{Container, ObjectReference, Pod, PodSpec};
+use kube::{Api, Client, Resource};
+use std::time::Duration;
+use vpn_types::*;
+
+use super::external;
+use crate::util::{events, list::list_all_paginated, messages, Error};
+
+/// Returns true if the container reads the Secret through its environment.
+fn container_references_secret(container: &Container, secret: &str) -> bool {
+    let env = container.env.iter().flatten().any(|var| {
+        var.value_from
+            .as_ref()
+            .and_then(|v| v.secret_key_ref.as_ref())
+            .and_then(|r| r.name.as_deref())
+            == Some(secret)
+    });
+    let env_from =
+        container.env_from.iter().flatten().any(|source| {
+            source.secret_ref.as_ref().and_then(|r| r.name.as_deref()) == Some(secret)
+        });
+    env || env_from
+}
+
+/// Returns true if the Pod mounts the Secret as a volume, projected or
+/// not, or injects any of its keys into the environment of a container.
+pub fn references_secret(pod: &Pod, secret: &str) -> bool {
+    pod.spec
+        .as_ref()
+        .is_some_and(|spec| spec_references_secret(spec, secret))
+}
+
+/// Returns true if Pods with the spec would reference the Secret, as
+/// described by [`references_secret`].
+pub fn spec_references_secret(spec: &PodSpec, secret: &str) -> bool {
+    let volumes = spec.volumes.iter().flatten().any(|volume| {
+        let mounted = volume
+            .secret
+            .as_ref()
+            .and_then(|s| s.secret_name.as_deref())
+            == Some(secret);
+        let projected = volume
+            .projected
+            .as_ref()
+            .and_then(|p| p.sources.as_ref())
+            .into_iter()
+            .flatten()
+            .any(|source| source.secret.as_ref().and_then(|s| s.name.as_deref()) == Some(secret));
+        mounted || projected
+    });
+    volumes
+        || spec
+            .containers
+            .iter()
+            .chain(spec.init_containers.iter().flatten())
+            .any(|container| container_references_secret(container, secret))
+}
+
+/// Returns true if the Pod has exited and can no longer read the Secret.
+pub fn is_finished(pod: &Pod) -> bool {
+    matches!(
+        pod.status.as_ref().and_then(|s| s.phase.as_deref()),
+        Some("Succeeded") | Some("Failed")
+    )
+}
+
+/// Returns true if the Pod is owned by the MaskConsumer, such as a
+/// verification Pod, and is garbage collected along with it.
+fn is_owned_by(pod: &Pod, instance: &MaskConsumer) -> bool {
+    let uid = instance.metadata.uid.as_deref();
+    pod.metadata
+        .owner_references
+        .iter()
+        .flatten()
+        .any(|or| Some(or.uid.as_str()) == uid)
+}
+
+/// Lists the Pods in the MaskConsumer's namespace that haven't exited and
+/// reference the Secret, except those owned by the MaskConsumer. Pods are
+/// listed in full, as they can't be filtered by the Secrets they reference
+/// on the server.
+pub async fn list_referencing_pods(
+    client: Client,
+    instance: &MaskConsumer,
+    secret: &str,
+) -> Result<Vec<ObjectReference>, Error> {
+    // The credentials of external MaskConsumers are used outside the cluster.
+    if external::is_external(instance) {
+        return Ok(Vec::new());
+    }
+    let namespace = instance.metadata.namespace.as_deref().unwrap();
+    let api: Api<Pod> = Api::namespaced(client, namespace);
+    Ok(list_all_paginated(&api, &Default::default())
+        .await?
+        .into_iter()
+        .filter(|pod| !is_finished(pod) && !is_owned_by(pod, instance))
+        .filter(|pod| references_secret(pod, secret))
+        .map(|pod| pod.object_ref(&()))
+        .collect())
+}
+
+/// Returns true if the MaskConsumer has been waiting on Pods to exit for
+/// longer than the grace period, after which its credentials are deleted
+/// regardless.
+pub fn grace_period_expired(instance: &MaskConsumer, grace: Duration, now: DateTime<Utc>) -> bool {
+    instance
+        .metadata
+        .deletion_timestamp
+        .as_ref()
+        .is_none_or(|deleted| {
+            now.signed_duration_since(deleted.0)
+                .to_std()
+                .is_ok_and(|waited| waited >= grace)
+        })
+}
+
+/// Returns the names of the referenced Pods.
+pub fn pod_names(pods: &[ObjectReference]) -> Vec<String> {
+    pods.iter().filter_map(|pod| pod.name.clone()).collect()
+}
+
+/// Publishes a Warning event on each of the Pods and on the Mask that owns
+/// the MaskConsumer, telling them the VPN credentials are being withdrawn.
+/// The Mask is referenced through the owner reference, so the event is
+/// published even if the Mask was already deleted.
+pub async fn warn(client: Client, instance: &MaskConsumer, secret: &str, pods: &[ObjectReference]) {
+    let namespace = instance.metadata.namespace.as_deref().unwrap();
+    let note = messages::credentials_withdrawn(namespace, secret);
+    for pod in pods {
+        events::warn_ref(
+            client.clone(),
+            pod.clone(),
+            "CredentialsWithdrawn",
+            "Delete",
+            note.clone(),
+        )
+        .await;
+    }
+    let mask = instance
+        .metadata
+        .owner_references
+        .iter()
+        .flatten()
+        .find(|or| or.kind == "Mask");
+    if let Some(mask) = mask {
+        let reference = ObjectReference {
+            api_version: Some(mask.api_version.clone()),
+            kind: Some(mask.kind.clone()),
+            name: Some(mask.name.clone()),
+            namespace: Some(namespace.to_owned()),
+            uid: Some(mask.uid.clone()),
+            ..Default::default()
+        };
+        let note = messages::credentials_withdrawn_from_pods(namespace, secret, &pod_names(pods));
+        events::warn_ref(client, reference, "CredentialsWithdrawn", "Delete", note).await;
+    }
+}
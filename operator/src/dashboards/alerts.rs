@@ -0,0 +1,115 @@
+use serde_json::{json, Value};
+
+use crate::util::metric_names::{self, full_name};
+
+/// A Prometheus alerting rule.
+struct Alert {
+    name: &'static str,
+    expr: String,
+    /// How long the condition must hold before the alert fires.
+    duration: &'static str,
+    severity: &'static str,
+    summary: &'static str,
+    description: &'static str,
+}
+
+impl Alert {
+    fn to_json(&self) -> Value {
+        json!({
+            "alert": self.name,
+            "expr": self.expr,
+            "for": self.duration,
+            "labels": { "severity": self.severity },
+            "annotations": {
+                "summary": self.summary,
+                "description": self.description,
+            },
+        })
+    }
+}
+
+/// Returns the alerting rules for metrics with the given prefix. The
+/// MasksWaiting alert fires once more than `masks_waiting_threshold`
+/// Masks have been waiting for a slot for 15 minutes.
+fn alerts(prefix: &str, masks_waiting_threshold: usize) -> Vec<Alert> {
+    let metric = |name| full_name(prefix, name);
+    vec![
+        Alert {
+            name: "VpnProviderVerifyFailed",
+            expr: format!(
+                "max by (namespace, name) ({}{{phase=\"ErrVerifyFailed\"}}) > 0",
+                metric(metric_names::PROVIDER_PHASE)
+            ),
+            duration: "10m",
+            severity: "warning",
+            summary: "MaskProvider credentials failed verification.",
+            description: "MaskProvider {{ $labels.namespace }}/{{ $labels.name }} has been in the ErrVerifyFailed phase for more than 10 minutes, so no new Masks are assigned to it.",
+        },
+        Alert {
+            name: "VpnProviderCanaryFailed",
+            expr: format!(
+                "sum by (namespace, name) (increase({}{{result=\"Failed\"}}[1h])) > 0",
+                metric(metric_names::PROVIDER_CANARIES_TOTAL)
+            ),
+            duration: "5m",
+            severity: "warning",
+            summary: "MaskProvider canary failed.",
+            description: "A canary Mask for MaskProvider {{ $labels.namespace }}/{{ $labels.name }} failed within the last hour, so Masks may not be getting its credentials. See the CanaryFailed events on the MaskProvider.",
+        },
+        Alert {
+            name: "VpnProviderSlotsNearlyFull",
+            expr: format!(
+                "{} / ({} > 0) > 0.9",
+                metric(metric_names::PROVIDER_ACTIVE_SLOTS),
+                metric(metric_names::PROVIDER_MAX_SLOTS)
+            ),
+            duration: "15m",
+            severity: "warning",
+            summary: "MaskProvider is running out of slots.",
+            description: "MaskProvider {{ $labels.namespace }}/{{ $labels.name }} has had more than 90% of its slots in use for 15 minutes.",
+        },
+        Alert {
+            name: "VpnMasksWaiting",
+            expr: format!(
+                "sum({}{{phase=\"Waiting\"}}) > {}",
+                metric(metric_names::MASK_PHASE),
+                masks_waiting_threshold
+            ),
+            duration: "15m",
+            severity: "warning",
+            summary: "Masks are waiting for MaskProvider slots.",
+            description: "{{ $value }} Masks have been waiting for a MaskProvider slot for 15 minutes.",
+        },
+        Alert {
+            name: "VpnOperatorActionsFailing",
+            expr: format!(
+                "sum by (kind, action) (rate({}[10m])) > 0",
+                metric(metric_names::ACTION_ERRORS_TOTAL)
+            ),
+            duration: "30m",
+            severity: "warning",
+            summary: "vpn-operator actions are failing.",
+            description: "The {{ $labels.kind }} controller has been failing to apply {{ $labels.action }} actions for 30 minutes. See the status.lastError of the affected resources.",
+        },
+    ]
+}
+
+/// Returns a PrometheusRule resource, as used by the Prometheus
+/// Operator, with the alerting rules for the given metrics prefix.
+pub fn prometheus_rule(prefix: &str, masks_waiting_threshold: usize) -> Value {
+    let rules: Vec<Value> = alerts(prefix, masks_waiting_threshold)
+        .iter()
+        .map(Alert::to_json)
+        .collect();
+    json!({
+        "apiVersion": "monitoring.coreos.com/v1",
+        "kind": "PrometheusRule",
+        "metadata": { "name": "vpn-operator" },
+        "spec": {
+            "groups": [{
+                "name": "vpn-operator",
+                "rules": rules,
+            }],
+        },
+    })
+}
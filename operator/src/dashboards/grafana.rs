@@ -0,0 +1,259 @@
+use serde_json::{json, Value};
+
+use crate::util::metric_names::{self, controller_name, full_name, CONTROLLERS};
+
+/// Unique identifier of the generated dashboard, so importing a newer
+/// version replaces the old one instead of creating a copy.
+pub const DASHBOARD_UID: &str = "vpn-operator";
+
+/// Width of a panel, which is half of Grafana's 24 column grid.
+const PANEL_WIDTH: u64 = 12;
+
+/// Height of a panel in grid units.
+const PANEL_HEIGHT: u64 = 8;
+
+/// A query of a panel, and how its series are named in the legend.
+struct Target {
+    expr: String,
+    legend: String,
+}
+
+impl Target {
+    fn new(expr: String, legend: &str) -> Self {
+        Target {
+            expr,
+            legend: legend.to_owned(),
+        }
+    }
+}
+
+/// A time series panel on the dashboard.
+struct Panel {
+    title: String,
+    unit: &'static str,
+    targets: Vec<Target>,
+}
+
+impl Panel {
+    fn new(title: impl Into<String>, unit: &'static str, targets: Vec<Target>) -> Self {
+        Panel {
+            title: title.into(),
+            unit,
+            targets,
+        }
+    }
+
+    /// Returns the panel's JSON model. Panels are laid out two per row.
+    fn to_json(&self, index: usize) -> Value {
+        let targets: Vec<Value> = self
+            .targets
+            .iter()
+            .enumerate()
+            .map(|(i, target)| {
+                json!({
+                    "datasource": { "type": "prometheus", "uid": "${datasource}" },
+                    "expr": target.expr,
+                    "legendFormat": target.legend,
+                    // Grafana names targets A, B, C, ...
+                    "refId": ((b'A' + i as u8) as char).to_string(),
+                })
+            })
+            .collect();
+        json!({
+            "id": index + 1,
+            "type": "timeseries",
+            "title": self.title,
+            "datasource": { "type": "prometheus", "uid": "${datasource}" },
+            "gridPos": {
+                "h": PANEL_HEIGHT,
+                "w": PANEL_WIDTH,
+                "x": (index as u64 % 2) * PANEL_WIDTH,
+                "y": (index as u64 / 2) * PANEL_HEIGHT,
+            },
+            "fieldConfig": { "defaults": { "unit": self.unit }, "overrides": [] },
+            "targets": targets,
+        })
+    }
+}
+
+/// Returns the panels of the dashboard for metrics with the given prefix.
+fn panels(prefix: &str) -> Vec<Panel> {
+    let metric = |name| full_name(prefix, name);
+    let mut panels = vec![
+        Panel::new(
+            "Reconciliations",
+            "ops",
+            CONTROLLERS
+                .iter()
+                .map(|tag| {
+                    Target::new(
+                        format!(
+                            "sum(rate({}[5m]))",
+                            controller_name(prefix, tag, metric_names::RECONCILE_COUNTER)
+                        ),
+                        tag,
+                    )
+                })
+                .collect(),
+        ),
+        Panel::new(
+            "Failed actions",
+            "ops",
+            vec![Target::new(
+                format!(
+                    "sum by (kind, action) (rate({}[5m]))",
+                    metric(metric_names::ACTION_ERRORS_TOTAL)
+                ),
+                "{{kind}} {{action}}",
+            )],
+        ),
+    ];
+    for tag in CONTROLLERS {
+        panels.push(Panel::new(
+            format!("Actions taken by the {} controller", tag),
+            "ops",
+            vec![Target::new(
+                format!(
+                    "sum by (action) (rate({}{{action!=\"NoOp\"}}[5m]))",
+                    controller_name(prefix, tag, metric_names::ACTION_COUNTER)
+                ),
+                "{{action}}",
+            )],
+        ));
+        panels.push(Panel::new(
+            format!("Action latency of the {} controller (p95)", tag),
+            "s",
+            vec![
+                Target::new(
+                    format!(
+                        "histogram_quantile(0.95, sum by (le, action) (rate({}_bucket[5m])))",
+                        controller_name(prefix, tag, metric_names::WRITE_DURATION_SECONDS)
+                    ),
+                    "write {{action}}",
+                ),
+                Target::new(
+                    format!(
+                        "histogram_quantile(0.95, sum by (le) (rate({}_bucket[5m])))",
+                        controller_name(prefix, tag, metric_names::READ_DURATION_SECONDS)
+                    ),
+                    "read",
+                ),
+            ],
+        ));
+    }
+    panels.extend([
+        Panel::new(
+            "MaskProvider slot utilization",
+            "percentunit",
+            vec![Target::new(
+                format!(
+                    "{} / ({} > 0)",
+                    metric(metric_names::PROVIDER_ACTIVE_SLOTS),
+                    metric(metric_names::PROVIDER_MAX_SLOTS)
+                ),
+                "{{namespace}}/{{name}}",
+            )],
+        ),
+        Panel::new(
+            "MaskProviders by phase",
+            "short",
+            vec![Target::new(
+                format!("sum by (phase) ({})", metric(metric_names::PROVIDER_PHASE)),
+                "{{phase}}",
+            )],
+        ),
+        Panel::new(
+            "Verification outcomes",
+            "short",
+            vec![Target::new(
+                format!(
+                    "sum by (action) (increase({}{{action=~\"Verified|VerifyFailed\"}}[1h]))",
+                    controller_name(prefix, "providers", metric_names::ACTION_COUNTER)
+                ),
+                "{{action}}",
+            )],
+        ),
+        Panel::new(
+            "Canary outcomes",
+            "short",
+            vec![Target::new(
+                format!(
+                    "sum by (result) (increase({}[1h]))",
+                    metric(metric_names::PROVIDER_CANARIES_TOTAL)
+                ),
+                "{{result}}",
+            )],
+        ),
+        Panel::new(
+            "Masks by phase",
+            "short",
+            vec![
+                Target::new(
+                    format!("sum by (phase) ({})", metric(metric_names::MASK_PHASE)),
+                    "{{phase}}",
+                ),
+                Target::new(
+                    format!(
+                        "sum({}{{phase=\"Waiting\"}}) or vector(0)",
+                        metric(metric_names::MASK_PHASE)
+                    ),
+                    "waiting",
+                ),
+            ],
+        ),
+        Panel::new(
+            "Mask time to Active (p50, p95)",
+            "s",
+            [(0.5, "p50"), (0.95, "p95")]
+                .iter()
+                .map(|(q, legend)| {
+                    Target::new(
+                        format!(
+                            "histogram_quantile({}, sum by (le) (rate({}_bucket[15m])))",
+                            q,
+                            metric(metric_names::MASK_TIME_TO_ACTIVE_SECONDS)
+                        ),
+                        legend,
+                    )
+                })
+                .collect(),
+        ),
+        Panel::new(
+            "Assignments frozen",
+            "bool_on_off",
+            vec![Target::new(
+                format!("max({})", metric(metric_names::ASSIGNMENTS_FROZEN)),
+                "frozen",
+            )],
+        ),
+    ]);
+    panels
+}
+
+/// Returns the Grafana dashboard for metrics with the given prefix. The
+/// Prometheus datasource is chosen with a variable when it's imported.
+pub fn dashboard(prefix: &str) -> Value {
+    let panels: Vec<Value> = panels(prefix)
+        .iter()
+        .enumerate()
+        .map(|(i, panel)| panel.to_json(i))
+        .collect();
+    json!({
+        "uid": DASHBOARD_UID,
+        "title": "vpn-operator",
+        "tags": ["vpn-operator"],
+        "editable": true,
+        "schemaVersion": 37,
+        "refresh": "30s",
+        "time": { "from": "now-6h", "to": "now" },
+        "templating": {
+            "list": [{
+                "name": "datasource",
+                "label": "Datasource",
+                "type": "datasource",
+                "query": "prometheus",
+            }],
+        },
+        "panels": panels,
+    })
+}
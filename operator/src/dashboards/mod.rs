@@ -0,0 +1,100 @@
+use std::path::Path;
+
+use crate::util::{metric_names, Error};
+
+pub(crate) mod alerts;
+pub(crate) mod grafana;
+
+/// Name of the generated Grafana dashboard file.
+pub const DASHBOARD_FILE: &str = "vpn-operator-dashboard.json";
+
+/// Name of the generated PrometheusRule file.
+pub const ALERTS_FILE: &str = "vpn-operator-alerts.yaml";
+
+/// Generated monitoring resources for the operator's metrics.
+pub struct Dashboards {
+    /// Grafana dashboard JSON, ready to import.
+    pub dashboard: String,
+
+    /// PrometheusRule YAML with the alerting rules.
+    pub alerts: String,
+}
+
+/// Generates the dashboard and alerting rules for metrics with the
+/// given prefix. Every metric they reference is one of those listed
+/// in [`metric_names`], so they can't drift from the operator.
+/// Fails if the prefix can't start a Prometheus metric name.
+pub fn generate(prefix: &str, masks_waiting_threshold: usize) -> Result<Dashboards, Error> {
+    if !is_valid_prefix(prefix) {
+        return Err(Error::UserInputError(format!(
+            "metrics prefix '{}' must be letters, digits, underscores and colons, not starting with a digit",
+            prefix
+        )));
+    }
+    let mut dashboard = serde_json::to_string_pretty(&grafana::dashboard(prefix))?;
+    dashboard.push('\n');
+    let alerts = serde_yaml::to_string(&alerts::prometheus_rule(prefix, masks_waiting_threshold))?;
+    for document in [&dashboard, &alerts] {
+        let unknown = unknown_metrics(prefix, document);
+        if !unknown.is_empty() {
+            return Err(Error::UnknownMetrics(unknown));
+        }
+    }
+    Ok(Dashboards { dashboard, alerts })
+}
+
+/// Returns true if the prefix is a valid start of a Prometheus metric name.
+fn is_valid_prefix(prefix: &str) -> bool {
+    prefix.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+/// Entrypoint for generating the dashboard and alerting rules, which
+/// are written to `output_dir` as [`DASHBOARD_FILE`] and [`ALERTS_FILE`].
+pub fn write(output_dir: &Path, prefix: &str, masks_waiting_threshold: usize) -> Result<(), Error> {
+    let dashboards = generate(prefix, masks_waiting_threshold)?;
+    std::fs::create_dir_all(output_dir)?;
+    std::fs::write(output_dir.join(DASHBOARD_FILE), dashboards.dashboard)?;
+    std::fs::write(output_dir.join(ALERTS_FILE), dashboards.alerts)?;
+    println!(
+        "Wrote {} and {} to {}",
+        DASHBOARD_FILE,
+        ALERTS_FILE,
+        output_dir.display()
+    );
+    Ok(())
+}
+
+/// Returns the names of the metrics with the given prefix that are
+/// referenced in `document`. The series of a histogram are reported
+/// by the histogram's name.
+pub fn referenced_metrics(prefix: &str, document: &str) -> Vec<String> {
+    let known = metric_names::all(prefix);
+    let mut referenced: Vec<String> = document
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+        .filter(|token| token.starts_with(&format!("{}_", prefix)))
+        .map(|token| {
+            ["_bucket", "_sum", "_count"]
+                .iter()
+                .filter_map(|suffix| token.strip_suffix(suffix))
+                .find(|histogram| known.iter().any(|k| k == histogram))
+                .unwrap_or(token)
+                .to_owned()
+        })
+        .collect();
+    referenced.sort();
+    referenced.dedup();
+    referenced
+}
+
+/// Returns the metrics referenced in `document` that the operator
+/// doesn't export.
+pub fn unknown_metrics(prefix: &str, document: &str) -> Vec<String> {
+    let known = metric_names::all(prefix);
+    referenced_metrics(prefix, document)
+        .into_iter()
+        .filter(|metric| !known.contains(metric))
+        .collect()
+}
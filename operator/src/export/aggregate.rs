@@ -0,0 +1,167 @@
+use kube::{runtime::watcher::Event, ResourceExt};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+use vpn_types::*;
+
+/// A watch event for any of the resources in the aggregate. The
+/// events are boxed, as the resources differ greatly in size.
+pub enum Change {
+    Provider(Box<Event<MaskProvider>>),
+    Mask(Box<Event<Mask>>),
+    Consumer(Box<Event<MaskConsumer>>),
+    Reservation(Box<Event<MaskReservation>>),
+}
+
+/// Summaries of every resource of one kind, keyed by namespace and name.
+struct Summaries<T> {
+    /// True once the initial list of resources has been received,
+    /// so the aggregate isn't written while it's still incomplete.
+    synced: bool,
+
+    items: BTreeMap<(String, String), T>,
+}
+
+impl<T> Default for Summaries<T> {
+    fn default() -> Self {
+        Summaries {
+            synced: false,
+            items: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> Summaries<T> {
+    /// Applies the watch event, summarizing each resource with `f`.
+    fn apply<K: ResourceExt>(&mut self, event: Event<K>, f: impl Fn(&K) -> T) {
+        let key = |k: &K| (k.namespace().unwrap_or_default(), k.name_any());
+        match event {
+            Event::Applied(k) => {
+                self.items.insert(key(&k), f(&k));
+            }
+            Event::Deleted(k) => {
+                self.items.remove(&key(&k));
+            }
+            Event::Restarted(ks) => {
+                self.items = ks.iter().map(|k| (key(k), f(k))).collect();
+                self.synced = true;
+            }
+        }
+    }
+}
+
+/// Maintains a compact summary of every resource managed by the
+/// operator, from which the [`FleetStatus`] document is generated.
+#[derive(Default)]
+pub struct Aggregator {
+    providers: Summaries<ProviderSummary>,
+    masks: Summaries<MaskSummary>,
+    consumers: Summaries<Option<MaskConsumerPhase>>,
+    reservations: Summaries<Option<MaskReservationPhase>>,
+}
+
+impl Aggregator {
+    /// Applies a watch event to the aggregate.
+    pub fn apply(&mut self, change: Change) {
+        match change {
+            Change::Provider(event) => self.providers.apply(*event, |p| ProviderSummary {
+                namespace: p.namespace().unwrap_or_default(),
+                name: p.name_any(),
+                phase: p.status.as_ref().and_then(|s| s.phase),
+                active_slots: p.status.as_ref().and_then(|s| s.active_slots),
+                max_slots: p.spec.max_slots,
+            }),
+            Change::Mask(event) => self.masks.apply(*event, |m| MaskSummary {
+                namespace: m.namespace().unwrap_or_default(),
+                name: m.name_any(),
+                phase: m.status.as_ref().and_then(|s| s.phase),
+            }),
+            Change::Consumer(event) => self
+                .consumers
+                .apply(*event, |c| c.status.as_ref().and_then(|s| s.phase)),
+            Change::Reservation(event) => self
+                .reservations
+                .apply(*event, |r| r.status.as_ref().and_then(|s| s.phase)),
+        }
+    }
+
+    /// Returns true once the initial list of every kind has been received.
+    pub fn synced(&self) -> bool {
+        self.providers.synced
+            && self.masks.synced
+            && self.consumers.synced
+            && self.reservations.synced
+    }
+
+    /// Generates the [`FleetStatus`] document from the current aggregate.
+    pub fn status(&self, cluster: Option<&str>, generated_at: String) -> FleetStatus {
+        FleetStatus {
+            version: FLEET_STATUS_VERSION,
+            cluster: cluster.map(|c| c.to_owned()),
+            generated_at,
+            providers: self.providers.items.values().cloned().collect(),
+            masks: self.masks.items.values().cloned().collect(),
+            consumers: count_phases(self.consumers.items.values()),
+            reservations: count_phases(self.reservations.items.values()),
+        }
+    }
+}
+
+/// Counts the resources in each phase. Resources without a phase
+/// are counted under `"Unknown"`.
+pub fn count_phases<'a, P: ToString + 'a>(
+    phases: impl Iterator<Item = &'a Option<P>>,
+) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for phase in phases {
+        let key = phase
+            .as_ref()
+            .map_or_else(|| "Unknown".to_owned(), |p| p.to_string());
+        *counts.entry(key).or_default() += 1;
+    }
+    counts
+}
+
+/// Limits how often the aggregate is written. Changes are marked as
+/// they arrive, and are written together no sooner than `interval`
+/// after the previous write, so a burst of watch events results in
+/// a single write.
+pub struct Debounce {
+    interval: Duration,
+    last_flush: Option<Instant>,
+    dirty: bool,
+}
+
+impl Debounce {
+    pub fn new(interval: Duration) -> Self {
+        Debounce {
+            interval,
+            last_flush: None,
+            dirty: false,
+        }
+    }
+
+    /// Records that the aggregate has changed since the last write.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Returns when the pending changes should be written, which is never
+    /// earlier than `now`, or None if there are no pending changes.
+    pub fn deadline(&self, now: Instant) -> Option<Instant> {
+        if !self.dirty {
+            return None;
+        }
+        Some(match self.last_flush {
+            Some(last_flush) => (last_flush + self.interval).max(now),
+            None => now,
+        })
+    }
+
+    /// Records that the pending changes were written at `now`.
+    pub fn flushed(&mut self, now: Instant) {
+        self.last_flush = Some(now);
+        self.dirty = false;
+    }
+}
@@ -0,0 +1,102 @@
+use futures::{stream, StreamExt, TryStreamExt};
+use kube::{api::ListParams, runtime::watcher, Api, Client};
+use std::time::{Duration, Instant};
+use vpn_types::*;
+
+use crate::{
+    rbac::Rule,
+    util::{clock, Error, PROBE_INTERVAL},
+};
+
+pub(crate) mod aggregate;
+pub(crate) mod sink;
+
+pub use sink::Sink;
+
+use aggregate::{Aggregator, Change, Debounce};
+
+/// API access the status exporter needs: watching all four kinds of
+/// resources, and writing its document to a ConfigMap. See [`crate::rbac`].
+pub const RBAC: &[Rule] = &[
+    Rule::new(
+        "vpn.beebs.dev",
+        &[
+            "maskproviders",
+            "masks",
+            "maskconsumers",
+            "maskreservations",
+        ],
+        &["list", "watch"],
+    ),
+    Rule::new("", &["configmaps"], &["create", "patch"]),
+];
+
+/// Entrypoint for the status exporter. It watches all four kinds of
+/// resources and writes the [`FleetStatus`] document to `sink` at most
+/// once every `interval`. Nothing besides the sink is ever modified, so
+/// the exporter is safe to run alongside the controllers, or in a
+/// cluster where they run with a different version.
+pub async fn run(
+    client: Client,
+    sink: Sink,
+    interval: Duration,
+    cluster: Option<String>,
+) -> Result<(), Error> {
+    println!("Starting status exporter...");
+    let lp = ListParams::default();
+    let mut changes = stream::select_all([
+        watcher(Api::<MaskProvider>::all(client.clone()), lp.clone())
+            .map_ok(|e| Change::Provider(Box::new(e)))
+            .boxed(),
+        watcher(Api::<Mask>::all(client.clone()), lp.clone())
+            .map_ok(|e| Change::Mask(Box::new(e)))
+            .boxed(),
+        watcher(Api::<MaskConsumer>::all(client.clone()), lp.clone())
+            .map_ok(|e| Change::Consumer(Box::new(e)))
+            .boxed(),
+        watcher(Api::<MaskReservation>::all(client.clone()), lp)
+            .map_ok(|e| Change::Reservation(Box::new(e)))
+            .boxed(),
+    ]);
+    let mut aggregator = Aggregator::default();
+    let mut debounce = Debounce::new(interval);
+    loop {
+        // Only write once every kind has been listed, so a partial
+        // document is never mistaken for missing resources.
+        let deadline = debounce
+            .deadline(Instant::now())
+            .filter(|_| aggregator.synced());
+        let flush = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                None => futures::future::pending().await,
+            }
+        };
+        tokio::select! {
+            change = changes.next() => match change {
+                Some(Ok(change)) => {
+                    aggregator.apply(change);
+                    debounce.mark_dirty();
+                }
+                Some(Err(e)) => {
+                    eprintln!("Failed to watch resources for status export: {}", e);
+                    tokio::time::sleep(PROBE_INTERVAL).await;
+                }
+                None => return Ok(()),
+            },
+            _ = flush => {
+                let status = aggregator.status(cluster.as_deref(), clock::now_k8s());
+                match sink.write(client.clone(), &status).await {
+                    Ok(()) => debounce.flushed(Instant::now()),
+                    Err(e) => {
+                        // The changes are still pending, so the write
+                        // is retried once the interval has elapsed.
+                        eprintln!("Failed to export status: {}", e);
+                        debounce.flushed(Instant::now());
+                        debounce.mark_dirty();
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{
+    api::{ObjectMeta, Patch, PatchParams},
+    Api, Client,
+};
+use std::{collections::BTreeMap, path::PathBuf};
+use vpn_types::FleetStatus;
+
+use crate::util::{config::ConfigMapRef, Error, MANAGER_NAME};
+
+/// Key in the ConfigMap under which the [`FleetStatus`] document is written.
+pub const FLEET_STATUS_KEY: &str = "fleetStatus.json";
+
+/// Destination of the [`FleetStatus`] document.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sink {
+    /// Written under [`FLEET_STATUS_KEY`] in the ConfigMap, which
+    /// is created if it doesn't exist.
+    ConfigMap(ConfigMapRef),
+
+    /// Written to the file, e.g. for a sidecar to ship elsewhere. The file
+    /// is replaced atomically, so readers never see a partial document.
+    File(PathBuf),
+}
+
+impl Sink {
+    /// Writes the document to the sink.
+    pub async fn write(&self, client: Client, status: &FleetStatus) -> Result<(), Error> {
+        let json = serde_json::to_string(status)?;
+        match self {
+            Sink::ConfigMap(config_map) => {
+                let api: Api<ConfigMap> = Api::namespaced(client, &config_map.namespace);
+                let cm = ConfigMap {
+                    metadata: ObjectMeta {
+                        name: Some(config_map.name.clone()),
+                        namespace: Some(config_map.namespace.clone()),
+                        ..Default::default()
+                    },
+                    data: Some(BTreeMap::from([(FLEET_STATUS_KEY.to_owned(), json)])),
+                    ..Default::default()
+                };
+                api.patch(
+                    &config_map.name,
+                    &PatchParams::apply(MANAGER_NAME).force(),
+                    &Patch::Apply(&cm),
+                )
+                .await?;
+            }
+            Sink::File(path) => {
+                let mut tmp = path.clone().into_os_string();
+                tmp.push(".tmp");
+                std::fs::write(&tmp, json)?;
+                std::fs::rename(&tmp, path)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+use k8s_openapi::api::batch::v1::Job;
+use kube::{
+    api::{ObjectMeta, Patch, PatchParams, Resource},
+    Api, Client,
+};
+use serde_json::json;
+use std::{collections::BTreeMap, time::Duration};
+use vpn_types::*;
+
+use crate::{
+    consumers::default_providers::parse_default_providers,
+    masks::util::get_consumer,
+    util::{
+        events, Error, ErrorContext, AUTO_MASK_ANNOTATION, AUTO_MASK_JOB_LABEL, MANAGER_NAME,
+        MASK_SECRET_ANNOTATION,
+    },
+};
+
+/// Shortest time between checks on a Job whose Mask can't be assigned.
+const MIN_WAITING_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Longest time between checks on a Job whose Mask can't be assigned.
+const MAX_WAITING_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Returns the MaskProvider tags in the Job's auto-mask annotation, or
+/// None if the Job doesn't have the annotation.
+pub fn auto_mask_tags(job: &Job) -> Option<Option<Vec<String>>> {
+    let value = job
+        .metadata
+        .annotations
+        .as_ref()?
+        .get(AUTO_MASK_ANNOTATION)?;
+    Some(parse_default_providers(value))
+}
+
+/// Returns the name of the Mask created for the Job, which is
+/// the name of the Job itself.
+pub fn get_mask_name(job: &Job) -> &str {
+    job.metadata.name.as_deref().unwrap()
+}
+
+/// Returns the name of the credentials Secret already given to the
+/// Job's Pod template, if it was given one.
+pub fn injected_secret(job: &Job) -> Option<&str> {
+    job.spec
+        .as_ref()?
+        .template
+        .metadata
+        .as_ref()?
+        .annotations
+        .as_ref()?
+        .get(MASK_SECRET_ANNOTATION)
+        .map(String::as_str)
+}
+
+/// Returns true if the Job is suspended.
+pub fn is_suspended(job: &Job) -> bool {
+    job.spec.as_ref().and_then(|s| s.suspend).unwrap_or(false)
+}
+
+/// Returns true if the Job completed or failed, after which
+/// its Mask is no longer needed.
+pub fn is_finished(job: &Job) -> bool {
+    job.status
+        .as_ref()
+        .and_then(|s| s.conditions.as_ref())
+        .is_some_and(|c| {
+            c.iter()
+                .any(|c| (c.type_ == "Complete" || c.type_ == "Failed") && c.status == "True")
+        })
+}
+
+/// Returns true if the Job has no running Pods, so none
+/// of its Pods can start without credentials.
+pub fn is_stopped(job: &Job) -> bool {
+    job.status.as_ref().and_then(|s| s.active).unwrap_or(0) == 0
+}
+
+/// Returns true if the Mask was created for the Job.
+pub fn is_owned_by(mask: &Mask, job: &Job) -> bool {
+    let uid = job.metadata.uid.as_deref().unwrap();
+    mask.metadata
+        .owner_references
+        .as_ref()
+        .is_some_and(|o| o.iter().any(|r| r.uid == uid))
+}
+
+/// Returns the Mask for the Job, owned by it so it's garbage collected
+/// along with the Job.
+pub fn job_mask(job: &Job, providers: Option<Vec<String>>) -> Mask {
+    let mut labels = BTreeMap::new();
+    labels.insert(
+        AUTO_MASK_JOB_LABEL.to_owned(),
+        job.metadata.uid.clone().unwrap(),
+    );
+    Mask {
+        metadata: ObjectMeta {
+            name: Some(get_mask_name(job).to_owned()),
+            namespace: job.metadata.namespace.clone(),
+            labels: Some(labels),
+            owner_references: Some(vec![job.controller_owner_ref(&()).unwrap()]),
+            ..Default::default()
+        },
+        spec: MaskSpec {
+            providers,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Gets the Job's Mask, if it exists.
+pub async fn get_mask(client: Client, job: &Job) -> Result<Option<Mask>, Error> {
+    let api: Api<Mask> = Api::namespaced(client, job.metadata.namespace.as_deref().unwrap());
+    let name = get_mask_name(job);
+    match api.get(name).await {
+        Ok(mask) => Ok(Some(mask)),
+        Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(None),
+        Err(e) => Err(e).context_kind_name("Mask", name),
+    }
+}
+
+/// Gets the name of the credentials Secret copied for the Mask, if
+/// the Mask is Active.
+pub async fn get_mask_secret(client: Client, mask: &Mask) -> Result<Option<String>, Error> {
+    if mask.status.as_ref().and_then(|s| s.phase) != Some(MaskPhase::Active) {
+        return Ok(None);
+    }
+    Ok(get_consumer(client, mask)
+        .await?
+        .and_then(|c| c.status)
+        .and_then(|s| s.provider)
+        .map(|p| p.secret))
+}
+
+/// Returns how long to wait before checking on a Job whose Mask can't
+/// be assigned, which grows with how long the Mask has been waiting so
+/// that Jobs stuck for long don't flood the API server with events.
+pub fn waiting_backoff(mask: &Mask) -> Duration {
+    let waited = mask
+        .metadata
+        .creation_timestamp
+        .as_ref()
+        .and_then(|t| (chrono::Utc::now() - t.0).to_std().ok())
+        .unwrap_or_default();
+    (waited / 2).clamp(MIN_WAITING_BACKOFF, MAX_WAITING_BACKOFF)
+}
+
+/// Suspends the Job so it doesn't start Pods before it has credentials.
+pub async fn suspend(client: Client, job: &Job) -> Result<(), Error> {
+    let name = job.metadata.name.as_deref().unwrap();
+    let api: Api<Job> = Api::namespaced(client.clone(), job.metadata.namespace.as_deref().unwrap());
+    let patch = json!({ "spec": { "suspend": true } });
+    api.patch(
+        name,
+        &PatchParams::apply(MANAGER_NAME),
+        &Patch::Merge(&patch),
+    )
+    .await
+    .context_kind_name("Job", name)?;
+    events::publish(
+        client,
+        job,
+        "Suspended",
+        "AutoMask",
+        "Suspended until the Job's Mask is assigned VPN credentials.".to_owned(),
+    )
+    .await;
+    Ok(())
+}
+
+/// Creates the Mask for the Job.
+pub async fn create_mask(
+    client: Client,
+    job: &Job,
+    providers: Option<Vec<String>>,
+) -> Result<Mask, Error> {
+    let api: Api<Mask> = Api::namespaced(client, job.metadata.namespace.as_deref().unwrap());
+    api.create(&Default::default(), &job_mask(job, providers))
+        .await
+        .context_kind_name("Mask", get_mask_name(job))
+}
+
+/// Gives the Job's Pod template the name of the credentials Secret and
+/// resumes the Job. Both are done in one patch, as the template can only
+/// be changed while the Job is suspended.
+pub async fn inject_secret(client: Client, job: &Job, secret: &str) -> Result<(), Error> {
+    let name = job.metadata.name.as_deref().unwrap();
+    let api: Api<Job> = Api::namespaced(client.clone(), job.metadata.namespace.as_deref().unwrap());
+    let patch = json!({
+        "spec": {
+            "suspend": false,
+            "template": {
+                "metadata": { "annotations": { MASK_SECRET_ANNOTATION: secret } },
+            },
+        },
+    });
+    api.patch(
+        name,
+        &PatchParams::apply(MANAGER_NAME),
+        &Patch::Merge(&patch),
+    )
+    .await
+    .context_kind_name("Job", name)?;
+    events::publish(
+        client,
+        job,
+        "CredentialsInjected",
+        "AutoMask",
+        format!("Resumed with VPN credentials in Secret {}.", secret),
+    )
+    .await;
+    Ok(())
+}
+
+/// Publishes a Warning event on the Job explaining why it can't
+/// be given credentials yet.
+pub async fn waiting(client: Client, job: &Job, reason: &str, message: String) {
+    events::warn(client, job, reason, "AutoMask", message).await
+}
+
+/// Deletes the Job's Mask, releasing its slot once the Job finished.
+pub async fn delete_mask(client: Client, job: &Job) -> Result<(), Error> {
+    let api: Api<Mask> = Api::namespaced(client, job.metadata.namespace.as_deref().unwrap());
+    let name = get_mask_name(job);
+    match api.delete(name, &Default::default()).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(e)) if e.code == 404 => Ok(()),
+        Err(e) => Err(e).context_kind_name("Mask", name),
+    }
+}
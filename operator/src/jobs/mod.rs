@@ -0,0 +1,17 @@
+pub(crate) mod actions;
+pub(crate) mod reconcile;
+
+pub use reconcile::run;
+
+use crate::rbac::Rule;
+
+/// API access the Job controller needs. See [`crate::rbac`].
+pub const RBAC: &[Rule] = &[
+    // Jobs are suspended until their Mask is Active, then resumed.
+    Rule::new("batch", &["jobs"], &["get", "list", "watch", "patch"]),
+    Rule::new(
+        "vpn.beebs.dev",
+        &["masks"],
+        &["get", "list", "watch", "create", "delete"],
+    ),
+];
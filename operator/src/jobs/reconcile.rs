@@ -0,0 +1,311 @@
+use futures::stream::StreamExt;
+use k8s_openapi::api::batch::v1::Job;
+use kube::{
+    api::ListParams,
+    client::Client,
+    runtime::{controller::Action, Controller},
+    Api, ResourceExt,
+};
+use std::sync::Arc;
+use tokio::{sync::Semaphore, time::Duration};
+use vpn_types::*;
+
+use super::actions;
+use crate::util::{
+    explain, report_reconcile_error, ControllerOptions, Error, AUTO_MASK_JOB_LABEL, PROBE_INTERVAL,
+};
+
+#[cfg(feature = "metrics")]
+use crate::util::metrics::ControllerMetrics;
+
+/// Entrypoint for the Job controller, which creates a Mask for each Job
+/// with the `vpn.beebs.dev/auto-mask` annotation. If `concurrency` is
+/// set, at most that many reconciliations will be performed at the same time.
+pub async fn run(
+    client: Client,
+    concurrency: Option<usize>,
+    options: ControllerOptions,
+) -> Result<(), Error> {
+    println!("Starting Job controller...");
+
+    // Preparation of resources used by the `kube_runtime::Controller`
+    let job_api: Api<Job> = Api::all(client.clone());
+    let context: Arc<ContextData> =
+        Arc::new(ContextData::new(client.clone(), concurrency, options));
+
+    // Jobs can't be filtered by annotation, so all of them are watched,
+    // but only the Masks created for Jobs are.
+    Controller::new(job_api, ListParams::default())
+        .owns(
+            Api::<Mask>::all(client),
+            ListParams::default().labels(AUTO_MASK_JOB_LABEL),
+        )
+        .run(reconcile, on_error, context)
+        .for_each(|_reconciliation_result| async move {})
+        .await;
+    Ok(())
+}
+
+/// Context injected with each `reconcile` and `on_error` method invocation.
+struct ContextData {
+    /// Kubernetes client to make Kubernetes API requests with. Required for K8S resource management.
+    client: Client,
+
+    /// Limits the number of concurrent reconciliations, if configured.
+    semaphore: Option<Semaphore>,
+
+    /// Settings from the command line, e.g. whether actions are explained.
+    options: ControllerOptions,
+
+    #[cfg(feature = "metrics")]
+    metrics: ControllerMetrics,
+}
+
+impl ContextData {
+    /// Constructs a new instance of ContextData.
+    ///
+    /// # Arguments:
+    /// - `client`: A Kubernetes client to make Kubernetes REST API requests with. Resources
+    ///   will be created and deleted with this client.
+    /// - `concurrency`: Optional maximum number of concurrent reconciliations.
+    /// - `options`: Settings from the command line.
+    pub fn new(client: Client, concurrency: Option<usize>, options: ControllerOptions) -> Self {
+        let semaphore = concurrency.map(Semaphore::new);
+        ContextData {
+            client,
+            semaphore,
+            options,
+            #[cfg(feature = "metrics")]
+            metrics: ControllerMetrics::new("jobs"),
+        }
+    }
+}
+
+/// Action to be taken upon a Job during reconciliation
+#[derive(Debug, PartialEq)]
+pub(crate) enum JobAction {
+    /// Suspend the Job so it doesn't start Pods without credentials.
+    Suspend,
+
+    /// Create the Job's Mask with the given MaskProvider tags.
+    CreateMask { providers: Option<Vec<String>> },
+
+    /// The Job's Mask can't be assigned credentials. A Warning event
+    /// is published and the Job is checked on again after `backoff`.
+    Waiting {
+        reason: String,
+        message: String,
+        backoff: Duration,
+    },
+
+    /// Give the Job's Pod template the name of the credentials Secret
+    /// and resume the Job.
+    InjectSecret { secret: String },
+
+    /// Delete the finished Job's Mask to release its slot.
+    DeleteMask,
+
+    /// The Job requires no actions to be taken.
+    NoOp,
+}
+
+impl JobAction {
+    fn to_str(&self) -> &str {
+        match self {
+            JobAction::Suspend => "Suspend",
+            JobAction::CreateMask { .. } => "CreateMask",
+            JobAction::Waiting { .. } => "Waiting",
+            JobAction::InjectSecret { .. } => "InjectSecret",
+            JobAction::DeleteMask => "DeleteMask",
+            JobAction::NoOp => "NoOp",
+        }
+    }
+}
+
+/// Reconciliation function for the Job resource.
+async fn reconcile(instance: Arc<Job>, context: Arc<ContextData>) -> Result<Action, Error> {
+    // Wait for a permit if the number of concurrent reconciliations is limited.
+    let _permit = match context.semaphore {
+        Some(ref semaphore) => Some(semaphore.acquire().await.unwrap()),
+        None => None,
+    };
+
+    // The `Client` is shared -> a clone from the reference is obtained
+    let client: Client = context.client.clone();
+
+    // Jobs are always namespaced.
+    let namespace = instance.namespace().unwrap();
+    let name = instance.name_any();
+
+    // Increment total number of reconciles for the Job resource.
+    #[cfg(feature = "metrics")]
+    context.metrics.reconcile(&name, &namespace);
+
+    // Benchmark the read phase of reconciliation.
+    #[cfg(feature = "metrics")]
+    let start = std::time::Instant::now();
+
+    // Read phase of reconciliation determines goal during the write phase.
+    let action = determine_action(client.clone(), &instance).await?;
+
+    if action != JobAction::NoOp {
+        println!("{}/{} ACTION: {:?}", namespace, name, action);
+    }
+
+    // Report the read phase performance and count the action.
+    #[cfg(feature = "metrics")]
+    context
+        .metrics
+        .read(&name, &namespace, action.to_str(), start);
+
+    // Benchmark the write phase of reconciliation.
+    #[cfg(feature = "metrics")]
+    let timer = match action {
+        // Don't measure performance for NoOp actions.
+        JobAction::NoOp => None,
+        // Start a performance timer for the write phase.
+        _ => Some(context.metrics.write(&name, &namespace, action.to_str())),
+    };
+
+    // Perform the action. The action's name is attached to any error
+    // so the error handler can report which action failed.
+    let action_name = action.to_str().to_owned();
+    let result = explain::scope(
+        context.options.explain,
+        "jobs",
+        &action_name,
+        apply_action(client, &instance, action),
+    )
+    .await;
+
+    // Report the write phase performance with the action's outcome.
+    #[cfg(feature = "metrics")]
+    if let Some(timer) = timer {
+        timer.observe(&result);
+    }
+
+    result.map_err(|e| Error::action(&action_name, e))
+}
+
+/// Performs the action as decided by the `determine_action` function.
+/// This is the write phase of reconciliation.
+async fn apply_action(client: Client, instance: &Job, action: JobAction) -> Result<Action, Error> {
+    Ok(match action {
+        JobAction::Suspend => {
+            actions::suspend(client, instance).await?;
+            Action::requeue(Duration::ZERO)
+        }
+        JobAction::CreateMask { providers } => {
+            // The Job is checked on as its Mask changes.
+            actions::create_mask(client, instance, providers).await?;
+            Action::await_change()
+        }
+        JobAction::Waiting {
+            reason,
+            message,
+            backoff,
+        } => {
+            actions::waiting(client, instance, &reason, message).await;
+            Action::requeue(backoff)
+        }
+        JobAction::InjectSecret { secret } => {
+            actions::inject_secret(client, instance, &secret).await?;
+            Action::await_change()
+        }
+        JobAction::DeleteMask => {
+            actions::delete_mask(client, instance).await?;
+            Action::await_change()
+        }
+        // Jobs without the annotation are never requeued, and the others
+        // are reconciled again whenever they or their Masks change.
+        JobAction::NoOp => Action::await_change(),
+    })
+}
+
+/// Resources arrives into reconciliation queue in a certain state. This function looks at
+/// the state of given Job and decides which actions needs to be performed.
+/// The finite set of possible actions is represented by the `JobAction` enum.
+///
+/// # Arguments
+/// - `instance`: A reference to the Job being reconciled to decide next action upon.
+pub(crate) async fn determine_action(client: Client, instance: &Job) -> Result<JobAction, Error> {
+    // Only Jobs that opted in get a Mask. Deleted Jobs take
+    // their Mask with them through garbage collection.
+    let providers = match actions::auto_mask_tags(instance) {
+        Some(providers) if instance.metadata.deletion_timestamp.is_none() => providers,
+        _ => return Ok(JobAction::NoOp),
+    };
+
+    // Release the slot as soon as the Job no longer needs it, rather
+    // than when the Job is deleted.
+    if actions::is_finished(instance) {
+        return Ok(match actions::get_mask(client, instance).await? {
+            Some(mask)
+                if actions::is_owned_by(&mask, instance)
+                    && mask.metadata.deletion_timestamp.is_none() =>
+            {
+                JobAction::DeleteMask
+            }
+            _ => JobAction::NoOp,
+        });
+    }
+
+    // The Pod template can't be changed once the Job is running, so
+    // a Job that was given credentials is left alone from then on.
+    if actions::injected_secret(instance).is_some() {
+        return Ok(JobAction::NoOp);
+    }
+
+    // Keep the Job from starting Pods without credentials.
+    if !actions::is_suspended(instance) {
+        return Ok(JobAction::Suspend);
+    }
+
+    let mask = match actions::get_mask(client.clone(), instance).await? {
+        Some(mask) => mask,
+        None => return Ok(JobAction::CreateMask { providers }),
+    };
+    if !actions::is_owned_by(&mask, instance) {
+        return Ok(JobAction::Waiting {
+            reason: "MaskConflict".to_owned(),
+            message: format!(
+                "Mask {} already exists and wasn't created for the Job.",
+                actions::get_mask_name(instance)
+            ),
+            backoff: PROBE_INTERVAL,
+        });
+    }
+
+    // Tell the user why the Mask can't be assigned, backing off
+    // the longer it stays that way.
+    let status = mask.status.as_ref();
+    let phase = status.and_then(|s| s.phase);
+    if phase.is_some_and(|p| p.to_string().starts_with("Err")) {
+        return Ok(JobAction::Waiting {
+            reason: phase.unwrap().to_string(),
+            message: status
+                .and_then(|s| s.message.clone())
+                .unwrap_or_else(|| "The Job's Mask can't be assigned credentials.".to_owned()),
+            backoff: actions::waiting_backoff(&mask),
+        });
+    }
+
+    // Resume the Job once the credentials are copied and
+    // the Pods from before it was suspended are gone.
+    match actions::get_mask_secret(client, &mask).await? {
+        Some(secret) if actions::is_stopped(instance) => Ok(JobAction::InjectSecret { secret }),
+        _ => Ok(JobAction::NoOp),
+    }
+}
+
+/// Actions to be taken when a reconciliation fails - for whatever reason.
+/// Prints out the error to `stderr` and requeues the resource for another reconciliation.
+/// Jobs have no status object of the operator's to record the error in.
+///
+/// # Arguments
+/// - `instance`: The erroneous resource.
+/// - `error`: A reference to the `kube::Error` that occurred during reconciliation.
+/// - `_context`: Unused argument. Context Data "injected" automatically by kube-rs.
+fn on_error(instance: Arc<Job>, error: &Error, _context: Arc<ContextData>) -> Action {
+    report_reconcile_error(&instance.metadata.name, error)
+}
@@ -1,11 +1,20 @@
 use clap::{Parser, Subcommand};
 use kube::client::Client;
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
+mod clusterproviders;
 mod consumers;
+mod dashboards;
+mod export;
+mod jobs;
 mod masks;
 mod providers;
+mod rbac;
 mod reservations;
+mod status;
 mod util;
+mod verify_all;
+mod webhook;
 
 #[cfg(feature = "metrics")]
 mod metrics;
@@ -24,8 +33,139 @@ struct Cli {
 
     /// Prometheus metrics server scrape port. Disabled by default.
     #[cfg(feature = "metrics")]
-    #[arg(long, env = "METRICS_PORT")]
+    #[arg(long, env = "METRICS_PORT", value_parser = parse_port)]
     metrics_port: Option<u16>,
+
+    /// Export `provider_info` and `mask_info` metrics, which are always
+    /// one and labeled with information about each `MaskProvider` and
+    /// `Mask` for joining with other metrics. There is a series for
+    /// every resource, so they're disabled by default.
+    #[cfg(feature = "metrics")]
+    #[arg(long, env = "INFO_METRICS")]
+    info_metrics: bool,
+
+    /// Give each controller its own Kubernetes client, and therefore
+    /// its own connection pool, with a user agent identifying the
+    /// controller (e.g. `vpn-operator/masks`). This keeps API server
+    /// throttling of one controller from starving the others.
+    #[arg(long, env = "ISOLATED_CLIENTS")]
+    isolated_clients: bool,
+
+    /// Maximum number of concurrent `MaskConsumer` reconciliations.
+    #[arg(long, env = "CONCURRENCY_CONSUMERS")]
+    concurrency_consumers: Option<usize>,
+
+    /// Maximum number of concurrent `Mask` reconciliations.
+    #[arg(long, env = "CONCURRENCY_MASKS")]
+    concurrency_masks: Option<usize>,
+
+    /// Maximum number of concurrent `MaskProvider` reconciliations.
+    #[arg(long, env = "CONCURRENCY_PROVIDERS")]
+    concurrency_providers: Option<usize>,
+
+    /// Maximum number of concurrent `MaskReservation` reconciliations.
+    #[arg(long, env = "CONCURRENCY_RESERVATIONS")]
+    concurrency_reservations: Option<usize>,
+
+    /// Maximum number of concurrent `ClusterMaskProvider` reconciliations.
+    #[arg(long, env = "CONCURRENCY_CLUSTER_PROVIDERS")]
+    concurrency_cluster_providers: Option<usize>,
+
+    /// Namespace the operator is deployed to, which the `MaskProvider`s
+    /// of `ClusterMaskProvider`s with `spec.shared: true` are created in.
+    /// Defaults to the namespace of their credentials.
+    #[arg(long, env = "OPERATOR_NAMESPACE")]
+    operator_namespace: Option<String>,
+
+    /// Maximum number of concurrent Job reconciliations.
+    #[arg(long, env = "CONCURRENCY_JOBS")]
+    concurrency_jobs: Option<usize>,
+
+    /// Only copy VPN credentials into namespaces with this label, given as
+    /// `key=value`. `MaskConsumer`s in other namespaces enter the
+    /// `ErrNamespaceNotOptedIn` phase until the namespace is labeled.
+    #[arg(long, env = "REQUIRE_NAMESPACE_OPTIN_LABEL")]
+    require_namespace_optin_label: Option<consumers::OptInLabel>,
+
+    /// Annotate resources with the most recent action taken on them,
+    /// as compact JSON under `vpn.beebs.dev/last-action`. Useful for
+    /// understanding why a resource is in its current state.
+    #[arg(long, env = "EXPLAIN_ANNOTATIONS")]
+    explain_annotations: bool,
+
+    /// Refuse all new slot reservations. `MaskConsumer`s that aren't
+    /// assigned yet wait until assignments are unfrozen, while those
+    /// already assigned are left untouched.
+    #[arg(long, env = "FREEZE_ASSIGNMENTS")]
+    freeze_assignments: bool,
+
+    /// Operator config ConfigMap, given as `namespace/name`. It is watched
+    /// for changes, so settings like `assignmentsFrozen: "true"` and the
+    /// `defaultVerify` settings of MaskProviders take effect without
+    /// restarting the controllers.
+    #[arg(long, env = "CONFIG_MAP")]
+    config_map: Option<util::config::ConfigMapRef>,
+
+    /// Name of the selector that decides which `MaskProvider`s are tried
+    /// first when assigning a `MaskConsumer`. Built in are `default`,
+    /// which tries them in the order they're listed, `least-loaded`
+    /// and `weighted`. Forks can add their own in `register_selectors`.
+    #[arg(long, env = "PROVIDER_SELECTOR", default_value = consumers::selector::DEFAULT_SELECTOR)]
+    provider_selector: String,
+
+    /// Try the `MaskProvider`s tagged `region=<value>` with the region of a
+    /// `Mask`'s namespace first, before falling back to all others. Masks
+    /// can choose for themselves with `spec.strategy`.
+    #[arg(long, env = "TOPOLOGY_AWARE")]
+    topology_aware: bool,
+
+    /// Namespace label holding the region of the namespace, which
+    /// topology-aware `Mask`s match against `MaskProvider` tags.
+    #[arg(long, env = "TOPOLOGY_LABEL", default_value = consumers::topology::DEFAULT_TOPOLOGY_LABEL)]
+    topology_label: String,
+
+    /// How long a `MaskConsumer` with `deletionPolicy: WaitForPods` keeps
+    /// its credentials while Pods still use them, e.g. `10m`. After that
+    /// the credentials are deleted regardless.
+    #[arg(long, env = "WITHDRAWAL_GRACE_PERIOD", default_value = "5m", value_parser = parse_interval)]
+    withdrawal_grace_period: Duration,
+
+    /// How long a `MaskConsumer` created with `spec.external: true` may go
+    /// without its client renewing the `vpn.beebs.dev/heartbeat` annotation,
+    /// e.g. `2m`. After that it's deleted, releasing its slot.
+    #[arg(long, env = "EXTERNAL_HEARTBEAT_TIMEOUT", default_value = "5m", value_parser = parse_interval)]
+    external_heartbeat_timeout: Duration,
+
+    /// How long a status that hasn't changed goes without being rewritten,
+    /// e.g. `30m`. Statuses are always rewritten when their phase changes,
+    /// so this only bounds how old `status.lastUpdated` can get.
+    #[arg(long, env = "STATUS_FRESHNESS_INTERVAL", default_value = "10m", value_parser = parse_interval)]
+    status_freshness_interval: Duration,
+
+    /// How often `MaskProvider`s with `spec.canary: true` are assigned a
+    /// short-lived canary `Mask` to check the whole assignment pipeline,
+    /// e.g. `1h`. Canaries are disabled if unset.
+    #[arg(long, env = "CANARY_INTERVAL", value_parser = parse_interval)]
+    canary_interval: Option<Duration>,
+
+    /// Maximum number of `MaskProvider`s verified at the same time across
+    /// the cluster. Others due for verification wait their turn in order of
+    /// creation. Unlimited if unset.
+    #[arg(long, env = "MAX_CONCURRENT_VERIFICATIONS")]
+    max_concurrent_verifications: Option<usize>,
+
+    /// Annotate the Pod templates of Deployments and StatefulSets that use
+    /// a `MaskConsumer`'s credentials with their checksum whenever they're
+    /// written, which rolls out new Pods. Only workloads labeled with
+    /// `vpn.beebs.dev/rollout-on-credentials-change=true` are modified.
+    #[arg(long, env = "ANNOTATE_CONSUMING_PODS")]
+    annotate_consuming_pods: bool,
+
+    /// Also run the Job controller with `manage-all`, which creates a
+    /// `Mask` for each Job with the `vpn.beebs.dev/auto-mask` annotation
+    /// and keeps the Job suspended until the `Mask` has credentials.
+    #[arg(long, env = "AUTO_MASK_JOBS")]
+    auto_mask_jobs: bool,
 }
 
 /// List of subcommands for the binary. Clap will convert the
@@ -37,31 +177,392 @@ enum Command {
     ManageMasks,
     ManageProviders,
     ManageReservations,
+    ManageClusterProviders,
+    /// Creates a `Mask` for each Job with the `vpn.beebs.dev/auto-mask`
+    /// annotation and resumes the Job once the `Mask` has credentials.
+    ManageJobs,
+    /// Runs all five controllers in the same process, and the Job
+    /// controller if `--auto-mask-jobs` is set.
+    ManageAll,
+    /// Watches all four kinds of resources without modifying them and
+    /// continuously writes a compact aggregate status document, which
+    /// can be collected from many clusters for a fleet-wide view.
+    ExportStatus {
+        /// Write the document to this ConfigMap, given as `namespace/name`.
+        #[arg(
+            long,
+            env = "EXPORT_CONFIG_MAP",
+            required_unless_present = "export_file",
+            conflicts_with = "export_file"
+        )]
+        export_config_map: Option<util::config::ConfigMapRef>,
+
+        /// Write the document to this file, e.g. for a sidecar to ship.
+        #[arg(long, env = "EXPORT_FILE")]
+        export_file: Option<PathBuf>,
+
+        /// Minimum time between writes, e.g. `30s`. Changes that arrive
+        /// in the meantime are written together.
+        #[arg(long, env = "EXPORT_INTERVAL", default_value = "10s", value_parser = parse_interval)]
+        export_interval: Duration,
+
+        /// Name of the cluster to include in the document.
+        #[arg(long, env = "CLUSTER_NAME")]
+        cluster_name: Option<String>,
+    },
+    /// Prints a summary of the VPN fleet and exits: each MaskProvider's
+    /// phase, slots in use, when it was last verified and any warnings,
+    /// the number of Masks in each phase by namespace, and every resource
+    /// in an error phase with its message. Nothing is modified.
+    Status {
+        /// Only show resources in this namespace.
+        #[arg(long, short = 'n')]
+        namespace: Option<String>,
+
+        /// Only show this MaskProvider, given as `namespace/name` or as
+        /// a name in `--namespace`, and the Masks and MaskConsumers
+        /// assigned to it.
+        #[arg(long)]
+        provider: Option<status::ProviderRef>,
+
+        /// Print tables (`table`) or JSON for scripts (`json`).
+        #[arg(long, short = 'o', default_value = "table")]
+        output: status::OutputFormat,
+    },
+    /// Re-verifies MaskProviders by setting their verify-now annotation,
+    /// waits for each to pass or fail verification, and prints a summary.
+    /// Exits with a nonzero code if any failed or didn't finish in time.
+    /// Only the annotation is modified, so it's safe to run alongside the
+    /// controllers, which carry out the verifications.
+    VerifyAll {
+        /// Only re-verify MaskProviders in this namespace.
+        #[arg(long, short = 'n')]
+        namespace: Option<String>,
+
+        /// Only re-verify MaskProviders with this tag. May be repeated,
+        /// in which case MaskProviders with any of the tags are verified.
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+    },
+    /// Writes a Grafana dashboard and a PrometheusRule with alerts for
+    /// the operator's metrics, named with the current metrics prefix.
+    /// This doesn't require access to a cluster.
+    GenerateDashboards {
+        /// Directory to write the dashboard and alerting rules to.
+        #[arg(long, env = "OUTPUT_DIR", default_value = ".")]
+        output_dir: PathBuf,
+
+        /// Alert when more than this many Masks are waiting for a slot.
+        #[arg(long, env = "MASKS_WAITING_THRESHOLD", default_value_t = 10)]
+        masks_waiting_threshold: usize,
+    },
+    /// Prints the ClusterRoles the operator's components need, generated
+    /// from the API access each module declares, so they can't drift from
+    /// the code. This doesn't require access to a cluster.
+    GenerateRbac {
+        /// Prefix of the roles' names, which are suffixed with the
+        /// component, e.g. `vpn-operator`.
+        #[arg(long, default_value = "vpn")]
+        name_prefix: String,
+
+        /// Namespace the operator runs in. Its config ConfigMap is only
+        /// readable there.
+        #[arg(long, default_value = "vpn")]
+        operator_namespace: String,
+
+        /// Print a Role in this namespace for the namespaced resources
+        /// instead, leaving only the resources that aren't namespaced in
+        /// the ClusterRoles. May be repeated, once for each namespace the
+        /// operator would be restricted to.
+        #[arg(long = "watch-namespace")]
+        watch_namespaces: Vec<String>,
+
+        /// Print a Role allowing the previews requested with the
+        /// vpn.beebs.dev/explain annotation to be written in this
+        /// namespace. May be repeated. Previews aren't allowed anywhere
+        /// else.
+        #[arg(long = "preview-namespace")]
+        preview_namespaces: Vec<String>,
+    },
+    /// Serves the conversion webhook the API server uses to convert
+    /// `Mask` resources between versions `v1` and `v2`. This doesn't
+    /// require access to a cluster unless `--inject-sidecars` is set.
+    Webhook {
+        /// Port to serve HTTPS requests on.
+        #[arg(
+            long,
+            env = "WEBHOOK_PORT",
+            value_name = "WEBHOOK_PORT",
+            default_value = "8443",
+            value_parser = parse_port
+        )]
+        port: u16,
+
+        /// PEM-encoded TLS certificate chain.
+        #[arg(long, env = "TLS_CERT_FILE", default_value = "/tls/tls.crt")]
+        tls_cert_file: PathBuf,
+
+        /// PEM-encoded TLS private key.
+        #[arg(long, env = "TLS_KEY_FILE", default_value = "/tls/tls.key")]
+        tls_key_file: PathBuf,
+
+        /// Also serve the mutating webhook that injects a gluetun sidecar
+        /// into Pods annotated with `vpn.beebs.dev/mask`, wired to the
+        /// credentials of the named Mask. Requires access to the cluster.
+        #[arg(long, env = "INJECT_SIDECARS")]
+        inject_sidecars: bool,
+    },
+}
+
+/// Parses a human-readable duration given on the command line.
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    parse_duration::parse(s.trim()).map_err(|e| e.to_string())
+}
+
+/// Parses a port given on the command line. Surrounding whitespace, such
+/// as a trailing space left by a Helm template, is ignored.
+fn parse_port(s: &str) -> Result<u16, String> {
+    match s.trim().parse::<u16>() {
+        Ok(port) if port != 0 => Ok(port),
+        _ => Err("expected a port between 1 and 65535".to_owned()),
+    }
+}
+
+/// Returns the single-line message describing the invalid command line
+/// or environment variable, such as
+/// `error: invalid value 'x' for '--metrics-port <METRICS_PORT>': ...`.
+fn usage_error(error: &clap::Error) -> String {
+    error
+        .render()
+        .to_string()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// Parses the command line and environment variables. Invalid settings
+/// are reported on a single line, exiting with [`util::EXIT_CONFIG`]
+/// instead of panicking, so a crash-looping Pod's logs say what's wrong.
+fn parse_cli() -> Cli {
+    Cli::try_parse().unwrap_or_else(|e| {
+        if !e.use_stderr() {
+            // Help and version information were requested.
+            e.exit();
+        }
+        eprintln!("{}", usage_error(&e));
+        std::process::exit(util::EXIT_CONFIG);
+    })
+}
+
+/// Registers custom provider selectors, so they can be chosen with
+/// `--provider-selector`. This is the extension point for forks that
+/// compile their own [`ProviderSelector`](consumers::selector::ProviderSelector)
+/// implementations into the operator, e.g.:
+///
+/// ```ignore
+/// registry.register("same-namespace", SameNamespace);
+/// ```
+fn register_selectors(_registry: &mut consumers::selector::SelectorRegistry) {}
+
+/// Returns the client the controller of the given kind should use.
+/// With `--isolated-clients`, a new client is created for each controller.
+async fn controller_client(cli: &Cli, client: &Client, kind: &str) -> Client {
+    if !cli.isolated_clients {
+        return client.clone();
+    }
+    util::client::create_client(kind)
+        .await
+        .expect("Expected a valid KUBECONFIG environment variable.")
 }
 
 /// Secondary entrypoint that runs the appropriate subcommand.
-async fn run(client: Client) {
-    let cli = Cli::parse();
+async fn run(client: Client) -> Result<(), util::Error> {
+    let cli = parse_cli();
 
     #[cfg(feature = "metrics")]
     if let Some(metrics_port) = cli.metrics_port {
         tokio::spawn(metrics::run_server(metrics_port));
     }
 
-    match cli.command {
-        Command::ManageConsumers => consumers::run(client).await,
-        Command::ManageMasks => masks::run(client).await,
-        Command::ManageProviders => providers::run(client).await,
-        Command::ManageReservations => reservations::run(client).await,
+    // Settings every controller keeps in its context.
+    let options = util::ControllerOptions {
+        explain: cli.explain_annotations,
+        #[cfg(feature = "metrics")]
+        info_metrics: cli.info_metrics,
+        #[cfg(not(feature = "metrics"))]
+        info_metrics: false,
+    };
+    let consumer_settings = consumers::ConsumerSettings {
+        withdrawal_grace_period: cli.withdrawal_grace_period,
+        status_freshness: cli.status_freshness_interval,
+        rollout: cli.annotate_consuming_pods,
+        topology: consumers::topology::Settings {
+            label: cli.topology_label.clone(),
+            by_default: cli.topology_aware,
+        },
+        heartbeat_timeout: cli.external_heartbeat_timeout,
+    };
+
+    // The controllers refuse to run against an API server older than
+    // the oldest supported version, and adapt to the features it has.
+    let capabilities = match cli.command {
+        Command::ExportStatus { .. } => util::capabilities::Capabilities::default(),
+        _ => util::capabilities::probe(client.clone()).await?,
+    };
+
+    let config = Arc::new(util::config::OperatorConfig::new(cli.freeze_assignments));
+    if let Some(config_map) = cli.config_map.clone() {
+        let config = config.clone();
+        let client = client.clone();
+        tokio::spawn(async move { config.watch(client, config_map).await });
     }
-    .unwrap();
+
+    let mut selectors = consumers::selector::SelectorRegistry::builtin();
+    register_selectors(&mut selectors);
+    let selector = selectors.get(&cli.provider_selector).unwrap();
+
+    match cli.command {
+        Command::ManageConsumers => {
+            let client = controller_client(&cli, &client, "consumers").await;
+            consumers::run(
+                client,
+                cli.concurrency_consumers,
+                cli.require_namespace_optin_label.clone(),
+                config,
+                selector,
+                consumer_settings,
+                options,
+            )
+            .await
+        }
+        Command::ManageMasks => {
+            let client = controller_client(&cli, &client, "masks").await;
+            masks::run(
+                client,
+                cli.concurrency_masks,
+                cli.status_freshness_interval,
+                options,
+            )
+            .await
+        }
+        Command::ManageProviders => {
+            let client = controller_client(&cli, &client, "providers").await;
+            providers::run(
+                client,
+                cli.concurrency_providers,
+                cli.canary_interval,
+                cli.max_concurrent_verifications,
+                config,
+                capabilities,
+                options,
+            )
+            .await
+        }
+        Command::ManageReservations => {
+            let client = controller_client(&cli, &client, "reservations").await;
+            reservations::run(
+                client,
+                cli.concurrency_reservations,
+                cli.status_freshness_interval,
+                options,
+            )
+            .await
+        }
+        Command::ManageClusterProviders => {
+            let client = controller_client(&cli, &client, "clusterproviders").await;
+            clusterproviders::run(
+                client,
+                cli.concurrency_cluster_providers,
+                cli.status_freshness_interval,
+                cli.operator_namespace.clone(),
+                options,
+            )
+            .await
+        }
+        Command::ManageJobs => {
+            let client = controller_client(&cli, &client, "jobs").await;
+            jobs::run(client, cli.concurrency_jobs, options).await
+        }
+        Command::ManageAll => futures::try_join!(
+            consumers::run(
+                controller_client(&cli, &client, "consumers").await,
+                cli.concurrency_consumers,
+                cli.require_namespace_optin_label.clone(),
+                config.clone(),
+                selector,
+                consumer_settings,
+                options,
+            ),
+            masks::run(
+                controller_client(&cli, &client, "masks").await,
+                cli.concurrency_masks,
+                cli.status_freshness_interval,
+                options,
+            ),
+            providers::run(
+                controller_client(&cli, &client, "providers").await,
+                cli.concurrency_providers,
+                cli.canary_interval,
+                cli.max_concurrent_verifications,
+                config,
+                capabilities,
+                options,
+            ),
+            reservations::run(
+                controller_client(&cli, &client, "reservations").await,
+                cli.concurrency_reservations,
+                cli.status_freshness_interval,
+                options,
+            ),
+            clusterproviders::run(
+                controller_client(&cli, &client, "clusterproviders").await,
+                cli.concurrency_cluster_providers,
+                cli.status_freshness_interval,
+                cli.operator_namespace.clone(),
+                options,
+            ),
+            async {
+                match cli.auto_mask_jobs {
+                    true => {
+                        let client = controller_client(&cli, &client, "jobs").await;
+                        jobs::run(client, cli.concurrency_jobs, options).await
+                    }
+                    false => Ok(()),
+                }
+            },
+        )
+        .map(|_| ()),
+        Command::ExportStatus {
+            ref export_config_map,
+            ref export_file,
+            export_interval,
+            ref cluster_name,
+        } => {
+            let sink = match (export_config_map, export_file) {
+                (Some(config_map), _) => export::Sink::ConfigMap(config_map.clone()),
+                (None, Some(file)) => export::Sink::File(file.clone()),
+                (None, None) => unreachable!("clap requires a sink"),
+            };
+            let client = controller_client(&cli, &client, "export").await;
+            export::run(client, sink, export_interval, cluster_name.clone()).await
+        }
+        Command::GenerateDashboards { .. }
+        | Command::GenerateRbac { .. }
+        | Command::Webhook { .. } => {
+            unreachable!("handled before connecting")
+        }
+        Command::Status { .. } | Command::VerifyAll { .. } => {
+            unreachable!("handled before running")
+        }
+    }?;
 
     panic!("exited unexpectedly");
 }
 
 /// Main entrypoint that sets up the environment before running the secondary entrypoint `run`.
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<(), util::Error> {
     // Set the panic hook to exit the process with a non-zero exit code
     // when a panic occurs on any thread. This is desired behavior when
     // running in a container, as the metrics server or controller may
@@ -72,14 +573,116 @@ async fn main() {
         std::process::exit(1);
     }));
 
+    // Generating dashboards and RBAC and converting resources don't need a
+    // cluster, so they're done before a client is created.
+    let cli = parse_cli();
+    match cli.command {
+        Command::GenerateDashboards {
+            ref output_dir,
+            masks_waiting_threshold,
+        } => {
+            dashboards::write(
+                output_dir,
+                &util::metrics::prefix(),
+                masks_waiting_threshold,
+            )?;
+            return Ok(());
+        }
+        Command::GenerateRbac {
+            ref name_prefix,
+            ref operator_namespace,
+            ref watch_namespaces,
+            ref preview_namespaces,
+        } => {
+            print!(
+                "{}",
+                rbac::generate(
+                    name_prefix,
+                    operator_namespace,
+                    watch_namespaces,
+                    preview_namespaces
+                )?
+            );
+            return Ok(());
+        }
+        Command::Webhook {
+            port,
+            ref tls_cert_file,
+            ref tls_key_file,
+            inject_sidecars,
+        } => {
+            #[cfg(feature = "metrics")]
+            if let Some(metrics_port) = cli.metrics_port {
+                tokio::spawn(metrics::run_server(metrics_port));
+            }
+            // Injecting sidecars looks up the Masks that Pods ask for.
+            let client = match inject_sidecars {
+                true => Some(
+                    Client::try_default()
+                        .await
+                        .expect("Expected a valid KUBECONFIG environment variable."),
+                ),
+                false => None,
+            };
+            // The server only returns if it failed.
+            return webhook::run(port, tls_cert_file, tls_key_file, client).await;
+        }
+        _ => {}
+    }
+
     // Create a kubernetes client using the default configuration.
     // In-cluster, the kubeconfig will be set by the service account.
     let client: Client = Client::try_default()
         .await
         .expect("Expected a valid KUBECONFIG environment variable.");
 
+    // Querying the status exits once the report is printed,
+    // unlike the other commands, which run until they panic.
+    if let Command::Status {
+        ref namespace,
+        ref provider,
+        output,
+    } = cli.command
+    {
+        let filter = status::StatusFilter {
+            namespace: namespace.clone(),
+            provider: provider.clone(),
+        };
+        status::run(client, filter, output).await?;
+        return Ok(());
+    }
+
+    // Re-verifying also exits once the summary is printed, with an
+    // error code if any MaskProvider didn't pass.
+    if let Command::VerifyAll {
+        ref namespace,
+        ref tags,
+    } = cli.command
+    {
+        let filter = verify_all::VerifyAllFilter {
+            namespace: namespace.clone(),
+            tags: tags.clone(),
+        };
+        let default_verify =
+            verify_all::default_verify(client.clone(), cli.config_map.as_ref()).await?;
+        // Failures to verify individual MaskProviders are in the summary,
+        // so only listing them fails here.
+        let results = verify_all::run(
+            client,
+            &filter,
+            default_verify.as_ref(),
+            cli.max_concurrent_verifications,
+        )
+        .await?;
+        print!("{}", verify_all::summary(&results));
+        if results.iter().any(|r| r.outcome.is_failure()) {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // Run the secondary entrypoint.
-    run(client).await;
+    run(client).await?;
 
     // This is an unreachable branch. The controllers and metrics
     // servers should never exit without a panic.
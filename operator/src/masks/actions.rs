@@ -1,16 +1,24 @@
-use crate::util::{messages, patch::*, Error};
+use super::util::{consumer_name, get_last_slot, get_purpose, slot_labels};
+use crate::util::{clock, events, messages, patch::*, Error, ErrorContext};
+use json_patch::{AddOperation, PatchOperation, RemoveOperation, TestOperation};
 use kube::{
-    api::{ObjectMeta, Resource},
+    api::{ObjectMeta, Patch, PatchParams, Resource},
     Api, Client,
 };
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
 use vpn_types::*;
 
+#[cfg(feature = "metrics")]
+use super::util::time_to_active;
+#[cfg(feature = "metrics")]
+use crate::util::metrics::TIME_TO_ACTIVE_HISTOGRAM;
+
 /// Updates the `Mask`'s phase to Pending, which indicates
 /// the resource made its initial appearance to the operator.
 pub async fn pending(client: Client, instance: &Mask) -> Result<(), Error> {
     patch_status(client, instance, |status| {
-        status.message = Some(messages::PENDING.to_owned());
-        status.phase = Some(MaskPhase::Pending);
+        status.set_phase(MaskPhase::Pending, messages::PENDING);
     })
     .await?;
     Ok(())
@@ -18,10 +26,33 @@ pub async fn pending(client: Client, instance: &Mask) -> Result<(), Error> {
 
 /// Updates the `Mask`'s phase to Waiting, which indicates
 /// the `MaskConsumer` is waiting for a provider to be available.
-pub async fn waiting(client: Client, instance: &Mask) -> Result<(), Error> {
+/// The `MaskConsumer`'s message is used if there is one.
+pub async fn waiting(
+    client: Client,
+    instance: &Mask,
+    message: Option<String>,
+) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.set_phase(
+            MaskPhase::Waiting,
+            message.unwrap_or_else(|| messages::WAITING.to_owned()),
+        );
+    })
+    .await?;
+    Ok(())
+}
+
+/// Updates the `Mask`'s phase to Waiting while only some of its slots are
+/// assigned, listing the MaskProviders assigned to them so far.
+pub async fn partially_assigned(
+    client: Client,
+    instance: &Mask,
+    message: String,
+    providers: Vec<AssignedProvider>,
+) -> Result<(), Error> {
     patch_status(client, instance, |status| {
-        status.phase = Some(MaskPhase::Waiting);
-        status.message = Some(messages::WAITING.to_owned());
+        status.set_phase(MaskPhase::Waiting, message);
+        status.providers = Some(providers);
     })
     .await?;
     Ok(())
@@ -30,21 +61,110 @@ pub async fn waiting(client: Client, instance: &Mask) -> Result<(), Error> {
 /// Updates the `Mask`'s phase to Terminating.
 pub async fn terminating(client: Client, instance: &Mask) -> Result<(), Error> {
     patch_status(client, instance, |status| {
-        status.phase = Some(MaskPhase::Terminating);
-        status.message = Some(messages::TERMINATING.to_owned());
+        status.set_phase(MaskPhase::Terminating, messages::TERMINATING);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Updates the `Mask`'s phase to Terminating while its `MaskConsumer` is
+/// Terminating, naming the credentials `Secret` that is about to be deleted.
+/// A Warning event is published when the `Mask` enters the phase, but not
+/// when its status is refreshed.
+pub async fn withdrawing(client: Client, instance: &Mask, secret: String) -> Result<(), Error> {
+    let message = messages::assignment_withdrawn(&secret);
+    let entered = instance.status.as_ref().and_then(|s| s.phase) != Some(MaskPhase::Terminating);
+    patch_status(client.clone(), instance, |status| {
+        status.set_phase(MaskPhase::Terminating, message.clone());
     })
     .await?;
+    if entered {
+        events::warn(client, instance, "AssignmentWithdrawn", "Unassign", message).await;
+    }
     Ok(())
 }
 
+/// Updates the Mask's phase to Terminating and deletes its MaskConsumer,
+/// which releases the reserved slot. The slot, if any, is remembered so
+/// the Mask's deletion can wait for it to be free.
+pub async fn delete_consumer(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    instance: &Mask,
+    slot: Option<SlotAffinity>,
+) -> Result<(), Error> {
+    patch_status(client.clone(), instance, |status| {
+        // Keep the MaskProviders assigned to the slots, which are waited
+        // for until their MaskReservations are gone.
+        let providers = status.providers.take();
+        status.set_phase(MaskPhase::Terminating, messages::RELEASING_SLOT);
+        status.providers = providers;
+        if let Some(slot) = slot {
+            status.remember_slot(slot);
+        }
+    })
+    .await?;
+    let api: Api<MaskConsumer> = Api::namespaced(client, namespace);
+    match api.delete(name, &Default::default()).await {
+        Ok(_) => Ok(()),
+        // The MaskConsumer was deleted in the meantime.
+        Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(()),
+        Err(e) => Err(e).context_kind_name("MaskConsumer", name),
+    }
+}
+
+/// Deletes the MaskConsumer of a slot the Mask no longer requests, which
+/// releases the slot. The Mask's phase is left alone, as its other slots
+/// are still assigned.
+pub async fn delete_surplus_consumer(
+    client: Client,
+    name: &str,
+    namespace: &str,
+) -> Result<(), Error> {
+    let api: Api<MaskConsumer> = Api::namespaced(client, namespace);
+    match api.delete(name, &Default::default()).await {
+        Ok(_) => Ok(()),
+        // The MaskConsumer was deleted in the meantime.
+        Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(()),
+        Err(e) => Err(e).context_kind_name("MaskConsumer", name),
+    }
+}
+
 /// Updates the Mask's phase to Active, signifying that everything
 /// is fully reconciled and the VPN credentials are ready to be used.
-pub async fn active(client: Client, instance: &Mask) -> Result<(), Error> {
+/// The first time this happens, the time it took is recorded in the
+/// status and reported, labeled with the MaskProvider's namespace.
+/// The MaskProviders assigned to each of the Mask's slots are listed.
+pub async fn active(
+    client: Client,
+    instance: &Mask,
+    slot: Option<SlotAffinity>,
+    provider_namespace: Option<String>,
+    providers: Vec<AssignedProvider>,
+) -> Result<(), Error> {
+    let now = chrono::Utc::now();
+    let mut first_active = false;
     patch_status(client, instance, |status| {
-        status.phase = Some(MaskPhase::Active);
-        status.message = Some(messages::ACTIVE.to_owned());
+        // Only the first time the Mask becomes Active is recorded,
+        // so reassignments don't count towards the time to Active.
+        first_active = status.mark_first_active(clock::format_timestamp(now));
+        // Remember the reserved slot. This replaces any hint for
+        // a previously assigned MaskProvider.
+        status.set_active(slot, messages::ACTIVE);
+        status.providers = Some(providers);
     })
     .await?;
+    #[cfg(feature = "metrics")]
+    if first_active {
+        if let Some(elapsed) = time_to_active(instance, now) {
+            TIME_TO_ACTIVE_HISTOGRAM
+                .with_label_values(&[provider_namespace.as_deref().unwrap_or_default()])
+                .observe(elapsed.as_secs_f64());
+        }
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (first_active, provider_namespace);
     Ok(())
 }
 
@@ -53,39 +173,392 @@ pub async fn active(client: Client, instance: &Mask) -> Result<(), Error> {
 /// when attempting to assign this `Mask` a `MaskProvider`.
 pub async fn err_no_providers(client: Client, instance: &Mask) -> Result<(), Error> {
     patch_status(client, instance, |status| {
-        status.phase = Some(MaskPhase::ErrNoProviders);
-        status.message = Some(messages::ERR_NO_PROVIDERS.to_owned());
+        status.set_phase(MaskPhase::ErrNoProviders, messages::ERR_NO_PROVIDERS);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Updates the `Mask`'s phase to ErrNamespaceNotOptedIn, which indicates
+/// that the `MaskConsumer`'s namespace is missing the opt-in label. The
+/// `MaskConsumer`'s message is mirrored so it includes remediation guidance.
+pub async fn err_namespace_not_opted_in(
+    client: Client,
+    instance: &Mask,
+    message: Option<String>,
+) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.set_phase(
+            MaskPhase::ErrNamespaceNotOptedIn,
+            message.unwrap_or_default(),
+        );
+    })
+    .await?;
+    Ok(())
+}
+
+/// Updates the `Mask`'s phase to ErrSecretConflict, which indicates that
+/// a `Secret` with the name of the credentials copy belongs to something
+/// else. The `MaskConsumer`'s message is mirrored so it names the `Secret`.
+pub async fn err_secret_conflict(
+    client: Client,
+    instance: &Mask,
+    message: Option<String>,
+) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.set_phase(MaskPhase::ErrSecretConflict, message.unwrap_or_default());
     })
     .await?;
     Ok(())
 }
 
-/// Creates the child MaskConsumer for the Mask, which manages provider assignment.
+/// Updates the `Mask`'s phase to ErrMissingLabels, which indicates that
+/// every suitable `MaskProvider` requires labels the `Mask` is missing.
+/// The `MaskConsumer`'s message is mirrored so it names the labels.
+pub async fn err_missing_labels(
+    client: Client,
+    instance: &Mask,
+    message: Option<String>,
+) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.set_phase(MaskPhase::ErrMissingLabels, message.unwrap_or_default());
+    })
+    .await?;
+    Ok(())
+}
+
+/// Updates the `Mask`'s phase to ErrProviderLost, which indicates that
+/// the assigned `MaskProvider` was deleted or replaced. The `MaskConsumer`'s
+/// message is mirrored so it names the `MaskProvider`.
+pub async fn err_provider_lost(
+    client: Client,
+    instance: &Mask,
+    message: Option<String>,
+) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.set_phase(MaskPhase::ErrProviderLost, message.unwrap_or_default());
+    })
+    .await?;
+    Ok(())
+}
+
+/// Updates the `Mask`'s phase to ErrInvalidSpec, which indicates that
+/// its spec has a value that can't be used, as named by the message.
+pub async fn err_invalid_spec(
+    client: Client,
+    instance: &Mask,
+    message: String,
+) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.set_phase(MaskPhase::ErrInvalidSpec, message);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Updates the `Mask`'s phase to ErrQuotaExceeded, which indicates that
+/// reserving another slot would exceed a `MaskQuota` in its namespace.
+/// The `MaskConsumer`'s message is mirrored so it names the `MaskQuota`.
+pub async fn err_quota_exceeded(
+    client: Client,
+    instance: &Mask,
+    message: Option<String>,
+) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.set_phase(MaskPhase::ErrQuotaExceeded, message.unwrap_or_default());
+    })
+    .await?;
+    Ok(())
+}
+
+/// Updates the `Mask`'s phase to Waiting with a message indicating that a
+/// `MaskConsumer` with the expected name exists but belongs to another owner.
+/// This happens when a cluster is restored from a backup and the `Mask` is
+/// recreated with a different UID.
+pub async fn stale_consumer(client: Client, instance: &Mask) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.set_phase(MaskPhase::Waiting, messages::STALE_CONSUMER);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Creates the child MaskConsumer for the Mask's slot with the given index,
+/// which manages provider assignment. Only the first slot prefers the slot
+/// the Mask used last, takes over the slot of the Mask it replaces and
+/// gets the Service, as they all refer to a single slot of the Mask.
+/// A MaskConsumer with the same name that was created for the Mask but never
+/// got its owner reference, e.g. because the operator crashed in between, is
+/// adopted instead. Returns false if a stale MaskConsumer with the same name
+/// had to be deleted first, or belongs to another Mask, in which case
+/// creation should be retried later.
 pub async fn create_consumer(
     client: Client,
-    name: &str,
     namespace: &str,
     instance: &Mask,
-) -> Result<(), Error> {
+    index: usize,
+) -> Result<bool, Error> {
+    let name = &consumer_name(instance, index);
+    // Read the spec through its version-independent view.
+    let options = instance.spec.options();
+    let first = index == 0;
     let consumer = MaskConsumer {
         metadata: ObjectMeta {
             name: Some(name.to_owned()),
             namespace: Some(namespace.to_owned()),
             // Use an owner ref so it'll be deleted with the Mask.
             owner_references: Some(vec![instance.controller_owner_ref(&()).unwrap()]),
-            // Inherit labels from the Mask.
-            labels: instance.metadata.labels.clone(),
+            // Inherit labels from the Mask, including the ones that
+            // decide how the MaskConsumer is assigned a MaskProvider.
+            labels: Some(slot_labels(instance, index)),
             ..Default::default()
         },
         spec: MaskConsumerSpec {
             // Use the desired providers, if specified.
-            providers: instance.spec.providers.clone(),
-            ..Default::default()
+            providers: options.providers,
+            // Inherit the explicit settings. Anything omitted is
+            // resolved against the provider's defaults upon assignment.
+            settings: options.settings,
+            // Prefer the slot the Mask used last, if any.
+            slot_affinity: get_last_slot(instance).filter(|_| first),
+            // Inherit how the credentials are withdrawn from running Pods.
+            deletion_policy: options.deletion_policy,
+            // Inherit the anti-affinity group, if any.
+            anti_affinity: options.anti_affinity,
+            // Inherit how the MaskProviders are ordered during assignment.
+            strategy: options.strategy,
+            // Inherit the Mask it replaces, whose slot it takes over.
+            succession_of: options.succession_of.filter(|_| first),
+            // Verification Masks are created by the MaskProviders controller.
+            purpose: Some(get_purpose(instance)),
+            // Only clients outside of the cluster create external MaskConsumers.
+            external: None,
+            // Inherit the Service exposing the Pods using the credentials.
+            service: options.service.filter(|_| first),
         },
         ..Default::default()
     };
-    Api::<MaskConsumer>::namespaced(client, namespace)
-        .create(&Default::default(), &consumer)
-        .await?;
+    let api: Api<MaskConsumer> = Api::namespaced(client.clone(), namespace);
+    match api.create(&Default::default(), &consumer).await {
+        // MaskConsumer was created.
+        Ok(_) => Ok(true),
+        // A MaskConsumer with the same name already exists. Determine
+        // if it belongs to this Mask, has no owner yet or is left over
+        // from a previous one.
+        Err(kube::Error::Api(ae)) if ae.code == 409 => {
+            adopt_or_delete_consumer(client, name, namespace, instance, index).await
+        }
+        // Some other error occurred.
+        Err(e) => Err(e).context_kind_name("MaskConsumer", name),
+    }
+}
+
+/// Updates the MaskConsumer's anti-affinity group to match the Mask's, so
+/// that joining or leaving a group re-validates the existing assignment.
+pub async fn sync_anti_affinity(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    anti_affinity: Option<MaskAntiAffinity>,
+) -> Result<(), Error> {
+    let api: Api<MaskConsumer> = Api::namespaced(client, namespace);
+    // A null value removes the field from the spec.
+    let spec: Value = json!({
+        "spec": {
+            "antiAffinity": anti_affinity
+        }
+    });
+    let patch: Patch<&Value> = Patch::Merge(&spec);
+    api.patch(name, &Default::default(), &patch)
+        .await
+        .context_kind_name("MaskConsumer", name)?;
+    Ok(())
+}
+
+/// Updates the MaskConsumer's Service to match the Mask's, so that the
+/// MaskConsumer controller brings the Service it owns in line with it.
+/// The whole spec is replaced, or removed if `service` is None.
+pub async fn sync_service(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    service: Option<MaskServiceSpec>,
+) -> Result<(), Error> {
+    let api: Api<MaskConsumer> = Api::namespaced(client, namespace);
+    // A merge patch would keep the selector labels that were removed.
+    let operation = match service {
+        // Adding a member that exists replaces it.
+        Some(service) => PatchOperation::Add(AddOperation {
+            path: "/spec/service".to_owned(),
+            value: serde_json::to_value(service).unwrap(),
+        }),
+        None => PatchOperation::Remove(RemoveOperation {
+            path: "/spec/service".to_owned(),
+        }),
+    };
+    let patch = json_patch::Patch(vec![operation]);
+    api.patch(name, &Default::default(), &Patch::Json::<()>(patch))
+        .await
+        .context_kind_name("MaskConsumer", name)?;
+    Ok(())
+}
+
+/// Updates the MaskConsumer's labels to match the Mask's, so that adding
+/// the labels a MaskProvider requires unblocks the assignment. `labels`
+/// maps each label to its new value, or to None if it's removed.
+pub async fn sync_labels(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    labels: BTreeMap<String, Option<String>>,
+) -> Result<(), Error> {
+    let api: Api<MaskConsumer> = Api::namespaced(client, namespace);
+    // A null value removes the label.
+    let metadata: Value = json!({
+        "metadata": {
+            "labels": labels
+        }
+    });
+    let patch: Patch<&Value> = Patch::Merge(&metadata);
+    api.patch(name, &Default::default(), &patch)
+        .await
+        .context_kind_name("MaskConsumer", name)?;
     Ok(())
 }
+
+/// Adopts the MaskConsumer with the given name for the Mask's slot with
+/// the given index if nothing owns it, or
+/// deletes it if it is owned by a previous Mask with the same name. One
+/// owned by a Mask with another name is left alone. Returns true if the
+/// MaskConsumer is owned by the Mask now, and false if creation should be
+/// retried.
+async fn adopt_or_delete_consumer(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    instance: &Mask,
+    index: usize,
+) -> Result<bool, Error> {
+    let mask_uid = instance.metadata.uid.as_deref().unwrap();
+    let api: Api<MaskConsumer> = Api::namespaced(client.clone(), namespace);
+    let existing = match api.get(name).await {
+        Ok(existing) => existing,
+        // The conflicting MaskConsumer was deleted in the meantime.
+        Err(kube::Error::Api(ae)) if ae.code == 404 => return Ok(false),
+        Err(e) => return Err(e).context_kind_name("MaskConsumer", name),
+    };
+    let owners = existing
+        .metadata
+        .owner_references
+        .as_deref()
+        .unwrap_or_default();
+    if owners.iter().any(|r| r.uid == mask_uid) {
+        // The MaskConsumer belongs to this Mask after all.
+        return Ok(true);
+    }
+    if is_orphaned(&existing) {
+        // The MaskConsumer was created for this Mask, but its owner
+        // reference never made it, so it wouldn't be deleted with it.
+        return adopt_consumer(client, name, namespace, instance, index, &existing).await;
+    }
+    if let Some(owner) = owners
+        .iter()
+        .find(|r| r.controller == Some(true) && r.kind == Mask::kind(&()))
+        .filter(|r| Some(r.name.as_str()) != instance.metadata.name.as_deref())
+    {
+        // The MaskConsumer is the one of another Mask's slot, e.g. the
+        // second slot of a Mask named like this one without its suffix.
+        // It's never deleted, as that Mask still exists.
+        waiting(
+            client,
+            instance,
+            Some(messages::consumer_name_taken(name, &owner.name)),
+        )
+        .await?;
+        return Ok(false);
+    }
+    // The MaskConsumer is owned by a Mask that no longer exists. Delete it
+    // so it can be recreated with the correct owner. Its deletion may be
+    // blocked by its finalizer until the consumers controller cleans up.
+    println!(
+        "{}/{} deleting stale MaskConsumer with uid {}",
+        namespace,
+        name,
+        existing.metadata.uid.as_deref().unwrap_or_default(),
+    );
+    match api.delete(name, &Default::default()).await {
+        Ok(_) => {}
+        Err(kube::Error::Api(ae)) if ae.code == 404 => {}
+        Err(e) => return Err(e).context_kind_name("MaskConsumer", name),
+    }
+    stale_consumer(client, instance).await?;
+    Ok(false)
+}
+
+/// Returns true if the MaskConsumer has no controller and can be adopted
+/// by the Mask with the same name. External MaskConsumers never have an
+/// owner, so they aren't adopted, nor are MaskConsumers being deleted.
+fn is_orphaned(consumer: &MaskConsumer) -> bool {
+    let controlled = consumer
+        .metadata
+        .owner_references
+        .iter()
+        .flatten()
+        .any(|r| r.controller == Some(true));
+    !controlled
+        && consumer.spec.external != Some(true)
+        && consumer.metadata.deletion_timestamp.is_none()
+}
+
+/// Adds the Mask's owner reference and labels to the MaskConsumer, as if it
+/// had been created with them. The patch only applies to the MaskConsumer
+/// that was read, so returns false if it was deleted or replaced first.
+async fn adopt_consumer(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    instance: &Mask,
+    index: usize,
+    existing: &MaskConsumer,
+) -> Result<bool, Error> {
+    let mut owner_references = existing
+        .metadata
+        .owner_references
+        .clone()
+        .unwrap_or_default();
+    owner_references.push(instance.controller_owner_ref(&()).unwrap());
+    let mut labels = existing.metadata.labels.clone().unwrap_or_default();
+    labels.extend(slot_labels(instance, index));
+    let patch = json_patch::Patch(vec![
+        PatchOperation::Test(TestOperation {
+            path: "/metadata/uid".to_owned(),
+            value: Value::from(existing.metadata.uid.clone()),
+        }),
+        PatchOperation::Add(AddOperation {
+            path: "/metadata/ownerReferences".to_owned(),
+            value: serde_json::to_value(owner_references).unwrap(),
+        }),
+        PatchOperation::Add(AddOperation {
+            path: "/metadata/labels".to_owned(),
+            value: serde_json::to_value(labels).unwrap(),
+        }),
+    ]);
+    let api: Api<MaskConsumer> = Api::namespaced(client, namespace);
+    match api
+        .patch(name, &PatchParams::default(), &Patch::Json::<()>(patch))
+        .await
+    {
+        Ok(_) => {
+            println!(
+                "{}/{} adopted MaskConsumer with uid {}",
+                namespace,
+                name,
+                existing.metadata.uid.as_deref().unwrap_or_default(),
+            );
+            Ok(true)
+        }
+        // The MaskConsumer is gone, or was replaced by another one.
+        Err(kube::Error::Api(ae)) if ae.code == 404 || ae.code == 422 => Ok(false),
+        Err(e) => Err(e).context_kind_name("MaskConsumer", name),
+    }
+}
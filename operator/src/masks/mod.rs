@@ -1,5 +1,32 @@
-mod actions;
-mod reconcile;
+pub(crate) mod actions;
+pub(crate) mod reconcile;
+pub(crate) mod ttl;
 pub mod util;
 
 pub use reconcile::run;
+
+use crate::rbac::Rule;
+
+/// API access the Mask controller needs. See [`crate::rbac`].
+pub const RBAC: &[Rule] = &[
+    Rule::new(
+        "vpn.beebs.dev",
+        &["masks"],
+        &["get", "list", "watch", "patch", "delete"],
+    ),
+    Rule::new("vpn.beebs.dev", &["masks/status"], &["patch"]),
+    // The MaskConsumers are created, kept in sync with the Mask and deleted
+    // with it, when its ttl expires or when their slots aren't requested.
+    Rule::new(
+        "vpn.beebs.dev",
+        &["maskconsumers"],
+        &["get", "list", "watch", "create", "patch", "delete"],
+    ),
+    // Deleting a Mask waits for its slot to be free.
+    Rule::new("vpn.beebs.dev", &["maskreservations"], &["list"]),
+    // Previews of the credentials copy are rendered from the MaskProvider's.
+    Rule::new("vpn.beebs.dev", &["maskproviders"], &["get"]),
+    Rule::new("", &["secrets"], &["get"]),
+    // Pods still using the credentials keep the ttl from expiring.
+    Rule::new("", &["pods"], &["list"]),
+];
@@ -1,29 +1,65 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use futures::stream::StreamExt;
+use k8s_openapi::api::core::v1::ConfigMap;
 use kube::{
-    api::ListParams, client::Client, runtime::controller::Action, runtime::Controller, Api,
-    ResourceExt,
+    api::ListParams,
+    client::Client,
+    runtime::{controller::Action, events::Reporter, Controller},
+    Api, ResourceExt,
 };
-use std::sync::Arc;
-use tokio::time::Duration;
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::{sync::Semaphore, time::Duration};
 use vpn_types::*;
 
-use super::{actions, util::get_consumer};
-use crate::util::{
-    finalizer::{self, FINALIZER_NAME},
-    Error, PROBE_INTERVAL,
+use super::{
+    actions, ttl,
+    util::{
+        consumer_name, get_consumer, get_last_slot, get_slot_consumer, get_surplus_consumers,
+        is_any_slot_reserved, label_changes, slot_labels, slots,
+    },
+};
+use crate::{
+    consumers::{
+        actions::{consumer_secret, get_provider_secret},
+        service,
+    },
+    util::{
+        clock::Clock,
+        events, explain,
+        finalizer::{self, FINALIZER_NAME},
+        messages, needs_refresh,
+        patch::{ensure_status_initialized, record_action_error},
+        preview::{self, SecretPreview},
+        report_reconcile_error, ControllerOptions, Error, PROBE_INTERVAL,
+    },
 };
 
 #[cfg(feature = "metrics")]
-use crate::util::metrics::ControllerMetrics;
+use crate::util::metrics::{export_mask_info, observe_mask, ControllerMetrics};
+
+/// How often a Mask that is being deleted checks whether its slot was released.
+const RELEASE_INTERVAL: Duration = Duration::from_secs(2);
 
-/// Entrypoint for the `Mask` controller.
-pub async fn run(client: Client) -> Result<(), Error> {
+/// Entrypoint for the `Mask` controller. If `concurrency` is set, at most
+/// that many reconciliations will be performed at the same time. Statuses
+/// that mirror an unchanged MaskConsumer are only rewritten once they are
+/// older than `status_freshness`.
+pub async fn run(
+    client: Client,
+    concurrency: Option<usize>,
+    status_freshness: Duration,
+    options: ControllerOptions,
+) -> Result<(), Error> {
     println!("Starting Mask controller...");
 
     // Preparation of resources used by the `kube_runtime::Controller`
     let crd_api: Api<Mask> = Api::all(client.clone());
-    let context: Arc<ContextData> = Arc::new(ContextData::new(client.clone()));
+    let context: Arc<ContextData> = Arc::new(ContextData::new(
+        client.clone(),
+        concurrency,
+        status_freshness,
+        options,
+    ));
 
     // The controller comes from the `kube_runtime` crate and manages the reconciliation process.
     // It requires the following information:
@@ -53,6 +89,21 @@ struct ContextData {
     /// Kubernetes client to make Kubernetes API requests with. Required for K8S resource management.
     client: Client,
 
+    /// Limits the number of concurrent reconciliations, if configured.
+    semaphore: Option<Semaphore>,
+
+    /// How long an unchanged status goes without being rewritten.
+    status_freshness: Duration,
+
+    /// Source of the current time for checking the ttl.
+    clock: Clock,
+
+    /// Reports the resources' phase transitions as events.
+    reporter: Reporter,
+
+    /// Settings from the command line, e.g. whether actions are explained.
+    options: ControllerOptions,
+
     #[cfg(feature = "metrics")]
     metrics: ControllerMetrics,
 }
@@ -62,43 +113,138 @@ impl ContextData {
     ///
     /// # Arguments:
     /// - `client`: A Kubernetes client to make Kubernetes REST API requests with. Resources
-    /// will be created and deleted with this client.
-    pub fn new(client: Client) -> Self {
+    ///   will be created and deleted with this client.
+    /// - `concurrency`: Optional maximum number of concurrent reconciliations.
+    /// - `status_freshness`: How long an unchanged status goes without being rewritten.
+    /// - `options`: Settings from the command line.
+    pub fn new(
+        client: Client,
+        concurrency: Option<usize>,
+        status_freshness: Duration,
+        options: ControllerOptions,
+    ) -> Self {
+        let semaphore = concurrency.map(Semaphore::new);
         #[cfg(feature = "metrics")]
         {
-            return ContextData {
+            ContextData {
                 client,
+                semaphore,
+                options,
+                reporter: events::reporter(),
+                status_freshness,
+                clock: Clock::System,
                 metrics: ControllerMetrics::new("masks"),
-            };
+            }
         }
         #[cfg(not(feature = "metrics"))]
         {
-            return ContextData { client };
+            return ContextData {
+                client,
+                semaphore,
+                options,
+                reporter: events::reporter(),
+                status_freshness,
+                clock: Clock::System,
+            };
         }
     }
 }
 
 /// Action to be taken upon an `Mask` resource during reconciliation
 #[derive(Debug, PartialEq)]
-enum MaskAction {
+pub(crate) enum MaskAction {
     /// Set the Mask's phase to Pending.
     Pending,
 
-    /// Create a MaskConsumer to manage the provider assignment.
-    CreateConsumer,
+    /// Create a MaskConsumer to manage the provider assignment of the
+    /// Mask's slot with the contained index.
+    CreateConsumer(usize),
 
-    /// Delete all subresources.
+    /// Remove the finalizer, as the MaskConsumers are gone and the first
+    /// slot is free.
     Delete,
 
-    /// Signals that the MaskConsumer is Waiting.
-    Waiting,
+    /// Delete the named MaskConsumer, which releases its slot. Contains
+    /// the slot reserved by the first MaskConsumer, if any, which is
+    /// remembered so the Mask isn't deleted before it's free.
+    DeleteConsumer(String, Option<SlotAffinity>),
+
+    /// Delete the named MaskConsumer of a slot the Mask no longer requests.
+    DeleteSurplusConsumer(String),
+
+    /// Wait for the MaskConsumers to be deleted and the slot to be released.
+    WaitForRelease,
+
+    /// Update the named MaskConsumer's anti-affinity group to match the Mask's.
+    SyncAntiAffinity(String, Option<MaskAntiAffinity>),
+
+    /// Update the named MaskConsumer's labels to match the Mask's. Contains
+    /// the new value of each label that changed, or None if it was removed.
+    SyncLabels(String, BTreeMap<String, Option<String>>),
+
+    /// Update the named MaskConsumer's Service to match the Mask's. Only
+    /// the MaskConsumer of the first slot gets the Service.
+    SyncService(String, Option<MaskServiceSpec>),
+
+    /// Write the contained `ConfigMap` with the preview requested
+    /// with the explain annotation.
+    WritePreview(Box<ConfigMap>),
+
+    /// Signals that the MaskConsumer is Terminating, so the credentials are
+    /// about to be withdrawn. Contains the name of the credentials Secret.
+    Withdrawing(String),
+
+    /// Signals that the MaskConsumer is Waiting. Carries the MaskConsumer's
+    /// message, if any, so the reason shows up on the Mask as well.
+    Waiting(Option<String>),
+
+    /// Signals that only some of the Mask's slots are assigned. Contains
+    /// the message counting them and the MaskProviders assigned so far.
+    PartiallyAssigned(String, Vec<AssignedProvider>),
 
     /// Signals that the Mask is actively consuming VPN credentials.
-    Active,
+    /// Contains the slot reserved by the first MaskConsumer, which is
+    /// remembered so it can be preferred upon reassignment, the
+    /// namespace of its MaskProvider, and the MaskProviders assigned
+    /// to each of the Mask's slots.
+    Active {
+        slot: Option<SlotAffinity>,
+        provider_namespace: Option<String>,
+        providers: Vec<AssignedProvider>,
+    },
 
     /// Signals that the MaskConsumer was unable to be assigned a provider.
     ErrNoProviders,
 
+    /// Signals that the MaskConsumer's namespace is not opted in to receiving
+    /// credentials. Contains the MaskConsumer's message with remediation guidance.
+    ErrNamespaceNotOptedIn(Option<String>),
+
+    /// Signals that a Secret with the name of the MaskConsumer's credentials
+    /// copy belongs to something else. Contains the MaskConsumer's message
+    /// naming the Secret.
+    ErrSecretConflict(Option<String>),
+
+    /// Signals that every suitable MaskProvider requires labels that the
+    /// Mask is missing. Contains the MaskConsumer's message naming them.
+    ErrMissingLabels(Option<String>),
+
+    /// Signals that reserving another slot would exceed a MaskQuota in the
+    /// Mask's namespace. Contains the MaskConsumer's message naming it.
+    ErrQuotaExceeded(Option<String>),
+
+    /// Signals that the MaskConsumer's assigned MaskProvider was deleted or
+    /// replaced. Contains the MaskConsumer's message naming it.
+    ErrProviderLost(Option<String>),
+
+    /// Signals that the Mask's spec has a value that can't be used.
+    /// Contains the message naming it.
+    ErrInvalidSpec(String),
+
+    /// Release the slot or delete the Mask, as its `ttlAction` says,
+    /// because no Pod used the credentials for the Mask's `ttl`.
+    Expire,
+
     /// The Mask resource is in desired state and requires no actions to be taken.
     NoOp,
 }
@@ -107,11 +253,27 @@ impl MaskAction {
     fn to_str(&self) -> &str {
         match self {
             MaskAction::Pending => "Pending",
-            MaskAction::CreateConsumer => "CreateConsumer",
+            MaskAction::CreateConsumer(_) => "CreateConsumer",
             MaskAction::Delete => "Delete",
-            MaskAction::Waiting => "Waiting",
-            MaskAction::Active => "Active",
+            MaskAction::DeleteConsumer(_, _) => "DeleteConsumer",
+            MaskAction::DeleteSurplusConsumer(_) => "DeleteSurplusConsumer",
+            MaskAction::WaitForRelease => "WaitForRelease",
+            MaskAction::SyncAntiAffinity(_, _) => "SyncAntiAffinity",
+            MaskAction::SyncLabels(_, _) => "SyncLabels",
+            MaskAction::SyncService(_, _) => "SyncService",
+            MaskAction::WritePreview(_) => "WritePreview",
+            MaskAction::Withdrawing(_) => "Withdrawing",
+            MaskAction::Waiting(_) => "Waiting",
+            MaskAction::PartiallyAssigned(_, _) => "PartiallyAssigned",
+            MaskAction::Active { .. } => "Active",
             MaskAction::ErrNoProviders => "ErrNoProviders",
+            MaskAction::ErrNamespaceNotOptedIn(_) => "ErrNamespaceNotOptedIn",
+            MaskAction::ErrSecretConflict(_) => "ErrSecretConflict",
+            MaskAction::ErrMissingLabels(_) => "ErrMissingLabels",
+            MaskAction::ErrQuotaExceeded(_) => "ErrQuotaExceeded",
+            MaskAction::ErrProviderLost(_) => "ErrProviderLost",
+            MaskAction::ErrInvalidSpec(_) => "ErrInvalidSpec",
+            MaskAction::Expire => "Expire",
             MaskAction::NoOp => "NoOp",
         }
     }
@@ -124,13 +286,21 @@ fn needs_finalizer(instance: &Mask) -> bool {
 
 /// needs_pending returns true if the `Mask` resource
 /// requires a status update to set the phase to Pending.
-/// This should be the first action for any managed resource.
+/// This should be the first action for any managed resource,
+/// and is repeated for any status that is missing fields the
+/// controller relies on.
 fn needs_pending(instance: &Mask) -> bool {
-    needs_finalizer(instance) || instance.status.as_ref().map_or(true, |s| s.phase.is_none())
+    needs_finalizer(instance) || ensure_status_initialized(instance).is_err()
 }
 
 /// Reconciliation function for the `Mask` resource.
 async fn reconcile(instance: Arc<Mask>, context: Arc<ContextData>) -> Result<Action, Error> {
+    // Wait for a permit if the number of concurrent reconciliations is limited.
+    let _permit = match context.semaphore {
+        Some(ref semaphore) => Some(semaphore.acquire().await.unwrap()),
+        None => None,
+    };
+
     // The `Client` is shared -> a clone from the reference is obtained
     let client: Client = context.client.clone();
 
@@ -155,38 +325,37 @@ async fn reconcile(instance: Arc<Mask>, context: Arc<ContextData>) -> Result<Act
 
     // Increment total number of reconciles for the Mask resource.
     #[cfg(feature = "metrics")]
-    context
-        .metrics
-        .reconcile_counter
-        .with_label_values(&[&name, &namespace])
-        .inc();
+    context.metrics.reconcile(&name, &namespace);
+
+    // Export the Mask's current state for dashboards and alerts.
+    #[cfg(feature = "metrics")]
+    observe_mask(&instance);
 
     // Benchmark the read phase of reconciliation.
     #[cfg(feature = "metrics")]
     let start = std::time::Instant::now();
 
     // Read phase of reconciliation determines goal during the write phase.
-    let action = determine_action(client.clone(), &name, &namespace, &instance).await?;
+    let action = determine_action(
+        client.clone(),
+        &name,
+        &namespace,
+        &instance,
+        context.status_freshness,
+        context.clock.now(),
+        context.options.info_metrics,
+    )
+    .await?;
 
     if action != MaskAction::NoOp {
         println!("{}/{} ACTION: {:?}", namespace, name, action);
     }
 
-    // Report the read phase performance.
+    // Report the read phase performance and count the action.
     #[cfg(feature = "metrics")]
     context
         .metrics
-        .read_histogram
-        .with_label_values(&[&name, &namespace, action.to_str()])
-        .observe(start.elapsed().as_secs_f64());
-
-    // Increment the counter for the action.
-    #[cfg(feature = "metrics")]
-    context
-        .metrics
-        .action_counter
-        .with_label_values(&[&name, &namespace, action.to_str()])
-        .inc();
+        .read(&name, &namespace, action.to_str(), start);
 
     // Benchmark the write phase of reconciliation.
     #[cfg(feature = "metrics")]
@@ -194,21 +363,47 @@ async fn reconcile(instance: Arc<Mask>, context: Arc<ContextData>) -> Result<Act
         // Don't measure performance for NoOp actions.
         MaskAction::NoOp => None,
         // Start a performance timer for the write phase.
-        _ => Some(
-            context
-                .metrics
-                .write_histogram
-                .with_label_values(&[&name, &namespace, action.to_str()])
-                .start_timer(),
-        ),
+        _ => Some(context.metrics.write(&name, &namespace, action.to_str())),
     };
 
-    // Performs action as decided by the `determine_action` function.
-    // This is the write phase of reconciliation.
-    let result = match action {
+    // Perform the action. The action's name is attached to any error
+    // so the error handler can report which action failed, and to the
+    // events published for the phase transitions it makes.
+    let action_name = action.to_str().to_owned();
+    let result = events::scope(
+        context.reporter.clone(),
+        &action_name,
+        explain::scope(
+            context.options.explain,
+            "masks",
+            &action_name,
+            apply_action(client, &name, &namespace, &instance, action),
+        ),
+    )
+    .await;
+
+    // Report the write phase performance with the action's outcome.
+    #[cfg(feature = "metrics")]
+    if let Some(timer) = timer {
+        timer.observe(&result);
+    }
+
+    result.map_err(|e| Error::action(&action_name, e))
+}
+
+/// Performs the action as decided by the `determine_action` function.
+/// This is the write phase of reconciliation.
+async fn apply_action(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    instance: &Mask,
+    action: MaskAction,
+) -> Result<Action, Error> {
+    Ok(match action {
         MaskAction::Pending => {
             // Add the finalizer to the Mask resource.
-            let instance = finalizer::add(client.clone(), &name, &namespace).await?;
+            let instance = finalizer::add(client.clone(), name, namespace).await?;
 
             // Update the phase of the `Mask` resource to Pending.
             actions::pending(client, &instance).await?;
@@ -218,76 +413,172 @@ async fn reconcile(instance: Arc<Mask>, context: Arc<ContextData>) -> Result<Act
         }
         MaskAction::Delete => {
             // Show that the `Mask` is being terminated.
-            actions::terminating(client.clone(), &instance).await?;
-
-            // Note: we don't need to manually delete the `MaskConsumer` resource.
-            // Kubernetes will delete it automatically because of the owner reference.
+            actions::terminating(client.clone(), instance).await?;
 
             // Remove the finalizer, which will allow the Mask resource to be deleted.
-            finalizer::delete::<Mask>(client, &name, &namespace).await?;
+            // The MaskConsumer is already gone, so the slot can be reserved again.
+            finalizer::delete::<Mask>(client, name, namespace).await?;
 
             // Makes no sense to requeue after deleting, as the resource is gone.
             Action::await_change()
         }
-        MaskAction::Waiting => {
+        MaskAction::DeleteConsumer(consumer, slot) => {
+            // Delete the MaskConsumer explicitly, as the owner reference
+            // only deletes it once the Mask itself is gone.
+            actions::delete_consumer(client, &consumer, namespace, instance, slot).await?;
+
+            // Check back shortly, as the MaskConsumer's finalizer has to run first.
+            Action::requeue(RELEASE_INTERVAL)
+        }
+        MaskAction::DeleteSurplusConsumer(consumer) => {
+            // Release the slot the Mask no longer requests.
+            actions::delete_surplus_consumer(client, &consumer, namespace).await?;
+
+            // Requeue immediately to delete the next one, if any.
+            Action::requeue(Duration::ZERO)
+        }
+        // The MaskConsumer and MaskReservation controllers release the slot.
+        MaskAction::WaitForRelease => Action::requeue(RELEASE_INTERVAL),
+        MaskAction::SyncAntiAffinity(consumer, anti_affinity) => {
+            // Patch the MaskConsumer, which re-validates its assignment.
+            actions::sync_anti_affinity(client, &consumer, namespace, anti_affinity).await?;
+
+            // Requeue immediately to resume mirroring the MaskConsumer's status.
+            Action::requeue(Duration::ZERO)
+        }
+        MaskAction::SyncLabels(consumer, labels) => {
+            // Patch the MaskConsumer, which re-checks any required labels.
+            actions::sync_labels(client, &consumer, namespace, labels).await?;
+
+            // Requeue immediately to resume mirroring the MaskConsumer's status.
+            Action::requeue(Duration::ZERO)
+        }
+        MaskAction::SyncService(consumer, service) => {
+            // Patch the MaskConsumer, which updates the Service it owns.
+            actions::sync_service(client, &consumer, namespace, service).await?;
+
+            // Requeue immediately to resume mirroring the MaskConsumer's status.
+            Action::requeue(Duration::ZERO)
+        }
+        MaskAction::WritePreview(config_map) => {
+            // Overwrite the previous preview, without creating anything.
+            match preview::write(client, *config_map).await? {
+                // Requeue immediately to resume mirroring the MaskConsumer's status.
+                true => Action::requeue(Duration::ZERO),
+                // Previews aren't enabled in the namespace.
+                false => Action::requeue(preview::FORBIDDEN_RETRY),
+            }
+        }
+        MaskAction::Withdrawing(secret) => {
+            // Warn that the Pods using the credentials should be stopped.
+            actions::withdrawing(client, instance, secret).await?;
+
+            // Check back shortly, as the MaskConsumer's finalizer has to run first.
+            Action::requeue(RELEASE_INTERVAL)
+        }
+        MaskAction::Waiting(message) => {
             // Update the phase to Waiting.
-            actions::waiting(client, &instance).await?;
+            actions::waiting(client, instance, message).await?;
 
             // Try again after a short delay.
             Action::requeue(PROBE_INTERVAL)
         }
-        MaskAction::Active => {
-            // Update the phase to Active.
-            actions::active(client, &instance).await?;
+        MaskAction::PartiallyAssigned(message, providers) => {
+            // Update the phase to Waiting, listing the assigned slots.
+            actions::partially_assigned(client, instance, message, providers).await?;
 
-            // Resource is fully reconciled.
+            // Try again after a short delay.
             Action::requeue(PROBE_INTERVAL)
         }
-        MaskAction::CreateConsumer => {
-            // Immediately update the phase to Waiting.
-            actions::waiting(client.clone(), &instance).await?;
-
-            // Create the MaskConsumer object that will manage provider assignment.
-            actions::create_consumer(client, &name, &namespace, &instance).await?;
+        MaskAction::Active {
+            slot,
+            provider_namespace,
+            providers,
+        } => {
+            // Update the phase to Active and remember the reserved slot.
+            actions::active(client, instance, slot, provider_namespace, providers).await?;
 
-            // Requeue after a short delay to give the MaskConsumer time to reconcile.
+            // Resource is fully reconciled.
             Action::requeue(PROBE_INTERVAL)
         }
+        MaskAction::CreateConsumer(index) => {
+            // Create the MaskConsumer object that will manage provider assignment,
+            // or adopt the one left behind by an interrupted attempt.
+            if actions::create_consumer(client.clone(), namespace, instance, index).await? {
+                // Only show the Mask as Waiting once its first MaskConsumer
+                // exists. The others are counted once they're reconciled.
+                // A revoked assignment is shown until another is made.
+                if index == 0 && !provider_lost(instance) {
+                    actions::waiting(client, instance, None).await?;
+                }
+
+                // Requeue after a short delay to give the MaskConsumer time to reconcile.
+                Action::requeue(PROBE_INTERVAL)
+            } else {
+                // A stale MaskConsumer is being deleted, or the name is
+                // taken by another Mask's. Retry shortly.
+                Action::requeue(Duration::from_secs(2))
+            }
+        }
         MaskAction::ErrNoProviders => {
             // Reflect the error in the status object.
-            actions::err_no_providers(client, &instance).await?;
+            actions::err_no_providers(client, instance).await?;
 
             // Requeue after a short delay to allow time for a valid MaskProvider to appear.
             Action::requeue(PROBE_INTERVAL)
         }
-        // The resource is already in desired state, do nothing and re-check after 10 seconds
-        MaskAction::NoOp => Action::requeue(PROBE_INTERVAL),
-    };
+        MaskAction::ErrNamespaceNotOptedIn(message) => {
+            // Mirror the MaskConsumer's error in the status object.
+            actions::err_namespace_not_opted_in(client, instance, message).await?;
 
-    #[cfg(feature = "metrics")]
-    if let Some(timer) = timer {
-        timer.observe_duration();
-    }
+            // Requeue after a short delay to allow time for the namespace to be labeled.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        MaskAction::ErrSecretConflict(message) => {
+            // Mirror the MaskConsumer's error in the status object.
+            actions::err_secret_conflict(client, instance, message).await?;
 
-    Ok(result)
-}
+            // Requeue after a short delay to allow time for the Secret to be removed.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        MaskAction::ErrMissingLabels(message) => {
+            // Mirror the MaskConsumer's error in the status object.
+            actions::err_missing_labels(client, instance, message).await?;
 
-/// Returns the phase of the Mask.
-pub fn get_mask_phase(instance: &Mask) -> Result<(MaskPhase, Duration), Error> {
-    let status = instance
-        .status
-        .as_ref()
-        .ok_or_else(|| Error::UserInputError("No status".to_string()))?;
-    let phase = status
-        .phase
-        .ok_or_else(|| Error::UserInputError("No phase".to_string()))?;
-    let last_updated: chrono::DateTime<Utc> = status
-        .last_updated
-        .as_ref()
-        .ok_or_else(|| Error::UserInputError("No lastUpdated".to_string()))?
-        .parse()?;
-    let age: chrono::Duration = Utc::now() - last_updated;
-    Ok((phase, age.to_std()?))
+            // Requeue after a short delay to allow time for the labels to be added.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        MaskAction::ErrQuotaExceeded(message) => {
+            // Mirror the MaskConsumer's error in the status object.
+            actions::err_quota_exceeded(client, instance, message).await?;
+
+            // Requeue after a short delay to allow time for a slot to be released.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        MaskAction::ErrProviderLost(message) => {
+            // Mirror the MaskConsumer's error in the status object.
+            actions::err_provider_lost(client, instance, message).await?;
+
+            // Requeue after a short delay to allow time for reassignment.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        MaskAction::ErrInvalidSpec(message) => {
+            // Reflect the error in the status object.
+            actions::err_invalid_spec(client, instance, message).await?;
+
+            // Requeue after a short delay to allow time for the spec to be fixed.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        MaskAction::Expire => {
+            // Release the slot or delete the Mask.
+            ttl::expire(client, name, namespace, instance).await?;
+
+            // Check back shortly, as the MaskConsumer's finalizer has to run first.
+            Action::requeue(RELEASE_INTERVAL)
+        }
+        // The resource is already in desired state, do nothing and re-check after 10 seconds
+        MaskAction::NoOp => Action::requeue(PROBE_INTERVAL),
+    })
 }
 
 /// Resources arrives into reconciliation queue in a certain state. This function looks at
@@ -296,70 +587,493 @@ pub fn get_mask_phase(instance: &Mask) -> Result<(MaskPhase, Duration), Error> {
 ///
 /// # Arguments
 /// - `instance`: A reference to `Mask` being reconciled to decide next action upon.
-async fn determine_action(
+/// - `status_freshness`: How long an unchanged status goes without being rewritten.
+/// - `now`: The current time, against which the ttl is checked.
+/// - `info_metrics`: Whether information about the Mask is exported as a metric.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) async fn determine_action(
     client: Client,
     _name: &str,
     _namespace: &str,
     instance: &Mask,
+    status_freshness: Duration,
+    now: DateTime<Utc>,
+    info_metrics: bool,
 ) -> Result<MaskAction, Error> {
     if instance.metadata.deletion_timestamp.is_some() {
-        return Ok(MaskAction::Delete);
+        #[cfg(feature = "metrics")]
+        export_mask_info(info_metrics, instance, None);
+        return determine_delete_action(client, instance).await;
     }
 
     // The rest of the controller code assumes the presence of the
-    // status object and its phase field. If neither of these exist,
-    // the first thing that should be done is initializing them.
+    // status object and its bookkeeping fields. If any of these are
+    // missing, the first thing that should be done is initializing them.
     if needs_pending(instance) {
         return Ok(MaskAction::Pending);
     }
 
     // Get the child MaskConsumer resource that will manage provider
     // assignment and be deleted whenever the provider is unassigned.
-    let consumer = match get_consumer(client.clone(), instance).await? {
+    let consumer = get_consumer(client.clone(), instance).await?;
+
+    // Describe the Mask's assignment for joining with other metrics.
+    #[cfg(feature = "metrics")]
+    export_mask_info(info_metrics, instance, consumer.as_ref());
+
+    // A ttl that isn't a duration is shown rather than ignored.
+    let ttl = match ttl::parse(instance) {
+        Ok(ttl) => ttl,
+        Err(message) => {
+            return Ok(recent_status(
+                instance,
+                MaskPhase::ErrInvalidSpec,
+                &message,
+                MaskAction::ErrInvalidSpec(message.clone()),
+                status_freshness,
+            ))
+        }
+    };
+
+    // So is a Service that can't be created.
+    if let Some(message) = instance
+        .spec
+        .options()
+        .service
+        .and_then(|spec| service::validate(&instance.name_any(), &spec).err())
+    {
+        return Ok(recent_status(
+            instance,
+            MaskPhase::ErrInvalidSpec,
+            &message,
+            MaskAction::ErrInvalidSpec(message.clone()),
+            status_freshness,
+        ));
+    }
+
+    // And so is a Mask that doesn't reserve any slots, or too many. The
+    // schema bounds `slots` too, but older CRDs didn't.
+    let slots = slots(instance);
+    if slots == 0 || slots > MAX_MASK_SLOTS {
+        let message = messages::invalid_slots(slots);
+        return Ok(recent_status(
+            instance,
+            MaskPhase::ErrInvalidSpec,
+            &message,
+            MaskAction::ErrInvalidSpec(message.clone()),
+            status_freshness,
+        ));
+    }
+
+    let consumer = match consumer {
+        // The slot was released when the ttl expired, and isn't reserved
+        // again until the spec changes.
+        None if ttl::is_released(instance) => {
+            let message = messages::ttl_released(&instance.spec.options().ttl.unwrap_or_default());
+            return Ok(recent_status(
+                instance,
+                MaskPhase::Waiting,
+                &message,
+                MaskAction::Waiting(Some(message.clone())),
+                status_freshness,
+            ));
+        }
         // MaskConsumer has not been created yet.
-        None => return Ok(MaskAction::CreateConsumer),
+        None => return Ok(MaskAction::CreateConsumer(0)),
         // MaskConsumer has already been created.
         Some(consumer) => consumer,
     };
 
-    // Keep the status object synchronized with the MaskConsumer's status.
-    determine_status_action(instance, &consumer)
+    // Each of the other slots is reserved by a MaskConsumer of its own,
+    // created once the first one exists.
+    let mut consumers = vec![consumer];
+    for index in 1..slots {
+        match get_slot_consumer(client.clone(), instance, index).await? {
+            Some(consumer) => consumers.push(consumer),
+            None => return Ok(MaskAction::CreateConsumer(index)),
+        }
+    }
+
+    for (index, consumer) in consumers.iter().enumerate() {
+        // The anti-affinity group may be changed after the MaskConsumer
+        // is created, so propagate it instead of recreating the consumer.
+        let anti_affinity = instance.spec.options().anti_affinity;
+        if consumer.spec.anti_affinity != anti_affinity {
+            return Ok(MaskAction::SyncAntiAffinity(
+                consumer.name_any(),
+                anti_affinity,
+            ));
+        }
+
+        // Labels may be added after the MaskConsumer is created, such as
+        // the ones a MaskProvider requires, or stripped from it by something
+        // else, so keep propagating them.
+        let labels = label_changes(consumer.labels(), &slot_labels(instance, index));
+        if !labels.is_empty() {
+            return Ok(MaskAction::SyncLabels(consumer.name_any(), labels));
+        }
+
+        // The Service may be changed after the MaskConsumer is created,
+        // so propagate it like the labels.
+        let service = instance.spec.options().service.filter(|_| index == 0);
+        if consumer.spec.service != service {
+            return Ok(MaskAction::SyncService(consumer.name_any(), service));
+        }
+    }
+
+    // Release the slots the Mask no longer requests, starting with the last.
+    if let Some(surplus) = get_surplus_consumers(client.clone(), instance)
+        .await?
+        .into_iter()
+        .rev()
+        .find(|c| c.metadata.deletion_timestamp.is_none())
+    {
+        return Ok(MaskAction::DeleteSurplusConsumer(surplus.name_any()));
+    }
+
+    // Show what the credentials Secret copied for the Mask looks like, if requested.
+    if let Some(action) = determine_preview_action(client.clone(), instance, &consumers[0]).await? {
+        return Ok(action);
+    }
+
+    // Free the slots if no Pod used any of the credentials for the ttl. Pods
+    // are only listed once the ttl has passed since one was last seen.
+    if let Some(ttl) = ttl {
+        if consumers.iter().all(|c| ttl::is_idle(c, ttl, now)) {
+            let mut expired = true;
+            for consumer in &consumers {
+                if !ttl::is_expired(client.clone(), consumer).await? {
+                    expired = false;
+                    break;
+                }
+            }
+            if expired {
+                return Ok(MaskAction::Expire);
+            }
+        }
+    }
+
+    // Keep the status object synchronized with the MaskConsumers' statuses.
+    determine_status_action(instance, &consumers, status_freshness)
+}
+
+/// Determines whether the consumer-secret preview requested with the
+/// explain annotation needs to be written, rendering the credentials
+/// Secret copied for the MaskConsumer without its values. Nothing is
+/// previewed until the MaskConsumer is assigned a MaskProvider.
+async fn determine_preview_action(
+    client: Client,
+    instance: &Mask,
+    consumer: &MaskConsumer,
+) -> Result<Option<MaskAction>, Error> {
+    if preview::requested(&instance.metadata, &[preview::CONSUMER_SECRET]).is_none() {
+        return Ok(None);
+    }
+    let provider = match consumer.status.as_ref().and_then(|s| s.provider.as_ref()) {
+        Some(provider) => provider,
+        None => return Ok(None),
+    };
+    let namespace = consumer.namespace().unwrap();
+    let rendered = match get_provider_secret(client.clone(), provider).await {
+        Ok(secret) => consumer_secret(&namespace, consumer, secret).map(SecretPreview::from),
+        // Show that the MaskProvider or its credentials are gone.
+        Err(e @ Error::ProviderDeparted(_)) => Err(e),
+        Err(e) if e.status_code() == Some(404) => Err(e),
+        Err(e) => return Err(e),
+    };
+    let desired = preview::preview_config_map(instance, preview::CONSUMER_SECRET, rendered);
+    Ok(preview::needs_write(client, instance, desired)
+        .await?
+        .map(|config_map| MaskAction::WritePreview(Box::new(config_map))))
 }
 
-/// Helper function used to run an action if the phase of the `Mask`
-/// doesn't match the desired value or if the status object is stale.
-fn recent_status(instance: &Mask, phase: MaskPhase, action: MaskAction) -> MaskAction {
-    let (cur_phase, age) = get_mask_phase(instance).unwrap();
-    if cur_phase != phase || age > PROBE_INTERVAL {
+/// Determines the action for a Mask that is being deleted. The finalizer
+/// is only removed once its MaskConsumers are gone and the slots they
+/// reserved are free, so the slots can be reserved again as soon as the
+/// Mask is gone.
+async fn determine_delete_action(client: Client, instance: &Mask) -> Result<MaskAction, Error> {
+    let mut consumers = Vec::new();
+    for index in 0..slots(instance).clamp(1, MAX_MASK_SLOTS) {
+        consumers.extend(get_slot_consumer(client.clone(), instance, index).await?);
+    }
+    consumers.extend(get_surplus_consumers(client.clone(), instance).await?);
+    if let Some(consumer) = consumers
+        .iter()
+        .find(|c| c.metadata.deletion_timestamp.is_none())
+    {
+        // The first slot is remembered, so a Mask recreated with the same
+        // name prefers it. The others are waited for through the status.
+        let first = consumer.name_any() == consumer_name(instance, 0);
+        let slot = get_slot_affinity(consumer).filter(|_| first);
+        return Ok(MaskAction::DeleteConsumer(consumer.name_any(), slot));
+    }
+    if !consumers.is_empty() {
+        // The MaskConsumers' finalizers are still running.
+        return Ok(MaskAction::WaitForRelease);
+    }
+    if is_any_slot_reserved(client, instance).await? {
+        // The MaskReservations are deleted once they notice the MaskConsumers are gone.
+        return Ok(MaskAction::WaitForRelease);
+    }
+    Ok(MaskAction::Delete)
+}
+
+/// Helper function used to run an action if the phase or message of the
+/// `Mask` don't match the desired values or if the status object is stale.
+fn recent_status(
+    instance: &Mask,
+    phase: MaskPhase,
+    message: &str,
+    action: MaskAction,
+    status_freshness: Duration,
+) -> MaskAction {
+    // A status that isn't fully initialized is rewritten by the action.
+    match ensure_status_initialized(instance) {
+        Ok(status)
+            if status.phase == Some(phase)
+                && status.message.as_deref() == Some(message)
+                && !needs_refresh(status, status_freshness) =>
+        {
+            MaskAction::NoOp
+        }
+        _ => action,
+    }
+}
+
+/// Returns true if the Mask shows that its assignment was revoked, which
+/// it keeps showing until it's assigned another MaskProvider.
+fn provider_lost(instance: &Mask) -> bool {
+    instance.status.as_ref().and_then(|s| s.phase) == Some(MaskPhase::ErrProviderLost)
+}
+
+/// Returns the slot reserved by the MaskConsumer, if it has been assigned a MaskProvider.
+fn get_slot_affinity(consumer: &MaskConsumer) -> Option<SlotAffinity> {
+    consumer
+        .status
+        .as_ref()
+        .and_then(|s| s.provider.as_ref())
+        .map(|p| SlotAffinity {
+            provider_uid: p.uid.clone(),
+            slot: p.slot,
+        })
+}
+
+/// Returns the MaskProviders assigned to the Active MaskConsumers, in the
+/// order of their slots.
+fn assigned_providers(consumers: &[MaskConsumer]) -> Vec<AssignedProvider> {
+    consumers
+        .iter()
+        .filter_map(|c| c.status.as_ref())
+        .filter(|s| s.phase == Some(MaskConsumerPhase::Active))
+        .filter_map(|s| s.provider.clone())
+        .collect()
+}
+
+/// Determines the action given that the only thing left to do is keeping
+/// the phase in sync with the consumers, one per slot. A Mask with several
+/// slots is only Active once all of its MaskConsumers are, and counts the
+/// assigned slots while the others are waiting. Any other phase is mirrored
+/// from the first MaskConsumer that isn't Active. The MaskConsumers are
+/// watched, so changes to them are mirrored right away, and a status that
+/// already mirrors them is only rewritten once it's older than
+/// `status_freshness`.
+fn determine_status_action(
+    instance: &Mask,
+    consumers: &[MaskConsumer],
+    status_freshness: Duration,
+) -> Result<MaskAction, Error> {
+    let phase = |c: &MaskConsumer| c.status.as_ref().and_then(|s| s.phase);
+    let unassigned = match consumers
+        .iter()
+        .find(|c| phase(c) != Some(MaskConsumerPhase::Active))
+    {
+        // A single slot is mirrored as is.
+        _ if consumers.len() == 1 => {
+            return determine_consumer_status_action(instance, &consumers[0], status_freshness)
+        }
+        None => return Ok(active_status(instance, consumers, status_freshness)),
+        Some(unassigned) => unassigned,
+    };
+    match phase(unassigned) {
+        // Count the assigned slots, naming the reason the next one waits.
+        None | Some(MaskConsumerPhase::Pending) | Some(MaskConsumerPhase::Waiting)
+            if !provider_lost(instance) =>
+        {
+            let providers = assigned_providers(consumers);
+            let reason = unassigned
+                .status
+                .as_ref()
+                .filter(|s| s.phase == Some(MaskConsumerPhase::Waiting))
+                .and_then(|s| s.message.as_deref());
+            let message = messages::slots_assigned(providers.len(), consumers.len(), reason);
+            Ok(recent_status(
+                instance,
+                MaskPhase::Waiting,
+                &message,
+                MaskAction::PartiallyAssigned(message.clone(), providers),
+                status_freshness,
+            ))
+        }
+        _ => determine_consumer_status_action(instance, unassigned, status_freshness),
+    }
+}
+
+/// Returns the action that shows the Mask as Active, given that all of
+/// its MaskConsumers are. The slot of the first one is remembered right
+/// away if it changed.
+fn active_status(
+    instance: &Mask,
+    consumers: &[MaskConsumer],
+    status_freshness: Duration,
+) -> MaskAction {
+    let first = &consumers[0];
+    let slot = get_slot_affinity(first);
+    let remember = slot.is_some() && slot != get_last_slot(instance);
+    let action = MaskAction::Active {
+        slot,
+        provider_namespace: first
+            .status
+            .as_ref()
+            .and_then(|s| s.provider.as_ref())
+            .map(|p| p.namespace.clone()),
+        providers: assigned_providers(consumers),
+    };
+    if remember {
+        // Remember the newly reserved slot right away.
         action
     } else {
-        MaskAction::NoOp
+        recent_status(
+            instance,
+            MaskPhase::Active,
+            messages::ACTIVE,
+            action,
+            status_freshness,
+        )
     }
 }
 
-/// Determines the action given that the only thing left to do
-/// is periodically keeping the phase in sync with the consumer.
-fn determine_status_action(instance: &Mask, consumer: &MaskConsumer) -> Result<MaskAction, Error> {
+/// Determines the action that mirrors the phase of a single MaskConsumer.
+fn determine_consumer_status_action(
+    instance: &Mask,
+    consumer: &MaskConsumer,
+    status_freshness: Duration,
+) -> Result<MaskAction, Error> {
+    let message = consumer.status.as_ref().and_then(|s| s.message.clone());
+    let secret = consumer
+        .status
+        .as_ref()
+        .and_then(|s| s.provider.as_ref())
+        .map(|p| p.secret.clone());
     Ok(consumer
         .status
         .as_ref()
-        .map_or(None, |s| s.phase)
+        .and_then(|s| s.phase)
         .map(|p| match p {
-            // Inherit Pending, Waiting, and Terminating phases as Waiting.
+            // The assignment was revoked, and the Pods using the credentials
+            // still have to be recreated, so keep showing it while the
+            // MaskConsumer is recreated and waits for another MaskProvider.
             MaskConsumerPhase::Pending
             | MaskConsumerPhase::Waiting
-            | MaskConsumerPhase::Terminating => {
-                recent_status(instance, MaskPhase::Waiting, MaskAction::Waiting)
+            | MaskConsumerPhase::Terminating
+                if provider_lost(instance) =>
+            {
+                let message = instance.status.as_ref().and_then(|s| s.message.clone());
+                recent_status(
+                    instance,
+                    MaskPhase::ErrProviderLost,
+                    message.as_deref().unwrap_or_default(),
+                    MaskAction::ErrProviderLost(message.clone()),
+                    status_freshness,
+                )
+            }
+            // The credentials are about to be withdrawn. The Mask only
+            // becomes Waiting once the MaskConsumer is gone and recreated.
+            MaskConsumerPhase::Terminating if secret.is_some() => {
+                let secret = secret.clone().unwrap();
+                recent_status(
+                    instance,
+                    MaskPhase::Terminating,
+                    &messages::assignment_withdrawn(&secret),
+                    MaskAction::Withdrawing(secret),
+                    status_freshness,
+                )
             }
+            // Inherit Pending and Terminating phases as Waiting, as there
+            // are no credentials to withdraw.
+            MaskConsumerPhase::Pending | MaskConsumerPhase::Terminating => recent_status(
+                instance,
+                MaskPhase::Waiting,
+                messages::WAITING,
+                MaskAction::Waiting(None),
+                status_freshness,
+            ),
+            // Inherit the Waiting phase, mirroring the MaskConsumer's message.
+            MaskConsumerPhase::Waiting => recent_status(
+                instance,
+                MaskPhase::Waiting,
+                message.as_deref().unwrap_or(messages::WAITING),
+                MaskAction::Waiting(message.clone()),
+                status_freshness,
+            ),
             // Inherit the Active phase at a regular interval.
             MaskConsumerPhase::Active => {
-                recent_status(instance, MaskPhase::Active, MaskAction::Active)
+                active_status(instance, std::slice::from_ref(consumer), status_freshness)
             }
             // No providers error, use the ErrNoProviders phase.
             MaskConsumerPhase::ErrNoProviders => recent_status(
                 instance,
                 MaskPhase::ErrNoProviders,
+                messages::ERR_NO_PROVIDERS,
                 MaskAction::ErrNoProviders,
+                status_freshness,
+            ),
+            // Namespace is not opted in, mirror the MaskConsumer's message.
+            MaskConsumerPhase::ErrNamespaceNotOptedIn => recent_status(
+                instance,
+                MaskPhase::ErrNamespaceNotOptedIn,
+                message.as_deref().unwrap_or_default(),
+                MaskAction::ErrNamespaceNotOptedIn(message.clone()),
+                status_freshness,
+            ),
+            // A Secret is in the way, mirror the MaskConsumer's message.
+            MaskConsumerPhase::ErrSecretConflict => recent_status(
+                instance,
+                MaskPhase::ErrSecretConflict,
+                message.as_deref().unwrap_or_default(),
+                MaskAction::ErrSecretConflict(message.clone()),
+                status_freshness,
+            ),
+            // Labels are missing, mirror the MaskConsumer's message naming them.
+            MaskConsumerPhase::ErrMissingLabels => recent_status(
+                instance,
+                MaskPhase::ErrMissingLabels,
+                message.as_deref().unwrap_or_default(),
+                MaskAction::ErrMissingLabels(message.clone()),
+                status_freshness,
+            ),
+            // The settings can't be applied, mirror the MaskConsumer's message.
+            MaskConsumerPhase::ErrInvalidSpec => recent_status(
+                instance,
+                MaskPhase::ErrInvalidSpec,
+                message.as_deref().unwrap_or_default(),
+                MaskAction::ErrInvalidSpec(message.clone().unwrap_or_default()),
+                status_freshness,
+            ),
+            // A quota is exhausted, mirror the MaskConsumer's message naming it.
+            MaskConsumerPhase::ErrQuotaExceeded => recent_status(
+                instance,
+                MaskPhase::ErrQuotaExceeded,
+                message.as_deref().unwrap_or_default(),
+                MaskAction::ErrQuotaExceeded(message.clone()),
+                status_freshness,
+            ),
+            // The MaskProvider is gone, mirror the MaskConsumer's message naming it.
+            MaskConsumerPhase::ErrProviderLost => recent_status(
+                instance,
+                MaskPhase::ErrProviderLost,
+                message.as_deref().unwrap_or_default(),
+                MaskAction::ErrProviderLost(message.clone()),
+                status_freshness,
             ),
         })
         // If the MaskConsumer has no phase, do nothing.
@@ -373,8 +1087,10 @@ fn determine_status_action(instance: &Mask, consumer: &MaskConsumer) -> Result<M
 /// # Arguments
 /// - `instance`: The erroneous resource.
 /// - `error`: A reference to the `kube::Error` that occurred during reconciliation.
-/// - `_context`: Unused argument. Context Data "injected" automatically by kube-rs.
-fn on_error(instance: Arc<Mask>, error: &Error, _context: Arc<ContextData>) -> Action {
-    eprintln!("Reconciliation error:\n{:?}.\n{:?}", error, instance);
-    Action::requeue(Duration::from_secs(5))
+/// - `context`: Context Data "injected" automatically by kube-rs. Its client is used
+///   to record the failed action in the resource's status object.
+fn on_error(instance: Arc<Mask>, error: &Error, context: Arc<ContextData>) -> Action {
+    let action = report_reconcile_error(&instance, error);
+    record_action_error(context.client.clone(), instance, "masks", error);
+    action
 }
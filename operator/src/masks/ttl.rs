@@ -0,0 +1,115 @@
+use chrono::{DateTime, Utc};
+use kube::{api::DeleteParams, Api, Client};
+use std::time::Duration;
+use vpn_types::*;
+
+use super::util::{consumer_name, slots};
+use crate::{
+    consumers::withdrawal::list_referencing_pods,
+    util::{clock, events, messages, patch::patch_status, Error, ErrorContext},
+};
+
+/// Returns the Mask's ttl, or None if it doesn't have one. A ttl that
+/// isn't a duration is returned as the message explaining why, rather
+/// than defaulting to no ttl.
+pub fn parse(instance: &Mask) -> Result<Option<Duration>, String> {
+    let ttl = match instance.spec.options().ttl {
+        Some(ttl) => ttl,
+        None => return Ok(None),
+    };
+    parse_duration::parse(ttl.trim())
+        .map(Some)
+        .map_err(|e| messages::invalid_ttl(&ttl, &e.to_string()))
+}
+
+/// Returns true if the Mask's slot was released because its ttl expired
+/// and its spec hasn't changed since.
+pub fn is_released(instance: &Mask) -> bool {
+    let expired = instance.status.as_ref().and_then(|s| s.expired_generation);
+    expired.is_some() && expired == instance.metadata.generation
+}
+
+/// Returns when the MaskConsumer's credentials were last in use: when a
+/// Pod using them was last seen, or when the MaskConsumer was created if
+/// that's later. None if neither is known.
+pub fn idle_since(consumer: &MaskConsumer) -> Option<DateTime<Utc>> {
+    let created = consumer.metadata.creation_timestamp.as_ref().map(|t| t.0);
+    consumer
+        .status
+        .as_ref()
+        .and_then(|s| s.consumers.as_ref())
+        .into_iter()
+        .flatten()
+        .filter_map(|record| clock::parse_timestamp(&record.last_seen).ok())
+        .chain(created)
+        .max()
+}
+
+/// Returns true if the MaskConsumer holds a slot and its credentials
+/// haven't been in use for the ttl as of `now`. Pods that are using them
+/// right now may not have been seen yet, so they have to be checked too.
+pub fn is_idle(consumer: &MaskConsumer, ttl: Duration, now: DateTime<Utc>) -> bool {
+    let assigned = consumer
+        .status
+        .as_ref()
+        .is_some_and(|s| s.provider.is_some());
+    assigned
+        && consumer.metadata.deletion_timestamp.is_none()
+        && idle_since(consumer)
+            .and_then(|since| (now - since).to_std().ok())
+            .is_some_and(|idle| idle >= ttl)
+}
+
+/// Returns true if the idle MaskConsumer's ttl expired, i.e. no Pod that
+/// hasn't exited references its credentials Secret either.
+pub async fn is_expired(client: Client, consumer: &MaskConsumer) -> Result<bool, Error> {
+    let secret = match consumer.status.as_ref().and_then(|s| s.provider.as_ref()) {
+        Some(provider) => &provider.secret,
+        None => return Ok(false),
+    };
+    Ok(list_referencing_pods(client, consumer, secret)
+        .await?
+        .is_empty())
+}
+
+/// Releases the slots of the Mask whose ttl expired by deleting its
+/// MaskConsumers, remembering not to create another until its spec changes.
+/// With `ttlAction: Delete`, the Mask itself is deleted instead, which
+/// deletes the MaskConsumers before its finalizer is removed.
+pub async fn expire(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    instance: &Mask,
+) -> Result<(), Error> {
+    let options = instance.spec.options();
+    let ttl = options.ttl.unwrap_or_default();
+    let action = options.ttl_action.unwrap_or_default();
+    let note = messages::ttl_expired(&ttl, action);
+    events::publish(client.clone(), instance, "TtlExpired", "Expire", note).await;
+    if action == MaskTtlAction::Delete {
+        let api: Api<Mask> = Api::namespaced(client, namespace);
+        return match api.delete(name, &DeleteParams::default()).await {
+            Ok(_) => Ok(()),
+            // The Mask was deleted in the meantime.
+            Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(()),
+            Err(e) => Err(e).context_kind_name("Mask", name),
+        };
+    }
+    patch_status(client.clone(), instance, |status| {
+        status.set_phase(MaskPhase::Waiting, messages::ttl_released(&ttl));
+        status.expired_generation = instance.metadata.generation;
+    })
+    .await?;
+    let api: Api<MaskConsumer> = Api::namespaced(client, namespace);
+    for index in 0..slots(instance) {
+        let name = &consumer_name(instance, index);
+        match api.delete(name, &DeleteParams::default()).await {
+            Ok(_) => {}
+            // The MaskConsumer was deleted in the meantime.
+            Err(kube::Error::Api(ae)) if ae.code == 404 => {}
+            Err(e) => return Err(e).context_kind_name("MaskConsumer", name),
+        }
+    }
+    Ok(())
+}
@@ -1,23 +1,39 @@
-use kube::{client::Client, Api};
+use chrono::{DateTime, Utc};
+use kube::{api::ListParams, client::Client, Api, Resource, ResourceExt};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
 use vpn_types::*;
 
-use crate::util::Error;
+use crate::{
+    consumers::slots::{bounded_name, fnv1a, MAX_NAME_LEN},
+    util::{
+        list::list_all_paginated, Error, MASK_SLOT_LABEL, MASK_UID_LABEL, PROVIDER_UID_LABEL,
+        VERIFICATION_LABEL,
+    },
+};
 
-/// Returns the `MaskConsumer` resource that is managing provider assignment for the `Mask`.
+/// Returns the `MaskConsumer` resource that is managing provider assignment
+/// for the `Mask`'s first slot.
 pub async fn get_consumer(client: Client, instance: &Mask) -> Result<Option<MaskConsumer>, Error> {
-    let mask_name = instance.metadata.name.as_deref().unwrap();
+    get_slot_consumer(client, instance, 0).await
+}
+
+/// Returns the `MaskConsumer` resource that is managing provider assignment
+/// for the `Mask`'s slot with the given index.
+pub async fn get_slot_consumer(
+    client: Client,
+    instance: &Mask,
+    index: usize,
+) -> Result<Option<MaskConsumer>, Error> {
+    let name = consumer_name(instance, index);
     let mask_namespace = instance.metadata.namespace.as_deref().unwrap();
     let mask_uid = instance.metadata.uid.as_deref().unwrap();
     let mc_api: Api<MaskConsumer> = Api::namespaced(client, mask_namespace);
-    Ok(match mc_api.get(mask_name).await {
+    Ok(match mc_api.get(&name).await {
         // Ensure the MaskConsumer has an owner reference to the Mask.
-        Ok(mc)
-            if mc
-                .metadata
-                .owner_references
-                .as_ref()
-                .map_or(false, |o| o.iter().any(|r| r.uid == mask_uid)) =>
-        {
+        Ok(mc) if is_owned_by(&mc, mask_uid) => {
             // The MaskConsumer exists and the owner UID matches.
             Some(mc)
         }
@@ -31,3 +47,199 @@ pub async fn get_consumer(client: Client, instance: &Mask) -> Result<Option<Mask
         Err(e) => return Err(e.into()),
     })
 }
+
+/// Returns the MaskConsumers of every slot of the `Mask` but the first,
+/// in the order of their slots. They're listed by the label with the
+/// `Mask`'s UID, so the ones of slots it no longer requests are found too.
+pub async fn get_extra_consumers(
+    client: Client,
+    instance: &Mask,
+) -> Result<Vec<MaskConsumer>, Error> {
+    let mask_namespace = instance.metadata.namespace.as_deref().unwrap();
+    let mask_uid = instance.metadata.uid.as_deref().unwrap();
+    let mc_api: Api<MaskConsumer> = Api::namespaced(client, mask_namespace);
+    let lp = ListParams::default().labels(&format!("{}={}", MASK_UID_LABEL, mask_uid));
+    let mut consumers: Vec<(usize, MaskConsumer)> = list_all_paginated(&mc_api, &lp)
+        .await?
+        .into_iter()
+        .filter(|mc| is_owned_by(mc, mask_uid))
+        .filter_map(|mc| Some((slot_index(&mc)?, mc)))
+        .filter(|(index, _)| *index > 0)
+        .collect();
+    consumers.sort_by_key(|(index, _)| *index);
+    Ok(consumers.into_iter().map(|(_, mc)| mc).collect())
+}
+
+/// Returns the MaskConsumers left over from slots the `Mask` no longer
+/// requests, in the order of their slots.
+pub async fn get_surplus_consumers(
+    client: Client,
+    instance: &Mask,
+) -> Result<Vec<MaskConsumer>, Error> {
+    let slots = slots(instance).max(1);
+    Ok(get_extra_consumers(client, instance)
+        .await?
+        .into_iter()
+        .filter(|mc| slot_index(mc).is_some_and(|index| index >= slots))
+        .collect())
+}
+
+/// Returns true if the MaskConsumer has an owner reference to the `Mask`
+/// with the given UID.
+fn is_owned_by(consumer: &MaskConsumer, mask_uid: &str) -> bool {
+    consumer
+        .metadata
+        .owner_references
+        .as_ref()
+        .is_some_and(|o| o.iter().any(|r| r.uid == mask_uid))
+}
+
+/// Returns the index of the `Mask`'s slot reserved by the MaskConsumer,
+/// as found in its label. The first slot's MaskConsumer has no label.
+pub fn slot_index(consumer: &MaskConsumer) -> Option<usize> {
+    consumer.labels().get(MASK_SLOT_LABEL)?.parse().ok()
+}
+
+/// Returns the number of slots requested by the `Mask`.
+pub fn slots(instance: &Mask) -> usize {
+    instance.spec.options().slots.unwrap_or(1)
+}
+
+/// Returns the name of the MaskConsumer for the `Mask`'s slot with the
+/// given index. The first one is named after the Mask, so the `Mask`'s
+/// credentials Secret keeps its name no matter how many slots it has.
+/// The others get a hash of the `Mask`'s UID before their index, so they
+/// aren't named like the first MaskConsumer of a `Mask` such as `<mask>-1`.
+pub fn consumer_name(instance: &Mask, index: usize) -> String {
+    let name = instance.metadata.name.as_deref().unwrap();
+    match index {
+        0 => name.to_owned(),
+        _ => {
+            let uid = instance.metadata.uid.as_deref().unwrap();
+            let suffix = format!("-{:08x}-{}", fnv1a(uid), index);
+            bounded_name(name, &suffix, MAX_NAME_LEN)
+        }
+    }
+}
+
+/// Returns the labels of the MaskConsumer for the `Mask`'s slot with the
+/// given index: the [`consumer_labels`], plus the `Mask`'s UID and the
+/// index for every slot but the first, which they are listed by.
+pub fn slot_labels(instance: &Mask, index: usize) -> BTreeMap<String, String> {
+    let mut labels = consumer_labels(instance);
+    if index > 0 {
+        let uid = instance.metadata.uid.clone().unwrap();
+        labels.insert(MASK_UID_LABEL.to_owned(), uid);
+        labels.insert(MASK_SLOT_LABEL.to_owned(), index.to_string());
+    }
+    labels
+}
+
+/// Returns the slot most recently reserved for the Mask, if any.
+pub fn get_last_slot(instance: &Mask) -> Option<SlotAffinity> {
+    let status = instance.status.as_ref()?;
+    Some(SlotAffinity {
+        provider_uid: status.last_provider_uid.clone()?,
+        slot: status.last_slot?,
+    })
+}
+
+/// Returns the labels of the Mask's MaskConsumer. All of the Mask's labels
+/// are inherited, and they're authoritative: the MaskConsumer's labels are
+/// synchronized with them for as long as the Mask exists, so the
+/// `vpn.beebs.dev/*` labels the controllers rely on are re-applied if
+/// something like a mutating admission policy strips them. The verification
+/// label is restored from the Mask's controlling MaskProvider if the Mask
+/// itself lost it, as the verification Pod can't get credentials otherwise.
+pub fn consumer_labels(instance: &Mask) -> BTreeMap<String, String> {
+    let mut labels = instance.metadata.labels.clone().unwrap_or_default();
+    if !labels.contains_key(VERIFICATION_LABEL) {
+        if let Some(owner) = instance
+            .metadata
+            .owner_references
+            .as_ref()
+            .and_then(|o| o.iter().find(|r| r.controller == Some(true)))
+            .filter(|r| r.kind == MaskProvider::kind(&()))
+        {
+            labels.insert(VERIFICATION_LABEL.to_owned(), owner.uid.clone());
+        }
+    }
+    labels
+}
+
+/// Returns the purpose of the Mask's MaskConsumer. Only the Masks created
+/// by the MaskProviders controller carry the verification label.
+pub fn get_purpose(instance: &Mask) -> MaskConsumerPurpose {
+    match consumer_labels(instance).contains_key(VERIFICATION_LABEL) {
+        true => MaskConsumerPurpose::Verification,
+        false => MaskConsumerPurpose::Workload,
+    }
+}
+
+/// Returns true if the MaskReservation holds the slot for the Mask's
+/// MaskConsumer. Older reservations don't record their slot, in which
+/// case the slot is assumed to match.
+pub fn holds_slot(reservation: &MaskReservation, instance: &Mask, slot: usize) -> bool {
+    Some(reservation.spec.name.as_str()) == instance.metadata.name.as_deref()
+        && Some(reservation.spec.namespace.as_str()) == instance.metadata.namespace.as_deref()
+        && reservation.spec.slot.is_none_or(|s| s == slot)
+}
+
+/// Returns true if any of the slots reserved for the Mask is still held by
+/// a MaskReservation: the one most recently reserved for its first
+/// MaskConsumer, or one of those assigned to its slots as shown in its status.
+pub async fn is_any_slot_reserved(client: Client, instance: &Mask) -> Result<bool, Error> {
+    let last = get_last_slot(instance);
+    let assigned = instance
+        .status
+        .as_ref()
+        .and_then(|s| s.providers.clone())
+        .unwrap_or_default();
+    let provider_uids: BTreeSet<&str> = last
+        .iter()
+        .map(|l| l.provider_uid.as_str())
+        .chain(assigned.iter().map(|p| p.uid.as_str()))
+        .collect();
+    let api: Api<MaskReservation> = Api::all(client);
+    for provider_uid in provider_uids {
+        let lp = ListParams::default().labels(&format!("{}={}", PROVIDER_UID_LABEL, provider_uid));
+        let held = list_all_paginated(&api, &lp).await?.iter().any(|mr| {
+            let last = last
+                .as_ref()
+                .filter(|l| l.provider_uid == provider_uid)
+                .is_some_and(|l| holds_slot(mr, instance, l.slot));
+            last || assigned
+                .iter()
+                .any(|p| mr.metadata.uid.as_deref() == Some(p.reservation.as_str()))
+        });
+        if held {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Returns how long it took the `Mask` to become Active at `now`, measured
+/// from its creation. None if the creation timestamp is missing or later
+/// than `now`, which can happen with clock skew.
+pub fn time_to_active(instance: &Mask, now: DateTime<Utc>) -> Option<Duration> {
+    let created = instance.metadata.creation_timestamp.as_ref()?;
+    (now - created.0).to_std().ok()
+}
+
+/// Returns the labels that differ between the MaskConsumer and its Mask,
+/// mapped to the Mask's value, or to None if the Mask doesn't have them.
+pub fn label_changes(
+    consumer: &BTreeMap<String, String>,
+    mask: &BTreeMap<String, String>,
+) -> BTreeMap<String, Option<String>> {
+    let removed = consumer
+        .keys()
+        .filter(|key| !mask.contains_key(*key))
+        .map(|key| (key.clone(), None));
+    let changed = mask
+        .iter()
+        .filter(|(key, value)| consumer.get(*key) != Some(*value))
+        .map(|(key, value)| (key.clone(), Some(value.clone())));
+    removed.chain(changed).collect()
+}
@@ -7,23 +7,27 @@ use lazy_static::lazy_static;
 use prometheus::{labels, opts, register_counter, register_gauge, register_histogram_vec};
 use prometheus::{Counter, Encoder, Gauge, HistogramVec, TextEncoder};
 
-use crate::util::metrics::prefix;
+use crate::util::{
+    metric_names::{self, full_name},
+    metrics::prefix,
+    EXIT_UNAVAILABLE,
+};
 
 lazy_static! {
     static ref HTTP_COUNTER: Counter = register_counter!(opts!(
-        &format!("{}_http_requests_total", prefix()),
+        &full_name(&prefix(), metric_names::HTTP_REQUESTS_TOTAL),
         "Number of HTTP requests made to the metrics server.",
         labels! {"handler" => "all",}
     ))
     .unwrap();
     static ref HTTP_BODY_GAUGE: Gauge = register_gauge!(opts!(
-        &format!("{}_http_response_size_bytes", prefix()),
+        &full_name(&prefix(), metric_names::HTTP_RESPONSE_SIZE_BYTES),
         "Metrics server HTTP response sizes in bytes.",
         labels! {"handler" => "all",}
     ))
     .unwrap();
     static ref HTTP_REQ_HISTOGRAM: HistogramVec = register_histogram_vec!(
-        &format!("{}_http_request_duration_seconds", prefix()),
+        &full_name(&prefix(), metric_names::HTTP_REQUEST_DURATION_SECONDS),
         "Metrics server HTTP request latencies in seconds.",
         &["handler"]
     )
@@ -51,9 +55,19 @@ async fn serve_req(_req: Request<Body>) -> Result<Response<Body>, hyper::Error>
 /// Runs the prometheus metrics server on the given port.
 pub async fn run_server(port: u16) {
     let addr = ([0, 0, 0, 0], port).into();
+
+    // Failing to listen, e.g. because the port is already in use, is
+    // fatal. It's reported on a single line rather than as a panic.
+    let builder = match Server::try_bind(&addr) {
+        Ok(builder) => builder,
+        Err(err) => {
+            eprintln!("error: metrics server can't listen on {}: {}", addr, err);
+            std::process::exit(EXIT_UNAVAILABLE);
+        }
+    };
     println!("Metrics server listening on http://{}", addr);
 
-    let serve_future = Server::bind(&addr).serve(make_service_fn(|_| async {
+    let serve_future = builder.serve(make_service_fn(|_| async {
         Ok::<_, hyper::Error>(service_fn(serve_req))
     }));
 
@@ -1,294 +1,535 @@
-use crate::util::{deep_merge, messages, patch::*, Error, MANAGER_NAME, VERIFICATION_LABEL};
-use const_format::concatcp;
-use k8s_openapi::{
-    api::core::v1::{
-        Capabilities, Container, EnvVar, EnvVarSource, Pod, PodSpec, Secret, SecretKeySelector,
-        SecurityContext, Volume, VolumeMount,
+use super::{
+    capacity::{slots_summary, Capacity},
+    gluetun_version, namespaces, operation,
+    verify_defaults::cycle_verify,
+    verify_hash, verify_schedule, withdrawal,
+};
+use crate::{
+    consumers::actions::secret_name,
+    masks::util::consumer_labels,
+    util::{
+        clock, events, messages, patch::*, schedule::Availability, verified_provider_uid, Error,
+        ErrorContext, FORCE_DELETE_ANNOTATION, MANAGER_NAME, PROVIDER_SECRET_LABEL,
+        VERIFICATION_LABEL, VERIFY_NOW_ANNOTATION,
     },
-    apimachinery::pkg::apis::meta::v1::Time,
 };
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::{Pod, Secret};
 use kube::{
-    api::{Api, ObjectMeta, Resource},
+    api::{Api, DeleteParams, ObjectMeta, Patch, PatchParams, PostParams, Preconditions, Resource},
     Client,
 };
-use lazy_static::lazy_static;
-use serde_json::Value;
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, time::Duration};
+use vpn_render::{render_verify_pod, RenderNames};
 use vpn_types::*;
 
-/// Image to use for the curl container. This is used to
-/// retrieve the initial/unmasked IP address for the pod
-/// during initialization.
-pub const CURL_IMAGE: &str = "curlimages/curl:7.88.1";
-
-/// The IP service to use for getting the public IP address.
-pub const IP_SERVICE: &str = "https://api.ipify.org";
-
-/// Name of the shared volume, used to share files between
-/// containers and detect when the VPN connected. Containers
-/// should mount this volume at `SHARED_PATH` and access
-/// the initial ip file at `IP_FILE_PATH` to know when the
-/// VPN finishes connecting.
-pub const SHARED_VOLUME_NAME: &str = "shared";
-
-/// Shared directory path.
-pub const SHARED_PATH: &str = "/shared";
-
-/// The file containing the unmasked IP address of the pod.
-/// This is written by an init container so the executor
-/// knows when the VPN is connected.
-pub const IP_FILE_PATH: &str = concatcp!(SHARED_PATH, "/ip");
-
-/// VPN sidecar image. Efforts were made to use a stock
-/// image with no modifications, as to maximize the
-/// modular paradigm of using sidecars.
-pub const DEFAULT_VPN_IMAGE: &str = "qmcgaw/gluetun:v3.32.0";
-
-/// The name of the probe container within the verify pod.
-pub const PROBE_CONTAINER_NAME: &str = "probe";
-
-/// The name of the probe container within the verify pod.
-pub const VPN_CONTAINER_NAME: &str = "vpn";
-
-/// The script used by the probe container to check if the
-/// VPN is connected. Requires the environment variables.
-const PROBE_SCRIPT: &str = "#!/bin/sh
-INITIAL_IP=$(cat $IP_FILE_PATH) # created by init container
-echo \"Unmasked IP address is $INITIAL_IP\"
-INITIAL_WAIT=6s
-echo \"Waiting for $INITIAL_WAIT to allow the VPN container time to connect...\"
-sleep $INITIAL_WAIT
-TIMEOUT=5 # IP service request timeout (seconds)
-IP=$(curl -m $TIMEOUT -s $IP_SERVICE)
-ITER=0
-# Continue probing the IP service if it fails while the
-# VPN is connecting or returns the initial IP address.
-while [ $? -ne 0 ] || [ \"$IP\" = \"$INITIAL_IP\" ]; do
-    echo \"Current IP address is $IP, sleeping for $SLEEP_TIME\"
-    sleep $SLEEP_TIME
-    IP=$(curl -m $TIMEOUT -s $IP_SERVICE)
-    # exponential backoff
-    TIMEOUT=$((TIMEOUT + ITER))
-    SLEEP_TIME=$((SLEEP_TIME + ITER))
-    ITER=$((ITER + 1))
-done
-echo \"VPN connected. Masked IP address: $IP\"";
-
-lazy_static! {
-    static ref SHARED_VOLUME_MOUNT: VolumeMount = VolumeMount {
-        name: SHARED_VOLUME_NAME.to_owned(),
-        mount_path: SHARED_PATH.to_owned(),
-        ..Default::default()
-    };
-    static ref DEFAULT_INIT_CONTAINER: Container = Container {
-        name: "init".to_owned(),
-        image: Some(CURL_IMAGE.to_owned()),
-        image_pull_policy: Some("IfNotPresent".to_owned()),
-        command: Some(
-            vec!["curl", "-o", IP_FILE_PATH, "-s", IP_SERVICE]
-                .into_iter()
-                .map(String::from)
-                .collect()
-        ),
-        volume_mounts: Some(vec![SHARED_VOLUME_MOUNT.clone()]),
-        ..Default::default()
-    };
-    static ref DEFAULT_VPN_CONTAINER: Container = Container {
-        name: VPN_CONTAINER_NAME.to_owned(),
-        image: Some(DEFAULT_VPN_IMAGE.to_owned()),
-        image_pull_policy: Some("IfNotPresent".to_owned()),
-        security_context: Some(SecurityContext {
-            capabilities: Some(Capabilities {
-                add: Some(vec!["NET_ADMIN".to_owned()]),
-                ..Default::default()
-            }),
-            ..Default::default()
-        }),
-        ..Default::default()
-    };
-    static ref DEFAULT_PROBE_CONTAINER: Container = Container {
-        name: PROBE_CONTAINER_NAME.to_owned(),
-        image: Some(CURL_IMAGE.to_owned()),
-        image_pull_policy: Some("IfNotPresent".to_owned()),
-        command: Some(
-            vec!["sh", "-c", "echo \"$PROBE_SCRIPT\" | sh -"]
-                .into_iter()
-                .map(String::from)
-                .collect()
-        ),
-        env: Some(vec![
-            EnvVar {
-                name: "PROBE_SCRIPT".to_owned(),
-                value: Some(PROBE_SCRIPT.to_owned()),
-                ..Default::default()
-            },
-            EnvVar {
-                name: "IP_SERVICE".to_owned(),
-                value: Some(IP_SERVICE.to_owned()),
-                ..Default::default()
-            },
-            EnvVar {
-                name: "IP_FILE_PATH".to_owned(),
-                value: Some(IP_FILE_PATH.to_owned()),
-                ..Default::default()
-            },
-            EnvVar {
-                name: "SLEEP_TIME".to_owned(),
-                value: Some("10s".to_owned()),
-                ..Default::default()
-            },
-        ]),
-        volume_mounts: Some(vec![SHARED_VOLUME_MOUNT.clone()]),
-        ..Default::default()
-    };
-}
-
 /// Updates the MaskProvider's phase to Pending, which indicates
 /// the resource made its initial appearance to the operator.
 pub async fn pending(client: Client, instance: &MaskProvider) -> Result<(), Error> {
     patch_status(client, instance, |status| {
-        status.message = Some(messages::PENDING.to_owned());
-        status.phase = Some(MaskProviderPhase::Pending);
+        status.set_phase(MaskProviderPhase::Pending, messages::PENDING);
     })
     .await?;
     Ok(())
 }
 
 /// Updates the MaskProvider's phase to Ready, which indicates
-/// the VPN provider is ready to use.
-pub async fn ready(client: Client, instance: &MaskProvider) -> Result<(), Error> {
+/// the VPN provider is ready to use. `next_verification` is when
+/// its credentials are next verified, if periodically.
+pub async fn ready(
+    client: Client,
+    instance: &MaskProvider,
+    capacity: Capacity,
+    warnings: Vec<String>,
+    account: Option<AccountUtilization>,
+    availability: Option<Availability>,
+    next_verification: Option<String>,
+) -> Result<(), Error> {
+    warn_namespaces(client.clone(), instance, &warnings).await;
     patch_status(client, instance, |status| {
-        status.message = Some("VPN service is ready to use.".to_owned());
-        status.phase = Some(MaskProviderPhase::Ready);
-        status.active_slots = Some(0);
+        let message = keys_only_note(status, "VPN service is ready to use.".to_owned());
+        status.set_active_slots(0, message, warnings);
+        set_capacity(status, capacity, instance.spec.max_slots);
+        status.recent_assignments = None;
+        status.account = account;
+        set_availability(status, availability);
+        status.next_verification = next_verification;
+        // Nothing is left to drain.
+        status.operation = None;
     })
     .await?;
     Ok(())
 }
 
 /// Updates the MaskProvider's phase to Active, which indicates
-/// the VPN provider is in use by one or more pods.
+/// the VPN provider is in use by one or more pods. `operation` is the
+/// drain that is still unassigning them, if any.
+#[allow(clippy::too_many_arguments)]
 pub async fn active(
     client: Client,
     instance: &MaskProvider,
     active_slots: usize,
+    capacity: Capacity,
+    recent_assignments: Vec<RecentAssignment>,
+    warnings: Vec<String>,
+    account: Option<AccountUtilization>,
+    availability: Option<Availability>,
+    operation: Option<MaskProviderOperation>,
+    next_verification: Option<String>,
 ) -> Result<(), Error> {
+    warn_namespaces(client.clone(), instance, &warnings).await;
     patch_status(client, instance, |status| {
-        status.message = Some(format!("VPN service is in use by {} Masks.", active_slots));
-        status.phase = Some(MaskProviderPhase::Active);
-        status.active_slots = Some(active_slots);
+        let message = format!("VPN service is in use by {} Masks.", active_slots);
+        let message = keys_only_note(status, message);
+        status.set_active_slots(active_slots, message, warnings);
+        set_capacity(status, capacity, instance.spec.max_slots);
+        status.recent_assignments = Some(recent_assignments).filter(|r| !r.is_empty());
+        status.account = account;
+        set_availability(status, availability);
+        status.operation = operation;
+        status.next_verification = next_verification;
     })
     .await?;
     Ok(())
 }
 
+/// Returns the message with a note that only static checks ran, if the
+/// credentials were last verified with `verify.mode: KeysOnly`.
+fn keys_only_note(status: &MaskProviderStatus, message: String) -> String {
+    match status.last_verified_mode {
+        Some(MaskProviderVerifyMode::KeysOnly) => {
+            format!("{} {}", message, messages::KEYS_ONLY_NOTE)
+        }
+        _ => message,
+    }
+}
+
+/// Records the capacity planning figures of the MaskProvider, along with
+/// the free slots out of `max_slots` for `kubectl get`.
+fn set_capacity(status: &mut MaskProviderStatus, capacity: Capacity, max_slots: usize) {
+    status.available_slots = Some(capacity.available_slots);
+    status.slots_summary = Some(slots_summary(capacity.available_slots, max_slots));
+    status.pending_demand = Some(capacity.pending_demand);
+}
+
+/// Records whether the MaskProvider is outside of its availability hours.
+fn set_availability(status: &mut MaskProviderStatus, availability: Option<Availability>) {
+    status.out_of_hours = availability.as_ref().map(|a| a.out_of_hours);
+    status.availability_message = availability.map(|a| a.message);
+}
+
+/// Publishes Warning events if the MaskProvider's warnings have changed
+/// and are not empty, so misconfigured allowlists, secret keys that
+/// don't match the gluetun version and missing PriorityClasses show up
+/// in the events.
+async fn warn_namespaces(client: Client, instance: &MaskProvider, warnings: &[String]) {
+    if warnings.is_empty() || !namespaces::warnings_changed(instance, warnings) {
+        return;
+    }
+    let compat = gluetun_version::compatibility_warnings(instance);
+    let (compat, others): (Vec<&String>, Vec<&String>) =
+        warnings.iter().partition(|w| compat.contains(w));
+    let (priority, unknown): (Vec<&String>, Vec<&String>) = others
+        .into_iter()
+        .partition(|w| w.starts_with(messages::PRIORITY_CLASS_FIELD));
+    for (reason, warnings) in [
+        ("UnknownNamespaces", unknown),
+        ("RenamedSecretKeys", compat),
+        ("UnknownPriorityClass", priority),
+    ] {
+        if warnings.is_empty() {
+            continue;
+        }
+        let note = warnings
+            .into_iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ");
+        events::warn(client.clone(), instance, reason, "Validate", note).await;
+    }
+}
+
 /// Updates the `MaskProvider`'s phase to Terminating.
 pub async fn terminating(client: Client, instance: &MaskProvider) -> Result<(), Error> {
     patch_status(client, instance, |status| {
-        status.phase = Some(MaskProviderPhase::Terminating);
-        status.message = Some(messages::TERMINATING.to_owned());
+        status.set_phase(MaskProviderPhase::Terminating, messages::TERMINATING);
     })
     .await?;
     Ok(())
 }
 
+/// Returns true if the MaskProvider's force-delete annotation is `"true"`.
+pub fn force_delete_requested(instance: &MaskProvider) -> bool {
+    instance
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|a| a.get(FORCE_DELETE_ANNOTATION))
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("true"))
+}
+
+/// Returns the MaskConsumers the reservations are for, as `namespace/name`.
+pub fn assigned_consumers(reservations: &[MaskReservation]) -> Vec<String> {
+    let mut consumers: Vec<String> = reservations
+        .iter()
+        .map(|r| format!("{}/{}", r.spec.namespace, r.spec.name))
+        .collect();
+    consumers.sort();
+    consumers
+}
+
+/// Updates the MaskProvider's phase to Terminating with a message naming
+/// the MaskConsumers that block its deletion, and publishes a Warning event
+/// so the blocked deletion shows up alongside the delete request.
+pub async fn deletion_blocked(
+    client: Client,
+    instance: &MaskProvider,
+    message: String,
+) -> Result<(), Error> {
+    patch_status(client.clone(), instance, |status| {
+        status.set_phase(MaskProviderPhase::Terminating, message.clone());
+    })
+    .await?;
+    events::warn(client, instance, "DeletionBlocked", "Delete", message).await;
+    Ok(())
+}
+
+/// Deletes the MaskProvider's reservations so their MaskConsumers are
+/// unassigned before the MaskProvider itself is deleted. Reservations
+/// that are already being deleted are skipped. With `reportWithdrawal`,
+/// the Masks are told about it first, while their MaskConsumers can
+/// still be resolved. If telling one fails, the unassignment is retried
+/// with the next reconciliation, which skips the Masks already told.
+/// Returns how many of the reservations are left.
+pub async fn unassign_all(
+    client: Client,
+    instance: &MaskProvider,
+    reservations: &[MaskReservation],
+) -> Result<usize, Error> {
+    let pending = pending_reservations(reservations);
+    if pending.is_empty() {
+        return Ok(reservations.len());
+    }
+    let note = format!(
+        "Deletion forced with {}=true, unassigning {} MaskConsumer(s).",
+        FORCE_DELETE_ANNOTATION,
+        pending.len(),
+    );
+    events::warn(client.clone(), instance, "ForceDelete", "Delete", note).await;
+    if instance.spec.report_withdrawal == Some(true) {
+        withdrawal::report(client.clone(), instance, &pending).await?;
+    }
+    // The MaskConsumers go first, so none of them copies the credentials
+    // again between its copy being deleted and noticing the unassignment.
+    delete_consumers(client.clone(), &pending).await?;
+    let deleted = delete_reservations(client, pending).await?;
+    Ok(reservations.len() - deleted)
+}
+
+/// Deletes the MaskProvider's reservations because it's outside of its
+/// availability hours and `drainOutOfHours` is set. Reservations that
+/// are already being deleted are skipped. Returns how many of the
+/// reservations are left.
+pub async fn drain_out_of_hours(
+    client: Client,
+    instance: &MaskProvider,
+    reservations: &[MaskReservation],
+) -> Result<usize, Error> {
+    let pending = pending_reservations(reservations);
+    if pending.is_empty() {
+        return Ok(reservations.len());
+    }
+    let note = format!(
+        "Outside of availability hours, unassigning {} MaskConsumer(s).",
+        pending.len(),
+    );
+    events::publish(client.clone(), instance, "DrainOutOfHours", "Drain", note).await;
+    let deleted = delete_reservations(client, pending).await?;
+    Ok(reservations.len() - deleted)
+}
+
+/// Records the progress of the operation the MaskProvider is going
+/// through, unless the status already shows it.
+pub async fn record_operation(
+    client: Client,
+    instance: &MaskProvider,
+    operation: Option<MaskProviderOperation>,
+) -> Result<(), Error> {
+    if operation::is_current(instance, operation.as_ref()) {
+        return Ok(());
+    }
+    patch_status(client, instance, move |status| {
+        status.operation = operation;
+    })
+    .await?;
+    Ok(())
+}
+
+/// Returns the reservations that aren't already being deleted.
+pub fn pending_reservations(reservations: &[MaskReservation]) -> Vec<&MaskReservation> {
+    reservations
+        .iter()
+        .filter(|r| r.metadata.deletion_timestamp.is_none())
+        .collect()
+}
+
+/// Deletes the reservations, unassigning their MaskConsumers.
+/// Deletes the MaskConsumers holding the reservations. The uid
+/// precondition keeps a MaskConsumer that replaced one of them.
+async fn delete_consumers(client: Client, reservations: &[&MaskReservation]) -> Result<(), Error> {
+    for reservation in reservations {
+        let api: Api<MaskConsumer> = Api::namespaced(client.clone(), &reservation.spec.namespace);
+        let params = DeleteParams {
+            preconditions: Some(Preconditions {
+                uid: Some(reservation.spec.uid.clone()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        match api.delete(&reservation.spec.name, &params).await {
+            Ok(_) => {}
+            // Already gone or replaced.
+            Err(kube::Error::Api(e)) if e.code == 404 || e.code == 409 => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the reservations, returning how many of them are gone. Any
+/// still held by a finalizer are being deleted, but not yet gone.
+async fn delete_reservations(
+    client: Client,
+    reservations: Vec<&MaskReservation>,
+) -> Result<usize, Error> {
+    let mut deleted = 0;
+    for reservation in reservations {
+        let api: Api<MaskReservation> = Api::namespaced(
+            client.clone(),
+            reservation.metadata.namespace.as_deref().unwrap(),
+        );
+        match api
+            .delete(
+                reservation.metadata.name.as_deref().unwrap(),
+                &Default::default(),
+            )
+            .await
+        {
+            Ok(gone) if gone.is_right() => deleted += 1,
+            Ok(_) => {}
+            Err(kube::Error::Api(e)) if e.code == 404 => deleted += 1,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(deleted)
+}
+
 /// Updates the MaskProvider's phase to ErrSecretNotFound, which indicates
 /// the VPN provider is ready to use.
 pub async fn secret_not_found(client: Client, instance: &MaskProvider) -> Result<(), Error> {
     let message = format!("Secret '{}' does not exist.", instance.spec.secret);
     patch_status(client, instance, |status| {
-        status.message = Some(message);
-        status.phase = Some(MaskProviderPhase::ErrSecretNotFound);
+        status.set_phase(MaskProviderPhase::ErrSecretNotFound, message);
     })
     .await?;
     Ok(())
 }
 
-/// Update the status object to show the verification is in progress.
-pub async fn verify_progress(
+/// Updates the MaskProvider's phase to ErrSecretSuffixCollision, which
+/// indicates another MaskProvider already uses its stable secret suffix.
+pub async fn secret_suffix_collision(
     client: Client,
     instance: &MaskProvider,
-    _start_time: Option<Time>,
-    message: String,
+    other: &MaskProvider,
 ) -> Result<(), Error> {
+    let message = messages::secret_suffix_collision(
+        instance
+            .spec
+            .stable_secret_suffix
+            .as_deref()
+            .unwrap_or_default(),
+        other.metadata.namespace.as_deref().unwrap_or_default(),
+        other.metadata.name.as_deref().unwrap_or_default(),
+    );
     patch_status(client, instance, |status| {
-        status.message = Some(message);
-        status.phase = Some(MaskProviderPhase::Verifying);
+        status.set_phase(MaskProviderPhase::ErrSecretSuffixCollision, message);
     })
     .await?;
     Ok(())
 }
 
-/// Update the status object to show an error message was
-/// encountered during verification.
-pub async fn verify_failed(
+/// Updates the MaskProvider's phase to ErrAccountNotFound, which indicates
+/// the VpnAccount it shares its connections with doesn't exist.
+pub async fn account_not_found(
+    client: Client,
+    instance: &MaskProvider,
+    account: &str,
+) -> Result<(), Error> {
+    let message = messages::account_not_found(account);
+    patch_status(client, instance, |status| {
+        status.set_phase(MaskProviderPhase::ErrAccountNotFound, message);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Updates the MaskProvider's phase to ErrConfig, which indicates one
+/// of its transformations can't be applied to the credentials.
+pub async fn config_error(
     client: Client,
     instance: &MaskProvider,
     message: String,
 ) -> Result<(), Error> {
     patch_status(client, instance, |status| {
-        status.message = Some(message);
-        status.phase = Some(MaskProviderPhase::ErrVerifyFailed);
+        status.set_phase(MaskProviderPhase::ErrConfig, message);
     })
     .await?;
     Ok(())
 }
 
-/// Merges the container spec with the given overrides.
-fn merge_containers(container: Container, overrides: Value) -> Result<Container, Error> {
-    let mut val = serde_json::to_value(&container)?;
-    deep_merge(&mut val, overrides);
-    Ok(serde_json::from_value(val)?)
+/// Labels the MaskProvider's credentials Secret so the controller
+/// watches it for changes.
+pub async fn label_secret(
+    client: Client,
+    namespace: &str,
+    instance: &MaskProvider,
+) -> Result<(), Error> {
+    let name = &instance.spec.secret;
+    let api: Api<Secret> = Api::namespaced(client, namespace);
+    let patch = serde_json::json!({
+        "metadata": {
+            "labels": {
+                PROVIDER_SECRET_LABEL: "true",
+            },
+        },
+    });
+    api.patch(name, &PatchParams::default(), &Patch::Merge(&patch))
+        .await
+        .context_kind_name("Secret", name)?;
+    Ok(())
+}
+
+/// Records the checksum of the credentials copied for Masks, which
+/// the copies are checked against.
+pub async fn record_secret_hash(
+    client: Client,
+    instance: &MaskProvider,
+    hash: String,
+) -> Result<(), Error> {
+    patch_status(client, instance, move |status| {
+        status.secret_hash = Some(hash);
+    })
+    .await?;
+    Ok(())
 }
 
-/// Creates the container spec for the init container that
-/// retrieves the unmasked public IP address and writes it
-/// to the shared volume. This is done on startup so that
-/// the executor will truly know when it's okay to start
-/// downloading the video and/or thumbnail.
-fn get_init_container(overrides: Option<&Value>) -> Result<Container, Error> {
-    let container = DEFAULT_INIT_CONTAINER.clone();
-    match overrides {
-        Some(overrides) => merge_containers(container, overrides.clone()),
-        None => Ok(container),
+/// Writes the Secret with the MaskProvider's transformed credentials,
+/// replacing the previous version if there is one.
+pub async fn write_derived_secret(
+    client: Client,
+    namespace: &str,
+    mut secret: Secret,
+) -> Result<(), Error> {
+    let name = secret.metadata.name.clone().unwrap();
+    let api: Api<Secret> = Api::namespaced(client, namespace);
+    match api.get(&name).await {
+        Ok(existing) => {
+            secret.metadata.resource_version = existing.metadata.resource_version;
+            api.replace(&name, &PostParams::default(), &secret)
+                .await
+                .context_kind_name("Secret", &name)?;
+        }
+        Err(kube::Error::Api(ae)) if ae.code == 404 => {
+            api.create(&PostParams::default(), &secret)
+                .await
+                .context_kind_name("Secret", &name)?;
+        }
+        Err(e) => return Err(e).context_kind_name("Secret", &name),
     }
+    Ok(())
 }
 
-/// Returns the container the probes the external IP address
-/// and exits with code zero when it changes or exits nonzero
-/// if it fails to change before the timeout.
-fn get_probe_container(overrides: Option<&Value>) -> Result<Container, Error> {
-    let container = DEFAULT_PROBE_CONTAINER.clone();
-    match overrides {
-        Some(overrides) => merge_containers(container, overrides.clone()),
-        None => Ok(container),
-    }
+/// Update the status object to show the verification is in progress,
+/// having reached the step of `operation`. The operation is None while
+/// the verification waits in the queue, as it hasn't begun yet.
+pub async fn verify_progress(
+    client: Client,
+    instance: &MaskProvider,
+    operation: Option<MaskProviderOperation>,
+    message: String,
+) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.operation = operation;
+        status.set_phase(MaskProviderPhase::Verifying, message);
+    })
+    .await?;
+    Ok(())
 }
 
-/// Returns the container that connects to the VPN.
-fn get_vpn_container(secret: &Secret, overrides: Option<&Value>) -> Result<Container, Error> {
-    let secret_name = secret.metadata.name.as_deref().unwrap();
-    let mut container = DEFAULT_VPN_CONTAINER.clone();
-    container.env = secret.data.as_ref().map(|data| {
-        data.iter()
-            .map(|(key, _)| EnvVar {
-                name: key.clone(),
-                value_from: Some(EnvVarSource {
-                    secret_key_ref: Some(SecretKeySelector {
-                        name: Some(secret_name.to_owned()),
-                        key: key.clone(),
-                        ..Default::default()
-                    }),
-                    ..Default::default()
-                }),
-                ..Default::default()
-            })
-            .collect()
-    });
-    match overrides {
-        Some(overrides) => merge_containers(container, overrides.clone()),
-        None => Ok(container),
+/// Update the status object to show a verification cycle began
+/// with the effective verification settings.
+pub async fn verify_started(
+    client: Client,
+    instance: &MaskProvider,
+    verify: Option<MaskProviderVerifySpec>,
+    operation: MaskProviderOperation,
+    message: String,
+) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.effective_verify = verify;
+        status.operation = Some(operation);
+        status.set_phase(MaskProviderPhase::Verifying, message);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Update the status object to show an error message was
+/// encountered during verification.
+pub async fn verify_failed(
+    client: Client,
+    instance: &MaskProvider,
+    message: String,
+    verify_now: Option<String>,
+) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.set_phase(MaskProviderPhase::ErrVerifyFailed, message);
+        status.operation = None;
+        // A failed cycle still satisfies a manual trigger.
+        status.set_manual_verify(verify_now);
+    })
+    .await?;
+    Ok(())
+}
+
+/// Returns the message failing verification if the verification Mask has
+/// been Waiting for longer than `timeout` since it was created, or None if
+/// it may keep waiting. If its MaskConsumer is recognized as verifying the
+/// MaskProvider, it's only waiting for a slot of a full MaskProvider to be
+/// released, which can take any amount of time. Otherwise, waiting that
+/// long means the MaskConsumer is missing or isn't recognized as verifying
+/// the MaskProvider.
+pub fn verify_mask_stalled(
+    mask: &Mask,
+    consumer: Option<&MaskConsumer>,
+    timeout: Duration,
+    now: DateTime<Utc>,
+) -> Option<String> {
+    let verifying = consumer_labels(mask).remove(VERIFICATION_LABEL);
+    if verifying.is_some() && consumer.and_then(verified_provider_uid) == verifying.as_deref() {
+        return None;
+    }
+    let created = mask.metadata.creation_timestamp.as_ref()?;
+    let waited = (now - created.0).to_std().ok()?;
+    if waited <= timeout {
+        return None;
     }
+    Some(messages::verify_mask_stalled(
+        mask.metadata.namespace.as_deref().unwrap_or_default(),
+        mask.metadata.name.as_deref().unwrap_or_default(),
+        &timeout,
+        mask.status.as_ref().and_then(|s| s.message.as_deref()),
+    ))
 }
 
 /// Returns the name of the Mask resource used to reserve
@@ -321,6 +562,12 @@ fn verify_mask(name: &str, namespace: &str, instance: &MaskProvider) -> Mask {
             name: Some(get_verify_mask_name(name)),
             namespace: Some(namespace.to_owned()),
             labels: Some(verify_mask_labels(instance)),
+            // Remember which manual trigger, if any, this cycle satisfies.
+            annotations: verify_now(instance).map(|value| {
+                let mut annotations = BTreeMap::new();
+                annotations.insert(VERIFY_NOW_ANNOTATION.to_owned(), value.to_owned());
+                annotations
+            }),
             owner_references: Some(vec![instance.controller_owner_ref(&()).unwrap()]),
             ..Default::default()
         },
@@ -334,82 +581,198 @@ fn verify_mask(name: &str, namespace: &str, instance: &MaskProvider) -> Mask {
 }
 
 /// Returns a Pod resource that verifies the VPN credentials work.
-fn verify_pod(
+pub fn verify_pod(
     name: &str,
     namespace: &str,
     instance: &MaskProvider,
     secret: &Secret,
     consumer: &MaskConsumer,
 ) -> Result<Pod, Error> {
-    let overrides = instance
-        .spec
-        .verify
-        .as_ref()
-        .map_or(None, |v| v.overrides.as_ref());
-    let container_overrides = overrides.map_or(None, |o| o.containers.as_ref());
-
-    // Assemble the container specs with the overrides.
-    let init_container = get_init_container(container_overrides.map_or(None, |c| c.init.as_ref()))?;
-    let vpn_container =
-        get_vpn_container(secret, container_overrides.map_or(None, |c| c.vpn.as_ref()))?;
-    let probe_container =
-        get_probe_container(container_overrides.map_or(None, |c| c.probe.as_ref()))?;
-
-    // Assemble the containers into a pod.
-    let pod = Pod {
-        metadata: ObjectMeta {
-            name: Some(name.to_owned()),
-            namespace: Some(namespace.to_owned()),
-            labels: Some({
-                // Add a label to the pod so that we can easily find it.
-                let mut labels: BTreeMap<String, String> = BTreeMap::new();
-                labels.insert("app".to_owned(), MANAGER_NAME.to_owned());
-                labels
-            }),
-            // Setting the MaskConsumer as the owner will allow the
-            // pod to be properly garbage collected when the provider
-            // is unassigned from the Mask.
-            owner_references: Some(vec![consumer.controller_owner_ref(&()).unwrap()]),
-            ..Default::default()
-        },
-        spec: Some(PodSpec {
-            restart_policy: Some("Never".to_owned()),
-            init_containers: Some(vec![init_container]),
-            containers: vec![vpn_container, probe_container],
-            volumes: Some(vec![Volume {
-                name: SHARED_VOLUME_NAME.to_owned(),
-                empty_dir: Some(Default::default()),
-                ..Default::default()
-            }]),
-            ..Default::default()
-        }),
-        ..Default::default()
+    // Inject every key of the credentials Secret into the VPN container.
+    let secret_keys: Vec<String> = secret
+        .data
+        .iter()
+        .flatten()
+        .map(|(k, _)| k.clone())
+        .collect();
+    let names = RenderNames {
+        pod: name.to_owned(),
+        namespace: namespace.to_owned(),
+        secret: secret.metadata.name.clone().unwrap(),
+        provider_uid: instance.metadata.uid.clone().unwrap(),
+        owner: consumer.controller_owner_ref(&()),
     };
+    // Render the Pod with the verification settings the cycle began with.
+    let spec = MaskProviderSpec {
+        verify: cycle_verify(instance).cloned(),
+        ..instance.spec.clone()
+    };
+    Ok(render_verify_pod(&spec, &secret_keys, names)?)
+}
 
-    // Apply overrides to the pod if necessary.
-    match overrides.map_or(None, |o| o.pod.as_ref()) {
-        // Merge the overriden values into the resource.
-        Some(pod_template) => {
-            let mut val = serde_json::to_value(&pod)?;
-            deep_merge(&mut val, pod_template.clone());
-            Ok(serde_json::from_value(val)?)
-        }
-        // No pod override requested.
-        _ => Ok(pod),
-    }
+/// Returns the Pod that the MaskProvider's next verification would create
+/// with the effective verification settings, for the verify-pod preview.
+/// It's rendered as [`verify_pod`] does, except the verification
+/// MaskConsumer doesn't exist yet, so the Pod has no owner.
+pub fn preview_verify_pod(
+    name: &str,
+    namespace: &str,
+    instance: &MaskProvider,
+    verify: Option<&MaskProviderVerifySpec>,
+    secret: &Secret,
+) -> Result<Pod, Error> {
+    let secret_keys: Vec<String> = secret
+        .data
+        .iter()
+        .flatten()
+        .map(|(k, _)| k.clone())
+        .collect();
+    let names = RenderNames {
+        pod: name.to_owned(),
+        namespace: namespace.to_owned(),
+        // The name the verification MaskConsumer's copy will have.
+        secret: secret_name(&get_verify_mask_name(name), instance),
+        provider_uid: instance.metadata.uid.clone().unwrap(),
+        owner: None,
+    };
+    let spec = MaskProviderSpec {
+        verify: verify.cloned(),
+        ..instance.spec.clone()
+    };
+    Ok(render_verify_pod(&spec, &secret_keys, names)?)
+}
+
+/// Signals that the VPN credentials are verified. The probe's `note`, if
+/// any, is shown after the message.
+pub async fn verified(
+    client: Client,
+    instance: &MaskProvider,
+    verify_now: Option<String>,
+    node: Option<String>,
+    zone: Option<String>,
+    note: Option<String>,
+) -> Result<(), Error> {
+    let verification = Verification::now(instance, cycle_verify(instance))?;
+    let message = match note {
+        Some(note) => messages::verified_with_note(&note),
+        None => messages::VERIFIED.to_owned(),
+    };
+    patch_status(client, instance, |status| {
+        verification.record(status);
+        status.last_verified_node = node;
+        status.last_verified_zone = zone;
+        // Record the manual trigger so it isn't repeated.
+        status.set_manual_verify(verify_now);
+        status.set_phase(MaskProviderPhase::Verified, message);
+    })
+    .await?;
+    Ok(())
 }
 
-/// Signals that the VPN credentials are verified.
-pub async fn verified(client: Client, instance: &MaskProvider) -> Result<(), Error> {
+/// Updates the status to show the credentials Secret passed the static
+/// checks of `verify.mode: KeysOnly` under the settings `verify`, which
+/// are recorded as the cycle's. Nothing was dialed, so there's no node
+/// or zone the credentials were verified from.
+pub async fn keys_verified(
+    client: Client,
+    instance: &MaskProvider,
+    verify: Option<MaskProviderVerifySpec>,
+    verify_now: Option<String>,
+) -> Result<(), Error> {
+    let verification = Verification::now(instance, verify.as_ref())?;
     patch_status(client, instance, |status| {
-        status.last_verified = Some(chrono::Utc::now().to_rfc3339());
-        status.phase = Some(MaskProviderPhase::Verified);
-        status.message = Some("VPN credentials verified as authentic.".to_owned())
+        verification.record(status);
+        status.effective_verify = verify;
+        status.last_verified_node = None;
+        status.last_verified_zone = None;
+        // Record the manual trigger so it isn't repeated.
+        status.set_manual_verify(verify_now);
+        status.set_phase(MaskProviderPhase::Verified, messages::KEYS_VERIFIED);
     })
     .await?;
     Ok(())
 }
 
+/// Timestamps and checksum recorded when the credentials pass verification.
+struct Verification {
+    last_verified: String,
+    next_verification: Option<String>,
+    config_hash: String,
+    mode: MaskProviderVerifyMode,
+}
+
+impl Verification {
+    /// Returns what's recorded for credentials verified just now under
+    /// the settings.
+    fn now(
+        instance: &MaskProvider,
+        verify: Option<&MaskProviderVerifySpec>,
+    ) -> Result<Self, Error> {
+        // Remember what was verified, so any change to it is verified again.
+        let config_hash = verify_hash::config_hash(instance, verify)?;
+        // The next one is due the interval after the recorded time, plus the
+        // MaskProvider's offset within the spread window.
+        let last_verified = clock::now_k8s();
+        let next_verification =
+            verify_schedule::next_after(instance, verify, clock::parse_timestamp(&last_verified)?)?;
+        Ok(Self {
+            last_verified,
+            next_verification: next_verification.map(clock::format_timestamp),
+            config_hash,
+            mode: verify.and_then(|v| v.mode).unwrap_or_default(),
+        })
+    }
+
+    /// Records the verification in the status, ending the cycle.
+    fn record(&self, status: &mut MaskProviderStatus) {
+        status.last_verified = Some(self.last_verified.clone());
+        status.next_verification = self.next_verification.clone();
+        status.last_verified_config_hash = Some(self.config_hash.clone());
+        status.last_verified_mode = Some(self.mode);
+        status.operation = None;
+    }
+}
+
+/// Returns the value of the MaskProvider's verify-now annotation, if present.
+pub fn verify_now(instance: &MaskProvider) -> Option<&str> {
+    instance
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|a| a.get(VERIFY_NOW_ANNOTATION))
+        .map(String::as_str)
+}
+
+/// Returns the verify-now annotation value that was current when the
+/// verification Mask was created. Completing the verification cycle
+/// satisfies this manual trigger and no others.
+pub async fn get_verify_trigger(
+    client: Client,
+    name: &str,
+    namespace: &str,
+) -> Result<Option<String>, Error> {
+    let api: Api<Mask> = Api::namespaced(client, namespace);
+    let name = get_verify_mask_name(name);
+    match api.get(&name).await {
+        Ok(mask) => Ok(mask
+            .metadata
+            .annotations
+            .and_then(|mut a| a.remove(VERIFY_NOW_ANNOTATION))),
+        Err(kube::Error::Api(e)) if e.code == 404 => Ok(None),
+        Err(e) => Err(e).context_kind_name("Mask", &name),
+    }
+}
+
+/// Publishes an event indicating verification was triggered manually.
+pub async fn manual_verify_event(client: Client, instance: &MaskProvider) {
+    let note = format!(
+        "Verification triggered manually with {}={}",
+        VERIFY_NOW_ANNOTATION,
+        verify_now(instance).unwrap_or_default(),
+    );
+    events::publish(client, instance, "ManualVerify", "Verify", note).await;
+}
+
 /// Creates a Mask for the verification pod.
 pub async fn create_verify_mask(
     client: Client,
@@ -419,7 +782,10 @@ pub async fn create_verify_mask(
 ) -> Result<Mask, Error> {
     let mask_api: Api<Mask> = Api::namespaced(client, namespace);
     let mask = verify_mask(name, namespace, instance);
-    Ok(mask_api.create(&Default::default(), &mask).await?)
+    mask_api
+        .create(&Default::default(), &mask)
+        .await
+        .context_kind_name("Mask", &get_verify_mask_name(name))
 }
 
 /// Creates a pod that verifies the VPN credentials work.
@@ -434,7 +800,7 @@ pub async fn create_verify_pod(
     let assigned_provider = consumer
         .status
         .as_ref()
-        .map_or(None, |s| s.provider.as_ref())
+        .and_then(|s| s.provider.as_ref())
         .ok_or_else(|| {
             // This shouldn't happen under normal conditions because
             // this action shouldn't be called unless the the consumer
@@ -455,12 +821,18 @@ pub async fn create_verify_pod(
     // to inject into the VPN container's environment. The secret
     // has a unique name so there's no need to check its UID.
     let secret_api: Api<Secret> = Api::namespaced(client.clone(), namespace);
-    let secret = secret_api.get(&assigned_provider.secret).await?;
+    let secret = secret_api
+        .get(&assigned_provider.secret)
+        .await
+        .context_kind_name("Secret", &assigned_provider.secret)?;
 
     // Create the pod, honoring overrides in the MaskProvider spec.
     let pod = verify_pod(name, namespace, instance, &secret, consumer)?;
     let pod_api: Api<Pod> = Api::namespaced(client, namespace);
-    Ok(pod_api.create(&Default::default(), &pod).await?)
+    pod_api
+        .create(&Default::default(), &pod)
+        .await
+        .context_kind_name("Pod", name)
 }
 
 /// Deletes the verification Pod.
@@ -472,7 +844,7 @@ pub async fn delete_verify_pod(client: Client, name: &str, namespace: &str) -> R
         // Pod does not exist.
         Err(kube::Error::Api(e)) if e.code == 404 => Ok(()),
         // Error deleting Pod.
-        Err(e) => Err(e.into()),
+        Err(e) => Err(e).context_kind_name("Pod", name),
     }
 }
 
@@ -486,6 +858,6 @@ pub async fn delete_verify_mask(client: Client, name: &str, namespace: &str) ->
         // Pod does not exist.
         Err(kube::Error::Api(e)) if e.code == 404 => Ok(()),
         // Error deleting Pod.
-        Err(e) => Err(e.into()),
+        Err(e) => Err(e).context_kind_name("Mask", &name),
     }
 }
@@ -0,0 +1,83 @@
+use kube::ResourceExt;
+use std::{cmp::Reverse, collections::BTreeSet, sync::Arc};
+use vpn_types::*;
+
+use crate::{
+    consumers::slots::reservation_slot,
+    util::{clock, is_canary_reservation, is_verification_reservation},
+};
+
+/// Maximum number of entries in [`MaskProviderStatus::recent_assignments`].
+pub const MAX_RECENT_ASSIGNMENTS: usize = 10;
+
+/// Returns the MaskConsumers most recently assigned a slot of the
+/// MaskProvider, newest first, given its reservations and the cached
+/// MaskConsumers. Each is summarized with the ServiceAccounts its status
+/// records using the MaskProvider's credentials. Reservations made for
+/// verification and canaries aren't assignments, so they aren't listed.
+pub fn recent_assignments(
+    instance: &MaskProvider,
+    reservations: &[MaskReservation],
+    consumers: &[Arc<MaskConsumer>],
+) -> Vec<RecentAssignment> {
+    let provider_name = instance.name_any();
+    let provider = format!(
+        "{}/{}",
+        instance.namespace().unwrap_or_default(),
+        provider_name
+    );
+    let mut reservations: Vec<(&MaskReservation, usize)> = reservations
+        .iter()
+        .filter(|mr| !is_verification_reservation(mr) && !is_canary_reservation(mr))
+        .filter_map(|mr| Some((mr, reservation_slot(mr, &provider_name)?)))
+        .collect();
+    reservations.sort_by_key(|(mr, slot)| {
+        (
+            Reverse(mr.metadata.creation_timestamp.as_ref().map(|t| t.0)),
+            *slot,
+        )
+    });
+    reservations
+        .into_iter()
+        .take(MAX_RECENT_ASSIGNMENTS)
+        .map(|(mr, slot)| {
+            let records: Vec<&ConsumerRecord> = consumers
+                .iter()
+                .find(|c| c.metadata.uid.as_deref() == Some(mr.spec.uid.as_str()))
+                .and_then(|c| c.status.as_ref()?.consumers.as_ref())
+                .into_iter()
+                .flatten()
+                .filter(|r| r.provider == provider)
+                .collect();
+            RecentAssignment {
+                namespace: mr.spec.namespace.clone(),
+                name: mr.spec.name.clone(),
+                slot,
+                assigned_at: mr
+                    .metadata
+                    .creation_timestamp
+                    .as_ref()
+                    .map(|t| clock::format_timestamp(t.0)),
+                service_accounts: records
+                    .iter()
+                    .map(|r| &r.service_account)
+                    .collect::<BTreeSet<_>>()
+                    .len(),
+                last_seen: records
+                    .iter()
+                    .max_by_key(|r| clock::parse_timestamp(&r.last_seen).ok())
+                    .map(|r| r.last_seen.clone()),
+            }
+        })
+        .collect()
+}
+
+/// Returns true if the MaskProvider's status already shows the assignments.
+pub fn is_current(instance: &MaskProvider, recent: &[RecentAssignment]) -> bool {
+    let current = instance
+        .status
+        .as_ref()
+        .and_then(|s| s.recent_assignments.as_deref())
+        .unwrap_or_default();
+    current == recent
+}
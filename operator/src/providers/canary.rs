@@ -0,0 +1,292 @@
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::Secret;
+use kube::{
+    api::{Api, ListParams, ObjectMeta, Resource},
+    Client,
+};
+use std::{collections::BTreeMap, time::Duration};
+use vpn_types::*;
+
+use crate::{
+    consumers::actions::is_consumer_secret,
+    masks::util::get_consumer,
+    util::{
+        clock, events, list::list_all_paginated, messages, patch::patch_status, Error,
+        ErrorContext, CANARY_LABEL, MANAGER_NAME,
+    },
+};
+
+#[cfg(feature = "metrics")]
+use crate::util::metrics::observe_canary;
+
+/// How long a canary Mask has to be assigned the MaskProvider and
+/// given well-formed credentials before the canary fails.
+pub const CANARY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Outcome of a canary that finished or was skipped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CanaryOutcome {
+    pub result: MaskProviderCanaryResult,
+
+    /// Time from the canary Mask's creation until it finished.
+    /// Unset if the canary was skipped.
+    pub duration: Option<Duration>,
+
+    /// Explains why the canary failed or was skipped.
+    pub message: Option<String>,
+}
+
+impl CanaryOutcome {
+    /// Returns the outcome of a canary skipped because every slot is in use.
+    pub fn skipped() -> Self {
+        CanaryOutcome {
+            result: MaskProviderCanaryResult::Skipped,
+            duration: None,
+            message: Some(messages::CANARY_SKIPPED.to_owned()),
+        }
+    }
+}
+
+/// Returns the name of the canary Mask for the MaskProvider.
+pub fn get_canary_mask_name(name: &str) -> String {
+    format!("{}-canary", name)
+}
+
+/// Returns the interval between the MaskProvider's canaries, or None if
+/// the operator doesn't run canaries or the MaskProvider didn't opt in.
+pub fn interval(instance: &MaskProvider, canary_interval: Option<Duration>) -> Option<Duration> {
+    canary_interval.filter(|_| instance.spec.canary == Some(true))
+}
+
+/// Returns true if the MaskProvider has never had a canary or the last
+/// one finished at least `interval` before `now`.
+pub fn is_due(instance: &MaskProvider, interval: Duration, now: DateTime<Utc>) -> bool {
+    let interval = chrono::Duration::from_std(interval).unwrap_or(chrono::Duration::MAX);
+    instance
+        .status
+        .as_ref()
+        .and_then(|s| s.last_canary_at.as_deref())
+        .and_then(|t| clock::parse_timestamp(t).ok())
+        .is_none_or(|last| now - last >= interval)
+}
+
+/// Returns how long ago the canary Mask was created.
+pub fn elapsed(mask: &Mask, now: DateTime<Utc>) -> Duration {
+    mask.metadata
+        .creation_timestamp
+        .as_ref()
+        .and_then(|t| (now - t.0).to_std().ok())
+        .unwrap_or_default()
+}
+
+/// Returns true if the credentials Secret was copied for the canary's
+/// MaskConsumer and has a value for each of its keys.
+pub fn is_well_formed(secret: &Secret, consumer: &MaskConsumer) -> bool {
+    is_consumer_secret(secret, consumer)
+        && secret
+            .data
+            .as_ref()
+            .is_some_and(|data| !data.is_empty() && data.values().all(|v| !v.0.is_empty()))
+}
+
+/// Checks on the canary Mask as of `now`, given its MaskConsumer and the
+/// credentials Secret copied for it, if they exist. Returns None while the
+/// canary is still in progress. A canary still waiting for a slot when it
+/// times out didn't fail, as every slot being in use says nothing about
+/// the pipeline.
+pub fn check(
+    mask: &Mask,
+    consumer: Option<&MaskConsumer>,
+    secret: Option<&Secret>,
+    now: DateTime<Utc>,
+) -> Option<CanaryOutcome> {
+    let status = mask.status.as_ref();
+    let phase = status.and_then(|s| s.phase);
+    let failed = |message: String| CanaryOutcome {
+        result: MaskProviderCanaryResult::Failed,
+        duration: Some(elapsed(mask, now)),
+        message: Some(message),
+    };
+    match phase {
+        Some(MaskPhase::Waiting) if elapsed(mask, now) > CANARY_TIMEOUT => {
+            return Some(CanaryOutcome {
+                result: MaskProviderCanaryResult::TimedOutWaiting,
+                duration: Some(elapsed(mask, now)),
+                message: Some(messages::canary_timed_out_waiting(&CANARY_TIMEOUT)),
+            });
+        }
+        Some(MaskPhase::Active) => {
+            if let (Some(consumer), Some(secret)) = (consumer, secret) {
+                if is_well_formed(secret, consumer) {
+                    return Some(CanaryOutcome {
+                        result: MaskProviderCanaryResult::Succeeded,
+                        duration: Some(elapsed(mask, now)),
+                        message: None,
+                    });
+                }
+            }
+        }
+        Some(
+            phase @ (MaskPhase::ErrNoProviders
+            | MaskPhase::ErrNamespaceNotOptedIn
+            | MaskPhase::ErrSecretConflict
+            | MaskPhase::ErrMissingLabels
+            | MaskPhase::ErrQuotaExceeded
+            | MaskPhase::ErrProviderLost
+            | MaskPhase::ErrInvalidSpec),
+        ) => {
+            let message = status.and_then(|s| s.message.as_deref());
+            return Some(failed(messages::canary_failed(&phase.to_string(), message)));
+        }
+        _ => {}
+    }
+    if elapsed(mask, now) > CANARY_TIMEOUT {
+        let phase = phase.map(|p| p.to_string());
+        return Some(failed(messages::canary_timed_out(
+            &CANARY_TIMEOUT,
+            phase.as_deref(),
+        )));
+    }
+    None
+}
+
+/// Returns the canary Mask, which is labeled with the MaskProvider's uid
+/// so only it is assigned to the canary, and so dashboards can tell
+/// canaries apart from real Masks.
+pub fn canary_mask(name: &str, namespace: &str, instance: &MaskProvider) -> Mask {
+    let mut labels = BTreeMap::new();
+    labels.insert("app".to_owned(), MANAGER_NAME.to_owned());
+    labels.insert(
+        CANARY_LABEL.to_owned(),
+        instance.metadata.uid.clone().unwrap(),
+    );
+    Mask {
+        metadata: ObjectMeta {
+            name: Some(get_canary_mask_name(name)),
+            namespace: Some(namespace.to_owned()),
+            labels: Some(labels),
+            owner_references: Some(vec![instance.controller_owner_ref(&()).unwrap()]),
+            ..Default::default()
+        },
+        // Note: `providers` is omitted because the label constrains
+        // the controller to assign this MaskProvider.
+        spec: Default::default(),
+        ..Default::default()
+    }
+}
+
+/// Gets the canary Mask for the MaskProvider, if it exists.
+pub async fn get_mask(client: Client, name: &str, namespace: &str) -> Result<Option<Mask>, Error> {
+    let api: Api<Mask> = Api::namespaced(client, namespace);
+    let name = get_canary_mask_name(name);
+    match api.get(&name).await {
+        Ok(mask) => Ok(Some(mask)),
+        Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(None),
+        Err(e) => Err(e).context_kind_name("Mask", &name),
+    }
+}
+
+/// Gets the canary Mask's MaskConsumer and the credentials Secret
+/// copied for it, if they exist yet.
+pub async fn get_credentials(
+    client: Client,
+    mask: &Mask,
+) -> Result<(Option<MaskConsumer>, Option<Secret>), Error> {
+    let consumer = match get_consumer(client.clone(), mask).await? {
+        Some(consumer) => consumer,
+        None => return Ok((None, None)),
+    };
+    let secret_name = match consumer.status.as_ref().and_then(|s| s.provider.as_ref()) {
+        Some(provider) => provider.secret.clone(),
+        None => return Ok((Some(consumer), None)),
+    };
+    let api: Api<Secret> = Api::namespaced(client, mask.metadata.namespace.as_deref().unwrap());
+    match api.get(&secret_name).await {
+        Ok(secret) => Ok((Some(consumer), Some(secret))),
+        Err(kube::Error::Api(ae)) if ae.code == 404 => Ok((Some(consumer), None)),
+        Err(e) => Err(e).context_kind_name("Secret", &secret_name),
+    }
+}
+
+/// Creates the canary Mask for the MaskProvider.
+pub async fn create_mask(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    instance: &MaskProvider,
+) -> Result<Mask, Error> {
+    let api: Api<Mask> = Api::namespaced(client, namespace);
+    api.create(&Default::default(), &canary_mask(name, namespace, instance))
+        .await
+        .context_kind_name("Mask", &get_canary_mask_name(name))
+}
+
+/// Deletes the canary Mask, releasing its slot.
+pub async fn delete_mask(client: Client, name: &str, namespace: &str) -> Result<(), Error> {
+    let api: Api<Mask> = Api::namespaced(client, namespace);
+    let name = get_canary_mask_name(name);
+    match api.delete(&name, &Default::default()).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(e)) if e.code == 404 => Ok(()),
+        Err(e) => Err(e).context_kind_name("Mask", &name),
+    }
+}
+
+/// Records the canary's outcome in the MaskProvider's status and metrics.
+/// Failures are also published as a `CanaryFailed` Warning event.
+pub async fn finished(
+    client: Client,
+    instance: &MaskProvider,
+    outcome: CanaryOutcome,
+    now: DateTime<Utc>,
+) -> Result<(), Error> {
+    #[cfg(feature = "metrics")]
+    observe_canary(instance, outcome.result, outcome.duration);
+    let CanaryOutcome {
+        result,
+        duration,
+        message,
+    } = outcome;
+    patch_status(client.clone(), instance, |status| {
+        status.last_canary_at = Some(clock::format_timestamp(now));
+        status.last_canary_result = Some(result);
+        status.last_canary_duration = duration.map(|d| format!("{:.1}s", d.as_secs_f64()));
+    })
+    .await?;
+    if let Some(message) = message {
+        match result {
+            MaskProviderCanaryResult::Failed => {
+                events::warn(client, instance, "CanaryFailed", "Canary", message).await
+            }
+            MaskProviderCanaryResult::TimedOutWaiting => {
+                events::publish(client, instance, "CanaryTimedOutWaiting", "Canary", message).await
+            }
+            _ => events::publish(client, instance, "CanarySkipped", "Canary", message).await,
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the canary Masks left behind by a previous run of the operator.
+/// Their timing would include the time the operator was down, so they are
+/// abandoned rather than resumed, and the MaskProviders create new ones
+/// when they're next due. Returns the number of canary Masks deleted.
+pub async fn cleanup(client: Client) -> Result<usize, Error> {
+    let api: Api<Mask> = Api::all(client.clone());
+    let lp = ListParams::default().labels(CANARY_LABEL);
+    let mut deleted = 0;
+    for mask in list_all_paginated(&api, &lp).await? {
+        if mask.metadata.deletion_timestamp.is_some() {
+            continue;
+        }
+        let name = mask.metadata.name.as_deref().unwrap();
+        let namespace = mask.metadata.namespace.as_deref().unwrap();
+        let api: Api<Mask> = Api::namespaced(client.clone(), namespace);
+        match api.delete(name, &Default::default()).await {
+            Ok(_) => deleted += 1,
+            Err(kube::Error::Api(e)) if e.code == 404 => {}
+            Err(e) => return Err(e).context_kind_name("Mask", name),
+        }
+    }
+    Ok(deleted)
+}
@@ -0,0 +1,115 @@
+use futures::StreamExt;
+use kube::{
+    api::ListParams,
+    runtime::{
+        reflector::{reflector, store::Writer},
+        watcher,
+    },
+    Api, Client,
+};
+use std::sync::Arc;
+use vpn_types::*;
+
+use crate::util::{
+    consumer_purpose, is_canary_reservation, is_verification_reservation, PROBE_INTERVAL,
+};
+
+/// Capacity planning figures recorded in a MaskProvider's status.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Capacity {
+    /// Slots that aren't reserved by Masks.
+    pub available_slots: usize,
+
+    /// Waiting MaskConsumers that the MaskProvider could be assigned to.
+    pub pending_demand: usize,
+}
+
+impl Capacity {
+    /// Returns true if the MaskProvider's status already shows the figures.
+    pub fn is_current(&self, instance: &MaskProvider) -> bool {
+        let summary = slots_summary(self.available_slots, instance.spec.max_slots);
+        instance.status.as_ref().is_some_and(|s| {
+            s.available_slots == Some(self.available_slots)
+                && s.pending_demand == Some(self.pending_demand)
+                && s.slots_summary.as_ref() == Some(&summary)
+        })
+    }
+}
+
+/// Formats the free slots out of the maximum for `kubectl get`, e.g. `3/5`.
+pub fn slots_summary(available_slots: usize, max_slots: usize) -> String {
+    format!("{}/{}", available_slots, max_slots)
+}
+
+/// Returns true if the MaskConsumer is waiting for a slot and the
+/// MaskProvider could be assigned to it: the MaskProvider has one of the
+/// tags in the MaskConsumer's `spec.providers`, if any, and permits its
+/// namespace. Verification MaskConsumers only wait for the MaskProvider
+/// they verify, so they aren't counted.
+pub fn is_pending_demand(provider: &MaskProvider, consumer: &MaskConsumer) -> bool {
+    let waiting = consumer
+        .status
+        .as_ref()
+        .and_then(|s| s.phase)
+        .is_some_and(|phase| phase == MaskConsumerPhase::Waiting);
+    if !waiting
+        || consumer.metadata.deletion_timestamp.is_some()
+        || consumer_purpose(consumer) != MaskConsumerPurpose::Workload
+    {
+        return false;
+    }
+    let namespace = consumer.metadata.namespace.as_deref().unwrap_or_default();
+    let permitted = provider
+        .spec
+        .namespaces
+        .as_ref()
+        .is_none_or(|ns| ns.iter().any(|n| n == namespace));
+    let tagged = consumer.spec.providers.as_ref().is_none_or(|wanted| {
+        provider
+            .spec
+            .tags
+            .iter()
+            .flatten()
+            .any(|tag| wanted.contains(tag))
+    });
+    permitted && tagged
+}
+
+/// Returns the capacity figures of the MaskProvider given its
+/// reservations and the cached MaskConsumers. Reservations made for
+/// verification and canaries aren't slots reserved by Masks, so they
+/// aren't counted.
+pub fn get_capacity(
+    instance: &MaskProvider,
+    reservations: &[MaskReservation],
+    consumers: &[Arc<MaskConsumer>],
+) -> Capacity {
+    let active_slots = reservations
+        .iter()
+        .filter(|mr| !is_verification_reservation(mr) && !is_canary_reservation(mr))
+        .count();
+    Capacity {
+        available_slots: instance.spec.max_slots.saturating_sub(active_slots),
+        pending_demand: consumers
+            .iter()
+            .filter(|c| is_pending_demand(instance, c))
+            .count(),
+    }
+}
+
+/// Keeps `writer`'s cache of every MaskConsumer up-to-date, which the
+/// pending demand of the MaskProviders is counted from without listing
+/// them with each reconcile. Failures are logged and retried.
+pub async fn watch_consumers(client: Client, writer: Writer<MaskConsumer>) {
+    let api: Api<MaskConsumer> = Api::all(client);
+    let mut events = reflector(writer, watcher(api, ListParams::default())).boxed();
+    while let Some(event) = events.next().await {
+        if let Err(e) = event {
+            eprintln!(
+                "Failed to watch MaskConsumers for the pending demand: {}",
+                e
+            );
+            tokio::time::sleep(PROBE_INTERVAL).await;
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::Pod;
+use kube::{
+    api::{Patch, PatchParams},
+    Api, Client,
+};
+use std::time::Duration;
+use vpn_types::*;
+
+use super::actions::get_verify_mask_name;
+use crate::util::{capabilities::Capabilities, Error, ErrorContext, VERIFY_RESCHEDULED_ANNOTATION};
+
+/// Maximum number of times the verification Pod is recreated after being
+/// disrupted within a verification cycle, before the cycle fails.
+pub const MAX_VERIFY_RESCHEDULES: usize = 3;
+
+/// Reasons the kubelet or node lifecycle controller give a Pod that was
+/// stopped by a disruption of its node, or preempted by a Pod with a
+/// higher priority, rather than by its own failure.
+const DISRUPTED_REASONS: &[&str] = &[
+    "NodeLost",
+    "Evicted",
+    "Preempting",
+    "Shutdown",
+    "Terminated",
+];
+
+/// Returns the reason the verification Pod was disrupted, if it was lost
+/// with its node, evicted or is being deleted to make way for something
+/// else, in which case recreating it may well succeed. Genuine failures
+/// of the Pod's containers return None.
+pub fn verify_pod_disruption(pod: &Pod) -> Option<String> {
+    let status = pod.status.as_ref()?;
+    // Set on Pods that are about to be deleted because of a disruption,
+    // such as an eviction, preemption or a taint on their node.
+    let target = status
+        .conditions
+        .iter()
+        .flatten()
+        .find(|c| c.type_ == "DisruptionTarget" && c.status == "True");
+    if let Some(condition) = target {
+        return Some(
+            condition
+                .reason
+                .clone()
+                .unwrap_or_else(|| "DisruptionTarget".to_owned()),
+        );
+    }
+    if let Some(reason) = status
+        .reason
+        .as_deref()
+        .filter(|r| DISRUPTED_REASONS.contains(r))
+    {
+        return Some(reason.to_owned());
+    }
+    // The Pod's node stopped reporting its status.
+    match status.phase.as_deref() {
+        Some("Unknown") => Some("NodeLost".to_owned()),
+        _ => None,
+    }
+}
+
+/// Reason given to a verification Pod that was deleted before it could
+/// finish, on API servers that don't mark disrupted Pods.
+pub const DELETED_REASON: &str = "Deleted";
+
+/// Returns the reason the verification Pod was disrupted on API servers
+/// older than 1.26, which delete evicted and drained Pods without the
+/// `DisruptionTarget` condition. A Pod that is deleted while it is
+/// still verifying, and while its Mask isn't, was deleted by someone
+/// other than the operator, as the operator only deletes the Pod once
+/// verification is over or to recreate it.
+pub fn unmarked_disruption(
+    pod: &Pod,
+    mask: Option<&Mask>,
+    instance: &MaskProvider,
+    capabilities: &Capabilities,
+) -> Option<String> {
+    if capabilities.disruption_conditions || pod.metadata.deletion_timestamp.is_none() {
+        return None;
+    }
+    let phase = pod.status.as_ref().and_then(|s| s.phase.as_deref());
+    if !matches!(phase, Some("Pending" | "Running")) {
+        return None;
+    }
+    let verifying = instance
+        .status
+        .as_ref()
+        .is_some_and(|s| s.phase == Some(MaskProviderPhase::Verifying));
+    let mask_active = mask.is_some_and(|m| m.metadata.deletion_timestamp.is_none());
+    (verifying && mask_active).then(|| DELETED_REASON.to_owned())
+}
+
+/// Returns the uids of the verification Pods that were already recreated
+/// during the cycle of the verification Mask.
+pub fn rescheduled_pods(mask: &Mask) -> Vec<String> {
+    mask.metadata
+        .annotations
+        .as_ref()
+        .and_then(|a| a.get(VERIFY_RESCHEDULED_ANNOTATION))
+        .map(|uids| {
+            uids.split(',')
+                .filter(|uid| !uid.is_empty())
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Returns true if a disrupted verification Pod can still be recreated:
+/// fewer than [`MAX_VERIFY_RESCHEDULES`] were recreated before, and the
+/// cycle, which began when the verification Mask was created, hasn't
+/// used up the verification timeout yet.
+pub fn can_reschedule(
+    mask: &Mask,
+    rescheduled: &[String],
+    timeout: Duration,
+    now: DateTime<Utc>,
+) -> bool {
+    let elapsed = mask
+        .metadata
+        .creation_timestamp
+        .as_ref()
+        .and_then(|created| (now - created.0).to_std().ok())
+        .unwrap_or_default();
+    rescheduled.len() < MAX_VERIFY_RESCHEDULES && elapsed < timeout
+}
+
+/// Records on the verification Mask that the Pods with the given uids
+/// were recreated, so they count towards the limit for the cycle.
+pub async fn record_reschedules(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    rescheduled: &[String],
+) -> Result<(), Error> {
+    let name = get_verify_mask_name(name);
+    let api: Api<Mask> = Api::namespaced(client, namespace);
+    let patch = serde_json::json!({
+        "metadata": {
+            "annotations": {
+                VERIFY_RESCHEDULED_ANNOTATION: rescheduled.join(","),
+            },
+        },
+    });
+    api.patch(&name, &PatchParams::default(), &Patch::Merge(&patch))
+        .await
+        .context_kind_name("Mask", &name)?;
+    Ok(())
+}
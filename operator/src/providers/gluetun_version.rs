@@ -0,0 +1,55 @@
+use vpn_render::parse_version;
+use vpn_types::*;
+
+use crate::util::messages;
+
+/// Env vars that gluetun renamed, as `(old, new, version)` where
+/// `version` is the first release that expects the new name.
+pub const RENAMED_KEYS: [(&str, &str, (u32, u32, u32)); 8] = [
+    ("USER", "OPENVPN_USER", (3, 0, 0)),
+    ("PASSWORD", "OPENVPN_PASSWORD", (3, 0, 0)),
+    ("PROTOCOL", "OPENVPN_PROTOCOL", (3, 0, 0)),
+    ("REGION", "SERVER_REGIONS", (3, 0, 0)),
+    ("COUNTRY", "SERVER_COUNTRIES", (3, 0, 0)),
+    ("CITY", "SERVER_CITIES", (3, 0, 0)),
+    ("VPNSP", "VPN_SERVICE_PROVIDER", (3, 29, 0)),
+    ("PORT_FORWARDING", "VPN_PORT_FORWARDING", (3, 36, 0)),
+];
+
+/// Returns a warning for each key that gluetun expects under another
+/// name in the given version. Versions that can't be parsed are never
+/// warned about, as the renames that apply to them are unknown.
+pub fn renamed_key_warnings(version: &str, keys: &[String]) -> Vec<String> {
+    let version = match parse_version(version) {
+        Some(version) => version,
+        None => return Vec::new(),
+    };
+    let mut warnings = Vec::new();
+    for key in keys {
+        for (old, new, since) in RENAMED_KEYS.iter() {
+            if key == old && version >= *since {
+                warnings.push(messages::gluetun_key_renamed(old, new, since));
+            } else if key == new && version < *since {
+                warnings.push(messages::gluetun_key_too_new(new, old, since));
+            }
+        }
+    }
+    warnings
+}
+
+/// Returns the warnings to display in the MaskProvider's status about
+/// keys in `spec.maskDefaults.secretKeys` that don't match the names
+/// expected by its `spec.gluetunVersion`.
+pub fn compatibility_warnings(instance: &MaskProvider) -> Vec<String> {
+    let version = match instance.spec.gluetun_version.as_deref() {
+        Some(version) => version,
+        None => return Vec::new(),
+    };
+    let keys = instance
+        .spec
+        .mask_defaults
+        .as_ref()
+        .and_then(|d| d.secret_keys.as_deref())
+        .unwrap_or_default();
+    renamed_key_warnings(version, keys)
+}
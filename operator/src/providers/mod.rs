@@ -1,4 +1,72 @@
-mod actions;
-mod reconcile;
+pub(crate) mod actions;
+pub(crate) mod assignments;
+pub(crate) mod canary;
+pub(crate) mod capacity;
+pub(crate) mod disruption;
+pub(crate) mod gluetun_version;
+pub(crate) mod namespaces;
+pub(crate) mod operation;
+pub(crate) mod overview;
+pub(crate) mod placement;
+pub(crate) mod reconcile;
+pub(crate) mod suffix;
+pub(crate) mod transforms;
+pub(crate) mod verify_assert;
+pub(crate) mod verify_defaults;
+pub(crate) mod verify_failure;
+pub(crate) mod verify_hash;
+pub(crate) mod verify_keys;
+pub(crate) mod verify_queue;
+pub(crate) mod verify_schedule;
+pub(crate) mod watches;
+pub(crate) mod withdrawal;
 
 pub use reconcile::run;
+
+use crate::rbac::Rule;
+
+/// API access the MaskProvider controller needs. See [`crate::rbac`].
+pub const RBAC: &[Rule] = &[
+    Rule::new(
+        "vpn.beebs.dev",
+        &["maskproviders"],
+        &["get", "list", "watch", "patch"],
+    ),
+    Rule::new("vpn.beebs.dev", &["maskproviders/status"], &["patch"]),
+    // Verifications and canaries are run with Masks the MaskProvider owns.
+    Rule::new(
+        "vpn.beebs.dev",
+        &["masks"],
+        &["get", "list", "watch", "create", "patch", "delete"],
+    ),
+    Rule::new(
+        "vpn.beebs.dev",
+        &["maskconsumers"],
+        &["get", "list", "watch", "delete"],
+    ),
+    Rule::new(
+        "vpn.beebs.dev",
+        &["maskreservations"],
+        &["get", "list", "watch", "patch", "delete"],
+    ),
+    // Transformed credentials are written next to the originals.
+    Rule::new(
+        "",
+        &["secrets"],
+        &["get", "list", "watch", "create", "update", "delete"],
+    ),
+    // The verification Pod is created and deleted by the controller.
+    Rule::new("", &["pods"], &["get", "list", "watch", "create", "delete"]),
+    Rule::new("", &["namespaces"], &["get", "list"]),
+    // Verifications are placed in the zone of a Node.
+    Rule::new("", &["nodes"], &["get"]),
+    Rule::new("scheduling.k8s.io", &["priorityclasses"], &["get"]),
+    // The VpnFleet overview is written by the replica holding the Lease.
+    Rule::new("vpn.beebs.dev", &["vpnfleets"], &["get", "create", "patch"]),
+    Rule::new("vpn.beebs.dev", &["vpnfleets/status"], &["patch"]),
+    Rule::new(
+        "coordination.k8s.io",
+        &["leases"],
+        &["get", "create", "update"],
+    ),
+];
@@ -0,0 +1,55 @@
+use k8s_openapi::api::core::v1::Namespace;
+use kube::{Api, Client};
+use vpn_types::*;
+
+use crate::util::{messages, Error};
+
+/// Returns the entries of the MaskProvider's `spec.namespaces`
+/// that don't correspond to an existing Namespace, in order.
+pub async fn get_unknown_namespaces(
+    client: Client,
+    instance: &MaskProvider,
+) -> Result<Vec<String>, Error> {
+    let api: Api<Namespace> = Api::all(client);
+    let mut unknown: Vec<String> = Vec::new();
+    for namespace in instance.spec.namespaces.iter().flatten() {
+        if unknown.contains(namespace) {
+            continue;
+        }
+        match api.get(namespace).await {
+            Ok(_) => {}
+            Err(kube::Error::Api(ae)) if ae.code == 404 => unknown.push(namespace.clone()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(unknown)
+}
+
+/// Returns the warnings to display in the MaskProvider's status
+/// given the namespaces from its allowlist that don't exist.
+pub fn namespace_warnings(unknown: &[String]) -> Vec<String> {
+    if unknown.is_empty() {
+        return Vec::new();
+    }
+    vec![messages::unknown_namespaces(unknown)]
+}
+
+/// Returns true if the warnings differ from those in the status.
+pub fn warnings_changed(instance: &MaskProvider, warnings: &[String]) -> bool {
+    instance
+        .status
+        .as_ref()
+        .and_then(|s| s.warnings.as_deref())
+        .unwrap_or_default()
+        != warnings
+}
+
+/// Returns true if a Mask in some namespace could be assigned either
+/// MaskProvider. A MaskProvider without an allowlist can be assigned
+/// to every namespace.
+pub fn namespaces_overlap(a: &MaskProvider, b: &MaskProvider) -> bool {
+    match (a.spec.namespaces.as_ref(), b.spec.namespaces.as_ref()) {
+        (Some(a), Some(b)) => a.iter().any(|namespace| b.contains(namespace)),
+        _ => true,
+    }
+}
@@ -0,0 +1,104 @@
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+use vpn_types::*;
+
+use crate::util::clock;
+
+/// Number of steps a verification cycle takes.
+pub const VERIFY_STEPS: usize = 4;
+
+/// A step of a verification cycle, numbered as in
+/// [`MaskProviderOperation::step`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VerifyStep {
+    /// The verification Mask was created and is waiting for a slot.
+    MaskCreated = 1,
+
+    /// The verification Mask was assigned a slot and the Pod is next.
+    SlotAssigned = 2,
+
+    /// The verification Pod was created and is waiting to start.
+    PodCreated = 3,
+
+    /// The verification Pod is running and probing the connection.
+    Probing = 4,
+}
+
+/// Returns the MaskProvider's operation if it's of the kind.
+fn current(
+    instance: &MaskProvider,
+    kind: MaskProviderOperationKind,
+) -> Option<&MaskProviderOperation> {
+    instance
+        .status
+        .as_ref()
+        .and_then(|s| s.operation.as_ref())
+        .filter(|op| op.kind == kind)
+}
+
+/// Returns the deadline of a step that started at `start` and may take
+/// up to `timeout`, as a timestamp.
+pub fn deadline(start: DateTime<Utc>, timeout: Duration) -> Option<String> {
+    let timeout = chrono::Duration::from_std(timeout).ok()?;
+    Some(clock::format_timestamp(start + timeout))
+}
+
+/// Returns the operation of a verification cycle that began at `now`.
+pub fn verify_started(deadline: Option<String>, now: DateTime<Utc>) -> MaskProviderOperation {
+    MaskProviderOperation {
+        kind: MaskProviderOperationKind::Verifying,
+        started_at: clock::format_timestamp(now),
+        deadline,
+        step: VerifyStep::MaskCreated as usize,
+        steps_total: VERIFY_STEPS,
+    }
+}
+
+/// Returns the operation of the MaskProvider's verification cycle
+/// having reached the step. The cycle is considered to have begun at
+/// `now` if the MaskProvider isn't already known to be verifying, e.g.
+/// because the cycle began before the operation was recorded.
+pub fn verifying(
+    instance: &MaskProvider,
+    step: VerifyStep,
+    deadline: Option<String>,
+    now: DateTime<Utc>,
+) -> MaskProviderOperation {
+    let started_at = current(instance, MaskProviderOperationKind::Verifying)
+        .map(|op| op.started_at.clone())
+        .unwrap_or_else(|| clock::format_timestamp(now));
+    MaskProviderOperation {
+        started_at,
+        step: step as usize,
+        ..verify_started(deadline, now)
+    }
+}
+
+/// Returns the operation of the MaskProvider draining with `remaining`
+/// MaskConsumers still assigned, or None once none are. The total is
+/// the number assigned when draining began, so the step only advances.
+pub fn draining(
+    instance: &MaskProvider,
+    remaining: usize,
+    now: DateTime<Utc>,
+) -> Option<MaskProviderOperation> {
+    if remaining == 0 {
+        return None;
+    }
+    let (started_at, steps_total) = match current(instance, MaskProviderOperationKind::Draining) {
+        Some(op) => (op.started_at.clone(), op.steps_total.max(remaining)),
+        None => (clock::format_timestamp(now), remaining),
+    };
+    Some(MaskProviderOperation {
+        kind: MaskProviderOperationKind::Draining,
+        started_at,
+        deadline: None,
+        step: steps_total - remaining,
+        steps_total,
+    })
+}
+
+/// Returns true if the MaskProvider's status already shows the operation.
+pub fn is_current(instance: &MaskProvider, operation: Option<&MaskProviderOperation>) -> bool {
+    instance.status.as_ref().and_then(|s| s.operation.as_ref()) == operation
+}
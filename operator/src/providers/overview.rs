@@ -0,0 +1,194 @@
+use futures::{stream, StreamExt, TryStreamExt};
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    runtime::{
+        reflector::{self, reflector, Store},
+        watcher,
+    },
+    Api, Client,
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use vpn_types::*;
+
+use crate::{
+    export::aggregate::Debounce,
+    util::{
+        clock,
+        lease::{self, LeaseLock, LEASE_DURATION},
+        Error, ErrorContext, MANAGER_NAME, PROBE_INTERVAL,
+    },
+};
+
+/// Name of the singleton VpnFleet.
+pub const FLEET_NAME: &str = "default";
+
+/// Name of the Lease electing the replica that writes the VpnFleet,
+/// in the namespace of the operator.
+pub const LEASE_NAME: &str = "vpn-fleet-overview";
+
+/// Minimum time between writes of the VpnFleet. Changes that arrive in
+/// the meantime are written together.
+pub const WRITE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Returns the overview of the MaskProviders and Masks in the caches.
+/// The writer and timestamp are left for [`write`] to fill in.
+pub fn summarize(providers: &[Arc<MaskProvider>], masks: &[Arc<Mask>]) -> VpnFleetStatus {
+    let phase = |p: &MaskProvider| p.status.as_ref().and_then(|s| s.phase);
+    VpnFleetStatus {
+        providers: providers.len(),
+        ready: providers
+            .iter()
+            .filter(|p| {
+                matches!(
+                    phase(p),
+                    Some(MaskProviderPhase::Ready | MaskProviderPhase::Active)
+                )
+            })
+            .count(),
+        failed_verification: providers
+            .iter()
+            .filter(|p| phase(p) == Some(MaskProviderPhase::ErrVerifyFailed))
+            .count(),
+        total_slots: providers.iter().map(|p| p.spec.max_slots).sum(),
+        used_slots: providers
+            .iter()
+            .filter_map(|p| p.status.as_ref().and_then(|s| s.active_slots))
+            .sum(),
+        masks_waiting: masks
+            .iter()
+            .filter(|m| m.status.as_ref().and_then(|s| s.phase) == Some(MaskPhase::Waiting))
+            .count(),
+        ..Default::default()
+    }
+}
+
+/// Writes the overview to the VpnFleet as `writer`, creating it if it
+/// doesn't exist.
+pub async fn write(client: Client, writer: &str, status: VpnFleetStatus) -> Result<(), Error> {
+    let api: Api<VpnFleet> = Api::all(client);
+    let params = PatchParams::apply(MANAGER_NAME).force();
+    let fleet = VpnFleet::new(FLEET_NAME, VpnFleetSpec {});
+    api.patch(FLEET_NAME, &params, &Patch::Apply(&fleet))
+        .await
+        .context_kind_name("VpnFleet", FLEET_NAME)?;
+    let fleet = VpnFleet {
+        status: Some(VpnFleetStatus {
+            writer: Some(writer.to_owned()),
+            last_updated: Some(clock::now_k8s()),
+            ..status
+        }),
+        ..fleet
+    };
+    api.patch_status(FLEET_NAME, &params, &Patch::Apply(&fleet))
+        .await
+        .context_kind_name("VpnFleet", FLEET_NAME)?;
+    Ok(())
+}
+
+/// Which kind of resource was listed in full by a watch event.
+enum Listed {
+    Providers,
+    Masks,
+}
+
+/// Returns `kind` if the watch event lists every resource of the kind.
+fn listed<K>(event: &watcher::Event<K>, kind: Listed) -> Option<Listed> {
+    matches!(event, watcher::Event::Restarted(_)).then_some(kind)
+}
+
+/// Keeps the VpnFleet's status up-to-date from caches of every
+/// MaskProvider and Mask, writing it at most once every `interval`.
+/// Each replica maintains the caches, but only the one holding the
+/// Lease writes, so replicas never overwrite each other with caches
+/// that lag behind. Failures are logged and retried.
+pub async fn run(client: Client, interval: Duration) {
+    let lock = LeaseLock::new(
+        client.clone(),
+        &lease::namespace().await,
+        LEASE_NAME,
+        lease::identity(),
+    );
+    let lp = ListParams::default();
+    let (providers, provider_writer): (Store<MaskProvider>, _) = reflector::store();
+    let (masks, mask_writer): (Store<Mask>, _) = reflector::store();
+    let mut changes = stream::select(
+        reflector(
+            provider_writer,
+            watcher(Api::<MaskProvider>::all(client.clone()), lp.clone()),
+        )
+        .map_ok(|e| listed(&e, Listed::Providers))
+        .boxed(),
+        reflector(mask_writer, watcher(Api::<Mask>::all(client.clone()), lp))
+            .map_ok(|e| listed(&e, Listed::Masks))
+            .boxed(),
+    );
+    let mut debounce = Debounce::new(interval);
+    let mut renew = tokio::time::interval(LEASE_DURATION / 3);
+    let (mut providers_listed, mut masks_listed) = (false, false);
+    let mut leader = false;
+    let mut written: Option<VpnFleetStatus> = None;
+    loop {
+        // Only write once both kinds have been listed, so a partial
+        // cache is never mistaken for missing resources.
+        let deadline = debounce
+            .deadline(Instant::now())
+            .filter(|_| leader && providers_listed && masks_listed);
+        let flush = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                None => futures::future::pending().await,
+            }
+        };
+        tokio::select! {
+            change = changes.next() => match change {
+                Some(Ok(listed)) => {
+                    match listed {
+                        Some(Listed::Providers) => providers_listed = true,
+                        Some(Listed::Masks) => masks_listed = true,
+                        None => {}
+                    }
+                    debounce.mark_dirty();
+                }
+                Some(Err(e)) => {
+                    eprintln!("Failed to watch resources for the VpnFleet: {}", e);
+                    tokio::time::sleep(PROBE_INTERVAL).await;
+                }
+                None => return,
+            },
+            _ = renew.tick() => {
+                let was_leader = leader;
+                leader = match lock.try_acquire(chrono::Utc::now()).await {
+                    Ok(leader) => leader,
+                    Err(e) => {
+                        eprintln!("Failed to renew the Lease {}: {}", LEASE_NAME, e);
+                        false
+                    }
+                };
+                if leader && !was_leader {
+                    // Another replica may have written since this one did.
+                    written = None;
+                    debounce.mark_dirty();
+                }
+            }
+            _ = flush => {
+                let status = summarize(&providers.state(), &masks.state());
+                if written.as_ref() != Some(&status) {
+                    match write(client.clone(), lock.identity(), status.clone()).await {
+                        Ok(()) => written = Some(status),
+                        Err(e) => {
+                            // Retried once the interval has elapsed.
+                            eprintln!("Failed to write the VpnFleet: {}", e);
+                            debounce.flushed(Instant::now());
+                            debounce.mark_dirty();
+                            continue;
+                        }
+                    }
+                }
+                debounce.flushed(Instant::now());
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use k8s_openapi::api::{core::v1::Node, scheduling::v1::PriorityClass};
+use kube::{client::Client, Api};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use vpn_render::ZONE_LABEL;
+use vpn_types::*;
+
+use crate::util::{messages, Error, PROBE_INTERVAL};
+
+/// Returns the zone of the node with the given name, or None if the
+/// node is gone or doesn't have the zone label.
+pub async fn get_node_zone(client: Client, node: &str) -> Result<Option<String>, Error> {
+    let api: Api<Node> = Api::all(client);
+    match api.get(node).await {
+        Ok(node) => Ok(node
+            .metadata
+            .labels
+            .and_then(|mut labels| labels.remove(ZONE_LABEL))),
+        Err(kube::Error::Api(e)) if e.code == 404 => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether a PriorityClass exists and when that was checked.
+type CachedExistence = (Instant, bool);
+
+/// Checks whether the PriorityClasses named in verification settings
+/// exist. The answers are cached for `ttl`, so refreshing the status of
+/// many MaskProviders with the same PriorityClass doesn't hammer the API
+/// server, while a PriorityClass created later is noticed within a
+/// bounded amount of time.
+pub struct PriorityClasses {
+    ttl: Duration,
+    cache: Mutex<HashMap<String, CachedExistence>>,
+}
+
+impl Default for PriorityClasses {
+    /// PriorityClasses are re-checked every probe interval.
+    fn default() -> Self {
+        PriorityClasses::new(PROBE_INTERVAL)
+    }
+}
+
+impl PriorityClasses {
+    pub fn new(ttl: Duration) -> Self {
+        PriorityClasses {
+            ttl,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the warnings to display in the MaskProvider's status if the
+    /// PriorityClass named in the verification settings doesn't exist.
+    /// Nothing is checked if verification is skipped.
+    pub async fn warnings(
+        &self,
+        client: Client,
+        verify: Option<&MaskProviderVerifySpec>,
+    ) -> Result<Vec<String>, Error> {
+        let name = match verify.filter(|v| v.skip != Some(true)) {
+            Some(MaskProviderVerifySpec {
+                priority_class_name: Some(name),
+                ..
+            }) => name,
+            _ => return Ok(Vec::new()),
+        };
+        Ok(match self.exists(client, name).await? {
+            true => Vec::new(),
+            false => vec![messages::unknown_priority_class(name)],
+        })
+    }
+
+    /// Returns true if the PriorityClass exists, using the cache if possible.
+    async fn exists(&self, client: Client, name: &str) -> Result<bool, Error> {
+        if let Some((checked, exists)) = self.cache.lock().unwrap().get(name) {
+            if checked.elapsed() < self.ttl {
+                return Ok(*exists);
+            }
+        }
+        let api: Api<PriorityClass> = Api::all(client);
+        let exists = match api.get(name).await {
+            Ok(_) => true,
+            Err(kube::Error::Api(e)) if e.code == 404 => false,
+            Err(e) => return Err(e.into()),
+        };
+        // Expired answers are dropped so PriorityClasses that are no
+        // longer named don't stay cached.
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (checked, _)| checked.elapsed() < self.ttl);
+        cache.insert(name.to_owned(), (Instant::now(), exists));
+        Ok(exists)
+    }
+}
@@ -1,35 +1,95 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use futures::stream::StreamExt;
-use k8s_openapi::api::core::v1::{Pod, PodStatus, Secret};
+use k8s_openapi::api::core::v1::{ConfigMap, Pod, PodStatus, Secret};
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
 use kube::{
-    api::ListParams, client::Client, runtime::controller::Action, runtime::Controller, Api,
-    ResourceExt,
+    api::ListParams,
+    client::Client,
+    runtime::{
+        controller::Action,
+        events::Reporter,
+        reflector::{self, Store},
+        Controller,
+    },
+    Api, ResourceExt,
 };
-use lazy_static::lazy_static;
 use std::sync::Arc;
-use tokio::time::Duration;
+use tokio::{sync::Semaphore, time::Duration};
+use vpn_render::{PROBE_CONTAINER_NAME, VPN_CONTAINER_NAME};
 use vpn_types::*;
 
-use super::actions::{self, get_verify_mask_name, PROBE_CONTAINER_NAME, VPN_CONTAINER_NAME};
+use super::{
+    actions::{self, get_verify_mask_name},
+    assignments,
+    canary::{self, CanaryOutcome},
+    capacity::{self, Capacity},
+    disruption, gluetun_version, namespaces,
+    operation::{self, VerifyStep},
+    overview,
+    placement::{self, PriorityClasses},
+    suffix, transforms, verify_assert,
+    verify_defaults::{cycle_verify, effective_verify},
+    verify_failure, verify_hash, verify_keys, verify_queue, verify_schedule,
+    watches::{
+        owned_mask_list_params, secret_list_params, secret_providers, verification_list_params,
+        verify_consumer_provider, verify_pod_provider,
+    },
+};
 use crate::{
+    consumers::{account, actions::validate_copy_encryption, secret_template},
     masks::util::get_consumer,
     util::{
+        capabilities::Capabilities,
+        clock::Clock,
+        config::OperatorConfig,
+        events, explain,
         finalizer::{self, FINALIZER_NAME},
-        Error, PROBE_INTERVAL,
+        is_canary_reservation, is_verification_reservation,
+        list::list_provider_reservations,
+        messages,
+        patch::{ensure_status_initialized, record_action_error, status_phase},
+        preview, report_reconcile_error,
+        schedule::{self, Availability},
+        status_age, ControllerOptions, Error, PROBE_INTERVAL, PROVIDER_SECRET_LABEL,
     },
 };
 
 #[cfg(feature = "metrics")]
-use crate::util::metrics::ControllerMetrics;
-
-/// Entrypoint for the `MaskProvider` controller.
-pub async fn run(client: Client) -> Result<(), Error> {
+use crate::util::metrics::{observe_provider, ControllerMetrics, VERIFICATION_QUEUE_DEPTH_GAUGE};
+
+/// Entrypoint for the `MaskProvider` controller. If `concurrency` is set, at most
+/// that many reconciliations will be performed at the same time. If
+/// `canary_interval` is set, MaskProviders with `spec.canary` are assigned
+/// a canary Mask that often. The default verification settings are read
+/// from `config`. If `max_verifications` is set, at most that many
+/// MaskProviders are verified at the same time across the cluster.
+pub async fn run(
+    client: Client,
+    concurrency: Option<usize>,
+    canary_interval: Option<Duration>,
+    max_verifications: Option<usize>,
+    config: Arc<OperatorConfig>,
+    capabilities: Capabilities,
+    options: ControllerOptions,
+) -> Result<(), Error> {
     println!("Starting MaskProvider controller...");
 
+    // Canaries in progress when the operator stopped can't be timed.
+    match canary::cleanup(client.clone()).await {
+        Ok(0) => {}
+        Ok(deleted) => println!("Deleted {} canary Masks left behind.", deleted),
+        Err(e) => eprintln!("Failed to delete canary Masks left behind: {:?}", e),
+    }
+
+    // Show the health of the whole fleet in the VpnFleet.
+    tokio::spawn(overview::run(client.clone(), overview::WRITE_INTERVAL));
+
+    // Cache the MaskConsumers to count the ones waiting for a slot.
+    let (consumers, writer) = reflector::store();
+    tokio::spawn(capacity::watch_consumers(client.clone(), writer));
+
     // Preparation of resources used by the `kube_runtime::Controller`
     let crd_api: Api<MaskProvider> = Api::all(client.clone());
-    let context: Arc<ContextData> = Arc::new(ContextData::new(client.clone()));
 
     // The controller comes from the `kube_runtime` crate and manages the reconciliation process.
     // It requires the following information:
@@ -37,14 +97,48 @@ pub async fn run(client: Client) -> Result<(), Error> {
     // - `kube::api::ListParams` to select the `MaskProvider` resources with. Can be used for MaskProvider filtering `MaskProvider` resources before reconciliation,
     // - `reconcile` function with reconciliation logic to be called each time a resource of `MaskProvider` kind is created/updated/deleted,
     // - `on_error` function to call whenever reconciliation fails.
-    Controller::new(crd_api, ListParams::default())
+    let controller = Controller::new(crd_api, ListParams::default());
+    let providers = controller.store();
+    let context: Arc<ContextData> = Arc::new(ContextData::new(
+        client.clone(),
+        concurrency,
+        canary_interval,
+        max_verifications,
+        config,
+        capabilities,
+        consumers,
+        providers.clone(),
+        options,
+    ));
+    controller
         // The controller uses `MaskReservation` resources to reserve slots.
         .owns(
             Api::<MaskReservation>::all(client.clone()),
             ListParams::default(),
         )
-        // The controller uses a special `Mask` to verify the credentials.
-        .owns(Api::<Mask>::all(client), ListParams::default())
+        // The controller uses special `Mask`s to verify the credentials
+        // and for canaries, which are the only ones it needs to watch.
+        .owns(Api::<Mask>::all(client.clone()), owned_mask_list_params())
+        // React to the verification `MaskConsumer` and Pod as soon as they
+        // change, rather than waiting for the next requeue.
+        .watches(
+            Api::<MaskConsumer>::all(client.clone()),
+            verification_list_params(),
+            verify_consumer_provider,
+        )
+        .watches(
+            Api::<Pod>::all(client.clone()),
+            verification_list_params(),
+            verify_pod_provider,
+        )
+        // Record the checksum of the credentials as soon as they change.
+        // Only the Secrets labelled as referenced by a MaskProvider are
+        // watched, rather than caching every Secret in the cluster.
+        .watches(
+            Api::<Secret>::all(client),
+            secret_list_params(),
+            move |secret| secret_providers(&providers, &secret),
+        )
         .run(reconcile, on_error, context)
         .for_each(|_reconciliation_result| async move {
             //match reconciliation_result {
@@ -68,6 +162,44 @@ struct ContextData {
     /// Kubernetes client to make Kubernetes API requests with. Required for K8S resource management.
     client: Client,
 
+    /// Limits the number of concurrent reconciliations, if configured.
+    semaphore: Option<Semaphore>,
+
+    /// Source of the current time for checking availability hours.
+    clock: Clock,
+
+    /// How often MaskProviders with `spec.canary` are assigned a canary
+    /// Mask, or None if canaries are disabled.
+    canary_interval: Option<Duration>,
+
+    /// Maximum number of MaskProviders verified at the same time
+    /// across the cluster, or None if unlimited.
+    max_verifications: Option<usize>,
+
+    /// Runtime configuration with the default verification settings.
+    config: Arc<OperatorConfig>,
+
+    /// Features of the API server detected at startup, which decide
+    /// how disrupted verification Pods are recognized.
+    capabilities: Capabilities,
+
+    /// Cache of every MaskConsumer, which the pending demand is
+    /// counted from.
+    consumers: Store<MaskConsumer>,
+
+    /// The controller's cache of every MaskProvider, which stable
+    /// secret suffix collisions are found in.
+    providers: Store<MaskProvider>,
+
+    /// Whether the PriorityClasses of verification Pods exist.
+    priority_classes: PriorityClasses,
+
+    /// Reports the resources' phase transitions as events.
+    reporter: Reporter,
+
+    /// Settings from the command line, e.g. whether actions are explained.
+    options: ControllerOptions,
+
     #[cfg(feature = "metrics")]
     metrics: ControllerMetrics,
 }
@@ -77,57 +209,214 @@ impl ContextData {
     ///
     /// # Arguments:
     /// - `client`: A Kubernetes client to make Kubernetes REST API requests with. Resources
-    /// will be created and deleted with this client.
-    pub fn new(client: Client) -> Self {
+    ///   will be created and deleted with this client.
+    /// - `concurrency`: Optional maximum number of concurrent reconciliations.
+    /// - `canary_interval`: How often to assign canary Masks, if at all.
+    /// - `max_verifications`: Optional maximum number of concurrent verifications.
+    /// - `config`: Runtime configuration with the default verification settings.
+    /// - `capabilities`: Features of the API server detected at startup.
+    /// - `consumers`: Cache of every MaskConsumer.
+    /// - `providers`: The controller's cache of every MaskProvider.
+    /// - `options`: Settings from the command line.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        client: Client,
+        concurrency: Option<usize>,
+        canary_interval: Option<Duration>,
+        max_verifications: Option<usize>,
+        config: Arc<OperatorConfig>,
+        capabilities: Capabilities,
+        consumers: Store<MaskConsumer>,
+        providers: Store<MaskProvider>,
+        options: ControllerOptions,
+    ) -> Self {
+        let semaphore = concurrency.map(Semaphore::new);
+        let priority_classes = PriorityClasses::default();
         #[cfg(feature = "metrics")]
         {
-            return ContextData {
+            ContextData {
                 client,
+                semaphore,
+                options,
+                reporter: events::reporter(),
+                clock: Clock::System,
+                canary_interval,
+                max_verifications,
+                config,
+                capabilities,
+                consumers,
+                providers,
+                priority_classes,
                 metrics: ControllerMetrics::new("providers"),
-            };
+            }
         }
         #[cfg(not(feature = "metrics"))]
         {
-            return ContextData { client };
+            return ContextData {
+                client,
+                semaphore,
+                options,
+                reporter: events::reporter(),
+                clock: Clock::System,
+                canary_interval,
+                max_verifications,
+                config,
+                capabilities,
+                consumers,
+                providers,
+                priority_classes,
+            };
         }
     }
 }
 
 /// Action to be taken upon an `MaskProvider` resource during reconciliation
 #[derive(Debug, PartialEq)]
-enum MaskProviderAction {
+pub(crate) enum MaskProviderAction {
     /// Set the `MaskProvider` resource status.phase to Pending.
     Pending,
 
     /// Cleans up all subresources across all namespaces.
     Delete,
 
+    /// Hold the finalizer because `MaskConsumer`s are still assigned
+    /// slots. Contains the status message naming the `MaskConsumer`s.
+    DeleteBlocked(String),
+
+    /// Delete the contained `MaskReservation`s, unassigning their
+    /// `MaskConsumer`s, because deletion was forced with an annotation.
+    ForceDelete(Vec<MaskReservation>),
+
     /// Set the `MaskProvider` resource status.phase to ErrSecretNotFound.
     SecretNotFound,
 
-    /// Create a Mask to reserve a slot for verification.
-    CreateVerifyMask,
+    /// Set the `MaskProvider` resource status.phase to ErrSecretSuffixCollision
+    /// because the contained `MaskProvider` already uses its stable secret suffix.
+    SecretSuffixCollision(Box<MaskProvider>),
+
+    /// Set the `MaskProvider` resource status.phase to ErrAccountNotFound
+    /// because the contained `VpnAccount` name doesn't exist.
+    AccountNotFound(String),
+
+    /// Set the `MaskProvider` resource status.phase to ErrConfig.
+    /// Contains the message explaining the invalid configuration.
+    ConfigError(String),
+
+    /// Write the contained `Secret` with the transformed credentials.
+    WriteDerivedSecret(Box<Secret>),
+
+    /// Label the credentials Secret so that its changes are watched.
+    LabelSecret,
+
+    /// Record the contained checksum of the credentials copied for
+    /// `Mask`s in the status, because the Secret changed.
+    RecordSecretHash(String),
+
+    /// Write the contained `ConfigMap` with the preview requested
+    /// with the explain annotation.
+    WritePreview(Box<ConfigMap>),
+
+    /// Create a Mask to reserve a slot for verification. `manual` is true
+    /// if verification was requested with the verify-now annotation, and
+    /// `verify` are the effective settings the cycle is recorded to use.
+    CreateVerifyMask {
+        manual: bool,
+        verify: Option<MaskProviderVerifySpec>,
+    },
+
+    /// Leave the status Verifying because too many other `MaskProvider`s
+    /// are being verified. Contains the position in the queue.
+    VerifyQueued(usize),
 
     /// Create a gluetun pod and verify that the external IP changes.
-    CreateVerifyPod(MaskConsumer),
+    CreateVerifyPod(Box<MaskConsumer>),
 
-    /// Set the status to Verifying.
+    /// Set the status to Verifying, having reached `step`. `start_time`
+    /// is when the step's time limit began, if it has one.
     Verifying {
         message: String,
+        step: VerifyStep,
         start_time: Option<Time>,
     },
 
-    /// Set the status to Verified.
-    Verified,
+    /// Set the status to Verified. Contains the name of the node
+    /// the verification Pod ran on, if it was scheduled, and the note
+    /// the probe left in its termination message, if any.
+    Verified {
+        node: Option<String>,
+        note: Option<String>,
+    },
+
+    /// Delete the verification Pod because it was disrupted, so it's
+    /// recreated. `rescheduled` are the uids of the Pods recreated during
+    /// the cycle, including this one, and `reason` is the disruption.
+    RescheduleVerifyPod {
+        rescheduled: Vec<String>,
+        reason: String,
+    },
+
+    /// Wait for the verification Pod and Mask to finish deleting
+    /// before resuming the periodic status updates.
+    AwaitVerifyCleanup,
 
     /// Set the status to ErrVerifyFailed.
     VerifyFailed(String),
 
+    /// Set the status to Verified, the credentials Secret having passed
+    /// the static checks of `verify.mode: KeysOnly`. `manual` is true if
+    /// verification was requested with the verify-now annotation, and
+    /// `verify` are the settings the checks used.
+    KeysVerified {
+        manual: bool,
+        verify: Option<MaskProviderVerifySpec>,
+    },
+
+    /// Set the status to ErrVerifyFailed, the credentials Secret having
+    /// failed the static checks of `verify.mode: KeysOnly`.
+    KeysVerifyFailed { manual: bool, message: String },
+
+    /// Delete the contained `MaskConsumer`s, because the `MaskProvider`
+    /// failed re-verification and has `onVerifyFailure: Evict`.
+    EvictOnVerifyFailure(Vec<MaskConsumer>),
+
+    /// Create a canary Mask to check the MaskProvider can be assigned.
+    CreateCanaryMask,
+
+    /// Record the outcome of the canary as of `at` and delete its Mask.
+    CanaryFinished {
+        outcome: CanaryOutcome,
+        at: DateTime<Utc>,
+    },
+
+    /// Delete the canary Mask because canaries were disabled.
+    DeleteCanaryMask,
+
+    /// Delete the contained `MaskReservation`s, unassigning their
+    /// `MaskConsumer`s, because the `MaskProvider` is outside of its
+    /// availability hours and `drainOutOfHours` is set.
+    DrainOutOfHours(Vec<MaskReservation>),
+
     /// Set the `MaskProvider` resource status.phase to Ready.
-    Ready,
+    Ready {
+        capacity: Capacity,
+        warnings: Vec<String>,
+        account: Option<AccountUtilization>,
+        availability: Option<Availability>,
+        next_verification: Option<String>,
+    },
 
-    /// Set the `MaskProvider` resource status.phase to Active.
-    Active { active_slots: usize },
+    /// Set the `MaskProvider` resource status.phase to Active. `operation`
+    /// is the drain that is still unassigning the `MaskConsumer`s, if any.
+    Active {
+        active_slots: usize,
+        capacity: Capacity,
+        recent_assignments: Vec<RecentAssignment>,
+        warnings: Vec<String>,
+        account: Option<AccountUtilization>,
+        availability: Option<Availability>,
+        operation: Option<MaskProviderOperation>,
+        next_verification: Option<String>,
+    },
 
     /// This `MaskProvider` resource is in desired state and requires no actions to be taken
     NoOp,
@@ -138,13 +427,32 @@ impl MaskProviderAction {
         match self {
             MaskProviderAction::Pending => "Pending",
             MaskProviderAction::Delete => "Delete",
+            MaskProviderAction::DeleteBlocked(_) => "DeleteBlocked",
+            MaskProviderAction::ForceDelete(_) => "ForceDelete",
             MaskProviderAction::SecretNotFound => "SecretNotFound",
-            MaskProviderAction::CreateVerifyMask => "CreateVerifyMask",
+            MaskProviderAction::SecretSuffixCollision(_) => "SecretSuffixCollision",
+            MaskProviderAction::AccountNotFound(_) => "AccountNotFound",
+            MaskProviderAction::ConfigError(_) => "ConfigError",
+            MaskProviderAction::WriteDerivedSecret(_) => "WriteDerivedSecret",
+            MaskProviderAction::LabelSecret => "LabelSecret",
+            MaskProviderAction::RecordSecretHash(_) => "RecordSecretHash",
+            MaskProviderAction::WritePreview(_) => "WritePreview",
+            MaskProviderAction::CreateVerifyMask { .. } => "CreateVerifyMask",
+            MaskProviderAction::VerifyQueued(_) => "VerifyQueued",
             MaskProviderAction::CreateVerifyPod(_) => "CreateVerifyPod",
             MaskProviderAction::Verifying { .. } => "Verifying",
-            MaskProviderAction::Verified => "Verified",
+            MaskProviderAction::Verified { .. } => "Verified",
+            MaskProviderAction::RescheduleVerifyPod { .. } => "RescheduleVerifyPod",
+            MaskProviderAction::AwaitVerifyCleanup => "AwaitVerifyCleanup",
             MaskProviderAction::VerifyFailed(_) => "VerifyFailed",
-            MaskProviderAction::Ready => "Ready",
+            MaskProviderAction::KeysVerified { .. } => "KeysVerified",
+            MaskProviderAction::KeysVerifyFailed { .. } => "KeysVerifyFailed",
+            MaskProviderAction::EvictOnVerifyFailure(_) => "EvictOnVerifyFailure",
+            MaskProviderAction::CreateCanaryMask => "CreateCanaryMask",
+            MaskProviderAction::CanaryFinished { .. } => "CanaryFinished",
+            MaskProviderAction::DeleteCanaryMask => "DeleteCanaryMask",
+            MaskProviderAction::DrainOutOfHours(_) => "DrainOutOfHours",
+            MaskProviderAction::Ready { .. } => "Ready",
             MaskProviderAction::Active { .. } => "Active",
             MaskProviderAction::NoOp => "NoOp",
         }
@@ -156,6 +464,12 @@ async fn reconcile(
     instance: Arc<MaskProvider>,
     context: Arc<ContextData>,
 ) -> Result<Action, Error> {
+    // Wait for a permit if the number of concurrent reconciliations is limited.
+    let _permit = match context.semaphore {
+        Some(ref semaphore) => Some(semaphore.acquire().await.unwrap()),
+        None => None,
+    };
+
     // The `Client` is shared -> a clone from the reference is obtained
     let client: Client = context.client.clone();
 
@@ -179,38 +493,51 @@ async fn reconcile(
     let name = instance.name_any();
 
     #[cfg(feature = "metrics")]
-    context
-        .metrics
-        .reconcile_counter
-        .with_label_values(&[&name, &namespace])
-        .inc();
+    context.metrics.reconcile(&name, &namespace);
+
+    // Export the MaskProvider's current state for dashboards and alerts.
+    #[cfg(feature = "metrics")]
+    observe_provider(&instance, context.options.info_metrics);
 
     // Benchmark the read phase of reconciliation.
     #[cfg(feature = "metrics")]
     let start = std::time::Instant::now();
 
+    // Merge the MaskProvider's verification settings onto the default.
+    let verify = effective_verify(&instance, context.config.default_verify().as_ref())?;
+
     // Read phase of reconciliation determines goal during the write phase.
-    let action = determine_action(client.clone(), &name, &namespace, &instance).await?;
+    let now = context.clock.now();
+    let action = determine_action(
+        client.clone(),
+        &name,
+        &namespace,
+        &instance,
+        verify,
+        context.canary_interval,
+        &context.capabilities,
+        &context.priority_classes,
+        &context.consumers.state(),
+        &context.providers.state(),
+        now,
+    )
+    .await?;
+
+    // Defer the verification if too many are already in flight.
+    let action = match context.max_verifications {
+        Some(max) => queue_verification(client.clone(), &instance, max, action).await?,
+        None => action,
+    };
 
     if action != MaskProviderAction::NoOp {
         println!("{}/{} ACTION: {:?}", namespace, name, action.to_str());
     }
 
-    // Report the read phase performance.
+    // Report the read phase performance and count the action.
     #[cfg(feature = "metrics")]
     context
         .metrics
-        .read_histogram
-        .with_label_values(&[&name, &namespace, action.to_str()])
-        .observe(start.elapsed().as_secs_f64());
-
-    // Increment the counter for the action.
-    #[cfg(feature = "metrics")]
-    context
-        .metrics
-        .action_counter
-        .with_label_values(&[&name, &namespace, action.to_str()])
-        .inc();
+        .read(&name, &namespace, action.to_str(), start);
 
     // Benchmark the write phase of reconciliation.
     #[cfg(feature = "metrics")]
@@ -218,23 +545,50 @@ async fn reconcile(
         // Don't measure performance for NoOp actions.
         MaskProviderAction::NoOp => None,
         // Start a performance timer for the write phase.
-        _ => Some(
-            context
-                .metrics
-                .write_histogram
-                .with_label_values(&[&name, &namespace, action.to_str()])
-                .start_timer(),
-        ),
+        _ => Some(context.metrics.write(&name, &namespace, action.to_str())),
     };
 
-    // Performs action as decided by the `determine_action` function.
-    // This is the write phase of reconciliation.
-    let result = match action {
+    // Perform the action. The action's name is attached to any error
+    // so the error handler can report which action failed, and to the
+    // events published for the phase transitions it makes.
+    let action_name = action.to_str().to_owned();
+    let result = events::scope(
+        context.reporter.clone(),
+        &action_name,
+        explain::scope(
+            context.options.explain,
+            "providers",
+            &action_name,
+            apply_action(client, &name, &namespace, &instance, action, now),
+        ),
+    )
+    .await;
+
+    // Report the write phase performance with the action's outcome.
+    #[cfg(feature = "metrics")]
+    if let Some(timer) = timer {
+        timer.observe(&result);
+    }
+
+    result.map_err(|e| Error::action(&action_name, e))
+}
+
+/// Performs the action as decided by the `determine_action` function.
+/// This is the write phase of reconciliation, as of the same `now`.
+async fn apply_action(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    instance: &MaskProvider,
+    action: MaskProviderAction,
+    now: DateTime<Utc>,
+) -> Result<Action, Error> {
+    Ok(match action {
         MaskProviderAction::Pending => {
             // Give the `MaskProvider` resource a finalizer. This will be done
             // regardless of whether we do it now, but doing it now might
             // increase performance.
-            let instance = finalizer::add(client.clone(), &name, &namespace).await?;
+            let instance = finalizer::add(client.clone(), name, namespace).await?;
 
             // Update the phase of the `MaskProvider` resource to Pending.
             actions::pending(client, &instance).await?;
@@ -245,30 +599,111 @@ async fn reconcile(
         MaskProviderAction::Delete => {
             // Update the phase to Terminating. This will prevent the provider
             // from being assigned to new MaskConsumers.
-            actions::terminating(client.clone(), &instance).await?;
+            actions::terminating(client.clone(), instance).await?;
 
             // Remove the finalizer, which will allow the MaskProvider resource to be deleted.
-            finalizer::delete::<MaskProvider>(client, &name, &namespace).await?;
+            finalizer::delete::<MaskProvider>(client, name, namespace).await?;
 
             // No need to requeue as the resource is being deleted.
             Action::await_change()
         }
+        MaskProviderAction::DeleteBlocked(message) => {
+            // Name the blocking MaskConsumers in the status object.
+            actions::deletion_blocked(client, instance, message).await?;
+
+            // Requeue after a while in case the annotation is added. The
+            // reservations draining will also trigger reconciliation.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        MaskProviderAction::ForceDelete(reservations) => {
+            // Unassign the MaskConsumers while the MaskProvider still exists,
+            // so their credentials are removed before it goes away.
+            let remaining = actions::unassign_all(client.clone(), instance, &reservations).await?;
+
+            // Show how many are left to unassign.
+            let operation = operation::draining(instance, remaining, now);
+            actions::record_operation(client, instance, operation).await?;
+
+            // Check back shortly to remove the finalizer once the
+            // reservations have finished deleting.
+            Action::requeue(Duration::from_secs(2))
+        }
         MaskProviderAction::SecretNotFound => {
             // Reflect the error in the status object.
-            actions::secret_not_found(client, &instance).await?;
+            actions::secret_not_found(client, instance).await?;
 
             // Requeue after a while if the resource doesn't change.
             Action::requeue(PROBE_INTERVAL)
         }
-        MaskProviderAction::CreateVerifyMask => {
+        MaskProviderAction::SecretSuffixCollision(other) => {
+            // Reflect the error in the status object.
+            actions::secret_suffix_collision(client, instance, &other).await?;
+
+            // Requeue after a while in case the other MaskProvider changes.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        MaskProviderAction::AccountNotFound(account) => {
+            // Reflect the error in the status object.
+            actions::account_not_found(client, instance, &account).await?;
+
+            // Requeue after a while in case the VpnAccount is created.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        MaskProviderAction::ConfigError(message) => {
+            // Reflect the error in the status object.
+            actions::config_error(client, instance, message).await?;
+
+            // Requeue after a while in case the credentials change.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        MaskProviderAction::WriteDerivedSecret(secret) => {
+            // Keep the transformed credentials in sync with the source.
+            actions::write_derived_secret(client, namespace, *secret).await?;
+
+            // Requeue immediately to proceed with reconciliation.
+            Action::requeue(Duration::ZERO)
+        }
+        MaskProviderAction::LabelSecret => {
+            // Have the Secret's changes reach the controller right away.
+            actions::label_secret(client, namespace, instance).await?;
+
+            // Requeue immediately to proceed with reconciliation.
+            Action::requeue(Duration::ZERO)
+        }
+        MaskProviderAction::RecordSecretHash(hash) => {
+            // Let the copies be checked against the current credentials.
+            actions::record_secret_hash(client, instance, hash).await?;
+
+            // Requeue immediately to proceed with reconciliation.
+            Action::requeue(Duration::ZERO)
+        }
+        MaskProviderAction::WritePreview(config_map) => {
+            // Overwrite the previous preview, without creating anything.
+            match preview::write(client, *config_map).await? {
+                // Requeue immediately to proceed with reconciliation.
+                true => Action::requeue(Duration::ZERO),
+                // Previews aren't enabled in the namespace.
+                false => Action::requeue(preview::FORBIDDEN_RETRY),
+            }
+        }
+        MaskProviderAction::CreateVerifyMask { manual, verify } => {
             // Create the verification Mask.
-            actions::create_verify_mask(client.clone(), &name, &namespace, &instance).await?;
+            actions::create_verify_mask(client.clone(), name, namespace, instance).await?;
 
-            // Indicate that verification is in progress.
-            actions::verify_progress(
+            // Mark the manual trigger so it shows up in the resource's events.
+            if manual {
+                actions::manual_verify_event(client.clone(), instance).await;
+            }
+
+            // Indicate that verification is in progress, recording the
+            // settings the rest of the cycle uses. The Mask has until the
+            // timeout to be assigned a slot.
+            let deadline = operation::deadline(now, verify_timeout(verify.as_ref()));
+            actions::verify_started(
                 client,
-                &instance,
-                None,
+                instance,
+                verify,
+                operation::verify_started(deadline, now),
                 "Created verification Mask.".to_owned(),
             )
             .await?;
@@ -276,17 +711,31 @@ async fn reconcile(
             // Requeue after a short delay to allow the verification time to complete.
             Action::requeue(PROBE_INTERVAL)
         }
+        MaskProviderAction::VerifyQueued(position) => {
+            // Show the MaskProvider's place in the queue.
+            let message = messages::verify_queued(position);
+            actions::verify_progress(client, instance, None, message).await?;
+
+            // Requeue after a while to check whether it's its turn.
+            Action::requeue(PROBE_INTERVAL)
+        }
         MaskProviderAction::CreateVerifyPod(consumer) => {
             // Create the verification pod.
             let pod =
-                actions::create_verify_pod(client.clone(), &name, &namespace, &instance, &consumer)
+                actions::create_verify_pod(client.clone(), name, namespace, instance, &consumer)
                     .await?;
 
             // Indicate that verification is in progress.
+            let operation = verify_operation(
+                instance,
+                VerifyStep::PodCreated,
+                pod.metadata.creation_timestamp,
+                now,
+            );
             actions::verify_progress(
                 client,
-                &instance,
-                pod.metadata.creation_timestamp,
+                instance,
+                Some(operation),
                 "Created verification Pod.".to_owned(),
             )
             .await?;
@@ -295,90 +744,233 @@ async fn reconcile(
             Action::requeue(PROBE_INTERVAL)
         }
         MaskProviderAction::Verifying {
-            start_time,
             message,
+            step,
+            start_time,
         } => {
             // Post the progress to the status object.
-            actions::verify_progress(client, &instance, start_time, message).await?;
+            let operation = verify_operation(instance, step, start_time, now);
+            actions::verify_progress(client, instance, Some(operation), message).await?;
 
             // Requeue after a short delay to allow the verification time to complete.
             Action::requeue(PROBE_INTERVAL)
         }
         MaskProviderAction::VerifyFailed(message) => {
             // Update the phase of the `MaskProvider` resource to Verified.
-            actions::verify_failed(client.clone(), &instance, message).await?;
+            let trigger = actions::get_verify_trigger(client.clone(), name, namespace).await?;
+            actions::verify_failed(client.clone(), instance, message, trigger).await?;
 
             // Delete the verification Pod so it can be recreated.
-            actions::delete_verify_pod(client.clone(), &name, &namespace).await?;
+            actions::delete_verify_pod(client.clone(), name, namespace).await?;
 
             // Delete the verification Mask so it can be recreated.
-            actions::delete_verify_mask(client, &name, &namespace).await?;
+            actions::delete_verify_mask(client, name, namespace).await?;
 
             // Requeue after a delay so the user has time to see the error phase.
             Action::requeue(PROBE_INTERVAL)
         }
-        MaskProviderAction::Verified => {
+        MaskProviderAction::KeysVerified { manual, verify } => {
+            // Mark the manual trigger so it shows up in the resource's events.
+            let trigger = match manual {
+                true => {
+                    actions::manual_verify_event(client.clone(), instance).await;
+                    actions::verify_now(instance).map(str::to_owned)
+                }
+                false => None,
+            };
+
+            // Set the timestamp of when the checks passed. Nothing was
+            // created, so there's nothing to clean up.
+            actions::keys_verified(client, instance, verify, trigger).await?;
+
+            // Requeue immediately to proceed with reconciliation.
+            Action::requeue(Duration::ZERO)
+        }
+        MaskProviderAction::KeysVerifyFailed { manual, message } => {
+            // A failed check still satisfies a manual trigger.
+            let trigger = match manual {
+                true => actions::verify_now(instance).map(str::to_owned),
+                false => None,
+            };
+            actions::verify_failed(client, instance, message, trigger).await?;
+
+            // Requeue after a while in case the credentials change.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        MaskProviderAction::EvictOnVerifyFailure(consumers) => {
+            // Stop the MaskConsumers from using the failed credentials.
+            verify_failure::evict(client, instance, &consumers).await?;
+
+            // Requeue shortly to retry verification once they're gone.
+            Action::requeue(Duration::from_secs(2))
+        }
+        MaskProviderAction::Verified { node, note } => {
+            // Record where the credentials were verified from.
+            let zone = match node.as_deref() {
+                Some(node) => placement::get_node_zone(client.clone(), node).await?,
+                None => None,
+            };
+
             // Set the timestamp of when the verification completed.
-            actions::verified(client.clone(), &instance).await?;
+            let trigger = actions::get_verify_trigger(client.clone(), name, namespace).await?;
+            actions::verified(client.clone(), instance, trigger, node, zone, note).await?;
 
             // Delete the verification Pod.
-            actions::delete_verify_pod(client.clone(), &name, &namespace).await?;
+            actions::delete_verify_pod(client.clone(), name, namespace).await?;
 
             // Delete the verification Mask.
-            actions::delete_verify_mask(client, &name, &namespace).await?;
+            actions::delete_verify_mask(client, name, namespace).await?;
 
             // Requeue immediately to proceed with reconciliation.
             Action::requeue(Duration::ZERO)
         }
-        MaskProviderAction::Ready => {
+        MaskProviderAction::RescheduleVerifyPod {
+            rescheduled,
+            reason,
+        } => {
+            // Count the Pod towards the cycle's limit before deleting it.
+            disruption::record_reschedules(client.clone(), name, namespace, &rescheduled).await?;
+
+            // Delete the verification Pod. It's recreated once it's gone,
+            // as the verification Mask is still Active.
+            actions::delete_verify_pod(client.clone(), name, namespace).await?;
+
+            // Show why verification is taking longer. The slot is still
+            // assigned, so the cycle is back to waiting for its Pod.
+            let operation = verify_operation(instance, VerifyStep::SlotAssigned, None, now);
+            actions::verify_progress(
+                client,
+                instance,
+                Some(operation),
+                messages::verify_pod_rescheduled(&reason),
+            )
+            .await?;
+
+            // Check back shortly to recreate the Pod once it's gone.
+            Action::requeue(Duration::from_secs(2))
+        }
+        MaskProviderAction::AwaitVerifyCleanup => {
+            // Check back shortly so the status is updated promptly
+            // once the verification resources are gone.
+            Action::requeue(Duration::from_secs(2))
+        }
+        MaskProviderAction::CreateCanaryMask => {
+            // The canary is checked on as its Mask changes.
+            canary::create_mask(client, name, namespace, instance).await?;
+
+            // Requeue in case the canary times out without changing.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        MaskProviderAction::CanaryFinished { outcome, at } => {
+            // Record the outcome before the Mask is gone.
+            canary::finished(client.clone(), instance, outcome, at).await?;
+
+            // Release the canary's slot.
+            canary::delete_mask(client, name, namespace).await?;
+
+            // Requeue after a short delay.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        MaskProviderAction::DeleteCanaryMask => {
+            // Release the canary's slot without recording an outcome.
+            canary::delete_mask(client, name, namespace).await?;
+
+            // Requeue after a short delay.
+            Action::requeue(PROBE_INTERVAL)
+        }
+        MaskProviderAction::DrainOutOfHours(reservations) => {
+            // Unassign the MaskConsumers until the availability hours resume.
+            let remaining =
+                actions::drain_out_of_hours(client.clone(), instance, &reservations).await?;
+
+            // Show how many are left to unassign.
+            let operation = operation::draining(instance, remaining, now);
+            actions::record_operation(client, instance, operation).await?;
+
+            // Check back shortly to update the status once the
+            // reservations have finished deleting.
+            Action::requeue(Duration::from_secs(2))
+        }
+        MaskProviderAction::Ready {
+            capacity,
+            warnings,
+            account,
+            availability,
+            next_verification,
+        } => {
             // Update the phase of the `MaskProvider` resource to Ready.
-            actions::ready(client, &instance).await?;
+            actions::ready(
+                client,
+                instance,
+                capacity,
+                warnings,
+                account,
+                availability,
+                next_verification,
+            )
+            .await?;
 
             // Requeue after a short delay.
             Action::requeue(PROBE_INTERVAL)
         }
-        MaskProviderAction::Active { active_slots } => {
+        MaskProviderAction::Active {
+            active_slots,
+            capacity,
+            recent_assignments,
+            warnings,
+            account,
+            availability,
+            operation,
+            next_verification,
+        } => {
             // Update the phase of the `MaskProvider` resource to Active.
-            actions::active(client, &instance, active_slots).await?;
+            actions::active(
+                client,
+                instance,
+                active_slots,
+                capacity,
+                recent_assignments,
+                warnings,
+                account,
+                availability,
+                operation,
+                next_verification,
+            )
+            .await?;
 
             // Requeue after a short delay.
             Action::requeue(PROBE_INTERVAL)
         }
         // The resource is already in desired state, do nothing and re-check after 10 seconds
         MaskProviderAction::NoOp => Action::requeue(PROBE_INTERVAL),
-    };
-
-    #[cfg(feature = "metrics")]
-    if let Some(timer) = timer {
-        timer.observe_duration();
-    }
+    })
+}
 
-    Ok(result)
+/// Returns the operation of the MaskProvider's verification cycle having
+/// reached the step, which times out with the cycle's verification timeout
+/// after `start_time`, if it has one.
+fn verify_operation(
+    instance: &MaskProvider,
+    step: VerifyStep,
+    start_time: Option<Time>,
+    now: DateTime<Utc>,
+) -> MaskProviderOperation {
+    let deadline = start_time.and_then(|t| operation::deadline(t.0, get_verify_timeout(instance)));
+    operation::verifying(instance, step, deadline, now)
 }
 
 /// needs_pending returns true if the `MaskProvider` resource
 /// requires a status update to set the phase to Pending.
-/// This should be the first action for any managed resource.
+/// This should be the first action for any managed resource,
+/// and is repeated for any status that is missing fields the
+/// controller relies on.
 fn needs_pending(instance: &MaskProvider) -> bool {
-    needs_finalizer(instance) || instance.status.as_ref().map_or(true, |s| s.phase.is_none())
+    needs_finalizer(instance) || ensure_status_initialized(instance).is_err()
 }
 
 /// Returns the phase of the MaskProvider.
 pub fn get_provider_phase(instance: &MaskProvider) -> Result<(MaskProviderPhase, Duration), Error> {
-    let status = instance
-        .status
-        .as_ref()
-        .ok_or_else(|| Error::UserInputError("No status".to_string()))?;
-    let phase = status
-        .phase
-        .ok_or_else(|| Error::UserInputError("No phase".to_string()))?;
-    let last_updated: chrono::DateTime<Utc> = status
-        .last_updated
-        .as_ref()
-        .ok_or_else(|| Error::UserInputError("No lastUpdated".to_string()))?
-        .parse()?;
-    let age: chrono::Duration = Utc::now() - last_updated;
-    Ok((phase, age.to_std()?))
+    status_phase(instance)
 }
 
 /// Gets the secret that contains the credentials for the MaskProvider.
@@ -406,14 +998,28 @@ fn needs_finalizer(instance: &MaskProvider) -> bool {
 ///
 /// # Arguments
 /// - `instance`: A reference to `MaskProvider` being reconciled to decide next action upon.
-async fn determine_action(
+/// - `verify`: The effective verification settings of the `MaskProvider`.
+/// - `capabilities`: Features of the API server detected at startup.
+/// - `priority_classes`: Whether the PriorityClasses of verification Pods exist.
+/// - `consumers`: The cached MaskConsumers, whose waiting ones are the demand.
+/// - `providers`: The cached MaskProviders, whose stable secret suffixes
+///   must not collide with that of `instance`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn determine_action(
     client: Client,
     name: &str,
     namespace: &str,
     instance: &MaskProvider,
+    verify: Option<MaskProviderVerifySpec>,
+    canary_interval: Option<Duration>,
+    capabilities: &Capabilities,
+    priority_classes: &PriorityClasses,
+    consumers: &[Arc<MaskConsumer>],
+    providers: &[Arc<MaskProvider>],
+    now: DateTime<Utc>,
 ) -> Result<MaskProviderAction, Error> {
     if instance.metadata.deletion_timestamp.is_some() {
-        return Ok(MaskProviderAction::Delete);
+        return determine_delete_action(client, instance).await;
     }
 
     // Ensure that the resource has a status object with a phase.
@@ -428,27 +1034,227 @@ async fn determine_action(
     }
 
     // Ensure the MaskProvider credentials secret exists.
-    if get_secret(client.clone(), namespace, instance)
-        .await?
-        .is_none()
-    {
+    let secret = match get_secret(client.clone(), namespace, instance).await? {
+        Some(secret) => secret,
         // The resource specifies using a Secret that doesn't exist.
-        // This is the only error state for the MaskProvider resource.
-        return Ok(MaskProviderAction::SecretNotFound);
+        None => return Ok(MaskProviderAction::SecretNotFound),
+    };
+
+    // Only labelled Secrets are watched for changes to the credentials.
+    if !secret.labels().contains_key(PROVIDER_SECRET_LABEL) {
+        return Ok(MaskProviderAction::LabelSecret);
+    }
+
+    // The names of the copies can't be rendered from an invalid template.
+    if let Err(e) = secret_template::validate(instance) {
+        return Ok(MaskProviderAction::ConfigError(messages::config_error(&e)));
+    }
+
+    // The copies can't be annotated with an invalid or reserved key.
+    if let Err(e) = validate_copy_encryption(instance) {
+        return Ok(MaskProviderAction::ConfigError(messages::config_error(&e)));
+    }
+
+    // Ensure the transformed credentials are current before they're used.
+    if let Some(action) =
+        determine_transform_action(client.clone(), namespace, instance, &secret).await?
+    {
+        return Ok(action);
+    }
+
+    // Record the checksum of the credentials as they're copied, so the
+    // copies can be checked against it without reading the Secret.
+    if let Some(hash) = transforms::effective_hash(instance, &secret) {
+        let recorded = instance
+            .status
+            .as_ref()
+            .and_then(|s| s.secret_hash.as_ref());
+        if recorded != Some(&hash) {
+            return Ok(MaskProviderAction::RecordSecretHash(hash));
+        }
+    }
+
+    // Show what the next verification Pod would look like, if requested.
+    if let Some(action) = determine_preview_action(
+        client.clone(),
+        name,
+        namespace,
+        instance,
+        &secret,
+        verify.as_ref(),
+    )
+    .await?
+    {
+        return Ok(action);
+    }
+
+    // Ensure no other MaskProvider names its credentials Secrets the same way.
+    if let Some(other) = suffix::find_suffix_collision(instance, providers) {
+        return Ok(MaskProviderAction::SecretSuffixCollision(Box::new(
+            other.clone(),
+        )));
+    }
+
+    // Ensure the VpnAccount the MaskProvider shares its connections with exists.
+    let account = match account::account_ref(instance) {
+        Some(name) => match account::get_account(client.clone(), name).await? {
+            Some(account) => Some(account),
+            None => return Ok(MaskProviderAction::AccountNotFound(name.to_owned())),
+        },
+        None => None,
+    };
+
+    // Evict the MaskConsumers if the credentials failed re-verification.
+    if verify_failure::evicts(instance) {
+        let reservations = list_reservations(client.clone(), instance).await?;
+        let consumers = verify_failure::list_evictable(client.clone(), &reservations).await?;
+        if !consumers.is_empty() {
+            return Ok(MaskProviderAction::EvictOnVerifyFailure(consumers));
+        }
     }
 
     // Check if the MaskProvider requires verification.
-    if let Some(action) = determine_verify_action(client.clone(), name, namespace, instance).await?
+    if let Some(action) = determine_verify_action(
+        client.clone(),
+        name,
+        namespace,
+        instance,
+        &secret,
+        verify.clone(),
+        capabilities,
+        priority_classes,
+        now,
+    )
+    .await?
+    {
+        return Ok(action);
+    }
+
+    // Check on the canary or start the next one if it's due.
+    if let Some(action) = determine_canary_action(
+        client.clone(),
+        name,
+        namespace,
+        instance,
+        canary_interval,
+        now,
+    )
+    .await?
     {
         return Ok(action);
     }
 
     // Remaining actions aim to keep the status object current.
-    determine_status_action(client, namespace, instance).await
+    determine_status_action(
+        client,
+        instance,
+        verify.as_ref(),
+        account.as_ref(),
+        priority_classes,
+        consumers,
+        now,
+    )
+    .await
 }
 
-lazy_static! {
-    static ref DEFAULT_VERIFY_SPEC: MaskProviderVerifySpec = Default::default();
+/// Determines the action needed to keep the Secret with the MaskProvider's
+/// transformed credentials in sync with `source`, if it has transformations.
+async fn determine_transform_action(
+    client: Client,
+    namespace: &str,
+    instance: &MaskProvider,
+    source: &Secret,
+) -> Result<Option<MaskProviderAction>, Error> {
+    if !transforms::has_transforms(instance) {
+        return Ok(None);
+    }
+    let desired = match transforms::derived_secret(instance, source) {
+        Ok(desired) => desired,
+        Err(e) => {
+            return Ok(Some(MaskProviderAction::ConfigError(
+                messages::config_error(&e.to_string()),
+            )))
+        }
+    };
+    let name = transforms::derived_secret_name(instance);
+    let api: Api<Secret> = Api::namespaced(client, namespace);
+    let existing = match api.get(&name).await {
+        Ok(existing) => existing,
+        Err(kube::Error::Api(ae)) if ae.code == 404 => {
+            return Ok(Some(MaskProviderAction::WriteDerivedSecret(Box::new(
+                desired,
+            ))))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    // Never overwrite a Secret that something else put there.
+    let owned = existing
+        .owner_references()
+        .iter()
+        .any(|o| Some(&o.uid) == instance.metadata.uid.as_ref());
+    if !owned {
+        return Ok(Some(MaskProviderAction::ConfigError(
+            messages::err_secret_conflict(namespace, &name, "doesn't belong to the MaskProvider"),
+        )));
+    }
+    if existing.data == desired.data && existing.type_ == desired.type_ {
+        return Ok(None);
+    }
+    Ok(Some(MaskProviderAction::WriteDerivedSecret(Box::new(
+        desired,
+    ))))
+}
+
+/// Determines whether the verify-pod preview requested with the explain
+/// annotation needs to be written, rendering the Pod that the next
+/// verification would create with the effective settings `verify`.
+async fn determine_preview_action(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    instance: &MaskProvider,
+    source: &Secret,
+    verify: Option<&MaskProviderVerifySpec>,
+) -> Result<Option<MaskProviderAction>, Error> {
+    if preview::requested(&instance.metadata, &[preview::VERIFY_POD]).is_none() {
+        return Ok(None);
+    }
+    // The verification Pod uses the transformed credentials, if any.
+    let rendered = match transforms::has_transforms(instance) {
+        true => transforms::derived_secret(instance, source)
+            .map_err(|e| Error::UserInputError(e.to_string())),
+        false => Ok(source.clone()),
+    }
+    .and_then(|secret| actions::preview_verify_pod(name, namespace, instance, verify, &secret));
+    let desired = preview::preview_config_map(instance, preview::VERIFY_POD, rendered);
+    Ok(preview::needs_write(client, instance, desired)
+        .await?
+        .map(|config_map| MaskProviderAction::WritePreview(Box::new(config_map))))
+}
+
+/// Replaces the creation of a verification Mask with waiting in the
+/// queue if `max` verifications are already in flight across the cluster,
+/// or other MaskProviders have been waiting longer. Other actions are
+/// returned unchanged.
+async fn queue_verification(
+    client: Client,
+    instance: &MaskProvider,
+    max: usize,
+    action: MaskProviderAction,
+) -> Result<MaskProviderAction, Error> {
+    if !matches!(action, MaskProviderAction::CreateVerifyMask { .. }) {
+        return Ok(action);
+    }
+    let queue = verify_queue::check_queue(client, instance, max).await?;
+
+    // Export how many MaskProviders are held back.
+    #[cfg(feature = "metrics")]
+    VERIFICATION_QUEUE_DEPTH_GAUGE.set(queue.depth as i64);
+
+    Ok(match queue.position {
+        Some(position) => MaskProviderAction::VerifyQueued(position),
+        None => action,
+    })
 }
 
 const DEFAULT_VERIFY_TIMEOUT: Duration = Duration::from_secs(60);
@@ -479,8 +1285,8 @@ async fn get_verify_pod(client: Client, name: &str, namespace: &str) -> Result<O
 }
 
 /// Returns the amount of time that has passed since the Pod's creation.
-fn get_pod_age(pod: &Pod) -> Result<Duration, Error> {
-    Ok((chrono::Utc::now()
+fn get_pod_age(pod: &Pod, now: DateTime<Utc>) -> Result<Duration, Error> {
+    Ok((now
         - pod
             .metadata
             .creation_timestamp
@@ -491,14 +1297,17 @@ fn get_pod_age(pod: &Pod) -> Result<Duration, Error> {
 }
 
 /// Returns the amount of time the verification pod is allowed to run
-/// before it is considered a failure.
+/// before it is considered a failure, per the settings the cycle began with.
 fn get_verify_timeout(instance: &MaskProvider) -> Duration {
-    instance
-        .spec
-        .verify
-        .as_ref()
-        .map_or(None, |v| v.timeout.as_deref())
-        .map_or(None, |t| parse_duration::parse(t).ok())
+    verify_timeout(cycle_verify(instance))
+}
+
+/// Returns the timeout of the verification settings, or the default
+/// if they don't set a valid one.
+pub(crate) fn verify_timeout(verify: Option<&MaskProviderVerifySpec>) -> Duration {
+    verify
+        .and_then(|v| v.timeout.as_deref())
+        .and_then(|t| parse_duration::parse(t).ok())
         .unwrap_or(DEFAULT_VERIFY_TIMEOUT)
 }
 
@@ -506,31 +1315,62 @@ fn get_verify_timeout(instance: &MaskProvider) -> Duration {
 /// and the Pod is not.
 async fn determine_verify_mask_action(
     client: Client,
+    instance: &MaskProvider,
     mask: &Mask,
+    priority_classes: &PriorityClasses,
+    now: DateTime<Utc>,
 ) -> Result<MaskProviderAction, Error> {
-    Ok(match mask.status.as_ref().map_or(None, |s| s.phase) {
+    Ok(match mask.status.as_ref().and_then(|s| s.phase) {
         // Controller is still processing the Mask. If it's in the Terminating
-        // phase, it is most likely pending recreation.
-        None | Some(MaskPhase::Pending) | Some(MaskPhase::Terminating) => {
-            MaskProviderAction::Verifying {
-                start_time: None,
-                message: "Waiting on the controller for the verification Mask.".to_owned(),
+        // or ErrProviderLost phase, it is most likely pending recreation.
+        None
+        | Some(MaskPhase::Pending)
+        | Some(MaskPhase::Terminating)
+        | Some(MaskPhase::ErrProviderLost) => MaskProviderAction::Verifying {
+            message: "Waiting on the controller for the verification Mask.".to_owned(),
+            step: VerifyStep::MaskCreated,
+            start_time: mask.metadata.creation_timestamp.clone(),
+        },
+        // The MaskProvider has too many active slots, we will have to wait
+        // for one to be released. Waiting for any other reason times out,
+        // as the MaskConsumer may not be recognized as verifying the
+        // MaskProvider.
+        Some(MaskPhase::Waiting) => {
+            let consumer = get_consumer(client, mask).await?;
+            let timeout = get_verify_timeout(instance);
+            match actions::verify_mask_stalled(mask, consumer.as_ref(), timeout, now) {
+                Some(message) => MaskProviderAction::VerifyFailed(message),
+                None => MaskProviderAction::Verifying {
+                    message: "Waiting for the verification Mask to be assigned a slot.".to_owned(),
+                    step: VerifyStep::MaskCreated,
+                    start_time: mask.metadata.creation_timestamp.clone(),
+                },
             }
         }
-        // The MaskProvider has too many active slots, we will have to wait.
-        Some(MaskPhase::Waiting) => MaskProviderAction::Verifying {
-            start_time: None,
-            message: "Waiting for the verification Mask to be assigned a slot.".to_owned(),
-        },
         // The Mask is ready to be used by the verification Pod.
-        Some(MaskPhase::Active) => match get_consumer(client, mask).await {
+        Some(MaskPhase::Active) => match get_consumer(client.clone(), mask).await {
             // Consumer doesn't exist yet for some reason, we will have to wait.
             Ok(None) => MaskProviderAction::Verifying {
-                start_time: None,
                 message: "Waiting on the controller for the verification MaskConsumer.".to_owned(),
+                step: VerifyStep::SlotAssigned,
+                start_time: None,
             },
-            // Consumer exists. Create the pod.
-            Ok(Some(consumer)) => MaskProviderAction::CreateVerifyPod(consumer),
+            // Consumer exists. Create the pod, unless its PriorityClass
+            // doesn't exist, in which case the API server would refuse
+            // it. Wait with a warning until the PriorityClass is created.
+            Ok(Some(consumer)) => {
+                let warnings = priority_classes
+                    .warnings(client, cycle_verify(instance))
+                    .await?;
+                match warnings.into_iter().next() {
+                    Some(warning) => MaskProviderAction::Verifying {
+                        message: warning,
+                        step: VerifyStep::SlotAssigned,
+                        start_time: None,
+                    },
+                    None => MaskProviderAction::CreateVerifyPod(Box::new(consumer)),
+                }
+            }
             // Some unknown error occured.
             Err(e) => return Err(e),
         },
@@ -538,6 +1378,32 @@ async fn determine_verify_mask_action(
         Some(MaskPhase::ErrNoProviders) => MaskProviderAction::VerifyFailed(
             "Verification Mask observed unexpected ErrNoProviders.".to_owned(),
         ),
+        // Unreachable branch: verification Masks aren't required to have labels.
+        Some(MaskPhase::ErrMissingLabels) => MaskProviderAction::VerifyFailed(
+            "Verification Mask observed unexpected ErrMissingLabels.".to_owned(),
+        ),
+        // Unreachable branch: verification Masks aren't subject to quotas.
+        Some(MaskPhase::ErrQuotaExceeded) => MaskProviderAction::VerifyFailed(
+            "Verification Mask observed unexpected ErrQuotaExceeded.".to_owned(),
+        ),
+        // Unreachable branch: verification Masks don't have a ttl.
+        Some(MaskPhase::ErrInvalidSpec) => MaskProviderAction::VerifyFailed(
+            "Verification Mask observed unexpected ErrInvalidSpec.".to_owned(),
+        ),
+        // The MaskProvider's namespace must be opted in to verify the credentials.
+        Some(MaskPhase::ErrNamespaceNotOptedIn) => MaskProviderAction::VerifyFailed(
+            mask.status
+                .as_ref()
+                .and_then(|s| s.message.clone())
+                .unwrap_or_else(|| "Verification Mask namespace is not opted in.".to_owned()),
+        ),
+        // Something else owns a Secret with the name of the credentials copy.
+        Some(MaskPhase::ErrSecretConflict) => MaskProviderAction::VerifyFailed(
+            mask.status
+                .as_ref()
+                .and_then(|s| s.message.clone())
+                .unwrap_or_else(|| "Verification Mask has a conflicting Secret.".to_owned()),
+        ),
     })
 }
 
@@ -545,6 +1411,7 @@ async fn determine_verify_mask_action(
 fn determine_verify_pod_action(
     instance: &MaskProvider,
     pod: &Pod,
+    now: DateTime<Utc>,
 ) -> Result<MaskProviderAction, Error> {
     // Examine the status object of the pod.
     let status = pod
@@ -561,8 +1428,17 @@ fn determine_verify_pod_action(
     // (DigitalOcean w/ containerd) the pods enter the phase Running
     // (but it will read NotReady), and the container status can be
     // inspected to determine the VPN connection was successful.
+    // Remember the node so the Verified transition can record it.
+    let node = pod.spec.as_ref().and_then(|s| s.node_name.clone());
     if is_probe_successful(status) {
-        return Ok(MaskProviderAction::Verified);
+        let note = probe_note(status);
+        return Ok(MaskProviderAction::Verified { node, note });
+    }
+
+    // The probe gave up, e.g. because the egress doesn't look as asserted.
+    // It isn't restarted, so there's no point in waiting for the timeout.
+    if let Some(message) = probe_failure(status) {
+        return Ok(MaskProviderAction::VerifyFailed(message));
     }
 
     Ok(match phase {
@@ -570,14 +1446,17 @@ fn determine_verify_pod_action(
         // This may be an error if the pod isn't able to be scheduled.
         "Pending" => match check_pod_scheduling_error(status) {
             Some(message) => MaskProviderAction::VerifyFailed(message),
-            None => check_verify_timeout(instance, &pod)?,
+            None => check_verify_timeout(instance, pod, VerifyStep::PodCreated, now)?,
         },
         // Verification pod is still waiting for the IP to change.
-        "Running" => check_verify_timeout(instance, &pod)?,
+        "Running" => check_verify_timeout(instance, pod, VerifyStep::Probing, now)?,
         // Verification has completed (new IP obtained).
         // This is what should be observed according to the
         // Kubernetes docs, but it doesn't seem to be the case.
-        "Succeeded" => MaskProviderAction::Verified,
+        "Succeeded" => MaskProviderAction::Verified {
+            node,
+            note: probe_note(status),
+        },
         // Unknown error.
         _ => MaskProviderAction::VerifyFailed(
             "Unknown error occurred during verification.".to_owned(),
@@ -585,22 +1464,67 @@ fn determine_verify_pod_action(
     })
 }
 
+/// Determines the action given that the verification Pod was disrupted
+/// for `reason`. The Pod is recreated as long as the cycle's verification
+/// Mask allows it, and the cycle fails otherwise.
+fn determine_disruption_action(
+    instance: &MaskProvider,
+    pod: &Pod,
+    mask: Option<&Mask>,
+    reason: String,
+    now: DateTime<Utc>,
+) -> MaskProviderAction {
+    let uid = pod.metadata.uid.clone().unwrap_or_default();
+    let mut rescheduled = mask.map(disruption::rescheduled_pods).unwrap_or_default();
+    if rescheduled.contains(&uid) {
+        // The Pod is already being deleted to be recreated.
+        return MaskProviderAction::AwaitVerifyCleanup;
+    }
+    match mask {
+        Some(mask)
+            if disruption::can_reschedule(
+                mask,
+                &rescheduled,
+                get_verify_timeout(instance),
+                now,
+            ) =>
+        {
+            rescheduled.push(uid);
+            MaskProviderAction::RescheduleVerifyPod {
+                rescheduled,
+                reason,
+            }
+        }
+        _ => MaskProviderAction::VerifyFailed(messages::verify_pod_disrupted(
+            &reason,
+            rescheduled.len(),
+        )),
+    }
+}
+
 /// Returns the action given that the verification Pod
-/// is in a Pending or Running phase. Checks to see if
-/// the verification attempt has timed out.
-fn check_verify_timeout(instance: &MaskProvider, pod: &Pod) -> Result<MaskProviderAction, Error> {
+/// is in a Pending or Running phase, which is the `step`
+/// the cycle reached. Checks to see if the verification
+/// attempt has timed out.
+fn check_verify_timeout(
+    instance: &MaskProvider,
+    pod: &Pod,
+    step: VerifyStep,
+    now: DateTime<Utc>,
+) -> Result<MaskProviderAction, Error> {
     // Make sure the verification pod isn't too old.
     // If it goes past the timeout, it doesn't matter what
     // phase it's in, it will be considered a failure.
-    Ok(if get_pod_age(pod)? > get_verify_timeout(instance) {
+    Ok(if get_pod_age(pod, now)? > get_verify_timeout(instance) {
         MaskProviderAction::VerifyFailed(
             "Verification timed out waiting for Pod to schedule.".to_owned(),
         )
     } else {
         // Still waiting for pod to be scheduled.
         MaskProviderAction::Verifying {
-            start_time: pod.metadata.creation_timestamp.clone(),
             message: "Waiting on verification Pod to start.".to_owned(),
+            step,
+            start_time: pod.metadata.creation_timestamp.clone(),
         }
     })
 }
@@ -618,48 +1542,128 @@ fn is_probe_successful(status: &PodStatus) -> bool {
     status
         .container_statuses
         .as_ref()
-        .map_or(None, |cs| {
-            cs.iter().filter(|s| s.name == VPN_CONTAINER_NAME).next()
-        })
-        .map_or(false, |cs| {
+        .and_then(|cs| cs.iter().find(|s| s.name == VPN_CONTAINER_NAME))
+        .is_some_and(|cs| {
             // VPN container should still be running.
-            cs.state.as_ref().map_or(false, |s| s.running.is_some())
+            cs.state.as_ref().is_some_and(|s| s.running.is_some())
         })
         && status
             .container_statuses
             .as_ref()
-            .map_or(None, |cs| {
-                cs.iter().filter(|s| s.name == PROBE_CONTAINER_NAME).next()
-            })
-            .map_or(false, |cs| {
+            .and_then(|cs| cs.iter().find(|s| s.name == PROBE_CONTAINER_NAME))
+            .is_some_and(|cs| {
                 // Probe container should have exited with code 0.
-                cs.state.as_ref().map_or(false, |s| {
-                    s.terminated.as_ref().map_or(false, |t| t.exit_code == 0)
-                })
+                cs.state
+                    .as_ref()
+                    .is_some_and(|s| s.terminated.as_ref().is_some_and(|t| t.exit_code == 0))
             })
 }
 
+/// Returns the termination message of the probe container if it exited
+/// zero, which notes what wasn't checked, e.g. because the location of the
+/// egress couldn't be looked up.
+fn probe_note(status: &PodStatus) -> Option<String> {
+    status
+        .container_statuses
+        .as_ref()?
+        .iter()
+        .find(|s| s.name == PROBE_CONTAINER_NAME)?
+        .state
+        .as_ref()?
+        .terminated
+        .as_ref()
+        .filter(|t| t.exit_code == 0)?
+        .message
+        .as_deref()
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .map(str::to_owned)
+}
+
+/// Returns the message failing verification if the probe container exited
+/// nonzero, which is the reason it wrote to its termination message, if any.
+fn probe_failure(status: &PodStatus) -> Option<String> {
+    let terminated = status
+        .container_statuses
+        .as_ref()?
+        .iter()
+        .find(|s| s.name == PROBE_CONTAINER_NAME)?
+        .state
+        .as_ref()?
+        .terminated
+        .as_ref()
+        .filter(|t| t.exit_code != 0)?;
+    Some(
+        match terminated
+            .message
+            .as_deref()
+            .map(str::trim)
+            .filter(|m| !m.is_empty())
+        {
+            Some(reason) => messages::verify_probe_failed(reason),
+            None => messages::verify_probe_exited(terminated.exit_code),
+        },
+    )
+}
+
 /// Checks if verification is necessary and returns the appropriate action.
+/// `verify` are the MaskProvider's effective verification settings, which
+/// only decide whether a new cycle is due. Cycles in progress keep using
+/// the settings they began with. `secret` holds the credentials, which
+/// are checked right away if the settings don't dial them.
+#[allow(clippy::too_many_arguments)]
 async fn determine_verify_action(
     client: Client,
     name: &str,
     namespace: &str,
     instance: &MaskProvider,
+    secret: &Secret,
+    verify: Option<MaskProviderVerifySpec>,
+    capabilities: &Capabilities,
+    priority_classes: &PriorityClasses,
+    now: DateTime<Utc>,
 ) -> Result<Option<MaskProviderAction>, Error> {
-    let verify = match instance.spec.verify {
-        // User is requesting verification be skipped.
-        Some(ref verify) if verify.skip.unwrap_or(false) => return Ok(None),
-        // Use the specified verification settings.
-        Some(ref verify) => verify,
-        // Use default verification settings.
-        None => &DEFAULT_VERIFY_SPEC,
-    };
+    // User is requesting verification be skipped.
+    if verify.as_ref().is_some_and(|v| v.skip.unwrap_or(false)) {
+        return Ok(None);
+    }
 
     // Check if the verify pod exists. Its existence implies that
     // verification was required at some point.
     if let Some(pod) = get_verify_pod(client.clone(), name, namespace).await? {
+        // A Pod that was disrupted before it could verify the credentials
+        // is recreated, as a drained or lost node says nothing about them.
+        let succeeded = pod.status.as_ref().is_some_and(is_probe_successful);
+        if let Some(reason) = disruption::verify_pod_disruption(&pod).filter(|_| !succeeded) {
+            let mask = get_verify_mask(client.clone(), name, namespace).await?;
+            return Ok(Some(determine_disruption_action(
+                instance,
+                &pod,
+                mask.as_ref(),
+                reason,
+                now,
+            )));
+        }
+        if pod.metadata.deletion_timestamp.is_some() {
+            // Older API servers don't mark disrupted Pods, so a Pod
+            // deleted mid-verification is assumed to be disrupted.
+            let mask = get_verify_mask(client.clone(), name, namespace).await?;
+            if let Some(reason) =
+                disruption::unmarked_disruption(&pod, mask.as_ref(), instance, capabilities)
+            {
+                return Ok(Some(determine_disruption_action(
+                    instance,
+                    &pod,
+                    mask.as_ref(),
+                    reason,
+                    now,
+                )));
+            }
+            // Verification is over and the Pod is being cleaned up.
+            return Ok(Some(MaskProviderAction::AwaitVerifyCleanup));
+        }
         // Verification Pod exists. Examine its status object.
-        return Ok(Some(determine_verify_pod_action(instance, &pod)?));
+        return Ok(Some(determine_verify_pod_action(instance, &pod, now)?));
     }
 
     // Check if the verify Mask exists. Its existence implies that
@@ -667,85 +1671,309 @@ async fn determine_verify_action(
     // periodic verification and it's still important not to exceed
     // the spec's maxSlots.
     if let Some(mask) = get_verify_mask(client.clone(), name, namespace).await? {
+        if mask.metadata.deletion_timestamp.is_some() {
+            // Verification is over and the Mask is being cleaned up. Its
+            // slot is released once the Mask and its MaskConsumer are gone.
+            return Ok(Some(MaskProviderAction::AwaitVerifyCleanup));
+        }
         // Verification Mask exists. Examine its status object.
-        return Ok(Some(determine_verify_mask_action(client, &mask).await?));
+        return Ok(Some(
+            determine_verify_mask_action(client, instance, &mask, priority_classes, now).await?,
+        ));
+    }
+
+    // Verification was requested manually with the verify-now annotation.
+    if manual_verify_requested(instance) {
+        return start_verification(instance, secret, true, verify).map(Some);
     }
 
     // Determine if we need to verify the credentials.
-    if let Some(ref last_verified) = instance.status.as_ref().unwrap().last_verified {
-        // The service has been verified before.
-        let interval = match verify.interval {
+    if ensure_status_initialized(instance)?.last_verified.is_some() {
+        if !verify_hash::is_current(instance, verify.as_ref())? {
+            // The credentials, verification settings or image changed since
+            // the last verification, or it's unknown what was verified.
+            return start_verification(instance, secret, false, verify).map(Some);
+        }
+        // The service has been verified before. Determine when it's due
+        // again, offset within the spread window so MaskProviders verified
+        // together aren't all verified again at once.
+        match verify_schedule::next_verification(instance, verify.as_ref())? {
             // Verification has passed once and the user is not
             // requesting periodic verification.
             None => return Ok(None),
-            // User is requesting periodic verification.
-            Some(ref interval) => interval,
-        };
-        // Parse the interval spec into a Duration.
-        let interval = chrono::Duration::from_std(parse_duration::parse(interval)?)?;
-        // Determine the age of the verificataion.
-        let last_verified: chrono::DateTime<Utc> = last_verified.parse()?;
-        let age: chrono::Duration = Utc::now() - last_verified;
-        if age < interval {
             // Verification is up to date.
-            return Ok(None);
+            Some(next) if now < next => return Ok(None),
+            // Verification is stale.
+            Some(_) => {}
         }
-        // Verification is stale.
     }
 
     // Create the verification resources.
-    Ok(Some(MaskProviderAction::CreateVerifyMask))
+    start_verification(instance, secret, false, verify).map(Some)
 }
 
-/// Returns the number of reservation ConfigMaps for a MaskProvider.
-async fn count_reservations(
+/// Returns the action that starts a verification cycle under the settings.
+/// Credentials that are dialed get a verification Mask, unless the probe
+/// can't check their egress assertions, while those that are only checked
+/// statically are checked right away, without creating anything. A failed
+/// check that's already shown is left as is, unless it was requested
+/// manually.
+fn start_verification(
+    instance: &MaskProvider,
+    secret: &Secret,
+    manual: bool,
+    verify: Option<MaskProviderVerifySpec>,
+) -> Result<MaskProviderAction, Error> {
+    let settings = match verify.as_ref() {
+        Some(settings) if verify_keys::is_keys_only(Some(settings)) => settings,
+        // Don't start a dial whose egress assertions the probe can't check.
+        Some(settings) => match verify_assert::validate(settings) {
+            Ok(()) => return Ok(MaskProviderAction::CreateVerifyMask { manual, verify }),
+            Err(message) => {
+                return Ok(MaskProviderAction::ConfigError(messages::config_error(
+                    &message,
+                )))
+            }
+        },
+        None => return Ok(MaskProviderAction::CreateVerifyMask { manual, verify }),
+    };
+    let patterns = match verify_keys::compile_patterns(settings) {
+        Ok(patterns) => patterns,
+        Err(message) => {
+            return Ok(MaskProviderAction::ConfigError(messages::config_error(
+                &message,
+            )))
+        }
+    };
+    // The checks apply to the credentials as they're copied.
+    let secret = match transforms::has_transforms(instance) {
+        true => transforms::derived_secret(instance, secret)
+            .map_err(|e| Error::UserInputError(e.to_string()))?,
+        false => secret.clone(),
+    };
+    let message = match verify_keys::check(settings, &patterns, &secret) {
+        Ok(()) => return Ok(MaskProviderAction::KeysVerified { manual, verify }),
+        Err(message) => message,
+    };
+    let status = instance.status.as_ref();
+    let shown = status.and_then(|s| s.phase) == Some(MaskProviderPhase::ErrVerifyFailed)
+        && status.and_then(|s| s.message.as_deref()) == Some(message.as_str());
+    Ok(match shown && !manual {
+        true => MaskProviderAction::NoOp,
+        false => MaskProviderAction::KeysVerifyFailed { manual, message },
+    })
+}
+
+/// Checks on the MaskProvider's canary Mask, or decides whether to create
+/// one. Returns None while the canary is in progress, so the status is
+/// still kept current, or if no canary is due.
+async fn determine_canary_action(
     client: Client,
+    name: &str,
     namespace: &str,
     instance: &MaskProvider,
-) -> Result<usize, Error> {
+    canary_interval: Option<Duration>,
+    now: DateTime<Utc>,
+) -> Result<Option<MaskProviderAction>, Error> {
+    if canary_interval.is_none() {
+        // Canaries are disabled for every MaskProvider.
+        return Ok(None);
+    }
+    let interval = canary::interval(instance, canary_interval);
+    if let Some(mask) = canary::get_mask(client.clone(), name, namespace).await? {
+        if mask.metadata.deletion_timestamp.is_some() {
+            // The canary is over and its Mask is being cleaned up.
+            return Ok(None);
+        }
+        if interval.is_none() {
+            // The MaskProvider opted out while the canary was in progress.
+            return Ok(Some(MaskProviderAction::DeleteCanaryMask));
+        }
+        let (consumer, secret) = canary::get_credentials(client, &mask).await?;
+        return Ok(
+            canary::check(&mask, consumer.as_ref(), secret.as_ref(), now)
+                .map(|outcome| MaskProviderAction::CanaryFinished { outcome, at: now }),
+        );
+    }
+    if !interval.is_some_and(|interval| canary::is_due(instance, interval, now)) {
+        return Ok(None);
+    }
+    // Only MaskProviders that can currently be assigned are tested.
+    let status = instance.status.as_ref();
+    if !matches!(
+        status.and_then(|s| s.phase),
+        Some(MaskProviderPhase::Ready) | Some(MaskProviderPhase::Active)
+    ) || status.and_then(|s| s.out_of_hours) == Some(true)
+    {
+        return Ok(None);
+    }
+    // Rather than wait for a slot, skip the canary if the MaskProvider is full.
+    if list_reservations(client, instance).await?.len() >= instance.spec.max_slots {
+        return Ok(Some(MaskProviderAction::CanaryFinished {
+            outcome: CanaryOutcome::skipped(),
+            at: now,
+        }));
+    }
+    Ok(Some(MaskProviderAction::CreateCanaryMask))
+}
+
+/// Returns true if the verify-now annotation has a value that differs
+/// from the last manually triggered verification. This makes the
+/// trigger edge-triggered: each new value results in exactly one
+/// verification cycle, regardless of `lastVerified`.
+fn manual_verify_requested(instance: &MaskProvider) -> bool {
+    match actions::verify_now(instance) {
+        Some(value) => {
+            instance
+                .status
+                .as_ref()
+                .and_then(|s| s.last_manual_verify.as_deref())
+                != Some(value)
+        }
+        None => false,
+    }
+}
+
+/// Returns the MaskProvider's reservations for real MaskConsumers.
+async fn list_reservations(
+    client: Client,
+    instance: &MaskProvider,
+) -> Result<Vec<MaskReservation>, Error> {
     // Only count reservations that belong to this specific MaskProvider.
     // Filtering this way excludes reservations from deleted resources
     // that were immediately recreated.
-    let uid = instance.metadata.uid.as_deref().unwrap();
-
-    // Count the ConfigMaps with the MaskProvider as the owner.
-    Ok(Api::<MaskReservation>::namespaced(client, namespace)
-        .list(&ListParams::default())
+    Ok(list_provider_reservations(client, instance)
         .await?
         .into_iter()
-        .filter(|cm| {
-            // Only inspect ConfigMaps owned by this MaskProvider.
-            cm.metadata
-                .owner_references
-                .as_ref()
-                .map_or(false, |ors| ors.iter().any(|or| or.uid == uid))
-        })
-        .count())
+        // Reservations made for verification and canaries aren't real consumers.
+        .filter(|mr| !is_verification_reservation(mr) && !is_canary_reservation(mr))
+        .collect())
+}
+
+/// Determines the action for a MaskProvider that is being deleted. The
+/// finalizer is held while MaskConsumers are still assigned its slots,
+/// so deleting the MaskProvider by accident doesn't pull the credentials
+/// out from under running workloads, unless deletion is forced.
+async fn determine_delete_action(
+    client: Client,
+    instance: &MaskProvider,
+) -> Result<MaskProviderAction, Error> {
+    let reservations = list_reservations(client, instance).await?;
+    if reservations.is_empty() {
+        // Nothing depends on the MaskProvider anymore.
+        return Ok(MaskProviderAction::Delete);
+    }
+    if actions::force_delete_requested(instance) {
+        return Ok(MaskProviderAction::ForceDelete(reservations));
+    }
+    let message = messages::deletion_blocked(&actions::assigned_consumers(&reservations));
+    let status = instance.status.as_ref();
+    if status.and_then(|s| s.phase) == Some(MaskProviderPhase::Terminating)
+        && status.and_then(|s| s.message.as_deref()) == Some(message.as_str())
+        && status_age(status.and_then(|s| s.last_updated.as_deref())) <= PROBE_INTERVAL
+    {
+        // Already showing the blocking MaskConsumers.
+        return Ok(MaskProviderAction::NoOp);
+    }
+    Ok(MaskProviderAction::DeleteBlocked(message))
 }
 
 /// Determines the action given that the only thing left to do
-/// is periodically keeping the Active phase up-to-date.
+/// is periodically keeping the Active phase up-to-date. `verify` are
+/// the effective verification settings, which are validated as well.
 async fn determine_status_action(
     client: Client,
-    namespace: &str,
     instance: &MaskProvider,
+    verify: Option<&MaskProviderVerifySpec>,
+    account: Option<&VpnAccount>,
+    priority_classes: &PriorityClasses,
+    consumers: &[Arc<MaskConsumer>],
+    now: DateTime<Utc>,
 ) -> Result<MaskProviderAction, Error> {
-    // Count the ConfigMaps with the MaskProvider as the owner.
-    let active_slots = count_reservations(client, namespace, instance).await?;
+    // Count the MaskReservations with the MaskProvider as the owner.
+    let reservations = list_reservations(client.clone(), instance).await?;
+    let active_slots = reservations.len();
+    // New assignments are refused outside of the availability hours,
+    // and existing ones are unassigned if the MaskProvider drains.
+    let availability = schedule::availability(instance, now);
+    let out_of_hours = availability.as_ref().map(|a| a.out_of_hours);
+    let draining = out_of_hours == Some(true)
+        && instance
+            .spec
+            .availability
+            .as_ref()
+            .and_then(|a| a.drain_out_of_hours)
+            == Some(true);
+    if draining && !actions::pending_reservations(&reservations).is_empty() {
+        return Ok(MaskProviderAction::DrainOutOfHours(reservations));
+    }
+    // The drain is in progress until the reservations are gone.
+    let operation = match draining {
+        true => operation::draining(instance, active_slots, now),
+        false => None,
+    };
+    // Forecast the demand from MaskConsumers waiting for a slot.
+    let capacity = capacity::get_capacity(instance, &reservations, consumers);
+    // Mirror which workloads used the credentials of the latest assignments.
+    let recent_assignments = assignments::recent_assignments(instance, &reservations, consumers);
     let (phase, age) = get_provider_phase(instance)?;
-    if active_slots > 0 {
-        if phase != MaskProviderPhase::Active || age > PROBE_INTERVAL {
-            // Keep the Active status up to date.
-            return Ok(MaskProviderAction::Active { active_slots });
+    let desired_phase = if active_slots > 0 {
+        MaskProviderPhase::Active
+    } else {
+        MaskProviderPhase::Ready
+    };
+    // Show when the credentials are next verified.
+    let next_verification = verify_schedule::next_verification_status(instance, verify)?;
+    if phase == desired_phase
+        && age <= PROBE_INTERVAL
+        && instance.status.as_ref().and_then(|s| s.out_of_hours) == out_of_hours
+        && instance
+            .status
+            .as_ref()
+            .and_then(|s| s.next_verification.as_ref())
+            == next_verification.as_ref()
+        && capacity.is_current(instance)
+        && assignments::is_current(instance, &recent_assignments)
+        && operation::is_current(instance, operation.as_ref())
+    {
+        // Nothing to do, resource is fully reconciled.
+        return Ok(MaskProviderAction::NoOp);
+    }
+    // Validate the namespace allowlist with each status refresh, so
+    // creating a missing namespace later clears the warning.
+    let unknown = namespaces::get_unknown_namespaces(client.clone(), instance).await?;
+    let mut warnings = namespaces::namespace_warnings(&unknown);
+    // Warn about secret keys that gluetun expects under another name.
+    warnings.extend(gluetun_version::compatibility_warnings(instance));
+    // Warn if the verification Pod's PriorityClass doesn't exist.
+    warnings.extend(priority_classes.warnings(client.clone(), verify).await?);
+    // Show how much of the shared VpnAccount is in use, if any.
+    let account = match account {
+        Some(account) => Some(account::get_utilization(client, account).await?),
+        None => None,
+    };
+    Ok(if active_slots > 0 {
+        // Keep the Active status up to date.
+        MaskProviderAction::Active {
+            active_slots,
+            capacity,
+            recent_assignments,
+            warnings,
+            account,
+            availability,
+            operation,
+            next_verification,
         }
     } else {
-        if phase != MaskProviderPhase::Ready || age > PROBE_INTERVAL {
-            // Keep the Ready status up to date.
-            return Ok(MaskProviderAction::Ready);
+        // Keep the Ready status up to date.
+        MaskProviderAction::Ready {
+            capacity,
+            warnings,
+            account,
+            availability,
+            next_verification,
         }
-    }
-    // Nothing to do, resource is fully reconciled.
-    Ok(MaskProviderAction::NoOp)
+    })
 }
 
 /// Actions to be taken when a reconciliation fails - for whatever reason.
@@ -755,17 +1983,16 @@ async fn determine_status_action(
 /// # Arguments
 /// - `instance`: The erroneous resource.
 /// - `error`: A reference to the `kube::Error` that occurred during reconciliation.
-/// - `_context`: Unused argument. Context Data "injected" automatically by kube-rs.
-fn on_error(instance: Arc<MaskProvider>, error: &Error, _context: Arc<ContextData>) -> Action {
-    eprintln!("Reconciliation error:\n{:?}.\n{:?}", error, instance);
-    Action::requeue(Duration::from_secs(5))
+/// - `context`: Context Data "injected" automatically by kube-rs. Its client is used
+///   to record the failed action in the resource's status object.
+fn on_error(instance: Arc<MaskProvider>, error: &Error, context: Arc<ContextData>) -> Action {
+    let action = report_reconcile_error(&instance, error);
+    record_action_error(context.client.clone(), instance, "providers", error);
+    action
 }
 
 fn check_pod_scheduling_error(status: &PodStatus) -> Option<String> {
-    let conditions: &Vec<_> = match status.conditions.as_ref() {
-        Some(conditions) => conditions,
-        None => return None,
-    };
+    let conditions: &Vec<_> = status.conditions.as_ref()?;
     for condition in conditions {
         if condition.type_ == "PodScheduled" && condition.status == "False" {
             return Some(
@@ -0,0 +1,37 @@
+use std::sync::Arc;
+use vpn_types::*;
+
+use super::namespaces::namespaces_overlap;
+
+/// Returns the MaskProvider whose stable secret suffix collides with that
+/// of `instance`, if any. Two MaskProviders collide if they have the same
+/// suffix and can be assigned to the same namespaces, as their credentials
+/// would be copied to Secrets with the same names. The older MaskProvider
+/// keeps the suffix, so only the newer one observes the collision.
+/// `providers` is the controller's cache of every MaskProvider.
+pub fn find_suffix_collision<'a>(
+    instance: &MaskProvider,
+    providers: &'a [Arc<MaskProvider>],
+) -> Option<&'a MaskProvider> {
+    let suffix = instance.spec.stable_secret_suffix.as_ref()?;
+    providers.iter().map(AsRef::as_ref).find(|other| {
+        other.metadata.uid != instance.metadata.uid
+            // Recreating a MaskProvider shouldn't collide with the one being deleted.
+            && other.metadata.deletion_timestamp.is_none()
+            && other.spec.stable_secret_suffix.as_ref() == Some(suffix)
+            && namespaces_overlap(instance, other)
+            && precedes(other, instance)
+    })
+}
+
+/// Returns true if MaskProvider `a` was created before `b`,
+/// breaking ties by UID so exactly one of them wins.
+fn precedes(a: &MaskProvider, b: &MaskProvider) -> bool {
+    let key = |p: &MaskProvider| {
+        (
+            p.metadata.creation_timestamp.as_ref().map(|t| t.0),
+            p.metadata.uid.clone(),
+        )
+    };
+    key(a) < key(b)
+}
@@ -0,0 +1,194 @@
+use k8s_openapi::{api::core::v1::Secret, ByteString};
+use kube::{api::ObjectMeta, Resource, ResourceExt};
+use openssl::{
+    base64,
+    pkey::{Id, PKey},
+};
+use std::{collections::BTreeMap, fmt};
+use vpn_types::*;
+
+use crate::consumers::rollout::credentials_hash;
+
+/// A transformation that can't be applied to the credentials.
+#[derive(Debug, PartialEq)]
+pub struct TransformError {
+    /// Index of the failing transformation in `spec.transforms`.
+    pub index: usize,
+
+    /// Explanation of why it failed.
+    pub message: String,
+}
+
+impl fmt::Display for TransformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "transforms[{}]: {}", self.index, self.message)
+    }
+}
+
+/// Returns the name of the Secret holding the MaskProvider's
+/// transformed credentials.
+pub fn derived_secret_name(provider: &MaskProvider) -> String {
+    format!("{}-transformed", provider.name_any())
+}
+
+/// Returns true if the MaskProvider transforms its credentials.
+pub fn has_transforms(provider: &MaskProvider) -> bool {
+    provider
+        .spec
+        .transforms
+        .as_ref()
+        .is_some_and(|transforms| !transforms.is_empty())
+}
+
+/// Returns the name of the Secret the MaskProvider's credentials are
+/// verified with and copied from: the transformed Secret if it has
+/// transformations, otherwise the one it references.
+pub fn effective_secret_name(provider: &MaskProvider) -> String {
+    if has_transforms(provider) {
+        derived_secret_name(provider)
+    } else {
+        provider.spec.secret.clone()
+    }
+}
+
+/// Returns the checksum of the credentials copied for Masks, which are
+/// `source`'s data after the MaskProvider's transformations, or None if
+/// they can't be transformed.
+pub fn effective_hash(provider: &MaskProvider, source: &Secret) -> Option<String> {
+    if !has_transforms(provider) {
+        return Some(credentials_hash(source.data.as_ref()));
+    }
+    let derived = derived_secret(provider, source).ok()?;
+    Some(credentials_hash(derived.data.as_ref()))
+}
+
+/// Returns the Secret with the MaskProvider's transformed credentials,
+/// owned by the MaskProvider so it's deleted along with it.
+pub fn derived_secret(provider: &MaskProvider, source: &Secret) -> Result<Secret, TransformError> {
+    let transforms = provider.spec.transforms.as_deref().unwrap_or_default();
+    let data = apply(transforms, source.data.clone().unwrap_or_default())?;
+    Ok(Secret {
+        metadata: ObjectMeta {
+            name: Some(derived_secret_name(provider)),
+            namespace: provider.namespace(),
+            owner_references: Some(vec![provider.controller_owner_ref(&()).unwrap()]),
+            ..Default::default()
+        },
+        data: Some(data),
+        type_: source.type_.clone(),
+        ..Default::default()
+    })
+}
+
+/// Applies the transformations to the Secret's data in order.
+pub fn apply(
+    transforms: &[Transform],
+    mut data: BTreeMap<String, ByteString>,
+) -> Result<BTreeMap<String, ByteString>, TransformError> {
+    for (index, transform) in transforms.iter().enumerate() {
+        apply_one(transform, &mut data).map_err(|message| TransformError { index, message })?;
+    }
+    Ok(data)
+}
+
+/// Applies a single transformation, returning why it failed if it did.
+fn apply_one(transform: &Transform, data: &mut BTreeMap<String, ByteString>) -> Result<(), String> {
+    match transform {
+        Transform::Base64Decode { key } => {
+            let key = required("key", key)?;
+            let decoded = decode(text(data, key)?).map_err(|e| format!("key '{}' {}", key, e))?;
+            data.insert(key.to_owned(), ByteString(decoded));
+        }
+        Transform::DeriveWireguardPublicKey { from, to } => {
+            let (from, to) = (required("from", from)?, required("to", to)?);
+            let public_key = wireguard_public_key(text(data, from)?)
+                .map_err(|e| format!("key '{}' {}", from, e))?;
+            data.insert(checked_key(to)?, ByteString(public_key.into_bytes()));
+        }
+        Transform::Template { to, pattern } => {
+            let (to, pattern) = (required("to", to)?, required("pattern", pattern)?);
+            let rendered = render(pattern, data)?;
+            data.insert(checked_key(to)?, ByteString(rendered.into_bytes()));
+        }
+        Transform::Rename { from, to } => {
+            let (from, to) = (required("from", from)?, required("to", to)?);
+            let to = checked_key(to)?;
+            let value = data
+                .remove(from)
+                .ok_or_else(|| format!("key '{}' does not exist", from))?;
+            data.insert(to, value);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the value of the transformation's field, which the schema
+/// can't require, or why it's missing.
+fn required<'a>(field: &str, value: &'a str) -> Result<&'a str, String> {
+    match value.is_empty() {
+        true => Err(format!("'{}' is required", field)),
+        false => Ok(value),
+    }
+}
+
+/// Returns the value of the key as text.
+fn text<'a>(data: &'a BTreeMap<String, ByteString>, key: &str) -> Result<&'a str, String> {
+    let value = data
+        .get(key)
+        .ok_or_else(|| format!("key '{}' does not exist", key))?;
+    std::str::from_utf8(&value.0).map_err(|_| format!("key '{}' is not valid UTF-8", key))
+}
+
+/// Returns the key if it's one a Secret can have.
+fn checked_key(key: &str) -> Result<String, String> {
+    let valid = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(key.to_owned())
+    } else {
+        Err(format!("'{}' is not a valid Secret key", key))
+    }
+}
+
+/// Decodes the base64 value, ignoring surrounding whitespace.
+fn decode(value: &str) -> Result<Vec<u8>, String> {
+    base64::decode_block(value.trim()).map_err(|_| "is not valid base64".to_owned())
+}
+
+/// Returns the base64-encoded X25519 public key of the base64-encoded
+/// WireGuard private key.
+fn wireguard_public_key(private_key: &str) -> Result<String, String> {
+    let private_key = decode(private_key)?;
+    if private_key.len() != 32 {
+        return Err(format!(
+            "is not a WireGuard private key: expected 32 bytes, got {}",
+            private_key.len()
+        ));
+    }
+    let public_key = PKey::private_key_from_raw_bytes(&private_key, Id::X25519)
+        .and_then(|key| key.raw_public_key())
+        .map_err(|e| format!("is not a WireGuard private key: {}", e))?;
+    Ok(base64::encode_block(&public_key))
+}
+
+/// Replaces every `{{KEY}}` placeholder in the pattern with the value of `KEY`.
+fn render(pattern: &str, data: &BTreeMap<String, ByteString>) -> Result<String, String> {
+    let mut rendered = String::with_capacity(pattern.len());
+    let mut rest = pattern;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or_else(|| {
+            format!(
+                "placeholder at offset {} is not closed",
+                pattern.len() - rest.len() + start
+            )
+        })?;
+        rendered.push_str(text(data, after[..end].trim())?);
+        rest = &after[end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
@@ -0,0 +1,32 @@
+use std::net::Ipv4Addr;
+use vpn_types::*;
+
+use crate::util::messages;
+
+/// Checks that the egress assertions of the settings can be evaluated by
+/// the probe, which only understands IPv4 ranges in CIDR notation. Returns
+/// the message explaining the first range that can't be.
+pub fn validate(verify: &MaskProviderVerifySpec) -> Result<(), String> {
+    let cidrs = verify
+        .assert
+        .as_ref()
+        .and_then(|a| a.cidrs.as_ref())
+        .into_iter()
+        .flatten();
+    for cidr in cidrs {
+        if !is_ipv4_cidr(cidr) {
+            return Err(messages::invalid_assert_cidr(cidr));
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if the range is an IPv4 address and prefix length.
+fn is_ipv4_cidr(cidr: &str) -> bool {
+    match cidr.split_once('/') {
+        Some((address, bits)) => {
+            address.parse::<Ipv4Addr>().is_ok() && bits.parse::<u8>().is_ok_and(|b| b <= 32)
+        }
+        None => false,
+    }
+}
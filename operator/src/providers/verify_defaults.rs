@@ -0,0 +1,60 @@
+use serde_json::Value;
+use vpn_render::deep_merge;
+use vpn_types::*;
+
+use crate::util::Error;
+
+/// Returns the verification settings that apply to the MaskProvider's next
+/// verification cycle: its own `spec.verify` merged onto the operator's
+/// default. Fields the MaskProvider sets, including `skip`, take precedence,
+/// while those it leaves unset are taken from the default.
+pub fn effective_verify(
+    instance: &MaskProvider,
+    default: Option<&MaskProviderVerifySpec>,
+) -> Result<Option<MaskProviderVerifySpec>, Error> {
+    let (own, default) = match (instance.spec.verify.as_ref(), default) {
+        (own, None) => return Ok(own.cloned()),
+        (None, default) => return Ok(default.cloned()),
+        (Some(own), Some(default)) => (own, default),
+    };
+    let mut merged = serde_json::to_value(default)?;
+    deep_merge(&mut merged, set_fields(own)?);
+    Ok(Some(serde_json::from_value(merged)?))
+}
+
+/// Returns the verification settings of the MaskProvider's current cycle,
+/// as recorded when it began. MaskProviders whose cycle began before the
+/// settings were recorded fall back to their own `spec.verify`.
+pub fn cycle_verify(instance: &MaskProvider) -> Option<&MaskProviderVerifySpec> {
+    instance
+        .status
+        .as_ref()
+        .and_then(|status| status.effective_verify.as_ref())
+        .or(instance.spec.verify.as_ref())
+}
+
+/// Serializes the verification settings without the fields that are unset,
+/// which would otherwise remove the default's when merged. Only the fields
+/// of the settings' own structs are removed, as an explicit null within the
+/// free-form overrides is meaningful.
+fn set_fields(verify: &MaskProviderVerifySpec) -> Result<Value, Error> {
+    let mut value = serde_json::to_value(verify)?;
+    remove_unset(&mut value);
+    if let Some(overrides) = value.get_mut("overrides") {
+        remove_unset(overrides);
+        if let Some(containers) = overrides.get_mut("containers") {
+            remove_unset(containers);
+        }
+    }
+    if let Some(placement) = value.get_mut("placement") {
+        remove_unset(placement);
+    }
+    Ok(value)
+}
+
+/// Removes the null fields of the serialized struct.
+fn remove_unset(value: &mut Value) {
+    if let Value::Object(fields) = value {
+        fields.retain(|_, v| !v.is_null());
+    }
+}
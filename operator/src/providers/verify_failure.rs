@@ -0,0 +1,88 @@
+use kube::{
+    api::{DeleteParams, Preconditions},
+    Api, Client, ResourceExt,
+};
+use vpn_types::*;
+
+use super::withdrawal;
+use crate::util::{events, messages, Error, ErrorContext};
+
+/// Returns true if the MaskProvider's MaskConsumers are to be evicted:
+/// it failed re-verification after having been verified before, and
+/// has `onVerifyFailure: Evict`.
+pub fn evicts(instance: &MaskProvider) -> bool {
+    let status = instance.status.as_ref();
+    instance.spec.on_verify_failure == Some(VerifyFailureAction::Evict)
+        && status.and_then(|s| s.phase) == Some(MaskProviderPhase::ErrVerifyFailed)
+        && status.and_then(|s| s.last_verified.as_ref()).is_some()
+}
+
+/// Returns the MaskConsumers holding the reservations that can still be
+/// evicted, i.e. that exist and aren't already being deleted.
+pub async fn list_evictable(
+    client: Client,
+    reservations: &[MaskReservation],
+) -> Result<Vec<MaskConsumer>, Error> {
+    let mut consumers = Vec::new();
+    for reservation in reservations {
+        let consumer = Api::<MaskConsumer>::namespaced(client.clone(), &reservation.spec.namespace)
+            .get_opt(&reservation.spec.name)
+            .await
+            .context_kind_name("MaskConsumer", &reservation.spec.name)?;
+        consumers.extend(consumer.filter(|c| {
+            c.uid().as_deref() == Some(reservation.spec.uid.as_str())
+                && c.metadata.deletion_timestamp.is_none()
+        }));
+    }
+    Ok(consumers)
+}
+
+/// Deletes the MaskConsumers because the MaskProvider failed
+/// re-verification, which deletes their credentials and releases their
+/// slots. Their Masks are told why before they go back to Waiting.
+pub async fn evict(
+    client: Client,
+    instance: &MaskProvider,
+    consumers: &[MaskConsumer],
+) -> Result<(), Error> {
+    let namespace = instance.metadata.namespace.as_deref().unwrap();
+    let name = instance.metadata.name.as_deref().unwrap();
+    events::warn(
+        client.clone(),
+        instance,
+        "VerifyFailureEviction",
+        "Evict",
+        messages::verify_failure_evicting(consumers.len()),
+    )
+    .await;
+    let message = messages::verify_failure_evicted(namespace, name);
+    for consumer in consumers {
+        withdrawal::report_consumer(
+            client.clone(),
+            instance,
+            consumer,
+            "VerifyFailureEviction",
+            &message,
+        )
+        .await?;
+        let api: Api<MaskConsumer> = Api::namespaced(
+            client.clone(),
+            consumer.metadata.namespace.as_deref().unwrap(),
+        );
+        let params = DeleteParams {
+            // Never delete a MaskConsumer that replaced the evicted one.
+            preconditions: Some(Preconditions {
+                uid: consumer.uid(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        match api.delete(&consumer.name_any(), &params).await {
+            Ok(_) => {}
+            // Already gone or replaced.
+            Err(kube::Error::Api(e)) if e.code == 404 || e.code == 409 => {}
+            Err(e) => return Err(e).context_kind_name("MaskConsumer", &consumer.name_any()),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use openssl::sha::Sha256;
+use serde_json::json;
+use vpn_render::vpn_image;
+use vpn_types::*;
+
+use crate::util::Error;
+
+/// Returns the hex-encoded SHA-256 checksum of what the MaskProvider's
+/// credentials are verified with under the verification settings: the
+/// checksum of the credentials, the settings and the gluetun image.
+/// `skip`, `interval` and `spread` are left out, as they only decide
+/// whether and when verification runs, not how.
+pub fn config_hash(
+    instance: &MaskProvider,
+    verify: Option<&MaskProviderVerifySpec>,
+) -> Result<String, Error> {
+    let verify = verify
+        .map(|verify| MaskProviderVerifySpec {
+            skip: None,
+            interval: None,
+            spread: None,
+            ..verify.clone()
+        })
+        // Settings with nothing else set verify the same as none at all.
+        .filter(|verify| *verify != MaskProviderVerifySpec::default());
+    let secret_hash = instance
+        .status
+        .as_ref()
+        .and_then(|s| s.secret_hash.as_ref());
+    let inputs = json!({
+        "secretHash": secret_hash,
+        "verify": verify,
+        "image": vpn_image(&instance.spec),
+    });
+    let mut hasher = Sha256::new();
+    hasher.update(&serde_json::to_vec(&inputs)?);
+    Ok(hasher
+        .finish()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Returns true if the credentials were last verified with the same
+/// inputs as they would be under the verification settings. Credentials
+/// verified before the checksum was recorded, or whose `lastVerified`
+/// was set by hand, never are.
+pub fn is_current(
+    instance: &MaskProvider,
+    verify: Option<&MaskProviderVerifySpec>,
+) -> Result<bool, Error> {
+    let recorded = instance
+        .status
+        .as_ref()
+        .and_then(|s| s.last_verified_config_hash.as_deref());
+    Ok(recorded.is_some() && recorded == Some(config_hash(instance, verify)?.as_str()))
+}
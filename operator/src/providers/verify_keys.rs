@@ -0,0 +1,100 @@
+use k8s_openapi::api::core::v1::Secret;
+use regex::Regex;
+use std::collections::BTreeSet;
+use vpn_types::*;
+
+use crate::util::messages;
+
+/// Pattern from `verify.keyPatterns` that a key's value must match.
+#[derive(Clone, Debug)]
+pub struct KeyPattern {
+    /// Key of the credentials Secret.
+    pub key: String,
+
+    /// Pattern as written in the settings, for messages.
+    pub pattern: String,
+
+    /// Pattern anchored to match the whole value.
+    pub regex: Regex,
+}
+
+/// Returns true if the settings verify the credentials with static checks
+/// of the Secret only, instead of dialing them.
+pub fn is_keys_only(verify: Option<&MaskProviderVerifySpec>) -> bool {
+    verify.and_then(|v| v.mode).unwrap_or_default() == MaskProviderVerifyMode::KeysOnly
+}
+
+/// Compiles the settings' `keyPatterns`, each anchored so it has to match
+/// the whole value. A pattern that isn't a valid regular expression is
+/// returned as the message explaining why.
+pub fn compile_patterns(verify: &MaskProviderVerifySpec) -> Result<Vec<KeyPattern>, String> {
+    verify
+        .key_patterns
+        .iter()
+        .flatten()
+        .map(|(key, pattern)| {
+            Regex::new(&format!("^(?:{})$", pattern))
+                .map(|regex| KeyPattern {
+                    key: key.clone(),
+                    pattern: pattern.clone(),
+                    regex,
+                })
+                .map_err(|e| messages::invalid_key_pattern(key, &e.to_string()))
+        })
+        .collect()
+}
+
+/// Returns the value of the key in the Secret, if it has one.
+fn value(secret: &Secret, key: &str) -> Option<String> {
+    if let Some(value) = secret.string_data.as_ref().and_then(|d| d.get(key)) {
+        return Some(value.clone());
+    }
+    secret
+        .data
+        .as_ref()
+        .and_then(|d| d.get(key))
+        .map(|value| String::from_utf8_lossy(&value.0).into_owned())
+}
+
+/// Checks the credentials Secret without dialing it: every required key,
+/// including those with a pattern, must have a non-empty value, and the
+/// values must match their patterns. Without any required keys, the
+/// Secret must have at least one. Returns the message failing the
+/// verification if a check doesn't pass.
+pub fn check(
+    verify: &MaskProviderVerifySpec,
+    patterns: &[KeyPattern],
+    secret: &Secret,
+) -> Result<(), String> {
+    let required: BTreeSet<&str> = verify
+        .required_keys
+        .iter()
+        .flatten()
+        .map(String::as_str)
+        .chain(patterns.iter().map(|p| p.key.as_str()))
+        .collect();
+    if required.is_empty() {
+        let data = secret.data.as_ref().map_or(0, |d| d.len());
+        let string_data = secret.string_data.as_ref().map_or(0, |d| d.len());
+        if data + string_data == 0 {
+            return Err(messages::VERIFY_NO_KEYS.to_owned());
+        }
+    }
+    for key in required {
+        match value(secret, key) {
+            None => return Err(messages::verify_key_missing(key)),
+            Some(value) if value.is_empty() => return Err(messages::verify_key_empty(key)),
+            Some(_) => {}
+        }
+    }
+    for pattern in patterns {
+        let value = value(secret, &pattern.key).unwrap_or_default();
+        if !pattern.regex.is_match(&value) {
+            return Err(messages::verify_key_mismatch(
+                &pattern.key,
+                &pattern.pattern,
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use kube::{api::ListParams, Api, Client, ResourceExt};
+use std::collections::BTreeSet;
+use vpn_types::*;
+
+use super::watches::verification_list_params;
+use crate::util::{list::list_all_paginated, Error, VERIFICATION_LABEL};
+
+/// Outcome of checking whether a MaskProvider may begin verification
+/// without exceeding the cap on concurrent verifications.
+#[derive(Debug, PartialEq)]
+pub struct QueueCheck {
+    /// Position of the MaskProvider in the queue, starting from 1, or
+    /// None if it may begin verification now.
+    pub position: Option<usize>,
+
+    /// Number of MaskProviders that can't begin verification yet.
+    pub depth: usize,
+}
+
+/// Decides whether the MaskProvider may begin verification, given every
+/// MaskProvider in the cluster, the UIDs of those with a verification in
+/// flight, and the maximum number of verifications in flight at once.
+/// MaskProviders that were deferred are left Verifying without a
+/// verification Mask, and are started in order of creation so that
+/// none of them waits forever. Pending MaskProviders are about to be
+/// verified for the first time, so they're in line as well.
+pub fn check(
+    instance: &MaskProvider,
+    providers: &[MaskProvider],
+    in_flight: &BTreeSet<String>,
+    max: usize,
+) -> QueueCheck {
+    let uid = instance.uid().unwrap_or_default();
+    let mut waiting: Vec<&MaskProvider> = providers
+        .iter()
+        .filter(|p| p.uid().is_some_and(|u| u != uid && !in_flight.contains(&u)))
+        .filter(|p| {
+            matches!(
+                p.status.as_ref().and_then(|s| s.phase),
+                Some(MaskProviderPhase::Pending | MaskProviderPhase::Verifying)
+            )
+        })
+        .chain(std::iter::once(instance))
+        .collect();
+    waiting.sort_by(|a, b| {
+        let key = |p: &MaskProvider| (p.creation_timestamp(), p.namespace(), p.name_any());
+        key(a).cmp(&key(b))
+    });
+    let free = max.saturating_sub(in_flight.len());
+    let depth = waiting.len().saturating_sub(free);
+    let index = waiting
+        .iter()
+        .position(|p| p.uid() == instance.uid())
+        .unwrap();
+    QueueCheck {
+        position: index.checked_sub(free).map(|behind| behind + 1),
+        depth,
+    }
+}
+
+/// Returns the UIDs of the MaskProviders with a verification in flight,
+/// i.e. that have a verification Mask, including one being deleted.
+pub fn in_flight(masks: &[Mask]) -> BTreeSet<String> {
+    masks
+        .iter()
+        .filter_map(|mask| mask.labels().get(VERIFICATION_LABEL).cloned())
+        .collect()
+}
+
+/// Lists the MaskProviders and verification Masks across all namespaces
+/// and checks the MaskProvider's place in the verification queue.
+pub async fn check_queue(
+    client: Client,
+    instance: &MaskProvider,
+    max: usize,
+) -> Result<QueueCheck, Error> {
+    let providers = list_all_paginated(
+        &Api::<MaskProvider>::all(client.clone()),
+        &ListParams::default(),
+    )
+    .await?;
+    let masks = list_all_paginated(&Api::<Mask>::all(client), &verification_list_params()).await?;
+    Ok(check(instance, &providers, &in_flight(&masks), max))
+}
@@ -0,0 +1,82 @@
+use chrono::{DateTime, Utc};
+use openssl::sha::sha256;
+use std::time::Duration;
+use vpn_types::*;
+
+use crate::util::{clock, Error};
+
+/// Percentage of the interval that re-verifications are spread across
+/// if the verification settings don't specify a `spread`.
+pub const DEFAULT_SPREAD_PERCENT: u32 = 10;
+
+/// Returns the window that re-verifications every `interval` are spread
+/// across: the settings' `spread`, or [`DEFAULT_SPREAD_PERCENT`] of the
+/// interval if unset.
+pub fn spread(verify: &MaskProviderVerifySpec, interval: Duration) -> Result<Duration, Error> {
+    match verify.spread.as_deref() {
+        Some(spread) => Ok(parse_duration::parse(spread)?),
+        None => Ok(interval * DEFAULT_SPREAD_PERCENT / 100),
+    }
+}
+
+/// Returns the MaskProvider's offset within the `spread` window, derived
+/// from its uid so it's the same every time it's computed. MaskProviders
+/// created together have unrelated uids, so their offsets are spread
+/// evenly across the window.
+pub fn jitter(uid: &str, spread: Duration) -> Duration {
+    let window = spread.as_millis() as u64;
+    if window == 0 {
+        return Duration::ZERO;
+    }
+    let digest = sha256(uid.as_bytes());
+    let hash = u64::from_be_bytes(digest[..8].try_into().unwrap());
+    Duration::from_millis(hash % window)
+}
+
+/// Returns when credentials verified at `last_verified` are due to be
+/// verified again under the settings, or None if they aren't verified
+/// periodically. That's the interval after `last_verified`, plus the
+/// MaskProvider's [`jitter`] within the spread window.
+pub fn next_after(
+    instance: &MaskProvider,
+    verify: Option<&MaskProviderVerifySpec>,
+    last_verified: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>, Error> {
+    let verify = match verify {
+        Some(verify) => verify,
+        None => return Ok(None),
+    };
+    let interval = match verify.interval.as_deref() {
+        Some(interval) => parse_duration::parse(interval)?,
+        None => return Ok(None),
+    };
+    let uid = instance.metadata.uid.as_deref().unwrap_or_default();
+    let delay = interval + jitter(uid, spread(verify, interval)?);
+    Ok(Some(last_verified + chrono::Duration::from_std(delay)?))
+}
+
+/// Returns when the MaskProvider's credentials are next verified under
+/// the settings, going by `status.lastVerified`. None if they were never
+/// verified or aren't verified periodically.
+pub fn next_verification(
+    instance: &MaskProvider,
+    verify: Option<&MaskProviderVerifySpec>,
+) -> Result<Option<DateTime<Utc>>, Error> {
+    let last_verified = match instance
+        .status
+        .as_ref()
+        .and_then(|s| s.last_verified.as_deref())
+    {
+        Some(last_verified) => clock::parse_timestamp(last_verified)?,
+        None => return Ok(None),
+    };
+    next_after(instance, verify, last_verified)
+}
+
+/// Returns the MaskProvider's `status.nextVerification` under the settings.
+pub fn next_verification_status(
+    instance: &MaskProvider,
+    verify: Option<&MaskProviderVerifySpec>,
+) -> Result<Option<String>, Error> {
+    Ok(next_verification(instance, verify)?.map(clock::format_timestamp))
+}
@@ -0,0 +1,65 @@
+use k8s_openapi::api::core::v1::{Pod, Secret};
+use kube::{
+    api::ListParams,
+    runtime::reflector::{ObjectRef, Store},
+    ResourceExt,
+};
+use vpn_types::*;
+
+use crate::util::{consumer_purpose, MANAGER_NAME, PROVIDER_SECRET_LABEL, VERIFICATION_LABEL};
+
+/// Selects only the resources created to verify a `MaskProvider`, so
+/// the controller isn't woken up by unrelated Pods and `MaskConsumer`s.
+pub fn verification_list_params() -> ListParams {
+    ListParams::default().labels(VERIFICATION_LABEL)
+}
+
+/// Selects only the `Mask`s the operator creates for itself, i.e. the
+/// verification and canary `Mask`s owned by `MaskProvider`s, so events
+/// of the ordinary `Mask`s across the cluster aren't mapped to owners.
+pub fn owned_mask_list_params() -> ListParams {
+    ListParams::default().labels(&format!("app={}", MANAGER_NAME))
+}
+
+/// Maps a verification Pod to the `MaskProvider` it verifies. The Pod
+/// is named after the `MaskProvider` and created in its namespace.
+pub fn verify_pod_provider(pod: Pod) -> Option<ObjectRef<MaskProvider>> {
+    if !pod.labels().contains_key(VERIFICATION_LABEL) {
+        return None;
+    }
+    Some(ObjectRef::new(&pod.name_any()).within(pod.namespace().as_deref()?))
+}
+
+/// Maps a verification `MaskConsumer` to the `MaskProvider` it was
+/// assigned, which is always the one being verified. Unassigned
+/// `MaskConsumer`s aren't mapped, as there's nothing to react to yet.
+pub fn verify_consumer_provider(consumer: MaskConsumer) -> Option<ObjectRef<MaskProvider>> {
+    if consumer_purpose(&consumer) != MaskConsumerPurpose::Verification {
+        return None;
+    }
+    let provider = consumer.status?.provider?;
+    Some(ObjectRef::new(&provider.name).within(&provider.namespace))
+}
+
+/// Selects only the Secrets the controller labelled as referenced by a
+/// `MaskProvider`, so the rest of the cluster's Secrets aren't cached.
+pub fn secret_list_params() -> ListParams {
+    ListParams::default().labels(PROVIDER_SECRET_LABEL)
+}
+
+/// Maps a Secret to the `MaskProvider`s in its namespace that reference
+/// it, so a change to the credentials is recorded in their status right
+/// away. Secrets can't be selected by what references them, so the
+/// `MaskProvider`s are looked up in `store`.
+pub fn secret_providers(
+    store: &Store<MaskProvider>,
+    secret: &Secret,
+) -> Vec<ObjectRef<MaskProvider>> {
+    let namespace = secret.namespace();
+    store
+        .state()
+        .iter()
+        .filter(|p| p.namespace() == namespace && p.spec.secret == secret.name_any())
+        .map(|p| ObjectRef::from_obj(p.as_ref()))
+        .collect()
+}
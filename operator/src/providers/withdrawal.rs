@@ -0,0 +1,114 @@
+use kube::{Api, Client};
+use vpn_types::*;
+
+use crate::util::{
+    clock, events, is_verification_reservation, messages, patch::patch_status, Error, ErrorContext,
+};
+
+/// Returns the name of the Mask that owns the MaskConsumer, if any.
+pub fn owning_mask(consumer: &MaskConsumer) -> Option<&str> {
+    consumer
+        .metadata
+        .owner_references
+        .iter()
+        .flatten()
+        .find(|or| or.kind == "Mask")
+        .map(|or| or.name.as_str())
+}
+
+/// Tells the Masks holding the reservations that the MaskProvider was
+/// withdrawn from them, by recording it in their status and publishing a
+/// Warning event in their namespace. MaskConsumers and Masks that no longer
+/// exist or are being deleted are skipped, as are verification reservations.
+/// It's safe to call again if reporting to some of the Masks failed, as
+/// Masks that were already told are skipped as well.
+pub async fn report(
+    client: Client,
+    instance: &MaskProvider,
+    reservations: &[&MaskReservation],
+) -> Result<(), Error> {
+    let name = instance.metadata.name.as_deref().unwrap();
+    let namespace = instance.metadata.namespace.as_deref().unwrap();
+    let message = messages::provider_withdrawn(namespace, name);
+    for reservation in reservations {
+        if is_verification_reservation(reservation) {
+            continue;
+        }
+        let consumer = Api::<MaskConsumer>::namespaced(client.clone(), &reservation.spec.namespace)
+            .get_opt(&reservation.spec.name)
+            .await
+            .context_kind_name("MaskConsumer", &reservation.spec.name)?;
+        if let Some(consumer) = consumer {
+            report_consumer(
+                client.clone(),
+                instance,
+                &consumer,
+                "ProviderWithdrawn",
+                &message,
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Tells the Mask that owns the MaskConsumer that the MaskProvider was
+/// withdrawn from it, by recording the message in its status and
+/// publishing a Warning event with the reason. Nothing is reported if
+/// the Mask is gone or being deleted, or its status already shows the
+/// same withdrawal, so retrying after a failure doesn't report it twice.
+pub async fn report_consumer(
+    client: Client,
+    instance: &MaskProvider,
+    consumer: &MaskConsumer,
+    reason: &str,
+    message: &str,
+) -> Result<(), Error> {
+    let mask = match get_mask(client.clone(), consumer).await? {
+        Some(mask) => mask,
+        None => return Ok(()),
+    };
+    if is_reported(&mask, instance, message) {
+        return Ok(());
+    }
+    let withdrawn = ProviderWithdrawn {
+        name: instance.metadata.name.clone().unwrap(),
+        namespace: instance.metadata.namespace.clone().unwrap(),
+        message: message.to_owned(),
+        at: clock::now_k8s(),
+    };
+    patch_status(client.clone(), &mask, |status| {
+        status.provider_withdrawn = Some(withdrawn);
+    })
+    .await?;
+    events::warn(client, &mask, reason, "Unassign", message.to_owned()).await;
+    Ok(())
+}
+
+/// Returns true if the Mask's status already shows that the MaskProvider
+/// was withdrawn from it with the message.
+fn is_reported(mask: &Mask, instance: &MaskProvider, message: &str) -> bool {
+    mask.status
+        .as_ref()
+        .and_then(|s| s.provider_withdrawn.as_ref())
+        .is_some_and(|w| {
+            Some(w.name.as_str()) == instance.metadata.name.as_deref()
+                && Some(w.namespace.as_str()) == instance.metadata.namespace.as_deref()
+                && w.message == message
+        })
+}
+
+/// Returns the Mask that owns the MaskConsumer, or None if it's
+/// gone or being deleted.
+async fn get_mask(client: Client, consumer: &MaskConsumer) -> Result<Option<Mask>, Error> {
+    let mask_name = match owning_mask(consumer) {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    let namespace = consumer.metadata.namespace.as_deref().unwrap();
+    let mask = Api::<Mask>::namespaced(client, namespace)
+        .get_opt(mask_name)
+        .await
+        .context_kind_name("Mask", mask_name)?;
+    Ok(mask.filter(|m| m.metadata.deletion_timestamp.is_none()))
+}
@@ -0,0 +1,217 @@
+use k8s_openapi::api::rbac::v1::{ClusterRole, PolicyRule, Role};
+use kube::api::ObjectMeta;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::util::Error;
+
+/// Permission to use `verbs` on `resources` in the API `group`. Each
+/// module that talks to the API server declares the rules it needs, and
+/// [`generate`] combines them into the roles the operator is bound to.
+#[derive(Clone, Copy, Debug)]
+pub struct Rule {
+    /// API group of the resources. The core group is `""`.
+    pub group: &'static str,
+
+    /// Plural names of the resources, including subresources such
+    /// as `masks/status`.
+    pub resources: &'static [&'static str],
+
+    /// Verbs allowed on the resources.
+    pub verbs: &'static [&'static str],
+}
+
+impl Rule {
+    /// Returns a rule allowing `verbs` on `resources` in `group`.
+    pub const fn new(
+        group: &'static str,
+        resources: &'static [&'static str],
+        verbs: &'static [&'static str],
+    ) -> Self {
+        Self {
+            group,
+            resources,
+            verbs,
+        }
+    }
+}
+
+/// Rules every controller needs: publishing events. Reading the
+/// operator config ConfigMap is granted separately, by the `config`
+/// component, in the operator's namespace only.
+pub const COMMON: &[Rule] = &[Rule::new("events.k8s.io", &["events"], &["create"])];
+
+/// Where a [`Component`]'s rules are granted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    /// Cluster-wide, or in each of the watched namespaces if the
+    /// operator is restricted to some.
+    Watched,
+
+    /// Only by Roles in the namespaces previews are enabled in.
+    Previews,
+
+    /// Only by a Role in the operator's own namespace.
+    Operator,
+}
+
+/// A program run with its own service account, and the rules it needs.
+pub struct Component {
+    /// Suffix of the generated roles' names.
+    pub name: &'static str,
+
+    /// Rules declared by the modules the component runs.
+    pub rules: &'static [&'static [Rule]],
+
+    /// Where the rules are granted.
+    pub scope: Scope,
+}
+
+/// Every component of the operator. A module's rules must be listed
+/// under each component that runs it.
+pub const REGISTRY: &[Component] = &[
+    // The controllers and the webhook, which run with the same
+    // service account in the chart.
+    Component {
+        name: "operator",
+        rules: &[
+            COMMON,
+            crate::masks::RBAC,
+            crate::consumers::RBAC,
+            crate::providers::RBAC,
+            crate::reservations::RBAC,
+            crate::clusterproviders::RBAC,
+            crate::jobs::RBAC,
+            crate::webhook::RBAC,
+        ],
+        scope: Scope::Watched,
+    },
+    // Reading the operator config ConfigMap, which the controllers and
+    // `verify-all` only ever read from the operator's namespace.
+    Component {
+        name: "config",
+        rules: &[crate::util::config::RBAC],
+        scope: Scope::Operator,
+    },
+    // Writing the previews requested with the explain annotation, which
+    // is only granted in the namespaces previews are enabled in.
+    Component {
+        name: "previews",
+        rules: &[crate::util::preview::RBAC],
+        scope: Scope::Previews,
+    },
+    Component {
+        name: "status-exporter",
+        rules: &[crate::export::RBAC],
+        scope: Scope::Watched,
+    },
+    // The `status` and `verify-all` commands, for the humans or
+    // pipelines that run them.
+    Component {
+        name: "cli",
+        rules: &[crate::status::RBAC, crate::verify_all::RBAC],
+        scope: Scope::Watched,
+    },
+];
+
+/// Resources that aren't namespaced, which can only be granted by a
+/// ClusterRole.
+pub const CLUSTER_SCOPED: &[&str] = &[
+    "namespaces",
+    "nodes",
+    "priorityclasses",
+    "clustermaskproviders",
+    "clustermaskproviders/status",
+    "vpnfleets",
+    "vpnfleets/status",
+];
+
+/// Returns the verbs allowed on each resource, keyed by API group and
+/// resource, for all of the rules.
+pub fn verbs(rules: &[&[Rule]]) -> BTreeMap<(&'static str, &'static str), BTreeSet<&'static str>> {
+    let mut verbs: BTreeMap<_, BTreeSet<_>> = BTreeMap::new();
+    for rule in rules.iter().copied().flatten() {
+        for resource in rule.resources {
+            verbs
+                .entry((rule.group, *resource))
+                .or_default()
+                .extend(rule.verbs.iter().copied());
+        }
+    }
+    verbs
+}
+
+/// Returns the policy rules allowing the verbs, with resources that
+/// allow the same verbs in the same group combined into one rule.
+fn policy_rules(
+    verbs: impl Iterator<Item = ((&'static str, &'static str), BTreeSet<&'static str>)>,
+) -> Vec<PolicyRule> {
+    let mut combined: BTreeMap<(&str, BTreeSet<&str>), Vec<&str>> = BTreeMap::new();
+    for ((group, resource), verbs) in verbs {
+        combined.entry((group, verbs)).or_default().push(resource);
+    }
+    combined
+        .into_iter()
+        .map(|((group, verbs), resources)| PolicyRule {
+            api_groups: Some(vec![group.to_owned()]),
+            resources: Some(resources.into_iter().map(str::to_owned).collect()),
+            verbs: verbs.into_iter().map(str::to_owned).collect(),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Generates the RBAC the operator needs, as YAML documents with a role
+/// for each component named `{prefix}-{component}`. Without any
+/// `watch_namespaces`, each component gets a ClusterRole with all of its
+/// rules. Otherwise, each gets a Role in each of the namespaces with the
+/// rules for namespaced resources, and a ClusterRole only for the
+/// resources that aren't namespaced. Components scoped to previews only
+/// get a Role in each of the `preview_namespaces`, and those scoped to
+/// the operator only get one in `operator_namespace`.
+pub fn generate(
+    prefix: &str,
+    operator_namespace: &str,
+    watch_namespaces: &[String],
+    preview_namespaces: &[String],
+) -> Result<String, Error> {
+    let operator_namespace = [operator_namespace.to_owned()];
+    let mut documents = Vec::new();
+    for component in REGISTRY {
+        let name = format!("{}-{}", prefix, component.name);
+        let namespaces: &[String] = match component.scope {
+            Scope::Watched => watch_namespaces,
+            Scope::Previews => preview_namespaces,
+            Scope::Operator => &operator_namespace,
+        };
+        let (cluster_scoped, namespaced): (Vec<_>, Vec<_>) = verbs(component.rules)
+            .into_iter()
+            .partition(|((_, resource), _)| {
+                (namespaces.is_empty() && component.scope == Scope::Watched)
+                    || CLUSTER_SCOPED.contains(resource)
+            });
+        if !cluster_scoped.is_empty() {
+            documents.push(serde_yaml::to_string(&ClusterRole {
+                metadata: ObjectMeta {
+                    name: Some(name.clone()),
+                    ..Default::default()
+                },
+                rules: Some(policy_rules(cluster_scoped.into_iter())),
+                ..Default::default()
+            })?);
+        }
+        if namespaced.is_empty() {
+            continue;
+        }
+        for namespace in namespaces {
+            documents.push(serde_yaml::to_string(&Role {
+                metadata: ObjectMeta {
+                    name: Some(name.clone()),
+                    namespace: Some(namespace.clone()),
+                    ..Default::default()
+                },
+                rules: Some(policy_rules(namespaced.iter().cloned())),
+            })?);
+        }
+    }
+    Ok(documents.join("---\n"))
+}
@@ -1,4 +1,4 @@
-use crate::util::{messages, patch::*, Error};
+use crate::util::{messages, patch::*, Error, ErrorContext};
 use kube::{Api, Client};
 use vpn_types::*;
 
@@ -6,8 +6,7 @@ use vpn_types::*;
 /// the resource made its initial appearance to the operator.
 pub async fn pending(client: Client, instance: &MaskReservation) -> Result<(), Error> {
     patch_status(client, instance, |status| {
-        status.message = Some(messages::PENDING.to_owned());
-        status.phase = Some(MaskReservationPhase::Pending);
+        status.set_phase(MaskReservationPhase::Pending, messages::PENDING);
     })
     .await?;
     Ok(())
@@ -16,8 +15,10 @@ pub async fn pending(client: Client, instance: &MaskReservation) -> Result<(), E
 /// Updates the `MaskReservation`'s phase to Active.
 pub async fn active(client: Client, instance: &MaskReservation) -> Result<(), Error> {
     patch_status(client, instance, |status| {
-        status.phase = Some(MaskReservationPhase::Active);
-        status.message = Some("MaskReservation is in use by the MaskConsumer.".to_owned());
+        status.set_phase(
+            MaskReservationPhase::Active,
+            "MaskReservation is in use by the MaskConsumer.",
+        );
     })
     .await?;
     Ok(())
@@ -26,8 +27,7 @@ pub async fn active(client: Client, instance: &MaskReservation) -> Result<(), Er
 /// Updates the `MaskReservation`'s phase to Terminating.
 pub async fn terminating(client: Client, instance: &MaskReservation) -> Result<(), Error> {
     patch_status(client, instance, |status| {
-        status.phase = Some(MaskReservationPhase::Terminating);
-        status.message = Some(messages::TERMINATING.to_owned());
+        status.set_phase(MaskReservationPhase::Terminating, messages::TERMINATING);
     })
     .await?;
     Ok(())
@@ -38,24 +38,29 @@ pub async fn terminating(client: Client, instance: &MaskReservation) -> Result<(
 /// collect the slots for a `MaskProvider`.
 pub async fn delete(client: Client, name: &str, namespace: &str) -> Result<(), Error> {
     let mr_api: Api<MaskReservation> = Api::namespaced(client, namespace);
-    mr_api.delete(name, &Default::default()).await?;
-    Ok(())
+    match mr_api.delete(name, &Default::default()).await {
+        Ok(_) => Ok(()),
+        // The MaskReservation was deleted in the meantime, e.g. with its namespace.
+        Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(()),
+        Err(e) => Err(e).context_kind_name("MaskReservation", name),
+    }
 }
 
 /// Deletes the [`MaskConsumer`] referenced by the given [`MaskReservation`].
-/// Returns true if the [`MaskConsumer`] does not exist, false if it does exist
-/// and was deleted.
+/// Returns true once the [`MaskConsumer`] is observed to be gone, and false
+/// while it still exists. A [`MaskConsumer`] that is already being deleted
+/// isn't deleted again, as only its own finalizer decides when it's gone.
 pub async fn delete_consumer(client: Client, instance: &MaskReservation) -> Result<bool, Error> {
     // Retrieve the MaskConsumer referenced by this MaskReservation.
     let mc_api: Api<MaskConsumer> = Api::namespaced(client, &instance.spec.namespace);
-    let _mc = match mc_api.get(&instance.spec.name).await {
+    let mc = match mc_api.get(&instance.spec.name).await {
         // Ensure the `MaskConsumer` has the same UID as referenced in the spec.
         Ok(mc)
             if mc
                 .metadata
                 .uid
                 .as_deref()
-                .map_or(false, |uid| instance.spec.uid == uid) =>
+                .is_some_and(|uid| instance.spec.uid == uid) =>
         {
             // The referenced MaskConsumer is still around. We will need to
             // delete it and requeue to ensure it is deleted before removing
@@ -68,15 +73,25 @@ pub async fn delete_consumer(client: Client, instance: &MaskReservation) -> Resu
         // MaskConsumer is no longer around.
         Err(kube::Error::Api(ae)) if ae.code == 404 => return Ok(true),
         // Some other error occurred.
-        Err(e) => return Err(e.into()),
+        Err(e) => return Err(e).context_kind_name("MaskConsumer", &instance.spec.name),
     };
 
+    // The MaskConsumer is already terminating, e.g. because its Mask or
+    // namespace was deleted at the same time. Wait for it to disappear.
+    if mc.metadata.deletion_timestamp.is_some() {
+        return Ok(false);
+    }
+
     // Delete the `MaskConsumer`. Its deletion logic is trivial and should be
     // removed by the Kubernetes cluster as soon as its child resources are gone.
-    mc_api
+    match mc_api
         .delete(&instance.spec.name, &Default::default())
-        .await?;
-
-    // Requeue to ensure the `MaskConsumer` is deleted.
-    Ok(false)
+        .await
+    {
+        // Requeue to ensure the `MaskConsumer` is deleted.
+        Ok(_) => Ok(false),
+        // The MaskConsumer finished deleting in the meantime.
+        Err(kube::Error::Api(ae)) if ae.code == 404 => Ok(true),
+        Err(e) => Err(e).context_kind_name("MaskConsumer", &instance.spec.name),
+    }
 }
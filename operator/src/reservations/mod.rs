@@ -1,4 +1,18 @@
-mod actions;
-mod reconcile;
+pub(crate) mod actions;
+pub(crate) mod reconcile;
 
 pub use reconcile::run;
+
+use crate::rbac::Rule;
+
+/// API access the MaskReservation controller needs. See [`crate::rbac`].
+pub const RBAC: &[Rule] = &[
+    Rule::new(
+        "vpn.beebs.dev",
+        &["maskreservations"],
+        &["get", "list", "watch", "patch", "delete"],
+    ),
+    Rule::new("vpn.beebs.dev", &["maskreservations/status"], &["patch"]),
+    // Reservations are deleted once their MaskConsumer is gone.
+    Rule::new("vpn.beebs.dev", &["maskconsumers"], &["get"]),
+];
@@ -1,29 +1,45 @@
-use chrono::Utc;
 use futures::stream::StreamExt;
 use kube::{
-    api::ListParams, client::Client, runtime::controller::Action, runtime::Controller, Api,
-    ResourceExt,
+    api::ListParams,
+    client::Client,
+    runtime::{controller::Action, events::Reporter, Controller},
+    Api, ResourceExt,
 };
 use std::sync::Arc;
-use tokio::time::Duration;
+use tokio::{sync::Semaphore, time::Duration};
 use vpn_types::*;
 
 use super::actions;
 use crate::util::{
+    events, explain,
     finalizer::{self, FINALIZER_NAME},
-    Error, PROBE_INTERVAL,
+    needs_refresh,
+    patch::{ensure_status_initialized, record_action_error, status_phase},
+    report_reconcile_error, ControllerOptions, Error, PROBE_INTERVAL,
 };
 
 #[cfg(feature = "metrics")]
 use crate::util::metrics::ControllerMetrics;
 
-/// Entrypoint for the `MaskReservation` controller.
-pub async fn run(client: Client) -> Result<(), Error> {
+/// Entrypoint for the `MaskReservation` controller. If `concurrency` is set, at most
+/// that many reconciliations will be performed at the same time. Active statuses are
+/// only rewritten once they are older than `status_freshness`.
+pub async fn run(
+    client: Client,
+    concurrency: Option<usize>,
+    status_freshness: Duration,
+    options: ControllerOptions,
+) -> Result<(), Error> {
     println!("Starting MaskReservation controller...");
 
     // Preparation of resources used by the `kube_runtime::Controller`
     let crd_api: Api<MaskReservation> = Api::all(client.clone());
-    let context: Arc<ContextData> = Arc::new(ContextData::new(client.clone()));
+    let context: Arc<ContextData> = Arc::new(ContextData::new(
+        client.clone(),
+        concurrency,
+        status_freshness,
+        options,
+    ));
 
     // The controller comes from the `kube_runtime` crate and manages the reconciliation process.
     // It requires the following information:
@@ -52,6 +68,18 @@ struct ContextData {
     /// Kubernetes client to make Kubernetes API requests with. Required for K8S resource management.
     client: Client,
 
+    /// Limits the number of concurrent reconciliations, if configured.
+    semaphore: Option<Semaphore>,
+
+    /// How long an unchanged status goes without being rewritten.
+    status_freshness: Duration,
+
+    /// Reports the resources' phase transitions as events.
+    reporter: Reporter,
+
+    /// Settings from the command line, e.g. whether actions are explained.
+    options: ControllerOptions,
+
     #[cfg(feature = "metrics")]
     metrics: ControllerMetrics,
 }
@@ -61,25 +89,44 @@ impl ContextData {
     ///
     /// # Arguments:
     /// - `client`: A Kubernetes client to make Kubernetes REST API requests with. Resources
-    /// will be created and deleted with this client.
-    pub fn new(client: Client) -> Self {
+    ///   will be created and deleted with this client.
+    /// - `concurrency`: Optional maximum number of concurrent reconciliations.
+    /// - `status_freshness`: How long an unchanged status goes without being rewritten.
+    /// - `options`: Settings from the command line.
+    pub fn new(
+        client: Client,
+        concurrency: Option<usize>,
+        status_freshness: Duration,
+        options: ControllerOptions,
+    ) -> Self {
+        let semaphore = concurrency.map(Semaphore::new);
         #[cfg(feature = "metrics")]
         {
-            return ContextData {
+            ContextData {
                 client,
+                semaphore,
+                options,
+                reporter: events::reporter(),
+                status_freshness,
                 metrics: ControllerMetrics::new("reservations"),
-            };
+            }
         }
         #[cfg(not(feature = "metrics"))]
         {
-            return ContextData { client };
+            return ContextData {
+                client,
+                semaphore,
+                options,
+                reporter: events::reporter(),
+                status_freshness,
+            };
         }
     }
 }
 
 /// Action to be taken upon an [`MaskReservation`] resource during reconciliation
 #[derive(Debug, PartialEq)]
-enum ReservationAction {
+pub(crate) enum ReservationAction {
     /// Set the [`MaskReservationStatus::phase`] to [`Pending`](MaskReservationPhase::Pending)
     /// and add a finalizer to the resource.
     Pending,
@@ -110,9 +157,10 @@ impl ReservationAction {
 
 /// Returns true if the [`MaskReservation`] resource requires a status
 /// update to set the phase to `Pending`. This should be the first action
-/// for any managed resource.
+/// for any managed resource, and is repeated for any status that is
+/// missing fields the controller relies on.
 fn needs_pending(instance: &MaskReservation) -> bool {
-    needs_finalizer(instance) || instance.status.as_ref().map_or(true, |s| s.phase.is_none())
+    needs_finalizer(instance) || ensure_status_initialized(instance).is_err()
 }
 
 /// Returns true if the [`MaskReservation`] is missing the finalizer.
@@ -125,6 +173,12 @@ async fn reconcile(
     instance: Arc<MaskReservation>,
     context: Arc<ContextData>,
 ) -> Result<Action, Error> {
+    // Wait for a permit if the number of concurrent reconciliations is limited.
+    let _permit = match context.semaphore {
+        Some(ref semaphore) => Some(semaphore.acquire().await.unwrap()),
+        None => None,
+    };
+
     // The `Client` is shared -> a clone from the reference is obtained
     let client: Client = context.client.clone();
 
@@ -149,38 +203,31 @@ async fn reconcile(
 
     // Increment total number of reconciles for the MaskReservation resource.
     #[cfg(feature = "metrics")]
-    context
-        .metrics
-        .reconcile_counter
-        .with_label_values(&[&name, &namespace])
-        .inc();
+    context.metrics.reconcile(&name, &namespace);
 
     // Benchmark the read phase of reconciliation.
     #[cfg(feature = "metrics")]
     let start = std::time::Instant::now();
 
     // Read phase of reconciliation determines goal during the write phase.
-    let action = determine_action(client.clone(), &name, &namespace, &instance).await?;
+    let action = determine_action(
+        client.clone(),
+        &name,
+        &namespace,
+        &instance,
+        context.status_freshness,
+    )
+    .await?;
 
     if action != ReservationAction::NoOp {
         println!("{}/{} ACTION: {:?}", namespace, name, action);
     }
 
-    // Report the read phase performance.
+    // Report the read phase performance and count the action.
     #[cfg(feature = "metrics")]
     context
         .metrics
-        .read_histogram
-        .with_label_values(&[&name, &namespace, action.to_str()])
-        .observe(start.elapsed().as_secs_f64());
-
-    // Increment the counter for the action.
-    #[cfg(feature = "metrics")]
-    context
-        .metrics
-        .action_counter
-        .with_label_values(&[&name, &namespace, action.to_str()])
-        .inc();
+        .read(&name, &namespace, action.to_str(), start);
 
     // Benchmark the write phase of reconciliation.
     #[cfg(feature = "metrics")]
@@ -188,24 +235,50 @@ async fn reconcile(
         // Don't measure performance for NoOp actions.
         ReservationAction::NoOp => None,
         // Start a performance timer for the write phase.
-        _ => Some(
-            context
-                .metrics
-                .write_histogram
-                .with_label_values(&[&name, &namespace, action.to_str()])
-                .start_timer(),
-        ),
+        _ => Some(context.metrics.write(&name, &namespace, action.to_str())),
     };
 
-    // Performs action as decided by the `determine_action` function.
-    // This is the write phase of reconciliation.
-    let result = match action {
+    // Perform the action. The action's name is attached to any error
+    // so the error handler can report which action failed, and to the
+    // events published for the phase transitions it makes.
+    let action_name = action.to_str().to_owned();
+    let result = events::scope(
+        context.reporter.clone(),
+        &action_name,
+        explain::scope(
+            context.options.explain,
+            "reservations",
+            &action_name,
+            apply_action(client, &name, &namespace, &instance, action),
+        ),
+    )
+    .await;
+
+    // Report the write phase performance with the action's outcome.
+    #[cfg(feature = "metrics")]
+    if let Some(timer) = timer {
+        timer.observe(&result);
+    }
+
+    result.map_err(|e| Error::action(&action_name, e))
+}
+
+/// Performs the action as decided by the `determine_action` function.
+/// This is the write phase of reconciliation.
+async fn apply_action(
+    client: Client,
+    name: &str,
+    namespace: &str,
+    instance: &MaskReservation,
+    action: ReservationAction,
+) -> Result<Action, Error> {
+    Ok(match action {
         ReservationAction::Pending => {
             // Add the finalizer. This will prevent the reservation from
             // being deleted before the associated MaskConsumer is removed,
             // effectively preventing the slot from being reprovisioned until
             // we know for sure that the connection is severed.
-            let instance = finalizer::add(client.clone(), &name, &namespace).await?;
+            let instance = finalizer::add(client.clone(), name, namespace).await?;
 
             // Update the phase to Pending.
             actions::pending(client, &instance).await?;
@@ -215,13 +288,13 @@ async fn reconcile(
         }
         ReservationAction::Delete { delete_resource } => {
             // Show that the reservation is being terminated.
-            actions::terminating(client.clone(), &instance).await?;
+            actions::terminating(client.clone(), instance).await?;
 
             // Delete the associated MaskConsumer so the slot isn't reassigned
             // before all Pods using the credentials are truly disconnected.
-            let result = if actions::delete_consumer(client.clone(), &instance).await? {
+            let result = if actions::delete_consumer(client.clone(), instance).await? {
                 // Remove the finalizer, which will allow the MaskReservation resource to be deleted.
-                finalizer::delete::<MaskReservation>(client.clone(), &name, &namespace).await?;
+                finalizer::delete::<MaskReservation>(client.clone(), name, namespace).await?;
 
                 // Makes no sense to requeue after deleting, as the resource is gone.
                 Action::await_change()
@@ -233,48 +306,28 @@ async fn reconcile(
             if delete_resource {
                 // Delete the MaskReservation resource itself. This will happen when
                 // the referenced MaskConsumer is deleted.
-                actions::delete(client.clone(), &name, &namespace).await?;
+                actions::delete(client.clone(), name, namespace).await?;
             }
 
             result
         }
         ReservationAction::Active => {
             // Update the phase to Active, meaning the reservation is in use.
-            actions::active(client, &instance).await?;
+            actions::active(client, instance).await?;
 
             // Resource is fully reconciled.
             Action::requeue(PROBE_INTERVAL)
         }
         // The resource is already in desired state, do nothing and re-check after 10 seconds
         ReservationAction::NoOp => Action::requeue(PROBE_INTERVAL),
-    };
-
-    #[cfg(feature = "metrics")]
-    if let Some(timer) = timer {
-        timer.observe_duration();
-    }
-
-    Ok(result)
+    })
 }
 
 /// Returns the phase of the MaskReservation.
 pub fn get_reservation_phase(
     instance: &MaskReservation,
 ) -> Result<(MaskReservationPhase, Duration), Error> {
-    let status = instance
-        .status
-        .as_ref()
-        .ok_or_else(|| Error::UserInputError("No status".to_string()))?;
-    let phase = status
-        .phase
-        .ok_or_else(|| Error::UserInputError("No phase".to_string()))?;
-    let last_updated: chrono::DateTime<Utc> = status
-        .last_updated
-        .as_ref()
-        .ok_or_else(|| Error::UserInputError("No lastUpdated".to_string()))?
-        .parse()?;
-    let age: chrono::Duration = Utc::now() - last_updated;
-    Ok((phase, age.to_std()?))
+    status_phase(instance)
 }
 
 /// Resources arrives into reconciliation queue in a certain state. This function looks at
@@ -283,11 +336,13 @@ pub fn get_reservation_phase(
 ///
 /// # Arguments
 /// - `instance`: A reference to `MaskReservation` being reconciled to decide next action upon.
-async fn determine_action(
+/// - `status_freshness`: How long an unchanged status goes without being rewritten.
+pub(crate) async fn determine_action(
     client: Client,
     _name: &str,
     _namespace: &str,
     instance: &MaskReservation,
+    status_freshness: Duration,
 ) -> Result<ReservationAction, Error> {
     if instance.metadata.deletion_timestamp.is_some() {
         return Ok(ReservationAction::Delete {
@@ -296,8 +351,8 @@ async fn determine_action(
     }
 
     // The rest of the controller code assumes the presence of the
-    // status object and its phase field. If neither of these exist,
-    // the first thing that should be done is initializing them.
+    // status object and its bookkeeping fields. If any of these are
+    // missing, the first thing that should be done is initializing them.
     if needs_pending(instance) {
         return Ok(ReservationAction::Pending);
     }
@@ -308,7 +363,7 @@ async fn determine_action(
         });
     }
 
-    determine_status_action(instance)
+    determine_status_action(instance, status_freshness)
 }
 
 /// Returns the `MaskConsumer` referenced by the `MaskReservation`.
@@ -325,7 +380,7 @@ async fn get_consumer(
                 .metadata
                 .uid
                 .as_deref()
-                .map_or(false, |uid| uid == instance.spec.uid) =>
+                .is_some_and(|uid| uid == instance.spec.uid) =>
         {
             // UID matches, associated MaskConsumer is still around.
             Ok(Some(consumer))
@@ -339,11 +394,18 @@ async fn get_consumer(
     }
 }
 
-/// Determines the action given that the only thing left to do
-/// is periodically keeping the Ready/Active phase up-to-date.
-fn determine_status_action(instance: &MaskReservation) -> Result<ReservationAction, Error> {
-    let (phase, age) = get_reservation_phase(instance)?;
-    if phase != MaskReservationPhase::Active || age > PROBE_INTERVAL {
+/// Determines the action given that the only thing left to do is keeping
+/// the Active phase up-to-date. The MaskConsumer's existence is checked on
+/// every reconcile, so the status is only rewritten when the phase changes
+/// or it's older than `status_freshness`, not to prove it's still correct.
+fn determine_status_action(
+    instance: &MaskReservation,
+    status_freshness: Duration,
+) -> Result<ReservationAction, Error> {
+    let (phase, _) = get_reservation_phase(instance)?;
+    if phase != MaskReservationPhase::Active
+        || needs_refresh(ensure_status_initialized(instance)?, status_freshness)
+    {
         Ok(ReservationAction::Active)
     } else {
         Ok(ReservationAction::NoOp)
@@ -357,8 +419,10 @@ fn determine_status_action(instance: &MaskReservation) -> Result<ReservationActi
 /// # Arguments
 /// - `instance`: The erroneous resource.
 /// - `error`: A reference to the `kube::Error` that occurred during reconciliation.
-/// - `_context`: Unused argument. Context Data "injected" automatically by kube-rs.
-fn on_error(instance: Arc<MaskReservation>, error: &Error, _context: Arc<ContextData>) -> Action {
-    eprintln!("Reconciliation error:\n{:?}.\n{:?}", error, instance);
-    Action::requeue(Duration::from_secs(5))
+/// - `context`: Context Data "injected" automatically by kube-rs. Its client is used
+///   to record the failed action in the resource's status object.
+fn on_error(instance: Arc<MaskReservation>, error: &Error, context: Arc<ContextData>) -> Action {
+    let action = report_reconcile_error(&instance, error);
+    record_action_error(context.client.clone(), instance, "reservations", error);
+    action
 }
@@ -0,0 +1,70 @@
+use kube::{api::ListParams, Api, Client};
+use std::str::FromStr;
+use vpn_types::*;
+
+use crate::{
+    rbac::Rule,
+    util::{list::list_all_paginated, Error},
+};
+
+pub(crate) mod report;
+
+pub use report::{ProviderRef, StatusFilter, StatusReport};
+
+/// How the `status` command prints its report.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// Tables meant for humans.
+    Table,
+
+    /// The [`StatusReport`] as JSON, meant for scripts.
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("expected table or json, got '{}'", s)),
+        }
+    }
+}
+
+/// API access the `status` command needs. See [`crate::rbac`].
+pub const RBAC: &[Rule] = &[Rule::new(
+    "vpn.beebs.dev",
+    &["maskproviders", "masks", "maskconsumers"],
+    &["list"],
+)];
+
+/// Entrypoint for the `status` command. It lists the resources once,
+/// with the same paginated listing the controllers use, and prints
+/// the [`StatusReport`] to stdout. Nothing is ever modified.
+pub async fn run(client: Client, filter: StatusFilter, output: OutputFormat) -> Result<(), Error> {
+    let lp = ListParams::default();
+    let providers = list_all_paginated(&api::<MaskProvider>(&client, &filter), &lp).await?;
+    let masks = list_all_paginated(&api::<Mask>(&client, &filter), &lp).await?;
+    let consumers = list_all_paginated(&api::<MaskConsumer>(&client, &filter), &lp).await?;
+    let report = StatusReport::build(&providers, &masks, &consumers, &filter, chrono::Utc::now());
+    match output {
+        OutputFormat::Table => print!("{}", report.table()),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+    Ok(())
+}
+
+/// Returns the API for listing the resources the filter may match,
+/// which are only those in its namespace, if it has one.
+fn api<K>(client: &Client, filter: &StatusFilter) -> Api<K>
+where
+    K: kube::Resource<Scope = kube::core::NamespaceResourceScope>,
+    <K as kube::Resource>::DynamicType: Default,
+{
+    match filter.namespace.as_deref() {
+        Some(namespace) => Api::namespaced(client.clone(), namespace),
+        None => Api::all(client.clone()),
+    }
+}
@@ -0,0 +1,378 @@
+use chrono::{DateTime, Utc};
+use kube::ResourceExt;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    time::Duration,
+};
+use vpn_types::*;
+
+use crate::export::aggregate::count_phases;
+use crate::util::clock;
+
+/// Limits the [`StatusReport`] to part of the fleet.
+#[derive(Clone, Debug, Default)]
+pub struct StatusFilter {
+    /// Only report resources in this namespace.
+    pub namespace: Option<String>,
+
+    /// Only report this MaskProvider, and the Masks and MaskConsumers
+    /// assigned to it.
+    pub provider: Option<ProviderRef>,
+}
+
+impl StatusFilter {
+    /// Returns true if the MaskProvider matches the filter. A provider
+    /// given without a namespace is looked for in the filtered namespace,
+    /// or in every namespace if there isn't one.
+    fn is_provider(&self, namespace: &str, name: &str) -> bool {
+        self.provider.as_ref().is_none_or(|provider| {
+            provider.name == name
+                && provider
+                    .namespace
+                    .as_deref()
+                    .or(self.namespace.as_deref())
+                    .is_none_or(|filtered| filtered == namespace)
+        })
+    }
+}
+
+/// MaskProvider given to [`StatusFilter`] as `namespace/name` or `name`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProviderRef {
+    pub namespace: Option<String>,
+    pub name: String,
+}
+
+impl FromStr for ProviderRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (namespace, name) = match s.split_once('/') {
+            Some((namespace, name)) => (Some(namespace.to_owned()), name),
+            None => (None, s),
+        };
+        if name.is_empty() || namespace.as_deref() == Some("") {
+            return Err(format!("expected namespace/name or name, got '{}'", s));
+        }
+        Ok(ProviderRef {
+            namespace,
+            name: name.to_owned(),
+        })
+    }
+}
+
+/// Human-readable summary of the state of the VPN fleet, as printed
+/// by the `status` command.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct StatusReport {
+    /// Every MaskProvider, sorted by namespace and name.
+    pub providers: Vec<ProviderRow>,
+
+    /// Number of Masks in each phase, by namespace.
+    pub masks: BTreeMap<String, BTreeMap<String, usize>>,
+
+    /// Every resource in an error phase, sorted by kind, namespace and name.
+    pub errors: Vec<ErrorRow>,
+}
+
+/// A MaskProvider in the [`StatusReport`].
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ProviderRow {
+    pub namespace: String,
+    pub name: String,
+
+    /// The phase, or `"Unknown"` if the MaskProvider doesn't have one yet.
+    pub phase: String,
+
+    #[serde(rename = "activeSlots")]
+    pub active_slots: usize,
+
+    #[serde(rename = "maxSlots")]
+    pub max_slots: usize,
+
+    /// Timestamp of when the credentials were last verified.
+    #[serde(rename = "lastVerified", skip_serializing_if = "Option::is_none")]
+    pub last_verified: Option<String>,
+
+    /// How long ago the credentials were last verified, e.g. `3h12m`.
+    #[serde(rename = "lastVerifiedAge", skip_serializing_if = "Option::is_none")]
+    pub last_verified_age: Option<String>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// A resource in an error phase in the [`StatusReport`].
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ErrorRow {
+    pub kind: String,
+    pub namespace: String,
+    pub name: String,
+    pub phase: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl StatusReport {
+    /// Summarizes the resources that match the filter as of `now`.
+    pub fn build(
+        providers: &[MaskProvider],
+        masks: &[Mask],
+        consumers: &[MaskConsumer],
+        filter: &StatusFilter,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let in_namespace = |namespace: &str| {
+            filter
+                .namespace
+                .as_deref()
+                .is_none_or(|filtered| filtered == namespace)
+        };
+        // Masks are matched to a MaskProvider through their MaskConsumer,
+        // which has the same namespace and name.
+        let assigned: HashMap<(String, String), &AssignedProvider> = consumers
+            .iter()
+            .filter_map(|c| Some((key(c), c.status.as_ref()?.provider.as_ref()?)))
+            .collect();
+        let for_provider = |key: &(String, String)| {
+            filter.provider.is_none()
+                || assigned
+                    .get(key)
+                    .is_some_and(|p| filter.is_provider(&p.namespace, &p.name))
+        };
+
+        let providers: Vec<&MaskProvider> = providers
+            .iter()
+            .filter(|p| in_namespace(&p.namespace().unwrap_or_default()))
+            .filter(|p| filter.is_provider(&p.namespace().unwrap_or_default(), &p.name_any()))
+            .collect();
+        let masks: Vec<&Mask> = masks
+            .iter()
+            .filter(|m| in_namespace(&m.namespace().unwrap_or_default()) && for_provider(&key(*m)))
+            .collect();
+        let consumers: Vec<&MaskConsumer> = consumers
+            .iter()
+            .filter(|c| in_namespace(&c.namespace().unwrap_or_default()) && for_provider(&key(*c)))
+            .collect();
+
+        let mut phases: BTreeMap<String, Vec<Option<MaskPhase>>> = BTreeMap::new();
+        for mask in &masks {
+            phases
+                .entry(mask.namespace().unwrap_or_default())
+                .or_default()
+                .push(mask.status.as_ref().and_then(|s| s.phase));
+        }
+
+        let mut errors: Vec<ErrorRow> = providers
+            .iter()
+            .filter_map(|p| {
+                let status = p.status.as_ref()?;
+                error_row("MaskProvider", *p, status.phase?, status.message.as_deref())
+            })
+            .chain(masks.iter().filter_map(|m| {
+                let status = m.status.as_ref()?;
+                error_row("Mask", *m, status.phase?, status.message.as_deref())
+            }))
+            .chain(consumers.iter().filter_map(|c| {
+                let status = c.status.as_ref()?;
+                error_row("MaskConsumer", *c, status.phase?, status.message.as_deref())
+            }))
+            .collect();
+        errors.sort_by(|a, b| {
+            (&a.kind, &a.namespace, &a.name).cmp(&(&b.kind, &b.namespace, &b.name))
+        });
+
+        let mut providers: Vec<ProviderRow> =
+            providers.iter().map(|p| provider_row(p, now)).collect();
+        providers.sort_by(|a, b| (&a.namespace, &a.name).cmp(&(&b.namespace, &b.name)));
+
+        StatusReport {
+            providers,
+            masks: phases
+                .into_iter()
+                .map(|(namespace, phases)| (namespace, count_phases(phases.iter())))
+                .collect(),
+            errors,
+        }
+    }
+
+    /// Renders the report as tables, one for each section.
+    pub fn table(&self) -> String {
+        let providers = self
+            .providers
+            .iter()
+            .map(|p| {
+                vec![
+                    p.namespace.clone(),
+                    p.name.clone(),
+                    p.phase.clone(),
+                    format!("{}/{}", p.active_slots, p.max_slots),
+                    p.last_verified_age
+                        .as_ref()
+                        .map_or_else(|| "never".to_owned(), |age| format!("{} ago", age)),
+                    or_none(p.warnings.join("; ")),
+                ]
+            })
+            .collect();
+        let masks = self
+            .masks
+            .iter()
+            .map(|(namespace, phases)| {
+                vec![
+                    namespace.clone(),
+                    phases.values().sum::<usize>().to_string(),
+                    phases
+                        .iter()
+                        .map(|(phase, count)| format!("{}={}", phase, count))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ]
+            })
+            .collect();
+        let errors = self
+            .errors
+            .iter()
+            .map(|e| {
+                vec![
+                    e.kind.clone(),
+                    e.namespace.clone(),
+                    e.name.clone(),
+                    e.phase.clone(),
+                    or_none(e.message.clone().unwrap_or_default()),
+                ]
+            })
+            .collect();
+        [
+            section(
+                "MaskProviders",
+                &[
+                    "NAMESPACE",
+                    "NAME",
+                    "PHASE",
+                    "SLOTS",
+                    "LAST VERIFIED",
+                    "WARNINGS",
+                ],
+                providers,
+            ),
+            section("Masks", &["NAMESPACE", "TOTAL", "PHASES"], masks),
+            section(
+                "Errors",
+                &["KIND", "NAMESPACE", "NAME", "PHASE", "MESSAGE"],
+                errors,
+            ),
+        ]
+        .join("\n")
+    }
+}
+
+/// Returns the namespace and name of the resource.
+fn key<K: ResourceExt>(resource: &K) -> (String, String) {
+    (
+        resource.namespace().unwrap_or_default(),
+        resource.name_any(),
+    )
+}
+
+/// Summarizes the MaskProvider as of `now`.
+fn provider_row(provider: &MaskProvider, now: DateTime<Utc>) -> ProviderRow {
+    let status = provider.status.as_ref();
+    let last_verified = status.and_then(|s| s.last_verified.clone());
+    ProviderRow {
+        namespace: provider.namespace().unwrap_or_default(),
+        name: provider.name_any(),
+        phase: status
+            .and_then(|s| s.phase)
+            .map_or_else(|| "Unknown".to_owned(), |p| p.to_string()),
+        active_slots: status.and_then(|s| s.active_slots).unwrap_or(0),
+        max_slots: provider.spec.max_slots,
+        last_verified_age: last_verified
+            .as_deref()
+            .and_then(|t| clock::parse_timestamp(t).ok())
+            .and_then(|t| (now - t).to_std().ok())
+            .map(format_age),
+        last_verified,
+        warnings: status.and_then(|s| s.warnings.clone()).unwrap_or_default(),
+    }
+}
+
+/// Returns the resource's row in the errors section,
+/// or None if its phase isn't an error phase.
+fn error_row<K: ResourceExt>(
+    kind: &str,
+    resource: &K,
+    phase: impl ToString,
+    message: Option<&str>,
+) -> Option<ErrorRow> {
+    let phase = phase.to_string();
+    if !phase.starts_with("Err") {
+        return None;
+    }
+    let (namespace, name) = key(resource);
+    Some(ErrorRow {
+        kind: kind.to_owned(),
+        namespace,
+        name,
+        phase,
+        message: message.map(|m| m.to_owned()),
+    })
+}
+
+/// Formats the age with its two most significant units, e.g. `3h12m`.
+pub fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    let units = [
+        (secs / 86400, "d"),
+        (secs / 3600 % 24, "h"),
+        (secs / 60 % 60, "m"),
+        (secs % 60, "s"),
+    ];
+    let parts: Vec<String> = units
+        .iter()
+        .skip_while(|(value, _)| *value == 0)
+        .take(2)
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect();
+    match parts.is_empty() {
+        true => "0s".to_owned(),
+        false => parts.concat(),
+    }
+}
+
+/// Returns `-` in place of an empty cell.
+pub(crate) fn or_none(cell: String) -> String {
+    match cell.is_empty() {
+        true => "-".to_owned(),
+        false => cell,
+    }
+}
+
+/// Renders a titled table with its columns aligned, or a note
+/// if the table has no rows.
+pub(crate) fn section(title: &str, headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    if rows.is_empty() {
+        return format!("{}: none\n", title);
+    }
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let headers = headers.iter().map(|h| h.to_string()).collect();
+    let mut out = format!("{}:\n", title);
+    for row in std::iter::once(headers).chain(rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
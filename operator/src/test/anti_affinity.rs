@@ -0,0 +1,208 @@
+use chrono::{TimeZone, Utc};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use kube::{api::ObjectMeta, client::Client, Api};
+use std::time::Duration;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::util::*;
+use crate::{
+    consumers::anti_affinity::{assigned_members, conflicting_member, exclude},
+    util::messages,
+};
+
+/// Name of the anti-affinity group used by the tests.
+const GROUP: &str = "scrapers";
+
+/// Returns a member of the group created `created` seconds after the
+/// epoch and assigned the MaskProvider with the uid, if any.
+fn member(name: &str, created: i64, provider: Option<&str>) -> MaskConsumer {
+    MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            uid: Some(name.to_owned()),
+            creation_timestamp: Some(Time(Utc.timestamp_opt(created, 0).unwrap())),
+            ..Default::default()
+        },
+        spec: MaskConsumerSpec {
+            anti_affinity: Some(MaskAntiAffinity {
+                group: GROUP.to_owned(),
+            }),
+            ..Default::default()
+        },
+        status: provider.map(|uid| MaskConsumerStatus {
+            provider: Some(AssignedProvider {
+                name: uid.to_owned(),
+                namespace: "vpn".to_owned(),
+                uid: uid.to_owned(),
+                slot: 0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+    }
+}
+
+#[test]
+fn members_providers_excluded() {
+    let members = [member("a", 0, Some("p1")), member("b", 1, None)];
+    let remaining = exclude(
+        vec![
+            mask_provider("p1", "vpn", "p1"),
+            mask_provider("p2", "vpn", "p2"),
+        ],
+        &members,
+    );
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].metadata.uid.as_deref(), Some("p2"));
+    assert_eq!(assigned_members(&members), vec!["a".to_owned()]);
+}
+
+#[test]
+fn younger_member_yields() {
+    let older = member("a", 0, Some("p1"));
+    let younger = member("b", 1, Some("p1"));
+    let elsewhere = member("c", 0, Some("p2"));
+
+    // The younger member is unassigned in favor of the older one.
+    let members = [older.clone(), elsewhere.clone()];
+    let conflict = conflicting_member(&younger, &members).unwrap();
+    assert_eq!(conflict.metadata.name.as_deref(), Some("a"));
+
+    // The older member keeps its MaskProvider.
+    let members = [younger, elsewhere.clone()];
+    assert!(conflicting_member(&older, &members).is_none());
+
+    // A member that is being deleted is about to release the MaskProvider.
+    let mut deleting = member("d", 0, Some("p2"));
+    deleting.metadata.deletion_timestamp = Some(Time(Utc::now()));
+    assert!(conflicting_member(&member("e", 1, Some("p2")), &[deleting]).is_none());
+}
+
+#[test]
+fn waiting_message_names_members() {
+    let message = messages::anti_affinity_waiting(GROUP, &["a".to_owned(), "b".to_owned()]);
+    assert!(message.contains("'scrapers': a, b."));
+}
+
+/// Returns the test Mask in the anti-affinity group.
+fn grouped_mask(namespace: &str, index: usize, provider_label: &str) -> Mask {
+    let mut mask = get_test_mask(namespace, index, provider_label);
+    mask.spec.anti_affinity = Some(MaskAntiAffinity {
+        group: GROUP.to_owned(),
+    });
+    mask
+}
+
+/// Creates a MaskProvider with room for both Masks that is
+/// assigned to Masks requesting the `tag`, and waits for it to
+/// become Ready.
+async fn create_provider(
+    client: Client,
+    namespace: &str,
+    name: &str,
+    tag: &str,
+) -> Result<MaskProvider, Error> {
+    let provider_ready = {
+        let client = client.clone();
+        let namespace = namespace.to_owned();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let mut provider = get_test_provider(client.clone(), name, namespace).await?;
+    provider.spec.max_slots = 2;
+    provider.spec.tags = Some(vec![tag.to_owned()]);
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), namespace);
+    let provider = api.create(&Default::default(), &provider).await?;
+    create_test_provider_secret(client, namespace, &provider).await?;
+    provider_ready.await.unwrap()?;
+    Ok(provider)
+}
+
+/// Creates both Masks of the group.
+async fn create_grouped_masks(client: Client, namespace: &str, tag: &str) -> Result<(), Error> {
+    let api: Api<Mask> = Api::namespaced(client, namespace);
+    for index in 0..2 {
+        api.create(&Default::default(), &grouped_mask(namespace, index, tag))
+            .await?;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn disjoint_placement() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let tag = test_provider_name(&uid);
+
+    // Either MaskProvider has a free slot for both Masks.
+    for suffix in ["a", "b"] {
+        let name = format!("{}-{}", tag, suffix);
+        create_provider(client.clone(), &namespace, &name, &tag).await?;
+    }
+    create_grouped_masks(client.clone(), &namespace, &tag).await?;
+
+    // The members are assigned different MaskProviders.
+    let first = wait_for_provider_assignment(client.clone(), &namespace, 0).await?;
+    let second = wait_for_provider_assignment(client.clone(), &namespace, 1).await?;
+    assert_ne!(first.uid, second.uid);
+    for index in 0..2 {
+        wait_for_mask_phase(client.clone(), &namespace, index, MaskPhase::Active).await?;
+    }
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn waits_without_disjoint_provider() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let tag = test_provider_name(&uid);
+
+    // The only MaskProvider has a free slot for the second Mask.
+    create_provider(client.clone(), &namespace, &tag, &tag).await?;
+    let api: Api<Mask> = Api::namespaced(client.clone(), &namespace);
+    api.create(&Default::default(), &grouped_mask(&namespace, 0, &tag))
+        .await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+    api.create(&Default::default(), &grouped_mask(&namespace, 1, &tag))
+        .await?;
+
+    // The second Mask waits, explaining which member holds the MaskProvider.
+    wait_for_mask_phase(client.clone(), &namespace, 1, MaskPhase::Waiting).await?;
+    let expected = messages::anti_affinity_waiting(GROUP, &[format!("{}-0", MASK_NAME)]);
+    let mut message = None;
+    for _ in 0..30 {
+        message = api
+            .get(&format!("{}-1", MASK_NAME))
+            .await?
+            .status
+            .and_then(|s| s.message);
+        if message.as_deref() == Some(expected.as_str()) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    assert_eq!(message.as_deref(), Some(expected.as_str()));
+
+    // Leaving the group lets it share the MaskProvider.
+    let mask = api.get(&format!("{}-1", MASK_NAME)).await?;
+    let mut spec = mask.spec.clone();
+    spec.anti_affinity = None;
+    api.replace(
+        &format!("{}-1", MASK_NAME),
+        &Default::default(),
+        &Mask { spec, ..mask },
+    )
+    .await?;
+    wait_for_mask_phase(client.clone(), &namespace, 1, MaskPhase::Active).await?;
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use kube::{api::ObjectMeta, client::Client};
+use serde_json::json;
+use vpn_types::*;
+
+use super::mock::*;
+use crate::{masks::actions::withdrawing, util::messages};
+
+/// Name of the Mask's credentials copy.
+const SECRET_NAME: &str = "test-mask-provider-uid";
+
+/// Returns a Mask in the given phase.
+fn mask(phase: MaskPhase, message: String) -> Mask {
+    Mask {
+        metadata: ObjectMeta {
+            name: Some("test-mask".to_owned()),
+            namespace: Some("team".to_owned()),
+            uid: Some("mask-uid".to_owned()),
+            ..Default::default()
+        },
+        spec: Default::default(),
+        status: Some(MaskStatus {
+            phase: Some(phase),
+            message: Some(message),
+            ..Default::default()
+        }),
+    }
+}
+
+/// Returns a client that accepts status updates to the Mask and events.
+fn mock_mask(instance: &Mask) -> (Client, Captured) {
+    mock_method_routes(vec![
+        (
+            "PATCH",
+            "/apis/vpn.beebs.dev/v1/namespaces/team/masks/test-mask/status",
+            200,
+            serde_json::to_value(instance).unwrap(),
+        ),
+        (
+            "POST",
+            "/apis/events.k8s.io/v1/namespaces/team/events",
+            201,
+            json!({}),
+        ),
+    ])
+}
+
+#[tokio::test]
+async fn withdrawal_warned_once() {
+    let message = messages::assignment_withdrawn(SECRET_NAME);
+
+    // Entering the phase names the Secret and publishes the warning.
+    let active = mask(MaskPhase::Active, messages::ACTIVE.to_owned());
+    let (client, captured) = mock_mask(&active);
+    withdrawing(client, &active, SECRET_NAME.to_owned())
+        .await
+        .unwrap();
+    {
+        let captured = captured.lock().unwrap();
+        let patch = captured
+            .iter()
+            .find(|r| r.method == "PATCH")
+            .expect("Mask status was not updated");
+        assert_eq!(patch_op(patch, "/status/phase").unwrap(), "Terminating");
+        assert_eq!(patch_op(patch, "/status/message").unwrap(), &json!(message));
+        let event = captured
+            .iter()
+            .find(|r| r.method == "POST")
+            .expect("event was not published");
+        assert_eq!(event.body["type"], "Warning");
+        assert_eq!(event.body["reason"], "AssignmentWithdrawn");
+        assert_eq!(event.body["note"], json!(message));
+        assert_eq!(event.body["regarding"]["name"], "test-mask");
+    }
+
+    // Refreshing the status doesn't publish it again.
+    let terminating = mask(MaskPhase::Terminating, message);
+    let (client, captured) = mock_mask(&terminating);
+    withdrawing(client, &terminating, SECRET_NAME.to_owned())
+        .await
+        .unwrap();
+    let captured = captured.lock().unwrap();
+    assert!(captured.iter().any(|r| r.method == "PATCH"));
+    assert!(!captured.iter().any(|r| r.method == "POST"));
+}
@@ -0,0 +1,285 @@
+use k8s_openapi::api::batch::v1::Job;
+use kube::{client::Client, Api};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::{
+    mock::{consumer_value, decide, merged},
+    util::*,
+};
+use crate::{
+    jobs::{
+        actions::{injected_secret, is_finished},
+        reconcile::{determine_action, JobAction},
+    },
+    util::{AUTO_MASK_ANNOTATION, AUTO_MASK_JOB_LABEL, MASK_SECRET_ANNOTATION},
+};
+
+/// Returns a Job annotated with the given MaskProvider tags, with
+/// `patch` merged into it.
+fn job(tags: Option<&str>, patch: Value) -> Value {
+    let annotations = match tags {
+        Some(tags) => json!({ AUTO_MASK_ANNOTATION: tags }),
+        None => json!({}),
+    };
+    let job = json!({
+        "apiVersion": "batch/v1",
+        "kind": "Job",
+        "metadata": {
+            "name": "pipeline",
+            "namespace": "default",
+            "uid": "job-uid",
+            "annotations": annotations,
+        },
+        "spec": {
+            "suspend": true,
+            "template": { "spec": { "containers": [] } },
+        },
+        "status": {},
+    });
+    merged(job, patch)
+}
+
+/// Returns the Job's Mask in the given phase, owned by the Job with
+/// the given uid.
+fn mask(owner_uid: &str, phase: &str) -> Value {
+    json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "Mask",
+        "metadata": {
+            "name": "pipeline",
+            "namespace": "default",
+            "uid": "mask-uid",
+            "creationTimestamp": chrono::Utc::now().to_rfc3339(),
+            "labels": { AUTO_MASK_JOB_LABEL: owner_uid },
+            "ownerReferences": [{
+                "apiVersion": "batch/v1",
+                "kind": "Job",
+                "name": "pipeline",
+                "uid": owner_uid,
+                "controller": true,
+            }],
+        },
+        "spec": {},
+        "status": { "phase": phase, "message": format!("Mask is {}.", phase) },
+    })
+}
+
+/// Returns the Mask's MaskConsumer, assigned credentials in `secret`.
+fn pipeline_consumer(secret: &str) -> Value {
+    consumer_value(json!({
+        "metadata": {
+            "name": "pipeline",
+            "ownerReferences": [mask_owner_ref("pipeline")],
+        },
+        "status": { "provider": { "secret": secret } },
+    }))
+}
+
+/// Returns true if the Job completed rather than failed.
+fn is_finished_successfully(job: &Job) -> bool {
+    job.status
+        .as_ref()
+        .and_then(|s| s.conditions.as_ref())
+        .is_some_and(|c| {
+            c.iter()
+                .any(|c| c.type_ == "Complete" && c.status == "True")
+        })
+}
+
+/// Returns the action decided for the first object, a Job, given all
+/// of the objects.
+async fn action(objects: &[Value]) -> JobAction {
+    let instance: Job = serde_json::from_value(objects[0].clone()).unwrap();
+    decide(objects, |client| {
+        let instance = instance.clone();
+        async move { determine_action(client, &instance).await }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn unannotated_jobs_ignored() {
+    let objects = [job(None, json!({ "spec": { "suspend": false } }))];
+    assert_eq!(action(&objects).await, JobAction::NoOp);
+}
+
+#[tokio::test]
+async fn suspended_until_credentials() {
+    // Pods never start before the Mask exists.
+    let objects = [job(Some("us, eu"), json!({ "spec": { "suspend": false } }))];
+    assert_eq!(action(&objects).await, JobAction::Suspend);
+
+    let objects = [job(Some("us, eu"), json!({}))];
+    assert_eq!(
+        action(&objects).await,
+        JobAction::CreateMask {
+            providers: Some(vec!["us".to_owned(), "eu".to_owned()]),
+        }
+    );
+
+    // An empty annotation accepts any MaskProvider.
+    let objects = [job(Some(""), json!({}))];
+    assert_eq!(
+        action(&objects).await,
+        JobAction::CreateMask { providers: None }
+    );
+
+    // A Mask that is still waiting for a slot is waited on quietly.
+    let objects = [job(Some(""), json!({})), mask("job-uid", "Waiting")];
+    assert_eq!(action(&objects).await, JobAction::NoOp);
+}
+
+#[tokio::test]
+async fn credentials_injected() {
+    let objects = [
+        job(Some(""), json!({})),
+        mask("job-uid", "Active"),
+        pipeline_consumer("pipeline-credentials"),
+    ];
+    assert_eq!(
+        action(&objects).await,
+        JobAction::InjectSecret {
+            secret: "pipeline-credentials".to_owned(),
+        }
+    );
+
+    // Pods from before the Job was suspended must stop first.
+    let objects = [
+        job(Some(""), json!({ "status": { "active": 1 } })),
+        mask("job-uid", "Active"),
+        pipeline_consumer("pipeline-credentials"),
+    ];
+    assert_eq!(action(&objects).await, JobAction::NoOp);
+
+    // A Job that was given credentials is left alone.
+    let injected = json!({
+        "spec": {
+            "suspend": false,
+            "template": { "metadata": { "annotations": {
+                MASK_SECRET_ANNOTATION: "pipeline-credentials",
+            }}},
+        },
+    });
+    let objects = [
+        job(Some(""), injected),
+        mask("job-uid", "Active"),
+        pipeline_consumer("pipeline-credentials"),
+    ];
+    assert_eq!(action(&objects).await, JobAction::NoOp);
+}
+
+#[tokio::test]
+async fn unassignable_masks_reported() {
+    let objects = [
+        job(Some("nowhere"), json!({})),
+        mask("job-uid", "ErrNoProviders"),
+    ];
+    assert_eq!(
+        action(&objects).await,
+        JobAction::Waiting {
+            reason: "ErrNoProviders".to_owned(),
+            message: "Mask is ErrNoProviders.".to_owned(),
+            backoff: Duration::from_secs(10),
+        }
+    );
+
+    // A Mask of the same name that belongs to something else is not used.
+    let objects = [job(Some(""), json!({})), mask("other-uid", "Active")];
+    assert!(matches!(
+        action(&objects).await,
+        JobAction::Waiting { reason, .. } if reason == "MaskConflict"
+    ));
+}
+
+#[tokio::test]
+async fn finished_jobs_release_slot() {
+    let finished =
+        json!({ "status": { "conditions": [{ "type": "Complete", "status": "True" }] } });
+    let objects = [job(Some(""), finished.clone()), mask("job-uid", "Active")];
+    assert_eq!(action(&objects).await, JobAction::DeleteMask);
+
+    let objects = [job(Some(""), finished.clone()), mask("other-uid", "Active")];
+    assert_eq!(action(&objects).await, JobAction::NoOp);
+
+    let objects = [job(Some(""), finished)];
+    assert_eq!(action(&objects).await, JobAction::NoOp);
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn short_job() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+
+    // Create a MaskProvider for the Job's Mask to be assigned.
+    let provider_ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let spec = get_test_provider(client.clone(), &provider_name, &namespace).await?;
+    let providers: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let provider = providers.create(&Default::default(), &spec).await?;
+    create_test_provider_secret(client.clone(), &namespace, &provider).await?;
+    provider_ready.await.unwrap()?;
+
+    // The Job doesn't start suspended, so the controller has to suspend it.
+    let job: Job = serde_json::from_value(json!({
+        "apiVersion": "batch/v1",
+        "kind": "Job",
+        "metadata": {
+            "name": "short-job",
+            "annotations": { AUTO_MASK_ANNOTATION: "" },
+        },
+        "spec": {
+            "backoffLimit": 0,
+            "template": { "spec": {
+                "restartPolicy": "Never",
+                "containers": [{
+                    "name": "job",
+                    "image": "busybox",
+                    "command": ["true"],
+                }],
+            }},
+        },
+    }))
+    .unwrap();
+    let jobs: Api<Job> = Api::namespaced(client.clone(), &namespace);
+    jobs.create(&Default::default(), &job).await?;
+
+    // The Job runs with the credentials Secret's name in its Pod template.
+    let mut finished = None;
+    for _ in 0..120 {
+        let job = jobs.get("short-job").await?;
+        if is_finished(&job) {
+            finished = Some(job);
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    let finished = finished.expect("Job didn't finish");
+    assert!(injected_secret(&finished).is_some_and(|s| !s.is_empty()));
+    assert!(is_finished_successfully(&finished));
+
+    // The Mask is deleted once the Job finished, releasing its slot.
+    let masks: Api<Mask> = Api::namespaced(client.clone(), &namespace);
+    let mut released = false;
+    for _ in 0..30 {
+        if masks.get_opt("short-job").await?.is_none() {
+            released = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    assert!(released);
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,289 @@
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use kube::{client::Client, Api};
+use serde_json::json;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::{mock, util::*};
+use crate::{
+    consumers::actions::list_active_providers,
+    util::{
+        clock::Clock,
+        schedule::{availability, is_available, Schedule},
+    },
+};
+
+/// Returns the UTC time for the given date and time.
+fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+        .unwrap()
+}
+
+/// Returns a Ready MaskProvider with the given availability.
+fn scheduled_provider(name: &str, spec: Option<MaskProviderAvailabilitySpec>) -> MaskProvider {
+    let mut provider = ready_provider(name, "default", name);
+    provider.spec.availability = spec;
+    provider
+}
+
+/// Returns business hours in Berlin.
+fn business_hours() -> MaskProviderAvailabilitySpec {
+    MaskProviderAvailabilitySpec {
+        schedule: "Mon-Fri 08:00-20:00".to_owned(),
+        timezone: "Europe/Berlin".to_owned(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn windows_parsed() {
+    // 2026-01-05 is a Monday.
+    let schedule = Schedule::parse("Mon,Wed 09:00-17:00; Sat 10:00-14:00", "").unwrap();
+    assert!(schedule.contains(utc(2026, 1, 5, 9, 0)));
+    assert!(schedule.contains(utc(2026, 1, 5, 16, 59)));
+    assert!(!schedule.contains(utc(2026, 1, 5, 17, 0)));
+    assert!(!schedule.contains(utc(2026, 1, 6, 12, 0)));
+    assert!(schedule.contains(utc(2026, 1, 7, 12, 0)));
+    assert!(schedule.contains(utc(2026, 1, 10, 10, 0)));
+    assert!(!schedule.contains(utc(2026, 1, 10, 14, 0)));
+
+    // Omitting the days means every day, and 24:00 is the end of the day.
+    let schedule = Schedule::parse("12:00-24:00", "UTC").unwrap();
+    for day in 5..12 {
+        assert!(!schedule.contains(utc(2026, 1, day, 11, 59)));
+        assert!(schedule.contains(utc(2026, 1, day, 23, 59)));
+    }
+
+    // Day ranges can wrap around the end of the week, and names are
+    // case-insensitive.
+    let schedule = Schedule::parse("sat-MON 00:00-24:00", "").unwrap();
+    let days: Vec<bool> = (5..12)
+        .map(|day| schedule.contains(utc(2026, 1, day, 12, 0)))
+        .collect();
+    assert_eq!(days, [true, false, false, false, false, true, true]);
+}
+
+#[test]
+fn windows_run_past_midnight() {
+    // Friday nights, from 2026-01-09 22:00 until Saturday 02:00.
+    let schedule = Schedule::parse("Fri 22:00-02:00", "").unwrap();
+    assert!(!schedule.contains(utc(2026, 1, 8, 23, 0)));
+    assert!(!schedule.contains(utc(2026, 1, 9, 21, 59)));
+    assert!(schedule.contains(utc(2026, 1, 9, 23, 0)));
+    assert!(schedule.contains(utc(2026, 1, 10, 1, 59)));
+    assert!(!schedule.contains(utc(2026, 1, 10, 2, 0)));
+    assert!(!schedule.contains(utc(2026, 1, 10, 23, 0)));
+    assert!(!schedule.contains(utc(2026, 1, 11, 1, 0)));
+    assert_eq!(
+        schedule.next_change(utc(2026, 1, 9, 23, 0)),
+        Some(utc(2026, 1, 10, 2, 0))
+    );
+    assert_eq!(
+        schedule.next_change(utc(2026, 1, 10, 2, 0)),
+        Some(utc(2026, 1, 16, 22, 0))
+    );
+}
+
+#[test]
+fn timezones_respected() {
+    let schedule = Schedule::parse("Mon-Fri 08:00-20:00", "Europe/Berlin").unwrap();
+    // Berlin is an hour ahead of UTC in the winter...
+    assert!(!schedule.contains(utc(2026, 1, 5, 6, 30)));
+    assert!(schedule.contains(utc(2026, 1, 5, 7, 30)));
+    assert!(schedule.contains(utc(2026, 1, 5, 18, 59)));
+    assert!(!schedule.contains(utc(2026, 1, 5, 19, 0)));
+    // ...and two hours ahead in the summer.
+    assert!(schedule.contains(utc(2026, 7, 6, 6, 30)));
+    assert!(!schedule.contains(utc(2026, 7, 6, 18, 0)));
+    // The local day decides the weekday: Friday 23:30 UTC is Saturday in Berlin.
+    assert!(!schedule.contains(utc(2026, 1, 9, 23, 30)));
+
+    // Changes are found across daylight saving time transitions. The
+    // clocks go forward on Sunday 2026-03-29, so Monday 08:00 is 06:00 UTC
+    // while Friday 20:00 was 19:00 UTC.
+    assert_eq!(
+        schedule.next_change(utc(2026, 3, 27, 12, 0)),
+        Some(utc(2026, 3, 27, 19, 0))
+    );
+    assert_eq!(
+        schedule.next_change(utc(2026, 3, 28, 12, 0)),
+        Some(utc(2026, 3, 30, 6, 0))
+    );
+
+    // A schedule that covers every minute never changes.
+    let always = Schedule::parse("00:00-24:00", "America/New_York").unwrap();
+    assert!(always.contains(utc(2026, 3, 8, 7, 30)));
+    assert_eq!(always.next_change(utc(2026, 3, 8, 7, 30)), None);
+}
+
+#[test]
+fn invalid_schedules_rejected() {
+    for schedule in [
+        "",
+        " ; ",
+        "Mon-Fri",
+        "Mon-Fry 08:00-20:00",
+        "Mon-Fri 08:00",
+        "Mon-Fri 08:00-08:00",
+        "Mon-Fri 25:00-26:00",
+        "Mon-Fri 08:60-20:00",
+        "Mon-Fri 08:5-20:00",
+        "Mon-Fri 24:00-02:00",
+        "Mon-Fri 08:00-24:30",
+    ] {
+        assert!(
+            Schedule::parse(schedule, "").is_err(),
+            "'{}' should be invalid",
+            schedule
+        );
+    }
+    let error = Schedule::parse("Mon-Fri 08:00-20:00", "Mars/Olympus_Mons").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Invalid user input: spec.availability: unknown time zone 'Mars/Olympus_Mons'"
+    );
+}
+
+#[test]
+fn availability_described() {
+    // Providers without availability are always available.
+    let always = scheduled_provider("always", None);
+    assert_eq!(availability(&always, utc(2026, 1, 10, 12, 0)), None);
+    assert!(is_available(&always, utc(2026, 1, 10, 12, 0)));
+
+    // Saturday 2026-03-28 is outside of business hours until Monday.
+    let office = scheduled_provider("office", Some(business_hours()));
+    let weekend = availability(&office, utc(2026, 3, 28, 12, 0)).unwrap();
+    assert!(weekend.out_of_hours);
+    assert_eq!(
+        weekend.message,
+        "Outside of availability 'Mon-Fri 08:00-20:00' (Europe/Berlin). \
+         Opens at 2026-03-30T08:00:00+02:00."
+    );
+    assert!(!is_available(&office, utc(2026, 3, 28, 12, 0)));
+
+    let weekday = availability(&office, utc(2026, 3, 27, 12, 0)).unwrap();
+    assert!(!weekday.out_of_hours);
+    assert_eq!(
+        weekday.message,
+        "Within availability 'Mon-Fri 08:00-20:00' (Europe/Berlin). \
+         Closes at 2026-03-27T20:00:00+01:00."
+    );
+
+    // An invalid schedule makes the provider unavailable.
+    let typo = scheduled_provider(
+        "typo",
+        Some(MaskProviderAvailabilitySpec {
+            schedule: "Mon-Fri 8am-8pm".to_owned(),
+            ..Default::default()
+        }),
+    );
+    let invalid = availability(&typo, utc(2026, 3, 27, 12, 0)).unwrap();
+    assert!(invalid.out_of_hours);
+    assert!(invalid.message.contains("spec.availability"));
+}
+
+#[tokio::test]
+async fn out_of_hours_providers_excluded() {
+    let list = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskProviderList",
+        "metadata": {},
+        "items": [
+            scheduled_provider("always", None),
+            scheduled_provider("office", Some(business_hours())),
+        ],
+    });
+    let names = |providers: Vec<MaskProvider>| -> Vec<String> {
+        providers
+            .into_iter()
+            .map(|p| p.metadata.name.unwrap())
+            .collect()
+    };
+
+    // Both are available on a weekday afternoon.
+    let (client, _) = mock::mock_client(list.clone());
+    let clock = Clock::Fixed(utc(2026, 3, 27, 12, 0));
+    let providers = list_active_providers(client, None, "default", clock.now())
+        .await
+        .unwrap();
+    assert_eq!(names(providers), ["always", "office"]);
+
+    // Only the one without availability hours is left on the weekend.
+    let (client, _) = mock::mock_client(list);
+    let clock = Clock::Fixed(utc(2026, 3, 28, 12, 0));
+    let providers = list_active_providers(client, None, "default", clock.now())
+        .await
+        .unwrap();
+    assert_eq!(names(providers), ["always"]);
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn out_of_hours_refuses_assignment() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+
+    // Only available on the day after tomorrow, so the controllers,
+    // which use the system clock, see it as out of hours.
+    const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+    let now = Utc::now();
+    let day = (now.weekday().num_days_from_monday() as usize + 2) % 7;
+    let mut provider = get_test_provider(client.clone(), &provider_name, &namespace).await?;
+    provider.spec.availability = Some(MaskProviderAvailabilitySpec {
+        schedule: format!("{} 00:00-24:00", DAYS[day]),
+        ..Default::default()
+    });
+    let ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let provider = api.create(&Default::default(), &provider).await?;
+    create_test_provider_secret(client.clone(), &namespace, &provider).await?;
+    ready.await.unwrap()?;
+
+    // The phase stays Ready, while the status shows it's out of hours.
+    let status = api.get(&provider_name).await?.status.unwrap();
+    assert_eq!(status.out_of_hours, Some(true));
+    assert!(status
+        .availability_message
+        .unwrap()
+        .starts_with("Outside of availability"));
+
+    // Faking the clock shows the MaskProvider is excluded now, but
+    // would be assigned during its availability hours.
+    let tags = vec![provider_name.clone()];
+    let listed = |now: DateTime<Utc>| {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        let tags = tags.clone();
+        async move {
+            list_active_providers(client, Some(&tags), &namespace, Clock::Fixed(now).now())
+                .await
+                .unwrap()
+                .len()
+        }
+    };
+    assert_eq!(listed(now).await, 0);
+    assert_eq!(listed(now + chrono::Duration::days(2)).await, 1);
+
+    // A Mask asking for the MaskProvider finds no providers.
+    let fail = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_mask_phase(client, &namespace, 0, MaskPhase::ErrNoProviders).await },
+        )
+    };
+    create_test_mask(client.clone(), &namespace, 0, &provider_name).await?;
+    fail.await.unwrap()?;
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
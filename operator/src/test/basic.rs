@@ -6,10 +6,11 @@ use vpn_types::*;
 use super::util::*;
 
 #[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
 async fn basic() -> Result<(), Error> {
     let client: Client = Client::try_default().await.unwrap();
     let (uid, namespace) = create_test_namespace(client.clone()).await?;
-    let provider_label = format!("{}-{}", PROVIDER_NAME, uid);
+    let provider_label = test_provider_name(&uid);
 
     // Create the test MaskProvider and wait for it to be Ready.
     let provider_ready = {
@@ -0,0 +1,295 @@
+use chrono::{DateTime, Duration as ChronoDuration, TimeZone, Utc};
+use k8s_openapi::{
+    api::core::v1::Secret,
+    apimachinery::pkg::apis::meta::v1::{OwnerReference, Time},
+    ByteString,
+};
+use kube::api::ObjectMeta;
+use serde_json::json;
+use std::{collections::BTreeMap, time::Duration};
+use vpn_types::*;
+
+use super::{mock::*, util::*};
+use crate::{
+    consumers::actions::retain_canary_target,
+    providers::canary::{self, CanaryOutcome, CANARY_TIMEOUT},
+    util::{
+        clock::{format_timestamp, Clock},
+        is_canary_reservation, messages, CANARY_LABEL, PROVIDER_UID_LABEL,
+    },
+};
+
+/// When the canary Mask of the tests was created.
+fn created() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2023, 5, 1, 12, 0, 0).unwrap()
+}
+
+/// Returns the time `millis` milliseconds after the canary was created,
+/// as seen by a fixed clock.
+fn after(millis: i64) -> DateTime<Utc> {
+    Clock::Fixed(created() + ChronoDuration::milliseconds(millis)).now()
+}
+
+/// Returns a MaskProvider with canaries enabled, last run at `last`.
+fn canary_provider(last: Option<DateTime<Utc>>) -> MaskProvider {
+    let mut provider = ready_provider("my-vpn", "default", "provider-uid");
+    provider.spec.max_slots = 1;
+    provider.spec.canary = Some(true);
+    provider.status.as_mut().unwrap().last_canary_at = last.map(|t| t.to_rfc3339());
+    provider
+}
+
+/// Returns the provider's canary Mask in the given phase.
+fn mask(phase: MaskPhase, message: Option<&str>) -> Mask {
+    let mut mask = canary::canary_mask("my-vpn", "default", &canary_provider(None));
+    mask.metadata.uid = Some("mask-uid".to_owned());
+    mask.metadata.creation_timestamp = Some(Time(created()));
+    mask.status = Some(MaskStatus {
+        phase: Some(phase),
+        message: message.map(str::to_owned),
+        ..Default::default()
+    });
+    mask
+}
+
+/// Returns the canary's MaskConsumer, assigned the provider.
+fn canary_consumer() -> MaskConsumer {
+    assigned(
+        mask_consumer("my-vpn-canary", "default", "consumer-uid"),
+        None,
+        assigned_provider(
+            "my-vpn",
+            "default",
+            "provider-uid",
+            "my-vpn-canary-provider-uid",
+        ),
+    )
+}
+
+/// Returns the credentials copied for the canary's MaskConsumer.
+fn secret(password: &str) -> Secret {
+    Secret {
+        metadata: ObjectMeta {
+            name: Some("my-vpn-canary-provider-uid".to_owned()),
+            labels: Some(BTreeMap::from([(
+                PROVIDER_UID_LABEL.to_owned(),
+                "provider-uid".to_owned(),
+            )])),
+            owner_references: Some(vec![OwnerReference {
+                uid: "consumer-uid".to_owned(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+        data: Some(BTreeMap::from([
+            ("OPENVPN_USER".to_owned(), ByteString(b"user".to_vec())),
+            (
+                "OPENVPN_PASSWORD".to_owned(),
+                ByteString(password.as_bytes().to_vec()),
+            ),
+        ])),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn canary_due() {
+    let interval = Some(Duration::from_secs(3600));
+    assert_eq!(
+        canary::interval(&canary_provider(None), interval),
+        Some(Duration::from_secs(3600))
+    );
+    assert_eq!(canary::interval(&canary_provider(None), None), None);
+    let mut opted_out = canary_provider(None);
+    opted_out.spec.canary = None;
+    assert_eq!(canary::interval(&opted_out, interval), None);
+
+    // The first canary is due right away, later ones once per interval.
+    let interval = Duration::from_secs(3600);
+    assert!(canary::is_due(&canary_provider(None), interval, after(0)));
+    let last = canary_provider(Some(created()));
+    assert!(!canary::is_due(&last, interval, after(3_599_000)));
+    assert!(canary::is_due(&last, interval, after(3_600_000)));
+}
+
+#[test]
+fn canary_lifecycle() {
+    let consumer = canary_consumer();
+    let secret = secret("pass");
+
+    // The canary is in progress until its credentials are copied.
+    assert_eq!(
+        canary::check(&mask(MaskPhase::Pending, None), None, None, after(1_000)),
+        None
+    );
+    let active = mask(MaskPhase::Active, None);
+    assert_eq!(
+        canary::check(&active, Some(&consumer), None, after(3_000)),
+        None
+    );
+
+    // It succeeds once they are, timed from the Mask's creation.
+    assert_eq!(
+        canary::check(&active, Some(&consumer), Some(&secret), after(4_200)),
+        Some(CanaryOutcome {
+            result: MaskProviderCanaryResult::Succeeded,
+            duration: Some(Duration::from_millis(4_200)),
+            message: None,
+        })
+    );
+}
+
+#[test]
+fn malformed_credentials_time_out() {
+    let consumer = canary_consumer();
+    let empty = secret("");
+    assert!(!canary::is_well_formed(&empty, &consumer));
+    let active = mask(MaskPhase::Active, None);
+    assert_eq!(
+        canary::check(&active, Some(&consumer), Some(&empty), after(30_000)),
+        None
+    );
+    let timeout = CANARY_TIMEOUT.as_millis() as i64 + 1;
+    let outcome = canary::check(&active, Some(&consumer), Some(&empty), after(timeout)).unwrap();
+    assert_eq!(outcome.result, MaskProviderCanaryResult::Failed);
+    assert_eq!(
+        outcome.duration,
+        Some(Duration::from_millis(timeout as u64))
+    );
+    assert!(outcome
+        .message
+        .unwrap()
+        .contains("within 60s (phase Active)"));
+
+    // Credentials copied for another MaskConsumer don't count either.
+    let mut other = consumer.clone();
+    other.metadata.uid = Some("other-uid".to_owned());
+    assert!(!canary::is_well_formed(&secret("pass"), &other));
+}
+
+#[test]
+fn error_phase_fails() {
+    let mask = mask(
+        MaskPhase::ErrNoProviders,
+        Some("No valid MaskProviders available."),
+    );
+    let outcome = canary::check(&mask, None, None, after(2_000)).unwrap();
+    assert_eq!(outcome.result, MaskProviderCanaryResult::Failed);
+    assert_eq!(
+        outcome.message.as_deref(),
+        Some("Canary Mask entered the ErrNoProviders phase: No valid MaskProviders available.")
+    );
+}
+
+#[test]
+fn full_provider_waits_until_timeout() {
+    // A canary waiting for a slot is in progress until it times out.
+    let waiting = mask(MaskPhase::Waiting, None);
+    assert_eq!(canary::check(&waiting, None, None, after(1_000)), None);
+
+    // Then it's reported apart from both failures and skipped canaries.
+    let outcome = canary::check(&waiting, None, None, after(61_000)).unwrap();
+    assert_eq!(outcome.result, MaskProviderCanaryResult::TimedOutWaiting);
+    assert_eq!(outcome.duration, Some(Duration::from_secs(61)));
+    assert_eq!(
+        outcome.message,
+        Some(messages::canary_timed_out_waiting(&CANARY_TIMEOUT))
+    );
+    assert_eq!(CanaryOutcome::skipped().duration, None);
+}
+
+#[test]
+fn canary_targets_provider() {
+    let mask = mask(MaskPhase::Pending, None);
+    assert_eq!(
+        mask.metadata.labels.as_ref().unwrap().get(CANARY_LABEL),
+        Some(&"provider-uid".to_owned())
+    );
+
+    // The canary's MaskConsumer inherits the label and may only be
+    // assigned the MaskProvider it tests.
+    let mut labelled = canary_consumer();
+    labelled.metadata.labels = mask.metadata.labels.clone();
+    let mut other = canary_provider(None);
+    other.metadata.uid = Some("other-uid".to_owned());
+    let providers = vec![other.clone(), canary_provider(None)];
+    let retained = retain_canary_target(providers.clone(), &labelled);
+    assert_eq!(retained, vec![canary_provider(None)]);
+    assert_eq!(retain_canary_target(providers, &canary_consumer()).len(), 2);
+
+    // Its reservation isn't counted as a real MaskConsumer.
+    let reservation = MaskReservation {
+        metadata: ObjectMeta {
+            labels: mask.metadata.labels.clone(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    assert!(is_canary_reservation(&reservation));
+    assert!(!is_canary_reservation(&Default::default()));
+}
+
+#[tokio::test]
+async fn outcome_recorded() {
+    let instance = canary_provider(None);
+    let (client, captured) = mock_client(serde_json::to_value(&instance).unwrap());
+    let outcome = CanaryOutcome {
+        result: MaskProviderCanaryResult::Succeeded,
+        duration: Some(Duration::from_millis(4_200)),
+        message: None,
+    };
+    canary::finished(client, &instance, outcome, after(4_200))
+        .await
+        .unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 1);
+    assert_eq!(
+        patch_op(&captured[0], "/status/lastCanaryResult"),
+        Some(&json!("Succeeded"))
+    );
+    assert_eq!(
+        patch_op(&captured[0], "/status/lastCanaryDuration"),
+        Some(&json!("4.2s"))
+    );
+    assert_eq!(
+        patch_op(&captured[0], "/status/lastCanaryAt"),
+        Some(&json!(format_timestamp(after(4_200))))
+    );
+}
+
+#[tokio::test]
+async fn cleanup_on_restart() {
+    let mut leftover = mask(MaskPhase::Active, None);
+    leftover.metadata.resource_version = Some("1".to_owned());
+    let mut deleting = leftover.clone();
+    deleting.metadata.name = Some("other-vpn-canary".to_owned());
+    deleting.metadata.deletion_timestamp = Some(Time(created()));
+    let (client, captured) = mock_method_routes(vec![
+        (
+            "GET",
+            "/apis/vpn.beebs.dev/v1/masks",
+            200,
+            json!({
+                "apiVersion": "vpn.beebs.dev/v1",
+                "kind": "MaskList",
+                "metadata": {},
+                "items": [leftover, deleting],
+            }),
+        ),
+        (
+            "DELETE",
+            "/apis/vpn.beebs.dev/v1/namespaces/default/masks/my-vpn-canary",
+            200,
+            serde_json::to_value(&leftover).unwrap(),
+        ),
+    ]);
+
+    // Only the canary that isn't already being deleted is deleted.
+    assert_eq!(canary::cleanup(client).await.unwrap(), 1);
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 2);
+    assert!(captured[0]
+        .path
+        .contains("labelSelector=vpn.beebs.dev%2Fcanary"));
+    assert_eq!(captured[1].method, "DELETE");
+}
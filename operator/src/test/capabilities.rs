@@ -0,0 +1,179 @@
+use k8s_openapi::apimachinery::pkg::{apis::meta::v1::APIResourceList, version::Info};
+use serde_json::{json, Value};
+
+use super::{mock::mock_method_routes, util::mask_consumer};
+use crate::util::{
+    capabilities::{detect, Capabilities, MIN_MINOR_VERSION},
+    patch::patch_status,
+    Error,
+};
+
+/// Returns the version the API server reports for `minor`.
+fn version(minor: &str) -> Value {
+    json!({
+        "major": "1",
+        "minor": minor,
+        "gitVersion": format!("v1.{}.3", minor.trim_end_matches('+')),
+        "gitCommit": "",
+        "gitTreeState": "clean",
+        "buildDate": "2023-06-14T09:47:40Z",
+        "goVersion": "go1.20.5",
+        "compiler": "gc",
+        "platform": "linux/amd64",
+    })
+}
+
+/// Returns the resources served in the operator's API group, with or
+/// without their status subresources.
+fn resources(status: bool) -> Value {
+    let names: Vec<String> = [
+        "masks",
+        "maskconsumers",
+        "maskproviders",
+        "maskreservations",
+        "maskquotas",
+        "clustermaskproviders",
+    ]
+    .into_iter()
+    .flat_map(|plural| {
+        let mut names = vec![plural.to_owned()];
+        if status {
+            names.push(format!("{}/status", plural));
+        }
+        names
+    })
+    .collect();
+    served(&names)
+}
+
+/// Returns the operator's API group serving exactly the named resources.
+fn served(names: &[String]) -> Value {
+    let resources: Vec<Value> = names
+        .iter()
+        .map(|name| {
+            json!({
+                "name": name,
+                "singularName": "",
+                "namespaced": true,
+                "kind": "",
+                "verbs": ["get", "patch"],
+            })
+        })
+        .collect();
+    json!({
+        "kind": "APIResourceList",
+        "apiVersion": "v1",
+        "groupVersion": "vpn.beebs.dev/v1",
+        "resources": resources,
+    })
+}
+
+/// Returns the capabilities detected from the given discovery responses.
+fn from_discovery(version: Value, resources: Value) -> Result<Capabilities, Error> {
+    let version: Info = serde_json::from_value(version).unwrap();
+    let resources: APIResourceList = serde_json::from_value(resources).unwrap();
+    Capabilities::from_discovery(&version, &resources)
+}
+
+#[tokio::test]
+async fn detected_from_discovery() {
+    let (client, captured) = mock_method_routes(vec![
+        ("GET", "/version", 200, version("27+")),
+        ("GET", "/apis/vpn.beebs.dev/v1", 200, resources(true)),
+    ]);
+    let capabilities = detect(client).await.unwrap();
+    assert_eq!(
+        capabilities,
+        Capabilities {
+            minor: 27,
+            git_version: "v1.27.3".to_owned(),
+            disruption_conditions: true,
+            missing_status_subresources: vec![],
+        }
+    );
+    assert!(!capabilities.untested());
+    assert_eq!(captured.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn disruption_conditions_from_1_26() {
+    let capabilities = from_discovery(version("25"), resources(true)).unwrap();
+    assert!(!capabilities.disruption_conditions);
+    let capabilities = from_discovery(version("26"), resources(true)).unwrap();
+    assert!(capabilities.disruption_conditions);
+}
+
+#[test]
+fn missing_status_subresources() {
+    let capabilities = from_discovery(version("28"), resources(false)).unwrap();
+    assert_eq!(capabilities.missing_status_subresources.len(), 6);
+}
+
+#[test]
+fn status_subresources_detected_per_kind() {
+    // MaskQuota is optional and not installed, and only the Mask CRD is
+    // outdated, so the other kinds keep patching the status subresource.
+    let names = [
+        "masks",
+        "maskconsumers",
+        "maskconsumers/status",
+        "maskproviders",
+        "maskproviders/status",
+        "maskreservations",
+        "maskreservations/status",
+        "clustermaskproviders",
+        "clustermaskproviders/status",
+    ]
+    .map(str::to_owned);
+    let capabilities = from_discovery(version("28"), served(&names)).unwrap();
+    assert_eq!(capabilities.missing_status_subresources, vec!["masks"]);
+}
+
+#[test]
+fn old_versions_refused() {
+    let oldest = MIN_MINOR_VERSION.to_string();
+    assert!(from_discovery(version(&oldest), resources(true)).is_ok());
+    let older = (MIN_MINOR_VERSION - 1).to_string();
+    assert!(matches!(
+        from_discovery(version(&older), resources(true)),
+        Err(Error::UnsupportedVersion(_))
+    ));
+    assert!(matches!(
+        from_discovery(version("unknown"), resources(true)),
+        Err(Error::UnsupportedVersion(_))
+    ));
+}
+
+#[test]
+fn newer_versions_untested() {
+    let capabilities = from_discovery(version("35"), resources(true)).unwrap();
+    assert!(capabilities.untested());
+}
+
+#[tokio::test]
+async fn status_patched_on_resource_without_subresource() {
+    // The MaskConsumer CRD lacks the status subresource, so patching it
+    // answers 404 and the status is patched on the resource itself.
+    let consumer = mask_consumer("test-consumer", "default", "consumer-uid");
+    let path = "/apis/vpn.beebs.dev/v1/namespaces/default/maskconsumers/test-consumer";
+    let (client, captured) = mock_method_routes(vec![
+        (
+            "PATCH",
+            "/apis/vpn.beebs.dev/v1/namespaces/default/maskconsumers/test-consumer/status",
+            404,
+            super::mock::status_failure(404),
+        ),
+        ("PATCH", path, 200, serde_json::to_value(&consumer).unwrap()),
+    ]);
+    patch_status(client, &consumer, |status| {
+        status.message = Some("patched".to_owned());
+    })
+    .await
+    .unwrap();
+    let captured = captured.lock().unwrap();
+    let paths: Vec<&str> = captured
+        .iter()
+        .map(|r| r.path.split('?').next().unwrap())
+        .collect();
+    assert_eq!(paths, vec![format!("{}/status", path).as_str(), path]);
+}
@@ -0,0 +1,53 @@
+use clap::Parser;
+
+use crate::{parse_interval, parse_port, usage_error, Cli};
+
+#[test]
+fn port_whitespace_trimmed() {
+    assert_eq!(parse_port("9090"), Ok(9090));
+    // e.g. a trailing space left by a Helm template.
+    assert_eq!(parse_port("9090 "), Ok(9090));
+    assert_eq!(parse_port(" 8443\n"), Ok(8443));
+    assert_eq!(
+        parse_interval(" 5m ").unwrap(),
+        std::time::Duration::from_secs(300)
+    );
+}
+
+#[test]
+fn port_range_validated() {
+    assert_eq!(parse_port("1"), Ok(1));
+    assert_eq!(parse_port("65535"), Ok(65535));
+    for invalid in ["0", "65536", "-1", "", "9090x", "http"] {
+        assert!(parse_port(invalid).is_err(), "{:?} accepted", invalid);
+    }
+}
+
+#[test]
+fn invalid_port_reported_on_one_line() {
+    let error = match Cli::try_parse_from(["vpn-operator", "--metrics-port", "9090x", "manage-all"])
+    {
+        Ok(_) => panic!("invalid port accepted"),
+        Err(error) => error,
+    };
+    // Settings errors aren't mistaken for requests for help.
+    assert!(error.use_stderr());
+    let message = usage_error(&error);
+    assert!(!message.contains('\n'));
+    assert!(message.contains("METRICS_PORT"), "{}", message);
+    assert!(message.contains("'9090x'"), "{}", message);
+    assert!(message.contains("between 1 and 65535"), "{}", message);
+
+    let error = match Cli::try_parse_from(["vpn-operator", "webhook", "--port", "0"]) {
+        Ok(_) => panic!("port 0 accepted"),
+        Err(error) => error,
+    };
+    assert!(usage_error(&error).contains("WEBHOOK_PORT"));
+}
+
+#[test]
+fn valid_ports_parsed() {
+    let cli =
+        Cli::try_parse_from(["vpn-operator", "--metrics-port", "9090 ", "manage-all"]).unwrap();
+    assert_eq!(cli.metrics_port, Some(9090));
+}
@@ -0,0 +1,256 @@
+use serde_json::{json, Value};
+use std::time::Duration;
+use vpn_types::*;
+
+use super::mock::{decide, merged};
+use crate::{
+    clusterproviders::reconcile::{determine_action, ClusterProviderAction},
+    util::messages,
+};
+
+/// Default of `--status-freshness-interval`.
+const FRESHNESS: Duration = Duration::from_secs(600);
+
+/// Returns a Ready ClusterMaskProvider targeting the `team-a` namespace,
+/// with `patch` merged into it.
+fn cluster_provider(patch: Value) -> Value {
+    let cluster_provider = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "ClusterMaskProvider",
+        "metadata": { "name": "shared", "uid": "cluster-uid" },
+        "spec": {
+            "secret": "credentials",
+            "maxSlots": 2,
+            "secretNamespace": "vpn",
+            "targetNamespaces": ["team-a"],
+        },
+        "status": {
+            "phase": "Ready",
+            "message": messages::children_ready(1, 0, 2),
+            "lastUpdated": chrono::Utc::now().to_rfc3339(),
+            "children": [{ "namespace": "team-a", "phase": "Ready" }],
+        },
+    });
+    merged(cluster_provider, patch)
+}
+
+/// Returns the owner reference of the ClusterMaskProvider's children.
+fn owner() -> Value {
+    json!([{
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "ClusterMaskProvider",
+        "name": "shared",
+        "uid": "cluster-uid",
+    }])
+}
+
+/// Returns the Secret with the credentials in the given namespace.
+fn secret(namespace: &str, name: &str, password: &str, owner: Value) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": { "name": name, "namespace": namespace, "ownerReferences": owner },
+        "data": { "OPENVPN_PASSWORD": password },
+    })
+}
+
+/// Returns the credentials the children are copied from.
+fn source() -> Value {
+    secret("vpn", "credentials", "cGFzcw==", Value::Null)
+}
+
+/// Returns the Ready child MaskProvider in `team-a`, with `patch` merged
+/// into it.
+fn child(patch: Value) -> Value {
+    let child = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskProvider",
+        "metadata": { "name": "shared", "namespace": "team-a", "ownerReferences": owner() },
+        "spec": { "secret": "shared-credentials", "maxSlots": 2 },
+        "status": { "phase": "Ready" },
+    });
+    merged(child, patch)
+}
+
+/// Returns the objects of a fully propagated ClusterMaskProvider.
+fn propagated() -> Vec<Value> {
+    vec![
+        json!({ "apiVersion": "v1", "kind": "Namespace", "metadata": { "name": "team-a" } }),
+        source(),
+        secret("team-a", "shared-credentials", "cGFzcw==", owner()),
+        child(json!({})),
+    ]
+}
+
+/// Returns the objects of a fully propagated ClusterMaskProvider, with
+/// `patch` merged into the last object of the given kind.
+fn propagated_with(kind: &str, patch: Value) -> Vec<Value> {
+    let mut objects = propagated();
+    let object = objects
+        .iter_mut()
+        .rev()
+        .find(|o| o["kind"] == kind)
+        .unwrap();
+    *object = merged(object.clone(), patch);
+    objects
+}
+
+/// Returns the action decided for the ClusterMaskProvider with the
+/// given objects in the cluster.
+async fn action(cluster_provider: Value, objects: &[Value]) -> ClusterProviderAction {
+    let instance: ClusterMaskProvider = serde_json::from_value(cluster_provider).unwrap();
+    decide(objects, |client| {
+        let instance = instance.clone();
+        async move { determine_action(client, &instance, FRESHNESS, Some("vpn-operator")).await }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn cluster_provider_actions() {
+    let stale = (chrono::Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+    let team_a = || "team-a".to_owned();
+    let conflict = messages::child_conflict("MaskProvider", "team-a", "shared");
+    let cases = [
+        (
+            "being deleted",
+            cluster_provider(
+                json!({ "metadata": { "deletionTimestamp": "2023-01-01T00:00:00Z" } }),
+            ),
+            propagated(),
+            ClusterProviderAction::NoOp,
+        ),
+        (
+            "missing status",
+            cluster_provider(json!({ "status": null })),
+            propagated(),
+            ClusterProviderAction::Pending,
+        ),
+        (
+            "source missing",
+            cluster_provider(json!({})),
+            propagated()
+                .into_iter()
+                .filter(|o| o["metadata"]["namespace"] != "vpn")
+                .collect(),
+            ClusterProviderAction::SecretNotFound,
+        ),
+        (
+            "source missing shown",
+            cluster_provider(json!({ "status": { "phase": "ErrSecretNotFound" } })),
+            vec![],
+            ClusterProviderAction::NoOp,
+        ),
+        (
+            "copy missing",
+            cluster_provider(json!({})),
+            propagated_with("Secret", json!({ "metadata": { "name": "unrelated" } })),
+            ClusterProviderAction::CopySecret {
+                namespace: team_a(),
+            },
+        ),
+        (
+            "copy outdated",
+            cluster_provider(json!({})),
+            propagated_with("Secret", json!({ "data": { "OPENVPN_PASSWORD": "b2xk" } })),
+            ClusterProviderAction::CopySecret {
+                namespace: team_a(),
+            },
+        ),
+        (
+            "child missing",
+            cluster_provider(json!({})),
+            propagated_with(
+                "MaskProvider",
+                json!({ "metadata": { "name": "unrelated" } }),
+            ),
+            ClusterProviderAction::CreateChild {
+                namespace: team_a(),
+            },
+        ),
+        (
+            "child outdated",
+            cluster_provider(json!({})),
+            propagated_with("MaskProvider", json!({ "spec": { "maxSlots": 1 } })),
+            ClusterProviderAction::UpdateChild {
+                namespace: team_a(),
+            },
+        ),
+        (
+            "namespace untargeted",
+            cluster_provider(json!({ "spec": { "targetNamespaces": [] } })),
+            propagated(),
+            ClusterProviderAction::DeleteChild {
+                namespace: team_a(),
+            },
+        ),
+        (
+            "child in the way",
+            cluster_provider(json!({})),
+            propagated_with(
+                "MaskProvider",
+                json!({ "metadata": { "ownerReferences": null } }),
+            ),
+            ClusterProviderAction::Status {
+                children: vec![ChildProviderStatus {
+                    namespace: team_a(),
+                    phase: None,
+                    active_slots: None,
+                    message: Some(conflict),
+                }],
+            },
+        ),
+        (
+            "child active",
+            cluster_provider(json!({})),
+            propagated_with(
+                "MaskProvider",
+                json!({ "status": { "phase": "Active", "activeSlots": 1 } }),
+            ),
+            ClusterProviderAction::Status {
+                children: vec![ChildProviderStatus {
+                    namespace: team_a(),
+                    phase: Some(MaskProviderPhase::Active),
+                    active_slots: Some(1),
+                    message: None,
+                }],
+            },
+        ),
+        (
+            "stale status",
+            cluster_provider(json!({ "status": { "lastUpdated": stale } })),
+            propagated(),
+            ClusterProviderAction::Status {
+                children: vec![ChildProviderStatus {
+                    namespace: team_a(),
+                    phase: Some(MaskProviderPhase::Ready),
+                    active_slots: None,
+                    message: None,
+                }],
+            },
+        ),
+        (
+            "propagated",
+            cluster_provider(json!({})),
+            propagated(),
+            ClusterProviderAction::NoOp,
+        ),
+        (
+            // The child in team-a is removed once the shared one is up.
+            "shared",
+            cluster_provider(json!({ "spec": { "shared": true } })),
+            propagated(),
+            ClusterProviderAction::CopySecret {
+                namespace: "vpn-operator".to_owned(),
+            },
+        ),
+    ];
+    for (case, cluster_provider, objects, expected) in cases {
+        assert_eq!(
+            action(cluster_provider, &objects).await,
+            expected,
+            "{}",
+            case
+        );
+    }
+}
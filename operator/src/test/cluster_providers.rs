@@ -0,0 +1,607 @@
+use k8s_openapi::{
+    api::core::v1::{Namespace, Secret},
+    apimachinery::pkg::apis::meta::v1::Time,
+    ByteString,
+};
+use kube::{
+    api::{ObjectMeta, Patch, PatchParams},
+    client::Client,
+    Api,
+};
+use serde_json::json;
+use std::{collections::BTreeMap, time::Duration};
+use vpn_types::*;
+
+use super::{mock::*, util::*};
+use crate::{
+    clusterproviders::actions::{self, ChildChange},
+    consumers::actions::parent_cluster_provider,
+};
+
+/// Returns a ClusterMaskProvider targeting `team-a` and the namespaces
+/// labeled as tenants.
+fn cluster_provider() -> ClusterMaskProvider {
+    ClusterMaskProvider {
+        metadata: ObjectMeta {
+            name: Some("nordvpn".to_owned()),
+            uid: Some("cluster-uid".to_owned()),
+            ..Default::default()
+        },
+        spec: ClusterMaskProviderSpec {
+            provider: MaskProviderSpec {
+                secret: "nordvpn-credentials".to_owned(),
+                max_slots: 2,
+                tags: Some(vec!["nordvpn".to_owned()]),
+                ..Default::default()
+            },
+            secret_namespace: "vpn".to_owned(),
+            target_namespaces: Some(vec!["team-a".to_owned()]),
+            namespace_selector: Some(BTreeMap::from([(
+                "vpn.beebs.dev/tenant".to_owned(),
+                "true".to_owned(),
+            )])),
+            slots_per_namespace: Some(1),
+            shared: None,
+        },
+        status: None,
+    }
+}
+
+/// Returns the credentials Secret of the ClusterMaskProvider.
+fn source(password: &str) -> Secret {
+    Secret {
+        metadata: ObjectMeta {
+            name: Some("nordvpn-credentials".to_owned()),
+            namespace: Some("vpn".to_owned()),
+            ..Default::default()
+        },
+        data: Some(BTreeMap::from([(
+            "OPENVPN_PASSWORD".to_owned(),
+            ByteString(password.as_bytes().to_vec()),
+        )])),
+        ..Default::default()
+    }
+}
+
+/// Returns a namespace with the given labels.
+fn namespace(name: &str, labels: &[(&str, &str)]) -> Namespace {
+    Namespace {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            labels: Some(
+                labels
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            ),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Returns the up-to-date child MaskProvider and credentials in the namespace.
+fn child(namespace: &str, phase: MaskProviderPhase, active: usize) -> (MaskProvider, Secret) {
+    let instance = cluster_provider();
+    let mut provider = actions::child_provider(&instance, namespace);
+    provider.status = Some(MaskProviderStatus {
+        phase: Some(phase),
+        active_slots: Some(active),
+        ..Default::default()
+    });
+    let secret = actions::child_secret(&instance, &source("pass"), namespace);
+    (provider, secret)
+}
+
+/// Returns the status of a child without a conflict.
+fn status(namespace: &str, phase: MaskProviderPhase, active: usize) -> ChildProviderStatus {
+    ChildProviderStatus {
+        namespace: namespace.to_owned(),
+        phase: Some(phase),
+        active_slots: Some(active),
+        message: None,
+    }
+}
+
+/// Returns the names of the targets.
+fn targets(names: &[&str]) -> Vec<String> {
+    names.iter().map(|n| n.to_string()).collect()
+}
+
+#[test]
+fn namespaces_targeted() {
+    let spec = cluster_provider().spec;
+    assert!(actions::is_target(&spec, &namespace("team-a", &[])));
+    assert!(actions::is_target(
+        &spec,
+        &namespace("team-b", &[("vpn.beebs.dev/tenant", "true")])
+    ));
+    assert!(!actions::is_target(
+        &spec,
+        &namespace("team-c", &[("vpn.beebs.dev/tenant", "false")])
+    ));
+    assert!(!actions::is_target(&spec, &namespace("kube-system", &[])));
+
+    // Namespaces being deleted aren't targeted.
+    let mut deleting = namespace("team-a", &[]);
+    deleting.metadata.deletion_timestamp = Some(Time(chrono::Utc::now()));
+    assert!(!actions::is_target(&spec, &deleting));
+
+    // An empty selector doesn't select every namespace.
+    let mut spec = spec;
+    spec.namespace_selector = Some(BTreeMap::new());
+    assert!(!actions::is_target(&spec, &namespace("team-b", &[])));
+}
+
+#[test]
+fn children_fan_out() {
+    let instance = cluster_provider();
+    let child = actions::child_provider(&instance, "team-a");
+    assert_eq!(child.metadata.name.as_deref(), Some("nordvpn"));
+    assert_eq!(child.spec.secret, "nordvpn-credentials");
+    assert_eq!(child.spec.max_slots, 1);
+    assert_eq!(child.spec.tags, Some(vec!["nordvpn".to_owned()]));
+    assert!(actions::is_child(&child.metadata, &instance));
+
+    // The credentials are copied before the child is created.
+    let source = source("pass");
+    let plan = actions::plan(
+        &instance,
+        &source,
+        &targets(&["team-b", "team-a"]),
+        &[],
+        &[],
+    );
+    assert_eq!(
+        plan.change,
+        Some(ChildChange::CopySecret {
+            namespace: "team-a".to_owned()
+        })
+    );
+    let secret = actions::child_secret(&instance, &source, "team-a");
+    let plan = actions::plan(
+        &instance,
+        &source,
+        &targets(&["team-a", "team-b"]),
+        &[],
+        &[secret],
+    );
+    assert_eq!(
+        plan.change,
+        Some(ChildChange::Create {
+            namespace: "team-a".to_owned()
+        })
+    );
+}
+
+#[test]
+fn shared_child_in_operator_namespace() {
+    let mut instance = cluster_provider();
+    assert_eq!(
+        actions::shared_namespace(&instance, Some("vpn-operator")),
+        None
+    );
+
+    // The targets are ignored, and the only child has all of the slots.
+    instance.spec.shared = Some(true);
+    instance.spec.provider.namespaces = Some(vec!["team-b".to_owned()]);
+    assert_eq!(
+        actions::shared_namespace(&instance, Some("vpn-operator")).as_deref(),
+        Some("vpn-operator")
+    );
+    assert_eq!(
+        actions::shared_namespace(&instance, None).as_deref(),
+        Some("vpn")
+    );
+    let child = actions::child_provider(&instance, "vpn-operator");
+    assert_eq!(child.spec.max_slots, 2);
+    assert_eq!(child.spec.namespaces, Some(vec!["team-b".to_owned()]));
+
+    // Masks assigned the child record the ClusterMaskProvider.
+    assert_eq!(parent_cluster_provider(&child).as_deref(), Some("nordvpn"));
+    let other = mask_provider("nordvpn", "team-a", "provider-uid");
+    assert_eq!(parent_cluster_provider(&other), None);
+}
+
+#[test]
+fn updates_propagated() {
+    let instance = cluster_provider();
+    let (provider, secret) = child("team-a", MaskProviderPhase::Ready, 0);
+    let all = targets(&["team-a"]);
+    let plan = actions::plan(
+        &instance,
+        &source("pass"),
+        &all,
+        std::slice::from_ref(&provider),
+        std::slice::from_ref(&secret),
+    );
+    assert_eq!(plan.change, None);
+
+    // Changed credentials are copied again.
+    let plan = actions::plan(
+        &instance,
+        &source("new"),
+        &all,
+        std::slice::from_ref(&provider),
+        std::slice::from_ref(&secret),
+    );
+    assert_eq!(
+        plan.change,
+        Some(ChildChange::CopySecret {
+            namespace: "team-a".to_owned()
+        })
+    );
+
+    // Changes to the ClusterMaskProvider and edits to the child are reverted.
+    let mut changed = instance.clone();
+    changed.spec.slots_per_namespace = Some(3);
+    let update = Some(ChildChange::Update {
+        namespace: "team-a".to_owned(),
+    });
+    let plan = actions::plan(
+        &changed,
+        &source("pass"),
+        &all,
+        std::slice::from_ref(&provider),
+        std::slice::from_ref(&secret),
+    );
+    assert_eq!(plan.change, update);
+    let mut edited = provider;
+    edited.spec.tags = None;
+    let plan = actions::plan(&instance, &source("pass"), &all, &[edited], &[secret]);
+    assert_eq!(plan.change, update);
+}
+
+#[tokio::test]
+async fn removed_keys_dropped_from_child() {
+    // The child was created while the ClusterMaskProvider had tags.
+    let (provider, secret) = child("team-a", MaskProviderPhase::Ready, 0);
+    let (client, _, stored) = mock_store(serde_json::to_value(&provider).unwrap());
+
+    // The tags are removed from the template, so the child is updated.
+    let mut instance = cluster_provider();
+    instance.spec.provider.tags = None;
+    let all = targets(&["team-a"]);
+    let plan = actions::plan(
+        &instance,
+        &source("pass"),
+        &all,
+        std::slice::from_ref(&provider),
+        std::slice::from_ref(&secret),
+    );
+    assert_eq!(
+        plan.change,
+        Some(ChildChange::Update {
+            namespace: "team-a".to_owned()
+        })
+    );
+    actions::update_child(client, &instance, "team-a")
+        .await
+        .unwrap();
+
+    // The tags are gone from the child, so it no longer needs updating.
+    let updated: MaskProvider = serde_json::from_value(stored.lock().unwrap().clone()).unwrap();
+    assert_eq!(updated.spec.tags, None);
+    let plan = actions::plan(&instance, &source("pass"), &all, &[updated], &[secret]);
+    assert_eq!(plan.change, None);
+}
+
+#[test]
+fn removed_namespace_cleaned_up() {
+    let instance = cluster_provider();
+    let (provider, secret) = child("team-b", MaskProviderPhase::Ready, 0);
+    let plan = actions::plan(
+        &instance,
+        &source("pass"),
+        &[],
+        &[provider],
+        std::slice::from_ref(&secret),
+    );
+    let delete = Some(ChildChange::Delete {
+        namespace: "team-b".to_owned(),
+    });
+    assert_eq!(plan.change, delete);
+
+    // A leftover copy of the credentials is deleted as well.
+    let plan = actions::plan(&instance, &source("pass"), &[], &[], &[secret]);
+    assert_eq!(plan.change, delete);
+}
+
+#[test]
+fn foreign_resources_left_alone() {
+    let instance = cluster_provider();
+    let mut foreign = MaskProvider {
+        metadata: ObjectMeta {
+            name: Some("nordvpn".to_owned()),
+            namespace: Some("team-a".to_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let plan = actions::plan(
+        &instance,
+        &source("pass"),
+        &targets(&["team-a"]),
+        std::slice::from_ref(&foreign),
+        &[],
+    );
+    assert_eq!(plan.change, None);
+    assert_eq!(
+        plan.children[0].message.as_deref(),
+        Some("MaskProvider team-a/nordvpn already exists and doesn't belong to the ClusterMaskProvider.")
+    );
+    assert_eq!(
+        actions::aggregate(&plan.children, 1).0,
+        ClusterMaskProviderPhase::Degraded
+    );
+
+    // It isn't deleted when the namespace is no longer targeted either.
+    foreign.metadata.namespace = Some("team-b".to_owned());
+    let plan = actions::plan(&instance, &source("pass"), &[], &[foreign], &[]);
+    assert_eq!(plan.change, None);
+}
+
+#[test]
+fn status_aggregated() {
+    let ready = [
+        status("team-a", MaskProviderPhase::Ready, 0),
+        status("team-b", MaskProviderPhase::Ready, 0),
+    ];
+    assert_eq!(
+        actions::aggregate(&ready, 1),
+        (
+            ClusterMaskProviderPhase::Ready,
+            "0 of 2 slots in use across 2 namespaces.".to_owned()
+        )
+    );
+    let active = [
+        status("team-a", MaskProviderPhase::Active, 1),
+        status("team-b", MaskProviderPhase::Ready, 0),
+    ];
+    assert_eq!(
+        actions::aggregate(&active, 1),
+        (
+            ClusterMaskProviderPhase::Active,
+            "1 of 2 slots in use across 2 namespaces.".to_owned()
+        )
+    );
+    assert_eq!(actions::active_slots(&active), 1);
+    let verifying = [
+        status("team-a", MaskProviderPhase::Verifying, 0),
+        status("team-b", MaskProviderPhase::Ready, 0),
+    ];
+    assert_eq!(
+        actions::aggregate(&verifying, 1),
+        (
+            ClusterMaskProviderPhase::Propagating,
+            "Waiting for 1 of 2 child MaskProviders to become Ready.".to_owned()
+        )
+    );
+    let failed = [
+        status("team-a", MaskProviderPhase::ErrVerifyFailed, 0),
+        status("team-b", MaskProviderPhase::Verifying, 0),
+    ];
+    assert_eq!(
+        actions::aggregate(&failed, 1),
+        (
+            ClusterMaskProviderPhase::Degraded,
+            "Child MaskProviders are degraded in namespaces: team-a.".to_owned()
+        )
+    );
+}
+
+#[tokio::test]
+async fn status_recorded() {
+    let mut instance = cluster_provider();
+    instance.status = Some(ClusterMaskProviderStatus {
+        phase: Some(ClusterMaskProviderPhase::Pending),
+        ..Default::default()
+    });
+    let (client, captured) = mock_client(serde_json::to_value(&instance).unwrap());
+    let children = vec![
+        status("team-a", MaskProviderPhase::Active, 1),
+        status("team-b", MaskProviderPhase::Ready, 0),
+    ];
+    actions::update_status(client, &instance, children)
+        .await
+        .unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 1);
+    // The status of the cluster-scoped resource isn't namespaced.
+    assert!(captured[0]
+        .path
+        .starts_with("/apis/vpn.beebs.dev/v1/clustermaskproviders/nordvpn/status"));
+    assert_eq!(
+        patch_op(&captured[0], "/status/phase"),
+        Some(&json!("Active"))
+    );
+    assert_eq!(
+        patch_op(&captured[0], "/status/activeSlots"),
+        Some(&json!(1))
+    );
+    assert_eq!(patch_op(&captured[0], "/status/maxSlots"), Some(&json!(2)));
+}
+
+/// Creates a ClusterMaskProvider for the test credentials in `namespace`,
+/// targeting the namespaces labeled with the test's UUID.
+async fn create_cluster_provider(
+    client: Client,
+    uid: &str,
+    namespace: &str,
+) -> Result<ClusterMaskProvider, Error> {
+    let name = test_provider_name(uid);
+    let provider = get_test_provider(client.clone(), &name, namespace).await?;
+    let api: Api<ClusterMaskProvider> = Api::all(client.clone());
+    let instance = api
+        .create(
+            &Default::default(),
+            &ClusterMaskProvider {
+                metadata: ObjectMeta {
+                    name: Some(name),
+                    ..Default::default()
+                },
+                spec: ClusterMaskProviderSpec {
+                    provider: MaskProviderSpec {
+                        // Masks in the target namespaces may use the children.
+                        namespaces: None,
+                        ..provider.spec.clone()
+                    },
+                    secret_namespace: namespace.to_owned(),
+                    namespace_selector: Some(BTreeMap::from([(
+                        "vpn.beebs.dev/test".to_owned(),
+                        uid.to_owned(),
+                    )])),
+                    ..Default::default()
+                },
+                status: None,
+            },
+        )
+        .await?;
+    create_test_provider_secret(client, namespace, &provider).await?;
+    Ok(instance)
+}
+
+/// Labels the namespace so it is targeted by the test's ClusterMaskProvider,
+/// or removes the label if `uid` is None.
+async fn label_namespace(client: Client, namespace: &str, uid: Option<&str>) -> Result<(), Error> {
+    let api: Api<Namespace> = Api::all(client);
+    let patch = json!({ "metadata": { "labels": { "vpn.beebs.dev/test": uid } } });
+    api.patch(namespace, &PatchParams::default(), &Patch::Merge(&patch))
+        .await?;
+    Ok(())
+}
+
+/// Waits for the child MaskProvider in the namespace to satisfy `f`.
+async fn wait_for_child(
+    client: Client,
+    namespace: &str,
+    name: &str,
+    f: impl Fn(&MaskProvider) -> bool,
+) -> Result<MaskProvider, Error> {
+    let api: Api<MaskProvider> = Api::namespaced(client, namespace);
+    for _ in 0..120 {
+        match api.get_opt(name).await? {
+            Some(child) if f(&child) => return Ok(child),
+            _ => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    }
+    Err(Error::Other(format!(
+        "child MaskProvider {}/{} did not reach the expected state before timeout",
+        namespace, name
+    )))
+}
+
+/// Waits for the child MaskProvider in the namespace to be deleted.
+async fn wait_for_child_deleted(client: Client, namespace: &str, name: &str) -> Result<(), Error> {
+    let api: Api<MaskProvider> = Api::namespaced(client, namespace);
+    for _ in 0..120 {
+        if api.get_opt(name).await?.is_none() {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    Err(Error::Other(format!(
+        "child MaskProvider {}/{} was not deleted before timeout",
+        namespace, name
+    )))
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn cluster_provider_fan_out() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let (_, tenant_a) = create_test_namespace(client.clone()).await?;
+    let (_, tenant_b) = create_test_namespace(client.clone()).await?;
+    let instance = create_cluster_provider(client.clone(), &uid, &namespace).await?;
+    let name = instance.metadata.name.clone().unwrap();
+    label_namespace(client.clone(), &tenant_a, Some(&uid)).await?;
+    label_namespace(client.clone(), &tenant_b, Some(&uid)).await?;
+
+    // Each tenant gets a child with a copy of the credentials.
+    for tenant in [&tenant_a, &tenant_b] {
+        wait_for_child(client.clone(), tenant, &name, |p| {
+            p.status.as_ref().and_then(|s| s.phase) == Some(MaskProviderPhase::Ready)
+        })
+        .await?;
+        let secret =
+            wait_for_secret(client.clone(), format!("{}-credentials", name), tenant).await?;
+        assert!(secret.data.is_some());
+    }
+
+    // Masks in a tenant namespace are assigned its child.
+    create_test_mask(client.clone(), &tenant_a, 0, &name).await?;
+    let assigned = wait_for_provider_assignment(client.clone(), &tenant_a, 0).await?;
+    assert_eq!(assigned.namespace, tenant_a);
+
+    // Garbage collect the test resources.
+    let api: Api<ClusterMaskProvider> = Api::all(client.clone());
+    api.delete(&name, &Default::default()).await?;
+    for tenant in [tenant_a, tenant_b] {
+        cleanup(client.clone(), &tenant).await?;
+    }
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn cluster_provider_update_propagated() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let (_, tenant) = create_test_namespace(client.clone()).await?;
+    let instance = create_cluster_provider(client.clone(), &uid, &namespace).await?;
+    let name = instance.metadata.name.clone().unwrap();
+    label_namespace(client.clone(), &tenant, Some(&uid)).await?;
+    wait_for_child(client.clone(), &tenant, &name, |_| true).await?;
+
+    // Changing the ClusterMaskProvider changes the child.
+    let api: Api<ClusterMaskProvider> = Api::all(client.clone());
+    let patch = json!({ "spec": { "slotsPerNamespace": 3 } });
+    api.patch(&name, &PatchParams::default(), &Patch::Merge(&patch))
+        .await?;
+    wait_for_child(client.clone(), &tenant, &name, |p| p.spec.max_slots == 3).await?;
+
+    // Edits to the child are reverted.
+    let children: Api<MaskProvider> = Api::namespaced(client.clone(), &tenant);
+    let patch = json!({ "spec": { "maxSlots": 10 } });
+    children
+        .patch(&name, &PatchParams::default(), &Patch::Merge(&patch))
+        .await?;
+    wait_for_child(client.clone(), &tenant, &name, |p| p.spec.max_slots == 3).await?;
+
+    // Garbage collect the test resources.
+    api.delete(&name, &Default::default()).await?;
+    cleanup(client.clone(), &tenant).await?;
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn cluster_provider_namespace_removed() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let (_, tenant) = create_test_namespace(client.clone()).await?;
+    let instance = create_cluster_provider(client.clone(), &uid, &namespace).await?;
+    let name = instance.metadata.name.clone().unwrap();
+    label_namespace(client.clone(), &tenant, Some(&uid)).await?;
+    wait_for_child(client.clone(), &tenant, &name, |_| true).await?;
+
+    // The child and its credentials are deleted once the namespace
+    // is no longer targeted.
+    label_namespace(client.clone(), &tenant, None).await?;
+    wait_for_child_deleted(client.clone(), &tenant, &name).await?;
+    let secrets: Api<Secret> = Api::namespaced(client.clone(), &tenant);
+    assert!(secrets
+        .get_opt(&format!("{}-credentials", name))
+        .await?
+        .is_none());
+
+    // Garbage collect the test resources.
+    let api: Api<ClusterMaskProvider> = Api::all(client.clone());
+    api.delete(&name, &Default::default()).await?;
+    cleanup(client.clone(), &tenant).await?;
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
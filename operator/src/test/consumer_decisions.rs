@@ -0,0 +1,551 @@
+use k8s_openapi::api::core::v1::{ObjectReference, Pod};
+use kube::Resource;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use vpn_types::*;
+
+use super::mock::{consumer_value, decide, merged, mock_cluster, provider_value};
+use crate::{
+    consumers::{
+        optin::OptInLabel,
+        reconcile::{determine_action, ConsumerAction, ContextData},
+    },
+    util::{
+        config::OperatorConfig, messages, CONSUMER_UID_LABEL, CREDENTIALS_HASH_ANNOTATION,
+        MASK_NAME_LABEL, PROVIDER_UID_LABEL,
+    },
+};
+
+/// Returns a MaskConsumer that waits to be assigned a MaskProvider.
+fn unassigned(patch: Value) -> Value {
+    let status =
+        json!({ "status": { "phase": "Waiting", "message": messages::WAITING, "provider": null } });
+    merged(consumer_value(status), patch)
+}
+
+/// Returns the MaskReservation of the MaskConsumer's slot.
+fn reservation(uid: &str) -> Value {
+    json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskReservation",
+        "metadata": {
+            "name": "provider-0",
+            "namespace": "providers",
+            "uid": uid,
+            "labels": { PROVIDER_UID_LABEL: "provider-uid" },
+        },
+        "spec": { "name": "mask-0", "namespace": "default", "uid": "consumer-uid", "slot": 0 },
+    })
+}
+
+/// Returns the MaskConsumer's copy of the credentials, with `patch`
+/// merged into it.
+fn secret(patch: Value) -> Value {
+    let secret = json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {
+            "name": "mask-0-provider-uid",
+            "namespace": "default",
+            "labels": {
+                PROVIDER_UID_LABEL: "provider-uid",
+                MASK_NAME_LABEL: "mask-0",
+                CONSUMER_UID_LABEL: "consumer-uid",
+            },
+            "ownerReferences": [{
+                "apiVersion": "vpn.beebs.dev/v1",
+                "kind": "MaskConsumer",
+                "name": "mask-0",
+                "uid": "consumer-uid",
+            }],
+        },
+        "data": {},
+    });
+    merged(secret, patch)
+}
+
+/// Returns the MaskProvider's credentials with the given data.
+fn provider_secret(data: Value) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": { "name": "provider-credentials", "namespace": "providers" },
+        "data": data,
+    })
+}
+
+/// Returns a running Pod that mounts the MaskConsumer's credentials.
+fn pod() -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Pod",
+        "metadata": { "name": "workload", "namespace": "default", "uid": "pod-uid" },
+        "spec": {
+            "containers": [],
+            "volumes": [{ "name": "vpn", "secret": { "secretName": "mask-0-provider-uid" } }],
+        },
+        "status": { "phase": "Running" },
+    })
+}
+
+/// Returns the reference to the Pod that events are published on.
+fn pod_reference() -> ObjectReference {
+    serde_json::from_value::<Pod>(pod())
+        .unwrap()
+        .object_ref(&())
+}
+
+/// Returns the namespace of the MaskConsumer with the given labels.
+fn namespace(labels: Value) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Namespace",
+        "metadata": { "name": "default", "labels": labels },
+    })
+}
+
+/// Returns the label namespaces opt in to receiving credentials with.
+fn opt_in_label() -> OptInLabel {
+    OptInLabel {
+        key: "vpn.beebs.dev/opt-in".to_owned(),
+        value: "true".to_owned(),
+    }
+}
+
+/// Returns the action decided for the MaskConsumer with the given objects
+/// in the cluster, optionally requiring namespaces to opt in with
+/// [`opt_in_label`] and freezing assignments.
+async fn action(consumer: Value, opt_in: bool, frozen: bool, objects: &[Value]) -> ConsumerAction {
+    let instance: MaskConsumer = serde_json::from_value(consumer).unwrap();
+    let context = ContextData::for_tests(
+        mock_cluster(vec![]).0,
+        opt_in.then(opt_in_label),
+        Arc::new(OperatorConfig::new(frozen)),
+    );
+    decide(objects, |client| {
+        let (instance, context) = (instance.clone(), &context);
+        async move { determine_action(client, "default", &instance, context).await }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn deleted_consumer_actions() {
+    let deleted = |patch: Value| {
+        merged(
+            consumer_value(
+                json!({ "metadata": { "deletionTimestamp": chrono::Utc::now().to_rfc3339() } }),
+            ),
+            patch,
+        )
+    };
+    let lost = messages::err_provider_lost("providers", "provider");
+    let cases = [
+        (
+            "credentials unused",
+            deleted(json!({})),
+            vec![secret(json!({})), provider_value(json!({}))],
+            ConsumerAction::Delete {
+                delete_resource: false,
+                pods: vec![],
+            },
+        ),
+        (
+            "credentials in use",
+            deleted(json!({})),
+            vec![secret(json!({})), provider_value(json!({})), pod()],
+            ConsumerAction::Delete {
+                delete_resource: false,
+                pods: vec![pod_reference()],
+            },
+        ),
+        (
+            "waiting for pods",
+            deleted(json!({ "spec": { "deletionPolicy": "WaitForPods" } })),
+            vec![secret(json!({})), provider_value(json!({})), pod()],
+            ConsumerAction::WaitForPods {
+                pods: vec![pod_reference()],
+                warn: true,
+            },
+        ),
+        (
+            "blocking pods shown",
+            deleted(json!({
+                "spec": { "deletionPolicy": "WaitForPods" },
+                "status": {
+                    "phase": "Terminating",
+                    "message": messages::withdrawal_blocked("mask-0-provider-uid", &["workload".to_owned()]),
+                },
+            })),
+            vec![secret(json!({})), provider_value(json!({})), pod()],
+            ConsumerAction::NoOp,
+        ),
+        (
+            "grace period expired",
+            consumer_value(json!({
+                "metadata": { "deletionTimestamp": "2023-01-01T00:00:00Z" },
+                "spec": { "deletionPolicy": "WaitForPods" },
+            })),
+            vec![secret(json!({})), provider_value(json!({})), pod()],
+            ConsumerAction::Delete {
+                delete_resource: false,
+                pods: vec![pod_reference()],
+            },
+        ),
+        (
+            "provider deletion forced",
+            deleted(json!({})),
+            vec![
+                secret(json!({})),
+                provider_value(
+                    json!({ "metadata": { "deletionTimestamp": "2024-01-01T00:00:00Z" } }),
+                ),
+                pod(),
+            ],
+            ConsumerAction::ProviderLost(lost.clone()),
+        ),
+        (
+            "provider loss shown",
+            deleted(json!({ "status": { "phase": "ErrProviderLost", "message": lost } })),
+            vec![secret(json!({})), pod()],
+            ConsumerAction::Delete {
+                delete_resource: false,
+                pods: vec![pod_reference()],
+            },
+        ),
+    ];
+    for (case, consumer, objects, expected) in cases {
+        assert_eq!(
+            action(consumer, false, false, &objects).await,
+            expected,
+            "{}",
+            case
+        );
+    }
+}
+
+#[tokio::test]
+async fn assignment_actions() {
+    let cases = [
+        (
+            "unassigned",
+            unassigned(json!({})),
+            false,
+            false,
+            vec![],
+            ConsumerAction::Assign,
+        ),
+        (
+            "namespace not opted in",
+            unassigned(json!({})),
+            true,
+            false,
+            vec![namespace(json!({}))],
+            ConsumerAction::NamespaceNotOptedIn(opt_in_label()),
+        ),
+        (
+            "namespace opted in",
+            unassigned(json!({})),
+            true,
+            false,
+            vec![namespace(json!({ "vpn.beebs.dev/opt-in": "true" }))],
+            ConsumerAction::Assign,
+        ),
+        (
+            "assignments frozen",
+            unassigned(json!({})),
+            false,
+            true,
+            vec![],
+            ConsumerAction::AssignmentsFrozen,
+        ),
+        (
+            "freeze shown",
+            unassigned(json!({ "status": { "message": messages::ASSIGNMENTS_FROZEN } })),
+            false,
+            true,
+            vec![],
+            ConsumerAction::NoOp,
+        ),
+        (
+            "frozen after assignment",
+            consumer_value(json!({})),
+            false,
+            true,
+            vec![reservation("reservation-uid"), secret(json!({}))],
+            ConsumerAction::NoOp,
+        ),
+    ];
+    for (case, consumer, opt_in, frozen, objects, expected) in cases {
+        assert_eq!(
+            action(consumer, opt_in, frozen, &objects).await,
+            expected,
+            "{}",
+            case
+        );
+    }
+}
+
+#[tokio::test]
+async fn consumer_actions() {
+    let stale = (chrono::Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+    let fresh = (chrono::Utc::now() - chrono::Duration::minutes(5)).to_rfc3339();
+    let expired = (chrono::Utc::now() - chrono::Duration::minutes(11)).to_rfc3339();
+    let foreign = messages::err_secret_conflict(
+        "default",
+        "mask-0-provider-uid",
+        "was not created by vpn-operator",
+    );
+    let lost = messages::err_provider_lost("providers", "provider");
+    let unprojectable = "spec.fileProjection: source key 'OPENVPN_PASSWORD' of file 'password.txt' is not among the copied credentials";
+    let projecting = |patch: Value| {
+        let status = json!({ "status": {
+            "phase": "ErrInvalidSpec",
+            "message": unprojectable,
+            "effectiveSettings": { "fileProjection": { "password.txt": "OPENVPN_PASSWORD" } },
+        } });
+        merged(consumer_value(status), patch)
+    };
+    let cases = [
+        (
+            "missing finalizer",
+            consumer_value(json!({ "metadata": { "finalizers": null } })),
+            vec![reservation("reservation-uid"), secret(json!({}))],
+            ConsumerAction::Pending,
+        ),
+        (
+            "missing status",
+            consumer_value(json!({ "status": null })),
+            vec![reservation("reservation-uid"), secret(json!({}))],
+            ConsumerAction::Pending,
+        ),
+        (
+            "reservation missing",
+            consumer_value(json!({})),
+            vec![secret(json!({})), provider_value(json!({}))],
+            ConsumerAction::Delete {
+                delete_resource: true,
+                pods: vec![],
+            },
+        ),
+        (
+            "slot reserved again",
+            consumer_value(json!({})),
+            vec![
+                reservation("other-uid"),
+                secret(json!({})),
+                provider_value(json!({})),
+                pod(),
+            ],
+            ConsumerAction::Delete {
+                delete_resource: true,
+                pods: vec![pod_reference()],
+            },
+        ),
+        (
+            "secret missing",
+            consumer_value(json!({})),
+            vec![reservation("reservation-uid"), provider_value(json!({}))],
+            ConsumerAction::CreateSecret,
+        ),
+        (
+            "provider being deleted",
+            consumer_value(json!({})),
+            vec![
+                reservation("reservation-uid"),
+                provider_value(
+                    json!({ "metadata": { "deletionTimestamp": "2024-01-01T00:00:00Z" } }),
+                ),
+                pod(),
+            ],
+            ConsumerAction::ProviderLost(lost.clone()),
+        ),
+        (
+            "provider replaced",
+            consumer_value(json!({})),
+            vec![
+                reservation("reservation-uid"),
+                provider_value(json!({ "metadata": { "uid": "other-uid" } })),
+            ],
+            ConsumerAction::ProviderLost(lost.clone()),
+        ),
+        (
+            "provider gone",
+            consumer_value(json!({})),
+            vec![reservation("reservation-uid")],
+            ConsumerAction::ProviderLost(lost.clone()),
+        ),
+        (
+            "provider gone with its slots",
+            consumer_value(json!({})),
+            vec![secret(json!({}))],
+            ConsumerAction::ProviderLost(lost.clone()),
+        ),
+        (
+            "provider loss shown",
+            consumer_value(json!({ "status": { "phase": "ErrProviderLost", "message": lost } })),
+            vec![secret(json!({})), pod()],
+            ConsumerAction::Delete {
+                delete_resource: true,
+                pods: vec![pod_reference()],
+            },
+        ),
+        (
+            "provider replaced after copying",
+            consumer_value(json!({})),
+            vec![
+                reservation("reservation-uid"),
+                secret(json!({})),
+                provider_value(json!({ "metadata": { "uid": "other-uid" } })),
+                pod(),
+            ],
+            ConsumerAction::ProviderLost(lost.clone()),
+        ),
+        (
+            "secret in the way",
+            consumer_value(json!({})),
+            vec![
+                reservation("reservation-uid"),
+                secret(json!({ "metadata": { "labels": null, "ownerReferences": null } })),
+            ],
+            ConsumerAction::SecretConflict(foreign.clone()),
+        ),
+        (
+            "conflict shown",
+            consumer_value(
+                json!({ "status": { "phase": "ErrSecretConflict", "message": foreign } }),
+            ),
+            vec![
+                reservation("reservation-uid"),
+                secret(json!({ "metadata": { "labels": null, "ownerReferences": null } })),
+            ],
+            ConsumerAction::NoOp,
+        ),
+        (
+            "projection invalid",
+            projecting(json!({})),
+            vec![
+                reservation("reservation-uid"),
+                provider_value(json!({})),
+                provider_secret(json!({ "OPENVPN_USER": "dXNlcg==" })),
+            ],
+            ConsumerAction::NoOp,
+        ),
+        (
+            "projection invalid past window",
+            projecting(json!({ "status": { "lastUpdated": expired } })),
+            vec![
+                reservation("reservation-uid"),
+                provider_value(json!({})),
+                provider_secret(json!({ "OPENVPN_USER": "dXNlcg==" })),
+            ],
+            ConsumerAction::InvalidSpec(unprojectable.to_owned()),
+        ),
+        (
+            "projection allowed by credentials",
+            projecting(json!({})),
+            vec![
+                reservation("reservation-uid"),
+                provider_value(json!({})),
+                provider_secret(json!({ "OPENVPN_PASSWORD": "cGFzcw==" })),
+            ],
+            ConsumerAction::CreateSecret,
+        ),
+        (
+            "credentials rewritten",
+            consumer_value(json!({})),
+            vec![
+                reservation("reservation-uid"),
+                secret(
+                    json!({ "metadata": { "annotations": { CREDENTIALS_HASH_ANNOTATION: "hash" } } }),
+                ),
+            ],
+            ConsumerAction::RecordSecretHash("hash".to_owned()),
+        ),
+        (
+            "secret copied before labeling",
+            consumer_value(json!({})),
+            vec![
+                reservation("reservation-uid"),
+                secret(json!({ "metadata": { "labels": {
+                    MASK_NAME_LABEL: null,
+                    CONSUMER_UID_LABEL: null,
+                } } })),
+            ],
+            ConsumerAction::LabelSecret(
+                [
+                    (MASK_NAME_LABEL.to_owned(), "mask-0".to_owned()),
+                    (CONSUMER_UID_LABEL.to_owned(), "consumer-uid".to_owned()),
+                ]
+                .into(),
+            ),
+        ),
+        (
+            "encryption annotation removed",
+            consumer_value(json!({ "status": { "provider": {
+                "copyEncryption": { "annotationKey": "kms.example.com/encrypted" },
+            } } })),
+            vec![reservation("reservation-uid"), secret(json!({}))],
+            ConsumerAction::AnnotateSecret(
+                [("kms.example.com/encrypted".to_owned(), "true".to_owned())].into(),
+            ),
+        ),
+        (
+            "namespace not allowed",
+            consumer_value(json!({})),
+            vec![
+                reservation("reservation-uid"),
+                secret(json!({})),
+                provider_value(json!({
+                    "spec": { "namespaces": ["other"] },
+                    // The copy isn't stale, as no checksum is recorded.
+                    "status": { "secretHash": null },
+                })),
+            ],
+            ConsumerAction::PolicyViolation(messages::policy_violation(
+                "default",
+                "providers",
+                "provider",
+            )),
+        ),
+        (
+            "pending",
+            consumer_value(json!({ "status": { "phase": "Pending" } })),
+            vec![reservation("reservation-uid"), secret(json!({}))],
+            ConsumerAction::Active,
+        ),
+        (
+            "stale status",
+            consumer_value(json!({ "status": { "lastUpdated": stale } })),
+            vec![reservation("reservation-uid"), secret(json!({}))],
+            ConsumerAction::Active,
+        ),
+        (
+            // Older than the probe interval, but within the freshness window.
+            "unchanged within window",
+            consumer_value(json!({ "status": { "lastUpdated": fresh } })),
+            vec![reservation("reservation-uid"), secret(json!({}))],
+            ConsumerAction::NoOp,
+        ),
+        (
+            "unchanged past window",
+            consumer_value(json!({ "status": { "lastUpdated": expired } })),
+            vec![reservation("reservation-uid"), secret(json!({}))],
+            ConsumerAction::Active,
+        ),
+        (
+            "active",
+            consumer_value(json!({})),
+            vec![reservation("reservation-uid"), secret(json!({}))],
+            ConsumerAction::NoOp,
+        ),
+    ];
+    for (case, consumer, objects, expected) in cases {
+        assert_eq!(
+            action(consumer, false, false, &objects).await,
+            expected,
+            "{}",
+            case
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use futures::{StreamExt, TryStreamExt};
+use kube::{
+    api::{ListParams, WatchEvent},
+    client::Client,
+    Api,
+};
+use serde_json::json;
+use std::collections::BTreeMap;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::util::*;
+use crate::{
+    consumers::actions::reservation_marks,
+    masks::util::get_purpose,
+    util::{consumer_purpose, verified_provider_uid, VERIFICATION_LABEL},
+};
+
+/// Returns the labels of a resource created to verify the MaskProvider.
+fn verification_labels() -> BTreeMap<String, String> {
+    BTreeMap::from([(VERIFICATION_LABEL.to_owned(), "provider-uid".to_owned())])
+}
+
+/// Returns a MaskConsumer with the given purpose and labels.
+fn purposed_consumer(
+    purpose: Option<MaskConsumerPurpose>,
+    labels: Option<BTreeMap<String, String>>,
+) -> MaskConsumer {
+    let mut consumer = mask_consumer("my-mask", "default", "consumer-uid");
+    consumer.metadata.labels = labels;
+    consumer.spec.purpose = purpose;
+    consumer
+}
+
+#[test]
+fn legacy_consumer_deserialized() {
+    // MaskConsumers created before the field existed don't have it.
+    let legacy = |labels: serde_json::Value| -> MaskConsumer {
+        serde_json::from_value(json!({
+            "apiVersion": "vpn.beebs.dev/v1",
+            "kind": "MaskConsumer",
+            "metadata": { "name": "my-mask", "labels": labels },
+            "spec": { "providers": ["default"] },
+        }))
+        .unwrap()
+    };
+    let workload = legacy(json!({}));
+    assert_eq!(workload.spec.purpose, None);
+    assert_eq!(consumer_purpose(&workload), MaskConsumerPurpose::Workload);
+    assert_eq!(verified_provider_uid(&workload), None);
+
+    // Legacy verification MaskConsumers are recognized by their label.
+    let verification = legacy(json!({ VERIFICATION_LABEL: "provider-uid" }));
+    assert_eq!(verification.spec.purpose, None);
+    assert_eq!(
+        consumer_purpose(&verification),
+        MaskConsumerPurpose::Verification
+    );
+    assert_eq!(verified_provider_uid(&verification), Some("provider-uid"));
+}
+
+#[test]
+fn purpose_serialized() {
+    let spec = MaskConsumerSpec {
+        purpose: Some(MaskConsumerPurpose::Verification),
+        ..Default::default()
+    };
+    let value = serde_json::to_value(&spec).unwrap();
+    assert_eq!(value["purpose"], "Verification");
+    let spec: MaskConsumerSpec = serde_json::from_value(value).unwrap();
+    assert_eq!(spec.purpose, Some(MaskConsumerPurpose::Verification));
+    assert_eq!(
+        MaskConsumerPurpose::default(),
+        MaskConsumerPurpose::Workload
+    );
+}
+
+#[test]
+fn typed_purpose_preferred() {
+    // A workload MaskConsumer isn't force assigned because of a stray label.
+    let workload = purposed_consumer(
+        Some(MaskConsumerPurpose::Workload),
+        Some(verification_labels()),
+    );
+    assert_eq!(consumer_purpose(&workload), MaskConsumerPurpose::Workload);
+    assert_eq!(verified_provider_uid(&workload), None);
+    // Nor is its reservation excluded from slot accounting.
+    assert!(reservation_marks(&workload).is_empty());
+
+    let verification = purposed_consumer(
+        Some(MaskConsumerPurpose::Verification),
+        Some(verification_labels()),
+    );
+    assert_eq!(verified_provider_uid(&verification), Some("provider-uid"));
+    assert_eq!(reservation_marks(&verification), verification_labels());
+}
+
+#[test]
+fn purpose_inherited_from_mask() {
+    let mut mask = get_test_mask("default", 0, "default");
+    assert_eq!(get_purpose(&mask), MaskConsumerPurpose::Workload);
+    // Verification Masks are labeled by the MaskProviders controller.
+    mask.metadata.labels = Some(verification_labels());
+    assert_eq!(get_purpose(&mask), MaskConsumerPurpose::Verification);
+}
+
+/// Waits for the MaskConsumer that verifies the test MaskProvider.
+async fn wait_for_verification_consumer(
+    client: Client,
+    namespace: &str,
+) -> Result<MaskConsumer, Error> {
+    let api: Api<MaskConsumer> = Api::namespaced(client, namespace);
+    let lp = ListParams::default()
+        .labels(VERIFICATION_LABEL)
+        .timeout(120);
+    let mut stream = api.watch(&lp, "0").await?.boxed();
+    while let Some(event) = stream.try_next().await? {
+        if let WatchEvent::Added(consumer) = event {
+            return Ok(consumer);
+        }
+    }
+    Err(Error::Other(
+        "Verification MaskConsumer not created before timeout".to_owned(),
+    ))
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn consumer_purpose_set() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+
+    // Verify the MaskProvider with the fake harness, so the
+    // outcome doesn't depend on the credentials.
+    let mut provider = get_test_provider(client.clone(), &provider_name, &namespace).await?;
+    let verify = provider.spec.verify.as_mut().unwrap();
+    verify.skip = Some(false);
+    verify.overrides = Some(fake_verify_overrides());
+    let verification = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(async move { wait_for_verification_consumer(client, &namespace).await })
+    };
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let provider = api.create(&Default::default(), &provider).await?;
+    create_test_provider_secret(client.clone(), &namespace, &provider).await?;
+    let consumer = verification.await.unwrap()?;
+    assert_eq!(
+        consumer.spec.purpose,
+        Some(MaskConsumerPurpose::Verification)
+    );
+    wait_for_provider_phase(client.clone(), &namespace, MaskProviderPhase::Ready).await?;
+
+    // The MaskConsumers of ordinary Masks serve a workload.
+    create_test_mask(client.clone(), &namespace, 0, &provider_name).await?;
+    wait_for_provider_assignment(client.clone(), &namespace, 0).await?;
+    let mc_api: Api<MaskConsumer> = Api::namespaced(client.clone(), &namespace);
+    let consumer = mc_api.get(&format!("{}-0", MASK_NAME)).await?;
+    assert_eq!(consumer.spec.purpose, Some(MaskConsumerPurpose::Workload));
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,321 @@
+use k8s_openapi::{api::core::v1::Secret, ByteString};
+use kube::{api::ObjectMeta, client::Client};
+use serde_json::json;
+use std::collections::BTreeMap;
+use vpn_types::*;
+
+use super::{mock::*, util::*};
+use crate::{
+    consumers::actions::{
+        annotate_secret, consumer_secret, create_secret, missing_secret_annotations,
+        validate_copy_encryption,
+    },
+    providers::reconcile::{determine_action, MaskProviderAction},
+    util::{messages, CREDENTIALS_HASH_ANNOTATION, PROVIDER_SECRET_LABEL, PROVIDER_UID_LABEL},
+};
+
+/// Name of the MaskConsumer's credentials copy.
+const SECRET_NAME: &str = "test-mask-provider-uid";
+
+/// Key of the annotation that engages the external encryption.
+const ENCRYPTED: &str = "kms.example.com/encrypted";
+
+/// Returns a MaskConsumer assigned a slot with a MaskProvider that
+/// configured the given copy encryption.
+fn encrypting_consumer(copy_encryption: Option<CopyEncryptionSpec>) -> MaskConsumer {
+    assigned(
+        mask_consumer("test-mask", "team", "consumer-uid"),
+        Some(MaskConsumerPhase::Active),
+        AssignedProvider {
+            copy_encryption,
+            ..assigned_provider("test-provider", "default", "provider-uid", SECRET_NAME)
+        },
+    )
+}
+
+/// Returns the copy encryption with the annotation's default value.
+fn encryption() -> Option<CopyEncryptionSpec> {
+    Some(CopyEncryptionSpec {
+        annotation_key: ENCRYPTED.to_owned(),
+        annotation_value: None,
+    })
+}
+
+/// Returns the MaskProvider's credentials.
+fn provider_secret() -> Secret {
+    Secret {
+        metadata: ObjectMeta {
+            name: Some("test-secret".to_owned()),
+            namespace: Some("default".to_owned()),
+            ..Default::default()
+        },
+        data: Some(BTreeMap::from([(
+            "VPN_SERVICE_PROVIDER".to_owned(),
+            ByteString(b"nordvpn".to_vec()),
+        )])),
+        ..Default::default()
+    }
+}
+
+/// Returns a client whose API server holds the MaskProvider and its
+/// credentials, and answers the creation of the copy with `create`.
+/// `existing` is returned for the copy when it already exists.
+fn mock_copy(create: u16, existing: &Secret) -> (Client, Captured) {
+    let provider = MaskProvider {
+        metadata: ObjectMeta {
+            name: Some("test-provider".to_owned()),
+            namespace: Some("default".to_owned()),
+            uid: Some("provider-uid".to_owned()),
+            ..Default::default()
+        },
+        spec: MaskProviderSpec {
+            secret: "test-secret".to_owned(),
+            max_slots: 1,
+            copy_encryption: encryption(),
+            ..Default::default()
+        },
+        status: None,
+    };
+    let existing = serde_json::to_value(existing).unwrap();
+    mock_method_routes(vec![
+        (
+            "GET",
+            "/apis/vpn.beebs.dev/v1/namespaces/default/maskproviders/test-provider",
+            200,
+            serde_json::to_value(&provider).unwrap(),
+        ),
+        (
+            "GET",
+            "/api/v1/namespaces/default/secrets/test-secret",
+            200,
+            serde_json::to_value(provider_secret()).unwrap(),
+        ),
+        (
+            "POST",
+            "/api/v1/namespaces/team/secrets",
+            create,
+            match create {
+                409 => status_failure(409),
+                _ => existing.clone(),
+            },
+        ),
+        (
+            "GET",
+            "/api/v1/namespaces/team/secrets/",
+            200,
+            existing.clone(),
+        ),
+        (
+            "PUT",
+            "/api/v1/namespaces/team/secrets/",
+            200,
+            existing.clone(),
+        ),
+        ("DELETE", "/api/v1/namespaces/team/secrets/", 200, existing),
+    ])
+}
+
+/// Returns a copy the operator left behind without the annotation.
+fn leftover(immutable: bool) -> Secret {
+    Secret {
+        metadata: ObjectMeta {
+            name: Some(SECRET_NAME.to_owned()),
+            namespace: Some("team".to_owned()),
+            uid: Some("secret-uid".to_owned()),
+            resource_version: Some("1".to_owned()),
+            labels: Some(BTreeMap::from([(
+                PROVIDER_UID_LABEL.to_owned(),
+                "provider-uid".to_owned(),
+            )])),
+            ..Default::default()
+        },
+        data: Some(BTreeMap::from([(
+            "VPN_SERVICE_PROVIDER".to_owned(),
+            ByteString(b"stale".to_vec()),
+        )])),
+        immutable: immutable.then_some(true),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn copies_are_annotated() {
+    // Without copy encryption, only the operator's annotations are set.
+    let secret = consumer_secret("team", &encrypting_consumer(None), provider_secret()).unwrap();
+    let annotations = secret.metadata.annotations.unwrap();
+    assert!(!annotations.contains_key(ENCRYPTED));
+
+    let instance = encrypting_consumer(encryption());
+    let secret = consumer_secret("team", &instance, provider_secret()).unwrap();
+    let annotations = secret.metadata.annotations.as_ref().unwrap();
+    assert_eq!(annotations[ENCRYPTED], "true");
+    assert!(annotations.contains_key(CREDENTIALS_HASH_ANNOTATION));
+    assert!(missing_secret_annotations(&secret, &instance).is_empty());
+
+    // A custom value is used as it is.
+    let instance = encrypting_consumer(Some(CopyEncryptionSpec {
+        annotation_key: ENCRYPTED.to_owned(),
+        annotation_value: Some("kms-key-1".to_owned()),
+    }));
+    let secret = consumer_secret("team", &instance, provider_secret()).unwrap();
+    assert_eq!(secret.metadata.annotations.unwrap()[ENCRYPTED], "kms-key-1");
+
+    // The operator's own annotations can't be overridden.
+    let instance = encrypting_consumer(Some(CopyEncryptionSpec {
+        annotation_key: CREDENTIALS_HASH_ANNOTATION.to_owned(),
+        annotation_value: None,
+    }));
+    let secret = consumer_secret("team", &instance, provider_secret()).unwrap();
+    assert_ne!(
+        secret.metadata.annotations.unwrap()[CREDENTIALS_HASH_ANNOTATION],
+        "true"
+    );
+}
+
+#[tokio::test]
+async fn adopted_copies_are_annotated() {
+    let (client, captured) = mock_copy(409, &leftover(false));
+    create_secret(client, "team", &encrypting_consumer(encryption()))
+        .await
+        .unwrap();
+
+    // The leftover copy is replaced with one that carries the annotation.
+    let captured = captured.lock().unwrap();
+    let replace = captured
+        .iter()
+        .find(|r| r.method == "PUT")
+        .expect("Secret was not replaced");
+    assert_eq!(replace.body["metadata"]["annotations"][ENCRYPTED], "true");
+}
+
+#[tokio::test]
+async fn recreated_copies_are_annotated() {
+    // Immutable copies with stale data are deleted first...
+    let (client, captured) = mock_copy(409, &leftover(true));
+    create_secret(client, "team", &encrypting_consumer(encryption()))
+        .await
+        .unwrap();
+    assert!(captured
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|r| r.method == "DELETE"));
+
+    // ...and created again with the annotation on the next reconciliation.
+    let (client, captured) = mock_copy(201, &leftover(true));
+    create_secret(client, "team", &encrypting_consumer(encryption()))
+        .await
+        .unwrap();
+    let captured = captured.lock().unwrap();
+    let create = captured
+        .iter()
+        .find(|r| r.method == "POST")
+        .expect("Secret was not created");
+    assert_eq!(create.body["metadata"]["annotations"][ENCRYPTED], "true");
+}
+
+#[tokio::test]
+async fn removed_annotation_restored() {
+    let instance = encrypting_consumer(encryption());
+    let mut secret = consumer_secret("team", &instance, provider_secret()).unwrap();
+    secret
+        .metadata
+        .annotations
+        .as_mut()
+        .unwrap()
+        .remove(ENCRYPTED);
+    let missing = missing_secret_annotations(&secret, &instance);
+    assert_eq!(
+        missing,
+        BTreeMap::from([(ENCRYPTED.to_owned(), "true".to_owned())])
+    );
+
+    // Only the missing annotation is merged in.
+    let (client, captured) = mock_client(json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": { "name": SECRET_NAME, "namespace": "team" },
+    }));
+    annotate_secret(client, "team", SECRET_NAME, missing)
+        .await
+        .unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 1);
+    assert_eq!(captured[0].method, "PATCH");
+    assert_eq!(
+        captured[0].body,
+        json!({ "metadata": { "annotations": { ENCRYPTED: "true" } } })
+    );
+}
+
+/// Returns the MaskProvider with the copy encryption annotation key.
+fn encrypting_provider(annotation_key: &str) -> MaskProvider {
+    serde_json::from_value(provider_value(json!({
+        "spec": { "copyEncryption": { "annotationKey": annotation_key } },
+    })))
+    .unwrap()
+}
+
+#[test]
+fn annotation_keys_validated() {
+    for key in [ENCRYPTED, "encrypted", "kms.example.com/encrypted.v1"] {
+        assert_eq!(validate_copy_encryption(&encrypting_provider(key)), Ok(()));
+    }
+    for key in [
+        "",
+        "kms.example.com/",
+        "/encrypted",
+        "-encrypted",
+        "kms.example.com/encrypted?",
+        "Kms.Example.Com/encrypted",
+        "kms.example.com/a/b",
+    ] {
+        let error = validate_copy_encryption(&encrypting_provider(key)).unwrap_err();
+        assert!(error.contains("not a valid annotation key"), "{}", key);
+    }
+
+    // The operator's own annotations can't be set or overwritten.
+    let error =
+        validate_copy_encryption(&encrypting_provider(CREDENTIALS_HASH_ANNOTATION)).unwrap_err();
+    assert!(error.contains("reserved for the operator"));
+}
+
+#[tokio::test]
+async fn reserved_annotation_key_is_config_error() {
+    let instance = encrypting_provider("vpn.beebs.dev/encrypted");
+    let secret = json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {
+            "name": "provider-credentials",
+            "namespace": "providers",
+            "labels": { PROVIDER_SECRET_LABEL: "true" },
+        },
+        "data": {},
+    });
+    let action = decide(&[secret], |client| {
+        let instance = instance.clone();
+        async move {
+            determine_action(
+                client,
+                "provider",
+                "providers",
+                &instance,
+                None,
+                None,
+                &Default::default(),
+                &Default::default(),
+                &[],
+                &[],
+                chrono::Utc::now(),
+            )
+            .await
+        }
+    })
+    .await;
+    let error = validate_copy_encryption(&instance).unwrap_err();
+    assert_eq!(
+        action,
+        MaskProviderAction::ConfigError(messages::config_error(&error))
+    );
+}
@@ -0,0 +1,406 @@
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use k8s_openapi::api::core::v1::{
+    Container, Pod, PodSpec, PodStatus, SecretVolumeSource, Volume, VolumeMount,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use kube::{api::ObjectMeta, client::Client, Api};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::Duration,
+};
+use vpn_types::*;
+
+use super::{mock::*, util::*};
+use crate::{
+    consumers::{
+        actions::record_consumers,
+        audit::{merge_records, service_account, PodUsage, MAX_RECORDS, RECORD_RETENTION},
+    },
+    providers::assignments::{recent_assignments, MAX_RECENT_ASSIGNMENTS},
+    util::{clock::format_timestamp, VERIFICATION_LABEL},
+};
+
+/// The MaskProvider whose credentials are used, as `namespace/name`.
+const PROVIDER: &str = "vpn/my-provider";
+
+/// Name of the MaskConsumer's credentials copy.
+const SECRET_NAME: &str = "my-mask-provider-uid";
+
+/// How often a record's `lastSeen` is moved forward.
+const RESOLUTION: Duration = Duration::from_secs(600);
+
+/// Returns the time the given number of minutes before `now`.
+fn ago(now: DateTime<Utc>, minutes: i64) -> String {
+    (now - ChronoDuration::minutes(minutes)).to_rfc3339()
+}
+
+/// Returns a record of the ServiceAccount using PROVIDER's credentials.
+fn record(service_account: &str, first_seen: String, last_seen: String) -> ConsumerRecord {
+    ConsumerRecord {
+        service_account: service_account.to_owned(),
+        provider: PROVIDER.to_owned(),
+        first_seen,
+        last_seen,
+    }
+}
+
+/// Returns the set of ServiceAccounts.
+fn accounts(names: &[&str]) -> BTreeSet<String> {
+    names.iter().map(|n| n.to_string()).collect()
+}
+
+/// Returns a Pod running as the ServiceAccount that mounts the Secret.
+fn pod(name: &str, service_account: Option<&str>, secret: &str) -> Pod {
+    Pod {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            ..Default::default()
+        },
+        spec: Some(PodSpec {
+            service_account_name: service_account.map(|s| s.to_owned()),
+            containers: vec![Container {
+                name: "app".to_owned(),
+                image: Some("busybox".to_owned()),
+                command: Some(vec!["sleep".to_owned(), "3600".to_owned()]),
+                volume_mounts: Some(vec![VolumeMount {
+                    name: "vpn".to_owned(),
+                    mount_path: "/vpn".to_owned(),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }],
+            volumes: Some(vec![Volume {
+                name: "vpn".to_owned(),
+                secret: Some(SecretVolumeSource {
+                    secret_name: Some(secret.to_owned()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn new_service_accounts_recorded() {
+    let now = Utc::now();
+    let records = merge_records(
+        &[],
+        &accounts(&["scraper", "worker"]),
+        PROVIDER,
+        now,
+        RESOLUTION,
+        RECORD_RETENTION,
+    );
+    assert_eq!(
+        records,
+        vec![
+            record("scraper", format_timestamp(now), format_timestamp(now)),
+            record("worker", format_timestamp(now), format_timestamp(now)),
+        ]
+    );
+    // Merging the same Pods again right away changes nothing.
+    assert_eq!(
+        merge_records(
+            &records,
+            &accounts(&["scraper", "worker"]),
+            PROVIDER,
+            now + ChronoDuration::seconds(30),
+            RESOLUTION,
+            RECORD_RETENTION,
+        ),
+        records
+    );
+}
+
+#[test]
+fn last_seen_refreshed_after_resolution() {
+    let now = Utc::now();
+    let records = vec![record("scraper", ago(now, 60), ago(now, 11))];
+    let merged = merge_records(
+        &records,
+        &accounts(&["scraper"]),
+        PROVIDER,
+        now,
+        RESOLUTION,
+        RECORD_RETENTION,
+    );
+    assert_eq!(
+        merged,
+        vec![record("scraper", ago(now, 60), format_timestamp(now))]
+    );
+
+    // The same ServiceAccount using another MaskProvider's credentials
+    // is recorded separately.
+    let merged = merge_records(
+        &records,
+        &accounts(&["scraper"]),
+        "vpn/other-provider",
+        now,
+        RESOLUTION,
+        RECORD_RETENTION,
+    );
+    assert_eq!(merged.len(), 2);
+    assert_eq!(merged[0], records[0]);
+    assert_eq!(merged[1].provider, "vpn/other-provider");
+}
+
+#[test]
+fn gone_pods_pruned_after_retention() {
+    let now = Utc::now();
+    let retention = Duration::from_secs(3600);
+    let records = vec![
+        record("expired", ago(now, 300), ago(now, 61)),
+        record("recent", ago(now, 300), ago(now, 59)),
+        record("malformed", "yesterday".to_owned(), "yesterday".to_owned()),
+    ];
+    // Records of Pods that are gone are kept until the retention window
+    // has passed, and unreadable ones are pruned right away.
+    let merged = merge_records(
+        &records,
+        &accounts(&[]),
+        PROVIDER,
+        now,
+        RESOLUTION,
+        retention,
+    );
+    assert_eq!(merged, vec![records[1].clone()]);
+}
+
+#[test]
+fn records_capped_oldest_first() {
+    let now = Utc::now();
+    let records: Vec<ConsumerRecord> = (0..MAX_RECORDS)
+        .map(|i| record(&format!("sa-{}", i), ago(now, 5), ago(now, 5)))
+        .collect();
+    let merged = merge_records(
+        &records,
+        &accounts(&["newcomer"]),
+        PROVIDER,
+        now,
+        RESOLUTION,
+        RECORD_RETENTION,
+    );
+    assert_eq!(merged.len(), MAX_RECORDS);
+    assert_eq!(merged[0].service_account, "sa-1");
+    assert_eq!(merged[MAX_RECORDS - 1].service_account, "newcomer");
+}
+
+#[test]
+fn default_service_account() {
+    let spec = |name: Option<&str>| PodSpec {
+        service_account_name: name.map(|n| n.to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(service_account(&spec(None)), "default");
+    assert_eq!(service_account(&spec(Some(""))), "default");
+    assert_eq!(service_account(&spec(Some("scraper"))), "scraper");
+}
+
+#[tokio::test]
+async fn pod_usage_cached() {
+    let mut finished = pod("finished", Some("batch"), SECRET_NAME);
+    finished.status = Some(PodStatus {
+        phase: Some("Succeeded".to_owned()),
+        ..Default::default()
+    });
+    let (client, captured) = mock_routes(vec![(
+        "/api/v1/namespaces/team/pods",
+        json!({
+            "apiVersion": "v1",
+            "kind": "PodList",
+            "metadata": {},
+            "items": [
+                pod("a", Some("scraper"), SECRET_NAME),
+                pod("b", Some("scraper"), SECRET_NAME),
+                pod("c", None, SECRET_NAME),
+                pod("unrelated", Some("other"), "other-secret"),
+                finished,
+            ],
+        }),
+    )]);
+    let usage = PodUsage::new(Duration::from_secs(60));
+    for _ in 0..2 {
+        let found = usage
+            .service_accounts(client.clone(), "team", SECRET_NAME)
+            .await
+            .unwrap();
+        assert_eq!(found, accounts(&["default", "scraper"]));
+    }
+    // The namespace's Pods are only listed once per TTL.
+    assert_eq!(captured.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn records_written_to_status() {
+    let now = Utc::now();
+    let consumer = MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some("my-mask".to_owned()),
+            namespace: Some("team".to_owned()),
+            ..Default::default()
+        },
+        status: Some(Default::default()),
+        ..Default::default()
+    };
+    let records = vec![record("scraper", now.to_rfc3339(), now.to_rfc3339())];
+    let (client, captured) = mock_client(json!(consumer));
+    record_consumers(client, &consumer, records.clone())
+        .await
+        .unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(
+        patch_op(captured.last().unwrap(), "/status/consumers"),
+        Some(&json!(records))
+    );
+}
+
+/// Returns a MaskReservation of the slot for the MaskConsumer with the
+/// uid, made the given number of minutes before `now`.
+fn reservation(slot: usize, uid: &str, now: DateTime<Utc>, minutes: i64) -> MaskReservation {
+    MaskReservation {
+        metadata: ObjectMeta {
+            name: Some(format!("my-provider-{}", slot)),
+            namespace: Some("vpn".to_owned()),
+            creation_timestamp: Some(Time(now - ChronoDuration::minutes(minutes))),
+            ..Default::default()
+        },
+        spec: MaskReservationSpec {
+            name: format!("mask-{}", slot),
+            namespace: "team".to_owned(),
+            uid: uid.to_owned(),
+            slot: Some(slot),
+        },
+        status: None,
+    }
+}
+
+#[test]
+fn records_mirrored_onto_provider() {
+    let now = Utc::now();
+    let provider = mask_provider("my-provider", "vpn", "provider-uid");
+    let mut consumer = mask_consumer("mask-0", "team", "consumer-uid");
+    consumer.status = Some(MaskConsumerStatus {
+        consumers: Some(vec![
+            record("scraper", ago(now, 90), ago(now, 30)),
+            record("crawler", ago(now, 60), ago(now, 5)),
+            // Records of other MaskProviders aren't counted.
+            ConsumerRecord {
+                provider: "vpn/other".to_owned(),
+                ..record("indexer", ago(now, 60), ago(now, 1))
+            },
+        ]),
+        ..Default::default()
+    });
+    let mut verification = reservation(2, "verify-uid", now, 0);
+    verification.metadata.labels = Some(BTreeMap::from([(
+        VERIFICATION_LABEL.to_owned(),
+        "provider-uid".to_owned(),
+    )]));
+    let reservations = vec![
+        reservation(0, "consumer-uid", now, 120),
+        reservation(1, "unused-uid", now, 10),
+        verification,
+    ];
+    let recent = recent_assignments(&provider, &reservations, &[Arc::new(consumer)]);
+
+    // Newest first, without the verification's reservation.
+    assert_eq!(
+        recent,
+        vec![
+            RecentAssignment {
+                namespace: "team".to_owned(),
+                name: "mask-1".to_owned(),
+                slot: 1,
+                assigned_at: Some(format_timestamp(now - ChronoDuration::minutes(10))),
+                service_accounts: 0,
+                last_seen: None,
+            },
+            RecentAssignment {
+                namespace: "team".to_owned(),
+                name: "mask-0".to_owned(),
+                slot: 0,
+                assigned_at: Some(format_timestamp(now - ChronoDuration::minutes(120))),
+                service_accounts: 2,
+                last_seen: Some(ago(now, 5)),
+            },
+        ]
+    );
+
+    // Only the most recent assignments are kept.
+    let reservations: Vec<MaskReservation> = (0..MAX_RECENT_ASSIGNMENTS + 2)
+        .map(|slot| reservation(slot, "unused-uid", now, slot as i64))
+        .collect();
+    let recent = recent_assignments(&provider, &reservations, &[]);
+    assert_eq!(recent.len(), MAX_RECENT_ASSIGNMENTS);
+    assert_eq!(recent[0].slot, 0);
+    assert_eq!(recent.last().unwrap().slot, MAX_RECENT_ASSIGNMENTS - 1);
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn pod_mounting_secret_recorded() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+    create_test_provider(client.clone(), &namespace, &uid).await?;
+    wait_for_provider_phase(client.clone(), &namespace, MaskProviderPhase::Ready).await?;
+    create_test_mask(client.clone(), &namespace, 0, &provider_name).await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+    let name = format!("{}-0", MASK_NAME);
+    let consumer_api: Api<MaskConsumer> = Api::namespaced(client.clone(), &namespace);
+    let secret = consumer_api
+        .get(&name)
+        .await?
+        .status
+        .unwrap()
+        .provider
+        .unwrap()
+        .secret;
+
+    // A dummy Pod mounting the credentials is recorded under
+    // the ServiceAccount it runs as.
+    Api::<Pod>::namespaced(client.clone(), &namespace)
+        .create(&Default::default(), &pod("secret-user", None, &secret))
+        .await?;
+    let mut records = None;
+    for _ in 0..60 {
+        records = consumer_api.get(&name).await?.status.unwrap().consumers;
+        if records.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    let records = records.expect("Pod using the credentials was not recorded");
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].service_account, "default");
+    assert_eq!(
+        records[0].provider,
+        format!("{}/{}", namespace, provider_name)
+    );
+
+    // The MaskProvider mirrors the count with its next status refresh.
+    let provider_api: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let mut mirrored = 0;
+    for _ in 0..60 {
+        let provider = provider_api.get(&provider_name).await?;
+        mirrored = provider
+            .status
+            .and_then(|s| s.recent_assignments)
+            .and_then(|r| r.into_iter().find(|a| a.name == name))
+            .map_or(0, |a| a.service_accounts);
+        if mirrored > 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    assert_eq!(mirrored, 1);
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
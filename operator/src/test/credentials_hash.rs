@@ -0,0 +1,298 @@
+use k8s_openapi::{
+    api::{
+        apps::v1::{Deployment, DeploymentSpec},
+        core::v1::{
+            Container, EnvFromSource, PodSpec, PodTemplateSpec, Secret, SecretEnvSource,
+            SecretVolumeSource, Volume,
+        },
+    },
+    ByteString,
+};
+use kube::api::ObjectMeta;
+use serde_json::json;
+use std::collections::BTreeMap;
+use vpn_types::*;
+
+use super::{mock::*, util::*};
+use crate::{
+    consumers::{
+        actions::{consumer_secret, record_secret_hash},
+        rollout::{credentials_hash, needs_rollout, secret_hash},
+    },
+    util::{CREDENTIALS_HASH_ANNOTATION, ROLLOUT_LABEL},
+};
+
+/// Name of the tests' credentials Secret.
+const SECRET: &str = "my-mask-provider-uid";
+
+/// Returns Secret data with the given keys and values.
+fn data(pairs: &[(&str, &str)]) -> BTreeMap<String, ByteString> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), ByteString(v.as_bytes().to_vec())))
+        .collect()
+}
+
+/// Returns a MaskConsumer assigned the test MaskProvider.
+fn waiting_consumer() -> MaskConsumer {
+    assigned(
+        mask_consumer("my-mask", "default", "consumer-uid"),
+        Some(MaskConsumerPhase::Waiting),
+        assigned_provider("my-provider", "vpn", "provider-uid", SECRET),
+    )
+}
+
+/// Returns a Pod template whose container reads the Secret with envFrom.
+fn template(secret: &str) -> PodTemplateSpec {
+    PodTemplateSpec {
+        metadata: None,
+        spec: Some(PodSpec {
+            containers: vec![Container {
+                name: "app".to_owned(),
+                env_from: Some(vec![EnvFromSource {
+                    secret_ref: Some(SecretEnvSource {
+                        name: Some(secret.to_owned()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }),
+    }
+}
+
+/// Returns a Deployment with the labels and the Pod template.
+fn deployment(name: &str, labels: &[(&str, &str)], template: PodTemplateSpec) -> Deployment {
+    Deployment {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some("default".to_owned()),
+            labels: Some(
+                labels
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            ),
+            ..Default::default()
+        },
+        spec: Some(DeploymentSpec {
+            template,
+            ..Default::default()
+        }),
+        status: None,
+    }
+}
+
+#[test]
+fn hash_deterministic() {
+    let credentials = data(&[("OPENVPN_USER", "user"), ("OPENVPN_PASSWORD", "pass")]);
+    let hash = credentials_hash(Some(&credentials));
+    assert_eq!(hash.len(), 64);
+    assert_eq!(hash, credentials_hash(Some(&credentials.clone())));
+
+    // Keys are hashed in sorted order regardless of insertion order.
+    let mut reversed = BTreeMap::new();
+    for (k, v) in credentials.iter().rev() {
+        reversed.insert(k.clone(), v.clone());
+    }
+    assert_eq!(hash, credentials_hash(Some(&reversed)));
+
+    // Secrets without data hash like empty ones, which is the
+    // SHA-256 checksum of no input at all.
+    assert_eq!(credentials_hash(None), credentials_hash(Some(&data(&[]))));
+    assert_eq!(
+        credentials_hash(None),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+}
+
+#[test]
+fn hash_changes_with_data() {
+    let hash = credentials_hash(Some(&data(&[("OPENVPN_USER", "user")])));
+    assert_ne!(
+        hash,
+        credentials_hash(Some(&data(&[("OPENVPN_USER", "other")])))
+    );
+    assert_ne!(
+        hash,
+        credentials_hash(Some(&data(&[("OPENVPN_USERS", "user")])))
+    );
+    // Moving bytes between a key and its value changes the checksum.
+    assert_ne!(
+        credentials_hash(Some(&data(&[("ab", "c")]))),
+        credentials_hash(Some(&data(&[("a", "bc")])))
+    );
+}
+
+#[test]
+fn secret_annotated() {
+    let provider_secret = Secret {
+        data: Some(data(&[
+            ("OPENVPN_USER", "user"),
+            ("OPENVPN_PASSWORD", "pass"),
+        ])),
+        ..Default::default()
+    };
+    let secret = consumer_secret("default", &waiting_consumer(), provider_secret).unwrap();
+    assert_eq!(
+        secret_hash(&secret),
+        Some(credentials_hash(secret.data.as_ref()).as_str())
+    );
+
+    // The checksum covers the data as copied, not the MaskProvider's.
+    let mut restricted = waiting_consumer();
+    restricted.status.as_mut().unwrap().effective_settings = Some(MaskDefaultsSpec {
+        secret_keys: Some(vec!["OPENVPN_USER".to_owned()]),
+        ..Default::default()
+    });
+    let provider_secret = Secret {
+        data: Some(data(&[
+            ("OPENVPN_USER", "user"),
+            ("OPENVPN_PASSWORD", "pass"),
+        ])),
+        ..Default::default()
+    };
+    let secret = consumer_secret("default", &restricted, provider_secret).unwrap();
+    assert_eq!(
+        secret_hash(&secret),
+        Some(credentials_hash(Some(&data(&[("OPENVPN_USER", "user")]))).as_str())
+    );
+}
+
+#[test]
+fn only_opted_in_workloads_rolled_out() {
+    let opted_in = BTreeMap::from([(ROLLOUT_LABEL.to_owned(), "true".to_owned())]);
+    assert!(needs_rollout(&opted_in, &template(SECRET), SECRET, "hash"));
+
+    // Workloads without the label, or with another value, are left alone.
+    assert!(!needs_rollout(
+        &BTreeMap::new(),
+        &template(SECRET),
+        SECRET,
+        "hash"
+    ));
+    let opted_out = BTreeMap::from([(ROLLOUT_LABEL.to_owned(), "false".to_owned())]);
+    assert!(!needs_rollout(
+        &opted_out,
+        &template(SECRET),
+        SECRET,
+        "hash"
+    ));
+
+    // So are those that don't use the Secret.
+    assert!(!needs_rollout(
+        &opted_in,
+        &template("other"),
+        SECRET,
+        "hash"
+    ));
+
+    // Templates that mount the Secret as a volume are rolled out as well.
+    let mounted = PodTemplateSpec {
+        metadata: None,
+        spec: Some(PodSpec {
+            volumes: Some(vec![Volume {
+                name: "credentials".to_owned(),
+                secret: Some(SecretVolumeSource {
+                    secret_name: Some(SECRET.to_owned()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }),
+    };
+    assert!(needs_rollout(&opted_in, &mounted, SECRET, "hash"));
+
+    // Templates already annotated with the checksum aren't patched again.
+    let mut annotated = template(SECRET);
+    annotated.metadata = Some(ObjectMeta {
+        annotations: Some(BTreeMap::from([(
+            CREDENTIALS_HASH_ANNOTATION.to_owned(),
+            "hash".to_owned(),
+        )])),
+        ..Default::default()
+    });
+    assert!(!needs_rollout(&opted_in, &annotated, SECRET, "hash"));
+    assert!(needs_rollout(&opted_in, &annotated, SECRET, "new-hash"));
+}
+
+#[tokio::test]
+async fn workloads_annotated() {
+    let list = |kind: &str, items: Vec<Deployment>| {
+        json!({
+            "apiVersion": "apps/v1",
+            "kind": kind,
+            "metadata": {},
+            "items": items,
+        })
+    };
+    let opted_in = [(ROLLOUT_LABEL, "true")];
+    let (client, captured) = mock_method_routes(vec![
+        (
+            "GET",
+            "/apis/apps/v1/namespaces/default/deployments",
+            200,
+            list(
+                "DeploymentList",
+                vec![
+                    deployment("uses-secret", &opted_in, template(SECRET)),
+                    deployment("uses-other", &opted_in, template("other")),
+                ],
+            ),
+        ),
+        (
+            "GET",
+            "/apis/apps/v1/namespaces/default/statefulsets",
+            200,
+            list("StatefulSetList", vec![]),
+        ),
+        (
+            "PATCH",
+            "/apis/apps/v1/namespaces/default/deployments/uses-secret",
+            200,
+            json!(deployment("uses-secret", &opted_in, template(SECRET))),
+        ),
+        (
+            "PATCH",
+            "/apis/vpn.beebs.dev/v1/namespaces/default/maskconsumers/my-mask",
+            200,
+            json!(waiting_consumer()),
+        ),
+    ]);
+    record_secret_hash(
+        client,
+        "default",
+        &waiting_consumer(),
+        "new-hash".to_owned(),
+        true,
+    )
+    .await
+    .unwrap();
+    let captured = captured.lock().unwrap();
+
+    // Only opted-in workloads are listed.
+    let selector = format!("labelSelector={}%3Dtrue", ROLLOUT_LABEL.replace('/', "%2F"));
+    assert!(captured[0].path.contains(&selector));
+
+    // Only the workload using the Secret has its Pod template annotated.
+    let patches: Vec<_> = captured
+        .iter()
+        .filter(|r| r.method == "PATCH" && r.path.contains("/deployments/"))
+        .collect();
+    assert_eq!(patches.len(), 1);
+    assert!(patches[0].path.contains("/deployments/uses-secret"));
+    assert_eq!(
+        patches[0].body["spec"]["template"]["metadata"]["annotations"][CREDENTIALS_HASH_ANNOTATION],
+        "new-hash"
+    );
+
+    // The checksum is recorded in the MaskConsumer's status.
+    let status = captured.last().unwrap();
+    assert_eq!(
+        patch_op(status, "/status/provider/secretHash"),
+        Some(&json!("new-hash"))
+    );
+}
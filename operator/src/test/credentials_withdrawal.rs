@@ -0,0 +1,371 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use k8s_openapi::{
+    api::{
+        core::v1::{
+            Container, EnvFromSource, EnvVar, EnvVarSource, Pod, PodSpec, PodStatus,
+            ProjectedVolumeSource, SecretEnvSource, SecretKeySelector, SecretProjection,
+            SecretVolumeSource, Volume, VolumeProjection,
+        },
+        events::v1::Event,
+    },
+    apimachinery::pkg::apis::meta::v1::{OwnerReference, Time},
+};
+use kube::{
+    api::{DeleteParams, ListParams, ObjectMeta},
+    client::Client,
+    Api, Resource,
+};
+use serde_json::json;
+use std::time::Duration;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::{mock::*, util::*};
+use crate::consumers::withdrawal::{
+    grace_period_expired, list_referencing_pods, references_secret, warn,
+};
+
+/// Name of the MaskConsumer's credentials copy.
+const SECRET_NAME: &str = "test-mask-0-provider-uid";
+
+/// Returns a Pod with the given name and spec.
+fn pod(name: &str, spec: PodSpec) -> Pod {
+    Pod {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some("team".to_owned()),
+            uid: Some(format!("{}-uid", name)),
+            ..Default::default()
+        },
+        spec: Some(spec),
+        ..Default::default()
+    }
+}
+
+/// Returns a container that injects the Secret with `envFrom`.
+fn env_from_container(secret: &str) -> Container {
+    Container {
+        name: "app".to_owned(),
+        env_from: Some(vec![EnvFromSource {
+            secret_ref: Some(SecretEnvSource {
+                name: Some(secret.to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn secret_references_found() {
+    // Mounted as a volume.
+    let mounted = pod(
+        "mounted",
+        PodSpec {
+            volumes: Some(vec![Volume {
+                name: "vpn".to_owned(),
+                secret: Some(SecretVolumeSource {
+                    secret_name: Some(SECRET_NAME.to_owned()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+    );
+    assert!(references_secret(&mounted, SECRET_NAME));
+    assert!(!references_secret(&mounted, "other"));
+
+    // Projected alongside other sources.
+    let projected = pod(
+        "projected",
+        PodSpec {
+            volumes: Some(vec![Volume {
+                name: "vpn".to_owned(),
+                projected: Some(ProjectedVolumeSource {
+                    sources: Some(vec![VolumeProjection {
+                        secret: Some(SecretProjection {
+                            name: Some(SECRET_NAME.to_owned()),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }]),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+    );
+    assert!(references_secret(&projected, SECRET_NAME));
+
+    // A single key injected into an init container's environment.
+    let env = pod(
+        "env",
+        PodSpec {
+            init_containers: Some(vec![Container {
+                name: "init".to_owned(),
+                env: Some(vec![EnvVar {
+                    name: "OPENVPN_USER".to_owned(),
+                    value_from: Some(EnvVarSource {
+                        secret_key_ref: Some(SecretKeySelector {
+                            name: Some(SECRET_NAME.to_owned()),
+                            key: "OPENVPN_USER".to_owned(),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+    );
+    assert!(references_secret(&env, SECRET_NAME));
+
+    // All of the Secret's keys injected with envFrom.
+    let env_from = pod(
+        "env-from",
+        PodSpec {
+            containers: vec![env_from_container(SECRET_NAME)],
+            ..Default::default()
+        },
+    );
+    assert!(references_secret(&env_from, SECRET_NAME));
+    assert!(!references_secret(
+        &pod("empty", Default::default()),
+        SECRET_NAME
+    ));
+}
+
+/// Returns a MaskConsumer owned by a Mask, deleted at the given time.
+fn withdrawing_consumer(deleted: Option<chrono::DateTime<Utc>>) -> MaskConsumer {
+    let mut consumer = mask_consumer("test-mask-0", "team", "consumer-uid");
+    consumer.metadata.deletion_timestamp = deleted.map(Time);
+    consumer.metadata.owner_references = Some(vec![mask_owner_ref("test-mask-0")]);
+    consumer.spec.deletion_policy = Some(DeletionPolicy::WaitForPods);
+    consumer
+}
+
+#[test]
+fn grace_period_measured_from_deletion() {
+    let now = Utc::now();
+    let grace = Duration::from_secs(300);
+    let recent = withdrawing_consumer(Some(now - ChronoDuration::seconds(60)));
+    assert!(!grace_period_expired(&recent, grace, now));
+    let expired = withdrawing_consumer(Some(now - ChronoDuration::seconds(300)));
+    assert!(grace_period_expired(&expired, grace, now));
+}
+
+#[tokio::test]
+async fn only_running_pods_listed() {
+    let running = pod(
+        "running",
+        PodSpec {
+            containers: vec![env_from_container(SECRET_NAME)],
+            ..Default::default()
+        },
+    );
+    let mut finished = pod("finished", running.spec.clone().unwrap());
+    finished.status = Some(PodStatus {
+        phase: Some("Succeeded".to_owned()),
+        ..Default::default()
+    });
+    // Pods owned by the MaskConsumer are deleted along with it.
+    let mut owned = pod("owned", running.spec.clone().unwrap());
+    owned.metadata.owner_references = Some(vec![OwnerReference {
+        kind: "MaskConsumer".to_owned(),
+        name: "test-mask-0".to_owned(),
+        uid: "consumer-uid".to_owned(),
+        ..Default::default()
+    }]);
+    let unrelated = pod(
+        "unrelated",
+        PodSpec {
+            containers: vec![env_from_container("other")],
+            ..Default::default()
+        },
+    );
+    let (client, _) = mock_client(json!({
+        "apiVersion": "v1",
+        "kind": "PodList",
+        "metadata": {},
+        "items": [running, finished, owned, unrelated],
+    }));
+    let pods = list_referencing_pods(client, &withdrawing_consumer(None), SECRET_NAME)
+        .await
+        .unwrap();
+    assert_eq!(pods.len(), 1);
+    assert_eq!(pods[0].name.as_deref(), Some("running"));
+    assert_eq!(pods[0].kind.as_deref(), Some("Pod"));
+}
+
+#[tokio::test]
+async fn pods_and_mask_warned() {
+    let (client, captured) = mock_client(json!({}));
+    let pods = vec![
+        pod("a", Default::default()).object_ref(&()),
+        pod("b", Default::default()).object_ref(&()),
+    ];
+    warn(client, &withdrawing_consumer(None), SECRET_NAME, &pods).await;
+
+    // One event per Pod, and one on the Mask naming them all.
+    let captured = captured.lock().unwrap();
+    let events: Vec<_> = captured.iter().filter(|r| r.method == "POST").collect();
+    assert_eq!(events.len(), 3);
+    assert!(events
+        .iter()
+        .all(|e| e.body["reason"] == "CredentialsWithdrawn" && e.body["type"] == "Warning"));
+    assert_eq!(events[0].body["regarding"]["name"], "a");
+    assert_eq!(events[1].body["regarding"]["name"], "b");
+    let mask = &events[2].body;
+    assert_eq!(mask["regarding"]["kind"], "Mask");
+    assert_eq!(mask["regarding"]["uid"], "mask-uid");
+    let note = mask["note"].as_str().unwrap();
+    assert!(note.starts_with("VPN credentials are being withdrawn"));
+    assert!(note.ends_with(": a, b"), "{}", note);
+}
+
+/// Creates a Pod that injects the Secret into its environment. It sleeps
+/// long enough to still be using the credentials when they're withdrawn.
+async fn create_secret_user(client: Client, namespace: &str, secret: &str) -> Result<Pod, Error> {
+    let api: Api<Pod> = Api::namespaced(client, namespace);
+    let pod = Pod {
+        metadata: ObjectMeta {
+            name: Some("secret-user".to_owned()),
+            ..Default::default()
+        },
+        spec: Some(PodSpec {
+            containers: vec![Container {
+                image: Some("busybox".to_owned()),
+                command: Some(vec!["sleep".to_owned(), "3600".to_owned()]),
+                ..env_from_container(secret)
+            }],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    Ok(api.create(&Default::default(), &pod).await?)
+}
+
+/// Creates a MaskProvider and an Active Mask with the given deletion
+/// policy, then a Pod using the Mask's credentials. Returns the test's
+/// namespace and the name of the credentials Secret.
+async fn setup(client: Client, policy: DeletionPolicy) -> Result<(String, String), Error> {
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+    let ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    create_test_provider(client.clone(), &namespace, &uid).await?;
+    ready.await.unwrap()?;
+    let mut mask = get_test_mask(&namespace, 0, &provider_name);
+    mask.spec.deletion_policy = Some(policy);
+    Api::<Mask>::namespaced(client.clone(), &namespace)
+        .create(&Default::default(), &mask)
+        .await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+    let consumer = Api::<MaskConsumer>::namespaced(client.clone(), &namespace)
+        .get(&format!("{}-0", MASK_NAME))
+        .await?;
+    let secret = consumer.status.unwrap().provider.unwrap().secret;
+    create_secret_user(client, &namespace, &secret).await?;
+    Ok((namespace, secret))
+}
+
+/// Waits for a CredentialsWithdrawn event regarding the object.
+async fn wait_for_withdrawn_event(
+    client: Client,
+    namespace: &str,
+    name: &str,
+) -> Result<(), Error> {
+    let api: Api<Event> = Api::namespaced(client, namespace);
+    for _ in 0..60 {
+        if api
+            .list(&ListParams::default())
+            .await?
+            .into_iter()
+            .any(|e| {
+                e.reason.as_deref() == Some("CredentialsWithdrawn")
+                    && e.regarding.and_then(|r| r.name).as_deref() == Some(name)
+            })
+        {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    panic!("no CredentialsWithdrawn event regarding {}", name);
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn immediate_policy_warns_pods() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (namespace, _) = setup(client.clone(), DeletionPolicy::Immediate).await?;
+
+    // The Mask is deleted right away, but the Pod is told why its
+    // credentials went away.
+    delete_test_mask(client.clone(), &namespace, 0).await?;
+    wait_for_withdrawn_event(client.clone(), &namespace, "secret-user").await?;
+    wait_for_withdrawn_event(client.clone(), &namespace, &format!("{}-0", MASK_NAME)).await?;
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn wait_for_pods_holds_credentials() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (namespace, secret) = setup(client.clone(), DeletionPolicy::WaitForPods).await?;
+
+    // Deleting the Mask leaves its MaskConsumer Terminating, naming the Pod.
+    let name = format!("{}-0", MASK_NAME);
+    Api::<Mask>::namespaced(client.clone(), &namespace)
+        .delete(&name, &DeleteParams::default())
+        .await?;
+    wait_for_withdrawn_event(client.clone(), &namespace, "secret-user").await?;
+    let consumer_api: Api<MaskConsumer> = Api::namespaced(client.clone(), &namespace);
+    let mut message = None;
+    for _ in 0..60 {
+        let status = consumer_api.get(&name).await?.status.unwrap();
+        if status.phase == Some(MaskConsumerPhase::Terminating)
+            && status
+                .message
+                .as_deref()
+                .unwrap_or_default()
+                .contains("secret-user")
+        {
+            message = status.message;
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    let message = message.expect("MaskConsumer doesn't name the blocking Pod");
+    assert!(message.contains(&secret), "{}", message);
+
+    // The credentials are withdrawn once the Pod is gone.
+    Api::<Pod>::namespaced(client.clone(), &namespace)
+        .delete("secret-user", &DeleteParams::default())
+        .await?;
+    let mut deleted = false;
+    for _ in 0..120 {
+        if consumer_api.get_opt(&name).await?.is_none() {
+            deleted = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    assert!(deleted, "MaskConsumer was not deleted after the Pod exited");
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
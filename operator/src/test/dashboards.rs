@@ -0,0 +1,116 @@
+use serde_json::Value;
+
+use crate::{
+    dashboards::{generate, referenced_metrics, unknown_metrics, Dashboards},
+    util::{
+        metric_names::{self, controller_name, full_name},
+        Error,
+    },
+};
+
+/// Generates the dashboards for the default metrics prefix and threshold.
+fn generate_default() -> Dashboards {
+    generate("vpno", 10).unwrap()
+}
+
+#[test]
+fn dashboards_match_snapshot() {
+    // The checked-in files are regenerated with
+    // `vpn-operator generate-dashboards --output-dir dashboards`.
+    let dashboards = generate_default();
+    assert_eq!(
+        dashboards.dashboard,
+        include_str!("../../../dashboards/vpn-operator-dashboard.json")
+    );
+    assert_eq!(
+        dashboards.alerts,
+        include_str!("../../../dashboards/vpn-operator-alerts.yaml")
+    );
+}
+
+#[test]
+fn dashboard_is_valid_json() {
+    let dashboard: Value = serde_json::from_str(&generate_default().dashboard).unwrap();
+    let panels = dashboard["panels"].as_array().unwrap();
+    assert!(!panels.is_empty());
+    let mut ids: Vec<u64> = panels.iter().map(|p| p["id"].as_u64().unwrap()).collect();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), panels.len());
+    for panel in panels {
+        let targets = panel["targets"].as_array().unwrap();
+        assert!(!targets.is_empty(), "{} has no queries", panel["title"]);
+        assert!(targets.iter().all(|t| t["expr"].is_string()));
+    }
+}
+
+#[test]
+fn alerts_are_valid_yaml() {
+    let rule: Value = serde_yaml::from_str(&generate("vpno", 25).unwrap().alerts).unwrap();
+    assert_eq!(rule["kind"], "PrometheusRule");
+    let rules = rule["spec"]["groups"][0]["rules"].as_array().unwrap();
+    let names: Vec<&str> = rules.iter().map(|r| r["alert"].as_str().unwrap()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "VpnProviderVerifyFailed",
+            "VpnProviderCanaryFailed",
+            "VpnProviderSlotsNearlyFull",
+            "VpnMasksWaiting",
+            "VpnOperatorActionsFailing",
+        ]
+    );
+    assert!(rules.iter().all(|r| r["for"].is_string()));
+    // The threshold is configurable.
+    assert!(rules[3]["expr"].as_str().unwrap().ends_with("> 25"));
+}
+
+#[test]
+fn only_known_metrics_referenced() {
+    let Dashboards { dashboard, alerts } = generate("custom", 10).unwrap();
+    for document in [&dashboard, &alerts] {
+        assert!(unknown_metrics("custom", document).is_empty());
+        // Nothing is left over from the default prefix.
+        assert!(referenced_metrics("vpno", document).is_empty());
+    }
+    // The panels cover each area of the operator.
+    let referenced = referenced_metrics("custom", &dashboard);
+    for metric in [
+        full_name("custom", metric_names::PROVIDER_ACTIVE_SLOTS),
+        full_name("custom", metric_names::PROVIDER_PHASE),
+        full_name("custom", metric_names::MASK_PHASE),
+        full_name("custom", metric_names::MASK_TIME_TO_ACTIVE_SECONDS),
+        controller_name("custom", "consumers", metric_names::WRITE_DURATION_SECONDS),
+    ] {
+        assert!(
+            referenced.contains(&metric),
+            "{} is not on the dashboard",
+            metric
+        );
+    }
+}
+
+#[test]
+fn invalid_prefix_rejected() {
+    for prefix in ["", "vpn-operator", "1vpno", "vpno prod"] {
+        assert!(
+            matches!(generate(prefix, 10), Err(Error::UserInputError(_))),
+            "{:?} was accepted",
+            prefix
+        );
+    }
+    assert!(generate("team:vpno", 10).is_ok());
+}
+
+#[test]
+fn unknown_metrics_detected() {
+    let document = "rate(vpno_masks_write_duration_seconds_bucket[5m]) + vpno_masks_waiting";
+    assert_eq!(
+        referenced_metrics("vpno", document),
+        vec!["vpno_masks_waiting", "vpno_masks_write_duration_seconds"]
+    );
+    assert_eq!(
+        unknown_metrics("vpno", document),
+        vec!["vpno_masks_waiting"]
+    );
+}
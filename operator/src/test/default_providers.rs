@@ -0,0 +1,158 @@
+use serde_json::{json, Value};
+use std::{sync::Arc, time::Duration};
+use vpn_types::*;
+
+use super::{mock::*, util::*};
+use crate::{
+    consumers::{
+        actions::list_active_providers,
+        default_providers::{parse_default_providers, NamespaceDefaults, ProviderTags},
+        namespaces::NamespaceCache,
+    },
+    util::{clock::Clock, messages},
+};
+
+fn defaults_with_ttl(ttl: Duration) -> NamespaceDefaults {
+    NamespaceDefaults::new(Arc::new(NamespaceCache::new(ttl)))
+}
+
+/// Returns a Namespace resource with the given annotations.
+fn namespace(annotations: Value) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Namespace",
+        "metadata": {
+            "name": "team-a",
+            "annotations": annotations,
+        },
+    })
+}
+
+/// Returns a MaskConsumer in the `team-a` namespace asking for the providers.
+fn requesting_consumer(providers: Option<&[&str]>) -> MaskConsumer {
+    let mut consumer = mask_consumer("test-mask", "team-a", "consumer-uid");
+    consumer.spec.providers = providers.map(|p| p.iter().map(|t| t.to_string()).collect());
+    consumer
+}
+
+/// Returns a Ready MaskProvider with the given tag, if any.
+fn tagged_provider(name: &str, tag: Option<&str>) -> MaskProvider {
+    let mut provider = ready_provider(name, "vpn", name);
+    provider.spec.tags = tag.map(|t| vec![t.to_owned()]);
+    provider
+}
+
+/// Returns the names of the MaskProviders with any of the tags.
+async fn assignable(tags: &ProviderTags) -> Vec<String> {
+    let (client, _) = mock_client(json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskProviderList",
+        "metadata": {},
+        "items": [
+            tagged_provider("team-a-vpn", Some("team-a-vpn")),
+            tagged_provider("team-b-vpn", Some("team-b-vpn")),
+            tagged_provider("untagged", None),
+        ],
+    }));
+    list_active_providers(client, tags.filter(), "team-a", Clock::System.now())
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|p| p.metadata.name.unwrap())
+        .collect()
+}
+
+/// Looks up the default providers of `team-a` in the annotations.
+async fn lookup(defaults: &NamespaceDefaults, annotations: Value) -> Option<Vec<String>> {
+    let (client, _) = mock_client(namespace(annotations));
+    defaults.providers(client, "team-a").await.unwrap()
+}
+
+#[test]
+fn annotation_parsed() {
+    assert_eq!(
+        parse_default_providers("team-a-vpn, backup"),
+        Some(vec!["team-a-vpn".to_owned(), "backup".to_owned()])
+    );
+    assert_eq!(
+        parse_default_providers("team-a-vpn,,"),
+        Some(vec!["team-a-vpn".to_owned()])
+    );
+    assert_eq!(parse_default_providers(" , "), None);
+}
+
+#[tokio::test]
+async fn spec_wins() {
+    let defaults = defaults_with_ttl(Duration::from_secs(60));
+    let annotated = lookup(
+        &defaults,
+        json!({ "vpn.beebs.dev/default-providers": "team-a-vpn" }),
+    )
+    .await;
+    let tags = ProviderTags::resolve(&requesting_consumer(Some(&["team-b-vpn"])), annotated);
+    assert_eq!(tags, ProviderTags::Spec(vec!["team-b-vpn".to_owned()]));
+    assert_eq!(assignable(&tags).await, ["team-b-vpn"]);
+    assert_eq!(
+        messages::reserved_slot(0, "vpn", "team-b-vpn", tags.source().as_deref(), None),
+        "reserved slot 0 for MaskProvider vpn/team-b-vpn using the tags from spec.providers (team-b-vpn)"
+    );
+}
+
+#[tokio::test]
+async fn annotation_used() {
+    let defaults = defaults_with_ttl(Duration::from_secs(60));
+    let annotated = lookup(
+        &defaults,
+        json!({ "vpn.beebs.dev/default-providers": "team-a-vpn,backup" }),
+    )
+    .await;
+    let tags = ProviderTags::resolve(&requesting_consumer(None), annotated);
+    assert_eq!(
+        tags,
+        ProviderTags::Namespace(vec!["team-a-vpn".to_owned(), "backup".to_owned()])
+    );
+    assert_eq!(assignable(&tags).await, ["team-a-vpn"]);
+    assert!(tags
+        .source()
+        .unwrap()
+        .contains("vpn.beebs.dev/default-providers annotation (team-a-vpn, backup)"));
+
+    // The annotation is cached, so changing it only affects assignments
+    // after the cached copy expires.
+    let (client, captured) = mock_client(namespace(json!({})));
+    assert_eq!(
+        defaults.providers(client, "team-a").await.unwrap(),
+        Some(vec!["team-a-vpn".to_owned(), "backup".to_owned()])
+    );
+    assert!(captured.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn annotation_changed_later() {
+    let defaults = defaults_with_ttl(Duration::ZERO);
+    assert_eq!(lookup(&defaults, json!({})).await, None);
+    assert_eq!(
+        lookup(
+            &defaults,
+            json!({ "vpn.beebs.dev/default-providers": "backup" })
+        )
+        .await,
+        Some(vec!["backup".to_owned()])
+    );
+}
+
+#[tokio::test]
+async fn neither_present() {
+    let defaults = defaults_with_ttl(Duration::from_secs(60));
+    let annotated = lookup(&defaults, Value::Null).await;
+    let tags = ProviderTags::resolve(&requesting_consumer(None), annotated);
+    assert_eq!(tags, ProviderTags::Any);
+    assert_eq!(
+        assignable(&tags).await,
+        ["team-a-vpn", "team-b-vpn", "untagged"]
+    );
+    assert_eq!(
+        messages::reserved_slot(0, "vpn", "untagged", tags.source().as_deref(), None),
+        "reserved slot 0 for MaskProvider vpn/untagged"
+    );
+}
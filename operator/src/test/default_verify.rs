@@ -0,0 +1,237 @@
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use kube::api::ObjectMeta;
+use serde_json::json;
+use std::collections::BTreeMap;
+use vpn_render::VPN_CONTAINER_NAME;
+use vpn_types::*;
+
+use super::{mock::*, util::*};
+use crate::{
+    providers::{
+        actions::{verify_pod, verify_started},
+        operation,
+        verify_defaults::{cycle_verify, effective_verify},
+    },
+    util::config::{OperatorConfig, DEFAULT_VERIFY_KEY},
+};
+
+/// Returns the operator's default verification settings.
+fn default_verify() -> MaskProviderVerifySpec {
+    MaskProviderVerifySpec {
+        timeout: Some("120s".to_owned()),
+        interval: Some("24h".to_owned()),
+        overrides: Some(MaskProviderVerifyOverridesSpec {
+            containers: Some(MaskProviderVerifyContainerOverridesSpec {
+                vpn: Some(json!({ "image": "qmcgaw/gluetun:v3.35" })),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        placement: Some(MaskProviderVerifyPlacementSpec {
+            node_selector: Some(BTreeMap::from([("egress".to_owned(), "true".to_owned())])),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Returns a MaskProvider with the given verification settings.
+fn verifying_provider(verify: Option<MaskProviderVerifySpec>) -> MaskProvider {
+    let mut provider = ready_provider("test-provider", "default", "provider-uid");
+    provider.spec.verify = verify;
+    provider
+}
+
+/// Returns an operator config ConfigMap with the default verification settings.
+fn config_map(default_verify: &str) -> ConfigMap {
+    ConfigMap {
+        data: Some(BTreeMap::from([(
+            DEFAULT_VERIFY_KEY.to_owned(),
+            default_verify.to_owned(),
+        )])),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn default_applied_without_verify() {
+    let verify = effective_verify(&verifying_provider(None), Some(&default_verify())).unwrap();
+    assert_eq!(verify, Some(default_verify()));
+
+    // Without a default, the MaskProvider's own settings apply unchanged.
+    let own = MaskProviderVerifySpec {
+        timeout: Some("60s".to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(
+        effective_verify(&verifying_provider(Some(own.clone())), None).unwrap(),
+        Some(own)
+    );
+    assert_eq!(
+        effective_verify(&verifying_provider(None), None).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn provider_fields_take_precedence() {
+    let own = MaskProviderVerifySpec {
+        timeout: Some("60s".to_owned()),
+        placement: Some(MaskProviderVerifyPlacementSpec {
+            zone: Some("eu-west-2a".to_owned()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let verify = effective_verify(&verifying_provider(Some(own)), Some(&default_verify()))
+        .unwrap()
+        .unwrap();
+    assert_eq!(verify.timeout.as_deref(), Some("60s"));
+    // Fields the MaskProvider leaves unset come from the default.
+    assert_eq!(verify.interval.as_deref(), Some("24h"));
+    assert_eq!(verify.overrides, default_verify().overrides);
+    // Nested settings are merged field by field as well.
+    let placement = verify.placement.unwrap();
+    assert_eq!(placement.zone.as_deref(), Some("eu-west-2a"));
+    assert_eq!(
+        placement.node_selector,
+        default_verify().placement.unwrap().node_selector
+    );
+}
+
+#[test]
+fn explicit_skip_wins() {
+    let own = MaskProviderVerifySpec {
+        skip: Some(true),
+        ..Default::default()
+    };
+    let verify = effective_verify(&verifying_provider(Some(own)), Some(&default_verify()))
+        .unwrap()
+        .unwrap();
+    assert_eq!(verify.skip, Some(true));
+
+    // And so does an explicit `false` over a default that skips.
+    let skipping = MaskProviderVerifySpec {
+        skip: Some(true),
+        ..Default::default()
+    };
+    let own = MaskProviderVerifySpec {
+        skip: Some(false),
+        ..Default::default()
+    };
+    let verify = effective_verify(&verifying_provider(Some(own)), Some(&skipping))
+        .unwrap()
+        .unwrap();
+    assert_eq!(verify.skip, Some(false));
+}
+
+#[test]
+fn override_nulls_preserved() {
+    // An explicit null in the free-form overrides unsets the field
+    // of the rendered Pod, so it's kept rather than dropped as unset.
+    let own = MaskProviderVerifySpec {
+        overrides: Some(MaskProviderVerifyOverridesSpec {
+            pod: Some(json!({ "spec": { "dnsPolicy": null } })),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let verify = effective_verify(&verifying_provider(Some(own)), Some(&default_verify()))
+        .unwrap()
+        .unwrap();
+    let overrides = verify.overrides.unwrap();
+    assert_eq!(
+        overrides.pod,
+        Some(json!({ "spec": { "dnsPolicy": null } }))
+    );
+    assert_eq!(
+        overrides.containers,
+        default_verify().overrides.unwrap().containers
+    );
+}
+
+#[test]
+fn default_reloaded_from_config_map() {
+    let config = OperatorConfig::new(false);
+    assert_eq!(config.default_verify(), None);
+
+    config.update(Some(&config_map(
+        "timeout: 120s\ninterval: 24h\nplacement:\n  nodeSelector:\n    egress: \"true\"\n",
+    )));
+    let verify = config.default_verify().unwrap();
+    assert_eq!(verify.timeout.as_deref(), Some("120s"));
+    assert_eq!(verify.placement, default_verify().placement);
+
+    // Invalid settings keep the previous ones.
+    config.update(Some(&config_map("timeout: [")));
+    assert_eq!(config.default_verify(), Some(verify));
+
+    // Removing the key or the ConfigMap clears the default.
+    config.update(Some(&config_map("")));
+    assert_eq!(config.default_verify(), None);
+    config.update(Some(&config_map("timeout: 120s")));
+    config.update(None);
+    assert_eq!(config.default_verify(), None);
+}
+
+#[test]
+fn cycle_uses_recorded_settings() {
+    let mut instance = verifying_provider(None);
+    // Cycles that began before the settings were recorded use the spec.
+    assert_eq!(cycle_verify(&instance), None);
+
+    // Otherwise the settings the cycle began with apply, even if the
+    // default changed since.
+    instance.status.as_mut().unwrap().effective_verify = Some(default_verify());
+    assert_eq!(cycle_verify(&instance), Some(&default_verify()));
+
+    let consumer = MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some("test-provider-verify".to_owned()),
+            uid: Some("consumer-uid".to_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let secret = Secret {
+        metadata: ObjectMeta {
+            name: Some("test-provider-verify-provider-uid".to_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let pod = verify_pod("test-provider", "default", &instance, &secret, &consumer).unwrap();
+    let spec = pod.spec.unwrap();
+    assert_eq!(
+        spec.node_selector,
+        default_verify().placement.unwrap().node_selector
+    );
+    let vpn = spec
+        .containers
+        .iter()
+        .find(|c| c.name == VPN_CONTAINER_NAME)
+        .unwrap();
+    assert_eq!(vpn.image.as_deref(), Some("qmcgaw/gluetun:v3.35"));
+}
+
+#[tokio::test]
+async fn effective_settings_recorded() {
+    let instance = verifying_provider(None);
+    let (client, captured) = mock_client(json!(instance));
+    verify_started(
+        client,
+        &instance,
+        Some(default_verify()),
+        operation::verify_started(None, chrono::Utc::now()),
+        "Created verification Mask.".to_owned(),
+    )
+    .await
+    .unwrap();
+    let captured = captured.lock().unwrap();
+    let status = captured.last().unwrap();
+    assert_eq!(
+        patch_op(status, "/status/effectiveVerify"),
+        Some(&json!(default_verify()))
+    );
+    assert_eq!(patch_op(status, "/status/phase"), Some(&json!("Verifying")));
+}
@@ -0,0 +1,255 @@
+use k8s_openapi::{
+    api::{core::v1::Secret, events::v1::Event},
+    apimachinery::pkg::apis::meta::v1::Time,
+};
+use kube::{
+    api::{ListParams, ObjectMeta, Patch, PatchParams},
+    client::Client,
+    Api,
+};
+use std::{collections::BTreeMap, time::Duration};
+use tokio::spawn;
+use vpn_types::*;
+
+use super::{mock, util::*};
+use crate::{
+    providers::actions,
+    util::{messages, FORCE_DELETE_ANNOTATION},
+};
+
+/// Returns a MaskReservation for the MaskConsumer.
+fn reservation(name: &str, consumer: &str, terminating: bool) -> MaskReservation {
+    MaskReservation {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some("vpn".to_owned()),
+            deletion_timestamp: terminating.then(|| Time(chrono::Utc::now())),
+            ..Default::default()
+        },
+        spec: MaskReservationSpec {
+            name: consumer.to_owned(),
+            namespace: "default".to_owned(),
+            uid: format!("{}-uid", consumer),
+            slot: None,
+        },
+        ..Default::default()
+    }
+}
+
+/// Returns a MaskProvider with the given force-delete annotation.
+fn annotated_provider(force_delete: Option<&str>) -> MaskProvider {
+    let mut provider = mask_provider("test-provider", "vpn", "provider-uid");
+    provider.metadata.annotations =
+        force_delete.map(|v| BTreeMap::from([(FORCE_DELETE_ANNOTATION.to_owned(), v.to_owned())]));
+    provider
+}
+
+#[test]
+fn force_delete_requires_true() {
+    assert!(!actions::force_delete_requested(&annotated_provider(None)));
+    assert!(!actions::force_delete_requested(&annotated_provider(Some(
+        ""
+    ))));
+    assert!(!actions::force_delete_requested(&annotated_provider(Some(
+        "false"
+    ))));
+    assert!(actions::force_delete_requested(&annotated_provider(Some(
+        "true"
+    ))));
+    assert!(actions::force_delete_requested(&annotated_provider(Some(
+        " True "
+    ))));
+}
+
+#[test]
+fn blocking_consumers_are_named() {
+    let reservations = vec![
+        reservation("test-provider-1", "b", false),
+        reservation("test-provider-0", "a", false),
+    ];
+    let consumers = actions::assigned_consumers(&reservations);
+    assert_eq!(consumers, vec!["default/a", "default/b"]);
+    assert_eq!(
+        messages::deletion_blocked(&consumers),
+        "Deletion blocked by 2 assigned MaskConsumer(s): default/a, default/b. Delete them, or annotate the MaskProvider with vpn.beebs.dev/force-delete=true to unassign them."
+    );
+}
+
+#[tokio::test]
+async fn forced_deletion_unassigns_consumers() {
+    let (client, captured) = mock::mock_client(serde_json::json!({
+        "kind": "Status",
+        "apiVersion": "v1",
+        "metadata": {},
+        "status": "Success",
+    }));
+    let reservations = vec![
+        reservation("test-provider-0", "a", false),
+        // Already being deleted, so it isn't deleted again.
+        reservation("test-provider-1", "b", true),
+    ];
+    let remaining = actions::unassign_all(client, &annotated_provider(Some("true")), &reservations)
+        .await
+        .unwrap();
+    // Only the one that was already being deleted is left to drain.
+    assert_eq!(remaining, 1);
+    let captured = captured.lock().unwrap();
+    let deletes: Vec<&str> = captured
+        .iter()
+        .filter(|r| r.method == "DELETE")
+        .map(|r| r.path.as_str())
+        .collect();
+    // The MaskConsumer goes before its reservation, so it can't copy the
+    // credentials again in between.
+    assert_eq!(
+        deletes,
+        vec![
+            "/apis/vpn.beebs.dev/v1/namespaces/default/maskconsumers/a?",
+            "/apis/vpn.beebs.dev/v1/namespaces/vpn/maskreservations/test-provider-0?",
+        ]
+    );
+    // Only the MaskConsumer that holds the reservation is deleted.
+    let consumer_delete = captured.iter().find(|r| r.method == "DELETE").unwrap();
+    assert_eq!(consumer_delete.body["preconditions"]["uid"], "a-uid");
+    // The forced deletion shows up as an event.
+    assert!(captured
+        .iter()
+        .any(|r| r.method == "POST" && r.body["reason"] == "ForceDelete"));
+}
+
+/// Creates a Ready MaskProvider with a Mask assigned its slot, then
+/// requests deletion of the MaskProvider and waits for it to be blocked.
+/// Returns the name of the MaskProvider and the Mask's credentials Secret.
+async fn create_blocked_provider(
+    client: Client,
+    namespace: &str,
+    uid: &str,
+) -> Result<(String, String), Error> {
+    let provider_name = test_provider_name(uid);
+    let provider_ready = {
+        let client = client.clone();
+        let namespace = namespace.to_owned();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    create_test_provider(client.clone(), namespace, uid).await?;
+    provider_ready.await.unwrap()?;
+    let assigned = {
+        let client = client.clone();
+        let namespace = namespace.to_owned();
+        spawn(async move { wait_for_provider_assignment(client, &namespace, 0).await })
+    };
+    create_test_mask(client.clone(), namespace, 0, &provider_name).await?;
+    let assigned = assigned.await.unwrap()?;
+    wait_for_secret(client.clone(), assigned.secret.clone(), namespace).await?;
+
+    // Request deletion. The finalizer holds the MaskProvider
+    // while the Mask is still assigned its slot.
+    let terminating = {
+        let client = client.clone();
+        let namespace = namespace.to_owned();
+        spawn(async move {
+            wait_for_provider_phase(client, &namespace, MaskProviderPhase::Terminating).await
+        })
+    };
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), namespace);
+    api.delete(&provider_name, &Default::default()).await?;
+    terminating.await.unwrap()?;
+    Ok((provider_name, assigned.secret))
+}
+
+/// Waits for the MaskProvider to report that its deletion is blocked.
+async fn wait_for_blocked_message(
+    client: Client,
+    namespace: &str,
+    name: &str,
+) -> Result<MaskProvider, Error> {
+    let api: Api<MaskProvider> = Api::namespaced(client, namespace);
+    for _ in 0..60 {
+        let provider = api.get(name).await?;
+        if provider
+            .status
+            .as_ref()
+            .and_then(|s| s.message.as_deref())
+            .is_some_and(|m| m.starts_with("Deletion blocked"))
+        {
+            return Ok(provider);
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    Err(Error::Other(format!(
+        "MaskProvider {} deletion not blocked before timeout",
+        name
+    )))
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn deletion_blocked_until_released() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let (provider_name, secret_name) =
+        create_blocked_provider(client.clone(), &namespace, &uid).await?;
+
+    // The blocking MaskConsumer is named, and its credentials are left alone.
+    let provider = wait_for_blocked_message(client.clone(), &namespace, &provider_name).await?;
+    let message = provider.status.unwrap().message.unwrap();
+    assert!(message.contains(&format!("{}/{}-0", namespace, MASK_NAME)));
+    Api::<Secret>::namespaced(client.clone(), &namespace)
+        .get(&secret_name)
+        .await?;
+    let events: Api<Event> = Api::namespaced(client.clone(), &namespace);
+    assert!(events
+        .list(&ListParams::default())
+        .await?
+        .into_iter()
+        .any(|e| e.reason.as_deref() == Some("DeletionBlocked")));
+
+    // Once the Mask releases its slot, the deletion proceeds.
+    delete_test_mask(client.clone(), &namespace, 0).await?;
+    delete_test_provider(client.clone(), &namespace, &provider_name).await?;
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn deletion_forced_with_annotation() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let (provider_name, _) = create_blocked_provider(client.clone(), &namespace, &uid).await?;
+    wait_for_blocked_message(client.clone(), &namespace, &provider_name).await?;
+
+    // Forcing the deletion unassigns the Mask, which has no other
+    // MaskProvider to be assigned to.
+    let no_providers = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_mask_phase(client, &namespace, 0, MaskPhase::ErrNoProviders).await },
+        )
+    };
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let patch = serde_json::json!({
+        "metadata": {
+            "annotations": {
+                FORCE_DELETE_ANNOTATION: "true",
+            },
+        },
+    });
+    api.patch(
+        &provider_name,
+        &PatchParams::default(),
+        &Patch::Merge(&patch),
+    )
+    .await?;
+    delete_test_provider(client.clone(), &namespace, &provider_name).await?;
+    no_providers.await.unwrap()?;
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::{api::ObjectMeta, client::Client, Api, ResourceExt};
+use std::clone::Clone;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::util::*;
+
+/// Simulates a cluster restored from a backup, where a `MaskConsumer` with
+/// the `Mask`'s name already exists but is owned by a `Mask` UID that no
+/// longer exists. The controller should replace it with its own.
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn disaster_recovery() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_label = test_provider_name(&uid);
+
+    // Create the test MaskProvider and wait for it to be Ready.
+    let provider_ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let provider = create_test_provider(client.clone(), &namespace, &uid).await?;
+    provider_ready.await.unwrap()?;
+
+    // Fabricate the stale MaskConsumer. It requests a tag that no provider
+    // has, so it will never be assigned a slot on its own.
+    let mask_name = format!("{}-{}", MASK_NAME, 0);
+    let mc_api: Api<MaskConsumer> = Api::namespaced(client.clone(), &namespace);
+    let stale = mc_api
+        .create(
+            &Default::default(),
+            &MaskConsumer {
+                metadata: ObjectMeta {
+                    name: Some(mask_name.clone()),
+                    namespace: Some(namespace.clone()),
+                    owner_references: Some(vec![OwnerReference {
+                        api_version: "vpn.beebs.dev/v1".to_owned(),
+                        kind: "Mask".to_owned(),
+                        name: mask_name.clone(),
+                        uid: uuid::Uuid::new_v4().to_string(),
+                        controller: Some(true),
+                        ..Default::default()
+                    }]),
+                    ..Default::default()
+                },
+                spec: MaskConsumerSpec {
+                    providers: Some(vec![format!("{}-stale", provider_label)]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .await?;
+
+    // Create the Mask and wait for it to become Active.
+    let mask_active = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(async move { wait_for_mask_phase(client, &namespace, 0, MaskPhase::Active).await })
+    };
+    let mask = create_test_mask(client.clone(), &namespace, 0, &provider_label).await?;
+    mask_active.await.unwrap()?;
+
+    // The MaskConsumer should have been replaced with one owned by the Mask.
+    let consumer = mc_api.get(&mask_name).await?;
+    assert_ne!(consumer.metadata.uid, stale.metadata.uid);
+    assert!(consumer
+        .owner_references()
+        .iter()
+        .any(|o| Some(&o.uid) == mask.metadata.uid.as_ref()));
+    let assigned = consumer.status.and_then(|s| s.provider).unwrap();
+    assert_eq!(&assigned.uid, provider.metadata.uid.as_ref().unwrap());
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+
+    Ok(())
+}
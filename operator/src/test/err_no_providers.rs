@@ -6,10 +6,11 @@ use vpn_types::*;
 use super::util::*;
 
 #[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
 async fn err_no_providers() -> Result<(), Error> {
     let client: Client = Client::try_default().await.unwrap();
     let (uid, namespace) = create_test_namespace(client.clone()).await?;
-    let provider_label = format!("{}-{}", PROVIDER_NAME, uid);
+    let provider_label = test_provider_name(&uid);
 
     // Watch for the error message in the Mask's status.
     let fail = {
@@ -0,0 +1,130 @@
+use kube::api::ObjectMeta;
+use std::collections::BTreeMap;
+use vpn_types::*;
+
+use super::mock::*;
+use crate::util::{
+    explain::{self, LastAction, LAST_ACTION_ANNOTATION, MAX_REASON_LEN},
+    patch::patch_status,
+};
+
+/// Returns a Mask, optionally annotated with a previous action.
+fn test_mask(last_action: Option<&LastAction>) -> Mask {
+    Mask {
+        metadata: ObjectMeta {
+            name: Some("test-mask".to_owned()),
+            namespace: Some("default".to_owned()),
+            annotations: last_action.map(|last_action| {
+                BTreeMap::from([(
+                    LAST_ACTION_ANNOTATION.to_owned(),
+                    serde_json::to_string(last_action).unwrap(),
+                )])
+            }),
+            ..Default::default()
+        },
+        status: Some(MaskStatus {
+            phase: Some(MaskPhase::Pending),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Returns the explanation written by the captured metadata patch.
+fn written_last_action(request: &CapturedRequest) -> LastAction {
+    // JSON pointers escape the slash in the annotation key as `~1`.
+    let path = format!(
+        "/metadata/annotations/{}",
+        LAST_ACTION_ANNOTATION.replace('/', "~1")
+    );
+    let value = patch_op(request, "/metadata/annotations")
+        .map(|annotations| annotations[LAST_ACTION_ANNOTATION].clone())
+        .or_else(|| patch_op(request, &path).cloned())
+        .expect("metadata patch should set the last action annotation");
+    serde_json::from_str(value.as_str().unwrap()).unwrap()
+}
+
+/// Patches the Mask's status as though `action` was being applied.
+async fn apply(mask: &Mask, action: &str, message: &str) -> Vec<CapturedRequest> {
+    let (client, captured) = mock_client(serde_json::to_value(mask).unwrap());
+    let message = message.to_owned();
+    explain::scope(true, "masks", action, async {
+        patch_status(client, mask, move |status| {
+            status.phase = Some(MaskPhase::Waiting);
+            status.message = Some(message);
+        })
+        .await
+    })
+    .await
+    .unwrap();
+    let captured = captured.lock().unwrap();
+    captured.clone()
+}
+
+#[tokio::test]
+async fn last_action_is_annotated_and_overwritten() {
+    // The annotation is written to the main resource, as the
+    // status subresource ignores metadata, then the status follows.
+    let mask = test_mask(None);
+    let captured = apply(&mask, "Waiting", "No MaskProviders available.").await;
+    assert_eq!(captured.len(), 2);
+    assert_eq!(captured[0].method, "PATCH");
+    assert!(captured[0]
+        .path
+        .starts_with("/apis/vpn.beebs.dev/v1/namespaces/default/masks/test-mask?"));
+    assert!(captured[1]
+        .path
+        .contains("/namespaces/default/masks/test-mask/status"));
+    assert_eq!(patch_op(&captured[1], "/metadata/annotations"), None);
+    let first = written_last_action(&captured[0]);
+    assert_eq!(first.action, "Waiting");
+    assert_eq!(first.controller, "masks");
+    assert_eq!(first.reason.as_deref(), Some("No MaskProviders available."));
+    assert!(chrono::DateTime::parse_from_rfc3339(&first.at).is_ok());
+
+    // The next action overwrites the previous explanation.
+    let mask = test_mask(Some(&first));
+    let captured = apply(&mask, "CreateConsumer", "Assigning a MaskProvider.").await;
+    assert_eq!(captured.len(), 2);
+    let second = written_last_action(&captured[0]);
+    assert_eq!(second.action, "CreateConsumer");
+    assert_eq!(second.reason.as_deref(), Some("Assigning a MaskProvider."));
+
+    // Verbose reasons are truncated to keep the annotation compact.
+    let captured = apply(&mask, "Waiting", &"x".repeat(4 * MAX_REASON_LEN)).await;
+    let third = written_last_action(&captured[0]);
+    assert_eq!(third.reason.unwrap().len(), MAX_REASON_LEN);
+}
+
+#[tokio::test]
+async fn status_patch_outside_action_is_not_annotated() {
+    // Only patches made while applying an action are explained.
+    let mask = test_mask(None);
+    let (client, captured) = mock_client(serde_json::to_value(&mask).unwrap());
+    patch_status(client, &mask, |status| {
+        status.phase = Some(MaskPhase::Waiting);
+    })
+    .await
+    .unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 1);
+    assert!(captured[0].path.contains("/status"));
+}
+
+#[tokio::test]
+async fn disabled_scope_is_not_annotated() {
+    // Without --explain-annotations, actions aren't explained.
+    let mask = test_mask(None);
+    let (client, captured) = mock_client(serde_json::to_value(&mask).unwrap());
+    explain::scope(false, "masks", "Waiting", async {
+        patch_status(client, &mask, |status| {
+            status.phase = Some(MaskPhase::Waiting);
+        })
+        .await
+    })
+    .await
+    .unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 1);
+    assert!(captured[0].path.contains("/status"));
+}
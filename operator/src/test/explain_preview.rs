@@ -0,0 +1,343 @@
+use k8s_openapi::api::core::v1::{ConfigMap, Pod, Secret};
+use serde_json::{json, Value};
+use std::time::Duration;
+use vpn_types::*;
+
+use super::mock::{
+    consumer_value, decide, merged, mock_method_routes, provider_value, status_failure,
+};
+use crate::{
+    consumers::rollout::credentials_hash,
+    masks::reconcile::{self as masks, MaskAction},
+    providers::reconcile::{self as providers, MaskProviderAction},
+    util::{
+        finalizer::FINALIZER_NAME,
+        messages,
+        preview::{self, CONSUMER_SECRET, ERROR_KEY, VERIFY_POD},
+        Error, CREDENTIALS_HASH_ANNOTATION, EXPLAIN_ANNOTATION, PROVIDER_SECRET_LABEL,
+    },
+};
+
+/// Returns the MaskProvider of [`provider_value`] with the checksum of its
+/// credentials recorded and a preview of the verification Pod requested,
+/// with `patch` merged into it.
+fn explained_provider(patch: Value) -> Value {
+    let explained = provider_value(json!({
+        "metadata": { "annotations": { EXPLAIN_ANNOTATION: VERIFY_POD } },
+        "status": { "secretHash": secret_hash() },
+    }));
+    merged(explained, patch)
+}
+
+/// Returns the MaskProvider's credentials Secret.
+fn secret() -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {
+            "name": "provider-credentials",
+            "namespace": "providers",
+            "labels": { PROVIDER_SECRET_LABEL: "true" },
+        },
+        "data": {
+            "OPENVPN_USER": "dXNlcg==",
+            "OPENVPN_PASSWORD": "aHVudGVyMg==",
+        },
+    })
+}
+
+/// Returns the checksum of the credentials in [`secret`].
+fn secret_hash() -> String {
+    let secret: Secret = serde_json::from_value(secret()).unwrap();
+    credentials_hash(secret.data.as_ref())
+}
+
+/// Returns an Active Mask that requested the consumer-secret preview.
+fn mask() -> Value {
+    json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "Mask",
+        "metadata": {
+            "name": "mask-0",
+            "namespace": "default",
+            "uid": "mask-uid",
+            "finalizers": [FINALIZER_NAME],
+            "annotations": { EXPLAIN_ANNOTATION: CONSUMER_SECRET },
+        },
+        "spec": {},
+        "status": {
+            "phase": "Active",
+            "message": messages::ACTIVE,
+            "lastUpdated": chrono::Utc::now().to_rfc3339(),
+            "lastSlot": 0,
+            "lastProviderUid": "provider-uid",
+        },
+    })
+}
+
+/// Returns the action decided for the MaskProvider with the given
+/// verification settings and objects in the cluster.
+async fn provider_action(
+    provider: Value,
+    verify: Option<MaskProviderVerifySpec>,
+    objects: &[Value],
+) -> MaskProviderAction {
+    let instance: MaskProvider = serde_json::from_value(provider).unwrap();
+    decide(objects, |client| {
+        let instance = instance.clone();
+        let verify = verify.clone();
+        async move {
+            providers::determine_action(
+                client,
+                "provider",
+                "providers",
+                &instance,
+                verify,
+                None,
+                &Default::default(),
+                &Default::default(),
+                &[],
+                &[],
+                chrono::Utc::now(),
+            )
+            .await
+        }
+    })
+    .await
+}
+
+/// Returns the action decided for the Mask with the given objects in the cluster.
+async fn mask_action(objects: &[Value]) -> MaskAction {
+    let instance: Mask = serde_json::from_value(mask()).unwrap();
+    decide(objects, |client| {
+        let instance = instance.clone();
+        async move {
+            masks::determine_action(
+                client,
+                "mask-0",
+                "default",
+                &instance,
+                Duration::from_secs(600),
+                chrono::Utc::now(),
+                false,
+            )
+            .await
+        }
+    })
+    .await
+}
+
+/// Returns the verification Pod previewed for the MaskProvider.
+async fn previewed_pod(verify: Option<MaskProviderVerifySpec>) -> Pod {
+    let config_map = match provider_action(explained_provider(json!({})), verify, &[secret()]).await
+    {
+        MaskProviderAction::WritePreview(config_map) => config_map,
+        action => panic!("expected WritePreview, got {:?}", action),
+    };
+    assert_eq!(
+        config_map.metadata.name.as_deref(),
+        Some("provider-explain")
+    );
+    assert_eq!(config_map.metadata.namespace.as_deref(), Some("providers"));
+    let owner = &config_map.metadata.owner_references.as_ref().unwrap()[0];
+    assert_eq!(owner.uid, "provider-uid");
+    let data = config_map.data.unwrap();
+    serde_yaml::from_str(&data["verify-pod.yaml"]).unwrap()
+}
+
+#[tokio::test]
+async fn verify_pod_previewed() {
+    // The Pod is rendered with the effective settings, without an owner
+    // because the verification MaskConsumer doesn't exist yet.
+    let pod = previewed_pod(None).await;
+    assert_eq!(pod.metadata.name.as_deref(), Some("provider"));
+    assert_eq!(pod.metadata.owner_references, None);
+    let vpn = pod
+        .spec
+        .as_ref()
+        .unwrap()
+        .containers
+        .iter()
+        .find(|c| c.name == vpn_render::VPN_CONTAINER_NAME)
+        .unwrap();
+    let env = vpn.env.as_ref().unwrap();
+    let refs: Vec<_> = env
+        .iter()
+        .filter_map(|e| e.value_from.as_ref()?.secret_key_ref.as_ref())
+        .collect();
+    assert!(!refs.is_empty());
+    assert!(refs
+        .iter()
+        .all(|r| r.name.as_deref() == Some("provider-verify-provider-uid")));
+
+    // Container overrides are merged into the preview.
+    let pod = previewed_pod(Some(MaskProviderVerifySpec {
+        overrides: Some(MaskProviderVerifyOverridesSpec {
+            containers: Some(MaskProviderVerifyContainerOverridesSpec {
+                vpn: Some(json!({ "imagePullPolicy": "Always" })),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }))
+    .await;
+    let vpn = pod
+        .spec
+        .as_ref()
+        .unwrap()
+        .containers
+        .iter()
+        .find(|c| c.name == vpn_render::VPN_CONTAINER_NAME)
+        .unwrap();
+    assert_eq!(vpn.image_pull_policy.as_deref(), Some("Always"));
+
+    // So are Pod overrides.
+    let pod = previewed_pod(Some(MaskProviderVerifySpec {
+        overrides: Some(MaskProviderVerifyOverridesSpec {
+            pod: Some(json!({ "spec": { "dnsPolicy": "None" } })),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }))
+    .await;
+    assert_eq!(pod.spec.unwrap().dns_policy.as_deref(), Some("None"));
+}
+
+#[tokio::test]
+async fn preview_written_once() {
+    let current = match provider_action(explained_provider(json!({})), None, &[secret()]).await {
+        MaskProviderAction::WritePreview(config_map) => *config_map,
+        action => panic!("expected WritePreview, got {:?}", action),
+    };
+    let current = serde_json::to_value(ConfigMap {
+        metadata: kube::api::ObjectMeta {
+            resource_version: Some("1".to_owned()),
+            ..current.metadata.clone()
+        },
+        ..current
+    })
+    .unwrap();
+    let with_kind = |config_map: Value| {
+        merged(
+            config_map,
+            json!({ "apiVersion": "v1", "kind": "ConfigMap" }),
+        )
+    };
+
+    // An up to date preview isn't written again.
+    let action = provider_action(
+        explained_provider(json!({})),
+        None,
+        &[secret(), with_kind(current.clone())],
+    )
+    .await;
+    assert!(!matches!(action, MaskProviderAction::WritePreview(_)));
+
+    // Nor is a ConfigMap with the name that belongs to something else.
+    let foreign = merged(
+        current.clone(),
+        json!({ "metadata": { "ownerReferences": null }, "data": { "other": "data" } }),
+    );
+    let action = provider_action(
+        explained_provider(json!({})),
+        None,
+        &[secret(), with_kind(foreign)],
+    )
+    .await;
+    assert!(!matches!(action, MaskProviderAction::WritePreview(_)));
+
+    // Without the annotation, nothing is previewed.
+    let unannotated = explained_provider(json!({ "metadata": { "annotations": null } }));
+    let action = provider_action(unannotated, None, &[secret()]).await;
+    assert!(!matches!(action, MaskProviderAction::WritePreview(_)));
+
+    // Neither are values meant for Masks.
+    let mistaken = explained_provider(json!({ "metadata": { "annotations": {
+        EXPLAIN_ANNOTATION: CONSUMER_SECRET,
+    }}}));
+    let action = provider_action(mistaken, None, &[secret()]).await;
+    assert!(!matches!(action, MaskProviderAction::WritePreview(_)));
+}
+
+#[tokio::test]
+async fn consumer_secret_previewed() {
+    let objects = [
+        consumer_value(json!({})),
+        explained_provider(json!({ "metadata": { "annotations": null } })),
+        secret(),
+    ];
+    let config_map = match mask_action(&objects).await {
+        MaskAction::WritePreview(config_map) => config_map,
+        action => panic!("expected WritePreview, got {:?}", action),
+    };
+    assert_eq!(config_map.metadata.name.as_deref(), Some("mask-0-explain"));
+    assert_eq!(config_map.metadata.namespace.as_deref(), Some("default"));
+    let yaml = &config_map.data.unwrap()["consumer-secret.yaml"];
+    let rendered: Value = serde_yaml::from_str(yaml).unwrap();
+    assert_eq!(rendered["metadata"]["name"], "mask-0-provider-uid");
+    assert_eq!(
+        rendered["keys"],
+        json!(["OPENVPN_PASSWORD", "OPENVPN_USER"])
+    );
+
+    // The values are never shown, nor the annotations holding their digests.
+    assert!(rendered.get("data").is_none());
+    assert!(rendered["metadata"].get("annotations").is_none());
+    assert!(!yaml.contains(CREDENTIALS_HASH_ANNOTATION));
+    assert!(!yaml.contains("aHVudGVyMg=="));
+    assert!(!yaml.contains("hunter2"));
+
+    // Nothing is previewed until a MaskProvider is assigned.
+    let objects = [
+        consumer_value(json!({ "status": { "phase": "Pending", "provider": null } })),
+        secret(),
+    ];
+    assert!(!matches!(
+        mask_action(&objects).await,
+        MaskAction::WritePreview(_)
+    ));
+}
+
+#[test]
+fn render_error_shown() {
+    let instance: MaskProvider = serde_json::from_value(explained_provider(json!({}))).unwrap();
+    let config_map = preview::preview_config_map(
+        &instance,
+        VERIFY_POD,
+        Err::<Pod, _>(Error::UserInputError("bad overrides".to_owned())),
+    );
+    let data = config_map.data.unwrap();
+    assert_eq!(data.len(), 1);
+    assert!(data[ERROR_KEY].contains("bad overrides"));
+}
+
+#[tokio::test]
+async fn forbidden_preview_skipped() {
+    // Previews aren't enabled in the namespace, so creating one is
+    // forbidden. That doesn't fail the reconcile.
+    let instance: MaskProvider = serde_json::from_value(explained_provider(json!({}))).unwrap();
+    let config_map = preview::preview_config_map(
+        &instance,
+        VERIFY_POD,
+        Err::<Pod, _>(Error::UserInputError("bad overrides".to_owned())),
+    );
+    let path = "/api/v1/namespaces/providers/configmaps";
+    let (client, captured) = mock_method_routes(vec![
+        ("GET", path, 404, status_failure(404)),
+        ("POST", path, 403, status_failure(403)),
+    ]);
+    assert!(!preview::write(client, config_map.clone()).await.unwrap());
+    assert_eq!(captured.lock().unwrap().len(), 2);
+
+    // Reading ConfigMaps is only granted where previews are enabled
+    // too, so the preview is considered missing, and writing it is
+    // skipped the same way.
+    let (client, captured) = mock_method_routes(vec![("GET", path, 403, status_failure(403))]);
+    let desired = preview::needs_write(client.clone(), &instance, config_map.clone())
+        .await
+        .unwrap();
+    assert_eq!(desired, Some(config_map.clone()));
+    assert!(!preview::write(client, config_map).await.unwrap());
+    assert_eq!(captured.lock().unwrap().len(), 2);
+}
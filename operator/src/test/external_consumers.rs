@@ -0,0 +1,247 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use vpn_types::*;
+
+use super::mock::{consumer_value, decide, merged, mock_cluster, provider_value};
+use crate::{
+    consumers::{
+        external::{heartbeat_expired, DEFAULT_HEARTBEAT_TIMEOUT},
+        reconcile::{determine_action, ConsumerAction, ContextData},
+    },
+    util::{
+        config::OperatorConfig, messages, CONSUMER_UID_LABEL, HEARTBEAT_ANNOTATION,
+        MASK_NAME_LABEL, PROVIDER_UID_LABEL,
+    },
+};
+
+/// Returns the time `minutes` ago, formatted like a heartbeat.
+fn minutes_ago(minutes: i64) -> String {
+    (Utc::now() - ChronoDuration::minutes(minutes)).to_rfc3339()
+}
+
+/// Returns the [`consumer_value`] of a router outside of the cluster that
+/// reported a heartbeat a minute ago, with `patch` merged into it.
+fn external_consumer(patch: Value) -> Value {
+    let external = consumer_value(json!({
+        "metadata": {
+            "name": "office-router",
+            "namespace": "external",
+            "creationTimestamp": minutes_ago(60),
+            "annotations": { HEARTBEAT_ANNOTATION: minutes_ago(1) },
+            "ownerReferences": null,
+        },
+        "spec": { "external": true },
+        "status": { "provider": { "secret": "office-router-provider-uid" } },
+    }));
+    merged(external, patch)
+}
+
+/// Returns the MaskConsumer with the heartbeat annotation set to `heartbeat`,
+/// or without it if null.
+fn with_heartbeat(heartbeat: Value) -> Value {
+    external_consumer(json!({ "metadata": { "annotations": { HEARTBEAT_ANNOTATION: heartbeat } } }))
+}
+
+/// Returns the MaskReservation of the MaskConsumer's slot.
+fn reservation() -> Value {
+    json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskReservation",
+        "metadata": {
+            "name": "provider-0",
+            "namespace": "providers",
+            "uid": "reservation-uid",
+            "labels": { PROVIDER_UID_LABEL: "provider-uid" },
+        },
+        "spec": {
+            "name": "office-router",
+            "namespace": "external",
+            "uid": "consumer-uid",
+            "slot": 0,
+        },
+    })
+}
+
+/// Returns the MaskConsumer's copy of the credentials.
+fn secret() -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {
+            "name": "office-router-provider-uid",
+            "namespace": "external",
+            "labels": {
+                PROVIDER_UID_LABEL: "provider-uid",
+                MASK_NAME_LABEL: "office-router",
+                CONSUMER_UID_LABEL: "consumer-uid",
+            },
+            "ownerReferences": [{
+                "apiVersion": "vpn.beebs.dev/v1",
+                "kind": "MaskConsumer",
+                "name": "office-router",
+                "uid": "consumer-uid",
+            }],
+        },
+        "data": {},
+    })
+}
+
+/// Returns a running Pod in the MaskConsumer's namespace that happens
+/// to mount its credentials.
+fn pod() -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Pod",
+        "metadata": { "name": "debug", "namespace": "external", "uid": "pod-uid" },
+        "spec": {
+            "serviceAccountName": "default",
+            "containers": [],
+            "volumes": [{ "name": "vpn", "secret": { "secretName": "office-router-provider-uid" } }],
+        },
+        "status": { "phase": "Running" },
+    })
+}
+
+/// Returns the action decided for the MaskConsumer with the given objects in the cluster.
+async fn action(consumer: Value, objects: &[Value]) -> ConsumerAction {
+    let instance: MaskConsumer = serde_json::from_value(consumer).unwrap();
+    let context = ContextData::for_tests(
+        mock_cluster(vec![]).0,
+        None,
+        Arc::new(OperatorConfig::new(false)),
+    );
+    decide(objects, |client| {
+        let (instance, context) = (instance.clone(), &context);
+        async move { determine_action(client, "external", &instance, context).await }
+    })
+    .await
+}
+
+#[test]
+fn heartbeat_expiry() {
+    let created =
+        |minutes: i64| json!({ "metadata": { "creationTimestamp": minutes_ago(minutes) } });
+    let cases = [
+        ("renewed", with_heartbeat(json!(minutes_ago(1))), false),
+        ("stale", with_heartbeat(json!(minutes_ago(6))), true),
+        (
+            "from the future",
+            with_heartbeat(json!(minutes_ago(-10))),
+            false,
+        ),
+        (
+            "never renewed, new",
+            merged(with_heartbeat(Value::Null), created(1)),
+            false,
+        ),
+        (
+            "never renewed, old",
+            merged(with_heartbeat(Value::Null), created(60)),
+            true,
+        ),
+        (
+            "malformed, new",
+            merged(with_heartbeat(json!("yesterday")), created(1)),
+            false,
+        ),
+        (
+            "malformed, old",
+            merged(with_heartbeat(json!("yesterday")), created(60)),
+            true,
+        ),
+        (
+            "older format",
+            with_heartbeat(json!((Utc::now() - ChronoDuration::minutes(1))
+                .naive_utc()
+                .format("%Y-%m-%dT%H:%M:%S%.f")
+                .to_string())),
+            false,
+        ),
+        (
+            "not external",
+            merged(
+                with_heartbeat(Value::Null),
+                json!({ "spec": { "external": null } }),
+            ),
+            false,
+        ),
+    ];
+    for (case, consumer, expected) in cases {
+        let instance: MaskConsumer = serde_json::from_value(consumer).unwrap();
+        assert_eq!(
+            heartbeat_expired(&instance, DEFAULT_HEARTBEAT_TIMEOUT, Utc::now()),
+            expected,
+            "{}",
+            case
+        );
+    }
+}
+
+#[tokio::test]
+async fn expired_consumer_released() {
+    let expired =
+        ConsumerAction::HeartbeatExpired(messages::heartbeat_expired(&DEFAULT_HEARTBEAT_TIMEOUT));
+
+    // An assigned MaskConsumer gives up its slot.
+    let objects = [reservation(), secret()];
+    let stale = with_heartbeat(json!(minutes_ago(6)));
+    assert_eq!(action(stale, &objects).await, expired);
+
+    // So does one that is still waiting for a slot.
+    let waiting = merged(
+        with_heartbeat(json!(minutes_ago(6))),
+        json!({ "status": { "phase": "Waiting", "message": messages::WAITING, "provider": null } }),
+    );
+    assert_eq!(action(waiting, &[]).await, expired);
+
+    // A renewed heartbeat keeps the slot.
+    assert_eq!(
+        action(external_consumer(json!({})), &objects).await,
+        ConsumerAction::NoOp
+    );
+}
+
+#[tokio::test]
+async fn pods_not_listed() {
+    // The Pods in the namespace aren't listed, so a Pod that happens
+    // to use the credentials isn't recorded.
+    let instance: MaskConsumer = serde_json::from_value(external_consumer(json!({}))).unwrap();
+    let (client, captured) = mock_cluster(vec![reservation(), secret(), pod()]);
+    let context =
+        ContextData::for_tests(client.clone(), None, Arc::new(OperatorConfig::new(false)));
+    let action = determine_action(client, "external", &instance, &context)
+        .await
+        .unwrap();
+    assert_eq!(action, ConsumerAction::NoOp);
+    let paths: Vec<String> = captured
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|r| r.path.clone())
+        .collect();
+    assert!(!paths.iter().any(|p| p.contains("/pods")), "{:?}", paths);
+
+    // Nor does it hold up the deletion of the MaskConsumer.
+    let deleted = external_consumer(json!({
+        "metadata": { "deletionTimestamp": Utc::now().to_rfc3339() },
+        "spec": { "deletionPolicy": "WaitForPods" },
+    }));
+    assert_eq!(
+        action_for(deleted).await,
+        ConsumerAction::Delete {
+            delete_resource: false,
+            pods: vec![],
+        }
+    );
+}
+
+/// Returns the action decided for the MaskConsumer with its reservation,
+/// MaskProvider, credentials and a Pod using them in the cluster.
+async fn action_for(consumer: Value) -> ConsumerAction {
+    action(
+        consumer,
+        &[reservation(), provider_value(json!({})), secret(), pod()],
+    )
+    .await
+}
@@ -0,0 +1,189 @@
+use k8s_openapi::{api::core::v1::Secret, ByteString};
+use kube::{client::Client, Api};
+use std::collections::BTreeMap;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::util::*;
+use crate::{
+    consumers::{
+        actions::consumer_secret,
+        gluetun::CONFIG_KEY,
+        projection::{is_valid_key, project},
+    },
+    util::Error as OperatorError,
+};
+
+/// Returns Secret data with the given keys and values.
+fn data(entries: &[(&str, &str)]) -> BTreeMap<String, ByteString> {
+    entries
+        .iter()
+        .map(|(k, v)| (k.to_string(), ByteString(v.as_bytes().to_vec())))
+        .collect()
+}
+
+/// Returns a file projection with the given file names and source keys.
+fn projection(files: &[(&str, &str)]) -> BTreeMap<String, String> {
+    files
+        .iter()
+        .map(|(file, source)| (file.to_string(), source.to_string()))
+        .collect()
+}
+
+/// Returns the message of the error, which must be an invalid spec.
+fn invalid_spec_message(error: OperatorError) -> String {
+    match error {
+        OperatorError::InvalidSpec(message) => message,
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn file_names_validated() {
+    assert!(is_valid_key("username.txt"));
+    assert!(is_valid_key("vpn-auth_file.conf"));
+    assert!(!is_valid_key(""));
+    assert!(!is_valid_key("."));
+    assert!(!is_valid_key(".."));
+    assert!(!is_valid_key("auth/password.txt"));
+    assert!(!is_valid_key("pass word.txt"));
+    assert!(!is_valid_key(&"a".repeat(254)));
+}
+
+#[test]
+fn files_projected() {
+    let credentials = data(&[("OPENVPN_USER", "user"), ("OPENVPN_PASSWORD", "pass")]);
+    let projected = project(
+        credentials.clone(),
+        &credentials,
+        &projection(&[
+            ("username.txt", "OPENVPN_USER"),
+            ("password.txt", "OPENVPN_PASSWORD"),
+        ]),
+    )
+    .unwrap();
+    // The env keys remain present alongside the files.
+    assert_eq!(
+        projected,
+        data(&[
+            ("OPENVPN_USER", "user"),
+            ("OPENVPN_PASSWORD", "pass"),
+            ("username.txt", "user"),
+            ("password.txt", "pass"),
+        ])
+    );
+}
+
+#[test]
+fn invalid_projection_refused() {
+    let credentials = data(&[("OPENVPN_USER", "user")]);
+    let message = |files: &[(&str, &str)]| {
+        invalid_spec_message(
+            project(credentials.clone(), &credentials, &projection(files)).unwrap_err(),
+        )
+    };
+    assert_eq!(
+        message(&[("auth/user", "OPENVPN_USER")]),
+        "spec.fileProjection: 'auth/user' is not a valid file name, expected alphanumeric characters, '-', '_' or '.'"
+    );
+    assert_eq!(
+        message(&[("OPENVPN_USER", "OPENVPN_USER")]),
+        "spec.fileProjection: file 'OPENVPN_USER' would replace the key of the same name"
+    );
+    assert_eq!(
+        message(&[("password.txt", "OPENVPN_PASSWORD")]),
+        "spec.fileProjection: source key 'OPENVPN_PASSWORD' of file 'password.txt' is not among the copied credentials"
+    );
+}
+
+/// Returns a MaskConsumer assigned a MaskProvider with the given settings.
+fn projecting_consumer(settings: MaskDefaultsSpec) -> MaskConsumer {
+    let mut consumer = assigned(
+        mask_consumer("test-mask-0", "default", "consumer-uid"),
+        None,
+        assigned_provider(
+            "test-provider",
+            "default",
+            "provider-uid",
+            "test-mask-0-provider-uid",
+        ),
+    );
+    consumer.status.as_mut().unwrap().effective_settings = Some(settings);
+    consumer
+}
+
+#[test]
+fn secret_includes_projected_files() {
+    let provider_secret = Secret {
+        data: Some(data(&[
+            ("OPENVPN_USER", "user"),
+            ("OPENVPN_PASSWORD", "pass"),
+        ])),
+        ..Default::default()
+    };
+
+    // Files can be projected from keys rendered into the config file,
+    // and from keys that aren't copied as is.
+    let instance = projecting_consumer(MaskDefaultsSpec {
+        secret_format: Some(SecretFormat::GluetunToml),
+        file_projection: Some(projection(&[("password.txt", "OPENVPN_PASSWORD")])),
+        ..Default::default()
+    });
+    let secret = consumer_secret("default", &instance, provider_secret.clone()).unwrap();
+    let keys: Vec<_> = secret.data.unwrap().into_keys().collect();
+    assert_eq!(keys, vec![CONFIG_KEY.to_owned(), "password.txt".to_owned()]);
+
+    // Keys outside the allowlist can't be projected.
+    let instance = projecting_consumer(MaskDefaultsSpec {
+        secret_keys: Some(vec!["OPENVPN_USER".to_owned()]),
+        file_projection: Some(projection(&[("password.txt", "OPENVPN_PASSWORD")])),
+        ..Default::default()
+    });
+    assert!(consumer_secret("default", &instance, provider_secret).is_err());
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn file_projection() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+    let provider_ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let provider = create_test_provider(client.clone(), &namespace, &uid).await?;
+    provider_ready.await.unwrap()?;
+    let provider_data = get_provider_secret(client.clone(), &provider)
+        .await?
+        .data
+        .unwrap_or_default();
+    let (source, value) = provider_data.iter().next().unwrap();
+
+    // Project one of the credentials as a file.
+    let assigned = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(async move { wait_for_provider_assignment(client, &namespace, 0).await })
+    };
+    let mut mask = get_test_mask(&namespace, 0, &provider_name);
+    mask.spec.settings.file_projection = Some(projection(&[("credential.txt", source)]));
+    Api::<Mask>::namespaced(client.clone(), &namespace)
+        .create(&Default::default(), &mask)
+        .await?;
+    let assigned = assigned.await.unwrap()?;
+    let secret = wait_for_secret(client.clone(), assigned.secret, &namespace).await?;
+    let data = secret.data.unwrap_or_default();
+    assert_eq!(data.get("credential.txt"), Some(value));
+    // The env keys remain present.
+    assert!(provider_data.keys().all(|k| data.contains_key(k)));
+
+    // Garbage collect the test resources.
+    delete_test_mask(client.clone(), &namespace, 0).await?;
+    delete_test_provider(client.clone(), &namespace, &provider_name).await?;
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use serde_json::{json, Value};
+use vpn_types::*;
+
+use super::mock::*;
+use crate::util::finalizer::{self, FINALIZER_NAME};
+
+/// Finalizer added by another controller, e.g. backup tooling.
+const FOREIGN_FINALIZER: &str = "backup.example.com/finalizer";
+
+/// Returns a Mask with the given finalizers, or none if `None`.
+fn mask(finalizers: Option<Vec<&str>>) -> Value {
+    json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "Mask",
+        "metadata": {
+            "name": "my-mask",
+            "namespace": "default",
+            "finalizers": finalizers,
+        },
+        "spec": {},
+    })
+}
+
+/// Adds the foreign finalizer to the stored resource.
+fn add_foreign(stored: &mut Value) {
+    let finalizers = &mut stored["metadata"]["finalizers"];
+    if finalizers.is_null() {
+        *finalizers = json!([]);
+    }
+    finalizers
+        .as_array_mut()
+        .unwrap()
+        .push(FOREIGN_FINALIZER.into());
+}
+
+/// Returns the number of PATCH requests captured.
+fn patches(captured: &Captured) -> usize {
+    captured
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|r| r.method == "PATCH")
+        .count()
+}
+
+#[tokio::test]
+async fn add_keeps_foreign_finalizer() {
+    let (client, captured, stored) = mock_store(mask(Some(vec![FOREIGN_FINALIZER])));
+    finalizer::add::<Mask>(client, "my-mask", "default")
+        .await
+        .unwrap();
+    assert_eq!(
+        stored.lock().unwrap()["metadata"]["finalizers"],
+        json!([FOREIGN_FINALIZER, FINALIZER_NAME])
+    );
+    assert_eq!(patches(&captured), 1);
+
+    // Nothing is patched if the finalizer is already there.
+    let (client, captured, _) = mock_store(mask(Some(vec![FINALIZER_NAME])));
+    finalizer::add::<Mask>(client, "my-mask", "default")
+        .await
+        .unwrap();
+    assert_eq!(patches(&captured), 0);
+}
+
+#[tokio::test]
+async fn add_races_foreign_finalizer() {
+    // The other controller adds its finalizer after ours were read.
+    let (client, captured, stored) = mock_contended_store(mask(None), add_foreign);
+    finalizer::add::<Mask>(client, "my-mask", "default")
+        .await
+        .unwrap();
+    assert_eq!(
+        stored.lock().unwrap()["metadata"]["finalizers"],
+        json!([FOREIGN_FINALIZER, FINALIZER_NAME])
+    );
+    // The first patch was refused and retried on a fresh read.
+    assert_eq!(patches(&captured), 2);
+}
+
+#[tokio::test]
+async fn delete_keeps_foreign_finalizer() {
+    let (client, _, stored) = mock_store(mask(Some(vec![FINALIZER_NAME, FOREIGN_FINALIZER])));
+    finalizer::delete::<Mask>(client, "my-mask", "default")
+        .await
+        .unwrap();
+    assert_eq!(
+        stored.lock().unwrap()["metadata"]["finalizers"],
+        json!([FOREIGN_FINALIZER])
+    );
+
+    // Nothing is patched if the finalizer is already gone.
+    let (client, captured, _) = mock_store(mask(Some(vec![FOREIGN_FINALIZER])));
+    finalizer::delete::<Mask>(client, "my-mask", "default")
+        .await
+        .unwrap();
+    assert_eq!(patches(&captured), 0);
+}
+
+#[tokio::test]
+async fn delete_races_foreign_finalizer() {
+    // The other controller adds its finalizer after ours were read.
+    let (client, captured, stored) =
+        mock_contended_store(mask(Some(vec![FINALIZER_NAME])), add_foreign);
+    finalizer::delete::<Mask>(client, "my-mask", "default")
+        .await
+        .unwrap();
+    assert_eq!(
+        stored.lock().unwrap()["metadata"]["finalizers"],
+        json!([FOREIGN_FINALIZER])
+    );
+    assert_eq!(patches(&captured), 2);
+}
+
+#[tokio::test]
+async fn contention_gives_up() {
+    // The finalizers change before every patch, so the test never passes.
+    let (client, captured) = mock_method_routes(vec![
+        (
+            "GET",
+            "/apis/vpn.beebs.dev/v1/namespaces/default/masks/my-mask",
+            200,
+            mask(None),
+        ),
+        (
+            "PATCH",
+            "/apis/vpn.beebs.dev/v1/namespaces/default/masks/my-mask",
+            422,
+            status_failure(422),
+        ),
+    ]);
+    let result = finalizer::add::<Mask>(client, "my-mask", "default").await;
+    assert!(matches!(result, Err(kube::Error::Api(e)) if e.code == 422));
+    assert_eq!(patches(&captured), 5);
+}
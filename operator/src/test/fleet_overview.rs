@@ -0,0 +1,283 @@
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use k8s_openapi::{api::coordination::v1::Lease, apimachinery::pkg::apis::meta::v1::MicroTime};
+use kube::{client::Client, Api};
+use serde_json::{json, Value};
+use std::{sync::Arc, time::Duration};
+use vpn_types::*;
+
+use super::{
+    mock::{mock_client, mock_method_routes, status_failure},
+    util::*,
+};
+use crate::{
+    providers::overview::{summarize, write, FLEET_NAME},
+    util::lease::{is_available, LeaseLock},
+};
+
+/// Returns a cached MaskProvider in the phase with `active` of `max` slots used.
+fn cached_provider(
+    phase: Option<MaskProviderPhase>,
+    active: Option<usize>,
+    max: usize,
+) -> Arc<MaskProvider> {
+    let mut provider = mask_provider("test-provider", "default", "provider-uid");
+    provider.spec.max_slots = max;
+    provider.status = Some(MaskProviderStatus {
+        phase,
+        active_slots: active,
+        ..Default::default()
+    });
+    Arc::new(provider)
+}
+
+/// Returns a cached Mask in the phase.
+fn mask(phase: Option<MaskPhase>) -> Arc<Mask> {
+    Arc::new(Mask {
+        status: Some(MaskStatus {
+            phase,
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn aggregation() {
+    let providers = [
+        cached_provider(Some(MaskProviderPhase::Active), Some(2), 2),
+        cached_provider(Some(MaskProviderPhase::Ready), Some(0), 4),
+        cached_provider(Some(MaskProviderPhase::ErrVerifyFailed), None, 3),
+        cached_provider(Some(MaskProviderPhase::Verifying), Some(1), 1),
+        cached_provider(None, None, 5),
+    ];
+    let masks = [
+        mask(Some(MaskPhase::Active)),
+        mask(Some(MaskPhase::Waiting)),
+        mask(Some(MaskPhase::Waiting)),
+        mask(Some(MaskPhase::ErrNoProviders)),
+        mask(None),
+    ];
+    assert_eq!(
+        summarize(&providers, &masks),
+        VpnFleetStatus {
+            providers: 5,
+            // Only Ready and Active MaskProviders can be assigned.
+            ready: 2,
+            failed_verification: 1,
+            total_slots: 15,
+            // MaskProviders without a status use no slots.
+            used_slots: 3,
+            masks_waiting: 2,
+            writer: None,
+            last_updated: None,
+        }
+    );
+
+    // Nothing in the cluster, e.g. right after installing.
+    assert_eq!(summarize(&[], &[]), VpnFleetStatus::default());
+}
+
+/// Returns the timestamp as a Lease's MicroTime is written.
+fn micro_time(time: DateTime<Utc>) -> Value {
+    serde_json::to_value(MicroTime(time)).unwrap()
+}
+
+/// Returns a Lease held by `holder`, renewed the given number of
+/// seconds ago.
+fn lease(holder: Option<&str>, renewed_secs_ago: i64) -> Value {
+    json!({
+        "apiVersion": "coordination.k8s.io/v1",
+        "kind": "Lease",
+        "metadata": { "name": "lease", "namespace": "vpn", "resourceVersion": "7" },
+        "spec": {
+            "holderIdentity": holder,
+            "leaseDurationSeconds": 30,
+            "acquireTime": micro_time(Utc::now() - ChronoDuration::hours(1)),
+            "renewTime": micro_time(Utc::now() - ChronoDuration::seconds(renewed_secs_ago)),
+            "leaseTransitions": 2,
+        },
+    })
+}
+
+#[test]
+fn lease_availability() {
+    let now = Utc::now();
+    let cases = [
+        ("unheld", lease(None, 5), true),
+        ("held by this replica", lease(Some("me"), 5), true),
+        ("held by another", lease(Some("other"), 5), false),
+        ("lapsed", lease(Some("other"), 60), true),
+    ];
+    for (case, lease, expected) in cases {
+        let lease: Lease = serde_json::from_value(lease).unwrap();
+        assert_eq!(is_available(&lease, "me", now), expected, "{}", case);
+    }
+    assert!(is_available(&Lease::default(), "me", now));
+}
+
+#[tokio::test]
+async fn lease_election() {
+    let acquire = |routes| async move {
+        let (client, captured) = mock_method_routes(routes);
+        let lock = LeaseLock::new(client, "vpn", "lease", "me".to_owned());
+        let leader = lock.try_acquire(Utc::now()).await.unwrap();
+        let captured = captured.lock().unwrap().clone();
+        (leader, captured)
+    };
+
+    // Another replica's Lease is left alone.
+    let (leader, captured) = acquire(vec![(
+        "GET",
+        "/apis/coordination.k8s.io/v1/namespaces/vpn/leases/lease",
+        200,
+        lease(Some("other"), 5),
+    )])
+    .await;
+    assert!(!leader);
+    assert!(captured.iter().all(|r| r.method == "GET"));
+
+    // A lapsed Lease is taken over with its resourceVersion.
+    let (leader, captured) = acquire(vec![
+        (
+            "GET",
+            "/apis/coordination.k8s.io/v1/namespaces/vpn/leases/lease",
+            200,
+            lease(Some("other"), 60),
+        ),
+        (
+            "PUT",
+            "/apis/coordination.k8s.io/v1/namespaces/vpn/leases/lease",
+            200,
+            lease(Some("me"), 0),
+        ),
+    ])
+    .await;
+    assert!(leader);
+    let taken = &captured.last().unwrap().body;
+    assert_eq!(taken["metadata"]["resourceVersion"], "7");
+    assert_eq!(taken["spec"]["holderIdentity"], "me");
+    assert_eq!(taken["spec"]["leaseTransitions"], 3);
+
+    // Renewing keeps when the Lease was taken.
+    let held = lease(Some("me"), 5);
+    let (leader, captured) = acquire(vec![
+        (
+            "GET",
+            "/apis/coordination.k8s.io/v1/namespaces/vpn/leases/lease",
+            200,
+            held.clone(),
+        ),
+        (
+            "PUT",
+            "/apis/coordination.k8s.io/v1/namespaces/vpn/leases/lease",
+            200,
+            held.clone(),
+        ),
+    ])
+    .await;
+    assert!(leader);
+    let renewed = &captured.last().unwrap().body;
+    assert_eq!(renewed["spec"]["acquireTime"], held["spec"]["acquireTime"]);
+    assert_ne!(renewed["spec"]["renewTime"], held["spec"]["renewTime"]);
+    assert_eq!(renewed["spec"]["leaseTransitions"], 2);
+
+    // Losing the race to another replica isn't an error.
+    let (leader, _) = acquire(vec![
+        (
+            "GET",
+            "/apis/coordination.k8s.io/v1/namespaces/vpn/leases/lease",
+            200,
+            lease(Some("other"), 60),
+        ),
+        (
+            "PUT",
+            "/apis/coordination.k8s.io/v1/namespaces/vpn/leases/lease",
+            409,
+            status_failure(409),
+        ),
+    ])
+    .await;
+    assert!(!leader);
+
+    // A missing Lease is created.
+    let (leader, captured) = acquire(vec![(
+        "POST",
+        "/apis/coordination.k8s.io/v1/namespaces/vpn/leases",
+        201,
+        lease(Some("me"), 0),
+    )])
+    .await;
+    assert!(leader);
+    assert_eq!(
+        captured.last().unwrap().body["spec"]["holderIdentity"],
+        "me"
+    );
+}
+
+#[tokio::test]
+async fn status_written_by_holder() {
+    let (client, captured) = mock_client(json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "VpnFleet",
+        "metadata": { "name": FLEET_NAME },
+        "spec": {},
+    }));
+    let status = VpnFleetStatus {
+        providers: 2,
+        ready: 1,
+        total_slots: 4,
+        used_slots: 1,
+        ..Default::default()
+    };
+    write(client, "vpn-providers-abc", status).await.unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 2);
+
+    // The singleton is created if missing, then its status is written.
+    assert_eq!(captured[0].method, "PATCH");
+    assert!(captured[0]
+        .path
+        .starts_with("/apis/vpn.beebs.dev/v1/vpnfleets/default?"));
+    assert!(captured[1]
+        .path
+        .starts_with("/apis/vpn.beebs.dev/v1/vpnfleets/default/status?"));
+    let written = &captured[1].body["status"];
+    assert_eq!(written["providers"], 2);
+    assert_eq!(written["ready"], 1);
+    assert_eq!(written["totalSlots"], 4);
+    assert_eq!(written["usedSlots"], 1);
+    assert_eq!(written["writer"], "vpn-providers-abc");
+    assert!(written["lastUpdated"].is_string());
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn fleet_overview_written() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    create_test_provider(client.clone(), &namespace, &uid).await?;
+    wait_for_provider_phase(client.clone(), &namespace, MaskProviderPhase::Ready).await?;
+
+    // The test MaskProvider is counted once the overview catches up.
+    let api: Api<VpnFleet> = Api::all(client.clone());
+    let mut status = None;
+    for _ in 0..60 {
+        status = api
+            .get_opt(FLEET_NAME)
+            .await?
+            .and_then(|fleet| fleet.status)
+            .filter(|s| s.ready > 0);
+        if status.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    let status = status.expect("VpnFleet was not written");
+    assert!(status.providers >= status.ready);
+    assert!(status.total_slots >= MAX_SLOTS);
+    assert!(status.writer.is_some());
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::api::ObjectMeta;
+use std::collections::BTreeMap;
+use vpn_types::*;
+
+use super::mock::*;
+use crate::{
+    consumers::actions,
+    util::{
+        config::{ConfigMapRef, OperatorConfig, ASSIGNMENTS_FROZEN_KEY},
+        messages,
+    },
+};
+
+/// Returns an operator config ConfigMap with the given frozen value.
+fn config_map(assignments_frozen: &str) -> ConfigMap {
+    ConfigMap {
+        data: Some(BTreeMap::from([(
+            ASSIGNMENTS_FROZEN_KEY.to_owned(),
+            assignments_frozen.to_owned(),
+        )])),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn config_map_ref_is_parsed() {
+    assert_eq!(
+        "vpn/vpn-config".parse::<ConfigMapRef>(),
+        Ok(ConfigMapRef {
+            namespace: "vpn".to_owned(),
+            name: "vpn-config".to_owned(),
+        })
+    );
+    assert!("vpn-config".parse::<ConfigMapRef>().is_err());
+    assert!("/vpn-config".parse::<ConfigMapRef>().is_err());
+    assert!("vpn/".parse::<ConfigMapRef>().is_err());
+}
+
+#[test]
+fn freeze_toggled_at_runtime() {
+    let config = OperatorConfig::new(false);
+    assert!(!config.assignments_frozen());
+
+    config.update(Some(&config_map(" True ")));
+    assert!(config.assignments_frozen());
+    #[cfg(feature = "metrics")]
+    assert_eq!(crate::util::metrics::ASSIGNMENTS_FROZEN_GAUGE.get(), 1);
+
+    config.update(Some(&config_map("false")));
+    assert!(!config.assignments_frozen());
+    #[cfg(feature = "metrics")]
+    assert_eq!(crate::util::metrics::ASSIGNMENTS_FROZEN_GAUGE.get(), 0);
+
+    // Deleting the ConfigMap restores the default.
+    config.update(Some(&config_map("true")));
+    config.update(None);
+    assert!(!config.assignments_frozen());
+}
+
+#[test]
+fn freeze_flag_overrides_config_map() {
+    let config = OperatorConfig::new(true);
+    config.update(Some(&config_map("false")));
+    assert!(config.assignments_frozen());
+    config.update(None);
+    assert!(config.assignments_frozen());
+}
+
+#[tokio::test]
+async fn frozen_consumer_is_told_why() {
+    let consumer = MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some("test-consumer".to_owned()),
+            namespace: Some("default".to_owned()),
+            ..Default::default()
+        },
+        status: Some(MaskConsumerStatus {
+            phase: Some(MaskConsumerPhase::Pending),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let (client, captured) = mock_client(serde_json::to_value(&consumer).unwrap());
+    actions::assignments_frozen(client, &consumer)
+        .await
+        .unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 1);
+    assert_eq!(
+        patch_op(&captured[0], "/status/phase"),
+        Some(&serde_json::json!("Waiting"))
+    );
+    assert_eq!(
+        patch_op(&captured[0], "/status/message"),
+        Some(&serde_json::json!(messages::ASSIGNMENTS_FROZEN))
+    );
+}
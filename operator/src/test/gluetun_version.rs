@@ -0,0 +1,217 @@
+use k8s_openapi::api::core::v1::Secret;
+use kube::{api::ObjectMeta, client::Client, Api};
+use tokio::spawn;
+use vpn_render::{image_tag, parse_version, DEFAULT_VPN_IMAGE, VPN_CONTAINER_NAME};
+use vpn_types::*;
+
+use super::util::*;
+use crate::{
+    consumers::actions::consumer_secret,
+    providers::{
+        actions::verify_pod,
+        gluetun_version::{compatibility_warnings, renamed_key_warnings},
+    },
+    util::GLUETUN_VERSION_ANNOTATION,
+};
+
+/// Returns the keys as owned strings.
+fn keys(keys: &[&str]) -> Vec<String> {
+    keys.iter().map(|k| k.to_string()).collect()
+}
+
+#[test]
+fn versions_parsed() {
+    assert_eq!(parse_version("v3.38.0"), Some((3, 38, 0)));
+    assert_eq!(parse_version("3.38"), Some((3, 38, 0)));
+    assert_eq!(parse_version("v3"), Some((3, 0, 0)));
+    assert_eq!(parse_version("latest"), None);
+    assert_eq!(parse_version("v3.38.0-rc1"), None);
+    assert_eq!(parse_version("3.38.0.1"), None);
+
+    // Versions are tagged with a leading `v`, other tags are kept.
+    assert_eq!(image_tag("3.38.0"), "v3.38.0");
+    assert_eq!(image_tag("v3.38.0"), "v3.38.0");
+    assert_eq!(image_tag("latest"), "latest");
+}
+
+#[test]
+fn renamed_keys_warned() {
+    // Keys that were renamed before the declared version.
+    assert_eq!(
+        renamed_key_warnings("v3.38.0", &keys(&["VPNSP", "OPENVPN_USER"])),
+        vec!["secret key VPNSP was renamed to VPN_SERVICE_PROVIDER in gluetun v3.29.0"]
+    );
+
+    // Keys that only exist after the declared version.
+    assert_eq!(
+        renamed_key_warnings("v3.28.0", &keys(&["VPN_SERVICE_PROVIDER", "VPNSP"])),
+        vec![
+            "secret key VPN_SERVICE_PROVIDER is not recognized before gluetun v3.29.0, use VPNSP instead"
+        ]
+    );
+
+    // The version the key was renamed in expects the new name.
+    assert!(renamed_key_warnings("v3.36.0", &keys(&["VPN_PORT_FORWARDING"])).is_empty());
+    assert_eq!(
+        renamed_key_warnings("v3.36.0", &keys(&["PORT_FORWARDING"])).len(),
+        1
+    );
+
+    // Unknown versions aren't warned about.
+    assert!(renamed_key_warnings("latest", &keys(&["VPNSP"])).is_empty());
+}
+
+/// Returns a MaskProvider for the gluetun version, whose Masks
+/// are only handed the given keys.
+fn versioned_provider(version: Option<&str>, secret_keys: Option<&[&str]>) -> MaskProvider {
+    let mut provider = mask_provider("test-provider", "default", "provider-uid");
+    provider.spec.gluetun_version = version.map(str::to_owned);
+    provider.spec.mask_defaults = secret_keys.map(|k| MaskDefaultsSpec {
+        secret_keys: Some(keys(k)),
+        ..Default::default()
+    });
+    provider
+}
+
+#[test]
+fn compatibility_checks_secret_keys() {
+    // Nothing to check without both a version and an allowlist.
+    assert!(compatibility_warnings(&versioned_provider(None, Some(&["VPNSP"]))).is_empty());
+    assert!(compatibility_warnings(&versioned_provider(Some("v3.38.0"), None)).is_empty());
+    assert_eq!(
+        compatibility_warnings(&versioned_provider(Some("v3.38.0"), Some(&["VPNSP"]))).len(),
+        1
+    );
+}
+
+/// Returns the image of the VPN container verifying the MaskProvider.
+fn verify_image(provider: &MaskProvider) -> String {
+    let consumer = MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some("test-provider-verify".to_owned()),
+            uid: Some("consumer-uid".to_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let secret = Secret {
+        metadata: ObjectMeta {
+            name: Some("test-provider-verify-provider-uid".to_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    verify_pod("test-provider", "default", provider, &secret, &consumer)
+        .unwrap()
+        .spec
+        .unwrap()
+        .containers
+        .into_iter()
+        .find(|c| c.name == VPN_CONTAINER_NAME)
+        .unwrap()
+        .image
+        .unwrap()
+}
+
+#[test]
+fn verification_uses_declared_version() {
+    assert_eq!(
+        verify_image(&versioned_provider(None, None)),
+        DEFAULT_VPN_IMAGE
+    );
+    assert_eq!(
+        verify_image(&versioned_provider(Some("3.38.0"), None)),
+        "qmcgaw/gluetun:v3.38.0"
+    );
+    assert_eq!(
+        verify_image(&versioned_provider(Some("latest"), None)),
+        "qmcgaw/gluetun:latest"
+    );
+}
+
+#[test]
+fn copied_secret_annotated() {
+    let consumer = |version: Option<&str>| {
+        assigned(
+            mask_consumer("test-mask-0", "default", "consumer-uid"),
+            None,
+            AssignedProvider {
+                gluetun_version: version.map(str::to_owned),
+                ..assigned_provider(
+                    "test-provider",
+                    "default",
+                    "provider-uid",
+                    "test-mask-0-provider-uid",
+                )
+            },
+        )
+    };
+    let secret = consumer_secret("default", &consumer(Some("v3.38.0")), Secret::default()).unwrap();
+    assert_eq!(
+        secret
+            .metadata
+            .annotations
+            .unwrap()
+            .get(GLUETUN_VERSION_ANNOTATION)
+            .map(String::as_str),
+        Some("v3.38.0")
+    );
+    let secret = consumer_secret("default", &consumer(None), Secret::default()).unwrap();
+    assert!(!secret
+        .metadata
+        .annotations
+        .unwrap()
+        .contains_key(GLUETUN_VERSION_ANNOTATION));
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn gluetun_version_propagated() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+
+    // Create a MaskProvider for the version of gluetun that is verified by default.
+    let ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let version = DEFAULT_VPN_IMAGE.split(':').nth(1).unwrap();
+    let mut provider = get_test_provider(client.clone(), &provider_name, &namespace).await?;
+    provider.spec.gluetun_version = Some(version.to_owned());
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let provider = api.create(&Default::default(), &provider).await?;
+    create_test_provider_secret(client.clone(), &namespace, &provider).await?;
+    ready.await.unwrap()?;
+
+    // The version is recorded on the assignment and the copied Secret.
+    create_test_mask(client.clone(), &namespace, 0, &provider_name).await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+    let consumer_api: Api<MaskConsumer> = Api::namespaced(client.clone(), &namespace);
+    let assigned = consumer_api
+        .get(&format!("{}-0", MASK_NAME))
+        .await?
+        .status
+        .unwrap()
+        .provider
+        .unwrap();
+    assert_eq!(assigned.gluetun_version.as_deref(), Some(version));
+    let secret_api: Api<Secret> = Api::namespaced(client.clone(), &namespace);
+    let secret = secret_api.get(&assigned.secret).await?;
+    assert_eq!(
+        secret
+            .metadata
+            .annotations
+            .as_ref()
+            .and_then(|a| a.get(GLUETUN_VERSION_ANNOTATION))
+            .map(String::as_str),
+        Some(version)
+    );
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use kube::{client::Client, core::ErrorResponse};
+use serde_json::json;
+
+use super::mock::*;
+use crate::{
+    consumers::actions,
+    util::{Error, ErrorContext, ERROR_REQUEUE, THROTTLED_REQUEUE},
+};
+
+/// Returns the error for an error response from the API server.
+fn api_error(code: u16, reason: &str) -> Error {
+    kube::Error::Api(ErrorResponse {
+        status: "Failure".to_owned(),
+        message: format!("the server responded with {}", code),
+        reason: reason.to_owned(),
+        code,
+    })
+    .into()
+}
+
+#[test]
+fn throttling_classified() {
+    for (code, reason) in [(429, "TooManyRequests"), (503, "ServiceUnavailable")] {
+        let error = api_error(code, reason);
+        assert_eq!(error.status_code(), Some(code));
+        assert_eq!(error.reason(), Some(reason));
+        assert!(error.is_throttled());
+        assert_eq!(error.requeue_after(), THROTTLED_REQUEUE);
+        assert_eq!(error.code_label(), code.to_string());
+    }
+    for (code, reason) in [(500, "InternalError"), (404, "NotFound"), (409, "Conflict")] {
+        let error = api_error(code, reason);
+        assert_eq!(error.status_code(), Some(code));
+        assert!(!error.is_throttled());
+        assert_eq!(error.requeue_after(), ERROR_REQUEUE);
+    }
+
+    // Errors that aren't error responses have no code.
+    let error = Error::UserInputError("bad".to_owned());
+    assert_eq!(error.status_code(), None);
+    assert_eq!(error.reason(), None);
+    assert!(!error.is_throttled());
+    assert_eq!(error.code_label(), "");
+}
+
+#[test]
+fn context_preserved_through_wrapping() {
+    let result: Result<(), Error> =
+        Err(api_error(429, "TooManyRequests")).context_kind_name("Secret", "my-vpn-secret");
+    let error = Error::action("CreateSecret", result.unwrap_err());
+
+    // The code and reason are still accessible through the context.
+    assert_eq!(error.status_code(), Some(429));
+    assert_eq!(error.reason(), Some("TooManyRequests"));
+    assert!(error.is_throttled());
+    assert!(matches!(error.root(), Error::KubeError { .. }));
+
+    // The message says what failed, on which resource, and why.
+    assert_eq!(
+        error.to_string(),
+        "CreateSecret failed: Secret my-vpn-secret: Kubernetes reported error 429 \
+         TooManyRequests: the server responded with 429"
+    );
+}
+
+#[test]
+fn other_kube_errors_displayed_as_before() {
+    let error: Error = kube::Error::LinesCodecMaxLineLengthExceeded.into();
+    assert_eq!(
+        error.to_string(),
+        "Kubernetes reported error: Error finding newline character"
+    );
+    assert_eq!(error.status_code(), None);
+}
+
+#[test]
+fn backtrace_optional() {
+    let error = Error::action(
+        "CreateSecret",
+        kube::Error::LinesCodecMaxLineLengthExceeded.into(),
+    );
+
+    // The message never includes the backtrace...
+    assert_eq!(
+        error.to_string(),
+        "CreateSecret failed: Kubernetes reported error: Error finding newline character"
+    );
+
+    // ...which is only captured with the `backtrace` feature.
+    if !cfg!(feature = "backtrace") {
+        assert!(error.backtrace().is_none());
+        assert!(format!("{:?}", error).contains("<not captured>"));
+    }
+}
+
+#[tokio::test]
+async fn throttled_request_has_context() {
+    let (service, _) = mock_service(
+        429,
+        json!({
+            "kind": "Status",
+            "apiVersion": "v1",
+            "status": "Failure",
+            "message": "too many requests, please try again later",
+            "reason": "TooManyRequests",
+            "code": 429,
+        }),
+    );
+    let client = Client::new(service, "default");
+    let error = actions::delete(client, "test-consumer", "default")
+        .await
+        .unwrap_err();
+    assert!(error.is_throttled());
+    assert_eq!(error.reason(), Some("TooManyRequests"));
+    assert!(error
+        .to_string()
+        .starts_with("MaskConsumer test-consumer: Kubernetes reported error 429"));
+}
@@ -0,0 +1,76 @@
+use kube::api::ObjectMeta;
+use vpn_types::*;
+
+use super::mock::*;
+use crate::util::{
+    patch::{patch_last_error, patch_status},
+    Error,
+};
+
+/// Returns an Active Mask, optionally carrying a failed action.
+fn test_mask(last_error: Option<LastError>) -> Mask {
+    Mask {
+        metadata: ObjectMeta {
+            name: Some("test-mask".to_owned()),
+            namespace: Some("default".to_owned()),
+            ..Default::default()
+        },
+        status: Some(MaskStatus {
+            phase: Some(MaskPhase::Active),
+            last_error,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn last_error_is_recorded_and_cleared() {
+    let last_error = LastError {
+        action: "CreateConsumer".to_owned(),
+        message: "forbidden".to_owned(),
+        at: chrono::Utc::now().to_rfc3339(),
+    };
+
+    // Recording the failed action patches only the lastError field.
+    let mask = test_mask(None);
+    let (client, captured) = mock_client(serde_json::to_value(&mask).unwrap());
+    patch_last_error(client, &mask, last_error.clone())
+        .await
+        .unwrap();
+    {
+        let captured = captured.lock().unwrap();
+        assert_eq!(captured[0].method, "PATCH");
+        assert!(captured[0]
+            .path
+            .contains("/namespaces/default/masks/test-mask/status"));
+        assert_eq!(
+            patch_op(&captured[0], "/status/lastError"),
+            Some(&serde_json::to_value(&last_error).unwrap())
+        );
+        assert_eq!(patch_op(&captured[0], "/status/lastUpdated"), None);
+    }
+
+    // The next successful status update clears it.
+    let mask = test_mask(Some(last_error));
+    let (client, captured) = mock_client(serde_json::to_value(&mask).unwrap());
+    patch_status(client, &mask, |status| {
+        status.phase = Some(MaskPhase::Waiting);
+    })
+    .await
+    .unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(
+        patch_op(&captured[0], "/status/lastError"),
+        Some(&serde_json::Value::Null)
+    );
+}
+
+#[test]
+fn action_error_names_action() {
+    let error = Error::action("CreateSecret", Error::UserInputError("bad".to_owned()));
+    assert_eq!(
+        error.to_string(),
+        "CreateSecret failed: Invalid user input: bad"
+    );
+}
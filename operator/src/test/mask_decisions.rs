@@ -0,0 +1,447 @@
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, time::Duration};
+use vpn_types::*;
+
+use super::mock::{consumer_value, decide, merged};
+use crate::{
+    masks::reconcile::{determine_action, MaskAction},
+    util::{finalizer::FINALIZER_NAME, messages, PROVIDER_UID_LABEL},
+};
+
+/// Default of `--status-freshness-interval`.
+const FRESHNESS: Duration = Duration::from_secs(600);
+
+/// Returns an Active Mask that last reserved slot 0 of `provider-uid`,
+/// with `patch` merged into it.
+fn mask(patch: Value) -> Value {
+    let mask = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "Mask",
+        "metadata": {
+            "name": "mask-0",
+            "namespace": "default",
+            "uid": "mask-uid",
+            "labels": { "team": "a" },
+            "finalizers": [FINALIZER_NAME],
+        },
+        "spec": {},
+        "status": {
+            "phase": "Active",
+            "message": messages::ACTIVE,
+            "lastUpdated": chrono::Utc::now().to_rfc3339(),
+            "lastSlot": 0,
+            "lastProviderUid": "provider-uid",
+        },
+    });
+    merged(mask, patch)
+}
+
+/// Returns the Mask's Active MaskConsumer assigned slot 0 of
+/// `provider-uid`, labelled for team `a`, with `patch` merged into it.
+fn team_consumer(patch: Value) -> Value {
+    let labels = json!({ "metadata": { "labels": { "team": "a" } } });
+    merged(consumer_value(labels), patch)
+}
+
+/// Returns a MaskReservation holding slot 0 of `provider-uid` for the
+/// Mask's MaskConsumer.
+fn reservation() -> Value {
+    json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskReservation",
+        "metadata": {
+            "name": "provider-0",
+            "namespace": "providers",
+            "labels": { PROVIDER_UID_LABEL: "provider-uid" },
+        },
+        "spec": { "name": "mask-0", "namespace": "default", "uid": "consumer-uid", "slot": 0 },
+    })
+}
+
+/// Returns the given slot of `provider-uid`.
+fn slot(slot: usize) -> Option<SlotAffinity> {
+    Some(SlotAffinity {
+        provider_uid: "provider-uid".to_owned(),
+        slot,
+    })
+}
+
+/// Returns the MaskProvider assigned to the MaskConsumer.
+fn providers(consumer: &Value) -> Vec<AssignedProvider> {
+    vec![serde_json::from_value(consumer["status"]["provider"].clone()).unwrap()]
+}
+
+/// Returns the action decided for the Mask with the given objects in
+/// the cluster.
+async fn action(mask: Value, objects: &[Value]) -> MaskAction {
+    let instance: Mask = serde_json::from_value(mask).unwrap();
+    decide(objects, |client| {
+        let instance = instance.clone();
+        async move {
+            determine_action(
+                client,
+                "mask-0",
+                "default",
+                &instance,
+                FRESHNESS,
+                chrono::Utc::now(),
+                false,
+            )
+            .await
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn deleted_mask_actions() {
+    let deleted = || mask(json!({ "metadata": { "deletionTimestamp": "2023-01-01T00:00:00Z" } }));
+    let cases = [
+        (
+            "consumer alive",
+            vec![team_consumer(json!({})), reservation()],
+            MaskAction::DeleteConsumer("mask-0".to_owned(), slot(0)),
+        ),
+        (
+            "consumer being deleted",
+            vec![
+                team_consumer(
+                    json!({ "metadata": { "deletionTimestamp": "2023-01-01T00:00:00Z" } }),
+                ),
+                reservation(),
+            ],
+            MaskAction::WaitForRelease,
+        ),
+        (
+            "slot still reserved",
+            vec![reservation()],
+            MaskAction::WaitForRelease,
+        ),
+        ("nothing left", vec![], MaskAction::Delete),
+    ];
+    for (case, objects, expected) in cases {
+        assert_eq!(action(deleted(), &objects).await, expected, "{}", case);
+    }
+}
+
+#[tokio::test]
+async fn mask_actions() {
+    let stale = (chrono::Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+    let fresh = (chrono::Utc::now() - chrono::Duration::minutes(5)).to_rfc3339();
+    let expired = (chrono::Utc::now() - chrono::Duration::minutes(11)).to_rfc3339();
+    let cases = [
+        (
+            "missing finalizer",
+            mask(json!({ "metadata": { "finalizers": null } })),
+            vec![team_consumer(json!({}))],
+            MaskAction::Pending,
+        ),
+        (
+            "missing status",
+            mask(json!({ "status": null })),
+            vec![team_consumer(json!({}))],
+            MaskAction::Pending,
+        ),
+        (
+            "consumer missing",
+            mask(json!({})),
+            vec![],
+            MaskAction::CreateConsumer(0),
+        ),
+        (
+            "consumer of another mask",
+            mask(json!({})),
+            vec![team_consumer(json!({ "metadata": { "ownerReferences": [{
+                "apiVersion": "vpn.beebs.dev/v1",
+                "kind": "Mask",
+                "name": "mask-0",
+                "uid": "other-uid",
+            }]}}))],
+            MaskAction::CreateConsumer(0),
+        ),
+        (
+            "anti-affinity joined",
+            mask(json!({ "spec": { "antiAffinity": { "group": "replicas" } } })),
+            vec![team_consumer(json!({}))],
+            MaskAction::SyncAntiAffinity(
+                "mask-0".to_owned(),
+                Some(MaskAntiAffinity {
+                    group: "replicas".to_owned(),
+                }),
+            ),
+        ),
+        (
+            "label added",
+            mask(json!({ "metadata": { "labels": { "tier": "b" } } })),
+            vec![team_consumer(json!({}))],
+            MaskAction::SyncLabels(
+                "mask-0".to_owned(),
+                BTreeMap::from([("tier".to_owned(), Some("b".to_owned()))]),
+            ),
+        ),
+        (
+            "label stripped from consumer",
+            mask(json!({})),
+            vec![team_consumer(
+                json!({ "metadata": { "labels": { "team": null } } }),
+            )],
+            MaskAction::SyncLabels(
+                "mask-0".to_owned(),
+                BTreeMap::from([("team".to_owned(), Some("a".to_owned()))]),
+            ),
+        ),
+        (
+            "service added",
+            mask(
+                json!({ "spec": { "service": { "port": 8888, "selectorLabels": { "app": "scraper" } } } }),
+            ),
+            vec![team_consumer(json!({}))],
+            MaskAction::SyncService(
+                "mask-0".to_owned(),
+                Some(MaskServiceSpec {
+                    port: 8888,
+                    target_port: None,
+                    selector_labels: [("app".to_owned(), "scraper".to_owned())].into(),
+                }),
+            ),
+        ),
+        (
+            "service removed",
+            mask(json!({})),
+            vec![team_consumer(
+                json!({ "spec": { "service": { "port": 8888, "selectorLabels": { "app": "scraper" } } } }),
+            )],
+            MaskAction::SyncService("mask-0".to_owned(), None),
+        ),
+        (
+            "consumer pending",
+            mask(json!({})),
+            vec![team_consumer(json!({ "status": { "phase": "Pending" } }))],
+            MaskAction::Waiting(None),
+        ),
+        (
+            "consumer terminating",
+            mask(json!({})),
+            vec![team_consumer(
+                json!({ "status": { "phase": "Terminating" } }),
+            )],
+            MaskAction::Withdrawing("mask-0-provider-uid".to_owned()),
+        ),
+        (
+            "consumer terminating unassigned",
+            mask(json!({ "status": { "phase": "Waiting", "message": messages::WAITING } })),
+            vec![team_consumer(
+                json!({ "status": { "phase": "Terminating", "provider": null } }),
+            )],
+            MaskAction::NoOp,
+        ),
+        (
+            "consumer waiting",
+            mask(json!({})),
+            vec![team_consumer(
+                json!({ "status": { "phase": "Waiting", "message": "All slots are in use." } }),
+            )],
+            MaskAction::Waiting(Some("All slots are in use.".to_owned())),
+        ),
+        (
+            "consumer over quota",
+            mask(json!({})),
+            vec![team_consumer(json!({ "status": {
+                "phase": "ErrQuotaExceeded",
+                "message": messages::err_quota_exceeded("team", 2),
+                "provider": null,
+            }}))],
+            MaskAction::ErrQuotaExceeded(Some(messages::err_quota_exceeded("team", 2))),
+        ),
+        (
+            "waiting mirrored",
+            mask(json!({ "status": { "phase": "Waiting", "message": "All slots are in use." } })),
+            vec![team_consumer(
+                json!({ "status": { "phase": "Waiting", "message": "All slots are in use." } }),
+            )],
+            MaskAction::NoOp,
+        ),
+        (
+            "new slot reserved",
+            mask(json!({})),
+            vec![team_consumer(
+                json!({ "status": { "provider": { "slot": 1 } } }),
+            )],
+            MaskAction::Active {
+                slot: slot(1),
+                provider_namespace: Some("providers".to_owned()),
+                providers: providers(&team_consumer(
+                    json!({ "status": { "provider": { "slot": 1 } } }),
+                )),
+            },
+        ),
+        (
+            "stale status",
+            mask(json!({ "status": { "lastUpdated": stale } })),
+            vec![team_consumer(json!({}))],
+            MaskAction::Active {
+                slot: slot(0),
+                provider_namespace: Some("providers".to_owned()),
+                providers: providers(&team_consumer(json!({}))),
+            },
+        ),
+        (
+            // Older than the probe interval, but within the freshness window.
+            "unchanged within window",
+            mask(json!({ "status": { "lastUpdated": fresh } })),
+            vec![team_consumer(json!({}))],
+            MaskAction::NoOp,
+        ),
+        (
+            "unchanged past window",
+            mask(json!({ "status": { "lastUpdated": expired } })),
+            vec![team_consumer(json!({}))],
+            MaskAction::Active {
+                slot: slot(0),
+                provider_namespace: Some("providers".to_owned()),
+                providers: providers(&team_consumer(json!({}))),
+            },
+        ),
+        (
+            "active mirrored",
+            mask(json!({})),
+            vec![team_consumer(json!({}))],
+            MaskAction::NoOp,
+        ),
+        (
+            "no providers",
+            mask(json!({})),
+            vec![team_consumer(
+                json!({ "status": { "phase": "ErrNoProviders", "provider": null } }),
+            )],
+            MaskAction::ErrNoProviders,
+        ),
+        (
+            "namespace not opted in",
+            mask(json!({})),
+            vec![team_consumer(
+                json!({ "status": { "phase": "ErrNamespaceNotOptedIn", "message": "not opted in" } }),
+            )],
+            MaskAction::ErrNamespaceNotOptedIn(Some("not opted in".to_owned())),
+        ),
+        (
+            "secret conflict",
+            mask(json!({})),
+            vec![team_consumer(
+                json!({ "status": { "phase": "ErrSecretConflict", "message": "in the way" } }),
+            )],
+            MaskAction::ErrSecretConflict(Some("in the way".to_owned())),
+        ),
+        (
+            "missing labels",
+            mask(json!({})),
+            vec![team_consumer(
+                json!({ "status": { "phase": "ErrMissingLabels", "message": "tier" } }),
+            )],
+            MaskAction::ErrMissingLabels(Some("tier".to_owned())),
+        ),
+        (
+            "invalid spec",
+            mask(json!({})),
+            vec![team_consumer(
+                json!({ "status": { "phase": "ErrInvalidSpec", "message": "spec.fileProjection" } }),
+            )],
+            MaskAction::ErrInvalidSpec("spec.fileProjection".to_owned()),
+        ),
+        (
+            "consumer without phase",
+            mask(json!({})),
+            vec![team_consumer(json!({ "status": null }))],
+            MaskAction::NoOp,
+        ),
+    ];
+    for (case, mask, objects, expected) in cases {
+        assert_eq!(action(mask, &objects).await, expected, "{}", case);
+    }
+}
+
+#[tokio::test]
+async fn withdrawal_sequence() {
+    let terminating = || team_consumer(json!({ "status": { "phase": "Terminating" } }));
+    let withdrawing = mask(json!({ "status": {
+        "phase": "Terminating",
+        "message": messages::assignment_withdrawn("mask-0-provider-uid"),
+    } }));
+
+    // The Active Mask warns that the credentials are being withdrawn...
+    assert_eq!(
+        action(mask(json!({})), &[terminating()]).await,
+        MaskAction::Withdrawing("mask-0-provider-uid".to_owned())
+    );
+
+    // ...and stays Terminating for as long as the MaskConsumer exists...
+    assert_eq!(
+        action(withdrawing.clone(), &[terminating()]).await,
+        MaskAction::NoOp
+    );
+
+    // ...only waiting for a slot once it's gone.
+    assert_eq!(
+        action(withdrawing, &[]).await,
+        MaskAction::CreateConsumer(0)
+    );
+}
+
+#[tokio::test]
+async fn provider_lost_sequence() {
+    let message = messages::err_provider_lost("providers", "provider");
+    let lost = mask(json!({ "status": { "phase": "ErrProviderLost", "message": message } }));
+
+    // The Active Mask shows that its MaskProvider is gone...
+    assert_eq!(
+        action(
+            mask(json!({})),
+            &[team_consumer(
+                json!({ "status": { "phase": "ErrProviderLost", "message": message } })
+            )]
+        )
+        .await,
+        MaskAction::ErrProviderLost(Some(message.clone()))
+    );
+
+    // ...and keeps showing it while the MaskConsumer is deleted...
+    assert_eq!(
+        action(
+            lost.clone(),
+            &[team_consumer(
+                json!({ "status": { "phase": "Terminating" } })
+            )]
+        )
+        .await,
+        MaskAction::NoOp
+    );
+    assert_eq!(
+        action(lost.clone(), &[]).await,
+        MaskAction::CreateConsumer(0)
+    );
+
+    // ...and recreated to wait for another MaskProvider...
+    for status in [
+        json!({ "phase": "Pending", "provider": null }),
+        json!({ "phase": "Waiting", "message": "All slots are in use.", "provider": null }),
+    ] {
+        assert_eq!(
+            action(lost.clone(), &[team_consumer(json!({ "status": status }))]).await,
+            MaskAction::NoOp
+        );
+    }
+
+    // ...until it's assigned one.
+    let assigned = team_consumer(json!({ "status": { "provider": { "uid": "new-uid" } } }));
+    assert_eq!(
+        action(lost, std::slice::from_ref(&assigned)).await,
+        MaskAction::Active {
+            slot: Some(SlotAffinity {
+                provider_uid: "new-uid".to_owned(),
+                slot: 0,
+            }),
+            provider_namespace: Some("providers".to_owned()),
+            providers: providers(&assigned),
+        }
+    );
+}
@@ -0,0 +1,74 @@
+use k8s_openapi::{api::core::v1::Secret, ByteString};
+use kube::api::ObjectMeta;
+use std::collections::BTreeMap;
+use vpn_types::*;
+
+use crate::consumers::actions::consumer_secret;
+
+fn settings(secret_keys: Option<&[&str]>, immutable_secret: Option<bool>) -> MaskDefaultsSpec {
+    MaskDefaultsSpec {
+        secret_keys: secret_keys.map(|keys| keys.iter().map(|k| k.to_string()).collect()),
+        immutable_secret,
+        secret_format: None,
+        file_projection: None,
+    }
+}
+
+fn provider_secret() -> Secret {
+    let mut data = BTreeMap::new();
+    data.insert("VPN_USERNAME".to_owned(), ByteString(b"user".to_vec()));
+    data.insert("VPN_PASSWORD".to_owned(), ByteString(b"pass".to_vec()));
+    Secret {
+        data: Some(data),
+        ..Default::default()
+    }
+}
+
+fn assigned_consumer(effective_settings: Option<MaskDefaultsSpec>) -> MaskConsumer {
+    MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some("test-mask-0".to_owned()),
+            namespace: Some("default".to_owned()),
+            uid: Some("consumer-uid".to_owned()),
+            ..Default::default()
+        },
+        status: Some(MaskConsumerStatus {
+            provider: Some(AssignedProvider {
+                name: "test-provider".to_owned(),
+                namespace: "default".to_owned(),
+                uid: "provider-uid".to_owned(),
+                slot: 0,
+                reservation: "reservation-uid".to_owned(),
+                secret: "test-mask-0-provider-uid".to_owned(),
+                ..Default::default()
+            }),
+            effective_settings,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn secret_uses_settings_resolved_at_assignment() {
+    // The consumer was assigned while the provider defaulted to
+    // copying only the username into a mutable Secret.
+    let resolved =
+        MaskDefaultsSpec::default().with_defaults(Some(&settings(Some(&["VPN_USERNAME"]), None)));
+    let consumer = assigned_consumer(Some(resolved));
+
+    // The provider's defaults have changed since, but the consumer's
+    // Secret is still built from the settings recorded in its status.
+    let secret = consumer_secret("default", &consumer, provider_secret()).unwrap();
+    let keys: Vec<_> = secret.data.unwrap().into_keys().collect();
+    assert_eq!(keys, vec!["VPN_USERNAME".to_owned()]);
+    assert_eq!(secret.immutable, None);
+}
+
+#[test]
+fn secret_is_immutable_when_requested() {
+    let consumer = assigned_consumer(Some(settings(None, Some(true))));
+    let secret = consumer_secret("default", &consumer, provider_secret()).unwrap();
+    assert_eq!(secret.data.unwrap().len(), 2);
+    assert_eq!(secret.immutable, Some(true));
+}
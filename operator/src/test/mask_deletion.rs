@@ -0,0 +1,126 @@
+use kube::api::ObjectMeta;
+use serde_json::json;
+use vpn_types::*;
+
+use super::mock::*;
+use crate::masks::util::{holds_slot, is_any_slot_reserved};
+
+/// Returns a Mask that was most recently assigned slot 2 of the MaskProvider.
+fn mask() -> Mask {
+    Mask {
+        metadata: ObjectMeta {
+            name: Some("my-mask".to_owned()),
+            namespace: Some("default".to_owned()),
+            uid: Some("mask-uid".to_owned()),
+            ..Default::default()
+        },
+        status: Some(MaskStatus {
+            phase: Some(MaskPhase::Terminating),
+            last_slot: Some(2),
+            last_provider_uid: Some("provider-uid".to_owned()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Returns a MaskReservation for the MaskConsumer in the slot.
+fn reservation(name: &str, namespace: &str, slot: Option<usize>) -> MaskReservation {
+    MaskReservation {
+        metadata: ObjectMeta {
+            name: Some(format!("my-provider-{}", slot.unwrap_or_default())),
+            namespace: Some("vpn".to_owned()),
+            ..Default::default()
+        },
+        spec: MaskReservationSpec {
+            name: name.to_owned(),
+            namespace: namespace.to_owned(),
+            uid: "consumer-uid".to_owned(),
+            slot,
+        },
+        status: None,
+    }
+}
+
+#[test]
+fn slot_held_by_mask() {
+    let mask = mask();
+    let held = |name, namespace, slot| holds_slot(&reservation(name, namespace, slot), &mask, 2);
+    assert!(held("my-mask", "default", Some(2)));
+    // Older reservations don't record their slot.
+    assert!(held("my-mask", "default", None));
+    // The slot may already be reserved again by another Mask.
+    assert!(!held("other", "default", Some(2)));
+    assert!(!held("my-mask", "other", Some(2)));
+    assert!(!held("my-mask", "default", Some(1)));
+}
+
+#[test]
+fn slot_remembered() {
+    let mut status = MaskStatus::default();
+    status.remember_slot(SlotAffinity {
+        provider_uid: "provider-uid".to_owned(),
+        slot: 2,
+    });
+    assert_eq!(status.last_slot, Some(2));
+    assert_eq!(status.last_provider_uid.as_deref(), Some("provider-uid"));
+}
+
+#[tokio::test]
+async fn deletion_waits_for_slot() {
+    let list = |items: Vec<MaskReservation>| {
+        json!({
+            "apiVersion": "vpn.beebs.dev/v1",
+            "kind": "MaskReservationList",
+            "metadata": {},
+            "items": items,
+        })
+    };
+
+    // The slot is still reserved for the Mask's MaskConsumer.
+    let (client, captured) = mock_routes(vec![(
+        "/apis/vpn.beebs.dev/v1/maskreservations",
+        list(vec![reservation("my-mask", "default", Some(2))]),
+    )]);
+    assert!(is_any_slot_reserved(client, &mask()).await.unwrap());
+    // Only the MaskProvider's reservations are listed.
+    assert!(captured.lock().unwrap()[0]
+        .path
+        .contains("labelSelector=vpn.beebs.dev%2Fowner%3Dprovider-uid"));
+
+    // The slot was released and reserved again by another Mask.
+    let (client, _) = mock_routes(vec![(
+        "/apis/vpn.beebs.dev/v1/maskreservations",
+        list(vec![reservation("other", "default", Some(2))]),
+    )]);
+    assert!(!is_any_slot_reserved(client, &mask()).await.unwrap());
+
+    // The slots of the other MaskConsumers are waited for too, as long
+    // as their MaskReservations exist.
+    let mut slots = mask();
+    slots.status.as_mut().unwrap().providers = Some(vec![AssignedProvider {
+        uid: "provider-uid".to_owned(),
+        slot: 3,
+        reservation: "reservation-uid".to_owned(),
+        ..Default::default()
+    }]);
+    let mut other = reservation("my-mask-1a2b3c4d-1", "default", Some(3));
+    other.metadata.uid = Some("reservation-uid".to_owned());
+    let (client, _) = mock_routes(vec![(
+        "/apis/vpn.beebs.dev/v1/maskreservations",
+        list(vec![reservation("other", "default", Some(2)), other]),
+    )]);
+    assert!(is_any_slot_reserved(client, &slots).await.unwrap());
+    let (client, _) = mock_routes(vec![(
+        "/apis/vpn.beebs.dev/v1/maskreservations",
+        list(vec![reservation("other", "default", Some(2))]),
+    )]);
+    assert!(!is_any_slot_reserved(client, &slots).await.unwrap());
+
+    // A Mask that was never assigned a slot has nothing to wait for.
+    let (client, captured) = mock_routes(vec![]);
+    let mut unassigned = mask();
+    unassigned.status = None;
+    assert!(!is_any_slot_reserved(client, &unassigned).await.unwrap());
+    assert!(captured.lock().unwrap().is_empty());
+}
@@ -0,0 +1,278 @@
+use kube::{api::ObjectMeta, client::Client, Api};
+use std::collections::BTreeMap;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::{mock::mock_cluster, util::*};
+use crate::{
+    consumers::quota::{count_usage, exceeded, quota_status, Quotas, Usage, UsageCache},
+    util::{messages, CANARY_LABEL, CONSUMER_NAMESPACE_LABEL, VERIFICATION_LABEL},
+};
+
+/// Returns a MaskProvider with the given uid and tags.
+fn tagged_provider(uid: &str, tags: &[&str]) -> MaskProvider {
+    let mut provider = mask_provider(uid, "providers", uid);
+    provider.spec.max_slots = 4;
+    provider.spec.secret = uid.to_owned();
+    provider.spec.tags = Some(tags.iter().map(|t| t.to_string()).collect());
+    provider
+}
+
+/// Returns a MaskReservation of a slot with the MaskProvider for a
+/// MaskConsumer in the namespace, carrying the given label.
+fn reservation(provider_uid: &str, namespace: &str, label: Option<&str>) -> MaskReservation {
+    MaskReservation {
+        metadata: ObjectMeta {
+            name: Some(format!("{}-{}", provider_uid, namespace)),
+            namespace: Some("providers".to_owned()),
+            labels: label.map(|l| BTreeMap::from([(l.to_owned(), provider_uid.to_owned())])),
+            owner_references: Some(vec![
+                k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference {
+                    api_version: "vpn.beebs.dev/v1".to_owned(),
+                    kind: "MaskProvider".to_owned(),
+                    name: provider_uid.to_owned(),
+                    uid: provider_uid.to_owned(),
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        },
+        spec: MaskReservationSpec {
+            name: "mask".to_owned(),
+            namespace: namespace.to_owned(),
+            uid: "consumer-uid".to_owned(),
+            slot: Some(0),
+        },
+        status: None,
+    }
+}
+
+/// Returns a MaskQuota in the `team` namespace.
+fn quota(name: &str, max_slots: usize, tags: &[(&str, usize)]) -> MaskQuota {
+    MaskQuota {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some("team".to_owned()),
+            ..Default::default()
+        },
+        spec: MaskQuotaSpec {
+            max_slots,
+            tags: Some(
+                tags.iter()
+                    .map(|(tag, max_slots)| MaskQuotaTagLimit {
+                        tag: tag.to_string(),
+                        max_slots: *max_slots,
+                    })
+                    .collect(),
+            )
+            .filter(|tags: &Vec<_>| !tags.is_empty()),
+        },
+        status: None,
+    }
+}
+
+/// Returns the usage with the given total and per-tag slots.
+fn usage(slots: usize, tags: &[(&str, usize)]) -> Usage {
+    Usage {
+        slots,
+        tags: tags.iter().map(|(t, n)| (t.to_string(), *n)).collect(),
+    }
+}
+
+#[test]
+fn usage_counted() {
+    let providers = [
+        tagged_provider("a", &["us", "fast"]),
+        tagged_provider("b", &["eu"]),
+    ];
+    let reservations = [
+        reservation("a", "team", None),
+        reservation("a", "team", None),
+        reservation("b", "team", None),
+        // Reservations of other namespaces don't count.
+        reservation("b", "other", None),
+        // Neither do those made for verification or canaries.
+        reservation("a", "team", Some(VERIFICATION_LABEL)),
+        reservation("a", "team", Some(CANARY_LABEL)),
+        // Reservations of deleted MaskProviders count without tags.
+        reservation("gone", "team", None),
+    ];
+    assert_eq!(
+        count_usage("team", &providers, &reservations),
+        usage(4, &[("us", 2), ("fast", 2), ("eu", 1)]),
+    );
+    assert_eq!(
+        count_usage("empty", &providers, &reservations),
+        Usage::default()
+    );
+}
+
+#[test]
+fn quota_enforced() {
+    let us = tagged_provider("a", &["us"]);
+    let eu = tagged_provider("b", &["eu"]);
+    let quotas = [quota("team", 3, &[("us", 1)])];
+    let cases = [
+        ("under", usage(1, &[]), &us, None),
+        (
+            "at namespace limit",
+            usage(3, &[]),
+            &eu,
+            Some(messages::err_quota_exceeded("team", 3)),
+        ),
+        (
+            "at tag limit",
+            usage(1, &[("us", 1)]),
+            &us,
+            Some(messages::err_tag_quota_exceeded("team", "us", 1)),
+        ),
+        (
+            "tag limit of other providers",
+            usage(1, &[("us", 1)]),
+            &eu,
+            None,
+        ),
+    ];
+    for (name, usage, provider, expected) in cases {
+        assert_eq!(exceeded(&quotas, &usage, provider), expected, "{}", name);
+    }
+
+    // Every quota in the namespace applies.
+    let quotas = [quota("a", 5, &[]), quota("b", 2, &[])];
+    assert_eq!(
+        exceeded(&quotas, &usage(2, &[]), &eu),
+        Some(messages::err_quota_exceeded("b", 2)),
+    );
+    assert_eq!(exceeded(&[], &usage(100, &[]), &eu), None);
+}
+
+#[test]
+fn usage_shown_in_status() {
+    let status = quota_status(
+        &quota("team", 3, &[("us", 1), ("eu", 2)]),
+        &usage(2, &[("us", 1)]),
+    );
+    assert_eq!(status.used_slots, Some(2));
+    assert_eq!(
+        status.tags,
+        Some(vec![
+            MaskQuotaTagUsage {
+                tag: "us".to_owned(),
+                used_slots: 1,
+                max_slots: 1,
+            },
+            MaskQuotaTagUsage {
+                tag: "eu".to_owned(),
+                used_slots: 0,
+                max_slots: 2,
+            },
+        ])
+    );
+
+    // Quotas without tag limits don't show any tags.
+    assert_eq!(
+        quota_status(&quota("team", 3, &[]), &usage(2, &[])).tags,
+        None
+    );
+}
+
+#[tokio::test]
+async fn oldest_reservations_confirmed() {
+    let provider = tagged_provider("a", &["us"]);
+    // Returns a reservation in the `team` namespace made `age` seconds ago.
+    let made = |uid: &str, age: i64| {
+        let mut reservation = reservation("a", "team", None);
+        reservation.metadata.name = Some(format!("a-{}", uid));
+        reservation.metadata.uid = Some(uid.to_owned());
+        reservation.metadata.labels = Some(BTreeMap::from([(
+            CONSUMER_NAMESPACE_LABEL.to_owned(),
+            "team".to_owned(),
+        )]));
+        reservation.metadata.creation_timestamp =
+            Some(k8s_openapi::apimachinery::pkg::apis::meta::v1::Time(
+                chrono::Utc::now() - chrono::Duration::seconds(age),
+            ));
+        reservation
+    };
+    let confirm = |quota: MaskQuota, reservations: Vec<MaskReservation>| {
+        let provider = provider.clone();
+        async move {
+            let own = reservations[0].clone();
+            let mut objects = vec![
+                serde_json::to_value(&quota).unwrap(),
+                serde_json::to_value(&provider).unwrap(),
+            ];
+            objects.extend(
+                reservations
+                    .iter()
+                    .map(|r| serde_json::to_value(r).unwrap()),
+            );
+            let (client, _) = mock_cluster(objects);
+            let consumer = mask_consumer("mask", "team", "consumer-uid");
+            let quotas = Quotas::new(std::time::Duration::from_secs(60), UsageCache::unwatched());
+            let admission = quotas.admit(client.clone(), &consumer).await.unwrap();
+            admission.confirm(client, &provider, &own).await.unwrap()
+        }
+    };
+
+    // Another replica reserved the last slot first.
+    assert_eq!(
+        confirm(
+            quota("team", 1, &[]),
+            vec![made("own", 1), made("other", 2)]
+        )
+        .await,
+        Some(messages::err_quota_exceeded("team", 1)),
+    );
+    // This one came first, so the other replica backs off instead.
+    assert_eq!(
+        confirm(
+            quota("team", 1, &[]),
+            vec![made("own", 2), made("other", 1)]
+        )
+        .await,
+        None,
+    );
+    // Tag limits are confirmed the same way.
+    assert_eq!(
+        confirm(
+            quota("team", 5, &[("us", 1)]),
+            vec![made("own", 1), made("other", 2)]
+        )
+        .await,
+        Some(messages::err_tag_quota_exceeded("team", "us", 1)),
+    );
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn mask_quota_exceeded() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+
+    // Forbid the namespace from reserving any slots.
+    let api: Api<MaskQuota> = Api::namespaced(client.clone(), &namespace);
+    let mut limit = quota("team", 0, &[]);
+    limit.metadata.namespace = Some(namespace.clone());
+    api.create(&Default::default(), &limit).await?;
+
+    // Watch for the error message in the Mask's status.
+    let fail = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(async move {
+            wait_for_mask_phase(client, &namespace, 0, MaskPhase::ErrQuotaExceeded).await
+        })
+    };
+
+    // The Mask isn't assigned even though the MaskProvider has a free slot.
+    let provider = create_test_provider(client.clone(), &namespace, &uid).await?;
+    let provider_label = provider.spec.tags.as_ref().unwrap()[0].clone();
+    create_test_mask(client.clone(), &namespace, 0, &provider_label).await?;
+    fail.await.unwrap()?;
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+
+    Ok(())
+}
@@ -0,0 +1,402 @@
+use k8s_openapi::api::core::v1::{Container, Pod, PodSpec, SecretVolumeSource, Service, Volume};
+use kube::{
+    api::{DeleteParams, ObjectMeta},
+    client::Client,
+    Api,
+};
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use vpn_types::*;
+
+use super::{
+    mock::{consumer_value, decide, merged, mock_cluster},
+    util::*,
+};
+use crate::{
+    consumers::{
+        reconcile::{determine_action, ConsumerAction, ContextData},
+        service::{desired_service, validate},
+    },
+    masks::reconcile::{determine_action as determine_mask_action, MaskAction},
+    util::{
+        clock, config::OperatorConfig, finalizer::FINALIZER_NAME, messages, CONSUMER_UID_LABEL,
+        HEARTBEAT_ANNOTATION, MASK_NAME_LABEL, PROVIDER_UID_LABEL,
+    },
+};
+
+/// Returns the Service requested for Pods labeled `app: scraper`.
+fn service_spec() -> MaskServiceSpec {
+    MaskServiceSpec {
+        port: 8888,
+        target_port: None,
+        selector_labels: [("app".to_owned(), "scraper".to_owned())].into(),
+    }
+}
+
+/// Returns an Active MaskConsumer requesting [`service_spec`], with
+/// `patch` merged into it.
+fn serving_consumer(patch: Value) -> Value {
+    let consumer = consumer_value(json!({
+        "spec": { "service": service_spec() },
+        "status": {
+            // The Pods' ServiceAccount is already recorded.
+            "consumers": [{
+                "serviceAccount": "default",
+                "provider": "providers/provider",
+                "firstSeen": clock::now_k8s(),
+                "lastSeen": clock::now_k8s(),
+            }],
+        },
+    }));
+    merged(consumer, patch)
+}
+
+/// Returns the objects of the MaskConsumer's assignment.
+fn assignment() -> Vec<Value> {
+    vec![
+        json!({
+            "apiVersion": "vpn.beebs.dev/v1",
+            "kind": "MaskReservation",
+            "metadata": {
+                "name": "provider-0",
+                "namespace": "providers",
+                "uid": "reservation-uid",
+                "labels": { PROVIDER_UID_LABEL: "provider-uid" },
+            },
+            "spec": { "name": "mask-0", "namespace": "default", "uid": "consumer-uid", "slot": 0 },
+        }),
+        json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "metadata": {
+                "name": "mask-0-provider-uid",
+                "namespace": "default",
+                "labels": {
+                    PROVIDER_UID_LABEL: "provider-uid",
+                    MASK_NAME_LABEL: "mask-0",
+                    CONSUMER_UID_LABEL: "consumer-uid",
+                },
+                "ownerReferences": [{
+                    "apiVersion": "vpn.beebs.dev/v1",
+                    "kind": "MaskConsumer",
+                    "name": "mask-0",
+                    "uid": "consumer-uid",
+                }],
+            },
+            "data": {},
+        }),
+    ]
+}
+
+/// Returns a running Pod with the labels that mounts the credentials.
+fn pod(labels: Value) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Pod",
+        "metadata": { "name": "scraper", "namespace": "default", "labels": labels },
+        "spec": {
+            "containers": [],
+            "volumes": [{ "name": "vpn", "secret": { "secretName": "mask-0-provider-uid" } }],
+        },
+        "status": { "phase": "Running" },
+    })
+}
+
+/// Returns a Service named after the Mask, owned by the given uid.
+fn service(owner_uid: &str) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Service",
+        "metadata": {
+            "name": "mask-0",
+            "namespace": "default",
+            "ownerReferences": [{
+                "apiVersion": "vpn.beebs.dev/v1",
+                "kind": "MaskConsumer",
+                "name": "mask-0",
+                "uid": owner_uid,
+            }],
+        },
+        "spec": { "selector": { "app": "scraper" }, "ports": [{ "port": 8888 }] },
+    })
+}
+
+/// Returns the action decided for the MaskConsumer with the given
+/// objects in the cluster.
+async fn action(consumer: Value, objects: &[Value]) -> ConsumerAction {
+    let instance: MaskConsumer = serde_json::from_value(consumer).unwrap();
+    let context = ContextData::for_tests(
+        mock_cluster(vec![]).0,
+        None,
+        Arc::new(OperatorConfig::new(false)),
+    );
+    decide(objects, |client| {
+        let (instance, context) = (instance.clone(), &context);
+        async move { determine_action(client, "default", &instance, context).await }
+    })
+    .await
+}
+
+#[test]
+fn validation() {
+    assert_eq!(validate("mask-0", &service_spec()), Ok(()));
+    let cases = [
+        ("0-mask", service_spec()),
+        ("Mask", service_spec()),
+        ("mask-", service_spec()),
+        ("mask.example", service_spec()),
+        (
+            "mask-0",
+            MaskServiceSpec {
+                port: 0,
+                ..service_spec()
+            },
+        ),
+        (
+            "mask-0",
+            MaskServiceSpec {
+                target_port: Some(65536),
+                ..service_spec()
+            },
+        ),
+        (
+            "mask-0",
+            MaskServiceSpec {
+                selector_labels: BTreeMap::new(),
+                ..service_spec()
+            },
+        ),
+    ];
+    for (name, spec) in cases {
+        let message = validate(name, &spec).unwrap_err();
+        assert!(message.starts_with("Invalid service: "), "{}", message);
+    }
+    assert!(validate(&"m".repeat(64), &service_spec()).is_err());
+}
+
+#[test]
+fn service_shape() {
+    let instance: MaskConsumer = serde_json::from_value(serving_consumer(json!({}))).unwrap();
+    let service = desired_service(&instance, &service_spec());
+    assert_eq!(service.metadata.name.as_deref(), Some("mask-0"));
+    assert_eq!(service.metadata.namespace.as_deref(), Some("default"));
+    let owner = &service.metadata.owner_references.as_ref().unwrap()[0];
+    assert_eq!(owner.kind, "MaskConsumer");
+    assert_eq!(owner.uid, "consumer-uid");
+    assert_eq!(owner.controller, Some(true));
+    let labels = service.metadata.labels.as_ref().unwrap();
+    assert_eq!(labels.get(MASK_NAME_LABEL).unwrap(), "mask-0");
+    assert_eq!(labels.get(CONSUMER_UID_LABEL).unwrap(), "consumer-uid");
+    let spec = service.spec.unwrap();
+    assert_eq!(spec.type_.as_deref(), Some("ClusterIP"));
+    assert_eq!(spec.selector, Some(service_spec().selector_labels));
+    let port = &spec.ports.unwrap()[0];
+    assert_eq!(port.port, 8888);
+    // The targetPort defaults to the port.
+    assert_eq!(
+        serde_json::to_value(&port.target_port).unwrap(),
+        json!(8888)
+    );
+}
+
+#[tokio::test]
+async fn service_actions() {
+    let instance: MaskConsumer = serde_json::from_value(serving_consumer(json!({}))).unwrap();
+    let desired = desired_service(&instance, &service_spec());
+    let with = |extra: Vec<Value>| [assignment(), extra].concat();
+    let cases = [
+        (
+            "pod labeled",
+            serving_consumer(json!({})),
+            with(vec![pod(json!({ "app": "scraper", "tier": "web" }))]),
+            ConsumerAction::CreateService(Box::new(desired.clone())),
+        ),
+        (
+            "pod not labeled",
+            serving_consumer(json!({})),
+            with(vec![pod(json!({ "app": "crawler" }))]),
+            ConsumerAction::NoOp,
+        ),
+        (
+            "no pods",
+            serving_consumer(json!({})),
+            with(vec![]),
+            ConsumerAction::NoOp,
+        ),
+        (
+            "service created",
+            serving_consumer(json!({})),
+            with(vec![service("consumer-uid")]),
+            ConsumerAction::RecordService("mask-0".to_owned()),
+        ),
+        (
+            "service recorded",
+            serving_consumer(json!({ "status": { "service": "mask-0" } })),
+            with(vec![service("consumer-uid")]),
+            ConsumerAction::NoOp,
+        ),
+        (
+            "service drifted",
+            serving_consumer(json!({ "status": { "service": "mask-0" } })),
+            with(vec![merged(
+                service("consumer-uid"),
+                json!({ "spec": { "ports": [{ "port": 9999 }] } }),
+            )]),
+            ConsumerAction::PatchService(Box::new(desired.clone())),
+        ),
+        (
+            "service in the way",
+            serving_consumer(json!({})),
+            with(vec![service("other-uid"), pod(json!({ "app": "scraper" }))]),
+            ConsumerAction::ServiceConflict(messages::service_conflict("mask-0")),
+        ),
+        (
+            "service in the way shown",
+            serving_consumer(json!({
+                "status": { "message": messages::service_conflict("mask-0") },
+            })),
+            with(vec![service("other-uid"), pod(json!({ "app": "scraper" }))]),
+            ConsumerAction::NoOp,
+        ),
+        (
+            "external consumer",
+            serving_consumer(json!({
+                "metadata": { "annotations": { HEARTBEAT_ANNOTATION: clock::now_k8s() } },
+                "spec": { "external": true },
+            })),
+            with(vec![pod(json!({ "app": "scraper" }))]),
+            ConsumerAction::NoOp,
+        ),
+    ];
+    for (case, consumer, objects, expected) in cases {
+        assert_eq!(action(consumer, &objects).await, expected, "{}", case);
+    }
+}
+
+#[tokio::test]
+async fn invalid_service_shown() {
+    let mask: Mask = serde_json::from_value(json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "Mask",
+        "metadata": {
+            "name": "mask-0",
+            "namespace": "default",
+            "uid": "mask-uid",
+            "generation": 1,
+            "finalizers": [FINALIZER_NAME],
+        },
+        "spec": { "service": { "port": 8888, "selectorLabels": {} } },
+        "status": {
+            "phase": "Active",
+            "message": messages::ACTIVE,
+            "lastUpdated": clock::now_k8s(),
+            "lastSlot": 0,
+            "lastProviderUid": "provider-uid",
+        },
+    }))
+    .unwrap();
+    let message = messages::invalid_service("selectorLabels must not be empty");
+    let action = decide(&[], |client| {
+        let mask = mask.clone();
+        async move {
+            determine_mask_action(
+                client,
+                "mask-0",
+                "default",
+                &mask,
+                Duration::from_secs(600),
+                chrono::Utc::now(),
+                false,
+            )
+            .await
+        }
+    })
+    .await;
+    assert_eq!(action, MaskAction::ErrInvalidSpec(message));
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn service_exposes_pods() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+    create_test_provider(client.clone(), &namespace, &uid).await?;
+    wait_for_provider_phase(client.clone(), &namespace, MaskProviderPhase::Ready).await?;
+    let mut mask = get_test_mask(&namespace, 0, &provider_name);
+    mask.spec.service = Some(service_spec());
+    let mask_api: Api<Mask> = Api::namespaced(client.clone(), &namespace);
+    mask_api.create(&Default::default(), &mask).await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+    let name = format!("{}-0", MASK_NAME);
+    let consumer_api: Api<MaskConsumer> = Api::namespaced(client.clone(), &namespace);
+    let secret = consumer_api
+        .get(&name)
+        .await?
+        .status
+        .unwrap()
+        .provider
+        .unwrap()
+        .secret;
+
+    // The Service is created once a labeled Pod mounts the credentials.
+    let pod = Pod {
+        metadata: ObjectMeta {
+            name: Some("scraper".to_owned()),
+            labels: Some(service_spec().selector_labels),
+            ..Default::default()
+        },
+        spec: Some(PodSpec {
+            containers: vec![Container {
+                name: "app".to_owned(),
+                image: Some("busybox".to_owned()),
+                command: Some(vec!["sleep".to_owned(), "3600".to_owned()]),
+                ..Default::default()
+            }],
+            volumes: Some(vec![Volume {
+                name: "vpn".to_owned(),
+                secret: Some(SecretVolumeSource {
+                    secret_name: Some(secret),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    Api::<Pod>::namespaced(client.clone(), &namespace)
+        .create(&Default::default(), &pod)
+        .await?;
+    let service_api: Api<Service> = Api::namespaced(client.clone(), &namespace);
+    let mut recorded = None;
+    for _ in 0..60 {
+        recorded = consumer_api.get(&name).await?.status.unwrap().service;
+        if recorded.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    assert_eq!(recorded.as_deref(), Some(name.as_str()));
+    let service = service_api.get(&name).await?;
+    assert_eq!(
+        service.spec.unwrap().selector,
+        Some(service_spec().selector_labels)
+    );
+
+    // Deleting the Mask garbage collects the Service with its MaskConsumer.
+    mask_api.delete(&name, &DeleteParams::default()).await?;
+    let mut deleted = false;
+    for _ in 0..120 {
+        if service_api.get_opt(&name).await?.is_none() {
+            deleted = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    assert!(deleted, "Service was not garbage collected");
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,439 @@
+use kube::{
+    api::{Patch, PatchParams},
+    client::Client,
+    Api,
+};
+use serde_json::{json, Value};
+use std::time::Duration;
+use vpn_types::*;
+
+use super::{
+    mock::{consumer_value, decide, merged},
+    util::*,
+};
+use crate::{
+    masks::{
+        reconcile::{determine_action, MaskAction},
+        util::consumer_name,
+    },
+    util::{
+        finalizer::FINALIZER_NAME, messages, MASK_SLOT_LABEL, MASK_UID_LABEL, PROVIDER_UID_LABEL,
+    },
+};
+
+/// Default of `--status-freshness-interval`.
+const FRESHNESS: Duration = Duration::from_secs(600);
+
+/// Returns a Waiting Mask requesting three slots, with `patch` merged into it.
+fn mask(patch: Value) -> Value {
+    let mask = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "Mask",
+        "metadata": {
+            "name": "mask-0",
+            "namespace": "default",
+            "uid": "mask-uid",
+            "finalizers": [FINALIZER_NAME],
+        },
+        "spec": { "slots": 3 },
+        "status": {
+            "phase": "Waiting",
+            "message": messages::WAITING,
+            "lastUpdated": chrono::Utc::now().to_rfc3339(),
+        },
+    });
+    merged(mask, patch)
+}
+
+/// Returns the name of the Mask's MaskConsumer for the slot with the
+/// given index.
+fn name(index: usize) -> String {
+    let instance: Mask = serde_json::from_value(mask(json!({}))).unwrap();
+    consumer_name(&instance, index)
+}
+
+/// Returns the Mask's MaskConsumer for the slot with the given index,
+/// assigned that slot of `provider-uid`, with `patch` merged into it.
+fn slot_consumer(index: usize, patch: Value) -> Value {
+    let name = name(index);
+    let labels = match index {
+        0 => json!({}),
+        _ => json!({ MASK_UID_LABEL: "mask-uid", MASK_SLOT_LABEL: index.to_string() }),
+    };
+    let consumer = consumer_value(json!({
+        "metadata": {
+            "name": name,
+            "uid": format!("consumer-uid-{}", index),
+            "labels": labels,
+        },
+        "status": {
+            "provider": {
+                "slot": index,
+                "reservation": format!("reservation-uid-{}", index),
+                "secret": format!("{}-provider-uid", name),
+            },
+        },
+    }));
+    merged(consumer, patch)
+}
+
+/// Returns the MaskProviders assigned to the MaskConsumers.
+fn providers(consumers: &[Value]) -> Vec<AssignedProvider> {
+    consumers
+        .iter()
+        .map(|c| serde_json::from_value(c["status"]["provider"].clone()).unwrap())
+        .collect()
+}
+
+/// Returns the action decided for the Mask with the given objects in
+/// the cluster.
+async fn action(mask: Value, objects: &[Value]) -> MaskAction {
+    let instance: Mask = serde_json::from_value(mask).unwrap();
+    decide(objects, |client| {
+        let instance = instance.clone();
+        async move {
+            determine_action(
+                client,
+                "mask-0",
+                "default",
+                &instance,
+                FRESHNESS,
+                chrono::Utc::now(),
+                false,
+            )
+            .await
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn slot_consumers_created_in_order() {
+    // The first slot's MaskConsumer is named after the Mask.
+    assert_eq!(
+        action(mask(json!({})), &[]).await,
+        MaskAction::CreateConsumer(0)
+    );
+    assert_eq!(
+        action(mask(json!({})), &[slot_consumer(0, json!({}))]).await,
+        MaskAction::CreateConsumer(1)
+    );
+    assert_eq!(
+        action(
+            mask(json!({})),
+            &[slot_consumer(0, json!({})), slot_consumer(1, json!({}))]
+        )
+        .await,
+        MaskAction::CreateConsumer(2)
+    );
+
+    // The other slots' MaskConsumers aren't named like the first one of
+    // a Mask named with the index as a suffix, nor counted as the Mask's.
+    assert!(name(1).starts_with("mask-0-"));
+    assert_ne!(name(1), "mask-0-1");
+    assert_ne!(name(1), name(2));
+    let other = json!({ "metadata": {
+        "name": "mask-0-1",
+        "uid": "other-consumer-uid",
+        "labels": null,
+        "ownerReferences": [{
+            "apiVersion": "vpn.beebs.dev/v1",
+            "kind": "Mask",
+            "name": "mask-0-1",
+            "uid": "other-mask-uid",
+            "controller": true,
+        }],
+    } });
+    assert_eq!(
+        action(
+            mask(json!({})),
+            &[slot_consumer(0, json!({})), slot_consumer(1, other)]
+        )
+        .await,
+        MaskAction::CreateConsumer(1)
+    );
+
+    // Each MaskConsumer follows the Mask's anti-affinity group.
+    let grouped = mask(json!({ "spec": { "antiAffinity": { "group": "scrapers" } } }));
+    let synced = json!({ "spec": { "antiAffinity": { "group": "scrapers" } } });
+    assert_eq!(
+        action(
+            grouped,
+            &[
+                slot_consumer(0, synced.clone()),
+                slot_consumer(1, synced),
+                slot_consumer(2, json!({})),
+            ]
+        )
+        .await,
+        MaskAction::SyncAntiAffinity(
+            name(2),
+            Some(MaskAntiAffinity {
+                group: "scrapers".to_owned(),
+            })
+        )
+    );
+
+    // Each MaskConsumer but the first is labelled to be listed by.
+    let unlabelled = slot_consumer(1, json!({ "metadata": { "labels": null } }));
+    assert_eq!(
+        action(
+            mask(json!({ "spec": { "slots": 2 } })),
+            &[slot_consumer(0, json!({})), unlabelled]
+        )
+        .await,
+        MaskAction::SyncLabels(
+            name(1),
+            [
+                (MASK_SLOT_LABEL.to_owned(), Some("1".to_owned())),
+                (MASK_UID_LABEL.to_owned(), Some("mask-uid".to_owned())),
+            ]
+            .into()
+        )
+    );
+
+    // A Mask without any slots, or with more than allowed, can't be assigned.
+    assert_eq!(
+        action(mask(json!({ "spec": { "slots": 0 } })), &[]).await,
+        MaskAction::ErrInvalidSpec(messages::invalid_slots(0))
+    );
+    let too_many = MAX_MASK_SLOTS + 1;
+    assert_eq!(
+        action(mask(json!({ "spec": { "slots": too_many } })), &[]).await,
+        MaskAction::ErrInvalidSpec(messages::invalid_slots(too_many))
+    );
+}
+
+#[tokio::test]
+async fn active_once_all_slots_assigned() {
+    let waiting = json!({ "status": {
+        "phase": "Waiting",
+        "message": "All slots are in use.",
+        "provider": null,
+    } });
+    let all = vec![
+        slot_consumer(0, json!({})),
+        slot_consumer(1, json!({})),
+        slot_consumer(2, json!({})),
+    ];
+
+    // Partial assignment is counted, naming why the rest are waiting.
+    let partial = vec![
+        all[0].clone(),
+        all[1].clone(),
+        slot_consumer(2, waiting.clone()),
+    ];
+    let message = messages::slots_assigned(2, 3, Some("All slots are in use."));
+    assert_eq!(message, "2/3 slots assigned. All slots are in use.");
+    assert_eq!(
+        action(mask(json!({})), &partial).await,
+        MaskAction::PartiallyAssigned(message.clone(), providers(&all[..2]))
+    );
+    assert_eq!(
+        action(mask(json!({ "status": { "message": message } })), &partial).await,
+        MaskAction::NoOp
+    );
+
+    // Only the slots that are assigned are counted.
+    let pending = json!({ "status": { "phase": "Pending", "message": null, "provider": null } });
+    assert_eq!(
+        action(
+            mask(json!({})),
+            &[
+                slot_consumer(0, waiting),
+                all[1].clone(),
+                slot_consumer(2, pending)
+            ]
+        )
+        .await,
+        MaskAction::PartiallyAssigned(
+            messages::slots_assigned(1, 3, Some("All slots are in use.")),
+            providers(&all[1..2])
+        )
+    );
+
+    // The Mask becomes Active once every slot is assigned, remembering
+    // the first one.
+    assert_eq!(
+        action(mask(json!({})), &all).await,
+        MaskAction::Active {
+            slot: Some(SlotAffinity {
+                provider_uid: "provider-uid".to_owned(),
+                slot: 0,
+            }),
+            provider_namespace: Some("providers".to_owned()),
+            providers: providers(&all),
+        }
+    );
+
+    // Errors of any slot are mirrored.
+    assert_eq!(
+        action(
+            mask(json!({})),
+            &[
+                all[0].clone(),
+                slot_consumer(
+                    1,
+                    json!({ "status": { "phase": "ErrNoProviders", "provider": null } })
+                ),
+                all[2].clone(),
+            ]
+        )
+        .await,
+        MaskAction::ErrNoProviders
+    );
+}
+
+#[tokio::test]
+async fn surplus_slots_released() {
+    // Fewer slots release the last ones first.
+    let one = mask(json!({ "spec": { "slots": 1 } }));
+    let all = [
+        slot_consumer(0, json!({})),
+        slot_consumer(1, json!({})),
+        slot_consumer(2, json!({})),
+    ];
+    assert_eq!(
+        action(one.clone(), &all).await,
+        MaskAction::DeleteSurplusConsumer(name(2))
+    );
+    let deleting = json!({ "metadata": {
+        "deletionTimestamp": "2023-01-01T00:00:00Z",
+        "finalizers": [FINALIZER_NAME],
+    } });
+    assert_eq!(
+        action(
+            one.clone(),
+            &[
+                all[0].clone(),
+                all[1].clone(),
+                slot_consumer(2, deleting.clone())
+            ]
+        )
+        .await,
+        MaskAction::DeleteSurplusConsumer(name(1))
+    );
+
+    // Deleting the Mask deletes every MaskConsumer, remembering the
+    // first slot so the Mask waits for it to be free.
+    let deleted = mask(json!({ "metadata": {
+        "deletionTimestamp": "2023-01-01T00:00:00Z",
+    } }));
+    assert_eq!(
+        action(deleted.clone(), &all).await,
+        MaskAction::DeleteConsumer(
+            "mask-0".to_owned(),
+            Some(SlotAffinity {
+                provider_uid: "provider-uid".to_owned(),
+                slot: 0,
+            })
+        )
+    );
+    assert_eq!(
+        action(
+            deleted.clone(),
+            &[slot_consumer(0, deleting.clone()), all[1].clone()]
+        )
+        .await,
+        MaskAction::DeleteConsumer(name(1), None)
+    );
+    assert_eq!(
+        action(
+            deleted.clone(),
+            &[
+                slot_consumer(0, deleting.clone()),
+                slot_consumer(1, deleting)
+            ]
+        )
+        .await,
+        MaskAction::WaitForRelease
+    );
+
+    // Once they're gone, the Mask waits for every slot to be released.
+    let assigned = merged(
+        deleted.clone(),
+        json!({ "status": { "providers": providers(&all) } }),
+    );
+    let reservation = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskReservation",
+        "metadata": {
+            "name": "provider-2",
+            "namespace": "providers",
+            "uid": "reservation-uid-2",
+            "labels": { PROVIDER_UID_LABEL: "provider-uid" },
+        },
+        "spec": {
+            "name": name(2),
+            "namespace": "default",
+            "uid": "consumer-uid-2",
+            "slot": 2,
+        },
+    });
+    assert_eq!(
+        action(assigned.clone(), &[reservation]).await,
+        MaskAction::WaitForRelease
+    );
+    assert_eq!(action(assigned, &[]).await, MaskAction::Delete);
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn slots_assigned_separately() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+
+    // A MaskProvider with room for both of the Mask's slots.
+    let mut provider = get_test_provider(client.clone(), &provider_name, &namespace).await?;
+    provider.spec.max_slots = 2;
+    let provider = Api::<MaskProvider>::namespaced(client.clone(), &namespace)
+        .create(&Default::default(), &provider)
+        .await?;
+    create_test_provider_secret(client.clone(), &namespace, &provider).await?;
+    wait_for_provider_phase(client.clone(), &namespace, MaskProviderPhase::Ready).await?;
+    let mut mask = get_test_mask(&namespace, 0, &provider_name);
+    mask.spec.slots = Some(2);
+    let mask_api: Api<Mask> = Api::namespaced(client.clone(), &namespace);
+    mask_api.create(&Default::default(), &mask).await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+
+    // Each slot has its own MaskConsumer and credentials.
+    let name = format!("{}-0", MASK_NAME);
+    let assigned = mask_api
+        .get(&name)
+        .await?
+        .status
+        .unwrap()
+        .providers
+        .unwrap();
+    assert_eq!(assigned.len(), 2);
+    assert_ne!(assigned[0].slot, assigned[1].slot);
+    assert_ne!(assigned[0].secret, assigned[1].secret);
+    let consumer_api: Api<MaskConsumer> = Api::namespaced(client.clone(), &namespace);
+    consumer_api.get(&name).await?;
+    let second = consumer_name(&mask_api.get(&name).await?, 1);
+    consumer_api.get(&second).await?;
+
+    // Requesting fewer slots releases the others.
+    mask_api
+        .patch(
+            &name,
+            &PatchParams::default(),
+            &Patch::Merge(json!({ "spec": { "slots": 1 } })),
+        )
+        .await?;
+    let mut released = false;
+    for _ in 0..60 {
+        if consumer_api.get_opt(&second).await?.is_none() {
+            released = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    assert!(released, "MaskConsumer {} was not deleted", second);
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,297 @@
+use chrono::Utc;
+use k8s_openapi::{
+    api::core::v1::Secret,
+    apimachinery::pkg::apis::meta::v1::{OwnerReference, Time},
+};
+use kube::{api::ObjectMeta, client::Client, Api};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+use vpn_types::*;
+
+use super::{mock::*, util::*};
+use crate::{
+    consumers::succession::{
+        get_predecessor, is_predecessor_copy, is_released, predecessor_name,
+        predecessor_reservation,
+    },
+    util::{MASK_NAME_LABEL, PROVIDER_UID_LABEL},
+};
+
+/// Returns a MaskConsumer named `new-mask` that succeeds `old-mask`,
+/// assigned slot 0 of the MaskProvider.
+fn successor() -> MaskConsumer {
+    MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some("new-mask".to_owned()),
+            namespace: Some("default".to_owned()),
+            uid: Some("new-uid".to_owned()),
+            ..Default::default()
+        },
+        spec: MaskConsumerSpec {
+            succession_of: Some("old-mask".to_owned()),
+            ..Default::default()
+        },
+        status: Some(MaskConsumerStatus {
+            provider: Some(assignment("new-reservation-uid")),
+            ..Default::default()
+        }),
+    }
+}
+
+/// Returns the MaskConsumer `old-mask` created by the Mask of the same
+/// name, assigned slot 0 of the MaskProvider through the reservation
+/// with `reservation-uid`.
+fn predecessor() -> MaskConsumer {
+    MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some("old-mask".to_owned()),
+            namespace: Some("default".to_owned()),
+            uid: Some("old-uid".to_owned()),
+            owner_references: Some(vec![mask_owner_ref("old-mask")]),
+            ..Default::default()
+        },
+        status: Some(MaskConsumerStatus {
+            provider: Some(assignment("reservation-uid")),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Returns slot 0 of the MaskProvider, assigned through the reservation.
+fn assignment(reservation: &str) -> AssignedProvider {
+    AssignedProvider {
+        reservation: reservation.to_owned(),
+        ..assigned_provider("my-provider", "vpn", "provider-uid", "vpn-credentials")
+    }
+}
+
+/// Returns the reservation of slot 0 of the MaskProvider for the
+/// MaskConsumer with the name and UID.
+fn reservation(name: &str, uid: &str) -> MaskReservation {
+    MaskReservation {
+        metadata: ObjectMeta {
+            name: Some("my-provider-0".to_owned()),
+            namespace: Some("vpn".to_owned()),
+            uid: Some("reservation-uid".to_owned()),
+            ..Default::default()
+        },
+        spec: MaskReservationSpec {
+            name: name.to_owned(),
+            namespace: "default".to_owned(),
+            uid: uid.to_owned(),
+            slot: Some(0),
+        },
+        ..Default::default()
+    }
+}
+
+/// Returns the Mask `old-mask`, which is being deleted if `terminating`.
+fn old_mask(terminating: bool) -> Mask {
+    Mask {
+        metadata: ObjectMeta {
+            name: Some("old-mask".to_owned()),
+            namespace: Some("default".to_owned()),
+            uid: Some("mask-uid".to_owned()),
+            deletion_timestamp: terminating.then(|| Time(Utc::now())),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Returns a copy of the MaskProvider's credentials made for `mask`
+/// and owned by `owner`.
+fn copy(mask: &str, owner: &str) -> Secret {
+    Secret {
+        metadata: ObjectMeta {
+            name: Some("vpn-credentials".to_owned()),
+            namespace: Some("default".to_owned()),
+            labels: Some(BTreeMap::from([
+                (MASK_NAME_LABEL.to_owned(), mask.to_owned()),
+                (PROVIDER_UID_LABEL.to_owned(), "provider-uid".to_owned()),
+            ])),
+            owner_references: Some(vec![OwnerReference {
+                api_version: "vpn.beebs.dev/v1".to_owned(),
+                kind: "MaskConsumer".to_owned(),
+                name: owner.to_owned(),
+                uid: "old-uid".to_owned(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Returns the value of the object with its apiVersion and kind.
+fn object<K: kube::Resource<DynamicType = ()> + serde::Serialize>(
+    resource: &K,
+) -> serde_json::Value {
+    let mut value = serde_json::to_value(resource).unwrap();
+    value["apiVersion"] = json!(K::api_version(&()));
+    value["kind"] = json!(K::kind(&()));
+    value
+}
+
+#[test]
+fn predecessor_named() {
+    assert_eq!(predecessor_name(&successor()), Some("old-mask"));
+    assert_eq!(predecessor_name(&predecessor()), None);
+
+    // A MaskConsumer can't succeed itself.
+    let mut instance = successor();
+    instance.spec.succession_of = Some("new-mask".to_owned());
+    assert_eq!(predecessor_name(&instance), None);
+}
+
+#[test]
+fn only_departing_predecessors_released() {
+    assert!(is_released(&predecessor(), None));
+    assert!(is_released(&predecessor(), Some(&old_mask(true))));
+    assert!(!is_released(&predecessor(), Some(&old_mask(false))));
+
+    // A Mask recreated with the same name doesn't hold the slot.
+    let mut recreated = old_mask(false);
+    recreated.metadata.uid = Some("other-mask-uid".to_owned());
+    assert!(is_released(&predecessor(), Some(&recreated)));
+
+    // The predecessor is being deleted while its Mask is still around.
+    let mut deleting = predecessor();
+    deleting.metadata.deletion_timestamp = Some(Time(Utc::now()));
+    assert!(is_released(&deleting, Some(&old_mask(false))));
+
+    // MaskConsumers without a Mask, including those of external clients,
+    // are never succeeded.
+    let mut unowned = predecessor();
+    unowned.metadata.owner_references = None;
+    assert!(!is_released(&unowned, None));
+    let mut external = predecessor();
+    external.spec.external = Some(true);
+    assert!(!is_released(&external, None));
+    external.metadata.deletion_timestamp = Some(Time(Utc::now()));
+    assert!(!is_released(&external, None));
+}
+
+#[tokio::test]
+async fn live_predecessor_not_succeeded() {
+    let found = |objects: Vec<serde_json::Value>| async move {
+        let (client, _) = mock_cluster(objects);
+        get_predecessor(client, &successor())
+            .await
+            .unwrap()
+            .and_then(|p| p.metadata.uid)
+    };
+
+    // The predecessor's Mask is still around and not being deleted.
+    let live = vec![object(&old_mask(false)), object(&predecessor())];
+    assert_eq!(found(live).await, None);
+
+    // The predecessor's Mask is being deleted or already gone.
+    let terminating = vec![object(&old_mask(true)), object(&predecessor())];
+    assert_eq!(found(terminating).await.as_deref(), Some("old-uid"));
+    assert_eq!(
+        found(vec![object(&predecessor())]).await.as_deref(),
+        Some("old-uid")
+    );
+
+    // The predecessor is gone too, so its reservation is pruned instead.
+    assert_eq!(found(vec![]).await, None);
+}
+
+#[test]
+fn predecessor_reservation_matched() {
+    let reservations = [reservation("old-mask", "old-uid")];
+    assert!(predecessor_reservation(&reservations, &predecessor()).is_some());
+
+    // The reservation was taken over by another MaskConsumer.
+    let reservations = [reservation("other-mask", "other-uid")];
+    assert!(predecessor_reservation(&reservations, &predecessor()).is_none());
+
+    // The predecessor was never assigned.
+    let mut unassigned = predecessor();
+    unassigned.status = None;
+    let reservations = [reservation("old-mask", "old-uid")];
+    assert!(predecessor_reservation(&reservations, &unassigned).is_none());
+}
+
+#[test]
+fn predecessor_copy_adopted() {
+    assert!(is_predecessor_copy(
+        &copy("old-mask", "old-mask"),
+        &successor()
+    ));
+
+    // Copies made for other Masks, or owned by anything else, aren't.
+    assert!(!is_predecessor_copy(
+        &copy("other-mask", "other-mask"),
+        &successor()
+    ));
+    assert!(!is_predecessor_copy(
+        &copy("old-mask", "other-mask"),
+        &successor()
+    ));
+
+    // Nor is the copy adopted without a predecessor.
+    let mut instance = successor();
+    instance.spec.succession_of = None;
+    assert!(!is_predecessor_copy(
+        &copy("old-mask", "old-mask"),
+        &instance
+    ));
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn renamed_mask_succeeds() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+
+    // Create a MaskProvider with a single slot and fill it.
+    let provider = create_test_provider(client.clone(), &namespace, &uid).await?;
+    let provider_name = provider.metadata.name.as_deref().unwrap();
+    let old = create_test_mask(client.clone(), &namespace, 0, provider_name).await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+    let old_name = old.metadata.name.clone().unwrap();
+
+    // Rename the Mask as GitOps would: delete the old one and create the
+    // new one right away, naming the old one as its predecessor.
+    let mask_api: Api<Mask> = Api::namespaced(client.clone(), &namespace);
+    mask_api.delete(&old_name, &Default::default()).await?;
+    let mut new = get_test_mask(&namespace, 1, provider_name);
+    new.spec.succession_of = Some(old_name);
+    mask_api.create(&Default::default(), &new).await?;
+
+    // The new Mask takes over the slot without ever waiting for it.
+    let new_name = new.metadata.name.unwrap();
+    let consumer_api: Api<MaskConsumer> = Api::namespaced(client.clone(), &namespace);
+    let start = Instant::now();
+    let assigned = loop {
+        assert!(
+            start.elapsed() < Duration::from_secs(120),
+            "MaskConsumer was not assigned before timeout"
+        );
+        if let Some(consumer) = consumer_api.get_opt(&new_name).await? {
+            let status = consumer.status.unwrap_or_default();
+            assert_ne!(
+                status.phase,
+                Some(MaskConsumerPhase::Waiting),
+                "successor waited for a slot"
+            );
+            if let Some(provider) = status.provider {
+                break provider;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    };
+    assert_eq!(assigned.uid, provider.metadata.uid.unwrap());
+    assert_eq!(assigned.slot, 0);
+    wait_for_mask_phase(client.clone(), &namespace, 1, MaskPhase::Active).await?;
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,242 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use serde_json::{json, Value};
+use std::time::Duration;
+use vpn_types::*;
+
+use super::mock::{consumer_value, decide, merged, mock_client, patch_op};
+use crate::{
+    masks::{
+        reconcile::{determine_action, MaskAction},
+        ttl,
+    },
+    util::{clock, finalizer::FINALIZER_NAME, messages},
+};
+
+/// Default of `--status-freshness-interval`.
+const FRESHNESS: Duration = Duration::from_secs(600);
+
+/// Returns the timestamp of the given number of minutes ago.
+fn minutes_ago(minutes: i64) -> String {
+    clock::format_timestamp(Utc::now() - ChronoDuration::minutes(minutes))
+}
+
+/// Returns an Active Mask with a ttl of an hour, with `patch` merged into it.
+fn mask(patch: Value) -> Value {
+    let mask = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "Mask",
+        "metadata": {
+            "name": "mask-0",
+            "namespace": "default",
+            "uid": "mask-uid",
+            "generation": 1,
+            "finalizers": [FINALIZER_NAME],
+        },
+        "spec": { "ttl": "1h" },
+        "status": {
+            "phase": "Active",
+            "message": messages::ACTIVE,
+            "lastUpdated": clock::now_k8s(),
+            "lastSlot": 0,
+            "lastProviderUid": "provider-uid",
+        },
+    });
+    merged(mask, patch)
+}
+
+/// Returns the Mask's Active MaskConsumer, created two hours ago, with
+/// `patch` merged into it.
+fn aged_consumer(patch: Value) -> Value {
+    let created = json!({ "metadata": { "creationTimestamp": minutes_ago(120) } });
+    merged(consumer_value(created), patch)
+}
+
+/// Returns a Pod in the phase that reads the Mask's credentials.
+fn pod(phase: &str) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Pod",
+        "metadata": { "name": "job-0", "namespace": "default" },
+        "spec": {
+            "containers": [{
+                "name": "job",
+                "envFrom": [{ "secretRef": { "name": "mask-0-provider-uid" } }],
+            }],
+        },
+        "status": { "phase": phase },
+    })
+}
+
+/// Returns the action decided for the Mask with the given objects in
+/// the cluster.
+async fn action(mask: Value, objects: &[Value]) -> MaskAction {
+    let instance: Mask = serde_json::from_value(mask).unwrap();
+    decide(objects, |client| {
+        let instance = instance.clone();
+        async move {
+            determine_action(
+                client,
+                "mask-0",
+                "default",
+                &instance,
+                FRESHNESS,
+                Utc::now(),
+                false,
+            )
+            .await
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn ttl_actions() {
+    let released = messages::ttl_released("1h");
+    let cases = [
+        (
+            "recently assigned",
+            mask(json!({})),
+            vec![aged_consumer(
+                json!({ "metadata": { "creationTimestamp": minutes_ago(5) } }),
+            )],
+            MaskAction::NoOp,
+        ),
+        (
+            "unused for the ttl",
+            mask(json!({})),
+            vec![aged_consumer(json!({}))],
+            MaskAction::Expire,
+        ),
+        (
+            "pod seen recently",
+            mask(json!({})),
+            vec![aged_consumer(json!({ "status": { "consumers": [{
+                "serviceAccount": "default",
+                "provider": "providers/provider",
+                "firstSeen": minutes_ago(120),
+                "lastSeen": minutes_ago(10),
+            }] } }))],
+            MaskAction::NoOp,
+        ),
+        (
+            "pod running but not seen yet",
+            mask(json!({})),
+            vec![aged_consumer(json!({})), pod("Running")],
+            MaskAction::NoOp,
+        ),
+        (
+            "pod exited",
+            mask(json!({})),
+            vec![aged_consumer(json!({})), pod("Failed")],
+            MaskAction::Expire,
+        ),
+        (
+            "no ttl",
+            mask(json!({ "spec": { "ttl": null } })),
+            vec![aged_consumer(json!({}))],
+            MaskAction::NoOp,
+        ),
+        (
+            "not assigned a slot",
+            mask(json!({ "status": { "phase": "Waiting", "message": messages::WAITING } })),
+            vec![aged_consumer(json!({ "status": {
+                "phase": "Waiting",
+                "message": messages::WAITING,
+                "provider": null,
+            } }))],
+            MaskAction::NoOp,
+        ),
+        (
+            "slot released",
+            mask(json!({ "status": { "expiredGeneration": 1 } })),
+            vec![],
+            MaskAction::Waiting(Some(released.clone())),
+        ),
+        (
+            "slot released and shown",
+            mask(json!({ "status": {
+                "expiredGeneration": 1,
+                "phase": "Waiting",
+                "message": released,
+            } })),
+            vec![],
+            MaskAction::NoOp,
+        ),
+        (
+            "spec changed since the slot was released",
+            mask(json!({ "metadata": { "generation": 2 }, "status": { "expiredGeneration": 1 } })),
+            vec![],
+            MaskAction::CreateConsumer(0),
+        ),
+    ];
+    for (case, mask, objects, expected) in cases {
+        assert_eq!(action(mask, &objects).await, expected, "{}", case);
+    }
+}
+
+#[tokio::test]
+async fn invalid_ttl_shown() {
+    let invalid = mask(json!({ "spec": { "ttl": "soon" } }));
+    let message = ttl::parse(&serde_json::from_value(invalid.clone()).unwrap()).unwrap_err();
+    assert!(message.starts_with("Invalid ttl 'soon'"), "{}", message);
+    // The ttl isn't ignored, and nothing else happens until it's fixed.
+    assert_eq!(
+        action(invalid.clone(), &[aged_consumer(json!({}))]).await,
+        MaskAction::ErrInvalidSpec(message.clone())
+    );
+    assert_eq!(
+        action(invalid, &[]).await,
+        MaskAction::ErrInvalidSpec(message)
+    );
+}
+
+/// Returns the requests made to expire the Mask with the ttl action.
+async fn expire(ttl_action: Option<&str>) -> Vec<(String, String, Value)> {
+    let mask = mask(json!({ "spec": { "ttlAction": ttl_action } }));
+    let instance: Mask = serde_json::from_value(mask.clone()).unwrap();
+    let (client, captured) = mock_client(mask);
+    ttl::expire(client, "mask-0", "default", &instance)
+        .await
+        .unwrap();
+    let captured = captured.lock().unwrap();
+    captured
+        .iter()
+        .filter(|r| !r.path.contains("/events"))
+        .map(|r| {
+            let expired = patch_op(r, "/status/expiredGeneration").cloned();
+            (
+                r.method.clone(),
+                r.path.clone(),
+                expired.unwrap_or(Value::Null),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn expired_mask_released_or_deleted() {
+    // By default, the slot is released and the Mask isn't assigned
+    // another until its spec changes.
+    let requests = expire(None).await;
+    let patch = requests
+        .iter()
+        .find(|(method, path, _)| method == "PATCH" && path.contains("/masks/mask-0/status"))
+        .expect("status not patched");
+    assert_eq!(patch.2, json!(1));
+    assert!(requests.iter().any(|(method, path, _)| method == "DELETE"
+        && path.starts_with("/apis/vpn.beebs.dev/v1/namespaces/default/maskconsumers/mask-0")));
+
+    // With ttlAction: Delete, the Mask goes away along with the slot.
+    let requests = expire(Some("Delete")).await;
+    let methods: Vec<(&str, &str)> = requests
+        .iter()
+        .map(|(method, path, _)| (method.as_str(), path.split('?').next().unwrap()))
+        .collect();
+    assert_eq!(
+        methods,
+        vec![(
+            "DELETE",
+            "/apis/vpn.beebs.dev/v1/namespaces/default/masks/mask-0"
+        )]
+    );
+}
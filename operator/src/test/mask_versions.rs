@@ -0,0 +1,266 @@
+use kube::{
+    api::ObjectMeta,
+    client::Client,
+    core::{
+        conversion::{ConversionRequest, ConversionReview},
+        TypeMeta,
+    },
+    Api,
+};
+use serde_json::json;
+use std::collections::BTreeMap;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::util::*;
+use crate::webhook::conversion::{convert, review};
+
+/// Returns a v1 Mask with every option set.
+fn v1_mask() -> Mask {
+    Mask {
+        metadata: ObjectMeta {
+            name: Some("test-mask-0".to_owned()),
+            namespace: Some("default".to_owned()),
+            uid: Some("mask-uid".to_owned()),
+            ..Default::default()
+        },
+        spec: MaskSpec {
+            providers: Some(vec!["my-vpn".to_owned()]),
+            settings: MaskDefaultsSpec {
+                secret_keys: Some(vec!["OPENVPN_USER".to_owned()]),
+                immutable_secret: Some(true),
+                secret_format: Some(SecretFormat::Both),
+                file_projection: Some(BTreeMap::from([(
+                    "password.txt".to_owned(),
+                    "OPENVPN_PASSWORD".to_owned(),
+                )])),
+            },
+            deletion_policy: Some(DeletionPolicy::WaitForPods),
+            anti_affinity: Some(MaskAntiAffinity {
+                group: "scrapers".to_owned(),
+            }),
+            strategy: Some(AssignmentStrategy::TopologyAware),
+            succession_of: Some("test-mask-old".to_owned()),
+            ttl: Some("6h".to_owned()),
+            ttl_action: Some(MaskTtlAction::Delete),
+            service: Some(MaskServiceSpec {
+                port: 8888,
+                target_port: Some(8888),
+                selector_labels: BTreeMap::from([("app".to_owned(), "scraper".to_owned())]),
+            }),
+            slots: Some(3),
+        },
+        status: Some(MaskStatus {
+            phase: Some(MaskPhase::Active),
+            last_slot: Some(2),
+            ..Default::default()
+        }),
+    }
+}
+
+#[test]
+fn typed_conversion_round_trips() {
+    let v1 = v1_mask();
+    let v2 = v2::Mask::from(v1.clone());
+    assert_eq!(v2.metadata, v1.metadata);
+    assert_eq!(v2.status, v1.status);
+    assert_eq!(v2.spec.assignment.providers, v1.spec.providers);
+    assert_eq!(v2.spec.credentials.keys, v1.spec.settings.secret_keys);
+    assert_eq!(v2.spec.credentials.format, Some(SecretFormat::Both));
+    assert_eq!(v2.spec.credentials.immutable, Some(true));
+    assert_eq!(v2.spec.credentials.files, v1.spec.settings.file_projection);
+    assert_eq!(
+        v2.spec.credentials.deletion_policy,
+        Some(DeletionPolicy::WaitForPods)
+    );
+    assert_eq!(v2.spec.assignment.anti_affinity, v1.spec.anti_affinity);
+    assert_eq!(
+        v2.spec.assignment.strategy,
+        Some(AssignmentStrategy::TopologyAware)
+    );
+    assert_eq!(v2.spec.assignment.succession_of, v1.spec.succession_of);
+    assert_eq!(v2.spec.assignment.ttl, v1.spec.ttl);
+    assert_eq!(v2.spec.assignment.ttl_action, Some(MaskTtlAction::Delete));
+    assert_eq!(v2.spec.service, v1.spec.service);
+    assert_eq!(v2.spec.assignment.slots, Some(3));
+    assert_eq!(Mask::from(v2.clone()), v1);
+
+    // Both versions read the same through the normalized view.
+    assert_eq!(v2.spec.options(), v1.spec.options());
+
+    // Unset options stay unset in both directions.
+    let empty = v2::Mask::from(Mask::default());
+    assert_eq!(empty.spec, v2::MaskSpec::default());
+    assert_eq!(Mask::from(empty), Mask::default());
+}
+
+#[test]
+fn objects_converted_in_both_directions() {
+    let v1 = serde_json::to_value(v1_mask()).unwrap();
+    let v2 = convert(v1.clone(), "vpn.beebs.dev/v2").unwrap();
+    assert_eq!(
+        v2["spec"],
+        json!({
+            "assignment": {
+                "providers": ["my-vpn"],
+                "antiAffinity": { "group": "scrapers" },
+                "strategy": "TopologyAware",
+                "successionOf": "test-mask-old",
+                "ttl": "6h",
+                "ttlAction": "Delete",
+                "slots": 3,
+            },
+            "credentials": {
+                "keys": ["OPENVPN_USER"],
+                "format": "Both",
+                "immutable": true,
+                "files": { "password.txt": "OPENVPN_PASSWORD" },
+                "deletionPolicy": "WaitForPods",
+            },
+            "service": {
+                "port": 8888,
+                "targetPort": 8888,
+                "selectorLabels": { "app": "scraper" },
+            },
+        })
+    );
+    // Only the apiVersion and spec change.
+    assert_eq!(v2["apiVersion"], "vpn.beebs.dev/v2");
+    assert_eq!(v2["metadata"], v1["metadata"]);
+    assert_eq!(v2["status"], v1["status"]);
+    assert_eq!(convert(v2.clone(), "vpn.beebs.dev/v1").unwrap(), v1);
+
+    // Options omitted in v2 are omitted in v1, and the reverse.
+    let sparse = json!({
+        "apiVersion": "vpn.beebs.dev/v2",
+        "kind": "Mask",
+        "metadata": { "name": "test-mask-1" },
+        "spec": { "credentials": { "format": "GluetunToml" } },
+    });
+    let converted = convert(sparse.clone(), "vpn.beebs.dev/v1").unwrap();
+    assert_eq!(converted["spec"], json!({ "secretFormat": "GluetunToml" }));
+    let back = convert(converted, "vpn.beebs.dev/v2").unwrap();
+    assert_eq!(
+        back["spec"],
+        json!({
+            "assignment": {},
+            "credentials": { "format": "GluetunToml" },
+        })
+    );
+
+    // Objects already in the desired version are returned as is.
+    assert_eq!(convert(sparse.clone(), "vpn.beebs.dev/v2").unwrap(), sparse);
+}
+
+#[test]
+fn unknown_versions_rejected() {
+    let v1 = serde_json::to_value(v1_mask()).unwrap();
+    assert!(convert(v1.clone(), "vpn.beebs.dev/v3").is_err());
+    let mut unknown = v1.clone();
+    unknown["apiVersion"] = json!("vpn.beebs.dev/v0");
+    assert!(convert(unknown, "vpn.beebs.dev/v2").is_err());
+    let mut provider = v1;
+    provider["kind"] = json!("MaskProvider");
+    assert!(convert(provider, "vpn.beebs.dev/v2").is_err());
+}
+
+/// Returns a review asking to convert the objects to the given version.
+fn conversion_review(
+    desired_api_version: &str,
+    objects: Vec<serde_json::Value>,
+) -> ConversionReview {
+    ConversionReview {
+        types: TypeMeta {
+            api_version: "apiextensions.k8s.io/v1".to_owned(),
+            kind: "ConversionReview".to_owned(),
+        },
+        request: Some(ConversionRequest {
+            types: None,
+            uid: "review-uid".to_owned(),
+            desired_api_version: desired_api_version.to_owned(),
+            objects,
+        }),
+        response: None,
+    }
+}
+
+#[test]
+fn review_converts_every_object() {
+    let v1 = serde_json::to_value(v1_mask()).unwrap();
+    let v2 = convert(v1.clone(), "vpn.beebs.dev/v2").unwrap();
+    let response = review(conversion_review(
+        "vpn.beebs.dev/v1",
+        vec![v2.clone(), v1.clone()],
+    ))
+    .response
+    .unwrap();
+    assert_eq!(response.uid, "review-uid");
+    assert!(response.result.is_success());
+    assert_eq!(response.converted_objects, vec![v1.clone(), v1.clone()]);
+
+    // A single object that can't be converted fails the whole review.
+    let response = review(conversion_review("vpn.beebs.dev/v3", vec![v1]))
+        .response
+        .unwrap();
+    assert_eq!(response.uid, "review-uid");
+    assert!(response.result.is_failure());
+    assert!(response.converted_objects.is_empty());
+
+    // Reviews without a request are answered as invalid.
+    let mut invalid = conversion_review("vpn.beebs.dev/v1", vec![]);
+    invalid.request = None;
+    let response = review(invalid).response.unwrap();
+    assert!(response.result.is_failure());
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn both_versions_reconcile() -> Result<(), Error> {
+    // Requires the conversion webhook to be running in the cluster, and
+    // the Mask CRD in crds/webhook/ applied so v2 is served.
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+    let provider_ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    create_test_provider(client.clone(), &namespace, &uid).await?;
+    provider_ready.await.unwrap()?;
+
+    // A Mask written as v1, as it was before v2 existed, is still
+    // reconciled, and can be read back as v2.
+    let mask_active = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(async move { wait_for_mask_phase(client, &namespace, 0, MaskPhase::Active).await })
+    };
+    create_test_mask(client.clone(), &namespace, 0, &provider_name).await?;
+    mask_active.await.unwrap()?;
+    let v2_api: Api<v2::Mask> = Api::namespaced(client.clone(), &namespace);
+    let mask = v2_api.get(&format!("{}-0", MASK_NAME)).await?;
+    assert_eq!(
+        mask.spec.assignment.providers,
+        Some(vec![provider_name.clone()])
+    );
+    assert_eq!(mask.status.and_then(|s| s.phase), Some(MaskPhase::Active));
+    delete_test_mask(client.clone(), &namespace, 0).await?;
+
+    // A Mask written as v2 is reconciled the same way.
+    let mask_active = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(async move { wait_for_mask_phase(client, &namespace, 1, MaskPhase::Active).await })
+    };
+    let mask = v2::Mask::from(get_test_mask(&namespace, 1, &provider_name));
+    v2_api.create(&Default::default(), &mask).await?;
+    mask_active.await.unwrap()?;
+    delete_test_mask(client.clone(), &namespace, 1).await?;
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
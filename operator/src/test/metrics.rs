@@ -0,0 +1,287 @@
+use chrono::Utc;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use kube::api::ObjectMeta;
+use prometheus::{proto::MetricFamily, IntGaugeVec, Opts, Registry};
+use vpn_types::*;
+
+use super::util::mask_provider;
+use crate::util::{
+    metric_names::{self, controller_name, full_name, CONTROLLERS},
+    metrics::{
+        observe_mask_info, observe_provider, observe_provider_info, prefix, ControllerMetrics,
+        InfoGauge, MASK_INFO_LABELS, PROVIDER_ACTIVE_SLOTS_GAUGE, PROVIDER_INFO_LABELS,
+    },
+    Error,
+};
+
+/// Returns the label sets and sample counts of the write histogram.
+fn write_samples(registry: &Registry) -> Vec<(Vec<(String, String)>, u64)> {
+    let families: Vec<MetricFamily> = registry.gather();
+    let family = families
+        .iter()
+        .find(|f| f.get_name().ends_with("_test_write_duration_seconds"))
+        .unwrap();
+    family
+        .get_metric()
+        .iter()
+        .map(|m| {
+            let labels = m
+                .get_label()
+                .iter()
+                .map(|l| (l.get_name().to_owned(), l.get_value().to_owned()))
+                .collect();
+            (labels, m.get_histogram().get_sample_count())
+        })
+        .collect()
+}
+
+fn labels(action: &str, outcome: &str) -> Vec<(String, String)> {
+    [
+        ("action", action),
+        ("name", "test-mask"),
+        ("namespace", "default"),
+        ("outcome", outcome),
+    ]
+    .iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect()
+}
+
+#[test]
+fn write_phase_is_labeled_with_outcome() {
+    let registry = Registry::new();
+    let metrics = ControllerMetrics::with_registry("test", &registry);
+
+    // A forced error is observed once with the error outcome.
+    let result: Result<(), Error> = Err(Error::UserInputError("forced".to_owned()));
+    metrics
+        .write("test-mask", "default", "CreateConsumer")
+        .observe(&result);
+    // A successful action is observed once with the success outcome.
+    let result: Result<(), Error> = Ok(());
+    metrics
+        .write("test-mask", "default", "Active")
+        .observe(&result);
+
+    let mut samples = write_samples(&registry);
+    samples.sort();
+    assert_eq!(
+        samples,
+        vec![
+            (labels("Active", "success"), 1),
+            (labels("CreateConsumer", "error"), 1),
+        ]
+    );
+}
+
+#[test]
+fn dropped_write_timer_is_observed_as_error() {
+    let registry = Registry::new();
+    let metrics = ControllerMetrics::with_registry("test", &registry);
+    drop(metrics.write("test-mask", "default", "Delete"));
+    assert_eq!(
+        write_samples(&registry),
+        vec![(labels("Delete", "error"), 1)]
+    );
+}
+
+#[test]
+fn registered_metrics_are_listed() {
+    // Every metric a controller registers is listed by name, so the
+    // generated dashboards can only reference metrics that exist.
+    let known = metric_names::all(&prefix());
+    for tag in CONTROLLERS {
+        let registry = Registry::new();
+        let metrics = ControllerMetrics::with_registry(tag, &registry);
+        metrics.reconcile("test-mask", "default");
+        metrics.read("test-mask", "default", "Active", std::time::Instant::now());
+        metrics
+            .write("test-mask", "default", "Active")
+            .observe(&Ok::<(), Error>(()));
+        let families = registry.gather();
+        assert_eq!(families.len(), 4);
+        for family in families {
+            let name = family.get_name();
+            assert!(known.iter().any(|k| k == name), "{} is not listed", name);
+        }
+    }
+    assert!(known.contains(&controller_name(
+        &prefix(),
+        "masks",
+        metric_names::ACTION_COUNTER
+    )));
+    assert!(known.contains(&full_name(&prefix(), metric_names::PROVIDER_MAX_SLOTS)));
+}
+
+/// Returns a MaskProvider in the given phase with slots in use.
+fn gauged_provider(phase: MaskProviderPhase, active_slots: usize, deleting: bool) -> MaskProvider {
+    let mut provider = mask_provider("gauge-test-provider", "gauge-test", "provider-uid");
+    provider.metadata.deletion_timestamp = deleting.then(|| Time(Utc::now()));
+    provider.spec.max_slots = 4;
+    provider.status = Some(MaskProviderStatus {
+        phase: Some(phase),
+        active_slots: Some(active_slots),
+        ..Default::default()
+    });
+    provider
+}
+
+/// Returns the phases of the test MaskProvider that have a series.
+fn provider_phases() -> Vec<String> {
+    prometheus::gather()
+        .iter()
+        .filter(|f| f.get_name() == full_name(&prefix(), metric_names::PROVIDER_PHASE))
+        .flat_map(|f| f.get_metric().iter())
+        .filter(|m| {
+            m.get_label()
+                .iter()
+                .any(|l| l.get_name() == "namespace" && l.get_value() == "gauge-test")
+        })
+        .map(|m| {
+            m.get_label()
+                .iter()
+                .find(|l| l.get_name() == "phase")
+                .unwrap()
+                .get_value()
+                .to_owned()
+        })
+        .collect()
+}
+
+#[test]
+fn provider_gauges_follow_resource() {
+    let labels = ["gauge-test", "gauge-test-provider"];
+    observe_provider(&gauged_provider(MaskProviderPhase::Ready, 0, false), false);
+    assert_eq!(provider_phases(), vec!["Ready"]);
+
+    // Changing phase replaces the series of the previous phase.
+    observe_provider(&gauged_provider(MaskProviderPhase::Active, 3, false), false);
+    assert_eq!(provider_phases(), vec!["Active"]);
+    assert_eq!(
+        PROVIDER_ACTIVE_SLOTS_GAUGE.with_label_values(&labels).get(),
+        3
+    );
+
+    // Deleting the MaskProvider removes its series.
+    observe_provider(
+        &gauged_provider(MaskProviderPhase::Terminating, 3, true),
+        false,
+    );
+    assert!(provider_phases().is_empty());
+    assert!(PROVIDER_ACTIVE_SLOTS_GAUGE
+        .remove_label_values(&labels)
+        .is_err());
+}
+
+/// Returns an info gauge with the labels, registered with the registry.
+fn info_gauge(registry: &Registry, labels: &[&str]) -> InfoGauge {
+    let gauge = IntGaugeVec::new(Opts::new("test_info", "Test info metric."), labels).unwrap();
+    registry.register(Box::new(gauge.clone())).unwrap();
+    InfoGauge::new(gauge)
+}
+
+/// Returns the label sets and values of every series in the registry.
+fn info_samples(registry: &Registry) -> Vec<(Vec<(String, String)>, i64)> {
+    registry
+        .gather()
+        .iter()
+        .flat_map(|f| f.get_metric().iter())
+        .map(|m| {
+            let labels = m
+                .get_label()
+                .iter()
+                .map(|l| (l.get_name().to_owned(), l.get_value().to_owned()))
+                .collect();
+            (labels, m.get_gauge().get_value() as i64)
+        })
+        .collect()
+}
+
+/// Returns the label set with the given names and values.
+fn label_set(labels: &[(&str, &str)]) -> Vec<(String, String)> {
+    labels
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn provider_info_follows_resource() {
+    let registry = Registry::new();
+    let gauge = info_gauge(&registry, &PROVIDER_INFO_LABELS);
+    let mut instance = gauged_provider(MaskProviderPhase::Ready, 0, false);
+    instance.metadata.uid = Some("provider-uid".to_owned());
+    instance.spec.tags = Some(vec!["us-west".to_owned(), "premium".to_owned()]);
+    observe_provider_info(&gauge, &instance);
+    // The tags are sorted so the series doesn't depend on their order.
+    let info = |tags: &str, max_slots: &str| {
+        label_set(&[
+            ("max_slots", max_slots),
+            ("name", "gauge-test-provider"),
+            ("namespace", "gauge-test"),
+            ("tags", tags),
+            ("uid", "provider-uid"),
+        ])
+    };
+    assert_eq!(
+        info_samples(&registry),
+        vec![(info("premium,us-west", "4"), 1)]
+    );
+
+    // Changing the information replaces the series.
+    instance.spec.max_slots = 8;
+    instance.spec.tags = None;
+    observe_provider_info(&gauge, &instance);
+    assert_eq!(info_samples(&registry), vec![(info("", "8"), 1)]);
+
+    // Deleting the MaskProvider removes its series.
+    instance.metadata.deletion_timestamp = Some(Time(Utc::now()));
+    observe_provider_info(&gauge, &instance);
+    assert!(info_samples(&registry).is_empty());
+}
+
+#[test]
+fn mask_info_follows_resource() {
+    let registry = Registry::new();
+    let gauge = info_gauge(&registry, &MASK_INFO_LABELS);
+    let mut mask = Mask {
+        metadata: ObjectMeta {
+            name: Some("info-test-mask".to_owned()),
+            namespace: Some("default".to_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let info = |provider: &str, slot: &str| {
+        label_set(&[
+            ("name", "info-test-mask"),
+            ("namespace", "default"),
+            ("provider", provider),
+            ("slot", slot),
+        ])
+    };
+
+    // Unassigned Masks have an empty provider and slot.
+    observe_mask_info(&gauge, &mask, None);
+    assert_eq!(info_samples(&registry), vec![(info("", ""), 1)]);
+
+    let consumer = MaskConsumer {
+        status: Some(MaskConsumerStatus {
+            provider: Some(AssignedProvider {
+                name: "my-provider".to_owned(),
+                namespace: "vpn".to_owned(),
+                slot: 2,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    observe_mask_info(&gauge, &mask, Some(&consumer));
+    assert_eq!(info_samples(&registry), vec![(info("my-provider", "2"), 1)]);
+
+    // Deleting the Mask removes its series.
+    mask.metadata.deletion_timestamp = Some(Time(Utc::now()));
+    observe_mask_info(&gauge, &mask, Some(&consumer));
+    assert!(info_samples(&registry).is_empty());
+}
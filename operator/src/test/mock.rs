@@ -0,0 +1,538 @@
+use hyper::{header::USER_AGENT, Body, Request, Response};
+use kube::Client;
+use serde_json::Value;
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex},
+};
+use tower::{
+    util::{BoxCloneService, ServiceFn},
+    ServiceExt,
+};
+
+/// A request captured by the mock transport.
+#[derive(Clone, Debug)]
+pub struct CapturedRequest {
+    /// HTTP method of the request.
+    pub method: String,
+
+    /// Path and query of the request.
+    pub path: String,
+
+    /// Value of the user agent header, if present.
+    pub user_agent: Option<String>,
+
+    /// Body of the request, parsed as JSON. Null if the body is empty.
+    pub body: Value,
+}
+
+/// List of requests captured by the mock transport, in order.
+pub type Captured = Arc<Mutex<Vec<CapturedRequest>>>;
+
+/// Returns a mock transport that records every request and answers
+/// with the given status code and JSON body. No cluster is required.
+pub fn mock_service(
+    status: u16,
+    response: Value,
+) -> (
+    BoxCloneService<Request<Body>, Response<Body>, Infallible>,
+    Captured,
+) {
+    mock_responder(move |_| (status, response.clone()))
+}
+
+/// Returns a mock transport that records every request and answers
+/// with the status code and JSON body returned by `respond`, which
+/// is given the captured request.
+fn mock_responder(
+    respond: impl Fn(&CapturedRequest) -> (u16, Value) + Clone + Send + 'static,
+) -> (
+    BoxCloneService<Request<Body>, Response<Body>, Infallible>,
+    Captured,
+) {
+    let captured: Captured = Default::default();
+    let service = {
+        let captured = captured.clone();
+        tower::service_fn(move |req: Request<Body>| {
+            let captured = captured.clone();
+            let respond = respond.clone();
+            async move {
+                let (parts, body) = req.into_parts();
+                let body = hyper::body::to_bytes(body).await.unwrap();
+                let path = parts
+                    .uri
+                    .path_and_query()
+                    .map(|p| p.to_string())
+                    .unwrap_or_default();
+                let request = CapturedRequest {
+                    method: parts.method.to_string(),
+                    path,
+                    user_agent: parts
+                        .headers
+                        .get(USER_AGENT)
+                        .map(|v| v.to_str().unwrap().to_owned()),
+                    body: serde_json::from_slice(&body).unwrap_or(Value::Null),
+                };
+                let (status, response) = respond(&request);
+                captured.lock().unwrap().push(request);
+                Ok::<_, Infallible>(
+                    Response::builder()
+                        .status(status)
+                        .body(Body::from(response.to_string()))
+                        .unwrap(),
+                )
+            }
+        })
+    };
+    (
+        ServiceExt::<Request<Body>>::boxed_clone(service as ServiceFn<_>),
+        captured,
+    )
+}
+
+/// Returns a client backed by [`mock_service`] that answers every
+/// request with status 200 and the given JSON body.
+pub fn mock_client(response: Value) -> (Client, Captured) {
+    let (service, captured) = mock_service(200, response);
+    (Client::new(service, "default"), captured)
+}
+
+/// Returns a client that answers the requests in order with status 200
+/// and the given JSON bodies. Requests beyond the last body are answered
+/// with the last body again.
+pub fn mock_sequence(responses: Vec<Value>) -> (Client, Captured) {
+    let next = Arc::new(Mutex::new(0));
+    let (service, captured) = mock_responder(move |_| {
+        let mut next = next.lock().unwrap();
+        let response = responses[(*next).min(responses.len() - 1)].clone();
+        *next += 1;
+        (200, response)
+    });
+    (Client::new(service, "default"), captured)
+}
+
+/// Returns a client that answers each request with the body of the
+/// first route whose path is a prefix of the request's path. Requests
+/// that match no route are answered with a 404 failure.
+pub fn mock_routes(routes: Vec<(&'static str, Value)>) -> (Client, Captured) {
+    let (service, captured) = mock_responder(move |request| {
+        routes
+            .iter()
+            .find(|(prefix, _)| request.path.starts_with(prefix))
+            .map_or_else(
+                || (404, status_failure(404)),
+                |(_, body)| (200, body.clone()),
+            )
+    });
+    (Client::new(service, "default"), captured)
+}
+
+/// Returns a client that answers each request with the status code and
+/// body of the first route whose method matches the request's and whose
+/// path is a prefix of the request's path. Requests that match no route
+/// are answered with a 404 failure.
+pub fn mock_method_routes(
+    routes: Vec<(&'static str, &'static str, u16, Value)>,
+) -> (Client, Captured) {
+    let (service, captured) = mock_responder(move |request| {
+        routes
+            .iter()
+            .find(|(method, prefix, _, _)| {
+                request.method == *method && request.path.starts_with(prefix)
+            })
+            .map_or_else(
+                || (404, status_failure(404)),
+                |(_, _, status, body)| (*status, body.clone()),
+            )
+    });
+    (Client::new(service, "default"), captured)
+}
+
+/// Returns a client backed by a single stored resource. JSON patches are
+/// applied to the stored resource, test operations included, and a patch
+/// whose test fails is refused with the same 422 failure the apiserver
+/// answers with. Every request is answered with the stored resource.
+pub fn mock_store(resource: Value) -> (Client, Captured, Arc<Mutex<Value>>) {
+    mock_contended_store(resource, |_| {})
+}
+
+/// Returns a client backed by a single stored resource, like [`mock_store`],
+/// where `interleave` modifies the stored resource right before the first
+/// PATCH is handled, as another controller writing between the read and
+/// the patch would.
+pub fn mock_contended_store(
+    resource: Value,
+    interleave: impl Fn(&mut Value) + Clone + Send + 'static,
+) -> (Client, Captured, Arc<Mutex<Value>>) {
+    let stored = Arc::new(Mutex::new(resource));
+    let interleaved = Arc::new(Mutex::new(false));
+    let (service, captured) = {
+        let stored = stored.clone();
+        mock_responder(move |request| {
+            let mut stored = stored.lock().unwrap();
+            if request.method == "PATCH" {
+                let mut interleaved = interleaved.lock().unwrap();
+                if !*interleaved {
+                    *interleaved = true;
+                    interleave(&mut stored);
+                }
+                let mut patch: json_patch::Patch =
+                    serde_json::from_value(request.body.clone()).unwrap();
+                // As with the apiserver, testing a missing value for null passes.
+                patch.0.retain(|op| match op {
+                    json_patch::PatchOperation::Test(test) => {
+                        !(test.value.is_null() && stored.pointer(&test.path).is_none())
+                    }
+                    _ => true,
+                });
+                let mut patched = stored.clone();
+                if let Err(e) = json_patch::patch(&mut patched, &patch) {
+                    let mut failure = status_failure(422);
+                    failure["message"] = format!("testing value failed: {}", e).into();
+                    return (422, failure);
+                }
+                *stored = patched;
+            }
+            (200, stored.clone())
+        })
+    };
+    (Client::new(service, "default"), captured, stored)
+}
+
+/// Returns a JSON body for a `Status` failure with the given code. A 404
+/// carries the NotFound reason, as `Api::get_opt` relies on it.
+pub fn status_failure(code: u16) -> Value {
+    let mut failure = serde_json::json!({
+        "kind": "Status",
+        "apiVersion": "v1",
+        "status": "Failure",
+        "code": code,
+    });
+    if code == 404 {
+        failure["reason"] = "NotFound".into();
+    }
+    failure
+}
+
+/// Returns the value of the JSON patch operation with the given path
+/// within the captured request body, if such an operation exists. Test
+/// operations are skipped, as they don't change the resource.
+pub fn patch_op<'a>(request: &'a CapturedRequest, path: &str) -> Option<&'a Value> {
+    request
+        .body
+        .as_array()?
+        .iter()
+        .find(|op| op["path"] == path && op["op"] != "test")
+        .map(|op| &op["value"])
+}
+
+/// Returns the API base path, plural name, namespace and name that the
+/// object is served at, derived from its `apiVersion` and `kind`.
+fn object_location(object: &Value) -> (String, String, Option<String>, String) {
+    let api_version = object["apiVersion"].as_str().unwrap();
+    let base = match api_version.contains('/') {
+        true => format!("/apis/{}", api_version),
+        false => format!("/api/{}", api_version),
+    };
+    let kind = object["kind"].as_str().unwrap().to_lowercase();
+    let plural = match kind.ends_with('s') {
+        true => format!("{}es", kind),
+        false => format!("{}s", kind),
+    };
+    let metadata = &object["metadata"];
+    (
+        base,
+        plural,
+        metadata["namespace"].as_str().map(str::to_owned),
+        metadata["name"].as_str().unwrap().to_owned(),
+    )
+}
+
+/// Splits the path of a request into the API base path, the namespace
+/// if the path is namespaced, the plural name, and the name of the
+/// object if the request is for a single object rather than a list.
+fn request_location(path: &str) -> Option<(String, Option<String>, String, Option<String>)> {
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    let (base, rest) = match segments.as_slice() {
+        ["api", version, rest @ ..] => (format!("/api/{}", version), rest),
+        ["apis", group, version, rest @ ..] => (format!("/apis/{}/{}", group, version), rest),
+        _ => return None,
+    };
+    let owned = |s: &&str| s.to_string();
+    Some(match rest {
+        ["namespaces", namespace, plural, name @ ..] => (
+            base,
+            Some(namespace.to_string()),
+            plural.to_string(),
+            name.first().map(owned),
+        ),
+        [plural, name @ ..] => (base, None, plural.to_string(), name.first().map(owned)),
+        _ => return None,
+    })
+}
+
+/// Returns the value of the query parameter, percent-decoded.
+fn query_param(query: &str, key: &str) -> Option<String> {
+    let value = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))?;
+    let mut decoded = Vec::new();
+    let mut bytes = value.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex: String = bytes.by_ref().take(2).map(char::from).collect();
+                decoded.push(u8::from_str_radix(&hex, 16).unwrap());
+            }
+            b'+' => decoded.push(b' '),
+            b => decoded.push(b),
+        }
+    }
+    Some(String::from_utf8(decoded).unwrap())
+}
+
+/// Returns true if the object's labels match the equality-based label
+/// selector, e.g. `a=b,c!=d,e,!f`.
+fn matches_labels(object: &Value, selector: &str) -> bool {
+    let labels = &object["metadata"]["labels"];
+    selector
+        .split(',')
+        .filter(|term| !term.is_empty())
+        .all(|term| {
+            if let Some((key, value)) = term.split_once("!=") {
+                labels[key] != value
+            } else if let Some((key, value)) =
+                term.split_once("==").or_else(|| term.split_once('='))
+            {
+                labels[key] == value
+            } else if let Some(key) = term.strip_prefix('!') {
+                labels[key].is_null()
+            } else {
+                !labels[term].is_null()
+            }
+        })
+}
+
+/// Returns true if the object's fields match the equality-based field
+/// selector, e.g. `metadata.name=a,metadata.namespace!=b`.
+fn matches_fields(object: &Value, selector: &str) -> bool {
+    let field = |path: &str| {
+        path.split('.')
+            .fold(object, |value, key| &value[key])
+            .as_str()
+            .map(str::to_owned)
+    };
+    selector
+        .split(',')
+        .filter(|term| !term.is_empty())
+        .all(|term| {
+            if let Some((path, value)) = term.split_once("!=") {
+                field(path).as_deref() != Some(value)
+            } else {
+                let (path, value) = term
+                    .split_once("==")
+                    .or_else(|| term.split_once('='))
+                    .unwrap();
+                field(path).as_deref() == Some(value)
+            }
+        })
+}
+
+/// Returns a client backed by an in-memory cluster that holds the given
+/// objects, each with its `apiVersion`, `kind` and `metadata`. Objects
+/// are read at their own paths, including their subresources, and their
+/// collections are listed within a namespace or across all of them,
+/// filtered by equality-based label and field selectors. Lists of kinds
+/// without objects are empty. Writes change
+/// nothing and are refused, so they show up as errors, and every other
+/// request is answered with a 404 failure.
+pub fn mock_cluster(objects: Vec<Value>) -> (Client, Captured) {
+    let (service, captured) = mock_responder(move |request| {
+        if request.method != "GET" {
+            return (405, status_failure(405));
+        }
+        let (path, query) = request.path.split_once('?').unwrap_or((&request.path, ""));
+        let (base, namespace, plural, name) = match request_location(path) {
+            Some(location) => location,
+            None => return (404, status_failure(404)),
+        };
+        let mut matching = objects.iter().filter(|object| {
+            let (b, p, ns, _) = object_location(object);
+            b == base && p == plural && (namespace.is_none() || ns == namespace)
+        });
+        match name {
+            Some(name) => matching
+                .find(|object| object_location(object).3 == name)
+                .map_or_else(
+                    || (404, status_failure(404)),
+                    |object| (200, object.clone()),
+                ),
+            None => {
+                let labels = query_param(query, "labelSelector").unwrap_or_default();
+                let fields = query_param(query, "fieldSelector").unwrap_or_default();
+                let items: Vec<Value> = matching
+                    .filter(|object| matches_labels(object, &labels))
+                    .filter(|object| matches_fields(object, &fields))
+                    .cloned()
+                    .collect();
+                (
+                    200,
+                    serde_json::json!({
+                        "apiVersion": "v1",
+                        "kind": "List",
+                        "metadata": { "resourceVersion": "" },
+                        "items": items,
+                    }),
+                )
+            }
+        }
+    });
+    (Client::new(service, "default"), captured)
+}
+
+/// Decides an action twice with `decide`, each time against an in-memory
+/// cluster holding `objects` as with [`mock_cluster`], the second time
+/// with the objects listed in reverse order. Returns the action after
+/// asserting that both decisions agree, so the decision doesn't depend
+/// on the order the API server lists objects in, and that deciding only
+/// read from the cluster, so it is idempotent.
+pub async fn decide<A, Fut>(objects: &[Value], decide: impl Fn(Client) -> Fut) -> A
+where
+    A: std::fmt::Debug + PartialEq,
+    Fut: std::future::Future<Output = Result<A, crate::util::Error>>,
+{
+    let mut decisions = Vec::new();
+    for objects in [objects.to_vec(), objects.iter().rev().cloned().collect()] {
+        let (client, captured) = mock_cluster(objects);
+        decisions.push(decide(client).await.unwrap());
+        let writes: Vec<String> = captured
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.method != "GET")
+            .map(|r| format!("{} {}", r.method, r.path))
+            .collect();
+        assert!(
+            writes.is_empty(),
+            "deciding wrote to the cluster: {:?}",
+            writes
+        );
+    }
+    let second = decisions.pop().unwrap();
+    let first = decisions.pop().unwrap();
+    assert_eq!(first, second, "decision depends on the order of objects");
+    first
+}
+
+/// Returns `base` with `patch` merged into it like a JSON merge patch:
+/// objects are merged recursively, and null removes the field.
+pub fn merged(mut base: Value, patch: Value) -> Value {
+    match (base.as_object_mut(), patch) {
+        (Some(fields), Value::Object(patch)) => {
+            for (key, value) in patch {
+                match value {
+                    Value::Null => {
+                        fields.remove(&key);
+                    }
+                    value => {
+                        let current = fields.remove(&key).unwrap_or(Value::Null);
+                        fields.insert(key, merged(current, value));
+                    }
+                }
+            }
+            base
+        }
+        (_, patch) => patch,
+    }
+}
+
+/// Returns the MaskProvider with the checksum of its current credentials,
+/// verification settings and image recorded as the last verified, so its
+/// verification is current as long as `lastVerified` is.
+pub fn with_verified_config(provider: Value) -> Value {
+    let instance: vpn_types::MaskProvider = serde_json::from_value(provider.clone()).unwrap();
+    let hash = crate::providers::verify_hash::config_hash(&instance, instance.spec.verify.as_ref())
+        .unwrap();
+    merged(
+        provider,
+        serde_json::json!({ "status": { "lastVerifiedConfigHash": hash } }),
+    )
+}
+
+/// Returns the Ready MaskProvider `provider` in the `providers` namespace
+/// with both of its slots free and the checksum of its empty credentials
+/// recorded, with `patch` merged into it.
+pub fn provider_value(patch: Value) -> Value {
+    let now = chrono::Utc::now().to_rfc3339();
+    let provider = serde_json::json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskProvider",
+        "metadata": {
+            "name": "provider",
+            "namespace": "providers",
+            "uid": "provider-uid",
+            "finalizers": [crate::util::finalizer::FINALIZER_NAME],
+        },
+        "spec": { "secret": "provider-credentials", "maxSlots": 2 },
+        "status": {
+            // The checksum of the credentials Secret's empty data.
+            "secretHash": crate::consumers::rollout::credentials_hash(None),
+            "phase": "Ready",
+            "message": "Ready",
+            "lastUpdated": now,
+            "lastVerified": now,
+            "availableSlots": 2,
+            "slotsSummary": "2/2",
+            "pendingDemand": 0,
+        },
+    });
+    merged(provider, patch)
+}
+
+/// Returns the Active MaskConsumer of the Mask `mask-0` in the `default`
+/// namespace, assigned slot 0 of the MaskProvider of [`provider_value`], with `patch`
+/// merged into it.
+pub fn consumer_value(patch: Value) -> Value {
+    let consumer = serde_json::json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskConsumer",
+        "metadata": {
+            "name": "mask-0",
+            "namespace": "default",
+            "uid": "consumer-uid",
+            "finalizers": [crate::util::finalizer::FINALIZER_NAME],
+            "ownerReferences": [{
+                "apiVersion": "vpn.beebs.dev/v1",
+                "kind": "Mask",
+                "name": "mask-0",
+                "uid": "mask-uid",
+                "controller": true,
+            }],
+        },
+        "spec": {},
+        "status": {
+            "phase": "Active",
+            "message": crate::util::messages::ACTIVE,
+            "lastUpdated": chrono::Utc::now().to_rfc3339(),
+            "provider": {
+                "name": "provider",
+                "namespace": "providers",
+                "uid": "provider-uid",
+                "slot": 0,
+                "reservation": "reservation-uid",
+                "secret": "mask-0-provider-uid",
+            },
+        },
+    });
+    merged(consumer, patch)
+}
+
+/// Returns the MaskProvider of [`provider_value`] with its verification
+/// current, with `patch` merged into it afterwards, so a patched
+/// verification config no longer matches the one last verified.
+pub fn verified_provider_value(patch: Value) -> Value {
+    merged(
+        with_verified_config(provider_value(serde_json::json!({}))),
+        patch,
+    )
+}
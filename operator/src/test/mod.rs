@@ -1,5 +1,102 @@
+pub(crate) mod mock;
 pub(crate) mod util;
 
+mod anti_affinity;
+mod assignment_withdrawn;
+mod auto_mask_jobs;
+mod availability;
 mod basic;
+mod canary;
+mod capabilities;
+mod cli_parsing;
+mod cluster_provider_decisions;
+mod cluster_providers;
+mod consumer_decisions;
+mod consumer_purpose;
+mod copy_encryption;
+mod credential_audit;
+mod credentials_hash;
+mod credentials_withdrawal;
+mod dashboards;
+mod default_providers;
+mod default_verify;
+mod deletion_interlock;
+mod disaster_recovery;
 mod err_no_providers;
+mod explain;
+mod explain_preview;
+mod external_consumers;
+mod file_projection;
+mod finalizers;
+mod fleet_overview;
+mod freeze;
+mod gluetun_version;
+mod kube_errors;
+mod last_error;
+mod mask_decisions;
+mod mask_defaults;
+mod mask_deletion;
+mod mask_quota;
+mod mask_service;
+mod mask_slots;
+mod mask_succession;
+mod mask_ttl;
+mod mask_versions;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod namespace_allowlist;
+mod namespace_opt_in;
+mod orphaned_consumer;
+mod pagination;
+mod partial_status;
+mod phase_events;
+mod policy_violation;
+mod provider_capacity;
+mod provider_decisions;
+mod provider_deletion_race;
+mod provider_lost;
+mod provider_operation;
+mod provider_recreated;
+mod provider_selector;
+mod provider_withdrawn;
+mod rbac;
+mod render_snapshots;
+mod required_labels;
+mod reservation_decisions;
+mod reservation_names;
+mod reservation_takeover;
+mod same_namespace;
+mod secret_conflict;
+mod secret_format;
+mod secret_hash_sync;
+mod secret_labels;
+mod secret_name_template;
+mod secret_transforms;
+mod shared_account;
+mod sidecar_injection;
+mod simultaneous_deletion;
+mod slot_affinity;
+mod stable_secret_suffix;
+mod stale_status;
+mod status_age;
+mod status_command;
+mod status_export;
+mod status_freshness;
+mod time_to_active;
+mod timestamps;
+mod topology_aware;
+mod user_agent;
+mod verification_queue;
+mod verification_slots;
+mod verify_all;
+mod verify_config_hash;
+mod verify_failure_eviction;
+mod verify_keys;
+mod verify_now;
+mod verify_placement;
+mod verify_pod_disruption;
+mod verify_priority_class;
+mod verify_schedule;
+mod verify_watchdog;
+mod verify_watches;
 mod waiting;
@@ -0,0 +1,131 @@
+use k8s_openapi::api::core::v1::Namespace;
+use kube::{api::ObjectMeta, Api, Client};
+use vpn_types::*;
+
+use super::{mock::*, util::*};
+use crate::providers::namespaces::{get_unknown_namespaces, namespace_warnings, warnings_changed};
+
+/// Returns a MaskProvider allowing the given namespaces.
+fn test_provider(namespaces: &[&str], warnings: Option<Vec<String>>) -> MaskProvider {
+    MaskProvider {
+        metadata: ObjectMeta {
+            name: Some("test-provider".to_owned()),
+            namespace: Some("default".to_owned()),
+            ..Default::default()
+        },
+        spec: MaskProviderSpec {
+            namespaces: Some(namespaces.iter().map(|n| n.to_string()).collect()),
+            ..Default::default()
+        },
+        status: Some(MaskProviderStatus {
+            warnings,
+            ..Default::default()
+        }),
+    }
+}
+
+#[tokio::test]
+async fn unknown_namespaces_are_reported() {
+    // Every lookup is answered with a 404, so each namespace is unknown.
+    let provider = test_provider(&["prod-scrapers", "staging", "prod-scrapers"], None);
+    let (service, captured) = mock_service(404, status_failure(404));
+    let client = Client::new(service, "default");
+    let unknown = get_unknown_namespaces(client, &provider).await.unwrap();
+    assert_eq!(unknown, vec!["prod-scrapers", "staging"]);
+    // Duplicate entries are only looked up once.
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 2);
+    assert!(captured[0]
+        .path
+        .starts_with("/api/v1/namespaces/prod-scrapers"));
+    assert_eq!(
+        namespace_warnings(&unknown),
+        vec!["spec.namespaces references namespaces that don't exist: prod-scrapers, staging"]
+    );
+}
+
+#[tokio::test]
+async fn existing_namespaces_are_not_reported() {
+    let provider = test_provider(&["prod-scraper"], None);
+    let (client, _) = mock_client(serde_json::json!({
+        "apiVersion": "v1",
+        "kind": "Namespace",
+        "metadata": { "name": "prod-scraper" },
+    }));
+    let unknown = get_unknown_namespaces(client, &provider).await.unwrap();
+    assert!(unknown.is_empty());
+    assert!(namespace_warnings(&unknown).is_empty());
+}
+
+#[tokio::test]
+async fn unset_allowlist_is_not_validated() {
+    let mut provider = test_provider(&[], None);
+    provider.spec.namespaces = None;
+    let (client, captured) = mock_client(status_failure(404));
+    assert!(get_unknown_namespaces(client, &provider)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(captured.lock().unwrap().is_empty());
+}
+
+#[test]
+fn warnings_are_compared_with_status() {
+    let warnings = namespace_warnings(&["prod-scrapers".to_owned()]);
+    assert!(warnings_changed(&test_provider(&[], None), &warnings));
+    assert!(!warnings_changed(
+        &test_provider(&[], Some(warnings.clone())),
+        &warnings
+    ));
+    // Clearing the warnings is also a change.
+    assert!(warnings_changed(&test_provider(&[], Some(warnings)), &[]));
+    assert!(!warnings_changed(&test_provider(&[], None), &[]));
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn unknown_namespace_warning() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+
+    // Allow the test namespace and a namespace that doesn't exist yet.
+    let missing = format!("{}-missing", namespace);
+    let name = test_provider_name(&uid);
+    let mut provider = get_test_provider(client.clone(), &name, &namespace).await?;
+    provider.spec.namespaces = Some(vec![namespace.clone(), missing.clone()]);
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let provider = api.create(&Default::default(), &provider).await?;
+    create_test_provider_secret(client.clone(), &namespace, &provider).await?;
+
+    // The provider stays usable, but its status warns about the typo.
+    let expected = vec![format!(
+        "spec.namespaces references namespaces that don't exist: {}",
+        missing
+    )];
+    let provider = wait_for_provider_warnings(client.clone(), &namespace, &expected).await?;
+    let status = provider.status.unwrap();
+    assert_eq!(status.phase, Some(MaskProviderPhase::Ready));
+    assert!(status.message.unwrap().contains(&expected[0]));
+
+    // Creating the namespace clears the warning with the next refresh.
+    let missing_api: Api<Namespace> = Api::all(client.clone());
+    missing_api
+        .create(
+            &Default::default(),
+            &Namespace {
+                metadata: ObjectMeta {
+                    name: Some(missing.clone()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .await?;
+    wait_for_provider_warnings(client.clone(), &namespace, &[]).await?;
+
+    // Garbage collect the test resources.
+    delete_namespace(client.clone(), &missing).await?;
+    cleanup(client, &namespace).await?;
+
+    Ok(())
+}
@@ -0,0 +1,131 @@
+use std::{sync::Arc, time::Duration};
+
+use super::mock::*;
+use crate::consumers::{
+    default_providers::NamespaceDefaults,
+    namespaces::NamespaceCache,
+    optin::NamespaceOptIn,
+    topology::{self, NamespaceTopology},
+    OptInLabel,
+};
+
+/// Returns a Namespace resource with the given labels.
+fn namespace(labels: serde_json::Value) -> serde_json::Value {
+    named_namespace("team-a", labels)
+}
+
+/// Returns a Namespace resource with the given name and labels.
+fn named_namespace(name: &str, labels: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({
+        "apiVersion": "v1",
+        "kind": "Namespace",
+        "metadata": {
+            "name": name,
+            "labels": labels,
+            "annotations": {
+                "vpn.beebs.dev/default-providers": "team-a-vpn",
+            },
+        },
+    })
+}
+
+fn opt_in_label() -> OptInLabel {
+    "vpn.beebs.dev/opt-in=true".parse().unwrap()
+}
+
+fn opt_in(ttl: Duration) -> NamespaceOptIn {
+    NamespaceOptIn::new(opt_in_label(), Arc::new(NamespaceCache::new(ttl)))
+}
+
+#[test]
+fn opt_in_label_is_parsed() {
+    assert_eq!(
+        "vpn.beebs.dev/opt-in=true".parse::<OptInLabel>(),
+        Ok(OptInLabel {
+            key: "vpn.beebs.dev/opt-in".to_owned(),
+            value: "true".to_owned(),
+        })
+    );
+    assert!("vpn.beebs.dev/opt-in".parse::<OptInLabel>().is_err());
+    assert!("=true".parse::<OptInLabel>().is_err());
+}
+
+#[tokio::test]
+async fn opted_in_namespace() {
+    let opt_in = opt_in(Duration::from_secs(60));
+    let (client, captured) = mock_client(namespace(serde_json::json!({
+        "vpn.beebs.dev/opt-in": "true",
+    })));
+    assert!(opt_in.is_opted_in(client, "team-a").await.unwrap());
+    assert!(captured.lock().unwrap()[0]
+        .path
+        .starts_with("/api/v1/namespaces/team-a"));
+}
+
+#[tokio::test]
+async fn namespace_not_opted_in() {
+    let opt_in = opt_in(Duration::from_secs(60));
+    // A different value for the label doesn't count.
+    let (client, _) = mock_client(namespace(serde_json::json!({
+        "vpn.beebs.dev/opt-in": "false",
+    })));
+    assert!(!opt_in.is_opted_in(client, "team-a").await.unwrap());
+
+    // The result is cached, so the namespace isn't fetched again.
+    let (client, captured) = mock_client(namespace(serde_json::json!({
+        "vpn.beebs.dev/opt-in": "true",
+    })));
+    assert!(!opt_in.is_opted_in(client, "team-a").await.unwrap());
+    assert!(captured.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn label_added_later() {
+    let opt_in = opt_in(Duration::ZERO);
+    let (client, _) = mock_client(namespace(serde_json::json!({})));
+    assert!(!opt_in.is_opted_in(client, "team-a").await.unwrap());
+
+    // Once the cached labels expire, labeling the namespace unblocks it.
+    let (client, captured) = mock_client(namespace(serde_json::json!({
+        "vpn.beebs.dev/opt-in": "true",
+    })));
+    assert!(opt_in.is_opted_in(client, "team-a").await.unwrap());
+    assert_eq!(captured.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn lookups_share_one_fetch() {
+    let namespaces = Arc::new(NamespaceCache::new(Duration::from_secs(60)));
+    let opt_in = NamespaceOptIn::new(opt_in_label(), namespaces.clone());
+    let defaults = NamespaceDefaults::new(namespaces.clone());
+    let topology = NamespaceTopology::new(topology::Settings::default(), namespaces.clone());
+    let (client, captured) = mock_client(namespace(serde_json::json!({
+        "vpn.beebs.dev/opt-in": "true",
+        "topology.vpn.beebs.dev/region": "us-east",
+    })));
+    assert!(opt_in.is_opted_in(client.clone(), "team-a").await.unwrap());
+    assert_eq!(
+        defaults.providers(client.clone(), "team-a").await.unwrap(),
+        Some(vec!["team-a-vpn".to_owned()])
+    );
+    assert_eq!(
+        topology.region(client, "team-a").await.unwrap().as_deref(),
+        Some("us-east")
+    );
+    // The opt-in label, the default providers and the region all come
+    // from the same copy of the namespace.
+    assert_eq!(captured.lock().unwrap().len(), 1);
+    assert_eq!(namespaces.cached(), 1);
+}
+
+#[tokio::test]
+async fn expired_namespaces_are_evicted() {
+    let namespaces = Arc::new(NamespaceCache::new(Duration::ZERO));
+    for name in ["team-a", "team-b", "team-c"] {
+        let (client, _) = mock_client(named_namespace(name, serde_json::json!({})));
+        namespaces.get(client, name).await.unwrap();
+        // Fetching one namespace drops the ones that expired, so
+        // namespaces that are never looked up again don't stay cached.
+        assert_eq!(namespaces.cached(), 1);
+    }
+}
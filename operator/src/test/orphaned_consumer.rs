@@ -0,0 +1,167 @@
+use kube::{api::ObjectMeta, client::Client};
+use serde_json::{json, Value};
+use vpn_types::*;
+
+use super::mock::*;
+use crate::{masks::actions::create_consumer, util::messages};
+
+/// Path of the Mask's MaskConsumer.
+const CONSUMER_PATH: &str = "/apis/vpn.beebs.dev/v1/namespaces/team/maskconsumers/test-mask";
+
+/// Returns the Mask whose MaskConsumer is created.
+fn mask() -> Mask {
+    Mask {
+        metadata: ObjectMeta {
+            name: Some("test-mask".to_owned()),
+            namespace: Some("team".to_owned()),
+            uid: Some("mask-uid".to_owned()),
+            labels: Some([("team".to_owned(), "scrapers".to_owned())].into()),
+            ..Default::default()
+        },
+        spec: Default::default(),
+        status: Some(MaskStatus {
+            phase: Some(MaskPhase::Pending),
+            ..Default::default()
+        }),
+    }
+}
+
+/// Returns the MaskConsumer with the Mask's name that already exists,
+/// with `patch` merged into it.
+fn existing(patch: Value) -> Value {
+    let consumer = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskConsumer",
+        "metadata": {
+            "name": "test-mask",
+            "namespace": "team",
+            "uid": "consumer-uid",
+            "labels": { "app": "scraper" },
+        },
+        "spec": {},
+    });
+    merged(consumer, patch)
+}
+
+/// Returns a client where creating the MaskConsumer conflicts with the
+/// existing one, which is answered to reads and patches.
+fn mock_conflict(existing: Value) -> (Client, Captured) {
+    mock_method_routes(vec![
+        (
+            "POST",
+            "/apis/vpn.beebs.dev/v1/namespaces/team/maskconsumers",
+            409,
+            status_failure(409),
+        ),
+        ("GET", CONSUMER_PATH, 200, existing.clone()),
+        ("PATCH", CONSUMER_PATH, 200, existing.clone()),
+        ("DELETE", CONSUMER_PATH, 200, existing),
+        (
+            "PATCH",
+            "/apis/vpn.beebs.dev/v1/namespaces/team/masks/test-mask/status",
+            200,
+            serde_json::to_value(mask()).unwrap(),
+        ),
+    ])
+}
+
+/// Creates the Mask's MaskConsumer with the existing one in the way.
+/// Returns whether it's owned by the Mask now and the requests made.
+async fn create(existing: Value) -> (bool, Vec<CapturedRequest>) {
+    let (client, captured) = mock_conflict(existing);
+    let owned = create_consumer(client, "team", &mask(), 0).await.unwrap();
+    let captured = captured.lock().unwrap();
+    (owned, captured.clone())
+}
+
+/// Returns the captured requests with the method.
+fn requests<'a>(captured: &'a [CapturedRequest], method: &str) -> Vec<&'a CapturedRequest> {
+    captured.iter().filter(|r| r.method == method).collect()
+}
+
+#[tokio::test]
+async fn orphaned_consumer_adopted() {
+    // A previous attempt created the MaskConsumer without an owner
+    // reference, e.g. because a mutating webhook dropped it.
+    let (owned, captured) = create(existing(json!({}))).await;
+    assert!(owned, "orphaned MaskConsumer should be adopted");
+    assert!(requests(&captured, "DELETE").is_empty());
+    let patches = requests(&captured, "PATCH");
+    assert_eq!(patches.len(), 1);
+    let adopt = patches[0];
+    assert!(adopt.path.starts_with(CONSUMER_PATH));
+    // Only the MaskConsumer that was read is adopted.
+    assert!(adopt.body.as_array().unwrap().contains(&json!({
+        "op": "test",
+        "path": "/metadata/uid",
+        "value": "consumer-uid",
+    })));
+    let owners = patch_op(adopt, "/metadata/ownerReferences").unwrap();
+    assert_eq!(owners.as_array().unwrap().len(), 1);
+    assert_eq!(owners[0]["kind"], "Mask");
+    assert_eq!(owners[0]["uid"], "mask-uid");
+    assert_eq!(owners[0]["controller"], true);
+    // The Mask's labels are added, and the others are left alone.
+    let labels = patch_op(adopt, "/metadata/labels").unwrap();
+    assert_eq!(labels["team"], "scrapers");
+    assert_eq!(labels["app"], "scraper");
+}
+
+#[tokio::test]
+async fn owned_consumer_not_adopted() {
+    // A MaskConsumer that already belongs to the Mask is left alone.
+    let owner = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "Mask",
+        "name": "test-mask",
+        "uid": "mask-uid",
+        "controller": true,
+    });
+    let (owned, captured) = create(existing(
+        json!({ "metadata": { "ownerReferences": [owner.clone()] } }),
+    ))
+    .await;
+    assert!(owned);
+    assert!(requests(&captured, "PATCH").is_empty());
+
+    // One left over from a previous Mask with the same name is deleted.
+    let mut stale = owner;
+    stale["uid"] = "old-mask-uid".into();
+    let (owned, captured) = create(existing(
+        json!({ "metadata": { "ownerReferences": [stale] } }),
+    ))
+    .await;
+    assert!(!owned);
+    assert_eq!(requests(&captured, "DELETE").len(), 1);
+    assert!(requests(&captured, "PATCH")
+        .iter()
+        .all(|r| !r.path.starts_with(CONSUMER_PATH)));
+
+    // One of another Mask's slots is left alone, even if its name is
+    // the one the Mask's own MaskConsumer would have.
+    let other = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "Mask",
+        "name": "test",
+        "uid": "other-mask-uid",
+        "controller": true,
+    });
+    let (owned, captured) = create(existing(
+        json!({ "metadata": { "ownerReferences": [other.clone()] } }),
+    ))
+    .await;
+    assert!(!owned);
+    assert!(requests(&captured, "DELETE").is_empty());
+    let waiting = requests(&captured, "PATCH");
+    assert_eq!(
+        patch_op(waiting[0], "/status/message"),
+        Some(&json!(messages::consumer_name_taken("test-mask", "test")))
+    );
+
+    // External MaskConsumers never have an owner, and aren't adopted.
+    let (owned, captured) = create(existing(json!({ "spec": { "external": true } }))).await;
+    assert!(!owned);
+    assert!(requests(&captured, "PATCH")
+        .iter()
+        .all(|r| !r.path.starts_with(CONSUMER_PATH)));
+}
@@ -0,0 +1,119 @@
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::{
+    api::{ListParams, ObjectMeta},
+    Api,
+};
+use serde_json::{json, Value};
+use vpn_types::*;
+
+use super::mock::*;
+use crate::util::list::{list_all_paginated, list_provider_reservations, PAGE_SIZE};
+
+/// Returns a page of MaskReservations, followed by the given continue token.
+fn page(reservations: Vec<MaskReservation>, token: Option<&str>) -> Value {
+    json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskReservationList",
+        "metadata": { "continue": token },
+        "items": reservations,
+    })
+}
+
+/// Returns a MaskReservation owned by the MaskProvider with the given uid.
+fn reservation(name: &str, owner_uid: &str) -> MaskReservation {
+    MaskReservation {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some("default".to_owned()),
+            owner_references: Some(vec![OwnerReference {
+                kind: "MaskProvider".to_owned(),
+                name: "test-provider".to_owned(),
+                uid: owner_uid.to_owned(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Returns the names of the MaskReservations.
+fn names(reservations: &[MaskReservation]) -> Vec<&str> {
+    reservations
+        .iter()
+        .map(|r| r.metadata.name.as_deref().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn continue_tokens_followed() {
+    let (client, captured) = mock_sequence(vec![
+        page(vec![reservation("a", "uid")], Some("page-2")),
+        page(vec![reservation("b", "uid")], Some("page-3")),
+        page(vec![reservation("c", "uid")], None),
+    ]);
+    let api: Api<MaskReservation> = Api::namespaced(client, "default");
+    let items = list_all_paginated(&api, &Default::default()).await.unwrap();
+    assert_eq!(names(&items), vec!["a", "b", "c"]);
+
+    // Every page is limited, and each one continues where the last ended.
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 3);
+    let limit = format!("limit={}", PAGE_SIZE);
+    assert!(captured.iter().all(|r| r.path.contains(&limit)));
+    assert!(!captured[0].path.contains("continue="));
+    assert!(captured[1].path.contains("continue=page-2"));
+    assert!(captured[2].path.contains("continue=page-3"));
+}
+
+#[tokio::test]
+async fn explicit_limit_kept() {
+    let (client, captured) = mock_sequence(vec![page(vec![reservation("a", "uid")], Some(""))]);
+    let api: Api<MaskReservation> = Api::namespaced(client, "default");
+    let items = list_all_paginated(&api, &ListParams::default().limit(10))
+        .await
+        .unwrap();
+    assert_eq!(names(&items), vec!["a"]);
+
+    // An empty continue token ends the list as well.
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 1);
+    assert!(captured[0].path.contains("limit=10"));
+}
+
+#[tokio::test]
+async fn provider_reservations_filtered_by_label() {
+    let (client, captured) = mock_sequence(vec![
+        // Reservations labeled with the MaskProvider's uid.
+        page(vec![reservation("labeled", "provider-uid")], None),
+        // Older reservations without the label, of any MaskProvider.
+        page(
+            vec![
+                reservation("unlabeled", "provider-uid"),
+                reservation("other", "other-uid"),
+            ],
+            None,
+        ),
+    ]);
+    let provider = MaskProvider {
+        metadata: ObjectMeta {
+            name: Some("test-provider".to_owned()),
+            namespace: Some("default".to_owned()),
+            uid: Some("provider-uid".to_owned()),
+            ..Default::default()
+        },
+        spec: Default::default(),
+        status: None,
+    };
+    let reservations = list_provider_reservations(client, &provider).await.unwrap();
+    assert_eq!(names(&reservations), vec!["labeled", "unlabeled"]);
+
+    // The API server is asked to filter by the label.
+    let captured = captured.lock().unwrap();
+    assert!(captured[0]
+        .path
+        .contains("labelSelector=vpn.beebs.dev%2Fowner%3Dprovider-uid"));
+    assert!(captured[1]
+        .path
+        .contains("labelSelector=%21vpn.beebs.dev%2Fowner"));
+}
@@ -0,0 +1,219 @@
+use kube::{api::ObjectMeta, Resource};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use vpn_types::*;
+
+use super::{
+    mock::{mock_client, patch_op},
+    util::mask_consumer,
+};
+use crate::{
+    consumers::reconcile::{apply_action, ConsumerAction, ContextData},
+    util::{
+        config::OperatorConfig,
+        patch::{
+            ensure_status_initialized, incomplete_status, record_action_error, status_phase,
+            Object, Status,
+        },
+        Error,
+    },
+};
+
+/// Timestamp of the tests' fully initialized statuses.
+const LAST_UPDATED: &str = "2023-03-01T00:00:00Z";
+
+/// Returns the metadata of the tests' resources.
+fn metadata() -> ObjectMeta {
+    ObjectMeta {
+        name: Some("test".to_owned()),
+        namespace: Some("default".to_owned()),
+        ..Default::default()
+    }
+}
+
+/// Asserts that the resource is only considered initialized with all of
+/// the phase, message and lastUpdated fields. `partial` returns the
+/// resource with its status missing the named field, or without a status
+/// at all if passed None. The status is complete if no field is named.
+fn assert_requires_bookkeeping<S, T>(
+    partial: impl Fn(Option<&str>) -> T,
+    phase: S::Phase,
+    qualified_name: &str,
+) where
+    S: Status,
+    S::Phase: PartialEq + std::fmt::Debug,
+    T: Resource + Object<S>,
+{
+    let complete = partial(Some(""));
+    let status = ensure_status_initialized(&complete).unwrap();
+    assert_eq!(status.phase(), Some(phase));
+    let (current, age) = status_phase(&complete).unwrap();
+    assert_eq!(current, phase);
+    assert!(age > Duration::ZERO);
+
+    // A status partially written by an older version of the operator is
+    // refused, so the controller initializes it again instead of failing.
+    for field in [Some("phase"), Some("message"), Some("lastUpdated"), None] {
+        let instance = partial(field);
+        assert!(
+            matches!(
+                ensure_status_initialized(&instance),
+                Err(Error::IncompleteStatus(ref name, _)) if name == qualified_name
+            ),
+            "{:?} missing",
+            field
+        );
+        assert!(matches!(
+            status_phase(&instance),
+            Err(Error::IncompleteStatus(..))
+        ));
+    }
+}
+
+/// Returns the value of a bookkeeping field unless it's the missing one.
+fn unless<T>(missing: Option<&str>, field: &str, value: T) -> Option<T> {
+    (missing != Some(field)).then_some(value)
+}
+
+#[test]
+fn mask_partial_status() {
+    assert_requires_bookkeeping(
+        |missing| Mask {
+            metadata: metadata(),
+            status: missing.map(|missing| MaskStatus {
+                phase: unless(Some(missing), "phase", MaskPhase::Active),
+                message: unless(Some(missing), "message", "Active".to_owned()),
+                last_updated: unless(Some(missing), "lastUpdated", LAST_UPDATED.to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        MaskPhase::Active,
+        "default/test",
+    );
+}
+
+#[test]
+fn provider_partial_status() {
+    assert_requires_bookkeeping(
+        |missing| MaskProvider {
+            metadata: metadata(),
+            status: missing.map(|missing| MaskProviderStatus {
+                phase: unless(Some(missing), "phase", MaskProviderPhase::Ready),
+                message: unless(Some(missing), "message", "Ready".to_owned()),
+                last_updated: unless(Some(missing), "lastUpdated", LAST_UPDATED.to_owned()),
+                // Verification bookkeeping is optional either way.
+                last_verified: None,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        MaskProviderPhase::Ready,
+        "default/test",
+    );
+}
+
+#[test]
+fn consumer_partial_status() {
+    assert_requires_bookkeeping(
+        |missing| MaskConsumer {
+            metadata: metadata(),
+            status: missing.map(|missing| MaskConsumerStatus {
+                phase: unless(Some(missing), "phase", MaskConsumerPhase::Waiting),
+                message: unless(Some(missing), "message", "Waiting".to_owned()),
+                last_updated: unless(Some(missing), "lastUpdated", LAST_UPDATED.to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        MaskConsumerPhase::Waiting,
+        "default/test",
+    );
+}
+
+#[test]
+fn reservation_partial_status() {
+    assert_requires_bookkeeping(
+        |missing| MaskReservation {
+            metadata: metadata(),
+            spec: Default::default(),
+            status: missing.map(|missing| MaskReservationStatus {
+                phase: unless(Some(missing), "phase", MaskReservationPhase::Active),
+                message: unless(Some(missing), "message", "Active".to_owned()),
+                last_updated: unless(Some(missing), "lastUpdated", LAST_UPDATED.to_owned()),
+                ..Default::default()
+            }),
+        },
+        MaskReservationPhase::Active,
+        "default/test",
+    );
+}
+
+#[test]
+fn cluster_provider_partial_status() {
+    assert_requires_bookkeeping(
+        |missing| ClusterMaskProvider {
+            // ClusterMaskProviders are cluster-scoped.
+            metadata: ObjectMeta {
+                namespace: None,
+                ..metadata()
+            },
+            spec: Default::default(),
+            status: missing.map(|missing| ClusterMaskProviderStatus {
+                phase: unless(Some(missing), "phase", ClusterMaskProviderPhase::Ready),
+                message: unless(Some(missing), "message", "Ready".to_owned()),
+                last_updated: unless(Some(missing), "lastUpdated", LAST_UPDATED.to_owned()),
+                ..Default::default()
+            }),
+        },
+        ClusterMaskProviderPhase::Ready,
+        "test",
+    );
+}
+
+#[tokio::test]
+async fn unassigned_consumer_actions_refused() {
+    // The status was reset since the action was decided, so there's
+    // no MaskProvider to copy the credentials of.
+    let mut instance = mask_consumer("test", "default", "consumer-uid");
+    instance.status = Some(MaskConsumerStatus {
+        phase: Some(MaskConsumerPhase::Waiting),
+        ..Default::default()
+    });
+    let (client, captured) = mock_client(serde_json::to_value(&instance).unwrap());
+    let context =
+        ContextData::for_tests(client.clone(), None, Arc::new(OperatorConfig::new(false)));
+    let actions = [
+        ConsumerAction::CreateSecret,
+        ConsumerAction::SyncSecret(Box::default()),
+        ConsumerAction::LabelSecret(BTreeMap::new()),
+        ConsumerAction::AnnotateSecret(BTreeMap::new()),
+    ];
+    for action in actions {
+        let name = format!("{:?}", action);
+        let result = apply_action(
+            client.clone(),
+            "test",
+            "default",
+            &instance,
+            action,
+            &context,
+        )
+        .await;
+        assert!(
+            matches!(result, Err(Error::IncompleteStatus(ref n, _)) if n == "default/test"),
+            "{}: {:?}",
+            name,
+            result
+        );
+    }
+    assert!(captured.lock().unwrap().is_empty());
+
+    // Unlike a stale status, the failure is recorded in the status.
+    let error = Error::action("CreateSecret", incomplete_status(&instance, "a field"));
+    record_action_error(client, Arc::new(instance), "consumers", &error);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 1);
+    let last_error = patch_op(&captured[0], "/status/lastError").unwrap();
+    assert_eq!(last_error["action"], "CreateSecret");
+}
@@ -0,0 +1,121 @@
+use kube::api::ObjectMeta;
+use vpn_types::*;
+
+use super::mock::*;
+use crate::util::{events, messages, patch::patch_status};
+
+/// Returns a Mask in the given phase with the given message.
+fn test_mask(phase: MaskPhase, message: &str) -> Mask {
+    Mask {
+        metadata: ObjectMeta {
+            name: Some("test-mask".to_owned()),
+            namespace: Some("default".to_owned()),
+            uid: Some("mask-uid".to_owned()),
+            ..Default::default()
+        },
+        status: Some(MaskStatus {
+            phase: Some(phase),
+            message: Some(message.to_owned()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Patches the Mask's status into the phase with the message as though
+/// `action` was being applied, and returns the events published.
+async fn apply(mask: &Mask, action: &str, phase: MaskPhase, message: &str) -> Vec<CapturedRequest> {
+    let (client, captured) = mock_client(serde_json::to_value(mask).unwrap());
+    let message = message.to_owned();
+    events::scope(events::reporter(), action, async {
+        patch_status(client, mask, move |status| {
+            status.phase = Some(phase);
+            status.message = Some(message);
+        })
+        .await
+    })
+    .await
+    .unwrap();
+    let captured = captured.lock().unwrap();
+    captured
+        .iter()
+        .filter(|r| r.method == "POST" && r.path.contains("/events"))
+        .cloned()
+        .collect()
+}
+
+#[tokio::test]
+async fn phase_change_publishes_event() {
+    let mask = test_mask(MaskPhase::Pending, "Pending");
+    let events = apply(&mask, "Waiting", MaskPhase::Waiting, messages::WAITING).await;
+    assert_eq!(events.len(), 1);
+    assert!(events[0]
+        .path
+        .starts_with("/apis/events.k8s.io/v1/namespaces/default/events"));
+    let event = &events[0].body;
+    assert_eq!(event["type"], "Normal");
+    assert_eq!(event["reason"], "Waiting");
+    assert_eq!(event["action"], "Waiting");
+    assert_eq!(event["note"], messages::WAITING);
+    assert_eq!(event["regarding"]["kind"], "Mask");
+    assert_eq!(event["regarding"]["name"], "test-mask");
+    assert_eq!(event["regarding"]["uid"], "mask-uid");
+}
+
+#[tokio::test]
+async fn error_phases_publish_warnings() {
+    let mask = test_mask(MaskPhase::Active, messages::ACTIVE);
+    let message = messages::err_quota_exceeded("quota", 1);
+    let events = apply(
+        &mask,
+        "ErrQuotaExceeded",
+        MaskPhase::ErrQuotaExceeded,
+        &message,
+    )
+    .await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].body["type"], "Warning");
+    assert_eq!(events[0].body["reason"], "ErrQuotaExceeded");
+    assert_eq!(events[0].body["note"], message.as_str());
+
+    // Staying in the error phase for the same reason isn't published
+    // again, while failing for another reason is.
+    let mask = test_mask(MaskPhase::ErrQuotaExceeded, &message);
+    let events = apply(
+        &mask,
+        "ErrQuotaExceeded",
+        MaskPhase::ErrQuotaExceeded,
+        &message,
+    )
+    .await;
+    assert!(events.is_empty());
+    let events = apply(
+        &mask,
+        "ErrQuotaExceeded",
+        MaskPhase::ErrQuotaExceeded,
+        &messages::err_tag_quota_exceeded("quota", "fast", 1),
+    )
+    .await;
+    assert_eq!(events.len(), 1);
+}
+
+#[tokio::test]
+async fn unchanged_phase_publishes_nothing() {
+    // Rewriting the status in the same phase, e.g. to refresh it, is
+    // not a transition.
+    let mask = test_mask(MaskPhase::Active, messages::ACTIVE);
+    let events = apply(&mask, "Active", MaskPhase::Active, "Still active.").await;
+    assert!(events.is_empty());
+
+    // Only patches made while applying an action are published.
+    let mask = test_mask(MaskPhase::Pending, "Pending");
+    let (client, captured) = mock_client(serde_json::to_value(&mask).unwrap());
+    patch_status(client, &mask, |status| {
+        status.phase = Some(MaskPhase::Waiting);
+    })
+    .await
+    .unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 1);
+    assert!(captured[0].path.contains("/status"));
+}
@@ -0,0 +1,212 @@
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use vpn_types::*;
+
+use super::{mock::*, util::*};
+use crate::{
+    consumers::{
+        actions,
+        policy::{check, PolicyCheck, ProviderPolicies, ProviderPolicy},
+    },
+    util::{messages, VERIFICATION_LABEL},
+};
+
+/// Returns the MaskProvider assigned to the test MaskConsumer.
+fn assignment() -> AssignedProvider {
+    assigned_provider("my-provider", "vpn", "provider-uid", "my-mask-provider-uid")
+}
+
+/// Returns an Active MaskConsumer in the `default` namespace showing
+/// the policy violation, if any.
+fn violating_consumer(policy_violation: Option<String>) -> MaskConsumer {
+    let mut consumer = assigned(
+        mask_consumer("my-mask", "default", "consumer-uid"),
+        Some(MaskConsumerPhase::Active),
+        assignment(),
+    );
+    consumer.status.as_mut().unwrap().policy_violation = policy_violation;
+    consumer
+}
+
+/// Returns the policy of a MaskProvider that only permits the namespaces.
+fn policy(namespaces: &[&str], on_violation: PolicyViolationAction) -> ProviderPolicy {
+    ProviderPolicy {
+        namespaces: Some(namespaces.iter().map(|n| n.to_string()).collect()),
+        on_violation,
+        secret_hash: None,
+    }
+}
+
+/// Returns the MaskProvider with the allowlist.
+fn allowlisting_provider(uid: &str, namespaces: Option<Vec<String>>) -> MaskProvider {
+    let mut provider = mask_provider("my-provider", "vpn", uid);
+    provider.spec.namespaces = namespaces;
+    provider
+}
+
+#[test]
+fn allowlist_permits() {
+    let permitted = policy(&["default"], PolicyViolationAction::Warn);
+    assert!(permitted.permits("default"));
+    assert!(!permitted.permits("other"));
+    // MaskProviders without an allowlist permit every namespace.
+    let open = ProviderPolicy::of(&allowlisting_provider("provider-uid", None));
+    assert!(open.permits("other"));
+    // Warn is the default.
+    assert_eq!(open.on_violation, PolicyViolationAction::Warn);
+}
+
+#[test]
+fn ignore_mode() {
+    let ignored = policy(&["other"], PolicyViolationAction::Ignore);
+    assert_eq!(
+        check(
+            &violating_consumer(None),
+            "default",
+            &assignment(),
+            Some(&ignored)
+        ),
+        PolicyCheck::Unchanged
+    );
+    // A violation shown from before violations were ignored is cleared.
+    let shown = Some("violation".to_owned());
+    assert_eq!(
+        check(
+            &violating_consumer(shown),
+            "default",
+            &assignment(),
+            Some(&ignored)
+        ),
+        PolicyCheck::Cleared
+    );
+}
+
+#[test]
+fn warn_mode() {
+    let warned = policy(&["other"], PolicyViolationAction::Warn);
+    let message = messages::policy_violation("default", "vpn", "my-provider");
+    assert_eq!(
+        check(
+            &violating_consumer(None),
+            "default",
+            &assignment(),
+            Some(&warned)
+        ),
+        PolicyCheck::Warn(message.clone())
+    );
+    // The violation is only reported once.
+    assert_eq!(
+        check(
+            &violating_consumer(Some(message.clone())),
+            "default",
+            &assignment(),
+            Some(&warned)
+        ),
+        PolicyCheck::Unchanged
+    );
+    // Permitting the namespace again clears it.
+    let permitted = policy(&["other", "default"], PolicyViolationAction::Warn);
+    assert_eq!(
+        check(
+            &violating_consumer(Some(message)),
+            "default",
+            &assignment(),
+            Some(&permitted)
+        ),
+        PolicyCheck::Cleared
+    );
+    assert_eq!(
+        check(
+            &violating_consumer(None),
+            "default",
+            &assignment(),
+            Some(&permitted)
+        ),
+        PolicyCheck::Unchanged
+    );
+}
+
+#[test]
+fn evict_mode() {
+    let evicted = policy(&["other"], PolicyViolationAction::Evict);
+    assert_eq!(
+        check(
+            &violating_consumer(None),
+            "default",
+            &assignment(),
+            Some(&evicted)
+        ),
+        PolicyCheck::Evict(messages::policy_eviction("default", "vpn", "my-provider"))
+    );
+    let permitted = policy(&["default"], PolicyViolationAction::Evict);
+    assert_eq!(
+        check(
+            &violating_consumer(None),
+            "default",
+            &assignment(),
+            Some(&permitted)
+        ),
+        PolicyCheck::Unchanged
+    );
+
+    // MaskConsumers verifying the MaskProvider are never evicted.
+    let mut verification = violating_consumer(None);
+    verification.metadata.labels = Some(BTreeMap::from([(
+        VERIFICATION_LABEL.to_owned(),
+        "provider-uid".to_owned(),
+    )]));
+    assert_eq!(
+        check(&verification, "default", &assignment(), Some(&evicted)),
+        PolicyCheck::Unchanged
+    );
+
+    // Deleted MaskProviders are handled when their reservations are.
+    assert_eq!(
+        check(&violating_consumer(None), "default", &assignment(), None),
+        PolicyCheck::Unchanged
+    );
+}
+
+#[tokio::test]
+async fn policy_cached() {
+    let allowlist = Some(vec!["default".to_owned()]);
+    let (client, captured) = mock_routes(vec![(
+        "/apis/vpn.beebs.dev/v1/namespaces/vpn/maskproviders/my-provider",
+        json!(allowlisting_provider("provider-uid", allowlist.clone())),
+    )]);
+    let policies = ProviderPolicies::new(Duration::from_secs(60));
+    for _ in 0..2 {
+        let policy = policies.get(client.clone(), &assignment()).await.unwrap();
+        assert_eq!(policy.unwrap().namespaces, allowlist);
+    }
+    // The MaskProvider is only fetched once per TTL.
+    assert_eq!(captured.lock().unwrap().len(), 1);
+
+    // A MaskProvider that replaced the assigned one has no say.
+    let (client, _) = mock_routes(vec![(
+        "/apis/vpn.beebs.dev/v1/namespaces/vpn/maskproviders/my-provider",
+        json!(allowlisting_provider("other-uid", allowlist)),
+    )]);
+    let policies = ProviderPolicies::new(Duration::from_secs(60));
+    assert_eq!(policies.get(client, &assignment()).await.unwrap(), None);
+
+    // Nor does one that was deleted.
+    let (client, _) = mock_routes(vec![]);
+    let policies = ProviderPolicies::new(Duration::from_secs(60));
+    assert_eq!(policies.get(client, &assignment()).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn violation_shown_in_status() {
+    let message = messages::policy_violation("default", "vpn", "my-provider");
+    let (client, captured) = mock_client(json!(violating_consumer(None)));
+    actions::policy_violation(client, &violating_consumer(None), Some(message.clone()))
+        .await
+        .unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(
+        patch_op(captured.last().unwrap(), "/status/policyViolation"),
+        Some(&json!(message))
+    );
+}
@@ -0,0 +1,223 @@
+use futures::{StreamExt, TryStreamExt};
+use kube::{
+    api::{ListParams, WatchEvent},
+    client::Client,
+    Api,
+};
+use serde_json::json;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::{
+    mock::{mock_client, patch_op},
+    util::*,
+};
+use crate::providers::{
+    actions::{active, ready},
+    capacity::{get_capacity, slots_summary, Capacity},
+};
+use crate::util::{CANARY_LABEL, VERIFICATION_LABEL};
+
+/// Waits for the test MaskProvider's status to show the capacity.
+async fn wait_for_capacity(
+    client: Client,
+    namespace: &str,
+    capacity: Capacity,
+) -> Result<(), Error> {
+    let provider_api: Api<MaskProvider> = Api::namespaced(client, namespace);
+    let lp = ListParams::default().timeout(120);
+    let mut stream = provider_api.watch(&lp, "0").await?.boxed();
+    while let Some(event) = stream.try_next().await? {
+        if let WatchEvent::Added(m) | WatchEvent::Modified(m) = event {
+            if capacity.is_current(&m) {
+                return Ok(());
+            }
+        }
+    }
+    Err(Error::Other(format!(
+        "MaskProvider did not report {:?} before timeout",
+        capacity
+    )))
+}
+
+#[test]
+fn summary_format() {
+    assert_eq!(slots_summary(3, 5), "3/5");
+    assert_eq!(slots_summary(0, 1), "0/1");
+    assert_eq!(slots_summary(0, 0), "0/0");
+}
+
+/// Returns a MaskReservation for the MaskConsumer with the given name,
+/// labelled with `label` if any.
+fn reservation(name: &str, label: Option<&str>) -> MaskReservation {
+    let mut mr = MaskReservation::new(
+        name,
+        MaskReservationSpec {
+            name: name.to_owned(),
+            namespace: "default".to_owned(),
+            uid: format!("{}-uid", name),
+            slot: None,
+        },
+    );
+    mr.metadata.labels = label.map(|l| [(l.to_owned(), "provider-uid".to_owned())].into());
+    mr
+}
+
+#[test]
+fn only_mask_reservations_counted() {
+    let mut provider = mask_provider("provider", "providers", "provider-uid");
+    provider.spec.max_slots = 3;
+    let mut waiting = mask_consumer("waiting", "default", "waiting-uid");
+    waiting.status = Some(MaskConsumerStatus {
+        phase: Some(MaskConsumerPhase::Waiting),
+        ..Default::default()
+    });
+    let assigned = mask_consumer("assigned", "default", "assigned-uid");
+    let consumers = [waiting, assigned].map(std::sync::Arc::new);
+
+    // Verification and canary reservations don't take a Mask's slot.
+    let reservations = [
+        reservation("mask", None),
+        reservation("verify", Some(VERIFICATION_LABEL)),
+        reservation("canary", Some(CANARY_LABEL)),
+    ];
+    assert_eq!(
+        get_capacity(&provider, &reservations, &consumers),
+        Capacity {
+            available_slots: 2,
+            pending_demand: 1,
+        }
+    );
+}
+
+#[tokio::test]
+async fn summary_written_with_phase() {
+    let provider = MaskProvider {
+        metadata: kube::api::ObjectMeta {
+            name: Some("provider".to_owned()),
+            namespace: Some("providers".to_owned()),
+            ..Default::default()
+        },
+        spec: MaskProviderSpec {
+            max_slots: 5,
+            ..Default::default()
+        },
+        status: Some(Default::default()),
+    };
+    let capacity = |available_slots| Capacity {
+        available_slots,
+        pending_demand: 0,
+    };
+
+    // The summary is patched along with the phase and the slots in use.
+    let (client, captured) = mock_client(serde_json::to_value(&provider).unwrap());
+    active(
+        client,
+        &provider,
+        2,
+        capacity(3),
+        vec![],
+        vec![],
+        None,
+        None,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    let patch = captured.lock().unwrap()[0].clone();
+    assert_eq!(patch_op(&patch, "/status/activeSlots"), Some(&json!(2)));
+    assert_eq!(
+        patch_op(&patch, "/status/slotsSummary"),
+        Some(&json!("3/5"))
+    );
+
+    let (client, captured) = mock_client(serde_json::to_value(&provider).unwrap());
+    ready(client, &provider, capacity(5), vec![], None, None, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        patch_op(&captured.lock().unwrap()[0], "/status/slotsSummary"),
+        Some(&json!("5/5"))
+    );
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn capacity_forecast() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+
+    // The MaskProvider has a single slot.
+    let provider = create_test_provider(client.clone(), &namespace, &uid).await?;
+    let provider_name = provider.metadata.name.as_deref().unwrap();
+    wait_for_capacity(
+        client.clone(),
+        &namespace,
+        Capacity {
+            available_slots: MAX_SLOTS,
+            pending_demand: 0,
+        },
+    )
+    .await?;
+
+    // Assigning the first Mask takes the slot.
+    create_test_mask(client.clone(), &namespace, 0, provider_name).await?;
+    wait_for_provider_assignment(client.clone(), &namespace, 0).await?;
+    wait_for_capacity(
+        client.clone(),
+        &namespace,
+        Capacity {
+            available_slots: 0,
+            pending_demand: 0,
+        },
+    )
+    .await?;
+
+    // The second Mask waits for the slot, which is forecast as demand.
+    create_test_mask(client.clone(), &namespace, 1, provider_name).await?;
+    wait_for_mask_phase(client.clone(), &namespace, 1, MaskPhase::Waiting).await?;
+    wait_for_capacity(
+        client.clone(),
+        &namespace,
+        Capacity {
+            available_slots: 0,
+            pending_demand: 1,
+        },
+    )
+    .await?;
+
+    // Releasing the slot lets the second Mask take it, meeting the demand.
+    let assigned = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(async move { wait_for_provider_assignment(client, &namespace, 1).await })
+    };
+    delete_test_mask(client.clone(), &namespace, 0).await?;
+    assigned.await.unwrap()?;
+    wait_for_capacity(
+        client.clone(),
+        &namespace,
+        Capacity {
+            available_slots: 0,
+            pending_demand: 0,
+        },
+    )
+    .await?;
+
+    // Releasing the last slot makes it available again.
+    delete_test_mask(client.clone(), &namespace, 1).await?;
+    wait_for_capacity(
+        client.clone(),
+        &namespace,
+        Capacity {
+            available_slots: MAX_SLOTS,
+            pending_demand: 0,
+        },
+    )
+    .await?;
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
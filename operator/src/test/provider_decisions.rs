@@ -0,0 +1,640 @@
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use vpn_render::{PROBE_CONTAINER_NAME, VPN_CONTAINER_NAME};
+use vpn_types::*;
+
+use super::mock::{decide, merged, verified_provider_value};
+use crate::{
+    providers::{
+        capacity::Capacity,
+        operation::VerifyStep,
+        reconcile::{determine_action, MaskProviderAction},
+        verify_schedule::next_verification_status,
+    },
+    util::{
+        messages, FORCE_DELETE_ANNOTATION, PROVIDER_SECRET_LABEL, PROVIDER_UID_LABEL,
+        VERIFICATION_LABEL,
+    },
+};
+
+/// Returns the MaskProvider's credentials Secret.
+fn secret() -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {
+            "name": "provider-credentials",
+            "namespace": "providers",
+            "labels": { PROVIDER_SECRET_LABEL: "true" },
+        },
+        "data": {},
+    })
+}
+
+/// Returns a MaskReservation of slot 0 of the MaskProvider, with `patch`
+/// merged into it.
+fn reservation(patch: Value) -> Value {
+    let reservation = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskReservation",
+        "metadata": {
+            "name": "provider-0",
+            "namespace": "providers",
+            "labels": { PROVIDER_UID_LABEL: "provider-uid" },
+        },
+        "spec": { "name": "mask-0", "namespace": "default", "uid": "consumer-uid", "slot": 0 },
+    });
+    merged(reservation, patch)
+}
+
+/// Returns the summary of the assignment of [`reservation`] in the
+/// MaskProvider's status.
+fn assignment() -> RecentAssignment {
+    RecentAssignment {
+        namespace: "default".to_owned(),
+        name: "mask-0".to_owned(),
+        slot: 0,
+        ..Default::default()
+    }
+}
+
+/// Returns a MaskConsumer waiting for a slot, with `patch` merged into it.
+fn waiting_consumer(patch: Value) -> Value {
+    let consumer = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskConsumer",
+        "metadata": { "name": "mask-1", "namespace": "default", "uid": "waiting-uid" },
+        "spec": {},
+        "status": { "phase": "Waiting", "message": messages::WAITING },
+    });
+    merged(consumer, patch)
+}
+
+/// Returns the verification Pod with `patch` merged into it.
+fn verify_pod(patch: Value) -> Value {
+    let pod = json!({
+        "apiVersion": "v1",
+        "kind": "Pod",
+        "metadata": {
+            "name": "provider",
+            "namespace": "providers",
+            "creationTimestamp": chrono::Utc::now().to_rfc3339(),
+        },
+        "spec": { "containers": [], "nodeName": "node-a" },
+        "status": { "phase": "Running" },
+    });
+    merged(pod, patch)
+}
+
+/// Returns the status of a container of the verification Pod.
+fn container_status(name: &str, state: Value) -> Value {
+    json!({
+        "name": name,
+        "image": "",
+        "imageID": "",
+        "ready": false,
+        "restartCount": 0,
+        "state": state,
+    })
+}
+
+/// Returns verification settings with the given interval.
+fn verify_every(interval: &str) -> Option<MaskProviderVerifySpec> {
+    Some(MaskProviderVerifySpec {
+        interval: Some(interval.to_owned()),
+        ..Default::default()
+    })
+}
+
+/// Returns the objects of the kind, as the controller's cache of them.
+fn cached<K: DeserializeOwned>(objects: &[Value], kind: &str) -> Vec<Arc<K>> {
+    objects
+        .iter()
+        .filter(|o| o["kind"] == kind)
+        .map(|o| Arc::new(serde_json::from_value(o.clone()).unwrap()))
+        .collect()
+}
+
+/// Returns the action decided for the MaskProvider with the given
+/// verification settings and objects in the cluster. The MaskConsumers
+/// and MaskProviders among the objects are the caches of them as well.
+async fn action(
+    provider: Value,
+    verify: Option<MaskProviderVerifySpec>,
+    objects: &[Value],
+) -> MaskProviderAction {
+    let instance: MaskProvider = serde_json::from_value(provider).unwrap();
+    let consumers: Vec<Arc<MaskConsumer>> = cached(objects, "MaskConsumer");
+    let providers: Vec<Arc<MaskProvider>> = cached(objects, "MaskProvider");
+    decide(objects, |client| {
+        let instance = instance.clone();
+        let verify = verify.clone();
+        let consumers = consumers.clone();
+        let providers = providers.clone();
+        async move {
+            determine_action(
+                client,
+                "provider",
+                "providers",
+                &instance,
+                verify,
+                None,
+                &Default::default(),
+                &Default::default(),
+                &consumers,
+                &providers,
+                chrono::Utc::now(),
+            )
+            .await
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn deleted_provider_actions() {
+    let deleted = |annotations: Value| {
+        verified_provider_value(json!({ "metadata": {
+            "deletionTimestamp": "2023-01-01T00:00:00Z",
+            "annotations": annotations,
+        }}))
+    };
+    let cases = [
+        (
+            "nothing assigned",
+            deleted(json!({})),
+            vec![secret()],
+            MaskProviderAction::Delete,
+        ),
+        (
+            "consumer assigned",
+            deleted(json!({})),
+            vec![secret(), reservation(json!({}))],
+            MaskProviderAction::DeleteBlocked(messages::deletion_blocked(&[
+                "default/mask-0".to_owned()
+            ])),
+        ),
+        (
+            "force deleted",
+            deleted(json!({ FORCE_DELETE_ANNOTATION: "true" })),
+            vec![secret(), reservation(json!({}))],
+            MaskProviderAction::ForceDelete(vec![
+                serde_json::from_value(reservation(json!({}))).unwrap()
+            ]),
+        ),
+    ];
+    for (case, provider, objects, expected) in cases {
+        assert_eq!(action(provider, None, &objects).await, expected, "{}", case);
+    }
+}
+
+#[tokio::test]
+async fn provider_actions() {
+    let stale = (chrono::Utc::now() - chrono::Duration::hours(2)).to_rfc3339();
+    // A periodically verified MaskProvider shows when it's next verified.
+    let verified_every_hour = {
+        let provider = verified_provider_value(json!({}));
+        let instance: MaskProvider = serde_json::from_value(provider.clone()).unwrap();
+        let next = next_verification_status(&instance, verify_every("1h").as_ref()).unwrap();
+        merged(provider, json!({ "status": { "nextVerification": next } }))
+    };
+    let ready = || MaskProviderAction::Ready {
+        capacity: Capacity {
+            available_slots: 2,
+            pending_demand: 0,
+        },
+        warnings: vec![],
+        account: None,
+        availability: None,
+        next_verification: None,
+    };
+    let suffixed = |name: &str, created: &str| {
+        verified_provider_value(json!({
+            "metadata": {
+                "name": name,
+                "uid": format!("{}-uid", name),
+                "creationTimestamp": created,
+            },
+            "spec": { "stableSecretSuffix": "vpn" },
+        }))
+    };
+    let older = suffixed("older", "2023-01-01T00:00:00Z");
+    let cases = [
+        (
+            "missing finalizer",
+            verified_provider_value(json!({ "metadata": { "finalizers": null } })),
+            None,
+            vec![secret()],
+            MaskProviderAction::Pending,
+        ),
+        (
+            "missing status",
+            verified_provider_value(json!({ "status": null })),
+            None,
+            vec![secret()],
+            MaskProviderAction::Pending,
+        ),
+        (
+            "secret missing",
+            verified_provider_value(json!({})),
+            None,
+            vec![],
+            MaskProviderAction::SecretNotFound,
+        ),
+        (
+            // Found in the cache, rather than by listing MaskProviders.
+            "suffix taken",
+            suffixed("provider", "2023-02-01T00:00:00Z"),
+            None,
+            vec![secret(), older.clone()],
+            MaskProviderAction::SecretSuffixCollision(Box::new(
+                serde_json::from_value(older).unwrap(),
+            )),
+        ),
+        (
+            "account missing",
+            verified_provider_value(json!({ "spec": { "accountRef": "shared" } })),
+            None,
+            vec![secret()],
+            MaskProviderAction::AccountNotFound("shared".to_owned()),
+        ),
+        (
+            "never verified",
+            verified_provider_value(
+                json!({ "status": { "phase": "Pending", "lastVerified": null } }),
+            ),
+            None,
+            vec![secret()],
+            MaskProviderAction::CreateVerifyMask {
+                manual: false,
+                verify: None,
+            },
+        ),
+        (
+            "verification skipped",
+            verified_provider_value(
+                json!({ "status": { "phase": "Pending", "lastVerified": null } }),
+            ),
+            Some(MaskProviderVerifySpec {
+                skip: Some(true),
+                ..Default::default()
+            }),
+            vec![secret()],
+            ready(),
+        ),
+        (
+            "verification due",
+            verified_provider_value(json!({ "status": { "lastVerified": stale } })),
+            verify_every("1h"),
+            vec![secret()],
+            MaskProviderAction::CreateVerifyMask {
+                manual: false,
+                verify: verify_every("1h"),
+            },
+        ),
+        (
+            "verified without checksum",
+            verified_provider_value(json!({ "status": { "lastVerifiedConfigHash": null } })),
+            None,
+            vec![secret()],
+            MaskProviderAction::CreateVerifyMask {
+                manual: false,
+                verify: None,
+            },
+        ),
+        (
+            "image changed since verification",
+            verified_provider_value(json!({ "spec": { "gluetunVersion": "v3.38.0" } })),
+            None,
+            vec![secret()],
+            MaskProviderAction::CreateVerifyMask {
+                manual: false,
+                verify: None,
+            },
+        ),
+        (
+            "settings changed since verification",
+            verified_provider_value(json!({})),
+            Some(MaskProviderVerifySpec {
+                timeout: Some("2m".to_owned()),
+                ..Default::default()
+            }),
+            vec![secret()],
+            MaskProviderAction::CreateVerifyMask {
+                manual: false,
+                verify: Some(MaskProviderVerifySpec {
+                    timeout: Some("2m".to_owned()),
+                    ..Default::default()
+                }),
+            },
+        ),
+        (
+            "verification current",
+            verified_every_hour,
+            verify_every("1h"),
+            vec![secret()],
+            MaskProviderAction::NoOp,
+        ),
+        (
+            "verification mask pending",
+            verified_provider_value(json!({ "status": { "phase": "Verifying" } })),
+            None,
+            vec![
+                secret(),
+                json!({
+                    "apiVersion": "vpn.beebs.dev/v1",
+                    "kind": "Mask",
+                    "metadata": { "name": "provider-verify", "namespace": "providers" },
+                    "spec": {},
+                }),
+            ],
+            MaskProviderAction::Verifying {
+                message: "Waiting on the controller for the verification Mask.".to_owned(),
+                step: VerifyStep::MaskCreated,
+                start_time: None,
+            },
+        ),
+        (
+            "verification pod succeeded",
+            verified_provider_value(json!({ "status": { "phase": "Verifying" } })),
+            None,
+            vec![
+                secret(),
+                verify_pod(json!({ "status": { "containerStatuses": [
+                    container_status(VPN_CONTAINER_NAME, json!({ "running": {} })),
+                    container_status(PROBE_CONTAINER_NAME, json!({ "terminated": { "exitCode": 0 } })),
+                ]}})),
+            ],
+            MaskProviderAction::Verified {
+                node: Some("node-a".to_owned()),
+                note: None,
+            },
+        ),
+        (
+            "verification probe couldn't look up the egress",
+            verified_provider_value(json!({ "status": { "phase": "Verifying" } })),
+            None,
+            vec![
+                secret(),
+                verify_pod(json!({ "status": { "containerStatuses": [
+                    container_status(VPN_CONTAINER_NAME, json!({ "running": {} })),
+                    container_status(PROBE_CONTAINER_NAME, json!({ "terminated": {
+                        "exitCode": 0,
+                        "message": "Couldn't look up the location.\n",
+                    } })),
+                ]}})),
+            ],
+            MaskProviderAction::Verified {
+                node: Some("node-a".to_owned()),
+                note: Some("Couldn't look up the location.".to_owned()),
+            },
+        ),
+        (
+            "verification probe failed an assertion",
+            verified_provider_value(json!({ "status": { "phase": "Verifying" } })),
+            None,
+            vec![
+                secret(),
+                verify_pod(json!({ "status": { "containerStatuses": [
+                    container_status(VPN_CONTAINER_NAME, json!({ "running": {} })),
+                    container_status(PROBE_CONTAINER_NAME, json!({ "terminated": {
+                        "exitCode": 2,
+                        "message": "Egress country is 'DE', expected 'NL'.\n",
+                    }})),
+                ]}})),
+            ],
+            MaskProviderAction::VerifyFailed(messages::verify_probe_failed(
+                "Egress country is 'DE', expected 'NL'.",
+            )),
+        ),
+        (
+            "verification probe exited without a reason",
+            verified_provider_value(json!({ "status": { "phase": "Verifying" } })),
+            None,
+            vec![
+                secret(),
+                verify_pod(json!({ "status": { "containerStatuses": [
+                    container_status(VPN_CONTAINER_NAME, json!({ "running": {} })),
+                    container_status(PROBE_CONTAINER_NAME, json!({ "terminated": { "exitCode": 137 } })),
+                ]}})),
+            ],
+            MaskProviderAction::VerifyFailed(messages::verify_probe_exited(137)),
+        ),
+        (
+            "egress assertion invalid",
+            verified_provider_value(
+                json!({ "status": { "phase": "Pending", "lastVerified": null } }),
+            ),
+            Some(MaskProviderVerifySpec {
+                assert: Some(MaskProviderVerifyAssertSpec {
+                    cidrs: Some(vec!["10.0.0.0/8".to_owned(), "2001:db8::/32".to_owned()]),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            vec![secret()],
+            MaskProviderAction::ConfigError(messages::config_error(
+                &messages::invalid_assert_cidr("2001:db8::/32"),
+            )),
+        ),
+        (
+            "verification pod deleted",
+            verified_provider_value(json!({ "status": { "phase": "Verifying" } })),
+            None,
+            vec![
+                secret(),
+                verify_pod(json!({ "metadata": { "deletionTimestamp": "2023-01-01T00:00:00Z" } })),
+            ],
+            MaskProviderAction::AwaitVerifyCleanup,
+        ),
+        (
+            "slot reserved",
+            verified_provider_value(json!({})),
+            None,
+            vec![secret(), reservation(json!({}))],
+            MaskProviderAction::Active {
+                active_slots: 1,
+                capacity: Capacity {
+                    available_slots: 1,
+                    pending_demand: 0,
+                },
+                recent_assignments: vec![assignment()],
+                warnings: vec![],
+                account: None,
+                availability: None,
+                operation: None,
+                next_verification: None,
+            },
+        ),
+        (
+            "stale status",
+            verified_provider_value(json!({ "status": { "lastUpdated": stale } })),
+            None,
+            vec![secret()],
+            ready(),
+        ),
+        (
+            "unknown namespace",
+            verified_provider_value(json!({
+                "spec": { "namespaces": ["missing"] },
+                "status": { "lastUpdated": stale },
+            })),
+            None,
+            vec![secret()],
+            MaskProviderAction::Ready {
+                capacity: Capacity {
+                    available_slots: 2,
+                    pending_demand: 0,
+                },
+                warnings: vec![messages::unknown_namespaces(&["missing".to_owned()])],
+                account: None,
+                availability: None,
+                next_verification: None,
+            },
+        ),
+        (
+            "ready",
+            verified_provider_value(json!({})),
+            None,
+            vec![
+                secret(),
+                // Verification reservations aren't real consumers.
+                reservation(
+                    json!({ "metadata": { "labels": { VERIFICATION_LABEL: "provider-uid" } } }),
+                ),
+            ],
+            MaskProviderAction::NoOp,
+        ),
+    ];
+    for (case, provider, verify, objects, expected) in cases {
+        assert_eq!(
+            action(provider, verify, &objects).await,
+            expected,
+            "{}",
+            case
+        );
+    }
+}
+
+#[tokio::test]
+async fn capacity_actions() {
+    let capacity = |available_slots, pending_demand| Capacity {
+        available_slots,
+        pending_demand,
+    };
+    let active = |capacity| MaskProviderAction::Active {
+        active_slots: 1,
+        capacity,
+        recent_assignments: vec![assignment()],
+        warnings: vec![],
+        account: None,
+        availability: None,
+        operation: None,
+        next_verification: None,
+    };
+    let full = || verified_provider_value(json!({ "spec": { "maxSlots": 1 } }));
+    let cases = [
+        (
+            "consumer waiting",
+            verified_provider_value(json!({})),
+            vec![secret(), waiting_consumer(json!({}))],
+            MaskProviderAction::Ready {
+                capacity: capacity(2, 1),
+                warnings: vec![],
+                account: None,
+                availability: None,
+                next_verification: None,
+            },
+        ),
+        (
+            "slot assigned",
+            full(),
+            vec![secret(), reservation(json!({}))],
+            active(capacity(0, 0)),
+        ),
+        (
+            "waiting on a full provider",
+            full(),
+            vec![
+                secret(),
+                reservation(json!({})),
+                waiting_consumer(json!({})),
+            ],
+            active(capacity(0, 1)),
+        ),
+        (
+            "forecast current",
+            verified_provider_value(json!({
+                "spec": { "maxSlots": 1 },
+                "status": {
+                    "phase": "Active",
+                    "availableSlots": 0,
+                    "slotsSummary": "0/1",
+                    "pendingDemand": 1,
+                    "recentAssignments": [assignment()],
+                },
+            })),
+            vec![
+                secret(),
+                reservation(json!({})),
+                waiting_consumer(json!({})),
+            ],
+            MaskProviderAction::NoOp,
+        ),
+        (
+            // Statuses written before the summary was added get it.
+            "summary missing",
+            verified_provider_value(json!({ "status": { "slotsSummary": null } })),
+            vec![secret()],
+            MaskProviderAction::Ready {
+                capacity: capacity(2, 0),
+                warnings: vec![],
+                account: None,
+                availability: None,
+                next_verification: None,
+            },
+        ),
+        (
+            "waiting for other providers",
+            verified_provider_value(json!({})),
+            vec![
+                secret(),
+                waiting_consumer(json!({ "spec": { "providers": ["other"] } })),
+            ],
+            MaskProviderAction::NoOp,
+        ),
+        (
+            "waiting in another namespace",
+            verified_provider_value(json!({ "spec": { "namespaces": ["default"] } })),
+            vec![
+                secret(),
+                json!({
+                    "apiVersion": "v1",
+                    "kind": "Namespace",
+                    "metadata": { "name": "default" },
+                }),
+                waiting_consumer(json!({ "metadata": { "namespace": "other" } })),
+            ],
+            MaskProviderAction::NoOp,
+        ),
+        (
+            "slot released",
+            full(),
+            vec![
+                secret(),
+                waiting_consumer(json!({ "status": { "phase": "Active" } })),
+            ],
+            MaskProviderAction::Ready {
+                capacity: capacity(1, 0),
+                warnings: vec![],
+                account: None,
+                availability: None,
+                next_verification: None,
+            },
+        ),
+    ];
+    for (case, provider, objects, expected) in cases {
+        assert_eq!(action(provider, None, &objects).await, expected, "{}", case);
+    }
+}
@@ -0,0 +1,56 @@
+use serde_json::{json, Value};
+use vpn_types::*;
+
+use super::mock::{consumer_value, mock_cluster, provider_value};
+use crate::consumers::actions::{create_secret, is_departing};
+
+/// Returns the MaskProvider's credentials, which outlive it while its
+/// MaskConsumers are unassigned.
+fn credentials() -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": { "name": "provider-credentials", "namespace": "providers" },
+        "data": {},
+    })
+}
+
+#[test]
+fn departing_providers() {
+    let consumer: MaskConsumer = serde_json::from_value(consumer_value(json!({}))).unwrap();
+    let assigned = consumer.status.unwrap().provider.unwrap();
+    let departing = |patch: Value| {
+        let found: MaskProvider = serde_json::from_value(provider_value(patch)).unwrap();
+        is_departing(Some(&found), &assigned)
+    };
+    assert!(!departing(json!({})));
+    assert!(departing(
+        json!({ "metadata": { "deletionTimestamp": "2024-01-01T00:00:00Z" } })
+    ));
+    assert!(departing(json!({ "metadata": { "uid": "other-uid" } })));
+    assert!(is_departing(None, &assigned));
+}
+
+#[tokio::test]
+async fn no_copy_after_provider_deletion() {
+    // The copy was decided on before the MaskProvider was deleted, and
+    // its unassignment deleted the copy before it was created again.
+    let terminating = provider_value(json!({
+        "metadata": { "deletionTimestamp": "2024-01-01T00:00:00Z" },
+    }));
+    let consumer: MaskConsumer = serde_json::from_value(consumer_value(json!({}))).unwrap();
+    for objects in [vec![terminating, credentials()], vec![credentials()]] {
+        let (client, captured) = mock_cluster(objects);
+        create_secret(client, "default", &consumer).await.unwrap();
+        // Writes are refused by the cluster, so the copy would have
+        // shown up as an error as well as a request.
+        let writes: Vec<String> = captured
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.method != "GET")
+            .map(|r| format!("{} {}", r.method, r.path))
+            .collect();
+        assert!(writes.is_empty(), "copied the credentials: {:?}", writes);
+    }
+}
@@ -0,0 +1,46 @@
+use kube::client::Client;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::util::*;
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn provider_lost_shown_until_reassigned() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+
+    // Assign the MaskProvider to a Mask.
+    let ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    create_test_provider(client.clone(), &namespace, &uid).await?;
+    ready.await.unwrap()?;
+    create_test_mask(client.clone(), &namespace, 0, &provider_name).await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+
+    // Delete the MaskProvider out from under the Mask, which shows that
+    // its assignment was revoked rather than never made.
+    let lost = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(async move {
+            wait_for_mask_phase(client, &namespace, 0, MaskPhase::ErrProviderLost).await
+        })
+    };
+    force_delete_test_provider(client.clone(), &namespace, &provider_name).await?;
+    lost.await.unwrap()?;
+
+    // The phase clears once another MaskProvider is assigned.
+    create_test_provider(client.clone(), &namespace, &uid).await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,208 @@
+use chrono::{DateTime, TimeZone, Utc};
+use serde_json::{json, Value};
+use std::time::Duration;
+use vpn_types::*;
+
+use super::mock::{decide, merged, with_verified_config};
+use crate::{
+    consumers::rollout::credentials_hash,
+    providers::{
+        operation::{deadline, draining, verify_started, verifying, VerifyStep, VERIFY_STEPS},
+        reconcile::{determine_action, MaskProviderAction},
+    },
+    util::{
+        clock::format_timestamp, finalizer::FINALIZER_NAME, PROVIDER_SECRET_LABEL,
+        PROVIDER_UID_LABEL,
+    },
+};
+
+/// Returns noon of the given day in January 2024, UTC.
+fn noon(day: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 1, day, 12, 0, 0).unwrap()
+}
+
+/// Returns a MaskProvider showing the operation.
+fn with_operation(operation: Option<MaskProviderOperation>) -> MaskProvider {
+    MaskProvider {
+        status: Some(MaskProviderStatus {
+            operation,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn verify_steps_share_start() {
+    let started = verify_started(deadline(noon(1), Duration::from_secs(60)), noon(1));
+    assert_eq!(started.kind, MaskProviderOperationKind::Verifying);
+    assert_eq!(started.started_at, format_timestamp(noon(1)));
+    assert_eq!(
+        started.deadline,
+        Some(format_timestamp(noon(1) + chrono::Duration::seconds(60)))
+    );
+    assert_eq!(started.step, VerifyStep::MaskCreated as usize);
+    assert_eq!(started.steps_total, VERIFY_STEPS);
+
+    // Later steps keep the start of the cycle.
+    let later = noon(1) + chrono::Duration::seconds(30);
+    let probing = verifying(
+        &with_operation(Some(started.clone())),
+        VerifyStep::Probing,
+        None,
+        later,
+    );
+    assert_eq!(probing.started_at, started.started_at);
+    assert_eq!(probing.step, VERIFY_STEPS);
+    assert_eq!(probing.deadline, None);
+
+    // Nor is a cycle seen mid-way, or after another operation, any older.
+    for operation in [None, draining(&with_operation(None), 1, noon(1))] {
+        let pod_created = verifying(
+            &with_operation(operation),
+            VerifyStep::PodCreated,
+            None,
+            later,
+        );
+        assert_eq!(pod_created.started_at, format_timestamp(later));
+        assert_eq!(pod_created.step, VerifyStep::PodCreated as usize);
+    }
+}
+
+#[test]
+fn drain_progress_advances() {
+    let began = draining(&with_operation(None), 3, noon(1)).unwrap();
+    assert_eq!(began.kind, MaskProviderOperationKind::Draining);
+    assert_eq!((began.step, began.steps_total), (0, 3));
+    assert_eq!(began.deadline, None);
+
+    // Unassigned MaskConsumers count as steps towards the total
+    // that was assigned when draining began.
+    let later = noon(1) + chrono::Duration::seconds(30);
+    let progressed = draining(&with_operation(Some(began.clone())), 1, later).unwrap();
+    assert_eq!((progressed.step, progressed.steps_total), (2, 3));
+    assert_eq!(progressed.started_at, began.started_at);
+
+    // Draining is over once none are left.
+    assert_eq!(draining(&with_operation(Some(progressed)), 0, later), None);
+}
+
+/// Returns a verified MaskProvider that drains outside of business
+/// hours, with `patch` merged into it.
+fn draining_provider(patch: Value) -> MaskProvider {
+    let provider = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskProvider",
+        "metadata": {
+            "name": "provider",
+            "namespace": "providers",
+            "uid": "provider-uid",
+            "finalizers": [FINALIZER_NAME],
+        },
+        "spec": {
+            "secret": "provider-credentials",
+            "maxSlots": 2,
+            "availability": {
+                "schedule": "Mon-Fri 08:00-20:00",
+                "timezone": "UTC",
+                "drainOutOfHours": true,
+            },
+        },
+        "status": {
+            "secretHash": credentials_hash(None),
+            "phase": "Active",
+            "message": "Active",
+            "lastUpdated": Utc::now().to_rfc3339(),
+            "lastVerified": Utc::now().to_rfc3339(),
+            "availableSlots": 1,
+            "slotsSummary": "1/2",
+            "pendingDemand": 0,
+            "outOfHours": true,
+        },
+    });
+    serde_json::from_value(merged(with_verified_config(provider), patch)).unwrap()
+}
+
+/// Returns the objects in the cluster while the MaskProvider's only
+/// MaskConsumer is being unassigned.
+fn draining_objects() -> Vec<Value> {
+    vec![
+        json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "metadata": {
+                "name": "provider-credentials",
+                "namespace": "providers",
+                "labels": { PROVIDER_SECRET_LABEL: "true" },
+            },
+            "data": {},
+        }),
+        json!({
+            "apiVersion": "vpn.beebs.dev/v1",
+            "kind": "MaskReservation",
+            "metadata": {
+                "name": "provider-0",
+                "namespace": "providers",
+                "labels": { PROVIDER_UID_LABEL: "provider-uid" },
+                "deletionTimestamp": "2024-01-06T12:00:00Z",
+            },
+            "spec": { "name": "mask-0", "namespace": "default", "uid": "consumer-uid", "slot": 0 },
+        }),
+    ]
+}
+
+/// Returns the action decided for the MaskProvider on a Saturday.
+async fn saturday_action(instance: MaskProvider) -> MaskProviderAction {
+    decide(&draining_objects(), |client| {
+        let instance = instance.clone();
+        async move {
+            determine_action(
+                client,
+                "provider",
+                "providers",
+                &instance,
+                None,
+                None,
+                &Default::default(),
+                &Default::default(),
+                &[],
+                &[],
+                noon(6),
+            )
+            .await
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn drain_shown_until_unassigned() {
+    // Draining began with two MaskConsumers, one of which is gone.
+    let began = json!({
+        "kind": "Draining",
+        "startedAt": "2024-01-06T11:59:00Z",
+        "step": 0,
+        "stepsTotal": 2,
+    });
+    let instance = draining_provider(json!({ "status": { "operation": began } }));
+    let (operation, recent_assignments) = match saturday_action(instance).await {
+        MaskProviderAction::Active {
+            operation,
+            recent_assignments,
+            ..
+        } => (operation, recent_assignments),
+        action => panic!("unexpected action {:?}", action),
+    };
+    let operation = operation.unwrap();
+    assert_eq!(operation.kind, MaskProviderOperationKind::Draining);
+    assert_eq!((operation.step, operation.steps_total), (1, 2));
+    assert_eq!(operation.started_at, "2024-01-06T11:59:00Z");
+
+    // Nothing changes while the status shows the progress.
+    let current = serde_json::to_value(&operation).unwrap();
+    let instance = draining_provider(json!({ "status": {
+        "operation": current,
+        "recentAssignments": recent_assignments,
+    } }));
+    assert_eq!(saturday_action(instance).await, MaskProviderAction::NoOp);
+}
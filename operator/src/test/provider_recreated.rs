@@ -0,0 +1,130 @@
+use k8s_openapi::api::core::v1::Secret;
+use kube::{api::ListParams, client::Client, Api};
+use serde_json::json;
+use std::time::Duration;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::{
+    mock::{consumer_value, mock_cluster, provider_value},
+    util::*,
+};
+use crate::{
+    consumers::actions::{get_provider_secret, sync_secret},
+    util::{Error as OperatorError, PROVIDER_UID_LABEL},
+};
+
+#[tokio::test]
+async fn replacement_credentials_not_copied() {
+    // The MaskProvider of the same name has new credentials.
+    let (client, captured) = mock_cluster(vec![
+        provider_value(json!({ "metadata": { "uid": "new-uid" } })),
+        json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "metadata": { "name": "provider-credentials", "namespace": "providers" },
+            "data": { "VPN_USERNAME": "bmV3" },
+        }),
+    ]);
+    let consumer: MaskConsumer = serde_json::from_value(consumer_value(json!({}))).unwrap();
+    let assigned = consumer.status.as_ref().unwrap().provider.as_ref().unwrap();
+    assert!(matches!(
+        get_provider_secret(client.clone(), assigned).await,
+        Err(OperatorError::ProviderDeparted(_))
+    ));
+
+    // Syncing the stale copy leaves it for the MaskConsumer's deletion.
+    sync_secret(client, "default", &consumer, Secret::default())
+        .await
+        .unwrap();
+    let captured = captured.lock().unwrap();
+    assert!(
+        captured.iter().all(|r| r.method == "GET"),
+        "copied the credentials"
+    );
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn recreated_provider_reassigned() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+
+    // Assign the MaskProvider to a Mask.
+    let ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let old = create_test_provider(client.clone(), &namespace, &uid).await?;
+    ready.await.unwrap()?;
+    create_test_mask(client.clone(), &namespace, 0, &provider_name).await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+
+    // Delete the MaskProvider out from under the Mask and recreate it
+    // with the same name as soon as its credentials are collected.
+    force_delete_test_provider(client.clone(), &namespace, &provider_name).await?;
+    let secret_api: Api<Secret> = Api::namespaced(client.clone(), &namespace);
+    for _ in 0..60 {
+        if secret_api.get_opt(&old.spec.secret).await?.is_none() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    let new = create_test_provider(client.clone(), &namespace, &uid).await?;
+    let new_uid = new.metadata.uid.clone().unwrap();
+    assert_ne!(old.metadata.uid.as_deref(), Some(new_uid.as_str()));
+
+    // The MaskConsumer is reassigned to the new MaskProvider...
+    let mc_api: Api<MaskConsumer> = Api::namespaced(client.clone(), &namespace);
+    let consumer_name = format!("{}-0", MASK_NAME);
+    let mut assigned = None;
+    for _ in 0..120 {
+        assigned = mc_api
+            .get_opt(&consumer_name)
+            .await?
+            .and_then(|mc| mc.status)
+            .and_then(|s| s.provider)
+            .filter(|p| p.uid == new_uid);
+        if assigned.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    let assigned = assigned.expect("MaskConsumer was not reassigned");
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+
+    // ...with a copy of its credentials...
+    let copy = secret_api.get(&assigned.secret).await?;
+    assert_eq!(
+        copy.metadata
+            .labels
+            .as_ref()
+            .and_then(|l| l.get(PROVIDER_UID_LABEL)),
+        Some(&new_uid)
+    );
+
+    // ...and the new MaskProvider's slots are within bounds.
+    let mr_api: Api<MaskReservation> = Api::namespaced(client.clone(), &namespace);
+    let reservations: Vec<MaskReservation> = mr_api
+        .list(&ListParams::default())
+        .await?
+        .into_iter()
+        .filter(|mr| mr.metadata.deletion_timestamp.is_none())
+        .filter(|mr| {
+            mr.metadata
+                .owner_references
+                .as_ref()
+                .is_some_and(|o| o.iter().any(|r| r.uid == new_uid))
+        })
+        .collect();
+    assert!(!reservations.is_empty());
+    assert!(reservations.len() <= MAX_SLOTS);
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
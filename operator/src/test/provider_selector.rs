@@ -0,0 +1,153 @@
+use vpn_types::*;
+
+use super::{
+    same_namespace::SameNamespace,
+    util::{mask_consumer, mask_provider},
+};
+use crate::consumers::selector::*;
+
+/// Returns a MaskProvider with the given slot usage and weight.
+fn weighted_provider(
+    name: &str,
+    active_slots: usize,
+    max_slots: usize,
+    weight: Option<u32>,
+) -> MaskProvider {
+    let mut provider = mask_provider(name, "default", &format!("{}-uid", name));
+    provider.spec.max_slots = max_slots;
+    provider.spec.weight = weight;
+    provider.spec.tags = Some(vec![name.to_owned()]);
+    provider.status = Some(MaskProviderStatus {
+        active_slots: Some(active_slots),
+        ..Default::default()
+    });
+    provider
+}
+
+/// Returns the names of the MaskProviders in order.
+fn names(providers: Vec<MaskProvider>) -> Vec<String> {
+    providers
+        .into_iter()
+        .map(|p| p.metadata.name.unwrap())
+        .collect()
+}
+
+#[test]
+fn default_keeps_list_order() {
+    let candidates = vec![
+        weighted_provider("a", 4, 5, None),
+        weighted_provider("b", 0, 5, None),
+        weighted_provider("c", 2, 5, None),
+    ];
+    let selector = SelectorRegistry::builtin().get(DEFAULT_SELECTOR).unwrap();
+    assert_eq!(
+        names(selector.select(
+            candidates,
+            &mask_consumer("test-consumer", "default", "consumer-uid")
+        )),
+        ["a", "b", "c"]
+    );
+}
+
+#[test]
+fn least_loaded_prefers_free_capacity() {
+    // Ratios are 0.8, 0.5, 0.5 and 0.1, so the ties keep their order.
+    let candidates = vec![
+        weighted_provider("a", 4, 5, None),
+        weighted_provider("b", 5, 10, None),
+        weighted_provider("c", 1, 2, None),
+        weighted_provider("d", 1, 10, None),
+    ];
+    assert_eq!(
+        names(LeastLoaded.select(
+            candidates,
+            &mask_consumer("test-consumer", "default", "consumer-uid")
+        )),
+        ["d", "b", "c", "a"]
+    );
+
+    // MaskProviders without a status yet count as empty.
+    let mut fresh = weighted_provider("fresh", 0, 5, None);
+    fresh.status = None;
+    let candidates = vec![weighted_provider("a", 1, 5, None), fresh];
+    assert_eq!(
+        names(LeastLoaded.select(
+            candidates,
+            &mask_consumer("test-consumer", "default", "consumer-uid")
+        )),
+        ["fresh", "a"]
+    );
+}
+
+#[test]
+fn weighted_follows_weights() {
+    let candidates = vec![
+        weighted_provider("heavy", 0, 5, Some(3)),
+        weighted_provider("light", 0, 5, None),
+        weighted_provider("off", 0, 5, Some(0)),
+    ];
+
+    // The order is the same every time for the same MaskConsumer.
+    let first = names(Weighted.select(
+        candidates.clone(),
+        &mask_consumer("test-consumer", "default", "consumer-uid"),
+    ));
+    for _ in 0..10 {
+        assert_eq!(
+            names(Weighted.select(
+                candidates.clone(),
+                &mask_consumer("test-consumer", "default", "consumer-uid")
+            )),
+            first
+        );
+    }
+
+    // A weight of zero is never tried, and the others are tried first in
+    // proportion to their weights.
+    let mut heavy_first = 0;
+    for i in 0..2000 {
+        let order = names(Weighted.select(
+            candidates.clone(),
+            &mask_consumer("test-consumer", "default", &format!("uid-{}", i)),
+        ));
+        assert_eq!(order.len(), 2);
+        assert!(!order.contains(&"off".to_owned()));
+        if order[0] == "heavy" {
+            heavy_first += 1;
+        }
+    }
+    // Expected 1500 of 2000 for weights of 3:1.
+    assert!(
+        (1350..1650).contains(&heavy_first),
+        "heavy was first {} times",
+        heavy_first
+    );
+}
+
+#[test]
+fn custom_selectors_registered() {
+    let mut registry = SelectorRegistry::builtin();
+    assert_eq!(registry.names(), ["default", "least-loaded", "weighted"]);
+
+    // Unknown names are rejected with the list of known ones.
+    let error = registry.get("same-namespace").err().unwrap();
+    assert_eq!(
+        error.to_string(),
+        "Invalid user input: unknown provider selector 'same-namespace', \
+         expected one of: default, least-loaded, weighted"
+    );
+
+    registry.register("same-namespace", SameNamespace);
+    let selector = registry.get("same-namespace").unwrap();
+    let candidates = vec![
+        weighted_provider("a", 0, 5, None),
+        weighted_provider("default", 0, 5, None),
+    ];
+    assert_eq!(
+        names(selector.select(
+            candidates,
+            &mask_consumer("test-consumer", "default", "consumer-uid")
+        )),
+        ["default", "a"]
+    );
+}
@@ -0,0 +1,249 @@
+use k8s_openapi::api::events::v1::Event;
+use kube::{
+    api::{ListParams, ObjectMeta},
+    client::Client,
+    Api,
+};
+use serde_json::json;
+use std::time::Duration;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::{mock::*, util::*};
+use crate::{
+    providers::withdrawal::{owning_mask, report},
+    util::messages,
+};
+
+/// Returns the force-deleted MaskProvider.
+fn withdrawn_provider() -> MaskProvider {
+    let mut provider = mask_provider("my-vpn", "vpn", "provider-uid");
+    provider.spec.report_withdrawal = Some(true);
+    provider
+}
+
+/// Returns a reservation for the MaskConsumer in the `team` namespace.
+fn reservation() -> MaskReservation {
+    MaskReservation {
+        metadata: ObjectMeta {
+            name: Some("my-vpn-0".to_owned()),
+            namespace: Some("vpn".to_owned()),
+            ..Default::default()
+        },
+        spec: MaskReservationSpec {
+            name: "test-mask".to_owned(),
+            namespace: "team".to_owned(),
+            uid: "consumer-uid".to_owned(),
+            slot: Some(0),
+        },
+        status: None,
+    }
+}
+
+/// Returns the MaskConsumer owned by the Mask.
+fn owned_consumer() -> MaskConsumer {
+    let mut consumer = mask_consumer("test-mask", "team", "consumer-uid");
+    consumer.metadata.owner_references = Some(vec![mask_owner_ref("test-mask")]);
+    consumer
+}
+
+/// Returns the Active Mask.
+fn mask() -> Mask {
+    Mask {
+        metadata: ObjectMeta {
+            name: Some("test-mask".to_owned()),
+            namespace: Some("team".to_owned()),
+            uid: Some("mask-uid".to_owned()),
+            ..Default::default()
+        },
+        status: Some(MaskStatus {
+            phase: Some(MaskPhase::Active),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn withdrawal_cleared_on_assignment() {
+    assert_eq!(owning_mask(&owned_consumer()), Some("test-mask"));
+    assert_eq!(owning_mask(&MaskConsumer::default()), None);
+
+    // The withdrawal survives other phases, but not becoming Active again.
+    let mut status = MaskStatus {
+        provider_withdrawn: Some(ProviderWithdrawn {
+            name: "my-vpn".to_owned(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    status.set_phase(
+        MaskPhase::ErrNoProviders,
+        "No valid MaskProviders available.",
+    );
+    assert!(status.provider_withdrawn.is_some());
+    status.set_active(None, "Ready");
+    assert_eq!(status.provider_withdrawn, None);
+}
+
+#[tokio::test]
+async fn withdrawal_reported_to_mask() {
+    let (client, captured) = mock_method_routes(vec![
+        (
+            "GET",
+            "/apis/vpn.beebs.dev/v1/namespaces/team/maskconsumers/test-mask",
+            200,
+            serde_json::to_value(owned_consumer()).unwrap(),
+        ),
+        (
+            "GET",
+            "/apis/vpn.beebs.dev/v1/namespaces/team/masks/test-mask",
+            200,
+            serde_json::to_value(mask()).unwrap(),
+        ),
+        (
+            "PATCH",
+            "/apis/vpn.beebs.dev/v1/namespaces/team/masks/test-mask/status",
+            200,
+            serde_json::to_value(mask()).unwrap(),
+        ),
+        (
+            "POST",
+            "/apis/events.k8s.io/v1/namespaces/team/events",
+            201,
+            json!({}),
+        ),
+    ]);
+    report(client, &withdrawn_provider(), &[&reservation()])
+        .await
+        .unwrap();
+
+    let captured = captured.lock().unwrap();
+    let patch = captured
+        .iter()
+        .find(|r| r.method == "PATCH")
+        .expect("Mask status was not updated");
+    let withdrawn = patch_op(patch, "/status/providerWithdrawn").unwrap();
+    assert_eq!(withdrawn["name"], "my-vpn");
+    assert_eq!(withdrawn["namespace"], "vpn");
+    assert!(withdrawn["at"].is_string());
+
+    // The event is published in the Mask's namespace.
+    let event = captured
+        .iter()
+        .find(|r| r.method == "POST")
+        .expect("event was not published");
+    assert_eq!(event.body["reason"], "ProviderWithdrawn");
+    assert_eq!(event.body["regarding"]["name"], "test-mask");
+    assert_eq!(event.body["regarding"]["namespace"], "team");
+}
+
+#[tokio::test]
+async fn withdrawal_reported_once() {
+    // The Mask was told before unassigning failed, and it's retried.
+    let mut told = mask();
+    told.status.as_mut().unwrap().provider_withdrawn = Some(ProviderWithdrawn {
+        name: "my-vpn".to_owned(),
+        namespace: "vpn".to_owned(),
+        message: messages::provider_withdrawn("vpn", "my-vpn"),
+        at: "2024-01-01T00:00:00Z".to_owned(),
+    });
+    let (client, captured) = mock_method_routes(vec![
+        (
+            "GET",
+            "/apis/vpn.beebs.dev/v1/namespaces/team/maskconsumers/test-mask",
+            200,
+            serde_json::to_value(owned_consumer()).unwrap(),
+        ),
+        (
+            "GET",
+            "/apis/vpn.beebs.dev/v1/namespaces/team/masks/test-mask",
+            200,
+            serde_json::to_value(told).unwrap(),
+        ),
+    ]);
+    report(client, &withdrawn_provider(), &[&reservation()])
+        .await
+        .unwrap();
+    // Neither the status nor the event is repeated.
+    let captured = captured.lock().unwrap();
+    assert!(captured.iter().all(|r| r.method == "GET"));
+}
+
+#[tokio::test]
+async fn deleted_masks_skipped() {
+    // The Mask is already gone, as are MaskConsumers of other reservations.
+    let (client, captured) = mock_method_routes(vec![(
+        "GET",
+        "/apis/vpn.beebs.dev/v1/namespaces/team/maskconsumers/test-mask",
+        200,
+        serde_json::to_value(owned_consumer()).unwrap(),
+    )]);
+    let mut orphaned = reservation();
+    orphaned.spec.name = "gone".to_owned();
+    report(client, &withdrawn_provider(), &[&reservation(), &orphaned])
+        .await
+        .unwrap();
+    let captured = captured.lock().unwrap();
+    assert!(captured.iter().all(|r| r.method == "GET"));
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn provider_withdrawn_on_deletion() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+
+    // Create a MaskProvider that reports its withdrawal and assign it.
+    let ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let mut provider = get_test_provider(client.clone(), &provider_name, &namespace).await?;
+    provider.spec.report_withdrawal = Some(true);
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let provider = api.create(&Default::default(), &provider).await?;
+    create_test_provider_secret(client.clone(), &namespace, &provider).await?;
+    ready.await.unwrap()?;
+    create_test_mask(client.clone(), &namespace, 0, &provider_name).await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+
+    // Delete the MaskProvider out from under the Mask.
+    force_delete_test_provider(client.clone(), &namespace, &provider_name).await?;
+
+    // The Mask records the withdrawal...
+    let mask_api: Api<Mask> = Api::namespaced(client.clone(), &namespace);
+    let mask_name = format!("{}-0", MASK_NAME);
+    let mut withdrawn = None;
+    for _ in 0..60 {
+        withdrawn = mask_api
+            .get(&mask_name)
+            .await?
+            .status
+            .and_then(|s| s.provider_withdrawn);
+        if withdrawn.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    let withdrawn = withdrawn.expect("withdrawal was not recorded on the Mask");
+    assert_eq!(withdrawn.name, provider_name);
+    assert_eq!(withdrawn.namespace, namespace);
+
+    // ...and a Warning event about it is published in its namespace.
+    let events: Api<Event> = Api::namespaced(client.clone(), &namespace);
+    assert!(events
+        .list(&ListParams::default())
+        .await?
+        .into_iter()
+        .any(|e| e.reason.as_deref() == Some("ProviderWithdrawn")
+            && e.regarding.and_then(|r| r.name).as_deref() == Some(mask_name.as_str())));
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
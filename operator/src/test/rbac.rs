@@ -0,0 +1,313 @@
+use k8s_openapi::api::{
+    apps::v1::{Deployment, StatefulSet},
+    batch::v1::Job,
+    coordination::v1::Lease,
+    core::v1::{ConfigMap, Namespace, Node, Pod, Secret, Service},
+    scheduling::v1::PriorityClass,
+};
+use kube::Resource;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+use vpn_types::*;
+
+use crate::rbac::{generate, verbs, Rule, COMMON, REGISTRY};
+
+/// Returns the API group and plural name of the resource.
+fn resource<K: Resource<DynamicType = ()>>() -> (String, String) {
+    (K::group(&()).into_owned(), K::plural(&()).into_owned())
+}
+
+/// Returns the API group and plural name of the kind named in an
+/// `Api<K>`. This is the checklist of every kind the operator talks to
+/// the API server about, so a new kind fails the tests until it's added
+/// here and to the RBAC of the modules that use it.
+fn kind_resource(kind: &str) -> (String, String) {
+    match kind {
+        "ClusterMaskProvider" => resource::<ClusterMaskProvider>(),
+        "ConfigMap" => resource::<ConfigMap>(),
+        "Deployment" => resource::<Deployment>(),
+        "Job" => resource::<Job>(),
+        "Lease" => resource::<Lease>(),
+        "Mask" => resource::<Mask>(),
+        "MaskConsumer" => resource::<MaskConsumer>(),
+        "MaskProvider" => resource::<MaskProvider>(),
+        "MaskQuota" => resource::<MaskQuota>(),
+        "MaskReservation" => resource::<MaskReservation>(),
+        "Namespace" => resource::<Namespace>(),
+        "Node" => resource::<Node>(),
+        "Pod" => resource::<Pod>(),
+        "PriorityClass" => resource::<PriorityClass>(),
+        "Secret" => resource::<Secret>(),
+        "Service" => resource::<Service>(),
+        "StatefulSet" => resource::<StatefulSet>(),
+        "VpnAccount" => resource::<VpnAccount>(),
+        "VpnFleet" => resource::<VpnFleet>(),
+        kind => panic!("Api<{}> is used, but {} isn't in the checklist", kind, kind),
+    }
+}
+
+/// Returns the kinds named in an `Api<K>` or `Api::<K>` anywhere in the
+/// source files under `dir`. Type parameters, such as `Api<K>`, are
+/// skipped, as are the tests.
+fn api_kinds(dir: &Path) -> BTreeSet<String> {
+    let mut kinds = BTreeSet::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            if path.file_name().unwrap() != "test" {
+                kinds.extend(api_kinds(&path));
+            }
+            continue;
+        }
+        let source = std::fs::read_to_string(&path).unwrap();
+        for (i, _) in source.match_indices("Api") {
+            let rest = source[i + 3..].trim_start_matches("::");
+            let kind: String = match rest.strip_prefix('<') {
+                Some(rest) => rest
+                    .chars()
+                    .take_while(char::is_ascii_alphanumeric)
+                    .collect(),
+                None => continue,
+            };
+            if kind.len() > 1 && kind != "Self" {
+                kinds.insert(kind);
+            }
+        }
+    }
+    kinds
+}
+
+/// Returns the resources the rules allow any verb on.
+fn covered(rules: &[&[Rule]]) -> BTreeSet<(String, String)> {
+    verbs(rules)
+        .into_keys()
+        .map(|(group, resource)| (group.to_owned(), resource.to_owned()))
+        .collect()
+}
+
+#[test]
+fn modules_declare_every_api() {
+    let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+    let operator = REGISTRY.iter().find(|c| c.name == "operator").unwrap();
+    let util: Vec<&[Rule]> = operator
+        .rules
+        .iter()
+        .copied()
+        .chain([crate::util::config::RBAC, crate::util::preview::RBAC])
+        .collect();
+    let modules: [(&str, &[&[Rule]]); 11] = [
+        ("masks", &[COMMON, crate::masks::RBAC]),
+        ("consumers", &[COMMON, crate::consumers::RBAC]),
+        ("providers", &[COMMON, crate::providers::RBAC]),
+        ("reservations", &[COMMON, crate::reservations::RBAC]),
+        ("clusterproviders", &[COMMON, crate::clusterproviders::RBAC]),
+        ("jobs", &[COMMON, crate::jobs::RBAC]),
+        ("webhook", &[crate::webhook::RBAC]),
+        ("export", &[crate::export::RBAC]),
+        ("status", &[crate::status::RBAC]),
+        (
+            "verify_all",
+            &[crate::verify_all::RBAC, crate::util::config::RBAC],
+        ),
+        // Helpers shared by the controllers only need to be covered by
+        // one of them, or by the roles they're granted separately.
+        ("util", &util),
+    ];
+    let mut checked = BTreeSet::new();
+    for (module, rules) in modules {
+        let covered = covered(rules);
+        for kind in api_kinds(&src.join(module)) {
+            assert!(
+                covered.contains(&kind_resource(&kind)),
+                "{} uses Api<{}>, but its RBAC doesn't mention {:?}",
+                module,
+                kind,
+                kind_resource(&kind),
+            );
+            checked.insert(kind);
+        }
+    }
+    // Every kind used anywhere is used in one of the modules above.
+    assert_eq!(api_kinds(&src), checked);
+}
+
+/// Returns the generated YAML documents.
+fn documents(watch_namespaces: &[&str], preview_namespaces: &[&str]) -> Vec<Value> {
+    let owned =
+        |namespaces: &[&str]| -> Vec<String> { namespaces.iter().map(|s| s.to_string()).collect() };
+    generate(
+        "vpn",
+        "vpn-system",
+        &owned(watch_namespaces),
+        &owned(preview_namespaces),
+    )
+    .unwrap()
+    .split("---\n")
+    .map(|document| serde_yaml::from_str(document).unwrap())
+    .collect()
+}
+
+/// Returns the resources the role's rules allow any verb on.
+fn role_resources(role: &Value) -> BTreeSet<String> {
+    role["rules"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .flat_map(|rule| rule["resources"].as_array().unwrap())
+        .map(|resource| resource.as_str().unwrap().to_owned())
+        .collect()
+}
+
+#[test]
+fn cluster_roles_generated() {
+    let documents = documents(&[], &[]);
+    let names: Vec<(&str, &str)> = documents
+        .iter()
+        .map(|d| {
+            (
+                d["kind"].as_str().unwrap(),
+                d["metadata"]["name"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            ("ClusterRole", "vpn-operator"),
+            ("Role", "vpn-config"),
+            ("ClusterRole", "vpn-status-exporter"),
+            ("ClusterRole", "vpn-cli"),
+        ]
+    );
+    let operator = role_resources(&documents[0]);
+    for resource in ["masks", "masks/status", "secrets", "namespaces", "events"] {
+        assert!(operator.contains(resource), "{} missing", resource);
+    }
+    // The operator config is only readable in the operator's namespace.
+    assert!(!operator.contains("configmaps"));
+    assert_eq!(documents[1]["metadata"]["namespace"], "vpn-system");
+    assert_eq!(
+        role_resources(&documents[1]),
+        BTreeSet::from(["configmaps".to_owned()])
+    );
+}
+
+#[test]
+fn namespaced_roles_generated() {
+    let documents = documents(&["team-a", "team-b"], &[]);
+    let operator: Vec<&Value> = documents
+        .iter()
+        .filter(|d| d["metadata"]["name"] == "vpn-operator")
+        .collect();
+    assert_eq!(operator.len(), 3);
+    // Resources that aren't namespaced are left in the ClusterRole.
+    assert_eq!(operator[0]["kind"], "ClusterRole");
+    assert_eq!(
+        role_resources(operator[0]),
+        BTreeSet::from(
+            [
+                "clustermaskproviders",
+                "clustermaskproviders/status",
+                "namespaces",
+                "nodes",
+                "priorityclasses",
+                "vpnfleets",
+                "vpnfleets/status",
+            ]
+            .map(str::to_owned)
+        )
+    );
+    // Everything else is granted in each of the namespaces.
+    for (role, namespace) in operator[1..].iter().zip(["team-a", "team-b"]) {
+        assert_eq!(role["kind"], "Role");
+        assert_eq!(role["metadata"]["namespace"], namespace);
+        let resources = role_resources(role);
+        assert!(resources.contains("secrets"));
+        assert!(!resources.contains("namespaces"));
+    }
+    // The status exporter has nothing that isn't namespaced.
+    let exporter: Vec<&str> = documents
+        .iter()
+        .filter(|d| d["metadata"]["name"] == "vpn-status-exporter")
+        .map(|d| d["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(exporter, vec!["Role", "Role"]);
+}
+
+#[test]
+fn preview_roles_generated() {
+    // Previews are never allowed cluster-wide, even if the operator is.
+    let documents = documents(&[], &["team-a"]);
+    let previews: Vec<&Value> = documents
+        .iter()
+        .filter(|d| d["metadata"]["name"] == "vpn-previews")
+        .collect();
+    assert_eq!(previews.len(), 1);
+    assert_eq!(previews[0]["kind"], "Role");
+    assert_eq!(previews[0]["metadata"]["namespace"], "team-a");
+    assert_eq!(
+        previews[0]["rules"][0]["verbs"],
+        serde_json::json!(["create", "get", "update"])
+    );
+
+    // The controllers can't read or write ConfigMaps anywhere else.
+    let operator = REGISTRY.iter().find(|c| c.name == "operator").unwrap();
+    assert!(!verbs(operator.rules).contains_key(&("", "configmaps")));
+}
+
+/// Returns the verbs the chart's ClusterRole grants on each resource,
+/// keyed by API group and resource, with the Helm templating removed.
+fn chart_verbs() -> BTreeMap<(String, String), BTreeSet<String>> {
+    let template = include_str!("../../../chart/templates/cluster_role.yaml");
+    let yaml: String = template
+        .lines()
+        .filter(|line| !line.contains("{{"))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let role: Value = serde_yaml::from_str(&yaml).unwrap();
+    let mut verbs: BTreeMap<_, BTreeSet<_>> = BTreeMap::new();
+    for rule in role["rules"].as_array().unwrap() {
+        for group in rule["apiGroups"].as_array().unwrap() {
+            for resource in rule["resources"].as_array().unwrap() {
+                verbs
+                    .entry((
+                        group.as_str().unwrap().to_owned(),
+                        resource.as_str().unwrap().to_owned(),
+                    ))
+                    .or_default()
+                    .extend(
+                        rule["verbs"]
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .map(|verb| verb.as_str().unwrap().to_owned()),
+                    );
+            }
+        }
+    }
+    verbs
+}
+
+#[test]
+fn chart_grants_generated_rules() {
+    // The chart's ClusterRole is bound to the controllers, so it must
+    // grant at least what they declare.
+    let chart = chart_verbs();
+    let operator = REGISTRY.iter().find(|c| c.name == "operator").unwrap();
+    let mut missing = Vec::new();
+    for ((group, resource), verbs) in verbs(operator.rules) {
+        let granted = chart.get(&(group.to_owned(), resource.to_owned()));
+        for verb in verbs {
+            if !granted.is_some_and(|granted| granted.contains(verb)) {
+                missing.push(format!("{}/{} {}", group, resource, verb));
+            }
+        }
+    }
+    assert!(missing.is_empty(), "chart doesn't grant {:?}", missing);
+    // ConfigMaps are only granted by the Roles in the release
+    // namespace and the preview namespaces.
+    assert!(!chart.contains_key(&("".to_owned(), "configmaps".to_owned())));
+}
@@ -0,0 +1,407 @@
+use k8s_openapi::{
+    api::core::v1::{Pod, Secret},
+    apimachinery::pkg::apis::meta::v1::OwnerReference,
+    ByteString,
+};
+use kube::api::ObjectMeta;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, path::PathBuf};
+use vpn_render::{
+    gluetun_container, render_verify_pod, shared_volume, vpn_init_container, GluetunOptions,
+    RenderError, RenderNames,
+};
+use vpn_types::*;
+
+use super::mock::*;
+use crate::{
+    providers::actions::{delete_verify_pod, verify_pod},
+    util::VERIFICATION_LABEL,
+};
+
+/// Set to rewrite the snapshots and other golden files with the actual
+/// output instead of comparing them, after reviewing the change in behavior.
+pub(super) const UPDATE_SNAPSHOTS: &str = "UPDATE_SNAPSHOTS";
+
+/// Compares the output with the golden file `snapshots/<file_name>`.
+pub(super) fn assert_golden(file_name: &str, output: &str) {
+    let path: PathBuf = [
+        env!("CARGO_MANIFEST_DIR"),
+        "src",
+        "test",
+        "snapshots",
+        file_name,
+    ]
+    .iter()
+    .collect();
+    if std::env::var_os(UPDATE_SNAPSHOTS).is_some() {
+        std::fs::write(&path, output).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        output == expected,
+        "output differs from {}, rerun with {}=1 to update it if the change is intended:\n{}",
+        path.display(),
+        UPDATE_SNAPSHOTS,
+        output,
+    );
+}
+
+/// Compares the rendered object as YAML with `snapshots/<name>.yaml`.
+fn assert_snapshot(name: &str, rendered: &impl Serialize) {
+    let rendered = serde_yaml::to_string(rendered).unwrap();
+    assert_golden(&format!("{}.yaml", name), &rendered);
+}
+
+/// Keys of the credentials Secret injected into the VPN container.
+fn secret_keys() -> Vec<String> {
+    ["VPN_SERVICE_PROVIDER", "OPENVPN_USER", "OPENVPN_PASSWORD"]
+        .iter()
+        .map(|k| k.to_string())
+        .collect()
+}
+
+/// Returns the names of the verification Pod of `test-provider`.
+fn names() -> RenderNames {
+    RenderNames {
+        pod: "test-provider".to_owned(),
+        namespace: "default".to_owned(),
+        secret: "test-provider-verify-provider-uid".to_owned(),
+        provider_uid: "provider-uid".to_owned(),
+        owner: Some(OwnerReference {
+            api_version: "vpn.beebs.dev/v1".to_owned(),
+            kind: "MaskConsumer".to_owned(),
+            name: "test-provider-verify".to_owned(),
+            uid: "consumer-uid".to_owned(),
+            controller: Some(true),
+            ..Default::default()
+        }),
+    }
+}
+
+/// Returns a MaskProviderSpec verified with the given options.
+fn spec(verify: MaskProviderVerifySpec) -> MaskProviderSpec {
+    MaskProviderSpec {
+        secret: "test-provider".to_owned(),
+        max_slots: 1,
+        verify: Some(verify),
+        ..Default::default()
+    }
+}
+
+/// Renders the verification Pod of the spec.
+fn render(spec: &MaskProviderSpec) -> Pod {
+    render_verify_pod(spec, &secret_keys(), names()).unwrap()
+}
+
+/// Returns overrides for the verification Pod's containers.
+fn container_overrides(
+    containers: MaskProviderVerifyContainerOverridesSpec,
+) -> MaskProviderVerifySpec {
+    MaskProviderVerifySpec {
+        overrides: Some(MaskProviderVerifyOverridesSpec {
+            containers: Some(containers),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn default_settings() {
+    let spec = MaskProviderSpec {
+        secret: "test-provider".to_owned(),
+        max_slots: 1,
+        ..Default::default()
+    };
+    assert_snapshot("verify_pod_default", &render(&spec));
+}
+
+#[test]
+fn gluetun_version() {
+    let spec = MaskProviderSpec {
+        gluetun_version: Some("3.38.0".to_owned()),
+        ..spec(Default::default())
+    };
+    assert_snapshot("verify_pod_gluetun_version", &render(&spec));
+}
+
+#[test]
+fn container_overrides_merged() {
+    // Objects are merged, while arrays such as the probe's
+    // environment replace the defaults entirely.
+    let spec = spec(container_overrides(
+        MaskProviderVerifyContainerOverridesSpec {
+            init: Some(json!({ "image": "curlimages/curl:8.5.0" })),
+            vpn: Some(json!({
+                "imagePullPolicy": "Always",
+                "resources": { "limits": { "memory": "128Mi" } },
+            })),
+            probe: Some(json!({
+                "env": [{ "name": "SLEEP_TIME", "value": "5s" }],
+            })),
+        },
+    ));
+    assert_snapshot("verify_pod_container_overrides", &render(&spec));
+}
+
+#[test]
+fn egress_assertions() {
+    // Only the assertions that are set are passed to the probe.
+    let spec = spec(MaskProviderVerifySpec {
+        assert: Some(MaskProviderVerifyAssertSpec {
+            country: Some("NL".to_owned()),
+            cidrs: Some(vec!["185.65.134.0/24".to_owned(), "10.0.0.0/8".to_owned()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    assert_snapshot("verify_pod_assert", &render(&spec));
+}
+
+#[test]
+fn pod_overrides_merged() {
+    // Null values remove the field from the rendered Pod.
+    let spec = spec(MaskProviderVerifySpec {
+        overrides: Some(MaskProviderVerifyOverridesSpec {
+            pod: Some(json!({
+                "metadata": {
+                    "annotations": { "sidecar.istio.io/inject": "false" },
+                    "labels": { "app": null },
+                },
+                "spec": {
+                    "dnsPolicy": "None",
+                    "dnsConfig": { "nameservers": ["1.1.1.1"] },
+                    "restartPolicy": null,
+                },
+            })),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    assert_snapshot("verify_pod_pod_overrides", &render(&spec));
+}
+
+#[test]
+fn controller_metadata_kept() {
+    // The overrides can't rename, move or disown the Pod, nor hide it
+    // from the controller, but other labels are still merged.
+    let spec = spec(MaskProviderVerifySpec {
+        overrides: Some(MaskProviderVerifyOverridesSpec {
+            pod: Some(json!({
+                "metadata": {
+                    "name": "custom-name",
+                    "generateName": "custom-",
+                    "namespace": "elsewhere",
+                    "ownerReferences": null,
+                    "labels": {
+                        "mylabel": "myvalue",
+                        VERIFICATION_LABEL: "other-uid",
+                    },
+                },
+            })),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    let pod = render(&spec);
+    assert_eq!(pod.metadata.name.as_deref(), Some("test-provider"));
+    assert_eq!(pod.metadata.generate_name, None);
+    assert_eq!(pod.metadata.namespace.as_deref(), Some("default"));
+    assert_eq!(
+        pod.metadata.owner_references,
+        Some(vec![names().owner.unwrap()])
+    );
+    let labels = pod.metadata.labels.as_ref().unwrap();
+    assert_eq!(labels.get(VERIFICATION_LABEL).unwrap(), "provider-uid");
+    assert_eq!(labels.get("mylabel").unwrap(), "myvalue");
+}
+
+#[tokio::test]
+async fn renamed_pod_cleaned_up() {
+    // Cleanup deletes the Pod by the provider's name, which is
+    // the name it was created with despite the overrides.
+    let spec = spec(MaskProviderVerifySpec {
+        overrides: Some(MaskProviderVerifyOverridesSpec {
+            pod: Some(json!({ "metadata": { "name": "custom-name" } })),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    let pod = render(&spec);
+    let (client, captured) = mock_method_routes(vec![(
+        "DELETE",
+        "/api/v1/namespaces/default/pods/test-provider",
+        200,
+        serde_json::to_value(&pod).unwrap(),
+    )]);
+    delete_verify_pod(client, "test-provider", "default")
+        .await
+        .unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 1);
+    assert_eq!(captured[0].method, "DELETE");
+    assert_eq!(
+        captured[0].path.split('?').next().unwrap(),
+        format!(
+            "/api/v1/namespaces/default/pods/{}",
+            pod.metadata.name.unwrap()
+        )
+    );
+}
+
+#[test]
+fn placement_with_overrides() {
+    // The placement is applied first and the Pod overrides on top.
+    let spec = spec(MaskProviderVerifySpec {
+        placement: Some(MaskProviderVerifyPlacementSpec {
+            node_selector: Some(BTreeMap::from([(
+                "kubernetes.io/arch".to_owned(),
+                "amd64".to_owned(),
+            )])),
+            zone: Some("eu-west-2a".to_owned()),
+            affinity: Some(json!({
+                "nodeAffinity": {
+                    "requiredDuringSchedulingIgnoredDuringExecution": {
+                        "nodeSelectorTerms": [{
+                            "matchExpressions": [{
+                                "key": "node.kubernetes.io/instance-type",
+                                "operator": "NotIn",
+                                "values": ["t3.micro"],
+                            }],
+                        }],
+                    },
+                },
+            })),
+        }),
+        overrides: Some(MaskProviderVerifyOverridesSpec {
+            pod: Some(json!({
+                "spec": {
+                    "nodeSelector": { "pool": "vpn" },
+                    "tolerations": [{ "key": "vpn", "operator": "Exists" }],
+                },
+            })),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    assert_snapshot("verify_pod_placement", &render(&spec));
+}
+
+#[test]
+fn invalid_overrides_rejected() {
+    let spec = spec(MaskProviderVerifySpec {
+        placement: Some(MaskProviderVerifyPlacementSpec {
+            affinity: Some(Value::String("eu-west-2a".to_owned())),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    assert!(matches!(
+        render_verify_pod(&spec, &secret_keys(), names()),
+        Err(RenderError::JsonError { .. })
+    ));
+    let spec = self::spec(container_overrides(
+        MaskProviderVerifyContainerOverridesSpec {
+            vpn: Some(json!({ "env": "OPENVPN_USER" })),
+            ..Default::default()
+        },
+    ));
+    assert!(render_verify_pod(&spec, &secret_keys(), names()).is_err());
+}
+
+#[test]
+fn operator_renders_same_pod() {
+    let provider = MaskProvider {
+        metadata: ObjectMeta {
+            name: Some("test-provider".to_owned()),
+            namespace: Some("default".to_owned()),
+            uid: Some("provider-uid".to_owned()),
+            ..Default::default()
+        },
+        spec: spec(Default::default()),
+        status: None,
+    };
+    let consumer = MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some("test-provider-verify".to_owned()),
+            uid: Some("consumer-uid".to_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let secret = Secret {
+        metadata: ObjectMeta {
+            name: Some("test-provider-verify-provider-uid".to_owned()),
+            ..Default::default()
+        },
+        data: Some(
+            secret_keys()
+                .into_iter()
+                .map(|k| (k, ByteString(Vec::new())))
+                .collect(),
+        ),
+        ..Default::default()
+    };
+    let pod = verify_pod("test-provider", "default", &provider, &secret, &consumer).unwrap();
+
+    // The Secret's keys are injected in the order they are stored.
+    let mut keys = secret_keys();
+    keys.sort();
+    let expected = render_verify_pod(&provider.spec, &keys, names()).unwrap();
+    assert_eq!(pod, expected);
+}
+
+#[test]
+fn gluetun_env_from_secret() {
+    // All of the Secret's keys are injected by default.
+    let container = gluetun_container("mask-credentials", Default::default());
+    assert_snapshot("gluetun_env_from", &container);
+}
+
+#[test]
+fn gluetun_secret_keys() {
+    let container = gluetun_container(
+        "mask-credentials",
+        GluetunOptions {
+            version: Some("3.38.0".to_owned()),
+            secret_keys: Some(secret_keys()),
+            ..Default::default()
+        },
+    );
+    assert_snapshot("gluetun_secret_keys", &container);
+}
+
+#[test]
+fn gluetun_cluster_options() {
+    // The image takes precedence over the version.
+    let container = gluetun_container(
+        "mask-credentials",
+        GluetunOptions {
+            image: Some("registry.example.com/gluetun:pinned".to_owned()),
+            version: Some("v3.38.0".to_owned()),
+            control_server: true,
+            dns_address: Some("10.96.0.10".to_owned()),
+            firewall_outbound_subnets: vec!["10.0.0.0/8".to_owned(), "172.16.0.0/12".to_owned()],
+            ..Default::default()
+        },
+    );
+    assert_snapshot("gluetun_cluster_options", &container);
+}
+
+#[test]
+fn verify_pod_uses_sidecar_builders() {
+    // The verification Pod runs the same containers as any other
+    // workload built with the helpers.
+    let pod = render(&spec(Default::default()));
+    let spec = pod.spec.unwrap();
+    assert_eq!(spec.init_containers, Some(vec![vpn_init_container()]));
+    assert_eq!(spec.volumes, Some(vec![shared_volume()]));
+    let keys = GluetunOptions {
+        secret_keys: Some(secret_keys()),
+        ..Default::default()
+    };
+    assert_eq!(spec.containers[0], gluetun_container(&names().secret, keys));
+    assert_snapshot("gluetun_init", &vpn_init_container());
+}
@@ -0,0 +1,206 @@
+use kube::{
+    api::{Patch, PatchParams},
+    client::Client,
+    Api,
+};
+use serde_json::json;
+use std::collections::BTreeMap;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::util::*;
+use crate::{
+    consumers::required_labels::{exclude, missing},
+    masks::util::label_changes,
+    util::{messages, CANARY_LABEL},
+};
+
+/// Label that the tests' MaskProviders require.
+const PURPOSE: &str = "usage-purpose";
+
+/// Returns a MaskProvider with the given uid requiring the labels.
+fn requiring_provider(uid: &str, required: &[&str]) -> MaskProvider {
+    let mut provider = mask_provider(uid, "vpn", uid);
+    provider.spec.required_mask_labels = Some(required.iter().map(|k| k.to_string()).collect());
+    provider
+}
+
+/// Returns a MaskConsumer with the labels.
+fn labelled_consumer(labels: &[(&str, &str)]) -> MaskConsumer {
+    let mut consumer = mask_consumer("mask", "default", "consumer-uid");
+    consumer.metadata.labels = Some(
+        labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+    );
+    consumer
+}
+
+/// Returns the uids of the MaskProviders.
+fn uids(providers: &[MaskProvider]) -> Vec<&str> {
+    providers
+        .iter()
+        .filter_map(|p| p.metadata.uid.as_deref())
+        .collect()
+}
+
+#[test]
+fn missing_labels_excluded() {
+    let providers = vec![
+        requiring_provider("corporate", &[PURPOSE, "team"]),
+        requiring_provider("personal", &[]),
+    ];
+    let (remaining, missing_keys) = exclude(providers.clone(), &labelled_consumer(&[("team", "")]));
+    assert_eq!(uids(&remaining), vec!["personal"]);
+    // Labels with empty values count as missing.
+    assert_eq!(missing_keys, vec!["team".to_owned(), PURPOSE.to_owned()]);
+
+    // Without any other MaskProvider, the Mask can't be assigned.
+    let (remaining, missing_keys) = exclude(providers[..1].to_vec(), &labelled_consumer(&[]));
+    assert!(remaining.is_empty());
+    assert_eq!(
+        messages::err_missing_labels(&missing_keys),
+        "Every suitable MaskProvider requires labels that the Mask is missing: team, usage-purpose. Add them with non-empty values to be assigned one."
+    );
+}
+
+#[test]
+fn present_labels_eligible() {
+    let labeled = labelled_consumer(&[(PURPOSE, "scraping"), ("team", "data")]);
+    let providers = vec![requiring_provider("corporate", &[PURPOSE, "team"])];
+    let (remaining, missing_keys) = exclude(providers, &labeled);
+    assert_eq!(uids(&remaining), vec!["corporate"]);
+    assert!(missing_keys.is_empty());
+
+    // MaskProviders without the field accept any Mask.
+    let mut unrestricted = requiring_provider("personal", &[]);
+    unrestricted.spec.required_mask_labels = None;
+    assert!(missing(&unrestricted, &labelled_consumer(&[])).is_empty());
+
+    // Canaries test the MaskProvider regardless of its required labels.
+    let canary = labelled_consumer(&[(CANARY_LABEL, "corporate")]);
+    assert!(missing(&requiring_provider("corporate", &[PURPOSE]), &canary).is_empty());
+}
+
+#[test]
+fn added_labels_propagated() {
+    let consumer_labels = BTreeMap::from([("team".to_owned(), "data".to_owned())]);
+    assert!(label_changes(&consumer_labels, &consumer_labels).is_empty());
+
+    // A label added to the Mask later is copied to the MaskConsumer.
+    let mut mask_labels = consumer_labels.clone();
+    mask_labels.insert(PURPOSE.to_owned(), "scraping".to_owned());
+    assert_eq!(
+        label_changes(&consumer_labels, &mask_labels),
+        BTreeMap::from([(PURPOSE.to_owned(), Some("scraping".to_owned()))])
+    );
+
+    // Labels removed from the Mask are removed from the MaskConsumer.
+    let mask_labels = BTreeMap::from([("team".to_owned(), "ops".to_owned())]);
+    let consumer_labels = BTreeMap::from([
+        ("team".to_owned(), "data".to_owned()),
+        (PURPOSE.to_owned(), "scraping".to_owned()),
+    ]);
+    assert_eq!(
+        label_changes(&consumer_labels, &mask_labels),
+        BTreeMap::from([
+            ("team".to_owned(), Some("ops".to_owned())),
+            (PURPOSE.to_owned(), None),
+        ])
+    );
+}
+
+/// Creates a MaskProvider that requires the `usage-purpose` label and
+/// waits for it to become Ready.
+async fn create_provider(client: Client, namespace: &str, uid: &str) -> Result<(), Error> {
+    let provider_ready = {
+        let client = client.clone();
+        let namespace = namespace.to_owned();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let name = test_provider_name(uid);
+    let mut provider = get_test_provider(client.clone(), &name, namespace).await?;
+    provider.spec.required_mask_labels = Some(vec![PURPOSE.to_owned()]);
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), namespace);
+    let provider = api.create(&Default::default(), &provider).await?;
+    create_test_provider_secret(client, namespace, &provider).await?;
+    provider_ready.await.unwrap()?;
+    Ok(())
+}
+
+/// Sets the `usage-purpose` label of the test Mask.
+async fn label_mask(client: Client, namespace: &str, slot: usize) -> Result<(), Error> {
+    let api: Api<Mask> = Api::namespaced(client, namespace);
+    let patch = json!({ "metadata": { "labels": { PURPOSE: "scraping" } } });
+    api.patch(
+        &format!("{}-{}", MASK_NAME, slot),
+        &PatchParams::default(),
+        &Patch::Merge(&patch),
+    )
+    .await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn required_labels_missing() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    create_provider(client.clone(), &namespace, &uid).await?;
+
+    // The Mask without the label isn't assigned the MaskProvider.
+    create_test_mask(client.clone(), &namespace, 0, &test_provider_name(&uid)).await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::ErrMissingLabels).await?;
+    let api: Api<Mask> = Api::namespaced(client.clone(), &namespace);
+    let status = api.get(&format!("{}-0", MASK_NAME)).await?.status.unwrap();
+    assert!(status.message.unwrap().contains(PURPOSE));
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn required_labels_present() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    create_provider(client.clone(), &namespace, &uid).await?;
+
+    // The Mask created with the label is assigned the MaskProvider.
+    let mut mask = get_test_mask(&namespace, 0, &test_provider_name(&uid));
+    mask.metadata.labels = Some(BTreeMap::from([(
+        PURPOSE.to_owned(),
+        "scraping".to_owned(),
+    )]));
+    let api: Api<Mask> = Api::namespaced(client.clone(), &namespace);
+    api.create(&Default::default(), &mask).await?;
+    wait_for_provider_assignment(client.clone(), &namespace, 0).await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn required_labels_added_later() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    create_provider(client.clone(), &namespace, &uid).await?;
+    create_test_mask(client.clone(), &namespace, 0, &test_provider_name(&uid)).await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::ErrMissingLabels).await?;
+
+    // Labeling the Mask unblocks it without recreating it.
+    label_mask(client.clone(), &namespace, 0).await?;
+    wait_for_provider_assignment(client.clone(), &namespace, 0).await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use serde_json::{json, Value};
+use std::time::Duration;
+use vpn_types::*;
+
+use super::mock::{consumer_value, decide, merged};
+use crate::{
+    reservations::reconcile::{determine_action, ReservationAction},
+    util::finalizer::FINALIZER_NAME,
+};
+
+/// Default of `--status-freshness-interval`.
+const FRESHNESS: Duration = Duration::from_secs(600);
+
+/// Returns the MaskConsumer the reservation is for, with the given uid.
+fn reserving_consumer(uid: &str) -> Value {
+    consumer_value(json!({ "metadata": { "uid": uid } }))
+}
+
+/// Returns an Active MaskReservation for the `mask-0` MaskConsumer, with
+/// `patch` merged into it.
+fn reservation(patch: Value) -> Value {
+    let reservation = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskReservation",
+        "metadata": {
+            "name": "reservation",
+            "namespace": "default",
+            "uid": "reservation-uid",
+            "finalizers": [FINALIZER_NAME],
+        },
+        "spec": { "name": "mask-0", "namespace": "default", "uid": "consumer-uid" },
+        "status": {
+            "phase": "Active",
+            "message": "the MaskConsumer exists",
+            "lastUpdated": chrono::Utc::now().to_rfc3339(),
+        },
+    });
+    merged(reservation, patch)
+}
+
+/// Returns the action decided for the reservation with the given
+/// objects in the cluster.
+async fn action(reservation: Value, objects: &[Value]) -> ReservationAction {
+    let instance: MaskReservation = serde_json::from_value(reservation).unwrap();
+    decide(objects, |client| {
+        let instance = instance.clone();
+        async move { determine_action(client, "reservation", "default", &instance, FRESHNESS).await }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn reservation_actions() {
+    let stale = (chrono::Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+    let fresh = (chrono::Utc::now() - chrono::Duration::minutes(5)).to_rfc3339();
+    let expired = (chrono::Utc::now() - chrono::Duration::minutes(11)).to_rfc3339();
+    let cases = [
+        (
+            "being deleted",
+            reservation(json!({ "metadata": { "deletionTimestamp": "2023-01-01T00:00:00Z" } })),
+            vec![reserving_consumer("consumer-uid")],
+            ReservationAction::Delete {
+                delete_resource: false,
+            },
+        ),
+        (
+            "missing finalizer",
+            reservation(json!({ "metadata": { "finalizers": null } })),
+            vec![reserving_consumer("consumer-uid")],
+            ReservationAction::Pending,
+        ),
+        (
+            "missing status",
+            reservation(json!({ "status": null })),
+            vec![reserving_consumer("consumer-uid")],
+            ReservationAction::Pending,
+        ),
+        (
+            "consumer missing",
+            reservation(json!({})),
+            vec![],
+            ReservationAction::Delete {
+                delete_resource: true,
+            },
+        ),
+        (
+            "consumer recreated",
+            reservation(json!({})),
+            vec![reserving_consumer("other-uid")],
+            ReservationAction::Delete {
+                delete_resource: true,
+            },
+        ),
+        (
+            "pending",
+            reservation(json!({ "status": { "phase": "Pending" } })),
+            vec![reserving_consumer("consumer-uid")],
+            ReservationAction::Active,
+        ),
+        (
+            "stale status",
+            reservation(json!({ "status": { "lastUpdated": stale } })),
+            vec![reserving_consumer("consumer-uid")],
+            ReservationAction::Active,
+        ),
+        (
+            // Older than the probe interval, but within the freshness window.
+            "unchanged within window",
+            reservation(json!({ "status": { "lastUpdated": fresh } })),
+            vec![reserving_consumer("consumer-uid")],
+            ReservationAction::NoOp,
+        ),
+        (
+            "unchanged past window",
+            reservation(json!({ "status": { "lastUpdated": expired } })),
+            vec![reserving_consumer("consumer-uid")],
+            ReservationAction::Active,
+        ),
+        (
+            "active",
+            reservation(json!({})),
+            vec![reserving_consumer("consumer-uid")],
+            ReservationAction::NoOp,
+        ),
+    ];
+    for (case, reservation, objects, expected) in cases {
+        assert_eq!(action(reservation, &objects).await, expected, "{}", case);
+    }
+}
@@ -0,0 +1,145 @@
+use kube::api::ObjectMeta;
+use vpn_types::*;
+
+use crate::consumers::slots::{reservation_name, reservation_slot, MAX_NAME_LEN};
+
+/// Slots to check each provider name against, including ones
+/// that share digits with the tricky provider names below.
+const SLOTS: &[usize] = &[0, 1, 5, 7, 9, 10, 42, 57, 99, 100, 12345, usize::MAX];
+
+/// Returns provider names that are likely to confuse a parser which
+/// takes the slot from the end of a MaskReservation's name.
+fn tricky_provider_names() -> Vec<String> {
+    let mut names: Vec<String> = [
+        "p",
+        "7",
+        "x-5",
+        "x-5-7",
+        "test-provider-0123abcd-7",
+        "us.east-1",
+        "provider-",
+        "a--1",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    // Long names, including ones that only differ after
+    // the point at which they would be truncated.
+    for len in [MAX_NAME_LEN - 2, MAX_NAME_LEN - 1, MAX_NAME_LEN] {
+        names.push("a".repeat(len));
+        names.push(format!("{}-1", "b".repeat(len - 2)));
+        names.push(format!("{}.-9", "c".repeat(len - 3)));
+    }
+    names.push(format!("{}x", "d".repeat(MAX_NAME_LEN - 1)));
+    names.push(format!("{}y", "d".repeat(MAX_NAME_LEN - 1)));
+    names
+}
+
+/// Returns a MaskReservation with the given name and spec slot.
+fn reservation(name: &str, slot: Option<usize>) -> MaskReservation {
+    MaskReservation {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            ..Default::default()
+        },
+        spec: MaskReservationSpec {
+            slot,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Returns true if the name is a valid DNS subdomain.
+fn is_dns_subdomain(name: &str) -> bool {
+    name.len() <= MAX_NAME_LEN
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        })
+}
+
+#[test]
+fn slot_round_trips_through_name() {
+    for provider in tricky_provider_names() {
+        for &slot in SLOTS {
+            let name = reservation_name(&provider, slot);
+            assert!(name.len() <= MAX_NAME_LEN, "{} is too long", name);
+            assert_eq!(
+                reservation_slot(&reservation(&name, Some(slot)), &provider),
+                Some(slot),
+                "spec slot of {}",
+                name
+            );
+            // Reservations created before the slot was
+            // stored in the spec only have their name.
+            assert_eq!(
+                reservation_slot(&reservation(&name, None), &provider),
+                Some(slot),
+                "slot parsed from {}",
+                name
+            );
+        }
+    }
+}
+
+#[test]
+fn names_are_valid_for_valid_providers() {
+    for provider in tricky_provider_names()
+        .into_iter()
+        .filter(|p| is_dns_subdomain(p))
+    {
+        for &slot in SLOTS {
+            let name = reservation_name(&provider, slot);
+            assert!(is_dns_subdomain(&name), "{} is not a valid name", name);
+        }
+    }
+}
+
+#[test]
+fn names_are_unique() {
+    let mut seen = std::collections::HashMap::new();
+    for provider in tricky_provider_names() {
+        for &slot in SLOTS {
+            let name = reservation_name(&provider, slot);
+            if let Some((other, other_slot)) = seen.insert(name.clone(), (provider.clone(), slot)) {
+                panic!(
+                    "{} is shared by {}/{} and {}/{}",
+                    name, other, other_slot, provider, slot
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn foreign_names_have_no_slot() {
+    let provider = "test-provider";
+    for name in [
+        "test-provider",
+        "test-provider-",
+        "test-provider-+5",
+        "test-provider-05",
+        "test-provider-5a",
+        "test-provider-x-5",
+        "other-provider-5",
+        "test-provider-1-5",
+    ] {
+        assert_eq!(
+            reservation_slot(&reservation(name, None), provider),
+            None,
+            "{} was parsed",
+            name
+        );
+    }
+
+    // A truncated name only belongs to the provider it was hashed from.
+    let long = "e".repeat(MAX_NAME_LEN);
+    let other = format!("{}f", "e".repeat(MAX_NAME_LEN - 1));
+    let name = reservation_name(&long, 3);
+    assert_eq!(reservation_slot(&reservation(&name, None), &other), None);
+}
@@ -0,0 +1,205 @@
+use chrono::Utc;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use kube::{api::ObjectMeta, client::Client, Api};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+use vpn_types::*;
+
+use super::{mock::*, util::*};
+use crate::{
+    consumers::{
+        actions::{inactive_slots, orphaned_reservation, take_over_reservation},
+        slots::reservation_name,
+    },
+    util::{CANARY_LABEL, PROBE_INTERVAL},
+};
+
+/// Returns a MaskProvider with the given number of slots.
+fn slotted_provider(max_slots: usize) -> MaskProvider {
+    let mut provider = mask_provider("my-provider", "vpn", "provider-uid");
+    provider.spec.max_slots = max_slots;
+    provider
+}
+
+/// Returns the reservation of the slot by the MaskConsumer with the name and UID.
+fn reservation(slot: usize, name: &str, uid: &str) -> MaskReservation {
+    MaskReservation {
+        metadata: ObjectMeta {
+            name: Some(reservation_name("my-provider", slot)),
+            namespace: Some("vpn".to_owned()),
+            uid: Some(format!("reservation-{}", slot)),
+            ..Default::default()
+        },
+        spec: MaskReservationSpec {
+            name: name.to_owned(),
+            namespace: "default".to_owned(),
+            uid: uid.to_owned(),
+            slot: Some(slot),
+        },
+        ..Default::default()
+    }
+}
+
+#[test]
+fn orphan_found() {
+    let provider = slotted_provider(2);
+    let reservations = vec![
+        reservation(0, "other-mask", "other-uid"),
+        reservation(1, "my-mask", "old-uid"),
+    ];
+    let (orphan, slot) = orphaned_reservation(
+        &reservations,
+        &mask_consumer("my-mask", "default", "new-uid"),
+        &provider,
+    )
+    .expect("reservation was not found");
+    assert_eq!(slot, 1);
+    assert_eq!(orphan.spec.uid, "old-uid");
+
+    // The MaskConsumer's own reservation isn't an orphan.
+    assert!(orphaned_reservation(
+        &reservations,
+        &mask_consumer("my-mask", "default", "old-uid"),
+        &provider
+    )
+    .is_none());
+}
+
+#[test]
+fn orphan_ignored() {
+    let instance = mask_consumer("my-mask", "default", "new-uid");
+
+    // Reservations being deleted can't be taken over.
+    let mut deleting = reservation(0, "my-mask", "old-uid");
+    deleting.metadata.deletion_timestamp = Some(Time(Utc::now()));
+    assert!(orphaned_reservation(&[deleting], &instance, &slotted_provider(1)).is_none());
+
+    // Neither can slots the MaskProvider no longer has.
+    let reservations = [reservation(1, "my-mask", "old-uid")];
+    assert!(orphaned_reservation(&reservations, &instance, &slotted_provider(1)).is_none());
+
+    // Nor those of a canary, unless the MaskConsumer is the canary.
+    let mut canary = reservation(0, "my-mask", "old-uid");
+    canary.metadata.labels = Some(BTreeMap::from([(
+        CANARY_LABEL.to_owned(),
+        "provider-uid".to_owned(),
+    )]));
+    let reservations = [canary];
+    assert!(orphaned_reservation(&reservations, &instance, &slotted_provider(1)).is_none());
+    let mut instance = instance;
+    instance.metadata.labels = Some(BTreeMap::from([(
+        CANARY_LABEL.to_owned(),
+        "provider-uid".to_owned(),
+    )]));
+    assert!(orphaned_reservation(&reservations, &instance, &slotted_provider(1)).is_some());
+}
+
+#[test]
+fn slots_inactive() {
+    let reservations = vec![
+        reservation(0, "my-mask", "uid-0"),
+        reservation(2, "other-mask", "uid-2"),
+    ];
+    assert_eq!(
+        inactive_slots(&slotted_provider(4), &reservations),
+        vec![1, 3]
+    );
+}
+
+#[tokio::test]
+async fn taken_over() {
+    let orphan = reservation(0, "my-mask", "old-uid");
+    let (client, captured, stored) = mock_store(json!(orphan));
+    let taken = take_over_reservation(client, &orphan, "my-mask", "new-uid")
+        .await
+        .unwrap()
+        .expect("reservation was not taken over");
+    assert_eq!(taken.spec.uid, "new-uid");
+    assert_eq!(stored.lock().unwrap()["spec"]["uid"], json!("new-uid"));
+    let request = captured.lock().unwrap().last().cloned().unwrap();
+    assert_eq!(request.method, "PATCH");
+    assert_eq!(patch_op(&request, "/spec/uid"), Some(&json!("new-uid")));
+    assert_eq!(patch_op(&request, "/spec/name"), Some(&json!("my-mask")));
+
+    // Another MaskConsumer took it over first.
+    let mut other = orphan.clone();
+    other.spec.uid = "other-uid".to_owned();
+    let (client, _, stored) = mock_store(json!(other));
+    let taken = take_over_reservation(client, &orphan, "my-mask", "new-uid")
+        .await
+        .unwrap();
+    assert!(taken.is_none());
+    assert_eq!(stored.lock().unwrap()["spec"]["uid"], json!("other-uid"));
+
+    // The reservation was garbage collected in the meantime.
+    let (client, _) = mock_method_routes(vec![(
+        "PATCH",
+        "/apis/vpn.beebs.dev/v1/namespaces/vpn/maskreservations",
+        404,
+        status_failure(404),
+    )]);
+    let taken = take_over_reservation(client, &orphan, "my-mask", "new-uid")
+        .await
+        .unwrap();
+    assert!(taken.is_none());
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn reservation_takeover() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+
+    // Create a MaskProvider with a single slot and fill it.
+    let provider = create_test_provider(client.clone(), &namespace, &uid).await?;
+    let provider_name = provider.metadata.name.as_deref().unwrap();
+    create_test_mask(client.clone(), &namespace, 0, provider_name).await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+    let consumer_api: Api<MaskConsumer> = Api::namespaced(client.clone(), &namespace);
+    let consumer_name = format!("{}-{}", MASK_NAME, 0);
+    let old_consumer = consumer_api.get(&consumer_name).await?;
+    let old_uid = old_consumer.metadata.uid.unwrap();
+    let old_slot = old_consumer.status.unwrap().provider.unwrap().slot;
+
+    // Delete and immediately recreate the Mask, as GitOps would. The
+    // reservation is still around when the new MaskConsumer appears.
+    delete_test_mask(client.clone(), &namespace, 0).await?;
+    create_test_mask(client.clone(), &namespace, 0, provider_name).await?;
+
+    // The new MaskConsumer takes over the reservation instead of
+    // waiting for it to be garbage collected.
+    let mut waiting_since = None;
+    let start = Instant::now();
+    let assigned = loop {
+        assert!(
+            start.elapsed() < Duration::from_secs(120),
+            "MaskConsumer was not reassigned before timeout"
+        );
+        if let Some(consumer) = consumer_api.get_opt(&consumer_name).await? {
+            if consumer.metadata.uid.as_deref() != Some(&old_uid) {
+                let status = consumer.status.unwrap_or_default();
+                if let Some(provider) = status.provider {
+                    break provider;
+                }
+                if status.phase == Some(MaskConsumerPhase::Waiting) {
+                    let since = *waiting_since.get_or_insert_with(Instant::now);
+                    assert!(
+                        since.elapsed() <= PROBE_INTERVAL,
+                        "MaskConsumer was Waiting for longer than one reconcile"
+                    );
+                }
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    };
+    assert_eq!(assigned.uid, provider.metadata.uid.unwrap());
+    assert_eq!(assigned.slot, old_slot);
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,18 @@
+use vpn_types::*;
+
+use crate::consumers::selector::ProviderSelector;
+
+/// Prefers the MaskProviders tagged with the MaskConsumer's namespace.
+pub struct SameNamespace;
+
+impl ProviderSelector for SameNamespace {
+    fn select(
+        &self,
+        mut candidates: Vec<MaskProvider>,
+        consumer: &MaskConsumer,
+    ) -> Vec<MaskProvider> {
+        let namespace = consumer.metadata.namespace.clone().unwrap_or_default();
+        candidates.sort_by_key(|p| !p.spec.tags.as_ref().is_some_and(|t| t.contains(&namespace)));
+        candidates
+    }
+}
@@ -0,0 +1,299 @@
+use k8s_openapi::{
+    api::core::v1::Secret, apimachinery::pkg::apis::meta::v1::OwnerReference, ByteString,
+};
+use kube::{api::ObjectMeta, client::Client, Api};
+use serde_json::json;
+use std::collections::BTreeMap;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::{mock::*, util::*};
+use crate::{
+    consumers::actions::{create_secret, secret_conflict_reason},
+    util::PROVIDER_UID_LABEL,
+};
+
+/// Name of the MaskConsumer's credentials copy.
+const SECRET_NAME: &str = "test-mask-provider-uid";
+
+/// Returns a MaskConsumer assigned a slot with the MaskProvider, whose
+/// credentials are copied to the given Secret name.
+fn copying_consumer(secret: &str) -> MaskConsumer {
+    assigned(
+        mask_consumer("test-mask", "team", "consumer-uid"),
+        Some(MaskConsumerPhase::Waiting),
+        assigned_provider("test-provider", "default", "provider-uid", secret),
+    )
+}
+
+/// Returns a Secret with the given provider label and owner kind.
+fn secret(label: Option<&str>, owner: Option<&str>) -> Secret {
+    Secret {
+        metadata: ObjectMeta {
+            name: Some(SECRET_NAME.to_owned()),
+            namespace: Some("team".to_owned()),
+            uid: Some("secret-uid".to_owned()),
+            resource_version: Some("1".to_owned()),
+            labels: label
+                .map(|uid| BTreeMap::from([(PROVIDER_UID_LABEL.to_owned(), uid.to_owned())])),
+            owner_references: owner.map(|kind| {
+                vec![OwnerReference {
+                    kind: kind.to_owned(),
+                    name: "previous".to_owned(),
+                    uid: "previous-uid".to_owned(),
+                    ..Default::default()
+                }]
+            }),
+            ..Default::default()
+        },
+        data: Some(BTreeMap::from([(
+            "VPN_SERVICE_PROVIDER".to_owned(),
+            ByteString(b"stale".to_vec()),
+        )])),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn existing_secrets_classified() {
+    let instance = copying_consumer(SECRET_NAME);
+
+    // Copies the operator made for the MaskProvider can be adopted, even
+    // if a previous MaskConsumer with the same name owns them.
+    assert_eq!(
+        secret_conflict_reason(&secret(Some("provider-uid"), None), &instance),
+        None
+    );
+    assert_eq!(
+        secret_conflict_reason(
+            &secret(Some("provider-uid"), Some("MaskConsumer")),
+            &instance
+        ),
+        None
+    );
+
+    // Secrets from unrelated workloads are left alone.
+    assert_eq!(
+        secret_conflict_reason(&secret(None, None), &instance).as_deref(),
+        Some("was not created by vpn-operator")
+    );
+    assert_eq!(
+        secret_conflict_reason(&secret(Some("provider-uid"), Some("Deployment")), &instance)
+            .as_deref(),
+        Some("is owned by Deployment previous")
+    );
+    assert_eq!(
+        secret_conflict_reason(&secret(Some("other-uid"), None), &instance).as_deref(),
+        Some("holds credentials for MaskProvider other-uid")
+    );
+
+    // With a stable secret suffix, copies made for a previous MaskProvider are expected.
+    let stable = copying_consumer("test-mask-stable");
+    assert_eq!(
+        secret_conflict_reason(&secret(Some("other-uid"), None), &stable),
+        None
+    );
+}
+
+/// Returns a client whose API server already has `existing` under the
+/// name of the MaskConsumer's credentials copy.
+fn mock_conflict(existing: &Secret) -> (Client, Captured) {
+    let provider = MaskProvider {
+        metadata: ObjectMeta {
+            name: Some("test-provider".to_owned()),
+            namespace: Some("default".to_owned()),
+            uid: Some("provider-uid".to_owned()),
+            ..Default::default()
+        },
+        spec: MaskProviderSpec {
+            secret: "test-secret".to_owned(),
+            max_slots: 1,
+            ..Default::default()
+        },
+        status: None,
+    };
+    let provider_secret = Secret {
+        metadata: ObjectMeta {
+            name: Some("test-secret".to_owned()),
+            namespace: Some("default".to_owned()),
+            ..Default::default()
+        },
+        data: Some(BTreeMap::from([(
+            "VPN_SERVICE_PROVIDER".to_owned(),
+            ByteString(b"nordvpn".to_vec()),
+        )])),
+        ..Default::default()
+    };
+    let existing = serde_json::to_value(existing).unwrap();
+    mock_method_routes(vec![
+        (
+            "GET",
+            "/apis/vpn.beebs.dev/v1/namespaces/default/maskproviders/test-provider",
+            200,
+            serde_json::to_value(&provider).unwrap(),
+        ),
+        (
+            "GET",
+            "/api/v1/namespaces/default/secrets/test-secret",
+            200,
+            serde_json::to_value(&provider_secret).unwrap(),
+        ),
+        (
+            "POST",
+            "/api/v1/namespaces/team/secrets",
+            409,
+            status_failure(409),
+        ),
+        (
+            "GET",
+            "/api/v1/namespaces/team/secrets/",
+            200,
+            existing.clone(),
+        ),
+        ("PUT", "/api/v1/namespaces/team/secrets/", 200, existing),
+        (
+            "PATCH",
+            "/apis/vpn.beebs.dev/v1/namespaces/team/maskconsumers/test-mask/status",
+            200,
+            serde_json::to_value(copying_consumer(SECRET_NAME)).unwrap(),
+        ),
+    ])
+}
+
+#[tokio::test]
+async fn leftover_copy_adopted() {
+    let (client, captured) = mock_conflict(&secret(Some("provider-uid"), Some("MaskConsumer")));
+    create_secret(client, "team", &copying_consumer(SECRET_NAME))
+        .await
+        .unwrap();
+
+    // The Secret is replaced with the MaskConsumer's copy, which takes
+    // over ownership and refreshes the data.
+    let captured = captured.lock().unwrap();
+    let replace = captured
+        .iter()
+        .find(|r| r.method == "PUT")
+        .expect("Secret was not replaced");
+    assert_eq!(
+        replace.path,
+        format!("/api/v1/namespaces/team/secrets/{}?", SECRET_NAME)
+    );
+    assert_eq!(replace.body["metadata"]["resourceVersion"], "1");
+    assert_eq!(
+        replace.body["metadata"]["ownerReferences"][0]["uid"],
+        "consumer-uid"
+    );
+    assert_eq!(replace.body["data"]["VPN_SERVICE_PROVIDER"], "bm9yZHZwbg==");
+    assert!(!captured.iter().any(|r| r.method == "PATCH"));
+}
+
+#[tokio::test]
+async fn foreign_secret_rejected() {
+    let (client, captured) = mock_conflict(&secret(None, None));
+    create_secret(client, "team", &copying_consumer(SECRET_NAME))
+        .await
+        .unwrap();
+
+    // The Secret is left alone, and the MaskConsumer's status names it.
+    let captured = captured.lock().unwrap();
+    assert!(!captured
+        .iter()
+        .any(|r| r.method == "PUT" || r.method == "DELETE"));
+    let patch = captured
+        .iter()
+        .find(|r| r.method == "PATCH")
+        .expect("status was not updated");
+    assert_eq!(
+        patch_op(patch, "/status/phase"),
+        Some(&json!("ErrSecretConflict"))
+    );
+    assert_eq!(
+        patch_op(patch, "/status/message"),
+        Some(&json!(format!(
+            "Secret 'team/{}' already exists and was not created by vpn-operator. \
+             Delete or rename it so the credentials can be copied.",
+            SECRET_NAME
+        )))
+    );
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn recovers_after_foreign_secret_removed() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+    let ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let provider = create_test_provider(client.clone(), &namespace, &uid).await?;
+    ready.await.unwrap()?;
+
+    // Another workload already uses the name of the Mask's credentials copy.
+    let name = format!(
+        "{}-0-{}",
+        MASK_NAME,
+        provider.metadata.uid.as_deref().unwrap()
+    );
+    let secret_api: Api<Secret> = Api::namespaced(client.clone(), &namespace);
+    let foreign = Secret {
+        metadata: ObjectMeta {
+            name: Some(name.clone()),
+            ..Default::default()
+        },
+        string_data: Some(BTreeMap::from([(
+            "password".to_owned(),
+            "hunter2".to_owned(),
+        )])),
+        ..Default::default()
+    };
+    secret_api.create(&Default::default(), &foreign).await?;
+
+    // The Mask reports the conflict instead of erroring on every reconcile.
+    let conflict = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(async move {
+            wait_for_mask_phase(client, &namespace, 0, MaskPhase::ErrSecretConflict).await
+        })
+    };
+    create_test_mask(client.clone(), &namespace, 0, &provider_name).await?;
+    conflict.await.unwrap()?;
+    let mask_api: Api<Mask> = Api::namespaced(client.clone(), &namespace);
+    let message = mask_api
+        .get(&format!("{}-0", MASK_NAME))
+        .await?
+        .status
+        .unwrap()
+        .message
+        .unwrap();
+    assert!(
+        message.contains(&name),
+        "message doesn't name the Secret: {}",
+        message
+    );
+
+    // The foreign Secret was left untouched.
+    let untouched = secret_api.get(&name).await?;
+    assert!(untouched.metadata.owner_references.is_none());
+
+    // Removing it lets the credentials be copied.
+    secret_api.delete(&name, &Default::default()).await?;
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+    let copy = secret_api.get(&name).await?;
+    assert_eq!(
+        copy.metadata
+            .labels
+            .as_ref()
+            .and_then(|l| l.get(PROVIDER_UID_LABEL)),
+        provider.metadata.uid.as_ref()
+    );
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,228 @@
+use k8s_openapi::{api::core::v1::Secret, ByteString};
+use kube::{api::ObjectMeta, client::Client, Api};
+use std::collections::BTreeMap;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::util::*;
+use crate::consumers::{
+    actions::consumer_secret,
+    gluetun::{format_data, render, CONFIG_KEY, EXTRA_SECTION, SECTIONS},
+};
+
+/// Returns environment variables with the given names and values.
+fn env(vars: &[(&str, &str)]) -> BTreeMap<String, String> {
+    vars.iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+/// Parses a rendered config file back into environment variables.
+fn parse(config: &str) -> BTreeMap<String, String> {
+    let table: toml::value::Table = toml::from_str(config).unwrap();
+    let mut env = BTreeMap::new();
+    for (section, entries) in table {
+        let prefix = SECTIONS
+            .iter()
+            .find(|(_, s)| *s == section)
+            .map(|(prefix, _)| *prefix);
+        assert!(prefix.is_some() || section == EXTRA_SECTION);
+        for (key, value) in entries.as_table().unwrap() {
+            let name = match prefix {
+                Some(prefix) => format!("{}{}", prefix, key.to_ascii_uppercase()),
+                None => key.clone(),
+            };
+            env.insert(name, value.as_str().unwrap().to_owned());
+        }
+    }
+    env
+}
+
+#[test]
+fn wireguard_variables_rendered() {
+    let vars = env(&[
+        (
+            "WIREGUARD_PRIVATE_KEY",
+            "wOEI9rqqbDwnN8/Bpp22sVz48T71vJ4fYmFWujulwUU=",
+        ),
+        ("VPN_TYPE", "wireguard"),
+        ("SERVER_COUNTRIES", "Netherlands,Sweden"),
+        ("WIREGUARD_ADDRESSES", "10.64.222.21/32"),
+        ("VPN_SERVICE_PROVIDER", "mullvad"),
+    ]);
+    assert_eq!(
+        render(&vars),
+        r#"# Rendered by vpn-operator from the assigned MaskProvider's credentials.
+
+[vpn]
+service_provider = "mullvad"
+type = "wireguard"
+
+[wireguard]
+addresses = "10.64.222.21/32"
+private_key = "wOEI9rqqbDwnN8/Bpp22sVz48T71vJ4fYmFWujulwUU="
+
+[server_selection]
+countries = "Netherlands,Sweden"
+"#
+    );
+}
+
+#[test]
+fn openvpn_variables_round_trip() {
+    // Unknown and oddly named variables end up under [extra] with
+    // their names intact, and values are escaped as needed.
+    let vars = env(&[
+        ("VPN_SERVICE_PROVIDER", "private internet access"),
+        ("OPENVPN_USER", "p1234567"),
+        ("OPENVPN_PASSWORD", "pa\"ss\\word\n\t"),
+        ("SERVER_REGIONS", "CA Montreal"),
+        ("FIREWALL_OUTBOUND_SUBNETS", "10.0.0.0/8"),
+        ("HTTPPROXY", "on"),
+        ("TZ", "Europe/Amsterdam"),
+        ("OPENVPN_lower", "kept"),
+        ("my.key", "dotted"),
+        ("VPN_", "empty"),
+    ]);
+    let config = render(&vars);
+    assert_eq!(parse(&config), vars);
+    let table: toml::value::Table = toml::from_str(&config).unwrap();
+    assert_eq!(table["openvpn"]["user"].as_str(), Some("p1234567"));
+    assert_eq!(table[EXTRA_SECTION]["my.key"].as_str(), Some("dotted"));
+    assert_eq!(table[EXTRA_SECTION]["OPENVPN_lower"].as_str(), Some("kept"));
+    // Rendering what was parsed yields the same file.
+    assert_eq!(render(&parse(&config)), config);
+}
+
+/// Returns credentials as they appear in the MaskProvider's Secret.
+fn credentials() -> BTreeMap<String, ByteString> {
+    let mut data: BTreeMap<String, ByteString> = env(&[
+        ("VPN_SERVICE_PROVIDER", "nordvpn"),
+        ("OPENVPN_USER", "user"),
+        ("OPENVPN_PASSWORD", "pass"),
+    ])
+    .into_iter()
+    .map(|(k, v)| (k, ByteString(v.into_bytes())))
+    .collect();
+    data.insert("client.key".to_owned(), ByteString(vec![0xff, 0x00, 0xfe]));
+    data
+}
+
+#[test]
+fn data_laid_out_per_format() {
+    let data = credentials();
+    assert_eq!(format_data(data.clone(), SecretFormat::Env), data);
+
+    // Only the rendered file, plus binary values that can't be rendered.
+    let toml_only = format_data(data.clone(), SecretFormat::GluetunToml);
+    let keys: Vec<&str> = toml_only.keys().map(|k| k.as_str()).collect();
+    assert_eq!(keys, vec!["client.key", CONFIG_KEY]);
+    let config = std::str::from_utf8(&toml_only[CONFIG_KEY].0).unwrap();
+    assert_eq!(parse(config).len(), 3);
+
+    // Every key, alongside the rendered file.
+    let both = format_data(data.clone(), SecretFormat::Both);
+    assert_eq!(both.len(), data.len() + 1);
+    assert_eq!(both[CONFIG_KEY], toml_only[CONFIG_KEY]);
+
+    // Reformatting is idempotent, so reconciles don't change the Secret.
+    assert_eq!(format_data(both.clone(), SecretFormat::Both), both);
+    assert_eq!(format_data(data, SecretFormat::GluetunToml), toml_only);
+}
+
+#[test]
+fn secret_uses_resolved_format() {
+    let consumer = MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some("test-mask-0".to_owned()),
+            namespace: Some("default".to_owned()),
+            uid: Some("consumer-uid".to_owned()),
+            ..Default::default()
+        },
+        status: Some(MaskConsumerStatus {
+            provider: Some(AssignedProvider {
+                name: "test-provider".to_owned(),
+                namespace: "default".to_owned(),
+                uid: "provider-uid".to_owned(),
+                slot: 0,
+                reservation: "reservation-uid".to_owned(),
+                secret: "test-mask-0-provider-uid".to_owned(),
+                ..Default::default()
+            }),
+            // The allowlist is applied before the credentials are rendered.
+            effective_settings: Some(MaskDefaultsSpec {
+                secret_keys: Some(vec!["OPENVPN_USER".to_owned()]),
+                secret_format: Some(SecretFormat::GluetunToml),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let provider_secret = Secret {
+        data: Some(credentials()),
+        ..Default::default()
+    };
+    let data = consumer_secret("default", &consumer, provider_secret)
+        .unwrap()
+        .data
+        .unwrap();
+    assert_eq!(data.len(), 1);
+    let config = std::str::from_utf8(&data[CONFIG_KEY].0).unwrap();
+    assert_eq!(parse(config), env(&[("OPENVPN_USER", "user")]));
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn secret_format() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+    let provider_ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let provider = create_test_provider(client.clone(), &namespace, &uid).await?;
+    provider_ready.await.unwrap()?;
+    let provider_keys: Vec<String> = get_provider_secret(client.clone(), &provider)
+        .await?
+        .data
+        .unwrap_or_default()
+        .into_keys()
+        .collect();
+
+    // Request the config file alongside the env keys, then instead of
+    // them, from the same MaskProvider. Each Mask has its own name, so
+    // the first Mask's Secret is never mistaken for the second's.
+    for (slot, format) in [SecretFormat::Both, SecretFormat::GluetunToml]
+        .into_iter()
+        .enumerate()
+    {
+        let assigned = {
+            let client = client.clone();
+            let namespace = namespace.clone();
+            spawn(async move { wait_for_provider_assignment(client, &namespace, slot).await })
+        };
+        let mut mask = get_test_mask(&namespace, slot, &provider_name);
+        mask.spec.settings.secret_format = Some(format);
+        Api::<Mask>::namespaced(client.clone(), &namespace)
+            .create(&Default::default(), &mask)
+            .await?;
+        let assigned = assigned.await.unwrap()?;
+        let secret = wait_for_secret(client.clone(), assigned.secret, &namespace).await?;
+        let data = secret.data.unwrap_or_default();
+        let config = std::str::from_utf8(&data[CONFIG_KEY].0).unwrap();
+        toml::from_str::<toml::value::Table>(config).unwrap();
+        let has_env = provider_keys.iter().all(|k| data.contains_key(k));
+        assert_eq!(has_env, format == SecretFormat::Both);
+        delete_test_mask(client.clone(), &namespace, slot).await?;
+    }
+
+    // Garbage collect the test resources.
+    delete_test_provider(client.clone(), &namespace, &provider_name).await?;
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,354 @@
+use k8s_openapi::{api::core::v1::Secret, ByteString};
+use kube::runtime::{reflector, reflector::ObjectRef, watcher};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use vpn_types::*;
+
+use super::mock::{
+    consumer_value, decide, merged, mock_cluster, mock_method_routes, provider_value,
+    CapturedRequest,
+};
+use crate::{
+    consumers::{
+        actions::sync_secret,
+        policy::ProviderPolicy,
+        reconcile::{determine_action, ConsumerAction, ContextData},
+        rollout::credentials_hash,
+    },
+    providers::{
+        reconcile::{self as providers, MaskProviderAction},
+        transforms::effective_hash,
+        watches::{secret_list_params, secret_providers},
+    },
+    util::{
+        config::OperatorConfig, CONSUMER_UID_LABEL, CREDENTIALS_HASH_ANNOTATION, MASK_NAME_LABEL,
+        PROVIDER_SECRET_LABEL, PROVIDER_UID_LABEL, SOURCE_HASH_ANNOTATION,
+    },
+};
+
+/// Path of the MaskProvider's credentials Secret.
+const PROVIDER_SECRET_PATH: &str = "/api/v1/namespaces/providers/secrets/provider-credentials";
+
+/// Path of the MaskConsumer's copy of the credentials.
+const COPY_PATH: &str = "/api/v1/namespaces/default/secrets/mask-0-provider-uid";
+
+/// Returns the MaskProvider's credentials Secret with the password.
+fn provider_secret(password: &str) -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {
+            "name": "provider-credentials",
+            "namespace": "providers",
+            "labels": { PROVIDER_SECRET_LABEL: "true" },
+        },
+        "data": {
+            "OPENVPN_USER": "dXNlcg==",
+            "OPENVPN_PASSWORD": ByteString(password.as_bytes().to_vec()),
+        },
+    })
+}
+
+/// Returns the checksum of the credentials with the password.
+fn hash(password: &str) -> String {
+    let secret: Secret = serde_json::from_value(provider_secret(password)).unwrap();
+    credentials_hash(secret.data.as_ref())
+}
+
+/// Returns the MaskProvider having recorded the checksum of the
+/// credentials with the password.
+fn provider_with_hash(password: &str) -> Value {
+    provider_value(json!({ "status": { "secretHash": hash(password) } }))
+}
+
+/// Returns an Active MaskConsumer assigned slot 0 of the MaskProvider,
+/// whose copy of the credentials has the checksum `copied`.
+fn copied_consumer(copied: &str) -> Value {
+    consumer_value(json!({ "status": { "provider": { "secretHash": copied } } }))
+}
+
+/// Returns the MaskReservation of the MaskConsumer's slot.
+fn reservation() -> Value {
+    json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskReservation",
+        "metadata": {
+            "name": "provider-0",
+            "namespace": "providers",
+            "uid": "reservation-uid",
+            "labels": { PROVIDER_UID_LABEL: "provider-uid" },
+        },
+        "spec": { "name": "mask-0", "namespace": "default", "uid": "consumer-uid", "slot": 0 },
+    })
+}
+
+/// Returns the MaskConsumer's copy of the credentials with the password,
+/// annotated with the checksum of the credentials it was copied from.
+fn copy(password: &str) -> Value {
+    let source = provider_secret(password);
+    json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {
+            "name": "mask-0-provider-uid",
+            "namespace": "default",
+            "labels": {
+                PROVIDER_UID_LABEL: "provider-uid",
+                MASK_NAME_LABEL: "mask-0",
+                CONSUMER_UID_LABEL: "consumer-uid",
+            },
+            "annotations": {
+                CREDENTIALS_HASH_ANNOTATION: hash(password),
+                SOURCE_HASH_ANNOTATION: hash(password),
+            },
+            "ownerReferences": [{
+                "apiVersion": "vpn.beebs.dev/v1",
+                "kind": "MaskConsumer",
+                "name": "mask-0",
+                "uid": "consumer-uid",
+            }],
+        },
+        "data": source["data"],
+    })
+}
+
+/// Returns the action decided for the MaskConsumer with the objects in
+/// the cluster, along with the requests made while deciding.
+async fn consumer_action(
+    consumer: Value,
+    objects: Vec<Value>,
+) -> (ConsumerAction, Vec<CapturedRequest>) {
+    let instance: MaskConsumer = serde_json::from_value(consumer).unwrap();
+    let (client, captured) = mock_cluster(objects);
+    let context =
+        ContextData::for_tests(client.clone(), None, Arc::new(OperatorConfig::new(false)));
+    let action = determine_action(client, "default", &instance, &context)
+        .await
+        .unwrap();
+    let requests = captured.lock().unwrap().clone();
+    (action, requests)
+}
+
+/// Returns the action decided for the MaskProvider with the objects in the cluster.
+async fn provider_action(provider: Value, objects: &[Value]) -> MaskProviderAction {
+    let instance: MaskProvider = serde_json::from_value(provider).unwrap();
+    decide(objects, |client| {
+        let instance = instance.clone();
+        async move {
+            providers::determine_action(
+                client,
+                "provider",
+                "providers",
+                &instance,
+                None,
+                None,
+                &Default::default(),
+                &Default::default(),
+                &[],
+                &[],
+                chrono::Utc::now(),
+            )
+            .await
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn provider_records_hash() {
+    // Only labelled Secrets are watched, so the label comes first.
+    let unlabelled = merged(
+        provider_secret("hunter2"),
+        json!({ "metadata": { "labels": null } }),
+    );
+    let action = provider_action(provider_value(json!({})), &[unlabelled]).await;
+    assert_eq!(action, MaskProviderAction::LabelSecret);
+    assert_eq!(
+        secret_list_params().label_selector.as_deref(),
+        Some(PROVIDER_SECRET_LABEL)
+    );
+
+    // The checksum is recorded before anything else is done.
+    let action = provider_action(provider_value(json!({})), &[provider_secret("hunter2")]).await;
+    assert_eq!(
+        action,
+        MaskProviderAction::RecordSecretHash(hash("hunter2"))
+    );
+
+    // Rotated credentials replace the recorded checksum.
+    let action =
+        provider_action(provider_with_hash("hunter2"), &[provider_secret("hunter3")]).await;
+    assert_eq!(
+        action,
+        MaskProviderAction::RecordSecretHash(hash("hunter3"))
+    );
+
+    // A current checksum is left alone.
+    let action =
+        provider_action(provider_with_hash("hunter2"), &[provider_secret("hunter2")]).await;
+    assert!(
+        !matches!(action, MaskProviderAction::RecordSecretHash(_)),
+        "{:?}",
+        action
+    );
+}
+
+#[test]
+fn hash_of_transformed_credentials() {
+    // The copies are made from the transformed credentials, so those
+    // are what the checksum is of.
+    let source: Secret = serde_json::from_value(provider_secret("aHVudGVyMg==")).unwrap();
+    let mut instance: MaskProvider = serde_json::from_value(provider_value(json!({}))).unwrap();
+    assert_eq!(
+        effective_hash(&instance, &source),
+        Some(credentials_hash(source.data.as_ref()))
+    );
+    instance.spec.transforms = Some(vec![Transform::Base64Decode {
+        key: "OPENVPN_PASSWORD".to_owned(),
+    }]);
+    assert_eq!(effective_hash(&instance, &source), Some(hash("hunter2")));
+
+    // Credentials that can't be transformed have no checksum.
+    let invalid: Secret = serde_json::from_value(provider_secret("not base64!")).unwrap();
+    assert_eq!(effective_hash(&instance, &invalid), None);
+}
+
+#[test]
+fn secret_mapped_to_providers() {
+    let (store, mut writer) = reflector::store::<MaskProvider>();
+    let providers = [
+        provider_value(json!({})),
+        provider_value(json!({ "metadata": { "name": "other", "uid": "other-uid" } })),
+        provider_value(json!({
+            "metadata": { "name": "elsewhere", "namespace": "team", "uid": "elsewhere-uid" },
+        })),
+        provider_value(json!({
+            "metadata": { "name": "unrelated", "uid": "unrelated-uid" },
+            "spec": { "secret": "unrelated-credentials" },
+        })),
+    ];
+    for provider in providers {
+        let provider = serde_json::from_value(provider).unwrap();
+        writer.apply_watcher_event(&watcher::Event::Applied(provider));
+    }
+    let secret: Secret = serde_json::from_value(provider_secret("hunter2")).unwrap();
+    let mut mapped: Vec<String> = secret_providers(&store, &secret)
+        .iter()
+        .map(ObjectRef::to_string)
+        .collect();
+    mapped.sort();
+    assert_eq!(
+        mapped,
+        vec![
+            "MaskProvider.v1.vpn.beebs.dev/other.providers",
+            "MaskProvider.v1.vpn.beebs.dev/provider.providers",
+        ]
+    );
+}
+
+#[test]
+fn copy_staleness() {
+    let copy: Secret = serde_json::from_value(copy("hunter2")).unwrap();
+    let unrecorded = provider_value(json!({ "status": { "secretHash": null } }));
+    let mut instance: MaskProvider = serde_json::from_value(unrecorded).unwrap();
+
+    // Nothing is stale until the MaskProvider recorded its checksum.
+    assert!(!ProviderPolicy::of(&instance).is_stale(&copy));
+
+    instance.status.as_mut().unwrap().secret_hash = Some(hash("hunter2"));
+    assert!(!ProviderPolicy::of(&instance).is_stale(&copy));
+
+    instance.status.as_mut().unwrap().secret_hash = Some(hash("hunter3"));
+    assert!(ProviderPolicy::of(&instance).is_stale(&copy));
+
+    // Copies made before they were annotated are copied again.
+    let mut unannotated = copy.clone();
+    unannotated.metadata.annotations = None;
+    instance.status.as_mut().unwrap().secret_hash = Some(hash("hunter2"));
+    assert!(ProviderPolicy::of(&instance).is_stale(&unannotated));
+}
+
+#[tokio::test]
+async fn steady_state_skips_provider_secret() {
+    let (action, requests) = consumer_action(
+        copied_consumer(&hash("hunter2")),
+        vec![
+            provider_with_hash("hunter2"),
+            provider_secret("hunter2"),
+            reservation(),
+            copy("hunter2"),
+        ],
+    )
+    .await;
+    assert_eq!(action, ConsumerAction::NoOp);
+
+    // The copy is read, but the credentials it was copied from aren't.
+    let paths: Vec<&str> = requests.iter().map(|r| r.path.as_str()).collect();
+    assert!(paths.contains(&COPY_PATH), "{:?}", paths);
+    assert!(
+        !paths.iter().any(|p| p.starts_with(PROVIDER_SECRET_PATH)),
+        "{:?}",
+        paths
+    );
+}
+
+#[tokio::test]
+async fn rotation_propagates() {
+    // The MaskProvider recorded the rotated credentials, so the copy is
+    // replaced without first reading the credentials to compare them.
+    let (action, requests) = consumer_action(
+        copied_consumer(&hash("hunter2")),
+        vec![
+            provider_with_hash("hunter3"),
+            provider_secret("hunter3"),
+            reservation(),
+            copy("hunter2"),
+        ],
+    )
+    .await;
+    let stale: Secret = serde_json::from_value(copy("hunter2")).unwrap();
+    assert_eq!(action, ConsumerAction::SyncSecret(Box::new(stale.clone())));
+    assert!(!requests
+        .iter()
+        .any(|r| r.path.starts_with(PROVIDER_SECRET_PATH)));
+
+    // Only now are the current credentials read and copied.
+    let (client, captured) = mock_method_routes(vec![
+        (
+            "GET",
+            "/apis/vpn.beebs.dev/v1/namespaces/providers/maskproviders/provider",
+            200,
+            provider_with_hash("hunter3"),
+        ),
+        ("GET", PROVIDER_SECRET_PATH, 200, provider_secret("hunter3")),
+        ("PUT", COPY_PATH, 200, copy("hunter3")),
+    ]);
+    let instance: MaskConsumer = serde_json::from_value(copied_consumer(&hash("hunter2"))).unwrap();
+    sync_secret(client, "default", &instance, stale)
+        .await
+        .unwrap();
+    let put = captured
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|r| r.method == "PUT")
+        .cloned()
+        .unwrap();
+    assert_eq!(put.body["data"], provider_secret("hunter3")["data"]);
+    let annotations = &put.body["metadata"]["annotations"];
+    assert_eq!(annotations[SOURCE_HASH_ANNOTATION], hash("hunter3"));
+    assert_eq!(annotations[CREDENTIALS_HASH_ANNOTATION], hash("hunter3"));
+
+    // The new checksum is then recorded, rolling out opted-in workloads.
+    let (action, _) = consumer_action(
+        copied_consumer(&hash("hunter2")),
+        vec![
+            provider_with_hash("hunter3"),
+            provider_secret("hunter3"),
+            reservation(),
+            copy("hunter3"),
+        ],
+    )
+    .await;
+    assert_eq!(action, ConsumerAction::RecordSecretHash(hash("hunter3")));
+}
@@ -0,0 +1,141 @@
+use k8s_openapi::api::core::v1::Secret;
+use serde_json::json;
+use std::collections::BTreeMap;
+use vpn_types::*;
+
+use super::{
+    mock::mock_client,
+    util::{assigned, assigned_provider, mask_consumer, mask_provider},
+};
+use crate::{
+    consumers::{
+        actions::{consumer_secret, label_secret, missing_secret_labels, secret_name},
+        slots::{MAX_LABEL_LEN, MAX_NAME_LEN},
+    },
+    util::{CONSUMER_UID_LABEL, MASK_NAME_LABEL, PROVIDER_UID_LABEL},
+};
+
+/// Returns a MaskConsumer with the given name, assigned a slot with the
+/// MaskProvider, whose credentials are copied to the given Secret name.
+fn copying_consumer(name: &str, secret: &str) -> MaskConsumer {
+    assigned(
+        mask_consumer(name, "team", "consumer-uid"),
+        Some(MaskConsumerPhase::Active),
+        assigned_provider("test-provider", "default", "provider-uid", secret),
+    )
+}
+
+/// Returns the labels of the copied Secret.
+fn labels(secret: &Secret) -> &BTreeMap<String, String> {
+    secret.metadata.labels.as_ref().unwrap()
+}
+
+#[test]
+fn copies_are_labeled() {
+    let instance = copying_consumer("test-mask", "test-mask-provider-uid");
+    let secret = consumer_secret("team", &instance, Default::default()).unwrap();
+    assert_eq!(
+        labels(&secret),
+        &BTreeMap::from([
+            (PROVIDER_UID_LABEL.to_owned(), "provider-uid".to_owned()),
+            (MASK_NAME_LABEL.to_owned(), "test-mask".to_owned()),
+            (CONSUMER_UID_LABEL.to_owned(), "consumer-uid".to_owned()),
+        ])
+    );
+    assert!(missing_secret_labels(&secret, &instance).is_empty());
+}
+
+#[test]
+fn long_names_are_hashed() {
+    let uid = "0a1b2c3d-0000-4000-8000-0123456789ab";
+    let provider = mask_provider("test-provider", "default", uid);
+
+    // Short names are left as they were.
+    assert_eq!(
+        secret_name("test-mask", &provider),
+        format!("test-mask-{}", uid)
+    );
+
+    // Names that only differ after the point at which they're
+    // truncated still get different Secrets.
+    let a = format!("{}a", "m".repeat(MAX_NAME_LEN - 1));
+    let b = format!("{}b", "m".repeat(MAX_NAME_LEN - 1));
+    let (name_a, name_b) = (secret_name(&a, &provider), secret_name(&b, &provider));
+    assert_ne!(name_a, name_b);
+    for name in [&name_a, &name_b] {
+        assert!(name.len() <= MAX_NAME_LEN, "{} is too long", name);
+        // The provider UID is still recognizable.
+        assert!(name.ends_with(&format!("-{}", uid)), "{}", name);
+        assert!(name.starts_with('m'), "{}", name);
+    }
+    assert_eq!(secret_name(&a, &provider), name_a, "names must be stable");
+
+    // The Mask name is shortened to fit in a label value, but the
+    // Secret's actual name is the one recorded in the status.
+    let instance = copying_consumer(&a, &name_a);
+    let secret = consumer_secret("team", &instance, Default::default()).unwrap();
+    assert_eq!(secret.metadata.name.as_deref(), Some(name_a.as_str()));
+    let mask_label = &labels(&secret)[MASK_NAME_LABEL];
+    assert!(
+        mask_label.len() <= MAX_LABEL_LEN,
+        "{} is too long",
+        mask_label
+    );
+    assert_ne!(
+        mask_label,
+        &labels(
+            &consumer_secret("team", &copying_consumer(&b, &name_b), Default::default()).unwrap()
+        )[MASK_NAME_LABEL]
+    );
+    let at_limit = "l".repeat(MAX_LABEL_LEN);
+    let secret = consumer_secret(
+        "team",
+        &copying_consumer(&at_limit, "s"),
+        Default::default(),
+    )
+    .unwrap();
+    assert_eq!(labels(&secret)[MASK_NAME_LABEL], at_limit);
+}
+
+#[tokio::test]
+async fn existing_copies_are_migrated() {
+    // Copies made before the Mask was labeled only carry the provider UID.
+    let instance = copying_consumer("test-mask", "test-mask-provider-uid");
+    let mut secret = consumer_secret("team", &instance, Default::default()).unwrap();
+    secret.metadata.labels = Some(BTreeMap::from([(
+        PROVIDER_UID_LABEL.to_owned(),
+        "provider-uid".to_owned(),
+    )]));
+    let missing = missing_secret_labels(&secret, &instance);
+    assert_eq!(
+        missing,
+        BTreeMap::from([
+            (MASK_NAME_LABEL.to_owned(), "test-mask".to_owned()),
+            (CONSUMER_UID_LABEL.to_owned(), "consumer-uid".to_owned()),
+        ])
+    );
+
+    // Only the missing labels are merged in, leaving any others alone.
+    let (client, captured) = mock_client(json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": { "name": "test-mask-provider-uid", "namespace": "team" },
+    }));
+    label_secret(client, "team", "test-mask-provider-uid", missing)
+        .await
+        .unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 1);
+    assert_eq!(captured[0].method, "PATCH");
+    assert_eq!(
+        captured[0].path,
+        "/api/v1/namespaces/team/secrets/test-mask-provider-uid?"
+    );
+    assert_eq!(
+        captured[0].body,
+        json!({ "metadata": { "labels": {
+            MASK_NAME_LABEL: "test-mask",
+            CONSUMER_UID_LABEL: "consumer-uid",
+        } } })
+    );
+}
@@ -0,0 +1,259 @@
+use k8s_openapi::api::core::v1::Secret;
+use kube::{api::ObjectMeta, client::Client, Api};
+use serde_json::json;
+use std::{collections::BTreeMap, time::Duration};
+use tokio::spawn;
+use vpn_types::*;
+
+use super::{mock::decide, util::*};
+use crate::{
+    consumers::{
+        actions::{copy_secret_name, secret_conflict_reason},
+        secret_template::{is_dns_subdomain, render, validate},
+    },
+    providers::reconcile::{determine_action, MaskProviderAction},
+    util::{
+        finalizer::FINALIZER_NAME, messages, MASK_NAME_LABEL, PROVIDER_SECRET_LABEL,
+        PROVIDER_UID_LABEL, VERIFICATION_LABEL,
+    },
+};
+
+/// Returns a MaskProvider with the given copy Secret name template and slots.
+fn templated_provider(template: Option<&str>, max_slots: usize) -> MaskProvider {
+    let mut provider = mask_provider("provider", "providers", "provider-uid");
+    provider.spec.max_slots = max_slots;
+    provider.spec.secret = "provider".to_owned();
+    provider.spec.copy_secret_name_template = template.map(str::to_owned);
+    provider
+}
+
+/// Returns a MaskConsumer named `mask-0` with the given labels.
+fn labelled_consumer(labels: &[(&str, &str)]) -> MaskConsumer {
+    let mut consumer = mask_consumer("mask-0", "default", "0123abcd-4567-89ef");
+    consumer.metadata.labels = Some(
+        labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+    );
+    consumer
+}
+
+#[test]
+fn template_rendered() {
+    let template = "${MASK}-${PROVIDER}-${SLOT}-${UID_SHORT}";
+    assert_eq!(
+        render(template, "mask-0", "nordvpn", 3, "0123abcd-4567"),
+        "mask-0-nordvpn-3-0123abcd"
+    );
+    assert_eq!(
+        render("vpn-credentials", "mask-0", "nordvpn", 0, "uid"),
+        "vpn-credentials"
+    );
+
+    // Names that would be too long are shortened with a hash.
+    let long = "m".repeat(250);
+    let name = render("${MASK}-vpn", &long, "nordvpn", 0, "uid");
+    assert!(name.len() <= 253);
+    assert!(is_dns_subdomain(&name));
+}
+
+#[test]
+fn templates_validated() {
+    let cases = [
+        ("no template", None, 2, true),
+        ("mask name", Some("${MASK}-vpn"), 2, true),
+        ("consumer uid", Some("vpn-${UID_SHORT}"), 2, true),
+        ("fixed name with one slot", Some("vpn-credentials"), 1, true),
+        (
+            "fixed name with many slots",
+            Some("vpn-credentials"),
+            2,
+            false,
+        ),
+        (
+            "provider and slot alone",
+            Some("${PROVIDER}-${SLOT}"),
+            2,
+            false,
+        ),
+        (
+            "unknown placeholder",
+            Some("${MASK}-${NAMESPACE}"),
+            2,
+            false,
+        ),
+        ("unclosed placeholder", Some("${MASK"), 1, false),
+        ("uppercase", Some("VPN-${MASK}"), 2, false),
+        ("trailing separator", Some("${MASK}-"), 2, false),
+        ("empty", Some(""), 1, false),
+    ];
+    for (case, template, max_slots, valid) in cases {
+        assert_eq!(
+            validate(&templated_provider(template, max_slots)).is_ok(),
+            valid,
+            "{}",
+            case
+        );
+    }
+}
+
+#[test]
+fn copy_named_from_template() {
+    let templated = templated_provider(Some("vpn-${UID_SHORT}"), 2);
+    assert_eq!(
+        copy_secret_name("mask-0", &labelled_consumer(&[]), &templated, 1),
+        "vpn-0123abcd"
+    );
+
+    // Verification copies keep the default name.
+    let verification = labelled_consumer(&[(VERIFICATION_LABEL, "provider-uid")]);
+    assert_eq!(
+        copy_secret_name("mask-0", &verification, &templated, 1),
+        "mask-0-provider-uid"
+    );
+
+    // So do copies for MaskProviders without a valid template.
+    for provider in [
+        templated_provider(None, 2),
+        templated_provider(Some("vpn-credentials"), 2),
+    ] {
+        assert_eq!(
+            copy_secret_name("mask-0", &labelled_consumer(&[]), &provider, 1),
+            "mask-0-provider-uid"
+        );
+    }
+}
+
+#[test]
+fn other_masks_copies_conflict() {
+    let mut instance = labelled_consumer(&[]);
+    instance.status = Some(MaskConsumerStatus {
+        provider: Some(AssignedProvider {
+            uid: "provider-uid".to_owned(),
+            secret: "vpn-credentials".to_owned(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    let copy = |mask: &str| Secret {
+        metadata: ObjectMeta {
+            name: Some("vpn-credentials".to_owned()),
+            labels: Some(BTreeMap::from([
+                (PROVIDER_UID_LABEL.to_owned(), "provider-uid".to_owned()),
+                (MASK_NAME_LABEL.to_owned(), mask.to_owned()),
+            ])),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    assert_eq!(secret_conflict_reason(&copy("mask-0"), &instance), None);
+    assert_eq!(
+        secret_conflict_reason(&copy("mask-1"), &instance).as_deref(),
+        Some("holds credentials for Mask mask-1")
+    );
+}
+
+#[tokio::test]
+async fn invalid_template_is_config_error() {
+    let instance: MaskProvider = serde_json::from_value(json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskProvider",
+        "metadata": {
+            "name": "provider",
+            "namespace": "providers",
+            "uid": "provider-uid",
+            "finalizers": [FINALIZER_NAME],
+        },
+        "spec": {
+            "secret": "provider-credentials",
+            "maxSlots": 2,
+            "copySecretNameTemplate": "vpn-credentials",
+        },
+        "status": {
+            "phase": "Ready",
+            "message": "Ready",
+            "lastUpdated": chrono::Utc::now().to_rfc3339(),
+        },
+    }))
+    .unwrap();
+    let secret = json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {
+            "name": "provider-credentials",
+            "namespace": "providers",
+            "labels": { PROVIDER_SECRET_LABEL: "true" },
+        },
+        "data": {},
+    });
+    let action = decide(&[secret], |client| {
+        let instance = instance.clone();
+        async move {
+            determine_action(
+                client,
+                "provider",
+                "providers",
+                &instance,
+                None,
+                None,
+                &Default::default(),
+                &Default::default(),
+                &[],
+                &[],
+                chrono::Utc::now(),
+            )
+            .await
+        }
+    })
+    .await;
+    let error = validate(&instance).unwrap_err();
+    assert_eq!(
+        action,
+        MaskProviderAction::ConfigError(messages::config_error(&error))
+    );
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn fixed_secret_name() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+
+    // A single slot means a single Mask per namespace, so a fixed name is allowed.
+    let provider_ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let mut spec = get_test_provider(client.clone(), &provider_name, &namespace).await?;
+    spec.spec.max_slots = 1;
+    spec.spec.copy_secret_name_template = Some("vpn-credentials".to_owned());
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let provider = api.create(&Default::default(), &spec).await?;
+    create_test_provider_secret(client.clone(), &namespace, &provider).await?;
+    provider_ready.await.unwrap()?;
+
+    // The Mask's copy of the credentials has the fixed name.
+    create_test_mask(client.clone(), &namespace, 0, &provider_name).await?;
+    let assigned = wait_for_provider_assignment(client.clone(), &namespace, 0).await?;
+    assert_eq!(assigned.secret, "vpn-credentials");
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+    let secrets: Api<Secret> = Api::namespaced(client.clone(), &namespace);
+    let mut copied = None;
+    for _ in 0..30 {
+        copied = secrets.get_opt("vpn-credentials").await?;
+        if copied.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    assert!(copied.is_some());
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,313 @@
+use k8s_openapi::{api::core::v1::Secret, ByteString};
+use serde_json::json;
+use std::collections::BTreeMap;
+use vpn_types::*;
+
+use super::{mock::*, util::mask_provider};
+use crate::providers::{
+    actions::write_derived_secret,
+    transforms::{apply, derived_secret, effective_secret_name, TransformError},
+};
+
+/// WireGuard private key of the X25519 test vector in RFC 7748 section 6.1.
+const PRIVATE_KEY: &str = "dwdtCnMYpX08FsFyUbJmRd9ML4frwJkqsXf7pR25LCo=";
+
+/// Public key of the X25519 test vector in RFC 7748 section 6.1.
+const PUBLIC_KEY: &str = "hSDwCYkwp1R0i33ctD73Wg2/Og0mOBr066SpjqqbTmo=";
+
+/// Returns Secret data with the given keys and values.
+fn data(entries: &[(&str, &str)]) -> BTreeMap<String, ByteString> {
+    entries
+        .iter()
+        .map(|(k, v)| (k.to_string(), ByteString(v.as_bytes().to_vec())))
+        .collect()
+}
+
+/// Returns a MaskProvider with the given transformations.
+fn transforming_provider(transforms: Option<Vec<Transform>>) -> MaskProvider {
+    let mut provider = mask_provider("my-provider", "vpn", "provider-uid");
+    provider.spec.secret = "my-credentials".to_owned();
+    provider.spec.transforms = transforms;
+    provider
+}
+
+/// Returns the error of the transformation at `index`.
+fn error(index: usize, message: &str) -> TransformError {
+    TransformError {
+        index,
+        message: message.to_owned(),
+    }
+}
+
+#[test]
+fn base64_decode() {
+    let transforms = [Transform::Base64Decode {
+        key: "PASSWORD".to_owned(),
+    }];
+    // Surrounding whitespace, e.g. a trailing newline, is ignored.
+    let decoded = apply(&transforms, data(&[("PASSWORD", "aHVudGVyMg==\n")])).unwrap();
+    assert_eq!(decoded, data(&[("PASSWORD", "hunter2")]));
+
+    assert_eq!(
+        apply(&transforms, data(&[("PASSWORD", "not base64!")])),
+        Err(error(0, "key 'PASSWORD' is not valid base64"))
+    );
+    assert_eq!(
+        apply(&transforms, data(&[])),
+        Err(error(0, "key 'PASSWORD' does not exist"))
+    );
+}
+
+#[test]
+fn derive_wireguard_public_key() {
+    let transforms = [Transform::DeriveWireguardPublicKey {
+        from: "WIREGUARD_PRIVATE_KEY".to_owned(),
+        to: "WIREGUARD_PUBLIC_KEY".to_owned(),
+    }];
+    let derived = apply(&transforms, data(&[("WIREGUARD_PRIVATE_KEY", PRIVATE_KEY)])).unwrap();
+    assert_eq!(
+        derived,
+        data(&[
+            ("WIREGUARD_PRIVATE_KEY", PRIVATE_KEY),
+            ("WIREGUARD_PUBLIC_KEY", PUBLIC_KEY),
+        ])
+    );
+
+    // Keys of the wrong length are refused rather than padded.
+    assert_eq!(
+        apply(
+            &transforms,
+            data(&[("WIREGUARD_PRIVATE_KEY", "aHVudGVyMg==")])
+        ),
+        Err(error(
+            0,
+            "key 'WIREGUARD_PRIVATE_KEY' is not a WireGuard private key: expected 32 bytes, got 7"
+        ))
+    );
+    assert_eq!(
+        apply(&transforms, data(&[("WIREGUARD_PRIVATE_KEY", "???")])),
+        Err(error(0, "key 'WIREGUARD_PRIVATE_KEY' is not valid base64"))
+    );
+}
+
+#[test]
+fn template() {
+    let transforms = [Transform::Template {
+        to: "OPENVPN_USER".to_owned(),
+        pattern: "{{USERNAME}}+pmp ({{ REGION }})".to_owned(),
+    }];
+    let rendered = apply(
+        &transforms,
+        data(&[("USERNAME", "alice"), ("REGION", "eu")]),
+    )
+    .unwrap();
+    assert_eq!(
+        rendered.get("OPENVPN_USER"),
+        Some(&ByteString(b"alice+pmp (eu)".to_vec()))
+    );
+    // The keys the pattern reads are kept.
+    assert_eq!(rendered.len(), 3);
+
+    assert_eq!(
+        apply(&transforms, data(&[("USERNAME", "alice")])),
+        Err(error(0, "key 'REGION' does not exist"))
+    );
+    let unclosed = [Transform::Template {
+        to: "OPENVPN_USER".to_owned(),
+        pattern: "user-{{USERNAME".to_owned(),
+    }];
+    assert_eq!(
+        apply(&unclosed, data(&[("USERNAME", "alice")])),
+        Err(error(0, "placeholder at offset 5 is not closed"))
+    );
+}
+
+#[test]
+fn rename() {
+    let transforms = [Transform::Rename {
+        from: "PASSWORD".to_owned(),
+        to: "OPENVPN_PASSWORD".to_owned(),
+    }];
+    assert_eq!(
+        apply(&transforms, data(&[("PASSWORD", "hunter2")])).unwrap(),
+        data(&[("OPENVPN_PASSWORD", "hunter2")])
+    );
+    let invalid = [Transform::Rename {
+        from: "PASSWORD".to_owned(),
+        to: "OPENVPN PASSWORD".to_owned(),
+    }];
+    assert_eq!(
+        apply(&invalid, data(&[("PASSWORD", "hunter2")])),
+        Err(error(0, "'OPENVPN PASSWORD' is not a valid Secret key"))
+    );
+}
+
+#[test]
+fn missing_fields_rejected() {
+    // The schema can't require the fields of each variant, so a MaskProvider
+    // missing one is still read, and fails when it's transformed.
+    let instance: MaskProvider = serde_json::from_value(json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskProvider",
+        "metadata": { "name": "my-provider", "namespace": "vpn" },
+        "spec": {
+            "secret": "my-credentials",
+            "maxSlots": 1,
+            "transforms": [
+                { "type": "Base64Decode", "key": "PASSWORD" },
+                { "type": "Rename", "from": "PASSWORD" },
+            ],
+        },
+    }))
+    .unwrap();
+    let transforms = instance.spec.transforms.unwrap();
+    assert_eq!(
+        apply(&transforms, data(&[("PASSWORD", "aHVudGVyMg==")])),
+        Err(error(1, "'to' is required"))
+    );
+}
+
+#[test]
+fn applied_in_order() {
+    let transforms = vec![
+        Transform::Base64Decode {
+            key: "KEY".to_owned(),
+        },
+        Transform::Rename {
+            from: "KEY".to_owned(),
+            to: "WIREGUARD_PRIVATE_KEY".to_owned(),
+        },
+        Transform::DeriveWireguardPublicKey {
+            from: "WIREGUARD_PRIVATE_KEY".to_owned(),
+            to: "WIREGUARD_PUBLIC_KEY".to_owned(),
+        },
+        Transform::Rename {
+            from: "KEY".to_owned(),
+            to: "OTHER".to_owned(),
+        },
+    ];
+    // The private key was base64-encoded twice.
+    let twice = openssl::base64::encode_block(PRIVATE_KEY.as_bytes());
+    let result = apply(&transforms, data(&[("KEY", &twice)]));
+    // The last transformation fails as the key was already renamed.
+    assert_eq!(result, Err(error(3, "key 'KEY' does not exist")));
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "transforms[3]: key 'KEY' does not exist"
+    );
+
+    let derived = apply(&transforms[..3], data(&[("KEY", &twice)])).unwrap();
+    assert_eq!(
+        derived,
+        data(&[
+            ("WIREGUARD_PRIVATE_KEY", PRIVATE_KEY),
+            ("WIREGUARD_PUBLIC_KEY", PUBLIC_KEY),
+        ])
+    );
+}
+
+#[test]
+fn spec_deserialized() {
+    let spec: MaskProviderSpec = serde_json::from_value(json!({
+        "maxSlots": 1,
+        "secret": "my-credentials",
+        "transforms": [
+            { "type": "Base64Decode", "key": "PASSWORD" },
+            { "type": "Template", "to": "OPENVPN_USER", "pattern": "{{USERNAME}}" },
+        ],
+    }))
+    .unwrap();
+    assert_eq!(
+        spec.transforms,
+        Some(vec![
+            Transform::Base64Decode {
+                key: "PASSWORD".to_owned()
+            },
+            Transform::Template {
+                to: "OPENVPN_USER".to_owned(),
+                pattern: "{{USERNAME}}".to_owned(),
+            },
+        ])
+    );
+}
+
+#[test]
+fn effective_secret() {
+    // Without transformations, the referenced Secret is used directly.
+    assert_eq!(
+        effective_secret_name(&transforming_provider(None)),
+        "my-credentials"
+    );
+    assert_eq!(
+        effective_secret_name(&transforming_provider(Some(vec![]))),
+        "my-credentials"
+    );
+
+    let instance = transforming_provider(Some(vec![Transform::Rename {
+        from: "PASSWORD".to_owned(),
+        to: "OPENVPN_PASSWORD".to_owned(),
+    }]));
+    assert_eq!(effective_secret_name(&instance), "my-provider-transformed");
+
+    let source = Secret {
+        data: Some(data(&[("PASSWORD", "hunter2")])),
+        type_: Some("Opaque".to_owned()),
+        ..Default::default()
+    };
+    let derived = derived_secret(&instance, &source).unwrap();
+    assert_eq!(
+        derived.metadata.name.as_deref(),
+        Some("my-provider-transformed")
+    );
+    assert_eq!(derived.metadata.namespace.as_deref(), Some("vpn"));
+    assert_eq!(derived.data, Some(data(&[("OPENVPN_PASSWORD", "hunter2")])));
+    assert_eq!(derived.type_.as_deref(), Some("Opaque"));
+    // The MaskProvider owns it, so it's deleted along with it.
+    let owner = &derived.metadata.owner_references.unwrap()[0];
+    assert_eq!(owner.uid, "provider-uid");
+    assert_eq!(owner.controller, Some(true));
+
+    assert_eq!(
+        derived_secret(&instance, &Secret::default()).unwrap_err(),
+        error(0, "key 'PASSWORD' does not exist")
+    );
+}
+
+#[tokio::test]
+async fn derived_secret_written() {
+    let instance = transforming_provider(Some(vec![Transform::Rename {
+        from: "PASSWORD".to_owned(),
+        to: "OPENVPN_PASSWORD".to_owned(),
+    }]));
+    let source = Secret {
+        data: Some(data(&[("PASSWORD", "hunter2")])),
+        ..Default::default()
+    };
+    let derived = derived_secret(&instance, &source).unwrap();
+    let path = "/api/v1/namespaces/vpn/secrets";
+
+    // The Secret is created the first time.
+    let (client, captured) = mock_method_routes(vec![("POST", path, 201, json!(derived))]);
+    write_derived_secret(client, "vpn", derived.clone())
+        .await
+        .unwrap();
+    let create = captured.lock().unwrap().last().cloned().unwrap();
+    assert_eq!(create.method, "POST");
+    assert_eq!(
+        create.body["metadata"]["name"],
+        json!("my-provider-transformed")
+    );
+
+    // Afterwards it's replaced, guarded by its resourceVersion.
+    let mut existing = derived.clone();
+    existing.metadata.resource_version = Some("42".to_owned());
+    let (client, captured) = mock_method_routes(vec![
+        ("GET", path, 200, json!(existing)),
+        ("PUT", path, 200, json!(existing)),
+    ]);
+    write_derived_secret(client, "vpn", derived).await.unwrap();
+    let captured = captured.lock().unwrap();
+    let replace = captured.last().unwrap();
+    assert_eq!(replace.method, "PUT");
+    assert_eq!(replace.body["metadata"]["resourceVersion"], json!("42"));
+}
@@ -0,0 +1,218 @@
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::api::ObjectMeta;
+use std::collections::BTreeMap;
+use vpn_types::*;
+
+use super::{mock::*, util::mask_provider};
+use crate::{
+    consumers::account::{count_connections, Accounts, Admission},
+    util::{messages, PROBE_INTERVAL, VERIFICATION_LABEL},
+};
+
+/// Name of the VpnAccount shared by the test MaskProviders.
+const ACCOUNT: &str = "shared";
+
+/// Returns a MaskProvider with room for five slots of its own.
+fn sharing_provider(namespace: &str, uid: &str, account: Option<&str>) -> MaskProvider {
+    let mut provider = mask_provider("test-provider", namespace, uid);
+    provider.spec.secret = "test-secret".to_owned();
+    provider.spec.max_slots = 5;
+    provider.spec.account_ref = account.map(|a| a.to_owned());
+    provider
+}
+
+/// Returns a MaskReservation for a slot with the MaskProvider.
+fn reservation(provider: &MaskProvider, slot: usize, verification: bool) -> MaskReservation {
+    let uid = provider.metadata.uid.clone().unwrap();
+    MaskReservation {
+        metadata: ObjectMeta {
+            name: Some(format!("test-provider-{}", slot)),
+            namespace: provider.metadata.namespace.clone(),
+            owner_references: Some(vec![OwnerReference {
+                api_version: "vpn.beebs.dev/v1".to_owned(),
+                kind: "MaskProvider".to_owned(),
+                name: "test-provider".to_owned(),
+                uid: uid.clone(),
+                ..Default::default()
+            }]),
+            labels: verification.then(|| BTreeMap::from([(VERIFICATION_LABEL.to_owned(), uid)])),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Returns a JSON list body with the given items.
+fn list<T: serde::Serialize>(kind: &str, items: &[T]) -> serde_json::Value {
+    serde_json::json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": kind,
+        "metadata": {},
+        "items": items,
+    })
+}
+
+/// Returns the JSON body of the shared VpnAccount.
+fn account(max_connections: usize) -> serde_json::Value {
+    serde_json::json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "VpnAccount",
+        "metadata": { "name": ACCOUNT },
+        "spec": { "maxConnections": max_connections },
+    })
+}
+
+/// Returns a client for a cluster where two MaskProviders in different
+/// namespaces share the account, with two slots reserved with the first
+/// and `eu_slots` reserved with the second.
+fn shared_cluster(max_connections: usize, eu_slots: usize) -> (kube::Client, Captured) {
+    let us = sharing_provider("us", "us-uid", Some(ACCOUNT));
+    let eu = sharing_provider("eu", "eu-uid", Some(ACCOUNT));
+    let us_reservations = vec![reservation(&us, 0, false), reservation(&us, 1, false)];
+    let eu_reservations: Vec<_> = (0..eu_slots).map(|i| reservation(&eu, i, false)).collect();
+    mock_routes(vec![
+        (
+            "/apis/vpn.beebs.dev/v1/vpnaccounts/shared",
+            account(max_connections),
+        ),
+        (
+            "/apis/vpn.beebs.dev/v1/maskproviders",
+            list("MaskProviderList", &[us, eu]),
+        ),
+        (
+            "/apis/vpn.beebs.dev/v1/namespaces/us/maskreservations",
+            list("MaskReservationList", &us_reservations),
+        ),
+        (
+            "/apis/vpn.beebs.dev/v1/namespaces/eu/maskreservations",
+            list("MaskReservationList", &eu_reservations),
+        ),
+    ])
+}
+
+#[test]
+fn connections_are_counted_across_providers() {
+    let us = sharing_provider("us", "us-uid", Some(ACCOUNT));
+    let eu = sharing_provider("eu", "eu-uid", Some(ACCOUNT));
+    let other = sharing_provider("us", "other-uid", None);
+    let reservations = vec![
+        reservation(&us, 0, false),
+        reservation(&us, 1, false),
+        reservation(&eu, 0, false),
+        // Other accounts and verification don't count.
+        reservation(&other, 0, false),
+        reservation(&eu, 1, true),
+    ];
+    let providers = vec![us, eu, other];
+    assert_eq!(count_connections(ACCOUNT, &providers, &reservations), 3);
+    assert_eq!(count_connections("unknown", &providers, &reservations), 0);
+}
+
+#[tokio::test]
+async fn account_ceiling_is_shared() {
+    // The eu MaskProvider has no reservations of its own, but
+    // the us MaskProvider already uses the whole account.
+    let accounts = Accounts::new(PROBE_INTERVAL);
+    let (client, _) = shared_cluster(2, 0);
+    let eu = sharing_provider("eu", "eu-uid", Some(ACCOUNT));
+    match accounts.admit(client, &eu).await.unwrap() {
+        Admission::Full(account) => assert_eq!(account, ACCOUNT),
+        _ => panic!("expected the account to be full"),
+    }
+    assert_eq!(
+        messages::account_full(&[ACCOUNT.to_owned()]),
+        "Waiting on a slot from a MaskProvider: VpnAccount 'shared' has no connections available."
+    );
+
+    // Once there's room in the account, either MaskProvider can be used.
+    let accounts = Accounts::new(PROBE_INTERVAL);
+    let (client, _) = shared_cluster(4, 1);
+    assert!(matches!(
+        accounts.admit(client, &eu).await.unwrap(),
+        Admission::Admitted(_)
+    ));
+    let accounts = Accounts::new(PROBE_INTERVAL);
+    let (client, _) = shared_cluster(3, 1);
+    assert!(matches!(
+        accounts.admit(client, &eu).await.unwrap(),
+        Admission::Full(_)
+    ));
+}
+
+#[tokio::test]
+async fn account_limit_is_cached() {
+    let accounts = Accounts::new(PROBE_INTERVAL);
+    let eu = sharing_provider("eu", "eu-uid", Some(ACCOUNT));
+    let (client, captured) = shared_cluster(4, 0);
+    for _ in 0..2 {
+        let admission = accounts.admit(client.clone(), &eu).await.unwrap();
+        assert!(matches!(admission, Admission::Admitted(_)));
+    }
+    // The VpnAccount is fetched once, while the reservations
+    // are listed every time so the count is never stale.
+    let captured = captured.lock().unwrap();
+    let count = |prefix: &str| {
+        captured
+            .iter()
+            .filter(|r| r.path.starts_with(prefix))
+            .count()
+    };
+    assert_eq!(count("/apis/vpn.beebs.dev/v1/vpnaccounts/"), 1);
+    assert_eq!(
+        count("/apis/vpn.beebs.dev/v1/namespaces/us/maskreservations"),
+        2
+    );
+    assert_eq!(
+        count("/apis/vpn.beebs.dev/v1/namespaces/eu/maskreservations"),
+        2
+    );
+}
+
+#[tokio::test]
+async fn missing_account_refuses_assignment() {
+    let accounts = Accounts::new(PROBE_INTERVAL);
+    let (client, _) = mock_routes(vec![]);
+    let provider = sharing_provider("us", "us-uid", Some("missing"));
+    match accounts.admit(client, &provider).await.unwrap() {
+        Admission::Full(account) => assert_eq!(account, "missing"),
+        _ => panic!("expected the missing account to refuse assignment"),
+    }
+}
+
+#[tokio::test]
+async fn provider_without_account_is_unrestricted() {
+    let accounts = Accounts::new(PROBE_INTERVAL);
+    let (client, captured) = mock_routes(vec![]);
+    let provider = sharing_provider("us", "us-uid", None);
+    assert!(matches!(
+        accounts.admit(client, &provider).await.unwrap(),
+        Admission::Unrestricted
+    ));
+    assert!(captured.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn idle_account_locks_are_dropped() {
+    let accounts = Accounts::new(PROBE_INTERVAL);
+    let eu = sharing_provider("eu", "eu-uid", Some(ACCOUNT));
+    let (client, _) = shared_cluster(4, 0);
+    let admitted = accounts.admit(client, &eu).await.unwrap();
+    assert!(matches!(admitted, Admission::Admitted(_)));
+
+    // The held lock survives admission to another account.
+    let (client, _) = mock_routes(vec![]);
+    let other = sharing_provider("us", "us-uid", Some("missing"));
+    assert!(matches!(
+        accounts.admit(client.clone(), &other).await.unwrap(),
+        Admission::Full(_)
+    ));
+    assert_eq!(accounts.locked_accounts(), 2);
+
+    // Once released, both locks are dropped by the next admission.
+    drop(admitted);
+    assert!(matches!(
+        accounts.admit(client, &other).await.unwrap(),
+        Admission::Full(_)
+    ));
+    assert_eq!(accounts.locked_accounts(), 1);
+}
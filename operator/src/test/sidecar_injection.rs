@@ -0,0 +1,286 @@
+use k8s_openapi::api::core::v1::Pod;
+use kube::core::admission::AdmissionReview;
+use serde_json::{json, Value};
+use vpn_render::{SECRET_WAIT_CONTAINER_NAME, SECRET_WAIT_VOLUME_NAME, VPN_CONTAINER_NAME};
+use vpn_types::*;
+
+use super::mock::{consumer_value, merged, mock_cluster};
+use crate::{
+    util::{
+        messages, INJECTED_ANNOTATION, INJECT_NOT_READY_ANNOTATION, INJECT_OVERRIDES_ANNOTATION,
+    },
+    webhook::injection::{decide, review, Injection},
+};
+
+/// Returns a Pod asking for the credentials of `mask-0`, with `patch`
+/// merged into it.
+fn pod(patch: Value) -> Value {
+    let pod = json!({
+        "apiVersion": "v1",
+        "kind": "Pod",
+        "metadata": {
+            "name": "workload",
+            "namespace": "default",
+            "annotations": { "vpn.beebs.dev/mask": "mask-0" },
+        },
+        "spec": { "containers": [{ "name": "app", "image": "app:latest" }] },
+    });
+    merged(pod, patch)
+}
+
+/// Returns `mask-0` in the phase.
+fn mask(phase: &str) -> Value {
+    json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "Mask",
+        "metadata": { "name": "mask-0", "namespace": "default", "uid": "mask-uid" },
+        "spec": {},
+        "status": { "phase": phase },
+    })
+}
+
+/// Returns the MaskConsumer of `mask-0`, assigned a slot if `assigned`.
+fn injected_consumer(assigned: bool) -> Value {
+    let status = match assigned {
+        true => json!({ "provider": { "gluetunVersion": "3.38.0" } }),
+        false => json!({ "phase": null, "message": null, "provider": null }),
+    };
+    consumer_value(json!({ "status": status }))
+}
+
+/// Decides the injection into the Pod with the Mask and MaskConsumer.
+fn injection(pod: Value, mask: Option<Value>, consumer: Option<Value>) -> Injection {
+    let pod: Pod = serde_json::from_value(pod).unwrap();
+    let mask: Option<Mask> = mask.map(|m| serde_json::from_value(m).unwrap());
+    let consumer: Option<MaskConsumer> = consumer.map(|c| serde_json::from_value(c).unwrap());
+    decide(&pod, "default", "mask-0", mask.as_ref(), consumer.as_ref())
+}
+
+/// Returns the Pod the sidecar was injected into.
+fn injected(injection: Injection) -> Pod {
+    match injection {
+        Injection::Inject(pod) => *pod,
+        injection => panic!("unexpected injection {:?}", injection),
+    }
+}
+
+#[test]
+fn active_mask_injected() {
+    let pod = injected(injection(
+        pod(json!({})),
+        Some(mask("Active")),
+        Some(injected_consumer(true)),
+    ));
+    let spec = pod.spec.unwrap();
+    let names: Vec<&str> = spec.containers.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, vec![VPN_CONTAINER_NAME, "app"]);
+    let sidecar = &spec.containers[0];
+    // The credentials are wired in with the version they're written for.
+    assert_eq!(sidecar.image.as_deref(), Some("qmcgaw/gluetun:v3.38.0"));
+    let env_from = sidecar.env_from.as_ref().unwrap();
+    assert_eq!(
+        env_from[0].secret_ref.as_ref().unwrap().name.as_deref(),
+        Some("mask-0-provider-uid")
+    );
+    assert_eq!(spec.init_containers, None);
+    assert_eq!(spec.volumes, None);
+}
+
+#[test]
+fn overrides_merged() {
+    let overrides = json!({
+        "imagePullPolicy": "Always",
+        "resources": { "limits": { "memory": "128Mi" } },
+    });
+    let overridden = injected(injection(
+        pod(json!({ "metadata": { "annotations": {
+            INJECT_OVERRIDES_ANNOTATION: overrides.to_string(),
+        } } })),
+        Some(mask("Active")),
+        Some(injected_consumer(true)),
+    ));
+    let sidecar = &overridden.spec.unwrap().containers[0];
+    assert_eq!(sidecar.image_pull_policy.as_deref(), Some("Always"));
+    assert!(sidecar.resources.is_some());
+    // The rest of the container is kept.
+    assert!(sidecar.security_context.is_some());
+
+    // Overrides that aren't a container reject the Pod.
+    let invalid = injection(
+        pod(json!({ "metadata": { "annotations": {
+            INJECT_OVERRIDES_ANNOTATION: "{\"env\": \"VPN_TYPE\"}",
+        } } })),
+        Some(mask("Active")),
+        Some(injected_consumer(true)),
+    );
+    assert!(matches!(invalid, Injection::Deny(_)));
+}
+
+#[test]
+fn not_ready_rejected_or_waited() {
+    // Rejected by default.
+    assert_eq!(
+        injection(
+            pod(json!({})),
+            Some(mask("Waiting")),
+            Some(injected_consumer(true))
+        ),
+        Injection::Deny(messages::inject_mask_not_ready(
+            "default",
+            "mask-0",
+            Some(MaskPhase::Waiting)
+        ))
+    );
+    assert_eq!(
+        injection(pod(json!({})), None, None),
+        Injection::Deny(messages::inject_mask_not_found("default", "mask-0"))
+    );
+
+    // Waited for once the name of the copy is known.
+    let wait = json!({ "metadata": { "annotations": { INJECT_NOT_READY_ANNOTATION: "wait" } } });
+    let pod_waiting = injected(injection(
+        pod(wait.clone()),
+        Some(mask("Pending")),
+        Some(injected_consumer(true)),
+    ));
+    let spec = pod_waiting.spec.unwrap();
+    let init = spec.init_containers.unwrap();
+    assert_eq!(init[0].name, SECRET_WAIT_CONTAINER_NAME);
+    let volume = &spec.volumes.unwrap()[0];
+    assert_eq!(volume.name, SECRET_WAIT_VOLUME_NAME);
+    let source = volume.secret.as_ref().unwrap();
+    assert_eq!(source.secret_name.as_deref(), Some("mask-0-provider-uid"));
+    assert_eq!(source.optional, Some(true));
+
+    // But not before.
+    assert_eq!(
+        injection(
+            pod(wait),
+            Some(mask("Waiting")),
+            Some(injected_consumer(false))
+        ),
+        Injection::Deny(messages::inject_mask_unassigned("default", "mask-0"))
+    );
+
+    // Unknown behaviors are rejected.
+    let unknown =
+        json!({ "metadata": { "annotations": { INJECT_NOT_READY_ANNOTATION: "later" } } });
+    assert!(matches!(
+        injection(
+            pod(unknown),
+            Some(mask("Active")),
+            Some(injected_consumer(true))
+        ),
+        Injection::Deny(_)
+    ));
+}
+
+#[test]
+fn sidecar_injected_once() {
+    let injected_pod = injected(injection(
+        pod(json!({})),
+        Some(mask("Active")),
+        Some(injected_consumer(true)),
+    ));
+    assert_eq!(
+        injected_pod.metadata.annotations.as_ref().unwrap()[INJECTED_ANNOTATION],
+        "mask-0"
+    );
+    assert_eq!(
+        injection(
+            serde_json::to_value(&injected_pod).unwrap(),
+            Some(mask("Active")),
+            Some(injected_consumer(true)),
+        ),
+        Injection::Skip
+    );
+
+    // A container of the Pod's own that happens to have the sidecar's
+    // name doesn't count as the sidecar, and can't be injected next to.
+    let own = pod(json!({ "spec": { "containers": [
+        { "name": VPN_CONTAINER_NAME, "image": "my-vpn:latest" },
+    ] } }));
+    assert_eq!(
+        injection(own, Some(mask("Active")), Some(injected_consumer(true))),
+        Injection::Deny(messages::inject_container_conflict(VPN_CONTAINER_NAME))
+    );
+}
+
+/// Returns the AdmissionReview of creating the Pod.
+fn admission_review(pod: Value) -> AdmissionReview<Pod> {
+    serde_json::from_value(json!({
+        "apiVersion": "admission.k8s.io/v1",
+        "kind": "AdmissionReview",
+        "request": {
+            "uid": "request-uid",
+            "kind": { "group": "", "version": "v1", "kind": "Pod" },
+            "resource": { "group": "", "version": "v1", "resource": "pods" },
+            "namespace": "default",
+            "operation": "CREATE",
+            "userInfo": {},
+            "object": pod,
+            "dryRun": false,
+        },
+    }))
+    .unwrap()
+}
+
+/// Returns the response of the webhook to the review of the Pod, with
+/// the objects in the cluster.
+async fn respond(pod: Value, objects: Vec<Value>) -> Value {
+    let (client, captured) = mock_cluster(objects);
+    let review = review(client, admission_review(pod)).await;
+    // Only Pods that ask for a sidecar cost a request.
+    let requests = captured.lock().unwrap().len();
+    let response = review["response"].clone();
+    assert_eq!(response["uid"], "request-uid");
+    json!({ "requests": requests, "response": response })
+}
+
+#[tokio::test]
+async fn admission_reviewed() {
+    let objects = vec![mask("Active"), injected_consumer(true)];
+
+    // The sidecar is added with a JSON patch.
+    let answer = respond(pod(json!({})), objects.clone()).await;
+    let response = &answer["response"];
+    assert_eq!(response["allowed"], true);
+    assert_eq!(response["patchType"], "JSONPatch");
+    // The API server expects the patch encoded as base64.
+    let encoded = response["patch"].as_str().unwrap();
+    let patch = openssl::base64::decode_block(encoded).unwrap();
+    let patch: json_patch::Patch = serde_json::from_slice(&patch).unwrap();
+    let mut patched = pod(json!({}));
+    json_patch::patch(&mut patched, &patch).unwrap();
+    let patched: Pod = serde_json::from_value(patched).unwrap();
+    let names: Vec<String> = patched
+        .spec
+        .unwrap()
+        .containers
+        .into_iter()
+        .map(|c| c.name)
+        .collect();
+    assert_eq!(names, vec![VPN_CONTAINER_NAME, "app"]);
+
+    // Pods that don't ask for one are admitted as they are.
+    let answer = respond(
+        pod(json!({ "metadata": { "annotations": null } })),
+        objects.clone(),
+    )
+    .await;
+    assert_eq!(answer["requests"], 0);
+    assert_eq!(answer["response"]["allowed"], true);
+    assert_eq!(answer["response"]["patch"], Value::Null);
+
+    // Pods that can't get credentials are rejected with the reason.
+    let answer = respond(
+        pod(json!({})),
+        vec![mask("Waiting"), injected_consumer(false)],
+    )
+    .await;
+    assert_eq!(answer["response"]["allowed"], false);
+    assert_eq!(
+        answer["response"]["status"]["message"],
+        messages::inject_mask_not_ready("default", "mask-0", Some(MaskPhase::Waiting))
+    );
+}
@@ -0,0 +1,153 @@
+use k8s_openapi::api::core::v1::Namespace;
+use kube::{client::Client, Api};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::{
+    mock::{consumer_value, mock_method_routes, status_failure},
+    util::*,
+};
+use crate::reservations::actions;
+
+/// How long everything may take to clear after the simultaneous deletion.
+const CLEARED_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Returns the MaskReservation of the `mask-0` MaskConsumer.
+fn reservation() -> MaskReservation {
+    serde_json::from_value(json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskReservation",
+        "metadata": { "name": "provider-0", "namespace": "providers" },
+        "spec": { "name": "mask-0", "namespace": "default", "uid": "consumer-uid", "slot": 0 },
+    }))
+    .unwrap()
+}
+
+/// Returns the `mask-0` MaskConsumer, with `metadata` merged into its
+/// metadata.
+fn deleted_consumer(metadata: Value) -> Value {
+    consumer_value(json!({ "metadata": metadata }))
+}
+
+/// Path of the `mask-0` MaskConsumer.
+const CONSUMER_PATH: &str = "/apis/vpn.beebs.dev/v1/namespaces/default/maskconsumers/mask-0";
+
+/// Returns whether the reservation's finalizer may be removed, and the
+/// number of DELETE requests issued for the MaskConsumer.
+async fn delete_consumer(routes: Vec<(&'static str, &'static str, u16, Value)>) -> (bool, usize) {
+    let (client, captured) = mock_method_routes(routes);
+    let gone = actions::delete_consumer(client, &reservation())
+        .await
+        .unwrap();
+    let deletes = captured
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|r| r.method == "DELETE")
+        .count();
+    (gone, deletes)
+}
+
+#[tokio::test]
+async fn terminating_consumer_is_awaited() {
+    // The MaskConsumer is already being deleted, e.g. with its namespace,
+    // so it's left to its own finalizer rather than deleted again.
+    let terminating = deleted_consumer(json!({ "deletionTimestamp": "2023-01-01T00:00:00Z" }));
+    assert_eq!(
+        delete_consumer(vec![("GET", CONSUMER_PATH, 200, terminating)]).await,
+        (false, 0)
+    );
+}
+
+#[tokio::test]
+async fn consumer_deletion_awaits_absence() {
+    // Issuing the delete isn't enough: the finalizer stays until the
+    // MaskConsumer is observed gone.
+    assert_eq!(
+        delete_consumer(vec![
+            ("GET", CONSUMER_PATH, 200, deleted_consumer(json!({}))),
+            ("DELETE", CONSUMER_PATH, 200, deleted_consumer(json!({}))),
+        ])
+        .await,
+        (false, 1)
+    );
+    assert_eq!(delete_consumer(vec![]).await, (true, 0));
+}
+
+#[tokio::test]
+async fn consumer_vanishing_during_delete_is_gone() {
+    assert_eq!(
+        delete_consumer(vec![
+            ("GET", CONSUMER_PATH, 200, deleted_consumer(json!({}))),
+            ("DELETE", CONSUMER_PATH, 404, status_failure(404)),
+        ])
+        .await,
+        (true, 1)
+    );
+}
+
+/// Waits for the namespace, and with it every Mask, MaskConsumer,
+/// MaskReservation and MaskProvider in it, to be gone.
+async fn wait_for_namespace_gone(client: Client, namespace: &str) -> Result<(), Error> {
+    let api: Api<Namespace> = Api::all(client);
+    let deadline = tokio::time::Instant::now() + CLEARED_TIMEOUT;
+    while tokio::time::Instant::now() < deadline {
+        if api.get_opt(namespace).await?.is_none() {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    Err(Error::Other(format!(
+        "namespace {} not cleared within {:?}",
+        namespace, CLEARED_TIMEOUT
+    )))
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn simultaneous_deletion_clears() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+    let provider_ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    create_test_provider(client.clone(), &namespace, &uid).await?;
+    provider_ready.await.unwrap()?;
+
+    // One Mask is assigned the only slot, the other waits for it.
+    let assigned = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(async move { wait_for_provider_assignment(client, &namespace, 0).await })
+    };
+    create_test_mask(client.clone(), &namespace, 0, &provider_name).await?;
+    create_test_mask(client.clone(), &namespace, 1, &provider_name).await?;
+    let assigned = assigned.await.unwrap()?;
+    wait_for_secret(client.clone(), assigned.secret, &namespace).await?;
+
+    // Delete the MaskProvider, the Masks and the namespace all at once.
+    let providers: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let masks: Api<Mask> = Api::namespaced(client.clone(), &namespace);
+    let dp = Default::default();
+    let mask_names = [format!("{}-0", MASK_NAME), format!("{}-1", MASK_NAME)];
+    let (provider, mask0, mask1, ns) = tokio::join!(
+        providers.delete(&provider_name, &dp),
+        masks.delete(&mask_names[0], &dp),
+        masks.delete(&mask_names[1], &dp),
+        delete_namespace(client.clone(), &namespace),
+    );
+    provider?;
+    mask0?;
+    mask1?;
+    ns?;
+
+    // None of the finalizers may wait on each other forever.
+    wait_for_namespace_gone(client, &namespace).await
+}
@@ -0,0 +1,104 @@
+use kube::{client::Client, Api};
+use std::time::Duration;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::util::*;
+
+/// Waits for the Mask to remember the slot it reserved.
+async fn wait_for_last_slot(client: Client, namespace: &str, index: usize) -> Result<usize, Error> {
+    let api: Api<Mask> = Api::namespaced(client, namespace);
+    let name = format!("{}-{}", MASK_NAME, index);
+    for _ in 0..60 {
+        if let Some(slot) = api.get(&name).await?.status.and_then(|s| s.last_slot) {
+            return Ok(slot);
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    Err(Error::Other(format!(
+        "Mask {} did not record its slot before timeout",
+        name
+    )))
+}
+
+/// Waits for a MaskConsumer that replaced the one with `old_uid`
+/// to be assigned a MaskProvider.
+async fn wait_for_reassignment(
+    client: Client,
+    namespace: &str,
+    index: usize,
+    old_uid: &str,
+) -> Result<AssignedProvider, Error> {
+    let api: Api<MaskConsumer> = Api::namespaced(client, namespace);
+    let name = format!("{}-{}", MASK_NAME, index);
+    for _ in 0..120 {
+        if let Some(consumer) = api.get_opt(&name).await? {
+            if consumer.metadata.uid.as_deref() != Some(old_uid) {
+                if let Some(provider) = consumer.status.and_then(|s| s.provider) {
+                    return Ok(provider);
+                }
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    Err(Error::Other(format!(
+        "MaskConsumer {} was not reassigned before timeout",
+        name
+    )))
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn slot_affinity() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+
+    // Create a MaskProvider with enough slots for the previous
+    // slot to be distinguishable from the first free one.
+    let provider_ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let mut provider = get_test_provider(client.clone(), &provider_name, &namespace).await?;
+    provider.spec.max_slots = 3;
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let provider = api.create(&Default::default(), &provider).await?;
+    create_test_provider_secret(client.clone(), &namespace, &provider).await?;
+    provider_ready.await.unwrap()?;
+
+    // Reserve the first two slots, one Mask at a time.
+    for index in 0..2 {
+        create_test_mask(client.clone(), &namespace, index, &provider_name).await?;
+        wait_for_mask_phase(client.clone(), &namespace, index, MaskPhase::Active).await?;
+    }
+    let last_slot = wait_for_last_slot(client.clone(), &namespace, 1).await?;
+    assert_eq!(last_slot, 1);
+
+    // Free up a lower slot so the normal scan would pick it.
+    delete_test_mask(client.clone(), &namespace, 0).await?;
+
+    // Delete the second Mask's MaskConsumer. The Mask will create
+    // a new one, which should reserve the same slot as before.
+    let consumer_api: Api<MaskConsumer> = Api::namespaced(client.clone(), &namespace);
+    let consumer_name = format!("{}-{}", MASK_NAME, 1);
+    let old_uid = consumer_api
+        .get(&consumer_name)
+        .await?
+        .metadata
+        .uid
+        .unwrap();
+    consumer_api
+        .delete(&consumer_name, &Default::default())
+        .await?;
+    let assigned = wait_for_reassignment(client.clone(), &namespace, 1, &old_uid).await?;
+    assert_eq!(assigned.uid, provider.metadata.uid.unwrap());
+    assert_eq!(assigned.slot, last_slot);
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,227 @@
+use chrono::{TimeZone, Utc};
+use k8s_openapi::{
+    api::core::v1::Secret,
+    apimachinery::pkg::apis::meta::v1::{OwnerReference, Time},
+};
+use kube::{api::ObjectMeta, client::Client, Api};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio::spawn;
+use vpn_types::*;
+
+use super::util::*;
+use crate::{
+    consumers::actions::{is_consumer_secret, secret_name},
+    providers::suffix::find_suffix_collision,
+};
+
+/// Suffix used for the test MaskProvider's copied Secrets.
+const SUFFIX: &str = "stable";
+
+/// Returns a MaskProvider with the given stable secret suffix and allowlist.
+fn test_provider(
+    uid: &str,
+    created: i64,
+    suffix: Option<&str>,
+    namespaces: Option<&[&str]>,
+) -> MaskProvider {
+    MaskProvider {
+        metadata: ObjectMeta {
+            name: Some(format!("provider-{}", uid)),
+            namespace: Some("default".to_owned()),
+            uid: Some(uid.to_owned()),
+            creation_timestamp: Some(Time(Utc.timestamp_opt(created, 0).unwrap())),
+            ..Default::default()
+        },
+        spec: MaskProviderSpec {
+            stable_secret_suffix: suffix.map(|s| s.to_owned()),
+            namespaces: namespaces.map(|ns| ns.iter().map(|n| n.to_string()).collect()),
+            ..Default::default()
+        },
+        status: None,
+    }
+}
+
+/// Returns a MaskConsumer assigned to the MaskProvider with the given UID.
+fn test_consumer(provider_uid: &str) -> MaskConsumer {
+    MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some("test-mask".to_owned()),
+            namespace: Some("default".to_owned()),
+            uid: Some("consumer-uid".to_owned()),
+            ..Default::default()
+        },
+        status: Some(MaskConsumerStatus {
+            provider: Some(AssignedProvider {
+                uid: provider_uid.to_owned(),
+                secret: "test-mask-stable".to_owned(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Returns a copied Secret labeled with the provider UID and owned by the consumer UID.
+fn test_secret(provider_uid: &str, owner_uid: &str) -> Secret {
+    Secret {
+        metadata: ObjectMeta {
+            name: Some("test-mask-stable".to_owned()),
+            labels: Some(BTreeMap::from([(
+                "vpn.beebs.dev/owner".to_owned(),
+                provider_uid.to_owned(),
+            )])),
+            owner_references: Some(vec![OwnerReference {
+                uid: owner_uid.to_owned(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[test]
+fn secret_name_uses_suffix() {
+    let provider = test_provider("a1b2", 0, None, None);
+    assert_eq!(secret_name("test-mask", &provider), "test-mask-a1b2");
+    let provider = test_provider("a1b2", 0, Some(SUFFIX), None);
+    assert_eq!(secret_name("test-mask", &provider), "test-mask-stable");
+}
+
+#[test]
+fn secret_ownership_is_checked() {
+    let consumer = test_consumer("new-provider");
+    assert!(is_consumer_secret(
+        &test_secret("new-provider", "consumer-uid"),
+        &consumer
+    ));
+    // A copy of another MaskProvider's credentials with the same name.
+    assert!(!is_consumer_secret(
+        &test_secret("old-provider", "consumer-uid"),
+        &consumer
+    ));
+    // A copy left behind by a previous MaskConsumer.
+    assert!(!is_consumer_secret(
+        &test_secret("new-provider", "old-consumer-uid"),
+        &consumer
+    ));
+}
+
+#[test]
+fn suffix_collisions() {
+    let older = test_provider("a", 1, Some(SUFFIX), Some(&["team-a", "team-b"]));
+    let newer = test_provider("b", 2, Some(SUFFIX), Some(&["team-b"]));
+    let providers = vec![Arc::new(older.clone()), Arc::new(newer.clone())];
+
+    // Only the newer MaskProvider is rejected.
+    assert_eq!(
+        find_suffix_collision(&newer, &providers).and_then(|p| p.metadata.uid.as_deref()),
+        Some("a")
+    );
+    assert!(find_suffix_collision(&older, &providers).is_none());
+
+    // MaskProviders that can't be assigned to the same namespaces don't collide.
+    let disjoint = test_provider("c", 3, Some(SUFFIX), Some(&["team-c"]));
+    assert!(find_suffix_collision(&disjoint, &providers).is_none());
+
+    // A MaskProvider without an allowlist overlaps with every other.
+    let unrestricted = test_provider("d", 4, Some(SUFFIX), None);
+    assert!(find_suffix_collision(&unrestricted, &providers).is_some());
+
+    // Different suffixes never collide.
+    let other = test_provider("e", 5, Some("other"), None);
+    assert!(find_suffix_collision(&other, &providers).is_none());
+
+    // Recreating a MaskProvider doesn't collide with the one being deleted.
+    let mut deleting = older;
+    deleting.metadata.deletion_timestamp = Some(Time(Utc::now()));
+    assert!(
+        find_suffix_collision(&newer, &[Arc::new(deleting), Arc::new(newer.clone())]).is_none()
+    );
+}
+
+/// Waits for the Secret with the given name to be a copy made
+/// for the MaskProvider with the given UID.
+async fn wait_for_secret_provider(
+    client: Client,
+    namespace: &str,
+    name: &str,
+    provider_uid: &str,
+) -> Result<Secret, Error> {
+    let api: Api<Secret> = Api::namespaced(client, namespace);
+    for _ in 0..120 {
+        if let Some(secret) = api.get_opt(name).await? {
+            if secret
+                .metadata
+                .labels
+                .as_ref()
+                .and_then(|l| l.get("vpn.beebs.dev/owner"))
+                .is_some_and(|uid| uid == provider_uid)
+            {
+                return Ok(secret);
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    Err(Error::Other(format!(
+        "Secret {} was not copied for MaskProvider {} before timeout",
+        name, provider_uid
+    )))
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn stable_secret_suffix() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+
+    // Create a MaskProvider with a stable secret suffix.
+    let provider_ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let mut spec = get_test_provider(client.clone(), &provider_name, &namespace).await?;
+    spec.spec.stable_secret_suffix = Some(SUFFIX.to_owned());
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let provider = api.create(&Default::default(), &spec).await?;
+    create_test_provider_secret(client.clone(), &namespace, &provider).await?;
+    provider_ready.await.unwrap()?;
+
+    // The Mask's copy of the credentials is named after the suffix.
+    create_test_mask(client.clone(), &namespace, 0, &provider_name).await?;
+    let assigned = wait_for_provider_assignment(client.clone(), &namespace, 0).await?;
+    let name = format!("{}-0-{}", MASK_NAME, SUFFIX);
+    assert_eq!(assigned.secret, name);
+    let before = wait_for_secret_provider(
+        client.clone(),
+        &namespace,
+        &name,
+        provider.metadata.uid.as_deref().unwrap(),
+    )
+    .await?;
+
+    // Recreate the MaskProvider with identical credentials, as a GitOps
+    // re-apply would. Its UID changes, but the Secret's name doesn't.
+    // The Mask is still assigned a slot, so deletion has to be forced.
+    force_delete_test_provider(client.clone(), &namespace, &provider_name).await?;
+    let recreated = api.create(&Default::default(), &spec).await?;
+    assert_ne!(recreated.metadata.uid, provider.metadata.uid);
+    let after = wait_for_secret_provider(
+        client.clone(),
+        &namespace,
+        &name,
+        recreated.metadata.uid.as_deref().unwrap(),
+    )
+    .await?;
+    assert_eq!(after.data, before.data);
+    wait_for_mask_phase(client.clone(), &namespace, 0, MaskPhase::Active).await?;
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use vpn_types::*;
+
+use super::{
+    mock::*,
+    util::{assigned_provider, mask_consumer},
+};
+use crate::util::{messages, patch::patch_status, Error};
+
+/// Returns a Waiting MaskConsumer whose status is at the given revision.
+fn revised_consumer(status_revision: Option<u64>) -> MaskConsumer {
+    let mut consumer = mask_consumer("test-consumer", "default", "consumer-uid");
+    consumer.status = Some(MaskConsumerStatus {
+        phase: Some(MaskConsumerPhase::Waiting),
+        message: Some(messages::WAITING.to_owned()),
+        status_revision,
+        ..Default::default()
+    });
+    consumer
+}
+
+/// Returns the MaskProvider assigned to the consumer by the first update.
+fn assignment() -> AssignedProvider {
+    assigned_provider(
+        "test-provider",
+        "default",
+        "provider-uid",
+        "test-consumer-provider-uid",
+    )
+}
+
+/// Assigns a MaskProvider, as a reconcile that reserved a slot would.
+async fn assign(client: kube::Client, instance: &MaskConsumer) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.set_assigned(assignment(), Default::default(), "reserved slot 0");
+    })
+    .await?;
+    Ok(())
+}
+
+/// Reports no MaskProviders, as a reconcile that found none would.
+async fn no_providers(client: kube::Client, instance: &MaskConsumer) -> Result<(), Error> {
+    patch_status(client, instance, |status| {
+        status.set_phase(
+            MaskConsumerPhase::ErrNoProviders,
+            messages::ERR_NO_PROVIDERS,
+        );
+    })
+    .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn stale_update_refused() {
+    // Both reconciles start from the same snapshot, and the
+    // assignment is written first.
+    let snapshot = revised_consumer(Some(3));
+    let (client, _, stored) = mock_store(serde_json::to_value(&snapshot).unwrap());
+    assign(client.clone(), &snapshot).await.unwrap();
+    let err = no_providers(client, &snapshot).await.unwrap_err();
+    assert!(matches!(err, Error::StaleStatus(ref name) if name == "default/test-consumer"));
+
+    // The assignment is left intact, along with its message.
+    let stored: MaskConsumer = serde_json::from_value(stored.lock().unwrap().clone()).unwrap();
+    let status = stored.status.unwrap();
+    assert_eq!(status.status_revision, Some(4));
+    assert_eq!(status.phase, Some(MaskConsumerPhase::Waiting));
+    assert_eq!(status.message.as_deref(), Some("reserved slot 0"));
+    assert_eq!(status.provider, Some(assignment()));
+}
+
+#[tokio::test]
+async fn conflicting_updates_stay_consistent() {
+    for status_revision in [None, Some(7)] {
+        // Fire both updates from the same snapshot at once. Whichever
+        // lands first wins, and the other is refused as a whole.
+        let snapshot = revised_consumer(status_revision);
+        let (client, captured, stored) = mock_store(serde_json::to_value(&snapshot).unwrap());
+        let (assigned, errored) = tokio::join!(
+            assign(client.clone(), &snapshot),
+            no_providers(client, &snapshot),
+        );
+        assert!(assigned.is_ok() != errored.is_ok());
+        assert!(matches!(
+            assigned.err().or(errored.err()),
+            Some(Error::StaleStatus(_))
+        ));
+        assert_eq!(captured.lock().unwrap().len(), 2);
+
+        // The phase and message come from the same update.
+        let stored: MaskConsumer = serde_json::from_value(stored.lock().unwrap().clone()).unwrap();
+        let status = stored.status.unwrap();
+        assert_eq!(
+            status.status_revision,
+            Some(status_revision.unwrap_or(0) + 1)
+        );
+        match status.phase {
+            Some(MaskConsumerPhase::Waiting) => {
+                assert_eq!(status.message.as_deref(), Some("reserved slot 0"));
+                assert_eq!(status.provider, Some(assignment()));
+            }
+            Some(MaskConsumerPhase::ErrNoProviders) => {
+                assert_eq!(status.message.as_deref(), Some(messages::ERR_NO_PROVIDERS));
+                assert_eq!(status.provider, None);
+            }
+            phase => panic!("unexpected phase {:?}", phase),
+        }
+    }
+}
+
+#[tokio::test]
+async fn first_status_update_is_conditional() {
+    // A resource without a status object yet is only
+    // patched if no other update has created one.
+    let mut snapshot = revised_consumer(None);
+    snapshot.status = None;
+    let (client, captured, _) = mock_store(serde_json::to_value(&snapshot).unwrap());
+    assign(client.clone(), &snapshot).await.unwrap();
+    let err = no_providers(client, &snapshot).await.unwrap_err();
+    assert!(matches!(err, Error::StaleStatus(_)));
+    let captured = captured.lock().unwrap();
+    assert_eq!(
+        captured[0].body[0],
+        serde_json::json!({ "op": "test", "path": "/status", "value": null })
+    );
+}
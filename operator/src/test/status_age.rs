@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+use crate::util::{status_age, PROBE_INTERVAL};
+
+#[test]
+fn missing_last_updated_is_stale() {
+    // e.g. after `kubectl edit` wiped the status object.
+    assert_eq!(status_age(None), Duration::MAX);
+}
+
+#[test]
+fn malformed_last_updated_is_stale() {
+    assert_eq!(status_age(Some("")), Duration::MAX);
+    assert_eq!(status_age(Some("yesterday")), Duration::MAX);
+    assert_eq!(status_age(Some("2023-13-45T99:00:00Z")), Duration::MAX);
+}
+
+#[test]
+fn future_last_updated_is_fresh() {
+    // A replica whose clock runs ahead wrote the status, which mustn't
+    // make every reconciliation rewrite it.
+    let future = chrono::Utc::now() + chrono::Duration::hours(1);
+    assert_eq!(status_age(Some(&future.to_rfc3339())), Duration::ZERO);
+}
+
+#[test]
+fn recent_last_updated_is_fresh() {
+    let recent = chrono::Utc::now() - chrono::Duration::seconds(1);
+    let age = status_age(Some(&recent.to_rfc3339()));
+    assert!(age >= Duration::from_secs(1));
+    assert!(age < PROBE_INTERVAL);
+
+    let old = chrono::Utc::now() - chrono::Duration::minutes(5);
+    assert!(status_age(Some(&old.to_rfc3339())) > PROBE_INTERVAL);
+}
@@ -0,0 +1,266 @@
+use chrono::{DateTime, Utc};
+use kube::api::ObjectMeta;
+use std::time::Duration;
+use vpn_types::*;
+
+use super::{
+    render_snapshots::assert_golden,
+    util::{mask_consumer, mask_provider},
+};
+use crate::status::{report::format_age, OutputFormat, ProviderRef, StatusFilter, StatusReport};
+
+/// Time the reports are generated at.
+const NOW: &str = "2023-03-01T12:00:00Z";
+
+/// Returns metadata for a resource in the namespace.
+fn meta(namespace: &str, name: &str) -> ObjectMeta {
+    ObjectMeta {
+        name: Some(name.to_owned()),
+        namespace: Some(namespace.to_owned()),
+        ..Default::default()
+    }
+}
+
+/// Returns a MaskProvider with the given status.
+fn reported_provider(name: &str, status: MaskProviderStatus) -> MaskProvider {
+    let mut provider = mask_provider(name, "vpn", &format!("{}-uid", name));
+    provider.spec.max_slots = 4;
+    provider.status = Some(status);
+    provider
+}
+
+/// Returns a Mask in the given phase.
+fn mask(namespace: &str, name: &str, phase: MaskPhase, message: &str) -> Mask {
+    Mask {
+        metadata: meta(namespace, name),
+        status: Some(MaskStatus {
+            phase: Some(phase),
+            message: Some(message.to_owned()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Returns a MaskConsumer in the given phase, assigned the MaskProvider if any.
+fn reported_consumer(
+    namespace: &str,
+    name: &str,
+    phase: MaskConsumerPhase,
+    provider: Option<&str>,
+) -> MaskConsumer {
+    let mut consumer = mask_consumer(name, namespace, &format!("{}-uid", name));
+    consumer.status = Some(MaskConsumerStatus {
+        phase: Some(phase),
+        message: Some(phase.to_string()),
+        provider: provider.map(|name| AssignedProvider {
+            name: name.to_owned(),
+            namespace: "vpn".to_owned(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    consumer
+}
+
+/// Fabricated fleet of two working MaskProviders and one that failed
+/// verification, with Masks in two namespaces.
+fn fleet() -> (Vec<MaskProvider>, Vec<Mask>, Vec<MaskConsumer>) {
+    let providers = vec![
+        reported_provider(
+            "us-east",
+            MaskProviderStatus {
+                phase: Some(MaskProviderPhase::Active),
+                active_slots: Some(2),
+                last_verified: Some("2023-03-01T08:48:00Z".to_owned()),
+                warnings: Some(vec!["Namespace 'staging' does not exist.".to_owned()]),
+                ..Default::default()
+            },
+        ),
+        reported_provider(
+            "eu-west",
+            MaskProviderStatus {
+                phase: Some(MaskProviderPhase::Ready),
+                active_slots: Some(0),
+                last_verified: Some("2023-02-27T10:00:00Z".to_owned()),
+                ..Default::default()
+            },
+        ),
+        reported_provider(
+            "broken",
+            MaskProviderStatus {
+                phase: Some(MaskProviderPhase::ErrVerifyFailed),
+                message: Some("Verification Pod exited with code 1.".to_owned()),
+                ..Default::default()
+            },
+        ),
+    ];
+    let masks = vec![
+        mask("default", "web", MaskPhase::Active, "Active"),
+        mask("default", "worker", MaskPhase::Active, "Active"),
+        mask(
+            "default",
+            "batch",
+            MaskPhase::Waiting,
+            "Waiting for a slot.",
+        ),
+        mask(
+            "scraper",
+            "crawler",
+            MaskPhase::ErrNoProviders,
+            "No MaskProviders are available.",
+        ),
+    ];
+    let consumers = vec![
+        reported_consumer("default", "web", MaskConsumerPhase::Active, Some("us-east")),
+        reported_consumer(
+            "default",
+            "worker",
+            MaskConsumerPhase::Active,
+            Some("us-east"),
+        ),
+        reported_consumer("default", "batch", MaskConsumerPhase::Waiting, None),
+        reported_consumer(
+            "scraper",
+            "crawler",
+            MaskConsumerPhase::ErrNoProviders,
+            None,
+        ),
+    ];
+    (providers, masks, consumers)
+}
+
+/// Returns the report of the fabricated fleet.
+fn report(filter: StatusFilter) -> StatusReport {
+    let (providers, masks, consumers) = fleet();
+    StatusReport::build(
+        &providers,
+        &masks,
+        &consumers,
+        &filter,
+        NOW.parse::<DateTime<Utc>>().unwrap(),
+    )
+}
+
+#[test]
+fn status_table() {
+    assert_golden("status_table.txt", &report(Default::default()).table());
+}
+
+#[test]
+fn status_json() {
+    let json = serde_json::to_string_pretty(&report(Default::default())).unwrap();
+    assert_golden("status.json", &format!("{}\n", json));
+}
+
+#[test]
+fn status_by_namespace() {
+    let filter = StatusFilter {
+        namespace: Some("scraper".to_owned()),
+        ..Default::default()
+    };
+    assert_golden("status_namespace.txt", &report(filter).table());
+}
+
+#[test]
+fn status_by_provider() {
+    let filter = StatusFilter {
+        provider: Some("us-east".parse().unwrap()),
+        ..Default::default()
+    };
+    assert_golden("status_provider.txt", &report(filter).table());
+}
+
+#[test]
+fn status_by_provider_namespace() {
+    // Another MaskProvider named us-east, in another namespace, with a
+    // Mask of its own.
+    let (mut providers, mut masks, mut consumers) = fleet();
+    let mut other = reported_provider(
+        "us-east",
+        MaskProviderStatus {
+            phase: Some(MaskProviderPhase::Active),
+            active_slots: Some(1),
+            ..Default::default()
+        },
+    );
+    other.metadata.namespace = Some("backup".to_owned());
+    providers.push(other);
+    masks.push(mask("default", "cron", MaskPhase::Active, "Active"));
+    let mut consumer = reported_consumer(
+        "default",
+        "cron",
+        MaskConsumerPhase::Active,
+        Some("us-east"),
+    );
+    consumer
+        .status
+        .as_mut()
+        .unwrap()
+        .provider
+        .as_mut()
+        .unwrap()
+        .namespace = "backup".to_owned();
+    consumers.push(consumer);
+    let report = |provider: &str| {
+        let filter = StatusFilter {
+            provider: Some(provider.parse().unwrap()),
+            ..Default::default()
+        };
+        let now = NOW.parse::<DateTime<Utc>>().unwrap();
+        StatusReport::build(&providers, &masks, &consumers, &filter, now)
+    };
+
+    // Naming the namespace leaves out the other MaskProvider and its Mask.
+    assert_golden("status_provider.txt", &report("vpn/us-east").table());
+    let other = report("backup/us-east");
+    assert_eq!(other.providers.len(), 1);
+    assert_eq!(other.providers[0].namespace, "backup");
+    assert_eq!(other.masks["default"]["Active"], 1);
+
+    // Without one, both match, unless the namespace is filtered.
+    let both = report("us-east");
+    assert_eq!(both.providers.len(), 2);
+    assert_eq!(both.masks["default"]["Active"], 3);
+    let filter = StatusFilter {
+        namespace: Some("backup".to_owned()),
+        provider: Some("us-east".parse().unwrap()),
+    };
+    let now = NOW.parse::<DateTime<Utc>>().unwrap();
+    let filtered = StatusReport::build(&providers, &masks, &consumers, &filter, now);
+    assert_eq!(filtered.providers.len(), 1);
+    assert!(filtered.masks.is_empty());
+}
+
+#[test]
+fn empty_fleet() {
+    let report = StatusReport::build(&[], &[], &[], &Default::default(), Utc::now());
+    assert_eq!(
+        report.table(),
+        "MaskProviders: none\n\nMasks: none\n\nErrors: none\n"
+    );
+}
+
+#[test]
+fn ages_and_formats() {
+    assert_eq!(format_age(Duration::ZERO), "0s");
+    assert_eq!(format_age(Duration::from_secs(42)), "42s");
+    assert_eq!(
+        format_age(Duration::from_secs(3 * 3600 + 12 * 60 + 5)),
+        "3h12m"
+    );
+    assert_eq!(format_age(Duration::from_secs(2 * 86400 + 5)), "2d");
+    assert_eq!("json".parse(), Ok(OutputFormat::Json));
+    assert_eq!("table".parse(), Ok(OutputFormat::Table));
+    assert!("yaml".parse::<OutputFormat>().is_err());
+    assert_eq!(
+        "vpn/us-east".parse(),
+        Ok(ProviderRef {
+            namespace: Some("vpn".to_owned()),
+            name: "us-east".to_owned(),
+        })
+    );
+    assert_eq!("us-east".parse::<ProviderRef>().unwrap().namespace, None);
+    assert!("vpn/".parse::<ProviderRef>().is_err());
+    assert!("/us-east".parse::<ProviderRef>().is_err());
+}
@@ -0,0 +1,219 @@
+use kube::{api::ObjectMeta, runtime::watcher::Event};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+use vpn_types::*;
+
+use super::{
+    mock::*,
+    util::{mask_consumer, mask_provider},
+};
+use crate::{
+    export::{
+        aggregate::{Aggregator, Change, Debounce},
+        sink::FLEET_STATUS_KEY,
+        Sink,
+    },
+    util::config::ConfigMapRef,
+};
+
+/// Returns metadata for a resource in the namespace.
+fn meta(namespace: &str, name: &str) -> ObjectMeta {
+    ObjectMeta {
+        name: Some(name.to_owned()),
+        namespace: Some(namespace.to_owned()),
+        ..Default::default()
+    }
+}
+
+/// Returns a MaskProvider in the given phase with slots in use.
+fn exported_provider(name: &str, phase: MaskProviderPhase, active_slots: usize) -> MaskProvider {
+    let mut provider = mask_provider(name, "vpn", &format!("{}-uid", name));
+    provider.spec.max_slots = 4;
+    provider.status = Some(MaskProviderStatus {
+        phase: Some(phase),
+        active_slots: Some(active_slots),
+        ..Default::default()
+    });
+    provider
+}
+
+/// Returns a Mask in the given phase.
+fn mask(name: &str, phase: Option<MaskPhase>) -> Mask {
+    Mask {
+        metadata: meta("default", name),
+        status: phase.map(|phase| MaskStatus {
+            phase: Some(phase),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Returns a MaskConsumer in the given phase.
+fn exported_consumer(name: &str, phase: MaskConsumerPhase) -> MaskConsumer {
+    let mut consumer = mask_consumer(name, "default", &format!("{}-uid", name));
+    consumer.status = Some(MaskConsumerStatus {
+        phase: Some(phase),
+        ..Default::default()
+    });
+    consumer
+}
+
+/// Returns an aggregator that has received the initial
+/// list of each kind, with one MaskProvider and one Mask.
+fn synced_aggregator() -> Aggregator {
+    let mut aggregator = Aggregator::default();
+    aggregator.apply(Change::Provider(Box::new(Event::Restarted(vec![
+        exported_provider("us", MaskProviderPhase::Ready, 0),
+    ]))));
+    aggregator.apply(Change::Mask(Box::new(Event::Restarted(vec![mask(
+        "first",
+        Some(MaskPhase::Pending),
+    )]))));
+    aggregator.apply(Change::Consumer(Box::new(Event::Restarted(vec![]))));
+    assert!(!aggregator.synced());
+    aggregator.apply(Change::Reservation(Box::new(Event::Restarted(vec![]))));
+    assert!(aggregator.synced());
+    aggregator
+}
+
+#[test]
+fn aggregate_converges_after_burst() {
+    let mut aggregator = synced_aggregator();
+    // A burst of events as Masks come and go and the
+    // MaskProvider's slots are reserved and released.
+    for i in 0..50 {
+        aggregator.apply(Change::Provider(Box::new(Event::Applied(
+            exported_provider("us", MaskProviderPhase::Active, i % 4 + 1),
+        ))));
+        aggregator.apply(Change::Mask(Box::new(Event::Applied(mask(
+            &format!("mask-{}", i),
+            Some(MaskPhase::Waiting),
+        )))));
+        aggregator.apply(Change::Consumer(Box::new(Event::Applied(
+            exported_consumer(&format!("mask-{}", i), MaskConsumerPhase::Waiting),
+        ))));
+        if i % 2 == 1 {
+            aggregator.apply(Change::Mask(Box::new(Event::Deleted(mask(
+                &format!("mask-{}", i),
+                None,
+            )))));
+            aggregator.apply(Change::Consumer(Box::new(Event::Deleted(
+                exported_consumer(&format!("mask-{}", i), MaskConsumerPhase::Waiting),
+            ))));
+        }
+    }
+    aggregator.apply(Change::Provider(Box::new(Event::Applied(
+        exported_provider("us", MaskProviderPhase::Active, 2),
+    ))));
+    aggregator.apply(Change::Mask(Box::new(Event::Applied(mask("first", None)))));
+    aggregator.apply(Change::Consumer(Box::new(Event::Applied(
+        exported_consumer("mask-0", MaskConsumerPhase::Active),
+    ))));
+
+    let status = aggregator.status(Some("east"), "now".to_owned());
+    assert_eq!(status.version, FLEET_STATUS_VERSION);
+    assert_eq!(status.cluster.as_deref(), Some("east"));
+    assert_eq!(
+        status.providers,
+        vec![ProviderSummary {
+            namespace: "vpn".to_owned(),
+            name: "us".to_owned(),
+            phase: Some(MaskProviderPhase::Active),
+            active_slots: Some(2),
+            max_slots: 4,
+        }]
+    );
+    // The remaining Masks are sorted by name, and the
+    // one whose status was cleared has no phase.
+    assert_eq!(status.masks.len(), 26);
+    assert_eq!(status.masks[0].name, "first");
+    assert_eq!(status.masks[0].phase, None);
+    assert_eq!(status.masks[1].name, "mask-0");
+    assert!(status.masks[1..]
+        .iter()
+        .all(|m| m.phase == Some(MaskPhase::Waiting)));
+    assert_eq!(
+        status.consumers,
+        BTreeMap::from([("Active".to_owned(), 1), ("Waiting".to_owned(), 24)])
+    );
+    assert!(status.reservations.is_empty());
+
+    // Relisting replaces everything that was aggregated before.
+    aggregator.apply(Change::Mask(Box::new(Event::Restarted(vec![]))));
+    assert!(aggregator.status(None, "now".to_owned()).masks.is_empty());
+}
+
+#[test]
+fn debounce_respects_interval() {
+    let interval = Duration::from_secs(10);
+    let start = Instant::now();
+    let mut debounce = Debounce::new(interval);
+    assert_eq!(debounce.deadline(start), None);
+
+    // The first change is written right away.
+    debounce.mark_dirty();
+    assert_eq!(debounce.deadline(start), Some(start));
+    debounce.flushed(start);
+    assert_eq!(debounce.deadline(start), None);
+
+    // A burst of changes soon after is written once the interval
+    // has elapsed since the previous write, and not before.
+    for i in 0..100 {
+        let now = start + Duration::from_millis(i * 10);
+        debounce.mark_dirty();
+        assert_eq!(debounce.deadline(now), Some(start + interval));
+    }
+    debounce.flushed(start + interval);
+    assert_eq!(debounce.deadline(start + interval), None);
+
+    // A change long after the previous write isn't delayed.
+    let later = start + interval * 5;
+    debounce.mark_dirty();
+    assert_eq!(debounce.deadline(later), Some(later));
+}
+
+#[tokio::test]
+async fn status_written_to_file() {
+    let dir = std::env::temp_dir().join(format!("vpno-export-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir(&dir).unwrap();
+    let path = dir.join("fleet.json");
+    let status = synced_aggregator().status(Some("east"), "now".to_owned());
+    let (client, captured) = mock_routes(vec![]);
+    Sink::File(path.clone())
+        .write(client, &status)
+        .await
+        .unwrap();
+    let written: FleetStatus = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    assert_eq!(written, status);
+    // Only the document remains, and the cluster wasn't touched.
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    assert!(captured.lock().unwrap().is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn status_written_to_config_map() {
+    let status = synced_aggregator().status(None, "now".to_owned());
+    let (client, captured) = mock_client(serde_json::json!({
+        "apiVersion": "v1",
+        "kind": "ConfigMap",
+        "metadata": { "name": "fleet-status", "namespace": "vpn" },
+    }));
+    let sink = Sink::ConfigMap(ConfigMapRef {
+        namespace: "vpn".to_owned(),
+        name: "fleet-status".to_owned(),
+    });
+    sink.write(client, &status).await.unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 1);
+    assert_eq!(captured[0].method, "PATCH");
+    assert!(captured[0]
+        .path
+        .starts_with("/api/v1/namespaces/vpn/configmaps/fleet-status?"));
+    let document = captured[0].body["data"][FLEET_STATUS_KEY].as_str().unwrap();
+    let written: FleetStatus = serde_json::from_str(document).unwrap();
+    assert_eq!(written, status);
+}
@@ -0,0 +1,141 @@
+use kube::api::ObjectMeta;
+use serde_json::json;
+use std::time::Duration;
+use vpn_types::*;
+
+use super::mock::{consumer_value, decide};
+use crate::{
+    reservations::reconcile::{determine_action, ReservationAction},
+    util::{finalizer::FINALIZER_NAME, needs_refresh, PROBE_INTERVAL},
+};
+
+/// Default of `--status-freshness-interval`.
+const FRESHNESS: Duration = Duration::from_secs(600);
+
+/// Returns an Active MaskReservation status last updated `age` ago.
+fn status(age: Duration) -> MaskReservationStatus {
+    let last_updated = chrono::Utc::now() - chrono::Duration::from_std(age).unwrap();
+    MaskReservationStatus {
+        phase: Some(MaskReservationPhase::Active),
+        message: Some("the MaskConsumer exists".to_owned()),
+        last_updated: Some(last_updated.to_rfc3339()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn unchanged_status_not_rewritten_within_window() {
+    // A reconcile one probe interval after the last write is a NoOp.
+    assert!(!needs_refresh(&status(PROBE_INTERVAL), FRESHNESS));
+    assert!(!needs_refresh(&status(Duration::from_secs(599)), FRESHNESS));
+
+    // It is rewritten once the window has passed.
+    assert!(needs_refresh(&status(Duration::from_secs(601)), FRESHNESS));
+
+    // A missing timestamp is always stale.
+    let missing = MaskReservationStatus {
+        last_updated: None,
+        ..status(Duration::ZERO)
+    };
+    assert!(needs_refresh(&missing, FRESHNESS));
+
+    // Nor is one written by a replica whose clock runs ahead.
+    let ahead = MaskReservationStatus {
+        last_updated: Some((chrono::Utc::now() + chrono::Duration::seconds(30)).to_rfc3339()),
+        ..status(Duration::ZERO)
+    };
+    assert!(!needs_refresh(&ahead, FRESHNESS));
+}
+
+#[test]
+fn resolved_error_is_cleared() {
+    // A write clears the last error, so a recovered resource is
+    // rewritten right away instead of showing the error for minutes.
+    let failed = MaskReservationStatus {
+        last_error: Some(LastError {
+            action: "Active".to_owned(),
+            message: "ApiError: Internal error".to_owned(),
+            at: chrono::Utc::now().to_rfc3339(),
+        }),
+        ..status(Duration::from_secs(1))
+    };
+    assert!(needs_refresh(&failed, FRESHNESS));
+}
+
+/// Returns the number of status writes the MaskReservation controller
+/// decides on for a reservation that is reconciled every probe interval
+/// for an hour while its MaskConsumer exists.
+async fn writes_per_hour(freshness: Duration) -> usize {
+    let objects = [consumer_value(
+        json!({ "metadata": { "uid": "consumer-uid" } }),
+    )];
+    let mut writes = 0;
+    let mut since_write = Duration::ZERO;
+    for _ in 0..(3600 / PROBE_INTERVAL.as_secs()) {
+        since_write += PROBE_INTERVAL;
+        let instance = MaskReservation {
+            metadata: ObjectMeta {
+                name: Some("reservation".to_owned()),
+                namespace: Some("default".to_owned()),
+                finalizers: Some(vec![FINALIZER_NAME.to_owned()]),
+                ..Default::default()
+            },
+            spec: MaskReservationSpec {
+                name: "mask-0".to_owned(),
+                namespace: "default".to_owned(),
+                uid: "consumer-uid".to_owned(),
+                slot: None,
+            },
+            status: Some(status(since_write)),
+        };
+        let action =
+            decide(&objects, |client| {
+                let instance = instance.clone();
+                async move {
+                    determine_action(client, "reservation", "default", &instance, freshness).await
+                }
+            })
+            .await;
+        if action != ReservationAction::NoOp {
+            writes += 1;
+            since_write = Duration::ZERO;
+        }
+    }
+    writes
+}
+
+#[tokio::test]
+async fn steady_state_writes_reduced() {
+    // Refreshing every probe interval writes on every reconcile.
+    assert_eq!(writes_per_hour(PROBE_INTERVAL).await, 300);
+    assert_eq!(writes_per_hour(FRESHNESS).await, 6);
+}
+
+#[cfg(feature = "metrics")]
+#[tokio::test]
+async fn status_patches_counted() {
+    use super::mock::*;
+    use crate::util::{metrics::STATUS_PATCH_COUNTER, patch::patch_status};
+    use kube::api::ObjectMeta;
+
+    let reservation = MaskReservation {
+        metadata: ObjectMeta {
+            name: Some("test-provider-0".to_owned()),
+            namespace: Some("default".to_owned()),
+            ..Default::default()
+        },
+        spec: Default::default(),
+        status: Some(status(FRESHNESS * 2)),
+    };
+    let (client, captured) = mock_client(serde_json::to_value(&reservation).unwrap());
+    let counter = STATUS_PATCH_COUNTER.with_label_values(&["MaskReservation"]);
+    let before = counter.get();
+    patch_status(client, &reservation, |status| {
+        status.phase = Some(MaskReservationPhase::Active);
+    })
+    .await
+    .unwrap();
+    assert_eq!(captured.lock().unwrap().len(), 1);
+    // Other tests may patch MaskReservations concurrently.
+    assert!(counter.get() >= before + 1.0);
+}
@@ -0,0 +1,184 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use kube::{api::ObjectMeta, client::Client, Api};
+use std::time::Duration;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::{mock, util::*};
+use crate::masks::{actions, util::time_to_active};
+
+/// Returns a Mask created at the given time with the given status.
+fn mask(created: Option<chrono::DateTime<Utc>>, status: MaskStatus) -> Mask {
+    Mask {
+        metadata: ObjectMeta {
+            name: Some("test-mask-0".to_owned()),
+            namespace: Some("default".to_owned()),
+            creation_timestamp: created.map(Time),
+            ..Default::default()
+        },
+        status: Some(status),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn first_active_recorded_once() {
+    let mut status = MaskStatus {
+        phase: Some(MaskPhase::Waiting),
+        ..Default::default()
+    };
+    assert!(status.mark_first_active("first"));
+    status.set_active(None, "active");
+    assert_eq!(status.first_active_at.as_deref(), Some("first"));
+
+    // Refreshing the Active phase doesn't record it again.
+    assert!(!status.mark_first_active("refresh"));
+
+    // Neither does becoming Active again after a reassignment.
+    status.set_phase(MaskPhase::Waiting, "waiting");
+    assert!(!status.mark_first_active("reassigned"));
+    status.set_active(None, "active");
+    assert_eq!(status.first_active_at.as_deref(), Some("first"));
+}
+
+#[test]
+fn first_active_unknown_for_already_active() {
+    // A Mask that was Active before the field existed can't
+    // know when it became Active, so nothing is recorded.
+    let mut status = MaskStatus {
+        phase: Some(MaskPhase::Active),
+        ..Default::default()
+    };
+    assert!(!status.mark_first_active("now"));
+    assert_eq!(status.first_active_at, None);
+}
+
+#[test]
+fn time_to_active_measured_from_creation() {
+    let now = Utc::now();
+    let created = now - ChronoDuration::seconds(30);
+    assert_eq!(
+        time_to_active(&mask(Some(created), Default::default()), now),
+        Some(Duration::from_secs(30))
+    );
+    // Missing or future creation timestamps can't be measured.
+    assert_eq!(time_to_active(&mask(None, Default::default()), now), None);
+    let future = now + ChronoDuration::seconds(5);
+    assert_eq!(
+        time_to_active(&mask(Some(future), Default::default()), now),
+        None
+    );
+}
+
+#[tokio::test]
+async fn time_to_active_reported_once() {
+    let created = Utc::now() - ChronoDuration::seconds(12);
+    let waiting = mask(
+        Some(created),
+        MaskStatus {
+            phase: Some(MaskPhase::Waiting),
+            ..Default::default()
+        },
+    );
+    #[cfg(feature = "metrics")]
+    let histogram =
+        crate::util::metrics::TIME_TO_ACTIVE_HISTOGRAM.with_label_values(&["time-to-active-test"]);
+    #[cfg(feature = "metrics")]
+    let before = histogram.get_sample_count();
+
+    // Becoming Active for the first time records the timestamp.
+    let (client, captured) = mock::mock_client(serde_json::to_value(&waiting).unwrap());
+    actions::active(
+        client,
+        &waiting,
+        None,
+        Some("time-to-active-test".to_owned()),
+        vec![],
+    )
+    .await
+    .unwrap();
+    let first_active_at = {
+        let captured = captured.lock().unwrap();
+        mock::patch_op(&captured[0], "/status/firstActiveAt")
+            .and_then(|v| v.as_str())
+            .expect("firstActiveAt was not recorded")
+            .to_owned()
+    };
+    #[cfg(feature = "metrics")]
+    {
+        assert_eq!(histogram.get_sample_count(), before + 1);
+        assert!(histogram.get_sample_sum() >= 12.0);
+    }
+
+    // A reassigned Mask keeps the original timestamp and isn't reported again.
+    let reassigned = mask(
+        Some(created),
+        MaskStatus {
+            phase: Some(MaskPhase::Waiting),
+            first_active_at: Some(first_active_at),
+            ..Default::default()
+        },
+    );
+    let (client, captured) = mock::mock_client(serde_json::to_value(&reassigned).unwrap());
+    actions::active(
+        client,
+        &reassigned,
+        None,
+        Some("time-to-active-test".to_owned()),
+        vec![],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        mock::patch_op(&captured.lock().unwrap()[0], "/status/firstActiveAt"),
+        None
+    );
+    #[cfg(feature = "metrics")]
+    assert_eq!(histogram.get_sample_count(), before + 1);
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn first_active_populated() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+
+    // Create the test MaskProvider and a Mask, and wait for the Mask to be Active.
+    let provider_ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    create_test_provider(client.clone(), &namespace, &uid).await?;
+    provider_ready.await.unwrap()?;
+    let mask_active = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(async move { wait_for_mask_phase(client, &namespace, 0, MaskPhase::Active).await })
+    };
+    create_test_mask(client.clone(), &namespace, 0, &provider_name).await?;
+    mask_active.await.unwrap()?;
+
+    // The time the Mask became Active is recorded, no earlier than its creation.
+    let mask = Api::<Mask>::namespaced(client.clone(), &namespace)
+        .get(&format!("{}-0", MASK_NAME))
+        .await?;
+    let first_active_at: chrono::DateTime<Utc> = mask
+        .status
+        .as_ref()
+        .and_then(|s| s.first_active_at.as_deref())
+        .expect("firstActiveAt was not populated")
+        .parse()
+        .expect("firstActiveAt is not a valid timestamp");
+    let created = mask.metadata.creation_timestamp.unwrap().0;
+    // Creation timestamps are truncated to the second.
+    assert!(first_active_at >= created);
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
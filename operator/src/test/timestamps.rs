@@ -0,0 +1,94 @@
+use chrono::{TimeZone, Utc};
+use std::time::Duration;
+use vpn_types::*;
+
+use crate::util::{
+    clock::{format_timestamp, now_k8s, parse_timestamp},
+    patch::status_phase,
+    phase_age, status_age,
+};
+
+#[test]
+fn formatted_like_kubernetes() {
+    let time = Utc
+        .with_ymd_and_hms(2023, 6, 14, 9, 47, 40)
+        .unwrap()
+        .checked_add_signed(chrono::Duration::nanoseconds(123_456_789))
+        .unwrap();
+    assert_eq!(format_timestamp(time), "2023-06-14T09:47:40Z");
+
+    let now = now_k8s();
+    assert!(now.ends_with('Z'));
+    assert!(!now.contains('.'));
+    assert_eq!(format_timestamp(parse_timestamp(&now).unwrap()), now);
+}
+
+#[test]
+fn old_formats_parsed() {
+    let expected = Utc.with_ymd_and_hms(2023, 6, 14, 9, 47, 40).unwrap();
+    for timestamp in [
+        "2023-06-14T09:47:40Z",
+        // Written by earlier versions of the operator.
+        "2023-06-14T09:47:40.000000000+00:00",
+        "2023-06-14T11:47:40+02:00",
+        // Missing an offset, which is taken to be UTC.
+        "2023-06-14T09:47:40",
+        " 2023-06-14T09:47:40Z ",
+    ] {
+        assert_eq!(
+            parse_timestamp(timestamp).unwrap(),
+            expected,
+            "{}",
+            timestamp
+        );
+    }
+    assert!(parse_timestamp("yesterday").is_err());
+    assert!(parse_timestamp("2023-13-45T99:00:00Z").is_err());
+}
+
+#[test]
+fn chronological_as_strings() {
+    let earlier = Utc.with_ymd_and_hms(2023, 6, 14, 9, 47, 40).unwrap();
+    let later = earlier + chrono::Duration::seconds(1);
+    assert!(format_timestamp(earlier) < format_timestamp(later));
+}
+
+#[test]
+fn skewed_phase_age_clamped() {
+    // A replica whose clock runs ahead wrote the status.
+    let ahead = format_timestamp(Utc::now() + chrono::Duration::seconds(30));
+    assert_eq!(phase_age(Some(&ahead)), Duration::ZERO);
+    assert_eq!(phase_age(None), Duration::MAX);
+    assert_eq!(phase_age(Some("yesterday")), Duration::MAX);
+
+    // Old statuses keep their age.
+    let old = (Utc::now() - chrono::Duration::minutes(5)).to_rfc3339();
+    assert!(phase_age(Some(&old)) >= Duration::from_secs(300));
+    assert_eq!(
+        status_age(Some(&old)).as_secs() / 60,
+        phase_age(Some(&old)).as_secs() / 60
+    );
+}
+
+#[test]
+fn skewed_status_phase_clamped() {
+    let ahead = format_timestamp(Utc::now() + chrono::Duration::seconds(30));
+    let instance = MaskProvider {
+        metadata: kube::api::ObjectMeta {
+            name: Some("provider".to_owned()),
+            namespace: Some("providers".to_owned()),
+            ..Default::default()
+        },
+        spec: Default::default(),
+        status: Some(MaskProviderStatus {
+            phase: Some(MaskProviderPhase::Ready),
+            message: Some("Ready.".to_owned()),
+            last_updated: Some(ahead),
+            ..Default::default()
+        }),
+    };
+    assert_eq!(
+        status_phase(&instance).unwrap(),
+        (MaskProviderPhase::Ready, Duration::ZERO)
+    );
+}
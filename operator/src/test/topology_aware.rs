@@ -0,0 +1,213 @@
+use futures::{StreamExt, TryStreamExt};
+use k8s_openapi::api::core::v1::Namespace;
+use kube::{
+    api::{ListParams, Patch, PatchParams, WatchEvent},
+    client::Client,
+    Api,
+};
+use tokio::spawn;
+use vpn_types::*;
+
+use super::util::*;
+use crate::{
+    consumers::topology::{
+        prefer_region, provider_regions, tier, TopologyTier, DEFAULT_TOPOLOGY_LABEL,
+    },
+    util::messages,
+};
+
+/// Returns a MaskProvider with the given name and tags.
+fn tagged_provider(name: &str, tags: &[&str]) -> MaskProvider {
+    let mut provider = mask_provider(name, "vpn", name);
+    provider.spec.tags = Some(tags.iter().map(|t| (*t).to_owned()).collect());
+    provider
+}
+
+/// Returns the names of the MaskProviders in order.
+fn names(providers: &[MaskProvider]) -> Vec<&str> {
+    providers
+        .iter()
+        .map(|p| p.metadata.name.as_deref().unwrap())
+        .collect()
+}
+
+#[test]
+fn region_tags_are_parsed() {
+    let tagged = tagged_provider(
+        "a",
+        &["vpn", "region=eu", " region = us ", "region=", "zone=eu"],
+    );
+    assert_eq!(
+        provider_regions(&tagged).collect::<Vec<_>>(),
+        vec!["eu", "us"]
+    );
+
+    // Bare region names and other keys aren't regions.
+    assert_eq!(
+        provider_regions(&tagged_provider("b", &["eu", "Region=eu"])).count(),
+        0
+    );
+    let mut untagged = tagged_provider("c", &[]);
+    untagged.spec.tags = None;
+    assert_eq!(provider_regions(&untagged).count(), 0);
+}
+
+#[test]
+fn same_region_is_tried_first() {
+    let candidates = vec![
+        tagged_provider("us-1", &["region=us"]),
+        tagged_provider("eu-1", &["vpn", "region=eu"]),
+        tagged_provider("bare", &["eu"]),
+        tagged_provider("multi", &["region=us", "region=eu"]),
+        tagged_provider("untagged", &[]),
+        tagged_provider("eu-2", &["region = eu"]),
+    ];
+    assert_eq!(
+        names(&prefer_region(candidates.clone(), "eu")),
+        vec!["eu-1", "multi", "eu-2", "us-1", "bare", "untagged"]
+    );
+    assert_eq!(
+        names(&prefer_region(candidates.clone(), "us")),
+        vec!["us-1", "multi", "eu-1", "bare", "untagged", "eu-2"]
+    );
+
+    // Without any provider in the region, the order is kept.
+    assert_eq!(
+        names(&prefer_region(candidates.clone(), "ap")),
+        names(&candidates)
+    );
+    assert_eq!(tier(&candidates[1], "eu"), TopologyTier::SameRegion);
+    assert_eq!(tier(&candidates[2], "eu"), TopologyTier::OtherRegion);
+}
+
+#[test]
+fn tier_is_named_in_message() {
+    let message = messages::reserved_slot(
+        0,
+        "vpn",
+        "eu-1",
+        None,
+        Some(&messages::topology_tier("eu", true)),
+    );
+    assert_eq!(
+        message,
+        "reserved slot 0 for MaskProvider vpn/eu-1, in the namespace's region eu"
+    );
+    assert!(messages::topology_tier("eu", false).starts_with("outside the namespace's region eu"));
+}
+
+/// Creates a MaskProvider with one slot in the region that is assigned to
+/// Masks requesting the `tag`, and waits for it to become Ready.
+async fn create_provider(
+    client: Client,
+    namespace: &str,
+    name: &str,
+    tag: &str,
+    region: &str,
+) -> Result<MaskProvider, Error> {
+    let provider_ready = {
+        let client = client.clone();
+        let namespace = namespace.to_owned();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let mut provider = get_test_provider(client.clone(), name, namespace).await?;
+    provider.spec.tags = Some(vec![tag.to_owned(), format!("region={}", region)]);
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), namespace);
+    let provider = api.create(&Default::default(), &provider).await?;
+    create_test_provider_secret(client, namespace, &provider).await?;
+    provider_ready.await.unwrap()?;
+    Ok(provider)
+}
+
+/// Waits for the MaskConsumer of the test Mask to be assigned a
+/// MaskProvider, returning it along with the status message recorded by
+/// the assignment. The message is replaced once the MaskConsumer becomes
+/// Active, so this has to watch from before the Mask is created.
+async fn wait_for_assignment_message(
+    client: Client,
+    namespace: &str,
+    index: usize,
+) -> Result<(AssignedProvider, String), Error> {
+    let name = format!("{}-{}", MASK_NAME, index);
+    let api: Api<MaskConsumer> = Api::namespaced(client, namespace);
+    let lp = ListParams::default()
+        .fields(&format!("metadata.name={}", name))
+        .timeout(120);
+    let mut stream = api.watch(&lp, "0").await?.boxed();
+    while let Some(event) = stream.try_next().await? {
+        if let WatchEvent::Added(m) | WatchEvent::Modified(m) = event {
+            if let Some(status) = m.status {
+                if let Some(provider) = status.provider {
+                    return Ok((provider, status.message.unwrap_or_default()));
+                }
+            }
+        }
+    }
+    Err(Error::Other(format!(
+        "MaskProvider not assigned to MaskConsumer {} before timeout",
+        name,
+    )))
+}
+
+/// Creates a topology-aware test Mask and returns the MaskProvider it is
+/// assigned, along with the MaskConsumer's status message.
+async fn assign_mask(
+    client: Client,
+    namespace: &str,
+    index: usize,
+    tag: &str,
+) -> Result<(AssignedProvider, String), Error> {
+    let assigned = {
+        let client = client.clone();
+        let namespace = namespace.to_owned();
+        spawn(async move { wait_for_assignment_message(client, &namespace, index).await })
+    };
+    let mut mask = get_test_mask(namespace, index, tag);
+    mask.spec.strategy = Some(AssignmentStrategy::TopologyAware);
+    let api: Api<Mask> = Api::namespaced(client, namespace);
+    api.create(&Default::default(), &mask).await?;
+    assigned.await.unwrap()
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn same_region_preferred() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let tag = test_provider_name(&uid);
+
+    // The namespace is in the eu region.
+    let namespaces: Api<Namespace> = Api::all(client.clone());
+    let patch = serde_json::json!({
+        "metadata": {
+            "labels": {
+                DEFAULT_TOPOLOGY_LABEL: "eu",
+            },
+        },
+    });
+    namespaces
+        .patch(&namespace, &PatchParams::default(), &Patch::Merge(&patch))
+        .await?;
+
+    // The MaskProvider in another region is listed first.
+    let other = format!("{}-a", tag);
+    let same = format!("{}-b", tag);
+    create_provider(client.clone(), &namespace, &other, &tag, "us").await?;
+    create_provider(client.clone(), &namespace, &same, &tag, "eu").await?;
+
+    // The first Mask is assigned the MaskProvider in the namespace's region.
+    let (assigned, message) = assign_mask(client.clone(), &namespace, 0, &tag).await?;
+    assert_eq!(assigned.name, same);
+    assert!(message.ends_with(&messages::topology_tier("eu", true)));
+
+    // Its only slot is taken, so the second Mask falls back to the other region.
+    let (assigned, message) = assign_mask(client.clone(), &namespace, 1, &tag).await?;
+    assert_eq!(assigned.name, other);
+    assert!(message.ends_with(&messages::topology_tier("eu", false)));
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,25 @@
+use kube::{client::ClientBuilder, Api};
+use vpn_types::*;
+
+use super::mock::*;
+use crate::util::client::{user_agent, user_agent_layer};
+
+#[tokio::test]
+async fn user_agent_is_set() {
+    // The response is irrelevant, only the request headers are inspected.
+    let (transport, captured) = mock_service(404, status_failure(404));
+    let client = ClientBuilder::new(transport, "default")
+        .with_layer(&user_agent_layer("masks"))
+        .build();
+    let _ = Api::<Mask>::namespaced(client, "default")
+        .get("test-mask")
+        .await;
+
+    assert_eq!(user_agent("masks"), "vpn-operator/masks");
+    let captured = captured.lock().unwrap();
+    assert_eq!(captured.len(), 1);
+    assert_eq!(
+        captured[0].user_agent.as_deref(),
+        Some("vpn-operator/masks")
+    );
+}
@@ -1,15 +1,21 @@
 use futures::{StreamExt, TryStreamExt};
-use k8s_openapi::api::core::v1::{Namespace, Secret};
+use k8s_openapi::{
+    api::core::v1::{Namespace, Secret},
+    apimachinery::pkg::apis::meta::v1::OwnerReference,
+};
 use kube::{
-    api::{ListParams, ObjectMeta, Resource},
+    api::{ListParams, ObjectMeta, Patch, Resource},
     client::Client,
     core::{NamespaceResourceScope, WatchEvent},
     Api, CustomResourceExt,
 };
 use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
 use std::{clone::Clone, fmt::Debug};
 use vpn_types::*;
 
+use crate::{consumers::slots::reservation_name, util::Trace};
+
 /// Maximum number of slots for the real VPN provider.
 pub const MAX_SLOTS: usize = 1;
 
@@ -17,6 +23,13 @@ pub const MAX_SLOTS: usize = 1;
 /// a randomly generated UUID to distinguish it from other test providers.
 pub const PROVIDER_NAME: &str = "test-provider";
 
+/// Returns the name of the test MaskProvider with the given UUID. The name
+/// ends with a numeric segment, as MaskReservation names do, so the tests
+/// catch any code that confuses the provider's name with a slot number.
+pub fn test_provider_name(uid: &str) -> String {
+    format!("{}-{}-7", PROVIDER_NAME, uid)
+}
+
 /// Base name of the test Mask resource. If multiple Masks are used in a test,
 /// they will be named `test-mask-0`, `test-mask-1`, etc.
 pub const MASK_NAME: &str = "test-mask";
@@ -24,19 +37,108 @@ pub const MASK_NAME: &str = "test-mask";
 /// Prefix of all test namespaces.
 pub const NAMESPACE_PREFIX: &str = "vpn-test-";
 
+/// Returns a MaskProvider with the given name, namespace and uid, a
+/// default spec and no status, for tests to fill in what they're about.
+pub fn mask_provider(name: &str, namespace: &str, uid: &str) -> MaskProvider {
+    MaskProvider {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some(namespace.to_owned()),
+            uid: Some(uid.to_owned()),
+            ..Default::default()
+        },
+        spec: Default::default(),
+        status: None,
+    }
+}
+
+/// Returns a Ready MaskProvider with the given name, namespace and uid
+/// and a default spec.
+pub fn ready_provider(name: &str, namespace: &str, uid: &str) -> MaskProvider {
+    MaskProvider {
+        status: Some(MaskProviderStatus {
+            phase: Some(MaskProviderPhase::Ready),
+            ..Default::default()
+        }),
+        ..mask_provider(name, namespace, uid)
+    }
+}
+
+/// Returns a MaskConsumer with the given name, namespace and uid, a
+/// default spec and no status, for tests to fill in what they're about.
+pub fn mask_consumer(name: &str, namespace: &str, uid: &str) -> MaskConsumer {
+    MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some(namespace.to_owned()),
+            uid: Some(uid.to_owned()),
+            ..Default::default()
+        },
+        spec: Default::default(),
+        status: None,
+    }
+}
+
+/// Returns the slot 0 assignment of the MaskProvider with the given name,
+/// namespace and uid, its credentials copied to the Secret named `secret`.
+pub fn assigned_provider(name: &str, namespace: &str, uid: &str, secret: &str) -> AssignedProvider {
+    AssignedProvider {
+        name: name.to_owned(),
+        namespace: namespace.to_owned(),
+        uid: uid.to_owned(),
+        slot: 0,
+        reservation: "reservation-uid".to_owned(),
+        secret: secret.to_owned(),
+        ..Default::default()
+    }
+}
+
+/// Returns the MaskConsumer in the given phase, assigned the provider.
+pub fn assigned(
+    mut consumer: MaskConsumer,
+    phase: Option<MaskConsumerPhase>,
+    provider: AssignedProvider,
+) -> MaskConsumer {
+    consumer.status = Some(MaskConsumerStatus {
+        phase,
+        provider: Some(provider),
+        ..Default::default()
+    });
+    consumer
+}
+
+/// Returns the owner reference of a MaskConsumer to the `Mask` that
+/// controls it, which has the given name and the uid `mask-uid`.
+pub fn mask_owner_ref(name: &str) -> OwnerReference {
+    OwnerReference {
+        api_version: "vpn.beebs.dev/v1".to_owned(),
+        kind: "Mask".to_owned(),
+        name: name.to_owned(),
+        uid: "mask-uid".to_owned(),
+        controller: Some(true),
+        ..Default::default()
+    }
+}
+
 /// All errors possible to occur during testing.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     /// Any error originating from the `kube-rs` crate
     #[error("Kubernetes reported error: {source}")]
-    KubeError {
-        #[from]
-        source: kube::Error,
-    },
+    KubeError { source: kube::Error, trace: Trace },
     #[error("Error: {0}")]
     Other(String),
 }
 
+impl From<kube::Error> for Error {
+    fn from(source: kube::Error) -> Self {
+        Error::KubeError {
+            source,
+            trace: Trace::capture(),
+        }
+    }
+}
+
 /// Returns the Secret resource that contains actual VPN credentials
 /// when testing against external services. If the environment variables
 /// SECRET_NAME or SECRET_NAMESPACE are not set, this will return None,
@@ -54,6 +156,41 @@ pub async fn get_actual_provider_secret(client: Client) -> Result<Option<Secret>
     Ok(Some(secret_api.get(&name).await?))
 }
 
+/// Waits for the MaskProvider's slot to be released, i.e. for the
+/// MaskReservation holding it to be deleted. Masks only finish deleting
+/// once their slot is free, so this should return right away after
+/// [`delete_test_mask`].
+pub async fn wait_for_slot_free(
+    client: Client,
+    provider: &MaskProvider,
+    slot: usize,
+) -> Result<(), Error> {
+    let name = reservation_name(provider.metadata.name.as_deref().unwrap(), slot);
+    let mr_api: Api<MaskReservation> =
+        Api::namespaced(client, provider.metadata.namespace.as_deref().unwrap());
+    let lp = ListParams::default()
+        .fields(&format!("metadata.name={}", name))
+        .timeout(10);
+    let mut stream = mr_api.watch(&lp, "0").await?.boxed();
+    if mr_api.get_opt(&name).await?.is_none() {
+        return Ok(());
+    }
+    while let Some(event) = stream.try_next().await? {
+        if let WatchEvent::Deleted(_) = event {
+            return Ok(());
+        }
+    }
+    // Check if it's deleted now and we missed it.
+    if mr_api.get_opt(&name).await?.is_none() {
+        return Ok(());
+    }
+    Err(Error::Other(format!(
+        "slot {} of MaskProvider {} not released before timeout",
+        slot,
+        provider.metadata.name.as_deref().unwrap(),
+    )))
+}
+
 /// Returns the test MaskProvider's credentials Secret resource.
 /// If the environment specified a real secret, it will be used.
 /// This will also enable verification. Otherwise, mock credentials
@@ -104,6 +241,22 @@ pub async fn get_test_provider_secret(
     })
 }
 
+/// Returns verification overrides that stand in for the VPN and the
+/// external IP service: the probe reports a new IP after a few seconds.
+pub fn fake_verify_overrides() -> MaskProviderVerifyOverridesSpec {
+    MaskProviderVerifyOverridesSpec {
+        containers: Some(MaskProviderVerifyContainerOverridesSpec {
+            init: Some(json!({ "command": ["true"] })),
+            vpn: Some(json!({
+                "image": "curlimages/curl:7.88.1",
+                "command": ["sleep", "3600"],
+            })),
+            probe: Some(json!({ "command": ["sh", "-c", "sleep 5"] })),
+        }),
+        ..Default::default()
+    }
+}
+
 /// Returns the test MaskProvider resource. If we are using mock credentials,
 /// verification will be disabled. Otherwise, verification will be enabled.
 pub async fn get_test_provider(
@@ -150,6 +303,7 @@ pub fn get_test_mask(namespace: &str, slot: usize, provider_label: &str) -> Mask
         spec: MaskSpec {
             // Only use the MaskProvider created by this specific test.
             providers: Some(vec![provider_label.to_owned()]),
+            ..Default::default()
         },
         ..Default::default()
     }
@@ -161,7 +315,7 @@ pub async fn create_test_provider_secret(
     namespace: &str,
     provider: &MaskProvider,
 ) -> Result<Secret, Error> {
-    let secret = get_test_provider_secret(client.clone(), &provider).await?;
+    let secret = get_test_provider_secret(client.clone(), provider).await?;
     let secret_api: Api<Secret> = Api::namespaced(client, namespace);
     Ok(secret_api.create(&Default::default(), &secret).await?)
 }
@@ -172,7 +326,7 @@ pub async fn create_test_provider(
     namespace: &str,
     uid: &str,
 ) -> Result<MaskProvider, Error> {
-    let name = format!("{}-{}", PROVIDER_NAME, uid);
+    let name = test_provider_name(uid);
     let api: Api<MaskProvider> = Api::namespaced(client.clone(), namespace);
     let provider = api
         .create(
@@ -215,10 +369,10 @@ pub async fn wait_for_provider_phase(
     let mut stream = provider_api.watch(&lp, "0").await?.boxed();
     while let Some(event) = stream.try_next().await? {
         match event {
-            WatchEvent::Added(m) | WatchEvent::Modified(m) => {
-                if m.status.as_ref().map_or(false, |s| s.phase == Some(phase)) {
-                    return Ok(());
-                }
+            WatchEvent::Added(m) | WatchEvent::Modified(m)
+                if m.status.as_ref().is_some_and(|s| s.phase == Some(phase)) =>
+            {
+                return Ok(());
             }
             _ => {}
         }
@@ -232,7 +386,7 @@ pub async fn wait_for_provider_phase(
             provider
                 .status
                 .as_ref()
-                .map_or(false, |s| s.phase == Some(phase))
+                .is_some_and(|s| s.phase == Some(phase))
         })
     {
         return Ok(());
@@ -243,6 +397,64 @@ pub async fn wait_for_provider_phase(
     )))
 }
 
+/// Waits for the test MaskProvider to record the given verify-now
+/// annotation value, which happens after a manually triggered
+/// verification cycle completes.
+pub async fn wait_for_manual_verify(
+    client: Client,
+    namespace: &str,
+    value: &str,
+) -> Result<MaskProvider, Error> {
+    let provider_api: Api<MaskProvider> = Api::namespaced(client, namespace);
+    let lp = ListParams::default().timeout(180);
+    let mut stream = provider_api.watch(&lp, "0").await?.boxed();
+    while let Some(event) = stream.try_next().await? {
+        match event {
+            WatchEvent::Added(m) | WatchEvent::Modified(m)
+                if m.status
+                    .as_ref()
+                    .and_then(|s| s.last_manual_verify.as_deref())
+                    == Some(value) =>
+            {
+                return Ok(m);
+            }
+            _ => {}
+        }
+    }
+    Err(Error::Other(format!(
+        "MaskProvider did not record manual verification {} before timeout",
+        value
+    )))
+}
+
+/// Waits for the test MaskProvider's status to carry the given warnings.
+/// An empty list waits for the warnings to be cleared.
+pub async fn wait_for_provider_warnings(
+    client: Client,
+    namespace: &str,
+    warnings: &[String],
+) -> Result<MaskProvider, Error> {
+    let provider_api: Api<MaskProvider> = Api::namespaced(client, namespace);
+    let lp = ListParams::default().timeout(120);
+    let mut stream = provider_api.watch(&lp, "0").await?.boxed();
+    while let Some(event) = stream.try_next().await? {
+        match event {
+            WatchEvent::Added(m) | WatchEvent::Modified(m)
+                if m.status
+                    .as_ref()
+                    .is_some_and(|s| s.warnings.as_deref().unwrap_or_default() == warnings) =>
+            {
+                return Ok(m);
+            }
+            _ => {}
+        }
+    }
+    Err(Error::Other(format!(
+        "MaskProvider did not report warnings {:?} before timeout",
+        warnings
+    )))
+}
+
 /// Waits for the test MaskProvider to be assigned to the test Mask.
 pub async fn wait_for_provider_assignment(
     client: Client,
@@ -258,7 +470,7 @@ pub async fn wait_for_provider_assignment(
     while let Some(event) = stream.try_next().await? {
         match event {
             WatchEvent::Added(m) | WatchEvent::Modified(m) => {
-                match m.status.map_or(None, |s| s.provider) {
+                match m.status.and_then(|s| s.provider) {
                     Some(provider) => return Ok(provider),
                     _ => continue,
                 }
@@ -267,7 +479,7 @@ pub async fn wait_for_provider_assignment(
         }
     }
     // Check if it's assigned now and we missed it.
-    if let Some(provider) = mc_api.get(&name).await?.status.map_or(None, |s| s.provider) {
+    if let Some(provider) = mc_api.get(&name).await?.status.and_then(|s| s.provider) {
         return Ok(provider);
     }
     Err(Error::Other(format!(
@@ -292,7 +504,7 @@ pub async fn wait_for_mask_phase(
     while let Some(event) = stream.try_next().await? {
         match event {
             WatchEvent::Added(m) | WatchEvent::Modified(m) => {
-                if m.status.as_ref().map_or(false, |s| s.phase == Some(phase)) {
+                if m.status.as_ref().is_some_and(|s| s.phase == Some(phase)) {
                     return Ok(());
                 }
             }
@@ -305,7 +517,7 @@ pub async fn wait_for_mask_phase(
         .await?
         .status
         .as_ref()
-        .map_or(false, |s| s.phase == Some(phase))
+        .is_some_and(|s| s.phase == Some(phase))
     {
         return Ok(());
     }
@@ -389,6 +601,26 @@ pub async fn delete_test_provider(
     Ok(())
 }
 
+/// Deletes the test MaskProvider even if Masks are still assigned its
+/// slots, by setting the force-delete annotation before deleting it.
+pub async fn force_delete_test_provider(
+    client: Client,
+    namespace: &str,
+    name: &str,
+) -> Result<(), Error> {
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), namespace);
+    let patch = serde_json::json!({
+        "metadata": {
+            "annotations": {
+                "vpn.beebs.dev/force-delete": "true"
+            }
+        }
+    });
+    api.patch(name, &Default::default(), &Patch::Merge(&patch))
+        .await?;
+    delete_test_provider(client, namespace, name).await
+}
+
 /// Deletes the test Mask at the given slot.
 pub async fn delete_test_mask(client: Client, namespace: &str, slot: usize) -> Result<(), Error> {
     assert!(
@@ -403,16 +635,15 @@ pub async fn delete_test_mask(client: Client, namespace: &str, slot: usize) -> R
 }
 
 /// Waits for the resource to be deleted.
-pub async fn delete_wait<
-    T: Clone + Resource + CustomResourceExt + Serialize + DeserializeOwned + Debug,
->(
-    client: Client,
-    name: &str,
-    namespace: &str,
-) -> Result<bool, Error>
+pub async fn delete_wait<T>(client: Client, name: &str, namespace: &str) -> Result<bool, Error>
 where
+    T: Clone
+        + Resource<Scope = NamespaceResourceScope>
+        + CustomResourceExt
+        + Serialize
+        + DeserializeOwned
+        + Debug,
     <T as Resource>::DynamicType: Default,
-    T: Resource<Scope = NamespaceResourceScope>,
 {
     let api: Api<T> = Api::namespaced(client, namespace);
     match api.get(name).await {
@@ -0,0 +1,200 @@
+use chrono::{TimeZone, Utc};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use kube::api::ObjectMeta;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use vpn_types::*;
+
+use super::{mock::*, util::mask_provider};
+use crate::{
+    providers::verify_queue::{check, check_queue, in_flight, QueueCheck},
+    util::{messages, VERIFICATION_LABEL},
+};
+
+/// Returns a MaskProvider created `index` minutes into the day, so
+/// that MaskProviders with lower indices are older.
+fn queued_provider(index: u32, phase: MaskProviderPhase) -> MaskProvider {
+    let mut provider = mask_provider(
+        &format!("provider-{}", index),
+        "vpn",
+        &format!("uid-{}", index),
+    );
+    provider.metadata.creation_timestamp =
+        Some(Time(Utc.with_ymd_and_hms(2023, 3, 1, 0, index, 0).unwrap()));
+    provider.status = Some(MaskProviderStatus {
+        phase: Some(phase),
+        ..Default::default()
+    });
+    provider
+}
+
+/// Returns the verification Mask of the MaskProvider with the UID.
+fn verify_mask(uid: &str) -> Mask {
+    Mask {
+        metadata: ObjectMeta {
+            name: Some(format!("{}-verify", uid)),
+            namespace: Some("vpn".to_owned()),
+            labels: Some(BTreeMap::from([(
+                VERIFICATION_LABEL.to_owned(),
+                uid.to_owned(),
+            )])),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Returns the UIDs as a set.
+fn uids(uids: &[&str]) -> BTreeSet<String> {
+    uids.iter().map(|u| u.to_string()).collect()
+}
+
+#[test]
+fn starts_below_cap() {
+    let instance = queued_provider(0, MaskProviderPhase::Pending);
+    let providers = vec![
+        instance.clone(),
+        queued_provider(1, MaskProviderPhase::Ready),
+    ];
+    assert_eq!(
+        check(&instance, &providers, &uids(&[]), 2),
+        QueueCheck {
+            position: None,
+            depth: 0
+        }
+    );
+    // MaskProviders that aren't waiting don't take a turn.
+    assert_eq!(
+        check(&instance, &providers, &uids(&["uid-1"]), 2).position,
+        None
+    );
+}
+
+#[test]
+fn queued_at_cap() {
+    let instance = queued_provider(3, MaskProviderPhase::Ready);
+    let mut providers = vec![
+        queued_provider(0, MaskProviderPhase::Verifying),
+        queued_provider(1, MaskProviderPhase::Verifying),
+        queued_provider(2, MaskProviderPhase::Verifying),
+        instance.clone(),
+    ];
+    // The two oldest are verifying, and the third was queued before.
+    assert_eq!(
+        check(&instance, &providers, &uids(&["uid-0", "uid-1"]), 2),
+        QueueCheck {
+            position: Some(2),
+            depth: 2
+        }
+    );
+    // Once a verification finishes, the older one goes first.
+    providers[1].status.as_mut().unwrap().phase = Some(MaskProviderPhase::Verified);
+    assert_eq!(
+        check(&instance, &providers, &uids(&["uid-0"]), 2),
+        QueueCheck {
+            position: Some(1),
+            depth: 1
+        }
+    );
+    assert_eq!(
+        check(&providers[2], &providers, &uids(&["uid-0"]), 2).position,
+        None
+    );
+}
+
+#[test]
+fn five_providers_cap_of_two() {
+    let mut providers: Vec<MaskProvider> = (0..5)
+        .map(|i| queued_provider(i, MaskProviderPhase::Pending))
+        .collect();
+    let mut verifying = BTreeSet::new();
+    let mut started = BTreeMap::new();
+    let mut verified = Vec::new();
+    let mut max_depth = 0;
+    for round in 0..10 {
+        // Reconcile the youngest first, so the order isn't an accident.
+        for i in (0..providers.len()).rev() {
+            let uid = providers[i].metadata.uid.clone().unwrap();
+            let phase = providers[i].status.as_ref().unwrap().phase.unwrap();
+            if phase == MaskProviderPhase::Verified {
+                continue;
+            }
+            if verifying.remove(&uid) {
+                // The verification started last round has finished.
+                providers[i].status.as_mut().unwrap().phase = Some(MaskProviderPhase::Verified);
+                verified.push(uid);
+                continue;
+            }
+            let queue = check(&providers[i], &providers, &verifying, 2);
+            max_depth = max_depth.max(queue.depth);
+            if queue.position.is_none() {
+                verifying.insert(uid.clone());
+                started.insert(uid, round);
+            }
+            providers[i].status.as_mut().unwrap().phase = Some(MaskProviderPhase::Verifying);
+            assert!(verifying.len() <= 2, "{:?} verifying", verifying);
+        }
+    }
+    // Every MaskProvider was verified, two at a time in order of creation,
+    // each pair starting the round after the previous one finished.
+    let rounds: Vec<usize> = (0..5).map(|i| started[&format!("uid-{}", i)]).collect();
+    assert_eq!(rounds, vec![0, 0, 2, 2, 4]);
+    assert_eq!(verified.len(), 5);
+    assert!(max_depth > 0);
+}
+
+#[test]
+fn in_flight_verifications() {
+    let mut canary = verify_mask("uid-2");
+    canary.metadata.labels = None;
+    let masks = vec![verify_mask("uid-0"), verify_mask("uid-1"), canary];
+    assert_eq!(in_flight(&masks), uids(&["uid-0", "uid-1"]));
+}
+
+#[test]
+fn queued_message() {
+    assert_eq!(
+        messages::verify_queued(3),
+        "Queued for verification (position 3)."
+    );
+}
+
+#[tokio::test]
+async fn queue_listed() {
+    let instance = queued_provider(2, MaskProviderPhase::Ready);
+    let (client, captured) = mock_routes(vec![
+        (
+            "/apis/vpn.beebs.dev/v1/maskproviders",
+            json!({
+                "apiVersion": "vpn.beebs.dev/v1",
+                "kind": "MaskProviderList",
+                "metadata": {},
+                "items": [
+                    queued_provider(0, MaskProviderPhase::Verifying),
+                    queued_provider(1, MaskProviderPhase::Verifying),
+                    instance,
+                ],
+            }),
+        ),
+        (
+            "/apis/vpn.beebs.dev/v1/masks",
+            json!({
+                "apiVersion": "vpn.beebs.dev/v1",
+                "kind": "MaskList",
+                "metadata": {},
+                "items": [verify_mask("uid-0")],
+            }),
+        ),
+    ]);
+    let queue = check_queue(client, &instance, 1).await.unwrap();
+    assert_eq!(
+        queue,
+        QueueCheck {
+            position: Some(2),
+            depth: 2
+        }
+    );
+    // Only verification Masks are listed.
+    let captured = captured.lock().unwrap();
+    assert!(captured[1].path.contains("labelSelector"));
+}
@@ -0,0 +1,56 @@
+use kube::client::Client;
+use std::time::Duration;
+use tokio::{spawn, time::timeout};
+use vpn_types::*;
+
+use super::util::*;
+
+/// Regression test for verification reservations counting toward a
+/// MaskProvider's active slots. With maxSlots=1, the MaskProvider must
+/// become Ready after verification and a waiting Mask must be assigned
+/// the slot as soon as the verification Mask releases it.
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn verification_slots() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    if get_actual_provider_secret(client.clone()).await?.is_none() {
+        // Verification requires real VPN credentials.
+        println!("Skipping verification_slots test: no VPN credentials available.");
+        return Ok(());
+    }
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_label = test_provider_name(&uid);
+
+    // Watch for the MaskProvider to become Ready and the Mask to become Active.
+    let provider_ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let mask_active = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(async move { wait_for_mask_phase(client, &namespace, 0, MaskPhase::Active).await })
+    };
+
+    // Create the MaskProvider, which will be verified before it becomes
+    // Ready, and a Mask that has to wait for verification to finish.
+    create_test_provider(client.clone(), &namespace, &uid).await?;
+    create_test_mask(client.clone(), &namespace, 0, &provider_label).await?;
+
+    // The MaskProvider should not be stuck in the Active phase
+    // because of the verification Mask's reservation.
+    provider_ready.await.unwrap()?;
+
+    // The waiting Mask should be assigned promptly after verification.
+    timeout(Duration::from_secs(60), mask_active)
+        .await
+        .map_err(|_| Error::Other("Mask not assigned promptly after verification".to_owned()))?
+        .unwrap()?;
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
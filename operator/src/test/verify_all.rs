@@ -0,0 +1,280 @@
+use clap::Parser;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use kube::{
+    api::{Patch, PatchParams},
+    client::Client,
+    Api,
+};
+use serde_json::json;
+use std::time::Duration;
+use vpn_types::*;
+
+use super::{
+    mock::{mock_method_routes, status_failure},
+    util::*,
+};
+use crate::{
+    verify_all::{
+        self, outcome, summary, VerifyAllFilter, VerifyOutcome, VerifyResult, VERIFY_MARGIN,
+    },
+    Cli, Command,
+};
+
+/// Returns a MaskProvider with the given namespace, tags and status.
+fn tagged_provider(
+    namespace: &str,
+    tags: &[&str],
+    status: Option<MaskProviderStatus>,
+) -> MaskProvider {
+    let mut provider = mask_provider("my-vpn", namespace, "provider-uid");
+    provider.spec.tags = Some(tags.iter().map(|t| (*t).to_owned()).collect());
+    provider.status = status;
+    provider
+}
+
+/// Returns a status in the phase that recorded the manual trigger.
+fn finished(phase: MaskProviderPhase, trigger: Option<&str>) -> Option<MaskProviderStatus> {
+    Some(MaskProviderStatus {
+        phase: Some(phase),
+        last_manual_verify: trigger.map(|t| t.to_owned()),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn providers_filtered() {
+    let all = VerifyAllFilter::default();
+    assert!(all.matches(&tagged_provider("vpn", &[], None)));
+
+    let filter = VerifyAllFilter {
+        namespace: Some("vpn".to_owned()),
+        tags: vec!["eu".to_owned(), "us".to_owned()],
+    };
+    assert!(filter.matches(&tagged_provider("vpn", &["us"], None)));
+    assert!(!filter.matches(&tagged_provider("vpn", &["ap"], None)));
+    assert!(!filter.matches(&tagged_provider("other", &["eu"], None)));
+
+    // MaskProviders being deleted won't be verified again.
+    let mut deleted = tagged_provider("vpn", &["eu"], None);
+    deleted.metadata.deletion_timestamp = Some(Time(chrono::Utc::now()));
+    assert!(!filter.matches(&deleted));
+}
+
+#[test]
+fn outcome_awaits_trigger() {
+    let trigger = "verify-all-1";
+    let cases = [
+        ("no status", None, None),
+        (
+            "previous trigger",
+            finished(MaskProviderPhase::ErrVerifyFailed, Some("verify-all-0")),
+            None,
+        ),
+        (
+            "verifying",
+            finished(MaskProviderPhase::Verifying, None),
+            None,
+        ),
+        (
+            "verified",
+            finished(MaskProviderPhase::Verified, Some(trigger)),
+            Some(VerifyOutcome::Verified),
+        ),
+        (
+            "ready again",
+            finished(MaskProviderPhase::Ready, Some(trigger)),
+            Some(VerifyOutcome::Verified),
+        ),
+        (
+            "failed",
+            finished(MaskProviderPhase::ErrVerifyFailed, Some(trigger)),
+            Some(VerifyOutcome::Failed),
+        ),
+    ];
+    for (case, status, expected) in cases {
+        assert_eq!(
+            outcome(&tagged_provider("vpn", &[], status), trigger),
+            expected,
+            "{}",
+            case
+        );
+    }
+}
+
+#[test]
+fn summary_counts_outcomes() {
+    let result = |name: &str, outcome| VerifyResult {
+        namespace: "vpn".to_owned(),
+        name: name.to_owned(),
+        outcome,
+        duration: Duration::from_secs(75),
+        message: None,
+    };
+    let results = [
+        result("a", VerifyOutcome::Verified),
+        VerifyResult {
+            message: Some("Verification timed out.".to_owned()),
+            ..result("b", VerifyOutcome::Failed)
+        },
+        result("c", VerifyOutcome::Skipped),
+    ];
+    assert_eq!(
+        summary(&results),
+        "MaskProviders:\n\
+         NAMESPACE  NAME  RESULT    DURATION  MESSAGE\n\
+         vpn        a     Verified  1m15s     -\n\
+         vpn        b     Failed    1m15s     Verification timed out.\n\
+         vpn        c     Skipped   1m15s     -\n\
+         \n\
+         1 verified, 1 failed, 0 timed out, 1 skipped, 0 errors\n"
+    );
+    assert!(results.iter().any(|r| r.outcome.is_failure()));
+    assert!(!VerifyOutcome::Skipped.is_failure());
+    assert!(VerifyOutcome::Error.is_failure());
+}
+
+#[tokio::test]
+async fn provider_errors_collected() {
+    let provider = |name: &str, skip: bool| {
+        let mut provider = mask_provider(name, "vpn", &format!("{}-uid", name));
+        provider.spec.verify = Some(MaskProviderVerifySpec {
+            skip: Some(skip),
+            ..Default::default()
+        });
+        provider
+    };
+    let list = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskProviderList",
+        "metadata": {},
+        "items": [provider("a", false), provider("b", true)],
+    });
+    // Triggering the verification is refused, which fails only that one.
+    let (client, _) = mock_method_routes(vec![
+        ("GET", "/apis/vpn.beebs.dev/v1/maskproviders", 200, list),
+        (
+            "PATCH",
+            "/apis/vpn.beebs.dev/v1/namespaces/vpn/maskproviders/a",
+            403,
+            status_failure(403),
+        ),
+    ]);
+    let results = verify_all::run(client, &VerifyAllFilter::default(), None, None)
+        .await
+        .unwrap();
+    let outcomes: Vec<_> = results
+        .iter()
+        .map(|r| (r.name.as_str(), r.outcome))
+        .collect();
+    assert_eq!(
+        outcomes,
+        [("a", VerifyOutcome::Error), ("b", VerifyOutcome::Skipped)]
+    );
+    assert!(results[0].message.is_some());
+    assert!(summary(&results).ends_with("0 timed out, 1 skipped, 1 errors\n"));
+}
+
+#[test]
+fn command_parsed() {
+    let cli = Cli::try_parse_from([
+        "vpn-operator",
+        "--max-concurrent-verifications",
+        "2",
+        "verify-all",
+        "-n",
+        "vpn",
+        "--tag",
+        "eu",
+        "--tag",
+        "us",
+    ])
+    .unwrap();
+    assert_eq!(cli.max_concurrent_verifications, Some(2));
+    match cli.command {
+        Command::VerifyAll { namespace, tags } => {
+            assert_eq!(namespace.as_deref(), Some("vpn"));
+            assert_eq!(tags, vec!["eu", "us"]);
+        }
+        _ => panic!("expected verify-all"),
+    }
+}
+
+/// Creates a MaskProvider verified with the stand-in VPN, and waits
+/// for it to pass its first verification.
+async fn create_verified_provider(
+    client: Client,
+    namespace: &str,
+    name: &str,
+) -> Result<(), Error> {
+    let mut provider = get_test_provider(client.clone(), name, namespace).await?;
+    provider.spec.verify = Some(MaskProviderVerifySpec {
+        skip: Some(false),
+        timeout: Some("50s".to_owned()),
+        overrides: Some(fake_verify_overrides()),
+        ..Default::default()
+    });
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), namespace);
+    let provider = api.create(&Default::default(), &provider).await?;
+    create_test_provider_secret(client, namespace, &provider).await?;
+    let deadline = std::time::Instant::now() + Duration::from_secs(180);
+    while std::time::Instant::now() < deadline {
+        let phase = api.get(name).await?.status.and_then(|s| s.phase);
+        if phase == Some(MaskProviderPhase::Ready) {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    Err(Error::Other(format!("MaskProvider {} not Ready", name)))
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn verify_all_reports_failures() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let passing = format!("{}-a", test_provider_name(&uid));
+    let failing = format!("{}-b", test_provider_name(&uid));
+    create_verified_provider(client.clone(), &namespace, &passing).await?;
+    create_verified_provider(client.clone(), &namespace, &failing).await?;
+
+    // The second MaskProvider's probe never finishes from now on.
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let patch = json!({
+        "spec": {
+            "verify": {
+                "timeout": "10s",
+                "overrides": {
+                    "containers": { "probe": { "command": ["sleep", "3600"] } },
+                },
+            },
+        },
+    });
+    api.patch(&failing, &PatchParams::default(), &Patch::Merge(&patch))
+        .await?;
+
+    // Verify them one at a time, as with --max-concurrent-verifications=1.
+    let filter = VerifyAllFilter {
+        namespace: Some(namespace.clone()),
+        tags: vec![],
+    };
+    let results = verify_all::run(client.clone(), &filter, None, Some(1))
+        .await
+        .unwrap();
+    let outcomes: Vec<(&str, VerifyOutcome)> = results
+        .iter()
+        .map(|r| (r.name.as_str(), r.outcome))
+        .collect();
+    assert_eq!(
+        outcomes,
+        vec![
+            (passing.as_str(), VerifyOutcome::Verified),
+            (failing.as_str(), VerifyOutcome::Failed),
+        ]
+    );
+    assert!(results[1].duration < Duration::from_secs(10) + VERIFY_MARGIN);
+    assert!(summary(&results).contains("1 verified, 1 failed"));
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
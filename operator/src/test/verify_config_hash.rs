@@ -0,0 +1,148 @@
+use kube::{
+    api::{Patch, PatchParams},
+    client::Client,
+    Api,
+};
+use serde_json::json;
+use tokio::spawn;
+use vpn_types::*;
+
+use super::util::*;
+use crate::providers::verify_hash::{config_hash, is_current};
+
+/// Returns a MaskProvider whose credentials have the given checksum.
+fn hashed_provider(secret_hash: &str) -> MaskProvider {
+    let mut provider = mask_provider("test-provider", "default", "provider-uid");
+    provider.status = Some(MaskProviderStatus {
+        secret_hash: Some(secret_hash.to_owned()),
+        ..Default::default()
+    });
+    provider
+}
+
+/// Returns verification settings with the given timeout.
+fn with_timeout(timeout: &str) -> MaskProviderVerifySpec {
+    MaskProviderVerifySpec {
+        timeout: Some(timeout.to_owned()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn config_hash_inputs() {
+    let hash = |instance: &MaskProvider, verify: Option<MaskProviderVerifySpec>| {
+        config_hash(instance, verify.as_ref()).unwrap()
+    };
+    let instance = hashed_provider("abc");
+    let base = hash(&instance, Some(with_timeout("1m")));
+    assert_eq!(base, hash(&instance, Some(with_timeout("1m"))));
+
+    // Changing the credentials, the settings or the image changes it.
+    assert_ne!(
+        base,
+        hash(&hashed_provider("def"), Some(with_timeout("1m")))
+    );
+    assert_ne!(base, hash(&instance, Some(with_timeout("2m"))));
+    let overrides = MaskProviderVerifySpec {
+        overrides: Some(fake_verify_overrides()),
+        ..with_timeout("1m")
+    };
+    assert_ne!(base, hash(&instance, Some(overrides)));
+    let mut pinned = instance.clone();
+    pinned.spec.gluetun_version = Some("v3.38.0".to_owned());
+    assert_ne!(base, hash(&pinned, Some(with_timeout("1m"))));
+
+    // Whether and when verification runs doesn't change what's verified.
+    let skipped = MaskProviderVerifySpec {
+        skip: Some(true),
+        interval: Some("1h".to_owned()),
+        ..with_timeout("1m")
+    };
+    assert_eq!(base, hash(&instance, Some(skipped)));
+    let interval_only = MaskProviderVerifySpec {
+        interval: Some("1h".to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(hash(&instance, None), hash(&instance, Some(interval_only)));
+}
+
+#[test]
+fn verification_current() {
+    let verify = Some(with_timeout("1m"));
+    let mut instance = hashed_provider("abc");
+    let status = instance.status.as_mut().unwrap();
+    status.last_verified = Some("2023-01-01T00:00:00Z".to_owned());
+
+    // A lastVerified without the checksum says nothing about what was
+    // verified, e.g. if it was set by hand.
+    assert!(!is_current(&instance, verify.as_ref()).unwrap());
+
+    let hash = config_hash(&instance, verify.as_ref()).unwrap();
+    instance.status.as_mut().unwrap().last_verified_config_hash = Some(hash);
+    assert!(is_current(&instance, verify.as_ref()).unwrap());
+
+    // The credentials changed since.
+    instance.status.as_mut().unwrap().secret_hash = Some("def".to_owned());
+    assert!(!is_current(&instance, verify.as_ref()).unwrap());
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn unskipped_provider_verified() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+
+    // Create a MaskProvider that skips verification.
+    let mut provider = get_test_provider(client.clone(), &provider_name, &namespace).await?;
+    let verify = provider.spec.verify.as_mut().unwrap();
+    verify.skip = Some(true);
+    verify.overrides = Some(fake_verify_overrides());
+    let ready = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(
+            async move { wait_for_provider_phase(client, &namespace, MaskProviderPhase::Ready).await },
+        )
+    };
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let provider = api.create(&Default::default(), &provider).await?;
+    create_test_provider_secret(client.clone(), &namespace, &provider).await?;
+    ready.await.unwrap()?;
+
+    // Set lastVerified by hand, as a migration script might.
+    let status = json!({ "status": { "lastVerified": "2023-01-01T00:00:00Z" } });
+    api.patch_status(
+        &provider_name,
+        &PatchParams::default(),
+        &Patch::Merge(&status),
+    )
+    .await?;
+
+    // Enabling verification verifies the credentials anyway.
+    let verified = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(async move {
+            wait_for_provider_phase(client, &namespace, MaskProviderPhase::Verified).await
+        })
+    };
+    let unskip = json!({ "spec": { "verify": { "skip": false } } });
+    api.patch(
+        &provider_name,
+        &PatchParams::default(),
+        &Patch::Merge(&unskip),
+    )
+    .await?;
+    verified.await.unwrap()?;
+    let status = api.get(&provider_name).await?.status.unwrap();
+    assert_ne!(
+        status.last_verified.as_deref(),
+        Some("2023-01-01T00:00:00Z")
+    );
+    assert!(status.last_verified_config_hash.is_some());
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,357 @@
+use chrono::Utc;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use kube::{
+    api::{ObjectMeta, Patch, PatchParams},
+    client::Client,
+    Api,
+};
+use serde_json::json;
+use std::time::{Duration, Instant};
+use vpn_types::*;
+
+use super::{mock::*, util::*};
+use crate::{
+    providers::verify_failure::{evict, evicts, list_evictable},
+    util::{messages, VERIFY_NOW_ANNOTATION},
+};
+
+/// Returns a MaskProvider in the phase with the given action, verified
+/// before if `verified` is true.
+fn failing_provider(
+    phase: MaskProviderPhase,
+    action: Option<VerifyFailureAction>,
+    verified: bool,
+) -> MaskProvider {
+    let mut provider = mask_provider("my-vpn", "vpn", "provider-uid");
+    provider.spec.on_verify_failure = action;
+    provider.status = Some(MaskProviderStatus {
+        phase: Some(phase),
+        last_verified: verified.then(|| "2023-03-01T00:00:00+00:00".to_owned()),
+        ..Default::default()
+    });
+    provider
+}
+
+/// Returns the reservation of the MaskConsumer with the name and UID.
+fn reservation(name: &str, uid: &str) -> MaskReservation {
+    MaskReservation {
+        metadata: ObjectMeta {
+            name: Some(format!("my-vpn-{}", name)),
+            namespace: Some("vpn".to_owned()),
+            ..Default::default()
+        },
+        spec: MaskReservationSpec {
+            name: name.to_owned(),
+            namespace: "team".to_owned(),
+            uid: uid.to_owned(),
+            slot: Some(0),
+        },
+        status: None,
+    }
+}
+
+/// Returns the MaskConsumer with the name and UID, owned by the Mask of the same name.
+fn owned_consumer(name: &str, uid: &str) -> MaskConsumer {
+    let mut consumer = mask_consumer(name, "team", uid);
+    consumer.metadata.owner_references = Some(vec![mask_owner_ref(name)]);
+    consumer
+}
+
+#[test]
+fn evicts_after_failed_reverification() {
+    let evict = Some(VerifyFailureAction::Evict);
+    assert!(evicts(&failing_provider(
+        MaskProviderPhase::ErrVerifyFailed,
+        evict,
+        true
+    )));
+
+    // Consumers are kept by default.
+    assert!(!evicts(&failing_provider(
+        MaskProviderPhase::ErrVerifyFailed,
+        None,
+        true
+    )));
+    assert!(!evicts(&failing_provider(
+        MaskProviderPhase::ErrVerifyFailed,
+        Some(VerifyFailureAction::Keep),
+        true
+    )));
+    // A MaskProvider that never passed verification has nothing to evict.
+    assert!(!evicts(&failing_provider(
+        MaskProviderPhase::ErrVerifyFailed,
+        evict,
+        false
+    )));
+    assert!(!evicts(&failing_provider(
+        MaskProviderPhase::Verifying,
+        evict,
+        true
+    )));
+    assert!(!evicts(&failing_provider(
+        MaskProviderPhase::Active,
+        evict,
+        true
+    )));
+}
+
+#[test]
+fn eviction_messages() {
+    assert_eq!(
+        messages::verify_failure_evicted("vpn", "my-vpn"),
+        "MaskProvider vpn/my-vpn failed re-verification and has onVerifyFailure: Evict, so the Mask was unassigned and its credentials deleted. It will be assigned another MaskProvider if one is available."
+    );
+    assert_eq!(
+        messages::verify_failure_evicting(2),
+        "Re-verification failed and onVerifyFailure is Evict, evicting 2 MaskConsumer(s)."
+    );
+}
+
+#[test]
+fn spec_deserialized() {
+    let spec: MaskProviderSpec = serde_json::from_value(json!({
+        "maxSlots": 1,
+        "secret": "my-credentials",
+        "onVerifyFailure": "Evict",
+    }))
+    .unwrap();
+    assert_eq!(spec.on_verify_failure, Some(VerifyFailureAction::Evict));
+    assert_eq!(VerifyFailureAction::default(), VerifyFailureAction::Keep);
+}
+
+#[tokio::test]
+async fn evictable_consumers_listed() {
+    let mut deleting = owned_consumer("deleting", "deleting-uid");
+    deleting.metadata.deletion_timestamp = Some(Time(Utc::now()));
+    let (client, _) = mock_routes(vec![
+        (
+            "/apis/vpn.beebs.dev/v1/namespaces/team/maskconsumers/assigned",
+            json!(owned_consumer("assigned", "assigned-uid")),
+        ),
+        (
+            "/apis/vpn.beebs.dev/v1/namespaces/team/maskconsumers/replaced",
+            json!(owned_consumer("replaced", "new-uid")),
+        ),
+        (
+            "/apis/vpn.beebs.dev/v1/namespaces/team/maskconsumers/deleting",
+            json!(deleting),
+        ),
+    ]);
+    let reservations = vec![
+        reservation("assigned", "assigned-uid"),
+        // The MaskConsumer was recreated and doesn't hold the reservation.
+        reservation("replaced", "old-uid"),
+        reservation("deleting", "deleting-uid"),
+        // The MaskConsumer is gone.
+        reservation("gone", "gone-uid"),
+    ];
+    let consumers = list_evictable(client, &reservations).await.unwrap();
+    assert_eq!(consumers, vec![owned_consumer("assigned", "assigned-uid")]);
+}
+
+#[tokio::test]
+async fn consumers_evicted() {
+    let mask = Mask {
+        metadata: ObjectMeta {
+            name: Some("assigned".to_owned()),
+            namespace: Some("team".to_owned()),
+            uid: Some("mask-uid".to_owned()),
+            ..Default::default()
+        },
+        status: Some(MaskStatus {
+            phase: Some(MaskPhase::Active),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let (client, captured) = mock_method_routes(vec![
+        (
+            "GET",
+            "/apis/vpn.beebs.dev/v1/namespaces/team/masks/assigned",
+            200,
+            json!(mask),
+        ),
+        (
+            "PATCH",
+            "/apis/vpn.beebs.dev/v1/namespaces/team/masks/assigned/status",
+            200,
+            json!(mask),
+        ),
+        ("POST", "/apis/events.k8s.io/v1/namespaces", 201, json!({})),
+        (
+            "DELETE",
+            "/apis/vpn.beebs.dev/v1/namespaces/team/maskconsumers/assigned",
+            200,
+            json!(owned_consumer("assigned", "assigned-uid")),
+        ),
+    ]);
+    let instance = failing_provider(
+        MaskProviderPhase::ErrVerifyFailed,
+        Some(VerifyFailureAction::Evict),
+        true,
+    );
+    evict(
+        client,
+        &instance,
+        &[owned_consumer("assigned", "assigned-uid")],
+    )
+    .await
+    .unwrap();
+
+    let captured = captured.lock().unwrap();
+    // The eviction is attributed to the failed verification on both sides.
+    let events: Vec<_> = captured.iter().filter(|r| r.method == "POST").collect();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].body["reason"], "VerifyFailureEviction");
+    assert_eq!(events[0].body["regarding"]["kind"], "MaskProvider");
+    assert_eq!(events[1].body["reason"], "VerifyFailureEviction");
+    assert_eq!(events[1].body["regarding"]["name"], "assigned");
+    let patch = captured
+        .iter()
+        .find(|r| r.method == "PATCH")
+        .expect("Mask status was not updated");
+    let withdrawn = patch_op(patch, "/status/providerWithdrawn").unwrap();
+    assert_eq!(
+        withdrawn["message"],
+        json!(messages::verify_failure_evicted("vpn", "my-vpn"))
+    );
+
+    // Only the evicted MaskConsumer is deleted, never its replacement.
+    let delete = captured
+        .iter()
+        .find(|r| r.method == "DELETE")
+        .expect("MaskConsumer was not deleted");
+    assert_eq!(delete.body["preconditions"]["uid"], "assigned-uid");
+}
+
+/// Returns the MaskProvider once `done` returns true for it.
+async fn wait_for_provider(
+    api: &Api<MaskProvider>,
+    name: &str,
+    done: impl Fn(&MaskProvider) -> bool,
+) -> Result<MaskProvider, Error> {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(180) {
+        let provider = api.get(name).await?;
+        if done(&provider) {
+            return Ok(provider);
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    Err(Error::Other(format!(
+        "MaskProvider {} not in expected state before timeout",
+        name
+    )))
+}
+
+/// Returns the phase of the MaskProvider.
+fn phase(provider: &MaskProvider) -> Option<MaskProviderPhase> {
+    provider.status.as_ref().and_then(|s| s.phase)
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn verify_failure_eviction() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let tag = test_provider_name(&uid);
+
+    // The first MaskProvider is verified with a stand-in VPN, and evicts
+    // its MaskConsumers if re-verification fails. It has a slot to spare
+    // for verifying while both Masks are assigned.
+    let mut failing = get_test_provider(client.clone(), &format!("{}-a", tag), &namespace).await?;
+    failing.spec.max_slots = 3;
+    failing.spec.tags = Some(vec![tag.clone()]);
+    failing.spec.on_verify_failure = Some(VerifyFailureAction::Evict);
+    failing.spec.verify = Some(MaskProviderVerifySpec {
+        skip: Some(false),
+        timeout: Some("50s".to_owned()),
+        overrides: Some(fake_verify_overrides()),
+        ..Default::default()
+    });
+    let failing = api.create(&Default::default(), &failing).await?;
+    create_test_provider_secret(client.clone(), &namespace, &failing).await?;
+    let failing_name = failing.metadata.name.clone().unwrap();
+    wait_for_provider(&api, &failing_name, |p| {
+        phase(p) == Some(MaskProviderPhase::Ready)
+    })
+    .await?;
+
+    // Both Masks are assigned the first MaskProvider, as it's the only one.
+    for index in 0..2 {
+        create_test_mask(client.clone(), &namespace, index, &tag).await?;
+        wait_for_mask_phase(client.clone(), &namespace, index, MaskPhase::Active).await?;
+    }
+
+    // Add a healthy MaskProvider for the Masks to migrate to.
+    let mut healthy = get_test_provider(client.clone(), &format!("{}-b", tag), &namespace).await?;
+    healthy.spec.max_slots = 2;
+    healthy.spec.tags = Some(vec![tag.clone()]);
+    healthy.spec.verify = Some(MaskProviderVerifySpec {
+        skip: Some(true),
+        ..Default::default()
+    });
+    let healthy = api.create(&Default::default(), &healthy).await?;
+    create_test_provider_secret(client.clone(), &namespace, &healthy).await?;
+    let healthy_name = healthy.metadata.name.clone().unwrap();
+    wait_for_provider(&api, &healthy_name, |p| {
+        phase(p) == Some(MaskProviderPhase::Ready)
+    })
+    .await?;
+
+    // Force re-verification to fail: the probe never finishes.
+    let patch = json!({
+        "metadata": { "annotations": { VERIFY_NOW_ANNOTATION: "1" } },
+        "spec": {
+            "verify": {
+                "timeout": "10s",
+                "overrides": {
+                    "containers": { "probe": { "command": ["sleep", "3600"] } },
+                },
+            },
+        },
+    });
+    api.patch(
+        &failing_name,
+        &PatchParams::default(),
+        &Patch::Merge(&patch),
+    )
+    .await?;
+    wait_for_provider(&api, &failing_name, |p| {
+        phase(p) == Some(MaskProviderPhase::ErrVerifyFailed)
+    })
+    .await?;
+
+    // Both Masks migrate to the healthy MaskProvider and are told why.
+    let consumer_api: Api<MaskConsumer> = Api::namespaced(client.clone(), &namespace);
+    let mask_api: Api<Mask> = Api::namespaced(client.clone(), &namespace);
+    for index in 0..2 {
+        let name = format!("{}-{}", MASK_NAME, index);
+        let start = Instant::now();
+        loop {
+            assert!(
+                start.elapsed() < Duration::from_secs(120),
+                "Mask {} did not migrate before timeout",
+                name
+            );
+            let assigned = consumer_api
+                .get_opt(&name)
+                .await?
+                .and_then(|c| c.status)
+                .and_then(|s| s.provider);
+            if assigned.is_some_and(|p| p.name == healthy_name) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        wait_for_mask_phase(client.clone(), &namespace, index, MaskPhase::Active).await?;
+        let mask = mask_api.get(&name).await?;
+        let last_provider = mask.status.unwrap().last_provider_uid;
+        assert_eq!(last_provider, healthy.metadata.uid.clone());
+    }
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
@@ -0,0 +1,378 @@
+use k8s_openapi::{api::core::v1::Secret, ByteString};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use vpn_types::*;
+
+use super::mock::{self, decide, merged};
+use crate::{
+    consumers::rollout::credentials_hash,
+    providers::{
+        actions::{keys_verified, ready, verified},
+        capacity::Capacity,
+        reconcile::{determine_action, MaskProviderAction},
+        verify_keys::{check, compile_patterns},
+    },
+    util::{finalizer::FINALIZER_NAME, messages, PROVIDER_SECRET_LABEL},
+};
+
+/// Returns a Secret with the given data.
+fn secret(pairs: &[(&str, &str)]) -> Secret {
+    Secret {
+        data: Some(data(pairs)),
+        ..Default::default()
+    }
+}
+
+/// Returns Secret data with the given keys and values.
+fn data(pairs: &[(&str, &str)]) -> BTreeMap<String, ByteString> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), ByteString(v.as_bytes().to_vec())))
+        .collect()
+}
+
+/// Returns `KeysOnly` settings with the given required keys and patterns.
+fn keys_only(required: &[&str], patterns: &[(&str, &str)]) -> MaskProviderVerifySpec {
+    MaskProviderVerifySpec {
+        mode: Some(MaskProviderVerifyMode::KeysOnly),
+        required_keys: Some(required.iter().map(|k| k.to_string()).collect()),
+        key_patterns: Some(
+            patterns
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        ),
+        ..Default::default()
+    }
+}
+
+/// Returns the result of the static checks of the Secret under the settings.
+fn checked(verify: &MaskProviderVerifySpec, secret: &Secret) -> Result<(), String> {
+    check(verify, &compile_patterns(verify).unwrap(), secret)
+}
+
+#[test]
+fn key_checks() {
+    let verify = keys_only(
+        &["OPENVPN_USER"],
+        &[("VPN_SERVICE_PROVIDER", "nordvpn|surfshark")],
+    );
+    let credentials = [
+        ("OPENVPN_USER", "user"),
+        ("VPN_SERVICE_PROVIDER", "nordvpn"),
+    ];
+    assert_eq!(checked(&verify, &secret(&credentials)), Ok(()));
+
+    // Required keys must be present with non-empty values.
+    assert_eq!(
+        checked(&verify, &secret(&[("VPN_SERVICE_PROVIDER", "nordvpn")])),
+        Err(messages::verify_key_missing("OPENVPN_USER"))
+    );
+    assert_eq!(
+        checked(
+            &verify,
+            &secret(&[("OPENVPN_USER", ""), ("VPN_SERVICE_PROVIDER", "nordvpn")])
+        ),
+        Err(messages::verify_key_empty("OPENVPN_USER"))
+    );
+
+    // Keys with a pattern are required as well.
+    assert_eq!(
+        checked(&verify, &secret(&[("OPENVPN_USER", "user")])),
+        Err(messages::verify_key_missing("VPN_SERVICE_PROVIDER"))
+    );
+
+    // Without any required keys, the Secret only needs to have one.
+    let verify_any = keys_only(&[], &[]);
+    assert_eq!(checked(&verify_any, &secret(&credentials)), Ok(()));
+    assert_eq!(
+        checked(&verify_any, &secret(&[])),
+        Err(messages::VERIFY_NO_KEYS.to_owned())
+    );
+
+    // stringData is checked along with data.
+    let string_data = Secret {
+        string_data: Some(
+            credentials
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        ),
+        ..Default::default()
+    };
+    assert_eq!(checked(&verify, &string_data), Ok(()));
+}
+
+#[test]
+fn key_patterns() {
+    let verify = keys_only(&[], &[("VPN_SERVICE_PROVIDER", "nordvpn|surfshark")]);
+    for (value, matches) in [
+        ("nordvpn", true),
+        ("surfshark", true),
+        // Patterns match the whole value, even with alternation.
+        ("nordvpn2", false),
+        ("my-surfshark", false),
+        ("mullvad", false),
+    ] {
+        let result = checked(&verify, &secret(&[("VPN_SERVICE_PROVIDER", value)]));
+        assert_eq!(result.is_ok(), matches, "{}", value);
+    }
+    assert_eq!(
+        checked(&verify, &secret(&[("VPN_SERVICE_PROVIDER", "mullvad")])),
+        Err(messages::verify_key_mismatch(
+            "VPN_SERVICE_PROVIDER",
+            "nordvpn|surfshark"
+        ))
+    );
+
+    // The mismatch message never shows the value, as it may be secret.
+    let verify = keys_only(&[], &[("OPENVPN_PASSWORD", "[a-z]{8,}")]);
+    let message = checked(&verify, &secret(&[("OPENVPN_PASSWORD", "hunter2")])).unwrap_err();
+    assert!(!message.contains("hunter2"), "{}", message);
+
+    // A pattern that isn't a valid regular expression is reported by key.
+    let invalid = keys_only(&[], &[("SERVER_REGIONS", "(Netherlands")]);
+    let message = compile_patterns(&invalid).unwrap_err();
+    assert!(message.contains("SERVER_REGIONS"), "{}", message);
+}
+
+/// Returns a Pending MaskProvider that was never verified, with `patch`
+/// merged into it, and its credentials Secret with the given data.
+fn never_verified(patch: Value, pairs: &[(&str, &str)]) -> (Value, Value) {
+    let data = data(pairs);
+    let provider = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskProvider",
+        "metadata": {
+            "name": "provider",
+            "namespace": "providers",
+            "uid": "provider-uid",
+            "finalizers": [FINALIZER_NAME],
+        },
+        "spec": { "secret": "provider-credentials", "maxSlots": 2 },
+        "status": {
+            "secretHash": credentials_hash(Some(&data)),
+            "phase": "Pending",
+            "message": "Pending",
+            "lastUpdated": chrono::Utc::now().to_rfc3339(),
+            "availableSlots": 2,
+            "slotsSummary": "2/2",
+            "pendingDemand": 0,
+        },
+    });
+    let secret = json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {
+            "name": "provider-credentials",
+            "namespace": "providers",
+            "labels": { PROVIDER_SECRET_LABEL: "true" },
+        },
+        "data": data,
+    });
+    (merged(provider, patch), secret)
+}
+
+/// Returns the action decided for the MaskProvider with the given
+/// verification settings and objects in the cluster.
+async fn action(
+    provider: Value,
+    verify: Option<MaskProviderVerifySpec>,
+    objects: &[Value],
+) -> MaskProviderAction {
+    let instance: MaskProvider = serde_json::from_value(provider).unwrap();
+    decide(objects, |client| {
+        let instance = instance.clone();
+        let verify = verify.clone();
+        async move {
+            determine_action(
+                client,
+                "provider",
+                "providers",
+                &instance,
+                verify,
+                None,
+                &Default::default(),
+                &Default::default(),
+                &[],
+                &[],
+                chrono::Utc::now(),
+            )
+            .await
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn keys_only_actions() {
+    let credentials = [("OPENVPN_USER", "user")];
+    let verify = Some(keys_only(&["OPENVPN_USER"], &[]));
+
+    // Credentials that are dialed get a verification Mask.
+    let (provider, secret) = never_verified(json!({}), &credentials);
+    assert_eq!(
+        action(provider.clone(), None, std::slice::from_ref(&secret)).await,
+        MaskProviderAction::CreateVerifyMask {
+            manual: false,
+            verify: None,
+        }
+    );
+
+    // Static checks run right away, without creating anything.
+    assert_eq!(
+        action(provider, verify.clone(), &[secret]).await,
+        MaskProviderAction::KeysVerified {
+            manual: false,
+            verify: verify.clone(),
+        }
+    );
+
+    // Failing them fails verification, like a failed dial.
+    let (provider, secret) = never_verified(json!({}), &[("OPENVPN_PASSWORD", "secret")]);
+    let message = messages::verify_key_missing("OPENVPN_USER");
+    assert_eq!(
+        action(provider, verify.clone(), &[secret]).await,
+        MaskProviderAction::KeysVerifyFailed {
+            manual: false,
+            message: message.clone(),
+        }
+    );
+
+    // The failure isn't written again once it's shown.
+    let (provider, secret) = never_verified(
+        json!({ "status": { "phase": "ErrVerifyFailed", "message": message } }),
+        &[("OPENVPN_PASSWORD", "secret")],
+    );
+    assert_eq!(
+        action(provider, verify, &[secret]).await,
+        MaskProviderAction::NoOp
+    );
+
+    // An invalid pattern is a configuration error, not a failed check.
+    let invalid = keys_only(&[], &[("OPENVPN_USER", "(")]);
+    let (provider, secret) = never_verified(json!({}), &credentials);
+    assert!(matches!(
+        action(provider, Some(invalid), &[secret]).await,
+        MaskProviderAction::ConfigError(_)
+    ));
+}
+
+#[tokio::test]
+async fn verified_messages() {
+    let instance = MaskProvider {
+        metadata: kube::api::ObjectMeta {
+            name: Some("provider".to_owned()),
+            namespace: Some("providers".to_owned()),
+            ..Default::default()
+        },
+        status: Some(MaskProviderStatus {
+            phase: Some(MaskProviderPhase::Verifying),
+            last_verified_node: Some("kind-worker".to_owned()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    // Dialed credentials say they were verified as authentic.
+    let (client, captured) = mock::mock_client(serde_json::to_value(&instance).unwrap());
+    verified(client, &instance, None, None, None, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        mock::patch_op(&captured.lock().unwrap()[0], "/status/message"),
+        Some(&json!(messages::VERIFIED))
+    );
+
+    // Statically checked credentials say they weren't dialed, and don't
+    // keep the node of an earlier dial.
+    let verify = Some(keys_only(&["OPENVPN_USER"], &[]));
+    let (client, captured) = mock::mock_client(serde_json::to_value(&instance).unwrap());
+    keys_verified(client, &instance, verify, None)
+        .await
+        .unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(
+        mock::patch_op(&captured[0], "/status/phase"),
+        Some(&json!("Verified"))
+    );
+    assert_eq!(
+        mock::patch_op(&captured[0], "/status/message"),
+        Some(&json!(messages::KEYS_VERIFIED))
+    );
+    assert_ne!(messages::KEYS_VERIFIED, messages::VERIFIED);
+    assert!(mock::patch_op(&captured[0], "/status/lastVerified").is_some());
+    assert_eq!(
+        mock::patch_op(&captured[0], "/status/lastVerifiedNode"),
+        Some(&Value::Null)
+    );
+}
+
+#[tokio::test]
+async fn keys_only_note_persists() {
+    let mut instance = MaskProvider {
+        metadata: kube::api::ObjectMeta {
+            name: Some("provider".to_owned()),
+            namespace: Some("providers".to_owned()),
+            ..Default::default()
+        },
+        status: Some(MaskProviderStatus {
+            phase: Some(MaskProviderPhase::Verifying),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    // The mode is recorded along with the verification.
+    let verify = Some(keys_only(&["OPENVPN_USER"], &[]));
+    let (client, captured) = mock::mock_client(serde_json::to_value(&instance).unwrap());
+    keys_verified(client, &instance, verify, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        mock::patch_op(&captured.lock().unwrap()[0], "/status/lastVerifiedMode"),
+        Some(&json!("KeysOnly"))
+    );
+
+    // Once Ready, the message still says the credentials weren't dialed.
+    let status = instance.status.as_mut().unwrap();
+    status.phase = Some(MaskProviderPhase::Verified);
+    status.last_verified_mode = Some(MaskProviderVerifyMode::KeysOnly);
+    let (client, captured) = mock::mock_client(serde_json::to_value(&instance).unwrap());
+    ready(
+        client,
+        &instance,
+        Capacity::default(),
+        vec![],
+        None,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    let message = mock::patch_op(&captured.lock().unwrap()[0], "/status/message")
+        .and_then(Value::as_str)
+        .map(str::to_owned)
+        .unwrap();
+    assert!(message.ends_with(messages::KEYS_ONLY_NOTE), "{}", message);
+
+    // Credentials verified by dialing don't get the note.
+    let status = instance.status.as_mut().unwrap();
+    status.last_verified_mode = Some(MaskProviderVerifyMode::Dial);
+    let (client, captured) = mock::mock_client(serde_json::to_value(&instance).unwrap());
+    ready(
+        client,
+        &instance,
+        Capacity::default(),
+        vec![],
+        None,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        mock::patch_op(&captured.lock().unwrap()[0], "/status/message"),
+        Some(&json!("VPN service is ready to use."))
+    );
+}
@@ -0,0 +1,72 @@
+use k8s_openapi::api::events::v1::Event;
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    client::Client,
+    Api,
+};
+use tokio::spawn;
+use vpn_types::*;
+
+use super::util::*;
+
+/// Bumps the verify-now annotation on the MaskProvider.
+async fn bump_verify_now(
+    client: Client,
+    namespace: &str,
+    name: &str,
+    value: &str,
+) -> Result<(), Error> {
+    let api: Api<MaskProvider> = Api::namespaced(client, namespace);
+    let patch = serde_json::json!({
+        "metadata": {
+            "annotations": {
+                "vpn.beebs.dev/verify-now": value,
+            },
+        },
+    });
+    api.patch(name, &PatchParams::default(), &Patch::Merge(&patch))
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn verify_now() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+
+    // Create a MaskProvider that is verified. With mock credentials
+    // the verification will fail, but each trigger still results in
+    // a full verification cycle that records the annotation value.
+    let mut provider = get_test_provider(client.clone(), &provider_name, &namespace).await?;
+    provider.spec.verify.as_mut().unwrap().skip = Some(false);
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let provider = api.create(&Default::default(), &provider).await?;
+    create_test_provider_secret(client.clone(), &namespace, &provider).await?;
+
+    // Trigger verification twice and observe two cycles.
+    for value in ["1", "2"] {
+        let verified = {
+            let client = client.clone();
+            let namespace = namespace.clone();
+            spawn(async move { wait_for_manual_verify(client, &namespace, value).await })
+        };
+        bump_verify_now(client.clone(), &namespace, &provider_name, value).await?;
+        verified.await.unwrap()?;
+    }
+
+    // Each manual trigger is marked with an event.
+    let events: Api<Event> = Api::namespaced(client.clone(), &namespace);
+    let manual_triggers = events
+        .list(&ListParams::default())
+        .await?
+        .into_iter()
+        .filter(|e| e.reason.as_deref() == Some("ManualVerify"))
+        .count();
+    assert_eq!(manual_triggers, 2);
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
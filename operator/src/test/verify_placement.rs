@@ -0,0 +1,215 @@
+use k8s_openapi::api::core::v1::{Node, Secret};
+use kube::{
+    api::{ListParams, ObjectMeta},
+    client::Client,
+    Api,
+};
+use serde_json::json;
+use std::collections::BTreeMap;
+use tokio::spawn;
+use vpn_render::{node_selector, ZONE_LABEL};
+use vpn_types::*;
+
+use super::{mock, util::*};
+use crate::providers::actions::{verified, verify_pod};
+
+/// Returns a node selector with the given labels.
+fn labels(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn zone_expands_to_node_selector() {
+    // No placement, no selector.
+    assert_eq!(node_selector(&Default::default()), None);
+
+    // The zone alone becomes a selector on the zone label.
+    let zone_only = MaskProviderVerifyPlacementSpec {
+        zone: Some("eu-west-2a".to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(
+        node_selector(&zone_only),
+        Some(labels(&[(ZONE_LABEL, "eu-west-2a")]))
+    );
+
+    // It's merged with the other labels, and wins over an explicit zone.
+    let both = MaskProviderVerifyPlacementSpec {
+        node_selector: Some(labels(&[
+            ("kubernetes.io/arch", "amd64"),
+            (ZONE_LABEL, "us-east-1a"),
+        ])),
+        zone: Some("eu-west-2a".to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(
+        node_selector(&both),
+        Some(labels(&[
+            ("kubernetes.io/arch", "amd64"),
+            (ZONE_LABEL, "eu-west-2a"),
+        ]))
+    );
+}
+
+/// Returns a MaskProvider verified with the given placement and overrides.
+fn placed_provider(
+    placement: MaskProviderVerifyPlacementSpec,
+    overrides: Option<MaskProviderVerifyOverridesSpec>,
+) -> MaskProvider {
+    let mut provider = mask_provider("test-provider", "default", "provider-uid");
+    provider.spec.verify = Some(MaskProviderVerifySpec {
+        placement: Some(placement),
+        overrides,
+        ..Default::default()
+    });
+    provider
+}
+
+/// Returns the verification Pod's spec for the MaskProvider.
+fn verify_pod_spec(provider: &MaskProvider) -> k8s_openapi::api::core::v1::PodSpec {
+    let consumer = MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some("test-provider-verify".to_owned()),
+            uid: Some("consumer-uid".to_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let secret = Secret {
+        metadata: ObjectMeta {
+            name: Some("test-provider-verify-provider-uid".to_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    verify_pod("test-provider", "default", provider, &secret, &consumer)
+        .unwrap()
+        .spec
+        .unwrap()
+}
+
+#[test]
+fn placement_applied_to_verify_pod() {
+    let affinity = json!({
+        "nodeAffinity": {
+            "requiredDuringSchedulingIgnoredDuringExecution": {
+                "nodeSelectorTerms": [{
+                    "matchExpressions": [{
+                        "key": "node.kubernetes.io/instance-type",
+                        "operator": "NotIn",
+                        "values": ["t3.micro"],
+                    }],
+                }],
+            },
+        },
+    });
+    let placement = MaskProviderVerifyPlacementSpec {
+        zone: Some("eu-west-2a".to_owned()),
+        affinity: Some(affinity.clone()),
+        ..Default::default()
+    };
+    let spec = verify_pod_spec(&placed_provider(placement.clone(), None));
+    assert_eq!(
+        spec.node_selector,
+        Some(labels(&[(ZONE_LABEL, "eu-west-2a")]))
+    );
+    assert_eq!(serde_json::to_value(spec.affinity).unwrap(), affinity);
+
+    // Pod overrides are applied on top of the placement.
+    let overrides = MaskProviderVerifyOverridesSpec {
+        pod: Some(json!({ "spec": { "nodeSelector": { "pool": "vpn" } } })),
+        ..Default::default()
+    };
+    let spec = verify_pod_spec(&placed_provider(placement, Some(overrides)));
+    assert_eq!(
+        spec.node_selector,
+        Some(labels(&[(ZONE_LABEL, "eu-west-2a"), ("pool", "vpn")]))
+    );
+}
+
+#[tokio::test]
+async fn verified_records_node_and_zone() {
+    let instance = MaskProvider {
+        status: Some(MaskProviderStatus {
+            phase: Some(MaskProviderPhase::Verifying),
+            ..Default::default()
+        }),
+        ..placed_provider(Default::default(), None)
+    };
+    let (client, captured) = mock::mock_client(serde_json::to_value(&instance).unwrap());
+    verified(
+        client,
+        &instance,
+        None,
+        Some("kind-worker".to_owned()),
+        Some("eu-west-2a".to_owned()),
+        None,
+    )
+    .await
+    .unwrap();
+    let captured = captured.lock().unwrap();
+    assert_eq!(
+        mock::patch_op(&captured[0], "/status/lastVerifiedNode"),
+        Some(&json!("kind-worker"))
+    );
+    assert_eq!(
+        mock::patch_op(&captured[0], "/status/lastVerifiedZone"),
+        Some(&json!("eu-west-2a"))
+    );
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn verified_from_zone() -> Result<(), Error> {
+    // Requires a node labeled with a zone, e.g. a kind cluster created
+    // with `topology.kubernetes.io/zone` set on its workers.
+    let client: Client = Client::try_default().await.unwrap();
+    let zone = Api::<Node>::all(client.clone())
+        .list(&ListParams::default().labels(ZONE_LABEL))
+        .await?
+        .into_iter()
+        .find_map(|node| node.metadata.labels?.remove(ZONE_LABEL))
+        .expect("no node is labeled with a zone");
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+
+    // Verify the MaskProvider from the node's zone with the fake harness.
+    let mut provider = get_test_provider(client.clone(), &provider_name, &namespace).await?;
+    let verify = provider.spec.verify.as_mut().unwrap();
+    verify.skip = Some(false);
+    verify.overrides = Some(fake_verify_overrides());
+    verify.placement = Some(MaskProviderVerifyPlacementSpec {
+        zone: Some(zone.clone()),
+        ..Default::default()
+    });
+    let verified = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(async move {
+            wait_for_provider_phase(client, &namespace, MaskProviderPhase::Verified).await
+        })
+    };
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let provider = api.create(&Default::default(), &provider).await?;
+    create_test_provider_secret(client.clone(), &namespace, &provider).await?;
+    verified.await.unwrap()?;
+
+    // The zone is recorded, along with a node that is in it.
+    let status = api.get(&provider_name).await?.status.unwrap();
+    assert_eq!(status.last_verified_zone.as_deref(), Some(zone.as_str()));
+    let verified_node = status.last_verified_node.expect("node was not recorded");
+    let node_zone = Api::<Node>::all(client.clone())
+        .get(&verified_node)
+        .await?
+        .metadata
+        .labels
+        .and_then(|mut labels| labels.remove(ZONE_LABEL));
+    assert_eq!(node_zone, Some(zone));
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
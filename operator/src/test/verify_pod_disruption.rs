@@ -0,0 +1,335 @@
+use k8s_openapi::api::core::v1::Pod;
+use serde_json::{json, Value};
+use vpn_render::{PROBE_CONTAINER_NAME, VPN_CONTAINER_NAME};
+use vpn_types::*;
+
+use super::mock::{decide, merged, provider_value};
+use crate::{
+    providers::{
+        disruption::{verify_pod_disruption, MAX_VERIFY_RESCHEDULES},
+        reconcile::{determine_action, MaskProviderAction},
+    },
+    util::{
+        capabilities::Capabilities, messages, PROVIDER_SECRET_LABEL, VERIFY_RESCHEDULED_ANNOTATION,
+    },
+};
+
+/// Returns the MaskProvider's credentials Secret.
+fn secret() -> Value {
+    json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {
+            "name": "provider-credentials",
+            "namespace": "providers",
+            "labels": { PROVIDER_SECRET_LABEL: "true" },
+        },
+        "data": {},
+    })
+}
+
+/// Returns the verification Mask created `age` ago, which recreated the
+/// Pods with the given uids.
+fn verify_mask(age: chrono::Duration, rescheduled: &[&str]) -> Value {
+    json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "Mask",
+        "metadata": {
+            "name": "provider-verify",
+            "namespace": "providers",
+            "creationTimestamp": (chrono::Utc::now() - age).to_rfc3339(),
+            "annotations": { VERIFY_RESCHEDULED_ANNOTATION: rescheduled.join(",") },
+        },
+        "spec": {},
+        "status": { "phase": "Active" },
+    })
+}
+
+/// Returns the verification Pod with `status` merged into its status.
+fn verify_pod(status: Value) -> Value {
+    let pod = json!({
+        "apiVersion": "v1",
+        "kind": "Pod",
+        "metadata": {
+            "name": "provider",
+            "namespace": "providers",
+            "uid": "pod-uid",
+            "creationTimestamp": chrono::Utc::now().to_rfc3339(),
+        },
+        "spec": { "containers": [], "nodeName": "node-a" },
+        "status": { "phase": "Running" },
+    });
+    merged(pod, json!({ "status": status }))
+}
+
+/// Returns the status of a Pod whose node stopped reporting.
+fn node_lost() -> Value {
+    json!({ "phase": "Unknown", "reason": "NodeLost" })
+}
+
+/// Returns the status of a Pod evicted by the kubelet.
+fn evicted() -> Value {
+    json!({ "phase": "Failed", "reason": "Evicted" })
+}
+
+/// Returns the status of a Pod that is being evicted for a drain.
+fn draining() -> Value {
+    json!({ "conditions": [{
+        "type": "DisruptionTarget",
+        "status": "True",
+        "reason": "EvictionByEvictionAPI",
+    }]})
+}
+
+/// Returns the status of a Pod whose VPN container crashed.
+fn crashed() -> Value {
+    json!({
+        "phase": "Failed",
+        "containerStatuses": [{
+            "name": VPN_CONTAINER_NAME,
+            "image": "",
+            "imageID": "",
+            "ready": false,
+            "restartCount": 0,
+            "state": { "terminated": { "exitCode": 1 } },
+        }],
+    })
+}
+
+/// Returns the action decided for the MaskProvider with the given objects.
+async fn action(objects: &[Value]) -> MaskProviderAction {
+    action_on(objects, Capabilities::default()).await
+}
+
+/// Returns the action decided for the MaskProvider with the given
+/// objects, against an API server with the given capabilities.
+async fn action_on(objects: &[Value], capabilities: Capabilities) -> MaskProviderAction {
+    let verifying = provider_value(json!({ "status": {
+        "phase": "Verifying",
+        "message": "Created verification Pod.",
+        "lastVerified": null,
+        "availableSlots": null,
+        "slotsSummary": null,
+        "pendingDemand": null,
+    } }));
+    let instance: MaskProvider = serde_json::from_value(verifying).unwrap();
+    decide(objects, |client| {
+        let instance = instance.clone();
+        let capabilities = capabilities.clone();
+        async move {
+            determine_action(
+                client,
+                "provider",
+                "providers",
+                &instance,
+                None,
+                None,
+                &capabilities,
+                &Default::default(),
+                &[],
+                &[],
+                chrono::Utc::now(),
+            )
+            .await
+        }
+    })
+    .await
+}
+
+#[test]
+fn disruptions_recognized() {
+    let pod = |status: Value| -> Pod { serde_json::from_value(verify_pod(status)).unwrap() };
+    assert_eq!(
+        verify_pod_disruption(&pod(node_lost())).as_deref(),
+        Some("NodeLost")
+    );
+    assert_eq!(
+        verify_pod_disruption(&pod(json!({ "phase": "Unknown" }))).as_deref(),
+        Some("NodeLost")
+    );
+    assert_eq!(
+        verify_pod_disruption(&pod(evicted())).as_deref(),
+        Some("Evicted")
+    );
+    assert_eq!(
+        verify_pod_disruption(&pod(draining())).as_deref(),
+        Some("EvictionByEvictionAPI")
+    );
+    assert_eq!(verify_pod_disruption(&pod(crashed())), None);
+    assert_eq!(verify_pod_disruption(&pod(json!({}))), None);
+}
+
+#[tokio::test]
+async fn disrupted_pods_rescheduled() {
+    let fresh = chrono::Duration::seconds(10);
+    for (case, status, reason) in [
+        ("node lost", node_lost(), "NodeLost"),
+        ("evicted", evicted(), "Evicted"),
+        ("draining", draining(), "EvictionByEvictionAPI"),
+    ] {
+        let objects = [secret(), verify_mask(fresh, &[]), verify_pod(status)];
+        assert_eq!(
+            action(&objects).await,
+            MaskProviderAction::RescheduleVerifyPod {
+                rescheduled: vec!["pod-uid".to_owned()],
+                reason: reason.to_owned(),
+            },
+            "{}",
+            case
+        );
+    }
+}
+
+#[tokio::test]
+async fn crashed_pod_fails() {
+    let objects = [
+        secret(),
+        verify_mask(chrono::Duration::seconds(10), &[]),
+        verify_pod(crashed()),
+    ];
+    assert_eq!(
+        action(&objects).await,
+        MaskProviderAction::VerifyFailed("Unknown error occurred during verification.".to_owned())
+    );
+}
+
+#[tokio::test]
+async fn reschedules_bounded() {
+    let fresh = chrono::Duration::seconds(10);
+
+    // Earlier recreations are remembered across Pods.
+    let objects = [
+        secret(),
+        verify_mask(fresh, &["first-uid"]),
+        verify_pod(node_lost()),
+    ];
+    assert_eq!(
+        action(&objects).await,
+        MaskProviderAction::RescheduleVerifyPod {
+            rescheduled: vec!["first-uid".to_owned(), "pod-uid".to_owned()],
+            reason: "NodeLost".to_owned(),
+        },
+    );
+
+    // A Pod that is already being recreated isn't counted twice.
+    let objects = [
+        secret(),
+        verify_mask(fresh, &["pod-uid"]),
+        verify_pod(node_lost()),
+    ];
+    assert_eq!(
+        action(&objects).await,
+        MaskProviderAction::AwaitVerifyCleanup
+    );
+
+    // The cycle fails once the limit is reached...
+    let uids: Vec<String> = (0..MAX_VERIFY_RESCHEDULES)
+        .map(|i| format!("uid-{}", i))
+        .collect();
+    let uids: Vec<&str> = uids.iter().map(String::as_str).collect();
+    let objects = [secret(), verify_mask(fresh, &uids), verify_pod(evicted())];
+    assert_eq!(
+        action(&objects).await,
+        MaskProviderAction::VerifyFailed(messages::verify_pod_disrupted(
+            "Evicted",
+            MAX_VERIFY_RESCHEDULES
+        )),
+    );
+
+    // ...or the verification timeout is used up.
+    let objects = [
+        secret(),
+        verify_mask(chrono::Duration::minutes(5), &[]),
+        verify_pod(evicted()),
+    ];
+    assert_eq!(
+        action(&objects).await,
+        MaskProviderAction::VerifyFailed(messages::verify_pod_disrupted("Evicted", 0)),
+    );
+}
+
+#[tokio::test]
+async fn succeeded_pod_not_rescheduled() {
+    // A Pod that verified the credentials before its node went away passes.
+    let status = merged(
+        node_lost(),
+        json!({ "containerStatuses": [
+            {
+                "name": VPN_CONTAINER_NAME,
+                "image": "",
+                "imageID": "",
+                "ready": false,
+                "restartCount": 0,
+                "state": { "running": {} },
+            },
+            {
+                "name": PROBE_CONTAINER_NAME,
+                "image": "",
+                "imageID": "",
+                "ready": false,
+                "restartCount": 0,
+                "state": { "terminated": { "exitCode": 0 } },
+            },
+        ]}),
+    );
+    let objects = [
+        secret(),
+        verify_mask(chrono::Duration::seconds(10), &[]),
+        verify_pod(status),
+    ];
+    assert_eq!(
+        action(&objects).await,
+        MaskProviderAction::Verified {
+            node: Some("node-a".to_owned()),
+            note: None,
+        }
+    );
+}
+
+/// Returns the verification Pod being deleted while `status` applies.
+fn deleted_pod(status: Value) -> Value {
+    let deletion = json!({ "metadata": { "deletionTimestamp": chrono::Utc::now().to_rfc3339() } });
+    merged(verify_pod(status), deletion)
+}
+
+/// Returns the capabilities of a 1.25 API server, which doesn't mark
+/// disrupted Pods with the `DisruptionTarget` condition.
+fn without_disruption_conditions() -> Capabilities {
+    Capabilities {
+        minor: 25,
+        git_version: "v1.25.16".to_owned(),
+        disruption_conditions: false,
+        ..Capabilities::default()
+    }
+}
+
+#[tokio::test]
+async fn unmarked_deletion_rescheduled_on_old_servers() {
+    let fresh = chrono::Duration::seconds(10);
+    let objects = [secret(), verify_mask(fresh, &[]), deleted_pod(json!({}))];
+    assert_eq!(
+        action_on(&objects, without_disruption_conditions()).await,
+        MaskProviderAction::RescheduleVerifyPod {
+            rescheduled: vec!["pod-uid".to_owned()],
+            reason: "Deleted".to_owned(),
+        },
+    );
+
+    // Newer servers mark disrupted Pods, so an unmarked deletion is
+    // the cleanup of a finished verification.
+    assert_eq!(
+        action_on(&objects, Capabilities::default()).await,
+        MaskProviderAction::AwaitVerifyCleanup
+    );
+
+    // A Pod that already finished is being cleaned up.
+    let objects = [
+        secret(),
+        verify_mask(fresh, &[]),
+        deleted_pod(json!({ "phase": "Succeeded" })),
+    ];
+    assert_eq!(
+        action_on(&objects, without_disruption_conditions()).await,
+        MaskProviderAction::AwaitVerifyCleanup
+    );
+}
@@ -0,0 +1,264 @@
+use k8s_openapi::api::core::v1::{Pod, Secret};
+use kube::api::ObjectMeta;
+use serde_json::{json, Value};
+use vpn_types::*;
+
+use super::{
+    mock::{decide, mock_cluster, provider_value},
+    util::mask_provider,
+};
+use crate::{
+    providers::{
+        actions::verify_pod,
+        disruption::verify_pod_disruption,
+        operation::VerifyStep,
+        placement::PriorityClasses,
+        reconcile::{determine_action, MaskProviderAction},
+        verify_defaults::effective_verify,
+    },
+    util::{messages, PROVIDER_SECRET_LABEL},
+};
+
+/// Returns a MaskProvider verified with the given settings.
+fn verifying_provider(verify: Option<MaskProviderVerifySpec>) -> MaskProvider {
+    let mut provider = mask_provider("test-provider", "default", "provider-uid");
+    provider.spec.verify = verify;
+    provider
+}
+
+/// Returns verification settings with the given PriorityClass.
+fn with_priority(name: &str) -> MaskProviderVerifySpec {
+    MaskProviderVerifySpec {
+        priority_class_name: Some(name.to_owned()),
+        ..Default::default()
+    }
+}
+
+/// Returns the priorityClassName of the MaskProvider's verification Pod.
+fn pod_priority(provider: &MaskProvider) -> Option<String> {
+    let consumer = MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some("test-provider-verify".to_owned()),
+            uid: Some("consumer-uid".to_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let secret = Secret {
+        metadata: ObjectMeta {
+            name: Some("test-provider-verify-provider-uid".to_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    verify_pod("test-provider", "default", provider, &secret, &consumer)
+        .unwrap()
+        .spec
+        .unwrap()
+        .priority_class_name
+}
+
+/// Returns a PriorityClass with the given name.
+fn priority_class(name: &str) -> Value {
+    json!({
+        "apiVersion": "scheduling.k8s.io/v1",
+        "kind": "PriorityClass",
+        "metadata": { "name": name },
+        "value": 1000,
+    })
+}
+
+#[test]
+fn priority_class_applied_to_verify_pod() {
+    assert_eq!(pod_priority(&verifying_provider(None)), None);
+    assert_eq!(
+        pod_priority(&verifying_provider(Some(with_priority("verification")))).as_deref(),
+        Some("verification")
+    );
+
+    // Pod overrides are applied on top of it.
+    let verify = MaskProviderVerifySpec {
+        overrides: Some(MaskProviderVerifyOverridesSpec {
+            pod: Some(json!({ "spec": { "priorityClassName": "critical" } })),
+            ..Default::default()
+        }),
+        ..with_priority("verification")
+    };
+    assert_eq!(
+        pod_priority(&verifying_provider(Some(verify))).as_deref(),
+        Some("critical")
+    );
+}
+
+#[test]
+fn priority_class_defaulted() {
+    let default = with_priority("verification");
+    let verify = effective_verify(&verifying_provider(None), Some(&default)).unwrap();
+    assert_eq!(
+        verify.unwrap().priority_class_name.as_deref(),
+        Some("verification")
+    );
+
+    // The MaskProvider's own PriorityClass takes precedence.
+    let own = with_priority("critical");
+    let verify = effective_verify(&verifying_provider(Some(own)), Some(&default)).unwrap();
+    assert_eq!(
+        verify.unwrap().priority_class_name.as_deref(),
+        Some("critical")
+    );
+}
+
+#[tokio::test]
+async fn unknown_priority_class_warned() {
+    let (client, captured) = mock_cluster(vec![priority_class("verification")]);
+    let priority_classes = PriorityClasses::default();
+    let warnings = |verify: Option<MaskProviderVerifySpec>| {
+        let client = client.clone();
+        let priority_classes = &priority_classes;
+        async move {
+            priority_classes
+                .warnings(client, verify.as_ref())
+                .await
+                .unwrap()
+        }
+    };
+    assert!(warnings(None).await.is_empty());
+    assert!(warnings(Some(with_priority("verification")))
+        .await
+        .is_empty());
+    assert_eq!(
+        warnings(Some(with_priority("missing"))).await,
+        vec![messages::unknown_priority_class("missing")]
+    );
+
+    // Nothing is checked if verification is skipped.
+    let skipped = MaskProviderVerifySpec {
+        skip: Some(true),
+        ..with_priority("missing")
+    };
+    assert!(warnings(Some(skipped)).await.is_empty());
+
+    // Each PriorityClass is fetched once, however often it's checked.
+    assert!(warnings(Some(with_priority("verification")))
+        .await
+        .is_empty());
+    assert_eq!(
+        warnings(Some(with_priority("missing"))).await,
+        vec![messages::unknown_priority_class("missing")]
+    );
+    assert_eq!(captured.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn verification_waits_for_priority_class() {
+    let secret = json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {
+            "name": "provider-credentials",
+            "namespace": "providers",
+            "labels": { PROVIDER_SECRET_LABEL: "true" },
+        },
+        "data": {},
+    });
+    // The verification Mask was assigned a slot and its MaskConsumer exists.
+    let mask = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "Mask",
+        "metadata": { "name": "provider-verify", "namespace": "providers", "uid": "mask-uid" },
+        "spec": {},
+        "status": { "phase": "Active" },
+    });
+    let consumer = json!({
+        "apiVersion": "vpn.beebs.dev/v1",
+        "kind": "MaskConsumer",
+        "metadata": {
+            "name": "provider-verify",
+            "namespace": "providers",
+            "uid": "consumer-uid",
+            "ownerReferences": [{
+                "apiVersion": "vpn.beebs.dev/v1",
+                "kind": "Mask",
+                "name": "provider-verify",
+                "uid": "mask-uid",
+            }],
+        },
+        "spec": {},
+    });
+    let action = |priority_class_name: &str| {
+        let verifying = provider_value(json!({ "status": {
+            "phase": "Verifying",
+            "message": "Created verification Mask.",
+            "lastVerified": null,
+            "effectiveVerify": { "priorityClassName": priority_class_name },
+        } }));
+        let instance: MaskProvider = serde_json::from_value(verifying).unwrap();
+        let objects = [
+            secret.clone(),
+            mask.clone(),
+            consumer.clone(),
+            priority_class("verification"),
+        ];
+        async move {
+            decide(&objects, |client| {
+                let instance = instance.clone();
+                async move {
+                    determine_action(
+                        client,
+                        "provider",
+                        "providers",
+                        &instance,
+                        None,
+                        None,
+                        &Default::default(),
+                        &Default::default(),
+                        &[],
+                        &[],
+                        chrono::Utc::now(),
+                    )
+                    .await
+                }
+            })
+            .await
+        }
+    };
+    assert!(matches!(
+        action("verification").await,
+        MaskProviderAction::CreateVerifyPod(_)
+    ));
+    // The API server would refuse the Pod, so verification waits with a
+    // warning rather than failing to create it.
+    assert_eq!(
+        action("missing").await,
+        MaskProviderAction::Verifying {
+            message: messages::unknown_priority_class("missing"),
+            step: VerifyStep::SlotAssigned,
+            start_time: None,
+        }
+    );
+}
+
+#[test]
+fn preemption_is_a_disruption() {
+    let pod = |status: Value| -> Pod {
+        serde_json::from_value(json!({
+            "metadata": { "name": "test-provider" },
+            "status": status,
+        }))
+        .unwrap()
+    };
+    let preempted = json!({ "conditions": [{
+        "type": "DisruptionTarget",
+        "status": "True",
+        "reason": "PreemptionByScheduler",
+    }]});
+    assert_eq!(
+        verify_pod_disruption(&pod(preempted)).as_deref(),
+        Some("PreemptionByScheduler")
+    );
+    let preempting = json!({ "phase": "Failed", "reason": "Preempting" });
+    assert_eq!(
+        verify_pod_disruption(&pod(preempting)).as_deref(),
+        Some("Preempting")
+    );
+}
@@ -0,0 +1,210 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use serde_json::{json, Value};
+use std::time::Duration;
+use vpn_types::*;
+
+use super::{
+    mock::{decide, with_verified_config},
+    util::mask_provider,
+};
+use crate::{
+    consumers::rollout::credentials_hash,
+    providers::{
+        reconcile::{determine_action, MaskProviderAction},
+        verify_schedule::{jitter, next_verification, spread},
+    },
+    util::{clock, finalizer::FINALIZER_NAME, PROVIDER_SECRET_LABEL},
+};
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+/// Returns verification settings with the interval and spread.
+fn verify(interval: &str, spread: Option<&str>) -> MaskProviderVerifySpec {
+    MaskProviderVerifySpec {
+        interval: Some(interval.to_owned()),
+        spread: spread.map(str::to_owned),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn jitter_deterministic() {
+    let spread = 24 * HOUR / 10;
+    let first = jitter("3f6c1c9e-5d0b-4a8e-9c1e-0d3b2a4f5e6d", spread);
+    // Recomputing it, e.g. after a restart, gives the same offset.
+    for _ in 0..10 {
+        assert_eq!(
+            jitter("3f6c1c9e-5d0b-4a8e-9c1e-0d3b2a4f5e6d", spread),
+            first
+        );
+    }
+    assert!(first < spread);
+    // Another MaskProvider gets another offset.
+    assert_ne!(
+        jitter("0d3b2a4f-5e6d-4a8e-9c1e-3f6c1c9e5d0b", spread),
+        first
+    );
+    // Nothing is spread without a window.
+    assert_eq!(jitter("3f6c1c9e", Duration::ZERO), Duration::ZERO);
+}
+
+#[test]
+fn jitter_spread_evenly() {
+    // MaskProviders created by the same apply are spread across the window.
+    let spread = 24 * HOUR / 10;
+    let mut buckets = [0usize; 10];
+    for i in 0..10_000 {
+        let offset = jitter(&format!("provider-{}-uid", i), spread);
+        assert!(offset < spread);
+        buckets[(offset.as_millis() * 10 / spread.as_millis()) as usize] += 1;
+    }
+    for (i, count) in buckets.iter().enumerate() {
+        assert!(
+            (800..1200).contains(count),
+            "bucket {} has {} of 10000 offsets: {:?}",
+            i,
+            count,
+            buckets
+        );
+    }
+}
+
+#[test]
+fn spread_defaults_to_tenth_of_interval() {
+    assert_eq!(
+        spread(&verify("24h", None), 24 * HOUR).unwrap(),
+        24 * HOUR / 10
+    );
+    assert_eq!(spread(&verify("24h", Some("1h")), 24 * HOUR).unwrap(), HOUR);
+    assert_eq!(
+        spread(&verify("24h", Some("0s")), 24 * HOUR).unwrap(),
+        Duration::ZERO
+    );
+    assert!(spread(&verify("24h", Some("soon")), 24 * HOUR).is_err());
+}
+
+#[test]
+fn next_verification_offset() {
+    let last_verified = "2023-06-14T09:00:00Z";
+    let mut instance = mask_provider("provider", "providers", "provider-uid");
+    instance.spec.secret = "provider-credentials".to_owned();
+    instance.spec.max_slots = 1;
+    instance.status = Some(MaskProviderStatus {
+        last_verified: Some(last_verified.to_owned()),
+        ..Default::default()
+    });
+    let last_verified = clock::parse_timestamp(last_verified).unwrap();
+    let offset = ChronoDuration::from_std(jitter("provider-uid", HOUR)).unwrap();
+    assert_eq!(
+        next_verification(&instance, Some(&verify("24h", Some("1h")))).unwrap(),
+        Some(last_verified + ChronoDuration::hours(24) + offset)
+    );
+    // Without a spread, it's exactly the interval.
+    assert_eq!(
+        next_verification(&instance, Some(&verify("24h", Some("0s")))).unwrap(),
+        Some(last_verified + ChronoDuration::hours(24))
+    );
+    // Credentials that aren't verified periodically have no next time.
+    let once = MaskProviderVerifySpec::default();
+    assert_eq!(next_verification(&instance, Some(&once)).unwrap(), None);
+    assert_eq!(next_verification(&instance, None).unwrap(), None);
+    // Neither do credentials that were never verified.
+    let mut unverified = instance;
+    unverified.status = None;
+    assert_eq!(
+        next_verification(&unverified, Some(&verify("24h", None))).unwrap(),
+        None
+    );
+}
+
+/// Returns the action decided for the verified MaskProvider with the uid,
+/// last verified at the time with the settings.
+async fn action(
+    uid: &str,
+    last_verified: &str,
+    verify: MaskProviderVerifySpec,
+) -> MaskProviderAction {
+    let instance = with_verified_config(json!({
+            "apiVersion": "vpn.beebs.dev/v1",
+            "kind": "MaskProvider",
+            "metadata": {
+                "name": "provider",
+                "namespace": "providers",
+                "uid": uid,
+                "finalizers": [FINALIZER_NAME],
+            },
+            "spec": { "secret": "provider-credentials", "maxSlots": 1 },
+            "status": {
+                "secretHash": credentials_hash(None),
+                "phase": "Ready",
+                "message": "Ready",
+                "lastUpdated": clock::now_k8s(),
+                "lastVerified": last_verified,
+            },
+    }));
+    let instance: MaskProvider = serde_json::from_value(instance).unwrap();
+    let secret: Value = json!({
+        "apiVersion": "v1",
+        "kind": "Secret",
+        "metadata": {
+            "name": "provider-credentials",
+            "namespace": "providers",
+            "labels": { PROVIDER_SECRET_LABEL: "true" },
+        },
+        "data": {},
+    });
+    decide(&[secret], |client| {
+        let instance = instance.clone();
+        let verify = verify.clone();
+        async move {
+            determine_action(
+                client,
+                "provider",
+                "providers",
+                &instance,
+                Some(verify),
+                None,
+                &Default::default(),
+                &Default::default(),
+                &[],
+                &[],
+                Utc::now(),
+            )
+            .await
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn reverification_waits_for_offset() {
+    // Find MaskProviders whose offsets are in the first and last quarter
+    // of the window.
+    let spread = 4 * HOUR;
+    let uid = |quarter: u32| {
+        (0..)
+            .map(|i| format!("provider-{}-uid", i))
+            .find(|uid| (jitter(uid, spread).as_secs() * 4 / spread.as_secs()) as u32 == quarter)
+            .unwrap()
+    };
+    let (early, late) = (uid(0), uid(3));
+    // Both were verified at the same time, the interval and half the
+    // window ago.
+    let last_verified = clock::format_timestamp(Utc::now() - ChronoDuration::hours(26));
+    let settings = verify("24h", Some("4h"));
+    assert!(matches!(
+        action(&early, &last_verified, settings.clone()).await,
+        MaskProviderAction::CreateVerifyMask { manual: false, .. }
+    ));
+    // The other one isn't due until later in the window, which its
+    // status shows.
+    let next = clock::parse_timestamp(&last_verified).unwrap()
+        + ChronoDuration::hours(24)
+        + ChronoDuration::from_std(jitter(&late, spread)).unwrap();
+    match action(&late, &last_verified, settings).await {
+        MaskProviderAction::Ready {
+            next_verification, ..
+        } => assert_eq!(next_verification, Some(clock::format_timestamp(next))),
+        action => panic!("unexpected action {:?}", action),
+    }
+}
@@ -0,0 +1,172 @@
+use chrono::{DateTime, Utc};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{OwnerReference, Time};
+use kube::api::ObjectMeta;
+use std::{collections::BTreeMap, time::Duration};
+use vpn_types::*;
+
+use crate::{
+    masks::util::{consumer_labels, get_purpose, label_changes},
+    providers::actions::verify_mask_stalled,
+    util::{messages, verified_provider_uid, VERIFICATION_LABEL},
+};
+
+/// Time the tests' verification Mask was created.
+const CREATED: &str = "2023-03-01T00:00:00Z";
+
+/// Returns the verification Mask of the MaskProvider with the uid,
+/// with or without its verification label.
+fn verify_mask(labeled: bool) -> Mask {
+    Mask {
+        metadata: ObjectMeta {
+            name: Some("my-provider-verify".to_owned()),
+            namespace: Some("vpn".to_owned()),
+            creation_timestamp: Some(Time(CREATED.parse().unwrap())),
+            labels: Some(BTreeMap::from_iter(
+                labeled.then(|| (VERIFICATION_LABEL.to_owned(), "provider-uid".to_owned())),
+            )),
+            owner_references: Some(vec![OwnerReference {
+                api_version: "vpn.beebs.dev/v1".to_owned(),
+                kind: "MaskProvider".to_owned(),
+                name: "my-provider".to_owned(),
+                uid: "provider-uid".to_owned(),
+                controller: Some(true),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+        status: Some(MaskStatus {
+            phase: Some(MaskPhase::Waiting),
+            message: Some("No MaskProviders are available.".to_owned()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Returns the time the given duration after the Mask was created.
+fn after(duration: Duration) -> DateTime<Utc> {
+    CREATED.parse::<DateTime<Utc>>().unwrap() + chrono::Duration::from_std(duration).unwrap()
+}
+
+#[test]
+fn stripped_label_restored() {
+    let mask = verify_mask(true);
+    let labels = consumer_labels(&mask);
+    assert_eq!(
+        labels.get(VERIFICATION_LABEL).map(String::as_str),
+        Some("provider-uid")
+    );
+
+    // Something stripped the label from the MaskConsumer after it was
+    // created, so it's no longer recognized as verifying the MaskProvider.
+    let consumer = MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some("my-provider-verify".to_owned()),
+            namespace: Some("vpn".to_owned()),
+            ..Default::default()
+        },
+        spec: MaskConsumerSpec {
+            purpose: Some(MaskConsumerPurpose::Verification),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    assert_eq!(verified_provider_uid(&consumer), None);
+
+    // The label is re-applied when the MaskConsumer is synchronized.
+    assert_eq!(
+        label_changes(&BTreeMap::new(), &labels),
+        BTreeMap::from([(
+            VERIFICATION_LABEL.to_owned(),
+            Some("provider-uid".to_owned())
+        )])
+    );
+}
+
+#[test]
+fn label_restored_from_owner() {
+    // The verification Mask lost its label as well. Its MaskConsumer still
+    // gets one from the MaskProvider controlling the Mask.
+    let mask = verify_mask(false);
+    assert_eq!(
+        consumer_labels(&mask)
+            .get(VERIFICATION_LABEL)
+            .map(String::as_str),
+        Some("provider-uid")
+    );
+    assert_eq!(get_purpose(&mask), MaskConsumerPurpose::Verification);
+
+    // Masks created for workloads don't get one.
+    let mut workload = verify_mask(false);
+    workload.metadata.owner_references = None;
+    assert!(consumer_labels(&workload).is_empty());
+    assert_eq!(get_purpose(&workload), MaskConsumerPurpose::Workload);
+}
+
+#[test]
+fn waiting_verification_times_out() {
+    let mask = verify_mask(true);
+    let timeout = Duration::from_secs(60);
+    assert_eq!(
+        verify_mask_stalled(&mask, None, timeout, after(Duration::from_secs(30))),
+        None
+    );
+    assert_eq!(
+        verify_mask_stalled(&mask, None, timeout, after(Duration::from_secs(90))),
+        Some(messages::verify_mask_stalled(
+            "vpn",
+            "my-provider-verify",
+            &timeout,
+            Some("No MaskProviders are available.")
+        ))
+    );
+
+    // Clock skew doesn't fail verification.
+    let skewed = CREATED.parse::<DateTime<Utc>>().unwrap() - chrono::Duration::seconds(90);
+    assert_eq!(verify_mask_stalled(&mask, None, timeout, skewed), None);
+}
+
+#[test]
+fn full_provider_keeps_waiting() {
+    // The MaskConsumer is recognized as verifying the MaskProvider, so the
+    // Mask is only waiting for a slot to be released. That isn't a failure
+    // of the credentials, no matter how long it takes.
+    let mask = verify_mask(true);
+    let mut consumer = MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some("my-provider-verify".to_owned()),
+            namespace: Some("vpn".to_owned()),
+            labels: Some(BTreeMap::from([(
+                VERIFICATION_LABEL.to_owned(),
+                "provider-uid".to_owned(),
+            )])),
+            ..Default::default()
+        },
+        spec: MaskConsumerSpec {
+            purpose: Some(MaskConsumerPurpose::Verification),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let timeout = Duration::from_secs(60);
+    let later = after(Duration::from_secs(3600));
+    assert_eq!(
+        verify_mask_stalled(&mask, Some(&consumer), timeout, later),
+        None
+    );
+
+    // A MaskConsumer verifying another MaskProvider is a stall.
+    consumer.metadata.labels = Some(BTreeMap::from([(
+        VERIFICATION_LABEL.to_owned(),
+        "other-uid".to_owned(),
+    )]));
+    assert!(verify_mask_stalled(&mask, Some(&consumer), timeout, later).is_some());
+}
+
+#[test]
+fn stalled_message_points_at_consumer() {
+    let message =
+        messages::verify_mask_stalled("vpn", "my-provider-verify", &Duration::from_secs(60), None);
+    assert!(message.contains("MaskConsumer 'vpn/my-provider-verify'"));
+    assert!(message.contains(VERIFICATION_LABEL));
+}
@@ -0,0 +1,278 @@
+use chrono::{DateTime, Utc};
+use futures::{StreamExt, TryStreamExt};
+use k8s_openapi::api::core::v1::{Pod, Secret};
+use kube::{
+    api::{ListParams, ObjectMeta, WatchEvent},
+    client::Client,
+    Api,
+};
+use std::collections::BTreeMap;
+use tokio::spawn;
+use vpn_render::PROBE_CONTAINER_NAME;
+use vpn_types::*;
+
+use super::{mock::mock_cluster, util::*};
+use crate::{
+    providers::{
+        actions::verify_pod,
+        operation::{VerifyStep, VERIFY_STEPS},
+        watches::{owned_mask_list_params, verify_consumer_provider, verify_pod_provider},
+    },
+    util::{CANARY_LABEL, MANAGER_NAME, VERIFICATION_LABEL},
+};
+
+/// Returns the labels of a resource created to verify the MaskProvider.
+fn verification_labels() -> Option<BTreeMap<String, String>> {
+    Some(BTreeMap::from([(
+        VERIFICATION_LABEL.to_owned(),
+        "provider-uid".to_owned(),
+    )]))
+}
+
+/// Returns a MaskConsumer assigned to the given MaskProvider.
+fn assigned_consumer(labels: Option<BTreeMap<String, String>>) -> MaskConsumer {
+    MaskConsumer {
+        metadata: ObjectMeta {
+            name: Some("test-provider-verify".to_owned()),
+            namespace: Some("default".to_owned()),
+            uid: Some("consumer-uid".to_owned()),
+            labels,
+            ..Default::default()
+        },
+        status: Some(MaskConsumerStatus {
+            provider: Some(AssignedProvider {
+                name: "test-provider".to_owned(),
+                namespace: "default".to_owned(),
+                uid: "provider-uid".to_owned(),
+                slot: 0,
+                reservation: "reservation-uid".to_owned(),
+                secret: "test-provider-verify-provider-uid".to_owned(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn verify_pod_maps_to_provider() {
+    let provider = MaskProvider {
+        metadata: ObjectMeta {
+            name: Some("test-provider".to_owned()),
+            namespace: Some("default".to_owned()),
+            uid: Some("provider-uid".to_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let consumer = assigned_consumer(verification_labels());
+    let secret = Secret {
+        metadata: ObjectMeta {
+            name: Some("test-provider-verify-provider-uid".to_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let pod = verify_pod("test-provider", "default", &provider, &secret, &consumer).unwrap();
+    let provider_ref = verify_pod_provider(pod).unwrap();
+    assert_eq!(provider_ref.name, "test-provider");
+    assert_eq!(provider_ref.namespace.as_deref(), Some("default"));
+
+    // Pods that weren't created for verification are ignored.
+    let unrelated = Pod {
+        metadata: ObjectMeta {
+            name: Some("test-provider".to_owned()),
+            namespace: Some("default".to_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    assert!(verify_pod_provider(unrelated).is_none());
+}
+
+#[test]
+fn verify_consumer_maps_to_provider() {
+    let provider_ref = verify_consumer_provider(assigned_consumer(verification_labels())).unwrap();
+    assert_eq!(provider_ref.name, "test-provider");
+    assert_eq!(provider_ref.namespace.as_deref(), Some("default"));
+
+    // Ordinary MaskConsumers are ignored, even if they're assigned
+    // to the same MaskProvider.
+    assert!(verify_consumer_provider(assigned_consumer(None)).is_none());
+
+    // The typed purpose takes precedence over the label.
+    let mut workload = assigned_consumer(verification_labels());
+    workload.spec.purpose = Some(MaskConsumerPurpose::Workload);
+    assert!(verify_consumer_provider(workload).is_none());
+    let mut verification = assigned_consumer(None);
+    verification.spec.purpose = Some(MaskConsumerPurpose::Verification);
+    assert!(verify_consumer_provider(verification).is_some());
+
+    // Unassigned verification MaskConsumers have nothing to report.
+    let mut unassigned = assigned_consumer(verification_labels());
+    unassigned.status = None;
+    assert!(verify_consumer_provider(unassigned).is_none());
+}
+
+#[tokio::test]
+async fn only_owned_masks_watched() {
+    let mask = |name: &str, labels: serde_json::Value| {
+        serde_json::json!({
+            "apiVersion": "vpn.beebs.dev/v1",
+            "kind": "Mask",
+            "metadata": { "name": name, "namespace": "default", "labels": labels },
+            "spec": {},
+        })
+    };
+    let (client, captured) = mock_cluster(vec![
+        mask("workload-0", serde_json::json!({})),
+        mask("workload-1", serde_json::json!({ "team": "scrapers" })),
+        mask(
+            "test-provider-verify",
+            serde_json::json!({ "app": MANAGER_NAME, VERIFICATION_LABEL: "provider-uid" }),
+        ),
+        mask(
+            "test-provider-canary",
+            serde_json::json!({ "app": MANAGER_NAME, CANARY_LABEL: "provider-uid" }),
+        ),
+    ]);
+
+    // Events of ordinary Masks never reach the MaskProvider controller,
+    // so they don't enqueue reconciliations of their owners.
+    let api: Api<Mask> = Api::all(client);
+    let watched: Vec<String> = api
+        .list(&owned_mask_list_params())
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|m| m.metadata.name.unwrap())
+        .collect();
+    assert_eq!(watched, ["test-provider-verify", "test-provider-canary"]);
+    let captured = captured.lock().unwrap();
+    assert!(captured[0]
+        .path
+        .contains("labelSelector=app%3Dvpn-operator"));
+}
+
+/// Waits for the probe container of the verification Pod to exit
+/// successfully and returns when it finished.
+async fn wait_for_probe_completion(
+    client: Client,
+    namespace: &str,
+) -> Result<DateTime<Utc>, Error> {
+    let pod_api: Api<Pod> = Api::namespaced(client, namespace);
+    let lp = ListParams::default()
+        .labels(VERIFICATION_LABEL)
+        .timeout(120);
+    let mut stream = pod_api.watch(&lp, "0").await?.boxed();
+    while let Some(event) = stream.try_next().await? {
+        if let WatchEvent::Added(pod) | WatchEvent::Modified(pod) = event {
+            let finished_at = pod
+                .status
+                .and_then(|s| s.container_statuses)
+                .unwrap_or_default()
+                .into_iter()
+                .find(|c| c.name == PROBE_CONTAINER_NAME)
+                .and_then(|c| c.state?.terminated)
+                .filter(|t| t.exit_code == 0)
+                .and_then(|t| t.finished_at);
+            if let Some(finished_at) = finished_at {
+                return Ok(finished_at.0);
+            }
+        }
+    }
+    Err(Error::Other(
+        "Verification probe did not complete before timeout".to_owned(),
+    ))
+}
+
+/// Waits for the MaskProvider to become Verified and returns the steps
+/// of the verification operation it was seen going through, in order.
+/// Fails if the operation is still shown once it's Verified.
+async fn watch_verify_steps(client: Client, namespace: &str) -> Result<Vec<usize>, Error> {
+    let provider_api: Api<MaskProvider> = Api::namespaced(client, namespace);
+    let lp = ListParams::default().timeout(120);
+    let mut stream = provider_api.watch(&lp, "0").await?.boxed();
+    let mut steps = Vec::new();
+    while let Some(event) = stream.try_next().await? {
+        if let WatchEvent::Added(provider) | WatchEvent::Modified(provider) = event {
+            let status = provider.status.unwrap_or_default();
+            if status.phase == Some(MaskProviderPhase::Verified) {
+                assert_eq!(status.operation, None, "operation left behind");
+                return Ok(steps);
+            }
+            if let Some(operation) = status.operation {
+                assert_eq!(operation.kind, MaskProviderOperationKind::Verifying);
+                assert_eq!(operation.steps_total, VERIFY_STEPS);
+                steps.push(operation.step);
+            }
+        }
+    }
+    Err(Error::Other(
+        "MaskProvider not Verified before timeout".to_owned(),
+    ))
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
+async fn verify_completion_latency() -> Result<(), Error> {
+    let client: Client = Client::try_default().await.unwrap();
+    let (uid, namespace) = create_test_namespace(client.clone()).await?;
+    let provider_name = test_provider_name(&uid);
+
+    // Verify the MaskProvider with the fake harness, so the
+    // outcome doesn't depend on the credentials.
+    let mut provider = get_test_provider(client.clone(), &provider_name, &namespace).await?;
+    let verify = provider.spec.verify.as_mut().unwrap();
+    verify.skip = Some(false);
+    verify.overrides = Some(fake_verify_overrides());
+    let probe_completed = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(async move { wait_for_probe_completion(client, &namespace).await })
+    };
+    let verified = {
+        let client = client.clone();
+        let namespace = namespace.clone();
+        spawn(async move { watch_verify_steps(client, &namespace).await })
+    };
+    let api: Api<MaskProvider> = Api::namespaced(client.clone(), &namespace);
+    let provider = api.create(&Default::default(), &provider).await?;
+    create_test_provider_secret(client.clone(), &namespace, &provider).await?;
+    let finished_at = probe_completed.await.unwrap()?;
+    let steps = verified.await.unwrap()?;
+
+    // The progress shown to UIs only ever advanced, from the
+    // verification Mask's creation up to the probe.
+    assert_eq!(steps.first(), Some(&(VerifyStep::MaskCreated as usize)));
+    assert!(
+        steps.windows(2).all(|w| w[0] <= w[1]),
+        "steps went back: {:?}",
+        steps
+    );
+    assert_eq!(steps.last(), Some(&(VerifyStep::Probing as usize)));
+
+    // The Pod's change is reacted to right away, well before the
+    // periodic requeue would have noticed it. Container timestamps
+    // only have second precision, hence the margin.
+    let last_verified = api
+        .get(&provider_name)
+        .await?
+        .status
+        .and_then(|s| s.last_verified)
+        .unwrap();
+    let last_verified = DateTime::parse_from_rfc3339(&last_verified)
+        .unwrap()
+        .with_timezone(&Utc);
+    let latency = (last_verified - finished_at).to_std().unwrap_or_default();
+    assert!(
+        latency.as_secs_f64() < 3.0,
+        "Verified {:?} after the probe completed",
+        latency
+    );
+
+    // Garbage collect the test resources.
+    cleanup(client, &namespace).await?;
+    Ok(())
+}
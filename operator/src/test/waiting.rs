@@ -1,11 +1,12 @@
 use kube::{client::Client, Api, ResourceExt};
-use std::clone::Clone;
-use tokio::spawn;
+use std::{clone::Clone, time::Duration};
+use tokio::{spawn, time::timeout};
 use vpn_types::*;
 
 use super::util::*;
 
 #[tokio::test]
+#[cfg_attr(not(feature = "e2e"), ignore = "needs a cluster")]
 async fn waiting() -> Result<(), Error> {
     let client: Client = Client::try_default().await.unwrap();
     let (uid, namespace) = create_test_namespace(client.clone()).await?;
@@ -67,6 +68,7 @@ async fn waiting() -> Result<(), Error> {
     mask1_wait.await.unwrap()?;
 
     // Delete the first Mask and ensure the second Mask is assigned to the MaskProvider.
+    let released_slot = assigned_provider.slot;
     let assigned_provider = {
         let client = client.clone();
         let namespace = namespace.clone();
@@ -74,11 +76,17 @@ async fn waiting() -> Result<(), Error> {
     };
     delete_test_mask(client.clone(), &namespace, 0).await?;
 
-    // Ensure the test provider was assigned to the second Mask.
-    let assigned_provider = assigned_provider
+    // The Mask is only gone once its slot is free.
+    wait_for_slot_free(client.clone(), &provider, released_slot).await?;
+
+    // Ensure the test provider was assigned to the second Mask. The slot
+    // is already free, so the retry interval is all it has to wait for.
+    let assigned_provider = timeout(Duration::from_secs(30), assigned_provider)
         .await
+        .expect("second Mask not assigned within 30 seconds of the slot's release")
         .unwrap()
         .expect("failed to wait for provider assignment");
+    assert_eq!(assigned_provider.slot, released_slot);
     assert_eq!(assigned_provider.name, provider.name_any());
     assert_eq!(assigned_provider.namespace, provider.namespace().unwrap());
     assert_eq!(
@@ -98,7 +106,8 @@ async fn waiting() -> Result<(), Error> {
             async move { wait_for_mask_phase(client, &namespace, 1, MaskPhase::ErrNoProviders).await },
         )
     };
-    delete_test_provider(client.clone(), &namespace, &provider_name).await?;
+    // The Mask is still assigned a slot, so deletion has to be forced.
+    force_delete_test_provider(client.clone(), &namespace, provider_name).await?;
 
     // Ensure the ErrNoProviders phase was observed.
     mask1_wait.await.unwrap()?;
@@ -0,0 +1,155 @@
+use k8s_openapi::apimachinery::pkg::{apis::meta::v1::APIResourceList, version::Info};
+use kube::{Client, Resource};
+use std::fmt;
+use vpn_types::*;
+
+use super::Error;
+
+/// Oldest Kubernetes minor version the operator supports. The
+/// controllers refuse to start against an older API server.
+pub const MIN_MINOR_VERSION: u32 = 23;
+
+/// Newest Kubernetes minor version the operator was tested against.
+/// Newer API servers are expected to work, but are logged as untested.
+pub const MAX_TESTED_MINOR_VERSION: u32 = 29;
+
+/// First Kubernetes minor version that sets the `DisruptionTarget`
+/// condition on Pods by default.
+pub const DISRUPTION_CONDITIONS_MINOR_VERSION: u32 = 26;
+
+/// API group and version of the operator's own resources.
+const API_VERSION: &str = "vpn.beebs.dev/v1";
+
+/// Features of the API server that the version-sensitive code paths
+/// depend on, detected once at startup.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capabilities {
+    /// Minor version of the API server, e.g. 27 for 1.27.
+    pub minor: u32,
+
+    /// Full version of the API server as it reports it, e.g. `v1.27.3-eks-a5565ad`.
+    pub git_version: String,
+
+    /// Pods that are about to be deleted because of a disruption get
+    /// the `DisruptionTarget` condition, telling them apart from Pods
+    /// that are deleted on purpose.
+    pub disruption_conditions: bool,
+
+    /// Plurals of the installed CRDs that lack the status subresource,
+    /// whose statuses are patched on the resources themselves. CRDs that
+    /// aren't installed, e.g. the optional MaskQuota, aren't listed.
+    pub missing_status_subresources: Vec<String>,
+}
+
+impl Default for Capabilities {
+    /// Capabilities assumed when none were detected, e.g. in tests,
+    /// which are those of the newest tested version.
+    fn default() -> Self {
+        Capabilities {
+            minor: MAX_TESTED_MINOR_VERSION,
+            git_version: format!("v1.{}.0", MAX_TESTED_MINOR_VERSION),
+            disruption_conditions: true,
+            missing_status_subresources: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Kubernetes {} (disruption conditions: {}, status subresources: {})",
+            self.git_version,
+            self.disruption_conditions,
+            self.missing_status_subresources.is_empty(),
+        )
+    }
+}
+
+impl Capabilities {
+    /// Determines the capabilities from the API server's version and
+    /// the resources it serves in the operator's API group. Returns an
+    /// error if the version is older than [`MIN_MINOR_VERSION`].
+    pub fn from_discovery(version: &Info, resources: &APIResourceList) -> Result<Self, Error> {
+        let minor = parse_minor(version)?;
+        if minor < MIN_MINOR_VERSION {
+            return Err(Error::UnsupportedVersion(format!(
+                "Kubernetes {} is not supported, the oldest supported version is 1.{}",
+                version.git_version, MIN_MINOR_VERSION
+            )));
+        }
+        let served = |name: String| resources.resources.iter().any(|r| r.name == name);
+        let missing_status_subresources = [
+            MaskProvider::plural(&()),
+            Mask::plural(&()),
+            MaskConsumer::plural(&()),
+            MaskReservation::plural(&()),
+            MaskQuota::plural(&()),
+            ClusterMaskProvider::plural(&()),
+        ]
+        .into_iter()
+        .map(|plural| plural.into_owned())
+        .filter(|plural| served(plural.clone()) && !served(format!("{}/status", plural)))
+        .collect();
+        Ok(Capabilities {
+            minor,
+            git_version: version.git_version.clone(),
+            disruption_conditions: minor >= DISRUPTION_CONDITIONS_MINOR_VERSION,
+            missing_status_subresources,
+        })
+    }
+
+    /// Returns true if the API server is newer than the newest version
+    /// the operator was tested against.
+    pub fn untested(&self) -> bool {
+        self.minor > MAX_TESTED_MINOR_VERSION
+    }
+}
+
+/// Parses the minor version of the API server. Some distributions
+/// append a `+` to it, e.g. `27+` on EKS and GKE.
+fn parse_minor(version: &Info) -> Result<u32, Error> {
+    let unsupported = || {
+        Error::UnsupportedVersion(format!(
+            "Kubernetes version {} couldn't be parsed",
+            version.git_version
+        ))
+    };
+    if version.major != "1" {
+        return Err(unsupported());
+    }
+    version
+        .minor
+        .trim_end_matches('+')
+        .parse()
+        .map_err(|_| unsupported())
+}
+
+/// Queries the API server's version and the operator's resources it
+/// serves, and returns the capabilities they imply.
+pub async fn detect(client: Client) -> Result<Capabilities, Error> {
+    let version = client.apiserver_version().await?;
+    let resources = client.list_api_group_resources(API_VERSION).await?;
+    Capabilities::from_discovery(&version, &resources)
+}
+
+/// Detects the capabilities of the API server and logs them, warning
+/// about an untested version and CRDs that lack the status subresource.
+/// Returns an error if the API server is too old.
+pub async fn probe(client: Client) -> Result<Capabilities, Error> {
+    let capabilities = detect(client).await?;
+    println!("Detected {}", capabilities);
+    if capabilities.untested() {
+        eprintln!(
+            "Kubernetes {} is newer than 1.{}, the newest version vpn-operator was tested against.",
+            capabilities.git_version, MAX_TESTED_MINOR_VERSION
+        );
+    }
+    if !capabilities.missing_status_subresources.is_empty() {
+        eprintln!(
+            "The vpn.beebs.dev CRDs of {} are missing the status subresource, so their statuses are patched on the resources themselves. Reinstall the CRDs to fix this.",
+            capabilities.missing_status_subresources.join(", ")
+        );
+    }
+    Ok(capabilities)
+}
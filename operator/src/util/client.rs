@@ -0,0 +1,33 @@
+use hyper::header::{HeaderValue, USER_AGENT};
+use kube::{client::ClientBuilder, Client, Config};
+use tower_http::set_header::SetRequestHeaderLayer;
+
+use super::{Error, MANAGER_NAME};
+
+/// Returns the user agent used by the given controller kind,
+/// e.g. `vpn-operator/masks`. The suffix makes it possible to
+/// attribute API server usage to individual controllers when
+/// inspecting audit logs.
+pub fn user_agent(kind: &str) -> String {
+    format!("{}/{}", MANAGER_NAME, kind)
+}
+
+/// Returns a tower layer that overrides the user agent header
+/// on every request made by the client.
+pub fn user_agent_layer(kind: &str) -> SetRequestHeaderLayer<HeaderValue> {
+    SetRequestHeaderLayer::overriding(
+        USER_AGENT,
+        HeaderValue::from_str(&user_agent(kind)).unwrap(),
+    )
+}
+
+/// Creates a Kubernetes client using the default configuration
+/// with its own connection pool and a user agent identifying the
+/// controller kind. This isolates controllers that run in the same
+/// process from each other's API server throttling.
+pub async fn create_client(kind: &str) -> Result<Client, Error> {
+    let config = Config::infer().await.map_err(kube::Error::InferConfig)?;
+    Ok(ClientBuilder::try_from(config)?
+        .with_layer(&user_agent_layer(kind))
+        .build())
+}
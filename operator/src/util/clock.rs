@@ -0,0 +1,54 @@
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+
+/// Source of the current time for decisions that depend on it, such
+/// as [`MaskProvider`](vpn_types::MaskProvider) availability. Tests can
+/// fix the clock so those decisions can be checked at any time of day.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Clock {
+    /// The system clock.
+    #[default]
+    System,
+
+    /// A fixed point in time.
+    #[cfg(test)]
+    Fixed(DateTime<Utc>),
+}
+
+impl Clock {
+    /// Returns the current time according to the clock.
+    pub fn now(&self) -> DateTime<Utc> {
+        match self {
+            Clock::System => Utc::now(),
+            #[cfg(test)]
+            Clock::Fixed(now) => *now,
+        }
+    }
+}
+
+/// Formats the time the way Kubernetes writes timestamps: RFC 3339 in
+/// UTC with whole seconds and a `Z` suffix, e.g. `2023-06-14T09:47:40Z`.
+/// Every timestamp the operator writes has this format, so they sort
+/// chronologically as strings.
+pub fn format_timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Returns the current time formatted by [`format_timestamp`].
+pub fn now_k8s() -> String {
+    format_timestamp(Utc::now())
+}
+
+/// Parses a timestamp written by any version of the operator. Older
+/// versions wrote RFC 3339 with fractional seconds and a `+00:00`
+/// offset, which is accepted along with timestamps missing an offset,
+/// which are taken to be in UTC.
+pub fn parse_timestamp(timestamp: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+    let timestamp = timestamp.trim();
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|t| t.with_timezone(&Utc))
+        .or_else(|e| {
+            NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%dT%H:%M:%S%.f")
+                .map(|t| t.and_utc())
+                .map_err(|_| e)
+        })
+}
@@ -0,0 +1,160 @@
+use futures::{StreamExt, TryStreamExt};
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{
+    api::ListParams,
+    runtime::{watcher, watcher::Event},
+    Api, Client,
+};
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
+use vpn_types::MaskProviderVerifySpec;
+
+use crate::rbac::Rule;
+
+#[cfg(feature = "metrics")]
+use super::metrics::ASSIGNMENTS_FROZEN_GAUGE;
+
+/// Reading and watching the operator config ConfigMap, which is only
+/// granted in the operator's namespace. See [`crate::rbac`].
+pub const RBAC: &[Rule] = &[Rule::new("", &["configmaps"], &["get", "list", "watch"])];
+
+/// Key in the operator config ConfigMap that freezes assignments when `"true"`.
+pub const ASSIGNMENTS_FROZEN_KEY: &str = "assignmentsFrozen";
+
+/// Key in the operator config ConfigMap with the YAML verification settings
+/// of MaskProviders that don't specify them, as in `spec.verify`.
+pub const DEFAULT_VERIFY_KEY: &str = "defaultVerify";
+
+/// Reference to the operator config ConfigMap, given as `namespace/name`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigMapRef {
+    pub namespace: String,
+    pub name: String,
+}
+
+impl FromStr for ConfigMapRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('/') {
+            Some((namespace, name)) if !namespace.is_empty() && !name.is_empty() => {
+                Ok(ConfigMapRef {
+                    namespace: namespace.to_owned(),
+                    name: name.to_owned(),
+                })
+            }
+            _ => Err(format!("expected namespace/name, got '{}'", s)),
+        }
+    }
+}
+
+/// Operator configuration that can be changed at runtime. Settings
+/// from the CLI are fixed, while those from the operator config
+/// ConfigMap are reloaded whenever the ConfigMap changes.
+#[derive(Default)]
+pub struct OperatorConfig {
+    /// Assignments were frozen with `--freeze-assignments`.
+    freeze_flag: bool,
+
+    /// Assignments are frozen by the operator config ConfigMap.
+    frozen_by_config: AtomicBool,
+
+    /// Verification settings that MaskProviders' own `spec.verify` is
+    /// merged onto, from the operator config ConfigMap.
+    default_verify: RwLock<Option<MaskProviderVerifySpec>>,
+}
+
+impl OperatorConfig {
+    /// Creates a new config. If `freeze_assignments` is true,
+    /// assignments remain frozen regardless of the ConfigMap.
+    pub fn new(freeze_assignments: bool) -> Self {
+        let config = OperatorConfig {
+            freeze_flag: freeze_assignments,
+            frozen_by_config: AtomicBool::new(false),
+            default_verify: RwLock::new(None),
+        };
+        config.report();
+        config
+    }
+
+    /// Returns true if new slot reservations should be refused.
+    pub fn assignments_frozen(&self) -> bool {
+        self.freeze_flag || self.frozen_by_config.load(Ordering::Relaxed)
+    }
+
+    /// Returns the verification settings of MaskProviders that
+    /// don't specify them, if configured.
+    pub fn default_verify(&self) -> Option<MaskProviderVerifySpec> {
+        self.default_verify.read().unwrap().clone()
+    }
+
+    /// Applies the contents of the operator config ConfigMap.
+    /// `None` means the ConfigMap doesn't exist, which restores
+    /// the defaults.
+    pub fn update(&self, config_map: Option<&ConfigMap>) {
+        let data = config_map.and_then(|cm| cm.data.as_ref());
+        let frozen = data
+            .and_then(|data| data.get(ASSIGNMENTS_FROZEN_KEY))
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("true"));
+        if self.frozen_by_config.swap(frozen, Ordering::Relaxed) != frozen {
+            println!(
+                "Assignments {} by operator configuration",
+                if frozen { "frozen" } else { "unfrozen" }
+            );
+        }
+        self.update_default_verify(data.and_then(|data| data.get(DEFAULT_VERIFY_KEY)));
+        self.report();
+    }
+
+    /// Parses the default verification settings. Invalid settings are
+    /// logged and the previous ones are kept, so a typo doesn't change
+    /// how every MaskProvider is verified.
+    fn update_default_verify(&self, value: Option<&String>) {
+        let default_verify = match value.map(|v| v.trim()) {
+            None | Some("") => None,
+            Some(value) => match serde_yaml::from_str::<Option<MaskProviderVerifySpec>>(value) {
+                Ok(default_verify) => default_verify,
+                Err(e) => {
+                    eprintln!("Invalid {} in operator config: {}", DEFAULT_VERIFY_KEY, e);
+                    return;
+                }
+            },
+        };
+        let mut current = self.default_verify.write().unwrap();
+        if *current != default_verify {
+            println!("Default verification settings updated by operator configuration");
+            *current = default_verify;
+        }
+    }
+
+    /// Reports the frozen state to the metrics server.
+    fn report(&self) {
+        #[cfg(feature = "metrics")]
+        ASSIGNMENTS_FROZEN_GAUGE.set(self.assignments_frozen() as i64);
+    }
+
+    /// Watches the operator config ConfigMap and applies every change.
+    /// Watch errors are logged and the watch resumes after a delay.
+    pub async fn watch(&self, client: Client, config_map: ConfigMapRef) {
+        let api: Api<ConfigMap> = Api::namespaced(client, &config_map.namespace);
+        let lp = ListParams::default().fields(&format!("metadata.name={}", config_map.name));
+        let mut stream = watcher(api, lp).boxed();
+        loop {
+            match stream.try_next().await {
+                Ok(Some(Event::Applied(cm))) => self.update(Some(&cm)),
+                Ok(Some(Event::Deleted(_))) => self.update(None),
+                Ok(Some(Event::Restarted(cms))) => self.update(cms.first()),
+                Ok(None) => return,
+                Err(e) => {
+                    eprintln!("Failed to watch operator config: {}", e);
+                    tokio::time::sleep(super::PROBE_INTERVAL).await;
+                }
+            }
+        }
+    }
+}
@@ -1,10 +1,63 @@
+use kube::runtime::controller::Action;
+use std::time::Duration;
+
+/// Delay before retrying a reconciliation that failed.
+pub const ERROR_REQUEUE: Duration = Duration::from_secs(5);
+
+/// Delay before retrying a reconciliation that failed because the API
+/// server throttled a request, long enough to let the pressure ease.
+pub const THROTTLED_REQUEUE: Duration = Duration::from_secs(30);
+
+/// Backtrace of where an error was converted into [`Error`]. It's only
+/// captured when built with the `backtrace` feature, following the
+/// `RUST_BACKTRACE` and `RUST_LIB_BACKTRACE` variables like std does, and
+/// is empty otherwise.
+pub struct Trace {
+    // Boxed so it doesn't grow every `Result` returning an `Error`.
+    #[cfg(feature = "backtrace")]
+    backtrace: Box<std::backtrace::Backtrace>,
+}
+
+impl Trace {
+    /// Captures the backtrace of the caller, if enabled.
+    pub fn capture() -> Self {
+        Trace {
+            #[cfg(feature = "backtrace")]
+            backtrace: Box::new(std::backtrace::Backtrace::capture()),
+        }
+    }
+
+    /// Returns the backtrace, if one was captured.
+    #[cfg(feature = "backtrace")]
+    pub fn backtrace(&self) -> Option<&std::backtrace::Backtrace> {
+        match self.backtrace.status() {
+            std::backtrace::BacktraceStatus::Captured => Some(&self.backtrace),
+            _ => None,
+        }
+    }
+
+    /// Returns the backtrace, if one was captured.
+    #[cfg(not(feature = "backtrace"))]
+    pub fn backtrace(&self) -> Option<&std::backtrace::Backtrace> {
+        None
+    }
+}
+
+impl std::fmt::Debug for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.backtrace() {
+            // Printed on its own by the controllers' `on_error`.
+            Some(_) => write!(f, "<captured>"),
+            None => write!(f, "<not captured>"),
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
-    #[error("Kubernetes reported error: {source}")]
-    KubeError {
-        #[from]
-        source: kube::Error,
-    },
+    #[error("{}", describe_kube_error(.source))]
+    KubeError { source: kube::Error, trace: Trace },
 
     #[error("Invalid user input: {0}")]
     UserInputError(String),
@@ -27,9 +80,209 @@ pub enum Error {
         source: serde_json::Error,
     },
 
+    #[error("Yaml error: {source}")]
+    YamlError {
+        #[from]
+        source: serde_yaml::Error,
+    },
+
     #[error("Parse duration: {source}")]
     ParseDurationError {
         #[from]
         source: parse_duration::parse::Error,
     },
+
+    #[error("TLS error: {source}")]
+    TlsError {
+        #[from]
+        source: openssl::error::ErrorStack,
+    },
+
+    #[error("Failed to render the verification Pod: {source}")]
+    RenderError {
+        #[from]
+        source: vpn_render::RenderError,
+    },
+
+    #[error("I/O error: {source}")]
+    IoError {
+        #[from]
+        source: std::io::Error,
+    },
+
+    /// The API server is older than the oldest supported version,
+    /// or its version couldn't be determined.
+    #[error("{0}")]
+    UnsupportedVersion(String),
+
+    /// The resource's status was updated since it was read, so the
+    /// status patch was refused rather than overwrite the newer status.
+    #[error("Status of {0} is stale and must be read again")]
+    StaleStatus(String),
+
+    /// The resource's status is missing a field the controllers rely on,
+    /// e.g. because an older version of the operator wrote it partially.
+    /// Contains the resource's namespace and name, and the missing field.
+    #[error("Status of {0} is missing {1}")]
+    IncompleteStatus(String, &'static str),
+
+    /// The MaskProvider assigned to a MaskConsumer is gone, was replaced
+    /// by another of the same name, or is being deleted, so its
+    /// credentials must not be copied. Contains its namespace and name.
+    #[error("MaskProvider {0} was deleted or replaced since it was assigned")]
+    ProviderDeparted(String),
+
+    /// The generated dashboard or alerting rules reference metrics the
+    /// operator doesn't export. Contains their names.
+    #[error("Generated queries reference unknown metrics: {}", .0.join(", "))]
+    UnknownMetrics(Vec<String>),
+
+    /// A MaskConsumer's settings can't be applied to the credentials of
+    /// its assigned MaskProvider. Contains the message naming the setting.
+    #[error("Invalid spec: {0}")]
+    InvalidSpec(String),
+
+    /// Wraps an error that occurred while performing an action during
+    /// the write phase of reconciliation, so the error handler knows
+    /// which action failed.
+    #[error("{action} failed: {source}")]
+    ActionError { action: String, source: Box<Error> },
+
+    /// Wraps an error that occurred while requesting a resource, so the
+    /// error says which resource the request was for.
+    #[error("{kind} {name}: {source}")]
+    ContextError {
+        kind: String,
+        name: String,
+        source: Box<Error>,
+    },
+}
+
+impl From<kube::Error> for Error {
+    fn from(source: kube::Error) -> Self {
+        Error::KubeError {
+            source,
+            trace: Trace::capture(),
+        }
+    }
+}
+
+impl Error {
+    /// Wraps the error with the name of the action that caused it.
+    pub fn action(action: &str, source: Error) -> Self {
+        Error::ActionError {
+            action: action.to_owned(),
+            source: Box::new(source),
+        }
+    }
+
+    /// Returns the error without any action or resource context.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::ActionError { source, .. } | Error::ContextError { source, .. } => source.root(),
+            _ => self,
+        }
+    }
+
+    /// Returns the API server's error response, if the error is one.
+    pub fn api_error(&self) -> Option<&kube::core::ErrorResponse> {
+        match self.root() {
+            Error::KubeError {
+                source: kube::Error::Api(e),
+                ..
+            } => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Returns the backtrace of where the Kubernetes error was raised,
+    /// if the operator was built with the `backtrace` feature and
+    /// capturing is enabled with `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE`.
+    pub fn backtrace(&self) -> Option<&std::backtrace::Backtrace> {
+        match self.root() {
+            Error::KubeError { trace, .. } => trace.backtrace(),
+            _ => None,
+        }
+    }
+
+    /// Returns the HTTP status code of the API server's error response.
+    pub fn status_code(&self) -> Option<u16> {
+        self.api_error().map(|e| e.code)
+    }
+
+    /// Returns the reason of the API server's error response, e.g.
+    /// `TooManyRequests` or `Conflict`.
+    pub fn reason(&self) -> Option<&str> {
+        self.api_error().map(|e| e.reason.as_str())
+    }
+
+    /// Returns true if the API server refused the request because it is
+    /// overloaded or rate limiting us, in which case retrying quickly
+    /// only makes matters worse.
+    pub fn is_throttled(&self) -> bool {
+        matches!(self.status_code(), Some(429 | 503))
+    }
+
+    /// Returns how long to wait before retrying the reconciliation.
+    pub fn requeue_after(&self) -> Duration {
+        if self.is_throttled() {
+            THROTTLED_REQUEUE
+        } else {
+            ERROR_REQUEUE
+        }
+    }
+
+    /// Returns the status code as a metric label, which is empty if the
+    /// error isn't an error response from the API server.
+    pub fn code_label(&self) -> String {
+        self.status_code()
+            .map(|code| code.to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Prints a failed reconciliation of `instance` to `stderr`, with the
+/// backtrace of the Kubernetes error if one was captured, and returns the
+/// action that requeues the resource once the error's backoff elapses.
+pub fn report_reconcile_error(instance: &dyn std::fmt::Debug, error: &Error) -> Action {
+    eprintln!(
+        "Reconciliation error (code {}, reason {}):\n{:?}.\n{:?}",
+        error
+            .status_code()
+            .map_or("none".to_owned(), |c| c.to_string()),
+        error.reason().unwrap_or("none"),
+        error,
+        instance
+    );
+    if let Some(backtrace) = error.backtrace() {
+        eprintln!("Backtrace of the Kubernetes error:\n{}", backtrace);
+    }
+    Action::requeue(error.requeue_after())
+}
+
+/// Formats the kube error so the status code and reason of an error
+/// response are always visible.
+fn describe_kube_error(source: &kube::Error) -> String {
+    match source {
+        kube::Error::Api(e) => format!(
+            "Kubernetes reported error {} {}: {}",
+            e.code, e.reason, e.message
+        ),
+        e => format!("Kubernetes reported error: {}", e),
+    }
+}
+
+/// Adds the kind and name of the requested resource to an error.
+pub trait ErrorContext<T> {
+    fn context_kind_name(self, kind: &str, name: &str) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> ErrorContext<T> for Result<T, E> {
+    fn context_kind_name(self, kind: &str, name: &str) -> Result<T, Error> {
+        self.map_err(|e| Error::ContextError {
+            kind: kind.to_owned(),
+            name: name.to_owned(),
+            source: Box::new(e.into()),
+        })
+    }
 }
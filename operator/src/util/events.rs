@@ -0,0 +1,154 @@
+use k8s_openapi::api::core::v1::ObjectReference;
+use kube::{
+    runtime::events::{Event, EventType, Recorder, Reporter},
+    Client, Resource,
+};
+use std::future::Future;
+
+use super::MANAGER_NAME;
+
+tokio::task_local! {
+    /// The reporter and action of the write phase currently being applied.
+    static TRANSITIONS: Transitions;
+}
+
+/// The reporter and action of the write phase currently being applied.
+#[derive(Clone)]
+struct Transitions {
+    reporter: Reporter,
+    action: String,
+}
+
+/// Returns the reporter of the events published by the operator.
+pub fn reporter() -> Reporter {
+    Reporter {
+        controller: MANAGER_NAME.to_owned(),
+        instance: None,
+    }
+}
+
+/// Runs the write phase of an action. Status patches made within `f`
+/// that change the phase publish an event regarding the patched
+/// resource with `reporter`, see [`transition`].
+pub async fn scope<F: Future>(reporter: Reporter, action: &str, f: F) -> F::Output {
+    let transitions = Transitions {
+        reporter,
+        action: action.to_owned(),
+    };
+    TRANSITIONS.scope(transitions, f).await
+}
+
+/// Publishes an event regarding the resource, whose status was just
+/// patched into `phase` with the status message `note` by the action
+/// currently being applied. The event's reason is the name of the
+/// action, so `kubectl describe` shows the history of the resource.
+/// Error phases are published as Warning events. Nothing is published
+/// outside of [`scope`].
+pub async fn transition<K>(client: Client, instance: &K, phase: &str, note: Option<&str>)
+where
+    K: Resource,
+    <K as Resource>::DynamicType: Default,
+{
+    let transitions = match TRANSITIONS.try_with(Clone::clone) {
+        Ok(transitions) => transitions,
+        Err(_) => return,
+    };
+    let type_ = match phase.starts_with("Err") {
+        true => EventType::Warning,
+        false => EventType::Normal,
+    };
+    let reference = instance.object_ref(&Default::default());
+    publish_event(
+        client,
+        transitions.reporter,
+        reference,
+        type_,
+        &transitions.action,
+        phase,
+        note.unwrap_or_default().to_owned(),
+    )
+    .await
+}
+
+/// Publishes a Normal event regarding the resource. Events are
+/// informational, so failing to publish one is logged rather than
+/// failing the reconciliation.
+pub async fn publish<K>(client: Client, instance: &K, reason: &str, action: &str, note: String)
+where
+    K: Resource<DynamicType = ()>,
+{
+    let reference = instance.object_ref(&());
+    publish_event(
+        client,
+        reporter(),
+        reference,
+        EventType::Normal,
+        reason,
+        action,
+        note,
+    )
+    .await
+}
+
+/// Publishes a Warning event regarding the resource. Like [`publish`],
+/// failing to publish the event is logged rather than returned.
+pub async fn warn<K>(client: Client, instance: &K, reason: &str, action: &str, note: String)
+where
+    K: Resource<DynamicType = ()>,
+{
+    let reference = instance.object_ref(&());
+    publish_event(
+        client,
+        reporter(),
+        reference,
+        EventType::Warning,
+        reason,
+        action,
+        note,
+    )
+    .await
+}
+
+/// Publishes a Warning event regarding the referenced object. Unlike
+/// [`warn`], the object doesn't have to be fetched first, so this also
+/// works for objects that were already deleted.
+pub async fn warn_ref(
+    client: Client,
+    reference: ObjectReference,
+    reason: &str,
+    action: &str,
+    note: String,
+) {
+    publish_event(
+        client,
+        reporter(),
+        reference,
+        EventType::Warning,
+        reason,
+        action,
+        note,
+    )
+    .await
+}
+
+async fn publish_event(
+    client: Client,
+    reporter: Reporter,
+    reference: ObjectReference,
+    type_: EventType,
+    reason: &str,
+    action: &str,
+    note: String,
+) {
+    let recorder = Recorder::new(client, reporter, reference);
+    let event = Event {
+        type_,
+        reason: reason.to_owned(),
+        note: Some(note),
+        action: action.to_owned(),
+        secondary: None,
+    };
+    if let Err(e) = recorder.publish(event).await {
+        eprintln!("Failed to publish {} event: {}", reason, e);
+    }
+}
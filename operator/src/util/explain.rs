@@ -0,0 +1,80 @@
+use kube::api::ObjectMeta;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+
+/// Annotation that explains the most recent action taken on a resource.
+pub const LAST_ACTION_ANNOTATION: &str = "vpn.beebs.dev/last-action";
+
+/// Maximum length of the reason within the annotation. This keeps the
+/// annotation compact regardless of how verbose the status message is.
+pub const MAX_REASON_LEN: usize = 256;
+
+tokio::task_local! {
+    /// The controller and action currently being applied.
+    static CONTEXT: ExplainContext;
+}
+
+/// The controller and action currently being applied.
+#[derive(Clone, Debug)]
+struct ExplainContext {
+    controller: &'static str,
+    action: String,
+}
+
+/// Contents of the [`LAST_ACTION_ANNOTATION`] annotation.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LastAction {
+    /// Name of the action taken, e.g. `CreateConsumer`.
+    pub action: String,
+
+    /// Why the action was taken. This is the status message set by the action.
+    pub reason: Option<String>,
+
+    /// Timestamp of when the action was taken.
+    pub at: String,
+
+    /// Kind of controller that took the action, e.g. `masks`.
+    pub controller: String,
+}
+
+/// Runs the write phase of an action. Status patches made within `f`
+/// are explained with the controller and action, if `enabled` with
+/// `--explain-annotations`.
+pub async fn scope<F: Future>(
+    enabled: bool,
+    controller: &'static str,
+    action: &str,
+    f: F,
+) -> F::Output {
+    if !enabled {
+        return f.await;
+    }
+    let context = ExplainContext {
+        controller,
+        action: action.to_owned(),
+    };
+    CONTEXT.scope(context, f).await
+}
+
+/// Returns the explanation of the action currently being applied,
+/// or `None` if no action is being applied within an enabled [`scope`].
+pub fn last_action(reason: Option<&str>) -> Option<LastAction> {
+    CONTEXT
+        .try_with(|context| LastAction {
+            action: context.action.clone(),
+            reason: reason.map(|r| r.chars().take(MAX_REASON_LEN).collect()),
+            at: super::clock::now_k8s(),
+            controller: context.controller.to_owned(),
+        })
+        .ok()
+}
+
+/// Sets the [`LAST_ACTION_ANNOTATION`] annotation, overwriting any previous value.
+pub fn annotate(meta: &mut ObjectMeta, last_action: &LastAction) {
+    meta.annotations
+        .get_or_insert_with(Default::default)
+        .insert(
+            LAST_ACTION_ANNOTATION.to_owned(),
+            serde_json::to_string(last_action).unwrap(),
+        );
+}
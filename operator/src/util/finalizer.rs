@@ -1,17 +1,22 @@
+use json_patch::{AddOperation, PatchOperation, TestOperation};
 use kube::{
-    api::{Patch, Resource},
+    api::{Patch, PatchParams, Resource},
     core::NamespaceResourceScope,
     Api, Client, Error,
 };
 use serde::{de::DeserializeOwned, Serialize};
-use serde_json::{json, Value};
+use serde_json::Value;
 use std::{clone::Clone, fmt::Debug};
 
 /// Name of the kubernetes resource finalizer field.
 pub const FINALIZER_NAME: &str = "vpn.beebs.dev/finalizer";
 
+/// Number of times the finalizers are re-read and patched again when
+/// another controller changes them between the read and the patch.
+const FINALIZER_ATTEMPTS: usize = 5;
+
 /// Adds a finalizer record into a `T` kind of resource. If the finalizer already exists,
-/// this action has no effect.
+/// this action has no effect. Finalizers added by other controllers are kept.
 ///
 /// # Arguments:
 /// - `client` - Kubernetes client to modify the `T` resource with.
@@ -19,27 +24,24 @@ pub const FINALIZER_NAME: &str = "vpn.beebs.dev/finalizer";
 /// - `namespace` - Namespace where the `T` resource with given `name` resides.
 ///
 /// Note: Does not check for resource's existence for simplicity.
-pub async fn add<T: Clone + Resource + Serialize + DeserializeOwned + Debug>(
-    client: Client,
-    name: &str,
-    namespace: &str,
-) -> Result<T, Error>
+pub async fn add<T>(client: Client, name: &str, namespace: &str) -> Result<T, Error>
 where
     <T as Resource>::DynamicType: Default,
-    T: Resource<Scope = NamespaceResourceScope>,
+    T: Clone + Resource<Scope = NamespaceResourceScope> + Serialize + DeserializeOwned + Debug,
 {
     let api: Api<T> = Api::namespaced(client, namespace);
-    let finalizer: Value = json!({
-        "metadata": {
-            "finalizers": [FINALIZER_NAME]
+    update(&api, name, |finalizers| {
+        if finalizers.iter().any(|f| f == FINALIZER_NAME) {
+            return false;
         }
-    });
-    let patch: Patch<&Value> = Patch::Merge(&finalizer);
-    Ok(api.patch(name, &Default::default(), &patch).await?)
+        finalizers.push(FINALIZER_NAME.to_owned());
+        true
+    })
+    .await
 }
 
-/// Removes all finalizers from `T` resource. If there are no finalizers already, this
-/// action has no effect.
+/// Removes the operator's finalizer from `T` resource. If it is not present, this
+/// action has no effect. Finalizers added by other controllers are kept.
 ///
 /// # Arguments:
 /// - `client` - Kubernetes client to modify the `T` resource with.
@@ -47,21 +49,58 @@ where
 /// - `namespace` - Namespace where the `T` resource with given `name` resides.
 ///
 /// Note: Does not check for resource's existence for simplicity.
-pub async fn delete<T: Clone + Resource + Serialize + DeserializeOwned + Debug>(
-    client: Client,
-    name: &str,
-    namespace: &str,
-) -> Result<T, Error>
+pub async fn delete<T>(client: Client, name: &str, namespace: &str) -> Result<T, Error>
 where
     <T as Resource>::DynamicType: Default,
-    T: Resource<Scope = NamespaceResourceScope>,
+    T: Clone + Resource<Scope = NamespaceResourceScope> + Serialize + DeserializeOwned + Debug,
 {
     let api: Api<T> = Api::namespaced(client, namespace);
-    let finalizer: Value = json!({
-        "metadata": {
-            "finalizers": null
+    update(&api, name, |finalizers| {
+        let len = finalizers.len();
+        finalizers.retain(|f| f != FINALIZER_NAME);
+        finalizers.len() != len
+    })
+    .await
+}
+
+/// Reads the resource's finalizers, applies `modify` to them and writes them
+/// back with a JSON patch that tests the array is unchanged since it was read,
+/// so a finalizer another controller added or removed in the meantime is never
+/// overwritten. The patch is retried on a fresh read if the test fails. If
+/// `modify` returns false, the finalizers are left as they are.
+async fn update<T: Clone + Resource + Serialize + DeserializeOwned + Debug>(
+    api: &Api<T>,
+    name: &str,
+    modify: impl Fn(&mut Vec<String>) -> bool,
+) -> Result<T, Error> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let instance = api.get(name).await?;
+        let current = instance.meta().finalizers.clone();
+        let mut finalizers = current.clone().unwrap_or_default();
+        if !modify(&mut finalizers) {
+            return Ok(instance);
+        }
+        let patch = json_patch::Patch(vec![
+            // Testing for null also passes if there are no finalizers yet.
+            PatchOperation::Test(TestOperation {
+                path: "/metadata/finalizers".to_owned(),
+                value: current.map_or(Value::Null, Value::from),
+            }),
+            // Adding an existing member replaces it.
+            PatchOperation::Add(AddOperation {
+                path: "/metadata/finalizers".to_owned(),
+                value: Value::from(finalizers),
+            }),
+        ]);
+        match api
+            .patch(name, &PatchParams::default(), &Patch::Json::<()>(patch))
+            .await
+        {
+            // The finalizers changed since they were read.
+            Err(Error::Api(e)) if e.code == 422 && attempt < FINALIZER_ATTEMPTS => continue,
+            result => return result,
         }
-    });
-    let patch: Patch<&Value> = Patch::Merge(&finalizer);
-    Ok(api.patch(name, &Default::default(), &patch).await?)
+    }
 }
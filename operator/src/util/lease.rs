@@ -0,0 +1,131 @@
+use chrono::{DateTime, Utc};
+use k8s_openapi::{
+    api::coordination::v1::{Lease, LeaseSpec},
+    apimachinery::pkg::apis::meta::v1::MicroTime,
+};
+use kube::{
+    api::{ObjectMeta, PostParams},
+    Api, Client,
+};
+use std::time::Duration;
+
+use super::{Error, ErrorContext};
+
+/// How long a Lease is honored without being renewed. Once it lapses,
+/// e.g. because its holder was killed, another replica takes it over.
+pub const LEASE_DURATION: Duration = Duration::from_secs(30);
+
+/// Returns the identity the replica holds Leases under, which is the
+/// name of its Pod if running in one.
+pub fn identity() -> String {
+    std::env::var("HOSTNAME").unwrap_or_else(|_| uuid::Uuid::new_v4().to_string())
+}
+
+/// Returns the namespace the replica holds Leases in, which is that of
+/// its Pod if running in one, or else that of the kubeconfig context.
+pub async fn namespace() -> String {
+    kube::Config::infer()
+        .await
+        .map_or_else(|_| "default".to_owned(), |config| config.default_namespace)
+}
+
+/// Returns true if `identity` may take the Lease at `now`, which is the
+/// case if nobody holds it, `identity` already does, or the holder let
+/// it lapse.
+pub fn is_available(lease: &Lease, identity: &str, now: DateTime<Utc>) -> bool {
+    let spec = match lease.spec.as_ref() {
+        Some(spec) => spec,
+        None => return true,
+    };
+    match spec.holder_identity.as_deref() {
+        None | Some("") => return true,
+        Some(holder) if holder == identity => return true,
+        Some(_) => {}
+    }
+    let duration = chrono::Duration::seconds(
+        spec.lease_duration_seconds
+            .unwrap_or(LEASE_DURATION.as_secs() as i32) as i64,
+    );
+    match spec.renew_time.as_ref().or(spec.acquire_time.as_ref()) {
+        Some(MicroTime(renewed)) => *renewed + duration < now,
+        None => true,
+    }
+}
+
+/// Elects a single replica with a Lease, e.g. so only one of them
+/// writes a resource that they'd otherwise fight over. There's no
+/// handover: a replica that stops renewing is replaced once the
+/// Lease lapses.
+pub struct LeaseLock {
+    api: Api<Lease>,
+    name: String,
+    identity: String,
+}
+
+impl LeaseLock {
+    pub fn new(client: Client, namespace: &str, name: &str, identity: String) -> Self {
+        LeaseLock {
+            api: Api::namespaced(client, namespace),
+            name: name.to_owned(),
+            identity,
+        }
+    }
+
+    /// Returns the identity the Lease is held under.
+    pub fn identity(&self) -> &str {
+        &self.identity
+    }
+
+    /// Takes or renews the Lease, returning true if this replica holds
+    /// it. Losing a race with another replica isn't an error, as the
+    /// Lease is written with its `resourceVersion`.
+    pub async fn try_acquire(&self, now: DateTime<Utc>) -> Result<bool, Error> {
+        let renewed = LeaseSpec {
+            holder_identity: Some(self.identity.clone()),
+            lease_duration_seconds: Some(LEASE_DURATION.as_secs() as i32),
+            acquire_time: Some(MicroTime(now)),
+            renew_time: Some(MicroTime(now)),
+            ..Default::default()
+        };
+        let result = match self
+            .api
+            .get_opt(&self.name)
+            .await
+            .context_kind_name("Lease", &self.name)?
+        {
+            None => {
+                let lease = Lease {
+                    metadata: ObjectMeta {
+                        name: Some(self.name.clone()),
+                        ..Default::default()
+                    },
+                    spec: Some(renewed),
+                };
+                self.api.create(&PostParams::default(), &lease).await
+            }
+            Some(lease) if !is_available(&lease, &self.identity, now) => return Ok(false),
+            Some(mut lease) => {
+                let spec = lease.spec.get_or_insert_with(Default::default);
+                if spec.holder_identity.as_deref() == Some(self.identity.as_str()) {
+                    // Keep when the Lease was taken.
+                    spec.renew_time = renewed.renew_time;
+                    spec.lease_duration_seconds = renewed.lease_duration_seconds;
+                } else {
+                    let transitions = spec.lease_transitions.unwrap_or(0) + 1;
+                    *spec = LeaseSpec {
+                        lease_transitions: Some(transitions),
+                        ..renewed
+                    };
+                }
+                self.api
+                    .replace(&self.name, &PostParams::default(), &lease)
+                    .await
+            }
+        };
+        match result {
+            Ok(_) => Ok(true),
+            Err(kube::Error::Api(ae)) if ae.code == 409 => Ok(false),
+            Err(e) => Err(e).context_kind_name("Lease", &self.name),
+        }
+    }
+}
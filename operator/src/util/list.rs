@@ -0,0 +1,60 @@
+use kube::{api::ListParams, Api, Client, Resource};
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+use vpn_types::*;
+
+use super::{Error, PROVIDER_UID_LABEL};
+
+/// Number of objects requested per page when listing resources,
+/// so large collections don't have to be sent in one response.
+pub const PAGE_SIZE: u32 = 500;
+
+/// Lists every object matching the parameters, one page at a time.
+/// Pages default to [`PAGE_SIZE`] objects unless `lp` sets a limit,
+/// and continue tokens are followed until the last page.
+pub async fn list_all_paginated<K>(api: &Api<K>, lp: &ListParams) -> Result<Vec<K>, Error>
+where
+    K: Resource + Clone + DeserializeOwned + Debug,
+{
+    let mut lp = lp.clone();
+    if lp.limit.is_none() {
+        lp = lp.limit(PAGE_SIZE);
+    }
+    let mut items = Vec::new();
+    loop {
+        let page = api.list(&lp).await?;
+        items.extend(page.items);
+        match page.metadata.continue_ {
+            Some(token) if !token.is_empty() => lp = lp.continue_token(&token),
+            _ => return Ok(items),
+        }
+    }
+}
+
+/// Lists the MaskReservations owned by the MaskProvider, including those
+/// made for verification. Reservations are labeled with the MaskProvider's
+/// uid so the API server can filter them. Older reservations without the
+/// label are listed separately and matched by their owner reference.
+pub async fn list_provider_reservations(
+    client: Client,
+    provider: &MaskProvider,
+) -> Result<Vec<MaskReservation>, Error> {
+    let uid = provider.metadata.uid.as_deref().unwrap();
+    let api: Api<MaskReservation> =
+        Api::namespaced(client, provider.metadata.namespace.as_deref().unwrap());
+    let lp = ListParams::default().labels(&format!("{}={}", PROVIDER_UID_LABEL, uid));
+    let mut reservations = list_all_paginated(&api, &lp).await?;
+    let lp = ListParams::default().labels(&format!("!{}", PROVIDER_UID_LABEL));
+    reservations.extend(
+        list_all_paginated(&api, &lp)
+            .await?
+            .into_iter()
+            .filter(|mr| {
+                mr.metadata
+                    .owner_references
+                    .as_ref()
+                    .is_some_and(|ors| ors.iter().any(|or| or.uid == uid))
+            }),
+    );
+    Ok(reservations)
+}
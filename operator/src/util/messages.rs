@@ -6,10 +6,93 @@ pub const PENDING: &str = "Resource first appeared to the controller.";
 /// deletion is pending garbage collection.
 pub const TERMINATING: &str = "Resource deletion is pending garbage collection.";
 
+/// User-friendly message to display in a `Mask`'s `status.message` while
+/// its deletion waits for the `MaskConsumer` to release the reserved slot.
+pub const RELEASING_SLOT: &str =
+    "Waiting for the MaskConsumer to be deleted and its slot to be released.";
+
+/// User-friendly message to display in a `Mask`'s `status.message` while
+/// its `MaskConsumer` is Terminating, so the Pods using its credentials
+/// can be stopped before the credentials Secret is deleted.
+pub fn assignment_withdrawn(secret: &str) -> String {
+    format!(
+        "Provider assignment is being withdrawn; stop Pods using Secret '{}'.",
+        secret
+    )
+}
+
+/// User-friendly message to display in a `Mask`'s `status.message` when
+/// the Service requested with its `spec.service` can't be created.
+pub fn invalid_service(reason: &str) -> String {
+    format!("Invalid service: {}.", reason)
+}
+
+/// User-friendly message to display in an Active `MaskConsumer`'s
+/// `status.message` when a Service with its name isn't owned by it.
+pub fn service_conflict(name: &str) -> String {
+    format!(
+        "Active, but the requested Service wasn't created because Service '{}' already exists and isn't owned by the MaskConsumer.",
+        name
+    )
+}
+
+/// User-friendly message to display in a `Mask`'s `status.message` when
+/// its `ttl` isn't a duration.
+pub fn invalid_ttl(ttl: &str, reason: &str) -> String {
+    format!("Invalid ttl '{}': {}", ttl, reason)
+}
+
+/// User-friendly message to display in a `Mask`'s `status.message` when
+/// its `slots` is zero or more than a `Mask` can reserve.
+pub fn invalid_slots(slots: usize) -> String {
+    format!(
+        "Invalid slots {}: a Mask must reserve between 1 and {} slots.",
+        slots,
+        vpn_types::MAX_MASK_SLOTS
+    )
+}
+
+/// User-friendly message to display in a `Mask`'s `status.message` while
+/// only some of its slots are assigned. Includes the message of the first
+/// `MaskConsumer` that is still waiting, if it has one.
+pub fn slots_assigned(assigned: usize, slots: usize, reason: Option<&str>) -> String {
+    match reason {
+        Some(reason) => format!("{}/{} slots assigned. {}", assigned, slots, reason),
+        None => format!("{}/{} slots assigned.", assigned, slots),
+    }
+}
+
+/// User-friendly message to display in a `Mask`'s `status.message` once
+/// its slot was released because no Pod used its credentials for `ttl`.
+pub fn ttl_released(ttl: &str) -> String {
+    format!(
+        "Slot released after no Pod used the credentials for the ttl of {}. Change the spec to be assigned again.",
+        ttl
+    )
+}
+
+/// Note of the event published when a `Mask`'s `ttl` expires, naming
+/// what happens to it.
+pub fn ttl_expired(ttl: &str, action: vpn_types::MaskTtlAction) -> String {
+    let outcome = match action {
+        vpn_types::MaskTtlAction::Release => "releasing its slot",
+        vpn_types::MaskTtlAction::Delete => "deleting the Mask",
+    };
+    format!(
+        "No Pod used the credentials for the ttl of {}, {}.",
+        ttl, outcome
+    )
+}
+
 /// User-friendly message to display in `status.message` whenever a `Mask`
 /// or `MaskConsumer` is in the `Waiting` phase.
 pub const WAITING: &str = "Waiting on a slot from a MaskProvider.";
 
+/// User-friendly message to display in `status.message` whenever a `Mask`
+/// or `MaskConsumer` is in the `Waiting` phase because assignments are frozen.
+pub const ASSIGNMENTS_FROZEN: &str =
+    "Waiting on a slot from a MaskProvider: assignments frozen by operator configuration.";
+
 /// User-friendly message to display in `status.message` whenever a `Mask`
 /// or `MaskConsumer` is in the `Active` phase.
 pub const ACTIVE: &str = "Reserving slot with the assigned MaskProvider.";
@@ -17,3 +100,576 @@ pub const ACTIVE: &str = "Reserving slot with the assigned MaskProvider.";
 /// User-friendly message to display in `status.message` whenever a `Mask`
 /// or `MaskConsumer` is in the `ErrNoProviders` phase.
 pub const ERR_NO_PROVIDERS: &str = "No valid MaskProviders available.";
+
+/// User-friendly message to display in a `MaskConsumer`'s `status.message`
+/// whenever it is in the `ErrNoProviders` phase because no `MaskProvider`
+/// has any of the default providers of its namespace as a tag.
+pub fn err_no_default_providers(namespace: &str, tags: &[String]) -> String {
+    format!(
+        "No valid MaskProviders available with the tags {} from the {} annotation of namespace '{}'.",
+        tags.join(", "),
+        super::DEFAULT_PROVIDERS_ANNOTATION,
+        namespace,
+    )
+}
+
+/// Message recorded in a `MaskConsumer`'s `status.message` when it reserves
+/// a slot, naming where the tags used to filter the `MaskProvider`s came
+/// from, if any were used, and which topology tier the `MaskProvider` was
+/// picked from, if the `MaskConsumer` is topology aware.
+pub fn reserved_slot(
+    slot: usize,
+    namespace: &str,
+    name: &str,
+    source: Option<&str>,
+    topology: Option<&str>,
+) -> String {
+    let message = match source {
+        Some(source) => format!(
+            "reserved slot {} for MaskProvider {}/{} using the tags from {}",
+            slot, namespace, name, source,
+        ),
+        None => format!(
+            "reserved slot {} for MaskProvider {}/{}",
+            slot, namespace, name,
+        ),
+    };
+    match topology {
+        Some(topology) => format!("{}, {}", message, topology),
+        None => message,
+    }
+}
+
+/// Explains that a `MaskConsumer` took over the slot of its predecessor.
+pub fn succession(predecessor: &str, slot: usize, namespace: &str, name: &str) -> String {
+    format!(
+        "Took over slot {} for MaskProvider {}/{} from MaskConsumer {}, which this one succeeds.",
+        slot, namespace, name, predecessor,
+    )
+}
+
+/// Describes the topology tier a topology-aware `MaskConsumer` was
+/// assigned a `MaskProvider` from, for [`reserved_slot`].
+pub fn topology_tier(region: &str, same_region: bool) -> String {
+    if same_region {
+        format!("in the namespace's region {}", region)
+    } else {
+        format!(
+            "outside the namespace's region {} as no MaskProvider in it had a free slot",
+            region
+        )
+    }
+}
+
+/// User-friendly message to display in `status.message` whenever a `Mask`'s
+/// `MaskConsumer` has to be recreated because a `MaskConsumer` with the same
+/// name is owned by a `Mask` that no longer exists.
+pub const STALE_CONSUMER: &str =
+    "Deleting stale MaskConsumer owned by a previous Mask before recreating it.";
+
+/// User-friendly message to display in `status.message` whenever one of a
+/// `Mask`'s slots can't be reserved because the `MaskConsumer` it would
+/// create is named like one that belongs to another `Mask`.
+pub fn consumer_name_taken(name: &str, owner: &str) -> String {
+    format!(
+        "MaskConsumer '{}' belongs to Mask '{}'. Rename one of the Masks to reserve this slot.",
+        name, owner
+    )
+}
+
+/// User-friendly message to display in `status.message` whenever a `Mask`
+/// or `MaskConsumer` is in the `ErrNamespaceNotOptedIn` phase.
+pub fn err_namespace_not_opted_in(namespace: &str, label: &str) -> String {
+    format!(
+        "Namespace '{}' is not opted in to receiving VPN credentials. Add the label with `kubectl label namespace {} {}`.",
+        namespace, namespace, label,
+    )
+}
+
+/// User-friendly message to display in `status.message` whenever a `Mask`
+/// or `MaskConsumer` is in the `ErrMissingLabels` phase.
+pub fn err_missing_labels(keys: &[String]) -> String {
+    format!(
+        "Every suitable MaskProvider requires labels that the Mask is missing: {}. Add them with non-empty values to be assigned one.",
+        keys.join(", "),
+    )
+}
+
+/// User-friendly message to display in `status.message` whenever a `Mask`
+/// or `MaskConsumer` is in the `ErrQuotaExceeded` phase because the
+/// `MaskQuota`'s limit for the whole namespace is reached.
+pub fn err_quota_exceeded(quota: &str, max_slots: usize) -> String {
+    format!(
+        "MaskQuota '{}' allows at most {} slot(s) in the namespace, all of which are in use.",
+        quota, max_slots,
+    )
+}
+
+/// User-friendly message to display in `status.message` whenever a `Mask`
+/// or `MaskConsumer` is in the `ErrQuotaExceeded` phase because the
+/// `MaskQuota`'s limit for every suitable `MaskProvider`'s tag is reached.
+pub fn err_tag_quota_exceeded(quota: &str, tag: &str, max_slots: usize) -> String {
+    format!(
+        "MaskQuota '{}' allows at most {} slot(s) with MaskProviders tagged '{}', all of which are in use.",
+        quota, max_slots, tag,
+    )
+}
+
+/// User-friendly message to display in `status.message` whenever a `Mask`
+/// or `MaskConsumer` is in the `ErrSecretConflict` phase.
+pub fn err_secret_conflict(namespace: &str, name: &str, reason: &str) -> String {
+    format!(
+        "Secret '{}/{}' already exists and {}. Delete or rename it so the credentials can be copied.",
+        namespace, name, reason,
+    )
+}
+
+/// User-friendly message to display in `status.message` whenever a `Mask`
+/// or `MaskConsumer` is in the `ErrProviderLost` phase.
+pub fn err_provider_lost(namespace: &str, name: &str) -> String {
+    format!(
+        "MaskProvider {}/{} was deleted or replaced, so its credentials no longer work. Recreate the Pods using them once the Mask is assigned another MaskProvider.",
+        namespace, name,
+    )
+}
+
+/// Description of a `MaskProvider` being withdrawn from a `Mask`, shown
+/// in the `Mask`'s `status.providerWithdrawn` and its `ProviderWithdrawn` event.
+pub fn provider_withdrawn(namespace: &str, name: &str) -> String {
+    format!(
+        "MaskProvider {}/{} was deleted, so the Mask was unassigned and will be assigned another MaskProvider if one is available.",
+        namespace, name,
+    )
+}
+
+/// Description of a `MaskProvider` being withdrawn from a `Mask` because it
+/// failed re-verification with `onVerifyFailure: Evict`, shown in the
+/// `Mask`'s `status.providerWithdrawn` and its `VerifyFailureEviction` event.
+pub fn verify_failure_evicted(namespace: &str, name: &str) -> String {
+    format!(
+        "MaskProvider {}/{} failed re-verification and has onVerifyFailure: Evict, so the Mask was unassigned and its credentials deleted. It will be assigned another MaskProvider if one is available.",
+        namespace, name,
+    )
+}
+
+/// Note of the `VerifyFailureEviction` event published on a `MaskProvider`
+/// that evicts its `MaskConsumer`s after failing re-verification.
+pub fn verify_failure_evicting(count: usize) -> String {
+    format!(
+        "Re-verification failed and onVerifyFailure is Evict, evicting {} MaskConsumer(s).",
+        count,
+    )
+}
+
+/// Warning to display in a `MaskProvider`'s status whenever its
+/// `spec.namespaces` references namespaces that don't exist.
+pub fn unknown_namespaces(namespaces: &[String]) -> String {
+    format!(
+        "spec.namespaces references namespaces that don't exist: {}",
+        namespaces.join(", "),
+    )
+}
+
+/// Warning to display in a `MaskProvider`'s status whenever the
+/// `PriorityClass` of its verification `Pod` doesn't exist.
+pub fn unknown_priority_class(name: &str) -> String {
+    format!(
+        "{} references PriorityClass '{}', which doesn't exist. The verification Pod can't be created until it does.",
+        PRIORITY_CLASS_FIELD, name,
+    )
+}
+
+/// Field that [`unknown_priority_class`] warnings start with, which
+/// tells them apart from the other warnings.
+pub const PRIORITY_CLASS_FIELD: &str = "verify.priorityClassName";
+
+/// Note of the Warning event published on each Pod that still uses a
+/// `MaskConsumer`'s credentials Secret when it is deleted.
+pub fn credentials_withdrawn(namespace: &str, secret: &str) -> String {
+    format!(
+        "VPN credentials are being withdrawn: Secret '{}/{}' is being deleted, so the Pod will fail to start again.",
+        namespace, secret,
+    )
+}
+
+/// Note of the Warning event published on a `Mask` whose credentials
+/// Secret is deleted while Pods still use it.
+pub fn credentials_withdrawn_from_pods(namespace: &str, secret: &str, pods: &[String]) -> String {
+    format!(
+        "VPN credentials are being withdrawn while {} Pod(s) still use Secret '{}/{}': {}",
+        pods.len(),
+        namespace,
+        secret,
+        pods.join(", "),
+    )
+}
+
+/// User-friendly message to display in `status.message` whenever a
+/// `MaskConsumer` with `deletionPolicy: WaitForPods` is kept in the
+/// `Terminating` phase by Pods that still use its credentials.
+pub fn withdrawal_blocked(secret: &str, pods: &[String]) -> String {
+    format!(
+        "Waiting for {} Pod(s) still using Secret '{}' to exit before withdrawing the VPN credentials: {}",
+        pods.len(),
+        secret,
+        pods.join(", "),
+    )
+}
+
+/// Warning to display in a `MaskProvider`'s status whenever its
+/// `spec.maskDefaults.secretKeys` uses a name that its
+/// `spec.gluetunVersion` no longer recognizes.
+pub fn gluetun_key_renamed(old: &str, new: &str, since: &(u32, u32, u32)) -> String {
+    format!(
+        "secret key {} was renamed to {} in gluetun v{}.{}.{}",
+        old, new, since.0, since.1, since.2,
+    )
+}
+
+/// Warning to display in a `MaskProvider`'s status whenever its
+/// `spec.maskDefaults.secretKeys` uses a name that its
+/// `spec.gluetunVersion` doesn't recognize yet.
+pub fn gluetun_key_too_new(new: &str, old: &str, since: &(u32, u32, u32)) -> String {
+    format!(
+        "secret key {} is not recognized before gluetun v{}.{}.{}, use {} instead",
+        new, since.0, since.1, since.2, old,
+    )
+}
+
+/// User-friendly message to display in `status.message` whenever a
+/// `MaskProvider` is in the `ErrSecretSuffixCollision` phase.
+pub fn secret_suffix_collision(suffix: &str, namespace: &str, name: &str) -> String {
+    format!(
+        "stableSecretSuffix '{}' is already used by MaskProvider {}/{}, which can be assigned to the same namespaces.",
+        suffix, namespace, name,
+    )
+}
+
+/// User-friendly message to display in `status.message` whenever a
+/// `MaskConsumer` is waiting because the `VpnAccount` shared by the
+/// suitable `MaskProvider`s has no connections available.
+pub fn account_full(accounts: &[String]) -> String {
+    format!(
+        "Waiting on a slot from a MaskProvider: VpnAccount {} has no connections available.",
+        accounts
+            .iter()
+            .map(|a| format!("'{}'", a))
+            .collect::<Vec<_>>()
+            .join(", "),
+    )
+}
+
+/// User-friendly message to display in `status.message` whenever a
+/// `MaskProvider` is in the `ErrAccountNotFound` phase.
+pub fn account_not_found(account: &str) -> String {
+    format!("VpnAccount '{}' does not exist.", account)
+}
+
+/// User-friendly message to display in `status.message` whenever a
+/// `MaskProvider` is in the `ErrConfig` phase because of the error.
+pub fn config_error(error: &str) -> String {
+    format!("Invalid configuration: {}", error)
+}
+
+/// User-friendly message to display in `status.message` whenever a
+/// `MaskProvider`'s deletion is blocked by the `MaskConsumer`s that
+/// are still assigned one of its slots, given as `namespace/name`.
+pub fn deletion_blocked(consumers: &[String]) -> String {
+    format!(
+        "Deletion blocked by {} assigned MaskConsumer(s): {}. Delete them, or annotate the MaskProvider with {}=true to unassign them.",
+        consumers.len(),
+        consumers.join(", "),
+        super::FORCE_DELETE_ANNOTATION,
+    )
+}
+
+/// User-friendly message to display in `status.message` whenever a
+/// `MaskConsumer` is waiting because every suitable `MaskProvider` is
+/// assigned to another member of its anti-affinity group.
+pub fn anti_affinity_waiting(group: &str, members: &[String]) -> String {
+    format!(
+        "Waiting on a slot from a MaskProvider: every suitable MaskProvider is assigned to another Mask in anti-affinity group '{}': {}.",
+        group,
+        members.join(", "),
+    )
+}
+
+/// Note of the event published on a `MaskConsumer` that is unassigned
+/// because its `MaskProvider` is also assigned to an older member of
+/// its anti-affinity group.
+pub fn anti_affinity_conflict(group: &str, namespace: &str, name: &str, member: &str) -> String {
+    format!(
+        "MaskProvider {}/{} is also assigned to Mask '{}' in anti-affinity group '{}', unassigning it so another MaskProvider can be assigned.",
+        namespace, name, member, group,
+    )
+}
+
+/// Message recorded in a `MaskConsumer`'s `status.policyViolation`, and in
+/// the `PolicyViolation` event, when the `spec.namespaces` of its assigned
+/// `MaskProvider` no longer permits its namespace.
+pub fn policy_violation(namespace: &str, provider_namespace: &str, provider_name: &str) -> String {
+    format!(
+        "MaskProvider {}/{} no longer permits namespace '{}' in spec.namespaces.",
+        provider_namespace, provider_name, namespace,
+    )
+}
+
+/// Note of the `PolicyViolation` event published on a `MaskConsumer` that
+/// is unassigned because its `MaskProvider` no longer permits its namespace.
+pub fn policy_eviction(namespace: &str, provider_namespace: &str, provider_name: &str) -> String {
+    format!(
+        "MaskProvider {}/{} no longer permits namespace '{}' in spec.namespaces, unassigning it so another MaskProvider can be assigned.",
+        provider_namespace, provider_name, namespace,
+    )
+}
+
+/// Note of the event published on a `MaskProvider` whose canary was
+/// skipped because every slot is in use.
+pub const CANARY_SKIPPED: &str =
+    "Skipped canary Mask because every slot of the MaskProvider is in use.";
+
+/// Note of the event published on a `MaskProvider` whose canary `Mask`
+/// waited for a slot until it timed out.
+pub fn canary_timed_out_waiting(timeout: &std::time::Duration) -> String {
+    format!(
+        "Canary Mask waited {}s for a slot, but every slot of the MaskProvider stayed in use.",
+        timeout.as_secs()
+    )
+}
+
+/// Fails the verification of a `MaskProvider` whose verification `Mask`
+/// was never assigned it, pointing at the `MaskConsumer` responsible.
+pub fn verify_mask_stalled(
+    namespace: &str,
+    name: &str,
+    timeout: &std::time::Duration,
+    message: Option<&str>,
+) -> String {
+    let mut stalled = format!(
+        "Verification Mask was not assigned the MaskProvider within {}s. Check that MaskConsumer '{}/{}' has the {} label.",
+        timeout.as_secs(),
+        namespace,
+        name,
+        super::VERIFICATION_LABEL,
+    );
+    if let Some(message) = message {
+        stalled.push_str(&format!(" Last status: {}", message));
+    }
+    stalled
+}
+
+/// User-friendly message to display in `status.message` while a
+/// `MaskProvider` waits for other verifications to finish.
+pub fn verify_queued(position: usize) -> String {
+    format!("Queued for verification (position {}).", position)
+}
+
+/// Note of the `CanaryFailed` event published on a `MaskProvider` whose
+/// canary `Mask` ended up in an error phase.
+pub fn canary_failed(phase: &str, message: Option<&str>) -> String {
+    match message {
+        Some(message) => format!("Canary Mask entered the {} phase: {}", phase, message),
+        None => format!("Canary Mask entered the {} phase.", phase),
+    }
+}
+
+/// Note of the `CanaryFailed` event published on a `MaskProvider` whose
+/// canary `Mask` wasn't given well-formed credentials in time.
+pub fn canary_timed_out(timeout: &std::time::Duration, phase: Option<&str>) -> String {
+    format!(
+        "Canary Mask did not receive well-formed credentials within {}s (phase {}).",
+        timeout.as_secs(),
+        phase.unwrap_or("unknown"),
+    )
+}
+
+/// Note of the `HeartbeatExpired` event published on an external
+/// `MaskConsumer` that is deleted because its client stopped renewing
+/// its heartbeat.
+pub fn heartbeat_expired(timeout: &std::time::Duration) -> String {
+    format!(
+        "No heartbeat from the external client within {}s. Releasing its slot.",
+        timeout.as_secs(),
+    )
+}
+
+/// Message recorded in a `ClusterMaskProvider`'s `status.message` when
+/// its credentials `Secret` doesn't exist.
+pub fn cluster_secret_not_found(namespace: &str, name: &str) -> String {
+    format!("Secret '{}/{}' does not exist.", namespace, name)
+}
+
+/// Explains why a `ClusterMaskProvider` can't manage its child in a
+/// namespace: a resource with the child's name is already there.
+pub fn child_conflict(kind: &str, namespace: &str, name: &str) -> String {
+    format!(
+        "{} {}/{} already exists and doesn't belong to the ClusterMaskProvider.",
+        kind, namespace, name
+    )
+}
+
+/// Message recorded in a `ClusterMaskProvider`'s `status.message` when
+/// some of its children are in an error phase or can't be managed.
+pub fn children_degraded(namespaces: &[String]) -> String {
+    format!(
+        "Child MaskProviders are degraded in namespaces: {}.",
+        namespaces.join(", ")
+    )
+}
+
+/// Message recorded in a `ClusterMaskProvider`'s `status.message` while
+/// some of its children aren't ready to be assigned yet.
+pub fn children_propagating(pending: usize, total: usize) -> String {
+    format!(
+        "Waiting for {} of {} child MaskProviders to become Ready.",
+        pending, total
+    )
+}
+
+/// Message recorded in a `ClusterMaskProvider`'s `status.message` when
+/// every child is ready to be assigned.
+pub fn children_ready(namespaces: usize, active_slots: usize, max_slots: usize) -> String {
+    format!(
+        "{} of {} slots in use across {} namespaces.",
+        active_slots, max_slots, namespaces
+    )
+}
+
+/// User-friendly message to display in `status.message` while the
+/// verification `Pod` is recreated after a disruption such as its node
+/// being lost or drained.
+pub fn verify_pod_rescheduled(reason: &str) -> String {
+    format!(
+        "Verification Pod was disrupted ({}) and is being recreated.",
+        reason
+    )
+}
+
+/// Fails the verification of a `MaskProvider` whose verification `Pod`
+/// kept being disrupted, or was disrupted too late to be recreated.
+pub fn verify_pod_disrupted(reason: &str, reschedules: usize) -> String {
+    format!(
+        "Verification Pod was disrupted ({}) after being recreated {} time(s) within the verification timeout.",
+        reason, reschedules,
+    )
+}
+
+/// Fails the verification of a `MaskProvider` whose probe exited nonzero,
+/// with the reason the probe gave, e.g. an egress assertion that failed.
+pub fn verify_probe_failed(reason: &str) -> String {
+    format!("Verification probe failed: {}", reason)
+}
+
+/// Fails the verification of a `MaskProvider` whose probe exited nonzero
+/// without giving a reason.
+pub fn verify_probe_exited(exit_code: i32) -> String {
+    format!("Verification probe exited with code {}.", exit_code)
+}
+
+/// Explains why a range in `verify.assert.cidrs` can't be used.
+pub fn invalid_assert_cidr(cidr: &str) -> String {
+    format!(
+        "'{}' in verify.assert.cidrs is not an IPv4 range in CIDR notation, e.g. 10.0.0.0/8.",
+        cidr
+    )
+}
+
+/// Shown by a `MaskProvider` whose credentials were verified by dialing.
+pub const VERIFIED: &str = "VPN credentials verified as authentic.";
+
+/// Shown by a `MaskProvider` whose credentials were verified by dialing,
+/// with the note the probe left, e.g. because it couldn't look up the
+/// location of the egress to assert it.
+pub fn verified_with_note(note: &str) -> String {
+    format!("{} {}", VERIFIED, note)
+}
+
+/// Shown by a `MaskProvider` whose credentials passed the static checks
+/// of `verify.mode: KeysOnly`, which doesn't dial them.
+pub const KEYS_VERIFIED: &str = "Credentials Secret passed the static checks of verify.mode KeysOnly. The credentials weren't dialed, so they may still be rejected by the VPN service.";
+
+/// Appended to the `Ready` and `Active` messages of a `MaskProvider` whose
+/// credentials were last verified with `verify.mode: KeysOnly`.
+pub const KEYS_ONLY_NOTE: &str =
+    "Credentials were only checked statically (verify.mode KeysOnly), not dialed.";
+
+/// Fails the `KeysOnly` verification of credentials missing a required key.
+pub fn verify_key_missing(key: &str) -> String {
+    format!("Credentials Secret is missing the required key '{}'.", key)
+}
+
+/// Fails the `KeysOnly` verification of credentials with an empty value.
+pub fn verify_key_empty(key: &str) -> String {
+    format!(
+        "Credentials Secret has an empty value for the key '{}'.",
+        key
+    )
+}
+
+/// Fails the `KeysOnly` verification of credentials whose value doesn't
+/// match the key's pattern. The value isn't shown, as it may be secret.
+pub fn verify_key_mismatch(key: &str, pattern: &str) -> String {
+    format!(
+        "Credentials Secret's value for the key '{}' doesn't match the pattern '{}' in verify.keyPatterns.",
+        key, pattern,
+    )
+}
+
+/// Fails the `KeysOnly` verification of credentials without any keys,
+/// when no keys are required.
+pub const VERIFY_NO_KEYS: &str = "Credentials Secret has no keys.";
+
+/// Explains why a pattern in `verify.keyPatterns` can't be used.
+pub fn invalid_key_pattern(key: &str, reason: &str) -> String {
+    format!(
+        "Invalid pattern for '{}' in verify.keyPatterns: {}",
+        key, reason
+    )
+}
+
+/// Rejects a Pod whose sidecar is to be injected with the credentials
+/// of a `Mask` that doesn't exist.
+pub fn inject_mask_not_found(namespace: &str, name: &str) -> String {
+    format!("Mask {}/{} does not exist.", namespace, name)
+}
+
+/// Rejects a Pod whose sidecar is to be injected with the credentials
+/// of a `Mask` that doesn't have them yet.
+pub fn inject_mask_not_ready(
+    namespace: &str,
+    name: &str,
+    phase: Option<vpn_types::MaskPhase>,
+) -> String {
+    let phase = phase.map_or_else(|| "Pending".to_owned(), |phase| phase.to_string());
+    format!(
+        "Mask {}/{} is {} and has no VPN credentials yet. Retry once it's Active, or annotate the Pod with {}=wait to wait for them.",
+        namespace,
+        name,
+        phase,
+        super::INJECT_NOT_READY_ANNOTATION,
+    )
+}
+
+/// Rejects a Pod with an invalid sidecar injection annotation.
+pub fn inject_invalid_annotation(annotation: &str, reason: &str) -> String {
+    format!("Invalid annotation {}: {}", annotation, reason)
+}
+
+/// Rejects a Pod whose sidecar can't be injected because one of its
+/// own containers already has the sidecar's name.
+pub fn inject_container_conflict(container: &str) -> String {
+    format!(
+        "The Pod already has a container named '{}', which the injected gluetun sidecar would replace. Rename the container to have the sidecar injected.",
+        container
+    )
+}
+
+/// Rejects a Pod that waits for the credentials of a `Mask` that isn't
+/// assigned a `MaskProvider` yet, so the name of its credentials
+/// `Secret` isn't known.
+pub fn inject_mask_unassigned(namespace: &str, name: &str) -> String {
+    format!(
+        "Mask {}/{} hasn't been assigned a MaskProvider yet, so its VPN credentials can't be waited for. Retry once it's assigned.",
+        namespace, name
+    )
+}
@@ -0,0 +1,122 @@
+/// Tags of the controllers, each of which exports [`CONTROLLER_METRICS`].
+pub const CONTROLLERS: [&str; 6] = [
+    "masks",
+    "providers",
+    "reservations",
+    "consumers",
+    "clusterproviders",
+    "jobs",
+];
+
+/// Number of reconciliations by a controller.
+pub const RECONCILE_COUNTER: &str = "reconcile_counter";
+
+/// Number of actions taken by a controller.
+pub const ACTION_COUNTER: &str = "action_counter";
+
+/// Read phase latency of a controller.
+pub const READ_DURATION_SECONDS: &str = "read_duration_seconds";
+
+/// Write phase latency of a controller, labeled by outcome.
+pub const WRITE_DURATION_SECONDS: &str = "write_duration_seconds";
+
+/// Metrics exported by every controller, prefixed with its tag.
+pub const CONTROLLER_METRICS: [&str; 4] = [
+    RECONCILE_COUNTER,
+    ACTION_COUNTER,
+    READ_DURATION_SECONDS,
+    WRITE_DURATION_SECONDS,
+];
+
+/// Number of failed actions, labeled by controller kind, action and status code.
+pub const ACTION_ERRORS_TOTAL: &str = "reconcile_action_errors_total";
+
+/// Number of status patches, labeled by resource kind.
+pub const STATUS_PATCHES_TOTAL: &str = "status_patches_total";
+
+/// Whether new assignments are frozen.
+pub const ASSIGNMENTS_FROZEN: &str = "assignments_frozen";
+
+/// Time from a Mask's creation until it first became Active.
+pub const MASK_TIME_TO_ACTIVE_SECONDS: &str = "mask_time_to_active_seconds";
+
+/// One for each Mask's current phase.
+pub const MASK_PHASE: &str = "mask_phase";
+
+/// One for each MaskProvider's current phase.
+pub const PROVIDER_PHASE: &str = "provider_phase";
+
+/// Number of slots in use for each MaskProvider.
+pub const PROVIDER_ACTIVE_SLOTS: &str = "provider_active_slots";
+
+/// Maximum number of slots for each MaskProvider.
+pub const PROVIDER_MAX_SLOTS: &str = "provider_max_slots";
+
+/// Number of finished canary Masks, labeled by result.
+pub const PROVIDER_CANARIES_TOTAL: &str = "provider_canaries_total";
+
+/// Time from a canary Mask's creation until it finished.
+pub const PROVIDER_CANARY_DURATION_SECONDS: &str = "provider_canary_duration_seconds";
+
+/// Number of MaskProviders waiting to begin verification.
+pub const VERIFICATION_QUEUE_DEPTH: &str = "verification_queue_depth";
+
+/// Information about each MaskProvider, always one.
+pub const PROVIDER_INFO: &str = "provider_info";
+
+/// Information about each Mask, always one.
+pub const MASK_INFO: &str = "mask_info";
+
+/// Number of HTTP requests made to the metrics server.
+pub const HTTP_REQUESTS_TOTAL: &str = "http_requests_total";
+
+/// Metrics server HTTP response sizes in bytes.
+pub const HTTP_RESPONSE_SIZE_BYTES: &str = "http_response_size_bytes";
+
+/// Metrics server HTTP request latencies in seconds.
+pub const HTTP_REQUEST_DURATION_SECONDS: &str = "http_request_duration_seconds";
+
+/// Metrics exported once per process.
+pub const PROCESS_METRICS: [&str; 16] = [
+    ACTION_ERRORS_TOTAL,
+    STATUS_PATCHES_TOTAL,
+    ASSIGNMENTS_FROZEN,
+    MASK_TIME_TO_ACTIVE_SECONDS,
+    MASK_PHASE,
+    PROVIDER_PHASE,
+    PROVIDER_ACTIVE_SLOTS,
+    PROVIDER_MAX_SLOTS,
+    PROVIDER_CANARIES_TOTAL,
+    PROVIDER_CANARY_DURATION_SECONDS,
+    VERIFICATION_QUEUE_DEPTH,
+    PROVIDER_INFO,
+    MASK_INFO,
+    HTTP_REQUESTS_TOTAL,
+    HTTP_RESPONSE_SIZE_BYTES,
+    HTTP_REQUEST_DURATION_SECONDS,
+];
+
+/// Returns the full name of a metric exported once per process.
+pub fn full_name(prefix: &str, metric: &str) -> String {
+    format!("{}_{}", prefix, metric)
+}
+
+/// Returns the full name of a metric exported by the controller with `tag`.
+pub fn controller_name(prefix: &str, tag: &str, metric: &str) -> String {
+    format!("{}_{}_{}", prefix, tag, metric)
+}
+
+/// Returns the full name of every metric exported by the operator. The
+/// generated dashboards and alert rules only reference these names.
+pub fn all(prefix: &str) -> Vec<String> {
+    let controllers = CONTROLLERS.iter().flat_map(|tag| {
+        CONTROLLER_METRICS
+            .iter()
+            .map(move |metric| controller_name(prefix, tag, metric))
+    });
+    PROCESS_METRICS
+        .iter()
+        .map(|metric| full_name(prefix, metric))
+        .chain(controllers)
+        .collect()
+}
@@ -1,4 +1,361 @@
-use prometheus::{register_counter_vec, register_histogram_vec, CounterVec, HistogramVec};
+use kube::ResourceExt;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_counter_vec, register_counter_vec_with_registry, register_histogram_vec,
+    register_histogram_vec_with_registry, register_int_gauge, register_int_gauge_vec, CounterVec,
+    HistogramVec, IntGauge, IntGaugeVec, Registry,
+};
+use std::{collections::HashMap, sync::Mutex, time::Instant};
+use vpn_types::*;
+
+use super::metric_names::{self, controller_name, full_name};
+
+lazy_static! {
+    /// Number of failed actions, labeled by controller kind, action and
+    /// the API server's status code, if any. This is shared by all controllers in the process, so it can't be
+    /// a part of [`ControllerMetrics`].
+    pub static ref ACTION_ERROR_COUNTER: CounterVec = register_counter_vec!(
+        &full_name(&prefix(), metric_names::ACTION_ERRORS_TOTAL),
+        "Number of failed actions taken by the controllers.",
+        &["kind", "action", "code"]
+    )
+    .unwrap();
+
+    /// Number of status patches, labeled by the kind of resource. Each
+    /// one is a write to etcd, including those that only refresh the
+    /// status without changing the phase.
+    pub static ref STATUS_PATCH_COUNTER: CounterVec = register_counter_vec!(
+        &full_name(&prefix(), metric_names::STATUS_PATCHES_TOTAL),
+        "Number of status patches made by the controllers.",
+        &["kind"]
+    )
+    .unwrap();
+
+    /// Whether new assignments are frozen, either by the CLI flag or
+    /// the operator config ConfigMap. One if frozen, zero otherwise.
+    pub static ref ASSIGNMENTS_FROZEN_GAUGE: IntGauge = register_int_gauge!(
+        &full_name(&prefix(), metric_names::ASSIGNMENTS_FROZEN),
+        "Whether new MaskProvider assignments are frozen."
+    )
+    .unwrap();
+
+    /// Time from a Mask's creation until it first became Active, labeled
+    /// by the namespace of the MaskProvider it was assigned. The buckets
+    /// are finer around 30 seconds so SLOs can be measured against it.
+    pub static ref TIME_TO_ACTIVE_HISTOGRAM: HistogramVec = register_histogram_vec!(
+        &full_name(&prefix(), metric_names::MASK_TIME_TO_ACTIVE_SECONDS),
+        "Time from a Mask's creation until it first became Active.",
+        &["provider_namespace"],
+        vec![1.0, 2.5, 5.0, 10.0, 15.0, 20.0, 25.0, 30.0, 45.0, 60.0, 120.0, 300.0]
+    )
+    .unwrap();
+
+    /// Current phase of each Mask.
+    pub static ref MASK_PHASE_GAUGE: PhaseGauge = PhaseGauge::new(
+        register_int_gauge_vec!(
+            &full_name(&prefix(), metric_names::MASK_PHASE),
+            "One for the current phase of each Mask.",
+            &["namespace", "name", "phase"]
+        )
+        .unwrap()
+    );
+
+    /// Current phase of each MaskProvider.
+    pub static ref PROVIDER_PHASE_GAUGE: PhaseGauge = PhaseGauge::new(
+        register_int_gauge_vec!(
+            &full_name(&prefix(), metric_names::PROVIDER_PHASE),
+            "One for the current phase of each MaskProvider.",
+            &["namespace", "name", "phase"]
+        )
+        .unwrap()
+    );
+
+    /// Number of slots in use for each MaskProvider.
+    pub static ref PROVIDER_ACTIVE_SLOTS_GAUGE: IntGaugeVec = register_int_gauge_vec!(
+        &full_name(&prefix(), metric_names::PROVIDER_ACTIVE_SLOTS),
+        "Number of slots in use for each MaskProvider.",
+        &["namespace", "name"]
+    )
+    .unwrap();
+
+    /// Maximum number of slots for each MaskProvider.
+    pub static ref PROVIDER_MAX_SLOTS_GAUGE: IntGaugeVec = register_int_gauge_vec!(
+        &full_name(&prefix(), metric_names::PROVIDER_MAX_SLOTS),
+        "Maximum number of slots for each MaskProvider.",
+        &["namespace", "name"]
+    )
+    .unwrap();
+
+    /// Number of finished canary Masks, labeled by the MaskProvider they
+    /// tested and the result, including skipped ones.
+    pub static ref CANARY_COUNTER: CounterVec = register_counter_vec!(
+        &full_name(&prefix(), metric_names::PROVIDER_CANARIES_TOTAL),
+        "Number of finished canary Masks.",
+        &["namespace", "name", "result"]
+    )
+    .unwrap();
+
+    /// Time from a canary Mask's creation until it succeeded or failed,
+    /// labeled by the MaskProvider it tested and the result.
+    pub static ref CANARY_DURATION_HISTOGRAM: HistogramVec = register_histogram_vec!(
+        &full_name(&prefix(), metric_names::PROVIDER_CANARY_DURATION_SECONDS),
+        "Time from a canary Mask's creation until it finished.",
+        &["namespace", "name", "result"],
+        vec![1.0, 2.5, 5.0, 10.0, 15.0, 20.0, 25.0, 30.0, 45.0, 60.0, 120.0]
+    )
+    .unwrap();
+
+    /// Number of MaskProviders due for verification that are waiting for
+    /// one of the `--max-concurrent-verifications` to finish.
+    pub static ref VERIFICATION_QUEUE_DEPTH_GAUGE: IntGauge = register_int_gauge!(
+        &full_name(&prefix(), metric_names::VERIFICATION_QUEUE_DEPTH),
+        "Number of MaskProviders waiting to begin verification."
+    )
+    .unwrap();
+
+    /// Information about each MaskProvider for joining with other
+    /// metrics. Only registered if info metrics are enabled.
+    pub static ref PROVIDER_INFO_GAUGE: InfoGauge = InfoGauge::new(
+        register_int_gauge_vec!(
+            &full_name(&prefix(), metric_names::PROVIDER_INFO),
+            "Information about each MaskProvider, always one.",
+            &PROVIDER_INFO_LABELS
+        )
+        .unwrap()
+    );
+
+    /// Information about each Mask for joining with other metrics.
+    /// Only registered if info metrics are enabled.
+    pub static ref MASK_INFO_GAUGE: InfoGauge = InfoGauge::new(
+        register_int_gauge_vec!(
+            &full_name(&prefix(), metric_names::MASK_INFO),
+            "Information about each Mask, always one.",
+            &MASK_INFO_LABELS
+        )
+        .unwrap()
+    );
+}
+
+/// Labels of [`PROVIDER_INFO_GAUGE`].
+pub const PROVIDER_INFO_LABELS: [&str; 5] = ["namespace", "name", "uid", "tags", "max_slots"];
+
+/// Labels of [`MASK_INFO_GAUGE`].
+pub const MASK_INFO_LABELS: [&str; 4] = ["namespace", "name", "provider", "slot"];
+
+/// Exports the phase of each resource as a series that is one for its
+/// current phase. The series for the previous phase is removed when the
+/// phase changes, so each resource has at most one series at a time.
+pub struct PhaseGauge {
+    gauge: IntGaugeVec,
+
+    /// Current phase of each resource, keyed by namespace and name.
+    phases: Mutex<HashMap<(String, String), String>>,
+}
+
+impl PhaseGauge {
+    pub fn new(gauge: IntGaugeVec) -> Self {
+        PhaseGauge {
+            gauge,
+            phases: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the resource's current phase.
+    pub fn set(&self, namespace: &str, name: &str, phase: &str) {
+        let mut phases = self.phases.lock().unwrap();
+        let key = (namespace.to_owned(), name.to_owned());
+        if let Some(previous) = phases.insert(key, phase.to_owned()) {
+            if previous != phase {
+                let _ = self
+                    .gauge
+                    .remove_label_values(&[namespace, name, &previous]);
+            }
+        }
+        self.gauge
+            .with_label_values(&[namespace, name, phase])
+            .set(1);
+    }
+
+    /// Removes the resource's series, e.g. when it's deleted.
+    pub fn remove(&self, namespace: &str, name: &str) {
+        let mut phases = self.phases.lock().unwrap();
+        if let Some(previous) = phases.remove(&(namespace.to_owned(), name.to_owned())) {
+            let _ = self
+                .gauge
+                .remove_label_values(&[namespace, name, &previous]);
+        }
+    }
+}
+
+/// Exports information about each resource as the labels of a series
+/// that is always one. The series is replaced when the information
+/// changes, so each resource has at most one series at a time.
+pub struct InfoGauge {
+    gauge: IntGaugeVec,
+
+    /// Label values of each resource's series following its namespace
+    /// and name, keyed by namespace and name.
+    info: Mutex<HashMap<(String, String), Vec<String>>>,
+}
+
+impl InfoGauge {
+    pub fn new(gauge: IntGaugeVec) -> Self {
+        InfoGauge {
+            gauge,
+            info: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the information about the resource.
+    pub fn set(&self, namespace: &str, name: &str, info: Vec<String>) {
+        let mut infos = self.info.lock().unwrap();
+        let key = (namespace.to_owned(), name.to_owned());
+        if let Some(previous) = infos.insert(key, info.clone()) {
+            if previous != info {
+                let _ = self
+                    .gauge
+                    .remove_label_values(&label_values(namespace, name, &previous));
+            }
+        }
+        self.gauge
+            .with_label_values(&label_values(namespace, name, &info))
+            .set(1);
+    }
+
+    /// Removes the resource's series, e.g. when it's deleted.
+    pub fn remove(&self, namespace: &str, name: &str) {
+        let mut infos = self.info.lock().unwrap();
+        if let Some(previous) = infos.remove(&(namespace.to_owned(), name.to_owned())) {
+            let _ = self
+                .gauge
+                .remove_label_values(&label_values(namespace, name, &previous));
+        }
+    }
+}
+
+/// Returns the namespace and name followed by the other label values.
+fn label_values<'a>(namespace: &'a str, name: &'a str, info: &'a [String]) -> Vec<&'a str> {
+    [namespace, name]
+        .into_iter()
+        .chain(info.iter().map(String::as_str))
+        .collect()
+}
+
+/// Exports information about the MaskProvider to the gauge: its UID,
+/// its tags sorted and joined with commas, and its maximum number of
+/// slots. The series is removed once the MaskProvider is being deleted.
+pub fn observe_provider_info(gauge: &InfoGauge, instance: &MaskProvider) {
+    let namespace = instance.namespace().unwrap_or_default();
+    let name = instance.name_any();
+    if instance.metadata.deletion_timestamp.is_some() {
+        gauge.remove(&namespace, &name);
+        return;
+    }
+    let mut tags = instance.spec.tags.clone().unwrap_or_default();
+    tags.sort();
+    gauge.set(
+        &namespace,
+        &name,
+        vec![
+            instance.uid().unwrap_or_default(),
+            tags.join(","),
+            instance.spec.max_slots.to_string(),
+        ],
+    );
+}
+
+/// Exports information about the Mask to the gauge: the name of the
+/// MaskProvider assigned to its MaskConsumer and the reserved slot,
+/// both empty while it's unassigned. The series is removed once the
+/// Mask is being deleted.
+pub fn observe_mask_info(gauge: &InfoGauge, instance: &Mask, consumer: Option<&MaskConsumer>) {
+    let namespace = instance.namespace().unwrap_or_default();
+    let name = instance.name_any();
+    if instance.metadata.deletion_timestamp.is_some() {
+        gauge.remove(&namespace, &name);
+        return;
+    }
+    let assigned = consumer
+        .and_then(|c| c.status.as_ref())
+        .and_then(|s| s.provider.as_ref());
+    gauge.set(
+        &namespace,
+        &name,
+        vec![
+            assigned.map(|p| p.name.clone()).unwrap_or_default(),
+            assigned.map(|p| p.slot.to_string()).unwrap_or_default(),
+        ],
+    );
+}
+
+/// Exports information about the Mask, if info metrics are `enabled`.
+/// They have a series for every resource, so they're opt-in.
+pub fn export_mask_info(enabled: bool, instance: &Mask, consumer: Option<&MaskConsumer>) {
+    if enabled {
+        observe_mask_info(&MASK_INFO_GAUGE, instance, consumer);
+    }
+}
+
+/// Exports the Mask's phase. The series is removed once the Mask
+/// is being deleted, so deleted Masks aren't reported as waiting.
+pub fn observe_mask(instance: &Mask) {
+    let namespace = instance.namespace().unwrap_or_default();
+    let name = instance.name_any();
+    match instance.status.as_ref().and_then(|s| s.phase) {
+        Some(phase) if instance.metadata.deletion_timestamp.is_none() => {
+            MASK_PHASE_GAUGE.set(&namespace, &name, &phase.to_string())
+        }
+        _ => MASK_PHASE_GAUGE.remove(&namespace, &name),
+    }
+}
+
+/// Exports the MaskProvider's phase and slot usage, and information
+/// about it if `info` metrics are enabled. The series are removed once
+/// the MaskProvider is being deleted.
+pub fn observe_provider(instance: &MaskProvider, info: bool) {
+    if info {
+        observe_provider_info(&PROVIDER_INFO_GAUGE, instance);
+    }
+    let namespace = instance.namespace().unwrap_or_default();
+    let name = instance.name_any();
+    let labels = [namespace.as_str(), name.as_str()];
+    if instance.metadata.deletion_timestamp.is_some() {
+        PROVIDER_PHASE_GAUGE.remove(&namespace, &name);
+        let _ = PROVIDER_ACTIVE_SLOTS_GAUGE.remove_label_values(&labels);
+        let _ = PROVIDER_MAX_SLOTS_GAUGE.remove_label_values(&labels);
+        return;
+    }
+    let status = instance.status.as_ref();
+    match status.and_then(|s| s.phase) {
+        Some(phase) => PROVIDER_PHASE_GAUGE.set(&namespace, &name, &phase.to_string()),
+        None => PROVIDER_PHASE_GAUGE.remove(&namespace, &name),
+    }
+    PROVIDER_ACTIVE_SLOTS_GAUGE
+        .with_label_values(&labels)
+        .set(status.and_then(|s| s.active_slots).unwrap_or(0) as i64);
+    PROVIDER_MAX_SLOTS_GAUGE
+        .with_label_values(&labels)
+        .set(instance.spec.max_slots as i64);
+}
+
+/// Counts the MaskProvider's finished canary and observes how long it
+/// took, unless it was skipped and there is no duration.
+pub fn observe_canary(
+    instance: &MaskProvider,
+    result: MaskProviderCanaryResult,
+    duration: Option<std::time::Duration>,
+) {
+    let namespace = instance.namespace().unwrap_or_default();
+    let name = instance.name_any();
+    let result = result.to_string();
+    let labels = [namespace.as_str(), name.as_str(), result.as_str()];
+    CANARY_COUNTER.with_label_values(&labels).inc();
+    if let Some(duration) = duration {
+        CANARY_DURATION_HISTOGRAM
+            .with_label_values(&labels)
+            .observe(duration.as_secs_f64());
+    }
+}
 
 /// Contains the metrics for a controller. Each controller will use
 /// unique metric names, but they will use these same metric types.
@@ -12,7 +369,7 @@ pub struct ControllerMetrics {
     /// Read phase latency of the controller.
     pub read_histogram: HistogramVec,
 
-    /// Write phase latency of the controller.
+    /// Write phase latency of the controller, labeled by outcome.
     pub write_histogram: HistogramVec,
 }
 
@@ -20,29 +377,39 @@ impl ControllerMetrics {
     /// Creates a new set of metrics for a controller. The tag is used
     /// to associate the metrics with a specific controller.
     pub fn new(tag: &str) -> Self {
-        let pre = format!("{}_{}", prefix(), tag);
-        let reconcile_counter = register_counter_vec!(
-            &format!("{}_reconcile_counter", pre),
+        Self::with_registry(tag, prometheus::default_registry())
+    }
+
+    /// Creates a new set of metrics for a controller, registering
+    /// them with the given registry instead of the default one.
+    pub fn with_registry(tag: &str, registry: &Registry) -> Self {
+        let prefix = prefix();
+        let reconcile_counter = register_counter_vec_with_registry!(
+            &controller_name(&prefix, tag, metric_names::RECONCILE_COUNTER),
             "Number of reconciliations by the controller.",
-            &["name", "namespace"]
+            &["name", "namespace"],
+            registry
         )
         .unwrap();
-        let action_counter = register_counter_vec!(
-            &format!("{}_action_counter", pre),
+        let action_counter = register_counter_vec_with_registry!(
+            &controller_name(&prefix, tag, metric_names::ACTION_COUNTER),
             "Number of actions taken by the controller.",
-            &["name", "namespace", "action"]
+            &["name", "namespace", "action"],
+            registry
         )
         .unwrap();
-        let read_histogram = register_histogram_vec!(
-            &format!("{}_read_duration_seconds", pre),
+        let read_histogram = register_histogram_vec_with_registry!(
+            &controller_name(&prefix, tag, metric_names::READ_DURATION_SECONDS),
             "Read phase latency of the controller.",
-            &["name", "namespace", "action"]
+            &["name", "namespace", "action"],
+            registry
         )
         .unwrap();
-        let write_histogram = register_histogram_vec!(
-            &format!("{}_write_duration_seconds", pre),
+        let write_histogram = register_histogram_vec_with_registry!(
+            &controller_name(&prefix, tag, metric_names::WRITE_DURATION_SECONDS),
             "Write phase latency of the controller.",
-            &["name", "namespace", "action"]
+            &["name", "namespace", "action", "outcome"],
+            registry
         )
         .unwrap();
         ControllerMetrics {
@@ -52,6 +419,74 @@ impl ControllerMetrics {
             write_histogram,
         }
     }
+
+    /// Increments the number of reconciliations for the resource.
+    pub fn reconcile(&self, name: &str, namespace: &str) {
+        self.reconcile_counter
+            .with_label_values(&[name, namespace])
+            .inc();
+    }
+
+    /// Reports the read phase latency, measured from `start`, and
+    /// increments the counter for the action that was decided upon.
+    pub fn read(&self, name: &str, namespace: &str, action: &str, start: Instant) {
+        self.read_histogram
+            .with_label_values(&[name, namespace, action])
+            .observe(start.elapsed().as_secs_f64());
+        self.action_counter
+            .with_label_values(&[name, namespace, action])
+            .inc();
+    }
+
+    /// Starts a timer for the write phase of the action.
+    pub fn write(&self, name: &str, namespace: &str, action: &str) -> WriteTimer {
+        WriteTimer {
+            histogram: self.write_histogram.clone(),
+            labels: [name.to_owned(), namespace.to_owned(), action.to_owned()],
+            start: Instant::now(),
+            observed: false,
+        }
+    }
+}
+
+/// Guard for timing the write phase of reconciliation. The duration
+/// is observed exactly once, labeled with the outcome of the action.
+/// If the guard is dropped before [`WriteTimer::observe`] is called,
+/// e.g. because the reconciliation was cancelled, the duration is
+/// observed with the `error` outcome.
+pub struct WriteTimer {
+    histogram: HistogramVec,
+    labels: [String; 3],
+    start: Instant,
+    observed: bool,
+}
+
+impl WriteTimer {
+    /// Observes the duration of the write phase with the outcome
+    /// corresponding to the action's result.
+    pub fn observe<T, E>(mut self, result: &Result<T, E>) {
+        self.observe_outcome(match result {
+            Ok(_) => "success",
+            Err(_) => "error",
+        });
+    }
+
+    fn observe_outcome(&mut self, outcome: &str) {
+        if self.observed {
+            return;
+        }
+        self.observed = true;
+        let [name, namespace, action] = &self.labels;
+        self.histogram
+            .with_label_values(&[name, namespace, action, outcome])
+            .observe(self.start.elapsed().as_secs_f64());
+    }
+}
+
+impl Drop for WriteTimer {
+    fn drop(&mut self) {
+        self.observe_outcome("error");
+    }
 }
 
 /// Returns the metrics prefix, which can be overridden with the
@@ -1,16 +1,35 @@
 use std::time::Duration;
+use vpn_types::{MaskConsumer, MaskConsumerPurpose, MaskReservation};
 
+pub mod capabilities;
+pub mod client;
+pub mod clock;
+pub mod config;
+pub mod events;
+pub mod explain;
 pub mod finalizer;
+pub mod lease;
+pub mod list;
+pub mod metric_names;
 pub mod metrics;
 pub mod patch;
+pub mod preview;
+pub mod schedule;
 
 pub(crate) mod messages;
 
 mod error;
-mod merge;
 
 pub use error::*;
-pub use merge::deep_merge;
+pub(crate) use vpn_render::{MANAGER_NAME, VERIFICATION_LABEL};
+
+/// Exit code of the process when its settings are invalid,
+/// as `EX_CONFIG` in sysexits.h.
+pub(crate) const EXIT_CONFIG: i32 = 78;
+
+/// Exit code of the process when a server can't listen on its
+/// port, e.g. because it's already in use, as `EX_UNAVAILABLE`.
+pub(crate) const EXIT_UNAVAILABLE: i32 = 69;
 
 /// The default interval for requeuing a managed resource.
 pub(crate) const PROBE_INTERVAL: Duration = Duration::from_secs(12);
@@ -19,10 +38,207 @@ pub(crate) const PROBE_INTERVAL: Duration = Duration::from_secs(12);
 /// to the originating Provider UID.
 pub(crate) const PROVIDER_UID_LABEL: &str = "vpn.beebs.dev/owner";
 
-/// Name of the kubernetes resource manager.
-pub(crate) const MANAGER_NAME: &str = "vpn-operator";
+/// Name of the label the operator sets on the Secrets referenced by
+/// MaskProviders, so only those Secrets are watched for changes.
+pub(crate) const PROVIDER_SECRET_LABEL: &str = "vpn.beebs.dev/provider-secret";
+
+/// Name of the label on a copied credentials Secret holding the name of
+/// the Mask it was copied for, shortened to fit in a label value.
+pub(crate) const MASK_NAME_LABEL: &str = "vpn.beebs.dev/mask";
+
+/// Name of the label on a copied credentials Secret holding the UID
+/// of the MaskConsumer it was copied for.
+pub(crate) const CONSUMER_UID_LABEL: &str = "vpn.beebs.dev/consumer-uid";
+
+/// Name of the label on a MaskReservation holding the namespace of the
+/// MaskConsumer it was made for, so the reservations counting towards a
+/// namespace's MaskQuotas can be listed by the API server.
+pub(crate) const CONSUMER_NAMESPACE_LABEL: &str = "vpn.beebs.dev/consumer-namespace";
+
+/// Name of the label on the MaskConsumers of a Mask's additional slots
+/// holding the UID of the Mask, so they can be listed without knowing
+/// how many there are.
+pub(crate) const MASK_UID_LABEL: &str = "vpn.beebs.dev/mask-uid";
+
+/// Name of the label on the MaskConsumers of a Mask's additional slots
+/// holding the index of the slot they reserve.
+pub(crate) const MASK_SLOT_LABEL: &str = "vpn.beebs.dev/mask-slot";
+
+/// Name of the annotation on a copied credentials Secret holding the
+/// version of gluetun that the credentials are written for.
+pub(crate) const GLUETUN_VERSION_ANNOTATION: &str = "vpn.beebs.dev/gluetun-version";
+
+/// Name of the annotation on a copied credentials Secret holding the
+/// SHA-256 checksum of its data. With `--annotate-consuming-pods`, it is
+/// also set on the Pod templates of opted-in workloads using the Secret.
+pub(crate) const CREDENTIALS_HASH_ANNOTATION: &str = "vpn.beebs.dev/credentials-hash";
+
+/// Name of the annotation on a copied credentials Secret holding the
+/// checksum of the MaskProvider's credentials it was copied from, as in
+/// the MaskProvider's `status.secretHash`. A copy whose annotation differs
+/// from the status is stale and is copied again.
+pub(crate) const SOURCE_HASH_ANNOTATION: &str = "vpn.beebs.dev/source-hash";
+
+/// Name of the label that opts a Deployment or StatefulSet in to having
+/// its Pod template annotated with the checksum of the credentials it
+/// uses when set to `"true"`, so that new credentials roll it out.
+pub(crate) const ROLLOUT_LABEL: &str = "vpn.beebs.dev/rollout-on-credentials-change";
+
+/// An annotation on a MaskProvider that triggers verification whenever
+/// its value differs from the MaskProvider's `status.lastManualVerify`.
+pub(crate) const VERIFY_NOW_ANNOTATION: &str = "vpn.beebs.dev/verify-now";
+
+/// An annotation on a verification Mask listing the uids of the verification
+/// Pods that were deleted to be recreated because they were disrupted,
+/// e.g. by their node being drained, during the verification cycle.
+pub(crate) const VERIFY_RESCHEDULED_ANNOTATION: &str = "vpn.beebs.dev/verify-rescheduled";
+
+/// An annotation that allows a MaskProvider to be deleted while
+/// MaskConsumers are still assigned its slots when set to `"true"`.
+/// Without it, deletion is blocked until the slots are released.
+pub(crate) const FORCE_DELETE_ANNOTATION: &str = "vpn.beebs.dev/force-delete";
+
+/// An annotation on a Namespace with a comma-separated list of MaskProvider
+/// tags, used to filter the MaskProviders of Masks in the namespace that
+/// don't specify `spec.providers` themselves.
+pub(crate) const DEFAULT_PROVIDERS_ANNOTATION: &str = "vpn.beebs.dev/default-providers";
+
+/// An annotation on a MaskProvider or Mask that renders what the operator
+/// would create for it into the `<name>-explain` ConfigMap, without creating
+/// it. `"verify-pod"` previews a MaskProvider's next verification Pod and
+/// `"consumer-secret"` a Mask's credentials Secret, without its values.
+pub(crate) const EXPLAIN_ANNOTATION: &str = "vpn.beebs.dev/explain";
+
+/// Name of the label on a canary Mask, and the MaskConsumer and
+/// MaskReservation made for it, holding the UID of the MaskProvider
+/// it tests. Dashboards can use it to filter out canaries.
+pub(crate) const CANARY_LABEL: &str = "vpn.beebs.dev/canary";
+
+/// Name of the label on the MaskProviders and credentials Secrets created
+/// for a ClusterMaskProvider, holding the ClusterMaskProvider's name.
+pub(crate) const CLUSTER_PROVIDER_LABEL: &str = "vpn.beebs.dev/cluster-provider";
+
+/// An annotation on a Job with a comma-separated list of MaskProvider
+/// tags, which has the Job controller create a Mask for the Job. An
+/// empty value accepts any MaskProvider.
+pub(crate) const AUTO_MASK_ANNOTATION: &str = "vpn.beebs.dev/auto-mask";
+
+/// An annotation on the Pod template of a Job with an automatic Mask,
+/// holding the name of the Mask's credentials Secret. It is set just
+/// before the Job is resumed, so every Pod of the Job has it.
+pub(crate) const MASK_SECRET_ANNOTATION: &str = "vpn.beebs.dev/mask-secret";
+
+/// An annotation on a Pod naming the Mask in its namespace whose
+/// credentials the sidecar injection webhook wires a gluetun container to.
+pub(crate) const INJECT_MASK_ANNOTATION: &str = "vpn.beebs.dev/mask";
+
+/// An annotation on a Pod with the sidecar injection's behavior while
+/// the Mask isn't Active: `reject` (the default) or `wait`.
+pub(crate) const INJECT_NOT_READY_ANNOTATION: &str = "vpn.beebs.dev/mask-not-ready";
+
+/// An annotation on a Pod with overrides for the injected gluetun
+/// container, merged like the verification Pod's container overrides.
+pub(crate) const INJECT_OVERRIDES_ANNOTATION: &str = "vpn.beebs.dev/sidecar-overrides";
+
+/// An annotation the sidecar injection webhook sets on the Pods it
+/// injected the gluetun container into, holding the name of the Mask.
+pub(crate) const INJECTED_ANNOTATION: &str = "vpn.beebs.dev/sidecar-injected";
+
+/// Name of the annotation an external MaskConsumer's client renews with
+/// the current time to keep its slot.
+pub(crate) const HEARTBEAT_ANNOTATION: &str = "vpn.beebs.dev/heartbeat";
+
+/// Name of the label on an automatic Mask holding the UID of its Job.
+pub(crate) const AUTO_MASK_JOB_LABEL: &str = "vpn.beebs.dev/job";
+
+/// Settings from the command line that every controller is started
+/// with, kept in its context rather than in process-wide state.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ControllerOptions {
+    /// Whether the most recent action taken on a resource is recorded in
+    /// its annotations, with `--explain-annotations`.
+    pub explain: bool,
+
+    /// Whether information about each resource is exported as a metric,
+    /// with `--info-metrics`. Always false without the `metrics` feature.
+    pub info_metrics: bool,
+}
+
+/// Returns how long ago a status object was last updated, given its
+/// `lastUpdated` field. A missing or malformed timestamp is treated as
+/// infinitely stale, so the controller refreshes the status rather than
+/// failing the reconciliation over bookkeeping. A timestamp slightly in
+/// the future, written by a replica whose clock runs ahead, is treated
+/// as brand new, so the skew doesn't make every reconciliation rewrite it.
+pub fn status_age(last_updated: Option<&str>) -> Duration {
+    last_updated
+        .and_then(|t| clock::parse_timestamp(t).ok())
+        .map_or(Duration::MAX, |t| {
+            (chrono::Utc::now() - t).to_std().unwrap_or_default()
+        })
+}
+
+/// Returns how long ago a status object in its current phase was last
+/// updated, which is the [`status_age`] of its `lastUpdated` field.
+pub fn phase_age(last_updated: Option<&str>) -> Duration {
+    status_age(last_updated)
+}
+
+/// Returns true if a status object that already shows the desired phase
+/// and message should be rewritten anyway: it was last updated longer than
+/// `freshness` ago, or it still records a failed action that has since
+/// been resolved. Otherwise rewriting it would only cost an etcd write.
+pub fn needs_refresh<S: patch::Status>(status: &S, freshness: Duration) -> bool {
+    status.last_error().is_some() || status_age(status.last_updated()) > freshness
+}
+
+/// Returns true if the MaskReservation reserves a slot for verifying
+/// the MaskProvider rather than for a real MaskConsumer.
+pub fn is_verification_reservation(reservation: &MaskReservation) -> bool {
+    reservation
+        .metadata
+        .labels
+        .as_ref()
+        .is_some_and(|l| l.contains_key(VERIFICATION_LABEL))
+}
+
+/// Returns why the MaskConsumer was created. Objects created before
+/// [`MaskConsumerSpec::purpose`] existed fall back to the verification
+/// label, and are otherwise considered to serve a workload.
+pub fn consumer_purpose(consumer: &MaskConsumer) -> MaskConsumerPurpose {
+    consumer.spec.purpose.unwrap_or_else(|| {
+        match consumer
+            .metadata
+            .labels
+            .as_ref()
+            .is_some_and(|l| l.contains_key(VERIFICATION_LABEL))
+        {
+            true => MaskConsumerPurpose::Verification,
+            false => MaskConsumerPurpose::Workload,
+        }
+    })
+}
+
+/// Returns the uid of the MaskProvider a verification MaskConsumer is
+/// meant for, or None if the MaskConsumer serves a workload.
+pub fn verified_provider_uid(consumer: &MaskConsumer) -> Option<&str> {
+    if consumer_purpose(consumer) != MaskConsumerPurpose::Verification {
+        return None;
+    }
+    consumer
+        .metadata
+        .labels
+        .as_ref()?
+        .get(VERIFICATION_LABEL)
+        .map(|v| v.as_str())
+}
 
-/// A label that a Mask/MaskConsumer must have in order to force
-/// assignment to a MaskProvider with a specific uid, even if the
-/// MaskProvider has no open slots.
-pub(crate) const VERIFICATION_LABEL: &str = "vpn.beebs.dev/verify";
+/// Returns true if the MaskReservation reserves a slot for a canary
+/// Mask rather than for a real MaskConsumer.
+pub fn is_canary_reservation(reservation: &MaskReservation) -> bool {
+    reservation
+        .metadata
+        .labels
+        .as_ref()
+        .is_some_and(|l| l.contains_key(CANARY_LABEL))
+}
@@ -1,91 +1,360 @@
-use super::MANAGER_NAME;
+use super::{clock, events, explain, MANAGER_NAME};
+use json_patch::{PatchOperation, TestOperation};
 use kube::{
-    api::{Patch, PatchParams, Resource},
-    core::NamespaceResourceScope,
+    api::{ObjectMeta, Patch, PatchParams, Resource},
     Api, Client, Error,
 };
 use serde::{de::DeserializeOwned, Serialize};
-use std::{clone::Clone, fmt::Debug};
+use serde_json::Value;
+use std::{
+    clone::Clone,
+    fmt::{Debug, Display},
+    sync::Arc,
+    time::Duration,
+};
 use vpn_types::*;
 
+#[cfg(feature = "metrics")]
+use super::metrics::{ACTION_ERROR_COUNTER, STATUS_PATCH_COUNTER};
+
 pub trait Object<S: Status> {
     /// Returns a mutable reference to the status object, initializing
     /// it with the default value if it does not exist.
     fn mut_status(&mut self) -> &mut S;
+
+    /// Returns a reference to the status object, if it exists.
+    fn status(&self) -> Option<&S>;
+
+    /// Returns the API for the resource's scope, which is its
+    /// namespace unless the resource is cluster-scoped.
+    fn api(&self, client: Client) -> Api<Self>
+    where
+        Self: Resource + Sized;
 }
 
 pub trait Status {
+    /// Short description of the resource's current state.
+    type Phase: Copy + PartialEq + Display;
+
+    /// Returns the phase, if any.
+    fn phase(&self) -> Option<Self::Phase>;
+
     /// Sets the last updated timestamp to the given value.
     fn set_last_updated(&mut self, last_updated: String);
+
+    /// Returns the last updated timestamp, if any.
+    fn last_updated(&self) -> Option<&str>;
+
+    /// Sets or clears the most recent failed action.
+    fn set_last_error(&mut self, last_error: Option<LastError>);
+
+    /// Returns the most recent failed action, if any.
+    fn last_error(&self) -> Option<&LastError>;
+
+    /// Returns the human-readable status message, if any.
+    fn message(&self) -> Option<&str>;
+
+    /// Returns the revision of the status object, if it has one.
+    fn status_revision(&self) -> Option<u64>;
+
+    /// Sets the revision of the status object.
+    fn set_status_revision(&mut self, status_revision: u64);
 }
 
 impl Object<MaskStatus> for Mask {
     fn mut_status(&mut self) -> &mut MaskStatus {
-        if self.status.is_some() {
-            return self.status.as_mut().unwrap();
-        }
-        self.status = Some(Default::default());
-        self.status.as_mut().unwrap()
+        self.status.get_or_insert_with(Default::default)
+    }
+
+    fn status(&self) -> Option<&MaskStatus> {
+        self.status.as_ref()
+    }
+
+    fn api(&self, client: Client) -> Api<Self> {
+        Api::namespaced(client, self.metadata.namespace.as_deref().unwrap())
     }
 }
 
 impl Status for MaskStatus {
+    type Phase = MaskPhase;
+
+    fn phase(&self) -> Option<MaskPhase> {
+        self.phase
+    }
+
     fn set_last_updated(&mut self, last_updated: String) {
         self.last_updated = Some(last_updated);
     }
+
+    fn last_updated(&self) -> Option<&str> {
+        self.last_updated.as_deref()
+    }
+
+    fn set_last_error(&mut self, last_error: Option<LastError>) {
+        self.last_error = last_error;
+    }
+
+    fn last_error(&self) -> Option<&LastError> {
+        self.last_error.as_ref()
+    }
+
+    fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    fn status_revision(&self) -> Option<u64> {
+        self.status_revision
+    }
+
+    fn set_status_revision(&mut self, status_revision: u64) {
+        self.status_revision = Some(status_revision);
+    }
 }
 
 impl Object<MaskProviderStatus> for MaskProvider {
     fn mut_status(&mut self) -> &mut MaskProviderStatus {
-        if self.status.is_some() {
-            return self.status.as_mut().unwrap();
-        }
-        self.status = Some(Default::default());
-        self.status.as_mut().unwrap()
+        self.status.get_or_insert_with(Default::default)
+    }
+
+    fn status(&self) -> Option<&MaskProviderStatus> {
+        self.status.as_ref()
+    }
+
+    fn api(&self, client: Client) -> Api<Self> {
+        Api::namespaced(client, self.metadata.namespace.as_deref().unwrap())
     }
 }
 
 impl Status for MaskProviderStatus {
+    type Phase = MaskProviderPhase;
+
+    fn phase(&self) -> Option<MaskProviderPhase> {
+        self.phase
+    }
+
     fn set_last_updated(&mut self, last_updated: String) {
         self.last_updated = Some(last_updated);
     }
+
+    fn last_updated(&self) -> Option<&str> {
+        self.last_updated.as_deref()
+    }
+
+    fn set_last_error(&mut self, last_error: Option<LastError>) {
+        self.last_error = last_error;
+    }
+
+    fn last_error(&self) -> Option<&LastError> {
+        self.last_error.as_ref()
+    }
+
+    fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    fn status_revision(&self) -> Option<u64> {
+        self.status_revision
+    }
+
+    fn set_status_revision(&mut self, status_revision: u64) {
+        self.status_revision = Some(status_revision);
+    }
 }
 
 impl Object<MaskReservationStatus> for MaskReservation {
     fn mut_status(&mut self) -> &mut MaskReservationStatus {
-        if self.status.is_some() {
-            return self.status.as_mut().unwrap();
-        }
-        self.status = Some(Default::default());
-        self.status.as_mut().unwrap()
+        self.status.get_or_insert_with(Default::default)
+    }
+
+    fn status(&self) -> Option<&MaskReservationStatus> {
+        self.status.as_ref()
+    }
+
+    fn api(&self, client: Client) -> Api<Self> {
+        Api::namespaced(client, self.metadata.namespace.as_deref().unwrap())
     }
 }
 
 impl Status for MaskReservationStatus {
+    type Phase = MaskReservationPhase;
+
+    fn phase(&self) -> Option<MaskReservationPhase> {
+        self.phase
+    }
+
     fn set_last_updated(&mut self, last_updated: String) {
         self.last_updated = Some(last_updated);
     }
+
+    fn last_updated(&self) -> Option<&str> {
+        self.last_updated.as_deref()
+    }
+
+    fn set_last_error(&mut self, last_error: Option<LastError>) {
+        self.last_error = last_error;
+    }
+
+    fn last_error(&self) -> Option<&LastError> {
+        self.last_error.as_ref()
+    }
+
+    fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    fn status_revision(&self) -> Option<u64> {
+        self.status_revision
+    }
+
+    fn set_status_revision(&mut self, status_revision: u64) {
+        self.status_revision = Some(status_revision);
+    }
 }
 
 impl Object<MaskConsumerStatus> for MaskConsumer {
     fn mut_status(&mut self) -> &mut MaskConsumerStatus {
-        if self.status.is_some() {
-            return self.status.as_mut().unwrap();
-        }
-        self.status = Some(Default::default());
-        self.status.as_mut().unwrap()
+        self.status.get_or_insert_with(Default::default)
+    }
+
+    fn status(&self) -> Option<&MaskConsumerStatus> {
+        self.status.as_ref()
+    }
+
+    fn api(&self, client: Client) -> Api<Self> {
+        Api::namespaced(client, self.metadata.namespace.as_deref().unwrap())
     }
 }
 
 impl Status for MaskConsumerStatus {
+    type Phase = MaskConsumerPhase;
+
+    fn phase(&self) -> Option<MaskConsumerPhase> {
+        self.phase
+    }
+
+    fn set_last_updated(&mut self, last_updated: String) {
+        self.last_updated = Some(last_updated);
+    }
+
+    fn last_updated(&self) -> Option<&str> {
+        self.last_updated.as_deref()
+    }
+
+    fn set_last_error(&mut self, last_error: Option<LastError>) {
+        self.last_error = last_error;
+    }
+
+    fn last_error(&self) -> Option<&LastError> {
+        self.last_error.as_ref()
+    }
+
+    fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    fn status_revision(&self) -> Option<u64> {
+        self.status_revision
+    }
+
+    fn set_status_revision(&mut self, status_revision: u64) {
+        self.status_revision = Some(status_revision);
+    }
+}
+
+impl Object<ClusterMaskProviderStatus> for ClusterMaskProvider {
+    fn mut_status(&mut self) -> &mut ClusterMaskProviderStatus {
+        self.status.get_or_insert_with(Default::default)
+    }
+
+    fn status(&self) -> Option<&ClusterMaskProviderStatus> {
+        self.status.as_ref()
+    }
+
+    fn api(&self, client: Client) -> Api<Self> {
+        Api::all(client)
+    }
+}
+
+impl Status for ClusterMaskProviderStatus {
+    type Phase = ClusterMaskProviderPhase;
+
+    fn phase(&self) -> Option<ClusterMaskProviderPhase> {
+        self.phase
+    }
+
     fn set_last_updated(&mut self, last_updated: String) {
         self.last_updated = Some(last_updated);
     }
+
+    fn last_updated(&self) -> Option<&str> {
+        self.last_updated.as_deref()
+    }
+
+    fn set_last_error(&mut self, last_error: Option<LastError>) {
+        self.last_error = last_error;
+    }
+
+    fn last_error(&self) -> Option<&LastError> {
+        self.last_error.as_ref()
+    }
+
+    fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    fn status_revision(&self) -> Option<u64> {
+        self.status_revision
+    }
+
+    fn set_status_revision(&mut self, status_revision: u64) {
+        self.status_revision = Some(status_revision);
+    }
+}
+
+/// Fields of the status object that [`ensure_status_initialized`] requires.
+const BOOKKEEPING_FIELDS: &str = "its phase, message or lastUpdated";
+
+/// Returns the error for the resource's status missing `field`.
+pub fn incomplete_status<T: Resource>(instance: &T, field: &'static str) -> super::Error {
+    super::Error::IncompleteStatus(qualified_name(instance.meta()), field)
+}
+
+/// Returns the resource's status object if every field the controllers
+/// rely on is set: the phase, the message explaining it and when it was
+/// last updated. A status missing any of them, e.g. one partially written
+/// by an older version of the operator, is refused with
+/// [`IncompleteStatus`](super::Error::IncompleteStatus). The controllers check this
+/// before anything else and initialize such a status again with their
+/// Pending action, so it heals instead of failing every reconcile.
+pub fn ensure_status_initialized<S: Status, T: Resource + Object<S>>(
+    instance: &T,
+) -> Result<&S, super::Error> {
+    instance
+        .status()
+        .filter(|s| s.phase().is_some() && s.message().is_some() && s.last_updated().is_some())
+        .ok_or_else(|| incomplete_status(instance, BOOKKEEPING_FIELDS))
+}
+
+/// Returns the phase of the resource and how long ago its status was
+/// last updated. Fails like [`ensure_status_initialized`] if the status
+/// isn't fully initialized. See [`phase_age`] for how the age is determined.
+pub fn status_phase<S: Status, T: Resource + Object<S>>(
+    instance: &T,
+) -> Result<(S::Phase, Duration), super::Error> {
+    let status = ensure_status_initialized(instance)?;
+    let phase = status
+        .phase()
+        .ok_or_else(|| incomplete_status(instance, BOOKKEEPING_FIELDS))?;
+    Ok((phase, super::phase_age(status.last_updated())))
 }
 
 /// Patch the resource's status object with the provided function.
 /// The function is passed a mutable reference to the status object,
 /// which is to be mutated in-place. Move closures are supported.
+/// If explain annotations are enabled, the resource is also annotated
+/// with the action being applied. The patch is refused with
+/// [`StaleStatus`](super::Error::StaleStatus) if the status was
+/// updated since `instance` was read.
 pub async fn patch_status<
     S: Status,
     T: Clone + Resource + Object<S> + Serialize + DeserializeOwned + Debug,
@@ -93,25 +362,225 @@ pub async fn patch_status<
     client: Client,
     instance: &T,
     f: impl FnOnce(&mut S),
+) -> Result<T, super::Error>
+where
+    <T as Resource>::DynamicType: Default,
+{
+    patch_status_with_metadata(client, instance, f, |meta, status| {
+        if let Some(last_action) = explain::last_action(status.message()) {
+            explain::annotate(meta, &last_action);
+        }
+    })
+    .await
+}
+
+/// Same as [`patch_status`], but also mutates the resource's metadata
+/// with `m`, which is passed the updated status object. The status
+/// subresource ignores changes to metadata, so metadata changes are
+/// sent as a separate patch to the main resource before the status
+/// is patched. No extra request is made if the metadata is unchanged.
+pub async fn patch_status_with_metadata<
+    S: Status,
+    T: Clone + Resource + Object<S> + Serialize + DeserializeOwned + Debug,
+>(
+    client: Client,
+    instance: &T,
+    f: impl FnOnce(&mut S),
+    m: impl FnOnce(&mut ObjectMeta, &S),
+) -> Result<T, super::Error>
+where
+    <T as Resource>::DynamicType: Default,
+{
+    let revision = instance.status().and_then(|s| s.status_revision());
+    let mut modified = instance.clone();
+    let status = modified.mut_status();
+    // A successful write means the resource is no longer failing.
+    status.set_last_error(None);
+    f(status);
+    status.set_last_updated(clock::now_k8s());
+    status.set_status_revision(revision.map_or(1, |r| r + 1));
+    let name = instance.meta().name.as_deref().unwrap();
+    let api = instance.api(client.clone());
+    let mut annotated = instance.clone();
+    m(annotated.meta_mut(), modified.mut_status());
+    let stale = |e| match e {
+        Error::Api(e) if is_revision_test_failure(&e) => {
+            super::Error::StaleStatus(qualified_name(instance.meta()))
+        }
+        e => e.into(),
+    };
+    if annotated.meta() != instance.meta() {
+        let patch = Patch::Json::<T>(conditional_diff(instance, &annotated));
+        api.patch(name, &PatchParams::apply(MANAGER_NAME), &patch)
+            .await
+            .map_err(stale)?;
+    }
+    let patch = Patch::Json::<T>(conditional_diff(instance, &modified));
+    let patched = send_status_patch(&api, name, &patch).await.map_err(stale)?;
+    #[cfg(feature = "metrics")]
+    count_status_patch::<T>();
+    if let Some(phase) = transition(instance.status(), modified.status()) {
+        let note = modified.status().and_then(|s| s.message());
+        events::transition(client, &patched, &phase, note).await;
+    }
+    Ok(patched)
+}
+
+/// Returns the phase the status moved to if the patch is a transition
+/// worth an event: the phase changed, or the resource is still in an
+/// error phase with another message, i.e. it failed for another reason.
+fn transition<S: Status>(from: Option<&S>, to: Option<&S>) -> Option<String> {
+    let phase = to?.phase()?;
+    let previous = from.and_then(|s| s.phase());
+    let changed = previous != Some(phase);
+    let phase = phase.to_string();
+    let refailed = phase.starts_with("Err") && from.and_then(|s| s.message()) != to?.message();
+    (changed || refailed).then_some(phase)
+}
+
+/// Sends a patch of the resource's status through the status
+/// subresource. If the kind's CRD lacks the subresource, the API server
+/// answers 404 and the status is a regular field, so it's patched on the
+/// resource itself. Each kind falls back on its own, so one outdated CRD
+/// doesn't affect the others.
+async fn send_status_patch<T: Clone + Resource + Serialize + DeserializeOwned + Debug>(
+    api: &Api<T>,
+    name: &str,
+    patch: &Patch<T>,
+) -> Result<T, Error> {
+    let params = PatchParams::apply(MANAGER_NAME);
+    match api.patch_status(name, &params, patch).await {
+        Err(Error::Api(e)) if e.code == 404 => api.patch(name, &params, patch).await,
+        result => result,
+    }
+}
+
+/// Returns the resource's `namespace/name`, or only its
+/// name if the resource is cluster-scoped.
+fn qualified_name(meta: &ObjectMeta) -> String {
+    let name = meta.name.as_deref().unwrap_or_default();
+    match meta.namespace.as_deref() {
+        Some(namespace) => format!("{}/{}", namespace, name),
+        None => name.to_owned(),
+    }
+}
+
+/// Counts a status patch of a `T` resource. Every status patch is
+/// a write to etcd, so this measures the write load of the operator.
+#[cfg(feature = "metrics")]
+fn count_status_patch<T: Resource>()
+where
+    <T as Resource>::DynamicType: Default,
+{
+    STATUS_PATCH_COUNTER
+        .with_label_values(&[&T::kind(&Default::default())])
+        .inc();
+}
+
+/// Returns a JSON patch from `instance` to `modified` that only applies
+/// if the status revision is unchanged since `instance` was read. The
+/// operations of a JSON patch are applied atomically, so leading with a
+/// test of the revision makes the whole patch conditional on it. Testing
+/// for null also passes if the status has no revision yet.
+fn conditional_diff<S: Status, T: Object<S> + Serialize>(
+    instance: &T,
+    modified: &T,
+) -> json_patch::Patch {
+    let (path, value) = match instance.status() {
+        Some(status) => (
+            "/status/statusRevision",
+            status.status_revision().map_or(Value::Null, Value::from),
+        ),
+        None => ("/status", Value::Null),
+    };
+    let mut patch = json_patch::diff(
+        &serde_json::to_value(instance).unwrap(),
+        &serde_json::to_value(modified).unwrap(),
+    );
+    patch.0.insert(
+        0,
+        PatchOperation::Test(TestOperation {
+            path: path.to_owned(),
+            value,
+        }),
+    );
+    patch
+}
+
+/// Returns true if the apiserver refused a JSON patch because one of its
+/// test operations failed, which is reported as an invalid patch.
+fn is_revision_test_failure(e: &kube::error::ErrorResponse) -> bool {
+    e.code == 422 && e.message.contains("testing value")
+}
+
+/// Records the failed action in the resource's status object. Unlike
+/// [`patch_status`], this does not touch the last updated timestamp,
+/// as the status itself was not brought up to date.
+pub async fn patch_last_error<
+    S: Status,
+    T: Clone + Resource + Object<S> + Serialize + DeserializeOwned + Debug,
+>(
+    client: Client,
+    instance: &T,
+    last_error: LastError,
 ) -> Result<T, Error>
 where
     <T as Resource>::DynamicType: Default,
-    T: Resource<Scope = NamespaceResourceScope>,
 {
     let patch = Patch::Json::<T>({
         let mut modified = instance.clone();
-        let status = modified.mut_status();
-        f(status);
-        status.set_last_updated(chrono::Utc::now().to_rfc3339());
+        modified.mut_status().set_last_error(Some(last_error));
         json_patch::diff(
             &serde_json::to_value(instance).unwrap(),
             &serde_json::to_value(&modified).unwrap(),
         )
     });
     let name = instance.meta().name.as_deref().unwrap();
-    let namespace = instance.meta().namespace.as_deref().unwrap();
-    let api: Api<T> = Api::namespaced(client, namespace);
-    Ok(api
-        .patch_status(name, &PatchParams::apply(MANAGER_NAME), &patch)
-        .await?)
+    let api = instance.api(client);
+    let patched = send_status_patch(&api, name, &patch).await?;
+    #[cfg(feature = "metrics")]
+    count_status_patch::<T>();
+    Ok(patched)
+}
+
+/// Reports a failed reconciliation. If the error was caused by an action,
+/// the action error metric is incremented and the error is recorded in the
+/// resource's status object, unless the action's status update was only
+/// refused as stale. The status update is best-effort and happens
+/// in the background, as error handlers can't be async.
+pub fn record_action_error<
+    S: Status,
+    T: Clone + Resource + Object<S> + Serialize + DeserializeOwned + Debug + Send + Sync + 'static,
+>(
+    client: Client,
+    instance: Arc<T>,
+    kind: &'static str,
+    error: &super::Error,
+) where
+    <T as Resource>::DynamicType: Default,
+{
+    let (action, source) = match error {
+        // A stale status means a newer update won, not that the action failed.
+        super::Error::ActionError { source, .. }
+            if matches!(source.root(), super::Error::StaleStatus(_)) =>
+        {
+            return
+        }
+        super::Error::ActionError { action, source } => (action, source),
+        _ => return,
+    };
+    #[cfg(feature = "metrics")]
+    ACTION_ERROR_COUNTER
+        .with_label_values(&[kind, action.as_str(), &source.code_label()])
+        .inc();
+    let last_error = LastError {
+        action: action.clone(),
+        message: source.to_string(),
+        at: clock::now_k8s(),
+    };
+    tokio::spawn(async move {
+        if let Err(e) = patch_last_error(client, instance.as_ref(), last_error).await {
+            eprintln!("Failed to record {} error in status: {}", kind, e);
+        }
+    });
 }
@@ -0,0 +1,162 @@
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use kube::{
+    api::{ObjectMeta, PostParams},
+    Api, Client, Resource, ResourceExt,
+};
+use serde::Serialize;
+use std::{collections::BTreeMap, time::Duration};
+
+use super::{Error, ErrorContext, EXPLAIN_ANNOTATION};
+use crate::rbac::Rule;
+
+/// Reading and writing the previews, which is only granted in the
+/// namespaces they're enabled in.
+pub const RBAC: &[Rule] = &[Rule::new("", &["configmaps"], &["create", "get", "update"])];
+
+/// Value of the explain annotation on a MaskProvider that previews the
+/// Pod its next verification would create.
+pub const VERIFY_POD: &str = "verify-pod";
+
+/// Value of the explain annotation on a Mask that previews the
+/// credentials Secret copied for it, without any of its values.
+pub const CONSUMER_SECRET: &str = "consumer-secret";
+
+/// How long until a preview that wasn't allowed to be written is retried.
+pub const FORBIDDEN_RETRY: Duration = Duration::from_secs(300);
+
+/// Key of the preview ConfigMap holding the error that kept the
+/// resource from being rendered.
+pub const ERROR_KEY: &str = "error";
+
+/// Returns the preview requested with the explain annotation, if it's
+/// one of `supported`. Other values are ignored.
+pub fn requested<'a>(meta: &'a ObjectMeta, supported: &[&str]) -> Option<&'a str> {
+    meta.annotations
+        .as_ref()
+        .and_then(|a| a.get(EXPLAIN_ANNOTATION))
+        .map(String::as_str)
+        .filter(|value| supported.contains(value))
+}
+
+/// Returns the name of the ConfigMap holding the resource's preview.
+pub fn config_map_name(name: &str) -> String {
+    format!("{}-explain", name)
+}
+
+/// The parts of a Secret that are safe to show in a preview: its
+/// metadata and the names of its keys, but never their values. The
+/// annotations are left out as well, as they hold digests of the values.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SecretPreview {
+    pub metadata: ObjectMeta,
+
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub type_: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub immutable: Option<bool>,
+
+    pub keys: Vec<String>,
+}
+
+impl From<Secret> for SecretPreview {
+    fn from(secret: Secret) -> Self {
+        SecretPreview {
+            metadata: ObjectMeta {
+                annotations: None,
+                ..secret.metadata
+            },
+            type_: secret.type_,
+            immutable: secret.immutable,
+            keys: secret.data.into_iter().flatten().map(|(k, _)| k).collect(),
+        }
+    }
+}
+
+/// Returns the ConfigMap holding the preview of `target` for the
+/// resource, rendered as YAML under `<target>.yaml`. If rendering
+/// failed, the error is shown under [`ERROR_KEY`] instead. The
+/// ConfigMap is owned by the resource so it's deleted along with it.
+pub fn preview_config_map<K, T>(instance: &K, target: &str, rendered: Result<T, Error>) -> ConfigMap
+where
+    K: Resource<DynamicType = ()>,
+    T: Serialize,
+{
+    let (key, value) = match rendered.and_then(|r| Ok(serde_yaml::to_string(&r)?)) {
+        Ok(yaml) => (format!("{}.yaml", target), yaml),
+        Err(e) => (ERROR_KEY.to_owned(), e.to_string()),
+    };
+    ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(config_map_name(&instance.name_any())),
+            namespace: instance.namespace(),
+            owner_references: Some(vec![instance.controller_owner_ref(&()).unwrap()]),
+            ..Default::default()
+        },
+        data: Some(BTreeMap::from([(key, value)])),
+        ..Default::default()
+    }
+}
+
+/// Returns the preview ConfigMap if it has to be written because it
+/// doesn't exist yet or shows an outdated preview. A ConfigMap with
+/// the name that doesn't belong to the resource is never overwritten.
+pub async fn needs_write<K>(
+    client: Client,
+    instance: &K,
+    desired: ConfigMap,
+) -> Result<Option<ConfigMap>, Error>
+where
+    K: Resource<DynamicType = ()>,
+{
+    let name = desired.name_any();
+    let api: Api<ConfigMap> = Api::namespaced(client, &instance.namespace().unwrap());
+    let existing = match api.get(&name).await {
+        Ok(existing) => existing,
+        Err(kube::Error::Api(ae)) if ae.code == 404 => return Ok(Some(desired)),
+        // Previews aren't enabled in the namespace, which writing
+        // the preview reports.
+        Err(kube::Error::Api(ae)) if ae.code == 403 => return Ok(Some(desired)),
+        Err(e) => return Err(e).context_kind_name("ConfigMap", &name),
+    };
+    let owned = existing
+        .owner_references()
+        .iter()
+        .any(|o| Some(&o.uid) == instance.meta().uid.as_ref());
+    if !owned || existing.data == desired.data {
+        return Ok(None);
+    }
+    Ok(Some(desired))
+}
+
+/// Creates the preview ConfigMap, or overwrites the previous preview.
+/// Previews are only allowed in the namespaces they're enabled in, so
+/// being forbidden to write one is logged rather than failing. Returns
+/// false if the preview wasn't written because of that.
+pub async fn write(client: Client, mut config_map: ConfigMap) -> Result<bool, Error> {
+    let name = config_map.name_any();
+    let namespace = config_map.namespace().unwrap();
+    let api: Api<ConfigMap> = Api::namespaced(client, &namespace);
+    let written = match api.get(&name).await {
+        Ok(existing) => {
+            config_map.metadata.resource_version = existing.metadata.resource_version;
+            api.replace(&name, &PostParams::default(), &config_map)
+                .await
+        }
+        Err(kube::Error::Api(ae)) if ae.code == 404 => {
+            api.create(&PostParams::default(), &config_map).await
+        }
+        Err(e) => Err(e),
+    };
+    match written {
+        Ok(_) => Ok(true),
+        Err(kube::Error::Api(ae)) if ae.code == 403 => {
+            eprintln!(
+                "Previews aren't enabled in namespace {}, not writing ConfigMap {}.",
+                namespace, name
+            );
+            Ok(false)
+        }
+        Err(e) => Err(e).context_kind_name("ConfigMap", &name),
+    }
+}
@@ -0,0 +1,257 @@
+use chrono::{
+    DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Timelike, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use vpn_types::*;
+
+use super::Error;
+
+/// Number of minutes in a day.
+const DAY_MINUTES: u32 = 24 * 60;
+
+/// Abbreviated day names in the order of [`Weekday::num_days_from_monday`].
+const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// A weekly schedule of windows in a time zone, such as a
+/// [`MaskProvider`]'s availability.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+    windows: Vec<Window>,
+    timezone: Tz,
+}
+
+/// A time range on certain days of the week. If the range ends before it
+/// starts, it runs past midnight into the day after each of its days.
+#[derive(Clone, Debug, PartialEq)]
+struct Window {
+    /// Whether the window starts on each day, indexed from Monday.
+    days: [bool; 7],
+
+    /// Start of the window in minutes since midnight.
+    start: u32,
+
+    /// End of the window in minutes since midnight, up to [`DAY_MINUTES`].
+    end: u32,
+}
+
+impl Window {
+    /// Returns true if the minute of the day on the given weekday is
+    /// within the window.
+    fn contains(&self, weekday: Weekday, minute: u32) -> bool {
+        let today = self.days[weekday.num_days_from_monday() as usize];
+        if self.start < self.end {
+            return today && self.start <= minute && minute < self.end;
+        }
+        // The window runs past midnight, so it's either in the part that
+        // started today or the part that started yesterday.
+        let yesterday = self.days[weekday.pred().num_days_from_monday() as usize];
+        (today && minute >= self.start) || (yesterday && minute < self.end)
+    }
+}
+
+impl Schedule {
+    /// Parses the schedule in the IANA time zone. An empty time zone
+    /// means UTC.
+    pub fn parse(schedule: &str, timezone: &str) -> Result<Self, Error> {
+        let timezone = match timezone.trim() {
+            "" => Tz::UTC,
+            tz => tz
+                .parse()
+                .map_err(|_| invalid(format!("unknown time zone '{}'", tz)))?,
+        };
+        let windows = schedule
+            .split(';')
+            .map(str::trim)
+            .filter(|w| !w.is_empty())
+            .map(parse_window)
+            .collect::<Result<Vec<_>, _>>()?;
+        if windows.is_empty() {
+            return Err(invalid("schedule has no windows".to_owned()));
+        }
+        Ok(Schedule { windows, timezone })
+    }
+
+    /// Returns true if the time is within any of the schedule's windows.
+    pub fn contains(&self, at: DateTime<Utc>) -> bool {
+        let local = at.with_timezone(&self.timezone);
+        let minute = local.hour() * 60 + local.minute();
+        self.windows
+            .iter()
+            .any(|w| w.contains(local.weekday(), minute))
+    }
+
+    /// Returns the first time after `at` when the schedule switches between
+    /// being in and out of a window, or None if it never does.
+    pub fn next_change(&self, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let inside = self.contains(at);
+        let today = at.with_timezone(&self.timezone).date_naive();
+        // Every change happens at the start or end of a window, and the
+        // schedule repeats weekly, so a week and a day of boundaries is
+        // enough to find the next one.
+        let mut boundaries: Vec<DateTime<Utc>> = (-1..=8)
+            .filter_map(|offset| today.checked_add_signed(Duration::days(offset)))
+            .flat_map(|date| {
+                self.windows
+                    .iter()
+                    .flat_map(move |w| [(date, w.start), (date, w.end)])
+            })
+            .filter_map(|(date, minute)| self.to_utc(date, minute))
+            .filter(|&t| t > at)
+            .collect();
+        boundaries.sort();
+        boundaries.into_iter().find(|&t| self.contains(t) != inside)
+    }
+
+    /// Returns the UTC time of the minute of the local day, resolving
+    /// times skipped by daylight saving time to the instant after.
+    fn to_utc(&self, date: NaiveDate, minute: u32) -> Option<DateTime<Utc>> {
+        let (date, minute) = if minute >= DAY_MINUTES {
+            (date.succ_opt()?, minute - DAY_MINUTES)
+        } else {
+            (date, minute)
+        };
+        let time = NaiveTime::from_hms_opt(minute / 60, minute % 60, 0)?;
+        let local = date.and_time(time);
+        (0..=2)
+            .find_map(|hours| {
+                self.timezone
+                    .from_local_datetime(&(local + Duration::hours(hours)))
+                    .earliest()
+            })
+            .map(|t| t.with_timezone(&Utc))
+    }
+
+    /// Returns the time zone the schedule is in.
+    pub fn timezone(&self) -> Tz {
+        self.timezone
+    }
+}
+
+/// Availability of a [`MaskProvider`] at a point in time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Availability {
+    /// True if no new slots should be reserved with the [`MaskProvider`].
+    pub out_of_hours: bool,
+
+    /// Human-readable description for the [`MaskProvider`]'s status.
+    pub message: String,
+}
+
+/// Returns the [`MaskProvider`]'s availability at `now`, or None if it
+/// doesn't have [`MaskProviderSpec::availability`] and is always available.
+/// An invalid schedule makes the [`MaskProvider`] unavailable, so a typo
+/// can't result in usage that the schedule was meant to prevent.
+pub fn availability(provider: &MaskProvider, now: DateTime<Utc>) -> Option<Availability> {
+    let spec = provider.spec.availability.as_ref()?;
+    let schedule = match Schedule::parse(&spec.schedule, &spec.timezone) {
+        Ok(schedule) => schedule,
+        Err(e) => {
+            return Some(Availability {
+                out_of_hours: true,
+                message: e.to_string(),
+            })
+        }
+    };
+    let out_of_hours = !schedule.contains(now);
+    let state = if out_of_hours { "Outside of" } else { "Within" };
+    let mut message = format!(
+        "{} availability '{}' ({}).",
+        state,
+        spec.schedule,
+        schedule.timezone()
+    );
+    if let Some(next) = schedule.next_change(now) {
+        let change = if out_of_hours { "Opens" } else { "Closes" };
+        message = format!(
+            "{} {} at {}.",
+            message,
+            change,
+            next.with_timezone(&schedule.timezone()).to_rfc3339()
+        );
+    }
+    Some(Availability {
+        out_of_hours,
+        message,
+    })
+}
+
+/// Returns true if new slots can be reserved with the [`MaskProvider`] at `now`.
+pub fn is_available(provider: &MaskProvider, now: DateTime<Utc>) -> bool {
+    !availability(provider, now).is_some_and(|a| a.out_of_hours)
+}
+
+/// Parses a single window, e.g. `"Mon-Fri 08:00-20:00"` or `"22:00-06:00"`.
+fn parse_window(window: &str) -> Result<Window, Error> {
+    let (days, times) = match window.rsplit_once(char::is_whitespace) {
+        Some((days, times)) => (parse_days(days.trim())?, times),
+        None => ([true; 7], window),
+    };
+    let (start, end) = times.split_once('-').ok_or_else(|| {
+        invalid(format!(
+            "expected a time range like 08:00-20:00, got '{}'",
+            times
+        ))
+    })?;
+    let (start, end) = (parse_time(start)?, parse_time(end)?);
+    if start == DAY_MINUTES {
+        return Err(invalid(format!("window '{}' can't start at 24:00", window)));
+    }
+    if start == end {
+        return Err(invalid(format!("window '{}' is empty", window)));
+    }
+    Ok(Window { days, start, end })
+}
+
+/// Parses a comma-separated list of days and day ranges, e.g. `"Mon-Fri,Sun"`.
+fn parse_days(days: &str) -> Result<[bool; 7], Error> {
+    let mut result = [false; 7];
+    for part in days.split(',').map(str::trim) {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (parse_day(first)?, parse_day(last)?),
+            None => (parse_day(part)?, parse_day(part)?),
+        };
+        // Ranges may wrap around the end of the week, e.g. `Sat-Mon`.
+        let mut day = first;
+        loop {
+            result[day] = true;
+            if day == last {
+                break;
+            }
+            day = (day + 1) % 7;
+        }
+    }
+    Ok(result)
+}
+
+/// Parses an abbreviated day name, returning its index from Monday.
+fn parse_day(day: &str) -> Result<usize, Error> {
+    let day = day.trim();
+    DAYS.iter()
+        .position(|d| d.eq_ignore_ascii_case(day))
+        .ok_or_else(|| {
+            invalid(format!(
+                "unknown day '{}', expected one of {}",
+                day,
+                DAYS.join(", ")
+            ))
+        })
+}
+
+/// Parses a time of day as `HH:MM`, returning minutes since midnight.
+/// `24:00` is accepted as the end of the day.
+fn parse_time(time: &str) -> Result<u32, Error> {
+    let time = time.trim();
+    let parsed = time
+        .split_once(':')
+        .filter(|(_, m)| m.len() == 2)
+        .and_then(|(h, m)| {
+            let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+            (m < 60 && (h < 24 || (h == 24 && m == 0))).then_some(h * 60 + m)
+        });
+    parsed.ok_or_else(|| invalid(format!("invalid time '{}', expected HH:MM", time)))
+}
+
+/// Returns the error for an invalid availability schedule.
+fn invalid(message: String) -> Error {
+    Error::UserInputError(format!("spec.availability: {}", message))
+}
@@ -0,0 +1,287 @@
+use chrono::Utc;
+use futures::stream::{self, StreamExt};
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    Api, Client, ResourceExt,
+};
+use std::time::{Duration, Instant};
+use vpn_types::*;
+
+use crate::{
+    providers::{reconcile::verify_timeout, verify_defaults::effective_verify},
+    rbac::Rule,
+    status::report::{format_age, or_none, section},
+    util::{
+        config::{ConfigMapRef, OperatorConfig},
+        list::list_all_paginated,
+        Error, ErrorContext, VERIFY_NOW_ANNOTATION,
+    },
+};
+
+/// API access the `verify-all` command needs: setting the verify-now
+/// annotation and polling the statuses. The default verification
+/// settings are read from the operator's ConfigMap with
+/// [`crate::util::config::RBAC`]. See [`crate::rbac`].
+pub const RBAC: &[Rule] = &[Rule::new(
+    "vpn.beebs.dev",
+    &["maskproviders"],
+    &["get", "list", "patch"],
+)];
+
+/// Time allowed on top of a MaskProvider's verification timeout for the
+/// verification Mask to be assigned and its Pod to be scheduled.
+pub const VERIFY_MARGIN: Duration = Duration::from_secs(60);
+
+/// How often the MaskProviders' statuses are checked while waiting.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Limits the MaskProviders that the `verify-all` command re-verifies.
+#[derive(Clone, Debug, Default)]
+pub struct VerifyAllFilter {
+    /// Only re-verify MaskProviders in this namespace.
+    pub namespace: Option<String>,
+
+    /// Only re-verify MaskProviders with at least one of these tags.
+    /// All MaskProviders are re-verified if empty.
+    pub tags: Vec<String>,
+}
+
+impl VerifyAllFilter {
+    /// Returns true if the MaskProvider should be re-verified.
+    pub fn matches(&self, provider: &MaskProvider) -> bool {
+        let namespace = self
+            .namespace
+            .as_ref()
+            .is_none_or(|ns| provider.namespace().as_ref() == Some(ns));
+        let tagged = self.tags.is_empty()
+            || provider
+                .spec
+                .tags
+                .iter()
+                .flatten()
+                .any(|tag| self.tags.contains(tag));
+        namespace && tagged && provider.metadata.deletion_timestamp.is_none()
+    }
+}
+
+/// How re-verifying a MaskProvider turned out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VerifyOutcome {
+    /// The verification cycle passed.
+    Verified,
+
+    /// The verification cycle failed and the MaskProvider is ErrVerifyFailed.
+    Failed,
+
+    /// The verification cycle didn't finish before the deadline.
+    TimedOut,
+
+    /// The MaskProvider skips verification, so it wasn't triggered.
+    Skipped,
+
+    /// The verification couldn't be triggered or followed, e.g. because
+    /// the API server refused a request. The message has the error.
+    Error,
+}
+
+impl VerifyOutcome {
+    /// Returns true if the outcome makes the command exit with an error.
+    pub fn is_failure(&self) -> bool {
+        matches!(
+            self,
+            VerifyOutcome::Failed | VerifyOutcome::TimedOut | VerifyOutcome::Error
+        )
+    }
+}
+
+impl std::fmt::Display for VerifyOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// The result of re-verifying one MaskProvider.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerifyResult {
+    pub namespace: String,
+    pub name: String,
+    pub outcome: VerifyOutcome,
+
+    /// How long the verification took, from being triggered.
+    pub duration: Duration,
+
+    /// The MaskProvider's status message when the verification finished.
+    pub message: Option<String>,
+}
+
+/// Returns the outcome of the verification cycle triggered with the
+/// verify-now value `trigger`, or None if it hasn't finished yet. The
+/// value is only recorded in `status.lastManualVerify` once the cycle
+/// completes, whether it passed or not.
+pub fn outcome(provider: &MaskProvider, trigger: &str) -> Option<VerifyOutcome> {
+    let status = provider.status.as_ref()?;
+    if status.last_manual_verify.as_deref() != Some(trigger) {
+        return None;
+    }
+    Some(match status.phase {
+        Some(MaskProviderPhase::ErrVerifyFailed) => VerifyOutcome::Failed,
+        _ => VerifyOutcome::Verified,
+    })
+}
+
+/// Renders the results as a table, followed by the number of
+/// MaskProviders with each outcome.
+pub fn summary(results: &[VerifyResult]) -> String {
+    let rows = results
+        .iter()
+        .map(|r| {
+            vec![
+                r.namespace.clone(),
+                r.name.clone(),
+                r.outcome.to_string(),
+                format_age(r.duration),
+                or_none(r.message.clone().unwrap_or_default()),
+            ]
+        })
+        .collect();
+    let count = |outcome| results.iter().filter(|r| r.outcome == outcome).count();
+    format!(
+        "{}\n{} verified, {} failed, {} timed out, {} skipped, {} errors\n",
+        section(
+            "MaskProviders",
+            &["NAMESPACE", "NAME", "RESULT", "DURATION", "MESSAGE"],
+            rows,
+        ),
+        count(VerifyOutcome::Verified),
+        count(VerifyOutcome::Failed),
+        count(VerifyOutcome::TimedOut),
+        count(VerifyOutcome::Skipped),
+        count(VerifyOutcome::Error),
+    )
+}
+
+/// Returns the default verification settings from the operator config
+/// ConfigMap, if one is given, so each MaskProvider is waited on for as
+/// long as the controller allows its verification to take.
+pub async fn default_verify(
+    client: Client,
+    config_map: Option<&ConfigMapRef>,
+) -> Result<Option<MaskProviderVerifySpec>, Error> {
+    let config_map = match config_map {
+        Some(config_map) => config_map,
+        None => return Ok(None),
+    };
+    let api: Api<ConfigMap> = Api::namespaced(client, &config_map.namespace);
+    let config = OperatorConfig::new(false);
+    match api.get(&config_map.name).await {
+        Ok(cm) => config.update(Some(&cm)),
+        Err(kube::Error::Api(ae)) if ae.code == 404 => {}
+        Err(e) => return Err(e).context_kind_name("ConfigMap", &config_map.name),
+    }
+    Ok(config.default_verify())
+}
+
+/// Entrypoint for the `verify-all` command. Each MaskProvider matching
+/// the filter is re-verified with the verify-now annotation, at most
+/// `max_concurrent` at a time, and the results are returned sorted by
+/// namespace and name. A MaskProvider whose verification can't be
+/// triggered or followed gets the [`Error`](VerifyOutcome::Error) outcome
+/// without holding up the others. Nothing but the annotation is modified,
+/// so the controllers carry out the verifications as usual.
+pub async fn run(
+    client: Client,
+    filter: &VerifyAllFilter,
+    default_verify: Option<&MaskProviderVerifySpec>,
+    max_concurrent: Option<usize>,
+) -> Result<Vec<VerifyResult>, Error> {
+    let api: Api<MaskProvider> = match filter.namespace.as_deref() {
+        Some(namespace) => Api::namespaced(client.clone(), namespace),
+        None => Api::all(client.clone()),
+    };
+    let providers: Vec<MaskProvider> = list_all_paginated(&api, &ListParams::default())
+        .await?
+        .into_iter()
+        .filter(|p| filter.matches(p))
+        .collect();
+    // Every run uses a new value, so each triggers a new cycle.
+    let trigger = format!("verify-all-{}", Utc::now().timestamp_millis());
+    let limit = max_concurrent.unwrap_or(providers.len()).max(1);
+    let mut results: Vec<VerifyResult> = stream::iter(providers)
+        .map(|provider| {
+            let client = client.clone();
+            let trigger = trigger.clone();
+            async move {
+                let namespace = provider.namespace().unwrap();
+                let name = provider.name_any();
+                let started = Instant::now();
+                verify(client, provider, &trigger, default_verify)
+                    .await
+                    .unwrap_or_else(|e| VerifyResult {
+                        namespace,
+                        name,
+                        outcome: VerifyOutcome::Error,
+                        duration: started.elapsed(),
+                        message: Some(e.to_string()),
+                    })
+            }
+        })
+        .buffer_unordered(limit)
+        .collect()
+        .await;
+    results.sort_by(|a, b| (&a.namespace, &a.name).cmp(&(&b.namespace, &b.name)));
+    Ok(results)
+}
+
+/// Triggers verification of the MaskProvider and waits for it to finish,
+/// for at most its verification timeout plus [`VERIFY_MARGIN`].
+async fn verify(
+    client: Client,
+    provider: MaskProvider,
+    trigger: &str,
+    default_verify: Option<&MaskProviderVerifySpec>,
+) -> Result<VerifyResult, Error> {
+    let namespace = provider.namespace().unwrap();
+    let name = provider.name_any();
+    let verify = effective_verify(&provider, default_verify)?;
+    let mut result = VerifyResult {
+        namespace: namespace.clone(),
+        name: name.clone(),
+        outcome: VerifyOutcome::Skipped,
+        duration: Duration::ZERO,
+        message: None,
+    };
+    if verify.as_ref().and_then(|v| v.skip) == Some(true) {
+        return Ok(result);
+    }
+    let api: Api<MaskProvider> = Api::namespaced(client, &namespace);
+    let patch = serde_json::json!({
+        "metadata": {
+            "annotations": {
+                VERIFY_NOW_ANNOTATION: trigger,
+            },
+        },
+    });
+    api.patch(&name, &PatchParams::default(), &Patch::Merge(&patch))
+        .await
+        .context_kind_name("MaskProvider", &name)?;
+    let started = Instant::now();
+    let deadline = verify_timeout(verify.as_ref()) + VERIFY_MARGIN;
+    loop {
+        let current = api
+            .get(&name)
+            .await
+            .context_kind_name("MaskProvider", &name)?;
+        result.duration = started.elapsed();
+        result.message = current.status.as_ref().and_then(|s| s.message.clone());
+        if let Some(outcome) = outcome(&current, trigger) {
+            result.outcome = outcome;
+            return Ok(result);
+        }
+        if result.duration >= deadline {
+            result.outcome = VerifyOutcome::TimedOut;
+            return Ok(result);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
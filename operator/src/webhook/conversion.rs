@@ -0,0 +1,87 @@
+use kube::{
+    core::{
+        conversion::{ConversionRequest, ConversionResponse, ConversionReview},
+        Status,
+    },
+    Resource,
+};
+use serde_json::Value;
+use vpn_types::*;
+
+use crate::util::Error;
+
+/// Converts each object in the review to the desired version. If any
+/// object fails to convert, the whole review fails, as required by the
+/// API server.
+pub fn review(review: ConversionReview) -> ConversionReview {
+    let request = match ConversionRequest::from_review(review) {
+        Ok(request) => request,
+        Err(e) => {
+            return ConversionResponse::invalid(Status::failure(&e.to_string(), "InvalidRequest"))
+                .into_review()
+        }
+    };
+    let converted: Result<Vec<Value>, Error> = request
+        .objects
+        .iter()
+        .map(|object| convert(object.clone(), &request.desired_api_version))
+        .collect();
+    let response = ConversionResponse::for_request(request);
+    match converted {
+        Ok(objects) => response.success(objects),
+        Err(e) => response.failure(Status::failure(&e.to_string(), "ConversionFailed")),
+    }
+    .into_review()
+}
+
+/// Converts the `Mask` object to the desired API version. Only the spec
+/// differs between versions, so the rest of the object is kept as is.
+pub fn convert(mut object: Value, desired_api_version: &str) -> Result<Value, Error> {
+    if object["kind"].as_str() != Some(&Mask::kind(&())) {
+        return Err(Error::UserInputError(format!(
+            "cannot convert kind {}",
+            object["kind"]
+        )));
+    }
+    let api_version = object["apiVersion"].as_str().unwrap_or_default().to_owned();
+    if api_version == desired_api_version {
+        return Ok(object);
+    }
+    let spec = object
+        .get_mut("spec")
+        .map(Value::take)
+        .unwrap_or_else(|| Value::Object(Default::default()));
+    let options = if api_version == Mask::api_version(&()) {
+        serde_json::from_value::<MaskSpec>(spec)?.options()
+    } else if api_version == v2::Mask::api_version(&()) {
+        serde_json::from_value::<v2::MaskSpec>(spec)?.options()
+    } else {
+        return Err(Error::UserInputError(format!(
+            "cannot convert from {}",
+            api_version
+        )));
+    };
+    let mut spec = if desired_api_version == Mask::api_version(&()) {
+        serde_json::to_value(MaskSpec::from(options))?
+    } else if desired_api_version == v2::Mask::api_version(&()) {
+        serde_json::to_value(v2::MaskSpec::from(options))?
+    } else {
+        return Err(Error::UserInputError(format!(
+            "cannot convert to {}",
+            desired_api_version
+        )));
+    };
+    remove_nulls(&mut spec);
+    object["spec"] = spec;
+    object["apiVersion"] = Value::String(desired_api_version.to_owned());
+    Ok(object)
+}
+
+/// Removes the fields of unset options, which would otherwise be
+/// written as nulls, so that converted objects stay minimal.
+fn remove_nulls(value: &mut Value) {
+    if let Value::Object(fields) = value {
+        fields.retain(|_, v| !v.is_null());
+        fields.values_mut().for_each(remove_nulls);
+    }
+}
@@ -0,0 +1,259 @@
+use k8s_openapi::api::core::v1::Pod;
+use kube::{
+    core::{
+        admission::{AdmissionRequest, AdmissionResponse, AdmissionReview},
+        DynamicObject,
+    },
+    Api, Client,
+};
+use serde_json::Value;
+use std::str::FromStr;
+use vpn_render::{
+    gluetun_container, merge_containers, secret_wait_container, secret_wait_volume, GluetunOptions,
+    VPN_CONTAINER_NAME,
+};
+use vpn_types::*;
+
+use crate::{
+    masks::util::get_consumer,
+    util::{
+        messages, Error, ErrorContext, INJECTED_ANNOTATION, INJECT_MASK_ANNOTATION,
+        INJECT_NOT_READY_ANNOTATION, INJECT_OVERRIDES_ANNOTATION,
+    },
+};
+
+/// What to do with a Pod whose Mask doesn't have credentials yet.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NotReadyPolicy {
+    /// Reject the Pod, so its controller retries creating it later.
+    #[default]
+    Reject,
+
+    /// Admit the Pod with an init container that waits for the
+    /// credentials Secret, as long as its name is already known.
+    Wait,
+}
+
+impl FromStr for NotReadyPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "reject" => Ok(NotReadyPolicy::Reject),
+            "wait" => Ok(NotReadyPolicy::Wait),
+            other => Err(format!("expected reject or wait, got '{}'", other)),
+        }
+    }
+}
+
+/// Decision of the sidecar injection webhook for a Pod.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Injection {
+    /// Admit the Pod as is, because it doesn't ask for a sidecar or
+    /// already has one.
+    Skip,
+
+    /// Admit the Pod with the sidecar, as the Pod spec to replace its own.
+    Inject(Box<Pod>),
+
+    /// Reject the Pod with the message.
+    Deny(String),
+}
+
+/// Returns the annotation of the Pod.
+fn annotation<'a>(pod: &'a Pod, key: &str) -> Option<&'a str> {
+    pod.metadata
+        .annotations
+        .as_ref()?
+        .get(key)
+        .map(String::as_str)
+}
+
+/// Returns the name of the Mask whose credentials the Pod asks for.
+pub fn requested_mask(pod: &Pod) -> Option<&str> {
+    annotation(pod, INJECT_MASK_ANNOTATION).filter(|name| !name.is_empty())
+}
+
+/// Returns true if the sidecar was already injected into the Pod, e.g.
+/// because the webhook is invoked again after another webhook changed it.
+fn is_injected(pod: &Pod) -> bool {
+    annotation(pod, INJECTED_ANNOTATION).is_some()
+}
+
+/// Returns true if one of the Pod's own containers has the sidecar's name.
+fn has_container_conflict(pod: &Pod) -> bool {
+    pod.spec
+        .as_ref()
+        .is_some_and(|spec| spec.containers.iter().any(|c| c.name == VPN_CONTAINER_NAME))
+}
+
+/// Decides how to inject the gluetun sidecar into the Pod, which asks for
+/// the credentials of `mask` in its namespace. `consumer` is the Mask's
+/// MaskConsumer, which holds the name of its credentials Secret.
+pub fn decide(
+    pod: &Pod,
+    namespace: &str,
+    mask_name: &str,
+    mask: Option<&Mask>,
+    consumer: Option<&MaskConsumer>,
+) -> Injection {
+    if is_injected(pod) {
+        return Injection::Skip;
+    }
+    if has_container_conflict(pod) {
+        return Injection::Deny(messages::inject_container_conflict(VPN_CONTAINER_NAME));
+    }
+    let policy = match annotation(pod, INJECT_NOT_READY_ANNOTATION)
+        .map(NotReadyPolicy::from_str)
+        .transpose()
+    {
+        Ok(policy) => policy.unwrap_or_default(),
+        Err(reason) => {
+            return Injection::Deny(messages::inject_invalid_annotation(
+                INJECT_NOT_READY_ANNOTATION,
+                &reason,
+            ))
+        }
+    };
+    let mask = match mask {
+        Some(mask) => mask,
+        None => return Injection::Deny(messages::inject_mask_not_found(namespace, mask_name)),
+    };
+    let phase = mask.status.as_ref().and_then(|s| s.phase);
+    let provider = consumer
+        .and_then(|c| c.status.as_ref())
+        .and_then(|s| s.provider.as_ref());
+    let wait = match (phase, provider) {
+        (Some(MaskPhase::Active), Some(_)) => false,
+        (_, Some(_)) if policy == NotReadyPolicy::Wait => true,
+        (_, None) if policy == NotReadyPolicy::Wait => {
+            return Injection::Deny(messages::inject_mask_unassigned(namespace, mask_name))
+        }
+        _ => return Injection::Deny(messages::inject_mask_not_ready(namespace, mask_name, phase)),
+    };
+    match inject(pod, mask_name, provider.unwrap(), wait) {
+        Ok(pod) => Injection::Inject(Box::new(pod)),
+        Err(reason) => Injection::Deny(messages::inject_invalid_annotation(
+            INJECT_OVERRIDES_ANNOTATION,
+            &reason,
+        )),
+    }
+}
+
+/// Returns the Pod with the gluetun sidecar wired to the assigned
+/// MaskProvider's credentials, and with an init container that waits
+/// for them to be copied if `wait` is set. The sidecar runs the gluetun
+/// version the credentials are written for, with the Pod's overrides.
+/// The Pod is annotated with the Mask's name, so it isn't injected twice.
+fn inject(
+    pod: &Pod,
+    mask_name: &str,
+    provider: &AssignedProvider,
+    wait: bool,
+) -> Result<Pod, String> {
+    let mut sidecar = gluetun_container(
+        &provider.secret,
+        GluetunOptions {
+            version: provider.gluetun_version.clone(),
+            ..Default::default()
+        },
+    );
+    if let Some(overrides) = annotation(pod, INJECT_OVERRIDES_ANNOTATION) {
+        let overrides: Value = serde_json::from_str(overrides).map_err(|e| e.to_string())?;
+        sidecar = merge_containers(sidecar, overrides).map_err(|e| e.to_string())?;
+    }
+    let mut pod = pod.clone();
+    pod.metadata
+        .annotations
+        .get_or_insert_with(Default::default)
+        .insert(INJECTED_ANNOTATION.to_owned(), mask_name.to_owned());
+    let spec = pod.spec.get_or_insert_with(Default::default);
+    // The sidecar goes first, so it starts before the workload.
+    spec.containers.insert(0, sidecar);
+    if wait {
+        spec.init_containers
+            .get_or_insert_with(Vec::new)
+            .insert(0, secret_wait_container());
+        spec.volumes
+            .get_or_insert_with(Vec::new)
+            .push(secret_wait_volume(&provider.secret));
+    }
+    Ok(pod)
+}
+
+/// Gets the Mask, if it exists.
+async fn get_mask(client: Client, namespace: &str, name: &str) -> Result<Option<Mask>, Error> {
+    let api: Api<Mask> = Api::namespaced(client, namespace);
+    api.get_opt(name).await.context_kind_name("Mask", name)
+}
+
+/// Decides how to inject the sidecar into the Pod of the request, looking
+/// up the Mask it asks for. Pods that don't ask for one are admitted
+/// without reading anything.
+pub async fn determine_injection(
+    client: Client,
+    request: &AdmissionRequest<Pod>,
+) -> Result<Injection, Error> {
+    let pod = match request.object.as_ref() {
+        Some(pod) => pod,
+        None => return Ok(Injection::Skip),
+    };
+    let mask_name = match requested_mask(pod) {
+        Some(name) => name,
+        None => return Ok(Injection::Skip),
+    };
+    // Pods created by a controller don't have a namespace until admitted.
+    let namespace = request
+        .namespace
+        .as_deref()
+        .or(pod.metadata.namespace.as_deref())
+        .unwrap_or("default");
+    let mask = get_mask(client.clone(), namespace, mask_name).await?;
+    let consumer = match mask.as_ref() {
+        Some(mask) => get_consumer(client, mask).await?,
+        None => None,
+    };
+    Ok(decide(
+        pod,
+        namespace,
+        mask_name,
+        mask.as_ref(),
+        consumer.as_ref(),
+    ))
+}
+
+/// Serializes the `AdmissionReview` for the API server, which expects the
+/// patch as base64 rather than the array of bytes it's serialized as.
+fn encode_review(review: AdmissionReview<DynamicObject>) -> Value {
+    let mut review = serde_json::to_value(review).unwrap();
+    if let Some(patch) = review.pointer_mut("/response/patch") {
+        let bytes: Vec<u8> = serde_json::from_value(patch.take()).unwrap_or_default();
+        *patch = Value::String(openssl::base64::encode_block(&bytes));
+    }
+    review
+}
+
+/// Answers the `AdmissionReview` of a Pod, injecting the gluetun sidecar
+/// if the Pod asks for one. Pods are rejected if the Mask can't be read,
+/// so no Pod that asks for credentials starts without them.
+pub async fn review(client: Client, review: AdmissionReview<Pod>) -> Value {
+    let request: AdmissionRequest<Pod> = match review.try_into() {
+        Ok(request) => request,
+        Err(e) => return encode_review(AdmissionResponse::invalid(e.to_string()).into_review()),
+    };
+    let response = AdmissionResponse::from(&request);
+    let response = match determine_injection(client, &request).await {
+        Ok(Injection::Skip) => response,
+        Ok(Injection::Inject(injected)) => {
+            let original = serde_json::to_value(request.object.as_ref().unwrap()).unwrap();
+            let injected = serde_json::to_value(&*injected).unwrap();
+            match response.with_patch(json_patch::diff(&original, &injected)) {
+                Ok(response) => response,
+                Err(e) => AdmissionResponse::from(&request).deny(e.to_string()),
+            }
+        }
+        Ok(Injection::Deny(message)) => response.deny(message),
+        Err(e) => response.deny(e.to_string()),
+    };
+    encode_review(response.into_review())
+}
@@ -0,0 +1,125 @@
+use hyper::{
+    header::CONTENT_TYPE, server::conn::Http, service::service_fn, Body, Method, Request, Response,
+    StatusCode,
+};
+use k8s_openapi::api::core::v1::Pod;
+use kube::{
+    core::{admission::AdmissionReview, conversion::ConversionReview},
+    Client,
+};
+use openssl::ssl::{Ssl, SslAcceptor, SslFiletype, SslMethod};
+use std::{path::Path, pin::Pin, sync::Arc};
+use tokio::net::TcpListener;
+use tokio_openssl::SslStream;
+
+use crate::{rbac::Rule, util::Error};
+
+pub(crate) mod conversion;
+pub(crate) mod injection;
+
+/// Path the API server sends `ConversionReview`s to, as configured
+/// in the `Mask` CRD's `spec.conversion.webhook.clientConfig`.
+pub const CONVERT_PATH: &str = "/convert";
+
+/// Path the API server sends the `AdmissionReview`s of Pods to, as
+/// configured in the sidecar injection `MutatingWebhookConfiguration`.
+pub const INJECT_PATH: &str = "/inject";
+
+/// API access the webhook needs. Only injecting sidecars reads anything,
+/// namely the Masks that Pods ask for. See [`crate::rbac`].
+pub const RBAC: &[Rule] = &[Rule::new("vpn.beebs.dev", &["masks"], &["get"])];
+
+/// Returns a response with the status code and message.
+fn status_response(status: StatusCode, message: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(message))
+        .unwrap()
+}
+
+/// Handles a single request to the webhook server. Sidecars are only
+/// injected if the server was given a client to look up Masks with.
+async fn serve_req(
+    req: Request<Body>,
+    client: Option<Client>,
+) -> Result<Response<Body>, hyper::Error> {
+    match (req.method(), req.uri().path(), client) {
+        (&Method::POST, CONVERT_PATH, _) => {
+            let body = hyper::body::to_bytes(req.into_body()).await?;
+            let review: ConversionReview = match serde_json::from_slice(&body) {
+                Ok(review) => review,
+                Err(e) => return Ok(status_response(StatusCode::BAD_REQUEST, e.to_string())),
+            };
+            let review = conversion::review(review);
+            Ok(Response::builder()
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_vec(&review).unwrap()))
+                .unwrap())
+        }
+        (&Method::POST, INJECT_PATH, Some(client)) => {
+            let body = hyper::body::to_bytes(req.into_body()).await?;
+            let review: AdmissionReview<Pod> = match serde_json::from_slice(&body) {
+                Ok(review) => review,
+                Err(e) => return Ok(status_response(StatusCode::BAD_REQUEST, e.to_string())),
+            };
+            let review = injection::review(client, review).await;
+            Ok(Response::builder()
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_vec(&review).unwrap()))
+                .unwrap())
+        }
+        // Used for the Pod's readiness and liveness probes.
+        (&Method::GET, "/healthz", _) => Ok(Response::new(Body::from("ok"))),
+        _ => Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap()),
+    }
+}
+
+/// Entrypoint for the conversion webhook, which converts `Mask` resources
+/// between API versions for the API server. The API server only talks to
+/// webhooks over HTTPS, so a PEM-encoded certificate and key are required.
+/// Given a client, it also injects gluetun sidecars into Pods.
+pub async fn run(
+    port: u16,
+    tls_cert_file: &Path,
+    tls_key_file: &Path,
+    client: Option<Client>,
+) -> Result<(), Error> {
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
+    acceptor.set_certificate_chain_file(tls_cert_file)?;
+    acceptor.set_private_key_file(tls_key_file, SslFiletype::PEM)?;
+    acceptor.check_private_key()?;
+    let acceptor = Arc::new(acceptor.build());
+
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    println!("Webhook listening on https://0.0.0.0:{}", port);
+    loop {
+        let (tcp, addr) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let client = client.clone();
+        tokio::spawn(async move {
+            let ssl = match Ssl::new(acceptor.context()) {
+                Ok(ssl) => ssl,
+                Err(e) => return eprintln!("TLS setup for {} failed: {}", addr, e),
+            };
+            let mut stream = match SslStream::new(ssl, tcp) {
+                Ok(stream) => stream,
+                Err(e) => return eprintln!("TLS setup for {} failed: {}", addr, e),
+            };
+            if let Err(e) = Pin::new(&mut stream).accept().await {
+                return eprintln!("TLS handshake with {} failed: {}", addr, e);
+            }
+            if let Err(e) = Http::new()
+                .serve_connection(
+                    stream,
+                    service_fn(move |req| serve_req(req, client.clone())),
+                )
+                .await
+            {
+                eprintln!("Webhook connection with {} failed: {}", addr, e);
+            }
+        });
+    }
+}
@@ -0,0 +1,12 @@
+use thiserror::Error;
+
+/// Error returned when the verification Pod can't be rendered,
+/// which happens if overrides or placement can't be deserialized.
+#[derive(Error, Debug)]
+pub enum RenderError {
+    #[error("Json error: {source}")]
+    JsonError {
+        #[from]
+        source: serde_json::Error,
+    },
+}
@@ -0,0 +1,38 @@
+use vpn_types::MaskProviderSpec;
+
+use crate::gluetun_image;
+
+/// VPN sidecar image. Efforts were made to use a stock
+/// image with no modifications, as to maximize the
+/// modular paradigm of using sidecars.
+pub const DEFAULT_VPN_IMAGE: &str = "qmcgaw/gluetun:v3.32.0";
+
+/// Parses a gluetun version such as `v3.38.0` or `3.38`. Returns
+/// `None` for tags that aren't versions, e.g. `latest`.
+pub fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version.strip_prefix('v').unwrap_or(version).split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().map_or(Some(0), |p| p.parse().ok())?;
+    let patch = parts.next().map_or(Some(0), |p| p.parse().ok())?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((major, minor, patch))
+}
+
+/// Returns the image tag for the gluetun version. Versions are
+/// tagged with a leading `v`, other tags are used as is.
+pub fn image_tag(version: &str) -> String {
+    if parse_version(version).is_some() && !version.starts_with('v') {
+        format!("v{}", version)
+    } else {
+        version.to_owned()
+    }
+}
+
+/// Returns the gluetun image that verifies the MaskProvider's
+/// credentials, which is [`DEFAULT_VPN_IMAGE`] with its tag
+/// replaced by `spec.gluetunVersion`, if specified.
+pub fn vpn_image(spec: &MaskProviderSpec) -> String {
+    gluetun_image(spec.gluetun_version.as_deref())
+}
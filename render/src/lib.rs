@@ -0,0 +1,40 @@
+//! Renders the verification [`Pod`](k8s_openapi::api::core::v1::Pod) of a
+//! [`MaskProvider`](vpn_types::MaskProvider) from its spec alone, so the
+//! manifest can be inspected without a cluster. vpn-operator creates its
+//! verification Pods with [`render_verify_pod`]. The gluetun container
+//! it runs is built by [`gluetun_container`], which other workloads can
+//! use to run the same sidecar with a MaskConsumer's credentials.
+
+mod error;
+pub use error::*;
+
+mod image;
+pub use image::*;
+
+mod merge;
+pub use merge::*;
+
+mod placement;
+pub use placement::*;
+
+mod pod;
+pub use pod::*;
+
+mod sidecar;
+pub use sidecar::*;
+
+#[cfg(test)]
+mod test;
+
+/// Compiles and runs the examples in the README.
+#[cfg(doctest)]
+#[doc = include_str!("../README.md")]
+pub struct ReadmeDoctests;
+
+/// Name of the kubernetes resource manager.
+pub const MANAGER_NAME: &str = "vpn-operator";
+
+/// A label that a Mask/MaskConsumer must have in order to force
+/// assignment to a MaskProvider with a specific uid, even if the
+/// MaskProvider has no open slots.
+pub const VERIFICATION_LABEL: &str = "vpn.beebs.dev/verify";
@@ -0,0 +1,36 @@
+use k8s_openapi::api::core::v1::PodSpec;
+use std::collections::BTreeMap;
+use vpn_types::*;
+
+use crate::RenderError;
+
+/// Well-known label with the zone a node is in.
+pub const ZONE_LABEL: &str = "topology.kubernetes.io/zone";
+
+/// Returns the node selector for the verification Pod, with the
+/// placement's zone expanded into a selector on [`ZONE_LABEL`].
+pub fn node_selector(
+    placement: &MaskProviderVerifyPlacementSpec,
+) -> Option<BTreeMap<String, String>> {
+    let mut node_selector = placement.node_selector.clone();
+    if let Some(zone) = placement.zone.as_ref() {
+        node_selector
+            .get_or_insert_with(BTreeMap::new)
+            .insert(ZONE_LABEL.to_owned(), zone.clone());
+    }
+    node_selector
+}
+
+/// Constrains the verification Pod to the nodes allowed by the placement.
+pub fn apply_placement(
+    spec: &mut PodSpec,
+    placement: &MaskProviderVerifyPlacementSpec,
+) -> Result<(), RenderError> {
+    spec.node_selector = node_selector(placement);
+    spec.affinity = placement
+        .affinity
+        .clone()
+        .map(serde_json::from_value)
+        .transpose()?;
+    Ok(())
+}
@@ -0,0 +1,367 @@
+use k8s_openapi::{
+    api::core::v1::{Container, EnvVar, Pod, PodSpec, VolumeMount},
+    apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference},
+};
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use vpn_types::*;
+
+use crate::{
+    apply_placement, deep_merge, gluetun_container, shared_volume, shared_volume_mount, vpn_image,
+    vpn_init_container, GluetunOptions, RenderError, CURL_IMAGE, GEO_SERVICE, IP_FILE_PATH,
+    IP_SERVICE, MANAGER_NAME, VERIFICATION_LABEL,
+};
+
+/// The name of the probe container within the verify pod.
+pub const PROBE_CONTAINER_NAME: &str = "probe";
+
+/// The script used by the probe container to check if the
+/// VPN is connected. Requires the environment variables.
+const PROBE_SCRIPT: &str = "#!/bin/sh
+INITIAL_IP=$(cat $IP_FILE_PATH) # created by init container
+echo \"Unmasked IP address is $INITIAL_IP\"
+INITIAL_WAIT=6s
+echo \"Waiting for $INITIAL_WAIT to allow the VPN container time to connect...\"
+sleep $INITIAL_WAIT
+TIMEOUT=5 # IP service request timeout (seconds)
+IP=$(curl -m $TIMEOUT -s $IP_SERVICE)
+ITER=0
+# Continue probing the IP service if it fails while the
+# VPN is connecting or returns the initial IP address.
+while [ $? -ne 0 ] || [ \"$IP\" = \"$INITIAL_IP\" ]; do
+    echo \"Current IP address is $IP, sleeping for $SLEEP_TIME\"
+    sleep $SLEEP_TIME
+    IP=$(curl -m $TIMEOUT -s $IP_SERVICE)
+    # exponential backoff
+    TIMEOUT=$((TIMEOUT + ITER))
+    SLEEP_TIME=$((SLEEP_TIME + ITER))
+    ITER=$((ITER + 1))
+done
+echo \"VPN connected. Masked IP address: $IP\"";
+
+/// Appended to the probe script when verification asserts what the egress
+/// looks like. Each failed assertion exits nonzero with the reason written
+/// to the termination message, which the controller shows in the status.
+/// The location is looked up with retries, and if the lookup still fails,
+/// which says nothing about the credentials, the country and region aren't
+/// asserted. That is written to the termination message without failing.
+const ASSERT_SCRIPT: &str = r#"
+fail() {
+    echo "$1"
+    echo "$1" > /dev/termination-log
+    exit 2
+}
+lower() {
+    echo "$1" | tr '[:upper:]' '[:lower:]'
+}
+if [ -n "$ASSERT_COUNTRY" ] || [ -n "$ASSERT_REGION" ]; then
+    # The geolocation service may be rate limited or briefly unreachable.
+    LOOKUP_ATTEMPTS=5
+    LOOKUP_WAIT=2
+    ATTEMPT=1
+    while :; do
+        GEO=$(curl -m $TIMEOUT -sf $GEO_SERVICE | tr -d '\n')
+        COUNTRY=$(echo "$GEO" | sed -n 's/.*"country": *"\([^"]*\)".*/\1/p')
+        REGION=$(echo "$GEO" | sed -n 's/.*"region": *"\([^"]*\)".*/\1/p')
+        if [ -n "$COUNTRY" ] || [ $ATTEMPT -ge $LOOKUP_ATTEMPTS ]; then
+            break
+        fi
+        echo "Failed to look up the location of $IP, retrying in ${LOOKUP_WAIT}s"
+        sleep $LOOKUP_WAIT
+        # exponential backoff
+        LOOKUP_WAIT=$((LOOKUP_WAIT * 2))
+        ATTEMPT=$((ATTEMPT + 1))
+    done
+    if [ -z "$COUNTRY" ]; then
+        NOTE="Couldn't look up the location of $IP with $GEO_SERVICE after $ATTEMPT attempts, so the egress country and region weren't asserted."
+        echo "$NOTE"
+        echo "$NOTE" > /dev/termination-log
+    else
+        echo "Egress is located in $REGION, $COUNTRY"
+        if [ -n "$ASSERT_COUNTRY" ] && [ "$(lower "$COUNTRY")" != "$(lower "$ASSERT_COUNTRY")" ]; then
+            fail "Egress country is '$COUNTRY', expected '$ASSERT_COUNTRY'."
+        fi
+        if [ -n "$ASSERT_REGION" ] && [ "$(lower "$REGION")" != "$(lower "$ASSERT_REGION")" ]; then
+            fail "Egress region is '$REGION', expected '$ASSERT_REGION'."
+        fi
+    fi
+fi
+if [ -n "$ASSERT_CIDRS" ]; then
+    ip_int() {
+        OLD_IFS=$IFS
+        IFS=.
+        set -- $1
+        IFS=$OLD_IFS
+        echo $(( ($1 << 24) + ($2 << 16) + ($3 << 8) + $4 ))
+    }
+    IP_INT=$(ip_int "$IP")
+    MATCHED=
+    for CIDR in $(echo "$ASSERT_CIDRS" | tr ',' ' '); do
+        BITS=${CIDR#*/}
+        MASK=$(( (0xFFFFFFFF << (32 - BITS)) & 0xFFFFFFFF ))
+        if [ $(( IP_INT & MASK )) -eq $(( $(ip_int ${CIDR%/*}) & MASK )) ]; then
+            MATCHED=$CIDR
+        fi
+    done
+    if [ -z "$MATCHED" ]; then
+        fail "Egress IP address $IP is outside of $ASSERT_CIDRS."
+    fi
+fi
+if [ -n "$NOTE" ]; then
+    echo "Egress assertions passed, except for the location."
+else
+    echo "Egress assertions passed."
+fi
+"#;
+
+lazy_static! {
+    static ref SHARED_VOLUME_MOUNT: VolumeMount = shared_volume_mount();
+    static ref DEFAULT_INIT_CONTAINER: Container = vpn_init_container();
+    static ref DEFAULT_PROBE_CONTAINER: Container = Container {
+        name: PROBE_CONTAINER_NAME.to_owned(),
+        image: Some(CURL_IMAGE.to_owned()),
+        image_pull_policy: Some("IfNotPresent".to_owned()),
+        command: Some(
+            vec!["sh", "-c", "echo \"$PROBE_SCRIPT\" | sh -"]
+                .into_iter()
+                .map(String::from)
+                .collect()
+        ),
+        env: Some(vec![
+            EnvVar {
+                name: "PROBE_SCRIPT".to_owned(),
+                value: Some(PROBE_SCRIPT.to_owned()),
+                ..Default::default()
+            },
+            EnvVar {
+                name: "IP_SERVICE".to_owned(),
+                value: Some(IP_SERVICE.to_owned()),
+                ..Default::default()
+            },
+            EnvVar {
+                name: "IP_FILE_PATH".to_owned(),
+                value: Some(IP_FILE_PATH.to_owned()),
+                ..Default::default()
+            },
+            EnvVar {
+                name: "SLEEP_TIME".to_owned(),
+                value: Some("10s".to_owned()),
+                ..Default::default()
+            },
+        ]),
+        volume_mounts: Some(vec![SHARED_VOLUME_MOUNT.clone()]),
+        ..Default::default()
+    };
+}
+
+/// Names and references the verification Pod is rendered with, which
+/// the operator only knows once the verification slot is reserved.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenderNames {
+    /// Name of the Pod, which is the name of the MaskProvider.
+    pub pod: String,
+
+    /// Namespace of the Pod, which is the namespace of the MaskProvider.
+    pub namespace: String,
+
+    /// Name of the credentials Secret copied for the verification
+    /// MaskConsumer, whose keys are injected into the VPN container.
+    pub secret: String,
+
+    /// Uid of the MaskProvider, which the Pod is labeled with.
+    pub provider_uid: String,
+
+    /// Owner reference to the verification MaskConsumer, so the Pod is
+    /// garbage collected when the provider is unassigned from it.
+    pub owner: Option<OwnerReference>,
+}
+
+/// Merges the container spec with the given overrides. Objects are
+/// merged, arrays replace the defaults and null removes the field.
+pub fn merge_containers(container: Container, overrides: Value) -> Result<Container, RenderError> {
+    let mut val = serde_json::to_value(&container)?;
+    deep_merge(&mut val, overrides);
+    Ok(serde_json::from_value(val)?)
+}
+
+/// Creates the container spec for the init container that
+/// retrieves the unmasked public IP address and writes it
+/// to the shared volume. This is done on startup so that
+/// the executor will truly know when it's okay to start
+/// downloading the video and/or thumbnail.
+pub fn get_init_container(overrides: Option<&Value>) -> Result<Container, RenderError> {
+    let container = DEFAULT_INIT_CONTAINER.clone();
+    match overrides {
+        Some(overrides) => merge_containers(container, overrides.clone()),
+        None => Ok(container),
+    }
+}
+
+/// Returns the container the probes the external IP address
+/// and exits with code zero when it changes or exits nonzero
+/// if it fails to change before the timeout. With `assert`, it
+/// also exits nonzero if the egress doesn't look as asserted.
+pub fn get_probe_container(
+    assert: Option<&MaskProviderVerifyAssertSpec>,
+    overrides: Option<&Value>,
+) -> Result<Container, RenderError> {
+    let mut container = DEFAULT_PROBE_CONTAINER.clone();
+    if let Some(assert) = assert {
+        assert_egress(&mut container, assert);
+    }
+    match overrides {
+        Some(overrides) => merge_containers(container, overrides.clone()),
+        None => Ok(container),
+    }
+}
+
+/// Makes the probe container assert what the egress looks like once the
+/// public IP address changes. The assertions are passed as environment
+/// variables, and only set ones are checked.
+fn assert_egress(container: &mut Container, assert: &MaskProviderVerifyAssertSpec) {
+    let env = container.env.get_or_insert_with(Default::default);
+    if let Some(script) = env.iter_mut().find(|e| e.name == "PROBE_SCRIPT") {
+        script.value = Some(format!("{}\n{}", PROBE_SCRIPT, ASSERT_SCRIPT));
+    }
+    let vars = [
+        ("GEO_SERVICE", Some(GEO_SERVICE.to_owned())),
+        ("ASSERT_COUNTRY", assert.country.clone()),
+        ("ASSERT_REGION", assert.region.clone()),
+        ("ASSERT_CIDRS", assert.cidrs.as_ref().map(|c| c.join(","))),
+    ];
+    for (name, value) in vars {
+        if let Some(value) = value {
+            env.push(EnvVar {
+                name: name.to_owned(),
+                value: Some(value),
+                ..Default::default()
+            });
+        }
+    }
+}
+
+/// Returns the container that connects to the VPN, with each of
+/// the Secret's keys injected into its environment.
+pub fn get_vpn_container(
+    secret: &str,
+    secret_keys: &[String],
+    image: String,
+    overrides: Option<&Value>,
+) -> Result<Container, RenderError> {
+    let container = gluetun_container(
+        secret,
+        GluetunOptions {
+            image: Some(image),
+            secret_keys: Some(secret_keys.to_vec()),
+            ..Default::default()
+        },
+    );
+    match overrides {
+        Some(overrides) => merge_containers(container, overrides.clone()),
+        None => Ok(container),
+    }
+}
+
+/// Returns the Pod that verifies the MaskProvider's VPN credentials
+/// work, honoring the placement and overrides in its spec. The keys
+/// of the credentials Secret are injected into the VPN container in
+/// the given order.
+pub fn render_verify_pod(
+    provider: &MaskProviderSpec,
+    secret_keys: &[String],
+    names: RenderNames,
+) -> Result<Pod, RenderError> {
+    let overrides = provider.verify.as_ref().and_then(|v| v.overrides.as_ref());
+    let container_overrides = overrides.and_then(|o| o.containers.as_ref());
+
+    // Assemble the container specs with the overrides.
+    let init_container = get_init_container(container_overrides.and_then(|c| c.init.as_ref()))?;
+    // Verify with the gluetun version the credentials are written for.
+    let vpn_container = get_vpn_container(
+        &names.secret,
+        secret_keys,
+        vpn_image(provider),
+        container_overrides.and_then(|c| c.vpn.as_ref()),
+    )?;
+    let probe_container = get_probe_container(
+        provider.verify.as_ref().and_then(|v| v.assert.as_ref()),
+        container_overrides.and_then(|c| c.probe.as_ref()),
+    )?;
+
+    // Assemble the containers into a pod.
+    let mut pod = Pod {
+        metadata: ObjectMeta {
+            name: Some(names.pod),
+            namespace: Some(names.namespace),
+            labels: Some({
+                // Add a label to the pod so that we can easily find it.
+                let mut labels: BTreeMap<String, String> = BTreeMap::new();
+                labels.insert("app".to_owned(), MANAGER_NAME.to_owned());
+                // Allow the controller to watch verification pods only.
+                labels.insert(VERIFICATION_LABEL.to_owned(), names.provider_uid);
+                labels
+            }),
+            // Setting the MaskConsumer as the owner will allow the
+            // pod to be properly garbage collected when the provider
+            // is unassigned from the Mask.
+            owner_references: names.owner.map(|owner| vec![owner]),
+            ..Default::default()
+        },
+        spec: Some(PodSpec {
+            restart_policy: Some("Never".to_owned()),
+            init_containers: Some(vec![init_container]),
+            containers: vec![vpn_container, probe_container],
+            volumes: Some(vec![shared_volume()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    // Constrain where the pod is scheduled, if requested.
+    if let Some(placement) = provider.verify.as_ref().and_then(|v| v.placement.as_ref()) {
+        apply_placement(pod.spec.as_mut().unwrap(), placement)?;
+    }
+
+    // Keep the pod from being the first to go when the cluster is under pressure.
+    if let Some(name) = provider
+        .verify
+        .as_ref()
+        .and_then(|v| v.priority_class_name.as_ref())
+    {
+        pod.spec.as_mut().unwrap().priority_class_name = Some(name.clone());
+    }
+
+    // Apply overrides to the pod if necessary.
+    match overrides.and_then(|o| o.pod.as_ref()) {
+        // Merge the overriden values into the resource.
+        Some(pod_template) => {
+            let owned = pod.metadata.clone();
+            let mut val = serde_json::to_value(&pod)?;
+            deep_merge(&mut val, pod_template.clone());
+            let mut pod: Pod = serde_json::from_value(val)?;
+            restore_owned_metadata(&mut pod.metadata, owned);
+            Ok(pod)
+        }
+        // No pod override requested.
+        _ => Ok(pod),
+    }
+}
+
+/// Restores the metadata the controller relies on to find, watch and
+/// delete the verification Pod, which the Pod overrides may not change.
+fn restore_owned_metadata(metadata: &mut ObjectMeta, owned: ObjectMeta) {
+    metadata.name = owned.name;
+    metadata.generate_name = None;
+    metadata.namespace = owned.namespace;
+    metadata.owner_references = owned.owner_references;
+    if let Some(uid) = owned
+        .labels
+        .and_then(|mut labels| labels.remove(VERIFICATION_LABEL))
+    {
+        metadata
+            .labels
+            .get_or_insert_with(Default::default)
+            .insert(VERIFICATION_LABEL.to_owned(), uid);
+    }
+}
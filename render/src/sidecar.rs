@@ -0,0 +1,274 @@
+use const_format::concatcp;
+use k8s_openapi::api::core::v1::{
+    Capabilities, Container, ContainerPort, EnvFromSource, EnvVar, EnvVarSource, SecretEnvSource,
+    SecretKeySelector, SecretVolumeSource, SecurityContext, Volume, VolumeMount,
+};
+
+use crate::{image_tag, DEFAULT_VPN_IMAGE};
+
+/// Image to use for the curl container. This is used to
+/// retrieve the initial/unmasked IP address for the pod
+/// during initialization.
+pub const CURL_IMAGE: &str = "curlimages/curl:7.88.1";
+
+/// The IP service to use for getting the public IP address.
+pub const IP_SERVICE: &str = "https://api.ipify.org";
+
+/// The IP geolocation service used to check where the egress is located
+/// when verification asserts a country or region. It must respond with
+/// JSON that has `country` and `region` fields.
+pub const GEO_SERVICE: &str = "https://ipinfo.io/json";
+
+/// Name of the shared volume, used to share files between
+/// containers and detect when the VPN connected. Containers
+/// should mount this volume at `SHARED_PATH` and access
+/// the initial ip file at `IP_FILE_PATH` to know when the
+/// VPN finishes connecting.
+pub const SHARED_VOLUME_NAME: &str = "shared";
+
+/// Shared directory path.
+pub const SHARED_PATH: &str = "/shared";
+
+/// The file containing the unmasked IP address of the pod.
+/// This is written by an init container so the executor
+/// knows when the VPN is connected.
+pub const IP_FILE_PATH: &str = concatcp!(SHARED_PATH, "/ip");
+
+/// The name of the init container that writes the unmasked IP address.
+pub const INIT_CONTAINER_NAME: &str = "init";
+
+/// The name of the VPN container within the verify pod.
+pub const VPN_CONTAINER_NAME: &str = "vpn";
+
+/// The name of the init container that waits for the credentials Secret.
+pub const SECRET_WAIT_CONTAINER_NAME: &str = "vpn-wait";
+
+/// Name of the volume the credentials Secret is awaited through.
+pub const SECRET_WAIT_VOLUME_NAME: &str = "vpn-credentials";
+
+/// Path the credentials Secret is awaited at.
+pub const SECRET_WAIT_PATH: &str = "/vpn-credentials";
+
+/// Port of gluetun's HTTP control server, when it's enabled.
+pub const CONTROL_SERVER_PORT: i32 = 8000;
+
+/// Name of the container port of gluetun's HTTP control server.
+pub const CONTROL_SERVER_PORT_NAME: &str = "control";
+
+/// gluetun variable with the listening address of its HTTP control server.
+pub const CONTROL_SERVER_ADDRESS_VAR: &str = "HTTP_CONTROL_SERVER_ADDRESS";
+
+/// gluetun variable that turns DNS over TLS on or off.
+pub const DOT_VAR: &str = "DOT";
+
+/// gluetun variable with the plaintext DNS server to use instead of DNS
+/// over TLS.
+pub const DNS_ADDRESS_VAR: &str = "DNS_ADDRESS";
+
+/// gluetun variable with the subnets that are reachable outside of the
+/// tunnel, comma separated.
+pub const FIREWALL_OUTBOUND_SUBNETS_VAR: &str = "FIREWALL_OUTBOUND_SUBNETS";
+
+/// Options of the gluetun container returned by [`gluetun_container`].
+/// The defaults are the container the verification Pod runs, with all
+/// of the Secret's keys injected.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GluetunOptions {
+    /// Image to run. Takes precedence over `version`.
+    pub image: Option<String>,
+
+    /// gluetun version the credentials are written for, e.g. `v3.38.0`.
+    /// Its tag of [`DEFAULT_VPN_IMAGE`] is run, as with a MaskProvider's
+    /// `spec.gluetunVersion`.
+    pub version: Option<String>,
+
+    /// Keys of the Secret to inject one by one, in the given order. All
+    /// of its keys are injected with `envFrom` if unspecified.
+    pub secret_keys: Option<Vec<String>>,
+
+    /// Enables gluetun's HTTP control server on [`CONTROL_SERVER_PORT`].
+    pub control_server: bool,
+
+    /// Plaintext DNS server to use instead of DNS over TLS, e.g. the
+    /// cluster's DNS Service so in-cluster names still resolve.
+    pub dns_address: Option<String>,
+
+    /// Subnets that are reachable outside of the tunnel, e.g. the
+    /// cluster's Pod and Service CIDRs.
+    pub firewall_outbound_subnets: Vec<String>,
+}
+
+impl GluetunOptions {
+    /// Returns the image the options select.
+    pub fn image(&self) -> String {
+        self.image
+            .clone()
+            .unwrap_or_else(|| gluetun_image(self.version.as_deref()))
+    }
+}
+
+/// Returns [`DEFAULT_VPN_IMAGE`] with its tag replaced by the gluetun
+/// version, if specified.
+pub fn gluetun_image(version: Option<&str>) -> String {
+    match version {
+        Some(version) => {
+            let repository = DEFAULT_VPN_IMAGE.split(':').next().unwrap();
+            format!("{}:{}", repository, image_tag(version))
+        }
+        None => DEFAULT_VPN_IMAGE.to_owned(),
+    }
+}
+
+/// Returns an environment variable with the value.
+fn env_var(name: &str, value: String) -> EnvVar {
+    EnvVar {
+        name: name.to_owned(),
+        value: Some(value),
+        ..Default::default()
+    }
+}
+
+/// Returns the canonical gluetun container, which connects to the VPN
+/// with the credentials in the Secret. It's named [`VPN_CONTAINER_NAME`]
+/// and may administer the Pod's network, which gluetun requires.
+pub fn gluetun_container(secret_name: &str, opts: GluetunOptions) -> Container {
+    let image = opts.image();
+    let mut env: Vec<EnvVar> = opts
+        .secret_keys
+        .iter()
+        .flatten()
+        .map(|key| EnvVar {
+            name: key.clone(),
+            value_from: Some(EnvVarSource {
+                secret_key_ref: Some(SecretKeySelector {
+                    name: Some(secret_name.to_owned()),
+                    key: key.clone(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .collect();
+    if opts.control_server {
+        env.push(env_var(
+            CONTROL_SERVER_ADDRESS_VAR,
+            format!(":{}", CONTROL_SERVER_PORT),
+        ));
+    }
+    if let Some(dns_address) = opts.dns_address {
+        env.push(env_var(DOT_VAR, "off".to_owned()));
+        env.push(env_var(DNS_ADDRESS_VAR, dns_address));
+    }
+    if !opts.firewall_outbound_subnets.is_empty() {
+        env.push(env_var(
+            FIREWALL_OUTBOUND_SUBNETS_VAR,
+            opts.firewall_outbound_subnets.join(","),
+        ));
+    }
+    Container {
+        name: VPN_CONTAINER_NAME.to_owned(),
+        image: Some(image),
+        image_pull_policy: Some("IfNotPresent".to_owned()),
+        env: (!env.is_empty()).then_some(env),
+        env_from: opts.secret_keys.is_none().then(|| {
+            vec![EnvFromSource {
+                secret_ref: Some(SecretEnvSource {
+                    name: Some(secret_name.to_owned()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }]
+        }),
+        ports: opts.control_server.then(|| {
+            vec![ContainerPort {
+                name: Some(CONTROL_SERVER_PORT_NAME.to_owned()),
+                container_port: CONTROL_SERVER_PORT,
+                ..Default::default()
+            }]
+        }),
+        security_context: Some(SecurityContext {
+            capabilities: Some(Capabilities {
+                add: Some(vec!["NET_ADMIN".to_owned()]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Returns the mount of the [`shared_volume`] at [`SHARED_PATH`].
+pub fn shared_volume_mount() -> VolumeMount {
+    VolumeMount {
+        name: SHARED_VOLUME_NAME.to_owned(),
+        mount_path: SHARED_PATH.to_owned(),
+        ..Default::default()
+    }
+}
+
+/// Returns the volume the containers share files through, such as the
+/// unmasked IP address at [`IP_FILE_PATH`].
+pub fn shared_volume() -> Volume {
+    Volume {
+        name: SHARED_VOLUME_NAME.to_owned(),
+        empty_dir: Some(Default::default()),
+        ..Default::default()
+    }
+}
+
+/// Returns the init container that writes the unmasked IP address to
+/// [`IP_FILE_PATH`] before the VPN connects, so other containers can
+/// tell when the address changes.
+pub fn vpn_init_container() -> Container {
+    Container {
+        name: INIT_CONTAINER_NAME.to_owned(),
+        image: Some(CURL_IMAGE.to_owned()),
+        image_pull_policy: Some("IfNotPresent".to_owned()),
+        command: Some(
+            vec!["curl", "-o", IP_FILE_PATH, "-s", IP_SERVICE]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        ),
+        volume_mounts: Some(vec![shared_volume_mount()]),
+        ..Default::default()
+    }
+}
+
+/// Returns the volume that [`secret_wait_container`] awaits the Secret
+/// through. It's optional, so the Pod starts while the Secret is missing.
+pub fn secret_wait_volume(secret_name: &str) -> Volume {
+    Volume {
+        name: SECRET_WAIT_VOLUME_NAME.to_owned(),
+        secret: Some(SecretVolumeSource {
+            secret_name: Some(secret_name.to_owned()),
+            optional: Some(true),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Returns the init container that blocks until the [`secret_wait_volume`]
+/// has any keys, which the kubelet fills in once the Secret is created.
+/// It keeps the other containers from starting without the credentials.
+pub fn secret_wait_container() -> Container {
+    let script = format!(
+        "until [ -n \"$(ls {})\" ]; do echo \"Waiting for VPN credentials...\"; sleep 5; done",
+        SECRET_WAIT_PATH
+    );
+    Container {
+        name: SECRET_WAIT_CONTAINER_NAME.to_owned(),
+        image: Some(CURL_IMAGE.to_owned()),
+        image_pull_policy: Some("IfNotPresent".to_owned()),
+        command: Some(vec!["sh".to_owned(), "-c".to_owned(), script]),
+        volume_mounts: Some(vec![VolumeMount {
+            name: SECRET_WAIT_VOLUME_NAME.to_owned(),
+            mount_path: SECRET_WAIT_PATH.to_owned(),
+            read_only: Some(true),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    }
+}
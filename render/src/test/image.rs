@@ -0,0 +1,32 @@
+use vpn_types::MaskProviderSpec;
+
+use crate::{gluetun_image, image_tag, parse_version, vpn_image, DEFAULT_VPN_IMAGE};
+
+#[test]
+fn versions_parsed() {
+    assert_eq!(parse_version("v3.38.0"), Some((3, 38, 0)));
+    assert_eq!(parse_version("3.38"), Some((3, 38, 0)));
+    assert_eq!(parse_version("v3"), Some((3, 0, 0)));
+    assert_eq!(parse_version("latest"), None);
+    assert_eq!(parse_version("v3.38.0.1"), None);
+    assert_eq!(parse_version("v3.x"), None);
+}
+
+#[test]
+fn versions_tagged() {
+    assert_eq!(image_tag("3.38.0"), "v3.38.0");
+    assert_eq!(image_tag("v3.38.0"), "v3.38.0");
+    assert_eq!(image_tag("latest"), "latest");
+}
+
+#[test]
+fn image_follows_gluetun_version() {
+    assert_eq!(gluetun_image(None), DEFAULT_VPN_IMAGE);
+    assert_eq!(gluetun_image(Some("3.38")), "qmcgaw/gluetun:v3.38");
+    let spec = MaskProviderSpec {
+        gluetun_version: Some("latest".to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(vpn_image(&spec), "qmcgaw/gluetun:latest");
+    assert_eq!(vpn_image(&MaskProviderSpec::default()), DEFAULT_VPN_IMAGE);
+}
@@ -0,0 +1,27 @@
+use serde_json::json;
+
+use crate::deep_merge;
+
+#[test]
+fn objects_merged() {
+    let mut value = json!({ "a": { "b": 1, "c": 2 }, "d": [1, 2] });
+    deep_merge(&mut value, json!({ "a": { "c": 3, "e": 4 }, "d": [3] }));
+    // Objects are merged, while arrays replace the defaults.
+    assert_eq!(value, json!({ "a": { "b": 1, "c": 3, "e": 4 }, "d": [3] }));
+}
+
+#[test]
+fn null_removes_field() {
+    let mut value = json!({ "a": { "b": 1, "c": 2 } });
+    deep_merge(&mut value, json!({ "a": { "b": null }, "missing": null }));
+    assert_eq!(value, json!({ "a": { "c": 2 } }));
+}
+
+#[test]
+fn scalars_replaced() {
+    let mut value = json!({ "a": "b" });
+    deep_merge(&mut value, json!({ "a": { "c": 1 } }));
+    assert_eq!(value, json!({ "a": { "c": 1 } }));
+    deep_merge(&mut value, json!("d"));
+    assert_eq!(value, json!("d"));
+}
@@ -0,0 +1,4 @@
+mod image;
+mod merge;
+mod pod;
+mod sidecar;
@@ -0,0 +1,107 @@
+use k8s_openapi::api::core::v1::Pod;
+use serde_json::json;
+use vpn_types::*;
+
+use crate::{
+    render_verify_pod, RenderNames, INIT_CONTAINER_NAME, PROBE_CONTAINER_NAME, VERIFICATION_LABEL,
+    VPN_CONTAINER_NAME, ZONE_LABEL,
+};
+
+/// Returns the names the verification Pod is rendered with.
+fn names() -> RenderNames {
+    RenderNames {
+        pod: "my-vpn".to_owned(),
+        namespace: "vpn".to_owned(),
+        secret: "my-vpn-verify".to_owned(),
+        provider_uid: "provider-uid".to_owned(),
+        owner: None,
+    }
+}
+
+/// Renders the verification Pod of a MaskProvider verified with `verify`.
+fn render(verify: MaskProviderVerifySpec) -> Pod {
+    let spec = MaskProviderSpec {
+        verify: Some(verify),
+        ..Default::default()
+    };
+    render_verify_pod(&spec, &["OPENVPN_USER".to_owned()], names()).unwrap()
+}
+
+#[test]
+fn default_pod_rendered() {
+    let pod = render(Default::default());
+    assert_eq!(pod.metadata.name.as_deref(), Some("my-vpn"));
+    assert_eq!(pod.metadata.namespace.as_deref(), Some("vpn"));
+    assert_eq!(
+        pod.metadata.labels.unwrap()[VERIFICATION_LABEL],
+        "provider-uid"
+    );
+    let spec = pod.spec.unwrap();
+    assert_eq!(spec.restart_policy.as_deref(), Some("Never"));
+    assert_eq!(spec.init_containers.unwrap()[0].name, INIT_CONTAINER_NAME);
+    let names: Vec<_> = spec.containers.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, [VPN_CONTAINER_NAME, PROBE_CONTAINER_NAME]);
+    // The Secret's keys are injected one by one.
+    let env = spec.containers[0].env.as_ref().unwrap();
+    assert_eq!(env[0].name, "OPENVPN_USER");
+    assert_eq!(spec.containers[0].env_from, None);
+}
+
+#[test]
+fn placement_and_priority_applied() {
+    let pod = render(MaskProviderVerifySpec {
+        placement: Some(MaskProviderVerifyPlacementSpec {
+            zone: Some("us-east-1a".to_owned()),
+            ..Default::default()
+        }),
+        priority_class_name: Some("verification".to_owned()),
+        ..Default::default()
+    });
+    let spec = pod.spec.unwrap();
+    assert_eq!(spec.node_selector.unwrap()[ZONE_LABEL], "us-east-1a");
+    assert_eq!(spec.priority_class_name.as_deref(), Some("verification"));
+}
+
+#[test]
+fn pod_overrides_keep_owned_metadata() {
+    let pod = render(MaskProviderVerifySpec {
+        overrides: Some(MaskProviderVerifyOverridesSpec {
+            pod: Some(json!({
+                "metadata": {
+                    "name": "renamed",
+                    "labels": { VERIFICATION_LABEL: null, "team": "a" },
+                },
+                "spec": { "restartPolicy": "OnFailure" },
+            })),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    // The operator finds the Pod by its name and label, so they stay.
+    assert_eq!(pod.metadata.name.as_deref(), Some("my-vpn"));
+    let labels = pod.metadata.labels.unwrap();
+    assert_eq!(labels[VERIFICATION_LABEL], "provider-uid");
+    assert_eq!(labels["team"], "a");
+    assert_eq!(
+        pod.spec.unwrap().restart_policy.as_deref(),
+        Some("OnFailure")
+    );
+}
+
+#[test]
+fn invalid_overrides_rejected() {
+    let spec = MaskProviderSpec {
+        verify: Some(MaskProviderVerifySpec {
+            overrides: Some(MaskProviderVerifyOverridesSpec {
+                containers: Some(MaskProviderVerifyContainerOverridesSpec {
+                    vpn: Some(json!({ "image": 1 })),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    assert!(render_verify_pod(&spec, &[], names()).is_err());
+}
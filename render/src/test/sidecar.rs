@@ -0,0 +1,97 @@
+use k8s_openapi::api::core::v1::Container;
+
+use crate::{
+    gluetun_container, secret_wait_container, secret_wait_volume, GluetunOptions,
+    CONTROL_SERVER_ADDRESS_VAR, CONTROL_SERVER_PORT, DEFAULT_VPN_IMAGE, DNS_ADDRESS_VAR, DOT_VAR,
+    FIREWALL_OUTBOUND_SUBNETS_VAR, SECRET_WAIT_VOLUME_NAME, VPN_CONTAINER_NAME,
+};
+
+/// Returns the names and values of the container's environment variables.
+fn env(container: &Container) -> Vec<(String, Option<String>)> {
+    container
+        .env
+        .iter()
+        .flatten()
+        .map(|e| (e.name.clone(), e.value.clone()))
+        .collect()
+}
+
+#[test]
+fn all_keys_injected_by_default() {
+    let container = gluetun_container("creds", Default::default());
+    assert_eq!(container.name, VPN_CONTAINER_NAME);
+    assert_eq!(container.image.as_deref(), Some(DEFAULT_VPN_IMAGE));
+    assert_eq!(container.env, None);
+    let env_from = container.env_from.unwrap();
+    assert_eq!(
+        env_from[0].secret_ref.as_ref().unwrap().name.as_deref(),
+        Some("creds")
+    );
+    // gluetun administers the Pod's network.
+    let capabilities = container.security_context.unwrap().capabilities.unwrap();
+    assert_eq!(capabilities.add.unwrap(), ["NET_ADMIN"]);
+}
+
+#[test]
+fn options_applied() {
+    let container = gluetun_container(
+        "creds",
+        GluetunOptions {
+            version: Some("3.38.0".to_owned()),
+            secret_keys: Some(vec!["WIREGUARD_PRIVATE_KEY".to_owned()]),
+            control_server: true,
+            dns_address: Some("10.96.0.10".to_owned()),
+            firewall_outbound_subnets: vec!["10.0.0.0/8".to_owned(), "172.16.0.0/12".to_owned()],
+            ..Default::default()
+        },
+    );
+    assert_eq!(container.image.as_deref(), Some("qmcgaw/gluetun:v3.38.0"));
+    // Only the listed keys are injected.
+    assert_eq!(container.env_from, None);
+    let key = &container.env.as_ref().unwrap()[0];
+    let selector = key.value_from.as_ref().unwrap().secret_key_ref.as_ref();
+    assert_eq!(selector.unwrap().key, "WIREGUARD_PRIVATE_KEY");
+    assert_eq!(
+        env(&container)[1..],
+        [
+            (
+                CONTROL_SERVER_ADDRESS_VAR.to_owned(),
+                Some(format!(":{}", CONTROL_SERVER_PORT))
+            ),
+            (DOT_VAR.to_owned(), Some("off".to_owned())),
+            (DNS_ADDRESS_VAR.to_owned(), Some("10.96.0.10".to_owned())),
+            (
+                FIREWALL_OUTBOUND_SUBNETS_VAR.to_owned(),
+                Some("10.0.0.0/8,172.16.0.0/12".to_owned())
+            ),
+        ]
+    );
+    assert_eq!(
+        container.ports.unwrap()[0].container_port,
+        CONTROL_SERVER_PORT
+    );
+
+    // An image takes precedence over the version.
+    let container = gluetun_container(
+        "creds",
+        GluetunOptions {
+            image: Some("registry.local/gluetun:pinned".to_owned()),
+            version: Some("3.38.0".to_owned()),
+            ..Default::default()
+        },
+    );
+    assert_eq!(
+        container.image.as_deref(),
+        Some("registry.local/gluetun:pinned")
+    );
+}
+
+#[test]
+fn secret_awaited_through_volume() {
+    let volume = secret_wait_volume("creds");
+    let secret = volume.secret.unwrap();
+    assert_eq!(secret.secret_name.as_deref(), Some("creds"));
+    assert_eq!(secret.optional, Some(true));
+    let mounts = secret_wait_container().volume_mounts.unwrap();
+    assert_eq!(mounts[0].name, SECRET_WAIT_VOLUME_NAME);
+}
@@ -0,0 +1,43 @@
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// [`VpnAccountSpec`] describes a VPN service account whose connection limit is
+/// shared by every [`MaskProvider`] that references it with [`MaskProviderSpec::account_ref`].
+/// This is useful when one account is split into several [`MaskProvider`] resources
+/// (e.g. one per region), as the account's device limit applies to all of them
+/// together, regardless of each [`MaskProvider`]'s own [`MaskProviderSpec::max_slots`].
+#[derive(CustomResource, Serialize, Deserialize, Default, Debug, PartialEq, Clone, JsonSchema)]
+#[kube(
+    group = "vpn.beebs.dev",
+    version = "v1",
+    kind = "VpnAccount",
+    plural = "vpnaccounts",
+    derive = "PartialEq"
+)]
+#[kube(derive = "Default")]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".spec.maxConnections\", \"name\": \"MAX\", \"type\": \"integer\" }"
+)]
+pub struct VpnAccountSpec {
+    /// Maximum number of [`MaskConsumer`] resources that can be assigned any
+    /// of the [`MaskProvider`]s referencing this account at the same time.
+    #[serde(rename = "maxConnections")]
+    pub max_connections: usize,
+}
+
+/// Found in [`MaskProviderStatus::account`], this struct shows how much
+/// of a [`VpnAccount`]'s connection limit is in use.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct AccountUtilization {
+    /// Name of the [`VpnAccount`] resource.
+    pub name: String,
+
+    /// Number of slots reserved with all of the [`MaskProvider`]s
+    /// that reference the [`VpnAccount`].
+    pub connections: usize,
+
+    /// The [`VpnAccount`]'s [`VpnAccountSpec::max_connections`].
+    #[serde(rename = "maxConnections")]
+    pub max_connections: usize,
+}
@@ -0,0 +1,183 @@
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt};
+
+use crate::{LastError, MaskProviderPhase, MaskProviderSpec};
+
+/// [`ClusterMaskProviderSpec`] describes a VPN service provider that is
+/// defined once for the whole cluster and exposed to each of a number of
+/// namespaces as a child [`MaskProvider`](crate::MaskProvider) of its own,
+/// so per-namespace RBAC and slot usage apply to each tenant separately.
+/// The controller creates, updates and deletes the children as namespaces
+/// start or stop matching, and reverts any edits made to them. With
+/// [`shared`](ClusterMaskProviderSpec::shared), a single child in the
+/// operator's namespace is assigned to every namespace instead.
+#[derive(CustomResource, Serialize, Default, Deserialize, Debug, PartialEq, Clone, JsonSchema)]
+#[kube(
+    group = "vpn.beebs.dev",
+    version = "v1",
+    kind = "ClusterMaskProvider",
+    plural = "clustermaskproviders",
+    derive = "PartialEq",
+    status = "ClusterMaskProviderStatus"
+)]
+#[kube(derive = "Default")]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".status.activeSlots\", \"name\": \"USED\", \"type\": \"integer\" }"
+)]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".status.phase\", \"name\": \"PHASE\", \"type\": \"string\" }"
+)]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".status.lastUpdated\", \"name\": \"AGE\", \"type\": \"date\" }"
+)]
+pub struct ClusterMaskProviderSpec {
+    /// Spec of every child [`MaskProvider`](crate::MaskProvider).
+    /// [`MaskProviderSpec::secret`] refers to a [`Secret`](k8s_openapi::api::core::v1::Secret)
+    /// in [`secretNamespace`](ClusterMaskProviderSpec::secret_namespace),
+    /// which is copied next to each child. Unless
+    /// [`slotsPerNamespace`](ClusterMaskProviderSpec::slots_per_namespace) is set,
+    /// [`MaskProviderSpec::max_slots`] applies to each child separately. Use
+    /// [`MaskProviderSpec::account_ref`] to limit the children's connections
+    /// all together.
+    #[serde(flatten)]
+    pub provider: MaskProviderSpec,
+
+    /// Namespace of the [`Secret`](k8s_openapi::api::core::v1::Secret)
+    /// containing the credentials.
+    #[serde(rename = "secretNamespace")]
+    pub secret_namespace: String,
+
+    /// Optional list of namespaces to create a child [`MaskProvider`](crate::MaskProvider) in.
+    /// Ignored if [`shared`](ClusterMaskProviderSpec::shared) is set.
+    #[serde(rename = "targetNamespaces")]
+    pub target_namespaces: Option<Vec<String>>,
+
+    /// Optional labels selecting more namespaces to create a child
+    /// [`MaskProvider`](crate::MaskProvider) in. A namespace is selected
+    /// if it has all of the labels. If neither this nor
+    /// [`targetNamespaces`](ClusterMaskProviderSpec::target_namespaces) is
+    /// set, no children are created. Ignored if
+    /// [`shared`](ClusterMaskProviderSpec::shared) is set.
+    #[serde(rename = "namespaceSelector")]
+    pub namespace_selector: Option<BTreeMap<String, String>>,
+
+    /// Optional number of slots each child [`MaskProvider`](crate::MaskProvider)
+    /// has, overriding [`MaskProviderSpec::max_slots`].
+    #[serde(rename = "slotsPerNamespace")]
+    pub slots_per_namespace: Option<usize>,
+
+    /// If true, the credentials are shared by all namespaces rather than
+    /// replicated: a single child [`MaskProvider`](crate::MaskProvider) is
+    /// kept in the operator's namespace, where its credentials and
+    /// [`MaskReservation`](crate::MaskReservation)s live, and it is assigned
+    /// to [`Mask`](crate::Mask)s in every namespace that
+    /// [`MaskProviderSpec::namespaces`] permits. Only the per-[`Mask`](crate::Mask)
+    /// copies of the credentials are written to the tenants' namespaces.
+    pub shared: Option<bool>,
+}
+
+/// Found in [`ClusterMaskProviderStatus::children`], this struct shows
+/// the state of a child [`MaskProvider`](crate::MaskProvider).
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct ChildProviderStatus {
+    /// Namespace of the child [`MaskProvider`](crate::MaskProvider).
+    pub namespace: String,
+
+    /// The child's [`MaskProviderStatus::phase`](crate::MaskProviderStatus::phase).
+    pub phase: Option<MaskProviderPhase>,
+
+    /// The child's [`MaskProviderStatus::active_slots`](crate::MaskProviderStatus::active_slots).
+    #[serde(rename = "activeSlots")]
+    pub active_slots: Option<usize>,
+
+    /// Explains why the child isn't managed, if a resource that doesn't
+    /// belong to the [`ClusterMaskProvider`] is in its way.
+    pub message: Option<String>,
+}
+
+/// Status object for the [`ClusterMaskProvider`] resource.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct ClusterMaskProviderStatus {
+    /// A short description of the [`ClusterMaskProvider`] resource's current state.
+    pub phase: Option<ClusterMaskProviderPhase>,
+
+    /// A human-readable message indicating details about why the
+    /// [`ClusterMaskProvider`] is in this phase.
+    pub message: Option<String>,
+
+    /// Timestamp of when the [`ClusterMaskProviderStatus`] object was last updated.
+    #[serde(rename = "lastUpdated")]
+    pub last_updated: Option<String>,
+
+    /// Incremented by every status update. Each update is only applied
+    /// if the revision is unchanged since the [`ClusterMaskProviderStatus`] object was read,
+    /// so a stale update can never overwrite a newer one.
+    #[serde(rename = "statusRevision")]
+    pub status_revision: Option<u64>,
+
+    /// State of each child [`MaskProvider`](crate::MaskProvider), sorted by namespace.
+    pub children: Option<Vec<ChildProviderStatus>>,
+
+    /// Number of slots in use with all of the children together.
+    #[serde(rename = "activeSlots")]
+    pub active_slots: Option<usize>,
+
+    /// Number of slots of all of the children together.
+    #[serde(rename = "maxSlots")]
+    pub max_slots: Option<usize>,
+
+    /// The most recent failed action, if the last reconciliation of the
+    /// [`ClusterMaskProvider`] failed. Cleared by the next successful status update.
+    #[serde(rename = "lastError")]
+    pub last_error: Option<LastError>,
+}
+
+/// A short description of the [`ClusterMaskProvider`] resource's current state.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+pub enum ClusterMaskProviderPhase {
+    /// The [`ClusterMaskProvider`] resource first appeared to the controller.
+    Pending,
+
+    /// Some children aren't [`Ready`](MaskProviderPhase::Ready) yet,
+    /// e.g. because their credentials are still being verified.
+    Propagating,
+
+    /// Every child is ready to be assigned, and none of them are.
+    Ready,
+
+    /// Every child is ready to be assigned, and some of them are.
+    Active,
+
+    /// A child is in an error phase, or a resource that doesn't belong
+    /// to the [`ClusterMaskProvider`] is in the way of one.
+    Degraded,
+
+    /// The [`Secret`](k8s_openapi::api::core::v1::Secret) resource referenced
+    /// by [`MaskProviderSpec::secret`] is missing from
+    /// [`secretNamespace`](ClusterMaskProviderSpec::secret_namespace).
+    ErrSecretNotFound,
+}
+
+impl fmt::Display for ClusterMaskProviderPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClusterMaskProviderPhase::Pending => write!(f, "Pending"),
+            ClusterMaskProviderPhase::Propagating => write!(f, "Propagating"),
+            ClusterMaskProviderPhase::Ready => write!(f, "Ready"),
+            ClusterMaskProviderPhase::Active => write!(f, "Active"),
+            ClusterMaskProviderPhase::Degraded => write!(f, "Degraded"),
+            ClusterMaskProviderPhase::ErrSecretNotFound => write!(f, "ErrSecretNotFound"),
+        }
+    }
+}
+
+impl ClusterMaskProviderStatus {
+    /// Sets the phase along with the message explaining it, so a
+    /// message from a previous phase is never left behind.
+    pub fn set_phase(&mut self, phase: ClusterMaskProviderPhase, message: impl Into<String>) {
+        self.phase = Some(phase);
+        self.message = Some(message.into());
+    }
+}
@@ -3,6 +3,23 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{fmt, str::FromStr};
 
+use crate::{
+    AssignmentStrategy, CopyEncryptionSpec, DeletionPolicy, LastError, MaskAntiAffinity,
+    MaskDefaultsSpec, MaskServiceSpec,
+};
+
+/// Found in [`MaskConsumerSpec::slot_affinity`], this struct identifies
+/// the slot a [`Mask`] previously reserved with a [`MaskProvider`].
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct SlotAffinity {
+    /// UID of the [`MaskProvider`] the slot belongs to.
+    #[serde(rename = "providerUid")]
+    pub provider_uid: String,
+
+    /// Index of the preferred slot.
+    pub slot: usize,
+}
+
 /// Found in [`MaskConsumerStatus::provider`], this struct contains
 /// details about the [`MaskProvider`] assigned to this [`Mask`].
 #[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
@@ -34,6 +51,28 @@ pub struct AssignedProvider {
     /// Its contents mirror that of the [`Secret`](k8s_openapi::api::core::v1::Secret)
     /// referenced by [`MaskProviderSpec::secret`].
     pub secret: String,
+
+    /// Version of gluetun that the credentials are written for, copied from
+    /// [`MaskProviderSpec::gluetun_version`] when the slot was assigned.
+    #[serde(rename = "gluetunVersion")]
+    pub gluetun_version: Option<String>,
+
+    /// Encryption annotation set on the [`secret`](AssignedProvider::secret),
+    /// copied from [`MaskProviderSpec::copy_encryption`] when the slot was assigned.
+    #[serde(rename = "copyEncryption")]
+    pub copy_encryption: Option<CopyEncryptionSpec>,
+
+    /// SHA-256 checksum of the credentials in the [`secret`](AssignedProvider::secret),
+    /// as found in its `vpn.beebs.dev/credentials-hash` annotation. Set once
+    /// the [`Secret`](k8s_openapi::api::core::v1::Secret) has been written.
+    #[serde(rename = "secretHash")]
+    pub secret_hash: Option<String>,
+
+    /// Name of the [`ClusterMaskProvider`](crate::ClusterMaskProvider) that
+    /// the assigned [`MaskProvider`] is the child of, if any. The credentials
+    /// are still copied from the child's [`Secret`](k8s_openapi::api::core::v1::Secret).
+    #[serde(rename = "clusterProvider")]
+    pub cluster_provider: Option<String>,
 }
 
 /// [`MaskConsumerSpec`] describes the configuration for a [`MaskConsumer`] resource,
@@ -57,6 +96,18 @@ pub struct AssignedProvider {
 /// the [`MaskProvider`]'s [`spec.maxSlots`](MaskProviderSpec::max_slots) will be respected.
 /// This is important for some VPN services that allow unlimited connections but reserve the
 /// right to ban you if you utilize automation to create a massive number of connections.
+///
+/// Clients outside of the cluster that share the same VPN accounts can reserve slots too,
+/// so the [`MaskProvider`]'s [`spec.maxSlots`](MaskProviderSpec::max_slots) accounts for
+/// them. Such a client creates a [`MaskConsumer`] itself, without a [`Mask`], with
+/// [`spec.external`](MaskConsumerSpec::external) set to `true`. It's assigned a slot and
+/// its credentials are copied into the [`Secret`](k8s_openapi::api::core::v1::Secret)
+/// named in [`MaskConsumerStatus::provider`] like any other, but no `Pod`s are expected
+/// to use them. Instead, the client proves it's still alive by setting the
+/// `vpn.beebs.dev/heartbeat` annotation to the current time, in RFC 3339 format, more
+/// often than the operator's heartbeat timeout (5 minutes by default). A [`MaskConsumer`]
+/// whose heartbeat is older than that, or that never had one for that long after it was
+/// created, is deleted, which releases its slot. Deleting it releases the slot right away.
 #[derive(CustomResource, Serialize, Deserialize, Default, Debug, PartialEq, Clone, JsonSchema)]
 #[kube(
     group = "vpn.beebs.dev",
@@ -77,6 +128,70 @@ pub struct AssignedProvider {
 pub struct MaskConsumerSpec {
     /// List of desired providers, inherited from the parent [`MaskSpec::providers`].
     pub providers: Option<Vec<String>>,
+
+    /// Explicit settings, inherited from the parent [`MaskSpec::settings`].
+    #[serde(flatten)]
+    pub settings: MaskDefaultsSpec,
+
+    /// The slot previously used by the parent [`Mask`], taken from
+    /// [`MaskStatus::last_slot`] and [`MaskStatus::last_provider_uid`].
+    /// If the [`MaskConsumer`] is assigned the same [`MaskProvider`],
+    /// this slot is attempted first.
+    #[serde(rename = "slotAffinity")]
+    pub slot_affinity: Option<SlotAffinity>,
+
+    /// Policy for withdrawing the credentials while Pods still reference
+    /// them, inherited from the parent [`MaskSpec::deletion_policy`].
+    #[serde(rename = "deletionPolicy")]
+    pub deletion_policy: Option<DeletionPolicy>,
+
+    /// Anti-affinity group, inherited from the parent [`MaskSpec::anti_affinity`].
+    /// Kept in sync with the [`Mask`], so joining or leaving a group re-validates
+    /// the assignment.
+    #[serde(rename = "antiAffinity")]
+    pub anti_affinity: Option<MaskAntiAffinity>,
+
+    /// Strategy for ordering the suitable [`MaskProvider`]s, inherited
+    /// from the parent [`MaskSpec::strategy`] when the [`MaskConsumer`]
+    /// is created.
+    pub strategy: Option<AssignmentStrategy>,
+
+    /// Name of the [`MaskConsumer`] whose reservation is taken over upon
+    /// assignment, inherited from the parent [`MaskSpec::succession_of`].
+    /// Only a predecessor whose [`Mask`] is gone or being deleted is taken over.
+    #[serde(rename = "successionOf")]
+    pub succession_of: Option<String>,
+
+    /// Why the [`MaskConsumer`] was created. Set by the controller when the
+    /// [`MaskConsumer`] is created. Objects created before this field existed
+    /// don't have it, and are treated as [`MaskConsumerPurpose::Workload`]
+    /// unless they carry the verification label.
+    pub purpose: Option<MaskConsumerPurpose>,
+
+    /// True if the [`MaskConsumer`] was created directly by a client outside
+    /// of the cluster rather than for a [`Mask`]. External [`MaskConsumer`]s
+    /// must renew the `vpn.beebs.dev/heartbeat` annotation to keep their slot,
+    /// as described for [`MaskConsumerSpec`].
+    pub external: Option<bool>,
+
+    /// Service exposing a port of the Pods using the credentials,
+    /// inherited from the parent [`MaskSpec::service`] when the
+    /// [`MaskConsumer`] is created.
+    pub service: Option<MaskServiceSpec>,
+}
+
+/// Found in [`MaskConsumerSpec::purpose`], this enum distinguishes the
+/// [`MaskConsumer`]s of user [`Mask`]s from those the controller creates
+/// to verify a [`MaskProvider`]'s credentials.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, JsonSchema)]
+pub enum MaskConsumerPurpose {
+    /// The credentials are consumed by a user workload.
+    #[default]
+    Workload,
+
+    /// The credentials are consumed by the verification Pod of the
+    /// [`MaskProvider`] the [`MaskConsumer`] is assigned to.
+    Verification,
 }
 
 /// Status object for the [`MaskConsumer`] resource.
@@ -93,8 +208,65 @@ pub struct MaskConsumerStatus {
     #[serde(rename = "lastUpdated")]
     pub last_updated: Option<String>,
 
+    /// Incremented by every status update. Each update is only applied
+    /// if the revision is unchanged since the [`MaskConsumerStatus`] object was read,
+    /// so a stale update can never overwrite a newer one.
+    #[serde(rename = "statusRevision")]
+    pub status_revision: Option<u64>,
+
     /// Details about the assigned provider and credentials.
     pub provider: Option<AssignedProvider>,
+
+    /// The settings in effect for the assigned provider. These are resolved
+    /// once at assignment time by applying [`MaskProviderSpec::mask_defaults`]
+    /// under [`MaskConsumerSpec::settings`], so changing a provider's defaults
+    /// only affects new assignments and never already-assigned consumers.
+    #[serde(rename = "effectiveSettings")]
+    pub effective_settings: Option<MaskDefaultsSpec>,
+
+    /// Explains why the assigned provider's [`MaskProviderSpec::namespaces`]
+    /// no longer permits the [`MaskConsumer`]'s namespace, if it was changed
+    /// after the assignment and [`MaskProviderSpec::on_policy_violation`]
+    /// is `Warn`. Cleared once the namespace is permitted again.
+    #[serde(rename = "policyViolation")]
+    pub policy_violation: Option<String>,
+
+    /// ServiceAccounts whose Pods were seen using the credentials, for
+    /// auditing which workloads used which [`MaskProvider`]. Holds at most
+    /// ten records, dropping the oldest first, and records of Pods that
+    /// haven't been seen for the retention window are pruned.
+    pub consumers: Option<Vec<ConsumerRecord>>,
+
+    /// The most recent failed action, if the last reconciliation of the
+    /// [`MaskConsumer`] failed. Cleared by the next successful status update.
+    #[serde(rename = "lastError")]
+    pub last_error: Option<LastError>,
+
+    /// Name of the Service requested with [`MaskConsumerSpec::service`],
+    /// once it was created. The Service is owned by the [`MaskConsumer`].
+    pub service: Option<String>,
+}
+
+/// Found in [`MaskConsumerStatus::consumers`], this struct records the
+/// Pods of a ServiceAccount using the credentials of a [`MaskProvider`].
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct ConsumerRecord {
+    /// Name of the ServiceAccount the Pods run as.
+    #[serde(rename = "serviceAccount")]
+    pub service_account: String,
+
+    /// The [`MaskProvider`] whose credentials the Pods used,
+    /// as `namespace/name`.
+    pub provider: String,
+
+    /// Timestamp of when the Pods were first seen using the credentials.
+    #[serde(rename = "firstSeen")]
+    pub first_seen: String,
+
+    /// Timestamp of when the Pods were last seen using the credentials.
+    /// Only updated once every status refresh interval.
+    #[serde(rename = "lastSeen")]
+    pub last_seen: String,
 }
 
 /// A short description of the [`MaskConsumer`] resource's current state.
@@ -114,6 +286,33 @@ pub enum MaskConsumerPhase {
 
     /// No suitable [`MaskProvider`] resources were found.
     ErrNoProviders,
+
+    /// The [`MaskConsumer`]'s namespace is missing the label required to opt in
+    /// to receiving copies of VPN credentials.
+    ErrNamespaceNotOptedIn,
+
+    /// A [`Secret`](k8s_openapi::api::core::v1::Secret) with the name of the
+    /// credentials copy already exists and wasn't created by the operator
+    /// for this [`MaskConsumer`], so it can't be overwritten.
+    ErrSecretConflict,
+
+    /// Every suitable [`MaskProvider`] requires labels that the [`MaskConsumer`] is
+    /// missing, as listed in [`MaskProviderSpec::required_mask_labels`].
+    ErrMissingLabels,
+
+    /// Reserving another slot would exceed a [`MaskQuota`] in the
+    /// [`MaskConsumer`]'s namespace, so it waits for one to be released.
+    ErrQuotaExceeded,
+
+    /// The assigned [`MaskProvider`] was deleted or replaced by another of
+    /// the same name, so its credentials no longer work. The [`MaskConsumer`]
+    /// is deleted next, releasing its slot.
+    ErrProviderLost,
+
+    /// The [`MaskConsumer`]'s settings can't be applied to the credentials
+    /// of its assigned [`MaskProvider`], such as a file projection from a
+    /// key they lack, so they aren't copied. The message names the setting.
+    ErrInvalidSpec,
 }
 
 impl FromStr for MaskConsumerPhase {
@@ -126,6 +325,12 @@ impl FromStr for MaskConsumerPhase {
             "Active" => Ok(MaskConsumerPhase::Active),
             "Terminating" => Ok(MaskConsumerPhase::Terminating),
             "ErrNoProviders" => Ok(MaskConsumerPhase::ErrNoProviders),
+            "ErrNamespaceNotOptedIn" => Ok(MaskConsumerPhase::ErrNamespaceNotOptedIn),
+            "ErrSecretConflict" => Ok(MaskConsumerPhase::ErrSecretConflict),
+            "ErrMissingLabels" => Ok(MaskConsumerPhase::ErrMissingLabels),
+            "ErrQuotaExceeded" => Ok(MaskConsumerPhase::ErrQuotaExceeded),
+            "ErrProviderLost" => Ok(MaskConsumerPhase::ErrProviderLost),
+            "ErrInvalidSpec" => Ok(MaskConsumerPhase::ErrInvalidSpec),
             _ => Err(()),
         }
     }
@@ -139,6 +344,55 @@ impl fmt::Display for MaskConsumerPhase {
             MaskConsumerPhase::Active => write!(f, "Active"),
             MaskConsumerPhase::Terminating => write!(f, "Terminating"),
             MaskConsumerPhase::ErrNoProviders => write!(f, "ErrNoProviders"),
+            MaskConsumerPhase::ErrNamespaceNotOptedIn => write!(f, "ErrNamespaceNotOptedIn"),
+            MaskConsumerPhase::ErrSecretConflict => write!(f, "ErrSecretConflict"),
+            MaskConsumerPhase::ErrMissingLabels => write!(f, "ErrMissingLabels"),
+            MaskConsumerPhase::ErrQuotaExceeded => write!(f, "ErrQuotaExceeded"),
+            MaskConsumerPhase::ErrProviderLost => write!(f, "ErrProviderLost"),
+            MaskConsumerPhase::ErrInvalidSpec => write!(f, "ErrInvalidSpec"),
+        }
+    }
+}
+
+impl MaskConsumerStatus {
+    /// Sets the phase along with the message explaining it, so a
+    /// message from a previous phase is never left behind. The
+    /// ErrNoProviders phase means no [`MaskProvider`] could be
+    /// assigned, so it also clears any assigned provider.
+    pub fn set_phase(&mut self, phase: MaskConsumerPhase, message: impl Into<String>) {
+        if phase == MaskConsumerPhase::ErrNoProviders {
+            self.provider = None;
+            self.effective_settings = None;
+            self.policy_violation = None;
         }
+        self.phase = Some(phase);
+        self.message = Some(message.into());
+    }
+
+    /// Assigns a [`MaskProvider`] along with the settings resolved for it.
+    /// The phase becomes Waiting, as the credentials [`Secret`](k8s_openapi::api::core::v1::Secret)
+    /// has yet to be created, and only then does the [`MaskConsumer`] become Active.
+    pub fn set_assigned(
+        &mut self,
+        provider: AssignedProvider,
+        effective_settings: MaskDefaultsSpec,
+        message: impl Into<String>,
+    ) {
+        self.provider = Some(provider);
+        self.effective_settings = Some(effective_settings);
+        self.policy_violation = None;
+        self.phase = Some(MaskConsumerPhase::Waiting);
+        self.message = Some(message.into());
+    }
+
+    /// Clears the assigned [`MaskProvider`] and the settings resolved for
+    /// it. The [`MaskConsumer`] is left Waiting for another slot, with
+    /// `reason` explaining why.
+    pub fn clear_provider(&mut self, reason: impl Into<String>) {
+        self.provider = None;
+        self.effective_settings = None;
+        self.policy_violation = None;
+        self.phase = Some(MaskConsumerPhase::Waiting);
+        self.message = Some(reason.into());
     }
 }
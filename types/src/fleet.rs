@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{MaskPhase, MaskProviderPhase};
+
+/// Current version of the [`FleetStatus`] schema. It is incremented
+/// whenever a change is made that older parsers can't ignore, such as
+/// removing or changing the meaning of a field.
+pub const FLEET_STATUS_VERSION: u32 = 1;
+
+/// [`FleetStatus`] is the compact aggregate of every resource managed by
+/// vpn-operator in a cluster, as written by the `export-status` command.
+/// It is meant to be collected from many clusters and parsed by external
+/// fleet tooling, so fields are only ever added to a given version.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct FleetStatus {
+    /// Version of the schema, always [`FLEET_STATUS_VERSION`] when written.
+    pub version: u32,
+
+    /// Name of the cluster, if one was given to the exporter.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<String>,
+
+    /// Timestamp of when the document was generated, in RFC 3339 format.
+    #[serde(rename = "generatedAt")]
+    pub generated_at: String,
+
+    /// Every [`MaskProvider`](crate::MaskProvider) in the cluster,
+    /// sorted by namespace and name.
+    pub providers: Vec<ProviderSummary>,
+
+    /// Every [`Mask`](crate::Mask) in the cluster, sorted by namespace and name.
+    pub masks: Vec<MaskSummary>,
+
+    /// Number of [`MaskConsumer`](crate::MaskConsumer) resources in each phase.
+    /// Resources without a phase are counted under `"Unknown"`.
+    pub consumers: BTreeMap<String, usize>,
+
+    /// Number of [`MaskReservation`](crate::MaskReservation) resources in each phase.
+    /// Resources without a phase are counted under `"Unknown"`.
+    pub reservations: BTreeMap<String, usize>,
+}
+
+/// Summary of a [`MaskProvider`](crate::MaskProvider) in a [`FleetStatus`].
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ProviderSummary {
+    pub namespace: String,
+    pub name: String,
+
+    /// The [`MaskProviderStatus::phase`](crate::MaskProviderStatus::phase).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phase: Option<MaskProviderPhase>,
+
+    /// The [`MaskProviderStatus::active_slots`](crate::MaskProviderStatus::active_slots).
+    #[serde(rename = "activeSlots", skip_serializing_if = "Option::is_none")]
+    pub active_slots: Option<usize>,
+
+    /// The [`MaskProviderSpec::max_slots`](crate::MaskProviderSpec::max_slots).
+    #[serde(rename = "maxSlots")]
+    pub max_slots: usize,
+}
+
+/// Summary of a [`Mask`](crate::Mask) in a [`FleetStatus`].
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct MaskSummary {
+    pub namespace: String,
+    pub name: String,
+
+    /// The [`MaskStatus::phase`](crate::MaskStatus::phase).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phase: Option<MaskPhase>,
+}
@@ -0,0 +1,17 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Details about the most recent failed reconciliation action for a resource.
+/// It is set whenever an action fails and cleared by the next successful
+/// status update, so its presence means the resource is currently failing.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct LastError {
+    /// Name of the action that failed (e.g. `CreateSecret`).
+    pub action: String,
+
+    /// The error message reported by the controller.
+    pub message: String,
+
+    /// Timestamp of when the error occurred.
+    pub at: String,
+}
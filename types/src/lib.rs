@@ -1,11 +1,37 @@
+mod account;
+pub use account::*;
+
+mod cluster_provider;
+pub use cluster_provider::*;
+
 mod consumer;
 pub use consumer::*;
 
+mod fleet;
+pub use fleet::*;
+
+mod last_error;
+pub use last_error::*;
+
 mod mask;
 pub use mask::*;
 
+mod overview;
+pub use overview::*;
+
 mod provider;
 pub use provider::*;
 
+mod quota;
+pub use quota::*;
+
 mod reservation;
 pub use reservation::*;
+
+mod settings;
+pub use settings::*;
+
+pub mod v2;
+
+#[cfg(test)]
+mod test;
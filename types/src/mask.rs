@@ -1,7 +1,13 @@
 use kube::CustomResource;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{fmt, str::FromStr};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use crate::{AssignedProvider, LastError, MaskDefaultsSpec, SlotAffinity};
+
+/// Maximum number of slots a single [`Mask`] can reserve with
+/// [`slots`](MaskSpec::slots), each of which is a [`MaskConsumer`].
+pub const MAX_MASK_SLOTS: usize = 32;
 
 /// [`MaskSpec`] describes the configuration for a [`Mask`] resource,
 /// which is the mechanism for reserving slots with [`MaskProvider`] resources.
@@ -39,7 +45,213 @@ pub struct MaskSpec {
     /// These values correspond to [`MaskProviderSpec::tags`], and
     /// only one of them has to match for the [`MaskProvider`] to be
     /// considered suitable.
+    /// If omitted, the tags in the namespace's
+    /// `vpn.beebs.dev/default-providers` annotation are used, if any.
     pub providers: Option<Vec<String>>,
+
+    /// Settings for consuming the assigned [`MaskProvider`]'s credentials.
+    /// Any setting omitted here is inherited from the assigned provider's
+    /// [`MaskProviderSpec::mask_defaults`].
+    #[serde(flatten)]
+    pub settings: MaskDefaultsSpec,
+
+    /// What happens when the credentials are withdrawn while Pods in the
+    /// namespace still reference the copied [`Secret`](k8s_openapi::api::core::v1::Secret).
+    /// Defaults to [`DeletionPolicy::Immediate`].
+    #[serde(rename = "deletionPolicy")]
+    pub deletion_policy: Option<DeletionPolicy>,
+
+    /// Keeps the [`Mask`] from being assigned a [`MaskProvider`] that is
+    /// assigned to any other [`Mask`] in the same anti-affinity group,
+    /// so the members never share an exit identity.
+    #[serde(rename = "antiAffinity")]
+    pub anti_affinity: Option<MaskAntiAffinity>,
+
+    /// How the suitable [`MaskProvider`]s are ordered when the [`Mask`] is
+    /// assigned one. Defaults to the operator's `--topology-aware` setting.
+    pub strategy: Option<AssignmentStrategy>,
+
+    /// Optional name of the [`Mask`] in the same namespace that this one
+    /// replaces, e.g. when a [`Mask`] is renamed by deleting and recreating
+    /// it. Once the predecessor is deleted or being deleted, this [`Mask`]
+    /// takes over the slot it reserved instead of reserving another, so
+    /// a [`MaskProvider`] with no free slots doesn't keep it waiting. A
+    /// predecessor that isn't being deleted is never taken over.
+    #[serde(rename = "successionOf")]
+    pub succession_of: Option<String>,
+
+    /// Optional duration string (e.g. `"6h"`) after which the [`Mask`]'s
+    /// slot is freed if no Pod uses its credentials, for [`Mask`]s that are
+    /// abandoned by whatever created them. The time counts from when the
+    /// [`MaskConsumer`] was created or a Pod using the credentials was last
+    /// seen, whichever is later. A value that isn't a duration puts the
+    /// [`Mask`] in the [`ErrInvalidSpec`](MaskPhase::ErrInvalidSpec) phase.
+    pub ttl: Option<String>,
+
+    /// What happens once the [`ttl`](MaskSpec::ttl) expires. Defaults to
+    /// [`MaskTtlAction::Release`].
+    #[serde(rename = "ttlAction")]
+    pub ttl_action: Option<MaskTtlAction>,
+
+    /// Optional ClusterIP Service named after the [`Mask`] that exposes a
+    /// port of the Pods using its credentials, such as the HTTP proxy of a
+    /// gluetun sidecar. It's created once such a Pod carries the selector
+    /// labels, and is deleted with the [`MaskConsumer`].
+    pub service: Option<MaskServiceSpec>,
+
+    /// Number of slots reserved for the [`Mask`], each with its own
+    /// [`MaskConsumer`] and copy of the credentials, for workloads that
+    /// need several VPN connections at once. Defaults to 1. The first
+    /// [`MaskConsumer`] is named after the [`Mask`], and the others get
+    /// a suffix of a hash of the [`Mask`]'s uid and the index of their slot,
+    /// e.g. `<mask>-1a2b3c4d-1`. The [`Mask`] only becomes
+    /// [`Active`](MaskPhase::Active) once every slot is assigned. At most
+    /// [`MAX_MASK_SLOTS`] slots can be reserved.
+    #[schemars(range(max = "MAX_MASK_SLOTS"))]
+    pub slots: Option<usize>,
+}
+
+/// Found in [`MaskSpec::service`], this struct describes the Service that
+/// exposes a port of the Pods using a [`Mask`]'s credentials.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct MaskServiceSpec {
+    /// Port the Service listens on.
+    pub port: i32,
+
+    /// Port of the Pods that the Service forwards to. Defaults to
+    /// [`port`](MaskServiceSpec::port).
+    #[serde(rename = "targetPort")]
+    pub target_port: Option<i32>,
+
+    /// Labels that select the Pods behind the Service. Must not be empty.
+    /// The Service is only created once a Pod that uses the credentials
+    /// carries all of them, and its endpoints follow the labels from then on.
+    #[serde(rename = "selectorLabels")]
+    pub selector_labels: BTreeMap<String, String>,
+}
+
+/// What happens to a [`Mask`] whose [`ttl`](MaskSpec::ttl) expired.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, JsonSchema)]
+pub enum MaskTtlAction {
+    /// The [`MaskConsumer`] is deleted, freeing the slot, and the [`Mask`]
+    /// stays [`Waiting`](MaskPhase::Waiting) without being assigned again
+    /// until its spec changes.
+    #[default]
+    Release,
+
+    /// The [`Mask`] itself is deleted.
+    Delete,
+}
+
+/// Groups [`Mask`]s that must be assigned distinct [`MaskProvider`]s.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct MaskAntiAffinity {
+    /// Name of the anti-affinity group. [`Mask`]s in the same namespace
+    /// with the same group are never assigned the same [`MaskProvider`],
+    /// even on different slots. A [`Mask`] waits if every suitable
+    /// [`MaskProvider`] is assigned to another member.
+    pub group: String,
+}
+
+/// Strategy for ordering the suitable [`MaskProvider`]s of a [`Mask`].
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+pub enum AssignmentStrategy {
+    /// The [`MaskProvider`]s are tried in the order of the operator's
+    /// `--provider-selector`, even if `--topology-aware` is set.
+    Selector,
+
+    /// [`MaskProvider`]s tagged `region=<value>` with the region of the
+    /// [`Mask`]'s namespace, taken from the label set by the operator's
+    /// `--topology-label`, are tried before all others. Each group keeps
+    /// the order of the operator's `--provider-selector`.
+    TopologyAware,
+}
+
+/// Policy for deleting a [`MaskConsumer`]'s credentials while Pods still
+/// reference its copy of them. Either way, a Warning event is published
+/// on each of the Pods and on the [`Mask`], if it still exists.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, JsonSchema)]
+pub enum DeletionPolicy {
+    /// The credentials are deleted right away.
+    #[default]
+    Immediate,
+
+    /// The [`MaskConsumer`] is kept in the [`Terminating`](MaskConsumerPhase::Terminating)
+    /// phase until the Pods are gone or the consumers controller's grace period expires.
+    WaitForPods,
+}
+
+/// Version-independent view of the options in a [`Mask`]'s spec. The
+/// controllers read [`Mask`]s through it, so they behave the same no
+/// matter which version of the resource was written. Each version's spec
+/// converts to and from it, which is how [`v2::Mask`](crate::v2::Mask)
+/// resources are converted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MaskOptions {
+    /// Tags of the [`MaskProvider`]s that may be assigned, if restricted.
+    pub providers: Option<Vec<String>>,
+
+    /// Settings for consuming the assigned [`MaskProvider`]'s credentials.
+    pub settings: MaskDefaultsSpec,
+
+    /// Policy for withdrawing credentials that Pods still reference.
+    pub deletion_policy: Option<DeletionPolicy>,
+
+    /// Group of [`Mask`]s that must be assigned distinct [`MaskProvider`]s.
+    pub anti_affinity: Option<MaskAntiAffinity>,
+
+    /// Strategy for ordering the suitable [`MaskProvider`]s.
+    pub strategy: Option<AssignmentStrategy>,
+
+    /// Name of the [`Mask`] this one replaces, if any.
+    pub succession_of: Option<String>,
+
+    /// Duration after which the slot of an unused [`Mask`] is freed.
+    pub ttl: Option<String>,
+
+    /// What happens once the [`ttl`](MaskOptions::ttl) expires.
+    pub ttl_action: Option<MaskTtlAction>,
+
+    /// Service exposing a port of the Pods using the credentials, if any.
+    pub service: Option<MaskServiceSpec>,
+
+    /// Number of slots reserved for the [`Mask`], if not 1.
+    pub slots: Option<usize>,
+}
+
+impl MaskSpec {
+    /// Returns the version-independent view of the spec.
+    pub fn options(&self) -> MaskOptions {
+        MaskOptions {
+            providers: self.providers.clone(),
+            settings: self.settings.clone(),
+            deletion_policy: self.deletion_policy,
+            anti_affinity: self.anti_affinity.clone(),
+            strategy: self.strategy,
+            succession_of: self.succession_of.clone(),
+            ttl: self.ttl.clone(),
+            ttl_action: self.ttl_action,
+            service: self.service.clone(),
+            slots: self.slots,
+        }
+    }
+}
+
+impl From<MaskOptions> for MaskSpec {
+    fn from(options: MaskOptions) -> Self {
+        MaskSpec {
+            providers: options.providers,
+            settings: options.settings,
+            deletion_policy: options.deletion_policy,
+            anti_affinity: options.anti_affinity,
+            strategy: options.strategy,
+            succession_of: options.succession_of,
+            ttl: options.ttl,
+            ttl_action: options.ttl_action,
+            service: options.service,
+            slots: options.slots,
+        }
+    }
 }
 
 /// Status object for the [`Mask`] resource.
@@ -55,6 +267,72 @@ pub struct MaskStatus {
     /// Timestamp of when the [`MaskStatus`] object was last updated.
     #[serde(rename = "lastUpdated")]
     pub last_updated: Option<String>,
+
+    /// Incremented by every status update. Each update is only applied
+    /// if the revision is unchanged since the [`MaskStatus`] object was read,
+    /// so a stale update can never overwrite a newer one.
+    #[serde(rename = "statusRevision")]
+    pub status_revision: Option<u64>,
+
+    /// The most recent failed action, if the last reconciliation of the
+    /// [`Mask`] failed. Cleared by the next successful status update.
+    #[serde(rename = "lastError")]
+    pub last_error: Option<LastError>,
+
+    /// Slot index most recently reserved for the [`Mask`]. If the [`Mask`]
+    /// loses its [`MaskConsumer`] and is later assigned the same
+    /// [`MaskProvider`], this slot is preferred when it is free.
+    #[serde(rename = "lastSlot")]
+    pub last_slot: Option<usize>,
+
+    /// UID of the [`MaskProvider`] that [`MaskStatus::last_slot`] belongs to.
+    #[serde(rename = "lastProviderUid")]
+    pub last_provider_uid: Option<String>,
+
+    /// Timestamp of when the [`Mask`] first became Active, in RFC 3339
+    /// format. It is recorded once and kept through reassignments, so
+    /// it reflects how long the [`Mask`] initially waited for a slot.
+    #[serde(rename = "firstActiveAt")]
+    pub first_active_at: Option<String>,
+
+    /// Set when the [`Mask`] was unassigned because its [`MaskProvider`]
+    /// was deleted, if the [`MaskProvider`] has
+    /// [`reportWithdrawal`](MaskProviderSpec::report_withdrawal) enabled,
+    /// or because it failed re-verification and has
+    /// [`onVerifyFailure: Evict`](MaskProviderSpec::on_verify_failure).
+    /// Cleared the next time the [`Mask`] becomes Active.
+    #[serde(rename = "providerWithdrawn")]
+    pub provider_withdrawn: Option<ProviderWithdrawn>,
+
+    /// Generation of the [`Mask`] whose slot was freed because its
+    /// [`ttl`](MaskSpec::ttl) expired. No [`MaskConsumer`] is created for
+    /// the [`Mask`] again until its spec changes.
+    #[serde(rename = "expiredGeneration")]
+    pub expired_generation: Option<i64>,
+
+    /// The [`MaskProvider`]s assigned to the [`Mask`]'s [`MaskConsumer`]s,
+    /// in the order of their slots, while the [`Mask`] is
+    /// [`Active`](MaskPhase::Active) or [`Waiting`](MaskPhase::Waiting)
+    /// for the rest of its [`slots`](MaskSpec::slots) to be assigned.
+    /// Kept while the [`Mask`] is [`Terminating`](MaskPhase::Terminating),
+    /// until their slots are released, and cleared in any other phase.
+    pub providers: Option<Vec<AssignedProvider>>,
+}
+
+/// Describes the [`MaskProvider`] that was withdrawn from a [`Mask`].
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Default, JsonSchema)]
+pub struct ProviderWithdrawn {
+    /// Name of the [`MaskProvider`] that was withdrawn.
+    pub name: String,
+
+    /// Namespace of the [`MaskProvider`] that was withdrawn.
+    pub namespace: String,
+
+    /// Human-readable description of why the [`MaskProvider`] was withdrawn.
+    pub message: String,
+
+    /// Timestamp of when the [`MaskProvider`] was withdrawn.
+    pub at: String,
 }
 
 /// A short description of the [`Mask`] resource's current state.
@@ -69,11 +347,41 @@ pub enum MaskPhase {
     /// The [`MaskConsumer`] resource's assigned credentials are in use by a Pod.
     Active,
 
-    /// Resource deletion is pending garbage collection.
+    /// Resource deletion is pending garbage collection, or the
+    /// [`MaskConsumer`]'s assignment is being withdrawn and the Pods
+    /// using its credentials should be stopped. The [`Mask`] becomes
+    /// [`Waiting`](MaskPhase::Waiting) once the [`MaskConsumer`] is gone.
     Terminating,
 
     /// No suitable [`MaskProvider`] resources were found.
     ErrNoProviders,
+
+    /// The [`Mask`]'s namespace is missing the label required to opt in
+    /// to receiving copies of VPN credentials.
+    ErrNamespaceNotOptedIn,
+
+    /// A [`Secret`](k8s_openapi::api::core::v1::Secret) with the name of the
+    /// credentials copy already exists and wasn't created by the operator,
+    /// so the [`Mask`] can't receive its credentials until it's removed.
+    ErrSecretConflict,
+
+    /// Every suitable [`MaskProvider`] requires labels that the [`Mask`] is
+    /// missing, as listed in [`MaskProviderSpec::required_mask_labels`].
+    ErrMissingLabels,
+
+    /// Reserving another slot would exceed a [`MaskQuota`] in the
+    /// [`Mask`]'s namespace, so it waits for one to be released.
+    ErrQuotaExceeded,
+
+    /// The assigned [`MaskProvider`] was deleted or replaced, revoking the
+    /// assignment. Pods still using the credentials must be recreated to
+    /// use those of the next [`MaskProvider`]. The phase clears once the
+    /// [`Mask`] is assigned one.
+    ErrProviderLost,
+
+    /// The [`Mask`]'s spec has a value that can't be used, such as a
+    /// [`ttl`](MaskSpec::ttl) that isn't a duration. The message names it.
+    ErrInvalidSpec,
 }
 
 impl FromStr for MaskPhase {
@@ -86,6 +394,12 @@ impl FromStr for MaskPhase {
             "Waiting" => Ok(MaskPhase::Waiting),
             "Terminating" => Ok(MaskPhase::Terminating),
             "ErrNoProviders" => Ok(MaskPhase::ErrNoProviders),
+            "ErrNamespaceNotOptedIn" => Ok(MaskPhase::ErrNamespaceNotOptedIn),
+            "ErrSecretConflict" => Ok(MaskPhase::ErrSecretConflict),
+            "ErrMissingLabels" => Ok(MaskPhase::ErrMissingLabels),
+            "ErrQuotaExceeded" => Ok(MaskPhase::ErrQuotaExceeded),
+            "ErrProviderLost" => Ok(MaskPhase::ErrProviderLost),
+            "ErrInvalidSpec" => Ok(MaskPhase::ErrInvalidSpec),
             _ => Err(()),
         }
     }
@@ -99,6 +413,55 @@ impl fmt::Display for MaskPhase {
             MaskPhase::Waiting => write!(f, "Waiting"),
             MaskPhase::Terminating => write!(f, "Terminating"),
             MaskPhase::ErrNoProviders => write!(f, "ErrNoProviders"),
+            MaskPhase::ErrNamespaceNotOptedIn => write!(f, "ErrNamespaceNotOptedIn"),
+            MaskPhase::ErrSecretConflict => write!(f, "ErrSecretConflict"),
+            MaskPhase::ErrMissingLabels => write!(f, "ErrMissingLabels"),
+            MaskPhase::ErrQuotaExceeded => write!(f, "ErrQuotaExceeded"),
+            MaskPhase::ErrProviderLost => write!(f, "ErrProviderLost"),
+            MaskPhase::ErrInvalidSpec => write!(f, "ErrInvalidSpec"),
         }
     }
 }
+
+impl MaskStatus {
+    /// Sets the phase along with the message explaining it, so a
+    /// message from a previous phase is never left behind. The assigned
+    /// [`MaskProvider`]s are forgotten until they are set again.
+    pub fn set_phase(&mut self, phase: MaskPhase, message: impl Into<String>) {
+        self.phase = Some(phase);
+        self.message = Some(message.into());
+        self.providers = None;
+    }
+
+    /// Records `at` as the time the [`Mask`] first became Active, and
+    /// returns true if it wasn't recorded before. Must be called before
+    /// [`MaskStatus::set_active`]. A [`Mask`] that is already Active
+    /// without the timestamp became Active before it was tracked, so
+    /// the actual time is unknown and nothing is recorded.
+    pub fn mark_first_active(&mut self, at: impl Into<String>) -> bool {
+        if self.first_active_at.is_some() || self.phase == Some(MaskPhase::Active) {
+            return false;
+        }
+        self.first_active_at = Some(at.into());
+        true
+    }
+
+    /// Sets the Active phase. If a slot is given, it replaces the
+    /// previously remembered slot and its [`MaskProvider`], so the two
+    /// are always updated together. Any withdrawn [`MaskProvider`] is
+    /// forgotten, as the [`Mask`] has been assigned again.
+    pub fn set_active(&mut self, slot: Option<SlotAffinity>, message: impl Into<String>) {
+        self.set_phase(MaskPhase::Active, message);
+        self.provider_withdrawn = None;
+        if let Some(slot) = slot {
+            self.remember_slot(slot);
+        }
+    }
+
+    /// Remembers the slot and its [`MaskProvider`], replacing the
+    /// previously remembered ones.
+    pub fn remember_slot(&mut self, slot: SlotAffinity) {
+        self.last_provider_uid = Some(slot.provider_uid);
+        self.last_slot = Some(slot.slot);
+    }
+}
@@ -0,0 +1,77 @@
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// [`VpnFleetSpec`] describes the singleton, cluster-scoped overview of
+/// every [`MaskProvider`](crate::MaskProvider) and [`Mask`](crate::Mask)
+/// in the cluster, so `kubectl get vpnfleet` shows the health of the
+/// whole fleet in one row. It's created and kept up-to-date by the
+/// MaskProvider controller, and has nothing to configure.
+#[derive(CustomResource, Serialize, Deserialize, Default, Debug, PartialEq, Clone, JsonSchema)]
+#[kube(
+    group = "vpn.beebs.dev",
+    version = "v1",
+    kind = "VpnFleet",
+    plural = "vpnfleets",
+    derive = "PartialEq",
+    status = "VpnFleetStatus"
+)]
+#[kube(derive = "Default")]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".status.providers\", \"name\": \"PROVIDERS\", \"type\": \"integer\" }"
+)]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".status.ready\", \"name\": \"READY\", \"type\": \"integer\" }"
+)]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".status.failedVerification\", \"name\": \"FAILED\", \"type\": \"integer\" }"
+)]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".status.totalSlots\", \"name\": \"SLOTS\", \"type\": \"integer\" }"
+)]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".status.usedSlots\", \"name\": \"USED\", \"type\": \"integer\" }"
+)]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".status.masksWaiting\", \"name\": \"WAITING\", \"type\": \"integer\" }"
+)]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".status.lastUpdated\", \"name\": \"AGE\", \"type\": \"date\" }"
+)]
+pub struct VpnFleetSpec {}
+
+/// Status object for the [`VpnFleet`] resource.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct VpnFleetStatus {
+    /// Number of [`MaskProvider`](crate::MaskProvider)s in the cluster.
+    pub providers: usize,
+
+    /// Number of [`MaskProvider`](crate::MaskProvider)s that can be
+    /// assigned, i.e. in the `Ready` or `Active` phase.
+    pub ready: usize,
+
+    /// Number of [`MaskProvider`](crate::MaskProvider)s in the
+    /// `ErrVerifyFailed` phase.
+    #[serde(rename = "failedVerification")]
+    pub failed_verification: usize,
+
+    /// Sum of every [`MaskProviderSpec::max_slots`](crate::MaskProviderSpec::max_slots).
+    #[serde(rename = "totalSlots")]
+    pub total_slots: usize,
+
+    /// Sum of every [`MaskProviderStatus::active_slots`](crate::MaskProviderStatus::active_slots).
+    #[serde(rename = "usedSlots")]
+    pub used_slots: usize,
+
+    /// Number of [`Mask`](crate::Mask)s in the `Waiting` phase.
+    #[serde(rename = "masksWaiting")]
+    pub masks_waiting: usize,
+
+    /// Identity of the operator replica that wrote the status, which is
+    /// the holder of the Lease electing a single writer.
+    pub writer: Option<String>,
+
+    /// Timestamp of when the status was last written.
+    #[serde(rename = "lastUpdated")]
+    pub last_updated: Option<String>,
+}
@@ -2,7 +2,9 @@ use kube::CustomResource;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::{fmt, str::FromStr};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use crate::{AccountUtilization, LastError, MaskDefaultsSpec};
 
 /// Defines overrides for the different containers in the verification pod.
 /// The structure of these fields corresponds to the [`Container`](k8s_openapi::api::core::v1::Container)
@@ -43,10 +45,33 @@ pub struct MaskProviderVerifyOverridesSpec {
     /// Optional customization for the verification [`Pod`](k8s_openapi::api::core::v1::Pod) resource.
     /// The structure of this field corresponds to the [`Pod`](k8s_openapi::api::core::v1::Pod) schema.
     /// Validation is disabled for both peformance and simplicity.
+    /// The name, namespace, owner references and `vpn.beebs.dev/verify`
+    /// label are owned by the controller and can't be overriden.
     #[schemars(schema_with = "any_schema")]
     pub pod: Option<Value>,
 }
 
+/// Constrains where the verification [`Pod`](k8s_openapi::api::core::v1::Pod) is scheduled.
+/// Some VPN services only accept connections from certain regions, so the
+/// credentials have to be verified from a node in one of them.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct MaskProviderVerifyPlacementSpec {
+    /// Labels the node must have, as in the [`Pod`](k8s_openapi::api::core::v1::Pod)'s
+    /// `spec.nodeSelector`.
+    #[serde(rename = "nodeSelector")]
+    pub node_selector: Option<BTreeMap<String, String>>,
+
+    /// Scheduling constraints for the verification [`Pod`](k8s_openapi::api::core::v1::Pod).
+    /// The structure of this field corresponds to the [`Affinity`](k8s_openapi::api::core::v1::Affinity)
+    /// schema. Validation is disabled for both peformance and simplicity.
+    #[schemars(schema_with = "any_schema")]
+    pub affinity: Option<Value>,
+
+    /// Zone the node must be in. Shorthand for a [`nodeSelector`](MaskProviderVerifyPlacementSpec::node_selector)
+    /// on the `topology.kubernetes.io/zone` label, which it takes precedence over.
+    pub zone: Option<String>,
+}
+
 /// Configuration for verifying the [`MaskProvider`] credentials.
 /// Unless [`skip=true`](MaskProviderVerifySpec::skip), the credentials
 /// are dialed with a [gluetun](https://github.com/qdm12/gluetun) container
@@ -72,10 +97,122 @@ pub struct MaskProviderVerifySpec {
     /// then they are never verified).
     pub interval: Option<String>,
 
+    /// Duration string for the window re-verifications are spread across
+    /// (e.g. `"2h"`), so [`MaskProvider`]s verified at the same time aren't
+    /// all verified again at once. Each is verified [`interval`](MaskProviderVerifySpec::interval)
+    /// plus a stable offset within the window after it was last verified.
+    /// The offset is derived from its `uid`, so it doesn't change across
+    /// restarts. Defaults to 10% of the interval, and `"0s"` disables it.
+    pub spread: Option<String>,
+
     /// Optional customization for the verification [`Pod`](k8s_openapi::api::core::v1::Pod).
     /// Use this to setup the image, networking, etc. These values are
     /// merged onto the controller-created [`Pod`](k8s_openapi::api::core::v1::Pod).
     pub overrides: Option<MaskProviderVerifyOverridesSpec>,
+
+    /// Optional constraints on where the verification [`Pod`](k8s_openapi::api::core::v1::Pod)
+    /// is scheduled, e.g. to verify from the region the VPN service expects.
+    /// [`overrides`](MaskProviderVerifySpec::overrides) are applied after these.
+    pub placement: Option<MaskProviderVerifyPlacementSpec>,
+
+    /// Name of the `PriorityClass` of the verification [`Pod`](k8s_openapi::api::core::v1::Pod),
+    /// so it isn't the first to be evicted or preempted, e.g. by the cluster
+    /// autoscaler. A `PriorityClass` that doesn't exist is shown as a warning
+    /// in the [`MaskProvider`]'s status, and the Pod can't be created until
+    /// it does. [`overrides`](MaskProviderVerifySpec::overrides) are applied after this.
+    #[serde(rename = "priorityClassName")]
+    pub priority_class_name: Option<String>,
+
+    /// How the credentials are verified. `Dial` (the default) connects
+    /// with a gluetun container, while `KeysOnly` only checks the
+    /// credentials Secret, for VPN services gluetun can't dial.
+    pub mode: Option<MaskProviderVerifyMode>,
+
+    /// Keys the credentials Secret must have with non-empty values when
+    /// [`mode`](MaskProviderVerifySpec::mode) is `KeysOnly`, in addition
+    /// to the keys of [`keyPatterns`](MaskProviderVerifySpec::key_patterns).
+    #[serde(rename = "requiredKeys")]
+    pub required_keys: Option<Vec<String>>,
+
+    /// Regular expressions the values of the credentials Secret's keys
+    /// must match in full when [`mode`](MaskProviderVerifySpec::mode) is
+    /// `KeysOnly`, e.g. `{"VPN_SERVICE_PROVIDER": "nordvpn|surfshark"}`.
+    /// Each key is also required.
+    #[serde(rename = "keyPatterns")]
+    pub key_patterns: Option<BTreeMap<String, String>>,
+
+    /// What the egress must look like once connected, in addition to the
+    /// public IP address having changed, e.g. to catch a VPN service that
+    /// connects to the wrong region. Ignored when [`mode`](MaskProviderVerifySpec::mode)
+    /// is `KeysOnly`, as nothing is dialed.
+    pub assert: Option<MaskProviderVerifyAssertSpec>,
+}
+
+/// Assertions on the egress of the verification [`Pod`](k8s_openapi::api::core::v1::Pod)
+/// once the VPN is connected. The location is looked up with an IP
+/// geolocation service, and verification fails if any assertion doesn't hold.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct MaskProviderVerifyAssertSpec {
+    /// Country the egress must be located in, as the two-letter ISO 3166-1
+    /// code reported by the geolocation service (e.g. `"NL"`). Case-insensitive.
+    pub country: Option<String>,
+
+    /// Region the egress must be located in, as named by the geolocation
+    /// service (e.g. `"North Holland"`). Case-insensitive.
+    pub region: Option<String>,
+
+    /// IPv4 ranges in CIDR notation (e.g. `"185.65.134.0/24"`), one of
+    /// which must contain the public IP address.
+    pub cidrs: Option<Vec<String>>,
+}
+
+/// How the [`MaskProvider`] credentials are verified.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, JsonSchema)]
+pub enum MaskProviderVerifyMode {
+    /// Connect with a gluetun container and check that the public IP
+    /// address changes.
+    #[default]
+    Dial,
+
+    /// Only check that the credentials Secret has the required keys,
+    /// with non-empty values that match the patterns. No Pods or Masks
+    /// are created, so it doesn't take a slot.
+    KeysOnly,
+}
+
+/// Action taken on an assigned [`MaskConsumer`] whose namespace is no
+/// longer permitted by the [`MaskProviderSpec::namespaces`] allowlist.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, JsonSchema)]
+pub enum PolicyViolationAction {
+    /// The [`MaskConsumer`] keeps its slot and nothing is reported.
+    Ignore,
+
+    /// The [`MaskConsumer`] keeps its slot, but the violation is shown in
+    /// its [`policyViolation`](MaskConsumerStatus::policy_violation) and a
+    /// `PolicyViolation` Warning event is published.
+    #[default]
+    Warn,
+
+    /// The [`MaskConsumer`]'s slot and credentials are released and its
+    /// [`Mask`] goes back to [`Waiting`](MaskPhase::Waiting) to be assigned
+    /// a [`MaskProvider`] that permits its namespace.
+    Evict,
+}
+
+/// What happens to the [`MaskConsumer`]s assigned a [`MaskProvider`] whose
+/// credentials fail re-verification after having been verified before.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, JsonSchema)]
+pub enum VerifyFailureAction {
+    /// The [`MaskConsumer`]s keep their slots and credentials. No new
+    /// ones are assigned until the [`MaskProvider`] is verified again.
+    #[default]
+    Keep,
+
+    /// The [`MaskConsumer`]s are deleted along with their credentials,
+    /// and their [`Mask`]s go back to [`Waiting`](MaskPhase::Waiting) to
+    /// be assigned a healthy [`MaskProvider`]. For credentials that may
+    /// have been rotated or burned and must no longer be used.
+    Evict,
 }
 
 /// [`MaskProviderSpec`] is the configuration for the [`MaskProvider`] resource,
@@ -97,6 +234,15 @@ pub struct MaskProviderVerifySpec {
 #[kube(
     printcolumn = "{\"jsonPath\": \".status.activeSlots\", \"name\": \"USED\", \"type\": \"integer\" }"
 )]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".status.availableSlots\", \"name\": \"AVAILABLE\", \"type\": \"integer\" }"
+)]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".status.slotsSummary\", \"name\": \"FREE\", \"type\": \"string\" }"
+)]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".status.pendingDemand\", \"name\": \"WAITING\", \"type\": \"integer\" }"
+)]
 #[kube(
     printcolumn = "{\"jsonPath\": \".status.phase\", \"name\": \"PHASE\", \"type\": \"string\" }"
 )]
@@ -138,11 +284,252 @@ pub struct MaskProviderSpec {
     /// namespaces. If unset, all [`Mask`] namespaces are permitted.
     pub namespaces: Option<Vec<String>>,
 
+    /// What happens to [`MaskConsumer`]s that are already assigned when
+    /// [`namespaces`](MaskProviderSpec::namespaces) stops permitting their
+    /// namespace. The allowlist is re-checked while they're assigned, as it's
+    /// otherwise only enforced at assignment time. Defaults to `Warn`.
+    #[serde(rename = "onPolicyViolation")]
+    pub on_policy_violation: Option<PolicyViolationAction>,
+
     /// VPN service verification options. Used to ensure the credentials
     /// are valid before assigning the [`MaskProvider`] to [`Mask`] resources.
     /// Enabled by default. Set [`skip=true`](MaskProviderVerifySpec::skip) to
     /// disable verification.
     pub verify: Option<MaskProviderVerifySpec>,
+
+    /// What happens to the [`MaskConsumer`]s assigned this [`MaskProvider`]
+    /// when it enters [`ErrVerifyFailed`](MaskProviderPhase::ErrVerifyFailed)
+    /// after having been verified before. Verification [`MaskConsumer`]s
+    /// are never evicted. Defaults to `Keep`.
+    #[serde(rename = "onVerifyFailure")]
+    pub on_verify_failure: Option<VerifyFailureAction>,
+
+    /// Optional default settings for [`Mask`] resources assigned to this
+    /// [`MaskProvider`]. Settings specified on the [`Mask`] always win.
+    /// Defaults are resolved when a slot is assigned and recorded in
+    /// [`MaskConsumerStatus::effective_settings`]. Changing them does not
+    /// retroactively alter consumers that are already assigned; only new
+    /// assignments pick up the changes.
+    #[serde(rename = "maskDefaults")]
+    pub mask_defaults: Option<MaskDefaultsSpec>,
+
+    /// Optional suffix for the names of the credentials [`Secret`](k8s_openapi::api::core::v1::Secret)
+    /// resources copied to [`Mask`] namespaces. By default, the copies are
+    /// named `<mask>-<uid>` after the [`MaskProvider`]'s UID, which changes
+    /// whenever the [`MaskProvider`] is deleted and recreated. When set, the
+    /// copies are named `<mask>-<suffix>` instead, so recreating the
+    /// [`MaskProvider`] with identical credentials keeps the names stable.
+    /// Must be unique among [`MaskProvider`]s that can be assigned to the
+    /// same namespaces.
+    #[serde(rename = "stableSecretSuffix")]
+    pub stable_secret_suffix: Option<String>,
+
+    /// Optional template for the names of the credentials [`Secret`](k8s_openapi::api::core::v1::Secret)
+    /// resources copied to [`Mask`] namespaces, e.g. `vpn-credentials` to
+    /// fit the names downstream workloads expect. `${MASK}` is replaced with
+    /// the [`Mask`]'s name, `${PROVIDER}` with this [`MaskProvider`]'s name,
+    /// `${SLOT}` with the reserved slot and `${UID_SHORT}` with the first
+    /// eight characters of the [`MaskConsumer`]'s UID. Unless
+    /// [`MaskProviderSpec::max_slots`] is 1, the template must contain
+    /// `${MASK}` or `${UID_SHORT}` so the names are unique within a
+    /// namespace. Takes precedence over [`MaskProviderSpec::stable_secret_suffix`].
+    #[serde(rename = "copySecretNameTemplate")]
+    pub copy_secret_name_template: Option<String>,
+
+    /// Optional name of the [`VpnAccount`] this [`MaskProvider`] belongs to.
+    /// Every [`MaskProvider`] referencing the same [`VpnAccount`] shares its
+    /// [`VpnAccountSpec::max_connections`], so no new slots are reserved with
+    /// any of them once the account's ceiling is reached, even if this
+    /// [`MaskProvider`] has open slots of its own.
+    #[serde(rename = "accountRef")]
+    pub account_ref: Option<String>,
+
+    /// Optional hours during which the [`MaskProvider`] can be assigned to
+    /// new [`MaskConsumer`]s, for services whose terms only allow usage at
+    /// certain times. Outside of these hours, no new slots are reserved with
+    /// the [`MaskProvider`], while its phase stays Ready or Active and
+    /// existing assignments are left alone unless
+    /// [`drainOutOfHours`](MaskProviderAvailabilitySpec::drain_out_of_hours) is set.
+    pub availability: Option<MaskProviderAvailabilitySpec>,
+
+    /// Optional relative weight of this [`MaskProvider`] when the operator
+    /// runs with `--provider-selector=weighted`. A [`MaskProvider`] with
+    /// twice the weight of another is tried first for twice as many
+    /// [`MaskConsumer`]s. A weight of zero excludes it from new assignments.
+    /// Defaults to 1, and is ignored by the other selectors.
+    pub weight: Option<u32>,
+
+    /// If `true`, [`Mask`]s that are unassigned because this [`MaskProvider`]
+    /// is force-deleted are told so in their own namespace: their
+    /// [`MaskStatus::provider_withdrawn`] is set until they're assigned
+    /// again, and a `ProviderWithdrawn` Warning event is published on them.
+    /// Defaults to `false`, in which case they are unassigned silently.
+    #[serde(rename = "reportWithdrawal")]
+    pub report_withdrawal: Option<bool>,
+
+    /// Optional version of [gluetun](https://github.com/qdm12/gluetun) that
+    /// the credentials are written for, e.g. `v3.38.0`. Verification runs
+    /// this exact image tag instead of the operator's default, and the
+    /// version is recorded in [`AssignedProvider::gluetun_version`] and
+    /// annotated on every copied [`Secret`](k8s_openapi::api::core::v1::Secret)
+    /// so consumers can run a matching sidecar. The status warns if
+    /// [`MaskDefaultsSpec::secret_keys`] uses env var names that gluetun
+    /// renamed before or after this version.
+    #[serde(rename = "gluetunVersion")]
+    pub gluetun_version: Option<String>,
+
+    /// Optional annotation to set on every copied [`Secret`](k8s_openapi::api::core::v1::Secret),
+    /// for clusters where an external controller transparently encrypts or
+    /// decrypts Secrets that carry it. The setting is recorded in
+    /// [`AssignedProvider::copy_encryption`] when a slot is assigned.
+    #[serde(rename = "copyEncryption")]
+    pub copy_encryption: Option<CopyEncryptionSpec>,
+
+    /// If `true` and the operator runs with `--canary-interval`, a
+    /// short-lived canary [`Mask`] is periodically assigned this
+    /// [`MaskProvider`] to check the whole assignment pipeline works,
+    /// from reserving a slot to copying the credentials. The outcome is
+    /// recorded in [`MaskProviderStatus::last_canary_result`]. Canaries
+    /// are skipped while every slot is in use. Defaults to `false`.
+    pub canary: Option<bool>,
+
+    /// Optional label keys that a [`Mask`] must have, with non-empty values,
+    /// to be assigned this [`MaskProvider`], e.g. `usage-purpose` so that
+    /// every use of the credentials can be audited. A [`Mask`] missing any
+    /// of them is only assigned other [`MaskProvider`]s, and enters the
+    /// [`ErrMissingLabels`](MaskPhase::ErrMissingLabels) phase if there are
+    /// none. Adding the labels later unblocks it. Canary [`Mask`]s are exempt.
+    #[serde(rename = "requiredMaskLabels")]
+    pub required_mask_labels: Option<Vec<String>>,
+
+    /// Optional transformations applied, in order, to the data of the
+    /// [`Secret`](k8s_openapi::api::core::v1::Secret) referenced by
+    /// [`secret`](MaskProviderSpec::secret), e.g. to derive the WireGuard
+    /// public key gluetun expects from a private key. The result is written
+    /// to a [`Secret`](k8s_openapi::api::core::v1::Secret) named
+    /// `{name}-transformed` owned by the [`MaskProvider`], which is then used
+    /// for verification and copied to the [`MaskConsumer`]s in its place. A
+    /// transformation that fails puts the [`MaskProvider`] in the
+    /// [`ErrConfig`](MaskProviderPhase::ErrConfig) phase.
+    pub transforms: Option<Vec<Transform>>,
+}
+
+/// The annotation that engages an external encryption controller for
+/// the copies of the credentials, e.g. `kms.example.com/encrypted: "true"`.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct CopyEncryptionSpec {
+    /// Key of the annotation. Must be a valid annotation key that doesn't
+    /// use the operator's `vpn.beebs.dev/` prefix, or the [`MaskProvider`]
+    /// enters the [`ErrConfig`](MaskProviderPhase::ErrConfig) phase.
+    #[serde(rename = "annotationKey")]
+    pub annotation_key: String,
+
+    /// Value of the annotation. Defaults to `"true"`.
+    #[serde(rename = "annotationValue")]
+    pub annotation_value: Option<String>,
+}
+
+impl CopyEncryptionSpec {
+    /// Returns the annotation's value, or the default of `"true"`.
+    pub fn value(&self) -> &str {
+        self.annotation_value.as_deref().unwrap_or("true")
+    }
+}
+
+/// A transformation of the credentials [`Secret`](k8s_openapi::api::core::v1::Secret)'s
+/// data. Keys that no transformation writes are kept as they are.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type")]
+pub enum Transform {
+    /// Replaces the value of `key` with its base64 decoding, for values
+    /// that were encoded twice.
+    Base64Decode {
+        #[serde(default)]
+        key: String,
+    },
+
+    /// Writes the WireGuard (X25519) public key of the base64-encoded
+    /// private key in `from` to `to`, also base64-encoded.
+    DeriveWireguardPublicKey {
+        #[serde(default)]
+        from: String,
+        #[serde(default)]
+        to: String,
+    },
+
+    /// Writes `pattern` to `to`, with every `{{KEY}}` placeholder
+    /// replaced by the value of `KEY`, e.g. `"{{USER}}:{{PASSWORD}}"`.
+    Template {
+        #[serde(default)]
+        to: String,
+        #[serde(default)]
+        pattern: String,
+    },
+
+    /// Moves the value of `from` to `to`.
+    Rename {
+        #[serde(default)]
+        from: String,
+        #[serde(default)]
+        to: String,
+    },
+}
+
+/// The variants of [`Transform`] differ in their fields, which a
+/// structural schema can't express as alternatives, so the fields of
+/// every variant are optional. Missing fields deserialize as empty, so a
+/// malformed transformation is still read and rejected when it's applied,
+/// rather than failing to deserialize the whole [`MaskProvider`].
+impl JsonSchema for Transform {
+    fn schema_name() -> String {
+        "Transform".to_owned()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        serde_json::from_value(serde_json::json!({
+            "type": "object",
+            "required": ["type"],
+            "properties": {
+                "type": {
+                    "type": "string",
+                    "enum": [
+                        "Base64Decode",
+                        "DeriveWireguardPublicKey",
+                        "Template",
+                        "Rename",
+                    ],
+                },
+                "key": { "type": "string" },
+                "from": { "type": "string" },
+                "to": { "type": "string" },
+                "pattern": { "type": "string" },
+            },
+        }))
+        .unwrap()
+    }
+}
+
+/// Hours during which a [`MaskProvider`] accepts new assignments.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct MaskProviderAvailabilitySpec {
+    /// One or more windows separated by `;`, each being an optional list
+    /// of days followed by a time range, e.g. `"Mon-Fri 08:00-20:00"` or
+    /// `"Mon,Wed 09:00-17:00; Sat 10:00-14:00"`. Days may be ranges and are
+    /// comma-separated. Omitting the days means every day. A range that
+    /// ends before it starts runs past midnight into the next day, and
+    /// `24:00` means the end of the day.
+    pub schedule: String,
+
+    /// IANA name of the time zone the schedule is in, e.g. `"Europe/Berlin"`.
+    /// Daylight saving time is taken into account. Defaults to `UTC`.
+    #[serde(default)]
+    pub timezone: String,
+
+    /// If `true`, the [`MaskConsumer`]s assigned to the [`MaskProvider`] are
+    /// unassigned outside of the schedule, rather than only refusing new
+    /// assignments. Defaults to `false`.
+    #[serde(rename = "drainOutOfHours")]
+    pub drain_out_of_hours: Option<bool>,
 }
 
 /// Status object for the [`MaskProvider`] resource.
@@ -159,13 +546,250 @@ pub struct MaskProviderStatus {
     #[serde(rename = "lastUpdated")]
     pub last_updated: Option<String>,
 
+    /// Incremented by every status update. Each update is only applied
+    /// if the revision is unchanged since the [`MaskProviderStatus`] object was read,
+    /// so a stale update can never overwrite a newer one.
+    #[serde(rename = "statusRevision")]
+    pub status_revision: Option<u64>,
+
     /// Timestamp of when the credentials were last verified.
     #[serde(rename = "lastVerified")]
     pub last_verified: Option<String>,
 
+    /// Name of the node the credentials were last verified from.
+    #[serde(rename = "lastVerifiedNode")]
+    pub last_verified_node: Option<String>,
+
+    /// Zone of the node the credentials were last verified from, per
+    /// its `topology.kubernetes.io/zone` label, if it has one.
+    #[serde(rename = "lastVerifiedZone")]
+    pub last_verified_zone: Option<String>,
+
+    /// Checksum of what the credentials were last verified with: the
+    /// [`secret_hash`](MaskProviderStatus::secret_hash) of the credentials,
+    /// the verification settings that decide how they're verified and the
+    /// gluetun image. Verification is repeated whenever the checksum of the
+    /// current ones differs, regardless of [`last_verified`](MaskProviderStatus::last_verified).
+    #[serde(rename = "lastVerifiedConfigHash")]
+    pub last_verified_config_hash: Option<String>,
+
+    /// [`mode`](MaskProviderVerifySpec::mode) the credentials were last
+    /// verified with. Once `KeysOnly`, the phase message keeps noting that
+    /// only static checks ran until they're verified by dialing.
+    #[serde(rename = "lastVerifiedMode")]
+    pub last_verified_mode: Option<MaskProviderVerifyMode>,
+
+    /// Timestamp of when the credentials are next verified, which is
+    /// [`last_verified`](MaskProviderStatus::last_verified) plus the
+    /// [`interval`](MaskProviderVerifySpec::interval) and the
+    /// [`MaskProvider`]'s offset within the [`spread`](MaskProviderVerifySpec::spread).
+    /// Unset if they aren't verified periodically.
+    #[serde(rename = "nextVerification")]
+    pub next_verification: Option<String>,
+
+    /// Value of the `vpn.beebs.dev/verify-now` annotation as of the last
+    /// manually triggered verification. Changing the annotation to any
+    /// other value triggers a new verification cycle.
+    #[serde(rename = "lastManualVerify")]
+    pub last_manual_verify: Option<String>,
+
+    /// Verification settings that applied to the current or most recent
+    /// verification cycle: [`spec.verify`](MaskProviderSpec::verify) merged
+    /// onto the operator's default, with the former taking precedence.
+    /// Changes to either take effect with the next cycle.
+    #[serde(rename = "effectiveVerify")]
+    pub effective_verify: Option<MaskProviderVerifySpec>,
+
     /// Number of active slots reserved by [`Mask`] resources.
     #[serde(rename = "activeSlots")]
     pub active_slots: Option<usize>,
+
+    /// Number of slots that aren't reserved by [`Mask`] resources, out of
+    /// [`MaskProviderSpec::max_slots`]. Updated with each status refresh.
+    #[serde(rename = "availableSlots")]
+    pub available_slots: Option<usize>,
+
+    /// [`MaskProviderStatus::available_slots`] out of
+    /// [`MaskProviderSpec::max_slots`], e.g. `3/5`, so `kubectl get` shows
+    /// how close the [`MaskProvider`] is to saturation. Updated along with
+    /// [`MaskProviderStatus::active_slots`].
+    #[serde(rename = "slotsSummary")]
+    pub slots_summary: Option<String>,
+
+    /// Number of [`MaskConsumer`]s in the [`Waiting`](MaskConsumerPhase::Waiting)
+    /// phase that the [`MaskProvider`] could be assigned to, going by their
+    /// [`MaskConsumerSpec::providers`] and [`MaskProviderSpec::namespaces`].
+    /// A [`MaskConsumer`] that could be assigned several [`MaskProvider`]s
+    /// counts towards each of them. Updated with each status refresh.
+    #[serde(rename = "pendingDemand")]
+    pub pending_demand: Option<usize>,
+
+    /// Problems with the [`MaskProvider`]'s spec that don't prevent it
+    /// from being used, such as [`MaskProviderSpec::namespaces`] naming
+    /// namespaces that don't exist. Re-checked with each status refresh.
+    pub warnings: Option<Vec<String>>,
+
+    /// Utilization of the [`VpnAccount`] referenced by
+    /// [`MaskProviderSpec::account_ref`], counted across every
+    /// [`MaskProvider`] that shares it.
+    pub account: Option<AccountUtilization>,
+
+    /// True if the current time is outside of [`MaskProviderSpec::availability`],
+    /// in which case no new slots are reserved with the [`MaskProvider`].
+    /// This is informational and doesn't change the phase.
+    #[serde(rename = "outOfHours")]
+    pub out_of_hours: Option<bool>,
+
+    /// Describes the [`MaskProvider`]'s availability, including when it
+    /// next changes. Only set if [`MaskProviderSpec::availability`] is.
+    #[serde(rename = "availabilityMessage")]
+    pub availability_message: Option<String>,
+
+    /// The most recent failed action, if the last reconciliation of the
+    /// [`MaskProvider`] failed. Cleared by the next successful status update.
+    #[serde(rename = "lastError")]
+    pub last_error: Option<LastError>,
+
+    /// Timestamp of when the last canary [`Mask`] finished or was skipped.
+    /// See [`MaskProviderSpec::canary`].
+    #[serde(rename = "lastCanaryAt")]
+    pub last_canary_at: Option<String>,
+
+    /// Outcome of the last canary [`Mask`].
+    #[serde(rename = "lastCanaryResult")]
+    pub last_canary_result: Option<MaskProviderCanaryResult>,
+
+    /// Time from the last canary [`Mask`]'s creation until its credentials
+    /// were copied or it failed, e.g. `"4.2s"`. Unset if it was skipped.
+    #[serde(rename = "lastCanaryDuration")]
+    pub last_canary_duration: Option<String>,
+
+    /// SHA-256 checksum of the credentials that are copied for [`Mask`]s,
+    /// i.e. the data of [`MaskProviderSpec::secret`] after its transformations.
+    /// Updated whenever the [`Secret`](k8s_openapi::api::core::v1::Secret)
+    /// changes, so copies can be checked against it without reading it.
+    #[serde(rename = "secretHash")]
+    pub secret_hash: Option<String>,
+
+    /// Progress of the long-running operation the [`MaskProvider`] is
+    /// going through, if any, for UIs to show without parsing the
+    /// message. Cleared once the operation completes.
+    pub operation: Option<MaskProviderOperation>,
+
+    /// The [`MaskConsumer`]s most recently assigned a slot that are still
+    /// assigned, newest first, with a summary of which workloads used the
+    /// credentials from their [`MaskConsumerStatus::consumers`]. Holds at
+    /// most ten entries. Updated with each status refresh.
+    #[serde(rename = "recentAssignments")]
+    pub recent_assignments: Option<Vec<RecentAssignment>>,
+}
+
+/// Found in [`MaskProviderStatus::recent_assignments`], this struct
+/// summarizes a [`MaskConsumer`] assigned a slot of the [`MaskProvider`].
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct RecentAssignment {
+    /// Namespace of the [`MaskConsumer`].
+    pub namespace: String,
+
+    /// Name of the [`MaskConsumer`].
+    pub name: String,
+
+    /// Index of the slot assigned to the [`MaskConsumer`].
+    pub slot: usize,
+
+    /// Timestamp of when the slot was reserved.
+    #[serde(rename = "assignedAt")]
+    pub assigned_at: Option<String>,
+
+    /// Number of distinct ServiceAccounts whose Pods were recorded using
+    /// the credentials in the [`MaskConsumer`]'s status.
+    #[serde(rename = "serviceAccounts")]
+    pub service_accounts: usize,
+
+    /// Timestamp of when the credentials were last seen in use by any of
+    /// those Pods. Unset if they were never seen in use.
+    #[serde(rename = "lastSeen")]
+    pub last_seen: Option<String>,
+}
+
+/// A long-running operation on the [`MaskProvider`], as in
+/// [`MaskProviderStatus::operation`].
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MaskProviderOperation {
+    /// What the operation is.
+    pub kind: MaskProviderOperationKind,
+
+    /// Timestamp of when the operation began.
+    #[serde(rename = "startedAt")]
+    pub started_at: String,
+
+    /// Timestamp of when the current step times out and fails the
+    /// operation, if it has a time limit.
+    pub deadline: Option<String>,
+
+    /// Number of steps taken so far, out of [`steps_total`](MaskProviderOperation::steps_total).
+    /// Verification takes four: the verification [`Mask`] is created, it's
+    /// assigned a slot, the verification Pod is created and the Pod probes
+    /// the connection. Draining takes one for each unassigned [`MaskConsumer`].
+    pub step: usize,
+
+    /// Number of steps the operation takes. For draining, this is the
+    /// number of [`MaskConsumer`]s assigned when it began.
+    #[serde(rename = "stepsTotal")]
+    pub steps_total: usize,
+}
+
+/// What a [`MaskProviderOperation`] is.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+pub enum MaskProviderOperationKind {
+    /// The credentials are being verified.
+    Verifying,
+
+    /// The [`MaskConsumer`]s assigned to the [`MaskProvider`] are being
+    /// unassigned, because it's outside of its availability hours or
+    /// its deletion was forced.
+    Draining,
+}
+
+impl fmt::Display for MaskProviderOperationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaskProviderOperationKind::Verifying => write!(f, "Verifying"),
+            MaskProviderOperationKind::Draining => write!(f, "Draining"),
+        }
+    }
+}
+
+/// Outcome of a canary [`Mask`], as in [`MaskProviderStatus::last_canary_result`].
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+pub enum MaskProviderCanaryResult {
+    /// The canary [`Mask`] became Active with well-formed credentials.
+    Succeeded,
+
+    /// The canary [`Mask`] wasn't assigned the [`MaskProvider`] or given
+    /// well-formed credentials in time. A `CanaryFailed` Warning event
+    /// on the [`MaskProvider`] explains why.
+    Failed,
+
+    /// Every slot was in use, so no canary [`Mask`] was assigned rather
+    /// than having it wait for a slot.
+    Skipped,
+
+    /// The canary [`Mask`] was created, but every slot stayed in use
+    /// until it timed out. This says nothing about the pipeline, so it
+    /// isn't a failure.
+    TimedOutWaiting,
+}
+
+impl fmt::Display for MaskProviderCanaryResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaskProviderCanaryResult::Succeeded => write!(f, "Succeeded"),
+            MaskProviderCanaryResult::Failed => write!(f, "Failed"),
+            MaskProviderCanaryResult::Skipped => write!(f, "Skipped"),
+            MaskProviderCanaryResult::TimedOutWaiting => write!(f, "TimedOutWaiting"),
+        }
+    }
 }
 
 /// A short description of the [`MaskProvider`] resource's current state.
@@ -197,6 +821,19 @@ pub enum MaskProviderPhase {
 
     /// The credentials verification process failed.
     ErrVerifyFailed,
+
+    /// Another [`MaskProvider`] that can be assigned to the same namespaces
+    /// has the same [`MaskProviderSpec::stable_secret_suffix`]. The older
+    /// of the two is used and this one is not assigned until resolved.
+    ErrSecretSuffixCollision,
+
+    /// The [`VpnAccount`] referenced by [`MaskProviderSpec::account_ref`]
+    /// does not exist, so its connection ceiling can't be enforced.
+    ErrAccountNotFound,
+
+    /// One of the [`MaskProviderSpec::transforms`] can't be applied to the
+    /// credentials, e.g. because a key it reads is missing.
+    ErrConfig,
 }
 
 impl FromStr for MaskProviderPhase {
@@ -212,6 +849,9 @@ impl FromStr for MaskProviderPhase {
             "Terminating" => Ok(MaskProviderPhase::Terminating),
             "ErrSecretNotFound" => Ok(MaskProviderPhase::ErrSecretNotFound),
             "ErrVerifyFailed" => Ok(MaskProviderPhase::ErrVerifyFailed),
+            "ErrSecretSuffixCollision" => Ok(MaskProviderPhase::ErrSecretSuffixCollision),
+            "ErrAccountNotFound" => Ok(MaskProviderPhase::ErrAccountNotFound),
+            "ErrConfig" => Ok(MaskProviderPhase::ErrConfig),
             _ => Err(()),
         }
     }
@@ -228,6 +868,9 @@ impl fmt::Display for MaskProviderPhase {
             MaskProviderPhase::Terminating => write!(f, "Terminating"),
             MaskProviderPhase::ErrSecretNotFound => write!(f, "ErrSecretNotFound"),
             MaskProviderPhase::ErrVerifyFailed => write!(f, "ErrVerifyFailed"),
+            MaskProviderPhase::ErrSecretSuffixCollision => write!(f, "ErrSecretSuffixCollision"),
+            MaskProviderPhase::ErrAccountNotFound => write!(f, "ErrAccountNotFound"),
+            MaskProviderPhase::ErrConfig => write!(f, "ErrConfig"),
         }
     }
 }
@@ -246,3 +889,41 @@ fn any_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schem
     }))
     .unwrap()
 }
+
+impl MaskProviderStatus {
+    /// Sets the phase along with the message explaining it, so a
+    /// message from a previous phase is never left behind.
+    pub fn set_phase(&mut self, phase: MaskProviderPhase, message: impl Into<String>) {
+        self.phase = Some(phase);
+        self.message = Some(message.into());
+    }
+
+    /// Sets the number of slots in use, along with the phase it implies:
+    /// Ready if no slots are in use and Active otherwise. Any warnings are
+    /// recorded and appended to the message so they show up in `kubectl get`.
+    pub fn set_active_slots(
+        &mut self,
+        active_slots: usize,
+        message: impl Into<String>,
+        warnings: Vec<String>,
+    ) {
+        let phase = if active_slots == 0 {
+            MaskProviderPhase::Ready
+        } else {
+            MaskProviderPhase::Active
+        };
+        let message = warnings.iter().fold(message.into(), |message, warning| {
+            format!("{} Warning: {}.", message, warning)
+        });
+        self.set_phase(phase, message);
+        self.active_slots = Some(active_slots);
+        self.warnings = Some(warnings).filter(|w| !w.is_empty());
+    }
+
+    /// Records a manual verification trigger as satisfied, if there was one.
+    pub fn set_manual_verify(&mut self, verify_now: Option<String>) {
+        if verify_now.is_some() {
+            self.last_manual_verify = verify_now;
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// [`MaskQuotaSpec`] limits the number of slots that the [`Mask`]s in the
+/// namespace of the [`MaskQuota`] can reserve at the same time, across every
+/// [`MaskProvider`]. A [`Mask`] that would exceed any [`MaskQuota`] in its
+/// namespace enters the [`ErrQuotaExceeded`](MaskPhase::ErrQuotaExceeded)
+/// phase until a slot is released. Changes only apply to future assignments,
+/// so lowering a limit never unassigns a [`Mask`].
+#[derive(CustomResource, Serialize, Deserialize, Default, Debug, PartialEq, Clone, JsonSchema)]
+#[kube(
+    group = "vpn.beebs.dev",
+    version = "v1",
+    kind = "MaskQuota",
+    plural = "maskquotas",
+    derive = "PartialEq",
+    status = "MaskQuotaStatus",
+    namespaced
+)]
+#[kube(derive = "Default")]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".status.usedSlots\", \"name\": \"USED\", \"type\": \"integer\" }"
+)]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".spec.maxSlots\", \"name\": \"MAX\", \"type\": \"integer\" }"
+)]
+pub struct MaskQuotaSpec {
+    /// Maximum number of slots the namespace's [`Mask`]s can reserve
+    /// with all [`MaskProvider`]s together.
+    #[serde(rename = "maxSlots")]
+    pub max_slots: usize,
+
+    /// Optional lower limits for the slots reserved with the [`MaskProvider`]s
+    /// that have a tag. A slot with a [`MaskProvider`] that has several of
+    /// the tags counts towards each of their limits.
+    pub tags: Option<Vec<MaskQuotaTagLimit>>,
+}
+
+/// Found in [`MaskQuotaSpec::tags`], this struct limits the slots reserved
+/// with the [`MaskProvider`]s that have a tag.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct MaskQuotaTagLimit {
+    /// Tag of the [`MaskProvider`]s, as in [`MaskProviderSpec::tags`].
+    pub tag: String,
+
+    /// Maximum number of slots reserved with the [`MaskProvider`]s that have the tag.
+    #[serde(rename = "maxSlots")]
+    pub max_slots: usize,
+}
+
+/// Status object for the [`MaskQuota`] resource, showing how much of it is in use.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Default, JsonSchema)]
+pub struct MaskQuotaStatus {
+    /// Number of slots currently reserved by the namespace's [`Mask`]s.
+    #[serde(rename = "usedSlots")]
+    pub used_slots: Option<usize>,
+
+    /// Slots currently reserved with the [`MaskProvider`]s that have
+    /// each tag in [`MaskQuotaSpec::tags`].
+    pub tags: Option<Vec<MaskQuotaTagUsage>>,
+
+    /// Timestamp of when the [`MaskQuotaStatus`] object was last updated.
+    #[serde(rename = "lastUpdated")]
+    pub last_updated: Option<String>,
+}
+
+/// Found in [`MaskQuotaStatus::tags`], this struct shows how much
+/// of a [`MaskQuotaTagLimit`] is in use.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct MaskQuotaTagUsage {
+    /// Tag of the [`MaskProvider`]s.
+    pub tag: String,
+
+    /// Number of slots reserved with the [`MaskProvider`]s that have the tag.
+    #[serde(rename = "usedSlots")]
+    pub used_slots: usize,
+
+    /// The [`MaskQuotaTagLimit::max_slots`] of the tag.
+    #[serde(rename = "maxSlots")]
+    pub max_slots: usize,
+}
@@ -3,6 +3,8 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{fmt, str::FromStr};
 
+use crate::LastError;
+
 /// [`MaskReservationSpec`] describes the configuration for a [`MaskReservation`] resource,
 /// which is used to garbage collect slots by deleting a corresponding [`MaskConsumer`] in
 /// the [`Mask`]'s namespace before removing the finalizer on this object.
@@ -38,6 +40,11 @@ pub struct MaskReservationSpec {
 
     /// UID of the [`MaskConsumer`] resource reserving the slot.
     pub uid: String,
+
+    /// Index of the reserved slot with the [`MaskProvider`]. Reservations
+    /// created before this field existed have their slot parsed from
+    /// their name instead.
+    pub slot: Option<usize>,
 }
 
 /// Status object for the [`MaskReservation`] resource.
@@ -53,6 +60,17 @@ pub struct MaskReservationStatus {
     /// Timestamp of when the [`MaskReservationStatus`] object was last updated.
     #[serde(rename = "lastUpdated")]
     pub last_updated: Option<String>,
+
+    /// Incremented by every status update. Each update is only applied
+    /// if the revision is unchanged since the [`MaskReservationStatus`] object was read,
+    /// so a stale update can never overwrite a newer one.
+    #[serde(rename = "statusRevision")]
+    pub status_revision: Option<u64>,
+
+    /// The most recent failed action, if the last reconciliation of the
+    /// [`MaskReservation`] failed. Cleared by the next successful status update.
+    #[serde(rename = "lastError")]
+    pub last_error: Option<LastError>,
 }
 
 /// A short description of the [`MaskReservation`] resource's current state.
@@ -91,3 +109,12 @@ impl fmt::Display for MaskReservationPhase {
         }
     }
 }
+
+impl MaskReservationStatus {
+    /// Sets the phase along with the message explaining it, so a
+    /// message from a previous phase is never left behind.
+    pub fn set_phase(&mut self, phase: MaskReservationPhase, message: impl Into<String>) {
+        self.phase = Some(phase);
+        self.message = Some(message.into());
+    }
+}
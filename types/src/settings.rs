@@ -0,0 +1,73 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Settings that affect how a [`MaskConsumer`](crate::MaskConsumer) consumes
+/// the credentials of its assigned [`MaskProvider`](crate::MaskProvider).
+/// They can be specified on the [`Mask`](crate::Mask) itself or as defaults
+/// in [`MaskProviderSpec::mask_defaults`](crate::MaskProviderSpec::mask_defaults).
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct MaskDefaultsSpec {
+    /// Optional allowlist of keys to copy from the [`MaskProvider`](crate::MaskProvider)'s
+    /// credentials [`Secret`](k8s_openapi::api::core::v1::Secret). If unset, all keys are copied.
+    #[serde(rename = "secretKeys")]
+    pub secret_keys: Option<Vec<String>>,
+
+    /// If `true`, the copied credentials [`Secret`](k8s_openapi::api::core::v1::Secret)
+    /// is created as immutable. Defaults to `false`.
+    #[serde(rename = "immutableSecret")]
+    pub immutable_secret: Option<bool>,
+
+    /// How the credentials are laid out in the copied [`Secret`](k8s_openapi::api::core::v1::Secret).
+    /// Defaults to [`SecretFormat::Env`].
+    #[serde(rename = "secretFormat")]
+    pub secret_format: Option<SecretFormat>,
+
+    /// Optional map of file names to keys of the credentials. Each file name
+    /// is added to the copied [`Secret`](k8s_openapi::api::core::v1::Secret)
+    /// as an additional key with the value of its source key, e.g.
+    /// `password.txt: OPENVPN_PASSWORD`, so the same [`Secret`](k8s_openapi::api::core::v1::Secret)
+    /// can be mounted as files. The source keys remain present.
+    #[serde(rename = "fileProjection")]
+    pub file_projection: Option<BTreeMap<String, String>>,
+}
+
+/// Layout of the credentials in a [`MaskConsumer`](crate::MaskConsumer)'s
+/// copy of the [`MaskProvider`](crate::MaskProvider)'s [`Secret`](k8s_openapi::api::core::v1::Secret).
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, JsonSchema)]
+pub enum SecretFormat {
+    /// Each key is copied as is, ready to be consumed as environment variables.
+    #[default]
+    Env,
+
+    /// The keys are rendered into a single gluetun `config.toml` key,
+    /// ready to be mounted as a file.
+    GluetunToml,
+
+    /// Each key is copied as is, alongside the rendered `config.toml` key.
+    Both,
+}
+
+impl MaskDefaultsSpec {
+    /// Returns the effective settings given the explicitly specified
+    /// settings (`self`) and the provider's defaults. Explicit settings
+    /// always take precedence over the defaults.
+    pub fn with_defaults(&self, defaults: Option<&MaskDefaultsSpec>) -> MaskDefaultsSpec {
+        let defaults = match defaults {
+            Some(defaults) => defaults,
+            None => return self.clone(),
+        };
+        MaskDefaultsSpec {
+            secret_keys: self
+                .secret_keys
+                .clone()
+                .or_else(|| defaults.secret_keys.clone()),
+            immutable_secret: self.immutable_secret.or(defaults.immutable_secret),
+            secret_format: self.secret_format.or(defaults.secret_format),
+            file_projection: self
+                .file_projection
+                .clone()
+                .or_else(|| defaults.file_projection.clone()),
+        }
+    }
+}
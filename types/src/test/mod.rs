@@ -0,0 +1,2 @@
+mod settings;
+mod status_helpers;
@@ -0,0 +1,64 @@
+use crate::*;
+
+fn settings(secret_keys: Option<&[&str]>, immutable_secret: Option<bool>) -> MaskDefaultsSpec {
+    MaskDefaultsSpec {
+        secret_keys: secret_keys.map(|keys| keys.iter().map(|k| k.to_string()).collect()),
+        immutable_secret,
+        secret_format: None,
+        file_projection: None,
+    }
+}
+
+#[test]
+fn explicit_settings_win_over_defaults() {
+    let explicit = settings(Some(&["VPN_USERNAME"]), None);
+    let defaults = settings(Some(&["VPN_PASSWORD"]), Some(true));
+    assert_eq!(
+        explicit.with_defaults(Some(&defaults)),
+        settings(Some(&["VPN_USERNAME"]), Some(true))
+    );
+    let explicit = settings(None, Some(false));
+    assert_eq!(
+        explicit.with_defaults(Some(&defaults)),
+        settings(Some(&["VPN_PASSWORD"]), Some(false))
+    );
+}
+
+#[test]
+fn no_defaults_keeps_explicit_settings() {
+    let explicit = settings(Some(&["VPN_USERNAME"]), None);
+    assert_eq!(explicit.with_defaults(None), explicit);
+    assert_eq!(
+        MaskDefaultsSpec::default().with_defaults(Some(&MaskDefaultsSpec::default())),
+        MaskDefaultsSpec::default()
+    );
+}
+
+#[test]
+fn mask_settings_are_flattened() {
+    let spec: MaskSpec = serde_json::from_value(serde_json::json!({
+        "providers": ["default"],
+        "secretKeys": ["VPN_USERNAME"],
+        "immutableSecret": true,
+    }))
+    .unwrap();
+    assert_eq!(spec.settings, settings(Some(&["VPN_USERNAME"]), Some(true)));
+}
+
+#[test]
+fn projection_inherited_from_defaults() {
+    let defaults = MaskDefaultsSpec {
+        file_projection: Some([("username.txt".to_owned(), "OPENVPN_USER".to_owned())].into()),
+        ..Default::default()
+    };
+    let settings = MaskDefaultsSpec::default().with_defaults(Some(&defaults));
+    assert_eq!(settings.file_projection, defaults.file_projection);
+
+    // The Mask's own projection replaces the defaults entirely.
+    let explicit = MaskDefaultsSpec {
+        file_projection: Some([("user".to_owned(), "OPENVPN_USER".to_owned())].into()),
+        ..Default::default()
+    };
+    let settings = explicit.with_defaults(Some(&defaults));
+    assert_eq!(settings.file_projection, explicit.file_projection);
+}
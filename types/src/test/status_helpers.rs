@@ -0,0 +1,141 @@
+use crate::*;
+
+/// Returns a MaskConsumer status that has been assigned a MaskProvider.
+fn assigned_consumer_status() -> MaskConsumerStatus {
+    let mut status = MaskConsumerStatus {
+        phase: Some(MaskConsumerPhase::Pending),
+        message: Some("pending".to_owned()),
+        ..Default::default()
+    };
+    status.set_assigned(
+        AssignedProvider {
+            name: "test-provider".to_owned(),
+            namespace: "default".to_owned(),
+            uid: "provider-uid".to_owned(),
+            slot: 3,
+            reservation: "reservation-uid".to_owned(),
+            secret: "test-consumer-provider-uid".to_owned(),
+            ..Default::default()
+        },
+        MaskDefaultsSpec::default(),
+        "reserved slot 3",
+    );
+    status
+}
+
+#[test]
+fn set_phase_always_sets_message() {
+    let mut status = MaskStatus::default();
+    status.set_phase(MaskPhase::Waiting, "waiting");
+    assert_eq!(status.phase, Some(MaskPhase::Waiting));
+    assert_eq!(status.message.as_deref(), Some("waiting"));
+
+    let mut status = MaskReservationStatus::default();
+    status.set_phase(MaskReservationPhase::Active, "in use");
+    assert_eq!(status.phase, Some(MaskReservationPhase::Active));
+    assert_eq!(status.message.as_deref(), Some("in use"));
+}
+
+#[test]
+fn set_assigned_bumps_phase_to_waiting() {
+    // Whatever the previous phase, the consumer waits on its
+    // credentials Secret before it can become Active.
+    for phase in [
+        MaskConsumerPhase::Pending,
+        MaskConsumerPhase::Waiting,
+        MaskConsumerPhase::ErrNoProviders,
+    ] {
+        let mut status = assigned_consumer_status();
+        status.phase = Some(phase);
+        status.set_assigned(
+            status.provider.clone().unwrap(),
+            MaskDefaultsSpec::default(),
+            "reserved slot 3",
+        );
+        assert_eq!(status.phase, Some(MaskConsumerPhase::Waiting));
+        assert_eq!(status.message.as_deref(), Some("reserved slot 3"));
+        assert_eq!(status.provider.as_ref().unwrap().slot, 3);
+        assert!(status.effective_settings.is_some());
+    }
+}
+
+#[test]
+fn clear_provider_leaves_consumer_waiting() {
+    let mut status = assigned_consumer_status();
+    status.clear_provider("no slots");
+    assert_eq!(status.phase, Some(MaskConsumerPhase::Waiting));
+    assert_eq!(status.message.as_deref(), Some("no slots"));
+    assert_eq!(status.provider, None);
+    assert_eq!(status.effective_settings, None);
+}
+
+#[test]
+fn provider_cleared_only_when_phase_demands_it() {
+    // An assigned consumer keeps its provider through phases that
+    // don't contradict the assignment.
+    for phase in [
+        MaskConsumerPhase::Active,
+        MaskConsumerPhase::Terminating,
+        MaskConsumerPhase::ErrNamespaceNotOptedIn,
+    ] {
+        let mut status = assigned_consumer_status();
+        status.set_phase(phase, "message");
+        assert!(status.provider.is_some(), "{} cleared the provider", phase);
+    }
+
+    // A consumer without any providers can't be assigned one.
+    let mut status = assigned_consumer_status();
+    status.set_phase(MaskConsumerPhase::ErrNoProviders, "no providers");
+    assert_eq!(status.provider, None);
+    assert_eq!(status.effective_settings, None);
+}
+
+#[test]
+fn mask_remembers_slot_when_active() {
+    let mut status = MaskStatus::default();
+    status.set_active(
+        Some(SlotAffinity {
+            provider_uid: "provider-uid".to_owned(),
+            slot: 3,
+        }),
+        "active",
+    );
+    assert_eq!(status.phase, Some(MaskPhase::Active));
+    assert_eq!(status.last_slot, Some(3));
+    assert_eq!(status.last_provider_uid.as_deref(), Some("provider-uid"));
+
+    // Refreshing the Active phase keeps the remembered slot.
+    status.set_active(None, "active");
+    assert_eq!(status.last_slot, Some(3));
+}
+
+#[test]
+fn provider_phase_follows_active_slots() {
+    let mut status = MaskProviderStatus::default();
+    status.set_active_slots(2, "in use", vec![]);
+    assert_eq!(status.phase, Some(MaskProviderPhase::Active));
+    assert_eq!(status.active_slots, Some(2));
+    assert_eq!(status.message.as_deref(), Some("in use"));
+    assert_eq!(status.warnings, None);
+
+    status.set_active_slots(0, "ready", vec!["bad namespace".to_owned()]);
+    assert_eq!(status.phase, Some(MaskProviderPhase::Ready));
+    assert_eq!(status.active_slots, Some(0));
+    assert_eq!(
+        status.message.as_deref(),
+        Some("ready Warning: bad namespace.")
+    );
+    assert_eq!(status.warnings, Some(vec!["bad namespace".to_owned()]));
+}
+
+#[test]
+fn manual_verify_only_recorded_when_triggered() {
+    let mut status = MaskProviderStatus {
+        last_manual_verify: Some("1".to_owned()),
+        ..Default::default()
+    };
+    status.set_manual_verify(None);
+    assert_eq!(status.last_manual_verify.as_deref(), Some("1"));
+    status.set_manual_verify(Some("2".to_owned()));
+    assert_eq!(status.last_manual_verify.as_deref(), Some("2"));
+}
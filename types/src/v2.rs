@@ -0,0 +1,183 @@
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{
+    AssignmentStrategy, DeletionPolicy, MaskAntiAffinity, MaskDefaultsSpec, MaskOptions,
+    MaskServiceSpec, MaskStatus, MaskTtlAction, SecretFormat,
+};
+
+/// [`MaskSpec`] is the v2 schema of the [`Mask`] resource. It holds the
+/// same options as [`crate::MaskSpec`], grouped by what they affect:
+/// which [`MaskProvider`](crate::MaskProvider) is assigned, and how its
+/// credentials are copied. Both versions are served, and the conversion
+/// webhook converts between them through [`MaskOptions`], so a [`Mask`]
+/// can be read and written with either version regardless of how it is
+/// stored.
+#[derive(CustomResource, Serialize, Deserialize, Default, Debug, PartialEq, Clone, JsonSchema)]
+#[kube(
+    group = "vpn.beebs.dev",
+    version = "v2",
+    kind = "Mask",
+    plural = "masks",
+    derive = "PartialEq",
+    status = "MaskStatus",
+    namespaced
+)]
+#[kube(derive = "Default")]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".status.phase\", \"name\": \"PHASE\", \"type\": \"string\" }"
+)]
+#[kube(
+    printcolumn = "{\"jsonPath\": \".status.lastUpdated\", \"name\": \"AGE\", \"type\": \"date\" }"
+)]
+pub struct MaskSpec {
+    /// Options for assigning a [`MaskProvider`](crate::MaskProvider).
+    #[serde(default)]
+    pub assignment: MaskAssignmentSpec,
+
+    /// Options for consuming the assigned [`MaskProvider`](crate::MaskProvider)'s
+    /// credentials. Any option omitted here is inherited from the assigned
+    /// provider's [`MaskProviderSpec::mask_defaults`](crate::MaskProviderSpec::mask_defaults).
+    #[serde(default)]
+    pub credentials: MaskCredentialsSpec,
+
+    /// Service exposing a port of the Pods using the credentials.
+    /// Equivalent to `service` in v1.
+    pub service: Option<MaskServiceSpec>,
+}
+
+/// Options for assigning a [`MaskProvider`](crate::MaskProvider) to a [`Mask`].
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct MaskAssignmentSpec {
+    /// Optional list of providers to use at the exclusion of others.
+    /// Omit if you are okay with being assigned any [`MaskProvider`](crate::MaskProvider).
+    /// These values correspond to [`MaskProviderSpec::tags`](crate::MaskProviderSpec::tags),
+    /// and only one of them has to match for the provider to be considered suitable.
+    /// If omitted, the tags in the namespace's `vpn.beebs.dev/default-providers`
+    /// annotation are used, if any.
+    pub providers: Option<Vec<String>>,
+
+    /// Group of [`Mask`]s in the namespace that are never assigned the same
+    /// [`MaskProvider`](crate::MaskProvider). Equivalent to `antiAffinity` in v1.
+    #[serde(rename = "antiAffinity")]
+    pub anti_affinity: Option<MaskAntiAffinity>,
+
+    /// How the suitable [`MaskProvider`](crate::MaskProvider)s are ordered.
+    /// Equivalent to `strategy` in v1.
+    pub strategy: Option<AssignmentStrategy>,
+
+    /// Name of the [`Mask`] in the namespace that this one replaces, whose
+    /// slot is taken over once it's being deleted. Equivalent to `successionOf` in v1.
+    #[serde(rename = "successionOf")]
+    pub succession_of: Option<String>,
+
+    /// Duration string after which the slot is freed if no Pod uses the
+    /// credentials. Equivalent to `ttl` in v1.
+    pub ttl: Option<String>,
+
+    /// What happens once the `ttl` expires. Equivalent to `ttlAction` in v1.
+    #[serde(rename = "ttlAction")]
+    pub ttl_action: Option<MaskTtlAction>,
+
+    /// Number of slots reserved for the [`Mask`], each with its own copy
+    /// of the credentials. Defaults to 1. Equivalent to `slots` in v1.
+    #[schemars(range(max = "crate::MAX_MASK_SLOTS"))]
+    pub slots: Option<usize>,
+}
+
+/// Options for the [`Mask`]'s copy of the assigned [`MaskProvider`](crate::MaskProvider)'s
+/// credentials [`Secret`](k8s_openapi::api::core::v1::Secret).
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct MaskCredentialsSpec {
+    /// Optional allowlist of keys to copy from the provider's credentials.
+    /// If unset, all keys are copied. Equivalent to `secretKeys` in v1.
+    pub keys: Option<Vec<String>>,
+
+    /// How the credentials are laid out in the copied [`Secret`](k8s_openapi::api::core::v1::Secret).
+    /// Defaults to [`SecretFormat::Env`]. Equivalent to `secretFormat` in v1.
+    pub format: Option<SecretFormat>,
+
+    /// If `true`, the copied [`Secret`](k8s_openapi::api::core::v1::Secret) is created
+    /// as immutable. Defaults to `false`. Equivalent to `immutableSecret` in v1.
+    pub immutable: Option<bool>,
+
+    /// Optional map of file names to keys of the credentials, each added to
+    /// the copied [`Secret`](k8s_openapi::api::core::v1::Secret) as an additional
+    /// key. Equivalent to `fileProjection` in v1.
+    pub files: Option<BTreeMap<String, String>>,
+
+    /// What happens when the credentials are withdrawn while Pods still
+    /// reference the copied [`Secret`](k8s_openapi::api::core::v1::Secret).
+    /// Defaults to [`DeletionPolicy::Immediate`]. Equivalent to `deletionPolicy` in v1.
+    #[serde(rename = "deletionPolicy")]
+    pub deletion_policy: Option<DeletionPolicy>,
+}
+
+impl MaskSpec {
+    /// Returns the version-independent view of the spec.
+    pub fn options(&self) -> MaskOptions {
+        MaskOptions {
+            providers: self.assignment.providers.clone(),
+            settings: MaskDefaultsSpec {
+                secret_keys: self.credentials.keys.clone(),
+                immutable_secret: self.credentials.immutable,
+                secret_format: self.credentials.format,
+                file_projection: self.credentials.files.clone(),
+            },
+            deletion_policy: self.credentials.deletion_policy,
+            anti_affinity: self.assignment.anti_affinity.clone(),
+            strategy: self.assignment.strategy,
+            succession_of: self.assignment.succession_of.clone(),
+            ttl: self.assignment.ttl.clone(),
+            ttl_action: self.assignment.ttl_action,
+            service: self.service.clone(),
+            slots: self.assignment.slots,
+        }
+    }
+}
+
+impl From<MaskOptions> for MaskSpec {
+    fn from(options: MaskOptions) -> Self {
+        MaskSpec {
+            assignment: MaskAssignmentSpec {
+                providers: options.providers,
+                anti_affinity: options.anti_affinity,
+                strategy: options.strategy,
+                succession_of: options.succession_of,
+                ttl: options.ttl,
+                ttl_action: options.ttl_action,
+                slots: options.slots,
+            },
+            credentials: MaskCredentialsSpec {
+                keys: options.settings.secret_keys,
+                format: options.settings.secret_format,
+                immutable: options.settings.immutable_secret,
+                files: options.settings.file_projection,
+                deletion_policy: options.deletion_policy,
+            },
+            service: options.service,
+        }
+    }
+}
+
+impl From<crate::Mask> for Mask {
+    fn from(mask: crate::Mask) -> Self {
+        Mask {
+            metadata: mask.metadata,
+            spec: mask.spec.options().into(),
+            status: mask.status,
+        }
+    }
+}
+
+impl From<Mask> for crate::Mask {
+    fn from(mask: Mask) -> Self {
+        crate::Mask {
+            metadata: mask.metadata,
+            spec: mask.spec.options().into(),
+            status: mask.status,
+        }
+    }
+}